@@ -39,6 +39,7 @@ use rand::Rng;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use marty_core::{
+    bus::BuiltinDevices,
     cpu_808x::{Cpu, Segment, ReadWriteFlag},
     cpu_common::CpuType,
     bytequeue::ByteQueue,
@@ -130,7 +131,8 @@ pub fn cpu_bus_write_bench<'a>(c: &mut Criterion) {
         VideoType::CGA, 
         &machine_desc, 
         TraceLogger::None, 
-        false
+        false,
+        BuiltinDevices::default()
     );
 
     let mut rng = rand::thread_rng();
@@ -165,7 +167,8 @@ pub fn cpu_bus_read_cga_bench<'a>(c: &mut Criterion) {
         VideoType::CGA, 
         &machine_desc, 
         TraceLogger::None, 
-        false
+        false,
+        BuiltinDevices::default()
     );
 
 
@@ -202,7 +205,8 @@ pub fn cpu_bus_write_cga_bench<'a>(c: &mut Criterion) {
         VideoType::CGA, 
         &machine_desc, 
         TraceLogger::None, 
-        false
+        false,
+        BuiltinDevices::default()
     );
 
 
@@ -225,6 +229,62 @@ pub fn cpu_bus_write_cga_bench<'a>(c: &mut Criterion) {
 }
 
 
+pub fn cpu_bus_read_bench<'a>(c: &mut Criterion) {
+    let mut trace_logger = TraceLogger::None;
+    let mut cpu = Cpu::new(CpuType::Intel8088, TraceMode::None, trace_logger);
+
+    let machine_desc = MACHINE_DESCS[&MachineType::IBM_XT_5160];
+
+    // Install devices
+    cpu.bus_mut().install_devices(
+        VideoType::CGA, 
+        &machine_desc, 
+        TraceLogger::None, 
+        false,
+        BuiltinDevices::default()
+    );
+
+    let mut rng = rand::thread_rng();
+    cpu.randomize_seed(0);
+    cpu.randomize_mem();
+
+    c.bench_function("cpu_bus_read_bench", |b| {
+        b.iter(|| {
+            // Conventional memory, below any mapped region.
+            let addr = rng.gen_range(0..0xFFFF);
+            _ = cpu.bus_mut().read_u8(addr as usize, 0).unwrap();
+        });
+    });
+}
+
+pub fn cpu_bus_wait_cga_bench<'a>(c: &mut Criterion) {
+    let mut trace_logger = TraceLogger::None;
+    let mut cpu = Cpu::new(CpuType::Intel8088, TraceMode::None, trace_logger);
+
+    let machine_desc = MACHINE_DESCS[&MachineType::IBM_XT_5160];
+
+    // Install devices
+    cpu.bus_mut().install_devices(
+        VideoType::CGA, 
+        &machine_desc, 
+        TraceLogger::None, 
+        false,
+        BuiltinDevices::default()
+    );
+
+    let mut rng = rand::thread_rng();
+    cpu.randomize_seed(0);
+    cpu.randomize_mem();
+
+    c.bench_function("cpu_bus_wait_cga_bench", |b| {
+        b.iter(|| {
+            // CGA memory range to target MMIO wait state lookup.
+            let addr = rng.gen_range(0xB8000..0xBC000);
+            _ = cpu.bus_mut().get_read_wait(addr as usize, 0).unwrap();
+        });
+    });
+}
+
 /*
 criterion_group!(
     cpu_benches, 
@@ -238,9 +298,10 @@ criterion_group!(
 criterion_group!(
     cpu_benches,
     cpu_bus_write_bench,
+    cpu_bus_read_bench,
     cpu_bus_read_cga_bench,
     cpu_bus_write_cga_bench,
-    
+    cpu_bus_wait_cga_bench,
 );
 
 criterion_main!(cpu_benches);
@@ -204,5 +204,70 @@ pub fn render_cga_direct_bench(c: &mut Criterion) {
     });      
 }
 
-criterion_group!(render_benches, render_cga_direct_bench);
+pub fn render_composite_bench(c: &mut Criterion) {
+    // One frame of the CGA direct field, as converted by draw_cga_direct in composite mode
+    const W: u32 = 912;
+    const H: u32 = 262;
+
+    let mut rng = rand::thread_rng();
+    let frame_i = (0..(W * H)).map(|_| rng.gen_range(0..16)).collect::<Vec<u8>>();
+    let mut composite_buf = vec![0; (W * H * 2) as usize];
+    let mut frame_rgb = vec![0; (W * 2 * H * 2 * 4) as usize];
+
+    let mut sync_table = vec![(0.0, 0.0, 0.0); (W * 2) as usize + marty_render::CCYCLE as usize];
+    marty_render::regen_sync_table(&mut sync_table, (W * 2) as usize);
+
+    c.bench_function("render_composite_process_int_bench", |b| {
+        b.iter(|| {
+            marty_render::process_cga_composite_int(
+                black_box(&frame_i),
+                W,
+                H,
+                0,
+                0,
+                W,
+                marty_render::CompositeStandard::NtscOldCga,
+                &mut composite_buf
+            );
+        });
+    });
+
+    c.bench_function("render_composite_artifact_colors_bench", |b| {
+        b.iter(|| {
+            marty_render::artifact_colors_fast(
+                black_box(&composite_buf),
+                W * 2,
+                H,
+                &sync_table,
+                &mut frame_rgb,
+                W,
+                H * 2,
+                0.0,
+                1.0,
+                1.0,
+                true
+            );
+        });
+    });
+
+    c.bench_function("render_composite_artifact_colors_u32_bench", |b| {
+        b.iter(|| {
+            marty_render::artifact_colors_fast_u32(
+                black_box(&composite_buf),
+                W * 2,
+                H,
+                &sync_table,
+                &mut frame_rgb,
+                W,
+                H * 2,
+                0.0,
+                1.0,
+                1.0,
+                true
+            );
+        });
+    });
+}
+
+criterion_group!(render_benches, render_cga_direct_bench, render_composite_bench);
 criterion_main!(render_benches);
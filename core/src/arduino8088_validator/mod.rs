@@ -383,7 +383,9 @@ impl ArduinoValidator {
         return true;
     }
 
-    pub fn validate_registers(&mut self, regs: &VRegisters) -> bool {
+    /// Validate registers against the CPU. Flag differences are reported separately from other 
+    /// registers.
+    pub fn validate_registers(&mut self, regs: &VRegisters) -> Result<(), ValidatorError> {
 
         let mut regs_validate = true;
 
@@ -438,11 +440,12 @@ impl ArduinoValidator {
             cpu_flags_masked = ArduinoValidator::mask_undefined_flags(self.current_instr.opcode, self.current_instr.modrm, regs.flags);
         }
 
+        let mut flags_validate = true;
         if emu_flags_masked != cpu_flags_masked {
 
             trace_error!(self, "CPU flags mismatch! EMU: 0b{:08b} != CPU: 0b{:08b}", emu_flags_masked, cpu_flags_masked);
             //trace_error!(self, "Unmasked: EMU: 0b{:08b} != CPU: 0b{:08b}", self.current_frame.regs[1].flags, regs.flags);            
-            regs_validate = false;
+            flags_validate = false;
 
             let flag_diff = emu_flags_masked ^ cpu_flags_masked;
 
@@ -476,7 +479,13 @@ impl ArduinoValidator {
             //panic!("CPU flag mismatch!")
         }
 
-        regs_validate
+        if !regs_validate {
+            return Err(ValidatorError::RegisterMismatch);
+        }
+        if !flags_validate {
+            return Err(ValidatorError::FlagsMismatch);
+        }
+        Ok(())
     }
 
     pub fn validate_cycles(
@@ -798,7 +807,7 @@ impl CpuValidator for ArduinoValidator {
         let mut store_regs = self.cpu.store().expect("Failed to store registers!");
         self.cpu.adjust_ip(&mut store_regs);
 
-        if !self.validate_registers(&regs) {
+        if let Err(e) = self.validate_registers(&regs) {
             trace_error!(self, "Register validation failure. EMU BEFORE:");    
             RemoteCpu::print_regs(&self.current_instr.regs[0]);
             trace_error!(self, "EMU AFTER:");
@@ -807,7 +816,7 @@ impl CpuValidator for ArduinoValidator {
             trace_error!(self, "CPU AFTER:");   
             RemoteCpu::print_regs(&regs);
 
-            return Err(ValidatorError::RegisterMismatch);
+            return Err(e);
         }
 
         Ok(())
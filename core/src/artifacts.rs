@@ -0,0 +1,256 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    artifacts.rs
+
+    Manages the emulator's output directory. 
+
+    Each run of the emulator gets its own subfolder of the output directory, 
+    named for the time the run started, and every file the emulator produces 
+    (traces, screenshots, memory dumps, bug reports, etc.) is placed in a 
+    subfolder of the run folder by kind. 
+
+    Old run folders are deleted, oldest first, when the total size of the 
+    output directory exceeds the configured retention limit.
+*/
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+pub const DEFAULT_ARTIFACT_DIR: &str = "output";
+pub const DEFAULT_ARTIFACT_RETENTION_MB: u64 = 2048;
+
+const RUN_DIR_PREFIX: &str = "run_";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ArtifactKind {
+    Trace,
+    Screenshot,
+    Capture,
+    Dump,
+    Validator,
+    BugReport,
+}
+
+impl ArtifactKind {
+    pub fn subdir(&self) -> &'static str {
+        match self {
+            ArtifactKind::Trace => "traces",
+            ArtifactKind::Screenshot => "screenshots",
+            ArtifactKind::Capture => "captures",
+            ArtifactKind::Dump => "dumps",
+            ArtifactKind::Validator => "validator",
+            ArtifactKind::BugReport => "bugreports",
+        }
+    }
+}
+
+pub struct ArtifactManager {
+    root: PathBuf,
+    run_dir: PathBuf,
+    retention_bytes: Option<u64>,
+}
+
+impl ArtifactManager {
+
+    /// Create the folder for a new run under 'root'. A retention limit of None keeps all 
+    /// previous runs.
+    pub fn new(root: &Path, retention_mb: Option<u64>) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Two runs started within the same second get distinct folders.
+        let mut run_dir = root.join(format!("{}{}", RUN_DIR_PREFIX, timestamp));
+        let mut i = 1;
+        while run_dir.exists() {
+            run_dir = root.join(format!("{}{}_{}", RUN_DIR_PREFIX, timestamp, i));
+            i += 1;
+        }
+        fs::create_dir_all(&run_dir)?;
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            run_dir,
+            retention_bytes: retention_mb.map(|mb| mb * 1024 * 1024),
+        })
+    }
+
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Return the folder for the specified kind of artifact in the current run, creating it
+    /// if needed.
+    pub fn dir(&self, kind: ArtifactKind) -> PathBuf {
+        let dir = self.run_dir.join(kind.subdir());
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log::error!("Couldn't create artifact directory {}: {}", dir.display(), e);
+        }
+        dir
+    }
+
+    /// Return a path for an artifact of the specified kind in the current run. Only the file
+    /// name of 'name' is used, so a configured path such as "./traces/trace.log" is 
+    /// redirected into the run folder.
+    pub fn file_path(&self, kind: ArtifactKind, name: &str) -> PathBuf {
+        let file_name = Path::new(name).file_name().unwrap_or(name.as_ref());
+        self.dir(kind).join(file_name)
+    }
+
+    /// Return the most recently modified artifact of the specified kind in the current run,
+    /// if any.
+    pub fn latest(&self, kind: ArtifactKind) -> Option<PathBuf> {
+        fs::read_dir(self.run_dir.join(kind.subdir()))
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Some((modified, entry.path()))
+            })
+            .max_by_key(|(modified, _)| *modified)
+            .map(|(_, path)| path)
+    }
+
+    /// Delete the oldest previous runs until the output directory is within the retention
+    /// limit. The current run is never deleted. Returns the number of runs deleted.
+    pub fn enforce_retention(&self) -> usize {
+
+        let limit = match self.retention_bytes {
+            Some(limit) => limit,
+            None => return 0
+        };
+
+        let mut runs: Vec<(PathBuf, u64)> = match fs::read_dir(&self.root) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.is_dir() 
+                        && *path != self.run_dir
+                        && matches!(path.file_name(), Some(n) if n.to_string_lossy().starts_with(RUN_DIR_PREFIX))
+                })
+                .map(|path| {
+                    let size = dir_size(&path);
+                    (path, size)
+                })
+                .collect(),
+            Err(e) => {
+                log::error!("Couldn't read artifact directory {}: {}", self.root.display(), e);
+                return 0
+            }
+        };
+
+        // Run folders are named by start time, so sorting by name sorts oldest first.
+        runs.sort_by_key(|(path, _)| run_sort_key(path));
+
+        let mut total: u64 = runs.iter().map(|(_, size)| size).sum::<u64>() + dir_size(&self.run_dir);
+        let mut deleted = 0;
+
+        for (path, size) in runs {
+            if total <= limit {
+                break;
+            }
+            match fs::remove_dir_all(&path) {
+                Ok(_) => {
+                    log::debug!("Deleted old run folder {} ({} bytes)", path.display(), size);
+                    total = total.saturating_sub(size);
+                    deleted += 1;
+                }
+                Err(e) => {
+                    log::error!("Couldn't delete old run folder {}: {}", path.display(), e);
+                }
+            }
+        }
+        deleted
+    }
+}
+
+/// Sort run folders by the timestamp and sequence number in their names. Sorting the names as 
+/// strings would put 'run_100' before 'run_99'.
+fn run_sort_key(path: &Path) -> (u64, u64) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut parts = name.trim_start_matches(RUN_DIR_PREFIX).split('_');
+    let timestamp = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let seq = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    (timestamp, seq)
+}
+
+fn dir_size(path: &Path) -> u64 {
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let path = entry.path();
+                if path.is_dir() {
+                    dir_size(&path)
+                }
+                else {
+                    entry.metadata().map(|m| m.len()).unwrap_or(0)
+                }
+            })
+            .sum(),
+        Err(_) => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_run(root: &Path, name: &str, size: usize) {
+        let dir = root.join(name).join("traces");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("trace.log"), vec![0u8; size]).unwrap();
+    }
+
+    #[test]
+    fn test_retention_deletes_oldest_runs() {
+        let root = std::env::temp_dir().join(format!("marty_artifacts_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        make_run(&root, "run_99", 600 * 1024);
+        make_run(&root, "run_100", 600 * 1024);
+        make_run(&root, "run_101", 600 * 1024);
+
+        let artifacts = ArtifactManager::new(&root, Some(1)).unwrap();
+        let trace_path = artifacts.file_path(ArtifactKind::Trace, "./traces/instr_trace.log");
+        assert_eq!(trace_path, artifacts.run_dir().join("traces").join("instr_trace.log"));
+
+        assert_eq!(artifacts.enforce_retention(), 2);
+        assert!(!root.join("run_99").exists());
+        assert!(!root.join("run_100").exists());
+        assert!(root.join("run_101").exists());
+        assert!(artifacts.run_dir().exists());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,283 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    assembler.rs
+
+    Implements a minimal 8088 assembler for patching code from the 
+    debugger. A single instruction is assembled at a time. 
+
+    Supported are instructions without operands, INT, RET/RETF with a stack
+    adjustment, relative jumps, calls and loops, direct far jumps and calls,
+    PUSH/POP/INC/DEC of registers, MOV of an immediate or a register to a 
+    register, and the ALU instructions (ADD, OR, ADC, SBB, AND, SUB, XOR, 
+    CMP) between registers or with an immediate. Memory operands are not 
+    supported; enter the bytes in hex instead.
+
+    All numbers are hex, with an optional 'h' suffix or '0x' prefix, as 
+    elsewhere in the debugger. Jump and call targets are offsets within the
+    code segment.
+*/
+
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq)]
+pub enum AsmError {
+    Empty,
+    UnknownMnemonic(String),
+    BadOperands(String),
+    OutOfRange(u16),
+}
+impl Error for AsmError {}
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::Empty => write!(f, "Nothing to assemble."),
+            AsmError::UnknownMnemonic(m) => write!(f, "Unknown or unsupported mnemonic: {}", m),
+            AsmError::BadOperands(m) => write!(f, "Invalid or unsupported operands for {}", m),
+            AsmError::OutOfRange(target) => write!(f, "Target {:04X} is out of range of a short jump.", target),
+        }
+    }
+}
+
+const REG8: [&str; 8] = ["AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH"];
+const REG16: [&str; 8] = ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"];
+
+const NO_OPERAND_OPCODES: [(&str, u8); 36] = [
+    ("NOP", 0x90), ("HLT", 0xF4), ("CLI", 0xFA), ("STI", 0xFB), ("CLC", 0xF8), ("STC", 0xF9),
+    ("CMC", 0xF5), ("CLD", 0xFC), ("STD", 0xFD), ("RET", 0xC3), ("RETF", 0xCB), ("IRET", 0xCF),
+    ("INT3", 0xCC), ("INTO", 0xCE), ("PUSHF", 0x9C), ("POPF", 0x9D), ("CBW", 0x98), ("CWD", 0x99),
+    ("LAHF", 0x9F), ("SAHF", 0x9E), ("XLAT", 0xD7), ("MOVSB", 0xA4), ("MOVSW", 0xA5), ("CMPSB", 0xA6),
+    ("CMPSW", 0xA7), ("STOSB", 0xAA), ("STOSW", 0xAB), ("LODSB", 0xAC), ("LODSW", 0xAD), ("SCASB", 0xAE),
+    ("SCASW", 0xAF), ("REP", 0xF3), ("REPNE", 0xF2), ("LOCK", 0xF0), ("WAIT", 0x9B), ("REPE", 0xF3),
+];
+
+const JCC_OPCODES: [(&str, u8); 30] = [
+    ("JO", 0x70), ("JNO", 0x71), ("JB", 0x72), ("JC", 0x72), ("JNAE", 0x72), ("JNB", 0x73), 
+    ("JAE", 0x73), ("JNC", 0x73), ("JZ", 0x74), ("JE", 0x74), ("JNZ", 0x75), ("JNE", 0x75), 
+    ("JBE", 0x76), ("JNA", 0x76), ("JNBE", 0x77), ("JA", 0x77), ("JS", 0x78), ("JNS", 0x79), 
+    ("JP", 0x7A), ("JPE", 0x7A), ("JNP", 0x7B), ("JPO", 0x7B), ("JL", 0x7C), ("JNGE", 0x7C), 
+    ("JNL", 0x7D), ("JGE", 0x7D), ("JLE", 0x7E), ("JNG", 0x7E), ("JNLE", 0x7F), ("JG", 0x7F),
+];
+
+const LOOP_OPCODES: [(&str, u8); 6] = [
+    ("LOOPNE", 0xE0), ("LOOPNZ", 0xE0), ("LOOPE", 0xE1), ("LOOPZ", 0xE1), ("LOOP", 0xE2), ("JCXZ", 0xE3),
+];
+
+/// ALU instructions, by the value of their reg field in group opcodes 80h and 81h.
+const ALU_MNEMONICS: [&str; 8] = ["ADD", "OR", "ADC", "SBB", "AND", "SUB", "XOR", "CMP"];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operand {
+    Reg8(u8),
+    Reg16(u8),
+    Seg(u8),
+    Imm(u16),
+    Far(u16, u16),
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    let s = s.strip_prefix("0X").unwrap_or(s);
+    let s = s.strip_suffix('H').unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_operand(s: &str) -> Option<Operand> {
+    let s = s.trim();
+    if let Some(i) = REG8.iter().position(|r| *r == s) {
+        return Some(Operand::Reg8(i as u8))
+    }
+    if let Some(i) = REG16.iter().position(|r| *r == s) {
+        return Some(Operand::Reg16(i as u8))
+    }
+    if let Some(i) = ["ES", "CS", "SS", "DS"].iter().position(|r| *r == s) {
+        return Some(Operand::Seg(i as u8))
+    }
+    if let Some((segment, offset)) = s.split_once(':') {
+        return Some(Operand::Far(parse_number(segment.trim())?, parse_number(offset.trim())?))
+    }
+    parse_number(s).map(Operand::Imm)
+}
+
+fn modrm_reg(reg: u8, rm: u8) -> u8 {
+    0xC0 | (reg << 3) | rm
+}
+
+/// Return the displacement from the end of an instruction to a target, if it fits in a byte.
+fn rel8(ip: u16, size: u16, target: u16) -> Option<u8> {
+    let disp = target.wrapping_sub(ip.wrapping_add(size)) as i16;
+    i8::try_from(disp).ok().map(|d| d as u8)
+}
+
+fn rel16(ip: u16, size: u16, target: u16) -> [u8; 2] {
+    target.wrapping_sub(ip.wrapping_add(size)).to_le_bytes()
+}
+
+/// Assemble a single instruction located at the specified offset in the code segment.
+pub fn assemble(text: &str, ip: u16) -> Result<Vec<u8>, AsmError> {
+    let text = text.trim().to_ascii_uppercase();
+    let (mnemonic, operand_str) = match text.split_once(char::is_whitespace) {
+        Some((m, o)) => (m, o.trim()),
+        None => (text.as_str(), ""),
+    };
+    if mnemonic.is_empty() {
+        return Err(AsmError::Empty)
+    }
+
+    let operands: Vec<Operand> = if operand_str.is_empty() {
+        Vec::new()
+    }
+    else {
+        operand_str.split(',')
+            .map(parse_operand)
+            .collect::<Option<_>>()
+            .ok_or_else(|| AsmError::BadOperands(mnemonic.to_string()))?
+    };
+    let bad_operands = || AsmError::BadOperands(mnemonic.to_string());
+
+    if let Some((_, opcode)) = NO_OPERAND_OPCODES.iter().find(|(m, _)| *m == mnemonic) {
+        return match (opcode, operands.as_slice()) {
+            (_, []) => Ok(vec![*opcode]),
+            (0xC3 | 0xCB, [Operand::Imm(imm)]) => {
+                let [lo, hi] = imm.to_le_bytes();
+                Ok(vec![opcode - 1, lo, hi])
+            }
+            _ => Err(bad_operands()),
+        }
+    }
+
+    if let Some((_, opcode)) = JCC_OPCODES.iter().chain(LOOP_OPCODES.iter()).find(|(m, _)| *m == mnemonic) {
+        return match operands.as_slice() {
+            [Operand::Imm(target)] => {
+                let disp = rel8(ip, 2, *target).ok_or(AsmError::OutOfRange(*target))?;
+                Ok(vec![*opcode, disp])
+            }
+            _ => Err(bad_operands()),
+        }
+    }
+
+    if let Some(alu) = ALU_MNEMONICS.iter().position(|m| *m == mnemonic) {
+        let base = (alu as u8) << 3;
+        return match operands.as_slice() {
+            [Operand::Reg8(dst), Operand::Reg8(src)] => Ok(vec![base, modrm_reg(*src, *dst)]),
+            [Operand::Reg16(dst), Operand::Reg16(src)] => Ok(vec![base | 0x01, modrm_reg(*src, *dst)]),
+            [Operand::Reg8(0), Operand::Imm(imm)] if *imm <= 0xFF => Ok(vec![base | 0x04, *imm as u8]),
+            [Operand::Reg16(0), Operand::Imm(imm)] => {
+                let [lo, hi] = imm.to_le_bytes();
+                Ok(vec![base | 0x05, lo, hi])
+            }
+            [Operand::Reg8(dst), Operand::Imm(imm)] if *imm <= 0xFF => Ok(vec![0x80, modrm_reg(alu as u8, *dst), *imm as u8]),
+            [Operand::Reg16(dst), Operand::Imm(imm)] => {
+                let [lo, hi] = imm.to_le_bytes();
+                Ok(vec![0x81, modrm_reg(alu as u8, *dst), lo, hi])
+            }
+            _ => Err(bad_operands()),
+        }
+    }
+
+    match (mnemonic, operands.as_slice()) {
+        ("INT", [Operand::Imm(3)]) => Ok(vec![0xCC]),
+        ("INT", [Operand::Imm(vector)]) if *vector <= 0xFF => Ok(vec![0xCD, *vector as u8]),
+        ("JMP", [Operand::Imm(target)]) => match rel8(ip, 2, *target) {
+            Some(disp) => Ok(vec![0xEB, disp]),
+            None => {
+                let [lo, hi] = rel16(ip, 3, *target);
+                Ok(vec![0xE9, lo, hi])
+            }
+        },
+        ("CALL", [Operand::Imm(target)]) => {
+            let [lo, hi] = rel16(ip, 3, *target);
+            Ok(vec![0xE8, lo, hi])
+        }
+        ("JMP" | "JMPF", [Operand::Far(segment, offset)]) | ("CALL" | "CALLF", [Operand::Far(segment, offset)]) => {
+            let opcode = if mnemonic.starts_with("JMP") { 0xEA } else { 0x9A };
+            let [off_lo, off_hi] = offset.to_le_bytes();
+            let [seg_lo, seg_hi] = segment.to_le_bytes();
+            Ok(vec![opcode, off_lo, off_hi, seg_lo, seg_hi])
+        }
+        ("PUSH", [Operand::Reg16(reg)]) => Ok(vec![0x50 + reg]),
+        ("POP", [Operand::Reg16(reg)]) => Ok(vec![0x58 + reg]),
+        ("PUSH", [Operand::Seg(seg)]) => Ok(vec![0x06 + (seg << 3)]),
+        // POP CS is not a valid instruction
+        ("POP", [Operand::Seg(seg)]) if *seg != 1 => Ok(vec![0x07 + (seg << 3)]),
+        ("INC", [Operand::Reg16(reg)]) => Ok(vec![0x40 + reg]),
+        ("DEC", [Operand::Reg16(reg)]) => Ok(vec![0x48 + reg]),
+        ("INC", [Operand::Reg8(reg)]) => Ok(vec![0xFE, modrm_reg(0, *reg)]),
+        ("DEC", [Operand::Reg8(reg)]) => Ok(vec![0xFE, modrm_reg(1, *reg)]),
+        ("MOV", [Operand::Reg8(dst), Operand::Imm(imm)]) if *imm <= 0xFF => Ok(vec![0xB0 + dst, *imm as u8]),
+        ("MOV", [Operand::Reg16(dst), Operand::Imm(imm)]) => {
+            let [lo, hi] = imm.to_le_bytes();
+            Ok(vec![0xB8 + dst, lo, hi])
+        }
+        ("MOV", [Operand::Reg8(dst), Operand::Reg8(src)]) => Ok(vec![0x88, modrm_reg(*src, *dst)]),
+        ("MOV", [Operand::Reg16(dst), Operand::Reg16(src)]) => Ok(vec![0x89, modrm_reg(*src, *dst)]),
+        ("MOV", [Operand::Seg(dst), Operand::Reg16(src)]) if *dst != 1 => Ok(vec![0x8E, modrm_reg(*dst, *src)]),
+        ("MOV", [Operand::Reg16(dst), Operand::Seg(src)]) => Ok(vec![0x8C, modrm_reg(*src, *dst)]),
+        ("INT" | "JMP" | "JMPF" | "CALL" | "CALLF" | "PUSH" | "POP" | "INC" | "DEC" | "MOV", _) => Err(bad_operands()),
+        _ => Err(AsmError::UnknownMnemonic(mnemonic.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_simple() {
+        assert_eq!(assemble("nop", 0), Ok(vec![0x90]));
+        assert_eq!(assemble("ret 4", 0), Ok(vec![0xC2, 0x04, 0x00]));
+        assert_eq!(assemble("int 21h", 0), Ok(vec![0xCD, 0x21]));
+        assert_eq!(assemble("int 3", 0), Ok(vec![0xCC]));
+        assert_eq!(assemble("mov ax, 4C00", 0), Ok(vec![0xB8, 0x00, 0x4C]));
+        assert_eq!(assemble("mov ah,0x4c", 0), Ok(vec![0xB4, 0x4C]));
+        assert_eq!(assemble("mov ds, ax", 0), Ok(vec![0x8E, 0xD8]));
+        assert_eq!(assemble("xor ax, ax", 0), Ok(vec![0x31, 0xC0]));
+        assert_eq!(assemble("cmp al, 1B", 0), Ok(vec![0x3C, 0x1B]));
+        assert_eq!(assemble("and bx, 00FF", 0), Ok(vec![0x81, 0xE3, 0xFF, 0x00]));
+        assert_eq!(assemble("push es", 0), Ok(vec![0x06]));
+        assert_eq!(assemble("pop ds", 0), Ok(vec![0x1F]));
+        assert_eq!(assemble("jmp f000:e05b", 0), Ok(vec![0xEA, 0x5B, 0xE0, 0x00, 0xF0]));
+    }
+
+    #[test]
+    fn test_assemble_relative() {
+        assert_eq!(assemble("jmp 0100", 0x0100), Ok(vec![0xEB, 0xFE]));
+        assert_eq!(assemble("jz 0110", 0x0100), Ok(vec![0x74, 0x0E]));
+        assert_eq!(assemble("jmp 1000", 0x0100), Ok(vec![0xE9, 0xFD, 0x0E]));
+        assert_eq!(assemble("call 0100", 0x0200), Ok(vec![0xE8, 0xFD, 0xFE]));
+        assert_eq!(assemble("loop 0100", 0x0100), Ok(vec![0xE2, 0xFE]));
+        assert_eq!(assemble("jnz 1000", 0x0100), Err(AsmError::OutOfRange(0x1000)));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(assemble("  ", 0), Err(AsmError::Empty));
+        assert_eq!(assemble("frob ax", 0), Err(AsmError::UnknownMnemonic("FROB".to_string())));
+        assert_eq!(assemble("mov [bx], ax", 0), Err(AsmError::BadOperands("MOV".to_string())));
+        assert_eq!(assemble("pop cs", 0), Err(AsmError::BadOperands("POP".to_string())));
+    }
+}
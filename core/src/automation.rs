@@ -0,0 +1,483 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    automation.rs
+
+    Implements an observation/action interface for external automation agents,
+    such as test scripts and game-playing bots.
+
+    The server listens on a local TCP port and accepts a single client at a
+    time. Requests are single lines of text. Every request receives a response
+    line beginning with 'ok' or 'err'. Some responses are followed by a body:
+
+    observe               ok frame=<n> cycles=<n> state=<s> mode=<m> idle=<b> lockstep=<b> speed=<f>
+    text                  ok <columns> <rows>, followed by <rows> lines of UTF-8 text,
+                          translated from the configured codepage
+    frame                 ok <w> <h> <len>, followed by <len> bytes of indexed color
+    key down|up <code>    press or release a keyboard scancode (decimal or 0x hex)
+    mouse <dx> <dy> <l> <r>
+                          move the mouse and set button state (0 or 1)
+    reset                 reboot the machine
+    turbo on|off          set the turbo button
+    speed <factor>        set the emulation speed, from 0.1 to 16 times real speed
+    lockstep on|off       only run the machine when frames are requested by 'step'
+    step <frames>         in lockstep mode, run the given number of frames. The
+                          response is sent once the frames have completed.
+
+    Lockstep mode is the determinism hook for agents: with it enabled, the 
+    emulated machine only advances when the agent asks it to, so observations
+    do not depend on host speed.
+
+    Requests beyond the configured rate limit are rejected with an error 
+    rather than queued, so a misbehaving client cannot stall the emulator.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant}
+};
+
+use crate::machine::{Machine, MachineState};
+use crate::speed::{MIN_SPEED, MAX_SPEED};
+use crate::videocard::RenderMode;
+
+pub const DEFAULT_AUTOMATION_RATE_LIMIT: u32 = 1000;
+
+const MAX_LINE_LEN: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AutomationCommand {
+    Observe,
+    Text,
+    Frame,
+    KeyDown(u8),
+    KeyUp(u8),
+    Mouse { dx: f64, dy: f64, left: bool, right: bool },
+    Reset,
+    Turbo(bool),
+    Speed(f64),
+    Lockstep(bool),
+    Step(u32),
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u32>().ok()
+    }
+}
+
+fn parse_switch(s: Option<&str>) -> Result<bool, String> {
+    match s {
+        Some("on") | Some("1") => Ok(true),
+        Some("off") | Some("0") => Ok(false),
+        _ => Err("expected 'on' or 'off'".to_string())
+    }
+}
+
+impl AutomationCommand {
+    /// Parse a single request line.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut args = line.split_whitespace();
+        let command = args.next().ok_or("empty request")?.to_lowercase();
+
+        let cmd = match command.as_str() {
+            "observe" => AutomationCommand::Observe,
+            "text" => AutomationCommand::Text,
+            "frame" => AutomationCommand::Frame,
+            "key" => {
+                let direction = args.next();
+                let code = args.next()
+                    .and_then(parse_u32)
+                    .filter(|c| *c < 0x80)
+                    .ok_or("expected a scancode between 0 and 0x7F")? as u8;
+                match direction {
+                    Some("down") => AutomationCommand::KeyDown(code),
+                    Some("up") => AutomationCommand::KeyUp(code),
+                    _ => return Err("expected 'down' or 'up'".to_string())
+                }
+            }
+            "mouse" => {
+                let mut next_f64 = || args.next().and_then(|s| s.parse::<f64>().ok());
+                match (next_f64(), next_f64(), next_f64(), next_f64()) {
+                    (Some(dx), Some(dy), Some(l), Some(r)) => {
+                        AutomationCommand::Mouse { dx, dy, left: l != 0.0, right: r != 0.0 }
+                    }
+                    _ => return Err("expected <dx> <dy> <left> <right>".to_string())
+                }
+            }
+            "reset" => AutomationCommand::Reset,
+            "turbo" => AutomationCommand::Turbo(parse_switch(args.next())?),
+            "speed" => {
+                let speed = args.next()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|s| (MIN_SPEED..=MAX_SPEED).contains(s))
+                    .ok_or(format!("expected a speed between {} and {}", MIN_SPEED, MAX_SPEED))?;
+                AutomationCommand::Speed(speed)
+            }
+            "lockstep" => AutomationCommand::Lockstep(parse_switch(args.next())?),
+            "step" => {
+                let frames = args.next()
+                    .and_then(parse_u32)
+                    .filter(|f| *f > 0)
+                    .ok_or("expected a frame count")?;
+                AutomationCommand::Step(frames)
+            }
+            _ => return Err(format!("unknown request '{}'", command))
+        };
+
+        if args.next().is_some() {
+            return Err("too many arguments".to_string())
+        }
+        Ok(cmd)
+    }
+}
+
+struct Client {
+    stream: TcpStream,
+    in_buf: Vec<u8>,
+    out_buf: Vec<u8>,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            in_buf: Vec::new(),
+            out_buf: Vec::new(),
+        })
+    }
+
+    /// Read any available data from the client. Returns false if the client disconnected.
+    fn read(&mut self) -> bool {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(n) => self.in_buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false
+            }
+        }
+    }
+
+    /// Take the next complete line from the input buffer.
+    fn next_line(&mut self) -> Option<Result<String, String>> {
+        match self.in_buf.iter().position(|b| *b == b'\n') {
+            Some(pos) => {
+                let line: Vec<u8> = self.in_buf.drain(..=pos).collect();
+                if line.len() > MAX_LINE_LEN {
+                    return Some(Err("request too long".to_string()))
+                }
+                Some(Ok(String::from_utf8_lossy(&line).trim().to_string()))
+            }
+            None if self.in_buf.len() > MAX_LINE_LEN => {
+                self.in_buf.clear();
+                Some(Err("request too long".to_string()))
+            }
+            None => None
+        }
+    }
+
+    /// Write as much pending output as the socket will accept. Returns false if the client
+    /// disconnected.
+    fn flush(&mut self) -> bool {
+        while !self.out_buf.is_empty() {
+            match self.stream.write(&self.out_buf) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.out_buf.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false
+            }
+        }
+        true
+    }
+}
+
+pub struct AutomationServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    rate_limit: u32,
+    rate_window_start: Instant,
+    rate_window_count: u32,
+    lockstep: bool,
+    pending_frames: u32,
+}
+
+impl AutomationServer {
+    /// Start listening for an automation client on the specified local port.
+    pub fn new(port: u16, rate_limit: u32) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        log::info!("Automation server listening on 127.0.0.1:{}", port);
+
+        Ok(Self {
+            listener,
+            client: None,
+            rate_limit: rate_limit.max(1),
+            rate_window_start: Instant::now(),
+            rate_window_count: 0,
+            lockstep: false,
+            pending_frames: 0,
+        })
+    }
+
+    /// Return whether the machine should run this frame. In lockstep mode the machine only 
+    /// runs while frames requested by 'step' are pending.
+    pub fn may_run(&self) -> bool {
+        !self.lockstep || self.pending_frames > 0
+    }
+
+    /// Notify the server that the machine has run a frame. Completes any pending step request.
+    pub fn frame_done(&mut self) {
+        if self.pending_frames > 0 {
+            self.pending_frames -= 1;
+            if self.pending_frames == 0 {
+                self.respond_ok("");
+            }
+        }
+    }
+
+    /// Accept new clients and process any requests received. Should be called once per frame.
+    pub fn poll(&mut self, machine: &mut Machine) {
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                if self.client.is_some() {
+                    log::warn!("Automation client already connected; rejecting connection from {}", addr);
+                }
+                else {
+                    match Client::new(stream) {
+                        Ok(client) => {
+                            log::info!("Automation client connected from {}", addr);
+                            self.client = Some(client);
+                        }
+                        Err(e) => log::error!("Error accepting automation client: {}", e)
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => log::error!("Error accepting automation client: {}", e)
+        }
+
+        let connected = match &mut self.client {
+            Some(client) => client.read(),
+            None => return
+        };
+
+        // Requests are not processed while a step is in progress, so that responses stay in 
+        // order.
+        while connected && self.pending_frames == 0 {
+            let line = match self.client.as_mut().and_then(|c| c.next_line()) {
+                Some(line) => line,
+                None => break
+            };
+            if matches!(&line, Ok(l) if l.is_empty()) {
+                continue;
+            }
+            if !self.check_rate() {
+                self.respond_err("rate limit exceeded");
+                continue;
+            }
+            match line.and_then(|l| AutomationCommand::parse(&l)) {
+                Ok(cmd) => self.execute(cmd, machine),
+                Err(e) => self.respond_err(&e)
+            }
+        }
+
+        let flushed = match &mut self.client {
+            Some(client) => client.flush(),
+            None => true
+        };
+        if !connected || !flushed {
+            log::info!("Automation client disconnected");
+            self.client = None;
+            self.pending_frames = 0;
+        }
+    }
+
+    fn check_rate(&mut self) -> bool {
+        if self.rate_window_start.elapsed() >= Duration::from_secs(1) {
+            self.rate_window_start = Instant::now();
+            self.rate_window_count = 0;
+        }
+        self.rate_window_count += 1;
+        self.rate_window_count <= self.rate_limit
+    }
+
+    fn respond(&mut self, bytes: &[u8]) {
+        if let Some(client) = &mut self.client {
+            client.out_buf.extend_from_slice(bytes);
+        }
+    }
+
+    fn respond_ok(&mut self, msg: &str) {
+        let line = if msg.is_empty() { "ok\n".to_string() } else { format!("ok {}\n", msg) };
+        self.respond(line.as_bytes());
+    }
+
+    fn respond_err(&mut self, msg: &str) {
+        self.respond(format!("err {}\n", msg).as_bytes());
+    }
+
+    fn execute(&mut self, cmd: AutomationCommand, machine: &mut Machine) {
+        match cmd {
+            AutomationCommand::Observe => {
+                let (frame, mode) = match machine.videocard() {
+                    Some(card) => (card.get_frame_count(), format!("{:?}", card.get_display_mode())),
+                    None => (0, "None".to_string())
+                };
+                let msg = format!(
+                    "frame={} cycles={} state={:?} mode={} idle={} lockstep={} speed={}",
+                    frame,
+                    machine.cpu_cycles(),
+                    machine.get_state(),
+                    mode,
+                    machine.is_idle(),
+                    self.lockstep,
+                    machine.speed()
+                );
+                self.respond_ok(&msg);
+            }
+            AutomationCommand::Text => {
+                let screen = machine.videocard().and_then(|card| card.get_text_screen());
+                match screen {
+                    Some(screen) => {
+                        let mut body = format!("ok {} {}\n", screen.columns, screen.rows);
+                        // Control characters are decoded to the symbols they display as, so 
+                        // the response stays line-based.
+                        let codepage = machine.codepage();
+                        for row in screen.chars.chunks(screen.columns.max(1) as usize) {
+                            body.push_str(&codepage.decode(row));
+                            body.push('\n');
+                        }
+                        self.respond(body.as_bytes());
+                    }
+                    None => self.respond_err("no text screen available")
+                }
+            }
+            AutomationCommand::Frame => {
+                let frame = machine.videocard().and_then(|card| {
+                    if matches!(card.get_render_mode(), RenderMode::Indirect) {
+                        return None
+                    }
+                    let extents = card.get_display_extents();
+                    let buf = card.get_display_buf();
+                    let mut pixels = Vec::with_capacity((extents.aperture_w * extents.aperture_h) as usize);
+                    for y in 0..extents.aperture_h as usize {
+                        let start = (extents.aperture_y as usize + y) * extents.row_stride + extents.aperture_x as usize;
+                        let end = (start + extents.aperture_w as usize).min(buf.len());
+                        pixels.extend_from_slice(&buf[start.min(end)..end]);
+                    }
+                    Some((extents.aperture_w, extents.aperture_h, pixels))
+                });
+                match frame {
+                    Some((w, h, pixels)) => {
+                        self.respond(format!("ok {} {} {}\n", w, h, pixels.len()).as_bytes());
+                        self.respond(&pixels);
+                    }
+                    None => self.respond_err("frame capture not supported by this video card")
+                }
+            }
+            AutomationCommand::KeyDown(code) => {
+                machine.key_press(code);
+                self.respond_ok("");
+            }
+            AutomationCommand::KeyUp(code) => {
+                machine.key_release(code);
+                self.respond_ok("");
+            }
+            AutomationCommand::Mouse { dx, dy, left, right } => {
+                match machine.mouse_update(left, right, dx, dy) {
+                    true => self.respond_ok(""),
+                    false => self.respond_err("no mouse installed")
+                }
+            }
+            AutomationCommand::Reset => {
+                machine.change_state(MachineState::Rebooting);
+                self.respond_ok("");
+            }
+            AutomationCommand::Turbo(state) => {
+                machine.set_turbo_mode(state);
+                self.respond_ok("");
+            }
+            AutomationCommand::Speed(speed) => {
+                machine.set_speed(speed);
+                self.respond_ok("");
+            }
+            AutomationCommand::Lockstep(state) => {
+                self.lockstep = state;
+                self.respond_ok("");
+            }
+            AutomationCommand::Step(frames) => {
+                if self.lockstep {
+                    // Response is sent by frame_done() once all frames have run.
+                    self.pending_frames = frames;
+                }
+                else {
+                    self.respond_err("step requires lockstep mode");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(AutomationCommand::parse("observe"), Ok(AutomationCommand::Observe));
+        assert_eq!(AutomationCommand::parse("key down 0x1C"), Ok(AutomationCommand::KeyDown(0x1C)));
+        assert_eq!(AutomationCommand::parse("key up 28"), Ok(AutomationCommand::KeyUp(28)));
+        assert_eq!(AutomationCommand::parse("TURBO on"), Ok(AutomationCommand::Turbo(true)));
+        assert_eq!(AutomationCommand::parse("step 10"), Ok(AutomationCommand::Step(10)));
+        assert_eq!(AutomationCommand::parse("speed 2.5"), Ok(AutomationCommand::Speed(2.5)));
+        assert_eq!(
+            AutomationCommand::parse("mouse -4 2.5 1 0"), 
+            Ok(AutomationCommand::Mouse { dx: -4.0, dy: 2.5, left: true, right: false })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(AutomationCommand::parse("").is_err());
+        assert!(AutomationCommand::parse("jump").is_err());
+        assert!(AutomationCommand::parse("key down 0x80").is_err());
+        assert!(AutomationCommand::parse("key left 1").is_err());
+        assert!(AutomationCommand::parse("step 0").is_err());
+        assert!(AutomationCommand::parse("reset now").is_err());
+        assert!(AutomationCommand::parse("lockstep maybe").is_err());
+        assert!(AutomationCommand::parse("speed 32").is_err());
+    }
+}
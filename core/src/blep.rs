@@ -0,0 +1,174 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+
+    --------------------------------------------------------------------------
+
+    blep.rs
+
+    Implements band-limited step synthesis for square wave sources such as 
+    the PC speaker.
+
+    The speaker is driven by a 1-bit signal that can change level on any 
+    PIT clock, over 25 times per audio sample. Rather than averaging the 
+    levels within each sample, which aliases high tones into audible noise, 
+    each level change is added to the output as a band-limited impulse 
+    placed at its position within the sample, and the impulses are 
+    integrated into the output waveform. This delays the output by half 
+    the kernel length.
+*/
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Length of the band-limited impulse, in output samples.
+const KERNEL_TAPS: usize = 16;
+/// Number of sub-sample positions the impulse is tabulated at.
+const KERNEL_PHASES: usize = 32;
+/// Cutoff of the impulse as a fraction of the output sample rate.
+const KERNEL_CUTOFF: f64 = 0.45;
+
+pub struct BlepSynth {
+    kernel: Vec<[f32; KERNEL_TAPS]>,
+    deltas: VecDeque<f32>,
+    level: f32,
+    integrator: f32,
+    settle: usize,
+}
+
+impl Default for BlepSynth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlepSynth {
+    pub fn new() -> Self {
+        Self {
+            kernel: Self::make_kernel(),
+            deltas: VecDeque::from(vec![0.0; KERNEL_TAPS]),
+            level: 0.0,
+            integrator: 0.0,
+            settle: 0,
+        }
+    }
+
+    /// Tabulate a Blackman-windowed sinc impulse at each sub-sample phase. Each phase is 
+    /// normalized to sum to 1 so that a step integrates to exactly its height.
+    fn make_kernel() -> Vec<[f32; KERNEL_TAPS]> {
+        (0..=KERNEL_PHASES).map(|phase| {
+            let offset = phase as f64 / KERNEL_PHASES as f64;
+            let mut taps = [0.0f64; KERNEL_TAPS];
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - (KERNEL_TAPS / 2) as f64 - offset;
+                let sinc = match x == 0.0 {
+                    true => 1.0,
+                    false => (2.0 * PI * KERNEL_CUTOFF * x).sin() / (2.0 * PI * KERNEL_CUTOFF * x),
+                };
+                let w = (k as f64 - offset + 0.5) / KERNEL_TAPS as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            let mut kernel = [0.0f32; KERNEL_TAPS];
+            for (dst, tap) in kernel.iter_mut().zip(taps) {
+                *dst = (tap / sum) as f32;
+            }
+            kernel
+        }).collect()
+    }
+
+    /// Change the output level at the specified position within the current output sample,
+    /// from 0.0 (the start of the sample) to 1.0 (the start of the next).
+    pub fn set_level(&mut self, position: f32, level: f32) {
+        let delta = level - self.level;
+        if delta == 0.0 {
+            return
+        }
+        self.level = level;
+
+        let phase = (position.clamp(0.0, 1.0) * KERNEL_PHASES as f32).round() as usize;
+        for (dst, tap) in self.deltas.iter_mut().zip(self.kernel[phase].iter()) {
+            *dst += delta * tap;
+        }
+        self.settle = KERNEL_TAPS;
+    }
+
+    /// Complete the current output sample and return it.
+    pub fn read_sample(&mut self) -> f32 {
+        self.integrator += self.deltas.pop_front().unwrap_or(0.0);
+        self.deltas.push_back(0.0);
+
+        // Once every impulse has been integrated, snap to the level to keep rounding errors 
+        // from accumulating.
+        if self.settle > 0 {
+            self.settle -= 1;
+            if self.settle == 0 {
+                self.integrator = self.level;
+            }
+        }
+        self.integrator
+    }
+
+    pub fn reset(&mut self) {
+        self.deltas.iter_mut().for_each(|d| *d = 0.0);
+        self.level = 0.0;
+        self.integrator = 0.0;
+        self.settle = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_settles_to_level() {
+        let mut synth = BlepSynth::new();
+        synth.set_level(0.3, 1.0);
+        let samples: Vec<f32> = (0..KERNEL_TAPS * 2).map(|_| synth.read_sample()).collect();
+
+        // The step is centered half a kernel later.
+        assert!(samples[KERNEL_TAPS / 2 - 2] < 0.1);
+        assert!(samples[KERNEL_TAPS / 2 + 2] > 0.9);
+        assert!(samples[KERNEL_TAPS..].iter().all(|s| *s == 1.0));
+    }
+
+    #[test]
+    fn ultrasonic_square_wave_averages() {
+        // Toggle the level twice per sample, far above the cutoff. The output should hold
+        // steady at the average level rather than alias.
+        let mut synth = BlepSynth::new();
+        let mut samples = Vec::new();
+        for _ in 0..200 {
+            synth.set_level(0.1, 1.0);
+            synth.set_level(0.6, 0.0);
+            samples.push(synth.read_sample());
+        }
+        for s in &samples[KERNEL_TAPS..] {
+            assert!((s - 0.5).abs() < 0.05, "sample {} not near 0.5", s);
+        }
+    }
+}
@@ -26,18 +26,417 @@
 
     breakpoints.rs
 
-    Implement enum for breakpoint definitions.
+    Implement enum for breakpoint definitions, and a small expression parser
+    for breakpoint conditions.
 
+    A condition is one or more comparisons joined by && or ||, where && binds
+    tighter. Each side of a comparison is a register, a hex value, or a byte
+    or word of memory:
+
+        ax == 1234 && byte [ds:si] != 0
+        word [es:di] >= 8000 || cx == 0
+
+    Memory operands take the same segment:offset forms as the debugger's
+    address fields. An offset without a segment is relative to DS, unless it
+    is a five digit flat address.
 */
 
+use std::fmt;
+
+use crate::cpu_808x::{Register8, Register16};
+
 #[allow(dead_code)]
+#[derive (Clone)]
 pub enum BreakPointType {
 
     Execute(u16, u16), // Breakpoint on CS:IP
     ExecuteOffset(u16), // Breakpoint on *::IP
     ExecuteFlat(u32), // Breakpoint on CS<<4+IP
+    ExecuteFlatIf(u32, BreakPointCondition), // Breakpoint on CS<<4+IP when condition is true
     MemAccess(u16, u16), // Breakpoint on memory access, seg::offset
     MemAccessFlat(u32), // Breakpoint on memory access, seg<<4+offset
+    Watch(u32, u32, WatchType), // Watchpoint on memory range, start and length
     Interrupt(u8), // Breakpoint on interrupt #
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WatchType {
+    Read,
+    Write,
+    #[default]
+    ReadWrite,
+}
+
+impl WatchType {
+    pub fn matches(&self, write: bool) -> bool {
+        match self {
+            WatchType::Read => !write,
+            WatchType::Write => write,
+            WatchType::ReadWrite => true,
+        }
+    }
+}
+
+impl fmt::Display for WatchType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchType::Read => write!(f, "Read"),
+            WatchType::Write => write!(f, "Write"),
+            WatchType::ReadWrite => write!(f, "Read/Write"),
+        }
+    }
+}
+
+/// Provides the machine state a breakpoint condition is evaluated against.
+pub trait ConditionContext {
+    fn register8(&self, reg: Register8) -> u8;
+    fn register16(&self, reg: Register16) -> u16;
+    fn peek_u8(&self, address: u32) -> u8;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Term {
+    Value(u32),
+    Register(Register16),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operand {
+    Value(u16),
+    Reg8(Register8),
+    Reg16(Register16),
+    Mem8(Option<Term>, Term),
+    Mem16(Option<Term>, Term),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Comparison {
+    lhs: Operand,
+    op: CompareOp,
+    rhs: Operand,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BreakPointCondition {
+    // A list of alternatives, each of which is a list of comparisons that must all be true.
+    any: Vec<Vec<Comparison>>,
+}
+
+impl BreakPointCondition {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+
+        let mut any = Vec::new();
+        let mut all = vec![parser.comparison()?];
+        while let Some(token) = parser.next() {
+            match token.as_str() {
+                "&&" => all.push(parser.comparison()?),
+                "||" => {
+                    any.push(std::mem::take(&mut all));
+                    all.push(parser.comparison()?);
+                }
+                _ => return Err(format!("Unexpected '{}'", token)),
+            }
+        }
+        any.push(all);
+        Ok(Self { any })
+    }
+
+    pub fn evaluate(&self, ctx: &dyn ConditionContext) -> bool {
+        self.any.iter().any(|all| all.iter().all(|cmp| cmp.evaluate(ctx)))
+    }
+}
+
+impl Comparison {
+    fn evaluate(&self, ctx: &dyn ConditionContext) -> bool {
+        let lhs = self.lhs.evaluate(ctx);
+        let rhs = self.rhs.evaluate(ctx);
+        match self.op {
+            CompareOp::Equal => lhs == rhs,
+            CompareOp::NotEqual => lhs != rhs,
+            CompareOp::Less => lhs < rhs,
+            CompareOp::LessEqual => lhs <= rhs,
+            CompareOp::Greater => lhs > rhs,
+            CompareOp::GreaterEqual => lhs >= rhs,
+        }
+    }
+}
+
+impl Term {
+    fn evaluate(&self, ctx: &dyn ConditionContext) -> u32 {
+        match self {
+            Term::Value(v) => *v,
+            Term::Register(reg) => ctx.register16(*reg) as u32,
+        }
+    }
+}
+
+impl Operand {
+    fn address(segment: &Option<Term>, offset: &Term, ctx: &dyn ConditionContext) -> u32 {
+        match (segment, offset) {
+            (Some(segment), offset) => {
+                ((segment.evaluate(ctx) << 4) + (offset.evaluate(ctx) & 0xFFFF)) & 0xFFFFF
+            }
+            (None, Term::Value(flat)) if *flat > 0xFFFF => *flat & 0xFFFFF,
+            (None, offset) => {
+                (((ctx.register16(Register16::DS) as u32) << 4) + offset.evaluate(ctx)) & 0xFFFFF
+            }
+        }
+    }
+
+    fn evaluate(&self, ctx: &dyn ConditionContext) -> u16 {
+        match self {
+            Operand::Value(v) => *v,
+            Operand::Reg8(reg) => ctx.register8(*reg) as u16,
+            Operand::Reg16(reg) => ctx.register16(*reg),
+            Operand::Mem8(segment, offset) => {
+                ctx.peek_u8(Operand::address(segment, offset, ctx)) as u16
+            }
+            Operand::Mem16(segment, offset) => {
+                let address = Operand::address(segment, offset, ctx);
+                ctx.peek_u8(address) as u16 | (ctx.peek_u8((address + 1) & 0xFFFFF) as u16) << 8
+            }
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '[' | ']' | ':' => tokens.push(c.to_string()),
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                let mut token = c.to_string();
+                if let Some(&next) = chars.peek() {
+                    if next == '=' || (next == c && (c == '&' || c == '|')) {
+                        token.push(next);
+                        chars.next();
+                    }
+                }
+                tokens.push(token);
+            }
+            c if c.is_ascii_alphanumeric() => {
+                let mut token = c.to_ascii_lowercase().to_string();
+                while let Some(&next) = chars.peek() {
+                    if !next.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    token.push(next.to_ascii_lowercase());
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+            _ => return Err(format!("Unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn parse_register8(token: &str) -> Option<Register8> {
+    match token {
+        "al" => Some(Register8::AL),
+        "cl" => Some(Register8::CL),
+        "dl" => Some(Register8::DL),
+        "bl" => Some(Register8::BL),
+        "ah" => Some(Register8::AH),
+        "ch" => Some(Register8::CH),
+        "dh" => Some(Register8::DH),
+        "bh" => Some(Register8::BH),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_register16(token: &str) -> Option<Register16> {
+    match token {
+        "ax" => Some(Register16::AX),
+        "cx" => Some(Register16::CX),
+        "dx" => Some(Register16::DX),
+        "bx" => Some(Register16::BX),
+        "sp" => Some(Register16::SP),
+        "bp" => Some(Register16::BP),
+        "si" => Some(Register16::SI),
+        "di" => Some(Register16::DI),
+        "es" => Some(Register16::ES),
+        "cs" => Some(Register16::CS),
+        "ss" => Some(Register16::SS),
+        "ds" => Some(Register16::DS),
+        "ip" => Some(Register16::IP),
+        _ => None,
+    }
+}
+
+fn parse_value(token: &str) -> Option<u32> {
+    let digits = token.strip_prefix("0x").or_else(|| token.strip_suffix('h')).unwrap_or(token);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a String> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("Expected '{}', found '{}'", expected, token)),
+            None => Err(format!("Expected '{}'", expected)),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, String> {
+        let lhs = self.operand()?;
+        let op = match self.next().map(|t| t.as_str()) {
+            Some("==") => CompareOp::Equal,
+            Some("!=") => CompareOp::NotEqual,
+            Some("<") => CompareOp::Less,
+            Some("<=") => CompareOp::LessEqual,
+            Some(">") => CompareOp::Greater,
+            Some(">=") => CompareOp::GreaterEqual,
+            Some(token) => return Err(format!("Expected comparison, found '{}'", token)),
+            None => return Err("Expected comparison".to_string()),
+        };
+        let rhs = self.operand()?;
+        Ok(Comparison { lhs, op, rhs })
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        let token = self.next().ok_or("Expected operand")?;
+        match token.as_str() {
+            "byte" => {
+                self.expect("[")?;
+                let (segment, offset) = self.memory()?;
+                Ok(Operand::Mem8(segment, offset))
+            }
+            "word" => {
+                self.expect("[")?;
+                let (segment, offset) = self.memory()?;
+                Ok(Operand::Mem16(segment, offset))
+            }
+            "[" => {
+                let (segment, offset) = self.memory()?;
+                Ok(Operand::Mem8(segment, offset))
+            }
+            t => {
+                if let Some(reg) = parse_register8(t) {
+                    Ok(Operand::Reg8(reg))
+                }
+                else if let Some(reg) = parse_register16(t) {
+                    Ok(Operand::Reg16(reg))
+                }
+                else {
+                    match parse_value(t) {
+                        Some(v) if v <= 0xFFFF => Ok(Operand::Value(v as u16)),
+                        _ => Err(format!("Invalid operand '{}'", t)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, String> {
+        let token = self.next().ok_or("Expected address")?;
+        if let Some(reg) = parse_register16(token) {
+            Ok(Term::Register(reg))
+        }
+        else {
+            match parse_value(token) {
+                Some(v) if v <= 0xFFFFF => Ok(Term::Value(v)),
+                _ => Err(format!("Invalid address '{}'", token)),
+            }
+        }
+    }
+
+    // Parse the remainder of a memory operand after the opening bracket.
+    fn memory(&mut self) -> Result<(Option<Term>, Term), String> {
+        let first = self.term()?;
+        match self.next().map(|t| t.as_str()) {
+            Some("]") => Ok((None, first)),
+            Some(":") => {
+                let offset = self.term()?;
+                self.expect("]")?;
+                Ok((Some(first), offset))
+            }
+            Some(token) => Err(format!("Expected ']', found '{}'", token)),
+            None => Err("Expected ']'".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestContext {
+        mem: Vec<u8>,
+    }
+
+    impl ConditionContext for TestContext {
+        fn register8(&self, reg: Register8) -> u8 {
+            match reg {
+                Register8::AL => 0x34,
+                Register8::AH => 0x12,
+                _ => 0,
+            }
+        }
+        fn register16(&self, reg: Register16) -> u16 {
+            match reg {
+                Register16::AX => 0x1234,
+                Register16::DS => 0x0010,
+                Register16::SI => 0x0004,
+                _ => 0,
+            }
+        }
+        fn peek_u8(&self, address: u32) -> u8 {
+            self.mem[address as usize]
+        }
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut ctx = TestContext { mem: vec![0; 0x200] };
+        ctx.mem[0x104] = 0x41;
+        ctx.mem[0x105] = 0x42;
+
+        let eval = |expr: &str, ctx: &TestContext| BreakPointCondition::parse(expr).unwrap().evaluate(ctx);
+
+        assert!(eval("ax == 1234", &ctx));
+        assert!(eval("AL == 34h && ah < 0x13", &ctx));
+        assert!(eval("byte [ds:si] == 41", &ctx));
+        assert!(eval("word [si] == 4241", &ctx));
+        assert!(eval("[0010:0005] == 42", &ctx));
+        assert!(!eval("ax != 1234 && cx == 0", &ctx));
+        assert!(eval("ax != 1234 && cx == 0 || bx == 0", &ctx));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(BreakPointCondition::parse("").is_err());
+        assert!(BreakPointCondition::parse("ax ==").is_err());
+        assert!(BreakPointCondition::parse("ax = 1").is_err());
+        assert!(BreakPointCondition::parse("byte [ds:si == 1").is_err());
+        assert!(BreakPointCondition::parse("ax == 10000").is_err());
+        assert!(BreakPointCondition::parse("ax == 1 bx").is_err());
+    }
+}
+
@@ -36,7 +36,6 @@
 
 #![allow(dead_code)]
 use std::{
-    collections::HashMap,
     fmt,
     path::Path
 };
@@ -47,8 +46,8 @@ use crate::cpu_808x::*;
 use crate::bytequeue::*;
 
 use crate::syntax_token::SyntaxToken;
-use crate::machine_manager::MachineDescriptor;
-use crate::config::VideoType;
+use crate::machine_manager::{MachineDescriptor, CONVENTIONAL_RAM_MAX};
+use crate::config::{MachineType, VideoType};
 
 use crate::devices::{
     pit::Pit,
@@ -58,24 +57,44 @@ use crate::devices::{
     serial::*,
     fdc::FloppyController,
     hdc::*,
-    mouse::*
+    xtide::XtIdeController,
+    mouse::*,
+    adlib::AdLibCard,
+    sb::SoundBlaster,
+    timer_card::TimerCard,
+    a20::{self, A20Gate},
+    rtc_card::{RtcCard, RtcTimeSource},
+    game_port::GamePort,
+    ne2000::Ne2000,
+    sn76489::Sn76489
 };
 
 use crate::tracelogger::TraceLogger;
 use crate::videocard::{VideoCard, VideoCardDispatch};
 
-use crate::devices::cga::{self, CGACard};
+use crate::devices::cga::{self, CGACard, CgaFont};
+use crate::devices::mda::{self, MDACard};
 #[cfg(feature = "ega")]
 use crate::devices::ega::{self, EGACard};
 #[cfg(feature = "vga")]
 use crate::devices::vga::{self, VGACard};
+use crate::coverage::{CoverageMap, CoverageSummary};
+use crate::heatmap::{AccessSource, HeatmapRecorder, PageActivity};
+use crate::device_manager::{DeviceManager, IoConflict};
 use crate::memerror::MemError;
+use crate::mem_search::{SearchDirection, find_pattern};
+use crate::network::{packet::MacAddress, NetworkBackend};
+use crate::savestate::{SaveState, SaveStateError, StateFile, StateReader, StateWriter};
 
 pub const NO_IO_BYTE: u8 = 0xFF; // This is the byte read from a unconnected IO address.
 pub const FLOATING_BUS_BYTE: u8 = 0x00; // This is the byte read from an unmapped memory address.
 
 const ADDRESS_SPACE: usize = 1_048_576;
 const DEFAULT_WAIT_STATES: u32 = 0;
+// The motherboard inserts one wait state into every IO bus cycle.
+const IO_WAIT_STATES: u32 = 1;
+
+const MAX_DEVICE_ACCESSES: usize = 256;
 
 const MMIO_MAP_SIZE: usize =  0x2000;
 const MMIO_MAP_SHIFT: usize = 13;
@@ -87,10 +106,11 @@ pub const MEM_BPA_BIT: u8   = 0b0001_0000; // Bit to signify that this address i
 pub const MEM_CP_BIT: u8    = 0b0000_1000; // Bit to signify that this address is a ROM checkpoint
 pub const MEM_MMIO_BIT: u8  = 0b0000_0100; // Bit to signify that this address is MMIO mapped
 
-#[derive (Copy, Clone, Debug)]
+#[derive (Copy, Clone, Debug, PartialEq)]
 pub enum ClockFactor {
     Divisor(u8),
-    Multiplier(u8)
+    Multiplier(u8),
+    Ratio(u32, u32)     // .0 system ticks elapse for every .1 CPU cycles
 }
 
 #[derive (Copy, Clone, Debug)]
@@ -99,6 +119,37 @@ pub enum DeviceRunTimeUnit {
     Microseconds(f64),
 }
 
+/// Identifies the bus master that initiated a memory transaction.
+#[derive (Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BusInitiator {
+    Cpu,
+    Dma(u8), // DMA transfer on the specified channel
+}
+
+impl fmt::Display for BusInitiator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusInitiator::Cpu => write!(f, "CPU"),
+            BusInitiator::Dma(channel) => write!(f, "DMA channel {}", channel),
+        }
+    }
+}
+
+/// A memory access to a single address, tagged with the bus master that made it.
+#[derive (Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub address: u32,
+    pub write: bool,
+    pub initiator: BusInitiator,
+}
+
+impl fmt::Display for BusAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.write { "Write" } else { "Read" };
+        write!(f, "{} [{:05X}] by {}", kind, self.address, self.initiator)
+    }
+}
+
 pub enum DeviceEvent {
     DramRefreshUpdate(u16, u16)
 }
@@ -111,6 +162,12 @@ pub trait MemoryMappedDevice {
     fn get_write_wait(&mut self, address: usize, cycles: u32) -> u32;
     fn mmio_write_u8(&mut self, address: usize, data: u8, cycles: u32) -> u32; 
     fn mmio_write_u16(&mut self, address: usize, data: u16, cycles: u32) -> u32;
+
+    /// Return the system RAM address that a write to the specified address must also update,
+    /// for devices whose memory is shared with system RAM.
+    fn mmio_ram_alias(&self, _address: usize) -> Option<usize> {
+        None
+    }
 }
 
 pub struct MemoryDebug {
@@ -145,6 +202,7 @@ impl MemRangeDescriptor {
     }
 }
 
+#[derive (Copy, Clone, Debug, PartialEq)]
 pub enum IoDeviceType {
     Ppi,
     Pit,
@@ -155,12 +213,72 @@ pub enum IoDeviceType {
     Serial,
     FloppyController,
     HardDiskController,
+    XtIde,
     Mouse,
+    AdLib,
+    SoundBlaster,
+    TimerCard,
+    GamePort,
+    Ne2000,
+    Sn76489,
+    Mda,
     Cga,
     Ega,
     Vga,
+    A20Gate,
+    RtcCard,
+}
+
+impl fmt::Display for IoDeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IoDeviceType::Ppi => "PPI",
+            IoDeviceType::Pit => "PIT",
+            IoDeviceType::DmaPrimary => "primary DMA controller",
+            IoDeviceType::DmaSecondary => "secondary DMA controller",
+            IoDeviceType::PicPrimary => "primary PIC",
+            IoDeviceType::PicSecondary => "secondary PIC",
+            IoDeviceType::Serial => "serial port controller",
+            IoDeviceType::FloppyController => "floppy disk controller",
+            IoDeviceType::HardDiskController => "Xebec hard disk controller",
+            IoDeviceType::XtIde => "XT-IDE controller",
+            IoDeviceType::Mouse => "mouse",
+            IoDeviceType::AdLib => "AdLib card",
+            IoDeviceType::SoundBlaster => "Sound Blaster card",
+            IoDeviceType::TimerCard => "timer card",
+            IoDeviceType::GamePort => "game port",
+            IoDeviceType::Ne2000 => "NE2000 network card",
+            IoDeviceType::Sn76489 => "SN76489 sound generator",
+            IoDeviceType::Mda => "MDA card",
+            IoDeviceType::Cga => "CGA card",
+            IoDeviceType::Ega => "EGA card",
+            IoDeviceType::Vga => "VGA card",
+            IoDeviceType::A20Gate => "A20 gate",
+            IoDeviceType::RtcCard => "clock card",
+        };
+        write!(f, "{}", name)
+    }
 }
 
+/// Selects which of the devices present on every machine are installed.
+#[derive(Copy, Clone, Debug)]
+pub struct BuiltinDevices {
+    pub fdc: bool,
+    pub hdc: bool,
+    pub serial: bool,
+    pub video: bool,
+}
+
+impl Default for BuiltinDevices {
+    fn default() -> Self {
+        Self {
+            fdc: true,
+            hdc: true,
+            serial: true,
+            video: true,
+        }
+    }
+}
 
 pub enum IoDeviceDispatch {
     Static(IoDeviceType),
@@ -187,17 +305,69 @@ impl MmioData {
     }
 }
 
-#[derive (Copy, Clone)]
+#[derive (Copy, Clone, Debug, PartialEq)]
 pub enum MmioDeviceType {
     None,
     Memory,
     Video,
+    Mda,
     Cga,
     Ega,
     Vga,
     Rom
 }
 
+/// How the bus routes accesses to a memory-mapped region.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum MmioAccess {
+    /// Reads and writes are handled entirely by the device.
+    ReadWrite,
+    /// The region is backed by system memory. Reads come from memory, and writes update memory
+    /// and are also passed on to the device (used for the PCjr, whose video memory is system RAM).
+    WriteThrough,
+}
+
+/// Where the wait states for accesses to a memory-mapped region come from.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum MmioWaits {
+    /// The device reports wait states for each access.
+    Device,
+    /// Accesses take the wait states of system memory.
+    Memory,
+}
+
+/// A range of the address space claimed by a memory-mapped device. Address and size must be
+/// multiples of MMIO_MAP_SIZE.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct MmioRegion {
+    pub device: MmioDeviceType,
+    pub address: usize,
+    pub size: usize,
+    pub access: MmioAccess,
+    pub waits: MmioWaits,
+}
+
+
+/// The kind of a region of the address space, for annotating memory displays.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryRegionKind {
+    Rom,
+    VideoMemory,
+    EmsPageFrame,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryRegion {
+    pub kind: MemoryRegionKind,
+    pub address: usize,
+    pub size: usize,
+}
+
+impl MemoryRegion {
+    pub fn contains(&self, address: usize) -> bool {
+        address >= self.address && address < self.address + self.size
+    }
+}
 
 // Main bus struct.
 // Bus contains both the system memory and IO, and owns all connected devices.
@@ -212,13 +382,22 @@ pub struct BusInterface {
     machine_desc: Option<MachineDescriptor>,
     memory: Vec<u8>,
     memory_mask: Vec<u8>,
+    conventional_ram: usize,
+    address_mask: usize,
+    active_address_mask: usize,
     desc_vec: Vec<MemRangeDescriptor>,
-    mmio_map: Vec<(MemRangeDescriptor, MmioDeviceType)>,
-    mmio_map_fast: [MmioDeviceType; 128],
+    wait_regions: Vec<MemRangeDescriptor>,
+    video_wait_states: bool,
+    mmio_regions: Vec<MmioRegion>,
+    mmio_map_fast: [Option<u8>; ADDRESS_SPACE >> MMIO_MAP_SHIFT],
     mmio_data: MmioData,
+    ems_page_frame: Option<usize>,
+    coverage: CoverageMap,
+    coverage_enabled: bool,
+    heatmap: HeatmapRecorder,
     cursor: usize,
 
-    io_map: HashMap<u16, IoDeviceType>,
+    io_map: DeviceManager,
     ppi: Option<Ppi>,
     pit: Option<Pit>,
     dma_counter: u16,
@@ -229,15 +408,30 @@ pub struct BusInterface {
     serial: Option<SerialPortController>,
     fdc: Option<FloppyController>,
     hdc: Option<HardDiskController>,
+    xtide: Option<XtIdeController>,
     mouse: Option<Mouse>,
+    adlib: Option<AdLibCard>,
+    sb: Option<SoundBlaster>,
+    timer_card: Option<TimerCard>,
+    a20_gate: Option<A20Gate>,
+    rtc_card: Option<RtcCard>,
+    game_port: Option<GamePort>,
+    ne2000: Option<Ne2000>,
+    sn76489: Option<Sn76489>,
     video: VideoCardDispatch,
+    // The MDA can share the bus with a color card, so it has its own slot. 
+    mda: Option<MDACard>,
+    primary_video: VideoType,
 
     cycles_to_ticks: [u32; 256],
 
     timer_trigger1_armed: bool,
     timer_trigger2_armed: bool,
 
-    cga_tick_accum: u32
+    cga_tick_accum: u32,
+
+    // Accesses to breakpoint addresses by bus masters other than the CPU
+    device_accesses: Vec<BusAccess>,
 }
 
 impl ByteQueue for BusInterface {
@@ -343,14 +537,23 @@ impl Default for BusInterface {
             machine_desc: None,
             memory: vec![0; ADDRESS_SPACE],
             memory_mask: vec![0; ADDRESS_SPACE],
+            conventional_ram: CONVENTIONAL_RAM_MAX as usize,
+            address_mask: ADDRESS_SPACE - 1,
+            active_address_mask: ADDRESS_SPACE - 1,
             desc_vec: Vec::new(),
-            mmio_map: Vec::new(),
-            mmio_map_fast: [MmioDeviceType::Memory; ADDRESS_SPACE >> MMIO_MAP_SHIFT],
+            wait_regions: Vec::new(),
+            video_wait_states: true,
+            mmio_regions: Vec::new(),
+            mmio_map_fast: [None; ADDRESS_SPACE >> MMIO_MAP_SHIFT],
             mmio_data: MmioData::new(),
+            ems_page_frame: None,
+            coverage: CoverageMap::new(ADDRESS_SPACE),
+            coverage_enabled: false,
+            heatmap: Default::default(),
             cursor: 0,
 
 
-            io_map: HashMap::new(),
+            io_map: DeviceManager::new(),
             ppi: None,
             pit: None,
             dma_counter: 0,
@@ -361,8 +564,19 @@ impl Default for BusInterface {
             serial: None,
             fdc: None,
             hdc: None,
+            xtide: None,
             mouse: None,
+            adlib: None,
+            sb: None,
+            timer_card: None,
+            a20_gate: None,
+            rtc_card: None,
+            game_port: None,
+            ne2000: None,
+            sn76489: None,
             video: VideoCardDispatch::None,
+            mda: None,
+            primary_video: VideoType::CGA,
 
             cycles_to_ticks: [0; 256],
 
@@ -370,10 +584,34 @@ impl Default for BusInterface {
             timer_trigger2_armed: false,     
 
             cga_tick_accum: 0,
+
+            device_accesses: Vec::new(),
         }        
     }
 }
 
+impl SaveState for BusInterface {
+    const STATE_ID: &'static str = "bus";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.memory);
+        w.write_u16(self.dma_counter);
+        w.write_bool(self.timer_trigger1_armed);
+        w.write_bool(self.timer_trigger2_armed);
+        w.write_u32(self.cga_tick_accum);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        r.read_into(&mut self.memory)?;
+        self.dma_counter = r.read_u16()?;
+        self.timer_trigger1_armed = r.read_bool()?;
+        self.timer_trigger2_armed = r.read_bool()?;
+        self.cga_tick_accum = r.read_u32()?;
+        Ok(())
+    }
+}
+
 impl BusInterface {
     pub fn new(cpu_factor: ClockFactor, machine_desc: MachineDescriptor) -> BusInterface {
         BusInterface {
@@ -383,13 +621,22 @@ impl BusInterface {
             machine_desc: Some(machine_desc),
             memory: vec![0; ADDRESS_SPACE],
             memory_mask: vec![0; ADDRESS_SPACE],
+            conventional_ram: CONVENTIONAL_RAM_MAX as usize,
+            address_mask: ADDRESS_SPACE - 1,
+            active_address_mask: ADDRESS_SPACE - 1,
             desc_vec: Vec::new(),
-            mmio_map: Vec::new(),
-            mmio_map_fast: [MmioDeviceType::Memory; ADDRESS_SPACE >> MMIO_MAP_SHIFT],
+            wait_regions: Vec::new(),
+            video_wait_states: true,
+            mmio_regions: Vec::new(),
+            mmio_map_fast: [None; ADDRESS_SPACE >> MMIO_MAP_SHIFT],
             mmio_data: MmioData::new(),            
+            ems_page_frame: None,
+            coverage: CoverageMap::new(ADDRESS_SPACE),
+            coverage_enabled: false,
+            heatmap: Default::default(),
             cursor: 0,
 
-            io_map: HashMap::new(),
+            io_map: DeviceManager::new(),
             ppi: None,
             pit: None,
             dma_counter: 0,
@@ -400,15 +647,28 @@ impl BusInterface {
             serial: None,    
             fdc: None,
             hdc: None,
+            xtide: None,
             mouse: None,
+            adlib: None,
+            sb: None,
+            timer_card: None,
+            a20_gate: None,
+            rtc_card: None,
+            game_port: None,
+            ne2000: None,
+            sn76489: None,
             video: VideoCardDispatch::None,
+            mda: None,
+            primary_video: VideoType::CGA,
 
             cycles_to_ticks: [0; 256],
 
             timer_trigger1_armed: false,
             timer_trigger2_armed: false,  
 
-            cga_tick_accum: 0,        
+            cga_tick_accum: 0,
+
+            device_accesses: Vec::new(),
         }
     }
 
@@ -416,33 +676,88 @@ impl BusInterface {
         self.memory.len()
     }
 
+    /// Set the amount of conventional memory installed. Addresses from the end of installed
+    /// memory up to the 640K boundary are unpopulated: they read back as an open bus and 
+    /// discard writes, so the BIOS memory test counts only what is installed.
+    pub fn set_conventional_ram(&mut self, size: usize) {
+        self.conventional_ram = size.min(CONVENTIONAL_RAM_MAX as usize);
+        for (address, flags) in self.memory_mask[..CONVENTIONAL_RAM_MAX as usize].iter_mut().enumerate() {
+            if address < self.conventional_ram {
+                *flags &= !MEM_ROM_BIT;
+            }
+            else {
+                *flags |= MEM_ROM_BIT;
+            }
+        }
+        self.fill_unpopulated();
+    }
+
+    /// Return the amount of conventional memory installed.
+    pub fn conventional_ram(&self) -> usize {
+        self.conventional_ram
+    }
+
+    fn fill_unpopulated(&mut self) {
+        self.memory[self.conventional_ram..CONVENTIONAL_RAM_MAX as usize].fill(0xFF);
+    }
+
     /// Register a memory-mapped device.
     /// 
     /// The MemoryMappedDevice trait's read & write methods will be called instead for memory in the range
     /// specified withing MemRangeDescriptor.
-    pub fn register_map(&mut self, device: MmioDeviceType, mem_descriptor: MemRangeDescriptor) {
-        if mem_descriptor.address < self.mmio_data.first_map {
-            self.mmio_data.first_map = mem_descriptor.address;
+    pub fn register_map(&mut self, device: MmioDeviceType, mem_descriptor: MemRangeDescriptor) -> Result<(), MemError> {
+        let waits = match device {
+            MmioDeviceType::Video if !self.video_wait_states => MmioWaits::Memory,
+            _ => MmioWaits::Device,
+        };
+        self.register_region(MmioRegion {
+            device,
+            address: mem_descriptor.address,
+            size: mem_descriptor.size,
+            access: MmioAccess::ReadWrite,
+            waits,
+        })
+    }
+
+    /// Register a region of the address space for a memory-mapped device. A region registered 
+    /// later takes precedence over an earlier one where they overlap. Regions must be aligned 
+    /// to MMIO_MAP_SIZE and lie within the address space.
+    pub fn register_region(&mut self, region: MmioRegion) -> Result<(), MemError> {
+        if region.address % MMIO_MAP_SIZE != 0 
+            || region.size % MMIO_MAP_SIZE != 0 
+            || region.address + region.size > self.mmio_map_fast.len() << MMIO_MAP_SHIFT 
+            || region.address + region.size > self.memory_mask.len() {
+            return Err(MemError::MmioRegionError)
+        }
+        if self.mmio_regions.len() >= u8::MAX as usize {
+            return Err(MemError::MmioRegionLimitError)
+        }
+
+        if region.address < self.mmio_data.first_map {
+            self.mmio_data.first_map = region.address;
         }
-        if (mem_descriptor.address + mem_descriptor.size) > self.mmio_data.last_map {
-            self.mmio_data.last_map = mem_descriptor.address + mem_descriptor.size;
+        if (region.address + region.size) > self.mmio_data.last_map {
+            self.mmio_data.last_map = region.address + region.size;
         }
 
         // Mark memory flag bit as MMIO for this range.
-        for i in mem_descriptor.address..(mem_descriptor.address + mem_descriptor.size) {
-            self.memory_mask[i] |= MEM_MMIO_BIT;
+        for flags in &mut self.memory_mask[region.address..(region.address + region.size)] {
+            *flags |= MEM_MMIO_BIT;
         }
 
         // Add entry to mmio_map_fast
+        let index = self.mmio_regions.len() as u8;
+        let first_seg = region.address >> MMIO_MAP_SHIFT;
+        for seg in &mut self.mmio_map_fast[first_seg..(first_seg + region.size / MMIO_MAP_SIZE)] {
+            *seg = Some(index);
+        }
 
-        assert!(mem_descriptor.size % MMIO_MAP_SIZE == 0);
-        let map_segs = mem_descriptor.size / MMIO_MAP_SIZE;
+        self.mmio_regions.push(region);
+        Ok(())
+    }
 
-        for i in 0..map_segs {
-            self.mmio_map_fast[(mem_descriptor.address >> MMIO_MAP_SHIFT) + i] = device.clone();
-        }
-        
-        self.mmio_map.push((mem_descriptor, device));
+    pub fn mmio_regions(&self) -> &[MmioRegion] {
+        &self.mmio_regions
     }
 
     pub fn copy_from(&mut self, src: &[u8], location: usize, cycle_cost: u32, read_only: bool) -> Result<(), bool> {
@@ -498,6 +813,91 @@ impl BusInterface {
         Ok(())
     }
 
+    /// Write a byte into memory on behalf of the debugger. Memory-mapped devices receive the 
+    /// write as usual and the byte is also stored in system memory so that memory displays 
+    /// reflect it. ROM cannot be edited.
+    pub fn edit_u8(&mut self, address: usize, data: u8) -> Result<(), MemError> {
+        if address >= self.memory.len() {
+            return Err(MemError::SeekOutOfBoundsError)
+        }
+        if self.memory_mask[address] & MEM_ROM_BIT != 0 {
+            return Err(MemError::WriteProtectedError)
+        }
+        self.write_u8(address, data, 0)?;
+        self.memory[address] = data;
+        Ok(())
+    }
+
+    /// Search memory for the specified pattern. See mem_search::find_pattern().
+    pub fn search(&self, pattern: &[Option<u8>], start: usize, direction: SearchDirection) -> Option<usize> {
+        find_pattern(&self.memory, pattern, start, direction)
+    }
+
+    /// Set the address of the 64K EMS page frame, if an EMS board is installed.
+    pub fn set_ems_page_frame(&mut self, address: Option<usize>) {
+        self.ems_page_frame = address;
+    }
+
+    /// Return the ROM, video memory and EMS page frame regions of the address space.
+    pub fn memory_regions(&self) -> Vec<MemoryRegion> {
+        let mut regions: Vec<MemoryRegion> = self.desc_vec.iter()
+            .filter(|desc| desc.read_only)
+            .map(|desc| MemoryRegion { kind: MemoryRegionKind::Rom, address: desc.address, size: desc.size })
+            .collect();
+
+        for region in &self.mmio_regions {
+            // Write-through regions are system RAM that a device keeps a copy of.
+            if region.access == MmioAccess::ReadWrite && matches!(region.device, MmioDeviceType::Video | MmioDeviceType::Mda) {
+                regions.push(MemoryRegion { kind: MemoryRegionKind::VideoMemory, address: region.address, size: region.size });
+            }
+        }
+
+        if let Some(address) = self.ems_page_frame {
+            regions.push(MemoryRegion { kind: MemoryRegionKind::EmsPageFrame, address, size: 0x10000 });
+        }
+        regions
+    }
+
+    /// Enable or disable recording of the coverage map. Disabling recording keeps the map.
+    pub fn set_coverage_enabled(&mut self, state: bool) {
+        self.coverage_enabled = state;
+    }
+
+    pub fn coverage_enabled(&self) -> bool {
+        self.coverage_enabled
+    }
+
+    /// Mark the bytes of the instruction at the specified address as executed.
+    #[inline]
+    pub fn mark_executed(&mut self, address: usize, len: usize) {
+        if self.coverage_enabled {
+            self.coverage.mark_executed(address, len);
+        }
+    }
+
+    pub fn coverage(&self) -> &CoverageMap {
+        &self.coverage
+    }
+
+    pub fn coverage_summary(&self) -> CoverageSummary {
+        self.coverage.summary()
+    }
+
+    pub fn clear_coverage(&mut self) {
+        self.coverage.clear();
+    }
+
+    /// Enable or disable counting of memory accesses per page.
+    pub fn set_heatmap_enabled(&mut self, state: bool) {
+        self.heatmap.set_enabled(state);
+    }
+
+    /// Return the memory accesses per page counted since the last call, or None if there were
+    /// none.
+    pub fn take_page_activity(&mut self) -> Option<Vec<PageActivity>> {
+        self.heatmap.take()
+    }
+
     pub fn get_slice_at(&self, start: usize, len: usize ) -> &[u8] {
         &self.memory[start..start+len]
     }
@@ -514,6 +914,135 @@ impl BusInterface {
         });        
     }
 
+    /// Add a region of memory that incurs the specified number of additional wait states on
+    /// every access. This can be used to emulate slow expansion memory cards.
+    /// 
+    /// Wait regions only apply to conventional memory, not memory-mapped devices, and they
+    /// persist across a bus reset.
+    pub fn add_wait_region(&mut self, start: usize, size: usize, wait_states: u32) {
+        self.wait_regions.push({
+            MemRangeDescriptor {
+                address: start,
+                size: size,
+                cycle_cost: wait_states,
+                read_only: false
+            }
+        });
+    }
+
+    pub fn clear_wait_regions(&mut self) {
+        self.wait_regions.clear();
+    }
+
+    /// Enable or disable wait states on video memory access. When enabled, the installed video 
+    /// card determines the wait states for each access (the CGA inserts wait states to 
+    /// synchronize CPU access with the CRTC's memory cycles). When disabled, video memory is 
+    /// accessed as fast as conventional memory.
+    pub fn set_video_wait_states(&mut self, state: bool) {
+        self.video_wait_states = state;
+        for region in &mut self.mmio_regions {
+            if region.device == MmioDeviceType::Video && region.access == MmioAccess::ReadWrite {
+                region.waits = if state { MmioWaits::Device } else { MmioWaits::Memory };
+            }
+        }
+    }
+
+    /// Replace the character font of the installed CGA.
+    pub fn set_cga_font(&mut self, font: CgaFont) {
+        match &mut self.video {
+            VideoCardDispatch::Cga(cga) => cga.set_font(font),
+            _ => {
+                log::warn!("Can't set CGA font: the installed video card is not a CGA.");
+            }
+        }
+    }
+
+    /// Save the state of all devices on the bus that support save states into the state file.
+    pub fn save_devices(&self, state: &mut StateFile) {
+        state.save(self);
+        if let Some(ppi) = &self.ppi {
+            state.save(ppi);
+        }
+        if let Some(pit) = &self.pit {
+            state.save(pit);
+        }
+        if let Some(pic1) = &self.pic1 {
+            state.save(pic1);
+        }
+        if let Some(pic2) = &self.pic2 {
+            state.save_named("pic2", pic2);
+        }
+        if let Some(dma1) = &self.dma1 {
+            state.save(dma1);
+        }
+        if let Some(dma2) = &self.dma2 {
+            state.save_named("dma2", dma2);
+        }
+        match &self.video {
+            VideoCardDispatch::Cga(cga) => state.save(cga),
+            _ => {
+                log::warn!("Save states are not supported for the installed video card.");
+            }
+        }
+    }
+
+    /// Restore the state of all devices on the bus from the state file. Devices that do not 
+    /// support save states are left in their reset state.
+    pub fn load_devices(&mut self, state: &StateFile) -> Result<(), SaveStateError> {
+        state.load(self)?;
+        if let Some(ppi) = &mut self.ppi {
+            state.load(ppi)?;
+        }
+        if let Some(pit) = &mut self.pit {
+            state.load(pit)?;
+        }
+        if let Some(pic1) = &mut self.pic1 {
+            state.load(pic1)?;
+        }
+        if let Some(pic2) = &mut self.pic2 {
+            state.load_named("pic2", pic2)?;
+        }
+        if let Some(dma1) = &mut self.dma1 {
+            state.load(dma1)?;
+        }
+        if let Some(dma2) = &mut self.dma2 {
+            state.load_named("dma2", dma2)?;
+        }
+        if let VideoCardDispatch::Cga(cga) = &mut self.video {
+            state.load(cga)?;
+        }
+        if self.fdc.is_some() || self.hdc.is_some() || self.xtide.is_some() || self.serial.is_some() || self.mouse.is_some() {
+            log::debug!("Disk controllers, serial ports and mouse are not saved in state files.");
+        }
+        Ok(())
+    }
+
+    /// Set the amount of memory installed on the video card, in bytes. Only the EGA supports 
+    /// varying amounts of video memory.
+    pub fn set_video_memory_size(&mut self, size: usize) {
+        match &mut self.video {
+            #[cfg(feature = "ega")]
+            VideoCardDispatch::Ega(ega) => {
+                ega.set_memory_size(size);
+                log::debug!("EGA video memory size set to {}K", ega.memory_size() / 1024);
+            }
+            _ => {
+                log::warn!("Can't set video memory size to {}K: not configurable for the installed video card.", size / 1024);
+            }
+        }
+    }
+
+    #[inline]
+    /// Return the wait states for an access to unmapped memory at the specified address.
+    fn memory_wait(&self, address: usize) -> u32 {
+        for region in &self.wait_regions {
+            if address >= region.address && address < region.address + region.size {
+                return DEFAULT_WAIT_STATES + region.cycle_cost
+            }
+        }
+        DEFAULT_WAIT_STATES
+    }
+
     pub fn clear(&mut self) {
 
         // Remove return flags
@@ -525,6 +1054,12 @@ impl BusInterface {
         for byte_ref in &mut self.memory {
             *byte_ref = 0;
         }
+        self.fill_unpopulated();
+
+        // The PCjr's video memory is system RAM.
+        if let VideoCardDispatch::Cga(cga) = &mut self.video {
+            cga.clear_pcjr_ram();
+        }
     }
 
     pub fn reset(&mut self) {
@@ -553,7 +1088,8 @@ impl BusInterface {
     fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
         match self.cpu_factor {
             ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32)
+            ClockFactor::Multiplier(n) => cycles / (n as u32),
+            ClockFactor::Ratio(t, c) => (cycles as u64 * t as u64 / c as u64) as u32
         }
     }    
 
@@ -563,332 +1099,209 @@ impl BusInterface {
     fn system_ticks_to_cpu_cycles(&self, ticks: u32) -> u32 {
         match self.cpu_factor {
             ClockFactor::Divisor(n) => (ticks + (n as u32) - 1) / (n as u32),
-            ClockFactor::Multiplier(n) => ticks * (n as u32)
+            ClockFactor::Multiplier(n) => ticks * (n as u32),
+            ClockFactor::Ratio(t, c) => ((ticks as u64 * c as u64 + t as u64 - 1) / t as u64) as u32
         }
     }        
 
-    pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
-        if address < self.memory.len() {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES)
-            }
-            else {
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-
-                        match map_entry.1 {
-                            MmioDeviceType::Video => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let syswait = cga.get_read_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        let syswait = ega.get_read_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        let syswait = vga.get_read_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }
-                        return Err(MemError::MmioError)
-                    }
-                }
-                // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES)
-            }
+    /// Return the memory-mapped region containing the specified address, if any.
+    #[inline]
+    fn mmio_region(&self, address: usize) -> Option<MmioRegion> {
+        if address < self.mmio_data.first_map || address >= self.mmio_data.last_map {
+            return None
         }
-        Err(MemError::ReadOutOfBoundsError)        
+        self.mmio_map_fast[address >> MMIO_MAP_SHIFT].map(|index| self.mmio_regions[index as usize])
     }
 
-    pub fn get_write_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
-        if address < self.memory.len() {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-                return Ok(DEFAULT_WAIT_STATES)
-            }
-            else {
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-
-                        match map_entry.1 {
-                            MmioDeviceType::Video => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let syswait = cga.get_write_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        let syswait = ega.get_write_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        let syswait = vga.get_write_wait(address, system_ticks);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait));
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }
-                        return Err(MemError::MmioError)
-                    }
-                }
-                // We didn't match any mmio devices, return raw memory
-                return Ok(DEFAULT_WAIT_STATES)
+    /// Return the device that handles accesses to regions of the specified type. This is the 
+    /// only place the bus dispatches on the type of a memory-mapped device; a new device 
+    /// implements MemoryMappedDevice, registers its regions and is resolved here.
+    fn mmio_device(&mut self, device: MmioDeviceType) -> Option<&mut dyn MemoryMappedDevice> {
+        match device {
+            MmioDeviceType::Video => match &mut self.video {
+                VideoCardDispatch::Cga(cga) => Some(cga),
+                #[cfg(feature = "ega")]
+                VideoCardDispatch::Ega(ega) => Some(ega),
+                #[cfg(feature = "vga")]
+                VideoCardDispatch::Vga(vga) => Some(vga),
+                _ => None,
+            },
+            MmioDeviceType::Mda => self.mda.as_mut().map(|mda| mda as &mut dyn MemoryMappedDevice),
+            _ => None,
+        }
+    }
+
+    /// Return the wait states for an access to the specified address. Regions that take device
+    /// wait states ask the device; everything else takes the wait states of system memory.
+    fn access_wait(&mut self, address: usize, cycles: u32, write: bool) -> Result<u32, MemError> {
+        let address = address & self.active_address_mask;
+        if address >= self.memory.len() {
+            return Err(MemError::ReadOutOfBoundsError)
+        }
+        let waits = match self.mmio_region(address) {
+            Some(region) if region.waits == MmioWaits::Device => {
+                let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+                let syswait = match self.mmio_device(region.device) {
+                    Some(device) if write => device.get_write_wait(address, system_ticks),
+                    Some(device) => device.get_read_wait(address, system_ticks),
+                    None => return Err(MemError::MmioError),
+                };
+                self.system_ticks_to_cpu_cycles(syswait)
             }
+            _ => self.memory_wait(address),
+        };
+
+        // Every CPU memory bus cycle passes through here, so this is where they are counted.
+        if self.heatmap.enabled() {
+            let source = match self.mmio_region(address) {
+                Some(region) if matches!(region.device, MmioDeviceType::Video | MmioDeviceType::Mda) => AccessSource::Video,
+                _ => AccessSource::Cpu,
+            };
+            self.heatmap.record(address, source, write, waits);
         }
-        Err(MemError::ReadOutOfBoundsError)        
+        Ok(waits)
+    }
+
+    pub fn get_read_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
+        self.access_wait(address, cycles, false)
+    }
+
+    pub fn get_write_wait(&mut self, address: usize, cycles: u32) -> Result<u32, MemError> {
+        self.access_wait(address, cycles, true)
     }    
 
+    /// Read a byte on behalf of a bus master other than the CPU. Accesses to addresses with
+    /// an access breakpoint are recorded for the CPU to check against its watchpoints.
+    pub fn read_u8_from(&mut self, address: usize, cycles: u32, initiator: BusInitiator) -> Result<(u8, u32), MemError> {
+        self.record_device_access(address, false, initiator);
+        self.read_u8(address, cycles)
+    }
+
+    /// Write a byte on behalf of a bus master other than the CPU. Accesses to addresses with
+    /// an access breakpoint are recorded for the CPU to check against its watchpoints.
+    pub fn write_u8_from(&mut self, address: usize, data: u8, cycles: u32, initiator: BusInitiator) -> Result<u32, MemError> {
+        self.record_device_access(address, true, initiator);
+        self.write_u8(address, data, cycles)
+    }
+
+    fn record_device_access(&mut self, address: usize, write: bool, initiator: BusInitiator) {
+        if let BusInitiator::Dma(_) = initiator {
+            self.heatmap.record(address & self.active_address_mask, AccessSource::Dma, write, 0);
+        }
+        if initiator != BusInitiator::Cpu 
+            && self.get_flags(address) & MEM_BPA_BIT != 0 
+            && self.device_accesses.len() < MAX_DEVICE_ACCESSES {
+            self.device_accesses.push(BusAccess {
+                address: address as u32,
+                write,
+                initiator
+            });
+        }
+    }
+
+    /// Return and clear the list of device accesses to breakpoint addresses made since the 
+    /// last call.
+    pub fn take_device_accesses(&mut self) -> Vec<BusAccess> {
+        std::mem::take(&mut self.device_accesses)
+    }
+
     pub fn read_u8(&mut self, address: usize, cycles: u32) -> Result<(u8, u32), MemError> {
+        let address = address & self.active_address_mask;
         if address < self.memory.len() {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-                let b: u8 = self.memory[address];
-                return Ok((b, DEFAULT_WAIT_STATES))
-            }
-            else {
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-
-                        match map_entry.1 {
-                            MmioDeviceType::Video => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let (data, syswait) = MemoryMappedDevice::mmio_read_u8(cga, address, system_ticks);
-                                        return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        let (data, syswait) = MemoryMappedDevice::mmio_read_u8(ega, address, system_ticks);
-                                        return Ok((data, 0));
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        let (data, syswait) = MemoryMappedDevice::mmio_read_u8(vga, address, system_ticks);
-                                        return Ok((data, 0));
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }
-                        return Err(MemError::MmioError)
-                    }
+            if let Some(region) = self.mmio_region(address) {
+                if region.access == MmioAccess::ReadWrite {
+                    let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+                    let (data, syswait) = match self.mmio_device(region.device) {
+                        Some(device) => device.mmio_read_u8(address, system_ticks),
+                        None => return Err(MemError::MmioError),
+                    };
+                    return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
                 }
-                // We didn't match any mmio devices, return raw memory
-                let b: u8 = self.memory[address];
-                return Ok((b, DEFAULT_WAIT_STATES))
             }
+            return Ok((self.memory[address], DEFAULT_WAIT_STATES))
         }
         Err(MemError::ReadOutOfBoundsError)
     }
 
     pub fn read_u16(&mut self, address: usize, cycles: u32) -> Result<(u16, u32), MemError> {
+        let address = address & self.active_address_mask;
         if address < self.memory.len() - 1 {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-                let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
-                return Ok((w, DEFAULT_WAIT_STATES))
+            let region = self.mmio_region(address);
+            let region_hi = self.mmio_region(address + 1);
+            if let Some(region) = region.filter(|r| r.access == MmioAccess::ReadWrite && region_hi == Some(*r)) {
+                // Both bytes are in the same region, so let the device read the word.
+                let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
+                let (data, syswait) = match self.mmio_device(region.device) {
+                    Some(device) => device.mmio_read_u16(address, system_ticks),
+                    None => return Err(MemError::MmioError),
+                };
+                return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
             }
-            else {
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < (map_entry.0.address + map_entry.0.size - 1) {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-
-                        match map_entry.1 {
-                            MmioDeviceType::Cga | MmioDeviceType::Ega | MmioDeviceType::Vga => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        //let (data, syswait) = MemoryMappedDevice::read_u16(cga, address, system_ticks);
-                                        let (data, syswait) = cga.mmio_read_u16(address, system_ticks);
-                                        return Ok((data, self.system_ticks_to_cpu_cycles(syswait)));
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        let (data, syswait) = MemoryMappedDevice::mmio_read_u16(ega, address, system_ticks);
-                                        return Ok((data, 0));
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        let (data, syswait) = MemoryMappedDevice::mmio_read_u16(vga, address, system_ticks);
-                                        return Ok((data, 0));
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }
-                        return Err(MemError::MmioError)
-                    }
-                }
-                // We didn't match any mmio devices, return raw memory
-                let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
-                return Ok((w, DEFAULT_WAIT_STATES))            
+            if region.is_some() || region_hi.is_some() {
+                // Each byte may belong to a different region, so read them separately.
+                let (lo, wait_lo) = self.read_u8(address, cycles)?;
+                let (hi, wait_hi) = self.read_u8(address + 1, 0)?;
+                return Ok((lo as u16 | (hi as u16) << 8, wait_lo + wait_hi))
             }
+            let w: u16 = self.memory[address] as u16 | (self.memory[address + 1] as u16) << 8;
+            return Ok((w, DEFAULT_WAIT_STATES))
         }
         Err(MemError::ReadOutOfBoundsError)
     }
 
     pub fn write_u8(&mut self, address: usize, data: u8, cycles: u32) -> Result<u32, MemError> {
+        let address = address & self.active_address_mask;
         if address < self.memory.len() {
+            if self.coverage_enabled {
+                self.coverage.mark_written(address, 1);
+            }
             if self.memory_mask[address] & (MEM_MMIO_BIT | MEM_ROM_BIT) == 0 {
                 // Address is not mapped and not ROM, write to it.
                 self.memory[address] = data;                
                 return Ok(DEFAULT_WAIT_STATES);
             }
-            else {
-                /*
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-                        //let system_ticks = self.cycles_to_ticks[cycles as usize];
-
-                        match map_entry.1 {
-                            MmioDeviceType::Cga | MmioDeviceType::Ega | MmioDeviceType::Vga => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let syswait = MemoryMappedDevice::mmio_write_u8(cga, address, data, system_ticks);
-                                        //return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value. 
-                                        return Ok(0);
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        MemoryMappedDevice::mmio_write_u8( ega, address, data, system_ticks);
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        MemoryMappedDevice::mmio_write_u8(vga, address, data, system_ticks);
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }                        
-                        return Ok(map_entry.0.cycle_cost);
-                    }
+            if let Some(region) = self.mmio_region(address) {
+                if region.access == MmioAccess::WriteThrough {
+                    self.memory[address] = data;
                 }
-                return Ok(DEFAULT_WAIT_STATES);
-                */
-                
-                match self.mmio_map_fast[address >> MMIO_MAP_SHIFT] {
-                    MmioDeviceType::Video => {
-                        
-                        let system_ticks = self.cycles_to_ticks[cycles as usize];
-
-                        match &mut self.video {
-                            VideoCardDispatch::Cga(cga) => {
-                                let syswait = cga.mmio_write_u8(address, data, system_ticks);
-                                //return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value. 
-                                return Ok(0);
-                            }
-                            #[cfg(feature = "ega")]
-                            VideoCardDispatch::Ega(ega) => {
-                                MemoryMappedDevice::mmio_write_u8( ega, address, data, system_ticks);
-                            }
-                            #[cfg(feature = "vga")]
-                            VideoCardDispatch::Vga(vga) => {
-                                MemoryMappedDevice::mmio_write_u8(vga, address, data, system_ticks);
-                            }
-                            _ => {}
-                        }
-                    },
-                    _ => {
-                        if self.memory_mask[address] & MEM_ROM_BIT == 0 {
-                            self.memory[address] = data;                
-                        }
-                    }
+                let system_ticks = self.cycles_to_ticks[cycles as usize];
+                let (syswait, alias) = match self.mmio_device(region.device) {
+                    Some(device) => (device.mmio_write_u8(address, data, system_ticks), device.mmio_ram_alias(address)),
+                    None => return Ok(DEFAULT_WAIT_STATES),
+                };
+                if let Some(ram_address) = alias {
+                    self.memory[ram_address] = data;
                 }
-                return Ok(DEFAULT_WAIT_STATES);
+                return Ok(self.system_ticks_to_cpu_cycles(syswait));
+            }
+            if self.memory_mask[address] & MEM_ROM_BIT == 0 {
+                self.memory[address] = data;                
             }
+            return Ok(DEFAULT_WAIT_STATES);
         }
         Err(MemError::ReadOutOfBoundsError)
     }
 
     pub fn write_u16(&mut self, address: usize, data: u16, cycles: u32) -> Result<u32, MemError> {
+        let address = address & self.active_address_mask;
         if address < self.memory.len() - 1 {
-            if address < self.mmio_data.first_map || address > self.mmio_data.last_map {
-                // Address is not mapped.
-
-                // Little Endian is LO byte first
-                if self.memory_mask[address] & MEM_ROM_BIT == 0 {
-                    self.memory[address] = (data & 0xFF) as u8;
-                    self.memory[address+1] = (data >> 8) as u8;              
-                }
-                return Ok(DEFAULT_WAIT_STATES);
+            if self.mmio_region(address).is_some() || self.mmio_region(address + 1).is_some() {
+                // Each byte may belong to a different region, so write them separately.
+                let wait_lo = self.write_u8(address, (data & 0xFF) as u8, cycles)?;
+                let wait_hi = self.write_u8(address + 1, (data >> 8) as u8, 0)?;
+                return Ok(wait_lo + wait_hi)
             }
-            else {
-                // Handle memory-mapped devices
-                for map_entry in &self.mmio_map {
-                    if address >= map_entry.0.address && address < map_entry.0.address + map_entry.0.size - 1 {
-
-                        // Convert cpu cycles to system ticks
-                        let system_ticks = self.cpu_cycles_to_system_ticks(cycles);
-
-                        match map_entry.1 {
-                            MmioDeviceType::Cga | MmioDeviceType::Ega | MmioDeviceType::Vga => {
-                                match &mut self.video {
-                                    VideoCardDispatch::Cga(cga) => {
-                                        let mut syswait;
-                                        syswait = MemoryMappedDevice::mmio_write_u8(cga, address, (data & 0xFF) as u8, system_ticks);
-                                        syswait += MemoryMappedDevice::mmio_write_u8(cga, address + 1, (data >> 8) as u8, 0);
-                                        return Ok(self.system_ticks_to_cpu_cycles(syswait)); // temporary wait state value. 
-                                    }
-                                    #[cfg(feature = "ega")]
-                                    VideoCardDispatch::Ega(ega) => {
-                                        MemoryMappedDevice::mmio_write_u8(ega, address, (data & 0xFF) as u8, system_ticks);
-                                        MemoryMappedDevice::mmio_write_u8(ega, address + 1, (data >> 8) as u8, 0);
-                                    }
-                                    #[cfg(feature = "vga")]
-                                    VideoCardDispatch::Vga(vga) => {
-                                        MemoryMappedDevice::mmio_write_u8(vga, address, (data & 0xFF) as u8, system_ticks);
-                                        MemoryMappedDevice::mmio_write_u8(vga, address + 1, (data >> 8) as u8, 0);
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _=> {}
-                        }                             
-                        return Ok(map_entry.0.cycle_cost);
-                    }
-                }
-
-                // We didn't match any mmio devices, write to memory.
-                if self.memory_mask[address] & MEM_ROM_BIT == 0 {
-                    self.memory[address] = (data & 0xFF) as u8;
-                    self.memory[address+1] = (data >> 8) as u8;              
-                }
-                return Ok(DEFAULT_WAIT_STATES);
+            if self.coverage_enabled {
+                self.coverage.mark_written(address, 2);
+            }
+            // Little Endian is LO byte first
+            if self.memory_mask[address] & MEM_ROM_BIT == 0 {
+                self.memory[address] = (data & 0xFF) as u8;
             }
+            if self.memory_mask[address + 1] & MEM_ROM_BIT == 0 {
+                self.memory[address + 1] = (data >> 8) as u8;              
+            }
+            return Ok(DEFAULT_WAIT_STATES);
         }
         Err(MemError::ReadOutOfBoundsError)
     }
@@ -1165,15 +1578,26 @@ impl BusInterface {
         machine_desc: &MachineDescriptor, 
         video_trace: TraceLogger,
         video_frame_debug: bool,
+        builtin: BuiltinDevices,
     ) 
     {
 
         // Create PPI if PPI is defined for this machine type
         if machine_desc.have_ppi {
-            self.ppi = Some(Ppi::new(machine_desc.machine_type, video_type, machine_desc.num_floppies));
+            self.ppi = Some(Ppi::new(
+                machine_desc.machine_type, 
+                video_type, 
+                machine_desc.num_floppies, 
+                machine_desc.conventional_ram
+            ));
             // Add PPI ports to io_map
             let port_list = self.ppi.as_mut().unwrap().port_list();
-            self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Ppi)));
+            self.io_map.register(IoDeviceType::Ppi, port_list);
+        }
+
+        // The PCjr's memory is shared with its video gate array, which maps it itself.
+        if machine_desc.machine_type != MachineType::IBM_PCJR_4860 {
+            self.set_conventional_ram(machine_desc.conventional_ram as usize);
         }
 
         // Create the PIT. One PIT will always exist, but it may be an 8253 or 8254. 
@@ -1193,7 +1617,7 @@ impl BusInterface {
 
         // Add PIT ports to io_map
         let port_list = pit.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Pit)));
+        self.io_map.register(IoDeviceType::Pit, port_list);
         
         // Tie gates for pit channel 0 & 1 high. 
         pit.set_channel_gate(0, true, self);
@@ -1206,51 +1630,94 @@ impl BusInterface {
         
         // Add DMA ports to io_map
         let port_list = dma1.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::DmaPrimary)));
+        self.io_map.register(IoDeviceType::DmaPrimary, port_list);
         self.dma1 = Some(dma1);
 
         // Create PIC. One PIC will always exist.
         let pic1 = Pic::new();
         // Add PIC ports to io_map
         let port_list = pic1.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::PicPrimary)));
+        self.io_map.register(IoDeviceType::PicPrimary, port_list);
         self.pic1 = Some(pic1);
 
         // Create FDC. 
-        let fdc = FloppyController::new();
-        // Add FDC ports to io_map
-        let port_list = fdc.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::FloppyController)));
-        self.fdc = Some(fdc);
-
-        // Create HDC. This should probably be specified in the MachineDesc with an option to override it
-        // (Such as using a XTIDE instead of Xebec on PC & XT, perhaps)
-        let hdc = HardDiskController::new(DRIVE_TYPE2_DIP);
-        // Add HDC ports to io_map
-        let port_list = hdc.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::HardDiskController)));
-        self.hdc = Some(hdc);   
-
-        // Create serial port.
-        let serial = SerialPortController::new();
-        // Add Serial Controller ports to io_map
-        let port_list = serial.port_list();
-        self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Serial)));
-        self.serial = Some(serial);
-
-        // Create mouse.
-        let mouse = Mouse::new();
-        self.mouse = Some(mouse);
+        if builtin.fdc {
+            let fdc = FloppyController::new();
+            // Add FDC ports to io_map
+            let port_list = fdc.port_list();
+            self.io_map.register(IoDeviceType::FloppyController, port_list);
+            self.fdc = Some(fdc);
+        }
+
+        // Create the Xebec HDC. 
+        if builtin.hdc {
+            let hdc = HardDiskController::new(DRIVE_TYPE2_DIP);
+            // Add HDC ports to io_map
+            let port_list = hdc.port_list();
+            self.io_map.register(IoDeviceType::HardDiskController, port_list);
+            self.hdc = Some(hdc);   
+        }
+
+        // Create serial port. The mouse is attached to the serial port.
+        if builtin.serial {
+            let serial = SerialPortController::new();
+            // Add Serial Controller ports to io_map
+            let port_list = serial.port_list();
+            self.io_map.register(IoDeviceType::Serial, port_list);
+            self.serial = Some(serial);
+
+            // Create mouse.
+            let mouse = Mouse::new();
+            self.mouse = Some(mouse);
+        }
 
         // Create video card depending on VideoType
+        self.primary_video = video_type;
+        if builtin.video {
+            self.install_video(video_type, video_trace, video_frame_debug);
+
+            if machine_desc.machine_type == MachineType::IBM_PCJR_4860 {
+                self.install_pcjr_video();
+            }
+        }
+    
+        self.machine_desc = Some(machine_desc.clone());
+    }
+
+    /// Install a second video card alongside the primary card. One of the two cards must be 
+    /// an MDA, as the MDA is the only card whose memory and IO ports don't conflict with the 
+    /// others. 
+    pub fn install_secondary_video(&mut self, video_type: VideoType) {
+        if (video_type == VideoType::MDA) == (self.primary_video == VideoType::MDA) {
+            log::error!("Can't install a {:?} card alongside a {:?} card.", video_type, self.primary_video);
+            return
+        }
+        self.install_video(video_type, TraceLogger::None, false);
+    }
+
+    fn install_video(&mut self, video_type: VideoType, video_trace: TraceLogger, video_frame_debug: bool) {
         match video_type {
+            VideoType::MDA => {
+                let mda = MDACard::new(video_trace);
+                let port_list = mda.port_list();
+                self.register_video_ports(IoDeviceType::Mda, port_list);
+
+                let mem_descriptor = MemRangeDescriptor::new(mda::MDA_MEM_ADDRESS, mda::MDA_MEM_APERTURE, false);
+                if let Err(e) = self.register_map(MmioDeviceType::Mda, mem_descriptor) {
+                    log::error!("Failed to map MDA memory: {}", e);
+                }
+
+                self.mda = Some(mda);
+            }
             VideoType::CGA => {
                 let cga = CGACard::new(video_trace, video_frame_debug);
                 let port_list = cga.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Cga)));
+                self.io_map.register(IoDeviceType::Cga, port_list);
 
                 let mem_descriptor = MemRangeDescriptor::new(cga::CGA_MEM_ADDRESS, cga::CGA_MEM_APERTURE, false );
-                self.register_map(MmioDeviceType::Video, mem_descriptor);
+                if let Err(e) = self.register_map(MmioDeviceType::Video, mem_descriptor) {
+                    log::error!("Failed to map CGA memory: {}", e);
+                }
 
                 self.video = VideoCardDispatch::Cga(cga)
             }
@@ -1258,10 +1725,12 @@ impl BusInterface {
             VideoType::EGA => {
                 let ega = EGACard::new();
                 let port_list = ega.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Ega)));
+                self.register_video_ports(IoDeviceType::Ega, port_list);
 
                 let mem_descriptor = MemRangeDescriptor::new(ega::EGA_GFX_ADDRESS, ega::EGA_GFX_PLANE_SIZE, false );
-                self.register_map(MmioDeviceType::Video, mem_descriptor);
+                if let Err(e) = self.register_map(MmioDeviceType::Video, mem_descriptor) {
+                    log::error!("Failed to map EGA memory: {}", e);
+                }
 
                 self.video = VideoCardDispatch::Ega(ega)
             }
@@ -1269,23 +1738,197 @@ impl BusInterface {
             VideoType::VGA => {
                 let vga = VGACard::new(video_trace);
                 let port_list = vga.port_list();
-                self.io_map.extend(port_list.into_iter().map(|p| (p, IoDeviceType::Vga)));
+                self.register_video_ports(IoDeviceType::Vga, port_list);
 
                 //let mem_descriptor = MemRangeDescriptor::new(0xB8000, vga::VGA_TEXT_PLANE_SIZE, false );
                 //cpu.bus_mut().register_map(IoDeviceType::Vga, mem_descriptor);
 
                 let mem_descriptor = MemRangeDescriptor::new(vga::VGA_GFX_ADDRESS, vga::VGA_GFX_PLANE_SIZE, false );
-                self.register_map(MmioDeviceType::Video, mem_descriptor);
+                if let Err(e) = self.register_map(MmioDeviceType::Video, mem_descriptor) {
+                    log::error!("Failed to map VGA memory: {}", e);
+                }
 
                 self.video = VideoCardDispatch::Vga(vga)
             }
-            _=> {
-                // MDA not implemented
-                todo!("MDA not implemented");
+            #[allow(unreachable_patterns)]
+            _ => {
+                log::error!("{:?} support was not compiled in.", video_type);
             }
         }
-    
-        self.machine_desc = Some(machine_desc.clone());
+    }
+
+    /// Register the IO ports of a video card. The EGA and VGA also decode the MDA's CRTC and
+    /// status ports, which belong to the MDA when one is installed alongside them.
+    fn register_video_ports(&mut self, device: IoDeviceType, port_list: Vec<u16>) {
+        let mda_ports = mda::CRTC_REGISTER_BASE..=mda::MDA_STATUS_REGISTER;
+        match device {
+            IoDeviceType::Mda => self.io_map.assign(device, port_list),
+            _ if self.mda.is_some() => {
+                self.io_map.register(device, port_list.into_iter().filter(|p| !mda_ports.contains(p)))
+            }
+            _ => self.io_map.register(device, port_list),
+        }
+    }
+
+    /// Return the IO ports claimed by more than one device.
+    pub fn io_conflicts(&self) -> &[IoConflict] {
+        self.io_map.conflicts()
+    }
+
+    /// Return the inclusive range of IO ports claimed by each device, sorted by port.
+    pub fn io_port_map(&self) -> Vec<(IoDeviceType, u16, u16)> {
+        self.io_map.port_map()
+    }
+
+    /// Return the wait states for an IO bus cycle to the specified port: the wait state the
+    /// motherboard inserts into every IO cycle, plus any configured for the port's device.
+    #[inline]
+    pub fn get_io_wait(&self, port: u16) -> u32 {
+        IO_WAIT_STATES + self.io_map.wait_states(port)
+    }
+
+    /// Add wait states to IO bus cycles for a range of ports, to emulate slow expansion cards.
+    pub fn add_io_wait_ports(&mut self, start: u16, size: u16, wait_states: u32) {
+        let end = start.saturating_add(size);
+        self.io_map.set_wait_states(start..end, wait_states);
+    }
+
+    /// Add wait states to IO bus cycles for all ports claimed by an installed device.
+    pub fn set_device_io_wait_states(&mut self, device: IoDeviceType, wait_states: u32) {
+        self.io_map.set_device_wait_states(device, wait_states);
+    }
+
+    /// Install an AdLib card. The AdLib is an optional expansion card, so it is not created
+    /// by install_devices(). It needs to know the output sample rate to generate audio.
+    pub fn install_adlib(&mut self, sample_rate: u32) {
+        let adlib = AdLibCard::new(sample_rate);
+        let port_list = adlib.port_list();
+        self.io_map.register(IoDeviceType::AdLib, port_list);
+        self.adlib = Some(adlib);
+    }
+
+    /// Switch the CGA into PCjr mode, sharing the first 128K of RAM with it. Reads are served
+    /// from system memory as usual, but the RAM is registered as a write-through region so that
+    /// the card's copy of video memory is kept current.
+    fn install_pcjr_video(&mut self) {
+        if let VideoCardDispatch::Cga(cga) = &mut self.video {
+            cga.set_pcjr_mode();
+            self.io_map.register(IoDeviceType::Cga, [cga::PCJR_PAGE_REGISTER]);
+
+            let region = MmioRegion {
+                device: MmioDeviceType::Video,
+                address: 0,
+                size: cga::PCJR_RAM_SIZE,
+                access: MmioAccess::WriteThrough,
+                waits: MmioWaits::Memory,
+            };
+            if let Err(e) = self.register_region(region) {
+                log::error!("Failed to map PCjr video memory: {}", e);
+            }
+        }
+        else {
+            log::error!("The PCjr requires a CGA video type.");
+        }
+    }
+
+    /// Install the PCjr's SN76489 sound generator. It needs to know the output sample rate to 
+    /// generate audio.
+    pub fn install_sn76489(&mut self, base_port: u16, sample_rate: u32) {
+        let sn76489 = Sn76489::new(base_port, sample_rate);
+        let port_list = sn76489.port_list();
+        self.io_map.register(IoDeviceType::Sn76489, port_list);
+        self.sn76489 = Some(sn76489);
+    }
+
+    /// Install an XT-IDE hard disk controller. Like the AdLib, this is an optional expansion
+    /// card. Its BIOS is loaded separately as an option ROM.
+    pub fn install_xtide(&mut self) {
+        let xtide = XtIdeController::new();
+        let port_list = xtide.port_list();
+        self.io_map.register(IoDeviceType::XtIde, port_list);
+        self.xtide = Some(xtide);
+    }
+
+    /// Install a Sound Blaster card. Like the AdLib, this is an optional expansion card.
+    pub fn install_sound_blaster(&mut self) {
+        let sb = SoundBlaster::new();
+        let port_list = sb.port_list();
+        self.io_map.register(IoDeviceType::SoundBlaster, port_list);
+        self.sb = Some(sb);
+    }
+
+    /// Set the mask applied to every memory address, which determines the size of the address
+    /// space. Memory is extended to cover the masked range; the default mask covers the 8088's
+    /// 1MB address space.
+    pub fn set_address_mask(&mut self, mask: usize) {
+        let size = mask + 1;
+        if size > self.memory.len() {
+            self.memory.resize(size, 0);
+            self.memory_mask.resize(size, 0);
+        }
+        self.address_mask = mask;
+        self.update_address_mask();
+    }
+
+    /// Return the mask currently applied to memory addresses, including the effect of the A20
+    /// gate.
+    pub fn address_mask(&self) -> usize {
+        self.active_address_mask
+    }
+
+    fn update_address_mask(&mut self) {
+        self.active_address_mask = match &self.a20_gate {
+            Some(a20_gate) if !a20_gate.enabled() => self.address_mask & !a20::A20_ADDRESS_BIT,
+            _ => self.address_mask,
+        };
+    }
+
+    /// Install an A20 gate, controlled through the keyboard controller and system control port.
+    /// The gate starts disabled, so it only affects address masks that include line 20.
+    pub fn install_a20_gate(&mut self) {
+        let a20_gate = A20Gate::new();
+        let port_list = a20_gate.port_list();
+        self.io_map.register(IoDeviceType::A20Gate, port_list);
+        self.a20_gate = Some(a20_gate);
+        self.update_address_mask();
+    }
+
+    pub fn a20_gate(&self) -> &Option<A20Gate> {
+        &self.a20_gate
+    }
+
+    /// Install a clock/calendar card at the specified base port. The clock is set from the 
+    /// specified time source on every reset.
+    pub fn install_rtc_card(&mut self, base_port: u16, source: RtcTimeSource) {
+        let rtc_card = RtcCard::new(base_port, source);
+        let port_list = rtc_card.port_list();
+        self.io_map.register(IoDeviceType::RtcCard, port_list);
+        self.rtc_card = Some(rtc_card);
+    }
+
+    /// Install a high resolution timer card at the specified base port. Like the AdLib, this
+    /// is an optional expansion card.
+    pub fn install_timer_card(&mut self, base_port: u16) {
+        let timer_card = TimerCard::new(base_port);
+        let port_list = timer_card.port_list();
+        self.io_map.register(IoDeviceType::TimerCard, port_list);
+        self.timer_card = Some(timer_card);
+    }
+
+    pub fn install_game_port(&mut self) {
+        let game_port = GamePort::new();
+        let port_list = game_port.port_list();
+        self.io_map.register(IoDeviceType::GamePort, port_list);
+        self.game_port = Some(game_port);
+    }
+
+    /// Install an NE2000 network card at the specified base port and IRQ, exchanging frames
+    /// with the host through the specified backend.
+    pub fn install_ne2000(&mut self, base_port: u16, irq: u8, mac: MacAddress, backend: Box<dyn NetworkBackend>) {
+        let ne2000 = Ne2000::new(base_port, irq, mac, Some(backend));
+        let port_list = ne2000.port_list();
+        self.io_map.register(IoDeviceType::Ne2000, port_list);
+        self.ne2000 = Some(ne2000);
     }
 
     /// Return whether NMI is enabled.
@@ -1307,6 +1950,32 @@ impl BusInterface {
         }
     }
 
+    /// Return the state of the keyboard's NMI request on machines whose keyboard interrupts
+    /// through NMI (the PCjr), or None otherwise.
+    pub fn keyboard_nmi(&self) -> Option<bool> {
+        self.ppi.as_ref().and_then(|ppi| ppi.keyboard_nmi())
+    }
+
+    /// Return the time in microseconds until a device other than the PIT may next raise an 
+    /// interrupt without further IO from the CPU, or None if no device will. Input from the 
+    /// host arrives between calls to run_devices() and is not accounted for.
+    pub fn next_device_interrupt_us(&self) -> Option<f64> {
+        if self.mouse.as_ref().is_some_and(|mouse| mouse.has_pending_update()) {
+            return Some(0.0)
+        }
+        [
+            self.ppi.as_ref().and_then(|ppi| ppi.next_interrupt_us()),
+            self.fdc.as_ref().and_then(|fdc| fdc.next_interrupt_us()),
+            self.hdc.as_ref().and_then(|hdc| hdc.next_interrupt_us()),
+            self.sb.as_ref().and_then(|sb| sb.next_interrupt_us()),
+            self.serial.as_ref().and_then(|serial| serial.next_interrupt_us()),
+            self.ne2000.as_ref().and_then(|ne2000| ne2000.next_interrupt_us()),
+        ]
+        .into_iter()
+        .flatten()
+        .reduce(f64::min)
+    }
+
     pub fn run_devices(
         &mut self, 
         us: f64, 
@@ -1409,6 +2078,12 @@ impl BusInterface {
             hdc.run(&mut dma1, self, us);
             self.hdc = Some(hdc);
         }
+
+        // Run the Sound Blaster, passing it DMA controller while DMA is still unattached.
+        if let Some(mut sb) = self.sb.take() {
+            sb.run(&mut dma1, self, us);
+            self.sb = Some(sb);
+        }
         
         // Run the DMA controller.
         dma1.run(self);
@@ -1425,6 +2100,31 @@ impl BusInterface {
             }            
         }
 
+        // Run the AdLib timers.
+        if let Some(adlib) = &mut self.adlib {
+            adlib.run(us);
+        }
+
+        // Run the timer card.
+        if let Some(timer_card) = &mut self.timer_card {
+            timer_card.run(us);
+        }
+
+        // Run the clock card.
+        if let Some(rtc_card) = &mut self.rtc_card {
+            rtc_card.run(us);
+        }
+
+        // Run the game port one-shots.
+        if let Some(game_port) = &mut self.game_port {
+            game_port.run(us);
+        }
+
+        // Run the network card.
+        if let Some(ne2000) = &mut self.ne2000 {
+            ne2000.run(self.pic1.as_mut().unwrap(), us);
+        }
+
         // Run the video device.
         match &mut self.video {
             VideoCardDispatch::Cga(cga) => {
@@ -1500,6 +2200,10 @@ impl BusInterface {
             VideoCardDispatch::None => {}
         }
 
+        if let Some(mda) = &mut self.mda {
+            mda.run(DeviceRunTimeUnit::Microseconds(us));
+        }
+
         event
     }
 
@@ -1507,6 +2211,34 @@ impl BusInterface {
     pub fn reset_devices(&mut self) {
         self.pit.as_mut().unwrap().reset();
         self.pic1.as_mut().unwrap().reset();
+        if let Some(adlib) = &mut self.adlib {
+            adlib.reset();
+        }
+        if let Some(sb) = &mut self.sb {
+            sb.reset();
+        }
+        if let Some(xtide) = &mut self.xtide {
+            xtide.reset();
+        }
+        if let Some(timer_card) = &mut self.timer_card {
+            timer_card.reset();
+        }
+        if let Some(a20_gate) = &mut self.a20_gate {
+            a20_gate.reset();
+        }
+        if let Some(rtc_card) = &mut self.rtc_card {
+            rtc_card.reset();
+        }
+        self.update_address_mask();
+        if let Some(game_port) = &mut self.game_port {
+            game_port.reset();
+        }
+        if let Some(ne2000) = &mut self.ne2000 {
+            ne2000.reset();
+        }
+        if let Some(sn76489) = &mut self.sn76489 {
+            sn76489.reset();
+        }
         //self.video.borrow_mut().reset();
     }
 
@@ -1536,16 +2268,19 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(d) => {
-                d as u32 * cycles
-            }
-            ClockFactor::Multiplier(m) => {
-                cycles / m as u32
-            }
-        };
+        let sys_ticks = self.cpu_cycles_to_system_ticks(cycles);
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
+        // A read of the keyboard controller's data port after a read output port command
+        // comes from the A20 gate rather than the PPI.
+        if port == a20::KBC_DATA_PORT {
+            if let Some(a20_gate) = &mut self.a20_gate {
+                if a20_gate.output_buffer_full() {
+                    return a20_gate.read_u8(port, nul_delta)
+                }
+            }
+        }
+
         if let Some(device_id) = self.io_map.get(&port) {
             match device_id {
                 IoDeviceType::Ppi => {
@@ -1602,6 +2337,14 @@ impl BusInterface {
                         NO_IO_BYTE
                     }        
                 }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        xtide.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
@@ -1611,6 +2354,70 @@ impl BusInterface {
                         NO_IO_BYTE
                     }
                 }
+                IoDeviceType::AdLib => {
+                    if let Some(adlib) = &mut self.adlib {
+                        adlib.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::SoundBlaster => {
+                    if let Some(sb) = &mut self.sb {
+                        sb.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::TimerCard => {
+                    if let Some(timer_card) = &mut self.timer_card {
+                        timer_card.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::A20Gate => {
+                    if let Some(a20_gate) = &mut self.a20_gate {
+                        a20_gate.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::RtcCard => {
+                    if let Some(rtc_card) = &mut self.rtc_card {
+                        rtc_card.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::GamePort => {
+                    if let Some(game_port) = &mut self.game_port {
+                        game_port.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::Ne2000 => {
+                    if let Some(ne2000) = &mut self.ne2000 {
+                        ne2000.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
+                IoDeviceType::Sn76489 => {
+                    if let Some(sn76489) = &mut self.sn76489 {
+                        sn76489.read_u8(port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
                        
                 IoDeviceType::Cga | IoDeviceType::Ega | IoDeviceType::Vga => {
                     match &mut self.video {
@@ -1628,6 +2435,14 @@ impl BusInterface {
                         VideoCardDispatch::None => NO_IO_BYTE
                     }
                 }
+                IoDeviceType::Mda => {
+                    if let Some(mda) = &mut self.mda {
+                        IoDevice::read_u8(mda, port, nul_delta)
+                    }
+                    else {
+                        NO_IO_BYTE
+                    }
+                }
                 _ => {
                     NO_IO_BYTE
                 }
@@ -1656,16 +2471,21 @@ impl BusInterface {
         */
 
         // Convert cycles to system clock ticks
-        let sys_ticks = match self.cpu_factor {
-            ClockFactor::Divisor(d) => {
-                d as u32 * cycles
-            }
-            ClockFactor::Multiplier(m) => {
-                cycles / m as u32
-            }
-        };
+        let sys_ticks = self.cpu_cycles_to_system_ticks(cycles);
         let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
 
+        // A write to the keyboard controller's data port after a write output port command
+        // goes to the A20 gate rather than the PPI.
+        if port == a20::KBC_DATA_PORT {
+            if let Some(a20_gate) = &mut self.a20_gate {
+                if a20_gate.output_port_pending() {
+                    a20_gate.write_u8(port, data, None, nul_delta);
+                    self.update_address_mask();
+                    return
+                }
+            }
+        }
+
         if let Some(device_id) = self.io_map.get(&port) {
             match device_id {
                 IoDeviceType::Ppi => {
@@ -1716,12 +2536,59 @@ impl BusInterface {
                         self.hdc = Some(hdc);
                     }                            
                 }
+                IoDeviceType::XtIde => {
+                    if let Some(xtide) = &mut self.xtide {
+                        // XT-IDE does not need bus.
+                        xtide.write_u8(port, data, None, nul_delta);
+                    }
+                }
                 IoDeviceType::Serial => {
                     if let Some(serial) = &mut self.serial {
                         // Serial port write does not need bus.
                         serial.write_u8(port, data, None, nul_delta);
                     }
                 }
+                IoDeviceType::AdLib => {
+                    if let Some(adlib) = &mut self.adlib {
+                        adlib.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::SoundBlaster => {
+                    if let Some(sb) = &mut self.sb {
+                        sb.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::TimerCard => {
+                    if let Some(timer_card) = &mut self.timer_card {
+                        timer_card.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::A20Gate => {
+                    if let Some(a20_gate) = &mut self.a20_gate {
+                        a20_gate.write_u8(port, data, None, nul_delta);
+                    }
+                    self.update_address_mask();
+                }
+                IoDeviceType::RtcCard => {
+                    if let Some(rtc_card) = &mut self.rtc_card {
+                        rtc_card.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::GamePort => {
+                    if let Some(game_port) = &mut self.game_port {
+                        game_port.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::Ne2000 => {
+                    if let Some(ne2000) = &mut self.ne2000 {
+                        ne2000.write_u8(port, data, None, nul_delta);
+                    }
+                }
+                IoDeviceType::Sn76489 => {
+                    if let Some(sn76489) = &mut self.sn76489 {
+                        sn76489.write_u8(port, data, None, nul_delta);
+                    }
+                }
                 IoDeviceType::Cga | IoDeviceType::Ega | IoDeviceType::Vga => {
                     match &mut self.video {
                         VideoCardDispatch::Cga(cga) => {
@@ -1738,6 +2605,11 @@ impl BusInterface {
                         VideoCardDispatch::None => {}
                     }
                 }
+                IoDeviceType::Mda => {
+                    if let Some(mda) = &mut self.mda {
+                        IoDevice::write_u8(mda, port, data, None, nul_delta);
+                    }
+                }
                 _ => {}
             }
         }
@@ -1753,6 +2625,10 @@ impl BusInterface {
         &mut self.pit
     }
 
+    pub fn pic(&self) -> &Option<Pic> {
+        &self.pic1
+    }
+
     pub fn pic_mut(&mut self) -> &mut Option<Pic> {
         &mut self.pic1
     }
@@ -1761,6 +2637,10 @@ impl BusInterface {
         &mut self.ppi
     }
 
+    pub fn dma(&self) -> &Option<DMAController> {
+        &self.dma1
+    }
+
     pub fn dma_mut(&mut self) -> &mut Option<DMAController> {
         &mut self.dma1
     }
@@ -1769,19 +2649,85 @@ impl BusInterface {
         &mut self.serial
     }
 
+    pub fn fdc(&self) -> &Option<FloppyController> {
+        &self.fdc
+    }
+
     pub fn fdc_mut(&mut self) -> &mut Option<FloppyController> {
         &mut self.fdc
     }
 
     pub fn hdc_mut(&mut self) -> &mut Option<HardDiskController> {
         &mut self.hdc
+    }
+
+    pub fn xtide_mut(&mut self) -> &mut Option<XtIdeController> {
+        &mut self.xtide
     }    
 
     pub fn mouse_mut(&mut self) -> &mut Option<Mouse> {
         &mut self.mouse
     }
 
+    pub fn adlib_mut(&mut self) -> &mut Option<AdLibCard> {
+        &mut self.adlib
+    }
+
+    pub fn game_port_mut(&mut self) -> &mut Option<GamePort> {
+        &mut self.game_port
+    }
+
+    pub fn ne2000_mut(&mut self) -> &mut Option<Ne2000> {
+        &mut self.ne2000
+    }
+
+    pub fn sn76489_mut(&mut self) -> &mut Option<Sn76489> {
+        &mut self.sn76489
+    }
+
+    pub fn sb_mut(&mut self) -> &mut Option<SoundBlaster> {
+        &mut self.sb
+    }
+
+    /// Return the primary video card, the one selected by the motherboard switches. 
     pub fn video(&self) -> Option<Box<&dyn VideoCard>> {
+        match self.primary_video {
+            VideoType::MDA => self.mda_video(),
+            _ => self.color_video()
+        }
+    }
+
+    pub fn video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+        match self.primary_video {
+            VideoType::MDA => self.mda_video_mut(),
+            _ => self.color_video_mut()
+        }
+    }
+
+    /// Return the secondary video card, if one is installed.
+    pub fn secondary_video(&self) -> Option<Box<&dyn VideoCard>> {
+        match self.primary_video {
+            VideoType::MDA => self.color_video(),
+            _ => self.mda_video()
+        }
+    }
+
+    pub fn secondary_video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+        match self.primary_video {
+            VideoType::MDA => self.color_video_mut(),
+            _ => self.mda_video_mut()
+        }
+    }
+
+    fn mda_video(&self) -> Option<Box<&dyn VideoCard>> {
+        self.mda.as_ref().map(|mda| Box::new(mda as &dyn VideoCard))
+    }
+
+    fn mda_video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+        self.mda.as_mut().map(|mda| Box::new(mda as &mut dyn VideoCard))
+    }
+
+    fn color_video(&self) -> Option<Box<&dyn VideoCard>> {
 
         match &self.video {
             VideoCardDispatch::Cga(cga) => {
@@ -1801,7 +2747,7 @@ impl BusInterface {
         }
     }
 
-    pub fn video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+    fn color_video_mut(&mut self) -> Option<Box<&mut dyn VideoCard>> {
 
         match &mut self.video {
             VideoCardDispatch::Cga(cga) => {
@@ -1820,4 +2766,142 @@ impl BusInterface {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine_manager::MACHINE_DESCS;
+
+    fn test_bus(video_wait_states: bool) -> BusInterface {
+        let mut bus = BusInterface::new(ClockFactor::Divisor(3), MACHINE_DESCS[&MachineType::IBM_PC_5150]);
+        bus.set_video_wait_states(video_wait_states);
+        bus
+    }
+
+    #[test]
+    fn test_mmio_region_dispatch() {
+        let mut bus = test_bus(true);
+        bus.install_video(VideoType::CGA, TraceLogger::None, false);
+        bus.install_video(VideoType::MDA, TraceLogger::None, false);
+
+        assert_eq!(bus.mmio_region(0xAFFFF), None);
+        assert_eq!(bus.mmio_region(mda::MDA_MEM_ADDRESS).map(|r| r.device), Some(MmioDeviceType::Mda));
+        assert_eq!(bus.mmio_region(0xB7FFF).map(|r| r.device), Some(MmioDeviceType::Mda));
+        assert_eq!(bus.mmio_region(cga::CGA_MEM_ADDRESS).map(|r| r.device), Some(MmioDeviceType::Video));
+        assert_eq!(bus.mmio_region(0xBFFFF).map(|r| r.device), Some(MmioDeviceType::Video));
+        assert_eq!(bus.mmio_region(0xC0000), None);
+
+        // Writes go to the device, not to system memory.
+        bus.write_u8(0xB7FFF, 0x12, 0).unwrap();
+        bus.write_u8(cga::CGA_MEM_ADDRESS, 0x34, 0).unwrap();
+        bus.write_u8(cga::CGA_MEM_ADDRESS + 1, 0x56, 0).unwrap();
+        assert_eq!(bus.memory[cga::CGA_MEM_ADDRESS], 0);
+        assert_eq!(bus.read_u8(0xB7FFF, 0).unwrap().0, 0x12);
+        assert_eq!(bus.read_u8(cga::CGA_MEM_ADDRESS, 0).unwrap().0, 0x34);
+
+        // A word within one region is read by the device, one straddling two regions is
+        // read a byte from each.
+        assert_eq!(bus.read_u16(cga::CGA_MEM_ADDRESS, 0).unwrap().0, 0x5634);
+        assert_eq!(bus.read_u16(0xB7FFF, 0).unwrap().0, 0x3412);
+    }
+
+    #[test]
+    fn test_mmio_write_through() {
+        let mut bus = test_bus(true);
+        bus.install_video(VideoType::CGA, TraceLogger::None, false);
+        bus.install_pcjr_video();
+
+        let region = bus.mmio_region(0x100).unwrap();
+        assert_eq!(region.access, MmioAccess::WriteThrough);
+
+        // Writes update system memory as well as the card, and reads come from memory.
+        bus.write_u8(0x100, 0xAB, 0).unwrap();
+        assert_eq!(bus.memory[0x100], 0xAB);
+        assert_eq!(bus.read_u8(0x100, 0).unwrap().0, 0xAB);
+        bus.memory[0x101] = 0xCD;
+        assert_eq!(bus.read_u16(0x100, 0).unwrap().0, 0xCDAB);
+        assert_eq!(bus.get_read_wait(0x100, 0).unwrap(), bus.memory_wait(0x100));
+    }
+
+    #[test]
+    fn test_register_region_errors() {
+        let mut bus = test_bus(true);
+        let region = MmioRegion {
+            device: MmioDeviceType::Video,
+            address: 0xA0000,
+            size: MMIO_MAP_SIZE,
+            access: MmioAccess::ReadWrite,
+            waits: MmioWaits::Device,
+        };
+
+        let misaligned = MmioRegion { address: 0xA0001, ..region };
+        assert!(matches!(bus.register_region(misaligned), Err(MemError::MmioRegionError)));
+        let bad_size = MmioRegion { size: 0x1000, ..region };
+        assert!(matches!(bus.register_region(bad_size), Err(MemError::MmioRegionError)));
+        let out_of_bounds = MmioRegion { address: ADDRESS_SPACE - MMIO_MAP_SIZE, size: 2 * MMIO_MAP_SIZE, ..region };
+        assert!(matches!(bus.register_region(out_of_bounds), Err(MemError::MmioRegionError)));
+        assert!(bus.mmio_regions().is_empty());
+        assert_eq!(bus.mmio_region(0xA0000), None);
+
+        for _ in 0..u8::MAX {
+            bus.register_region(region).unwrap();
+        }
+        assert!(matches!(bus.register_region(region), Err(MemError::MmioRegionLimitError)));
+        assert_eq!(bus.mmio_regions().len(), u8::MAX as usize);
+    }
+
+    #[test]
+    fn test_mmio_wait_states() {
+        let mut bus = test_bus(true);
+        bus.install_video(VideoType::CGA, TraceLogger::None, false);
+
+        // The CGA inserts wait states that depend on the phase of its clock.
+        let waits: Vec<u32> = (0..16).map(|c| bus.get_read_wait(cga::CGA_MEM_ADDRESS, c).unwrap()).collect();
+        assert!(waits.iter().all(|&w| w > 0));
+        assert!(waits.iter().any(|&w| w != waits[0]));
+        assert_eq!(bus.get_write_wait(cga::CGA_MEM_ADDRESS, 0).unwrap(), waits[0]);
+        assert_eq!(bus.get_read_wait(0x1000, 0).unwrap(), bus.memory_wait(0x1000));
+
+        // With video wait states disabled, video memory is as fast as system memory.
+        let mut bus = test_bus(false);
+        bus.install_video(VideoType::CGA, TraceLogger::None, false);
+        for c in 0..16 {
+            assert_eq!(bus.get_read_wait(cga::CGA_MEM_ADDRESS, c).unwrap(), bus.memory_wait(cga::CGA_MEM_ADDRESS));
+        }
+    }
+
+    #[test]
+    fn test_address_mask_wrap() {
+        let mut bus = test_bus(true);
+        bus.set_address_mask(0x1FFFFF);
+        bus.install_a20_gate();
+
+        // With the A20 gate disabled, addresses above 1MB wrap around to the bottom of memory.
+        bus.write_u8(0x100010, 0x11, 0).unwrap();
+        assert_eq!(bus.read_u8(0x000010, 0).unwrap().0, 0x11);
+        assert_eq!(bus.read_u16(0x10000F, 0).unwrap().0, 0x1100);
+
+        // With it enabled, they reach the memory above 1MB.
+        bus.io_write_u8(a20::SYSTEM_CONTROL_PORT_A, 0x02, 0);
+        bus.write_u8(0x100010, 0x22, 0).unwrap();
+        assert_eq!(bus.read_u8(0x100010, 0).unwrap().0, 0x22);
+        assert_eq!(bus.read_u8(0x000010, 0).unwrap().0, 0x11);
+        assert_eq!(bus.memory[0x100010], 0x22);
+
+        // The mask still limits the address space to 2MB.
+        assert_eq!(bus.read_u8(0x300010, 0).unwrap().0, 0x22);
+    }
+
+    #[cfg(feature = "ega")]
+    #[test]
+    fn test_ega_wait_states() {
+        let mut bus = test_bus(true);
+        bus.install_video(VideoType::EGA, TraceLogger::None, false);
+
+        assert_eq!(bus.mmio_region(ega::EGA_GFX_ADDRESS).map(|r| r.waits), Some(MmioWaits::Device));
+        for c in 0..16 {
+            assert_eq!(bus.get_read_wait(ega::EGA_GFX_ADDRESS, c).unwrap(), 0);
+            assert_eq!(bus.read_u8(ega::EGA_GFX_ADDRESS, c).unwrap().1, 0);
+        }
+    }
+}
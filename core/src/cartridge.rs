@@ -0,0 +1,132 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    ---------------------------------------------------------------------------
+
+    cartridge.rs
+
+    Loads ROM cartridge images for the IBM PCjr.
+
+    PCjr cartridges map ROM into the D0000-EFFFF region, where the BIOS finds 
+    them by scanning for the 55AA option ROM signature. Cartridges are most 
+    commonly distributed as JRC files, which prefix the ROM data with a 512
+    byte header giving the segment the cartridge occupies. Raw ROM dumps 
+    carry no address, so they are mapped at the start of the first 
+    cartridge slot.
+
+*/
+
+use std::{error::Error, fmt::Display};
+
+pub const JRC_SIGNATURE: &[u8] = b"PCjr Cartridge image file";
+pub const JRC_HEADER_SIZE: usize = 0x200;
+const JRC_SEGMENT_OFFSET: usize = 0x1CE;
+
+pub const CARTRIDGE_DEFAULT_ADDRESS: u32 = 0xD0000;
+pub const CARTRIDGE_REGION_START: u32 = 0xD0000;
+pub const CARTRIDGE_REGION_END: u32 = 0xF0000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Empty,
+    BadHeader,
+    OutOfRange(u32),
+}
+
+impl Error for CartridgeError {}
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::Empty => write!(f, "Cartridge image contains no ROM data."),
+            CartridgeError::BadHeader => write!(f, "Cartridge image has a truncated JRC header."),
+            CartridgeError::OutOfRange(address) => {
+                write!(f, "Cartridge at {:05X} does not fit in the cartridge region (D0000-EFFFF).", address)
+            }
+        }
+    }
+}
+
+pub struct Cartridge {
+    pub address: u32,
+    pub rom: Vec<u8>,
+}
+
+impl Cartridge {
+    /// Parse a cartridge image, either a JRC file or a raw ROM dump.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let (address, rom) = if bytes.starts_with(JRC_SIGNATURE) {
+            if bytes.len() < JRC_HEADER_SIZE {
+                return Err(CartridgeError::BadHeader)
+            }
+            let segment = u16::from_le_bytes([bytes[JRC_SEGMENT_OFFSET], bytes[JRC_SEGMENT_OFFSET + 1]]);
+            ((segment as u32) << 4, bytes[JRC_HEADER_SIZE..].to_vec())
+        }
+        else {
+            (CARTRIDGE_DEFAULT_ADDRESS, bytes.to_vec())
+        };
+
+        if rom.is_empty() {
+            return Err(CartridgeError::Empty)
+        }
+        if address < CARTRIDGE_REGION_START || address + rom.len() as u32 > CARTRIDGE_REGION_END {
+            return Err(CartridgeError::OutOfRange(address))
+        }
+
+        Ok(Self { address, rom })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_jrc(segment: u16, rom: &[u8]) -> Vec<u8> {
+        let mut image = vec![0; JRC_HEADER_SIZE];
+        image[..JRC_SIGNATURE.len()].copy_from_slice(JRC_SIGNATURE);
+        image[JRC_SEGMENT_OFFSET..JRC_SEGMENT_OFFSET + 2].copy_from_slice(&segment.to_le_bytes());
+        image.extend_from_slice(rom);
+        image
+    }
+
+    #[test]
+    fn test_jrc_cartridge() {
+        let cart = Cartridge::from_bytes(&make_jrc(0xE800, &[0x55, 0xAA, 0x10])).unwrap();
+        assert_eq!(cart.address, 0xE8000);
+        assert_eq!(cart.rom, vec![0x55, 0xAA, 0x10]);
+
+        assert!(matches!(Cartridge::from_bytes(&make_jrc(0xE800, &[])), Err(CartridgeError::Empty)));
+        assert!(matches!(Cartridge::from_bytes(&make_jrc(0xC000, &[0x55])), Err(CartridgeError::OutOfRange(0xC0000))));
+        assert!(matches!(Cartridge::from_bytes(JRC_SIGNATURE), Err(CartridgeError::BadHeader)));
+    }
+
+    #[test]
+    fn test_raw_cartridge() {
+        let cart = Cartridge::from_bytes(&[0x55, 0xAA, 0x40]).unwrap();
+        assert_eq!(cart.address, CARTRIDGE_DEFAULT_ADDRESS);
+
+        // A raw image larger than the cartridge region can't be mapped.
+        assert!(Cartridge::from_bytes(&vec![0; 0x20001]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    codepage.rs
+
+    Implements translation between DOS codepages and Unicode.
+
+    Text read from the guest's screen is translated to UTF-8 using the 
+    configured codepage, and text sent to the guest is translated back, so
+    that non-English text round-trips correctly. Bytes 0x00-0x1F and 0x7F 
+    are displayed by the video card as the CP437 symbol glyphs (smileys, 
+    arrows and so on) in every codepage, and are translated as such when 
+    decoding screen contents.
+*/
+
+use std::str::FromStr;
+
+use serde_derive::Deserialize;
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum Codepage {
+    #[default]
+    Cp437,
+    Cp850,
+    Cp866,
+}
+
+impl FromStr for Codepage {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "cp437" | "437" => Ok(Codepage::Cp437),
+            "cp850" | "850" => Ok(Codepage::Cp850),
+            "cp866" | "866" => Ok(Codepage::Cp866),
+            _ => Err("Bad value for codepage".to_string()),
+        }
+    }
+}
+
+/// The symbols displayed for bytes 0x00-0x1F. 0x00 is displayed as a blank.
+const CONTROL_GLYPHS: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const DEL_GLYPH: char = '⌂';
+
+/// Bytes 0x80-0xFF of each codepage.
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{00A0}",
+);
+
+const CP850_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜø£Ø×ƒ",
+    "áíóúñÑªº¿®¬½¼¡«»",
+    "░▒▓│┤ÁÂÀ©╣║╗╝¢¥┐",
+    "└┴┬├─┼ãÃ╚╔╩╦╠═╬¤",
+    "ðÐÊËÈıÍÎÏ┘┌█▄¦Ì▀",
+    "ÓßÔÒõÕµþÞÚÛÙýÝ¯´",
+    "\u{00AD}±‗¾¶§÷¸°¨·¹³²■\u{00A0}",
+);
+
+const CP866_HIGH: &str = concat!(
+    "АБВГДЕЖЗИЙКЛМНОП",
+    "РСТУФХЦЧШЩЪЫЬЭЮЯ",
+    "абвгдежзийклмноп",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "рстуфхцчшщъыьэюя",
+    "ЁёЄєЇїЎў°∙·√№¤■\u{00A0}",
+);
+
+impl Codepage {
+    fn high_table(&self) -> &'static str {
+        match self {
+            Codepage::Cp437 => CP437_HIGH,
+            Codepage::Cp850 => CP850_HIGH,
+            Codepage::Cp866 => CP866_HIGH,
+        }
+    }
+
+    /// Translate a byte as displayed on screen to a Unicode character.
+    pub fn to_char(&self, byte: u8) -> char {
+        match byte {
+            0x00..=0x1F => CONTROL_GLYPHS.chars().nth(byte as usize).unwrap_or(' '),
+            0x7F => DEL_GLYPH,
+            0x20..=0x7E => byte as char,
+            _ => self.high_table().chars().nth((byte - 0x80) as usize).unwrap_or('?'),
+        }
+    }
+
+    /// Translate a Unicode character to a byte in this codepage, or None if the codepage 
+    /// can't represent it. ASCII characters, including control characters such as carriage
+    /// return, are passed through unchanged.
+    pub fn from_char(&self, c: char) -> Option<u8> {
+        if c.is_ascii() {
+            return Some(c as u8)
+        }
+        if let Some(i) = self.high_table().chars().position(|h| h == c) {
+            return Some(0x80 + i as u8)
+        }
+        if c == DEL_GLYPH {
+            return Some(0x7F)
+        }
+        CONTROL_GLYPHS.chars().skip(1).position(|g| g == c).map(|i| 1 + i as u8)
+    }
+
+    /// Translate a slice of screen bytes to a UTF-8 string.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|b| self.to_char(*b)).collect()
+    }
+
+    /// Translate a string to bytes in this codepage. Characters the codepage can't represent
+    /// are replaced with '?'.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        text.chars().map(|c| self.from_char(c).unwrap_or(b'?')).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables() {
+        for table in [CP437_HIGH, CP850_HIGH, CP866_HIGH] {
+            assert_eq!(table.chars().count(), 128);
+        }
+        assert_eq!(CONTROL_GLYPHS.chars().count(), 32);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for codepage in [Codepage::Cp437, Codepage::Cp850, Codepage::Cp866] {
+            for byte in 0x80..=0xFFu8 {
+                assert_eq!(codepage.from_char(codepage.to_char(byte)), Some(byte));
+            }
+        }
+
+        assert_eq!(Codepage::Cp437.decode(b"\x84\x01 caf\x82"), "ä☺ café");
+        assert_eq!(Codepage::Cp850.encode("Ørsted"), b"\x9Drsted");
+        assert_eq!(Codepage::Cp866.encode("Привет\r"), b"\x8F\xE0\xA8\xA2\xA5\xE2\r");
+        assert_eq!(Codepage::Cp866.encode("é"), b"?");
+    }
+}
@@ -36,6 +36,15 @@ use std::str::FromStr;
 use bpaf::{Bpaf};
 use serde_derive::{Deserialize};
 
+use crate::codepage::Codepage;
+use crate::palette::{DisplayPalette, MonochromePhosphor};
+use crate::monitor::MonitorType;
+use crate::cpu_common::CpuType;
+use crate::speed::CpuClock;
+use crate::devices::serial::UartType;
+use crate::config_validator::{self, ConfigIssue, ConfigError};
+use crate::machine_manager::{self, MachineProfile};
+
 const fn _default_true() -> bool { true }
 const fn _default_false() -> bool { true }
 
@@ -44,7 +53,8 @@ const fn _default_false() -> bool { true }
 pub enum MachineType {
     FUZZER_8088,
     IBM_PC_5150,
-    IBM_XT_5160
+    IBM_XT_5160,
+    IBM_PCJR_4860
 }
 
 impl FromStr for MachineType {
@@ -56,6 +66,7 @@ impl FromStr for MachineType {
         match s {
             "IBM_PC_5150" => Ok(MachineType::IBM_PC_5150),
             "IBM_XT_5160" => Ok(MachineType::IBM_XT_5160),
+            "IBM_PCJR_4860" => Ok(MachineType::IBM_PCJR_4860),
             _ => Err("Bad value for model".to_string()),
         }
     }
@@ -90,7 +101,8 @@ impl FromStr for VideoType {
 #[derive(Copy, Clone, Debug, Bpaf, Deserialize, PartialEq)] 
 pub enum HardDiskControllerType {
     None,
-    Xebec
+    Xebec,
+    XtIde
 }
 
 impl FromStr for HardDiskControllerType {
@@ -101,16 +113,29 @@ impl FromStr for HardDiskControllerType {
     {
         match s.to_lowercase().as_str() {
             "xebec" => Ok(HardDiskControllerType::Xebec),
+            "xtide" => Ok(HardDiskControllerType::XtIde),
             _ => Err("Bad value for videotype".to_string()),
         }
     }
 }
 
+/// The type of floppy drive installed in a drive bay. This determines which disk formats 
+/// can be mounted in the drive.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)] 
+pub enum FloppyDriveType {
+    Floppy360K,
+    Floppy720K,
+    Floppy12M,
+    Floppy144M
+}
+
 #[derive(Copy, Clone, Debug, Bpaf, Deserialize, PartialEq)] 
 pub enum ValidatorType {
     None,
     Pi8088,
-    Arduino8088
+    Arduino8088,
+    Reference,
+    Replay
 }
 
 impl FromStr for ValidatorType {
@@ -122,6 +147,8 @@ impl FromStr for ValidatorType {
         match s.to_lowercase().as_str() {
             "pi8088" => Ok(ValidatorType::Pi8088),
             "arduino8088" => Ok(ValidatorType::Arduino8088),
+            "reference" => Ok(ValidatorType::Reference),
+            "replay" => Ok(ValidatorType::Replay),
             _ => Err("Bad value for validatortype".to_string()),
         }
     }
@@ -155,6 +182,36 @@ impl FromStr for TraceMode {
     }
 }
 
+/// The format of cycle traces. Text traces are meant to be read; JSON and CSV traces are meant
+/// to be compared against other emulators.
+#[derive(Copy, Clone, Debug, Bpaf, Deserialize, PartialEq)] 
+pub enum TraceFormat {
+    Text,
+    Json,
+    Csv
+}
+
+impl Default for TraceFormat {
+    fn default() -> Self { 
+        TraceFormat::Text
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "json" => Ok(TraceFormat::Json),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err("Bad value for traceformat".to_string()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct RomOverride {
     pub path: PathBuf,
@@ -163,6 +220,41 @@ pub struct RomOverride {
     pub org: RomFileOrganization
 }
 
+/// An option ROM image to be mapped at the start of the specified segment.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OptionRomConfig {
+    pub path: PathBuf,
+    pub segment: u16,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WaitStateRegion {
+    pub address: u32,
+    pub size: u32,
+    pub wait_states: u32
+}
+
+/// A range of IO ports whose bus cycles take additional wait states.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IoWaitStateRegion {
+    pub port: u16,
+    pub size: u16,
+    pub wait_states: u32
+}
+
+/// A bridge from an emulated serial port to a host serial port or a TCP socket. Exactly one of
+/// host, tcp_connect or tcp_listen should be set.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SerialBridgeConfig {
+    /// The emulated port, 1 for COM1 or 2 for COM2
+    pub port: u8,
+    pub host: Option<String>,
+    pub tcp_connect: Option<String>,
+    pub tcp_listen: Option<u16>,
+    #[serde(default)]
+    pub telnet: bool,
+}
+
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq)] 
 pub enum RomFileOrganization {
     Normal,
@@ -193,16 +285,67 @@ pub struct Emulator {
 
     #[serde(default = "_default_false")]
     pub warpspeed: bool,    
+    #[serde(default)]
+    pub warp_boot: bool,
+    #[serde(default)]
+    pub pause_on_focus_loss: bool,
+
+    pub speed: Option<f64>,
+    pub fast_forward_frameskip: Option<u32>,
 
     #[serde(default = "_default_false")]
     pub correct_aspect: bool,    
 
+    #[serde(default)]
+    pub fullscreen: bool,
+    #[serde(default)]
+    pub fullscreen_mode: FullscreenMode,
+
+    #[serde(default)]
+    pub palette: DisplayPalette,
+    pub palette_file: Option<PathBuf>,
+    pub monochrome: Option<MonochromePhosphor>,
+
+    #[serde(default)]
+    pub crt_scanlines: bool,
+    #[serde(default)]
+    pub crt_barrel: bool,
+    #[serde(default)]
+    pub crt_persistence: bool,
+
+    #[serde(default = "_default_true")]
+    pub render_thread: bool,
+
     #[serde(default)]
     pub debug_mode: bool,
 
     #[serde(default)]
     pub no_bios: bool,
 
+    #[serde(default)]
+    pub deterministic: bool,
+
+    #[serde(default)]
+    pub rewind: bool,
+    pub rewind_interval: Option<u32>,
+    pub rewind_buffer_len: Option<u32>,
+
+    pub idle_enter_frames: Option<u32>,
+    pub idle_exit_frames: Option<u32>,
+
+    pub automation_port: Option<u16>,
+    pub automation_rate_limit: Option<u32>,
+
+    pub gdb_port: Option<u16>,
+
+    pub codepage: Option<Codepage>,
+
+    pub artifact_dir: Option<String>,
+    pub floppy_dir: Option<String>,
+    #[serde(default)]
+    pub drive_sounds: bool,
+    pub artifact_retention_mb: Option<u64>,
+
     pub run_bin: Option<String>,
     pub run_bin_seg: Option<u16>,
     pub run_bin_ofs: Option<u16>,
@@ -211,6 +354,8 @@ pub struct Emulator {
     pub trace_on: bool,
     pub trace_mode: TraceMode,
     pub trace_file: Option<String>,
+    #[serde(default)]
+    pub trace_format: TraceFormat,
 
     #[serde(default)]
     pub video_trace_file: Option<String>,
@@ -220,15 +365,69 @@ pub struct Emulator {
     #[serde(default)]
     pub pit_output_file: Option<String>,
     #[serde(default = "_default_false")]
-    pub pit_output_int_trigger: bool
+    pub pit_output_int_trigger: bool,
+
+    // Set by --validate-config. Not a config file option.
+    #[serde(skip)]
+    pub validate_only: bool,
+
+    // Set by --validation-preset and --validation-record. Not config file options.
+    #[serde(skip)]
+    pub validation_preset: Option<PathBuf>,
+    #[serde(skip)]
+    pub validation_record: bool,
+
+    // Set by --script. Not a config file option.
+    #[serde(skip)]
+    pub script: Option<PathBuf>,
+
+    // Set by --record-movie and --play-movie. Not config file options.
+    #[serde(skip)]
+    pub record_movie: Option<PathBuf>,
+    #[serde(skip)]
+    pub play_movie: Option<PathBuf>,
+}
+
+/// Which of the two fonts in the CGA character ROM to use. Real cards select the font with a
+/// jumper; the thick font is the factory default.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum CgaFontSelect {
+    #[default]
+    Thick,
+    Thin
+}
+
+/// How the emulator window fills the screen when fullscreen. Borderless resizes the window to
+/// cover the monitor; Exclusive switches the monitor to the video mode closest to its native one.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum FullscreenMode {
+    #[default]
+    Borderless,
+    Exclusive
+}
+
+/// The base color scheme of the GUI. An accent color set with 'theme_color' is applied over it.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+pub enum GuiThemeMode {
+    Dark,
+    Light
+}
 
+impl Default for GuiThemeMode {
+    fn default() -> Self {
+        GuiThemeMode::Dark
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Gui {
     #[serde(default)]
     pub gui_disabled: bool,
-    pub theme_color: Option<u32>
+    #[serde(default)]
+    pub theme: GuiThemeMode,
+    pub theme_color: Option<u32>,
+    #[serde(default = "_default_true")]
+    pub persist_layout: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -237,20 +436,83 @@ pub struct Validator {
     pub vtype: Option<ValidatorType>,
     pub trigger_address: Option<u32>,
     pub trace_file: Option<String>,
+    #[serde(default = "_default_true")]
+    pub minimize_failures: bool,
+    // Ignore flags left undefined by the instruction being validated.
+    #[serde(default = "_default_true")]
+    pub mask_flags: bool,
+    // A trace of validated instructions to record, or to replay with the Replay validator.
+    pub record_trace: Option<PathBuf>,
+    pub replay_trace: Option<PathBuf>,
+
+    // Set by --validator-case. Not a config file option.
+    #[serde(skip)]
+    pub replay_case: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Machine {
     pub model: MachineType,
+    pub profile: Option<String>,
+    pub cpu_type: Option<CpuType>,
     pub rom_override: Option<Vec<RomOverride>>,
     pub raw_rom: bool,
     pub turbo: bool,
+    pub cpu_clock: Option<CpuClock>,
+    pub conventional_memory: Option<u32>,
     pub video: VideoType,
+    pub secondary_video: Option<VideoType>,
+    pub monitor: Option<MonitorType>,
+    pub video_memory: Option<u32>,
+    pub video_wait_states: Option<bool>,
+    pub cga_font: Option<CgaFontSelect>,
+    pub cga_font_rom: Option<PathBuf>,
+    pub dram_refresh: Option<bool>,
+    pub dram_refresh_cadence: Option<u32>,
+    pub dram_refresh_adjust: Option<u32>,
     pub hdc: HardDiskControllerType,
+    pub fdc_enabled: Option<bool>,
+    pub serial_enabled: Option<bool>,
+    pub video_enabled: Option<bool>,
+    pub xtide_rom: Option<PathBuf>,
+    pub xtide_rom_address: Option<u32>,
+    pub option_roms: Option<Vec<OptionRomConfig>>,
+    pub option_rom_init: Option<bool>,
     pub drive0: Option<String>,
     pub drive1: Option<String>,
+    pub hdd_flush_interval: Option<u32>,
     pub floppy0: Option<String>,
-    pub floppy1: Option<String>
+    pub floppy1: Option<String>,
+    pub floppy0_type: Option<FloppyDriveType>,
+    pub floppy1_type: Option<FloppyDriveType>,
+    pub floppy0_write_protect: Option<bool>,
+    pub floppy1_write_protect: Option<bool>,
+    #[serde(default)]
+    pub adlib: bool,
+    #[serde(default)]
+    pub sound_blaster: bool,
+    #[serde(default)]
+    pub timer_card: bool,
+    pub timer_card_port: Option<u16>,
+    #[serde(default)]
+    pub rtc_card: bool,
+    pub rtc_card_port: Option<u16>,
+    pub rtc_card_time: Option<String>,
+    pub rtc_card_utc_offset: Option<i32>,
+    #[serde(default)]
+    pub a20_gate: bool,
+    pub address_mask: Option<u32>,
+    #[serde(default)]
+    pub game_port: bool,
+    #[serde(default)]
+    pub ne2000: bool,
+    pub ne2000_port: Option<u16>,
+    pub ne2000_irq: Option<u8>,
+    pub cartridges: Option<Vec<PathBuf>>,
+    pub serial_uart: Option<UartType>,
+    pub serial_bridge: Option<Vec<SerialBridgeConfig>>,
+    pub wait_state_regions: Option<Vec<WaitStateRegion>>,
+    pub io_wait_state_regions: Option<Vec<IoWaitStateRegion>>,
 }
 
 
@@ -259,11 +521,18 @@ pub struct Cpu {
     pub wait_states_enabled: bool,
     pub off_rails_detection: bool,
     pub instruction_history: bool,
+    pub instruction_history_len: Option<u32>,
+    #[serde(default = "_default_true")]
+    pub halt_skip: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Input {
     pub reverse_mouse_buttons: bool,
+    pub paste_delay_ms: Option<u32>,
+    pub mouse_sensitivity: Option<f64>,
+    #[serde(default)]
+    pub mouse_capture_on_click: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -273,7 +542,17 @@ pub struct ConfigFileParams {
     pub input: Input,
     pub machine: Machine,
     pub cpu: Cpu,
-    pub validator: Validator
+    pub validator: Validator,
+    #[serde(default)]
+    pub machine_profile: Vec<MachineProfile>,
+
+    // The machine profile selected in the [machine] section, once applied.
+    #[serde(skip)]
+    pub active_profile: Option<MachineProfile>,
+
+    // Non-fatal problems found while validating the config file.
+    #[serde(skip)]
+    pub issues: Vec<ConfigIssue>,
 }
 
 #[derive(Debug, Bpaf)]
@@ -311,12 +590,19 @@ pub struct CmdLineArgs {
     #[bpaf(long)]
     pub machine_model: Option<MachineType>,
 
+    #[bpaf(long)]
+    pub machine_profile: Option<String>,
+
     #[bpaf(long)]
     pub turbo: bool,
 
     #[bpaf(long)]
     pub validator: Option<ValidatorType>,
 
+    // Format of cycle traces: Text, Json or Csv.
+    #[bpaf(long)]
+    pub trace_format: Option<TraceFormat>,
+
     #[bpaf(long, switch)]
     pub debug_mode: bool,
 
@@ -326,23 +612,84 @@ pub struct CmdLineArgs {
     #[bpaf(long, switch)]
     pub video_frame_debug: bool,
 
+    #[bpaf(long)]
+    pub automation_port: Option<u16>,
+
+    #[bpaf(long)]
+    pub gdb_port: Option<u16>,
+
     #[bpaf(long)]
     pub run_bin: Option<String>,
     #[bpaf(long)]
     pub run_bin_seg: Option<u16>,
     #[bpaf(long)]
     pub run_bin_ofs: Option<u16>,    
+
+    // Check the configuration file for errors and exit.
+    #[bpaf(long, switch)]
+    pub validate_config: bool,
+
+    // Run the video effects in the specified validation preset and exit.
+    #[bpaf(long)]
+    pub validation_preset: Option<PathBuf>,
+
+    // Record observed checksums into the validation preset instead of checking them.
+    #[bpaf(long, switch)]
+    pub validation_record: bool,
+
+    // Run the specified script file at startup.
+    #[bpaf(long)]
+    pub script: Option<PathBuf>,
+
+    // In fuzzer mode, replay the specified validator case file instead of generating tests.
+    #[bpaf(long)]
+    pub validator_case: Option<PathBuf>,
+
+    // Run a fixed number of cycles per frame so that emulation is reproducible.
+    #[bpaf(long, switch)]
+    pub deterministic: bool,
+
+    // Record input to the specified movie file.
+    #[bpaf(long)]
+    pub record_movie: Option<PathBuf>,
+
+    // Play back input from the specified movie file.
+    #[bpaf(long)]
+    pub play_movie: Option<PathBuf>,
 }
 
 impl ConfigFileParams {
+    /// Return the monitor attached to the video card. If none is configured,
+    /// a monochrome phosphor selection implies a monochrome monitor, otherwise
+    /// the card's usual monitor is used.
+    pub fn monitor(&self) -> MonitorType {
+        match (self.machine.monitor, self.emulator.monochrome) {
+            (Some(monitor), _) => monitor,
+            (None, Some(_)) if self.machine.video == VideoType::CGA => MonitorType::CompositeMono,
+            (None, _) => MonitorType::default_for(self.machine.video),
+        }
+    }
+
+    /// Return whether emulation should be deterministic. Recording or playing a movie
+    /// requires deterministic mode.
+    pub fn deterministic(&self) -> bool {
+        self.emulator.deterministic || self.emulator.record_movie.is_some() || self.emulator.play_movie.is_some()
+    }
+
     pub fn overlay(&mut self, shell_args: CmdLineArgs) {
 
         if let Some(machine_model) = shell_args.machine_model { 
             self.machine.model = machine_model;
         }
+        if let Some(machine_profile) = shell_args.machine_profile {
+            self.machine.profile = Some(machine_profile);
+        }
         if let Some(validator) = shell_args.validator { 
             self.validator.vtype = Some(validator);
         }       
+        if let Some(trace_format) = shell_args.trace_format {
+            self.emulator.trace_format = trace_format;
+        }
 
         if let Some(basedir) = shell_args.basedir {
             self.emulator.basedir = basedir;
@@ -355,6 +702,22 @@ impl ConfigFileParams {
         self.emulator.debug_mode |= shell_args.debug_mode;
         self.emulator.no_bios |= shell_args.no_bios;
         self.emulator.video_frame_debug |= shell_args.video_frame_debug;
+        self.emulator.validate_only |= shell_args.validate_config;
+        self.emulator.validation_preset = shell_args.validation_preset;
+        self.emulator.validation_record |= shell_args.validation_record;
+        self.emulator.script = shell_args.script;
+        self.validator.replay_case = shell_args.validator_case;
+        self.emulator.deterministic |= shell_args.deterministic;
+        self.emulator.record_movie = shell_args.record_movie;
+        self.emulator.play_movie = shell_args.play_movie;
+
+        if let Some(automation_port) = shell_args.automation_port {
+            self.emulator.automation_port = Some(automation_port);
+        }
+
+        if let Some(gdb_port) = shell_args.gdb_port {
+            self.emulator.gdb_port = Some(gdb_port);
+        }
 
         if let Some(run_bin) = shell_args.run_bin {
             self.emulator.run_bin = Some(run_bin);
@@ -374,6 +737,29 @@ impl ConfigFileParams {
 
         self.input.reverse_mouse_buttons |= shell_args.reverse_mouse_buttons;
     }
+
+    /// Apply the selected machine profile, if any. The profile sets the machine model, and 
+    /// provides the turbo setting and memory and IO wait state regions unless they are configured
+    /// explicitly.
+    pub fn apply_profile(&mut self) -> Result<(), anyhow::Error> {
+        let Some(name) = &self.machine.profile else {
+            return Ok(())
+        };
+        let profile = machine_manager::find_profile(name, &self.machine_profile)
+            .ok_or_else(|| anyhow::anyhow!("Unknown machine profile: {}", name))?;
+
+        log::debug!("Using machine profile {}: {:?}", profile.name, profile.description);
+        self.machine.model = profile.model;
+        self.machine.turbo |= profile.turbo;
+        if self.machine.wait_state_regions.is_none() {
+            self.machine.wait_state_regions = profile.wait_state_regions.clone();
+        }
+        if self.machine.io_wait_state_regions.is_none() {
+            self.machine.io_wait_state_regions = profile.io_wait_state_regions.clone();
+        }
+        self.active_profile = Some(profile);
+        Ok(())
+    }
 }
 
 pub fn get_config<P>(default_path: P) -> Result<ConfigFileParams, anyhow::Error>
@@ -386,29 +772,48 @@ where
     // Allow configuration file path to be overridden by command line argument 'configfile'
     
     if let Some(configfile_path) = shell_args.configfile.as_ref() {
-        let toml_slice = std::fs::read(configfile_path)?;
-        toml_args = toml::from_slice(&toml_slice)?;
+        let toml_text = std::fs::read_to_string(configfile_path)?;
+        toml_args = parse_config_str(&toml_text)?;
     }
     else {
-        let toml_slice = std::fs::read(default_path)?;
-        toml_args = toml::from_slice(&toml_slice)?;
+        let toml_text = std::fs::read_to_string(default_path)?;
+        toml_args = parse_config_str(&toml_text)?;
     }
     
     log::debug!("toml_config: {:?}", toml_args);
 
     // Command line arguments override config file arguments
     toml_args.overlay(shell_args);
+    toml_args.apply_profile()?;
 
     Ok(toml_args)
 }
 
 pub fn get_config_from_str(toml_text: &str) -> Result<ConfigFileParams, anyhow::Error>
 {
-    let mut toml_args: ConfigFileParams;
-
-    toml_args = toml::from_str(toml_text)?;
+    let mut toml_args = parse_config_str(toml_text)?;
     
     log::debug!("toml_config: {:?}", toml_args);
+    toml_args.apply_profile()?;
+
+    Ok(toml_args)
+}
+
+/// Validate and parse configuration file text. If validation finds any errors, a ConfigError
+/// is returned listing every issue found. Warnings are stored in the returned config.
+fn parse_config_str(toml_text: &str) -> Result<ConfigFileParams, anyhow::Error> {
+
+    let issues = config_validator::validate_config_str(toml_text);
+    if issues.iter().any(|i| i.is_error()) {
+        return Err(ConfigError(issues).into())
+    }
+
+    let mut toml_args: ConfigFileParams = toml::from_str(toml_text)?;
+
+    for issue in &issues {
+        log::warn!("Config: {}", issue);
+    }
+    toml_args.issues = issues;
 
     Ok(toml_args)
 }
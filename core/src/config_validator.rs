@@ -0,0 +1,428 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    config_validator.rs
+
+    Validation layer for the configuration file.
+
+    Checks a configuration file for TOML syntax errors, unknown sections and
+    keys, and type errors, and reports them with line numbers and suggestions
+    for misspelled keys.
+
+    The set of valid keys is taken directly from the serde definitions of the
+    config structs, so it never falls out of date with config.rs.
+*/
+
+use std::fmt;
+use std::error::Error;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::config::*;
+use crate::machine_manager::{self, MachineProfile};
+use crate::rom_manager::{OPTION_ROM_ALIGNMENT, OPTION_ROM_SCAN_END, OPTION_ROM_SCAN_START};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IssueLevel {
+    Warning,
+    Error
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigIssue {
+    pub level: IssueLevel,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn warning(line: Option<usize>, message: String) -> Self {
+        Self {
+            level: IssueLevel::Warning,
+            line,
+            message
+        }
+    }
+
+    fn error(line: Option<usize>, message: String) -> Self {
+        Self {
+            level: IssueLevel::Error,
+            line,
+            message
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == IssueLevel::Error
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level_str = match self.level {
+            IssueLevel::Warning => "warning",
+            IssueLevel::Error => "error"
+        };
+        match self.line {
+            Some(line) => write!(f, "{} (line {}): {}", level_str, line, self.message),
+            None => write!(f, "{}: {}", level_str, self.message)
+        }
+    }
+}
+
+/// Error type returned when a configuration file fails validation. Contains all issues found,
+/// including warnings.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<ConfigIssue>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.0 {
+            writeln!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigError {}
+
+/// A Deserializer that deserializes nothing, but captures the list of field names serde
+/// passes to deserialize_struct().
+struct FieldNameExtractor<'a> {
+    fields: &'a mut Option<&'static [&'static str]>
+}
+
+impl<'de, 'a> Deserializer<'de> for FieldNameExtractor<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V
+    ) -> Result<V::Value, Self::Error>
+    {
+        *self.fields = Some(fields);
+        Err(de::Error::custom("field names captured"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Return the field names of a struct deriving Deserialize, as they appear in the config file.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields = None;
+    let _ = T::deserialize(FieldNameExtractor { fields: &mut fields });
+    fields.unwrap_or(&[])
+}
+
+fn section_fields(section: &str) -> Option<&'static [&'static str]> {
+    match section {
+        "emulator" => Some(struct_fields::<Emulator>()),
+        "gui" => Some(struct_fields::<Gui>()),
+        "input" => Some(struct_fields::<Input>()),
+        "machine" => Some(struct_fields::<Machine>()),
+        "cpu" => Some(struct_fields::<Cpu>()),
+        "validator" => Some(struct_fields::<Validator>()),
+        "machine_profile" => Some(struct_fields::<MachineProfile>()),
+        _ => None
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            }
+            else {
+                1 + prev.min(row[j]).min(row[j + 1])
+            };
+            prev = cur;
+        }
+    }
+    row[b_chars.len()]
+}
+
+/// Return the closest candidate to 'key', if any are close enough to be a likely misspelling.
+fn suggest<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let key_lower = key.to_lowercase();
+    candidates
+        .iter()
+        .map(|c| (edit_distance(&key_lower, c), *c))
+        .filter(|(d, c)| *d <= std::cmp::max(2, c.len() / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Find the 1-based line number of a section header, or of a key within a section.
+fn find_line(toml_text: &str, section: &str, key: Option<&str>) -> Option<usize> {
+    let mut current_section = String::new();
+
+    for (i, line) in toml_text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            current_section = line.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+            if key.is_none() && current_section == section {
+                return Some(i + 1);
+            }
+            continue;
+        }
+        if let Some(key) = key {
+            if current_section == section {
+                if let Some((line_key, _)) = line.split_once('=') {
+                    if line_key.trim().trim_matches('"') == key {
+                        return Some(i + 1);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn unknown_key_message(what: &str, key: &str, candidates: &[&str]) -> String {
+    match suggest(key, candidates) {
+        Some(s) => format!("unknown {} `{}`. Did you mean `{}`?", what, key, s),
+        None => format!("unknown {} `{}`", what, key)
+    }
+}
+
+/// Validate the text of a configuration file. Returns a list of issues found; any issue with
+/// an Error level means the configuration cannot be loaded.
+pub fn validate_config_str(toml_text: &str) -> Vec<ConfigIssue> {
+
+    let mut issues = Vec::new();
+
+    // Check TOML syntax.
+    let root = match toml_text.parse::<toml::Value>() {
+        Ok(toml::Value::Table(table)) => table,
+        Ok(_) => {
+            issues.push(ConfigIssue::error(None, "configuration must be a TOML table".to_string()));
+            return issues
+        }
+        Err(e) => {
+            let line = e.line_col().map(|(line, _)| line + 1);
+            let message = e.to_string();
+            let message = match message.split_once(" at line ") {
+                Some((msg, _)) => msg.to_string(),
+                None => message
+            };
+            issues.push(ConfigIssue::error(line, format!("invalid TOML: {}", message)));
+            return issues
+        }
+    };
+
+    // Check for unknown sections and keys.
+    let sections = struct_fields::<ConfigFileParams>();
+    for (section, value) in root.iter() {
+
+        let fields = match section_fields(section) {
+            Some(fields) if sections.contains(&section.as_str()) => fields,
+            _ => {
+                let line = find_line(toml_text, section, None);
+                issues.push(ConfigIssue::warning(line, unknown_key_message("section", section, sections)));
+                continue;
+            }
+        };
+
+        // Machine profiles are an array of tables, ie [[machine_profile]]
+        let tables: Vec<&toml::value::Table> = match value {
+            toml::Value::Table(table) => vec![table],
+            toml::Value::Array(array) if section == "machine_profile" => {
+                array.iter().filter_map(|v| v.as_table()).collect()
+            }
+            _ => {
+                let line = find_line(toml_text, "", Some(section));
+                issues.push(ConfigIssue::error(line, format!("`{}` must be a section, ie [{}]", section, section)));
+                continue;
+            }
+        };
+
+        for table in tables {
+            for key in table.keys() {
+                if !fields.contains(&key.as_str()) {
+                    let line = find_line(toml_text, section, Some(key));
+                    let message = unknown_key_message(&format!("key in [{}]", section), key, fields);
+                    issues.push(ConfigIssue::warning(line, message));
+                }
+            }
+        }
+    }
+
+    // Check types and required values by deserializing.
+    match toml::from_str::<ConfigFileParams>(toml_text) {
+        Ok(config) => {
+            // Check that the monitor can be attached to the video card.
+            if let Some(monitor) = config.machine.monitor {
+                if !monitor.supports(config.machine.video) {
+                    let line = find_line(toml_text, "machine", Some("monitor"));
+                    let message = format!("monitor {:?} can't be used with a {:?} card", monitor, config.machine.video);
+                    issues.push(ConfigIssue::warning(line, message));
+                }
+            }
+            // Check that the selected machine profile exists.
+            if let Some(name) = &config.machine.profile {
+                if machine_manager::find_profile(name, &config.machine_profile).is_none() {
+                    let line = find_line(toml_text, "machine", Some("profile"));
+                    issues.push(ConfigIssue::error(line, format!("unknown machine profile `{}`", name)));
+                }
+            }
+            // Only an MDA can share the bus with another video card.
+            if let Some(secondary) = config.machine.secondary_video {
+                if (secondary == VideoType::MDA) == (config.machine.video == VideoType::MDA) {
+                    let line = find_line(toml_text, "machine", Some("secondary_video"));
+                    let message = format!(
+                        "a {:?} card can't be installed alongside a {:?} card; one of them must be MDA", 
+                        secondary, 
+                        config.machine.video
+                    );
+                    issues.push(ConfigIssue::error(line, message));
+                }
+            }
+            // The PCjr's video gate array is a CGA, and only the PCjr has cartridge slots.
+            if config.machine.model == MachineType::IBM_PCJR_4860 {
+                if config.machine.video != VideoType::CGA {
+                    let line = find_line(toml_text, "machine", Some("video"));
+                    issues.push(ConfigIssue::error(line, "the IBM_PCJR_4860 requires a CGA video type".to_string()));
+                }
+            }
+            else if config.machine.cartridges.as_ref().map_or(false, |c| !c.is_empty()) {
+                let line = find_line(toml_text, "machine", Some("cartridges"));
+                issues.push(ConfigIssue::warning(line, "cartridges are only supported by the IBM_PCJR_4860".to_string()));
+            }
+            // Option ROMs are only found by the BIOS on 2K boundaries between C000 and F000.
+            for option_rom in config.machine.option_roms.iter().flatten() {
+                let address = (option_rom.segment as usize) << 4;
+                if address < OPTION_ROM_SCAN_START 
+                    || address >= OPTION_ROM_SCAN_END 
+                    || address % OPTION_ROM_ALIGNMENT != 0 
+                {
+                    let line = find_line(toml_text, "machine", Some("option_roms"));
+                    let message = format!(
+                        "option ROM {} at segment {:04X} will not be found by the BIOS; \
+                        option ROMs must be mapped on a 2K boundary between C000 and EF80", 
+                        option_rom.path.display(),
+                        option_rom.segment
+                    );
+                    issues.push(ConfigIssue::warning(line, message));
+                }
+            }
+            // Network and serial bridge traffic comes from the host, so can't be reproduced.
+            if config.emulator.deterministic {
+                if config.machine.ne2000 {
+                    let line = find_line(toml_text, "machine", Some("ne2000"));
+                    issues.push(ConfigIssue::warning(line, "the NE2000 network card is not deterministic".to_string()));
+                }
+                if config.machine.serial_bridge.as_ref().map_or(false, |b| !b.is_empty()) {
+                    let line = find_line(toml_text, "machine", Some("serial_bridge"));
+                    issues.push(ConfigIssue::warning(line, "bridged serial ports are not deterministic".to_string()));
+                }
+            }
+        }
+        Err(e) => {
+            // The position toml reports for a bad value is often the end of the enclosing table.
+            // If the error names the key, locate the key ourselves instead.
+            let message = e.to_string();
+            let key_line = message
+                .split("for key `")
+                .nth(1)
+                .and_then(|rest| rest.split('`').next())
+                .and_then(|key_path| key_path.split_once('.'))
+                .and_then(|(section, key)| find_line(toml_text, section, Some(key)));
+
+            let line = key_line.or(e.line_col().map(|(line, _)| line + 1));
+            let message = match message.split_once(" at line ") {
+                Some((msg, _)) => msg.to_string(),
+                None => message
+            };
+            issues.push(ConfigIssue::error(line, message));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CONFIG: &str = "\
+# Comment
+[emulator]
+basedir = \"./\"
+
+[machine]
+model = \"IBM5160\"
+vidoe = \"CGA\"
+";
+
+    #[test]
+    fn test_find_line_and_suggest() {
+        assert_eq!(find_line(TEST_CONFIG, "machine", None), Some(5));
+        assert_eq!(find_line(TEST_CONFIG, "machine", Some("vidoe")), Some(7));
+        assert_eq!(find_line(TEST_CONFIG, "emulator", Some("model")), None);
+
+        let candidates = ["video", "video_memory", "model"];
+        assert_eq!(suggest("vidoe", &candidates), Some("video"));
+        assert_eq!(suggest("MODEL", &candidates), Some("model"));
+        assert_eq!(suggest("floppy", &candidates), None);
+    }
+
+    #[test]
+    fn test_issues_have_line_numbers() {
+        // A syntax error is reported at its line
+        let issues = validate_config_str("[machine]\nmodel = \"IBM5160\"\nvideo = \n");
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].is_error());
+        assert_eq!(issues[0].line, Some(3));
+
+        // An unknown key is reported with a suggestion
+        let issues = validate_config_str(TEST_CONFIG);
+        let issue = issues.iter().find(|i| i.message.contains("vidoe")).unwrap();
+        assert_eq!(issue.level, IssueLevel::Warning);
+        assert_eq!(issue.line, Some(7));
+        assert!(issue.message.contains("Did you mean `video`?"));
+    }
+}
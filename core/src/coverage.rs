@@ -0,0 +1,179 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+
+    --------------------------------------------------------------------------
+
+    coverage.rs
+
+    Implements an execution coverage map of the address space. Each byte of 
+    memory has flags recording whether it was executed as part of an 
+    instruction or written to. A byte that is written after it was executed 
+    is flagged as self-modifying code.
+
+    Code that is loaded over code that already ran, such as an overlay, is 
+    also flagged, as it can't be told apart from code that modifies itself.
+*/
+
+use std::io::Write;
+
+pub const COVERAGE_EXECUTED: u8 = 0b0000_0001;
+pub const COVERAGE_WRITTEN: u8  = 0b0000_0010;
+pub const COVERAGE_SMC: u8      = 0b0000_0100;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CoverageSummary {
+    pub executed: usize,
+    pub written: usize,
+    pub smc: usize,
+}
+
+pub struct CoverageMap {
+    flags: Vec<u8>,
+}
+
+impl CoverageMap {
+    pub fn new(size: usize) -> Self {
+        Self {
+            flags: vec![0; size],
+        }
+    }
+
+    /// Mark the bytes of an instruction as executed. Addresses wrap around the end of the map.
+    pub fn mark_executed(&mut self, address: usize, len: usize) {
+        let size = self.flags.len();
+        for i in 0..len {
+            self.flags[(address + i) % size] |= COVERAGE_EXECUTED;
+        }
+    }
+
+    /// Mark bytes as written, flagging any that were already executed as self-modifying code.
+    pub fn mark_written(&mut self, address: usize, len: usize) {
+        let size = self.flags.len();
+        for i in 0..len {
+            let flags = &mut self.flags[(address + i) % size];
+            if *flags & COVERAGE_EXECUTED != 0 {
+                *flags |= COVERAGE_SMC;
+            }
+            *flags |= COVERAGE_WRITTEN;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.flags.fill(0);
+    }
+
+    /// Return the flags for the specified range of memory, clipped to the end of the map.
+    pub fn flags(&self, address: usize, len: usize) -> &[u8] {
+        let start = address.min(self.flags.len());
+        let end = (address + len).min(self.flags.len());
+        &self.flags[start..end]
+    }
+
+    pub fn summary(&self) -> CoverageSummary {
+        let mut summary = CoverageSummary::default();
+        for flags in &self.flags {
+            summary.executed += (flags & COVERAGE_EXECUTED != 0) as usize;
+            summary.written += (flags & COVERAGE_WRITTEN != 0) as usize;
+            summary.smc += (flags & COVERAGE_SMC != 0) as usize;
+        }
+        summary
+    }
+
+    /// Return the contiguous ranges of memory with any of the specified flags set, as pairs of
+    /// start address and length.
+    pub fn ranges(&self, mask: u8) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut start = None;
+        for (address, flags) in self.flags.iter().enumerate() {
+            match (flags & mask != 0, start) {
+                (true, None) => start = Some(address),
+                (false, Some(s)) => {
+                    ranges.push((s, address - s));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            ranges.push((s, self.flags.len() - s));
+        }
+        ranges
+    }
+
+    /// Write a text report of the executed and self-modified ranges of memory.
+    pub fn write_report(&self, w: &mut impl Write) -> std::io::Result<()> {
+        let summary = self.summary();
+        writeln!(w, "Executed bytes: {}", summary.executed)?;
+        writeln!(w, "Written bytes: {}", summary.written)?;
+        writeln!(w, "Self-modified bytes: {}", summary.smc)?;
+
+        for (title, mask) in [("Executed", COVERAGE_EXECUTED), ("Self-modified", COVERAGE_SMC)] {
+            writeln!(w, "\n{} ranges:", title)?;
+            for (start, len) in self.ranges(mask) {
+                writeln!(w, "{:05X}-{:05X} ({} bytes)", start, start + len - 1, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the raw map, one byte of flags per byte of memory.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smc_requires_write_after_execute() {
+        let mut map = CoverageMap::new(0x100);
+        map.mark_written(0x10, 2);
+        map.mark_executed(0x10, 2);
+        assert_eq!(map.summary().smc, 0);
+
+        map.mark_written(0x11, 1);
+        assert_eq!(map.flags(0x10, 2), &[COVERAGE_EXECUTED | COVERAGE_WRITTEN, COVERAGE_EXECUTED | COVERAGE_WRITTEN | COVERAGE_SMC]);
+        assert_eq!(map.summary(), CoverageSummary { executed: 2, written: 2, smc: 1 });
+    }
+
+    #[test]
+    fn ranges_and_wrap() {
+        let mut map = CoverageMap::new(0x100);
+        map.mark_executed(0xFE, 4);
+        map.mark_executed(0x40, 1);
+        assert_eq!(map.ranges(COVERAGE_EXECUTED), vec![(0x00, 2), (0x40, 1), (0xFE, 2)]);
+
+        let mut report = Vec::new();
+        map.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("000FE-000FF (2 bytes)"));
+
+        map.clear();
+        assert!(map.ranges(COVERAGE_EXECUTED).is_empty());
+    }
+}
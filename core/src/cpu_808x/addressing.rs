@@ -505,8 +505,9 @@ impl Cpu {
                     Register16::SS => {
                         self.set_register16(Register16::SS, value);
                         // Technically only MOV ss, nn instructions will inhibit interrupts for one instruction
-                        // Other writes may not. 
+                        // Other writes may not. NMI and trap are inhibited as well.
                         self.interrupt_inhibit = true;
+                        self.ss_inhibit = true;
                     },
                     Register16::DS => self.set_register16(Register16::DS, value),
                     _=> panic!("read_operand16(): Invalid Register16 operand")
@@ -92,6 +92,19 @@ impl Cpu {
         self.set_parity_flag_from_u16(result);
     }
 
+    /// Set the arithmetic flags from the result of a microcode ALU operation, as done by
+    /// microcode lines with the F bit set. 'wide' selects whether SZP is taken from an
+    /// 8-bit or 16-bit result.
+    pub fn set_alu_flags(&mut self, result: u16, wide: bool, carry: bool, overflow: bool, aux_carry: bool) {
+        self.set_flag_state(Flag::Carry, carry);
+        self.set_flag_state(Flag::Overflow, overflow);
+        self.set_flag_state(Flag::AuxCarry, aux_carry);
+        match wide {
+            true => self.set_szp_flags_from_result_u16(result),
+            false => self.set_szp_flags_from_result_u8(result as u8),
+        }
+    }
+
     pub fn add_u8(byte1: u8, byte2: u8, carry_in: bool) -> (u8, bool, bool, bool) {
         // OVERFLOW flag indicates signed overflow
         // CARRY flag indicates unsigned overflow
@@ -33,6 +33,7 @@
 
 use crate::cpu_808x::*;
 use crate::cpu_808x::muldiv::*;
+use crate::cpu_common::alu::*;
 
 impl Cpu {
 
@@ -129,7 +130,9 @@ impl Cpu {
     }
 
     /// Ascii adjust before Divison
-    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
+    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register.
+    /// The final step is a microcode ADD, so the undefined CF, OF and AF flags are set as for an ADD of
+    /// AL and the product.
     pub fn aad(&mut self, imm8: u8) {
 
         self.cycles_i(3, &[0x170, 0x171, MC_JUMP]);
@@ -137,13 +140,15 @@ impl Cpu {
         let (_, product) = 0u8.corx(self, self.ah as u16, imm8 as u16, false);
         assert!((product as u8) == product_native);
 
-        self.set_register8(Register8::AL, self.al.wrapping_add(product as u8));
+        // 172:          | ADD tmpa  | F
+        let (sum, carry, overflow, aux_carry) = self.al.alu_add(product as u8);
+        self.set_register8(Register8::AL, sum);
         self.set_register8(Register8::AH, 0);
         
         self.cycles_i(2, &[0x172, 0x173]);
 
         // Other sources set flags from AX register. Intel's documentation specifies AL
-        self.set_szp_flags_from_result_u8(self.al);
+        self.set_alu_flags(self.al as u16, false, carry, overflow, aux_carry);
     }
 
     /// DAA — Decimal Adjust AL after Addition
@@ -253,7 +258,8 @@ impl Cpu {
     }
 
     /// AAM - Ascii adjust AX After multiply
-    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
+    /// Flags: The SF, ZF, and PF flags are set according to the resulting binary value in the AL register.
+    /// The result is passed through the ALU, so the undefined CF, OF and AF flags are cleared.
     /// As AAM is implemented via CORD, it can throw an exception. This is indicated by a return value
     /// of false.
     pub fn aam(&mut self, imm8: u8) -> bool {
@@ -270,7 +276,7 @@ impl Cpu {
                 self.set_register8(Register8::AL, remainder as u8);
                 self.cycle_i(0x177);
                 // Other sources set flags from AX register. Intel's documentation specifies AL
-                self.set_szp_flags_from_result_u8(self.al);
+                self.set_alu_flags(self.al as u16, false, false, false, false);
                 return true
             }
             Err(_) => {
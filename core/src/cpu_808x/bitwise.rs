@@ -235,8 +235,14 @@ impl Cpu {
     /// Perform various 8-bit binary shift operations
     pub fn bitshift_op8(&mut self, opcode: Mnemonic, operand1: u8, operand2: u8) -> u8 {
 
+        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
+        let rot_count = match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
+            _=> operand2 & 0x1F
+        };
+
         // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
+        if rot_count == 0 {
             // Flags are not changed if shift amount is 0
             return operand1;
         }
@@ -244,34 +250,20 @@ impl Cpu {
         let result: u8;
         let carry: bool;
 
-        /*
-        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
-        let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
-            _=> operand2 & 0x1F
-        };
-        */
-
-        let rot_count = operand2;
-
         match opcode {
             Mnemonic::ROL => {
                 (result, carry) = Cpu::rol_u8_with_carry(operand1, rot_count);
                 self.set_flag_state(Flag::Carry, carry);
-                // Only set overflow on ROL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF
-                    self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ carry);
-                }
+                // Overflow is only defined for ROL of 1, but for larger counts the 8088 sets it from
+                // the last single-bit rotate: XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ carry);
             }
             Mnemonic::ROR => {
                 (result, carry) = Cpu::ror_u8_with_carry(operand1, rot_count);
                 self.set_flag_state(Flag::Carry, carry);
-                // Only set overflow on ROR of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of two MS bits
-                    self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ ((result & 0x40) != 0));
-                }          
+                // Overflow is only defined for ROR of 1. Set it from the last single-bit rotate:
+                // XOR of two MS bits
+                self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ ((result & 0x40) != 0));
             }
             Mnemonic::RCL => {
                 // Rotate with Carry Left
@@ -280,22 +272,17 @@ impl Cpu {
                 let existing_carry = self.get_flag(Flag::Carry);
                 (result, carry) = Cpu::rcl_u8_with_carry(operand1, rot_count, existing_carry);
                 self.set_flag_state(Flag::Carry, carry);
-                // Only set overflow on SHL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF
-                    self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ carry);
-                }             
+                // Overflow is only defined for RCL of 1. Set it from the last single-bit rotate:
+                // XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ carry);
             }
             Mnemonic::RCR => {
                 let existing_carry = self.get_flag(Flag::Carry);
-                // Only set overflow on SHL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF
-                    self.set_flag_state(Flag::Overflow, ((operand1 & 0x80) != 0) ^ existing_carry);
-                }               
-
                 (result, carry) = Cpu::rcr_u8_with_carry(operand1, rot_count, existing_carry);
                 self.set_flag_state(Flag::Carry, carry);
+                // Overflow is only defined for RCR of 1. Set it from the last single-bit rotate:
+                // XOR of two MS bits of the result, which for a count of 1 is MSB of the operand XOR CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ ((result & 0x40) != 0));
             }
             Mnemonic::SETMO => {
                 self.clear_flag(Flag::Carry);
@@ -325,42 +312,42 @@ impl Cpu {
                 }
             }            
             Mnemonic::SHL => {
-                (result, carry) = Cpu::shl_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shl_u8_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Only set overflow on SHL of 1
-                if operand2 == 1 {
-                    // If the two highest order bits were different, then they will change on shift
-                    // and overflow should be set
-                    self.set_flag_state(Flag::Overflow, (operand1 & 0xC0 == 0x80) || (operand1 & 0xC0 == 0x40));
-                }
+                // Overflow is only defined for SHL of 1, where it is set if the two highest order
+                // bits were different. For larger counts the 8088 sets it from the last single-bit 
+                // shift: XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x80) != 0) ^ carry);
+                // Aux carry is undefined, but is set from bit 4 of the result
+                self.set_flag_state(Flag::AuxCarry, result & 0x10 != 0);
                 
                 self.set_szp_flags_from_result_u8(result);
             }
             Mnemonic::SHR => {
-                (result, carry) = Cpu::shr_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shr_u8_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Only set overflow on SHR of 1
-                if operand2 == 1 {
-                    // Only time SHR sets overflow is if HO was 1 and becomes 0, which it always will,
-                    // so set overflow flag if it was set. 
-                    self.set_flag_state(Flag::Overflow, operand1 & 0x80 != 0 );
-                }
+                // Overflow is only defined for SHR of 1, where it is set if HO was 1, since it always 
+                // becomes 0. For larger counts the 8088 sets it from the last single-bit shift, where
+                // the previous HO bit is now bit 6 of the result.
+                self.set_flag_state(Flag::Overflow, result & 0x40 != 0);
+                // Aux carry is undefined, but is always cleared
+                self.clear_flag(Flag::AuxCarry);
                 self.set_szp_flags_from_result_u8(result);
             }
             Mnemonic::SAR => {
-                (result, carry) = Cpu::sar_u8_with_carry(operand1, operand2);
+                (result, carry) = Cpu::sar_u8_with_carry(operand1, rot_count);
                 // Set Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Clear overflow flag if shift count is 1
-                // AoA 6.6.2.2 SAR
-                if operand2 == 1 {
-                    self.clear_flag(Flag::Overflow);
-                }
+                // Clear overflow flag. Only defined for a shift count of 1 (AoA 6.6.2.2 SAR), but
+                // the sign bit never changes so the 8088 clears it for any count.
+                self.clear_flag(Flag::Overflow);
+                // Aux carry is undefined, but is always cleared
+                self.clear_flag(Flag::AuxCarry);
                 self.set_szp_flags_from_result_u8(result);
             }
             _=> panic!("Invalid opcode provided to bitshift_op8()")
@@ -373,8 +360,14 @@ impl Cpu {
     /// Peform various 16-bit binary shift operations
     pub fn bitshift_op16(&mut self, opcode: Mnemonic, operand1: u16, operand2: u8) -> u16 {
 
+        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
+        let rot_count = match self.cpu_type {
+            CpuType::Intel8088 | CpuType::Intel8086 => operand2,
+            _=> operand2 & 0x1F
+        };
+
         // Operand2 will either be 1 or value of CL register on 8088
-        if operand2 == 0 {
+        if rot_count == 0 {
             // Flags are not changed if shift amount is 0
             return operand1;
         }
@@ -382,16 +375,6 @@ impl Cpu {
         let result: u16;
         let carry: bool;
 
-        /*
-        // All processors after 8086 mask the rotation count to 5 bits (31 maximum)
-        let rot_count = match self.cpu_type {
-            CpuType::Cpu8088 | CpuType::Cpu8086 => operand2,
-            _=> operand2 & 0x1F
-        };
-        */
-
-        let rot_count = operand2;
-
         match opcode {
             Mnemonic::ROL => {
                 // Rotate Left
@@ -400,11 +383,9 @@ impl Cpu {
                 (result, carry) = Cpu::rol_u16_with_carry(operand1, rot_count);
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Overflow only defined for ROL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF*
-                    self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ carry);
-                }
+                // Overflow only defined for ROL of 1, but for larger counts the 8088 sets it from
+                // the last single-bit rotate: XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ carry);
             }
             Mnemonic::ROR => {
                 // Rotate Right
@@ -412,11 +393,9 @@ impl Cpu {
                 (result, carry) = Cpu::ror_u16_with_carry(operand1, rot_count);
                 self.set_flag_state(Flag::Carry, carry);
                 
-                // Overflow only defined for ROR of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of two MS bits*
-                    self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ ((result & 0x4000) != 0));
-                }
+                // Overflow only defined for ROR of 1. Set it from the last single-bit rotate:
+                // XOR of two MS bits
+                self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ ((result & 0x4000) != 0));
             }
             Mnemonic::RCL => {
                 // Rotate with Carry Left
@@ -426,28 +405,22 @@ impl Cpu {
                 let existing_carry = self.get_flag(Flag::Carry);
                 (result, carry) = Cpu::rcl_u16_with_carry(operand1, rot_count, existing_carry);
                 self.set_flag_state(Flag::Carry, carry);
-                // Overflow only defined for RCL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF*
-                    self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ carry);
-                }
+                // Overflow only defined for RCL of 1. Set it from the last single-bit rotate:
+                // XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ carry);
             }
             Mnemonic::RCR => {
                 // Rotate with Carry Right
                 // Flags: For right rotates, the OF flag is set to the exclusive OR of the two most-significant bits of the result.
 
-                // Only set overflow on SHL of 1
                 let existing_carry = self.get_flag(Flag::Carry);
-
-                // Overflow only defined for RCL of 1
-                if rot_count == 1 {
-                    // Set overflow to XOR of MSB and CF*
-                    self.set_flag_state(Flag::Overflow, ((operand1 & 0x8000) != 0) ^ existing_carry);
-                }
-
                 (result, carry) = Cpu::rcr_u16_with_carry(operand1, rot_count, existing_carry);
                 self.set_flag_state(Flag::Carry, carry);
 
+                // Overflow only defined for RCR of 1. Set it from the last single-bit rotate:
+                // XOR of two MS bits of the result, which for a count of 1 is MSB of the operand XOR CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ ((result & 0x4000) != 0));
+
                 // The rcr instruction does not affect the zero, sign, parity, or auxiliary carry flags.
                 // AoA 6.6.3.2
             }
@@ -479,41 +452,41 @@ impl Cpu {
                 }
             }            
             Mnemonic::SHL => {
-                (result, carry) = Cpu::shl_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shl_u16_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Only set overflow on SHL of 1
-                if operand2 == 1 {
-                    // If the two highest order bits were different, then they will change on shift
-                    // and overflow should be set
-                    self.set_flag_state(Flag::Overflow, (operand1 & 0xC000 == 0x8000) || (operand1 & 0xC000 == 0x4000));
-                }
+                // Overflow is only defined for SHL of 1, where it is set if the two highest order
+                // bits were different. For larger counts the 8088 sets it from the last single-bit 
+                // shift: XOR of MSB and CF
+                self.set_flag_state(Flag::Overflow, ((result & 0x8000) != 0) ^ carry);
+                // Aux carry is undefined, but is set from bit 4 of the result
+                self.set_flag_state(Flag::AuxCarry, result & 0x10 != 0);
                 self.set_szp_flags_from_result_u16(result);
             }
             Mnemonic::SHR => {
-                (result, carry) = Cpu::shr_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::shr_u16_with_carry(operand1, rot_count);
                 // Set state of Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Only set overflow on SHR of 1
-                if operand2 == 1 {
-                    // Only time SHR sets overflow is if HO was 1 and becomes 0, which it always will,
-                    // so set overflow flag if it was set. 
-                    self.set_flag_state(Flag::Overflow, operand1 & 0x8000 != 0 );
-                }
+                // Overflow is only defined for SHR of 1, where it is set if HO was 1, since it always 
+                // becomes 0. For larger counts the 8088 sets it from the last single-bit shift, where
+                // the previous HO bit is now bit 14 of the result.
+                self.set_flag_state(Flag::Overflow, result & 0x4000 != 0);
+                // Aux carry is undefined, but is always cleared
+                self.clear_flag(Flag::AuxCarry);
                 self.set_szp_flags_from_result_u16(result);
             }
             Mnemonic::SAR => {
-                (result, carry) = Cpu::sar_u16_with_carry(operand1, operand2);
+                (result, carry) = Cpu::sar_u16_with_carry(operand1, rot_count);
                 // Set Carry Flag
                 self.set_flag_state(Flag::Carry, carry);
 
-                // Clear overflow flag if shift count is 1
-                // AoA 6.6.2.2 SAR
-                if operand2 == 1 {
-                    self.clear_flag(Flag::Overflow);
-                }
+                // Clear overflow flag. Only defined for a shift count of 1 (AoA 6.6.2.2 SAR), but
+                // the sign bit never changes so the 8088 clears it for any count.
+                self.clear_flag(Flag::Overflow);
+                // Aux carry is undefined, but is always cleared
+                self.clear_flag(Flag::AuxCarry);
                 self.set_szp_flags_from_result_u16(result);
             }
             _=> panic!("Invalid opcode provided to bitshift_op16()")
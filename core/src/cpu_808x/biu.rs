@@ -31,6 +31,7 @@
 */
 use crate::cpu_808x::*;
 use crate::bytequeue::*;
+use crate::bus::BusInitiator;
 
 #[derive (Debug, PartialEq)]
 pub enum BiuState {
@@ -237,7 +238,7 @@ impl Cpu {
             match self.cpu_type {
                 // 8088 will have room in queue at 3 bytes,
                 // 8086 will have room in queue at 4 bytes
-                CpuType::Intel8088 => {
+                CpuType::Intel8088 | CpuType::NecV20 => {
                     if self.queue.len() == 3 {
                         self.biu_state = BiuState::Resuming(3);
                         trace_print!(self, "Resuming from suspend due to queue read.");
@@ -428,7 +429,7 @@ impl Cpu {
 
     pub fn biu_queue_has_room(&mut self) -> bool {
         match self.cpu_type {
-            CpuType::Intel8088 => {
+            CpuType::Intel8088 | CpuType::NecV20 => {
                 self.queue.len() < 4
             }
             CpuType::Intel8086 => {
@@ -691,7 +692,7 @@ impl Cpu {
         let mut word;
 
         match self.cpu_type {
-            CpuType::Intel8088 => {
+            CpuType::Intel8088 | CpuType::NecV20 => {
                 // 8088 performs two consecutive byte transfers
                 self.biu_bus_begin(
                     BusStatus::MemRead, 
@@ -751,7 +752,7 @@ impl Cpu {
     pub fn biu_write_u16(&mut self, seg: Segment, addr: u32, word: u16, flag: ReadWriteFlag) {
 
         match self.cpu_type {
-            CpuType::Intel8088 => {
+            CpuType::Intel8088 | CpuType::NecV20 => {
                 // 8088 performs two consecutive byte transfers
                 self.biu_bus_begin(
                     BusStatus::MemWrite, 
@@ -942,8 +943,15 @@ impl Cpu {
         self.trace_comment("BUS_BEGIN");
 
         // Check this address for a memory access breakpoint
-        if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 {
+        if self.bus.get_flags(address as usize) & MEM_BPA_BIT != 0 
+            && self.check_access_breakpoint(address, new_bus_status) {
             // Breakpoint hit
+            log::debug!("Memory breakpoint hit at {:05X}", address);
+            self.last_watch_hit = Some(BusAccess {
+                address,
+                write: new_bus_status == BusStatus::MemWrite,
+                initiator: BusInitiator::Cpu
+            });
             self.state = CpuState::BreakpointHit;
         }
 
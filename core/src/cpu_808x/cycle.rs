@@ -153,11 +153,8 @@ impl Cpu {
                                 self.bus_wait_states = self.bus.get_write_wait(self.address_bus as usize, self.instr_elapsed).unwrap();
                                 self.instr_elapsed = 0;
                             }
-                            BusStatus::IoRead => {
-                                self.bus_wait_states = 1;
-                            }
-                            BusStatus::IoWrite => {
-                                self.bus_wait_states = 1;
+                            BusStatus::IoRead | BusStatus::IoWrite => {
+                                self.bus_wait_states = self.bus.get_io_wait((self.address_bus & 0xFFFF) as u16);
                             }                                                                                                                     
                             _=> {}
                         }
@@ -227,6 +224,7 @@ impl Cpu {
                                     self.i8288.iorc = true;
                                     byte = self.bus.io_read_u8((self.address_bus & 0xFFFF) as u16, self.instr_elapsed);
                                     self.data_bus = byte as u16;
+                                    self.trace_io((self.address_bus & 0xFFFF) as u16, byte, false);
                                     self.instr_elapsed = 0;
                                     self.transfer_n += 1;
 
@@ -239,6 +237,7 @@ impl Cpu {
                                         (self.data_bus & 0x00FF) as u8,
                                         self.instr_elapsed
                                     );
+                                    self.trace_io((self.address_bus & 0xFFFF) as u16, (self.data_bus & 0x00FF) as u8, true);
                                     self.instr_elapsed = 0;
                                     self.transfer_n += 1;
 
@@ -294,8 +293,8 @@ impl Cpu {
         };
 
         // Perform cycle tracing, if enabled
-        if self.trace_enabled && self.trace_mode == TraceMode::Cycle {
-            self.trace_print(&self.cycle_state_string(false));   
+        if self.trace_sessions.is_active(TraceKind::Cycle) {
+            self.trace_cycle();
             self.trace_str_vec.push(self.cycle_state_string(true));
 
             self.trace_comment.clear();
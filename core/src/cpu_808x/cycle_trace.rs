@@ -0,0 +1,261 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+    
+    cpu_808x::cycle_trace.rs
+
+    Exports cycle traces in machine-readable formats, so that they can be
+    diffed against other emulators and the 8088 single-step test corpus.
+
+    Each cycle is described by the same fields as the cycles of a test in
+    the corpus: ALE, the address latch, the segment status, the memory and
+    IO read/write status, the data bus, the bus status, the T-state, the 
+    queue operation and the byte read from the queue. In JSON format, the 
+    cycles of each instruction are written as one object per line. In CSV
+    format, each cycle is written as one row.
+
+*/
+
+use serde_json::json;
+
+use crate::config::TraceFormat;
+use crate::cpu_808x::*;
+
+pub const CYCLE_TRACE_CSV_HEADER: &str = "cycle,pins,address,segment,memory,io,data,bus,t_state,queue_op,queue_byte";
+
+/// The state of the CPU bus for one cycle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CycleTraceEntry {
+    pub n: u32,
+    pub ale: bool,
+    pub address: u32,
+    pub segment: &'static str,
+    pub memory: String,
+    pub io: String,
+    pub data_bus: u16,
+    pub bus: &'static str,
+    pub t_state: &'static str,
+    pub queue_op: char,
+    pub queue_byte: u8,
+}
+
+impl CycleTraceEntry {
+    /// Bit 0 of the pin status is ALE.
+    fn pins(&self) -> u8 {
+        self.ale as u8
+    }
+
+    /// Return the cycle as an array, in the field order used by the single-step test corpus.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!([
+            self.pins(),
+            self.address,
+            self.segment,
+            self.memory,
+            self.io,
+            self.data_bus,
+            self.bus,
+            self.t_state,
+            self.queue_op.to_string(),
+            self.queue_byte
+        ])
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            self.n,
+            self.pins(),
+            self.address,
+            self.segment,
+            self.memory,
+            self.io,
+            self.data_bus,
+            self.bus,
+            self.t_state,
+            self.queue_op,
+            self.queue_byte
+        )
+    }
+}
+
+/// Return a JSON object for the cycles of the specified instruction, as a single line.
+pub fn instruction_record(name: &str, address: u32, bytes: &[u8], cycles: &[CycleTraceEntry]) -> String {
+    json!({
+        "name": name,
+        "address": address,
+        "bytes": bytes,
+        "cycles": cycles.iter().map(|c| c.to_json()).collect::<Vec<_>>(),
+    }).to_string()
+}
+
+/// Format read/write status signals as in the test corpus, eg "R--", or "---" when inactive.
+fn rw_status(read: bool, advanced_write: bool, write: bool) -> String {
+    [(read, 'R'), (advanced_write, 'A'), (write, 'W')]
+        .iter()
+        .map(|&(active, c)| if active { c } else { '-' })
+        .collect()
+}
+
+impl Cpu {
+
+    pub fn set_trace_format(&mut self, format: TraceFormat) {
+        self.trace_format = format;
+    }
+
+    fn cycle_trace_entry(&self) -> CycleTraceEntry {
+
+        let is_reading = self.i8288.mrdc | self.i8288.iorc;
+        let is_writing = self.i8288.mwtc | self.i8288.iowc;
+
+        let queue_read = matches!(self.last_queue_op, QueueOp::First | QueueOp::Subsequent);
+
+        CycleTraceEntry {
+            n: self.instr_cycle,
+            ale: self.i8288.ale,
+            address: self.address_bus,
+            // Segment status is only valid in T2 and later
+            segment: match (self.t_cycle, self.bus_segment) {
+                (TCycle::TInit | TCycle::T1, _) | (_, Segment::None) => "--",
+                (_, Segment::ES) => "ES",
+                (_, Segment::SS) => "SS",
+                (_, Segment::CS) => "CS",
+                (_, Segment::DS) => "DS",
+            },
+            memory: rw_status(self.i8288.mrdc, self.i8288.amwc, self.i8288.mwtc),
+            io: rw_status(self.i8288.iorc, self.i8288.aiowc, self.i8288.iowc),
+            data_bus: if is_reading || is_writing { self.data_bus } else { 0 },
+            // Bus status is only valid in T1 and T2
+            bus: match self.t_cycle {
+                TCycle::TInit | TCycle::T1 | TCycle::T2 => match self.bus_status {
+                    BusStatus::InterruptAck => "INTA",
+                    BusStatus::IoRead => "IOR",
+                    BusStatus::IoWrite => "IOW",
+                    BusStatus::Halt => "HALT",
+                    BusStatus::CodeFetch => "CODE",
+                    BusStatus::MemRead => "MEMR",
+                    BusStatus::MemWrite => "MEMW",
+                    BusStatus::Passive => "PASV",
+                },
+                _ => "PASV",
+            },
+            t_state: match self.t_cycle {
+                TCycle::TInit | TCycle::T1 => match self.bus_status {
+                    BusStatus::Passive => "Ti",
+                    _ => "T1",
+                },
+                TCycle::T2 => "T2",
+                TCycle::T3 => "T3",
+                TCycle::Tw => "Tw",
+                TCycle::T4 => "T4",
+            },
+            queue_op: match self.last_queue_op {
+                QueueOp::Idle => '-',
+                QueueOp::First => 'F',
+                QueueOp::Flush => 'E',
+                QueueOp::Subsequent => 'S',
+            },
+            queue_byte: if queue_read { self.last_queue_byte } else { 0 },
+        }
+    }
+
+    /// Trace the current cycle in the selected trace format. JSON traces are buffered until
+    /// the instruction completes.
+    pub fn trace_cycle(&mut self) {
+        match self.trace_format {
+            TraceFormat::Text => {
+                self.trace_print(&self.cycle_state_string(false));
+            }
+            TraceFormat::Csv => {
+                let entry = self.cycle_trace_entry();
+                self.trace_print(&entry.to_csv());
+            }
+            TraceFormat::Json => {
+                let entry = self.cycle_trace_entry();
+                self.cycle_trace_buf.push(entry);
+            }
+        }
+    }
+
+    /// Write the buffered cycles of the last instruction to the cycle trace, if any.
+    pub fn trace_cycle_record(&mut self) {
+        if self.cycle_trace_buf.is_empty() {
+            return
+        }
+
+        let address = self.i.address as usize & 0xFFFFF;
+        let len = (self.i.size as usize).min(0x100000 - address);
+        let record = instruction_record(
+            &self.i.to_string(),
+            self.i.address,
+            self.bus.get_slice_at(address, len),
+            &self.cycle_trace_buf
+        );
+        self.cycle_trace_buf.clear();
+        self.trace_print(&record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch_cycle() -> CycleTraceEntry {
+        CycleTraceEntry {
+            n: 0,
+            ale: true,
+            address: 0xFFFF0,
+            segment: "--",
+            memory: rw_status(false, false, false),
+            io: rw_status(false, false, false),
+            data_bus: 0,
+            bus: "CODE",
+            t_state: "T1",
+            queue_op: '-',
+            queue_byte: 0,
+        }
+    }
+
+    #[test]
+    fn test_cycle_formats() {
+        let cycle = fetch_cycle();
+        assert_eq!(cycle.to_json().to_string(), r#"[1,1048560,"--","---","---",0,"CODE","T1","-",0]"#);
+        assert_eq!(cycle.to_csv(), "0,1,1048560,--,---,---,0,CODE,T1,-,0");
+        assert_eq!(cycle.to_csv().split(',').count(), CYCLE_TRACE_CSV_HEADER.split(',').count());
+        assert_eq!(rw_status(true, false, true), "R-W");
+    }
+
+    #[test]
+    fn test_instruction_record() {
+        let record = instruction_record("nop", 0x100, &[0x90], &[fetch_cycle(), fetch_cycle()]);
+        let value: serde_json::Value = serde_json::from_str(&record).unwrap();
+
+        assert!(!record.contains('\n'));
+        assert_eq!(value["name"], "nop");
+        assert_eq!(value["bytes"], json!([0x90]));
+        assert_eq!(value["cycles"].as_array().unwrap().len(), 2);
+        assert_eq!(value["cycles"][0][6], "CODE");
+    }
+}
@@ -105,7 +105,14 @@ impl Display for InstructionDecodeError{
 }
 
 impl Cpu {
+    /// Decode an instruction as an Intel 8088 would.
     pub fn decode(bytes: &mut impl ByteQueue) -> Result<Instruction, Box<dyn std::error::Error>> {
+        Cpu::decode_for(bytes, CpuType::Intel8088)
+    }
+
+    /// Decode an instruction for the specified CPU type. The NEC V20 defines new instructions
+    /// for several opcodes that are undocumented aliases on the 8088.
+    pub fn decode_for(bytes: &mut impl ByteQueue, cpu_type: CpuType) -> Result<Instruction, Box<dyn std::error::Error>> {
 
         let mut operand1_type: OperandType = OperandType::NoOperand;
         let mut operand2_type: OperandType = OperandType::NoOperand;
@@ -139,6 +146,9 @@ impl Cpu {
                 0xF0 => OPCODE_PREFIX_LOCK,
                 0xF2 => OPCODE_PREFIX_REP1,
                 0xF3 => OPCODE_PREFIX_REP2,
+                // The V20 adds prefixes that repeat while carry is clear or set.
+                0x64 if cpu_type == CpuType::NecV20 => OPCODE_PREFIX_REPNC,
+                0x65 if cpu_type == CpuType::NecV20 => OPCODE_PREFIX_REPC,
                 _=> {
                     break;
                 }
@@ -355,6 +365,18 @@ impl Cpu {
             _=> (Mnemonic::NoOpcode, OperandTemplate::NoTemplate, OperandTemplate::NoTemplate,  0)
         };
 
+        if cpu_type == CpuType::NecV20 {
+            if let Some(v20_op) = Cpu::decode_v20_opcode(opcode) {
+                (mnemonic, operand1_template, operand2_template, op_flags) = v20_op;
+            }
+            // Read the second byte of an extended instruction, which determines its operands.
+            if opcode == 0x0F {
+                let ext_opcode = bytes.q_read_u8(QueueType::Subsequent, QueueReader::Biu);
+                size += 1;
+                (operand1_template, operand2_template) = Cpu::decode_v20_extended_opcode(ext_opcode);
+            }
+        }
+
         let mut modrm = Default::default();
 
         // If we haven't had a match yet, we are in a group instruction
@@ -398,6 +420,25 @@ impl Cpu {
                 (0x83, 0x06) => (Mnemonic::XOR,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8SignExtended,    I_LOAD_EA ),
                 (0x83, 0x07) => (Mnemonic::CMP,   OperandTemplate::ModRM16,   OperandTemplate::Immediate8SignExtended,    I_LOAD_EA ),   
                 
+                // V20 only: shift and rotate by immediate count
+                (0xC0, 0x00) => (Mnemonic::ROL,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x01) => (Mnemonic::ROR,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x02) => (Mnemonic::RCL,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x03) => (Mnemonic::RCR,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x04) => (Mnemonic::SHL,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x05) => (Mnemonic::SHR,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x06) => (Mnemonic::SETMOC, OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC0, 0x07) => (Mnemonic::SAR,    OperandTemplate::ModRM8,    OperandTemplate::Immediate8,    I_LOAD_EA ),
+
+                (0xC1, 0x00) => (Mnemonic::ROL,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x01) => (Mnemonic::ROR,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x02) => (Mnemonic::RCL,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x03) => (Mnemonic::RCR,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x04) => (Mnemonic::SHL,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x05) => (Mnemonic::SHR,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x06) => (Mnemonic::SETMOC, OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+                (0xC1, 0x07) => (Mnemonic::SAR,    OperandTemplate::ModRM16,   OperandTemplate::Immediate8,    I_LOAD_EA ),
+
                 (0xD0, 0x00) => (Mnemonic::ROL,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
                 (0xD0, 0x01) => (Mnemonic::ROR,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
                 (0xD0, 0x02) => (Mnemonic::RCL,   OperandTemplate::ModRM8,    OperandTemplate::NoOperand,    I_LOAD_EA ),
@@ -666,7 +707,28 @@ impl Cpu {
 
         //size = bytes.tell() as u32 - op_address;
 
-        if let Mnemonic::InvalidOpcode = mnemonic {
+        // Some V20 instructions have an additional immediate operand that doesn't fit the operand
+        // templates. Extra immediates are read during execute.
+        if cpu_type == CpuType::NecV20 {
+            match opcode {
+                0x69 => size += 2, // IMUL r16, r/m16, imm16
+                0x6B => size += 1, // IMUL r16, r/m16, imm8
+                0xC8 => {
+                    // ENTER imm16, imm8
+                    let (level, frame_size) = bytes.q_peek_farptr16();
+                    operand1_type = OperandType::Immediate16(frame_size);
+                    operand1_size = OperandSize::Operand16;
+                    operand2_type = OperandType::Immediate8(level as u8);
+                    operand2_size = OperandSize::Operand8;
+                    size += 3;
+                }
+                _ => {}
+            }
+        }
+
+        // The V20's extended instructions decode as an invalid opcode that executes as a NOP,
+        // as software may probe for them.
+        if mnemonic == Mnemonic::InvalidOpcode && !(cpu_type == CpuType::NecV20 && opcode == 0x0F) {
             return Err(Box::new(InstructionDecodeError::UnsupportedOpcode(opcode)));
         }
 
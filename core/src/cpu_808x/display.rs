@@ -55,6 +55,7 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::ADC => "ADC",
         Mnemonic::ADD => "ADD",
         Mnemonic::AND => "AND",
+        Mnemonic::BOUND => "BOUND",
         Mnemonic::CALL => "CALL",
         Mnemonic::CALLF => "CALLF",
         Mnemonic::CBW => "CBW",
@@ -71,12 +72,15 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::DEC => "DEC",
         Mnemonic::DIV => "DIV",
         Mnemonic::ESC => "ESC",
+        Mnemonic::ENTER => "ENTER",
         Mnemonic::FWAIT => "FWAIT",
         Mnemonic::HLT => "HLT",
         Mnemonic::IDIV => "IDIV",
         Mnemonic::IMUL => "IMUL",
         Mnemonic::IN => "IN",
         Mnemonic::INC => "INC",
+        Mnemonic::INSB => "INSB",
+        Mnemonic::INSW => "INSW",
         Mnemonic::INT => "INT",
         Mnemonic::INT3 => "INT3",
         Mnemonic::INTO => "INTO",
@@ -103,6 +107,7 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::LAHF => "LAHF",
         Mnemonic::LDS => "LDS",
         Mnemonic::LEA => "LEA",
+        Mnemonic::LEAVE => "LEAVE",
         Mnemonic::LES => "LES",
         Mnemonic::LOCK => "LOCK",
         Mnemonic::LODSB => "LODSB",
@@ -118,9 +123,13 @@ fn mnemonic_to_str(op: Mnemonic) -> &'static str {
         Mnemonic::NOT => "NOT",
         Mnemonic::OR => "OR",
         Mnemonic::OUT => "OUT",
+        Mnemonic::OUTSB => "OUTSB",
+        Mnemonic::OUTSW => "OUTSW",
         Mnemonic::POP => "POP",
+        Mnemonic::POPA => "POPA",
         Mnemonic::POPF => "POPF",
         Mnemonic::PUSH => "PUSH",
+        Mnemonic::PUSHA => "PUSHA",
         Mnemonic::PUSHF => "PUSHF",
         Mnemonic::RCL => "RCL",
         Mnemonic::RCR => "RCR",
@@ -671,6 +680,12 @@ fn prefix_to_string(i: &Instruction ) -> String {
     else if i.prefixes & OPCODE_PREFIX_LOCK != 0 {
         "lock".to_string()
     }
+    else if i.prefixes & OPCODE_PREFIX_REPNC != 0 {
+        "repnc".to_string()
+    }
+    else if i.prefixes & OPCODE_PREFIX_REPC != 0 {
+        "repc".to_string()
+    }
     else if i.prefixes & OPCODE_PREFIX_REP2 != 0 {
         match i.opcode {
             0xA4 | 0xA5 | 0xAA | 0xAB | 0xAC | 0xAD => "rep".to_string(),
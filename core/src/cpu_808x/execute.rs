@@ -97,13 +97,6 @@ impl Cpu {
 
         self.trace_comment("EXECUTE");
 
-        // Reset trap suppression flag
-        self.trap_suppressed = false;
-
-        // Decrement trap counters.
-        self.trap_enable_delay = self.trap_enable_delay.saturating_sub(1);
-        self.trap_disable_delay = self.trap_disable_delay.saturating_sub(1);
-
         // If we have an NX loaded RNI cycle from the previous instruction, execute it.
         // Otherwise wait one cycle before beginning instruction if there was no modrm.
         if self.nx {
@@ -134,7 +127,7 @@ impl Cpu {
         }
 
         // Check for REPx prefixes
-        if (self.i.prefixes & OPCODE_PREFIX_REP1 != 0) || (self.i.prefixes & OPCODE_PREFIX_REP2 != 0) {
+        if self.i.prefixes & (OPCODE_PREFIX_REP1 | OPCODE_PREFIX_REP2 | OPCODE_PREFIX_REPNC | OPCODE_PREFIX_REPC) != 0 {
             // A REPx prefix was set
             
             let mut invalid_rep = false;
@@ -148,11 +141,21 @@ impl Cpu {
                     if self.i.prefixes & OPCODE_PREFIX_REP1 != 0 {
                         self.rep_type = RepType::Repne;
                     }
+                    else if self.i.prefixes & OPCODE_PREFIX_REPNC != 0 {
+                        self.rep_type = RepType::Repnc;
+                    }
+                    else if self.i.prefixes & OPCODE_PREFIX_REPC != 0 {
+                        self.rep_type = RepType::Repc;
+                    }
                     else {
                         self.rep_type = RepType::Repe;
                     }
                 }
-                Mnemonic::MUL | Mnemonic::IMUL | Mnemonic::DIV | Mnemonic::IDIV => {
+                Mnemonic::INSB | Mnemonic::INSW | Mnemonic::OUTSB | Mnemonic::OUTSW => {
+                    // Valid string I/O ops with REP prefix (V20 only)
+                    self.rep_type = RepType::Rep;
+                }
+                Mnemonic::MUL | Mnemonic::IMUL | Mnemonic::DIV | Mnemonic::IDIV if self.cpu_type != CpuType::NecV20 => {
                     // REP prefix on MUL/DIV negates the product/quotient.
                     self.rep_type = RepType::Rep;
                 }
@@ -171,8 +174,17 @@ impl Cpu {
             }
         }
 
-        // Reset the wait cycle after STI
+        // Reset the wait cycle after STI or a write to SS
         self.interrupt_inhibit = false;
+        self.ss_inhibit = false;
+
+        // Latch the trap flag. A trap is taken after an instruction if TF was set when it began, so
+        // an instruction that sets TF (POPF, IRET) is not trapped, but one that clears it is. 
+        // A REP string instruction is trapped after each iteration, through RPTI.
+        self.trap_pending = self.get_flag(Flag::Trap);
+        if self.trap_pending && self.in_rep {
+            self.pending_interrupt = true;
+        }
         
         // Most instructions will issue an RNI. We can set RNI to false for those that don't.
         //self.rni = true;
@@ -196,7 +208,13 @@ impl Cpu {
             self.opcode0_counter = 0;
         }
 
+        // The V20 implements new instructions in several opcodes, and executes some existing
+        // instructions differently.
+        let v20_executed = self.cpu_type == CpuType::NecV20 
+            && self.execute_v20_instruction(&mut jump, &mut exception);
+
         match self.i.opcode {
+            _ if v20_executed => {}
             0x00 | 0x02 | 0x04 |  // ADD r/m8, r8 | r8, r/m8 | al, imm8
             0x08 | 0x0A | 0x0C |  // OR  r/m8, r8 | r8, r/m8 | al, imm8
             0x10 | 0x12 | 0x14 |  // ADC r/m8, r8 | r8, r/m8 | al, imm8 
@@ -773,6 +791,20 @@ impl Cpu {
                                     end = true;
                                 }
                             }
+                            RepType::Repnc => {
+                                // V20: Repeat while NOT carry. If carry flag is set, end REP.
+                                if self.get_flag(Flag::Carry) {
+                                    self.rep_end();
+                                    end = true;
+                                }
+                            }
+                            RepType::Repc => {
+                                // V20: Repeat while carry. If carry flag is NOT set, end REP.
+                                if !self.get_flag(Flag::Carry) {
+                                    self.rep_end();
+                                    end = true;
+                                }
+                            }
                             _=> {}
                         };
 
@@ -1452,8 +1484,6 @@ impl Cpu {
                         //self.multiply_u8(op1_value);
                         let product = self.mul8(self.al, op1_value, false, negate);
                         self.set_register16(Register16::AX, product);
-
-                        self.set_szp_flags_from_result_u8(self.ah);
                     }
                     Mnemonic::IMUL => {
                         let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
@@ -1461,8 +1491,6 @@ impl Cpu {
                         //self.multiply_i8(op1_value as i8);
                         let product = self.mul8(self.al, op1_value, true, negate);
                         self.set_register16(Register16::AX, product);
-
-                        self.set_szp_flags_from_result_u8(self.ah);
                     }                    
                     Mnemonic::DIV => {
                         let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
@@ -1549,8 +1577,6 @@ impl Cpu {
                         let (dx, ax) = self.mul16(self.ax, op1_value, false, negate);
                         self.set_register16(Register16::DX, dx);
                         self.set_register16(Register16::AX, ax);
-
-                        self.set_szp_flags_from_result_u16(self.dx);
                     }
                     Mnemonic::IMUL => {
                         let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
@@ -1560,8 +1586,6 @@ impl Cpu {
                         let (dx, ax) = self.mul16(self.ax, op1_value, true, negate);
                         self.set_register16(Register16::DX, dx);
                         self.set_register16(Register16::AX, ax);    
-
-                        self.set_szp_flags_from_result_u16(self.dx);                    
                     }
                     Mnemonic::DIV => {
                         let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
@@ -92,6 +92,37 @@ impl Cpu {
         //self.set_flags(0);
     }
 
+    /// Reset the CPU and load the specified register state, such as from a saved validator case.
+    #[cfg(feature = "cpu_validator")]
+    pub fn set_vregisters(&mut self, regs: &crate::cpu_validator::VRegisters) {
+
+        self.cs = regs.cs;
+        self.ip = regs.ip;
+
+        self.set_reset_vector(CpuAddress::Segmented(self.cs, self.ip));
+        self.reset();
+
+        self.set_register16(Register16::AX, regs.ax);
+        self.set_register16(Register16::BX, regs.bx);
+        self.set_register16(Register16::CX, regs.cx);
+        self.set_register16(Register16::DX, regs.dx);
+        self.set_register16(Register16::SP, regs.sp);
+        self.set_register16(Register16::BP, regs.bp);
+        self.set_register16(Register16::SI, regs.si);
+        self.set_register16(Register16::DI, regs.di);
+
+        // Adjust pc
+        self.pc = Cpu::calc_linear_address(self.cs, self.ip);
+        // Flush queue
+        self.queue.flush();
+
+        self.ds = regs.ds;
+        self.ss = regs.ss;
+        self.es = regs.es;
+
+        self.set_flags(regs.flags);
+    }
+
     #[allow(dead_code)]
     pub fn randomize_mem(&mut self) {
 
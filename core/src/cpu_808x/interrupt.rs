@@ -156,10 +156,22 @@ impl Cpu {
             }            
         }         
 
-        if interrupt == 0x16 {
-            if self.ah == 0x01 {
+        // Count calls that indicate the guest is waiting for something to do, for idle detection.
+        match (interrupt, self.ah) {
+            (0x16, 0x00 | 0x01 | 0x10 | 0x11) => {
+                // Keyboard read or poll
                 //log::trace!("int16,01: Poll keyboard @ [{:04X}]:[{:04X}]", self.cs, self.ip);
+                self.idle_call_count += 1;
             }
+            (0x28, _) => {
+                // DOS idle interrupt
+                self.idle_call_count += 1;
+            }
+            (0x2F, 0x16) if self.al == 0x80 => {
+                // Release current VM time slice
+                self.idle_call_count += 1;
+            }
+            _ => {}
         }
 
         self.int_count += 1;
@@ -170,6 +182,14 @@ impl Cpu {
 
         self.push_flags(ReadWriteFlag::Normal);
 
+        // As with other interrupts, if the trap flag was set on entry, a trap is taken before the 
+        // first instruction of the handler.
+        if self.get_flag(Flag::Trap) {
+            self.trap_pending = true;
+        }
+        self.clear_flag(Flag::Interrupt);
+        self.clear_flag(Flag::Trap);
+
         // Push return address of next instruction onto stack
         self.push_register16(Register16::CS, ReadWriteFlag::Normal);
 
@@ -280,6 +300,12 @@ impl Cpu {
         self.biu_suspend_fetch(); // 1a3 SUSP
         self.cycles_i(2, &[0x1a3, 0x1a4]);
         self.push_flags(ReadWriteFlag::Normal);
+        // If the trap flag was set on entry, a trap is taken before the first instruction of the 
+        // handler. This is how a debugger single-stepping through an INT or hardware interrupt sees
+        // the start of the handler.
+        if self.get_flag(Flag::Trap) {
+            self.trap_pending = true;
+        }
         self.clear_flag(Flag::Interrupt);
         self.clear_flag(Flag::Trap);        
         self.cycle_i(0x1a6);
@@ -305,6 +331,7 @@ impl Cpu {
     pub fn int1(&mut self) {
         self.cycles_i(2, &[0x198, MC_JUMP]);
         self.intr_routine(1, InterruptType::Hardware, true);
+        self.trap_pending = false;
         self.int_count += 1;        
     }
 
@@ -342,9 +369,170 @@ impl Cpu {
     /// Returns true if a trap can occur under current execution state.
     #[inline]
     pub fn trap_enabled(&self) -> bool {
-        // Trap if trap flag is set, OR trap flag has been cleared but disable delay in effect (to trap POPF that clears trap)
-        // but only if trap is not suppressed and enable delay is 0.
-        (self.get_flag(Flag::Trap) || self.trap_disable_delay != 0) && !self.trap_suppressed && self.trap_enable_delay == 0
+        // Trap if the trap flag was latched by the last instruction or set on entry to an interrupt,
+        // unless the last instruction wrote SS. A halted CPU only leaves halt for an interrupt.
+        self.trap_pending && !self.ss_inhibit && !self.halted
+    }
+
+}
+#[cfg(test)]
+mod tests {
+    use crate::cpu_808x::*;
+    use crate::config::TraceMode;
+    #[cfg(feature = "cpu_validator")]
+    use crate::config::ValidatorType;
+    use crate::cpu_common::CpuType;
+    use crate::tracelogger::TraceLogger;
+
+    const CODE_SEG: u16 = 0x1000;
+    const STACK_SEG: u16 = 0x2000;
+    const STACK_TOP: u16 = 0x0100;
+    const INT1_HANDLER: u16 = 0x0400;
+    const INT21_HANDLER: u16 = 0x0500;
+    const INT0_HANDLER: u16 = 0x0600;
+
+    /// Create a CPU with TF set, about to execute 'code' at CODE_SEG:0000. The INT1 handler is 
+    /// a single IRET, and the INT 0 and INT 21h handlers are a NOP followed by IRET.
+    fn trap_cpu(code: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(
+            CpuType::Intel8088,
+            TraceMode::None,
+            TraceLogger::None,
+            #[cfg(feature = "cpu_validator")]
+            ValidatorType::None,
+            #[cfg(feature = "cpu_validator")]
+            TraceLogger::None
+        );
+
+        let bus = cpu.bus_mut();
+        for (vector, handler) in [(0usize, INT0_HANDLER), (1, INT1_HANDLER), (0x21, INT21_HANDLER)] {
+            bus.write_u8(vector * 4, handler as u8, 0).unwrap();
+            bus.write_u8(vector * 4 + 1, (handler >> 8) as u8, 0).unwrap();
+        }
+        bus.write_u8(INT1_HANDLER as usize, 0xCF, 0).unwrap();
+        for handler in [INT0_HANDLER, INT21_HANDLER] {
+            bus.write_u8(handler as usize, 0x90, 0).unwrap();
+            bus.write_u8(handler as usize + 1, 0xCF, 0).unwrap();
+        }
+        let code_addr = Cpu::calc_linear_address(CODE_SEG, 0) as usize;
+        for (i, byte) in code.iter().enumerate() {
+            bus.write_u8(code_addr + i, *byte, 0).unwrap();
+        }
+
+        cpu.set_reset_vector(CpuAddress::Segmented(CODE_SEG, 0));
+        cpu.reset();
+        cpu.set_register16(Register16::SS, STACK_SEG);
+        cpu.set_register16(Register16::SP, STACK_TOP);
+        cpu.set_flags(CPU_FLAG_TRAP);
+        cpu
+    }
+
+    /// Read the word at SS:SP+offset. A push at the end of an instruction may still be writing
+    /// its high byte, so the bus cycle in progress is completed first.
+    fn stack_word(cpu: &mut Cpu, offset: u16) -> u16 {
+        cpu.biu_bus_wait_finish();
+        let addr = Cpu::calc_linear_address(cpu.ss, cpu.sp.wrapping_add(offset));
+        cpu.bus_mut().read_u16(addr as usize, 0).unwrap().0
+    }
+
+    fn step(cpu: &mut Cpu, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(false).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_trap_each_instruction() {
+        // NOP, NOP, NOP
+        let mut cpu = trap_cpu(&[0x90, 0x90, 0x90]);
+
+        // The first NOP executes, then the trap is taken.
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), 0x0001);
+        assert_eq!(stack_word(&mut cpu, 2), CODE_SEG);
+        assert!(stack_word(&mut cpu, 4) & CPU_FLAG_TRAP != 0);
+        assert!(!cpu.get_flag(Flag::Trap));
+
+        // IRET from the handler sets TF again. One more instruction executes before the next trap.
+        step(&mut cpu, 3);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), 0x0002);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_trap_nested_int() {
+        // INT 21h, NOP
+        let mut cpu = trap_cpu(&[0xCD, 0x21, 0x90]);
+
+        // INT 21h executes, then the trap is taken before the first instruction of its handler.
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), INT21_HANDLER);
+        assert_eq!(stack_word(&mut cpu, 2), 0);
+        assert!(stack_word(&mut cpu, 4) & CPU_FLAG_TRAP == 0);
+        // The INT 21h frame beneath it returns to the traced program with TF set.
+        assert_eq!(stack_word(&mut cpu, 6), 0x0002);
+        assert_eq!(stack_word(&mut cpu, 8), CODE_SEG);
+        assert!(stack_word(&mut cpu, 10) & CPU_FLAG_TRAP != 0);
+
+        // The INT 21h handler runs untraced: IRET (INT1), NOP, IRET (INT 21h). Then the NOP
+        // after INT 21h executes and is trapped.
+        step(&mut cpu, 3);
+        assert_eq!((cpu.cs, cpu.ip), (CODE_SEG, 0x0002));
+        assert!(cpu.get_flag(Flag::Trap));
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), 0x0003);
+    }
+
+    #[test]
+    fn test_trap_divide_error() {
+        // DIV BL with BL = 0
+        let mut cpu = trap_cpu(&[0xF6, 0xF3, 0x90]);
+        cpu.set_register16(Register16::AX, 0);
+        cpu.set_register16(Register16::BX, 0);
+
+        // The divide error is taken, then the trap is taken before the first instruction of its
+        // handler.
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), INT0_HANDLER);
+        assert_eq!(stack_word(&mut cpu, 2), 0);
+        assert!(stack_word(&mut cpu, 4) & CPU_FLAG_TRAP == 0);
+        // The exception frame beneath it returns to the traced program with TF set.
+        assert!(stack_word(&mut cpu, 10) & CPU_FLAG_TRAP != 0);
+    }
+
+    #[test]
+    fn test_mov_ss_inhibits_trap() {
+        // MOV SS, AX; NOP
+        let mut cpu = trap_cpu(&[0x8E, 0xD0, 0x90]);
+        cpu.set_register16(Register16::AX, STACK_SEG);
+
+        // No trap after MOV SS. The NOP executes and is trapped.
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (CODE_SEG, 0x0003));
+        step(&mut cpu, 1);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), 0x0003);
+    }
+
+    #[test]
+    fn test_popf_trap_timing() {
+        // POPF; NOP; NOP, with TF initially clear and set by POPF.
+        let mut cpu = trap_cpu(&[0x9D, 0x90, 0x90]);
+        cpu.set_flags(0);
+        let sp = cpu.sp;
+        let addr = Cpu::calc_linear_address(STACK_SEG, sp) as usize;
+        cpu.bus_mut().write_u8(addr, 0x00, 0).unwrap();
+        cpu.bus_mut().write_u8(addr + 1, (CPU_FLAG_TRAP >> 8) as u8, 0).unwrap();
+
+        // POPF is not trapped. The following NOP is.
+        step(&mut cpu, 2);
+        assert_eq!((cpu.cs, cpu.ip), (CODE_SEG, 0x0002));
+        step(&mut cpu, 1);
+        assert_eq!((cpu.cs, cpu.ip), (0, INT1_HANDLER));
+        assert_eq!(stack_word(&mut cpu, 0), 0x0002);
+    }
+}
@@ -43,6 +43,7 @@ pub enum Mnemonic {
     ADC,
     ADD,
     AND,
+    BOUND,
     CALL,
     CALLF,
     CBW,
@@ -59,12 +60,15 @@ pub enum Mnemonic {
     DEC,
     DIV,
     ESC,
+    ENTER,
     FWAIT,
     HLT,
     IDIV,
     IMUL,
     IN,
     INC,
+    INSB,
+    INSW,
     INT,
     INT3,
     INTO,
@@ -91,6 +95,7 @@ pub enum Mnemonic {
     LAHF,
     LDS,
     LEA,
+    LEAVE,
     LES,
     LOCK,
     LODSB,
@@ -106,9 +111,13 @@ pub enum Mnemonic {
     NOT,
     OR,
     OUT,
+    OUTSB,
+    OUTSW,
     POP,
+    POPA,
     POPF,
     PUSH,
+    PUSHA,
     PUSHF,
     RCL,
     RCR,
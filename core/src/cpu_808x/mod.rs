@@ -53,6 +53,7 @@ mod bcd;
 mod bitwise;
 mod biu;
 mod cycle;
+pub mod cycle_trace;
 mod decode;
 mod display;
 mod execute;
@@ -63,7 +64,9 @@ pub mod mnemonic;
 mod modrm;
 mod muldiv;
 mod stack;
+mod state;
 mod string;
+mod v20;
 mod queue;
 mod fuzzer;
 
@@ -77,32 +80,34 @@ pub use crate::cpu_808x::biu::ReadWriteFlag;
 
 use crate::cpu_common::{CpuType, CpuOption};
 
-use crate::config::TraceMode;
+use crate::config::{TraceFormat, TraceMode};
+use crate::cpu_808x::cycle_trace::CycleTraceEntry;
 #[cfg(feature = "cpu_validator")]
 use crate::config::ValidatorType;
 
-use crate::breakpoints::BreakPointType;
-use crate::bus::{BusInterface, MEM_RET_BIT, MEM_BPA_BIT, MEM_BPE_BIT};
+use crate::breakpoints::{BreakPointType, ConditionContext};
+use crate::bus::{BusAccess, BusInterface, MEM_RET_BIT, MEM_BPA_BIT, MEM_BPE_BIT};
 use crate::bytequeue::*;
 //use crate::interrupt::log_post_interrupt;
 
 use crate::syntax_token::*;
 use crate::tracelogger::TraceLogger;
+use crate::trace_session::{TraceFilter, TraceKind, TraceSessionId, TraceSessionManager, TraceSessionState};
 
 #[cfg(feature = "cpu_validator")]
 use crate::cpu_validator::{
     CpuValidator, CycleState, ValidatorMode, ValidatorResult, 
-    VRegisters, BusCycle, BusState, AccessType
+    VRegisters, BusCycle, BusState, AccessType, MismatchKind, ValidatorStats
 };
 #[cfg(feature = "arduino_validator")]
 use crate::arduino8088_validator::{ArduinoValidator};
+#[cfg(feature = "cpu_validator")]
+use crate::reference_validator::ReferenceValidator;
 
 macro_rules! trace_print {
     ($self:ident, $($t:tt)*) => {{
-        if $self.trace_enabled {
-            if let TraceMode::Cycle = $self.trace_mode  {
-                $self.trace_print(&format!($($t)*));
-            }
+        if $self.trace_sessions.is_active(TraceKind::Cycle) {
+            $self.trace_print(&format!($($t)*));
         }
     }};
 }
@@ -111,7 +116,7 @@ use trace_print;
 const QUEUE_MAX: usize = 6;
 const FETCH_DELAY: u8 = 2;
 
-const CPU_HISTORY_LEN: usize = 32;
+pub const DEFAULT_HISTORY_LEN: usize = 256;
 const CPU_CALL_STACK_LEN: usize = 16;
 
 const INTERRUPT_VEC_LEN: usize = 4;
@@ -169,6 +174,8 @@ pub const OPCODE_PREFIX_WAIT: u32            = 0b_0000_0100_0000;
 pub const OPCODE_PREFIX_LOCK: u32            = 0b_0000_1000_0000;
 pub const OPCODE_PREFIX_REP1: u32            = 0b_0001_0000_0000;
 pub const OPCODE_PREFIX_REP2: u32            = 0b_0010_0000_0000;
+pub const OPCODE_PREFIX_REPNC: u32           = 0b_0100_0000_0000;
+pub const OPCODE_PREFIX_REPC: u32            = 0b_1000_0000_0000;
 
 // The parity flag is calculated from the lower 8 bits of an alu operation regardless
 // of the operand width.  Thefore it is trivial to precalculate a 8-bit parity table.
@@ -274,6 +281,17 @@ pub enum CallStackEntry {
     }
 }
 
+impl CallStackEntry {
+    /// Return the flat address the call or interrupt transferred control to.
+    pub fn target(&self) -> u32 {
+        match *self {
+            CallStackEntry::Call { ret_cs, call_ip, .. } => Cpu::calc_linear_address(ret_cs, call_ip),
+            CallStackEntry::CallF { call_cs, call_ip, .. } => Cpu::calc_linear_address(call_cs, call_ip),
+            CallStackEntry::Interrupt { call_cs, call_ip, .. } => Cpu::calc_linear_address(call_cs, call_ip),
+        }
+    }
+}
+
 /// Representation of a flag in the eFlags CPU register
 pub enum Flag {
     Carry,
@@ -313,7 +331,7 @@ pub enum Register {
 }*/
 
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 #[derive(PartialEq)]
 pub enum Register8 {
     AL,
@@ -420,7 +438,9 @@ pub enum RepType {
     NoRep,
     Rep,
     Repne,
-    Repe
+    Repe,
+    Repnc,
+    Repc
 }
 impl Default for RepType {
     fn default() -> Self { RepType::NoRep }
@@ -475,7 +495,34 @@ pub enum InterruptType {
 }
 
 pub enum HistoryEntry {
-    Entry { cs: u16, ip: u16, cycles: u16, i: Instruction}
+    Entry { cs: u16, ip: u16, cycles: u16, i: Instruction, regs: HistoryRegisters }
+}
+
+/// The register state after an instruction in the instruction history has executed.
+#[derive(Copy, Clone, Default)]
+pub struct HistoryRegisters {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub sp: u16,
+    pub bp: u16,
+    pub si: u16,
+    pub di: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub ss: u16,
+    pub flags: u16,
+}
+
+impl fmt::Display for HistoryRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AX:{:04X} BX:{:04X} CX:{:04X} DX:{:04X} SP:{:04X} BP:{:04X} SI:{:04X} DI:{:04X} DS:{:04X} ES:{:04X} SS:{:04X} F:{:04X}",
+            self.ax, self.bx, self.cx, self.dx, self.sp, self.bp, self.si, self.di, self.ds, self.es, self.ss, self.flags
+        )
+    }
 }
 
 #[derive (Copy, Clone)]
@@ -528,6 +575,27 @@ impl Default for Instruction {
     }
 }
 
+/// The destination of a direct jump, call or loop instruction.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BranchTarget {
+    Near(u16),
+    Far(u16, u16),
+}
+
+impl Instruction {
+    /// Return the destination of this instruction if it is a direct branch, given the 
+    /// offset it is located at. Indirect branches through registers or memory return None.
+    pub fn branch_target(&self, ip: u16) -> Option<BranchTarget> {
+        let next_ip = ip.wrapping_add(self.size as u16);
+        match self.operand1_type {
+            OperandType::Relative8(rel8) => Some(BranchTarget::Near(next_ip.wrapping_add(rel8 as i16 as u16))),
+            OperandType::Relative16(rel16) => Some(BranchTarget::Near(next_ip.wrapping_add(rel16 as u16))),
+            OperandType::FarAddress(segment, offset) => Some(BranchTarget::Far(segment, offset)),
+            _ => None
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum TransferSize {
     Byte,
@@ -707,10 +775,12 @@ pub struct Cpu
     i: Instruction,                 // Currently executing instruction 
     instruction_history_on: bool,
     instruction_history: VecDeque<HistoryEntry>,
+    instruction_history_len: usize,
     call_stack: VecDeque<CallStackEntry>,
 
     // Breakpoints
     breakpoints: Vec<BreakPointType>,
+    last_watch_hit: Option<BusAccess>,
 
     step_over_target: Option<CpuAddress>,
 
@@ -718,17 +788,19 @@ pub struct Cpu
     //int_stack: Vec<InterruptDescriptor>,
     int_count: u64,
     iret_count: u64,
+    idle_call_count: u32,
     interrupt_inhibit: bool,
     pending_interrupt: bool,
 
     reset_vector: CpuAddress,
 
-    trace_enabled: bool,
-    trace_mode: TraceMode,
-    trace_logger: TraceLogger,
+    trace_sessions: TraceSessionManager,
+    main_trace_session: Option<TraceSessionId>,
     trace_comment: Vec<&'static str>,
     trace_instr: u16,
     trace_str_vec: Vec<String>,
+    trace_format: TraceFormat,
+    cycle_trace_buf: Vec<CycleTraceEntry>,
 
     enable_wait_states: bool,
     off_rails_detection: bool,
@@ -744,6 +816,10 @@ pub struct Cpu
     validator_state: CpuValidatorState,
     #[cfg(feature = "cpu_validator")]
     validator_end: usize,
+    #[cfg(feature = "cpu_validator")]
+    validator_stats: ValidatorStats,
+    #[cfg(feature = "cpu_validator")]
+    validator_mask_flags: bool,
 
     end_addr: usize,
 
@@ -758,9 +834,8 @@ pub struct Cpu
     dma_aen: bool,
 
     // Trap stuff
-    trap_enable_delay: u32,             // Number of cycles to delay trap flag enablement. 
-    trap_disable_delay: u32,            // Number of cycles to delay trap flag disablement.
-    trap_suppressed: bool,              // Suppress trap handling for the last executed instruction.
+    trap_pending: bool,                 // A single-step trap is due before the next instruction.
+    ss_inhibit: bool,                   // Inhibit NMI and trap for one instruction after a write to SS.
 
     nmi: bool,                          // Status of NMI line.
     nmi_triggered: bool,                // Has NMI been edge-triggered?
@@ -785,6 +860,7 @@ impl Default for CpuValidatorState {
     }
 }
 
+#[derive(Default)]
 pub struct CpuRegisterState {
     pub ah: u8,
     pub al: u8,
@@ -956,6 +1032,26 @@ impl Default for FetchState {
     }
 }
 
+impl ConditionContext for Cpu {
+    fn register8(&self, reg: Register8) -> u8 {
+        self.get_register8(reg)
+    }
+
+    fn register16(&self, reg: Register16) -> u16 {
+        self.get_register16(reg)
+    }
+
+    fn peek_u8(&self, address: u32) -> u8 {
+        let address = address as usize;
+        if address < self.bus.size() {
+            self.bus.get_slice_at(address, 1)[0]
+        }
+        else {
+            0xFF
+        }
+    }
+}
+
 impl Cpu {
 
     pub fn new(
@@ -970,7 +1066,7 @@ impl Cpu {
         let mut cpu: Cpu = Default::default();
         
         match cpu_type {
-            CpuType::Intel8088 => {
+            CpuType::Intel8088 | CpuType::NecV20 => {
                 cpu.queue.set_size(4);
                 cpu.fetch_size = TransferSize::Byte;
             }
@@ -988,13 +1084,17 @@ impl Cpu {
                 ValidatorType::Arduino8088 => {
                     Some(Box::new(ArduinoValidator::new(validator_trace)))
                 }
+                ValidatorType::Reference => {
+                    Some(Box::new(ReferenceValidator::new(validator_trace)))
+                }
                 _=> {
                     None
                 }
             };
 
+            cpu.validator_mask_flags = true;
             if let Some(ref mut validator) = cpu.validator {
-                match validator.init(ValidatorMode::Cycle, true, true, true) {
+                match validator.init(ValidatorMode::Cycle, cpu.validator_mask_flags, true, true) {
                     true => {},
                     false => {
                         panic!("Failed to init cpu validator.");
@@ -1003,12 +1103,23 @@ impl Cpu {
             }            
         }
 
-        cpu.trace_logger = trace_logger;
-        cpu.trace_mode = trace_mode;
+        // The trace configured in the config file runs as the main trace session, paused until
+        // trace logging is enabled.
+        let main_trace_kind = match trace_mode {
+            TraceMode::Instruction => Some(TraceKind::Instruction),
+            TraceMode::Cycle => Some(TraceKind::Cycle),
+            TraceMode::None => None,
+        };
+        if let Some(kind) = main_trace_kind {
+            let id = cpu.trace_sessions.start("main", kind, TraceFilter::default(), trace_logger, None);
+            cpu.trace_sessions.set_paused(id, true);
+            cpu.main_trace_session = Some(id);
+        }
         cpu.cpu_type = cpu_type;
 
         //cpu.instruction_history_on = true; // Control this from config/GUI instead
         cpu.instruction_history = VecDeque::with_capacity(16);
+        cpu.instruction_history_len = DEFAULT_HISTORY_LEN;
 
         cpu.reset_vector = CpuAddress::Segmented(0xFFFF, 0x0000);
         cpu.reset();
@@ -1051,6 +1162,7 @@ impl Cpu {
         
         self.instruction_count = 0; 
         self.int_count = 0;
+        self.idle_call_count = 0;
         self.iret_count = 0;
         self.instr_cycle = 0;
         self.cycle_num = 1;
@@ -1060,6 +1172,8 @@ impl Cpu {
         self.opcode0_counter = 0;
         self.interrupt_inhibit = false;
         self.pending_interrupt = false;
+        self.trap_pending = false;
+        self.ss_inhibit = false;
         self.is_error = false;
         self.instruction_history.clear();
         self.call_stack.clear();
@@ -1590,6 +1704,30 @@ impl Cpu {
         }
     }
 
+    /// Perform a far call to the specified address between instructions, as if a CALL FAR had
+    /// just executed. The current CS:IP is pushed as the return address. This lets the machine
+    /// run code on behalf of the BIOS, such as the init routines of option ROMs.
+    pub fn inject_far_call(&mut self, segment: u16, offset: u16) {
+
+        self.sp = self.sp.wrapping_sub(2);
+        let cs_addr = Cpu::calc_linear_address(self.ss, self.sp);
+        self.sp = self.sp.wrapping_sub(2);
+        let ip_addr = Cpu::calc_linear_address(self.ss, self.sp);
+
+        _ = self.bus.write_u16(cs_addr as usize, self.cs, 0);
+        _ = self.bus.write_u16(ip_addr as usize, self.ip, 0);
+
+        self.inject_jump(segment, offset);
+    }
+
+    /// Jump to the specified address between instructions, as if a JMP FAR had just executed.
+    pub fn inject_jump(&mut self, segment: u16, offset: u16) {
+        self.queue.flush();
+        self.set_register16(Register16::CS, segment);
+        self.set_register16(Register16::IP, offset);
+        self.pc = Cpu::calc_linear_address(segment, offset);
+    }
+
     pub fn get_linear_ip(&self) -> u32 {
         Cpu::calc_linear_address(self.cs, self.ip)
     }
@@ -1796,6 +1934,11 @@ impl Cpu {
         self.bus.set_flags(return_addr as usize, MEM_RET_BIT);
     }
 
+    /// Return the target addresses of the calls on the call stack, from outermost to innermost.
+    pub fn call_stack_targets(&self) -> impl Iterator<Item = u32> + Clone + '_ {
+        self.call_stack.iter().map(CallStackEntry::target)
+    }
+
     /// Rewind the call stack to the specified address.
     /// We have to rewind the call stack to the earliest appearance of this address we returned to, 
     /// because popping the call stack clears the return flag from the memory location, so we don't 
@@ -1866,6 +2009,34 @@ impl Cpu {
         self.halted = false;
     }
 
+    /// Return whether an NMI or an unmasked INTR is pending that would resume the CPU from halt.
+    pub fn halt_wake_pending(&self) -> bool {
+        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
+            return true
+        }
+        self.interrupts_enabled() && self.bus.pic().as_ref().map_or(false, |pic| pic.query_interrupt_line())
+    }
+
+    /// Advance the CPU's cycle counters by 'cycles' while halted, without running the cycles
+    /// individually. The halted bus is idle, so the only state to carry forward is the cycle
+    /// count, any outstanding wait states and the phase of DRAM refresh.
+    pub fn skip_halt_cycles(&mut self, cycles: u32) {
+        self.instr_cycle += cycles;
+        self.instr_elapsed += cycles;
+        self.cycle_num += cycles as u64;
+        self.wait_states = self.wait_states.saturating_sub(cycles);
+
+        if self.enable_wait_states
+            && self.dram_refresh_simulation
+            && self.dram_refresh_cycle_target > 0
+            && matches!(self.dma_state, DmaState::Idle)
+        {
+            // A refresh that falls within the skipped span completes while halted, so only its phase is kept.
+            let phase = self.dram_refresh_cycles.saturating_sub(self.dram_refresh_adjust) + cycles;
+            self.dram_refresh_cycles = self.dram_refresh_adjust + phase % self.dram_refresh_cycle_target;
+        }
+    }
+
     /// Execute a single instruction.
     /// 
     /// We divide instruction execution into separate fetch/decode and execute phases.
@@ -1883,9 +2054,11 @@ impl Cpu {
         self.instr_elapsed = 0;
 
         // If tracing is enabled, clear the trace string vector that holds the trace from the last instruction.
-        if self.trace_enabled {
+        if self.trace_sessions.is_active(TraceKind::Cycle) {
             self.trace_str_vec.clear();
         }
+        // Write out the last instruction's cycles, if buffered for a JSON cycle trace.
+        self.trace_cycle_record();
 
         // Check for interrupts.
         //
@@ -1900,7 +2073,7 @@ impl Cpu {
         self.pending_interrupt = false;
         let mut irq = 7;
 
-        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered {
+        if self.nmi && self.bus.nmi_enabled() && !self.nmi_triggered && !self.ss_inhibit {
             // NMI takes priority over trap and INTR.
             if self.halted {
                 // Resume from halt on interrupt
@@ -1912,12 +2085,6 @@ impl Cpu {
             let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
             return step_result              
         }
-        else if self.trap_enabled() {
-            // Trap takes priority over INTR.
-            self.int1();
-            let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
-            return step_result              
-        }
         else if self.interrupts_enabled() {
             if let Some(pic) = self.bus.pic_mut().as_mut() {
                 // Is INTR active? TODO: Could combine these calls (return Option<iv>) on query?
@@ -1952,6 +2119,16 @@ impl Cpu {
             }
         }
 
+        // INTR takes priority over trap. If INTR was taken above while a trap was pending, the trap
+        // remains pending and is taken before the first instruction of the ISR, as the 8088 does.
+        // A REP string instruction is trapped between iterations by RPTI, like INTR.
+        let intr_pending = self.pending_interrupt;
+        if !intr_pending && !self.in_rep && self.trap_enabled() {
+            self.int1();
+            let step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
+            return step_result
+        }
+
         // Halt state can be expensive since if we only executing a single cycle. 
         // See if we can get away with executing 3 halt cycles at at time - demo effects may require more precision
         if self.halted {
@@ -1979,8 +2156,16 @@ impl Cpu {
             return Ok((StepResult::BreakpointHit, 0))
         }
 
+        // Check memory accesses made by DMA since the last instruction against watchpoints.
+        if self.check_device_watchpoints() && !skip_breakpoint {
+            self.set_breakpoint_flag();
+            return Ok((StepResult::BreakpointHit, 0))
+        }
+
         // Check instruction address for breakpoint on execute flag
-        if !skip_breakpoint && self.bus.get_flags(instruction_address as usize) & MEM_BPE_BIT != 0 {
+        if !skip_breakpoint 
+            && self.bus.get_flags(instruction_address as usize) & MEM_BPE_BIT != 0 
+            && self.check_execute_breakpoint(instruction_address) {
             // Breakpoint hit.
             log::debug!("Breakpoint hit at {:05X}", instruction_address);
             self.set_breakpoint_flag();
@@ -2010,9 +2195,9 @@ impl Cpu {
             // instruction. 
             // This of course now requires decoding each instruction twice, but cycle tracing is pretty slow 
             // anyway.
-            if self.trace_mode == TraceMode::Cycle {
+            if self.trace_sessions.is_active(TraceKind::Cycle) {
                 self.bus.seek(instruction_address as usize);
-                self.i = match Cpu::decode(&mut self.bus) {
+                self.i = match Cpu::decode_for(&mut self.bus, self.cpu_type) {
                     Ok(i) => i,
                     Err(_) => {
                         self.is_running = false;
@@ -2026,7 +2211,8 @@ impl Cpu {
             
             // Fetch and decode the current instruction. This uses the CPU's own ByteQueue trait 
             // implementation, which fetches instruction bytes through the processor instruction queue.
-            self.i = match Cpu::decode(self) {
+            let cpu_type = self.cpu_type;
+            self.i = match Cpu::decode_for(self, cpu_type) {
                 Ok(i) => i,
                 Err(_) => {
                     self.is_running = false;
@@ -2061,6 +2247,7 @@ impl Cpu {
 
         // Since Cpu::decode doesn't know anything about the current IP, it can't set it, so we do that now.
         self.i.address = instruction_address;
+        self.bus.mark_executed(instruction_address as usize, self.i.size as usize);
 
         let mut check_interrupts = false;
 
@@ -2123,7 +2310,11 @@ impl Cpu {
 
                         if self.validator_state == CpuValidatorState::Running {
 
-                            match validator.validate_instruction(
+                            let opcode = self.i.opcode;
+                            let mnemonic = self.i.mnemonic.to_string();
+                            let validate_start = std::time::Instant::now();
+
+                            let validate_result = validator.validate_instruction(
                                 self.i.to_string(), 
                                 &instr_slice,
                                 peek_fetch as u16,
@@ -2131,7 +2322,13 @@ impl Cpu {
                                 0,
                                 &vregs,
                                 &self.cycle_states
-                            ) {
+                            );
+
+                            if let Ok(ValidatorResult::Ok | ValidatorResult::OkEnd) = validate_result {
+                                self.validator_stats.record_instruction(opcode, &mnemonic, validate_start.elapsed());
+                            }
+
+                            match validate_result {
     
                                 Ok(result) => {
                                     match result {
@@ -2144,6 +2341,7 @@ impl Cpu {
 
                                                 // Validation has reached program end address
                                                 if let Err(e) = validator.validate_regs(&vregs) {
+                                                    self.validator_stats.record_mismatch(opcode, &mnemonic, MismatchKind::from_error(&e));
                                                     log::warn!("Validation failure: {} Halting execution.", e);
                                                     self.is_running = false;
                                                     self.is_error = true;
@@ -2157,6 +2355,7 @@ impl Cpu {
                                             }
                                         }
                                         _=> {
+                                            self.validator_stats.record_mismatch(opcode, &mnemonic, MismatchKind::Other);
                                             log::warn!("Validation failure: Halting execution.");
                                             self.is_running = false;
                                             self.is_error = true;
@@ -2165,6 +2364,7 @@ impl Cpu {
                                     }
                                 }
                                 Err(e) => {
+                                    self.validator_stats.record_mismatch(opcode, &mnemonic, MismatchKind::from_error(&e));
                                     log::warn!("Validation failure: {} Halting execution.", e);
                                     self.is_running = false;
                                     self.is_error = true;
@@ -2183,25 +2383,15 @@ impl Cpu {
             ExecutionResult::Okay => {
                 // Normal non-jump instruction updates CS:IP to next instruction during execute()
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
+                    self.push_history(last_cs, last_ip);
                     self.instruction_count += 1;
                 }
 
                 check_interrupts = true;
 
                 // Perform instruction tracing, if enabled
-                if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
-                    self.trace_print(&self.instruction_state_string());   
+                if self.trace_sessions.is_active(TraceKind::Instruction) {
+                    self.trace_instruction(last_cs);
                 }                
 
                 Ok((StepResult::Normal, self.instr_cycle))
@@ -2209,25 +2399,15 @@ impl Cpu {
             ExecutionResult::OkayJump => {
                 // A control flow instruction updated CS:IP.
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
+                    self.push_history(last_cs, last_ip);
                     self.instruction_count += 1;
                 }
 
                 check_interrupts = true;
 
                 // Perform instruction tracing, if enabled
-                if self.trace_enabled && self.trace_mode == TraceMode::Instruction {
-                    self.trace_print(&self.instruction_state_string());   
+                if self.trace_sessions.is_active(TraceKind::Instruction) {
+                    self.trace_instruction(last_cs);
                 }
    
                 // Only CALLS will set a step over target. 
@@ -2246,18 +2426,7 @@ impl Cpu {
                 // earlier so that a REP string operation can call RPTI to be ready for
                 // an interrupt to occur.
                 if self.instruction_history_on {
-                    if self.instruction_history.len() == CPU_HISTORY_LEN {
-                        self.instruction_history.pop_front();
-                    }
-                    
-                    self.instruction_history.push_back(
-                        HistoryEntry::Entry {
-                            cs: last_cs, 
-                            ip: last_ip, 
-                            cycles: self.instr_cycle as u16, 
-                            i: self.i
-                        }
-                    );
+                    self.push_history(last_cs, last_ip);
                 }
                 self.instruction_count += 1;
                 check_interrupts = true;
@@ -2311,11 +2480,17 @@ impl Cpu {
             // the address of the next instruction. (Step Over skips ISRs)
             step_result = Ok((StepResult::Call(CpuAddress::Segmented(self.cs, self.ip)), self.instr_cycle));
             
-            if self.int_flags[irq as usize] != 0 {
-                // This interrupt has a breakpoint
-                self.set_breakpoint_flag();
-            }            
-            self.hw_interrupt(irq);
+            if intr_pending {
+                if self.int_flags[irq as usize] != 0 {
+                    // This interrupt has a breakpoint
+                    self.set_breakpoint_flag();
+                }            
+                self.hw_interrupt(irq);
+            }
+            else {
+                // RPTI was run for a single-step trap.
+                self.int1();
+            }
         }
 
         // Check registers and flags for internal consistency.
@@ -2351,13 +2526,18 @@ impl Cpu {
         // Clear bus flags for current breakpoints
         self.breakpoints.iter().for_each(|bp| {
             match bp {
-                BreakPointType::ExecuteFlat(addr) => {
+                BreakPointType::ExecuteFlat(addr) | BreakPointType::ExecuteFlatIf(addr, _) => {
                     log::debug!("Clearing breakpoint on execute at address: {:05X}", *addr);
                     self.bus.clear_flags(*addr as usize, MEM_BPE_BIT );
                 },
                 BreakPointType::MemAccessFlat(addr) => {
                     self.bus.clear_flags(*addr as usize, MEM_BPA_BIT );
                 }
+                BreakPointType::Watch(start, len, _) => {
+                    for addr in *start..(*start + *len).min(0x100000) {
+                        self.bus.clear_flags(addr as usize, MEM_BPA_BIT );
+                    }
+                }
                 BreakPointType::Interrupt(vector) => {
                     self.int_flags[*vector as usize] = 0;
                 }
@@ -2371,7 +2551,7 @@ impl Cpu {
         // Set bus flags for new breakpoints
         self.breakpoints.iter().for_each(|bp| {
             match bp {
-                BreakPointType::ExecuteFlat(addr) => {
+                BreakPointType::ExecuteFlat(addr) | BreakPointType::ExecuteFlatIf(addr, _) => {
                     log::debug!("Setting breakpoint on execute at address: {:05X}", *addr);
                     self.bus.set_flags(*addr as usize, MEM_BPE_BIT );
                 },
@@ -2379,6 +2559,12 @@ impl Cpu {
                     log::debug!("Setting breakpoint on memory access at address: {:05X}", *addr);
                     self.bus.set_flags(*addr as usize, MEM_BPA_BIT );
                 }
+                BreakPointType::Watch(start, len, watch_type) => {
+                    log::debug!("Setting {} watchpoint at address: {:05X}, length: {:X}", watch_type, *start, *len);
+                    for addr in *start..(*start + *len).min(0x100000) {
+                        self.bus.set_flags(addr as usize, MEM_BPA_BIT );
+                    }
+                }
                 BreakPointType::Interrupt(vector) => {
                     self.int_flags[*vector as usize] = INTERRUPT_BREAKPOINT;
                 }                
@@ -2388,6 +2574,60 @@ impl Cpu {
 
     }
 
+    /// Return whether an execute breakpoint at the specified address should stop the CPU. 
+    /// Unconditional breakpoints always stop; conditional breakpoints stop only if their
+    /// condition is true.
+    fn check_execute_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.iter().any(|bp| {
+            match bp {
+                BreakPointType::ExecuteFlat(addr) => *addr == address,
+                BreakPointType::ExecuteFlatIf(addr, condition) => {
+                    *addr == address && condition.evaluate(self)
+                }
+                _ => false
+            }
+        })
+    }
+
+    /// Return whether a bus cycle to the specified address should stop the CPU. Memory access
+    /// breakpoints stop on any access, while watchpoints stop only on memory reads or writes
+    /// of the type being watched.
+    fn check_access_breakpoint(&self, address: u32, bus_status: BusStatus) -> bool {
+        self.breakpoints.iter().any(|bp| {
+            match bp {
+                BreakPointType::MemAccessFlat(addr) => *addr == address,
+                BreakPointType::Watch(start, len, watch_type) => {
+                    let in_range = address >= *start && address < *start + *len;
+                    match bus_status {
+                        BusStatus::MemRead => in_range && watch_type.matches(false),
+                        BusStatus::MemWrite => in_range && watch_type.matches(true),
+                        _ => false
+                    }
+                }
+                _ => false
+            }
+        })
+    }
+
+    /// Check memory accesses made by bus masters other than the CPU against watchpoints,
+    /// recording the first matching access. The list of accesses is cleared.
+    fn check_device_watchpoints(&mut self) -> bool {
+        for access in self.bus.take_device_accesses() {
+            let bus_status = if access.write { BusStatus::MemWrite } else { BusStatus::MemRead };
+            if self.check_access_breakpoint(access.address, bus_status) {
+                log::debug!("Memory breakpoint hit: {}", access);
+                self.last_watch_hit = Some(access);
+                return true
+            }
+        }
+        false
+    }
+
+    /// Return the memory access that last triggered a watchpoint or memory access breakpoint.
+    pub fn last_watch_hit(&self) -> Option<BusAccess> {
+        self.last_watch_hit
+    }
+
     pub fn get_breakpoint_flag(&self) -> bool {
         if let CpuState::BreakpointHit = self.state {
             true
@@ -2405,29 +2645,80 @@ impl Cpu {
         self.state = CpuState::Normal;
     }
 
+    /// Add the instruction that just executed at the specified address to the instruction 
+    /// history, discarding the oldest entry if the history is full.
+    fn push_history(&mut self, cs: u16, ip: u16) {
+        while self.instruction_history.len() >= self.instruction_history_len {
+            self.instruction_history.pop_front();
+        }
+        let regs = HistoryRegisters {
+            ax: self.ax,
+            bx: self.bx,
+            cx: self.cx,
+            dx: self.dx,
+            sp: self.sp,
+            bp: self.bp,
+            si: self.si,
+            di: self.di,
+            ds: self.ds,
+            es: self.es,
+            ss: self.ss,
+            flags: self.flags,
+        };
+        self.instruction_history.push_back(
+            HistoryEntry::Entry {
+                cs, 
+                ip, 
+                cycles: self.instr_cycle as u16, 
+                i: self.i,
+                regs,
+            }
+        );
+    }
+
+    /// Return the number of entries in the instruction history.
+    pub fn instruction_history_len(&self) -> usize {
+        self.instruction_history.len()
+    }
+
+    /// Dump the instruction history as text, one instruction per line with its cycle count and 
+    /// the register state after it executed. Oldest instructions are first.
     pub fn dump_instruction_history_string(&self) -> String {
 
         let mut disassembly_string = String::new();
 
         for i in &self.instruction_history {
-            if let HistoryEntry::Entry {cs, ip, cycles: _, i} = i {      
-                let i_string = format!("{:05X} [{:04X}:{:04X}] {}\n", i.address, *cs, *ip, i);
+            if let HistoryEntry::Entry {cs, ip, cycles, i, regs} = i {      
+                let i_string = format!(
+                    "{:05X} [{:04X}:{:04X}] {:<32} {:>4} {}\n", 
+                    i.address, 
+                    *cs, 
+                    *ip, 
+                    i.to_string(), 
+                    cycles,
+                    regs
+                );
                 disassembly_string.push_str(&i_string);
             }
         }
         disassembly_string
     }
 
-    pub fn dump_instruction_history_tokens(&self) -> Vec<Vec<SyntaxToken>> {
+    /// Tokenize 'count' entries of the instruction history beginning at entry 'start', 
+    /// optionally including the register state after each instruction executed.
+    pub fn dump_instruction_history_tokens(&self, start: usize, count: usize, regs: bool) -> Vec<Vec<SyntaxToken>> {
 
         let mut history_vec = Vec::new();
 
-        for i in &self.instruction_history {
+        for i in self.instruction_history.iter().skip(start).take(count) {
             let mut i_token_vec = Vec::new();
-            if let HistoryEntry::Entry {cs, ip, cycles, i} = i {
+            if let HistoryEntry::Entry {cs, ip, cycles, i, regs: i_regs} = i {
                 i_token_vec.push(SyntaxToken::MemoryAddressFlat(i.address, format!("{:05X}", i.address)));
                 i_token_vec.push(SyntaxToken::MemoryAddressSeg16(*cs, *ip, format!("{:04X}:{:04X}", cs, ip)));
                 i_token_vec.push(SyntaxToken::Text(format!("{}", cycles)));
+                if regs {
+                    i_token_vec.push(SyntaxToken::Text(i_regs.to_string()));
+                }
                 i_token_vec.extend(i.tokenize());
             }
             history_vec.push(i_token_vec);
@@ -2712,15 +3003,36 @@ impl Cpu {
 
     #[inline]
     pub fn trace_print(&mut self, trace_str: &str) {
-        if self.trace_logger.is_some() {
-            self.trace_logger.println(trace_str);
+        self.trace_sessions.trace_cpu(TraceKind::Cycle, self.cs, trace_str);
+    }
+
+    /// Log the instruction just executed from the specified code segment to any instruction 
+    /// trace sessions.
+    fn trace_instruction(&mut self, cs: u16) {
+        let instr_str = self.instruction_state_string();
+        self.trace_sessions.trace_cpu(TraceKind::Instruction, cs, &instr_str);
+    }
+
+    /// Log an IO port access to any IO trace sessions.
+    #[inline]
+    pub fn trace_io(&mut self, port: u16, data: u8, write: bool) {
+        if self.trace_sessions.is_active(TraceKind::Io) {
+            let direction = if write { "OUT" } else { "IN " };
+            let io_str = format!("{:04x}:{:04x} {} {:04X} {:02X}", self.cs, self.ip, direction, port, data);
+            self.trace_sessions.trace_io(self.cs, port, &io_str);
         }
     }
 
+    pub fn trace_sessions(&self) -> &TraceSessionManager {
+        &self.trace_sessions
+    }
+
+    pub fn trace_sessions_mut(&mut self) -> &mut TraceSessionManager {
+        &mut self.trace_sessions
+    }
+
     pub fn trace_flush(&mut self) {
-        if self.trace_logger.is_some() {
-            self.trace_logger.flush();
-        }
+        self.trace_sessions.flush();
 
         #[cfg(feature = "cpu_validator")]
         {
@@ -2732,7 +3044,7 @@ impl Cpu {
 
     #[inline]
     pub fn trace_comment(&mut self, comment: &'static str) {
-        if self.trace_enabled {
+        if self.trace_sessions.is_active(TraceKind::Cycle) {
             self.trace_comment.push(comment);
         }
     }
@@ -2782,6 +3094,22 @@ impl Cpu {
         }
     }
 
+    /// Return the type of CPU being emulated.
+    pub fn cpu_type(&self) -> CpuType {
+        self.cpu_type
+    }
+
+    /// Return whether the CPU is in the halted state.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Return the number of idle-indicating software interrupts (keyboard polls, DOS idle calls) 
+    /// executed since the last call, and reset the count.
+    pub fn take_idle_call_count(&mut self) -> u32 {
+        std::mem::take(&mut self.idle_call_count)
+    }
+
     pub fn get_service_event(&mut self) -> Option<ServiceEvent> {
         self.service_events.pop_front()
     }
@@ -2794,6 +3122,13 @@ impl Cpu {
                 self.instruction_history.clear();
                 self.instruction_history_on = state;
             }
+            CpuOption::InstructionHistoryLen(len) => {
+                log::debug!("Setting InstructionHistoryLen to: {}", len);
+                self.instruction_history_len = (len as usize).max(1);
+                while self.instruction_history.len() > self.instruction_history_len {
+                    self.instruction_history.pop_front();
+                }
+            }
             CpuOption::SimulateDramRefresh(state, cycle_target, cycles) => {
                 log::debug!("Setting SimulateDramRefresh to: {:?} ({},{})", state, cycle_target, cycles);
                 self.dram_refresh_simulation = state;
@@ -2822,12 +3157,10 @@ impl Cpu {
             }   
             CpuOption::TraceLoggingEnabled(state) => {
                 log::debug!("Setting {:?} to: {:?}", opt, state);
-                self.trace_enabled = state;
-
-                // Flush the trace log file on stopping trace so that we can immediately
+                // Pausing the main trace session flushes its log so that we can immediately
                 // see results otherwise buffered
-                if state == false {
-                    self.trace_flush();
+                if let Some(id) = self.main_trace_session {
+                    self.trace_sessions.set_paused(id, !state);
                 }
             }                       
         }
@@ -2838,6 +3171,9 @@ impl Cpu {
             CpuOption::InstructionHistory(_) => {
                 self.instruction_history_on
             }
+            CpuOption::InstructionHistoryLen(..) => {
+                true
+            }
             CpuOption::SimulateDramRefresh(..) => {
                 self.dram_refresh_simulation
             }
@@ -2854,7 +3190,9 @@ impl Cpu {
                 self.enable_wait_states
             }   
             CpuOption::TraceLoggingEnabled(_) => {
-                self.trace_enabled
+                self.main_trace_session
+                    .and_then(|id| self.trace_sessions.get(id))
+                    .is_some_and(|session| session.state() == TraceSessionState::Running)
             }                       
         }        
     }
@@ -2868,6 +3206,51 @@ impl Cpu {
         self.validator_state
     }
 
+    /// Return statistics for the current validation run, or None if no validator is configured.
+    pub fn validator_stats(&self) -> Option<&crate::cpu_validator::ValidatorStats> {
+        #[cfg(feature = "cpu_validator")]
+        if self.validator.is_some() {
+            return Some(&self.validator_stats);
+        }
+        None
+    }
+
+    pub fn reset_validator_stats(&mut self) {
+        #[cfg(feature = "cpu_validator")]
+        {
+            self.validator_stats = Default::default();
+        }
+    }
+
+    /// Install a validator created outside of the CPU, such as one that needs a file to be 
+    /// opened. Returns false if the validator failed to initialize.
+    #[cfg(feature = "cpu_validator")]
+    pub fn set_validator(&mut self, mut validator: Box<dyn CpuValidator>) -> bool {
+        if !validator.init(ValidatorMode::Cycle, self.validator_mask_flags, true, true) {
+            return false;
+        }
+        self.validator = Some(validator);
+        true
+    }
+
+    /// Set whether the validator ignores flags that are undefined for the instruction being
+    /// validated. Any installed validator is re-initialized.
+    #[cfg(feature = "cpu_validator")]
+    pub fn set_validator_mask_flags(&mut self, mask_flags: bool) {
+        self.validator_mask_flags = mask_flags;
+        if let Some(ref mut validator) = self.validator {
+            if !validator.init(ValidatorMode::Cycle, mask_flags, true, true) {
+                log::error!("Failed to re-init cpu validator.");
+            }
+        }
+    }
+
+    /// Remove the installed validator, ie to wrap it in a TraceRecorder.
+    #[cfg(feature = "cpu_validator")]
+    pub fn take_validator(&mut self) -> Option<Box<dyn CpuValidator>> {
+        self.validator.take()
+    }
+
 }
 
 
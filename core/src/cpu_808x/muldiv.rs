@@ -54,6 +54,8 @@ macro_rules! impl_cord {
             
                 let mut carry;
                 let mut carry_sub;
+                let mut overflow_sub;
+                let mut aux_sub;
             
                 // 188:           | SUBT tmpa
                 (_, carry, _, _) = tmpa.alu_sub(tmpb as Self as u16);
@@ -82,7 +84,7 @@ macro_rules! impl_cord {
                     
                     // 18d:
                     tmpa = sigma_s as u16;
-                    (sigma_s, carry_sub, _, _) = (tmpa as Self).alu_sub(tmpb as Self);
+                    (sigma_s, carry_sub, overflow_sub, aux_sub) = (tmpa as Self).alu_sub(tmpb as Self);
                     sigma = sigma_s as u16;
             
                     cpu.cycles_i(4, &[0x18b, 0x18c, 0x18d, 0x18e]);
@@ -111,7 +113,7 @@ macro_rules! impl_cord {
             
                         // 18f: SIGMA->.     | F
                         carry = carry_sub;
-                        // SET FLAGS HERE
+                        cpu.set_alu_flags(sigma, Self::BITS == 16, carry_sub, overflow_sub, aux_sub);
             
                         cpu.cycles_i(2, &[0x18f, 0x190]);
             
@@ -202,10 +204,11 @@ macro_rules! impl_corx {
                     cpu.cycle_i(0x181); // 181:    | NCY 8 (jump if no carry)
         
                     if carry {
-                        (sigma_s, carry, _, _ ) = (tmpa as Self).alu_add(tmpb as Self); // 182:             | ADD tmpa 
+                        let (overflow, aux_carry);
+                        (sigma_s, carry, overflow, aux_carry) = (tmpa as Self).alu_add(tmpb as Self); // 182:             | ADD tmpa 
                         tmpa = sigma_s as u16; // 183: SIGMA->tmpa    | F
+                        cpu.set_alu_flags(tmpa, Self::BITS == 16, carry, overflow, aux_carry);
                         cpu.cycles_i(2, &[0x182, 0x183]);
-                        // SET FLAGS HERE
                     }
                     else {
                         // Jump delay for skipping to line 8
@@ -400,9 +403,11 @@ impl Cpu {
             tmpb = 0; 
             //(_, carry) = rcl_u8_with_carry(tmpc as u8, 1, carry);  // Test if tmpc is negative
             carry = tmpc & 0x80 != 0; // LRCY is just checking msb of tmpc
-            (sigma8, _, _, _) = (tmpa as u8).alu_adc(tmpb as u8, carry);
+            let (carry_adc, overflow, aux_carry);
+            (sigma8, carry_adc, overflow, aux_carry) = (tmpa as u8).alu_adc(tmpb as u8, carry);
             self.cycles_i(3, &[0x1cd, 0x1ce, 0x1cf]);
-            // SET FLAGS HERE
+            // 1cf: SIGMA->.       | F
+            self.set_alu_flags(sigma8 as u16, false, carry_adc, overflow, aux_carry);
 
             // 1d0:             | Z 8
             if sigma8 == 0 {
@@ -441,6 +446,8 @@ impl Cpu {
         // JMP
 
         self.cycles_i(6, &[0x155, 0x156, MC_JUMP, 0x1d2, 0x1d3, MC_JUMP]);
+        // PASS clears carry, overflow and aux carry; SZP come from the high byte.
+        self.set_alu_flags(sigma, false, false, false, false);
         zf = sigma == 0;
 
         // 1d0:                | Z 8  (jump if zero)
@@ -530,9 +537,11 @@ impl Cpu {
             tmpb = 0; // 1cd
             //(_, carry) = rcl_u16_with_carry(tmpc, 1, carry);  // Test if tmpc is negative
             carry = tmpc & 0x8000 != 0; // 1cd: LRCY is just checking msb of tmpc
-            (sigma, _, _, _) = tmpa.alu_adc(tmpb, carry);
+            let (carry_adc, overflow, aux_carry);
+            (sigma, carry_adc, overflow, aux_carry) = tmpa.alu_adc(tmpb, carry);
             self.cycles_i(3, &[0x1cd, 0x1ce, 0x1cf]);
-            // Set flags here
+            // 1cf: SIGMA->.       | F
+            self.set_alu_flags(sigma, true, carry_adc, overflow, aux_carry);
 
             // 1d0:             | Z 8
             if sigma == 0 {
@@ -565,6 +574,8 @@ impl Cpu {
         // 1d3: SIGMA->.       | UNC 12  | F  (Set flags)
         // JMP
         self.cycles_i(6, &[0x15d, 0x15e, MC_JUMP, 0x1d2, 0x1d3, MC_JUMP]);
+        // PASS clears carry, overflow and aux carry; SZP come from the high word.
+        self.set_alu_flags(sigma, true, false, false, false);
         zf = sigma == 0;
 
         // 1d0:                | Z 8  (jump if zero)
@@ -701,7 +712,9 @@ impl Cpu {
                 self.cycle_i(MC_JUMP);
             }
 
-            // clear carry, overflow flag here
+            // 1cc:             | CCOF RTN
+            self.clear_flag(Flag::Carry);
+            self.clear_flag(Flag::Overflow);
             self.cycles_i(2, &[0x1cc, MC_RTN]);
         }
 
@@ -821,7 +834,9 @@ impl Cpu {
                 self.cycle_i(MC_JUMP);
             }
 
-            // clear carry, overflow flag here
+            // 1cc:             | CCOF RTN
+            self.clear_flag(Flag::Carry);
+            self.clear_flag(Flag::Overflow);
             self.cycles_i(2, &[0x1cc, MC_RTN]);
         }
 
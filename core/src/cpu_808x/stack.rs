@@ -119,8 +119,9 @@ impl Cpu {
             Register16::DS => self.ds = data,
             Register16::SS => {
                 self.ss = data;
-                // Inhibit interrupts for one instruction after issuing POP SS
-                self.interrupt_inhibit = true
+                // Inhibit interrupts, NMI and trap for one instruction after issuing POP SS
+                self.interrupt_inhibit = true;
+                self.ss_inhibit = true;
             },
             Register16::ES => self.es = data,     
             Register16::IP => self.ip = data,      
@@ -151,23 +152,11 @@ impl Cpu {
         //let (result, _cost) = self.bus.read_u16(stack_addr as usize).unwrap();
         let result = self.biu_read_u16(Segment::SS, stack_addr, ReadWriteFlag::Normal);
 
-        let trap_was_set = self.get_flag(Flag::Trap);
-
-        // Ensure state of reserved flag bits
+        // Ensure state of reserved flag bits. A change to the trap flag takes effect on the
+        // next instruction, as the trap flag is latched when an instruction begins.
         self.flags = result & FLAGS_POP_MASK;
         self.flags |= CPU_FLAGS_RESERVED_ON;
 
-        // Was trap flag just set? Set trap enable delay.
-        let trap_is_set = self.get_flag(Flag::Trap);
-        if !trap_was_set && trap_is_set {
-            self.trap_enable_delay = 2;
-        }
-
-        // Was trap flag just disabled? Set trap disable delay.
-        if trap_was_set && !trap_is_set {
-            self.trap_disable_delay = 1;
-        }
-
         // Stack pointer grows downwards
         self.sp = self.sp.wrapping_add(2);
     }
@@ -0,0 +1,99 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    cpu_808x::state.rs
+
+    Implements saving and restoring CPU state to a state file.
+
+    Only architectural state is saved. The CPU is restored on an instruction
+    boundary with an empty prefetch queue.
+
+*/
+
+use crate::cpu_808x::*;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+impl SaveState for Cpu {
+    const STATE_ID: &'static str = "cpu";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.ax);
+        w.write_u16(self.bx);
+        w.write_u16(self.cx);
+        w.write_u16(self.dx);
+        w.write_u16(self.sp);
+        w.write_u16(self.bp);
+        w.write_u16(self.si);
+        w.write_u16(self.di);
+        w.write_u16(self.cs);
+        w.write_u16(self.ds);
+        w.write_u16(self.ss);
+        w.write_u16(self.es);
+        w.write_u16(self.ip);
+        w.write_u16(self.flags);
+        w.write_bool(self.halted);
+        w.write_bool(self.interrupt_inhibit);
+        w.write_u64(self.instruction_count);
+        w.write_u64(self.cycle_num);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0u16; 12];
+        for reg in regs.iter_mut() {
+            *reg = r.read_u16()?;
+        }
+        let ip = r.read_u16()?;
+        let flags = r.read_u16()?;
+        let halted = r.read_bool()?;
+        let interrupt_inhibit = r.read_bool()?;
+        let instruction_count = r.read_u64()?;
+        let cycle_num = r.read_u64()?;
+
+        // Reset the CPU to start executing at the saved CS:IP, then restore the original 
+        // reset vector.
+        let reset_vector = self.reset_vector;
+        self.reset_vector = CpuAddress::Segmented(regs[8], ip);
+        self.reset();
+        self.reset_vector = reset_vector;
+
+        let reg_list = [
+            Register16::AX, Register16::BX, Register16::CX, Register16::DX,
+            Register16::SP, Register16::BP, Register16::SI, Register16::DI,
+            Register16::CS, Register16::DS, Register16::SS, Register16::ES,
+        ];
+        for (reg, value) in reg_list.into_iter().zip(regs) {
+            self.set_register16(reg, value);
+        }
+        self.set_flags(flags);
+        self.halted = halted;
+        self.interrupt_inhibit = interrupt_inhibit;
+        self.instruction_count = instruction_count;
+        self.cycle_num = cycle_num;
+        Ok(())
+    }
+}
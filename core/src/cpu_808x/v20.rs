@@ -0,0 +1,670 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    cpu_808x::v20.rs
+
+    Implements the instruction set differences of the NEC V20.
+
+    The V20 is pin-compatible with the 8088 and adds the 80186 instruction
+    set extensions, placed in opcodes that are undocumented aliases on the
+    8088. It also masks shift counts to 5 bits, uses base 10 for AAM and AAD
+    regardless of the immediate operand, and has a hardware multiplier and
+    divider that is considerably faster than the 8088's microcode routines.
+
+    Cycle timings for V20 instructions are taken from the NEC datasheet and
+    are not cycle-accurate with respect to the BIU.
+
+    The V20's native mode extensions (0x0F prefixed instructions) and 8080
+    emulation mode are not implemented. An extended instruction is decoded
+    to its full length, including any ModRM byte, displacement and 
+    immediate operand, and executed as a NOP with a warning logged.
+*/
+
+use crate::cpu_808x::*;
+use crate::cpu_808x::decode::OperandTemplate;
+
+use crate::bytequeue::*;
+
+// Execution cycles for the V20 multiplier and divider, register operand forms.
+const V20_MULU8_CYCLES: u32 = 21;
+const V20_MULU16_CYCLES: u32 = 29;
+const V20_MUL8_CYCLES: u32 = 33;
+const V20_MUL16_CYCLES: u32 = 41;
+const V20_DIVU8_CYCLES: u32 = 19;
+const V20_DIVU16_CYCLES: u32 = 25;
+const V20_DIV8_CYCLES: u32 = 29;
+const V20_DIV16_CYCLES: u32 = 38;
+
+// Base execution cycles for shifts and rotates by count. One cycle is added per bit shifted.
+const V20_SHIFT_CYCLES: u32 = 7;
+const V20_SHIFT_MEM_CYCLES: u32 = 19;
+
+impl Cpu {
+
+    /// Return the decode template for opcodes that differ on the V20, or None if the opcode 
+    /// decodes the same as on the 8088. A mnemonic of NoOpcode indicates a group opcode.
+    pub fn decode_v20_opcode(opcode: u8) -> Option<(Mnemonic, OperandTemplate, OperandTemplate, u32)> {
+        let op = match opcode {
+            // Extended instruction prefix on the V20. These instructions are not supported; their
+            // operands are decoded by decode_v20_extended_opcode().
+            0x0F => (Mnemonic::InvalidOpcode, OperandTemplate::NoOperand, OperandTemplate::NoOperand, 0),
+            0x60 => (Mnemonic::PUSHA,  OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            0x61 => (Mnemonic::POPA,   OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            0x62 => (Mnemonic::BOUND,  OperandTemplate::Register16,  OperandTemplate::ModRM16,     I_LOAD_EA),
+            // 0x63 is undefined. 0x64 and 0x65 are the REPNC/REPC prefixes, handled by the decoder.
+            0x63 => (Mnemonic::NOP,    OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            // Coprocessor escapes
+            0x66 | 0x67 => (Mnemonic::ESC, OperandTemplate::ModRM16,   OperandTemplate::NoOperand,   I_LOAD_EA),
+            0x68 => (Mnemonic::PUSH,   OperandTemplate::Immediate16, OperandTemplate::NoOperand,   0),
+            0x69 => (Mnemonic::IMUL,   OperandTemplate::Register16,  OperandTemplate::ModRM16,     I_LOAD_EA),
+            0x6A => (Mnemonic::PUSH,   OperandTemplate::Immediate8SignExtended, OperandTemplate::NoOperand, 0),
+            0x6B => (Mnemonic::IMUL,   OperandTemplate::Register16,  OperandTemplate::ModRM16,     I_LOAD_EA),
+            0x6C => (Mnemonic::INSB,   OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            0x6D => (Mnemonic::INSW,   OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            0x6E => (Mnemonic::OUTSB,  OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            0x6F => (Mnemonic::OUTSW,  OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            // Shift and rotate by immediate count. Decoded as a group instruction.
+            0xC0 | 0xC1 => (Mnemonic::NoOpcode, OperandTemplate::NoTemplate, OperandTemplate::NoTemplate, 0),
+            // ENTER's operands are handled after templates, as it has two immediates.
+            0xC8 => (Mnemonic::ENTER,  OperandTemplate::NoTemplate,  OperandTemplate::NoTemplate,  0),
+            0xC9 => (Mnemonic::LEAVE,  OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            // SALC does not exist on the V20; 0xD6 is an alias for XLAT.
+            0xD6 => (Mnemonic::XLAT,   OperandTemplate::NoOperand,   OperandTemplate::NoOperand,   0),
+            _ => return None
+        };
+        Some(op)
+    }
+
+    /// Return the operand templates of an extended (0x0F prefixed) instruction, given the byte 
+    /// following the prefix. Only the operands' encoding is decoded, so that the instruction's 
+    /// length is known; the instructions themselves are not implemented.
+    pub fn decode_v20_extended_opcode(ext_opcode: u8) -> (OperandTemplate, OperandTemplate) {
+        match ext_opcode {
+            // TEST1, CLR1, SET1, NOT1 r/m, CL
+            0x10 | 0x12 | 0x14 | 0x16 => (OperandTemplate::ModRM8,  OperandTemplate::FixedRegister8(Register8::CL)),
+            0x11 | 0x13 | 0x15 | 0x17 => (OperandTemplate::ModRM16, OperandTemplate::FixedRegister8(Register8::CL)),
+            // TEST1, CLR1, SET1, NOT1 r/m, imm
+            0x18 | 0x1A | 0x1C | 0x1E => (OperandTemplate::ModRM8,  OperandTemplate::Immediate8),
+            0x19 | 0x1B | 0x1D | 0x1F => (OperandTemplate::ModRM16, OperandTemplate::Immediate8),
+            // ROL4, ROR4 r/m8
+            0x28 | 0x2A => (OperandTemplate::ModRM8, OperandTemplate::NoOperand),
+            // INS, EXT reg8, reg8
+            0x31 | 0x33 => (OperandTemplate::ModRM8, OperandTemplate::Register8),
+            // INS, EXT reg8, imm4
+            0x39 | 0x3B => (OperandTemplate::ModRM8, OperandTemplate::Immediate8),
+            // BRKEM imm8
+            0xFF => (OperandTemplate::Immediate8, OperandTemplate::NoOperand),
+            // ADD4S, SUB4S, CMP4S and undefined extended opcodes have no operands.
+            _ => (OperandTemplate::NoOperand, OperandTemplate::NoOperand),
+        }
+    }
+
+    /// Execute the current instruction if it behaves differently on the V20. Returns false if 
+    /// the instruction should be executed as on the 8088.
+    pub fn execute_v20_instruction(&mut self, jump: &mut bool, exception: &mut CpuException) -> bool {
+
+        match self.i.opcode {
+            0x0F => {
+                // Unimplemented extended instruction. Fetch any immediate operand and do nothing
+                // else.
+                for operand in [self.i.operand1_type, self.i.operand2_type] {
+                    if let OperandType::Immediate8(_) = operand {
+                        self.read_operand8(operand, SegmentOverride::None);
+                    }
+                }
+                let bytes = self.bus.get_slice_at(self.i.address as usize & 0xFFFFF, self.i.size as usize);
+                log::warn!(
+                    "Unimplemented V20 instruction {:02X?} at [{:04X}:{:04X}], executed as NOP", 
+                    bytes, 
+                    self.cs, 
+                    self.ip
+                );
+            }
+            0x60 => {
+                // PUSHA - Push all general purpose registers. SP is pushed with its original value.
+                let sp = self.sp;
+                self.cycles(3);
+                for reg in [Register16::AX, Register16::CX, Register16::DX, Register16::BX] {
+                    self.push_register16(reg, ReadWriteFlag::Normal);
+                }
+                self.push_u16(sp, ReadWriteFlag::Normal);
+                for reg in [Register16::BP, Register16::SI, Register16::DI] {
+                    self.push_register16(reg, ReadWriteFlag::Normal);
+                }
+            }
+            0x61 => {
+                // POPA - Pop all general purpose registers. The saved value of SP is discarded.
+                self.cycles(3);
+                for reg in [Register16::DI, Register16::SI, Register16::BP] {
+                    self.pop_register16(reg, ReadWriteFlag::Normal);
+                }
+                let _sp = self.pop_u16();
+                for reg in [Register16::BX, Register16::DX, Register16::CX, Register16::AX] {
+                    self.pop_register16(reg, ReadWriteFlag::Normal);
+                }
+            }
+            0x62 => {
+                // BOUND - Check array index against bounds, raising interrupt 5 if out of range.
+                // The lower bound is the first word of the memory operand, the upper bound the second.
+                let index = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap() as i16;
+                self.cycles(13);
+
+                match self.read_operand_farptr(self.i.operand2_type, self.i.segment_override, ReadWriteFlag::Normal) {
+                    Some((upper, lower)) => {
+                        if index < lower as i16 || index > upper as i16 {
+                            // The return address is that of the BOUND instruction itself.
+                            self.sw_interrupt(5);
+                            *jump = true;
+                        }
+                    }
+                    None => {
+                        log::warn!("BOUND with register operand at [{:04X}:{:04X}]", self.cs, self.ip);
+                    }
+                }
+            }
+            0x63 => {
+                // Undefined opcode. Do nothing.
+            }
+            0x66 | 0x67 => {
+                // ESC - Coprocessor escape
+                // Perform dummy read if memory operand
+                let _op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override);
+            }
+            0x68 => {
+                // PUSH imm16
+                let op1_value = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                self.cycles(2);
+                self.push_u16(op1_value, ReadWriteFlag::RNI);
+            }
+            0x6A => {
+                // PUSH imm8, sign-extended to 16 bits
+                let op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                self.cycles(2);
+                self.push_u16(op1_value as i8 as i16 as u16, ReadWriteFlag::RNI);
+            }
+            0x69 | 0x6B => {
+                // IMUL r16, r/m16, imm
+                let op2_value = self.read_operand16(self.i.operand2_type, self.i.segment_override).unwrap();
+                let imm = match self.i.opcode {
+                    0x69 => self.q_read_u16(QueueType::Subsequent, QueueReader::Eu) as i16,
+                    _ => self.q_read_i8(QueueType::Subsequent, QueueReader::Eu) as i16
+                };
+
+                let product = (op2_value as i16 as i32) * (imm as i32);
+                let overflow = product < i16::MIN.into() || product > i16::MAX.into();
+                self.set_flag_state(Flag::Carry, overflow);
+                self.set_flag_state(Flag::Overflow, overflow);
+                self.write_operand16(self.i.operand1_type, SegmentOverride::None, product as u16, ReadWriteFlag::Normal);
+
+                self.cycles(V20_MUL16_CYCLES);
+            }
+            0x6C..=0x6F => {
+                // INSB, INSW, OUTSB & OUTSW
+                if self.rep_start() {
+
+                    self.string_io_op(self.i.mnemonic, self.i.segment_override);
+                    self.cycles(3);
+
+                    // Check for end condition (CX==0)
+                    if self.in_rep {
+                        // Check for interrupt
+                        if self.pending_interrupt {
+                            self.rep_interrupt();
+                        }
+
+                        self.decrement_register16(Register16::CX);
+                        if self.cx == 0 {
+                            self.rep_end();
+                        }
+                    }
+                }
+            }
+            0xC0 | 0xD2 => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m8, imm8 | r/m8, cl
+                let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+
+                self.cycles(self.v20_shift_cycles(op2_value));
+                let result = self.bitshift_op8(self.i.mnemonic, op1_value, op2_value);
+                self.write_operand8(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+            }
+            0xC1 | 0xD3 => {
+                // ROL, ROR, RCL, RCR, SHL, SHR, SAR:  r/m16, imm8 | r/m16, cl
+                let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                let op2_value = self.read_operand8(self.i.operand2_type, self.i.segment_override).unwrap();
+
+                self.cycles(self.v20_shift_cycles(op2_value));
+                let result = self.bitshift_op16(self.i.mnemonic, op1_value, op2_value);
+                self.write_operand16(self.i.operand1_type, self.i.segment_override, result, ReadWriteFlag::RNI);
+            }
+            0xC8 => {
+                // ENTER imm16, imm8 - Create a stack frame with the specified nesting level
+                let frame_size = self.read_operand16(self.i.operand1_type, SegmentOverride::None).unwrap();
+                let level = self.read_operand8(self.i.operand2_type, SegmentOverride::None).unwrap() & 0x1F;
+
+                self.push_register16(Register16::BP, ReadWriteFlag::Normal);
+                let frame_ptr = self.sp;
+
+                if level > 0 {
+                    for _ in 1..level {
+                        self.bp = self.bp.wrapping_sub(2);
+                        let addr = self.calc_linear_address_seg(Segment::SS, self.bp);
+                        let word = self.biu_read_u16(Segment::SS, addr, ReadWriteFlag::Normal);
+                        self.push_u16(word, ReadWriteFlag::Normal);
+                        self.cycles(4);
+                    }
+                    self.push_u16(frame_ptr, ReadWriteFlag::Normal);
+                }
+
+                self.bp = frame_ptr;
+                self.sp = self.sp.wrapping_sub(frame_size);
+                self.cycles(match level { 0 => 12, 1 => 18, _ => 19 });
+            }
+            0xC9 => {
+                // LEAVE - Release the stack frame created by ENTER
+                self.sp = self.bp;
+                self.pop_register16(Register16::BP, ReadWriteFlag::Normal);
+                self.cycles(2);
+            }
+            0xD4 => {
+                // AAM - The V20 ignores the immediate operand and always uses base 10
+                let _op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                
+                if !self.aam(10) {
+                    *exception = CpuException::DivideError;
+                }
+            }
+            0xD5 => {
+                // AAD - The V20 ignores the immediate operand and always uses base 10
+                let _op1_value = self.read_operand8(self.i.operand1_type, SegmentOverride::None).unwrap();
+                self.aad(10);
+            }
+            0xD6 => {
+                // XLAT (alias)
+                let segment = Cpu::segment_override(self.i.segment_override, Segment::DS);
+                let disp16: u16 = self.bx.wrapping_add(self.al as u16);
+                let addr = self.calc_linear_address_seg(segment, disp16);
+
+                self.cycles(3);
+                let value = self.biu_read_u8(segment, addr);
+                self.set_register8(Register8::AL, value);
+            }
+            0xF6 | 0xF7 => {
+                // MUL, IMUL, DIV & IDIV use the V20's hardware multiplier and divider. A REP prefix 
+                // does not negate the result as it does on the 8088.
+                match (self.i.opcode, self.i.mnemonic) {
+                    (0xF6, Mnemonic::MUL) => {
+                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        self.multiply_u8(op1_value);
+                        self.cycles(V20_MULU8_CYCLES);
+                    }
+                    (0xF6, Mnemonic::IMUL) => {
+                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        self.multiply_i8(op1_value as i8);
+                        self.cycles(V20_MUL8_CYCLES);
+                    }
+                    (0xF6, Mnemonic::DIV) => {
+                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        if !self.divide_u8(op1_value) {
+                            *exception = CpuException::DivideError;
+                        }
+                        self.cycles(V20_DIVU8_CYCLES);
+                    }
+                    (0xF6, Mnemonic::IDIV) => {
+                        let op1_value = self.read_operand8(self.i.operand1_type, self.i.segment_override).unwrap();
+                        if !self.divide_i8(op1_value) {
+                            *exception = CpuException::DivideError;
+                        }
+                        self.cycles(V20_DIV8_CYCLES);
+                    }
+                    (0xF7, Mnemonic::MUL) => {
+                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        self.multiply_u16(op1_value);
+                        self.cycles(V20_MULU16_CYCLES);
+                    }
+                    (0xF7, Mnemonic::IMUL) => {
+                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        self.multiply_i16(op1_value as i16);
+                        self.cycles(V20_MUL16_CYCLES);
+                    }
+                    (0xF7, Mnemonic::DIV) => {
+                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        if !self.divide_u16(op1_value) {
+                            *exception = CpuException::DivideError;
+                        }
+                        self.cycles(V20_DIVU16_CYCLES);
+                    }
+                    (0xF7, Mnemonic::IDIV) => {
+                        let op1_value = self.read_operand16(self.i.operand1_type, self.i.segment_override).unwrap();
+                        if !self.divide_i16(op1_value) {
+                            *exception = CpuException::DivideError;
+                        }
+                        self.cycles(V20_DIV16_CYCLES);
+                    }
+                    // TEST, NOT and NEG execute as on the 8088
+                    _ => return false
+                }
+            }
+            _ => return false
+        }
+        true
+    }
+
+    /// Return the execution cycles of a shift or rotate by the specified count.
+    fn v20_shift_cycles(&self, count: u8) -> u32 {
+        let base = match self.i.operand1_type {
+            OperandType::AddressingMode(_) => V20_SHIFT_MEM_CYCLES,
+            _ => V20_SHIFT_CYCLES
+        };
+        base + (count & 0x1F) as u32
+    }
+
+    /// Perform a single iteration of a string I/O instruction.
+    fn string_io_op(&mut self, mnemonic: Mnemonic, segment_override: SegmentOverride) {
+
+        let delta = match mnemonic {
+            Mnemonic::INSB | Mnemonic::OUTSB => 1,
+            _ => 2
+        };
+
+        match mnemonic {
+            Mnemonic::INSB | Mnemonic::INSW => {
+                // Read from port DX to [es:di] (ES segment cannot be overridden)
+                let dest_addr = Cpu::calc_linear_address(self.es, self.di);
+                if mnemonic == Mnemonic::INSB {
+                    let byte = self.biu_io_read_u8(self.dx);
+                    self.biu_write_u8(Segment::ES, dest_addr, byte, ReadWriteFlag::Normal);
+                }
+                else {
+                    let lo = self.biu_io_read_u8(self.dx);
+                    let ho = self.biu_io_read_u8(self.dx.wrapping_add(1));
+                    let word = (ho as u16) << 8 | lo as u16;
+                    self.biu_write_u16(Segment::ES, dest_addr, word, ReadWriteFlag::Normal);
+                }
+
+                self.di = match self.get_flag(Flag::Direction) {
+                    false => self.di.wrapping_add(delta),
+                    true => self.di.wrapping_sub(delta)
+                };
+            }
+            Mnemonic::OUTSB | Mnemonic::OUTSW => {
+                // Write [ds:si] to port DX (Segment overrideable)
+                let segment = Cpu::segment_override(segment_override, Segment::DS);
+                let src_addr = self.calc_linear_address_seg(segment, self.si);
+                if mnemonic == Mnemonic::OUTSB {
+                    let byte = self.biu_read_u8(segment, src_addr);
+                    self.biu_io_write_u8(self.dx, byte, ReadWriteFlag::Normal);
+                }
+                else {
+                    let word = self.biu_read_u16(segment, src_addr, ReadWriteFlag::Normal);
+                    self.biu_io_write_u16(self.dx, word, ReadWriteFlag::Normal);
+                }
+
+                self.si = match self.get_flag(Flag::Direction) {
+                    false => self.si.wrapping_add(delta),
+                    true => self.si.wrapping_sub(delta)
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_808x::*;
+    use crate::config::TraceMode;
+    #[cfg(feature = "cpu_validator")]
+    use crate::config::ValidatorType;
+    use crate::cpu_common::CpuType;
+    use crate::tracelogger::TraceLogger;
+
+    const CODE_SEG: u16 = 0x1000;
+    const STACK_SEG: u16 = 0x2000;
+    const DATA_SEG: u16 = 0x3000;
+    const STACK_TOP: u16 = 0x0100;
+    const INT5_HANDLER: u16 = 0x0500;
+
+    /// Create a CPU of the specified type about to execute 'code' at CODE_SEG:0000. The INT 5
+    /// handler is a single IRET.
+    fn test_cpu(cpu_type: CpuType, code: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(
+            cpu_type,
+            TraceMode::None,
+            TraceLogger::None,
+            #[cfg(feature = "cpu_validator")]
+            ValidatorType::None,
+            #[cfg(feature = "cpu_validator")]
+            TraceLogger::None
+        );
+
+        let bus = cpu.bus_mut();
+        bus.write_u8(5 * 4, INT5_HANDLER as u8, 0).unwrap();
+        bus.write_u8(5 * 4 + 1, (INT5_HANDLER >> 8) as u8, 0).unwrap();
+        bus.write_u8(INT5_HANDLER as usize, 0xCF, 0).unwrap();
+        let code_addr = Cpu::calc_linear_address(CODE_SEG, 0) as usize;
+        for (i, byte) in code.iter().enumerate() {
+            bus.write_u8(code_addr + i, *byte, 0).unwrap();
+        }
+
+        cpu.set_reset_vector(CpuAddress::Segmented(CODE_SEG, 0));
+        cpu.reset();
+        cpu.set_register16(Register16::SS, STACK_SEG);
+        cpu.set_register16(Register16::SP, STACK_TOP);
+        cpu.set_register16(Register16::DS, DATA_SEG);
+        cpu.set_register16(Register16::ES, DATA_SEG);
+        cpu
+    }
+
+    /// Read the word at SS:offset, completing any bus cycle in progress first.
+    fn stack_word(cpu: &mut Cpu, offset: u16) -> u16 {
+        cpu.biu_bus_wait_finish();
+        let addr = Cpu::calc_linear_address(STACK_SEG, offset);
+        cpu.bus_mut().read_u16(addr as usize, 0).unwrap().0
+    }
+
+    fn write_word(cpu: &mut Cpu, seg: u16, offset: u16, value: u16) {
+        cpu.biu_bus_wait_finish();
+        let addr = Cpu::calc_linear_address(seg, offset) as usize;
+        cpu.bus_mut().write_u8(addr, value as u8, 0).unwrap();
+        cpu.bus_mut().write_u8(addr + 1, (value >> 8) as u8, 0).unwrap();
+    }
+
+    /// Step until the instruction at CODE_SEG:'ip' is reached. REP string instructions take a 
+    /// step per iteration.
+    fn run_to(cpu: &mut Cpu, ip: u16) {
+        for _ in 0..100 {
+            if (cpu.cs, cpu.ip) == (CODE_SEG, ip) {
+                return;
+            }
+            cpu.step(false).unwrap();
+        }
+        panic!("didn't reach {:04X}:{:04X}, at {:04X}:{:04X}", CODE_SEG, ip, cpu.cs, cpu.ip);
+    }
+
+    #[test]
+    fn test_pusha_popa() {
+        // PUSHA, POPA
+        let mut cpu = test_cpu(CpuType::NecV20, &[0x60, 0x61]);
+        let regs = [
+            Register16::AX, Register16::CX, Register16::DX, Register16::BX,
+            Register16::BP, Register16::SI, Register16::DI
+        ];
+        for (i, reg) in regs.iter().enumerate() {
+            cpu.set_register16(*reg, 0x1111 * (i as u16 + 1));
+        }
+
+        run_to(&mut cpu, 1);
+        assert_eq!(cpu.sp, STACK_TOP - 16);
+        // DI, SI, BP, SP, BX, DX, CX, AX from the top of the stack. SP is stored as it was
+        // before PUSHA.
+        let sp = cpu.sp;
+        let expected = [0x7777, 0x6666, 0x5555, STACK_TOP, 0x4444, 0x3333, 0x2222, 0x1111];
+        for (i, word) in expected.iter().enumerate() {
+            assert_eq!(stack_word(&mut cpu, sp + i as u16 * 2), *word);
+        }
+
+        // POPA discards the stored SP.
+        write_word(&mut cpu, STACK_SEG, sp + 6, 0x1234);
+        for reg in regs {
+            cpu.set_register16(reg, 0);
+        }
+        run_to(&mut cpu, 2);
+        assert_eq!(cpu.sp, STACK_TOP);
+        for (i, reg) in regs.iter().enumerate() {
+            assert_eq!(cpu.get_register16(*reg), 0x1111 * (i as u16 + 1));
+        }
+    }
+
+    #[test]
+    fn test_enter_nested() {
+        // ENTER 10h, 2
+        let mut cpu = test_cpu(CpuType::NecV20, &[0xC8, 0x10, 0x00, 0x02]);
+        // The enclosing procedure's frame, with its display entry below BP.
+        cpu.set_register16(Register16::BP, 0x00F0);
+        write_word(&mut cpu, STACK_SEG, 0x00EE, 0xAAAA);
+
+        run_to(&mut cpu, 4);
+        // Old BP, the enclosing frame pointer copied from the display, then the new frame pointer.
+        assert_eq!(stack_word(&mut cpu, 0x00FE), 0x00F0);
+        assert_eq!(stack_word(&mut cpu, 0x00FC), 0xAAAA);
+        assert_eq!(stack_word(&mut cpu, 0x00FA), 0x00FE);
+        assert_eq!(cpu.bp, 0x00FE);
+        assert_eq!(cpu.sp, 0x00FA - 0x10);
+    }
+
+    #[test]
+    fn test_bound() {
+        // BOUND AX, [0200h]
+        let code = [0x62, 0x06, 0x00, 0x02];
+
+        let mut cpu = test_cpu(CpuType::NecV20, &code);
+        write_word(&mut cpu, DATA_SEG, 0x0200, 0x0010);
+        write_word(&mut cpu, DATA_SEG, 0x0202, 0x0020);
+        cpu.set_register16(Register16::AX, 0x0020);
+        run_to(&mut cpu, 4);
+        assert_eq!(cpu.sp, STACK_TOP);
+
+        // Out of range raises INT 5, returning to the BOUND instruction.
+        let mut cpu = test_cpu(CpuType::NecV20, &code);
+        write_word(&mut cpu, DATA_SEG, 0x0200, 0x0010);
+        write_word(&mut cpu, DATA_SEG, 0x0202, 0x0020);
+        cpu.set_register16(Register16::AX, 0x0021);
+        cpu.step(false).unwrap();
+        assert_eq!((cpu.cs, cpu.ip), (0, INT5_HANDLER));
+        let sp = cpu.sp;
+        assert_eq!(stack_word(&mut cpu, sp), 0x0000);
+        assert_eq!(stack_word(&mut cpu, sp + 2), CODE_SEG);
+    }
+
+    #[test]
+    fn test_shift_count_masked() {
+        // SHL AL, CL; SHL BX, CL
+        let code = [0xD2, 0xE0, 0xD3, 0xE3];
+
+        for (cpu_type, expected) in [(CpuType::NecV20, 2), (CpuType::Intel8088, 0)] {
+            let mut cpu = test_cpu(cpu_type, &code);
+            cpu.set_register16(Register16::AX, 1);
+            cpu.set_register16(Register16::BX, 1);
+            cpu.set_register8(Register8::CL, 33);
+            run_to(&mut cpu, 4);
+            assert_eq!(cpu.get_register8(Register8::AL), expected, "{:?}", cpu_type);
+            assert_eq!(cpu.get_register16(Register16::BX), expected as u16, "{:?}", cpu_type);
+        }
+    }
+
+    #[test]
+    fn test_aam_ignores_immediate() {
+        // AAM 10h
+        for (cpu_type, expected) in [(CpuType::NecV20, 0x0505), (CpuType::Intel8088, 0x0307)] {
+            let mut cpu = test_cpu(cpu_type, &[0xD4, 0x10]);
+            cpu.set_register16(Register16::AX, 55);
+            run_to(&mut cpu, 2);
+            assert_eq!(cpu.get_register16(Register16::AX), expected, "{:?}", cpu_type);
+        }
+    }
+
+    #[test]
+    fn test_repc_repnc() {
+        // REPNC SCASB; REPC SCASB
+        let mut cpu = test_cpu(CpuType::NecV20, &[0x64, 0xAE, 0x65, 0xAE]);
+        let data = [0x01, 0x02, 0x20, 0x30, 0x03];
+        for (i, byte) in data.iter().enumerate() {
+            let addr = Cpu::calc_linear_address(DATA_SEG, i as u16) as usize;
+            cpu.bus_mut().write_u8(addr, *byte, 0).unwrap();
+        }
+        cpu.set_register8(Register8::AL, 0x10);
+        cpu.set_register16(Register16::CX, 5);
+        cpu.set_register16(Register16::DI, 0);
+
+        // Repeats while AL is not below the scanned byte, stopping after 20h sets carry.
+        run_to(&mut cpu, 2);
+        assert_eq!((cpu.di, cpu.cx), (3, 2));
+
+        // Repeats while AL is below the scanned byte, stopping after 03h clears carry.
+        run_to(&mut cpu, 4);
+        assert_eq!((cpu.di, cpu.cx), (5, 0));
+    }
+
+    #[test]
+    fn test_extended_opcode_nop() {
+        // An unimplemented extended instruction (0F 20) executes as a two byte NOP.
+        let mut cpu = test_cpu(CpuType::NecV20, &[0x0F, 0x20, 0x90]);
+        cpu.step(false).unwrap();
+        assert_eq!((cpu.cs, cpu.ip), (CODE_SEG, 2));
+    }
+
+    #[test]
+    fn test_extended_opcode_length() {
+        #[rustfmt::skip]
+        let code = [
+            0x0F, 0x12, 0x06, 0x34, 0x12,   // CLR1 byte [1234h], CL
+            0x0F, 0x1D, 0x47, 0x05, 0x0F,   // SET1 word [bx+5], 15
+            0x0F, 0x28, 0xC3,               // ROL4 BL
+            0x0F, 0x31, 0xC1,               // INS AL, CL
+            0x0F, 0x39, 0xC0, 0x04,         // INS AL, 4
+            0x0F, 0x16, 0x80, 0x00, 0x10,   // NOT1 byte [bx+si+1000h], CL
+            0x0F, 0x20,                     // ADD4S
+            0x40,                           // INC AX
+        ];
+        let mut cpu = test_cpu(CpuType::NecV20, &code);
+        cpu.set_register16(Register16::AX, 0);
+        cpu.set_register16(Register16::BX, 0);
+
+        // Each instruction executes as a NOP, leaving memory and registers unchanged.
+        for ip in [5, 10, 13, 16, 20, 25, 27] {
+            cpu.step(false).unwrap();
+            assert_eq!((cpu.cs, cpu.ip), (CODE_SEG, ip));
+        }
+        assert_eq!((cpu.ax, cpu.bx), (0, 0));
+        let data = Cpu::calc_linear_address(DATA_SEG, 0x1234) as usize;
+        assert_eq!(cpu.bus_mut().read_u8(data, 0).unwrap().0, 0);
+
+        // The instruction stream is still aligned.
+        cpu.step(false).unwrap();
+        assert_eq!((cpu.ip, cpu.ax), (28, 1));
+    }
+}
@@ -33,10 +33,11 @@
 #![allow(dead_code)]
 
 
-#[derive (Copy, Clone, Debug)]
+#[derive (Copy, Clone, Debug, PartialEq, Deserialize)]
 pub enum CpuType {
     Intel8088,
     Intel8086,
+    NecV20,
 }
 
 impl Default for CpuType {
@@ -46,6 +47,7 @@ impl Default for CpuType {
 #[derive (Debug)]
 pub enum CpuOption {
     InstructionHistory(bool),
+    InstructionHistoryLen(u32),
     SimulateDramRefresh(bool, u32, u32),
     DramRefreshAdjust(u32),
     HaltResumeDelay(u32),
@@ -54,6 +56,8 @@ pub enum CpuOption {
     TraceLoggingEnabled(bool)
 }
 
+use serde_derive::Deserialize;
+
 use crate::cpu_808x::*;
 
 pub mod alu;
@@ -33,10 +33,14 @@
 #![allow(dead_code)]
 
 use std::{ 
+    collections::BTreeMap,
     error::Error,
     fmt::Display,
+    time::Duration,
 };
 
+use serde_derive::{Deserialize, Serialize};
+
 use crate::cpu_808x::QueueOp;
 
 #[derive (PartialEq, Debug, Copy, Clone)]
@@ -64,7 +68,7 @@ pub enum ReadType {
     Data
 }
 
-#[derive (Copy, Clone, Default, PartialEq)]
+#[derive (Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct VRegisters {
     pub ax: u16,
     pub bx: u16,
@@ -88,6 +92,7 @@ pub enum ValidatorError {
     CpuError,
     MemOpMismatch,
     RegisterMismatch,
+    FlagsMismatch,
     CpuDesynced,
     CycleMismatch
 }
@@ -108,6 +113,9 @@ impl Display for ValidatorError{
             ValidatorError::RegisterMismatch => {
                 write!(f, "Instruction registers did not validate.")
             }
+            ValidatorError::FlagsMismatch => {
+                write!(f, "Instruction flags did not validate.")
+            }
             ValidatorError::CpuDesynced => {
                 write!(f, "CPU state desynced with client.")
             }
@@ -118,6 +126,126 @@ impl Display for ValidatorError{
     }
 }
 
+/// Categories of validation failure tracked by ValidatorStats.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum MismatchKind {
+    MemOps,
+    Registers,
+    Flags,
+    Cycles,
+    Other
+}
+
+pub const MISMATCH_KINDS: [MismatchKind; 5] = [
+    MismatchKind::MemOps,
+    MismatchKind::Registers,
+    MismatchKind::Flags,
+    MismatchKind::Cycles,
+    MismatchKind::Other,
+];
+
+impl MismatchKind {
+    pub fn from_error(e: &ValidatorError) -> Self {
+        match e {
+            ValidatorError::MemOpMismatch => MismatchKind::MemOps,
+            ValidatorError::RegisterMismatch => MismatchKind::Registers,
+            ValidatorError::FlagsMismatch => MismatchKind::Flags,
+            ValidatorError::CycleMismatch => MismatchKind::Cycles,
+            _ => MismatchKind::Other
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MismatchKind::MemOps => "Memory ops",
+            MismatchKind::Registers => "Registers",
+            MismatchKind::Flags => "Flags",
+            MismatchKind::Cycles => "Cycles",
+            MismatchKind::Other => "Other",
+        }
+    }
+}
+
+#[derive (Clone, Default, Debug, PartialEq)]
+pub struct OpcodeStats {
+    pub mnemonic: String,
+    pub validated: u64,
+    pub mismatches: u64,
+}
+
+/// Statistics collected over a validation run: instructions validated, mismatches by kind and 
+/// by opcode, and the time spent in the validator.
+#[derive (Clone, Default, Debug)]
+pub struct ValidatorStats {
+    pub instructions: u64,
+    pub mismatches: [u64; MISMATCH_KINDS.len()],
+    pub opcodes: BTreeMap<u8, OpcodeStats>,
+    pub elapsed: Duration,
+}
+
+impl ValidatorStats {
+    fn opcode_entry(&mut self, opcode: u8, mnemonic: &str) -> &mut OpcodeStats {
+        self.opcodes.entry(opcode).or_insert_with(|| OpcodeStats {
+            mnemonic: mnemonic.to_string(),
+            ..Default::default()
+        })
+    }
+
+    pub fn record_instruction(&mut self, opcode: u8, mnemonic: &str, elapsed: Duration) {
+        self.instructions += 1;
+        self.elapsed += elapsed;
+        self.opcode_entry(opcode, mnemonic).validated += 1;
+    }
+
+    pub fn record_mismatch(&mut self, opcode: u8, mnemonic: &str, kind: MismatchKind) {
+        self.mismatches[kind as usize] += 1;
+        self.opcode_entry(opcode, mnemonic).mismatches += 1;
+    }
+
+    pub fn mismatch_count(&self, kind: MismatchKind) -> u64 {
+        self.mismatches[kind as usize]
+    }
+
+    pub fn total_mismatches(&self) -> u64 {
+        self.mismatches.iter().sum()
+    }
+
+    /// Return the instructions validated per second of time spent in the validator.
+    pub fn instructions_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.instructions as f64 / secs } else { 0.0 }
+    }
+
+    /// Return up to 'count' opcodes with the most mismatches, in descending order.
+    pub fn top_opcodes(&self, count: usize) -> Vec<(u8, &OpcodeStats)> {
+        let mut ops: Vec<(u8, &OpcodeStats)> = self.opcodes
+            .iter()
+            .filter(|(_, s)| s.mismatches > 0)
+            .map(|(op, s)| (*op, s))
+            .collect();
+        ops.sort_by(|a, b| b.1.mismatches.cmp(&a.1.mismatches).then(a.0.cmp(&b.0)));
+        ops.truncate(count);
+        ops
+    }
+
+    /// Format the statistics as CSV: a summary section followed by a row per opcode.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        csv.push_str("statistic,value\n");
+        csv.push_str(&format!("instructions,{}\n", self.instructions));
+        for kind in MISMATCH_KINDS {
+            csv.push_str(&format!("{} mismatches,{}\n", kind.name(), self.mismatch_count(kind)));
+        }
+        csv.push_str(&format!("seconds,{:.3}\n", self.elapsed.as_secs_f64()));
+        csv.push('\n');
+        csv.push_str("opcode,mnemonic,validated,mismatches\n");
+        for (opcode, s) in &self.opcodes {
+            csv.push_str(&format!("{:02X},{},{},{}\n", opcode, s.mnemonic, s.validated, s.mismatches));
+        }
+        csv
+    }
+}
+
 #[derive (Copy, Clone, Debug, PartialEq)]
 pub enum BusCycle {
     T1,
@@ -229,3 +357,33 @@ pub trait CpuValidator {
     fn flush(&mut self);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validator_stats() {
+        let mut stats = ValidatorStats::default();
+
+        stats.record_instruction(0x90, "nop", Duration::from_millis(1));
+        stats.record_instruction(0x01, "add", Duration::from_millis(1));
+        stats.record_instruction(0x01, "add", Duration::from_millis(1));
+        stats.record_mismatch(0x01, "add", MismatchKind::from_error(&ValidatorError::FlagsMismatch));
+        stats.record_mismatch(0xF6, "mul", MismatchKind::Cycles);
+        stats.record_mismatch(0xF6, "mul", MismatchKind::Cycles);
+
+        assert_eq!(stats.instructions, 3);
+        assert_eq!(stats.mismatch_count(MismatchKind::Flags), 1);
+        assert_eq!(stats.mismatch_count(MismatchKind::Cycles), 2);
+        assert_eq!(stats.total_mismatches(), 3);
+        assert_eq!(stats.elapsed, Duration::from_millis(3));
+
+        let top: Vec<u8> = stats.top_opcodes(5).iter().map(|(op, _)| *op).collect();
+        assert_eq!(top, vec![0xF6, 0x01]);
+
+        let csv = stats.to_csv();
+        assert!(csv.contains("instructions,3\n"));
+        assert!(csv.contains("01,add,2,1\n"));
+        assert!(csv.contains("F6,mul,0,2\n"));
+    }
+}
@@ -0,0 +1,229 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+
+    --------------------------------------------------------------------------
+
+    device_manager.rs
+
+    Implements the registry of IO ports claimed by the devices on the bus.
+
+    Each device registers its ports as it is installed. A port claimed by 
+    two different devices is recorded as a conflict so that the machine 
+    can report it once all devices have been installed. As before, the 
+    device registered last receives the port.
+
+    Ports may also be given additional wait states, for devices that hold 
+    IO CH RDY low to lengthen the bus cycles that access them.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::bus::IoDeviceType;
+
+/// A set of IO ports claimed by a device that were already claimed by another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IoConflict {
+    pub existing: IoDeviceType,
+    pub device: IoDeviceType,
+    pub ports: Vec<u16>,
+}
+
+impl Display for IoConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The {} conflicts with the {} at IO port", self.device, self.existing)?;
+        match port_ranges(&self.ports).as_slice() {
+            [(start, end)] if start == end => write!(f, " {:03X}h", start)?,
+            ranges => {
+                write!(f, "s ")?;
+                let ranges: Vec<String> = ranges.iter().map(|(start, end)| match start == end {
+                    true => format!("{:03X}h", start),
+                    false => format!("{:03X}h-{:03X}h", start, end),
+                }).collect();
+                write!(f, "{}", ranges.join(", "))?;
+            }
+        }
+        write!(f, ". Change the port of one of the devices or disable one of them.")
+    }
+}
+
+/// Collapse a list of ports into sorted, inclusive ranges.
+fn port_ranges(ports: &[u16]) -> Vec<(u16, u16)> {
+    let mut sorted = ports.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for port in sorted {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == port => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+    ranges
+}
+
+#[derive(Default)]
+pub struct DeviceManager {
+    ports: HashMap<u16, IoDeviceType>,
+    conflicts: Vec<IoConflict>,
+    wait_states: HashMap<u16, u32>,
+}
+
+impl DeviceManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register the IO ports of a device, recording any ports already claimed by another device.
+    pub fn register(&mut self, device: IoDeviceType, ports: impl IntoIterator<Item = u16>) {
+        for port in ports {
+            if let Some(existing) = self.ports.insert(port, device) {
+                if existing != device {
+                    self.add_conflict(existing, device, port);
+                }
+            }
+        }
+    }
+
+    /// Assign IO ports to a device without reporting a conflict, for ports that are shared 
+    /// by design.
+    pub fn assign(&mut self, device: IoDeviceType, ports: impl IntoIterator<Item = u16>) {
+        for port in ports {
+            self.ports.insert(port, device);
+        }
+    }
+
+    fn add_conflict(&mut self, existing: IoDeviceType, device: IoDeviceType, port: u16) {
+        match self.conflicts.iter_mut().find(|c| c.existing == existing && c.device == device) {
+            Some(conflict) => conflict.ports.push(port),
+            None => self.conflicts.push(IoConflict { existing, device, ports: vec![port] }),
+        }
+    }
+
+    /// Set the additional wait states for accesses to the specified ports.
+    pub fn set_wait_states(&mut self, ports: impl IntoIterator<Item = u16>, wait_states: u32) {
+        for port in ports {
+            if wait_states > 0 {
+                self.wait_states.insert(port, wait_states);
+            }
+            else {
+                self.wait_states.remove(&port);
+            }
+        }
+    }
+
+    /// Set the additional wait states for accesses to every port currently claimed by a device.
+    pub fn set_device_wait_states(&mut self, device: IoDeviceType, wait_states: u32) {
+        let ports: Vec<u16> = self.ports.iter()
+            .filter(|(_, d)| **d == device)
+            .map(|(p, _)| *p)
+            .collect();
+        self.set_wait_states(ports, wait_states);
+    }
+
+    /// Return the additional wait states for an access to the specified port.
+    #[inline]
+    pub fn wait_states(&self, port: u16) -> u32 {
+        if self.wait_states.is_empty() {
+            return 0
+        }
+        self.wait_states.get(&port).copied().unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn get(&self, port: &u16) -> Option<&IoDeviceType> {
+        self.ports.get(port)
+    }
+
+    pub fn conflicts(&self) -> &[IoConflict] {
+        &self.conflicts
+    }
+
+    /// Return the inclusive port ranges claimed by each device, sorted by port.
+    pub fn port_map(&self) -> Vec<(IoDeviceType, u16, u16)> {
+        let mut ports: Vec<(u16, IoDeviceType)> = self.ports.iter().map(|(p, d)| (*p, *d)).collect();
+        ports.sort_unstable_by_key(|(port, _)| *port);
+
+        let mut map: Vec<(IoDeviceType, u16, u16)> = Vec::new();
+        for (port, device) in ports {
+            match map.last_mut() {
+                Some((last_device, _, end)) if *last_device == device && *end + 1 == port => *end = port,
+                _ => map.push((device, port, port)),
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicts_are_grouped() {
+        let mut devices = DeviceManager::new();
+        devices.register(IoDeviceType::XtIde, 0x300..0x310);
+        devices.register(IoDeviceType::Ne2000, 0x300..0x320);
+        devices.register(IoDeviceType::Ne2000, [0x300]);
+
+        assert_eq!(devices.conflicts().len(), 1);
+        assert_eq!(devices.conflicts()[0].ports.len(), 0x10);
+        assert_eq!(
+            devices.conflicts()[0].to_string(),
+            "The NE2000 network card conflicts with the XT-IDE controller at IO ports 300h-30Fh. \
+             Change the port of one of the devices or disable one of them."
+        );
+        assert_eq!(devices.get(&0x305), Some(&IoDeviceType::Ne2000));
+        assert_eq!(devices.port_map(), vec![(IoDeviceType::Ne2000, 0x300, 0x31F)]);
+    }
+
+    #[test]
+    fn assign_does_not_conflict() {
+        let mut devices = DeviceManager::new();
+        devices.register(IoDeviceType::Ega, [0x3B4, 0x3B5, 0x3C0]);
+        devices.assign(IoDeviceType::Mda, [0x3B4, 0x3B5]);
+
+        assert!(devices.conflicts().is_empty());
+        assert_eq!(devices.port_map(), vec![(IoDeviceType::Mda, 0x3B4, 0x3B5), (IoDeviceType::Ega, 0x3C0, 0x3C0)]);
+    }
+
+    #[test]
+    fn device_wait_states() {
+        let mut devices = DeviceManager::new();
+        devices.register(IoDeviceType::Ega, [0x3C0, 0x3C1]);
+        devices.register(IoDeviceType::XtIde, 0x300..0x310);
+        devices.set_device_wait_states(IoDeviceType::Ega, 2);
+        devices.set_wait_states([0x301], 1);
+
+        assert_eq!(devices.wait_states(0x3C1), 2);
+        assert_eq!(devices.wait_states(0x301), 1);
+        assert_eq!(devices.wait_states(0x300), 0);
+
+        devices.set_device_wait_states(IoDeviceType::Ega, 0);
+        assert_eq!(devices.wait_states(0x3C0), 0);
+    }
+}
@@ -0,0 +1,233 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::a20.rs
+
+    Implements the A20 gate, which masks address line 20 so that addresses
+    above 1MB wrap around as they do on an 8088.
+
+    AT-class machines control the gate through the output port of the 
+    keyboard controller: command D1h written to port 64h, followed by the 
+    new output port value written to port 60h, where bit 1 is the gate. 
+    Command D0h places the output port in the output buffer, to be read
+    from port 60h. The commands DDh and DFh disable and enable the gate 
+    directly, as on many later controllers. The 'fast A20' bit 1 of system 
+    control port 92h is also supported.
+
+    Only the A20 related keyboard controller commands are handled. Port 60h 
+    belongs to the PPI on PC and XT machines, so the bus passes a write to 
+    it to the gate only when a D1h command is pending, and a read from it 
+    only when the output port is waiting to be read after a D0h command.
+
+*/
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+pub const KBC_DATA_PORT: u16 = 0x60;
+pub const KBC_COMMAND_PORT: u16 = 0x64;
+pub const SYSTEM_CONTROL_PORT_A: u16 = 0x92;
+
+pub const A20_ADDRESS_BIT: usize = 1 << 20;
+
+const KBC_CMD_READ_OUTPUT_PORT: u8 = 0xD0;
+const KBC_CMD_WRITE_OUTPUT_PORT: u8 = 0xD1;
+const KBC_CMD_DISABLE_A20: u8 = 0xDD;
+const KBC_CMD_ENABLE_A20: u8 = 0xDF;
+
+const OUTPUT_PORT_A20: u8 = 0b0000_0010;
+// System reset is active low; keep it high.
+const OUTPUT_PORT_RESET: u8 = 0b0000_0001;
+const PORT_A_A20: u8 = 0b0000_0010;
+
+// Input buffer and output buffer empty, self test passed.
+const KBC_STATUS: u8 = 0b0000_0100;
+const KBC_STATUS_OUTPUT_FULL: u8 = 0b0000_0001;
+
+#[derive (Default)]
+pub struct A20Gate {
+    enabled: bool,
+    output_port_pending: bool,
+    output_buffer: Option<u8>,
+    port_a: u8,
+}
+
+impl A20Gate {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Return whether address line 20 is passed through to memory.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Return whether a write to the keyboard controller's data port is a new output port value.
+    pub fn output_port_pending(&self) -> bool {
+        self.output_port_pending
+    }
+
+    /// Return whether a read of the keyboard controller's data port returns the output port.
+    pub fn output_buffer_full(&self) -> bool {
+        self.output_buffer.is_some()
+    }
+
+    fn output_port(&self) -> u8 {
+        OUTPUT_PORT_RESET | if self.enabled { OUTPUT_PORT_A20 } else { 0 }
+    }
+
+    fn set_enabled(&mut self, state: bool) {
+        if state != self.enabled {
+            log::debug!("A20 gate {}", if state { "enabled" } else { "disabled" });
+        }
+        self.enabled = state;
+        if state {
+            self.port_a |= PORT_A_A20;
+        }
+        else {
+            self.port_a &= !PORT_A_A20;
+        }
+    }
+}
+
+impl IoDevice for A20Gate {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port {
+            KBC_DATA_PORT => self.output_buffer.take().unwrap_or(0xFF),
+            KBC_COMMAND_PORT if self.output_buffer.is_some() => KBC_STATUS | KBC_STATUS_OUTPUT_FULL,
+            KBC_COMMAND_PORT => KBC_STATUS,
+            SYSTEM_CONTROL_PORT_A => self.port_a,
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port {
+            KBC_DATA_PORT if self.output_port_pending => {
+                self.output_port_pending = false;
+                self.set_enabled(data & OUTPUT_PORT_A20 != 0);
+            }
+            KBC_COMMAND_PORT => {
+                self.output_port_pending = false;
+                self.output_buffer = None;
+                match data {
+                    KBC_CMD_READ_OUTPUT_PORT => self.output_buffer = Some(self.output_port()),
+                    KBC_CMD_WRITE_OUTPUT_PORT => self.output_port_pending = true,
+                    KBC_CMD_DISABLE_A20 => self.set_enabled(false),
+                    KBC_CMD_ENABLE_A20 => self.set_enabled(true),
+                    _ => log::trace!("A20 gate: unhandled keyboard controller command: {:02X}", data),
+                }
+            }
+            SYSTEM_CONTROL_PORT_A => {
+                self.port_a = data;
+                self.set_enabled(data & PORT_A_A20 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        vec![KBC_COMMAND_PORT, SYSTEM_CONTROL_PORT_A]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::ClockFactor;
+    use crate::config::MachineType;
+    use crate::machine_manager::MACHINE_DESCS;
+
+    fn test_bus() -> BusInterface {
+        let mut bus = BusInterface::new(ClockFactor::Divisor(3), MACHINE_DESCS[&MachineType::IBM_PC_5150]);
+        bus.set_address_mask(0x1FFFFF);
+        bus.install_a20_gate();
+        bus
+    }
+
+    fn a20_enabled(bus: &BusInterface) -> bool {
+        bus.a20_gate().as_ref().unwrap().enabled()
+    }
+
+    #[test]
+    fn test_fast_a20() {
+        let mut bus = test_bus();
+        assert!(!a20_enabled(&bus));
+        assert_eq!(bus.address_mask(), 0x0FFFFF);
+
+        bus.io_write_u8(SYSTEM_CONTROL_PORT_A, PORT_A_A20, 0);
+        assert!(a20_enabled(&bus));
+        assert_eq!(bus.address_mask(), 0x1FFFFF);
+        assert_eq!(bus.io_read_u8(SYSTEM_CONTROL_PORT_A, 0), PORT_A_A20);
+
+        bus.io_write_u8(SYSTEM_CONTROL_PORT_A, 0, 0);
+        assert!(!a20_enabled(&bus));
+        assert_eq!(bus.address_mask(), 0x0FFFFF);
+        assert_eq!(bus.io_read_u8(SYSTEM_CONTROL_PORT_A, 0), 0);
+    }
+
+    #[test]
+    fn test_write_output_port() {
+        let mut bus = test_bus();
+
+        // Without a pending write output port command, port 60h is not the output port.
+        bus.io_write_u8(KBC_DATA_PORT, OUTPUT_PORT_RESET | OUTPUT_PORT_A20, 0);
+        assert!(!a20_enabled(&bus));
+
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_WRITE_OUTPUT_PORT, 0);
+        bus.io_write_u8(KBC_DATA_PORT, OUTPUT_PORT_RESET | OUTPUT_PORT_A20, 0);
+        assert!(a20_enabled(&bus));
+        assert_eq!(bus.address_mask(), 0x1FFFFF);
+        assert_eq!(bus.io_read_u8(SYSTEM_CONTROL_PORT_A, 0), PORT_A_A20);
+
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_WRITE_OUTPUT_PORT, 0);
+        bus.io_write_u8(KBC_DATA_PORT, OUTPUT_PORT_RESET, 0);
+        assert!(!a20_enabled(&bus));
+        assert_eq!(bus.address_mask(), 0x0FFFFF);
+    }
+
+    #[test]
+    fn test_read_output_port() {
+        let mut bus = test_bus();
+        assert_eq!(bus.io_read_u8(KBC_COMMAND_PORT, 0), KBC_STATUS);
+
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_ENABLE_A20, 0);
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_READ_OUTPUT_PORT, 0);
+        assert_eq!(bus.io_read_u8(KBC_COMMAND_PORT, 0), KBC_STATUS | KBC_STATUS_OUTPUT_FULL);
+        assert_eq!(bus.io_read_u8(KBC_DATA_PORT, 0), OUTPUT_PORT_RESET | OUTPUT_PORT_A20);
+
+        // The output buffer is emptied by the read.
+        assert_eq!(bus.io_read_u8(KBC_COMMAND_PORT, 0), KBC_STATUS);
+        assert!(!bus.a20_gate().as_ref().unwrap().output_buffer_full());
+
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_DISABLE_A20, 0);
+        bus.io_write_u8(KBC_COMMAND_PORT, KBC_CMD_READ_OUTPUT_PORT, 0);
+        assert_eq!(bus.io_read_u8(KBC_DATA_PORT, 0), OUTPUT_PORT_RESET);
+    }
+}
@@ -0,0 +1,748 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::adlib.rs
+
+    Implements the AdLib Music Synthesizer Card, built around the Yamaha
+    YM3812 (OPL2) FM synthesis chip.
+
+    The OPL2 is emulated in floating point at the host's audio sample rate
+    rather than at the chip's native 49716Hz. This is not bit-exact to the
+    real chip, but the timers are accurate enough for AdLib detection
+    routines and the synthesis is close enough for music playback.
+
+    Rhythm mode is approximated; the percussion voices use noise in place of
+    the real chip's phase-bit tricks.
+*/
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+pub const ADLIB_ADDRESS_PORT: u16 = 0x388;
+pub const ADLIB_DATA_PORT: u16 = 0x389;
+
+/// The native sample rate of the OPL2 (3.579545Mhz / 72).
+pub const OPL2_NATIVE_RATE: f32 = 49716.0;
+
+/// Adjust the AdLib's output relative to the PC speaker.
+pub const ADLIB_VOLUME: f32 = 0.20;
+
+const OPL_NUM_CHANNELS: usize = 9;
+const OPL_NUM_OPERATORS: usize = 18;
+
+// Timer 1 has a resolution of 80us, Timer 2 320us.
+const TIMER1_TICK_US: f64 = 80.0;
+const TIMER2_TICK_US: f64 = 320.0;
+
+const STATUS_IRQ: u8        = 0b1000_0000;
+const STATUS_T1_FLAG: u8    = 0b0100_0000;
+const STATUS_T2_FLAG: u8    = 0b0010_0000;
+// The OPL2 always returns these bits set in the status register. The OPL3 does not,
+// which is how software tells them apart.
+const STATUS_OPL2_ID: u8    = 0b0000_0110;
+
+const TIMER_CTRL_IRQ_RESET: u8 = 0b1000_0000;
+const TIMER_CTRL_T1_MASK: u8   = 0b0100_0000;
+const TIMER_CTRL_T2_MASK: u8   = 0b0010_0000;
+const TIMER_CTRL_T2_START: u8  = 0b0000_0010;
+const TIMER_CTRL_T1_START: u8  = 0b0000_0001;
+
+// Maximum attenuation of the envelope generator, in dB.
+const ENV_MAX_DB: f32 = 96.0;
+// Attenuation at which an operator is considered silent.
+const ENV_SILENT_DB: f32 = 90.0;
+
+// Time for a decay or release envelope to span 96dB at rate 1. Each rate step halves this.
+const DECAY_BASE_MS: f32 = 39280.0;
+// Attack time at rate 1.
+const ATTACK_BASE_MS: f32 = 2826.0;
+
+const TREMOLO_FREQ: f32 = 3.7;
+const VIBRATO_FREQ: f32 = 6.1;
+
+const MULTIPLIER_TABLE: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0
+];
+
+// Key scale level attenuation in dB for block 7, indexed by the upper 4 bits of F-Number.
+const KSL_TABLE: [f32; 16] = [
+    0.000, 9.000, 12.000, 13.875, 15.000, 16.125, 16.875, 17.625,
+    18.000, 18.750, 19.125, 19.500, 19.875, 20.250, 20.625, 21.000
+];
+
+// dB/octave slope for each KSL register value.
+const KSL_SHIFT: [f32; 4] = [0.0, 0.5, 0.25, 1.0];
+
+// Operator register offsets within each register group. Groups of registers (0x20, 0x40, etc)
+// address operators with gaps in between, so we map offsets to operator index here.
+const OPERATOR_OFFSETS: [i8; 0x20] = [
+     0,  1,  2,  3,  4,  5, -1, -1,  6,  7,  8,  9, 10, 11, -1, -1,
+    12, 13, 14, 15, 16, 17, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+];
+
+// Modulator and carrier operator index for each channel.
+const CHANNEL_OPERATORS: [(usize, usize); OPL_NUM_CHANNELS] = [
+    (0, 3), (1, 4), (2, 5), (6, 9), (7, 10), (8, 11), (12, 15), (13, 16), (14, 17)
+];
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off
+}
+
+#[derive (Copy, Clone)]
+struct Operator {
+    // Register 0x20 group
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    ksr: bool,
+    mult: u8,
+    // Register 0x40 group
+    ksl: u8,
+    total_level: u8,
+    // Register 0x60 group
+    attack_rate: u8,
+    decay_rate: u8,
+    // Register 0x80 group
+    sustain_level: u8,
+    release_rate: u8,
+    // Register 0xE0 group
+    waveform: u8,
+
+    phase: f32,
+    env_stage: EnvelopeStage,
+    env_db: f32,
+    key_on: bool,
+    out: [f32; 2],
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            tremolo: false,
+            vibrato: false,
+            sustained: false,
+            ksr: false,
+            mult: 0,
+            ksl: 0,
+            total_level: 0,
+            attack_rate: 0,
+            decay_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            waveform: 0,
+            phase: 0.0,
+            env_stage: EnvelopeStage::Off,
+            env_db: ENV_MAX_DB,
+            key_on: false,
+            out: [0.0; 2],
+        }
+    }
+}
+
+#[derive (Copy, Clone, Default)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key_on: bool,
+    feedback: u8,
+    additive: bool,
+}
+
+/// Per-sample values shared by all operators.
+#[derive (Copy, Clone)]
+struct SampleContext {
+    rate: f32,
+    tremolo_db: f32,
+    vibrato_factor: f32,
+    waveform_select: bool,
+    note_select: bool,
+}
+
+impl Operator {
+    fn set_key(&mut self, state: bool) {
+        if state && !self.key_on {
+            // Key on restarts the attack and resets the phase generator.
+            self.phase = 0.0;
+            self.env_stage = EnvelopeStage::Attack;
+        }
+        else if !state && self.key_on && self.env_stage != EnvelopeStage::Off {
+            self.env_stage = EnvelopeStage::Release;
+        }
+        self.key_on = state;
+    }
+
+    /// Calculate the effective envelope rate (0-63) for the specified 4-bit rate register.
+    fn effective_rate(&self, rate: u8, channel: &Channel, note_select: bool) -> f32 {
+        if rate == 0 {
+            return 0.0;
+        }
+        // Key scale rate is determined by the block and either bit 9 or 8 of F-Number, per NTS
+        let note_bit = if note_select { (channel.fnum >> 8) & 0x01 } else { (channel.fnum >> 9) & 0x01 };
+        let ksr_offset = ((channel.block as u16) << 1 | note_bit) >> if self.ksr { 0 } else { 2 };
+        ((rate as u16 * 4) + ksr_offset).min(63) as f32
+    }
+
+    /// Convert an effective rate into a time in seconds, given the time for rate 1.
+    fn rate_to_seconds(base_ms: f32, effective_rate: f32) -> f32 {
+        base_ms / 1000.0 / f32::powf(2.0, (effective_rate - 4.0) / 4.0)
+    }
+
+    fn tick_envelope(&mut self, channel: &Channel, ctx: &SampleContext) {
+        match self.env_stage {
+            EnvelopeStage::Attack => {
+                let er = self.effective_rate(self.attack_rate, channel, ctx.note_select);
+                if er >= 60.0 {
+                    // Rates 15 are instantaneous
+                    self.env_db = 0.0;
+                }
+                else if er > 0.0 {
+                    // Attack is exponential in the dB domain, approaching 0dB.
+                    let secs = Operator::rate_to_seconds(ATTACK_BASE_MS, er);
+                    let coeff = f32::exp(-f32::ln(ENV_MAX_DB * 10.0) / (secs * ctx.rate));
+                    self.env_db *= coeff;
+                }
+                if self.env_db < 0.1 {
+                    self.env_db = 0.0;
+                    self.env_stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                let sustain_db = if self.sustain_level == 15 { ENV_MAX_DB } else { self.sustain_level as f32 * 3.0 };
+                self.env_db += self.linear_step(self.decay_rate, channel, ctx);
+                if self.env_db >= sustain_db {
+                    self.env_db = sustain_db;
+                    self.env_stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => {
+                // Non-sustained (percussive) envelopes continue to decay at the release rate.
+                if !self.sustained {
+                    self.env_db += self.linear_step(self.release_rate, channel, ctx);
+                }
+            }
+            EnvelopeStage::Release => {
+                self.env_db += self.linear_step(self.release_rate, channel, ctx);
+            }
+            EnvelopeStage::Off => {
+                self.env_db = ENV_MAX_DB;
+            }
+        }
+
+        if self.env_db >= ENV_MAX_DB {
+            self.env_db = ENV_MAX_DB;
+            if self.env_stage != EnvelopeStage::Attack {
+                self.env_stage = EnvelopeStage::Off;
+            }
+        }
+    }
+
+    /// Return the attenuation step in dB per sample for a linear decay at the specified rate.
+    fn linear_step(&self, rate: u8, channel: &Channel, ctx: &SampleContext) -> f32 {
+        let er = self.effective_rate(rate, channel, ctx.note_select);
+        if er == 0.0 {
+            return 0.0;
+        }
+        let secs = Operator::rate_to_seconds(DECAY_BASE_MS, er);
+        ENV_MAX_DB / (secs * ctx.rate)
+    }
+
+    fn ksl_attenuation(&self, channel: &Channel) -> f32 {
+        if self.ksl == 0 {
+            return 0.0;
+        }
+        let base = KSL_TABLE[(channel.fnum >> 6) as usize & 0x0F] - 6.0 * (7 - channel.block) as f32;
+        if base <= 0.0 {
+            0.0
+        }
+        else {
+            base * KSL_SHIFT[self.ksl as usize] * 2.0
+        }
+    }
+
+    fn waveform(&self, phase: f32, waveform_select: bool) -> f32 {
+        let phase = phase - phase.floor();
+        let s = f32::sin(phase * 2.0 * PI);
+        let wave = if waveform_select { self.waveform } else { 0 };
+        match wave {
+            0 => s,
+            1 => if phase < 0.5 { s } else { 0.0 },
+            2 => s.abs(),
+            _ => {
+                if phase < 0.25 || (0.5..0.75).contains(&phase) { s.abs() } else { 0.0 }
+            }
+        }
+    }
+
+    /// Advance the operator by one sample and return its output. 'modulation' is a phase offset
+    /// in cycles.
+    fn generate(&mut self, channel: &Channel, ctx: &SampleContext, modulation: f32) -> f32 {
+        self.tick_envelope(channel, ctx);
+
+        let freq = channel.fnum as f32 * OPL2_NATIVE_RATE / f32::powf(2.0, 20.0 - channel.block as f32);
+        let mut phase_inc = freq * MULTIPLIER_TABLE[self.mult as usize] / ctx.rate;
+        if self.vibrato {
+            phase_inc *= ctx.vibrato_factor;
+        }
+        self.phase += phase_inc;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+        }
+
+        let mut atten = self.env_db + self.total_level as f32 * 0.75 + self.ksl_attenuation(channel);
+        if self.tremolo {
+            atten += ctx.tremolo_db;
+        }
+
+        let out = if atten >= ENV_SILENT_DB {
+            0.0
+        }
+        else {
+            self.waveform(self.phase + modulation, ctx.waveform_select) * f32::powf(10.0, -atten / 20.0)
+        };
+
+        self.out[1] = self.out[0];
+        self.out[0] = out;
+        out
+    }
+
+    /// Feedback phase offset in cycles for the modulator, from the average of its last two outputs.
+    fn feedback(&self, fb: u8) -> f32 {
+        if fb == 0 {
+            0.0
+        }
+        else {
+            (self.out[0] + self.out[1]) / f32::powf(2.0, (7 - fb) as f32)
+        }
+    }
+
+    fn is_silent(&self) -> bool {
+        self.env_stage == EnvelopeStage::Off
+    }
+}
+
+pub struct AdLibCard {
+    address: u8,
+    registers: [u8; 256],
+    operators: [Operator; OPL_NUM_OPERATORS],
+    channels: [Channel; OPL_NUM_CHANNELS],
+
+    waveform_select: bool,
+    note_select: bool,
+    am_depth: bool,
+    vib_depth: bool,
+    rhythm_mode: bool,
+    rhythm_keys: u8,
+
+    timer1_value: u8,
+    timer2_value: u8,
+    timer1_count: u16,
+    timer2_count: u16,
+    timer1_running: bool,
+    timer2_running: bool,
+    timer1_accum: f64,
+    timer2_accum: f64,
+    timer1_masked: bool,
+    timer2_masked: bool,
+    status: u8,
+
+    sample_rate: f32,
+    lfo_am_phase: f32,
+    lfo_vib_phase: f32,
+}
+
+impl IoDevice for AdLibCard {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port {
+            ADLIB_ADDRESS_PORT => self.status | STATUS_OPL2_ID,
+            // The data port is write-only.
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port {
+            ADLIB_ADDRESS_PORT => {
+                self.address = data;
+            }
+            ADLIB_DATA_PORT => {
+                self.write_register(self.address, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        vec![ADLIB_ADDRESS_PORT, ADLIB_DATA_PORT]
+    }
+}
+
+impl AdLibCard {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            address: 0,
+            registers: [0; 256],
+            operators: [Default::default(); OPL_NUM_OPERATORS],
+            channels: [Default::default(); OPL_NUM_CHANNELS],
+
+            waveform_select: false,
+            note_select: false,
+            am_depth: false,
+            vib_depth: false,
+            rhythm_mode: false,
+            rhythm_keys: 0,
+
+            timer1_value: 0,
+            timer2_value: 0,
+            timer1_count: 0,
+            timer2_count: 0,
+            timer1_running: false,
+            timer2_running: false,
+            timer1_accum: 0.0,
+            timer2_accum: 0.0,
+            timer1_masked: false,
+            timer2_masked: false,
+            status: 0,
+
+            sample_rate: sample_rate as f32,
+            lfo_am_phase: 0.0,
+            lfo_vib_phase: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let sample_rate = self.sample_rate;
+        *self = AdLibCard::new(sample_rate as u32);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f32;
+    }
+
+    /// Return the value last written to the specified OPL2 register.
+    pub fn get_register(&self, reg: u8) -> u8 {
+        self.registers[reg as usize]
+    }
+
+    fn operator_index(reg: u8) -> Option<usize> {
+        match OPERATOR_OFFSETS[(reg & 0x1F) as usize] {
+            -1 => None,
+            n => Some(n as usize)
+        }
+    }
+
+    fn write_register(&mut self, reg: u8, data: u8) {
+        self.registers[reg as usize] = data;
+
+        match reg {
+            0x01 => {
+                self.waveform_select = data & 0x20 != 0;
+            }
+            0x02 => {
+                self.timer1_value = data;
+            }
+            0x03 => {
+                self.timer2_value = data;
+            }
+            0x04 => {
+                if data & TIMER_CTRL_IRQ_RESET != 0 {
+                    // IRQ reset clears the status flags. All other bits are ignored.
+                    self.status = 0;
+                    return
+                }
+                self.timer1_masked = data & TIMER_CTRL_T1_MASK != 0;
+                self.timer2_masked = data & TIMER_CTRL_T2_MASK != 0;
+
+                let t1_start = data & TIMER_CTRL_T1_START != 0;
+                if t1_start && !self.timer1_running {
+                    self.timer1_count = self.timer1_value as u16;
+                    self.timer1_accum = 0.0;
+                }
+                self.timer1_running = t1_start;
+
+                let t2_start = data & TIMER_CTRL_T2_START != 0;
+                if t2_start && !self.timer2_running {
+                    self.timer2_count = self.timer2_value as u16;
+                    self.timer2_accum = 0.0;
+                }
+                self.timer2_running = t2_start;
+            }
+            0x08 => {
+                self.note_select = data & 0x40 != 0;
+            }
+            0x20..=0x35 => {
+                if let Some(op) = AdLibCard::operator_index(reg) {
+                    let op = &mut self.operators[op];
+                    op.tremolo = data & 0x80 != 0;
+                    op.vibrato = data & 0x40 != 0;
+                    op.sustained = data & 0x20 != 0;
+                    op.ksr = data & 0x10 != 0;
+                    op.mult = data & 0x0F;
+                }
+            }
+            0x40..=0x55 => {
+                if let Some(op) = AdLibCard::operator_index(reg) {
+                    let op = &mut self.operators[op];
+                    op.ksl = data >> 6;
+                    op.total_level = data & 0x3F;
+                }
+            }
+            0x60..=0x75 => {
+                if let Some(op) = AdLibCard::operator_index(reg) {
+                    let op = &mut self.operators[op];
+                    op.attack_rate = data >> 4;
+                    op.decay_rate = data & 0x0F;
+                }
+            }
+            0x80..=0x95 => {
+                if let Some(op) = AdLibCard::operator_index(reg) {
+                    let op = &mut self.operators[op];
+                    op.sustain_level = data >> 4;
+                    op.release_rate = data & 0x0F;
+                }
+            }
+            0xA0..=0xA8 => {
+                let ch = &mut self.channels[(reg - 0xA0) as usize];
+                ch.fnum = (ch.fnum & 0x300) | data as u16;
+            }
+            0xB0..=0xB8 => {
+                let ch_idx = (reg - 0xB0) as usize;
+                let ch = &mut self.channels[ch_idx];
+                ch.fnum = (ch.fnum & 0xFF) | ((data as u16 & 0x03) << 8);
+                ch.block = (data >> 2) & 0x07;
+                ch.key_on = data & 0x20 != 0;
+
+                let key_on = ch.key_on;
+                let (m, c) = CHANNEL_OPERATORS[ch_idx];
+                self.operators[m].set_key(key_on);
+                self.operators[c].set_key(key_on);
+            }
+            0xBD => {
+                self.am_depth = data & 0x80 != 0;
+                self.vib_depth = data & 0x40 != 0;
+                self.rhythm_mode = data & 0x20 != 0;
+
+                if self.rhythm_mode {
+                    self.rhythm_keys = data & 0x1F;
+                    // Bass drum uses both operators of channel 6
+                    let bd = data & 0x10 != 0;
+                    self.operators[12].set_key(bd || self.channels[6].key_on);
+                    self.operators[15].set_key(bd || self.channels[6].key_on);
+                    // Snare drum: channel 7 carrier
+                    self.operators[16].set_key(data & 0x08 != 0 || self.channels[7].key_on);
+                    // Tom-tom: channel 8 modulator
+                    self.operators[14].set_key(data & 0x04 != 0 || self.channels[8].key_on);
+                    // Top cymbal: channel 8 carrier
+                    self.operators[17].set_key(data & 0x02 != 0 || self.channels[8].key_on);
+                    // Hi-hat: channel 7 modulator
+                    self.operators[13].set_key(data & 0x01 != 0 || self.channels[7].key_on);
+                }
+                else {
+                    self.rhythm_keys = 0;
+                }
+            }
+            0xC0..=0xC8 => {
+                let ch = &mut self.channels[(reg - 0xC0) as usize];
+                ch.feedback = (data >> 1) & 0x07;
+                ch.additive = data & 0x01 != 0;
+            }
+            0xE0..=0xF5 => {
+                if let Some(op) = AdLibCard::operator_index(reg) {
+                    self.operators[op].waveform = data & 0x03;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Run the AdLib's timers for the specified number of microseconds.
+    pub fn run(&mut self, us: f64) {
+        if self.timer1_running {
+            self.timer1_accum += us;
+            while self.timer1_accum >= TIMER1_TICK_US {
+                self.timer1_accum -= TIMER1_TICK_US;
+                self.timer1_count += 1;
+                if self.timer1_count > 0xFF {
+                    self.timer1_count = self.timer1_value as u16;
+                    if !self.timer1_masked {
+                        self.status |= STATUS_IRQ | STATUS_T1_FLAG;
+                    }
+                }
+            }
+        }
+
+        if self.timer2_running {
+            self.timer2_accum += us;
+            while self.timer2_accum >= TIMER2_TICK_US {
+                self.timer2_accum -= TIMER2_TICK_US;
+                self.timer2_count += 1;
+                if self.timer2_count > 0xFF {
+                    self.timer2_count = self.timer2_value as u16;
+                    if !self.timer2_masked {
+                        self.status |= STATUS_IRQ | STATUS_T2_FLAG;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Generate the next output sample at the configured sample rate.
+    pub fn generate_sample(&mut self) -> f32 {
+
+        // Advance the LFOs
+        self.lfo_am_phase = (self.lfo_am_phase + TREMOLO_FREQ / self.sample_rate).fract();
+        self.lfo_vib_phase = (self.lfo_vib_phase + VIBRATO_FREQ / self.sample_rate).fract();
+
+        let am_range = if self.am_depth { 4.8 } else { 1.0 };
+        let vib_cents = if self.vib_depth { 14.0 } else { 7.0 };
+
+        let ctx = SampleContext {
+            rate: self.sample_rate,
+            tremolo_db: am_range * 0.5 * (1.0 - f32::cos(self.lfo_am_phase * 2.0 * PI)),
+            vibrato_factor: f32::powf(2.0, vib_cents * f32::sin(self.lfo_vib_phase * 2.0 * PI) / 1200.0),
+            waveform_select: self.waveform_select,
+            note_select: self.note_select,
+        };
+
+        let melodic_channels = if self.rhythm_mode { 6 } else { OPL_NUM_CHANNELS };
+        let mut sample = 0.0;
+
+        for c in 0..melodic_channels {
+            sample += self.generate_channel(c, &ctx);
+        }
+
+        if self.rhythm_mode {
+            sample += self.generate_rhythm(&ctx);
+        }
+
+        sample / OPL_NUM_CHANNELS as f32
+    }
+
+    fn generate_channel(&mut self, c: usize, ctx: &SampleContext) -> f32 {
+        let ch = self.channels[c];
+        let (m, car) = CHANNEL_OPERATORS[c];
+
+        if self.operators[m].is_silent() && self.operators[car].is_silent() {
+            return 0.0
+        }
+
+        let fb = self.operators[m].feedback(ch.feedback);
+        let mod_out = self.operators[m].generate(&ch, ctx, fb);
+
+        if ch.additive {
+            mod_out + self.operators[car].generate(&ch, ctx, 0.0)
+        }
+        else {
+            // Modulator output at full scale modulates the carrier by +/- 4 cycles (8 pi)
+            self.operators[car].generate(&ch, ctx, mod_out * 4.0)
+        }
+    }
+
+    fn generate_rhythm(&mut self, ctx: &SampleContext) -> f32 {
+        let mut rng = rand::thread_rng();
+        let mut sample = 0.0;
+
+        // Bass drum is a normal two-operator voice on channel 6.
+        sample += self.generate_channel(6, ctx) * 2.0;
+
+        let ch7 = self.channels[7];
+        let ch8 = self.channels[8];
+
+        // Hi-hat and snare drum on channel 7, tom-tom and cymbal on channel 8. Each operator
+        // produces its own output. Noise is mixed in for the metallic percussion voices.
+        let noise: f32 = rng.gen_range(-1.0..1.0);
+
+        let hh = self.operators[13].generate(&ch7, ctx, 0.0);
+        let sd = self.operators[16].generate(&ch7, ctx, 0.0);
+        let tom = self.operators[14].generate(&ch8, ctx, 0.0);
+        let cy = self.operators[17].generate(&ch8, ctx, 0.0);
+
+        sample += (hh.abs() * noise) * 2.0;
+        sample += (sd + sd.abs() * noise) * 2.0;
+        sample += tom * 2.0;
+        sample += (cy.abs() * noise) * 2.0;
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::Microseconds(0.0);
+
+    fn write_reg(adlib: &mut AdLibCard, reg: u8, data: u8) {
+        adlib.write_u8(ADLIB_ADDRESS_PORT, reg, None, NO_DELTA);
+        adlib.write_u8(ADLIB_DATA_PORT, data, None, NO_DELTA);
+    }
+
+    #[test]
+    fn test_status_after_reset() {
+        let mut adlib = AdLibCard::new(44100);
+
+        // Reset both timers and the IRQ, as AdLib detection routines do
+        write_reg(&mut adlib, 0x04, 0x60);
+        write_reg(&mut adlib, 0x04, 0x80);
+
+        let status = adlib.read_u8(ADLIB_ADDRESS_PORT, NO_DELTA);
+        assert_eq!(status & 0xE0, 0x00);
+        assert_eq!(status & STATUS_OPL2_ID, 0x06);
+        assert_eq!(adlib.read_u8(ADLIB_DATA_PORT, NO_DELTA), 0xFF);
+    }
+
+    #[test]
+    fn test_timer_overflow_flags() {
+        let mut adlib = AdLibCard::new(44100);
+
+        // Start timer 1 one tick from overflow, with timer 2 masked and stopped
+        write_reg(&mut adlib, 0x02, 0xFF);
+        write_reg(&mut adlib, 0x04, 0x21);
+        adlib.run(TIMER1_TICK_US / 2.0);
+        assert_eq!(adlib.read_u8(ADLIB_ADDRESS_PORT, NO_DELTA) & 0xE0, 0x00);
+        adlib.run(TIMER1_TICK_US);
+        assert_eq!(adlib.read_u8(ADLIB_ADDRESS_PORT, NO_DELTA) & 0xE0, STATUS_IRQ | STATUS_T1_FLAG);
+
+        // IRQ reset clears the flags; a masked timer 2 does not set them again
+        write_reg(&mut adlib, 0x04, 0x80);
+        write_reg(&mut adlib, 0x03, 0xFF);
+        write_reg(&mut adlib, 0x04, 0x22);
+        adlib.run(TIMER2_TICK_US * 4.0);
+        assert_eq!(adlib.read_u8(ADLIB_ADDRESS_PORT, NO_DELTA) & 0xE0, 0x00);
+
+        // Unmasked timer 2 overflows
+        write_reg(&mut adlib, 0x04, 0x02);
+        adlib.run(TIMER2_TICK_US * 2.0);
+        assert_eq!(adlib.read_u8(ADLIB_ADDRESS_PORT, NO_DELTA) & 0xE0, STATUS_IRQ | STATUS_T2_FLAG);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::cga::font.rs
+
+    Character font handling for the CGA. The card has a single character ROM 
+    containing two 8x8 fonts, selected by a jumper: the default thick font and
+    a thinner single-dot font. A ROM dump can be loaded to use either font, or
+    a custom character set.
+
+*/
+
+use crate::config::CgaFontSelect;
+use crate::devices::cga::{CGA_FONT, CGA_FONT_SPAN, tablegen::*};
+
+/// Size of a single 8x8 font of 256 glyphs.
+pub const CGA_FONT_SIZE: usize = 2048;
+/// Size of the IBM character ROM (5788005), shared by the MDA and CGA.
+pub const CGA_FONT_ROM_SIZE: usize = 8192;
+
+// Offsets of the two CGA fonts within the character ROM. The MDA font occupies the first half.
+const CGA_FONT_ROM_THIN_OFFSET: usize = 0x1000;
+const CGA_FONT_ROM_THICK_OFFSET: usize = 0x1800;
+
+/// An 8x8 CGA font along with its unpacked glyph tables. The font is stored in span layout,
+/// where each byte is one row of a glyph and each glyph row spans all 256 characters.
+pub struct CgaFont {
+    data: Vec<u8>,
+    hires_table: Box<[[u64; 8]; 256]>,
+    lowres_table: Box<[[[u64; 8]; 2]; 256]>,
+}
+
+impl CgaFont {
+
+    /// The built-in thick font.
+    pub fn builtin() -> Self {
+        Self {
+            data: CGA_FONT.to_vec(),
+            hires_table: Box::new(CGA_HIRES_GLYPH_TABLE),
+            lowres_table: Box::new(CGA_LOWRES_GLYPH_TABLE),
+        }
+    }
+
+    /// Create a font from a character ROM dump. A full 8K character ROM contains both fonts, 
+    /// and 'select' picks between them. A 2K dump contains a single font, which is used 
+    /// regardless of 'select'. Glyphs in a dump are stored sequentially, 8 bytes per glyph.
+    pub fn from_rom(rom: &[u8], select: CgaFontSelect) -> Result<Self, String> {
+        let font = match rom.len() {
+            CGA_FONT_ROM_SIZE => {
+                let offset = match select {
+                    CgaFontSelect::Thick => CGA_FONT_ROM_THICK_OFFSET,
+                    CgaFontSelect::Thin => CGA_FONT_ROM_THIN_OFFSET,
+                };
+                &rom[offset..offset + CGA_FONT_SIZE]
+            }
+            CGA_FONT_SIZE => rom,
+            len => {
+                return Err(format!(
+                    "Invalid CGA font ROM size: {} bytes. Expected {} or {} bytes.", 
+                    len, 
+                    CGA_FONT_ROM_SIZE, 
+                    CGA_FONT_SIZE
+                ));
+            }
+        };
+
+        // Convert from glyph-sequential layout to span layout
+        let mut data = vec![0; CGA_FONT_SIZE];
+        for (glyph, rows) in font.chunks_exact(8).enumerate() {
+            for (row, byte) in rows.iter().enumerate() {
+                data[row * CGA_FONT_SPAN + glyph] = *byte;
+            }
+        }
+
+        Ok(Self {
+            hires_table: Box::new(cga_hires_glyph_table(&data)),
+            lowres_table: Box::new(cga_lowres_glyph_table(&data)),
+            data,
+        })
+    }
+
+    /// The raw font data, in span layout.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Return the bit value at (col,row) of the given glyph.
+    #[inline]
+    pub fn glyph_bit(&self, glyph: u8, col: u8, row: u8) -> bool {
+        let glyph_offset: usize = ((row & 0x07) as usize * CGA_FONT_SPAN) + glyph as usize;
+        self.data[glyph_offset] & (0x01 << (7 - col)) != 0
+    }
+
+    /// Return the unpacked hires drawing value for one row of a glyph.
+    #[inline]
+    pub fn hires_glyph_row(&self, glyph: usize, row: usize) -> u64 {
+        self.hires_table[glyph][row]
+    }
+
+    /// Return the unpacked, pixel-doubled lowres drawing value for one half (0 = left, 
+    /// 1 = right) of a glyph row.
+    #[inline]
+    pub fn lowres_glyph_row(&self, glyph: usize, half: usize, row: usize) -> u64 {
+        self.lowres_table[glyph][half][row]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a ROM with a non-repeating byte pattern, so layout conversion can be checked.
+    fn test_rom(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_font_rom_select() {
+        let rom = test_rom(CGA_FONT_ROM_SIZE);
+
+        let thick = CgaFont::from_rom(&rom, CgaFontSelect::Thick).unwrap();
+        let thin = CgaFont::from_rom(&rom, CgaFontSelect::Thin).unwrap();
+
+        // Row 2 of glyph 'A' from each half of the CGA portion of the ROM
+        let glyph = b'A' as usize;
+        assert_eq!(thick.data()[2 * CGA_FONT_SPAN + glyph], rom[CGA_FONT_ROM_THICK_OFFSET + glyph * 8 + 2]);
+        assert_eq!(thin.data()[2 * CGA_FONT_SPAN + glyph], rom[CGA_FONT_ROM_THIN_OFFSET + glyph * 8 + 2]);
+        assert_ne!(thick.data(), thin.data());
+    }
+
+    #[test]
+    fn test_font_tables() {
+        let mut rom = vec![0; CGA_FONT_SIZE];
+        // Glyph 1, row 3: leftmost and rightmost pixels set
+        rom[8 + 3] = 0b1000_0001;
+        let font = CgaFont::from_rom(&rom, CgaFontSelect::Thin).unwrap();
+
+        assert!(font.glyph_bit(1, 0, 3));
+        assert!(!font.glyph_bit(1, 1, 3));
+        assert!(font.glyph_bit(1, 7, 3));
+        assert_eq!(font.hires_glyph_row(1, 3), 0xFF00_0000_0000_00FF);
+        assert_eq!(font.lowres_glyph_row(1, 0, 3), 0x0000_0000_0000_FFFF);
+        assert_eq!(font.lowres_glyph_row(1, 1, 3), 0xFFFF_0000_0000_0000);
+
+        assert!(CgaFont::from_rom(&rom[..1000], CgaFontSelect::Thick).is_err());
+    }
+}
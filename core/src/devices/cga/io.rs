@@ -73,6 +73,7 @@ impl IoDevice for CGACard {
                     0
                 }            
                 CGA_STATUS_REGISTER => {
+                    self.reset_gate_array_flipflop();
                     self.handle_status_register_read()
                 }
                 _ => {
@@ -104,13 +105,20 @@ impl IoDevice for CGACard {
                 CGA_COLOR_CONTROL_REGISTER => {
                     self.handle_cc_register_write(data);
                 }
+                CGA_STATUS_REGISTER => {
+                    // The PCjr's gate array registers are written through the status port.
+                    self.handle_gate_array_write(data);
+                }
+                PCJR_PAGE_REGISTER => {
+                    self.handle_page_register_write(data);
+                }
                 _ => {}
             }
         }
     }
 
     fn port_list(&self) -> Vec<u16> {
-        vec![
+        let mut ports = vec![
             CRTC_REGISTER_SELECT0,
             CRTC_REGISTER0,
             CRTC_REGISTER_SELECT1,
@@ -121,7 +129,12 @@ impl IoDevice for CGACard {
             CGA_COLOR_CONTROL_REGISTER,
             CGA_LIGHTPEN_REGISTER,
             CGA_STATUS_REGISTER,
-        ]
+        ];
+
+        if self.is_pcjr() {
+            ports.push(PCJR_PAGE_REGISTER);
+        }
+        ports
     }
 
 }
@@ -69,8 +69,7 @@ impl MemoryMappedDevice for CGACard {
 
     fn mmio_read_u8(&mut self, address: usize, cycles: u32) -> (u8, u32) {
 
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
-        if a_offset < CGA_MEM_SIZE {
+        if let Some(a_offset) = self.vram_offset(address) {
             // Read within memory range
             
             // Look up wait states given the last ticked clock cycle + elapsed cycles
@@ -97,8 +96,7 @@ impl MemoryMappedDevice for CGACard {
     }
 
     fn mmio_write_u8(&mut self, address: usize, byte: u8, cycles: u32) -> u32 {
-        let a_offset = (address & CGA_MEM_MASK) - CGA_MEM_ADDRESS;
-        if a_offset < CGA_MEM_SIZE {
+        if let Some(a_offset) = self.vram_offset(address) {
             self.mem[a_offset] = byte;
 
             // Look up wait states given the last ticked clock cycle + elapsed cycles
@@ -120,12 +118,11 @@ impl MemoryMappedDevice for CGACard {
         }
     }
 
-    fn mmio_read_u16(&mut self, address: usize, _cycles: u32) -> (u16, u32) {
+    fn mmio_read_u16(&mut self, address: usize, cycles: u32) -> (u16, u32) {
 
-        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, 0);
+        let (lo_byte, wait1) = MemoryMappedDevice::mmio_read_u8(self, address, cycles);
         let (ho_byte, wait2) = MemoryMappedDevice::mmio_read_u8(self, address + 1, 0);
 
-        log::warn!("Unsupported 16 bit read from VRAM");
         return ((ho_byte as u16) << 8 | lo_byte as u16, wait1 + wait2)
     }    
 
@@ -135,4 +132,9 @@ impl MemoryMappedDevice for CGACard {
         0
     }
 
+    fn mmio_ram_alias(&self, address: usize) -> Option<usize> {
+        // The PCjr's video memory is system RAM.
+        self.pcjr_ram_address(address)
+    }
+
 }
\ No newline at end of file
@@ -38,18 +38,24 @@
 use std::{
     collections::HashMap,
     path::Path,
-    convert::TryInto
+    convert::TryInto,
+    sync::Arc
 };
 
 use bytemuck;
 
 #[macro_use]
 mod io;
+mod font;
 mod mmio;
+mod pcjr;
 mod tablegen;
+mod state;
 mod videocard;
 
 use crate::devices::cga::tablegen::*;
+pub use crate::devices::cga::font::{CgaFont, CGA_FONT_SIZE, CGA_FONT_ROM_SIZE};
+pub use crate::devices::cga::pcjr::{PcjrGateArray, PCJR_RAM_SIZE, PCJR_PAGE_REGISTER};
 
 use crate::bus::{BusInterface, DeviceRunTimeUnit};
 use crate::config::VideoType;
@@ -155,8 +161,13 @@ const SCANLINE_HBLANK_US: f64 = 52.38095911;
 const CGA_HBLANK: f64 = 0.1785714;
 
 const CGA_DEFAULT_CURSOR_BLINK_RATE: f64 = 0.0625;
-const CGA_CURSOR_BLINK_RATE_CLOCKS: u32 = FRAME_TIME_CLOCKS * 8;
-const CGA_CURSOR_BLINK_RATE_US: f64 = FRAME_TIME_US * 8.0;
+
+// The CGA derives its blink signals from a counter clocked by the CRTC's vertical sync.
+// The cursor blinks at 1/16th of the field rate, and text with the blink attribute at 1/32nd.
+// The CRTC's slow blink cursor mode also blinks at 1/32nd.
+const CGA_CURSOR_BLINK_BIT: u8 = 0x08;
+const CGA_SLOW_BLINK_BIT: u8 = 0x10;
+const CGA_TEXT_BLINK_BIT: u8 = 0x10;
 
 const CGA_DEFAULT_CURSOR_FRAME_CYCLE: u32 = 8;
 
@@ -184,9 +195,7 @@ const STATUS_LIGHTPEN_TRIGGER_SET: u8   = 0b0000_0010;
 const STATUS_LIGHTPEN_SWITCH_STATUS: u8 = 0b0000_0100;
 const STATUS_VERTICAL_RETRACE: u8       = 0b0000_1000;
 
-// Include the standard 8x8 CGA font.
-// TODO: Support alternate font with thinner glyphs? It was normally not accessable except 
-// by soldering a jumper
+// Include the standard 8x8 CGA font. The thin font can be used by loading a character ROM dump.
 const CGA_FONT: &'static [u8] = include_bytes!("../../../../assets/cga_8by8.bin");
 const CGA_FONT_SPAN: usize = 256; // Font bitmap is 2048 bits wide (256 * 8 characters)
 
@@ -346,6 +355,7 @@ pub struct CGACard {
     mode_hires_gfx: bool,
     mode_hires_txt: bool,
     mode_blinking: bool,
+    color_burst: bool,          // Whether a color burst was generated for the front buffer
     cc_palette: usize,
     cc_altcolor: u8,
     cc_overscan_color: u8,
@@ -435,16 +445,20 @@ pub struct CGACard {
     vma_t: usize,                   // VMA' register - Video memory address temporary
     vmws: usize,                    // Video memory word size
     rba: usize,                     // Render buffer address
-    blink_state: bool,              // Used to control blinking of cursor and text with blink attribute
-    blink_accum_us: f64,            // Microsecond accumulator for blink state flipflop
-    blink_accum_clocks: u32,        // CGA Clock accumulator for blink state flipflop
+    blink_state: bool,              // Blink phase of text with blink attribute
+    cursor_blink_state: bool,       // Blink phase of cursor
+    blink_counter: u8,              // Vsync counter from which blink phases are derived
+    blink_frozen: bool,             // Hold the blink phases for stable screenshots
+    crtc_log: Option<Vec<(u32, u8, u8)>>, // Recorded CRTC writes as (scanline, register, value)
     accumulated_us: f64,
     ticks_advanced: u32,            // Number of ticks we have advanced mid-instruction via port or mmio access.
     pixel_clocks_owed: u32,
     ticks_accum: u32,
     clocks_accum: u32,
 
-    mem: Box<[u8; CGA_MEM_SIZE]>,
+    mem: Box<[u8]>,
+    pcjr: Option<PcjrGateArray>,    // Video gate array, present when emulating a PCjr
+    font: Arc<CgaFont>,             // Character font, shared with snapshot clones
 
     back_buf: usize,
     front_buf: usize,
@@ -528,6 +542,7 @@ impl CGACard {
             mode_hires_gfx: false,
             mode_hires_txt: true,
             mode_blinking: true,
+            color_burst: true,
             cc_palette: 0,
             cc_altcolor: 0,
             cc_overscan_color: 0,            
@@ -616,8 +631,10 @@ impl CGACard {
             vmws: 2,    
             rba: 0,
             blink_state: false,
-            blink_accum_us: 0.0,
-            blink_accum_clocks: 0,
+            cursor_blink_state: false,
+            blink_counter: 0,
+            blink_frozen: false,
+            crtc_log: None,
 
             accumulated_us: 0.0,
             ticks_advanced: 0,
@@ -625,7 +642,9 @@ impl CGACard {
             clocks_accum: 0,
             pixel_clocks_owed: 0,
 
-            mem: vec![0; CGA_MEM_SIZE].into_boxed_slice().try_into().unwrap(),
+            mem: vec![0; CGA_MEM_SIZE].into_boxed_slice(),
+            pcjr: None,
+            font: Arc::new(CgaFont::builtin()),
 
             back_buf: 1,
             front_buf: 0,
@@ -719,7 +738,7 @@ impl CGACard {
         if self.ticks_advanced % CGA_LCHAR_CLOCK as u32 > 0 {
             // We have advanced the CGA card out of phase with the character clock. Count 
             // how many pixel clocks we need to tick by to be back in phase.
-            ((!self.cycles + 1) & 0x0F) as u32
+            ((!self.cycles).wrapping_add(1) & 0x0F) as u32
         }
         else {
             0
@@ -728,7 +747,7 @@ impl CGACard {
     
     #[inline]
     fn calc_phase_offset(&mut self) -> u32 {
-        ((!self.cycles + 1) & 0x0F) as u32
+        ((!self.cycles).wrapping_add(1) & 0x0F) as u32
     }
 
     fn get_cursor_span(&self) -> (u8, u8) {
@@ -822,6 +841,9 @@ impl CGACard {
     fn handle_crtc_register_write(&mut self, byte: u8 ) {
 
         //log::debug!("CGA: Write to CRTC register: {:?}: {:02}", self.crtc_register_selected, byte );
+        if let Some(log) = &mut self.crtc_log {
+            log.push((self.scanline, self.crtc_register_select_byte, byte));
+        }
         match self.crtc_register_selected {
             CRTCRegister::HorizontalTotal => {
                 // (R0) 8 bit write only
@@ -903,6 +925,7 @@ impl CGACard {
                 }
 
                 self.update_cursor_data();
+                self.update_blink_state();
             }
             CRTCRegister::CursorEndLine => {
                 self.crtc_cursor_end_line = byte & CURSOR_LINE_MASK;
@@ -1027,6 +1050,11 @@ impl CGACard {
         // Attempt to update clock.
         self.update_clock();
 
+        // The PCjr's overscan color comes from the gate array's border register in all modes.
+        if let Some(ga) = &self.pcjr {
+            self.cc_overscan_color = ga.border_color();
+        }
+
         // Updated mask to exclude enable bit in mode calculation.
         // "Disabled" isn't really a video mode, it just controls whether
         // the CGA card outputs video at a given moment. This can be toggled on
@@ -1193,6 +1221,9 @@ impl CGACard {
     /// Swaps the front and back buffers by exchanging indices.
     fn swap(&mut self) {
 
+        // The BW bit of the mode register disables the color burst on the composite output.
+        self.color_burst = !self.mode_bw;
+
         //std::mem::swap(&mut self.back_buf, &mut self.front_buf);
         
         if self.back_buf == 0 {
@@ -1208,15 +1239,16 @@ impl CGACard {
     }    
 
     /// Return the bit value at (col,row) of the given font glyph
-    fn get_glyph_bit(glyph: u8, col: u8, row: u8) -> bool {
+    fn get_glyph_bit(&self, glyph: u8, col: u8, row: u8) -> bool {
 
         debug_assert!(col < CGA_HCHAR_CLOCK);
         //debug_assert!(row < CRTC_CHAR_CLOCK);
-        let row_masked = row & 0x7;
+        self.font.glyph_bit(glyph, col, row)
+    }
 
-        // Calculate byte offset 
-        let glyph_offset: usize = (row_masked as usize * CGA_FONT_SPAN) + glyph as usize;
-        CGA_FONT[glyph_offset] & (0x01 << (7 - col)) != 0
+    /// Replace the character font. The font is shared between clones of the card.
+    pub fn set_font(&mut self, font: CgaFont) {
+        self.font = Arc::new(font);
     }
 
     /// Set the character attributes for the current character.
@@ -1224,9 +1256,12 @@ impl CGACard {
     fn set_char_addr(&mut self) {
 
         // Address from CRTC is masked by 0x1FFF by the CGA card (bit 13 ignored) and doubled.
-        let addr = (self.vma & CGA_TEXT_MODE_WRAP) << 1;
+        let addr = match &self.pcjr {
+            Some(ga) => ga.text_addr(self.vma),
+            None => (self.vma & CGA_TEXT_MODE_WRAP) << 1
+        };
 
-        if addr < CGA_MEM_SIZE - 1 {
+        if addr < self.mem.len() - 1 {
             self.cur_char = self.mem[addr];
             self.cur_attr = self.mem[addr + 1];
     
@@ -1243,6 +1278,12 @@ impl CGACard {
                 self.cur_bg = self.cur_attr >> 4;
                 self.cur_blink = false;
             }
+
+            // The PCjr maps attribute colors through its palette registers.
+            if let Some(ga) = &self.pcjr {
+                self.cur_fg = ga.color(self.cur_fg);
+                self.cur_bg = ga.color(self.cur_bg);
+            }
         }
         else {
             log::warn!("Character read out of range!");
@@ -1314,7 +1355,7 @@ impl CGACard {
     /// Draw a single character glyph column pixel in text mode, doubling the pixel if 
     /// in 40 column mode.
     pub fn draw_text_mode_pixel(&mut self) {
-        let mut new_pixel = match CGACard::get_glyph_bit(self.cur_char, self.char_col, self.vlc_c9) {
+        let mut new_pixel = match self.get_glyph_bit(self.cur_char, self.char_col, self.vlc_c9) {
             true => {
                 if self.cur_blink {
                     if self.blink_state { self.cur_fg } else { self.cur_bg }
@@ -1327,7 +1368,7 @@ impl CGACard {
         };
 
         // Do cursor
-        if (self.vma == self.crtc_cursor_address) && self.cursor_status && self.blink_state {
+        if (self.vma == self.crtc_cursor_address) && self.cursor_status && self.cursor_blink_state {
             // This cell has the cursor address, cursor is enabled and not blinking
             if self.cursor_data[(self.vlc_c9 & 0x1F) as usize] {
                 new_pixel = self.cur_fg;
@@ -1355,7 +1396,7 @@ impl CGACard {
             CGA_COLORS_U64[self.cur_bg as usize]
         }
         else {
-            let glyph_row_base = CGA_HIRES_GLYPH_TABLE[glyph & 0xFF][row];
+            let glyph_row_base = self.font.hires_glyph_row(glyph & 0xFF, row);
 
             // Combine glyph mask with foreground and background colors.
             glyph_row_base & CGA_COLORS_U64[self.cur_fg as usize] | !glyph_row_base & CGA_COLORS_U64[self.cur_bg as usize]
@@ -1372,8 +1413,8 @@ impl CGACard {
             (glyph, glyph)
         }
         else {
-            let glyph_row_base_0 = CGA_LOWRES_GLYPH_TABLE[glyph & 0xFF][0][row];
-            let glyph_row_base_1 = CGA_LOWRES_GLYPH_TABLE[glyph & 0xFF][1][row];
+            let glyph_row_base_0 = self.font.lowres_glyph_row(glyph & 0xFF, 0, row);
+            let glyph_row_base_1 = self.font.lowres_glyph_row(glyph & 0xFF, 1, row);
 
             // Combine glyph mask with foreground and background colors.
             let glyph0 = glyph_row_base_0 & CGA_COLORS_U64[self.cur_fg as usize] | !glyph_row_base_0 & CGA_COLORS_U64[self.cur_bg as usize];
@@ -1391,14 +1432,14 @@ impl CGACard {
         // Do cursor if visible, enabled and defined
         if     self.vma == self.crtc_cursor_address
             && self.cursor_status 
-            && self.blink_state
+            && self.cursor_blink_state
             && self.cursor_data[(self.vlc_c9 & 0x1F) as usize] 
         {
             self.draw_solid_char(self.cur_fg);
         }
         else if self.mode_enable {
             for i in (0..draw_span).step_by(self.clock_divisor as usize) {
-                let new_pixel = match CGACard::get_glyph_bit(self.cur_char, (i as u8 / self.clock_divisor), self.vlc_c9) {
+                let new_pixel = match self.get_glyph_bit(self.cur_char, (i as u8 / self.clock_divisor), self.vlc_c9) {
                     true => {
                         if self.cur_blink {
                             if self.blink_state { self.cur_fg } else { self.cur_bg }
@@ -1432,7 +1473,7 @@ impl CGACard {
         // Do cursor if visible, enabled and defined
         if     self.vma == self.crtc_cursor_address
             && self.cursor_status 
-            && self.blink_state
+            && self.cursor_blink_state
             && self.cursor_data[(self.vlc_c9 & 0x1F) as usize] 
         {
             self.draw_solid_hchar(self.cur_fg);
@@ -1460,7 +1501,7 @@ impl CGACard {
         // Do cursor if visible, enabled and defined
         if     self.vma == self.crtc_cursor_address
             && self.cursor_status 
-            && self.blink_state
+            && self.cursor_blink_state
             && self.cursor_data[(self.vlc_c9 & 0x1F) as usize] 
         {
             self.draw_solid_lchar(self.cur_fg);
@@ -1668,6 +1709,9 @@ impl CGACard {
     /// CRTC is set. This effectively creates a 0x2000 byte offset for odd character rows.
     #[inline]
     pub fn get_gfx_addr(&self, row: u8) -> usize {
+        if let Some(ga) = &self.pcjr {
+            return ga.gfx_addr(self.vma, row)
+        }
         let row_offset = if (row & 0x01) != 0 { 0x1000 } else { 0 };
         let addr = (self.vma & 0x0FFF | row_offset) << 1;
         addr 
@@ -1791,6 +1835,9 @@ impl CGACard {
                 if !self.mode_graphics {
                     self.draw_text_mode_hchar();
                 }
+                else if self.pcjr.is_some() {
+                    self.draw_pcjr_gfx_char();
+                }
                 else if self.mode_hires_gfx {
                     self.draw_hires_gfx_mode_char();
                 }
@@ -1871,6 +1918,9 @@ impl CGACard {
                 if !self.mode_graphics {
                     self.draw_text_mode_lchar();
                 }
+                else if self.pcjr.is_some() {
+                    self.draw_pcjr_gfx_char();
+                }
                 else if self.mode_hires_gfx {
                     self.draw_hires_gfx_mode_char();
                 }
@@ -1980,6 +2030,9 @@ impl CGACard {
                 if !self.mode_graphics {
                     self.draw_text_mode_pixel();
                 }
+                else if self.pcjr.is_some() {
+                    self.draw_pcjr_gfx_pixel();
+                }
                 else if self.mode_hires_gfx {
                     self.draw_hires_gfx_mode_pixel();
                 }   
@@ -2243,6 +2296,7 @@ impl CGACard {
                     trace_regs!(self);
                     trace!(self, "Entering vsync");
                     self.in_crtc_vblank = true;
+                    self.tick_blink();
                     self.in_display_area = false;
                 }
             }
@@ -2281,6 +2335,25 @@ impl CGACard {
         }   
     }
 
+    /// Advance the blink counter on CRTC vertical sync and update the blink phases of the 
+    /// cursor and blinking text.
+    pub fn tick_blink(&mut self) {
+        if !self.blink_frozen {
+            self.blink_counter = self.blink_counter.wrapping_add(1);
+        }
+        self.update_blink_state();
+    }
+
+    /// Derive the cursor and text blink phases from the blink counter.
+    pub fn update_blink_state(&mut self) {
+        let cursor_bit = match self.cursor_slowblink {
+            true => CGA_SLOW_BLINK_BIT,
+            false => CGA_CURSOR_BLINK_BIT
+        };
+        self.cursor_blink_state = self.blink_counter & cursor_bit != 0;
+        self.blink_state = self.blink_counter & CGA_TEXT_BLINK_BIT != 0;
+    }
+
     pub fn do_vsync(&mut self) {
 
         self.cycles_per_vsync = self.cur_screen_cycles;
@@ -0,0 +1,392 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::cga::pcjr.rs
+
+    Implementation of the video gate array of the IBM PCjr.
+
+    The PCjr pairs a CGA-compatible MC6845 with a gate array that adds a 16
+    entry palette, a border color register and 16 color graphics modes. It 
+    has no memory of its own: the CRTC displays, and the CPU window at B8000
+    maps, 16K pages of the first 128K of system RAM as selected by the page
+    register at 3DFh. The card keeps its own copy of that RAM which the bus
+    updates on every write.
+
+    Gate array registers are written through port 3DAh, alternating between
+    an address and a data write. Reading the status register at 3DAh resets
+    the flip-flop to expect an address.
+
+*/
+
+use crate::devices::cga::*;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+pub const PCJR_RAM_SIZE: usize = 0x20000;
+pub const PCJR_PAGE_SIZE: usize = 0x4000;
+pub const PCJR_PAGE_REGISTER: u16 = 0x3DF;
+
+const GA_MODE_CONTROL1: u8 = 0x00;
+const GA_PALETTE_MASK: u8 = 0x01;
+const GA_BORDER_COLOR: u8 = 0x02;
+const GA_MODE_CONTROL2: u8 = 0x03;
+const GA_PALETTE_BASE: u8 = 0x10;
+const GA_ADDRESS_MASK: u8 = 0x1F;
+
+const MC1_HIGH_BANDWIDTH: u8    = 0b0000_0001;
+const MC1_GRAPHICS: u8          = 0b0000_0010;
+const MC1_BW: u8                = 0b0000_0100;
+const MC1_ENABLE: u8            = 0b0000_1000;
+const MC1_16_COLOR: u8          = 0b0001_0000;
+
+const MC2_BLINK: u8             = 0b0000_0010;
+const MC2_2_COLOR: u8           = 0b0000_1000;
+
+const PAGE_MASK: u8 = 0b0000_0111;
+const PAGE_CPU_SHIFT: u8 = 3;
+const PAGE_ADDRESS_MODE_SHIFT: u8 = 6;
+// An address mode of 11b selects the 32K graphics modes, interleaving four banks 
+// of video memory and ignoring the low bit of both page numbers.
+const PAGE_ADDRESS_MODE_32K: u8 = 0b11;
+
+#[derive (Copy, Clone, Default)]
+pub struct PcjrGateArray {
+    address_latched: bool,
+    address: u8,
+    mode_control1: u8,
+    palette_mask: u8,
+    border_color: u8,
+    mode_control2: u8,
+    palette: [u8; 16],
+    page_register: u8,
+}
+
+impl PcjrGateArray {
+    pub fn new() -> Self {
+        let mut palette = [0; 16];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+
+        Self {
+            palette_mask: 0x0F,
+            palette,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn four_banks(&self) -> bool {
+        self.page_register >> PAGE_ADDRESS_MODE_SHIFT == PAGE_ADDRESS_MODE_32K
+    }
+
+    #[inline]
+    fn page_base(&self, page: u8) -> usize {
+        let page = if self.four_banks() { page & !0x01 } else { page };
+        page as usize * PCJR_PAGE_SIZE
+    }
+
+    /// Return the base address of the page displayed by the CRTC.
+    #[inline]
+    pub fn crt_base(&self) -> usize {
+        self.page_base(self.page_register & PAGE_MASK)
+    }
+
+    /// Return the base address of the page mapped into the CPU window at B8000.
+    #[inline]
+    pub fn cpu_base(&self) -> usize {
+        self.page_base((self.page_register >> PAGE_CPU_SHIFT) & PAGE_MASK)
+    }
+
+    /// The CPU window covers 32K in the four bank modes; otherwise a 16K page is mirrored.
+    #[inline]
+    fn cpu_window_mask(&self) -> usize {
+        if self.four_banks() { 0x7FFF } else { 0x3FFF }
+    }
+
+    /// Calculate the address of a character in text mode.
+    #[inline]
+    pub fn text_addr(&self, vma: usize) -> usize {
+        self.crt_base() + ((vma & CGA_TEXT_MODE_WRAP) << 1)
+    }
+
+    /// Calculate the address of a character in graphics mode. As on the CGA, the row counter 
+    /// selects the bank, but the 32K modes use the low two bits of the row to select between 
+    /// four banks.
+    #[inline]
+    pub fn gfx_addr(&self, vma: usize, row: u8) -> usize {
+        let bank_mask = if self.four_banks() { 0x03 } else { 0x01 };
+        self.crt_base() + (((vma & 0x0FFF) << 1) | ((row as usize & bank_mask) << 13))
+    }
+
+    /// Look up the color for a palette register index after applying the palette mask.
+    #[inline]
+    pub fn color(&self, index: u8) -> u8 {
+        self.palette[(index & self.palette_mask) as usize] & 0x0F
+    }
+
+    pub fn border_color(&self) -> u8 {
+        self.border_color & 0x0F
+    }
+
+    /// Translate the mode control registers into the equivalent CGA mode register value, 
+    /// which drives the clock and text/graphics selection shared with the CGA.
+    pub fn cga_mode_byte(&self) -> u8 {
+        let mut byte = 0;
+        if self.mode_control1 & MC1_HIGH_BANDWIDTH != 0 {
+            byte |= MODE_HIRES_TEXT;
+        }
+        if self.mode_control1 & MC1_GRAPHICS != 0 {
+            byte |= MODE_GRAPHICS;
+        }
+        if self.mode_control1 & MC1_BW != 0 {
+            byte |= MODE_BW;
+        }
+        if self.mode_control1 & MC1_ENABLE != 0 {
+            byte |= MODE_ENABLE;
+        }
+        if self.mode_control2 & MC2_2_COLOR != 0 {
+            byte |= MODE_HIRES_GRAPHICS;
+        }
+        if self.mode_control2 & MC2_BLINK != 0 {
+            byte |= MODE_BLINKING;
+        }
+        byte
+    }
+
+    /// Gate array registers are appended to the CGA's state section in PCjr mode.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.address_latched);
+        w.write_u8(self.address);
+        w.write_u8(self.mode_control1);
+        w.write_u8(self.palette_mask);
+        w.write_u8(self.border_color);
+        w.write_u8(self.mode_control2);
+        w.write_bytes(&self.palette);
+        w.write_u8(self.page_register);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.address_latched = r.read_bool()?;
+        self.address = r.read_u8()?;
+        self.mode_control1 = r.read_u8()?;
+        self.palette_mask = r.read_u8()?;
+        self.border_color = r.read_u8()?;
+        self.mode_control2 = r.read_u8()?;
+        r.read_into(&mut self.palette)?;
+        self.page_register = r.read_u8()?;
+        Ok(())
+    }
+}
+
+impl CGACard {
+
+    /// Configure the card as the video subsystem of a PCjr. The card's memory becomes a 
+    /// copy of the 128K of system RAM it shares with the CPU.
+    pub fn set_pcjr_mode(&mut self) {
+        self.pcjr = Some(PcjrGateArray::new());
+        self.mem = vec![0; PCJR_RAM_SIZE].into_boxed_slice();
+    }
+
+    pub fn is_pcjr(&self) -> bool {
+        self.pcjr.is_some()
+    }
+
+    /// Clear the card's copy of system RAM when the bus clears memory.
+    pub fn clear_pcjr_ram(&mut self) {
+        if self.pcjr.is_some() {
+            self.mem.fill(0);
+        }
+    }
+
+    /// Translate a bus address into an offset into the card's memory, if the card decodes it.
+    pub fn vram_offset(&self, address: usize) -> Option<usize> {
+        match &self.pcjr {
+            Some(ga) => {
+                if address < PCJR_RAM_SIZE {
+                    Some(address)
+                }
+                else if address >= CGA_MEM_ADDRESS && address < CGA_MEM_ADDRESS + CGA_MEM_APERTURE {
+                    Some((ga.cpu_base() + (address & ga.cpu_window_mask())) & (PCJR_RAM_SIZE - 1))
+                }
+                else {
+                    None
+                }
+            }
+            None => {
+                let a_offset = (address & CGA_MEM_MASK).wrapping_sub(CGA_MEM_ADDRESS);
+                if a_offset < CGA_MEM_SIZE { Some(a_offset) } else { None }
+            }
+        }
+    }
+
+    /// Return the system RAM address that a write to the specified bus address must also 
+    /// update. Only applies in PCjr mode, where video memory is system RAM.
+    pub fn pcjr_ram_address(&self, address: usize) -> Option<usize> {
+        match self.pcjr {
+            Some(_) => self.vram_offset(address),
+            None => None
+        }
+    }
+
+    /// Handle a write to the gate array through the status register port.
+    pub fn handle_gate_array_write(&mut self, data: u8) {
+        let mut mode_changed = false;
+
+        if let Some(ga) = &mut self.pcjr {
+            if !ga.address_latched {
+                ga.address = data & GA_ADDRESS_MASK;
+                ga.address_latched = true;
+                return
+            }
+            ga.address_latched = false;
+
+            match ga.address {
+                GA_MODE_CONTROL1 => {
+                    ga.mode_control1 = data;
+                    mode_changed = true;
+                }
+                GA_PALETTE_MASK => ga.palette_mask = data & 0x0F,
+                GA_BORDER_COLOR => ga.border_color = data & 0x0F,
+                GA_MODE_CONTROL2 => {
+                    ga.mode_control2 = data;
+                    mode_changed = true;
+                }
+                reg if reg >= GA_PALETTE_BASE => {
+                    ga.palette[(reg - GA_PALETTE_BASE) as usize] = data & 0x0F;
+                }
+                reg => {
+                    log::trace!("PCjr: Write to unknown gate array register {:02X}: {:02X}", reg, data);
+                }
+            }
+            self.cc_overscan_color = ga.border_color();
+        }
+
+        if mode_changed {
+            if let Some(ga) = &self.pcjr {
+                let mode_byte = ga.cga_mode_byte();
+                self.handle_mode_register(mode_byte);
+            }
+        }
+    }
+
+    /// Reading the status register resets the gate array flip-flop to expect an address.
+    pub fn reset_gate_array_flipflop(&mut self) {
+        if let Some(ga) = &mut self.pcjr {
+            ga.address_latched = false;
+        }
+    }
+
+    pub fn handle_page_register_write(&mut self, data: u8) {
+        if let Some(ga) = &mut self.pcjr {
+            ga.page_register = data;
+            log::trace!("PCjr: Page register: CRT base: {:05X} CPU base: {:05X}", ga.crt_base(), ga.cpu_base());
+        }
+    }
+
+    /// Decode the two bytes of graphics data for the current character into palette-mapped 
+    /// colors. Returns the colors and the number of pixels decoded.
+    fn get_pcjr_gfx_pixels(&self, ga: &PcjrGateArray) -> ([u8; 16], usize) {
+        let addr = ga.gfx_addr(self.vma, self.vlc_c9);
+        let byte0 = self.mem[addr & (PCJR_RAM_SIZE - 1)];
+        let byte1 = self.mem[(addr + 1) & (PCJR_RAM_SIZE - 1)];
+        let word = (byte0 as u16) << 8 | byte1 as u16;
+        let mut pixels = [0; 16];
+
+        let count = if ga.mode_control1 & MC1_16_COLOR != 0 {
+            // Four bits per pixel, high nibble first.
+            for (i, pixel) in pixels.iter_mut().take(4).enumerate() {
+                *pixel = ga.color((word >> (12 - i * 4)) as u8 & 0x0F);
+            }
+            4
+        }
+        else if ga.mode_control2 & MC2_2_COLOR != 0 {
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = ga.color((word >> (15 - i)) as u8 & 0x01);
+            }
+            16
+        }
+        else if ga.mode_control1 & MC1_HIGH_BANDWIDTH != 0 {
+            // 640x200 4 color mode is planar: the even byte holds bit 0 of each pixel and 
+            // the odd byte bit 1.
+            for (i, pixel) in pixels.iter_mut().take(8).enumerate() {
+                let bit0 = (byte0 >> (7 - i)) & 0x01;
+                let bit1 = (byte1 >> (7 - i)) & 0x01;
+                *pixel = ga.color(bit1 << 1 | bit0);
+            }
+            8
+        }
+        else {
+            for (i, pixel) in pixels.iter_mut().take(8).enumerate() {
+                *pixel = ga.color((word >> (14 - i * 2)) as u8 & 0x03);
+            }
+            8
+        };
+
+        (pixels, count)
+    }
+
+    /// Draw an entire character row in a PCjr graphics mode (8 or 16 pixels)
+    pub fn draw_pcjr_gfx_char(&mut self) {
+        let span = CGA_HCHAR_CLOCK as usize * self.clock_divisor as usize;
+
+        match self.pcjr {
+            Some(ga) if self.mode_enable => {
+                let (pixels, count) = self.get_pcjr_gfx_pixels(&ga);
+                for i in 0..span {
+                    self.buf[self.back_buf][self.rba + i] = pixels[i * count / span];
+                }
+            }
+            _ => {
+                for i in 0..span {
+                    self.buf[self.back_buf][self.rba + i] = self.disable_color;
+                }
+            }
+        }
+    }
+
+    /// Draw the pixel for the current character column in a PCjr graphics mode, doubling 
+    /// the pixel if in a low bandwidth mode.
+    pub fn draw_pcjr_gfx_pixel(&mut self) {
+        let divisor = self.clock_divisor as usize;
+        let span = CGA_HCHAR_CLOCK as usize * divisor;
+
+        match self.pcjr {
+            Some(ga) if self.mode_enable => {
+                let (pixels, count) = self.get_pcjr_gfx_pixels(&ga);
+                for i in 0..divisor {
+                    let x = self.char_col as usize * divisor + i;
+                    self.buf[self.back_buf][self.rba + i] = pixels[x * count / span];
+                }
+            }
+            _ => {
+                for i in 0..divisor {
+                    self.buf[self.back_buf][self.rba + i] = self.disable_color;
+                }
+            }
+        }
+    }
+}
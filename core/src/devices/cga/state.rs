@@ -0,0 +1,124 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::cga::state.rs
+
+    Implements saving and restoring CGA state to a state file.
+
+    The CGA is restored by replaying writes to its registers, so that all 
+    derived state is recalculated. Beam position is not saved; the display 
+    resumes from the start of a frame.
+
+*/
+
+use crate::devices::cga::*;
+use crate::savestate::{SaveState, SaveStateError, StateMigration, StateReader, StateWriter};
+
+// Offset of the blink state in a version 1 section: CRTC registers (with length prefix), 
+// register select, mode and color control bytes, and frame count.
+const V1_BLINK_STATE_OFFSET: usize = 4 + 16 + 3 + 8;
+
+impl CGACard {
+    fn crtc_register_values(&self) -> [u8; 16] {
+        [
+            self.crtc_horizontal_total,
+            self.crtc_horizontal_displayed,
+            self.crtc_horizontal_sync_pos,
+            self.crtc_sync_width,
+            self.crtc_vertical_total,
+            self.crtc_vertical_total_adjust,
+            self.crtc_vertical_displayed,
+            self.crtc_vertical_sync_pos,
+            self.crtc_interlace_mode,
+            self.crtc_maximum_scanline_address,
+            self.crtc_cursor_start_line,
+            self.crtc_cursor_end_line,
+            self.crtc_start_address_ho,
+            self.crtc_start_address_lo,
+            self.crtc_cursor_address_ho,
+            self.crtc_cursor_address_lo,
+        ]
+    }
+}
+
+/// Version 2 replaced the blink state flag with the blink counter it is now derived from.
+fn migrate_v1(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut data = data.to_vec();
+    let blink_state = data.get_mut(V1_BLINK_STATE_OFFSET).ok_or("missing blink state")?;
+    *blink_state = if *blink_state != 0 { CGA_TEXT_BLINK_BIT } else { 0 };
+    Ok(data)
+}
+
+impl SaveState for CGACard {
+    const STATE_ID: &'static str = "cga";
+    const STATE_VERSION: u16 = 2;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.crtc_register_values());
+        w.write_u8(self.crtc_register_select_byte);
+        w.write_u8(self.mode_byte);
+        w.write_u8(self.cc_register);
+        w.write_u64(self.frame_count);
+        w.write_u8(self.blink_counter);
+        w.write_bytes(&self.mem[..]);
+        if let Some(ga) = &self.pcjr {
+            ga.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut crtc_regs = [0; 16];
+        r.read_into(&mut crtc_regs)?;
+        for (i, byte) in crtc_regs.iter().enumerate() {
+            self.handle_crtc_register_select(i as u8);
+            self.handle_crtc_register_write(*byte);
+        }
+        self.handle_crtc_register_select(r.read_u8()?);
+
+        // Apply the mode immediately instead of deferring it as a register write might.
+        self.mode_byte = r.read_u8()?;
+        self.mode_pending = false;
+        self.update_mode();
+
+        self.handle_cc_register_write(r.read_u8()?);
+        self.frame_count = r.read_u64()?;
+        self.blink_counter = r.read_u8()?;
+        self.update_blink_state();
+        r.read_into(&mut self.mem[..])?;
+        if let Some(ga) = &mut self.pcjr {
+            ga.load_state(r)?;
+            let mode_byte = ga.cga_mode_byte();
+            self.mode_byte = mode_byte;
+            self.update_mode();
+        }
+        Ok(())
+    }
+
+    fn state_migrations() -> &'static [StateMigration] {
+        &[StateMigration { from_version: 1, migrate: migrate_v1 }]
+    }
+}
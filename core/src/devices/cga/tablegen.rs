@@ -38,7 +38,12 @@ use crate::devices::cga::*;
 /// 64 bit color constants and then OR'd together to produce
 /// the final 64 bit drawing value for drawing by one entire
 /// character row.
-pub const CGA_HIRES_GLYPH_TABLE: [[u64; 8]; 256] = {
+pub const CGA_HIRES_GLYPH_TABLE: [[u64; 8]; 256] = cga_hires_glyph_table(CGA_FONT);
+
+/// Unpack a font in CGA span layout into a hires glyph table. This is a const fn so that the
+/// built-in font's table can be built at compile time, while fonts loaded from a ROM dump
+/// are unpacked at runtime.
+pub const fn cga_hires_glyph_table(font: &[u8]) -> [[u64; 8]; 256] {
 
     let mut table: [[u64; 8]; 256] = [[0; 8]; 256];
     
@@ -56,7 +61,7 @@ pub const CGA_HIRES_GLYPH_TABLE: [[u64; 8]; 256] = {
 
             loop {
                 let glyph_offset: usize = (row * CGA_FONT_SPAN) + glyph as usize;
-                let bit_val = CGA_FONT[glyph_offset] & (0x01 << (7 - bit)) != 0;
+                let bit_val = font[glyph_offset] & (0x01 << (7 - bit)) != 0;
 
                 if bit_val {
                     glyph_u64 |= (if bit_val { 0xFF } else { 0x00 }) << (bit * 8);
@@ -88,7 +93,7 @@ pub const CGA_HIRES_GLYPH_TABLE: [[u64; 8]; 256] = {
     }
 
     table
-};
+}
 
 /// Constant initializer to unpack the CGA font by glyph into 
 /// 8 rows of 64 bit values. These values are then AND'd with
@@ -99,7 +104,10 @@ pub const CGA_HIRES_GLYPH_TABLE: [[u64; 8]; 256] = {
 /// This version of the table splits each row up into two 
 /// columns of 8 pixels for drawing glyphs 8 pixels at a time
 /// in low-resolution mode.
-pub const CGA_LOWRES_GLYPH_TABLE: [[[u64; 8]; 2]; 256] = {
+pub const CGA_LOWRES_GLYPH_TABLE: [[[u64; 8]; 2]; 256] = cga_lowres_glyph_table(CGA_FONT);
+
+/// Unpack a font in CGA span layout into a lowres glyph table. See cga_hires_glyph_table().
+pub const fn cga_lowres_glyph_table(font: &[u8]) -> [[[u64; 8]; 2]; 256] {
 
     let mut table: [[[u64; 8]; 2]; 256] = [[[0; 8]; 2]; 256];
     
@@ -118,7 +126,7 @@ pub const CGA_LOWRES_GLYPH_TABLE: [[[u64; 8]; 2]; 256] = {
 
             loop {
                 let glyph_offset: usize = (row * CGA_FONT_SPAN) + glyph as usize;
-                let bit_val = CGA_FONT[glyph_offset] & (0x01 << (7 - bit)) != 0;
+                let bit_val = font[glyph_offset] & (0x01 << (7 - bit)) != 0;
 
                 if bit_val {
                     glyph_u64 |= (if bit_val { 0xFF } else { 0x00 }) << ((bit * 2) * 8);
@@ -140,7 +148,7 @@ pub const CGA_LOWRES_GLYPH_TABLE: [[[u64; 8]; 2]; 256] = {
 
             loop {
                 let glyph_offset: usize = (row * CGA_FONT_SPAN) + glyph as usize;
-                let bit_val = CGA_FONT[glyph_offset] & (0x01 << (3 - bit)) != 0;
+                let bit_val = font[glyph_offset] & (0x01 << (3 - bit)) != 0;
 
                 if bit_val {
                     glyph_u64 |= (if bit_val { 0xFF } else { 0x00 }) << ((bit * 2) * 8);
@@ -173,7 +181,7 @@ pub const CGA_LOWRES_GLYPH_TABLE: [[[u64; 8]; 2]; 256] = {
     }
 
     table
-};
+}
 
 /// Constant initializer to unpack all possible 8 bit patterns
 pub const CGA_8BIT_TABLE: [u64; 256] = {
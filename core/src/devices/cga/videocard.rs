@@ -141,6 +141,11 @@ impl VideoCard for CGACard {
         true
     }
 
+    /// Return whether a color burst was generated for the frame in the front buffer.
+    fn get_color_burst(&self) -> bool {
+        self.color_burst
+    }
+
     /// Return the u8 slice representing the front buffer of the device. (Direct rendering only)
     fn get_display_buf(&self) -> &[u8] {
         &self.buf[self.front_buf][..]
@@ -195,7 +200,7 @@ impl VideoCard for CGACard {
                     pos_y: (addr / 40) as u32,
                     line_start: self.crtc_cursor_start_line,
                     line_end: self.crtc_cursor_end_line,
-                    visible: self.get_cursor_status()
+                    visible: self.get_cursor_status() && self.cursor_blink_state
                 }
             }
             DisplayMode::Mode2TextBw80 | DisplayMode::Mode3TextCo80 => {
@@ -205,7 +210,7 @@ impl VideoCard for CGACard {
                     pos_y: (addr / 80) as u32,
                     line_start: self.crtc_cursor_start_line,
                     line_end: self.crtc_cursor_end_line,
-                    visible: self.get_cursor_status()
+                    visible: self.get_cursor_status() && self.cursor_blink_state
                 }
             }
             _=> {
@@ -226,11 +231,18 @@ impl VideoCard for CGACard {
         1
     }
 
-    fn get_current_font(&self) -> FontInfo {
+    fn get_blink_info(&self) -> BlinkInfo {
+        BlinkInfo {
+            enabled: self.mode_blinking,
+            visible: self.blink_state
+        }
+    }
+
+    fn get_current_font(&self) -> FontInfo<'_> {
         FontInfo {
             w: CGA_HCHAR_CLOCK as u32,
             h: CRTC_FONT_HEIGHT as u32,
-            font_data: CGA_FONT
+            font_data: self.font.data()
         }
     }
 
@@ -434,13 +446,6 @@ impl VideoCard for CGACard {
             }
             */
 
-            // Handle blinking. TODO: Move blink handling into tick().
-            self.blink_accum_clocks += self.char_clock;
-            if self.blink_accum_clocks > CGA_CURSOR_BLINK_RATE_CLOCKS {
-                self.blink_state = !self.blink_state;
-                self.blink_accum_clocks -= CGA_CURSOR_BLINK_RATE_CLOCKS;
-            }
-
             // Char clock may update after tick_char() with deferred mode change, so save the 
             // current clock.
             let old_char_clock = self.char_clock;
@@ -475,14 +480,55 @@ impl VideoCard for CGACard {
         0
     }
 
-    fn get_plane_slice(&self, _plane: usize) -> &[u8] {
-        &DUMMY_PLANE
+    fn get_plane_slice(&self, plane: usize) -> &[u8] {
+        // The CGA has no bitplanes; present its video memory as plane 0
+        match plane {
+            0 => &self.mem[..],
+            _ => &DUMMY_PLANE
+        }
     }
 
     fn get_frame_count(&self) -> u64 {
         self.frame_count
     }
 
+    fn set_blink_frozen(&mut self, frozen: bool) {
+        self.blink_frozen = frozen;
+    }
+
+    fn set_crtc_logging(&mut self, enabled: bool) {
+        self.crtc_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    fn take_crtc_log(&mut self) -> Vec<(u32, u8, u8)> {
+        match &mut self.crtc_log {
+            Some(log) => std::mem::take(log),
+            None => Vec::new()
+        }
+    }
+
+    fn get_text_screen(&self) -> Option<TextScreen> {
+        if self.mode_graphics {
+            return None
+        }
+
+        let columns = self.crtc_horizontal_displayed as u32;
+        let rows = self.crtc_vertical_displayed as u32;
+        let size = (columns * rows) as usize;
+        let mut chars = Vec::with_capacity(size);
+        let mut attrs = Vec::with_capacity(size);
+
+        // CRTC addresses are in words in text mode. VRAM wraps at 16K.
+        let base = self.get_start_address() as usize * 2;
+        let page = self.pcjr.map_or(0, |ga| ga.crt_base());
+        for i in 0..size {
+            chars.push(self.mem[page + ((base + i * 2) & (CGA_MEM_SIZE - 1))]);
+            attrs.push(self.mem[page + ((base + i * 2 + 1) & (CGA_MEM_SIZE - 1))]);
+        }
+
+        Some(TextScreen { columns, rows, chars, attrs })
+    }
+
     fn dump_mem(&self, path: &Path) {
 
         let mut filename = path.to_path_buf();
@@ -30,7 +30,8 @@
 
 */
 
-use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::bus::{BusInitiator, BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub const DMA_CHANNEL_0_ADDR_PORT: u16  = 0x00; // R/W
 pub const DMA_CHANNEL_0_WC_PORT: u16    = 0x01; // R/W
@@ -130,10 +131,11 @@ pub struct DMAChannel {
     terminal_count_reached: bool,
     request: bool,
     masked: bool,
-    page: u8
+    page: u8,
+    transfer_count: u64
 }
 
-#[derive (Default)]
+#[derive (Default, Debug)]
 pub struct DMAChannelStringState {
 
     pub current_address_reg: String,
@@ -148,10 +150,12 @@ pub struct DMAChannelStringState {
     pub terminal_count: String,
     pub terminal_count_reached: String,
     pub masked: String,
-    pub page: String
+    pub request: String,
+    pub page: String,
+    pub physical_address: String
 }
 
-#[derive (Default)]
+#[derive (Default, Debug)]
 pub struct DMAControllerStringState {
     pub enabled: String,
     pub flipflop: String,
@@ -173,7 +177,8 @@ pub struct DMAController {
     status_reg: u8,
     temp_reg: u8,
 
-    dreq: bool
+    dreq: bool,
+    request_counts: [u64; 4]
 }
 
 impl IoDevice for DMAController {
@@ -352,7 +357,8 @@ impl DMAController {
             status_reg: 0,
             temp_reg: 0,
 
-            dreq: false
+            dreq: false,
+            request_counts: [0; 4]
         }
     }
 
@@ -595,7 +601,7 @@ impl DMAController {
     pub fn get_string_state(&self) -> DMAControllerStringState {
 
         let mut chan_vec = Vec::new();
-        for chan in self.channels.iter() {
+        for (i, chan) in self.channels.iter().enumerate() {
 
             chan_vec.push(DMAChannelStringState{
                 current_address_reg: format!("{:04X}", chan.current_address_reg),
@@ -610,7 +616,9 @@ impl DMAController {
                 terminal_count: format!("{:?}", chan.terminal_count),
                 terminal_count_reached: format!("{:?}", chan.terminal_count_reached),
                 masked: format!("{:?}", chan.masked),
-                page: format!("{:02X}", chan.page)
+                request: format!("{:?}", self.request_reg & (0x01 << i) != 0),
+                page: format!("{:02X}", chan.page),
+                physical_address: format!("{:05X}", self.get_dma_transfer_address(i) & 0xFFFFF)
             });
         }
 
@@ -654,9 +662,26 @@ impl DMAController {
     /// Equivalent to setting the DREQ line high for the given DMA channel
     pub fn request_service(&mut self, channel: usize) {
 
+        if self.request_reg & (0x01 << channel) == 0 {
+            self.request_counts[channel] += 1;
+        }
         self.request_reg |= 0x01 << channel;
     }
 
+    /// Return the number of times each channel's DREQ line has been raised.
+    pub fn get_request_counts(&self) -> [u64; 4] {
+        self.request_counts
+    }
+
+    /// Return the number of bytes transferred on each channel, including verify transfers.
+    pub fn get_transfer_counts(&self) -> [u64; 4] {
+        let mut counts = [0; 4];
+        for (count, chan) in counts.iter_mut().zip(self.channels.iter()) {
+            *count = chan.transfer_count;
+        }
+        counts
+    }
+
     /// Clear DMA Service 
     /// Equivlaent to de-asserting the DREQ line for the given DMA channel
     pub fn clear_service(&mut self, channel: usize ) {
@@ -697,7 +722,8 @@ impl DMAController {
             AddressMode::Increment => {
                 if self.channels[channel].current_word_count_reg > 0 {
 
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+                    (data, _cost) = bus.read_u8_from(bus_address, 0, BusInitiator::Dma(channel as u8)).unwrap();
+                    self.channels[channel].transfer_count += 1;
                     
                     if self.channels[channel].current_word_count_reg == 1 {
                         //log::trace!("car: {} cwc: {} ", self.channels[channel].current_address_reg, self.channels[channel].current_word_count_reg);
@@ -712,7 +738,8 @@ impl DMAController {
                 else if self.channels[channel].current_word_count_reg == 0 && !self.channels[channel].terminal_count {
                     
                     // Transfer one more on a 0 count, then set TC
-                    (data, _cost) = bus.read_u8(bus_address, 0).unwrap();
+                    (data, _cost) = bus.read_u8_from(bus_address, 0, BusInitiator::Dma(channel as u8)).unwrap();
+                    self.channels[channel].transfer_count += 1;
 
                     //self.channels[channel].current_address_reg += 1;
 
@@ -753,8 +780,9 @@ impl DMAController {
                     
                     // Don't transfer anything if in Verify mode
                     if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
+                        bus.write_u8_from(bus_address, data, 0, BusInitiator::Dma(channel as u8)).unwrap();
                     }
+                    self.channels[channel].transfer_count += 1;
                     
                     self.channels[channel].current_address_reg = self.channels[channel].current_address_reg.wrapping_add(1);
                     self.channels[channel].current_word_count_reg -= 1;
@@ -765,8 +793,9 @@ impl DMAController {
                     
                     // Transfer one more on a 0 count, then set TC
                     if let TransferType::Write = self.channels[channel].transfer_type {
-                        bus.write_u8(bus_address, data, 0).unwrap();
+                        bus.write_u8_from(bus_address, data, 0, BusInitiator::Dma(channel as u8)).unwrap();
                     }
+                    self.channels[channel].transfer_count += 1;
                     //self.channels[channel].current_address_reg += 1;
 
                     //log::trace!("DMA write {:02X} to address: {:06X} CWC: {}", data, bus_address, self.channels[channel].current_word_count_reg);
@@ -826,4 +855,63 @@ impl DMAController {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for DMAController {
+    const STATE_ID: &'static str = "dma";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.command_register);
+        w.write_bool(self.enabled);
+        w.write_bool(self.flipflop);
+        w.write_u8(self.request_reg);
+        w.write_u8(self.status_reg);
+        w.write_u8(self.temp_reg);
+        w.write_bool(self.dreq);
+        for chan in &self.channels {
+            w.write_u16(chan.current_address_reg);
+            w.write_u16(chan.current_word_count_reg);
+            w.write_u16(chan.base_address_reg);
+            w.write_u16(chan.base_word_count_reg);
+            w.write_u8(chan.mode_reg);
+            w.write_bool(chan.terminal_count);
+            w.write_bool(chan.terminal_count_reached);
+            w.write_bool(chan.request);
+            w.write_bool(chan.masked);
+            w.write_u8(chan.page);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        // Decode the command and mode registers by writing them, then restore the remaining 
+        // state directly.
+        self.handle_command_register_write(r.read_u8()?);
+        self.enabled = r.read_bool()?;
+        self.flipflop = r.read_bool()?;
+        self.request_reg = r.read_u8()?;
+        self.status_reg = r.read_u8()?;
+        self.temp_reg = r.read_u8()?;
+        self.dreq = r.read_bool()?;
+        for i in 0..self.channels.len() {
+            let current_address_reg = r.read_u16()?;
+            let current_word_count_reg = r.read_u16()?;
+            let base_address_reg = r.read_u16()?;
+            let base_word_count_reg = r.read_u16()?;
+            let mode_reg = r.read_u8()?;
+            self.handle_channel_mode_register_write((mode_reg & !0x03) | i as u8);
+
+            let chan = &mut self.channels[i];
+            chan.current_address_reg = current_address_reg;
+            chan.current_word_count_reg = current_word_count_reg;
+            chan.base_address_reg = base_address_reg;
+            chan.base_word_count_reg = base_word_count_reg;
+            chan.terminal_count = r.read_bool()?;
+            chan.terminal_count_reached = r.read_bool()?;
+            chan.request = r.read_bool()?;
+            chan.masked = r.read_bool()?;
+            chan.page = r.read_u8()?;
+        }
+        Ok(())
+    }
+}
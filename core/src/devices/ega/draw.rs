@@ -0,0 +1,242 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    ega::draw.rs
+
+    Implement the EGA's beam timing and direct (clock-accurate) rendering.
+
+    The CRTC is stepped one character clock at a time. Each scanline is drawn
+    into the back buffer when the display enable period of that line ends,
+    using the register state in effect at that moment. This lets programs that 
+    reprogram the palette or CRTC mid-frame (copper bars, split screens) render 
+    as they would on real hardware.
+
+    Pixels are stored as 6-bit rgbRGB values, as output by the Attribute 
+    Controller. In 200-line modes the monitor only decodes four color lines, so 
+    these are translated to their 64-color equivalents.
+
+*/
+
+use crate::devices::ega::*;
+
+/// Max width and height of the EGA direct framebuffer. Scanlines and pixels outside
+/// these bounds are clipped.
+pub const EGA_MAX_FIELD_W: u32 = 1024;
+pub const EGA_MAX_FIELD_H: u32 = 512;
+
+const EGA_CLOCK0: f64 = 14.318180;
+const EGA_CLOCK1: f64 = 16.257;
+const EGA_CHAR_CLOCK: u32 = 8;
+
+// Cursor and text blink rates, as masks of the frame counter
+const EGA_CURSOR_BLINK_MASK: u64 = 0x07;
+const EGA_TEXT_BLINK_MASK: u64 = 0x0F;
+
+/// The 64-color equivalents of the 16 colors a 200-line monitor can display, indexed
+/// by IRGB. Color 6 is displayed as brown.
+const EGA_200_LINE_COLORS: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07,
+    0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F
+];
+
+impl EGACard {
+
+    /// Return the number of microseconds per character clock for the current clock 
+    /// select and dot clock.
+    pub(crate) fn us_per_char(&self) -> f64 {
+        let clock = match self.misc_output_register.clock_select() {
+            ClockSelect::Clock16 => EGA_CLOCK1,
+            _ => EGA_CLOCK0
+        };
+        let divisor = match self.sequencer_clocking_mode.dot_clock() {
+            DotClock::Native => 1.0,
+            DotClock::HalfClock => 2.0
+        };
+        (EGA_CHAR_CLOCK as f64 * divisor) / clock
+    }
+
+    /// Advance the CRTC by one character clock.
+    pub(crate) fn tick_char(&mut self) {
+
+        self.hcc += 1;
+
+        if self.hcc == self.crtc_horizontal_display_end as u32 + 1 {
+            // Display enable period of this scanline has ended. 
+            self.in_hblank = true;
+            if !self.in_vblank {
+                self.draw_scanline();
+            }
+        }
+
+        // The EGA's Horizontal Total register is programmed with the character count - 2
+        if self.hcc >= self.crtc_horizontal_total as u32 + 2 {
+            self.hcc = 0;
+            self.in_hblank = false;
+            self.end_scanline();
+        }
+    }
+
+    /// Advance the vertical counters at the end of a scanline.
+    fn end_scanline(&mut self) {
+
+        self.scanline += 1;
+
+        if self.row_scan >= self.crtc_maximum_scanline as u32 {
+            self.row_scan = 0;
+            self.row_start += self.crtc_offset as usize * 2;
+        }
+        else {
+            self.row_scan += 1;
+        }
+
+        // The Line Compare register resets the memory address counter to 0, splitting the screen
+        if self.scanline == self.crtc_line_compare as u32 {
+            self.row_start = 0;
+            self.row_scan = 0;
+        }
+
+        if self.scanline > self.crtc_vertical_total as u32 {
+            self.end_frame();
+        }
+
+        self.in_vblank = self.scanline > self.crtc_vertical_display_end as u32;
+        self.in_vretrace = self.scanline >= self.crtc_vertical_retrace_start as u32 
+            && self.scanline < self.crtc_vertical_retrace_end_norm as u32;
+    }
+
+    /// Swap the display buffers and reset the vertical counters at the end of a frame.
+    fn end_frame(&mut self) {
+
+        self.scanline = 0;
+        self.row_scan = self.crtc_preset_row_scan as u32 & 0x1F;
+        self.row_start = self.crtc_start_address as usize;
+        self.frame_count += 1;
+
+        if self.frame_count & EGA_CURSOR_BLINK_MASK == 0 {
+            self.cursor_status = !self.cursor_status;
+        }
+        if !self.blink_frozen && self.frame_count & EGA_TEXT_BLINK_MASK == 0 {
+            self.blink_state = !self.blink_state;
+        }
+
+        let (w, h) = self.get_display_size();
+        self.extents.field_w = EGA_MAX_FIELD_W;
+        self.extents.field_h = EGA_MAX_FIELD_H;
+        self.extents.aperture_w = w.min(EGA_MAX_FIELD_W);
+        self.extents.aperture_h = h.min(EGA_MAX_FIELD_H);
+        self.extents.visible_w = self.extents.aperture_w;
+        self.extents.visible_h = self.extents.aperture_h;
+        self.extents.row_stride = EGA_MAX_FIELD_W as usize;
+
+        std::mem::swap(&mut self.front_buf, &mut self.back_buf);
+    }
+
+    /// Translate a 4-bit color from the display memory through the Color Plane Enable and 
+    /// Palette registers into the 6-bit color sent to the monitor.
+    fn attribute_color(&self, color: u8) -> u8 {
+        let color = color & self.attribute_color_plane_enable.enable_plane();
+        let bits = self.attribute_palette_registers[color as usize] & 0x3F;
+
+        match self.misc_output_register.clock_select() {
+            // 200-line monitors decode bit 4 as intensity and ignore the secondary colors
+            ClockSelect::Clock14 => EGA_200_LINE_COLORS[((bits & 0x07) | (bits & 0x10) >> 1) as usize],
+            _ => bits
+        }
+    }
+
+    /// Return the 4-bit color of the specified dot of the current text row.
+    fn text_dot(&self, dot: usize) -> u8 {
+        let addr = (self.row_start + dot / EGA_CHAR_CLOCK as usize) & self.plane_mask;
+        let glyph = self.planes[0].buf[addr];
+        let attr = self.planes[1].buf[addr];
+
+        let mut fg = attr & 0x0F;
+        let mut bg = attr >> 4;
+        if let AttributeBlinkOrIntensity::Blink = self.attribute_mode_control.enable_blink_or_intensity() {
+            bg &= 0x07;
+            if attr & 0x80 != 0 && !self.blink_state {
+                fg = bg;
+            }
+        }
+
+        // Draw the cursor
+        if self.cursor_status 
+            && addr == self.get_cursor_address() as usize & self.plane_mask 
+            && self.row_scan >= self.crtc_cursor_start as u32
+            && self.row_scan <= self.crtc_cursor_end as u32 {
+            return fg;
+        }
+
+        let font = &EGA_FONTS[self.current_font];
+        let glyph_row = match self.row_scan < font.h {
+            true => font.data[self.row_scan as usize * font.span + glyph as usize],
+            false => 0
+        };
+
+        match glyph_row & (0x80 >> (dot % EGA_CHAR_CLOCK as usize)) != 0 {
+            true => fg,
+            false => bg
+        }
+    }
+
+    /// Return the 4-bit color of the specified dot of the current graphics scanline.
+    fn graphics_dot(&self, dot: usize) -> u8 {
+        let addr = (self.row_start + dot / 8) & self.plane_mask;
+        let bit = 7 - (dot % 8);
+
+        let mut color = 0;
+        for (i, plane) in self.planes.iter().enumerate() {
+            color |= (plane.buf[addr] >> bit & 0x01) << i;
+        }
+        color
+    }
+
+    /// Draw the current scanline into the back buffer. 
+    /// CGA-compatible graphics modes (4-6) are not yet supported.
+    fn draw_scanline(&mut self) {
+
+        if self.scanline >= EGA_MAX_FIELD_H {
+            return
+        }
+
+        let (w, _) = self.get_display_size();
+        let w = w.min(EGA_MAX_FIELD_W) as usize;
+        let pan = self.attribute_pel_panning as usize & 0x07;
+        let row = self.scanline as usize * EGA_MAX_FIELD_W as usize;
+
+        // Take the buffer so we can read the display planes while we draw
+        let mut buf = std::mem::take(&mut self.buf[self.back_buf]);
+
+        for x in 0..w {
+            let color = match self.attribute_mode_control.mode() {
+                AttributeMode::Text => self.text_dot(x + pan),
+                AttributeMode::Graphics => self.graphics_dot(x + pan)
+            };
+            buf[row + x] = self.attribute_color(color);
+        }
+
+        self.buf[self.back_buf] = buf;
+    }
+}
@@ -56,11 +56,13 @@ use crate::videocard::*;
 
 mod attribute_regs;
 mod crtc_regs;
+mod draw;
 mod graphics_regs;
 mod sequencer_regs;
 
 use attribute_regs::*;
 use crtc_regs::*;
+pub use draw::{EGA_MAX_FIELD_W, EGA_MAX_FIELD_H};
 use graphics_regs::*;
 use sequencer_regs::*;
 
@@ -73,6 +75,11 @@ pub const EGA_GFX_ADDRESS: usize = 0xA0000;
 pub const EGA_TEXT_PLANE_SIZE: usize = 16384;
 pub const EGA_GFX_PLANE_SIZE: usize = 65536;
 
+// Total video memory sizes supported by the EGA. The base card shipped with 64K,
+// expandable to 128K or 256K with the Graphics Memory Expansion Card.
+pub const EGA_MEMORY_SIZES: [usize; 3] = [65536, 131072, 262144];
+pub const EGA_DEFAULT_MEMORY_SIZE: usize = 262144;
+
 // For an EGA card connected to an EGA monitor
 // See http://www.minuszerodegrees.net/ibm_ega/ibm_ega_switch_settings.htm
 // This is inverted (Checkit will report 0110)
@@ -250,6 +257,19 @@ pub struct EGACard {
     cursor_frames: u32,
     in_hblank: bool,
     in_vblank: bool,
+    in_vretrace: bool,
+
+    // Direct rendering state
+    char_clock_accum: f64,
+    hcc: u32,                   // Horizontal character counter
+    row_scan: u32,              // Scanline within the current character row
+    row_start: usize,           // Display memory address of the current character row
+    frame_count: u64,
+    blink_state: bool,
+    blink_frozen: bool,
+    buf: [Vec<u8>; 2],
+    front_buf: usize,
+    back_buf: usize,
     
     cursor_status: bool,
     cursor_slowblink: bool,
@@ -330,6 +350,7 @@ pub struct EGACard {
 
     // Display Planes
     planes: [DisplayPlane; 4],
+    plane_mask: usize,
     pixel_buf: [u8; 8],
     pipeline_buf: [u8; 4],
     write_buf: [u8; 4]
@@ -527,6 +548,21 @@ impl EGACard {
             scanline_cycles: 0.0,
             in_hblank: false,
             in_vblank: false,
+            in_vretrace: false,
+
+            char_clock_accum: 0.0,
+            hcc: 0,
+            row_scan: 0,
+            row_start: 0,
+            frame_count: 0,
+            blink_state: false,
+            blink_frozen: false,
+            buf: [
+                vec![0; (EGA_MAX_FIELD_W * EGA_MAX_FIELD_H) as usize],
+                vec![0; (EGA_MAX_FIELD_W * EGA_MAX_FIELD_H) as usize]
+            ],
+            front_buf: 0,
+            back_buf: 1,
 
             cursor_status: false,
             cursor_slowblink: false,
@@ -610,6 +646,7 @@ impl EGACard {
                 DisplayPlane::new(),
                 DisplayPlane::new()
             ],
+            plane_mask: EGA_DEFAULT_MEMORY_SIZE / 4 - 1,
 
             pixel_buf: [0; 8],
             pipeline_buf: [0; 4],
@@ -633,6 +670,10 @@ impl EGACard {
         self.scanline_cycles = 0.0;
         self.in_hblank = false;
         self.in_vblank = false;
+        self.in_vretrace = false;
+        self.hcc = 0;
+        self.row_scan = 0;
+        self.row_start = 0;
 
         self.cursor_status = false;
         self.cursor_slowblink = false;
@@ -740,7 +781,7 @@ impl EGACard {
         if self.in_hblank || self.in_vblank {
             byte |= 0x01;
         }
-        if self.in_vblank {
+        if self.in_vretrace {
             byte |= 0x08;
         }
 
@@ -815,7 +856,29 @@ impl EGACard {
         //if self.crt
     }
 
+    /// Set the total amount of video memory installed on the card. Memory is divided evenly
+    /// between the four planes; with less than 256K installed, plane addresses wrap around.
+    pub fn set_memory_size(&mut self, size: usize) {
+        let size = match EGA_MEMORY_SIZES.contains(&size) {
+            true => size,
+            false => {
+                log::warn!("Unsupported EGA memory size: {}K. Using {}K.", size / 1024, EGA_DEFAULT_MEMORY_SIZE / 1024);
+                EGA_DEFAULT_MEMORY_SIZE
+            }
+        };
+        self.plane_mask = size / 4 - 1;
+    }
+
+    /// Return the total amount of video memory installed on the card.
+    pub fn memory_size(&self) -> usize {
+        (self.plane_mask + 1) * 4
+    }
+
     fn plane_bounds_check(&self, address: usize) -> Option<usize> {
+        self.plane_offset(address).map(|offset| offset & self.plane_mask)
+    }
+
+    fn plane_offset(&self, address: usize) -> Option<usize> {
 
         match self.graphics_micellaneous.memory_map() {
             MemoryMap::A0000_128k => {
@@ -992,7 +1055,7 @@ impl VideoCard for EGACard {
     }
 
     fn get_render_mode(&self) -> RenderMode {
-        RenderMode::Indirect
+        RenderMode::Direct
     }
 
     fn get_display_mode(&self) -> DisplayMode {
@@ -1018,23 +1081,30 @@ impl VideoCard for EGACard {
         (width, height)
     }
 
-    /// Unimplemented for indirect rendering.
     fn get_display_extents(&self) -> &DisplayExtents {
         &self.extents
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Return the position of the CRT beam in dots and scanlines.
     fn get_beam_pos(&self) -> Option<(u32, u32)> {
-        None
+        Some((self.hcc * EGA_FONTS[self.current_font].w, self.scanline))
     }
 
-    /// Unimplemented
+    /// Advance the CRTC by the specified number of character clocks.
     fn debug_tick(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick_char();
+        }
+    }
+
+    /// This adapter has no composite output.
+    fn get_color_burst(&self) -> bool {
+        false
     }
 
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32 {
-        0
+        self.scanline
     }
 
     /// Return whether to double scanlines produced by this adapter.
@@ -1043,19 +1113,18 @@ impl VideoCard for EGACard {
         false
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Return the front buffer. Pixels are 6-bit rgbRGB values.
     fn get_display_buf(&self) -> &[u8] {
-        &[0]
+        &self.buf[self.front_buf]
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Return the back buffer, which holds the frame currently being drawn.
     fn get_back_buf(&self) -> &[u8] {
-        &[0]
+        &self.buf[self.back_buf]
     }      
     
-    /// Unimplemented for indirect rendering.
     fn get_display_aperture(&self) -> (u32, u32) {
-        (0, 0)
+        (self.extents.aperture_w, self.extents.aperture_h)
     }
 
     fn get_overscan_color(&self) -> u8 {
@@ -1132,7 +1201,14 @@ impl VideoCard for EGACard {
         }
     }    
 
-    fn get_current_font(&self) -> FontInfo {
+    fn get_blink_info(&self) -> BlinkInfo {
+        BlinkInfo {
+            enabled: matches!(self.attribute_mode_control.enable_blink_or_intensity(), AttributeBlinkOrIntensity::Blink),
+            visible: self.blink_state
+        }
+    }
+
+    fn get_current_font(&self) -> FontInfo<'_> {
 
         let w = EGA_FONTS[self.current_font].w;
         let h = EGA_FONTS[self.current_font].h;
@@ -1348,6 +1424,20 @@ impl VideoCard for EGACard {
 
     fn run(&mut self, time: DeviceRunTimeUnit) {
 
+        let elapsed_us = if let DeviceRunTimeUnit::Microseconds(us) = time {
+            us
+        }
+        else {
+            panic!("EGA requires us time unit");
+        };
+
+        self.char_clock_accum += elapsed_us;
+
+        let us_per_char = self.us_per_char();
+        while self.char_clock_accum >= us_per_char {
+            self.tick_char();
+            self.char_clock_accum -= us_per_char;
+        }
     }
 
     /*
@@ -1440,6 +1530,7 @@ impl VideoCard for EGACard {
             read_offset = (y_offset + x_byte_offset + self.crtc_start_address as u32 ) as usize;
         }
         
+        let read_offset = read_offset & self.plane_mask;
         if read_offset < self.planes[0].buf.len() {
 
             for i in 0..4 {
@@ -1483,7 +1574,37 @@ impl VideoCard for EGACard {
     }
 
     fn get_frame_count(&self) -> u64 {
-        0
+        self.frame_count
+    }
+
+    fn set_blink_frozen(&mut self, frozen: bool) {
+        self.blink_frozen = frozen;
+    }
+
+    fn set_crtc_logging(&mut self, _enabled: bool) {
+        // Not implemented
+    }
+
+    fn take_crtc_log(&mut self) -> Vec<(u32, u8, u8)> {
+        Vec::new()
+    }
+
+    fn get_text_screen(&self) -> Option<TextScreen> {
+        if self.mode_graphics {
+            return None
+        }
+
+        let columns = if self.is_40_columns() { 40 } else { 80 };
+        let rows = self.get_display_size().1 / self.get_character_height().max(1) as u32;
+        let size = (columns * rows) as usize;
+
+        // Characters are stored in plane 0 and attributes in plane 1.
+        let base = self.get_start_address() as usize;
+        let plane_len = self.planes[0].buf.len();
+        let chars = (0..size).map(|i| self.planes[0].buf[(base + i) % plane_len]).collect();
+        let attrs = (0..size).map(|i| self.planes[1].buf[(base + i) % plane_len]).collect();
+
+        Some(TextScreen { columns, rows, chars, attrs })
     }
 
     fn write_trace_log(&mut self, msg: String) {
@@ -1732,4 +1853,37 @@ mod tests {
 
 
     }
+
+    #[test]
+    fn palette_change_mid_frame_is_drawn() {
+        let mut ega = EGACard::new();
+
+        // A tiny 80x10 graphics display, 12 characters by 20 scanlines in total.
+        ega.misc_output_register = EMiscellaneousOutputRegister::new().with_clock_select(ClockSelect::Clock16);
+        ega.attribute_mode_control = AModeControl::new().with_mode(AttributeMode::Graphics);
+        ega.attribute_color_plane_enable = AColorPlaneEnable::new().with_enable_plane(0x0F);
+        ega.crtc_horizontal_total = 10;
+        ega.crtc_horizontal_display_end = 9;
+        ega.crtc_vertical_total = 19;
+        ega.crtc_vertical_display_end = 9;
+        ega.crtc_maximum_scanline = 0;
+        ega.crtc_offset = 5;
+        ega.planes[0].buf.fill(0xFF);
+
+        // Change the palette halfway down the display
+        ega.attribute_palette_registers[1] = 0x01;
+        ega.debug_tick(5 * 12);
+        ega.attribute_palette_registers[1] = 0x04;
+        ega.debug_tick(15 * 12);
+
+        assert_eq!(ega.get_frame_count(), 1);
+        assert_eq!(ega.get_display_aperture(), (80, 10));
+
+        let buf = ega.get_display_buf();
+        let stride = ega.get_display_extents().row_stride;
+        assert_eq!(buf[0], 0x01);
+        assert_eq!(buf[4 * stride + 79], 0x01);
+        assert_eq!(buf[5 * stride], 0x04);
+        assert_eq!(buf[9 * stride + 79], 0x04);
+    }
 }
\ No newline at end of file
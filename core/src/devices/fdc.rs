@@ -30,6 +30,7 @@
 */
 #![allow(dead_code)]
 use std::collections::{VecDeque, HashMap};
+use std::path::PathBuf;
 use lazy_static::lazy_static;
 
 use crate::bus::{IoDevice, DeviceRunTimeUnit};
@@ -37,6 +38,10 @@ use crate::devices::{
     dma,
 };
 use crate::bus::BusInterface;
+use crate::config::FloppyDriveType;
+use crate::disk_activity::DriveActivity;
+use crate::floppy_image::{self, FloppyImage, SectorId};
+use crate::virtual_floppy::VirtualFloppy;
 
 pub const FDC_IRQ: u8 = 0x06;
 pub const FDC_DMA: usize = 2;
@@ -44,6 +49,9 @@ pub const FDC_MAX_DRIVES: usize = 4;
 pub const FORMAT_BUFFER_SIZE: usize = 4;
 pub const SECTOR_SIZE: usize = 512;
 
+// Default interval between writes of modified disk images back to the host, in milliseconds.
+pub const DEFAULT_FLUSH_INTERVAL: u32 = 1000;
+
 pub const FDC_DIGITAL_OUTPUT_REGISTER: u16 = 0x3F2;
 pub const FDC_STATUS_REGISTER: u16 = 0x3F4;
 pub const FDC_DATA_REGISTER: u16 = 0x3F5;
@@ -103,7 +111,7 @@ pub const ST0_HEAD_ACTIVE: u8   = 0b0000_0100;
 pub const ST0_NOT_READY: u8     = 0b0000_1000;
 pub const ST0_UNIT_CHECK: u8    = 0b0001_0000;
 pub const ST0_SEEK_END: u8      = 0b0010_0000;
-pub const ST0_ABNORMAL_TERMINATION: u8 = 0b01000_0000;
+pub const ST0_ABNORMAL_TERMINATION: u8 = 0b0100_0000;
 pub const ST0_INVALID_OPCODE: u8    = 0b1000_0000;
 pub const ST0_ABNORMAL_POLLING: u8  = 0b1100_0000;
 pub const ST0_RESET: u8             = 0b1100_0000;
@@ -111,6 +119,10 @@ pub const ST0_RESET: u8             = 0b1100_0000;
 pub const ST1_NO_ID: u8         = 0b0000_0001;
 pub const ST1_WRITE_PROTECT: u8 = 0b0000_0010;
 pub const ST1_NODATA: u8        = 0b0000_0100;
+pub const ST1_DATA_ERROR: u8    = 0b0010_0000;
+
+pub const ST2_DATA_ERROR: u8    = 0b0010_0000;
+pub const ST2_CONTROL_MARK: u8  = 0b0100_0000;
 
 
 pub const ST3_ESIG: u8          = 0b1000_0000;
@@ -121,6 +133,7 @@ pub const ST3_DOUBLESIDED: u8   = 0b0000_1000;
 pub const ST3_HEAD: u8          = 0b0000_0100;
 
 pub struct DiskFormat {
+    desc: &'static str,
     cylinders: u8,
     heads: u8,
     sectors: u8
@@ -132,6 +145,7 @@ lazy_static! {
             (
                 163_840, 
                 DiskFormat{
+                    desc: "160K",
                     cylinders: 40,
                     heads: 1,
                     sectors: 8
@@ -139,6 +153,7 @@ lazy_static! {
             ),(
                 184_320,
                 DiskFormat{
+                    desc: "180K",
                     cylinders: 40,
                     heads: 1,
                     sectors: 9
@@ -146,6 +161,7 @@ lazy_static! {
             ),(
                 327_680,
                 DiskFormat{
+                    desc: "320K",
                     cylinders: 40,
                     heads: 2,
                     sectors: 8
@@ -153,6 +169,7 @@ lazy_static! {
             ),( 
                 368_640,
                 DiskFormat{
+                    desc: "360K",
                     cylinders: 40,
                     heads: 2,
                     sectors: 9
@@ -160,6 +177,7 @@ lazy_static! {
             ),(
                 737_280,
                 DiskFormat{
+                    desc: "720K",
                     cylinders: 80,
                     heads: 2,
                     sectors: 9
@@ -167,6 +185,7 @@ lazy_static! {
             ),(
                 1_228_800,
                 DiskFormat{
+                    desc: "1.2M",
                     cylinders: 80,
                     heads: 2,
                     sectors: 15
@@ -174,6 +193,7 @@ lazy_static! {
             ),(
                 1_474_560,
                 DiskFormat{ 
+                    desc: "1.44M",
                     cylinders: 80,
                     heads: 2,
                     sectors: 18
@@ -184,6 +204,20 @@ lazy_static! {
     };
 }
 
+/// Return whether a drive of the specified type can read a disk image of the specified size.
+/// Images smaller than a single sided disk, such as boot sector images, can be read by any drive.
+fn drive_accepts_image(drive_type: FloppyDriveType, image_len: usize) -> bool {
+    if image_len < 163_840 {
+        return true
+    }
+    match drive_type {
+        FloppyDriveType::Floppy360K => image_len <= 368_640,
+        FloppyDriveType::Floppy12M => image_len <= 368_640 || image_len == 1_228_800,
+        FloppyDriveType::Floppy720K => image_len == 737_280,
+        FloppyDriveType::Floppy144M => image_len == 737_280 || image_len == 1_474_560,
+    }
+}
+
 /// Represent the state of the DIO bit of the Main Status Register in a readable way.
 pub enum IoMode {
     ToCpu,
@@ -191,7 +225,7 @@ pub enum IoMode {
 }
 
 /// Represent the various commands that the NEC FDC knows how to handle.
-#[derive (Clone, Copy, Debug)]
+#[derive (Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     NoCommand,
     ReadTrack,
@@ -209,6 +243,27 @@ pub enum Command {
     Invalid
 }
 
+impl Command {
+    /// Decode the command field of a command byte.
+    pub fn from_byte(byte: u8) -> Command {
+        match byte & COMMAND_MASK {
+            COMMAND_READ_TRACK => Command::ReadTrack,
+            COMMAND_WRITE_SECTOR => Command::WriteSector,
+            COMMAND_READ_SECTOR => Command::ReadSector,
+            COMMAND_WRITE_DELETED_SECTOR => Command::WriteDeletedSector,
+            COMMAND_READ_DELETED_SECTOR => Command::ReadDeletedSector,
+            COMMAND_FORMAT_TRACK => Command::FormatTrack,
+            COMMAND_FIX_DRIVE_DATA => Command::FixDriveData,
+            COMMAND_CHECK_DRIVE_STATUS => Command::CheckDriveStatus,
+            COMMAND_CALIBRATE_DRIVE => Command::CalibrateDrive,
+            COMMAND_SENSE_INT_STATUS => Command::SenseIntStatus,
+            COMMAND_READ_SECTOR_ID => Command::ReadSectorID,
+            COMMAND_SEEK_HEAD => Command::SeekParkHead,
+            _ => Command::Invalid
+        }
+    }
+}
+
 /// Represents the possible values of the Interrupt Code field in Status Register 0.
 /// Returning 'AbnormalTermination' may result in a General Failure reading drive 
 /// message in DOS.
@@ -233,6 +288,9 @@ pub enum DriveError {
     BadWrite,
     WriteProtect,
     DMAError,
+    NoAddressMark,
+    DataError,
+    DeletedData,
 }
 
 /// Classify operations - an Operation is intiated by any Command that does not immediately
@@ -260,7 +318,24 @@ pub struct DiskDrive {
     positioning: bool,
     have_disk: bool,
     write_protected: bool,
-    disk_image: Vec<u8>
+    /// Write protect requested by the user; applies to any disk inserted
+    write_protect_option: bool,
+    drive_type: Option<FloppyDriveType>,
+    disk_image: Vec<u8>,
+    /// Host file that changes to the disk image are written back to
+    image_path: Option<PathBuf>,
+    /// Disk image has been modified since it was last written back
+    dirty: bool,
+    /// Host directory the disk image was built from, which changes are written back to
+    virtual_floppy: Option<VirtualFloppy>,
+    /// Sector accesses and head steps since the activity was last taken
+    activity: DriveActivity,
+    /// Structured image for disks that can't be represented as raw sectors
+    sector_image: Option<FloppyImage>,
+    /// Index of the next sector ID to pass under the head, for Read Sector ID
+    id_index: usize,
+    /// State of the generator used to randomize weak bits
+    weak_rng: u32
 }
 
 impl DiskDrive {
@@ -278,7 +353,16 @@ impl DiskDrive {
             positioning: false,
             have_disk: false,
             write_protected: false,
+            write_protect_option: false,
+            drive_type: None,
             disk_image: Vec::new(),
+            image_path: None,
+            dirty: false,
+            virtual_floppy: None,
+            activity: DriveActivity::default(),
+            sector_image: None,
+            id_index: 0,
+            weak_rng: 0x1234_5678,
         }
     }
 }
@@ -305,6 +389,8 @@ pub struct FloppyController {
     command: Command,
     command_fn: Option<CommandDispatchFn>,
     last_command: Command,
+    received_command: Command,  // The most recent command byte received, for debugging
+    command_count: u64,
     receiving_command: bool,
     command_byte_n: u32,
     operation: Operation,
@@ -324,7 +410,15 @@ pub struct FloppyController {
 
     in_dma: bool,
     dma_byte_count: usize,
-    dma_bytes_left: usize
+    dma_bytes_left: usize,
+
+    // Sectors read from a structured image, and the result of the read
+    image_buffer: VecDeque<u8>,
+    image_read_error: DriveError,
+    image_read_id: SectorId,
+
+    flush_interval: f64,
+    flush_timer: f64
 }
 
 /// IO Port handlers for the FDC
@@ -386,6 +480,8 @@ impl FloppyController {
             command: Command::NoCommand,
             command_fn: None,
             last_command: Command::NoCommand,
+            received_command: Command::NoCommand,
+            command_count: 0,
             command_byte_n: 0,
             receiving_command: false,
             operation: Operation::NoOperation,
@@ -412,6 +508,13 @@ impl FloppyController {
             in_dma: false,
             dma_byte_count: 0,
             dma_bytes_left: 0,
+
+            image_buffer: VecDeque::new(),
+            image_read_error: DriveError::NoError,
+            image_read_id: SectorId::default(),
+
+            flush_interval: DEFAULT_FLUSH_INTERVAL as f64 * 1000.0,
+            flush_timer: 0.0,
         }
     }
 
@@ -459,6 +562,14 @@ impl FloppyController {
 
     }
 
+    /// Set the type of drive installed as the specified drive. A drive with no type set will 
+    /// accept any supported disk image.
+    pub fn set_drive_type(&mut self, drive_select: usize, drive_type: Option<FloppyDriveType>) {
+        if drive_select < FDC_MAX_DRIVES {
+            self.drives[drive_select].drive_type = drive_type;
+        }
+    }
+
     /// Load a disk into the specified drive
     pub fn load_image_from(&mut self, drive_select: usize, src_vec: Vec<u8>) -> Result<(), &'static str>  {
         
@@ -466,6 +577,14 @@ impl FloppyController {
             return Err("Invalid drive selection");
         }
 
+        if let Some(result) = floppy_image::parse_structured_image(&src_vec) {
+            let image = result.map_err(|e| {
+                log::error!("Failed to parse floppy image: {}", e);
+                "Invalid or unsupported floppy image"
+            })?;
+            return self.load_structured_image(drive_select, image);
+        }
+
         let image_len: usize = src_vec.len();
 
         // Disk images must contain whole sectors
@@ -473,6 +592,12 @@ impl FloppyController {
             return Err("Invalid image length")
         }
 
+        if let Some(drive_type) = self.drives[drive_select].drive_type {
+            if !drive_accepts_image(drive_type, image_len) {
+                return Err("Disk format not supported by drive type")
+            }
+        }
+
         // Look up disk parameters based on image size
         if let Some(fmt) = DISK_FORMATS.get(&image_len) {
             self.drives[drive_select].max_cylinders = fmt.cylinders;
@@ -495,7 +620,12 @@ impl FloppyController {
         }
 
         self.drives[drive_select].have_disk = true;
+        self.drives[drive_select].write_protected = self.drives[drive_select].write_protect_option;
         self.drives[drive_select].disk_image = src_vec;
+        self.drives[drive_select].sector_image = None;
+        self.drives[drive_select].image_path = None;
+        self.drives[drive_select].virtual_floppy = None;
+        self.drives[drive_select].dirty = false;
         log::debug!("Loaded floppy image, size: {} c: {} h: {} s: {}", 
             self.drives[drive_select].disk_image.len(),
             self.drives[drive_select].max_cylinders,
@@ -506,6 +636,55 @@ impl FloppyController {
         Ok(())
     }
 
+    /// Load a structured disk image into the specified drive. Images with a standard layout are 
+    /// converted to raw sector images so they can be saved; otherwise the sector metadata is kept
+    /// and the disk is treated as write protected.
+    fn load_structured_image(&mut self, drive_select: usize, image: FloppyImage) -> Result<(), &'static str> {
+
+        if let Some(raw) = image.to_raw() {
+            if DISK_FORMATS.contains_key(&raw.len()) {
+                log::debug!("{} image has standard layout, loading as raw sector image", image.image_type.desc());
+                return self.load_image_from(drive_select, raw);
+            }
+        }
+
+        if image.tracks.is_empty() {
+            return Err("Floppy image contains no readable tracks")
+        }
+
+        if let Some(drive_type) = self.drives[drive_select].drive_type {
+            let max_cylinders = match drive_type {
+                FloppyDriveType::Floppy360K => 42,
+                _ => 84
+            };
+            if image.cylinders > max_cylinders {
+                return Err("Disk format not supported by drive type")
+            }
+        }
+
+        let drive = &mut self.drives[drive_select];
+        drive.max_cylinders = image.cylinders;
+        drive.max_heads = image.heads;
+        drive.max_sectors = image.max_sectors();
+        drive.have_disk = true;
+        drive.write_protected = true;
+        drive.disk_image.clear();
+        drive.image_path = None;
+        drive.virtual_floppy = None;
+        drive.dirty = false;
+        drive.id_index = 0;
+
+        log::debug!("Loaded {} floppy image, c: {} h: {} max s: {}", 
+            image.image_type.desc(),
+            image.cylinders,
+            image.heads,
+            drive.max_sectors
+        );
+        drive.sector_image = Some(image);
+
+        Ok(())
+    }
+
     pub fn get_image_data(&self, drive_select: usize) -> Option<&[u8]> {
 
         if self.drives[drive_select].disk_image.len() > 0 {
@@ -517,8 +696,96 @@ impl FloppyController {
         }
     }
 
-    /// Unload (eject) the disk in the specified drive
+    /// Return a description of the format of the disk in the specified drive, if any.
+    pub fn get_media_desc(&self, drive_select: usize) -> Option<&'static str> {
+
+        let drive = self.drives.get(drive_select)?;
+        if !drive.have_disk {
+            return None
+        }
+        if let Some(image) = &drive.sector_image {
+            return Some(image.image_type.desc())
+        }
+        match DISK_FORMATS.get(&drive.disk_image.len()) {
+            Some(fmt) => Some(fmt.desc),
+            None => Some("Boot image")
+        }
+    }
+
+    /// Set the host file that changes to the disk image in the specified drive are written back 
+    /// to. If None, changes are kept in memory only.
+    pub fn set_image_path(&mut self, drive_select: usize, path: Option<PathBuf>) {
+        if drive_select < FDC_MAX_DRIVES {
+            self.drives[drive_select].image_path = path;
+        }
+    }
+
+    /// Set the host directory that the disk image in the specified drive was built from. Changes
+    /// to the disk are written back to the directory instead of to an image file.
+    pub fn set_virtual_floppy(&mut self, drive_select: usize, virtual_floppy: Option<VirtualFloppy>) {
+        if drive_select < FDC_MAX_DRIVES {
+            self.drives[drive_select].virtual_floppy = virtual_floppy;
+        }
+    }
+
+    /// Return the type of drive installed as the specified drive, if set.
+    pub fn drive_type(&self, drive_select: usize) -> Option<FloppyDriveType> {
+        self.drives.get(drive_select).and_then(|drive| drive.drive_type)
+    }
+
+    /// Set the write protect switch for the specified drive. Structured disk images are always
+    /// write protected.
+    pub fn set_write_protect(&mut self, drive_select: usize, state: bool) {
+        if drive_select < FDC_MAX_DRIVES {
+            let drive = &mut self.drives[drive_select];
+            drive.write_protect_option = state;
+            drive.write_protected = state || drive.sector_image.is_some();
+        }
+    }
+
+    pub fn is_write_protected(&self, drive_select: usize) -> bool {
+        match self.drives.get(drive_select) {
+            Some(drive) => drive.write_protected,
+            None => false
+        }
+    }
+
+    /// Set the interval at which modified disk images are written back to the host filesystem,
+    /// in milliseconds. An interval of 0 writes back after every command that modifies a disk.
+    pub fn set_flush_interval(&mut self, interval_ms: u32) {
+        self.flush_interval = interval_ms as f64 * 1000.0;
+    }
+
+    /// Write any modified disk images back to their host files.
+    pub fn flush(&mut self) {
+        for drive_select in 0..FDC_MAX_DRIVES {
+            self.flush_drive(drive_select);
+        }
+    }
+
+    fn flush_drive(&mut self, drive_select: usize) {
+        let drive = &mut self.drives[drive_select];
+        if !drive.dirty {
+            return
+        }
+        if let Some(virtual_floppy) = &mut drive.virtual_floppy {
+            match virtual_floppy.sync(&drive.disk_image) {
+                Ok(n) => log::debug!("Wrote {} changes for drive {} to {}", n, drive_select, virtual_floppy.dir().display()),
+                Err(e) => log::error!("Failed to write changes for drive {} to {}: {}", drive_select, virtual_floppy.dir().display(), e)
+            }
+        }
+        else if let Some(path) = &drive.image_path {
+            match std::fs::write(path, &drive.disk_image) {
+                Ok(()) => log::debug!("Wrote floppy image for drive {} to {}", drive_select, path.display()),
+                Err(e) => log::error!("Failed to write floppy image for drive {} to {}: {}", drive_select, path.display(), e)
+            }
+        }
+        drive.dirty = false;
+    }
+
+    /// Unload (eject) the disk in the specified drive. Any changes are written back first.
     pub fn unload_image(&mut self, drive_select: usize) {
+        self.flush_drive(drive_select);
         let drive = &mut self.drives[drive_select];
 
         drive.cylinder = 0;
@@ -528,7 +795,11 @@ impl FloppyController {
         drive.max_heads = 1;
         drive.max_sectors = 8;
         drive.have_disk = false;
+        drive.write_protected = drive.write_protect_option;
         drive.disk_image.clear();
+        drive.sector_image = None;
+        drive.image_path = None;
+        drive.virtual_floppy = None;
     }
 
     pub fn handle_status_register_read(&mut self) -> u8 {
@@ -563,6 +834,24 @@ impl FloppyController {
         msr_byte
     }
 
+    /// Move the head of the specified drive to the specified cylinder, counting the steps taken.
+    fn step_to(&mut self, drive_select: usize, cylinder: u8) {
+        let drive = &mut self.drives[drive_select];
+        drive.activity.record_seek(drive.cylinder as u16, cylinder as u16);
+        drive.cylinder = cylinder;
+    }
+
+    /// Return the activity of the specified drive since this was last called.
+    pub fn take_activity(&mut self, drive_select: usize) -> DriveActivity {
+        match self.drives.get_mut(drive_select) {
+            Some(drive) => {
+                drive.activity.motor_on = drive.motor_on;
+                drive.activity.take()
+            }
+            None => DriveActivity::default()
+        }
+    }
+
     pub fn motor_on(&mut self, drive_select: usize) {
         if self.drives[drive_select].have_disk {
             self.drives[drive_select].motor_on = true;
@@ -678,6 +967,15 @@ impl FloppyController {
             DriveError::BadRead | DriveError::BadWrite | DriveError::BadSeek => {
                 st1_byte |= ST1_NODATA
             }
+            DriveError::NoAddressMark => {
+                st1_byte |= ST1_NO_ID
+            }
+            DriveError::DataError => {
+                st1_byte |= ST1_DATA_ERROR
+            }
+            DriveError::WriteProtect => {
+                st1_byte |= ST1_WRITE_PROTECT
+            }
             _=> {}
         }
 
@@ -692,9 +990,13 @@ impl FloppyController {
 
     /// Generate the value of the ST2 Status Register in response to a command
     pub fn make_st2_byte(&self, _drive_select: usize) -> u8 {
-        // The ST2 status register contains mostly error codes, so for now we can just always return success
-        // by returning 0 until we handle possible errors.
-        0
+        // The ST2 status register contains mostly error codes. Only structured disk images can
+        // currently produce them.
+        match self.last_error {
+            DriveError::DataError => ST2_DATA_ERROR,
+            DriveError::DeletedData => ST2_CONTROL_MARK,
+            _ => 0
+        }
     }
 
     /// Generate the value of the ST3 Status Register in response to a command
@@ -751,6 +1053,16 @@ impl FloppyController {
         out_byte
     }    
 
+    /// Return the number of command bytes received since the controller was created.
+    pub fn get_command_count(&self) -> u64 {
+        self.command_count
+    }
+
+    /// Return the most recently received command.
+    pub fn get_received_command(&self) -> Command {
+        self.received_command
+    }
+
     pub fn set_command(&mut self, command: Command, n_bytes: u32, command_fn: CommandDispatchFn ) {
         // Since we are entering a new command, clear the previous error status
         self.last_error = DriveError::NoError;
@@ -798,6 +1110,9 @@ impl FloppyController {
         if !self.receiving_command { 
 
             let command = data & COMMAND_MASK;
+            self.received_command = Command::from_byte(command);
+            self.command_count += 1;
+
             match command {
                 COMMAND_READ_TRACK => {
                     log::trace!("Received Read Track command: {:02}", command);
@@ -839,6 +1154,7 @@ impl FloppyController {
                 }
                 COMMAND_READ_SECTOR_ID => {
                     log::trace!("Received Read Sector ID command: {:02}", command);
+                    self.set_command(Command::ReadSectorID, 1, FloppyController::command_read_sector_id);
                 }
                 COMMAND_SEEK_HEAD => {
                     log::trace!("Received Seek/Park Head command: {:02}", command);
@@ -1014,7 +1330,7 @@ impl FloppyController {
         self.drive_select = drive_select;
 
         // Set CHS
-        self.drives[drive_select].cylinder = 0;
+        self.step_to(drive_select, 0);
         self.drives[drive_select].head = head_select;
         self.drives[drive_select].sector = 1;
         
@@ -1046,7 +1362,7 @@ impl FloppyController {
         }
     
         // Set CHS to new seeked values
-        self.drives[drive_select].cylinder = cylinder;
+        self.step_to(drive_select, cylinder);
         self.drives[drive_select].head = head_select;
         self.drives[drive_select].sector = 1;
 
@@ -1091,8 +1407,12 @@ impl FloppyController {
             return Continuation::CommandComplete
         }
 
+        // Structured images may contain sector IDs that don't match the physical track, so we
+        // search the track for them during the read instead.
+        let structured = self.drives[drive_select].sector_image.is_some();
+
         // Is this read out of bounds?
-        if !self.is_id_valid(drive_select, cylinder, head, sector) {
+        if !structured && !self.is_id_valid(drive_select, cylinder, head, sector) {
             self.last_error = DriveError::BadRead;
             self.send_interrupt = true;
             log::warn!("command_read_sector: invalid chs: drive:{}, c:{} h:{} s:{}", 
@@ -1100,10 +1420,14 @@ impl FloppyController {
             return Continuation::CommandComplete;
         }
 
-        // "Seek" to values given in command
-        self.drives[drive_select].cylinder = cylinder;
+        // "Seek" to values given in command. For structured images, the physical cylinder
+        // is wherever the last seek left the head.
+        if !structured {
+            self.step_to(drive_select, cylinder);
+        }
         self.drives[drive_select].head = head;
         self.drives[drive_select].sector = sector;
+        self.drives[drive_select].activity.record_access();
         
         // Start read operation
         self.operation = Operation::ReadSector(cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
@@ -1142,6 +1466,59 @@ impl FloppyController {
         Continuation::ContinueAsOperation
     }
 
+    /// Perform the Read Sector ID Command
+    /// Returns the ID field of the next sector to pass under the head of the selected drive.
+    pub fn command_read_sector_id(&mut self) -> Continuation {
+
+        let drive_head_select = self.data_register_in.pop_front().unwrap();
+        let drive_select = (drive_head_select & 0x03) as usize;
+        let head_select = (drive_head_select >> 2) & 0x01;
+
+        self.drive_select = drive_select;
+
+        // Like Read Sector, let the command time out if there is no disk.
+        if !self.drives[drive_select].have_disk {
+            return Continuation::CommandComplete
+        }
+
+        let drive = &mut self.drives[drive_select];
+        drive.head = head_select;
+
+        let id = match &drive.sector_image {
+            Some(image) => {
+                image.track(drive.cylinder, head_select)
+                    .filter(|t| !t.sectors.is_empty())
+                    .map(|t| t.sectors[drive.id_index % t.sectors.len()].id)
+            }
+            None => {
+                Some(SectorId {
+                    c: drive.cylinder,
+                    h: head_select,
+                    r: (drive.id_index % drive.max_sectors.max(1) as usize) as u8 + 1,
+                    n: floppy_image::STANDARD_SIZE_CODE
+                })
+            }
+        };
+        drive.id_index = drive.id_index.wrapping_add(1);
+
+        log::trace!("command_read_sector_id: drive: {} head: {} id: {:?}", drive_select, head_select, id);
+
+        match id {
+            Some(id) => {
+                self.send_results_phase(InterruptCode::NormalTermination, drive_select, id.c, id.h, id.r, id.n);
+            }
+            None => {
+                // Unformatted track
+                let c = self.drives[drive_select].cylinder;
+                self.last_error = DriveError::NoAddressMark;
+                self.send_results_phase(InterruptCode::AbnormalTermination, drive_select, c, head_select, 0, 0);
+            }
+        }
+
+        self.send_interrupt = true;
+        Continuation::CommandComplete
+    }
+
     /// Perform the Write Sector Command
     pub fn command_write_sector(&mut self) -> Continuation {
 
@@ -1161,10 +1538,36 @@ impl FloppyController {
             log::warn!("command_write_sector: non-matching head specifiers");
         }
 
+        // Set drive_select for status register reads
+        self.drive_select = drive_select;
+
+        // Like Read Sector, let the command time out if there is no disk.
+        if !self.drives[drive_select].have_disk {
+            return Continuation::CommandComplete
+        }
+
+        // Structured images are always write protected.
+        if self.drives[drive_select].write_protected {
+            self.last_error = DriveError::WriteProtect;
+            self.send_results_phase(InterruptCode::AbnormalTermination, drive_select, cylinder, head, sector, sector_size);
+            self.send_interrupt = true;
+            return Continuation::CommandComplete
+        }
+
+        if !self.is_id_valid(drive_select, cylinder, head, sector) {
+            log::warn!("command_write_sector: invalid chs: drive:{}, c:{} h:{} s:{}", 
+                drive_select, cylinder, head, sector);
+            self.last_error = DriveError::BadWrite;
+            self.send_results_phase(InterruptCode::AbnormalTermination, drive_select, cylinder, head, sector, sector_size);
+            self.send_interrupt = true;
+            return Continuation::CommandComplete
+        }
+
         // Set CHS
-        self.drives[drive_select].cylinder = cylinder;
+        self.step_to(drive_select, cylinder);
         self.drives[drive_select].head = head;
         self.drives[drive_select].sector = sector;
+        self.drives[drive_select].activity.record_access();
 
         // Start write operation
         self.operation = Operation::WriteSector(cylinder, head, sector, sector_size, track_len, gap3_len, data_len);
@@ -1198,10 +1601,28 @@ impl FloppyController {
         let gap3_len = self.data_register_in.pop_front().unwrap();
         let fill_byte = self.data_register_in.pop_front().unwrap();
 
-        let _drive_select = (drive_head_select & 0x03) as usize;
-        let _head_select = (drive_head_select >> 2) & 0x01;
+        let drive_select = (drive_head_select & 0x03) as usize;
+        let head_select = (drive_head_select >> 2) & 0x01;
+
+        self.drive_select = drive_select;
+
+        // Like Read Sector, let the command time out if there is no disk.
+        if !self.drives[drive_select].have_disk {
+            return Continuation::CommandComplete
+        }
+
+        self.drives[drive_select].head = head_select;
+
+        if self.drives[drive_select].write_protected {
+            let cylinder = self.drives[drive_select].cylinder;
+            self.last_error = DriveError::WriteProtect;
+            self.send_results_phase(InterruptCode::AbnormalTermination, drive_select, cylinder, head_select, 1, sector_size);
+            self.send_interrupt = true;
+            return Continuation::CommandComplete
+        }
 
         // Start format operation
+        self.drives[drive_select].activity.record_access();
         self.operation_init = false;
         self.operation = Operation::FormatTrack(sector_size, track_len, gap3_len, fill_byte);
 
@@ -1289,7 +1710,7 @@ impl FloppyController {
         head: u8,
         sector: u8, 
         sector_size: u8, 
-        _track_len: u8 ) {
+        track_len: u8 ) {
 
         if !self.in_dma {
             log::error!("FDC in invalid state: ReadSector operation without DMA! Aborting.");
@@ -1314,6 +1735,10 @@ impl FloppyController {
             log::trace!("DMA destination address: {:05X}", dst_address);
 
             self.dma_bytes_left = xfer_sectors * SECTOR_SIZE;
+            if self.drives[self.drive_select].sector_image.is_some() {
+                self.read_image_sectors(cylinder, head, sector, track_len, self.dma_bytes_left);
+                self.dma_bytes_left = self.dma_bytes_left.min(self.image_buffer.len());
+            }
             self.operation_init = true;
         }
 
@@ -1321,7 +1746,18 @@ impl FloppyController {
             // Bytes left to transfer
 
             // Check if DMA is ready
-            if dma.check_dma_ready(FDC_DMA) {
+            if dma.check_dma_ready(FDC_DMA) && self.drives[self.drive_select].sector_image.is_some() {
+                let byte = self.image_buffer.pop_front().unwrap_or(0);
+                dma.do_dma_write_u8(bus, FDC_DMA, byte);
+                self.dma_byte_count += 1;
+                self.dma_bytes_left -= 1;
+
+                if dma.check_terminal_count(FDC_DMA) {
+                    log::trace!("DMA terminal count triggered end of Sector Read operation, {} bytes read.", self.dma_byte_count);
+                    self.dma_bytes_left = 0;
+                }
+            }
+            else if dma.check_dma_ready(FDC_DMA) {
                 let base_address = self.get_image_address(self.drive_select, cylinder, head, sector);
                 let byte_address = base_address + self.dma_byte_count;
 
@@ -1358,13 +1794,31 @@ impl FloppyController {
             self.dma_byte_count = 0;
             self.dma_bytes_left = 0;
 
+            if self.drives[self.drive_select].sector_image.is_some() {
+                // Report the ID of the sector the read stopped on. The physical cylinder is unchanged.
+                let id = self.image_read_id;
+                let result = match self.image_read_error {
+                    DriveError::NoError | DriveError::DeletedData => InterruptCode::NormalTermination,
+                    _ => InterruptCode::AbnormalTermination
+                };
+                self.last_error = self.image_read_error;
+                self.image_read_error = DriveError::NoError;
+                self.image_buffer.clear();
+
+                self.send_results_phase(result, self.drive_select, id.c, id.h, id.r, id.n);
+                self.drives[self.drive_select].sector = id.r;
+                self.operation = Operation::NoOperation;
+                self.send_interrupt = true;
+                return
+            }
+
             let (new_c, new_h, new_s) = self.get_next_sector(self.drive_select, cylinder, head, sector);
 
             // Terminate normally by sending results registers
             self.send_results_phase(InterruptCode::NormalTermination, self.drive_select, new_c, new_h, new_s, sector_size);
 
             // Set new CHS
-            self.drives[self.drive_select].cylinder = new_c;
+            self.step_to(self.drive_select, new_c);
             self.drives[self.drive_select].head = new_h;
             self.drives[self.drive_select].sector = new_s;
         
@@ -1374,6 +1828,72 @@ impl FloppyController {
         }
     }
 
+    /// Fill the image buffer with sectors read from a structured image, starting with the sector 
+    /// with ID 'cylinder, head, sector' on the current physical track and continuing until the 
+    /// end of track sector, 'max_bytes' have been read, or an error occurs. A sector with a deleted
+    /// data mark also ends the read, as the FDC would with the Skip bit clear.
+    fn read_image_sectors(&mut self, cylinder: u8, head: u8, sector: u8, track_len: u8, max_bytes: usize) {
+
+        let DiskDrive { sector_image, cylinder: phys_c, weak_rng, .. } = &mut self.drives[self.drive_select];
+        let image = match sector_image {
+            Some(image) => image,
+            None => return
+        };
+
+        self.image_buffer.clear();
+        self.image_read_error = DriveError::NoError;
+
+        let mut r = sector;
+        loop {
+            let s = match image.find_sector(*phys_c, head, cylinder, head, r) {
+                Some(s) => s,
+                None => {
+                    log::debug!("read_image_sectors: sector not found: phys c:{} id c:{} h:{} r:{}", phys_c, cylinder, head, r);
+                    self.image_read_error = DriveError::BadRead;
+                    self.image_read_id = SectorId { c: cylinder, h: head, r, n: floppy_image::STANDARD_SIZE_CODE };
+                    return
+                }
+            };
+
+            for (i, byte) in s.data.iter().enumerate() {
+                let weak = s.weak_mask.as_ref().map_or(0, |m| m[i]);
+                if weak != 0 {
+                    // xorshift32
+                    *weak_rng ^= *weak_rng << 13;
+                    *weak_rng ^= *weak_rng >> 17;
+                    *weak_rng ^= *weak_rng << 5;
+                    self.image_buffer.push_back((byte & !weak) | (*weak_rng as u8 & weak));
+                }
+                else {
+                    self.image_buffer.push_back(*byte);
+                }
+            }
+
+            self.image_read_id = s.id;
+            if s.data_error {
+                self.image_read_error = DriveError::DataError;
+                return
+            }
+            if s.deleted {
+                self.image_read_error = DriveError::DeletedData;
+                return
+            }
+            if r >= track_len || self.image_buffer.len() >= max_bytes {
+                break
+            }
+            r = r.wrapping_add(1);
+        }
+
+        // Normal termination reports the sector following the last one read. At the end of the 
+        // track, this is sector 1 of the next cylinder.
+        self.image_read_id = if r >= track_len {
+            SectorId { c: cylinder.wrapping_add(1), h: head, r: 1, n: self.image_read_id.n }
+        }
+        else {
+            SectorId { c: cylinder, h: head, r: r + 1, n: self.image_read_id.n }
+        };
+    }
+
     fn operation_write_sector(
         &mut self, 
         dma: &mut dma::DMAController, 
@@ -1448,6 +1968,8 @@ impl FloppyController {
                 log::warn!("FDC sector write complete without DMA terminal count.");
             }
 
+            self.drives[self.drive_select].dirty = true;
+
             self.dma_byte_count = 0;
             self.dma_bytes_left = 0;
 
@@ -1457,7 +1979,7 @@ impl FloppyController {
             self.send_results_phase(InterruptCode::NormalTermination, self.drive_select, new_c, new_h, new_s, sector_size);
 
             // Set new CHS
-            self.drives[self.drive_select].cylinder = new_c;
+            self.step_to(self.drive_select, new_c);
             self.drives[self.drive_select].head = new_h;
             self.drives[self.drive_select].sector = new_s;
         
@@ -1523,8 +2045,7 @@ impl FloppyController {
                 log::trace!("Formatting cylinder: {} head: {} sector: {} size: {} with byte: {:02X}", 
                     f_cylinder, f_head, f_sector, f_sector_size, fill_byte);
 
-                self.format_sector(f_cylinder, f_head, f_sector, fill_byte);
-                self.send_interrupt = true;
+                self.format_sector(f_cylinder, f_head, f_sector, f_sector_size, fill_byte);
 
                 // Clear for next 4 bytes
                 self.format_buffer.clear();
@@ -1566,12 +2087,46 @@ impl FloppyController {
         }
     }    
 
-    pub fn format_sector(&mut self, _cylinder: u8, _head: u8, _sector: u8, _fill_byte: u8) {
+    /// Format a sector by filling it with 'fill_byte'. Raw sector images can only hold 512 byte 
+    /// sectors in the image's existing geometry, so other sector IDs are ignored.
+    pub fn format_sector(&mut self, cylinder: u8, head: u8, sector: u8, sector_size: u8, fill_byte: u8) {
+
+        if sector_size != floppy_image::STANDARD_SIZE_CODE || sector == 0 
+            || !self.is_id_valid(self.drive_select, cylinder, head, sector) 
+        {
+            log::warn!("format_sector: can't format sector c:{} h:{} s:{} n:{} on image", cylinder, head, sector, sector_size);
+            return
+        }
 
+        let address = self.get_image_address(self.drive_select, cylinder, head, sector);
+        let drive = &mut self.drives[self.drive_select];
+        if let Some(sector_data) = drive.disk_image.get_mut(address..address + SECTOR_SIZE) {
+            sector_data.fill(fill_byte);
+            drive.dirty = true;
+        }
     }
 
     /// Run the Floppy Drive Controller. Process running Operations.
-    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, _us: f64 ) {
+    /// Return the time in microseconds until the controller may next raise an interrupt 
+    /// without further IO, or None if it will not. Operations are not timed, so a running 
+    /// operation may finish on the next call to run().
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        match (self.send_interrupt, &self.operation) {
+            (false, Operation::NoOperation) => None,
+            _ => Some(0.0)
+        }
+    }
+
+    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64 ) {
+
+        // Write modified disk images back to the host periodically, once no operation is running
+        self.flush_timer += us;
+        if self.flush_timer >= self.flush_interval {
+            if let Operation::NoOperation = self.operation {
+                self.flush_timer = 0.0;
+                self.flush();
+            }
+        }
 
         // Send an interrupt if one is queued
         if self.send_interrupt {
@@ -0,0 +1,189 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::game_port.rs
+
+    Implements the IBM Game Control Adapter at port 201h, with two analog
+    joysticks of two axes and two buttons each.
+
+    Each axis is a potentiometer of 0 to 100K ohms that charges a capacitor
+    connected to one of the four one-shots of a 558 timer. Writing any value 
+    to the port fires all four one-shots, setting bits 0-3 of the port. Each 
+    bit clears again after a time proportional to the axis resistance:
+
+        t = 24.2us + 0.011us * R(ohms)
+
+    Software measures the position of each axis by counting how long it polls
+    the port before the bit clears, which is why most games calibrate the 
+    joystick before play. An axis with no joystick connected never clears. 
+
+    Bits 4-7 read the buttons, with a 0 bit meaning the button is pressed.
+*/
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+pub const GAME_PORT_ADDRESS: u16 = 0x201;
+pub const JOYSTICK_COUNT: usize = 2;
+
+/// Maximum resistance of a joystick potentiometer, in ohms.
+const POT_MAX_OHMS: f64 = 100_000.0;
+/// One-shot time constants, in microseconds.
+const ONE_SHOT_BASE_US: f64 = 24.2;
+const ONE_SHOT_US_PER_OHM: f64 = 0.011;
+
+const BUTTON_SHIFT: usize = 4;
+
+#[derive (Copy, Clone, Debug, Default)]
+pub struct JoystickState {
+    /// Axis positions from -1.0 (left or up) to 1.0 (right or down).
+    pub x: f64,
+    pub y: f64,
+    pub buttons: [bool; 2],
+}
+
+pub struct GamePort {
+    joysticks: [Option<JoystickState>; JOYSTICK_COUNT],
+    /// Remaining time in microseconds before each one-shot clears, or None if it is not firing.
+    one_shots: [Option<f64>; 4],
+}
+
+impl Default for GamePort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GamePort {
+    pub fn new() -> Self {
+        Self {
+            joysticks: [None; JOYSTICK_COUNT],
+            one_shots: [None; 4],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.one_shots = [None; 4];
+    }
+
+    /// Connect or disconnect a joystick.
+    pub fn set_connected(&mut self, stick: usize, connected: bool) {
+        if let Some(joystick) = self.joysticks.get_mut(stick) {
+            *joystick = match connected {
+                true => Some(joystick.unwrap_or_default()),
+                false => None
+            };
+        }
+    }
+
+    pub fn is_connected(&self, stick: usize) -> bool {
+        matches!(self.joysticks.get(stick), Some(Some(_)))
+    }
+
+    /// Set the position of a connected joystick. Positions are clamped to -1.0..=1.0.
+    pub fn set_position(&mut self, stick: usize, x: f64, y: f64) {
+        if let Some(Some(joystick)) = self.joysticks.get_mut(stick) {
+            joystick.x = x.clamp(-1.0, 1.0);
+            joystick.y = y.clamp(-1.0, 1.0);
+        }
+    }
+
+    /// Set the state of a button on a connected joystick.
+    pub fn set_button(&mut self, stick: usize, button: usize, pressed: bool) {
+        if let Some(Some(joystick)) = self.joysticks.get_mut(stick) {
+            if let Some(b) = joystick.buttons.get_mut(button) {
+                *b = pressed;
+            }
+        }
+    }
+
+    pub fn joystick(&self, stick: usize) -> Option<JoystickState> {
+        self.joysticks.get(stick).copied().flatten()
+    }
+
+    /// Return the one-shot time in microseconds for an axis position.
+    fn one_shot_us(position: f64) -> f64 {
+        let ohms = (position.clamp(-1.0, 1.0) + 1.0) / 2.0 * POT_MAX_OHMS;
+        ONE_SHOT_BASE_US + ONE_SHOT_US_PER_OHM * ohms
+    }
+
+    fn fire_one_shots(&mut self) {
+        for (i, one_shot) in self.one_shots.iter_mut().enumerate() {
+            // An axis without a joystick has no resistor to charge the capacitor, so its 
+            // one-shot never times out.
+            *one_shot = match self.joysticks[i / 2] {
+                Some(joystick) => {
+                    let position = if i % 2 == 0 { joystick.x } else { joystick.y };
+                    Some(GamePort::one_shot_us(position))
+                }
+                None => Some(f64::INFINITY)
+            };
+        }
+    }
+
+    /// Advance the one-shots by the specified number of microseconds.
+    pub fn run(&mut self, us: f64) {
+        for one_shot in self.one_shots.iter_mut() {
+            if let Some(remaining) = one_shot {
+                *remaining -= us;
+                if *remaining <= 0.0 {
+                    *one_shot = None;
+                }
+            }
+        }
+    }
+
+    fn read_status(&self) -> u8 {
+        let mut byte = 0;
+        for (i, one_shot) in self.one_shots.iter().enumerate() {
+            if one_shot.is_some() {
+                byte |= 1 << i;
+            }
+        }
+        for (i, joystick) in self.joysticks.iter().enumerate() {
+            for (b, pressed) in joystick.map_or([false; 2], |j| j.buttons).iter().enumerate() {
+                if !pressed {
+                    byte |= 1 << (BUTTON_SHIFT + i * 2 + b);
+                }
+            }
+        }
+        byte
+    }
+}
+
+impl IoDevice for GamePort {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        self.read_status()
+    }
+
+    fn write_u8(&mut self, _port: u16, _data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        self.fire_one_shots();
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        vec![GAME_PORT_ADDRESS]
+    }
+}
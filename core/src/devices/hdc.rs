@@ -46,6 +46,7 @@ use crate::devices::{
 //use crate::fdc::Operation;
 use crate::bus::IoDevice;
 use crate::vhd::VirtualHardDisk;
+use crate::disk_activity::DriveActivity;
 
 // Public consts
 pub const HDC_IRQ: u8 = 0x05;
@@ -53,6 +54,14 @@ pub const HDC_DMA: usize = 0x03;
 pub const SECTOR_SIZE: usize = 512;
 pub const DRIVE_TYPE2_DIP: u8 = 0b1010; // IBM Type 2, 20MB drive
 
+// Geometry limits of the Xebec controller.
+pub const HDC_MAX_CYLINDERS: u32 = 1024;
+pub const HDC_MAX_HEADS: u32 = 16;
+pub const HDC_SECTORS_PER_TRACK: u32 = 17;
+
+// Default interval between flushes of disk image writes, in milliseconds.
+pub const DEFAULT_FLUSH_INTERVAL: u32 = 1000;
+
 pub const HDC_DATA_REGISTER: u16 =      0x320;
 pub const HDC_STATUS_REGISTER: u16 =    0x321;
 // 0x322 is Read DIP on READ,  Controller Select on WRITE
@@ -217,6 +226,7 @@ pub struct HardDisk {
     max_heads: u8,
     max_sectors: u8,
     sector_buf: Vec<u8>,
+    activity: DriveActivity,
     vhd: Option<VirtualHardDisk>
 }
 
@@ -230,6 +240,7 @@ impl HardDisk {
             max_heads: 0,
             max_sectors: 0,
             sector_buf: vec![0; SECTOR_SIZE],
+            activity: DriveActivity::default(),
             vhd: None
         }
     }
@@ -295,6 +306,8 @@ pub struct HardDiskController {
 
     supported_formats: Vec<HardDiskFormat>,
     drive_type_dip: u8,
+    flush_interval: f64,
+    flush_timer: f64,
     state: State,
     last_error: OperationError,
     last_error_drive: usize,
@@ -339,6 +352,8 @@ impl HardDiskController {
                 }
             ],
             drive_type_dip,
+            flush_interval: DEFAULT_FLUSH_INTERVAL as f64 * 1000.0,
+            flush_timer: 0.0,
             state: State::Reset,
             last_error: OperationError::NoError,
             last_error_drive: 0,
@@ -384,6 +399,21 @@ impl HardDiskController {
         self.command_byte_n = 0;        
     }
 
+    /// Move the head of the selected drive to the specified cylinder, counting the steps taken.
+    fn step_to(&mut self, cylinder: u16) {
+        let drive = &mut self.drives[self.drive_select];
+        drive.activity.record_seek(drive.cylinder, cylinder);
+        drive.cylinder = cylinder;
+    }
+
+    /// Return the activity of the specified drive since this was last called, or None if
+    /// no disk image is mounted in the drive.
+    pub fn take_activity(&mut self, device_id: usize) -> Option<DriveActivity> {
+        let drive = self.drives.get_mut(device_id)?;
+        drive.activity.motor_on = drive.vhd.is_some();
+        drive.vhd.as_ref().map(|_| drive.activity.take())
+    }
+
     pub fn get_supported_formats(&self) -> Vec<HardDiskFormat> {
 
         self.supported_formats.clone()
@@ -395,17 +425,25 @@ impl HardDiskController {
             return Err(ControllerError::InvalidDevice)
         }
         
-        // Check that the VHD geometry is in the list of supported formats
-        // (Currently there is only one supported format but that might change)
-        let mut supported = false;
-        for format in &self.supported_formats {
-
-            if vhd.max_cylinders as u16 == format.max_cylinders
+        // Check that the VHD geometry is within the limits of the controller.
+        let supported = vhd.max_cylinders > 0 && vhd.max_cylinders <= HDC_MAX_CYLINDERS
+            && vhd.max_heads > 0 && vhd.max_heads <= HDC_MAX_HEADS
+            && vhd.max_sectors == HDC_SECTORS_PER_TRACK;
+
+        // The BIOS takes the drive geometry from a table indexed by the drive type DIP switches, 
+        // so a drive with a different geometry may not be fully usable.
+        let known_format = self.supported_formats.iter().any(|format| {
+            vhd.max_cylinders as u16 == format.max_cylinders
                 && vhd.max_heads as u8 == format.max_heads
-                && vhd.max_sectors as u8 == format.max_sectors {
-                    supported = true;
-                    break;
-                }
+                && vhd.max_sectors as u8 == format.max_sectors
+        });
+        if supported && !known_format {
+            log::warn!(
+                "Drive geometry c:{} h:{} s:{} does not match the configured drive type. The BIOS may not recognize the full drive.",
+                vhd.max_cylinders,
+                vhd.max_heads,
+                vhd.max_sectors
+            );
         }
 
         if supported {
@@ -421,6 +459,23 @@ impl HardDiskController {
         Ok(())
     }
 
+    /// Set the interval at which writes to disk images are flushed to the host filesystem, in
+    /// milliseconds. An interval of 0 flushes after every sector write.
+    pub fn set_flush_interval(&mut self, interval_ms: u32) {
+        self.flush_interval = interval_ms as f64 * 1000.0;
+    }
+
+    /// Flush any pending writes on all mounted disk images.
+    pub fn flush(&mut self) {
+        for (i, drive) in self.drives.iter_mut().enumerate() {
+            if let Some(vhd) = &mut drive.vhd {
+                if let Err(e) = vhd.flush() {
+                    log::error!("Failed to flush disk image for drive {}: {}", i, e);
+                }
+            }
+        }
+    }
+
     pub fn set_command(&mut self, command: Command, n_bytes: u32, command_fn: CommandDispatchFn ) {
 
         self.state = State::ReceivingCommand;
@@ -867,7 +922,8 @@ impl HardDiskController {
             
             // Set up Operation 
             self.operation_status.buffer_idx = 0;
-            self.drives[self.drive_select].cylinder = dcb.c;
+            self.step_to(dcb.c);
+            self.drives[self.drive_select].activity.record_access();
             self.drives[self.drive_select].head = dcb.h;
             self.drives[self.drive_select].sector = dcb.s;
             //self.command_status.block_ct = block_count;
@@ -917,7 +973,8 @@ impl HardDiskController {
 
             // Set up Operation 
             self.operation_status.buffer_idx = 0;
-            self.drives[self.drive_select].cylinder = dcb.c;
+            self.step_to(dcb.c);
+            self.drives[self.drive_select].activity.record_access();
             self.drives[self.drive_select].head = dcb.h;
             self.drives[self.drive_select].sector = dcb.s;
             
@@ -957,7 +1014,7 @@ impl HardDiskController {
         // Check drive status
         if self.drive_present(dcb.drive_select) {
             
-            self.drives[self.drive_select].cylinder = dcb.c;
+            self.step_to(dcb.c);
             self.drives[self.drive_select].head = dcb.h;
             // Seek does not specify a sector - we can only seek to the first sector on a track
             self.drives[self.drive_select].sector = 0;
@@ -1236,9 +1293,10 @@ impl HardDiskController {
                         self.drives[self.drive_select].head,
                         self.drives[self.drive_select].sector);
 
-                    self.drives[self.drive_select].cylinder = new_c;
+                    self.step_to(new_c);
                     self.drives[self.drive_select].head = new_h;
                     self.drives[self.drive_select].sector = new_s;
+                    self.drives[self.drive_select].activity.record_access();
                     self.operation_status.buffer_idx = 0;
 
                     match &mut self.drives[self.drive_select].vhd {
@@ -1342,9 +1400,10 @@ impl HardDiskController {
                         self.drives[self.drive_select].head,
                         self.drives[self.drive_select].sector);
 
-                    self.drives[self.drive_select].cylinder = new_c;
+                    self.step_to(new_c);
                     self.drives[self.drive_select].head = new_h;
                     self.drives[self.drive_select].sector = new_s;
+                    self.drives[self.drive_select].activity.record_access();
                     self.operation_status.buffer_idx = 0;
                 }
 
@@ -1379,7 +1438,24 @@ impl HardDiskController {
     }
 
     /// Run the HDC device.
-    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, _us: f64 ) {
+    /// Return the time in microseconds until the controller may next raise an interrupt 
+    /// without further IO, or None if it will not. Commands are not timed, so a running 
+    /// command may finish on the next call to run().
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        if self.send_interrupt || self.send_dreq || matches!(self.state, State::ExecutingCommand) {
+            return Some(0.0)
+        }
+        None
+    }
+
+    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64 ) {
+
+        // Periodically write back any sectors written to disk images
+        self.flush_timer += us;
+        if self.flush_timer >= self.flush_interval {
+            self.flush_timer = 0.0;
+            self.flush();
+        }
 
         // Handle interrupts
         if self.send_interrupt {
@@ -1439,4 +1515,76 @@ impl HardDiskController {
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use ringbuf::RingBuffer;
+    use crate::bus::{BuiltinDevices, ClockFactor};
+    use crate::config::{MachineType, VideoType};
+    use crate::machine_manager::MACHINE_DESCS;
+    use crate::tracelogger::TraceLogger;
+    use crate::vhd;
+
+    #[test]
+    fn test_write_sector_dma() {
+        let path = std::env::temp_dir().join(format!("martypc_hdc_write_{}.vhd", std::process::id()));
+        let _ = fs::remove_file(&path);
+        vhd::create_vhd(path.clone().into_os_string(), 2, 2, 17).unwrap();
+
+        let machine_desc = MACHINE_DESCS[&MachineType::IBM_PC_5150];
+        let mut bus = BusInterface::new(ClockFactor::Divisor(3), machine_desc);
+        bus.install_devices(VideoType::CGA, &machine_desc, TraceLogger::None, false, BuiltinDevices::default());
+
+        let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let disk = VirtualHardDisk::from_file(file).unwrap();
+        bus.hdc_mut().as_mut().unwrap().set_vhd(0, disk).unwrap();
+
+        // Place a sector of data in memory at 0x1000.
+        let pattern: Vec<u8> = (0..SECTOR_SIZE).map(|i| (i * 7) as u8).collect();
+        bus.copy_from(&pattern, 0x1000, 0, false).unwrap();
+
+        // Program DMA channel 3 for a single mode, memory to device transfer of one sector.
+        bus.io_write_u8(0x0B, 0x4B, 0);
+        bus.io_write_u8(0x0C, 0x00, 0);
+        bus.io_write_u8(0x06, 0x00, 0);
+        bus.io_write_u8(0x06, 0x10, 0);
+        bus.io_write_u8(0x07, ((SECTOR_SIZE - 1) & 0xFF) as u8, 0);
+        bus.io_write_u8(0x07, ((SECTOR_SIZE - 1) >> 8) as u8, 0);
+        bus.io_write_u8(0x82, 0x00, 0);
+        bus.io_write_u8(0x0A, 0x03, 0);
+
+        // Enable IRQ and DMA, then issue Write for drive 0, c: 1 h: 1 s: 3, one block.
+        bus.io_write_u8(HDC_WRITE_MASK_REGISTER, 0x03, 0);
+        for byte in [0x0A, 0x01, 0x03, 0x01, 0x01, 0x00] {
+            bus.io_write_u8(HDC_DATA_REGISTER, byte, 0);
+        }
+
+        let (mut producer, _consumer) = RingBuffer::<u8>::new(4096).split();
+        for _ in 0..(SECTOR_SIZE * 4) {
+            bus.run_devices(1.0, 4, None, &mut producer);
+            if let State::HaveCommandStatus = bus.hdc_mut().as_ref().unwrap().state {
+                break;
+            }
+        }
+
+        let hdc = bus.hdc_mut().as_mut().unwrap();
+        assert!(matches!(hdc.state, State::HaveCommandStatus));
+        assert!(matches!(hdc.last_error, OperationError::NoError));
+        hdc.flush();
+
+        // Reopen the image and confirm the sector was written back.
+        let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut disk = VirtualHardDisk::from_file(file).unwrap();
+        let mut buf = vec![0u8; SECTOR_SIZE];
+        disk.read_sector(&mut buf, 1, 1, 3).unwrap();
+        assert_eq!(buf, pattern);
+        disk.read_sector(&mut buf, 1, 1, 2).unwrap();
+        assert_eq!(buf, vec![0u8; SECTOR_SIZE]);
+
+        drop(disk);
+        drop(bus);
+        fs::remove_file(&path).unwrap();
+    }
+}
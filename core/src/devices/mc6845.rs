@@ -39,7 +39,7 @@ const CURSOR_ATTR_MASK: u8      = 0b0011_0000;
 const REGISTER_MAX: usize = 17;
 const REGISTER_UNREADABLE_VALUE: u8 = 0x00;
 
-#[derive (Copy, Clone, Debug)]
+#[derive (Copy, Clone, Debug, PartialEq)]
 pub enum CrtcRegister {
     HorizontalTotal,
     HorizontalDisplayed,
@@ -61,7 +61,7 @@ pub enum CrtcRegister {
     LightPenPositionL,
 }
 
-use crate::mc6845::CrtcRegister::*;
+use crate::devices::mc6845::CrtcRegister::*;
 
 macro_rules! trace {
     ($self:ident, $($t:tt)*) => {{
@@ -70,25 +70,6 @@ macro_rules! trace {
     }};
 }
 
-macro_rules! trace_regs {
-    ($self:ident) => {
-        $self.trace_logger.print(
-            &format!("")
-            /*
-            &format!(
-                "[SL:{:03} HCC:{:03} VCC:{:03} VT:{:03} VS:{:03}] ", 
-                $self.scanline,
-                $self.hcc_c0,
-                $self.vcc_c4,
-                $self.crtc_vertical_total,
-                $self.crtc_vertical_sync_pos
-            )
-            */
-        );
-    };
-}
-
-
 pub struct Crtc6845 {
 
     reg: [u8; 18],                  // Externally-accessable CRTC register file
@@ -96,57 +77,32 @@ pub struct Crtc6845 {
 
     start_address: u16,             // Calculated value from R12 & R13
     cursor_address: u16,            // Calculated value from R14 & R15
-    lightpen_position: u16,         // Calculated value from R16 & R17
 
     cursor_status: bool,
     cursor_start_line: u8,
     cursor_slow_blink: bool,
-    cursor_blink_rate: f64,
-
-    display_enable: bool,           // True if we are in counting in the display area, false otherwise
-
-    hcc_c0: u8,                     // Horizontal character counter (x pos of character)
-    vlc_c9: u8,                     // Vertical line counter - counts during vsync period
-    vcc_c4: u8,                     // Vertical character counter (y pos of character)
-    vsc_c3h: u8,
-    hsc_c3l: u8,
-    vtac_c5: u8,
-    vma: u16,                       // VMA register - Video memory address
-    vma_t: u16,                     // VMA' register - Video memory address temporary    
 
     trace_logger: TraceLogger,    
 }
 
 impl Crtc6845 {
 
-    fn new(trace_logger: TraceLogger) -> Self {
+    pub fn new(trace_logger: TraceLogger) -> Self {
         Self {
             reg: [0; 18],
             reg_select: HorizontalTotal,
         
             start_address: 0,
             cursor_address: 0,
-            lightpen_position: 0,
         
             cursor_status: false,
             cursor_start_line: 0,
             cursor_slow_blink: false,
-            cursor_blink_rate: 0.0,
-
-            display_enable: false,
-
-            hcc_c0: 0,
-            vlc_c9: 0,
-            vcc_c4: 0,
-            vsc_c3h: 0,
-            hsc_c3l: 0,
-            vtac_c5: 0,
-            vma: 0,
-            vma_t: 0,
 
             trace_logger
         }
     }
+
     pub fn select_register(&mut self, idx: usize) {
         if idx > REGISTER_MAX {
             return
@@ -172,6 +128,7 @@ impl Crtc6845 {
             16 => LightPenPositionH,
             _  => LightPenPositionL,
         };
+        self.reg_select = reg_select;
     }
 
     pub fn write_register(&mut self, byte: u8) {
@@ -197,7 +154,6 @@ impl Crtc6845 {
                 // (R4) 7 bit write only
                 self.reg[4] = byte & 0x7F;
 
-                trace_regs!(self);
                 trace!(
                     self,
                     "CRTC Register Write (04h): VerticalTotal updated: {}",
@@ -216,7 +172,6 @@ impl Crtc6845 {
                 // (R7) 7 bit write only
                 self.reg[7] = byte & 0x7F;
 
-                trace_regs!(self);
                 trace!(
                     self,
                     "CRTC Register Write (07h): VerticalSync updated: {}",
@@ -236,7 +191,7 @@ impl Crtc6845 {
                 self.reg[10] = byte & 0x7F;
 
                 self.cursor_start_line = byte & CURSOR_LINE_MASK;
-                match byte & CURSOR_ATTR_MASK >> 4 {
+                match (byte & CURSOR_ATTR_MASK) >> 4 {
                     0b00 | 0b10 => {
                         self.cursor_status = true;
                         self.cursor_slow_blink = false;
@@ -258,7 +213,6 @@ impl Crtc6845 {
             CrtcRegister::StartAddressH => {
                 // (R12) 6 bit write only
                 self.reg[12] = byte & 0x3F;
-                trace_regs!(self);
                 trace!(
                     self,
                     "CRTC Register Write (0Ch): StartAddressH updated: {:02X}",
@@ -269,7 +223,6 @@ impl Crtc6845 {
             CrtcRegister::StartAddressL => {
                 // (R13) 8 bit write only
                 self.reg[13] = byte;
-                trace_regs!(self);
                 trace!(
                     self,
                     "CRTC Register Write (0Dh): StartAddressL updated: {:02X}",
@@ -307,8 +260,47 @@ impl Crtc6845 {
         }
     }
 
-    pub fn read_address(&self) -> u16 {
-        self.vma
+    /// Return the value of the specified register.
+    pub fn reg(&self, reg: CrtcRegister) -> u8 {
+        self.reg[reg as usize]
+    }
+
+    /// Return the index of the currently selected register.
+    pub fn selected_register(&self) -> u8 {
+        self.reg_select as u8
+    }
+
+    /// Return the display start address calculated from R12 & R13.
+    pub fn start_address(&self) -> u16 {
+        self.start_address
+    }
+
+    /// Return the cursor address calculated from R14 & R15.
+    pub fn cursor_address(&self) -> u16 {
+        self.cursor_address
+    }
+
+    /// Return the character height in scanlines (R9 + 1).
+    pub fn char_height(&self) -> u32 {
+        self.reg[9] as u32 + 1
+    }
+
+    /// Return the cursor start and end scanlines.
+    pub fn cursor_lines(&self) -> (u8, u8) {
+        (self.cursor_start_line, self.reg[11])
+    }
+
+    /// Return whether the cursor is enabled, and whether it blinks at the slow rate.
+    pub fn cursor_status(&self) -> (bool, bool) {
+        (self.cursor_status, self.cursor_slow_blink)
+    }
+
+    pub fn write_trace_log(&mut self, msg: String) {
+        self.trace_logger.print(msg);
+    }
+
+    pub fn trace_flush(&mut self) {
+        self.trace_logger.flush();
     }
 
     fn update_start_address(&mut self) {
@@ -318,6 +310,4 @@ impl Crtc6845 {
     fn update_cursor_address(&mut self) {
         self.cursor_address = (self.reg[14] as u16) << 8 | self.reg[15] as u16
     }
-
-  
-}
\ No newline at end of file
+}
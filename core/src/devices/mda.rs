@@ -0,0 +1,759 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::mda.rs
+
+    Implementation of the IBM Monochrome Display Adapter.
+
+    The MDA displays 80x25 text from 4K of video memory at B0000, using a 
+    6845 CRTC at ports 3B0-3B7. Since it only has a single text mode, the 
+    card renders a complete frame into its display buffer at the end of 
+    each frame instead of emulating the raster cycle by cycle.
+
+*/
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice, MemoryMappedDevice};
+use crate::config::VideoType;
+use crate::devices::mc6845::{Crtc6845, CrtcRegister};
+use crate::tracelogger::TraceLogger;
+use crate::videocard::*;
+
+pub const MDA_MEM_ADDRESS: usize = 0xB0000;
+// The MDA only decodes 12 address lines, so its 4K of memory repeats throughout a 32K window.
+pub const MDA_MEM_APERTURE: usize = 0x8000;
+pub const MDA_MEM_SIZE: usize = 0x1000;
+pub const MDA_MEM_MASK: usize = MDA_MEM_SIZE - 1;
+
+// CRTC registers are mirrored from 0x3B0 - 0x3B7 due to incomplete
+// address decoding.
+pub const CRTC_REGISTER_BASE: u16           = 0x3B0;
+pub const CRTC_REGISTER_MASK: u16           = 0x007;
+pub const CRTC_REGISTER_SELECT: u16         = 0x3B4;
+pub const CRTC_REGISTER: u16                = 0x3B5;
+
+pub const MDA_MODE_CONTROL_REGISTER: u16    = 0x3B8;
+pub const MDA_STATUS_REGISTER: u16          = 0x3BA;
+
+const MODE_HIRES_TEXT: u8       = 0b0000_0001;
+const MODE_VIDEO_ENABLE: u8     = 0b0000_1000;
+const MODE_BLINKING: u8         = 0b0010_0000;
+
+const STATUS_HRETRACE: u8       = 0b0000_0001;
+const STATUS_VIDEO: u8          = 0b0000_1000;
+const STATUS_UNUSED: u8         = 0b1111_0000;
+
+const MDA_CLOCK: f64 = 16.257;
+const MDA_CHAR_CLOCK: u32 = 9;
+const CHARS_PER_US: f64 = MDA_CLOCK / MDA_CHAR_CLOCK as f64;
+
+pub const MDA_XRES: u32 = 720;
+pub const MDA_YRES: u32 = 350;
+const MDA_FONT_H: u32 = 14;
+const MDA_UNDERLINE_ROW: u32 = 12;
+
+// Shortest frame we will time, to avoid spinning if the CRTC is programmed with nonsense.
+const MIN_FRAME_CHARS: u64 = 1000;
+
+// Cursor blinks every 16 frames, blinking text every 32.
+const CURSOR_BLINK_MASK: u32 = 0x08;
+const TEXT_BLINK_MASK: u32 = 0x10;
+
+// Colors are written to the display buffer as CGA color indices, so that MDA frames can go
+// through the same direct rendering path as the CGA.
+const COLOR_BLACK: u8 = CGAColor::Black as u8;
+const COLOR_NORMAL: u8 = CGAColor::White as u8;
+const COLOR_BRIGHT: u8 = CGAColor::WhiteBright as u8;
+
+// CRTC values programmed by the IBM BIOS for mode 7.
+const DEFAULT_CRTC_REGISTERS: [u8; 16] = [
+    0x61, 0x50, 0x52, 0x0F, 0x19, 0x06, 0x19, 0x19, 0x02, 0x0D, 0x0B, 0x0C, 0x00, 0x00, 0x00, 0x00
+];
+
+const CRTC_REGISTERS: [CrtcRegister; 16] = [
+    CrtcRegister::HorizontalTotal,
+    CrtcRegister::HorizontalDisplayed,
+    CrtcRegister::HorizontalSyncPosition,
+    CrtcRegister::SyncWidth,
+    CrtcRegister::VerticalTotal,
+    CrtcRegister::VerticalTotalAdjust,
+    CrtcRegister::VerticalDisplayed,
+    CrtcRegister::VerticalSync,
+    CrtcRegister::InterlaceMode,
+    CrtcRegister::MaximumScanlineAddress,
+    CrtcRegister::CursorStartLine,
+    CrtcRegister::CursorEndLine,
+    CrtcRegister::StartAddressH,
+    CrtcRegister::StartAddressL,
+    CrtcRegister::CursorAddressH,
+    CrtcRegister::CursorAddressL,
+];
+
+// The EGA's 8x14 font was derived from the MDA character ROM.
+const MDA_FONT: &[u8] = include_bytes!("../../../assets/ega_8by14.bin");
+const MDA_FONT_SPAN: usize = 256;
+
+static DUMMY_PIXEL: [u8; 4] = [0, 0, 0, 0];
+
+const MDA_EXTENTS: DisplayExtents = DisplayExtents {
+    field_w: MDA_XRES,
+    field_h: MDA_YRES,
+    aperture_w: MDA_XRES,
+    aperture_h: MDA_YRES,
+    aperture_x: 0,
+    aperture_y: 0,
+    visible_w: MDA_XRES,
+    visible_h: MDA_YRES,
+    overscan_l: 0,
+    overscan_r: 0,
+    overscan_t: 0,
+    overscan_b: 0,
+    row_stride: MDA_XRES as usize,
+};
+
+pub struct MDACard {
+    crtc: Crtc6845,
+    mode_byte: u8,
+    mem: Box<[u8]>,
+
+    char_accum: f64,            // Fractional character clocks carried between runs
+    char_pos: u64,              // Character clocks elapsed in the current frame
+    frame_count: u64,
+    blink_counter: u32,
+    blink_frozen: bool,
+
+    extents: DisplayExtents,
+    buf: [Vec<u8>; 2],
+    front_buf: usize,
+    back_buf: usize,
+
+    crtc_log: Option<Vec<(u32, u8, u8)>>, // Recorded CRTC writes as (scanline, register, value)
+}
+
+/// Return the foreground and background colors for an MDA attribute byte. 
+fn attr_colors(attr: u8, blink_enabled: bool, blink_on: bool) -> (u8, u8) {
+
+    // With blinking disabled, bit 7 selects a bright background instead.
+    let bg_bright = !blink_enabled && attr & 0x80 != 0;
+
+    let (mut fg, bg) = match attr & 0x77 {
+        // Non-display
+        0x00 => (COLOR_BLACK, COLOR_BLACK),
+        // Reverse video
+        0x70 => (COLOR_BLACK, if bg_bright { COLOR_BRIGHT } else { COLOR_NORMAL }),
+        _ => (if attr & 0x08 != 0 { COLOR_BRIGHT } else { COLOR_NORMAL }, COLOR_BLACK)
+    };
+
+    if blink_enabled && attr & 0x80 != 0 && !blink_on {
+        fg = bg;
+    }
+    (fg, bg)
+}
+
+/// Return whether an MDA attribute byte selects underlined text.
+fn attr_underline(attr: u8) -> bool {
+    attr & 0x07 == 0x01
+}
+
+/// Return the 9 pixels of one row of a character cell, with bit 8 as the leftmost pixel.
+/// Glyphs are 8 pixels wide; the ninth column is blank except for the line graphics 
+/// characters C0-DF, which duplicate the eighth column so that box-drawing characters join
+/// up. An underline lights its entire row.
+fn glyph_row(glyph: u8, row: u32, underline: bool) -> u16 {
+    if underline && row == MDA_UNDERLINE_ROW {
+        return 0x1FF;
+    }
+    let glyph_byte = if row < MDA_FONT_H {
+        MDA_FONT[row as usize * MDA_FONT_SPAN + glyph as usize]
+    }
+    else {
+        0
+    };
+
+    let mut pixels = (glyph_byte as u16) << 1;
+    if (0xC0..=0xDF).contains(&glyph) {
+        pixels |= (glyph_byte & 0x01) as u16;
+    }
+    pixels
+}
+
+/// Draw a 9 pixel wide character cell into the display buffer.
+#[allow(clippy::too_many_arguments)]
+fn draw_glyph(buf: &mut [u8], x: u32, y: u32, glyph: u8, fg: u8, bg: u8, underline: bool, char_h: u32) {
+    for row in 0..char_h {
+        let py = y + row;
+        if py >= MDA_YRES {
+            break;
+        }
+        let pixels = glyph_row(glyph, row, underline);
+
+        let row_offset = (py * MDA_XRES + x) as usize;
+        for col in 0..MDA_CHAR_CLOCK {
+            let lit = pixels & (0x100 >> col) != 0;
+            buf[row_offset + col as usize] = if lit { fg } else { bg };
+        }
+    }
+}
+
+impl MDACard {
+
+    pub fn new(trace_logger: TraceLogger) -> Self {
+
+        let mut mda = Self {
+            crtc: Crtc6845::new(trace_logger),
+            mode_byte: 0,
+            mem: vec![0; MDA_MEM_SIZE].into_boxed_slice(),
+
+            char_accum: 0.0,
+            char_pos: 0,
+            frame_count: 0,
+            blink_counter: 0,
+            blink_frozen: false,
+
+            extents: MDA_EXTENTS,
+            buf: [
+                vec![0; (MDA_XRES * MDA_YRES) as usize],
+                vec![0; (MDA_XRES * MDA_YRES) as usize],
+            ],
+            front_buf: 0,
+            back_buf: 1,
+
+            crtc_log: None,
+        };
+
+        mda.reset_crtc();
+        mda
+    }
+
+    fn reset_crtc(&mut self) {
+        for (i, value) in DEFAULT_CRTC_REGISTERS.iter().enumerate() {
+            self.crtc.select_register(i);
+            self.crtc.write_register(*value);
+        }
+        self.crtc.select_register(0);
+    }
+
+    fn chars_per_line(&self) -> u64 {
+        self.crtc.reg(CrtcRegister::HorizontalTotal) as u64 + 1
+    }
+
+    fn frame_chars(&self) -> u64 {
+        let rows = self.crtc.reg(CrtcRegister::VerticalTotal) as u64 + 1;
+        let lines = rows * self.crtc.char_height() as u64 + self.crtc.reg(CrtcRegister::VerticalTotalAdjust) as u64;
+        std::cmp::max(self.chars_per_line() * lines, MIN_FRAME_CHARS)
+    }
+
+    /// Advance the card by the specified number of character clocks, completing frames as 
+    /// we pass the end of the field.
+    fn tick_chars(&mut self, chars: u64) {
+        self.char_pos += chars;
+        let frame_chars = self.frame_chars();
+        while self.char_pos >= frame_chars {
+            self.char_pos -= frame_chars;
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.draw_frame();
+        std::mem::swap(&mut self.front_buf, &mut self.back_buf);
+        self.frame_count += 1;
+        if !self.blink_frozen {
+            self.blink_counter = self.blink_counter.wrapping_add(1);
+        }
+    }
+
+    /// Return the text dimensions (columns, rows) that fit in the display.
+    fn text_size(&self) -> (u32, u32) {
+        let columns = std::cmp::min(self.crtc.reg(CrtcRegister::HorizontalDisplayed) as u32, MDA_XRES / MDA_CHAR_CLOCK);
+        let rows = std::cmp::min(self.crtc.reg(CrtcRegister::VerticalDisplayed) as u32, MDA_YRES / self.crtc.char_height());
+        (columns, rows)
+    }
+
+    /// Return the cursor position as a character cell offset from the start address.
+    fn cursor_cell(&self) -> usize {
+        (self.crtc.cursor_address().wrapping_sub(self.crtc.start_address()) as usize) & (MDA_MEM_MASK >> 1)
+    }
+
+    fn cursor_visible(&self) -> bool {
+        let (enabled, _) = self.crtc.cursor_status();
+        enabled && self.blink_counter & CURSOR_BLINK_MASK == 0
+    }
+
+    /// Draw the current contents of video memory into the back buffer.
+    fn draw_frame(&mut self) {
+
+        let (columns, rows) = self.text_size();
+        let char_h = self.crtc.char_height();
+        let start = self.crtc.start_address() as usize;
+        let blink_enabled = self.mode_byte & MODE_BLINKING != 0;
+        let blink_on = self.blink_counter & TEXT_BLINK_MASK == 0;
+        let cursor_cell = self.cursor_cell();
+        let cursor_visible = self.cursor_visible();
+        let (cursor_start, cursor_end) = self.crtc.cursor_lines();
+
+        let buf = &mut self.buf[self.back_buf];
+        buf.fill(COLOR_BLACK);
+
+        if self.mode_byte & MODE_VIDEO_ENABLE == 0 {
+            return
+        }
+
+        for row in 0..rows {
+            for col in 0..columns {
+                let cell = (row * columns + col) as usize;
+                let addr = ((start + cell) * 2) & MDA_MEM_MASK;
+                let glyph = self.mem[addr];
+                let attr = self.mem[addr + 1];
+
+                let (fg, bg) = attr_colors(attr, blink_enabled, blink_on);
+                let x = col * MDA_CHAR_CLOCK;
+                let y = row * char_h;
+                draw_glyph(buf, x, y, glyph, fg, bg, attr_underline(attr), char_h);
+
+                if cursor_visible && cell == cursor_cell {
+                    let color = if fg == COLOR_BLACK { COLOR_NORMAL } else { fg };
+                    let last_line = std::cmp::min(cursor_end as u32, char_h - 1);
+                    for line in cursor_start as u32..=last_line {
+                        let offset = ((y + line) * MDA_XRES + x) as usize;
+                        buf[offset..offset + MDA_CHAR_CLOCK as usize].fill(color);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_status_register_read(&self) -> u8 {
+        let line_chars = self.chars_per_line();
+        let hcc = self.char_pos % line_chars;
+        let scanline = self.char_pos / line_chars;
+        let displayed_lines = self.crtc.reg(CrtcRegister::VerticalDisplayed) as u64 * self.crtc.char_height() as u64;
+
+        let mut byte = STATUS_UNUSED;
+        if hcc >= self.crtc.reg(CrtcRegister::HorizontalDisplayed) as u64 {
+            byte |= STATUS_HRETRACE;
+        }
+        else if scanline < displayed_lines && self.mode_byte & MODE_VIDEO_ENABLE != 0 {
+            // We don't track individual dots, so treat the video signal as active across the 
+            // whole display area.
+            byte |= STATUS_VIDEO;
+        }
+        byte
+    }
+
+    fn handle_mode_register(&mut self, byte: u8) {
+        if byte & MODE_HIRES_TEXT == 0 {
+            log::warn!("MDA: High resolution bit cleared in mode control register. The MDA will not sync.");
+        }
+        self.mode_byte = byte;
+    }
+
+    fn handle_crtc_register_write(&mut self, byte: u8) {
+        let scanline = self.get_scanline();
+        if let Some(log) = &mut self.crtc_log {
+            log.push((scanline, self.crtc.selected_register(), byte));
+        }
+        self.crtc.write_register(byte);
+    }
+}
+
+impl IoDevice for MDACard {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+
+        if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
+            // Read is from CRTC register.
+            if port & 0x01 != 0 {
+                self.crtc.read_register()
+            }
+            else {
+                0
+            }
+        }
+        else {
+            match port {
+                MDA_STATUS_REGISTER => {
+                    self.handle_status_register_read()
+                }
+                _ => {
+                    0
+                }
+            }
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+
+        if (port & !CRTC_REGISTER_MASK) == CRTC_REGISTER_BASE {
+            // Even ports select a CRTC register, odd ports write it.
+            if port & 0x01 != 0 {
+                self.handle_crtc_register_write(data);
+            }
+            else {
+                self.crtc.select_register(data as usize);
+            }
+        }
+        else if port == MDA_MODE_CONTROL_REGISTER {
+            self.handle_mode_register(data);
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = (CRTC_REGISTER_BASE..=(CRTC_REGISTER_BASE | CRTC_REGISTER_MASK)).collect();
+        ports.push(MDA_MODE_CONTROL_REGISTER);
+        ports.push(MDA_STATUS_REGISTER);
+        ports
+    }
+}
+
+impl MemoryMappedDevice for MDACard {
+
+    fn get_read_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_read_u8(&mut self, address: usize, _cycles: u32) -> (u8, u32) {
+        (self.mem[address & MDA_MEM_MASK], 0)
+    }
+
+    fn mmio_read_u16(&mut self, address: usize, _cycles: u32) -> (u16, u32) {
+        let lo = self.mem[address & MDA_MEM_MASK];
+        let hi = self.mem[(address + 1) & MDA_MEM_MASK];
+        ((hi as u16) << 8 | lo as u16, 0)
+    }
+
+    fn get_write_wait(&mut self, _address: usize, _cycles: u32) -> u32 {
+        0
+    }
+
+    fn mmio_write_u8(&mut self, address: usize, data: u8, _cycles: u32) -> u32 {
+        self.mem[address & MDA_MEM_MASK] = data;
+        0
+    }
+
+    fn mmio_write_u16(&mut self, address: usize, data: u16, _cycles: u32) -> u32 {
+        self.mem[address & MDA_MEM_MASK] = (data & 0xFF) as u8;
+        self.mem[(address + 1) & MDA_MEM_MASK] = (data >> 8) as u8;
+        0
+    }
+}
+
+impl VideoCard for MDACard {
+
+    fn get_video_type(&self) -> VideoType {
+        VideoType::MDA
+    }
+
+    fn get_render_mode(&self) -> RenderMode {
+        RenderMode::Direct
+    }
+
+    fn get_display_mode(&self) -> DisplayMode {
+        match self.mode_byte & MODE_VIDEO_ENABLE != 0 {
+            true => DisplayMode::Mode2TextBw80,
+            false => DisplayMode::Disabled
+        }
+    }
+
+    fn get_display_size(&self) -> (u32, u32) {
+        let (columns, rows) = self.text_size();
+        (columns * MDA_CHAR_CLOCK, rows * self.crtc.char_height())
+    }
+
+    fn get_display_extents(&self) -> &DisplayExtents {
+        &self.extents
+    }
+
+    fn get_display_aperture(&self) -> (u32, u32) {
+        (self.extents.aperture_w, self.extents.aperture_h)
+    }
+
+    fn get_overscan_color(&self) -> u8 {
+        0
+    }
+
+    fn get_display_buf(&self) -> &[u8] {
+        &self.buf[self.front_buf][..]
+    }
+
+    fn get_back_buf(&self) -> &[u8] {
+        &self.buf[self.back_buf][..]
+    }
+
+    fn get_clock_divisor(&self) -> u32 {
+        1
+    }
+
+    fn get_beam_pos(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// The MDA has no composite output.
+    fn get_color_burst(&self) -> bool {
+        false
+    }
+
+    fn get_scanline(&self) -> u32 {
+        (self.char_pos / self.chars_per_line()) as u32
+    }
+
+    /// The direct rendering path always doubles scanlines, so the MDA reports that it does 
+    /// too. This does not change the appearance of the display once aspect corrected.
+    fn get_scanline_double(&self) -> bool {
+        true
+    }
+
+    /// Get the current display refresh rate of the device. For MDA, this is always 50.
+    fn get_refresh_rate(&self) -> u32 {
+        50
+    }
+
+    fn get_start_address(&self) -> u16 {
+        self.crtc.start_address()
+    }
+
+    fn is_40_columns(&self) -> bool {
+        false
+    }
+
+    fn is_graphics_mode(&self) -> bool {
+        false
+    }
+
+    fn get_cursor_info(&self) -> CursorInfo {
+        let (columns, _) = self.text_size();
+        let cell = self.cursor_cell() as u32;
+        let (line_start, line_end) = self.crtc.cursor_lines();
+
+        CursorInfo {
+            addr: self.crtc.cursor_address() as usize,
+            pos_x: cell % columns.max(1),
+            pos_y: cell / columns.max(1),
+            line_start,
+            line_end,
+            visible: self.cursor_visible()
+        }
+    }
+
+    fn get_blink_info(&self) -> BlinkInfo {
+        BlinkInfo {
+            enabled: self.mode_byte & MODE_BLINKING != 0,
+            visible: self.blink_counter & TEXT_BLINK_MASK == 0
+        }
+    }
+
+    fn get_current_font(&self) -> FontInfo<'_> {
+        FontInfo {
+            w: 8,
+            h: MDA_FONT_H,
+            font_data: MDA_FONT
+        }
+    }
+
+    fn get_character_height(&self) -> u8 {
+        self.crtc.char_height() as u8
+    }
+
+    fn get_cga_palette(&self) -> (CGAPalette, bool) {
+        (CGAPalette::Monochrome(CGAColor::White), false)
+    }
+
+    fn get_videocard_string_state(&self) -> HashMap<String, Vec<(String, VideoCardStateEntry)>> {
+
+        let mut map = HashMap::new();
+
+        let mut general_vec = Vec::new();
+        general_vec.push((format!("Adapter Type:"), VideoCardStateEntry::String(format!("{:?}", self.get_video_type()))));
+        general_vec.push((format!("Display Mode:"), VideoCardStateEntry::String(format!("{:?}", self.get_display_mode()))));
+        general_vec.push((format!("Mode Control:"), VideoCardStateEntry::String(format!("{:08b}", self.mode_byte))));
+        general_vec.push((format!("Frame Count:"), VideoCardStateEntry::String(format!("{}", self.frame_count))));
+        map.insert("General".to_string(), general_vec);
+
+        let mut crtc_vec = Vec::new();
+        for (i, reg) in CRTC_REGISTERS.iter().enumerate() {
+            crtc_vec.push((format!("[R{}] {:?}", i, reg), VideoCardStateEntry::String(format!("{}", self.crtc.reg(*reg)))));
+        }
+        crtc_vec.push(("Start Address".to_string(), VideoCardStateEntry::String(format!("{:04X}", self.crtc.start_address()))));
+        crtc_vec.push(("Cursor Address".to_string(), VideoCardStateEntry::String(format!("{:04X}", self.crtc.cursor_address()))));
+        map.insert("CRTC".to_string(), crtc_vec);
+
+        map
+    }
+
+    fn run(&mut self, time: DeviceRunTimeUnit) {
+
+        let us = if let DeviceRunTimeUnit::Microseconds(us) = time {
+            us
+        }
+        else {
+            panic!("MDA requires Microseconds time unit.")
+        };
+
+        self.char_accum += us * CHARS_PER_US;
+        let chars = self.char_accum as u64;
+        self.char_accum -= chars as f64;
+        self.tick_chars(chars);
+    }
+
+    fn debug_tick(&mut self, ticks: u32) {
+        self.tick_chars((ticks / MDA_CHAR_CLOCK) as u64);
+    }
+
+    fn reset(&mut self) {
+        log::debug!("Resetting");
+        self.mode_byte = 0;
+        self.char_accum = 0.0;
+        self.char_pos = 0;
+        self.reset_crtc();
+    }
+
+    fn get_pixel(&self, _x: u32, _y:u32) -> &[u8] {
+        &DUMMY_PIXEL
+    }
+
+    fn get_pixel_raw(&self, _x: u32, _y:u32) -> u8 {
+        0
+    }
+
+    fn get_plane_slice(&self, plane: usize) -> &[u8] {
+        // The MDA has no bitplanes; present its video memory as plane 0
+        match plane {
+            0 => &self.mem[..],
+            _ => &[]
+        }
+    }
+
+    fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    fn set_blink_frozen(&mut self, frozen: bool) {
+        self.blink_frozen = frozen;
+    }
+
+    fn set_crtc_logging(&mut self, enabled: bool) {
+        self.crtc_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    fn take_crtc_log(&mut self) -> Vec<(u32, u8, u8)> {
+        match &mut self.crtc_log {
+            Some(log) => std::mem::take(log),
+            None => Vec::new()
+        }
+    }
+
+    fn get_text_screen(&self) -> Option<TextScreen> {
+        let (columns, rows) = self.text_size();
+        let size = (columns * rows) as usize;
+        let mut chars = Vec::with_capacity(size);
+        let mut attrs = Vec::with_capacity(size);
+
+        let base = self.crtc.start_address() as usize * 2;
+        for i in 0..size {
+            chars.push(self.mem[(base + i * 2) & MDA_MEM_MASK]);
+            attrs.push(self.mem[(base + i * 2 + 1) & MDA_MEM_MASK]);
+        }
+
+        Some(TextScreen { columns, rows, chars, attrs })
+    }
+
+    fn dump_mem(&self, path: &Path) {
+
+        let mut filename = path.to_path_buf();
+        filename.push("mda_mem.bin");
+        
+        match std::fs::write(filename.clone(), &*self.mem) {
+            Ok(_) => {
+                log::debug!("Wrote memory dump: {}", filename.display())
+            }
+            Err(e) => {
+                log::error!("Failed to write memory dump '{}': {}", filename.display(), e)
+            }
+        }
+    }
+
+    fn write_trace_log(&mut self, msg: String) {
+        self.crtc.write_trace_log(msg);
+    }
+
+    fn trace_flush(&mut self) {
+        self.crtc.trace_flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::Microseconds(0.0);
+
+    #[test]
+    fn registers_and_memory_are_mirrored() {
+        let mut mda = MDACard::new(TraceLogger::None);
+
+        // Select and write the cursor address through mirrors of 3B4/3B5.
+        mda.write_u8(0x3B0, 14, None, NO_DELTA);
+        mda.write_u8(0x3B7, 0x01, None, NO_DELTA);
+        mda.write_u8(CRTC_REGISTER_SELECT, 15, None, NO_DELTA);
+        mda.write_u8(CRTC_REGISTER, 0x23, None, NO_DELTA);
+        assert_eq!(mda.crtc.cursor_address(), 0x0123);
+        assert_eq!(mda.read_u8(0x3B3, NO_DELTA), 0x23);
+
+        mda.mmio_write_u8(MDA_MEM_ADDRESS + 0x10, 0x41, 0);
+        assert_eq!(mda.mmio_read_u8(MDA_MEM_ADDRESS + MDA_MEM_SIZE + 0x10, 0).0, 0x41);
+        assert_eq!(mda.mmio_read_u8(MDA_MEM_ADDRESS + MDA_MEM_APERTURE - MDA_MEM_SIZE + 0x10, 0).0, 0x41);
+    }
+
+    #[test]
+    fn line_graphics_fill_ninth_column() {
+        // Horizontal line characters join up across cells; text characters do not.
+        for row in 0..MDA_FONT_H {
+            let line = glyph_row(0xC4, row, false);
+            assert_eq!(line & 0x01, (line >> 1) & 0x01);
+            assert_eq!(glyph_row(b'A', row, false) & 0x01, 0);
+            assert_eq!(glyph_row(0xB3, row, false) & 0x01, 0);
+        }
+        assert_eq!(glyph_row(0xC4, 7, false) & 0x03, 0x03);
+
+        // Underlines span the whole cell.
+        assert!(attr_underline(0x01));
+        assert!(!attr_underline(0x07));
+        assert_eq!(glyph_row(b' ', MDA_UNDERLINE_ROW, true), 0x1FF);
+        assert_eq!(glyph_row(b' ', MDA_UNDERLINE_ROW, false), 0);
+    }
+
+    #[test]
+    fn default_timing_is_50hz() {
+        let mut mda = MDACard::new(TraceLogger::None);
+        mda.write_u8(MDA_MODE_CONTROL_REGISTER, MODE_HIRES_TEXT | MODE_VIDEO_ENABLE, None, NO_DELTA);
+
+        for _ in 0..1000 {
+            mda.run(DeviceRunTimeUnit::Microseconds(1000.0));
+        }
+        assert!((49..=51).contains(&mda.get_frame_count()));
+    }
+}
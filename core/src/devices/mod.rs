@@ -31,6 +31,8 @@
 */
 
 pub mod cga;
+pub mod mc6845;
+pub mod mda;
 #[cfg(feature = "ega")]
 pub mod ega;
 #[cfg(feature = "vga")]
@@ -40,8 +42,18 @@ pub mod pit;
 pub mod pic;
 pub mod ppi;
 pub mod serial;
+pub mod serial_bridge;
 pub mod hdc;
+pub mod xtide;
 pub mod fdc;
 pub mod dma;
 pub mod mouse;
+pub mod adlib;
+pub mod sb;
+pub mod timer_card;
+pub mod a20;
+pub mod rtc_card;
+pub mod game_port;
+pub mod ne2000;
+pub mod sn76489;
 
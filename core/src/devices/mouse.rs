@@ -55,10 +55,15 @@ const MOUSE_UPDATE_RBUTTON: u8 = 0b0001_0000;
 const MOUSE_UPDATE_HO_BITS: u8 = 0b1100_0000;
 const MOUSE_UPDATE_LO_BITS: u8 = 0b0011_1111;
 
+// The largest movement a single update packet can report on each axis.
+const MOUSE_UPDATE_MAX_DELTA: f64 = 127.0;
+
 #[allow(dead_code)]
 pub struct Mouse {
 
     updates: VecDeque<MouseUpdate>,
+    remainder_x: f64,
+    remainder_y: f64,
     rts: bool,
     rts_low_timer: f64,
     dtr: bool,
@@ -72,72 +77,76 @@ impl Mouse {
     pub fn new() -> Self {
         Self {
             updates: VecDeque::new(),
+            remainder_x: 0.0,
+            remainder_y: 0.0,
             rts: false,
             rts_low_timer: 0.0,
             dtr: false,
         }
     }
 
+    /// Queue an update for host mouse motion and button state. Motion is scaled to mouse counts;
+    /// the fractional part is carried over to the next update so that slow movement isn't lost,
+    /// and movement too large for one packet is split across several.
     pub fn update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) {
 
-        let mut scaled_x = delta_x * MOUSE_SCALE;
-        let mut scaled_y = delta_y * MOUSE_SCALE;
-    
-        // Mouse scale can cause fractional integer updates. Adjust to Minimum movement of one unit
-        if scaled_x > 0.0 && scaled_x < 1.0 {
-            scaled_x = 1.0;
-        }
-        if scaled_x < 0.0 && scaled_x > -1.0 {
-            scaled_x = -1.0;
-        }
-        if scaled_y > 0.0 && scaled_y < 1.0 {
-            scaled_y = 1.0;
+        let scaled_x = delta_x * MOUSE_SCALE + self.remainder_x;
+        let scaled_y = delta_y * MOUSE_SCALE + self.remainder_y;
+
+        let mut counts_x = scaled_x.trunc();
+        let mut counts_y = scaled_y.trunc();
+        self.remainder_x = scaled_x - counts_x;
+        self.remainder_y = scaled_y - counts_y;
+
+        loop {
+            let packet_x = counts_x.clamp(-MOUSE_UPDATE_MAX_DELTA, MOUSE_UPDATE_MAX_DELTA);
+            let packet_y = counts_y.clamp(-MOUSE_UPDATE_MAX_DELTA, MOUSE_UPDATE_MAX_DELTA);
+            self.queue_packet(l_button_pressed, r_button_pressed, packet_x as i8, packet_y as i8);
+
+            counts_x -= packet_x;
+            counts_y -= packet_y;
+            if counts_x == 0.0 && counts_y == 0.0 {
+                break
+            }
         }
-        if scaled_y < 0.0 && scaled_y > -1.0 {
-            scaled_y = -1.0;
-        }        
-        let delta_x_i8 = scaled_x as i8;
-        let delta_y_i8 = scaled_y as i8;
+    }
+
+    /// Discard any fractional movement carried over from previous updates.
+    pub fn clear_motion(&mut self) {
+        self.remainder_x = 0.0;
+        self.remainder_y = 0.0;
+    }
+
+    fn queue_packet(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: i8, delta_y: i8) {
 
         let mut byte1 = MOUSE_UPDATE_STARTBIT;
 
         if l_button_pressed {
-            //log::debug!("Sending mouse button down");
             byte1 |= MOUSE_UPDATE_LBUTTON;
         }
-        /*
-        else {
-            log::debug!("Sending mouse button up");
-        }
-        */
-
         if r_button_pressed {
             byte1 |= MOUSE_UPDATE_RBUTTON;
         }
 
         // Pack HO 2 bits of Y into byte1
-        byte1 |= ((delta_y_i8 as u8) & MOUSE_UPDATE_HO_BITS) >> 4;
+        byte1 |= ((delta_y as u8) & MOUSE_UPDATE_HO_BITS) >> 4;
         // Pack HO 2 bits of X into byte1;
-        byte1 |= ((delta_x_i8 as u8) & MOUSE_UPDATE_HO_BITS) >> 6;
+        byte1 |= ((delta_x as u8) & MOUSE_UPDATE_HO_BITS) >> 6;
 
         // LO 6 bits of X into byte 2
-        let byte2 = (delta_x_i8 as u8) & MOUSE_UPDATE_LO_BITS;
+        let byte2 = (delta_x as u8) & MOUSE_UPDATE_LO_BITS;
         // LO 6 bits of Y into byte 3
-        let byte3 = (delta_y_i8 as u8) & MOUSE_UPDATE_LO_BITS;
-
-        // Queue update
+        let byte3 = (delta_y as u8) & MOUSE_UPDATE_LO_BITS;
 
         self.updates.push_back(MouseUpdate::Update(byte1, byte2, byte3));
-        /*
-        let mut serial = self.serial_ctrl.borrow_mut();
-        serial.queue_byte(MOUSE_PORT, byte1);
-        serial.queue_byte(MOUSE_PORT, byte2);
-        serial.queue_byte(MOUSE_PORT, byte3);*/
-
-
-     }
+    }
 
     /// Run the mouse device for the specified number of microseconds
+    /// Return whether the mouse has an update waiting to be sent to the serial port.
+    pub fn has_pending_update(&self) -> bool {
+        !self.updates.is_empty()
+    }
+
     pub fn run(&mut self, serial: &mut SerialPortController, us: f64) {
 
         // Send a queued update.
@@ -175,3 +184,40 @@ impl Mouse {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(mouse: &Mouse) -> Vec<(u8, u8, u8)> {
+        mouse.updates.iter().map(|MouseUpdate::Update(b1, b2, b3)| (*b1, *b2, *b3)).collect()
+    }
+
+    #[test]
+    fn small_motion_accumulates() {
+        let mut mouse = Mouse::new();
+
+        // Each update is less than one count, so only the third reports movement.
+        for _ in 0..3 {
+            mouse.update(false, false, 1.5, -1.5);
+        }
+        let updates = packets(&mouse);
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0], (MOUSE_UPDATE_STARTBIT, 0, 0));
+        assert_eq!(updates[1], (MOUSE_UPDATE_STARTBIT, 0, 0));
+        // +1 in X, -1 (0xFF) in Y
+        assert_eq!(updates[2], (MOUSE_UPDATE_STARTBIT | 0b0000_1100, 0x01, 0x3F));
+    }
+
+    #[test]
+    fn large_motion_is_split() {
+        let mut mouse = Mouse::new();
+
+        mouse.update(true, false, 300.0 / MOUSE_SCALE, 0.0);
+        let updates = packets(&mouse);
+        assert_eq!(updates.len(), 3);
+        // 127 + 127 + 46, with the left button held in every packet
+        assert_eq!(updates[0], (MOUSE_UPDATE_STARTBIT | MOUSE_UPDATE_LBUTTON | 0b01, 0x3F, 0));
+        assert_eq!(updates[1], updates[0]);
+        assert_eq!(updates[2], (MOUSE_UPDATE_STARTBIT | MOUSE_UPDATE_LBUTTON, 46, 0));
+    }
+}
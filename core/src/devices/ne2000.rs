@@ -0,0 +1,481 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::ne2000.rs
+
+    Implements an NE2000 compatible Ethernet card, built around the National
+    Semiconductor DP8390 network interface controller.
+
+    The card occupies 32 ports. The DP8390 registers are at base + 00h to
+    0Fh, in up to three pages selected by the command register. The card's
+    buffer memory is accessed a byte at a time through the remote DMA data
+    port at base + 10h, and reading base + 1Fh resets the card.
+
+    Buffer memory holds the station address PROM at 0000h and 32K of packet
+    RAM from 4000h to BFFFh. Received packets are stored in a ring of 256
+    byte pages between the PSTART and PSTOP registers, each preceded by a 
+    4 byte header with the receive status, next page pointer and length.
+
+    Frames are exchanged with the host through a NetworkBackend. Only 8-bit
+    transfers are emulated, as used by packet drivers on the PC/XT. 
+*/
+
+use std::collections::VecDeque;
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::devices::pic;
+use crate::network::{packet::{MacAddress, ETHERNET_MIN_LEN}, NetworkBackend};
+
+pub const NE2000_DEFAULT_PORT: u16 = 0x300;
+pub const NE2000_DEFAULT_IRQ: u8 = 2;
+pub const NE2000_DEFAULT_MAC: MacAddress = [0x00, 0x00, 0x1B, 0x12, 0x34, 0x56];
+
+const NE2000_PORT_COUNT: u16 = 0x20;
+const DATA_PORT: u16 = 0x10;
+const RESET_PORT: u16 = 0x1F;
+
+const PROM_SIZE: usize = 32;
+const RAM_START: usize = 0x4000;
+const MEM_SIZE: usize = 0xC000;
+const MAX_FRAME_SIZE: usize = 1514;
+/// Maximum number of received frames waiting for space in the receive ring.
+const MAX_RX_QUEUE: usize = 64;
+
+// Command register bits
+const CR_STOP: u8 = 0x01;
+const CR_TRANSMIT: u8 = 0x04;
+const CR_REMOTE_READ: u8 = 0x08;
+const CR_REMOTE_WRITE: u8 = 0x10;
+
+// Interrupt status register bits
+const ISR_RX: u8 = 0x01;
+const ISR_TX: u8 = 0x02;
+const ISR_RDC: u8 = 0x40;
+const ISR_RESET: u8 = 0x80;
+
+// Receive configuration register bits
+const RCR_BROADCAST: u8 = 0x04;
+const RCR_MULTICAST: u8 = 0x08;
+const RCR_PROMISCUOUS: u8 = 0x10;
+
+const TSR_TX_OK: u8 = 0x01;
+const RSR_RX_OK: u8 = 0x01;
+const RSR_PHYSICAL: u8 = 0x20;
+
+pub struct Ne2000 {
+    base_port: u16,
+    irq: u8,
+    irq_asserted: bool,
+    mac: MacAddress,
+    mem: Vec<u8>,
+
+    cr: u8,
+    isr: u8,
+    imr: u8,
+    rcr: u8,
+    tcr: u8,
+    dcr: u8,
+    tsr: u8,
+    rsr: u8,
+    /// Receive ring start and stop addresses.
+    start: usize,
+    stop: usize,
+    boundary: u8,
+    curr: u8,
+    tpsr: u8,
+    tbcr: u16,
+    rsar: u16,
+    rbcr: u16,
+    par: MacAddress,
+    mar: [u8; 8],
+
+    rx_queue: VecDeque<Vec<u8>>,
+    backend: Option<Box<dyn NetworkBackend>>,
+}
+
+impl Ne2000 {
+    pub fn new(base_port: u16, irq: u8, mac: MacAddress, backend: Option<Box<dyn NetworkBackend>>) -> Self {
+        let mut ne2000 = Self {
+            base_port,
+            irq,
+            irq_asserted: false,
+            mac,
+            mem: vec![0; MEM_SIZE],
+            cr: CR_STOP,
+            isr: 0,
+            imr: 0,
+            rcr: 0,
+            tcr: 0,
+            dcr: 0,
+            tsr: 0,
+            rsr: 0,
+            start: 0,
+            stop: 0,
+            boundary: 0,
+            curr: 0,
+            tpsr: 0,
+            tbcr: 0,
+            rsar: 0,
+            rbcr: 0,
+            par: [0; 6],
+            mar: [0; 8],
+            rx_queue: VecDeque::new(),
+            backend,
+        };
+        ne2000.reset();
+        ne2000
+    }
+
+    pub fn reset(&mut self) {
+        self.cr = CR_STOP;
+        self.isr = ISR_RESET;
+        self.imr = 0;
+        self.rx_queue.clear();
+
+        // The PROM holds the station address followed by the NE2000 signature bytes. Each
+        // byte appears twice, as the PROM is on the low half of the 16-bit data bus.
+        let mut prom = [0u8; 16];
+        prom[0..6].copy_from_slice(&self.mac);
+        prom[14] = 0x57;
+        prom[15] = 0x57;
+        for (i, byte) in prom.iter().enumerate() {
+            self.mem[i * 2] = *byte;
+            self.mem[i * 2 + 1] = *byte;
+        }
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    pub fn set_backend(&mut self, backend: Option<Box<dyn NetworkBackend>>) {
+        self.backend = backend;
+    }
+
+    /// Queue a frame to be received by the guest.
+    pub fn receive_frame(&mut self, frame: &[u8]) {
+        if self.rx_queue.len() < MAX_RX_QUEUE && frame.len() <= MAX_FRAME_SIZE {
+            self.rx_queue.push_back(frame.to_vec());
+        }
+    }
+
+    /// The update function is called per-frame to service the network backend.
+    pub fn update(&mut self) {
+        if let Some(mut backend) = self.backend.take() {
+            backend.poll();
+            while let Some(frame) = backend.receive() {
+                self.receive_frame(&frame);
+            }
+            self.backend = Some(backend);
+        }
+    }
+
+    /// Deliver queued frames to the receive ring and update the interrupt line.
+    /// Return the time in microseconds until the card may next raise an interrupt without 
+    /// further IO, or None if it will not. Frames from the host are stored on the next call to
+    /// run().
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        let level = self.isr & self.imr & 0x7F != 0;
+        if level != self.irq_asserted || (self.cr & CR_STOP == 0 && !self.rx_queue.is_empty()) {
+            return Some(0.0)
+        }
+        None
+    }
+
+    pub fn run(&mut self, pic: &mut pic::Pic, _us: f64) {
+        while self.cr & CR_STOP == 0 && !self.rx_queue.is_empty() && !self.rx_ring_full() {
+            if let Some(frame) = self.rx_queue.pop_front() {
+                self.store_frame(&frame);
+            }
+        }
+
+        let level = self.isr & self.imr & 0x7F != 0;
+        if level != self.irq_asserted {
+            match level {
+                true => pic.request_interrupt(self.irq),
+                false => pic.clear_interrupt(self.irq),
+            }
+            self.irq_asserted = level;
+        }
+    }
+
+    fn rx_ring_full(&self) -> bool {
+        if self.stop <= self.start {
+            return true
+        }
+        let index = (self.curr as usize) << 8;
+        let boundary = (self.boundary as usize) << 8;
+        let avail = match index < boundary {
+            true => boundary - index,
+            false => (self.stop - self.start) - (index - boundary)
+        };
+        avail < MAX_FRAME_SIZE + 4
+    }
+
+    /// Return whether the receive configuration accepts a frame with the specified destination.
+    fn accepts(&self, dst: &[u8]) -> bool {
+        if self.rcr & RCR_PROMISCUOUS != 0 {
+            return true
+        }
+        if dst == [0xFF; 6] {
+            return self.rcr & RCR_BROADCAST != 0
+        }
+        if dst[0] & 0x01 != 0 {
+            // Multicast addresses are filtered by the top 6 bits of the Ethernet CRC.
+            let bit = (crc32(dst) >> 26) as usize;
+            return self.rcr & RCR_MULTICAST != 0 && self.mar[bit >> 3] & (1 << (bit & 7)) != 0
+        }
+        dst == self.par
+    }
+
+    fn store_frame(&mut self, frame: &[u8]) {
+        if frame.len() < 6 || !self.accepts(&frame[0..6]) {
+            return
+        }
+        let mut data = frame.to_vec();
+        if data.len() < ETHERNET_MIN_LEN {
+            data.resize(ETHERNET_MIN_LEN, 0);
+        }
+
+        let mut index = (self.curr as usize) << 8;
+        if index >= MEM_SIZE {
+            index = self.start;
+        }
+        let total_len = data.len() + 4;
+        // The next packet starts on the next page after the header, data and CRC.
+        let mut next = index + ((total_len + 4 + 255) & !0xFF);
+        if next >= self.stop {
+            next -= self.stop - self.start;
+        }
+
+        self.rsr = RSR_RX_OK;
+        if data[0] & 0x01 != 0 {
+            self.rsr |= RSR_PHYSICAL;
+        }
+        self.mem[index] = self.rsr;
+        self.mem[index + 1] = (next >> 8) as u8;
+        self.mem[index + 2] = total_len as u8;
+        self.mem[index + 3] = (total_len >> 8) as u8;
+        index += 4;
+
+        // Copy the frame, wrapping around the end of the ring.
+        let mut remaining = &data[..];
+        while !remaining.is_empty() {
+            let avail = self.stop.saturating_sub(index);
+            if avail == 0 {
+                break
+            }
+            let len = remaining.len().min(avail);
+            self.mem[index..index + len].copy_from_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+            index += len;
+            if index == self.stop {
+                index = self.start;
+            }
+        }
+
+        self.curr = (next >> 8) as u8;
+        self.isr |= ISR_RX;
+    }
+
+    fn transmit(&mut self) {
+        let mut index = (self.tpsr as usize) << 8;
+        if index >= MEM_SIZE {
+            index -= MEM_SIZE - RAM_START;
+        }
+        let len = self.tbcr as usize;
+        if index + len <= MEM_SIZE {
+            if let Some(backend) = &mut self.backend {
+                backend.send(&self.mem[index..index + len]);
+            }
+        }
+        self.tsr = TSR_TX_OK;
+        self.isr |= ISR_TX;
+        self.cr &= !CR_TRANSMIT;
+    }
+
+    fn write_command(&mut self, data: u8) {
+        self.cr = data;
+        if data & CR_STOP == 0 {
+            self.isr &= !ISR_RESET;
+            if data & (CR_REMOTE_READ | CR_REMOTE_WRITE) != 0 && self.rbcr == 0 {
+                self.isr |= ISR_RDC;
+            }
+            if data & CR_TRANSMIT != 0 {
+                self.transmit();
+            }
+        }
+    }
+
+    fn mem_read(&self, addr: usize) -> u8 {
+        if addr < PROM_SIZE || (RAM_START..MEM_SIZE).contains(&addr) {
+            self.mem[addr]
+        }
+        else {
+            0xFF
+        }
+    }
+
+    fn mem_write(&mut self, addr: usize, data: u8) {
+        if addr < PROM_SIZE || (RAM_START..MEM_SIZE).contains(&addr) {
+            self.mem[addr] = data;
+        }
+    }
+
+    /// Advance the remote DMA address after a byte is transferred.
+    fn advance_remote_dma(&mut self) {
+        self.rsar = self.rsar.wrapping_add(1);
+        if self.rsar as usize == self.stop {
+            self.rsar = self.start as u16;
+        }
+        if self.rbcr <= 1 {
+            self.rbcr = 0;
+            self.isr |= ISR_RDC;
+        }
+        else {
+            self.rbcr -= 1;
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.rbcr == 0 {
+            return 0
+        }
+        let byte = self.mem_read(self.rsar as usize);
+        self.advance_remote_dma();
+        byte
+    }
+
+    fn write_data(&mut self, data: u8) {
+        if self.rbcr == 0 {
+            return
+        }
+        self.mem_write(self.rsar as usize, data);
+        self.advance_remote_dma();
+    }
+
+    fn read_register(&self, reg: u16) -> u8 {
+        if reg == 0 {
+            return self.cr
+        }
+        match (self.cr >> 6, reg) {
+            (0, 0x03) => self.boundary,
+            (0, 0x04) => self.tsr,
+            (0, 0x07) => self.isr,
+            (0, 0x08) => self.rsar as u8,
+            (0, 0x09) => (self.rsar >> 8) as u8,
+            (0, 0x0C) => self.rsr,
+            (1, 0x01..=0x06) => self.par[reg as usize - 1],
+            (1, 0x07) => self.curr,
+            (1, 0x08..=0x0F) => self.mar[reg as usize - 8],
+            (2, 0x01) => (self.start >> 8) as u8,
+            (2, 0x02) => (self.stop >> 8) as u8,
+            (2, 0x04) => self.tpsr,
+            (2, 0x0C) => self.rcr,
+            (2, 0x0D) => self.tcr,
+            (2, 0x0E) => self.dcr,
+            (2, 0x0F) => self.imr,
+            _ => 0
+        }
+    }
+
+    fn write_register(&mut self, reg: u16, data: u8) {
+        if reg == 0 {
+            self.write_command(data);
+            return
+        }
+        match (self.cr >> 6, reg) {
+            (0, 0x01) => self.start = (data as usize) << 8,
+            (0, 0x02) => self.stop = (data as usize) << 8,
+            (0, 0x03) => self.boundary = data,
+            (0, 0x04) => self.tpsr = data,
+            (0, 0x05) => self.tbcr = (self.tbcr & 0xFF00) | data as u16,
+            (0, 0x06) => self.tbcr = (self.tbcr & 0x00FF) | (data as u16) << 8,
+            // Writing 1 bits acknowledges interrupts. The reset bit can't be acknowledged.
+            (0, 0x07) => self.isr &= !(data & 0x7F),
+            (0, 0x08) => self.rsar = (self.rsar & 0xFF00) | data as u16,
+            (0, 0x09) => self.rsar = (self.rsar & 0x00FF) | (data as u16) << 8,
+            (0, 0x0A) => self.rbcr = (self.rbcr & 0xFF00) | data as u16,
+            (0, 0x0B) => self.rbcr = (self.rbcr & 0x00FF) | (data as u16) << 8,
+            (0, 0x0C) => self.rcr = data,
+            (0, 0x0D) => self.tcr = data,
+            (0, 0x0E) => self.dcr = data,
+            (0, 0x0F) => self.imr = data,
+            (1, 0x01..=0x06) => self.par[reg as usize - 1] = data,
+            (1, 0x07) => self.curr = data,
+            (1, 0x08..=0x0F) => self.mar[reg as usize - 8] = data,
+            _ => {}
+        }
+    }
+}
+
+/// Calculate the Ethernet CRC-32 of the data, as used by the multicast hash filter.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        let mut b = *byte;
+        for _ in 0..8 {
+            let carry = ((crc >> 31) as u8) ^ (b & 0x01);
+            crc <<= 1;
+            b >>= 1;
+            if carry != 0 {
+                crc ^= 0x04C1_1DB7;
+            }
+        }
+    }
+    crc
+}
+
+impl IoDevice for Ne2000 {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.base_port) {
+            reg @ 0x00..=0x0F => self.read_register(reg),
+            DATA_PORT..=0x17 => self.read_data(),
+            RESET_PORT => {
+                self.reset();
+                0
+            }
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.base_port) {
+            reg @ 0x00..=0x0F => self.write_register(reg, data),
+            DATA_PORT..=0x17 => self.write_data(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (self.base_port..self.base_port + NE2000_PORT_COUNT).collect()
+    }
+}
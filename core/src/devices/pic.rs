@@ -35,6 +35,8 @@
 //use std::io::Read;
 
 use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::interrupt::IrqState;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 
 pub const PIC_INTERRUPT_OFFSET: u8 = 8;
@@ -126,7 +128,7 @@ pub struct Pic {
     intr_timer: u32
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Debug)]
 pub struct PicStringState {
     pub imr: String,
     pub isr: String,
@@ -531,6 +533,16 @@ impl Pic {
         self.intr
     }
 
+    /// Return the state of the IR input lines.
+    pub fn get_ir(&self) -> u8 {
+        self.ir
+    }
+
+    /// Return the contents of the Interrupt Request Register.
+    pub fn get_irr(&self) -> u8 {
+        self.irr
+    }
+
     /// Represents the PIC's response to the 2nd INTA 'pulse'. The PIC will put the 
     /// highest-priority interrupt vector onto the bus.
     pub fn get_interrupt_vector(&mut self) -> Option<u8> {
@@ -569,6 +581,36 @@ impl Pic {
         None
     }
 
+    /// Return the number of interrupt requests received on each IR line, whether or not 
+    /// they were serviced.
+    pub fn get_request_counts(&self) -> [u64; 8] {
+        let mut counts = [0; 8];
+        for (count, stats) in counts.iter_mut().zip(self.interrupt_stats.iter()) {
+            *count = stats.imr_masked_count + stats.isr_masked_count + stats.serviced_count;
+        }
+        counts
+    }
+
+    /// Return the mask, request and service status of each IR line along with its 
+    /// interrupt vector and request counters.
+    pub fn get_irq_states(&self) -> Vec<IrqState> {
+        let requests = self.get_request_counts();
+        (0..8u8)
+            .map(|irq| {
+                let ir_bit = 0x01 << irq;
+                IrqState {
+                    irq,
+                    vector: self.int_offset.wrapping_add(irq),
+                    masked: self.imr & ir_bit != 0,
+                    requested: self.irr & ir_bit != 0,
+                    in_service: self.isr & ir_bit != 0,
+                    request_count: requests[irq as usize],
+                    serviced_count: self.interrupt_stats[irq as usize].serviced_count,
+                }
+            })
+            .collect()
+    }
+
     pub fn get_string_state(&self) -> PicStringState {
     
         let mut state = PicStringState {
@@ -609,4 +651,72 @@ impl Pic {
         }
     }
 
-}
\ No newline at end of file
+}
+
+impl SaveState for Pic {
+    const STATE_ID: &'static str = "pic";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.init_state {
+            InitializationState::Normal => 0,
+            InitializationState::ExpectingICW2 => 1,
+            InitializationState::ExpectingICW4 => 2
+        });
+        w.write_u8(self.int_offset);
+        w.write_u8(self.imr);
+        w.write_u8(self.isr);
+        w.write_u8(self.irr);
+        w.write_u8(self.ir);
+        w.write_bool(matches!(self.read_select, ReadSelect::ISR));
+        w.write_u8(self.irq);
+        w.write_bool(self.intr);
+        w.write_bool(self.buffered);
+        w.write_bool(self.nested);
+        w.write_bool(self.special_nested);
+        w.write_bool(self.polled);
+        w.write_bool(self.auto_eoi);
+        w.write_bool(self.rotate_on_aeoi);
+        w.write_bool(self.trigger_mode == TriggerMode::Level);
+        w.write_bool(self.expecting_icw2);
+        w.write_bool(self.expecting_icw4);
+        w.write_bool(self.intr_scheduled);
+        w.write_u32(self.intr_timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.init_state = match r.read_u8()? {
+            0 => InitializationState::Normal,
+            1 => InitializationState::ExpectingICW2,
+            2 => InitializationState::ExpectingICW4,
+            _ => return Err(r.invalid("bad initialization state"))
+        };
+        self.int_offset = r.read_u8()?;
+        self.imr = r.read_u8()?;
+        self.isr = r.read_u8()?;
+        self.irr = r.read_u8()?;
+        self.ir = r.read_u8()?;
+        self.read_select = match r.read_bool()? {
+            true => ReadSelect::ISR,
+            false => ReadSelect::IRR
+        };
+        self.irq = r.read_u8()?;
+        self.intr = r.read_bool()?;
+        self.buffered = r.read_bool()?;
+        self.nested = r.read_bool()?;
+        self.special_nested = r.read_bool()?;
+        self.polled = r.read_bool()?;
+        self.auto_eoi = r.read_bool()?;
+        self.rotate_on_aeoi = r.read_bool()?;
+        self.trigger_mode = match r.read_bool()? {
+            true => TriggerMode::Level,
+            false => TriggerMode::Edge
+        };
+        self.expecting_icw2 = r.read_bool()?;
+        self.expecting_icw4 = r.read_bool()?;
+        self.intr_scheduled = r.read_bool()?;
+        self.intr_timer = r.read_u32()?;
+        self.error = false;
+        Ok(())
+    }
+}
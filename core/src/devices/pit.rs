@@ -37,6 +37,7 @@ use std::collections::{VecDeque, BTreeMap};
 use modular_bitfield::prelude::*;
 
 use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 use crate::syntax_token::*;
 use crate::updatable::*;
@@ -1205,3 +1206,123 @@ impl ProgrammableIntervalTimer {
         state_vec
     }
 }
+
+impl Channel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(match self.mode.get() {
+            ChannelMode::InterruptOnTerminalCount => 0,
+            ChannelMode::HardwareRetriggerableOneShot => 1,
+            ChannelMode::RateGenerator => 2,
+            ChannelMode::SquareWaveGenerator => 3,
+            ChannelMode::SoftwareTriggeredStrobe => 4,
+            ChannelMode::HardwareTriggeredStrobe => 5
+        });
+        w.write_u8(match self.rw_mode.get() {
+            RwMode::Lsb => 1,
+            RwMode::Msb => 2,
+            RwMode::LsbMsb => 3
+        });
+        w.write_u8(match self.channel_state {
+            ChannelState::WaitingForReload => 0,
+            ChannelState::WaitingForGate => 1,
+            ChannelState::WaitingForLoadCycle => 2,
+            ChannelState::WaitingForLoadTrigger => 3,
+            ChannelState::Counting => 4
+        });
+        w.write_u32(self.cycles_in_state);
+        w.write_u16(*self.count_register.get());
+        w.write_bool(self.load_state == LoadState::WaitingForMsb);
+        w.write_bool(self.load_type == LoadType::SubsequentLoad);
+        w.write_u16(self.load_mask);
+        w.write_u16(*self.counting_element.get());
+        w.write_bool(self.ce_undefined);
+        w.write_bool(self.armed);
+        w.write_bool(self.read_state == ReadState::ReadLsb);
+        w.write_bool(self.count_is_latched);
+        w.write_bool(*self.output.get());
+        w.write_bool(self.output_on_reload);
+        w.write_bool(self.reload_on_trigger);
+        w.write_u16(*self.output_latch.get());
+        w.write_bool(self.bcd_mode);
+        w.write_bool(*self.gate.get());
+        w.write_bool(self.incomplete_reload);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        let mode = r.read_u8()?;
+        if mode > 5 {
+            return Err(r.invalid("bad channel mode"))
+        }
+        self.mode.update(ChannelMode::from(mode));
+        self.rw_mode.update(match r.read_u8()? {
+            1 => RwMode::Lsb,
+            2 => RwMode::Msb,
+            3 => RwMode::LsbMsb,
+            _ => return Err(r.invalid("bad rw mode"))
+        });
+        self.channel_state = match r.read_u8()? {
+            0 => ChannelState::WaitingForReload,
+            1 => ChannelState::WaitingForGate,
+            2 => ChannelState::WaitingForLoadCycle,
+            3 => ChannelState::WaitingForLoadTrigger,
+            4 => ChannelState::Counting,
+            _ => return Err(r.invalid("bad channel state"))
+        };
+        self.cycles_in_state = r.read_u32()?;
+        self.count_register.update(r.read_u16()?);
+        self.load_state = match r.read_bool()? {
+            true => LoadState::WaitingForMsb,
+            false => LoadState::WaitingForLsb
+        };
+        self.load_type = match r.read_bool()? {
+            true => LoadType::SubsequentLoad,
+            false => LoadType::InitialLoad
+        };
+        self.load_mask = r.read_u16()?;
+        self.counting_element.update(r.read_u16()?);
+        self.ce_undefined = r.read_bool()?;
+        self.armed = r.read_bool()?;
+        self.read_state = match r.read_bool()? {
+            true => ReadState::ReadLsb,
+            false => ReadState::NoRead
+        };
+        self.count_is_latched = r.read_bool()?;
+        self.output.update(r.read_bool()?);
+        self.output_on_reload = r.read_bool()?;
+        self.reload_on_trigger = r.read_bool()?;
+        self.output_latch.update(r.read_u16()?);
+        self.bcd_mode = r.read_bool()?;
+        self.gate.update(r.read_bool()?);
+        self.incomplete_reload = r.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for ProgrammableIntervalTimer {
+    const STATE_ID: &'static str = "pit";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u64(self.pit_cycles);
+        w.write_u32(self.sys_tick_accumulator);
+        w.write_f64(self.cycle_accumulator);
+        w.write_u8(self.channels.len() as u8);
+        for channel in &self.channels {
+            channel.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pit_cycles = r.read_u64()?;
+        self.sys_tick_accumulator = r.read_u32()?;
+        self.cycle_accumulator = r.read_f64()?;
+        if r.read_u8()? as usize != self.channels.len() {
+            return Err(r.invalid("wrong number of channels"))
+        }
+        for channel in &mut self.channels {
+            channel.load_state(r)?;
+        }
+        self.speaker_buf.clear();
+        Ok(())
+    }
+}
@@ -31,13 +31,20 @@
     Other than reporting DIP switch status and other system information the 
     PPI acts as the interface for the PC/XT keyboard. We emulate the keyboard 
     through the PPI.
+
+    The PCjr has no DIP switches and a different keyboard interface. Its 
+    keyboard sends each scancode as a bi-phase serial bit stream, which the 
+    BIOS samples on PC6 from an NMI handler. The NMI enable and keyboard 
+    latch are at port A0h, which we also implement here.
 */
 #![allow(dead_code)]
 
 use std::cell::Cell;
+use std::collections::VecDeque;
 
 use crate::config::{MachineType, VideoType};
 use crate::bus::{BusInterface, IoDevice, NO_IO_BYTE, DeviceRunTimeUnit};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use crate::devices::pic;
 
 pub const PPI_PORT_A: u16 = 0x60;
@@ -45,6 +52,26 @@ pub const PPI_PORT_B: u16 = 0x61;
 pub const PPI_PORT_C: u16 = 0x62;
 pub const PPI_COMMAND_PORT: u16 = 0x63;
 
+// PCjr NMI control register. Writing bit 7 enables NMI; reading clears the keyboard latch.
+pub const PCJR_NMI_PORT: u16 = 0xA0;
+pub const PCJR_NMI_ENABLE: u8 = 0b1000_0000;
+// Selects whether timer 1 is clocked by the output of timer 0 (unimplemented).
+pub const PCJR_TIMER1_SELECT: u8 = 0b0010_0000;
+
+// PCjr port C inputs
+pub const PCJR_PORTC_KB_LATCHED: u8   = 0b0000_0001;
+pub const PCJR_PORTC_NO_MODEM: u8     = 0b0000_0010;
+pub const PCJR_PORTC_NO_DISKETTE: u8  = 0b0000_0100;
+pub const PCJR_PORTC_NO_64K_CARD: u8  = 0b0000_1000;
+pub const PCJR_PORTC_KB_DATA: u8      = 0b0100_0000;
+
+// The PCjr keyboard sends a bit every 440us. Each bit cell is split into two halves of opposite 
+// levels: a 1 is sent high then low, a 0 low then high.
+pub const PCJR_KB_HALF_CELL_US: f64 = 220.0;
+// A start bit, 8 data bits and an odd parity bit, followed by 11 stop bit times of idle line.
+const PCJR_KB_CELLS: usize = 42;
+const PCJR_KB_QUEUE_LEN: usize = 16;
+
 pub const KB_RESET_US: f64 = 10_000.0; // Time with clock line pulled low before kb is reset - 10ms
 pub const KB_RESET_DELAY_US: f64 = 1000.0; // Delay period between detecting reset and sending reset byte - 1ms
 
@@ -108,8 +135,16 @@ pub const SW2_RAM_608K: u8       = 0b0000_1110;
 pub const SW2_RAM_640K: u8       = 0b0000_1101;
 pub const SW2_5: u8              = 0b0001_0000;
 
-// Above constants are not used yet, this controls the actual RAM amount (inverted DIP)
-pub const SW2_RAM_TEST: u8       = 0b1111_0010; // 640K
+// SW2 switches 6-8 are unused and read high (off).
+pub const SW2_UNUSED: u8         = 0b1110_0000;
+
+// Motherboard memory bank sizes. The 5150 switch settings follow the 16-64K motherboard, 
+// the 5160's the 64-256K motherboard. Both have four banks.
+pub const PC_RAM_BANK_SIZE: u32  = 0x4000;
+pub const XT_RAM_BANK_SIZE: u32  = 0x10000;
+pub const RAM_BANKS: u32         = 4;
+// SW2 switches 1-5 count the memory on expansion cards in 32K units.
+pub const SW2_RAM_UNIT: u32      = 0x8000;
 
 // PORT B INPUTS
 pub const PORTB_TIMER2_GATE: u8  = 0b0000_0001;
@@ -130,14 +165,16 @@ pub const PORTB_PRESENT_SW1_PORTA: u8  = 0b1000_0000;
 #[derive(Debug)]
 pub enum PortAMode {
     SwitchBlock1,
-    KeyboardByte
+    KeyboardByte,
+    Latch
 }
 #[derive(Debug)]
 pub enum PortCMode {
     Switch2OneToFour,
     Switch2Five,
     Switch1OneToFour,
-    Switch1FiveToEight
+    Switch1FiveToEight,
+    PcjrStatus
 }
 pub struct Ppi {
     machine_type: MachineType,
@@ -158,6 +195,13 @@ pub struct Ppi {
     dip_sw2: u8,
     timer_in: bool,
     speaker_in: bool,
+    port_a_latch: u8,
+    nmi_enable: bool,
+    kb_queue: VecDeque<u8>,
+    kb_latched: bool,
+    kb_cells: [bool; PCJR_KB_CELLS],
+    kb_cell_pos: usize,
+    kb_cell_accum: f64,
 }
 
 // This structure implements an interface for wires connected to the PPI from 
@@ -171,7 +215,7 @@ pub struct PpiWires {
     speaker_monitor: Cell<bool>,
 }
 
-#[derive(Default)]
+#[derive(Default, Debug)]
 pub struct PpiStringState {
     pub port_a_mode: String,
     pub port_a_value_bin: String,
@@ -185,7 +229,7 @@ pub struct PpiStringState {
 
 impl Ppi {
 
-    pub fn new(machine_type: MachineType, video_type: VideoType, num_floppies: u32 ) -> Self {
+    pub fn new(machine_type: MachineType, video_type: VideoType, num_floppies: u32, conventional_ram: u32) -> Self {
 
         let sw1_floppy_bits = match num_floppies {
             1 => SW1_ONE_FLOPPY,
@@ -201,11 +245,14 @@ impl Ppi {
             VideoType::EGA | VideoType::VGA => SW1_HAVE_EXPANSION
         };
 
+        let (sw1_ram_bits, sw2_ram_bits) = memory_switches(machine_type, conventional_ram);
+
         Self {
             machine_type,
             port_a_mode: match machine_type {
                 MachineType::IBM_PC_5150 => PortAMode::SwitchBlock1,
                 MachineType::IBM_XT_5160 => PortAMode::KeyboardByte,
+                MachineType::IBM_PCJR_4860 => PortAMode::Latch,
                 _ => {
                     panic!("Machine type: {:?} has no PPI", machine_type);
                 }
@@ -213,6 +260,7 @@ impl Ppi {
             port_c_mode: match machine_type {
                 MachineType::IBM_PC_5150 => PortCMode::Switch2OneToFour,
                 MachineType::IBM_XT_5160 => PortCMode::Switch1FiveToEight,
+                MachineType::IBM_PCJR_4860 => PortCMode::PcjrStatus,
                 _ => {
                     panic!("Machine type: {:?} has no PPI", machine_type);
                 }
@@ -230,23 +278,48 @@ impl Ppi {
             kb_enabled: true,
             dip_sw1: match machine_type {
                 MachineType::IBM_PC_5150 => {
-                    SW1_HAS_FLOPPIES | SW1_RAM_BANKS | sw1_floppy_bits | sw1_video_bits
+                    SW1_HAS_FLOPPIES | sw1_ram_bits | sw1_floppy_bits | sw1_video_bits
                 },
                 MachineType::IBM_XT_5160 => {
-                    SW1_HAS_FLOPPIES | SW1_RAM_BANKS | sw1_floppy_bits | sw1_video_bits                 
+                    SW1_HAS_FLOPPIES | sw1_ram_bits | sw1_floppy_bits | sw1_video_bits                 
                 },
+                MachineType::IBM_PCJR_4860 => 0,
                 _ => {
                     log::error!("Machine type: {:?} has no PPI", machine_type);
                     0
                 }
             },
-            dip_sw2: SW2_RAM_TEST,
+            dip_sw2: SW2_UNUSED | sw2_ram_bits,
             timer_in: false,
-            speaker_in: false
+            speaker_in: false,
+            port_a_latch: 0,
+            nmi_enable: false,
+            kb_queue: VecDeque::new(),
+            kb_latched: false,
+            kb_cells: [false; PCJR_KB_CELLS],
+            kb_cell_pos: 0,
+            kb_cell_accum: 0.0,
         }
     }
 }
 
+/// Return the SW1 memory bank bits and SW2 expansion memory bits describing the specified amount
+/// of conventional memory. The motherboard is filled first and the remainder is on expansion 
+/// cards. The 5160 BIOS sizes expansion memory itself, so it only reads the bank switches.
+pub fn memory_switches(machine_type: MachineType, conventional_ram: u32) -> (u8, u8) {
+    let bank_size = match machine_type {
+        MachineType::IBM_XT_5160 => XT_RAM_BANK_SIZE,
+        _ => PC_RAM_BANK_SIZE,
+    };
+    let planar = conventional_ram.min(bank_size * RAM_BANKS);
+    let banks = (planar / bank_size).max(1);
+    let expansion_units = (conventional_ram - planar) / SW2_RAM_UNIT;
+
+    let sw1_bits = (((banks - 1) as u8) << 2) & SW1_RAM_BANKS;
+    let sw2_bits = expansion_units.min(0x1F) as u8;
+    (sw1_bits, sw2_bits)
+}
+
 impl IoDevice for Ppi {
     fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
         //log::trace!("PPI Read from port: {:04X}", port);
@@ -257,6 +330,7 @@ impl IoDevice for Ppi {
                 // PPI PB7 supresses keyboard shift register output.
                 match self.port_a_mode {
                     PortAMode::SwitchBlock1 => self.dip_sw1,
+                    PortAMode::Latch => self.port_a_latch,
                     PortAMode::KeyboardByte => {
                         if self.kb_enabled {
                             self.kb_byte
@@ -275,7 +349,13 @@ impl IoDevice for Ppi {
             },
             PPI_COMMAND_PORT => {
                 NO_IO_BYTE
-            }            
+            }
+            PCJR_NMI_PORT => {
+                // Reading the NMI port clears the keyboard latch, allowing the next scancode 
+                // to be sent.
+                self.kb_latched = false;
+                NO_IO_BYTE
+            }
             _ => panic!("PPI: Bad port #")
         }
     }
@@ -283,7 +363,11 @@ impl IoDevice for Ppi {
     fn write_u8(&mut self, port: u16, byte: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
         match port {
             PPI_PORT_A => {
-                // Read-only port
+                // Read-only port, except on the PCjr where it is a latch. The PCjr BIOS stores 
+                // the scancode it has deserialized here for the keyboard interrupt handler.
+                if let PortAMode::Latch = self.port_a_mode {
+                    self.port_a_latch = byte;
+                }
             },
             PPI_PORT_B => {
                 //log::trace!("PPI: Write to Port B: {:02X}", byte);
@@ -295,17 +379,27 @@ impl IoDevice for Ppi {
             PPI_COMMAND_PORT => {
                 self.handle_command_port_write(byte);
             }
+            PCJR_NMI_PORT => {
+                self.nmi_enable = byte & PCJR_NMI_ENABLE != 0;
+                if byte & PCJR_TIMER1_SELECT != 0 {
+                    log::trace!("PPI: PCjr timer 1 clock select is not implemented");
+                }
+            }
             _ => panic!("PPI: Bad port #")
         }
     }
 
     fn port_list(&self) -> Vec<u16> {
-        vec![
+        let mut ports = vec![
             PPI_PORT_A,
             PPI_PORT_B,
             PPI_PORT_C,
             PPI_COMMAND_PORT,
-        ]
+        ];
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            ports.push(PCJR_NMI_PORT);
+        }
+        ports
     }
 }
 
@@ -368,6 +462,11 @@ impl Ppi {
                 }
                 self.port_a_mode = PortAMode::KeyboardByte;
             }
+            MachineType::IBM_PCJR_4860 => {
+                // On the PCjr, port B selects the sound source and controls the cassette motor.
+                // The keyboard is not reset through the PPI, so we are done.
+                return
+            }
             _ => {
                 panic!("Invalid model type for PPI");
             }
@@ -396,6 +495,13 @@ impl Ppi {
 
     /// Send a byte to the keyboard shift register.
     pub fn send_keyboard(&mut self, byte: u8 ) {
+        // The PCjr keyboard has its own buffer, as a scancode takes several milliseconds to send.
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            if self.kb_queue.len() < PCJR_KB_QUEUE_LEN {
+                self.kb_queue.push_back(byte);
+            }
+            return
+        }
         // Only send a scancode if the keyboard is not actively being reset.
         if self.kb_enabled && self.ksr_cleared && !self.kb_clock_low {
             self.ksr_cleared = false;
@@ -404,10 +510,59 @@ impl Ppi {
     }
 
     /// Return whether the keyboard enable line (PB7) is set and the keyboard clock line is not held low.
+    /// The PCjr keyboard never interrupts on IRQ1; it signals the CPU through NMI.
     pub fn kb_enabled(&self) -> bool {
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            return false
+        }
         self.kb_enabled && !self.kb_clock_low
     }
 
+    /// Return the state of the PCjr keyboard's NMI request, or None if this machine's keyboard 
+    /// does not use NMI. The request is held until the BIOS clears the keyboard latch.
+    pub fn keyboard_nmi(&self) -> Option<bool> {
+        match self.machine_type {
+            MachineType::IBM_PCJR_4860 => Some(self.kb_latched),
+            _ => None
+        }
+    }
+
+    /// Begin sending the next queued scancode to the PCjr, encoding it into bi-phase half cells.
+    fn start_pcjr_scancode(&mut self, byte: u8) {
+        let mut bits = vec![true];
+        bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+        // Odd parity
+        bits.push(byte.count_ones() % 2 == 0);
+
+        self.kb_cells = [false; PCJR_KB_CELLS];
+        for (i, bit) in bits.iter().enumerate() {
+            self.kb_cells[i * 2] = *bit;
+            self.kb_cells[i * 2 + 1] = !*bit;
+        }
+        self.kb_cell_pos = 1;
+        self.kb_latched = true;
+    }
+
+    /// Return the current level of the PCjr keyboard data line.
+    fn pcjr_kb_data(&self) -> bool {
+        self.kb_cell_pos > 0 && self.kb_cells[self.kb_cell_pos - 1]
+    }
+
+    /// Advance the PCjr keyboard serial line by one half cell.
+    fn tick_pcjr_keyboard(&mut self) {
+        if self.kb_cell_pos > 0 {
+            self.kb_cell_pos += 1;
+            if self.kb_cell_pos > PCJR_KB_CELLS {
+                self.kb_cell_pos = 0;
+            }
+        }
+        else if !self.kb_latched {
+            if let Some(byte) = self.kb_queue.pop_front() {
+                self.start_pcjr_scancode(byte);
+            }
+        }
+    }
+
     pub fn calc_port_c_value(&self) -> u8 {
 
         let mut speaker_bit = 0;
@@ -435,12 +590,33 @@ impl Ppi {
                 // On 5160, all four switches 5-8 are readable
                 (self.dip_sw1 >> 4 & 0x0F) | speaker_bit | timer_bit             
             }
+            (MachineType::IBM_PCJR_4860, PortCMode::PcjrStatus) => {
+                // Report no modem or diskette adapter, and the 64K memory expansion installed.
+                let mut byte = PCJR_PORTC_NO_MODEM | PCJR_PORTC_NO_DISKETTE | timer_bit;
+                if self.kb_latched {
+                    byte |= PCJR_PORTC_KB_LATCHED;
+                }
+                if self.pcjr_kb_data() {
+                    byte |= PCJR_PORTC_KB_DATA;
+                }
+                byte
+            }
             _=> {
                 panic!("Invalid PPI state");
             }
         }
     }
 
+    /// Override the values of the DIP switch blocks, as read from the PPI.
+    pub fn set_dip_switches(&mut self, sw1: Option<u8>, sw2: Option<u8>) {
+        if let Some(sw1) = sw1 {
+            self.dip_sw1 = sw1;
+        }
+        if let Some(sw2) = sw2 {
+            self.dip_sw2 = sw2;
+        }
+    }
+
     pub fn get_string_state(&self) -> PpiStringState {
         
         let port_a_value = match self.port_a_mode {
@@ -450,6 +626,9 @@ impl Ppi {
             PortAMode::KeyboardByte => {
                 self.kb_byte
             }
+            PortAMode::Latch => {
+                self.port_a_latch
+            }
         };
         let port_b_value = self.pb_byte;
         let port_c_value = self.calc_port_c_value();
@@ -488,11 +667,39 @@ impl Ppi {
 
     /// Return whether NMI generation is enabled
     pub fn nmi_enabled(&self) -> bool {
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            return self.nmi_enable
+        }
         self.pb_byte & PORTB_PARITY_MB_EN == 0 || self.pb_byte & PORTB_PARITY_EX_EN == 0
     }
 
+    /// Return the time in microseconds until the keyboard may next interrupt the CPU without 
+    /// further IO, or None if it will not.
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            // The next queued scancode is latched, raising NMI, on a half cell tick.
+            if !self.kb_latched && !self.kb_queue.is_empty() {
+                return Some((PCJR_KB_HALF_CELL_US - self.kb_cell_accum).max(0.0))
+            }
+            return None
+        }
+        if self.kb_do_reset {
+            return Some((KB_RESET_DELAY_US - self.kb_count_until_reset_byte).max(0.0))
+        }
+        None
+    }
+
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64 ) {
 
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            self.kb_cell_accum += us;
+            while self.kb_cell_accum >= PCJR_KB_HALF_CELL_US {
+                self.kb_cell_accum -= PCJR_KB_HALF_CELL_US;
+                self.tick_pcjr_keyboard();
+            }
+            return
+        }
+
         // Our keyboard byte was read, so clear the interrupt request line and reset the byte
         // read at the keyboard IO port to 0
         if self.keyboard_clear_scheduled {
@@ -529,4 +736,77 @@ impl Ppi {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for Ppi {
+    const STATE_ID: &'static str = "ppi";
+    const STATE_VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.pb_byte);
+        w.write_u8(self.kb_byte);
+        w.write_bool(self.kb_clock_low);
+        w.write_bool(self.kb_counting_low);
+        w.write_f64(self.kb_low_count);
+        w.write_bool(self.kb_do_reset);
+        w.write_f64(self.kb_count_until_reset_byte);
+        w.write_u32(self.kb_resets_counter);
+        w.write_bool(self.keyboard_clear_scheduled);
+        w.write_bool(self.ksr_cleared);
+        w.write_bool(self.kb_enabled);
+        w.write_bool(self.timer_in);
+        w.write_bool(self.speaker_in);
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            w.write_u8(self.port_a_latch);
+            w.write_bool(self.nmi_enable);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        // Writing port B sets the port A and C modes. The keyboard state it affects is 
+        // overwritten below.
+        self.handle_portb_write(r.read_u8()?);
+
+        self.kb_byte = r.read_u8()?;
+        self.kb_clock_low = r.read_bool()?;
+        self.kb_counting_low = r.read_bool()?;
+        self.kb_low_count = r.read_f64()?;
+        self.kb_do_reset = r.read_bool()?;
+        self.kb_count_until_reset_byte = r.read_f64()?;
+        self.kb_resets_counter = r.read_u32()?;
+        self.keyboard_clear_scheduled = r.read_bool()?;
+        self.ksr_cleared = r.read_bool()?;
+        self.kb_enabled = r.read_bool()?;
+        self.timer_in = r.read_bool()?;
+        self.speaker_in = r.read_bool()?;
+        if let MachineType::IBM_PCJR_4860 = self.machine_type {
+            self.port_a_latch = r.read_u8()?;
+            self.nmi_enable = r.read_bool()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_switches_5150() {
+        // 64K on the motherboard and 576K on expansion cards
+        assert_eq!(memory_switches(MachineType::IBM_PC_5150, 0xA0000), (SW1_RAM_BANKS, 18));
+        assert_eq!(memory_switches(MachineType::IBM_PC_5150, 0x10000), (SW1_RAM_BANKS, 0));
+        assert_eq!(memory_switches(MachineType::IBM_PC_5150, 0x40000), (SW1_RAM_BANKS, 6));
+
+        let ppi = Ppi::new(MachineType::IBM_PC_5150, VideoType::CGA, 2, 0x40000);
+        assert_eq!(ppi.dip_sw2, SW2_UNUSED | 6);
+    }
+
+    #[test]
+    fn test_memory_switches_5160() {
+        assert_eq!(memory_switches(MachineType::IBM_XT_5160, 0x10000), (0b0000_0000, 0));
+        assert_eq!(memory_switches(MachineType::IBM_XT_5160, 0x20000), (0b0000_0100, 0));
+        assert_eq!(memory_switches(MachineType::IBM_XT_5160, 0x40000), (SW1_RAM_BANKS, 0));
+        assert_eq!(memory_switches(MachineType::IBM_XT_5160, 0xA0000).0, SW1_RAM_BANKS);
+    }
+}
@@ -0,0 +1,354 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::rtc_card.rs
+
+    Implements a clock/calendar card based on the National Semiconductor 
+    MM58167A real time clock, as found on the AST SixPakPlus and many 
+    compatible multifunction cards. The chip occupies 32 ports from the 
+    base port (2C0h on the SixPakPlus) and is read by DOS clock drivers 
+    such as ASTCLOCK and CLOCK.SYS.
+
+    Counters are BCD. The chip has no year counter, so drivers keep the 
+    year in one of its RAM registers; the card initializes RAM register 0Ah
+    with the years since 1980 for drivers that keep it there.
+
+    The clock is set from host time on reset and then advances with 
+    emulated time. It can instead be set to a fixed time on every reset, 
+    so that runs are deterministic.
+
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+pub const RTC_CARD_DEFAULT_PORT: u16 = 0x2C0;
+
+const RTC_PORT_COUNT: u16 = 0x20;
+
+const REG_TEN_THOUSANDTHS: u16 = 0x00;
+const REG_HUNDREDTHS: u16 = 0x01;
+const REG_SECONDS: u16 = 0x02;
+const REG_MINUTES: u16 = 0x03;
+const REG_HOURS: u16 = 0x04;
+const REG_DAY_OF_WEEK: u16 = 0x05;
+const REG_DAY_OF_MONTH: u16 = 0x06;
+const REG_MONTH: u16 = 0x07;
+const REG_RAM_START: u16 = 0x08;
+const REG_RAM_END: u16 = 0x0F;
+const REG_INTERRUPT_STATUS: u16 = 0x10;
+const REG_INTERRUPT_CONTROL: u16 = 0x11;
+const REG_COUNTER_RESET: u16 = 0x12;
+const REG_RAM_RESET: u16 = 0x13;
+const REG_STATUS: u16 = 0x14;
+const REG_GO: u16 = 0x15;
+
+const REG_YEAR_RAM: u16 = 0x0A;
+const YEAR_BASE: i64 = 1980;
+
+const US_PER_SECOND: i64 = 1_000_000;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A calendar date and time of day.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Convert seconds since 1970-01-01 00:00:00 to a date and time.
+    pub fn from_unix_seconds(seconds: i64) -> Self {
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let secs = seconds.rem_euclid(SECONDS_PER_DAY) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+
+    /// Convert the date and time to seconds since 1970-01-01 00:00:00.
+    pub fn to_unix_seconds(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+    }
+
+    /// Parse a date and time of the form "YYYY-MM-DD HH:MM:SS". The time may be omitted.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.trim().splitn(2, [' ', 'T']);
+        let date: Vec<&str> = parts.next()?.split('-').collect();
+        let time: Vec<&str> = parts.next().map(|t| t.split(':').collect()).unwrap_or_default();
+        if date.len() != 3 || time.len() > 3 {
+            return None
+        }
+        let field = |list: &[&str], i: usize| -> Option<u32> {
+            list.get(i).map_or(Some(0), |s| s.parse().ok())
+        };
+        let datetime = Self {
+            year: date[0].parse().ok()?,
+            month: field(&date, 1)?,
+            day: field(&date, 2)?,
+            hour: field(&time, 0)?,
+            minute: field(&time, 1)?,
+            second: field(&time, 2)?,
+        };
+        let valid = (1..=12).contains(&datetime.month)
+            && datetime.day >= 1 && datetime.day <= days_in_month(datetime.year, datetime.month)
+            && datetime.hour < 24 && datetime.minute < 60 && datetime.second < 60;
+        valid.then_some(datetime)
+    }
+
+    /// Return the day of the week, from 1 for Sunday to 7 for Saturday.
+    pub fn day_of_week(&self) -> u32 {
+        // 1970-01-01 was a Thursday.
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as u32 + 1
+    }
+}
+
+// Date conversions from Howard Hinnant's public domain algorithms.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u32 {
+    (value >> 4) as u32 * 10 + (value & 0x0F) as u32
+}
+
+/// Where the clock is set from on reset.
+#[derive (Copy, Clone, Debug)]
+pub enum RtcTimeSource {
+    /// Host time, adjusted by an offset from UTC in minutes.
+    Host(i32),
+    /// A fixed time, for deterministic runs.
+    Fixed(DateTime),
+}
+
+pub struct RtcCard {
+    base_port: u16,
+    source: RtcTimeSource,
+    // Microseconds since 1970-01-01 00:00:00 in guest time.
+    time_us: i64,
+    accum: f64,
+    ram: [u8; 8],
+    interrupt_control: u8,
+}
+
+impl RtcCard {
+    pub fn new(base_port: u16, source: RtcTimeSource) -> Self {
+        let mut card = Self {
+            base_port,
+            source,
+            time_us: 0,
+            accum: 0.0,
+            ram: [0; 8],
+            interrupt_control: 0,
+        };
+        card.reset();
+        card
+    }
+
+    pub fn reset(&mut self) {
+        let seconds = match self.source {
+            RtcTimeSource::Host(offset_minutes) => {
+                let unix = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0);
+                unix + offset_minutes as i64 * 60
+            }
+            RtcTimeSource::Fixed(datetime) => datetime.to_unix_seconds(),
+        };
+        self.time_us = seconds * US_PER_SECOND;
+        self.accum = 0.0;
+        self.ram = [0; 8];
+        self.ram[(REG_YEAR_RAM - REG_RAM_START) as usize] = to_bcd((self.datetime().year - YEAR_BASE).clamp(0, 99) as u32);
+        self.interrupt_control = 0;
+    }
+
+    pub fn datetime(&self) -> DateTime {
+        DateTime::from_unix_seconds(self.time_us.div_euclid(US_PER_SECOND))
+    }
+
+    fn set_datetime(&mut self, datetime: DateTime) {
+        let fraction = self.time_us.rem_euclid(US_PER_SECOND);
+        self.time_us = datetime.to_unix_seconds() * US_PER_SECOND + fraction;
+    }
+
+    /// Advance the clock by the specified number of microseconds.
+    pub fn run(&mut self, us: f64) {
+        self.accum += us;
+        let whole = self.accum.floor();
+        self.accum -= whole;
+        self.time_us += whole as i64;
+    }
+
+    /// Set one of the time counters from a BCD value written by the guest.
+    fn write_counter(&mut self, register: u16, data: u8) {
+        let mut datetime = self.datetime();
+        let value = from_bcd(data);
+        match register {
+            REG_HUNDREDTHS => {
+                let seconds = self.time_us.div_euclid(US_PER_SECOND);
+                self.time_us = seconds * US_PER_SECOND + value.min(99) as i64 * 10_000;
+                return
+            }
+            REG_SECONDS if value < 60 => datetime.second = value,
+            REG_MINUTES if value < 60 => datetime.minute = value,
+            REG_HOURS if value < 24 => datetime.hour = value,
+            REG_DAY_OF_MONTH if value >= 1 && value <= days_in_month(datetime.year, datetime.month) => {
+                datetime.day = value
+            }
+            REG_MONTH if (1..=12).contains(&value) => {
+                datetime.month = value;
+                datetime.day = datetime.day.min(days_in_month(datetime.year, value));
+            }
+            _ => {
+                log::trace!("RTC card: ignored write of {:02X} to register {:02X}", data, register);
+                return
+            }
+        }
+        self.set_datetime(datetime);
+    }
+}
+
+impl IoDevice for RtcCard {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        let datetime = self.datetime();
+        let fraction_us = self.time_us.rem_euclid(US_PER_SECOND);
+        match port.wrapping_sub(self.base_port) {
+            REG_TEN_THOUSANDTHS => ((fraction_us / 100 % 10) as u8) << 4,
+            REG_HUNDREDTHS => to_bcd((fraction_us / 10_000) as u32),
+            REG_SECONDS => to_bcd(datetime.second),
+            REG_MINUTES => to_bcd(datetime.minute),
+            REG_HOURS => to_bcd(datetime.hour),
+            REG_DAY_OF_WEEK => to_bcd(datetime.day_of_week()),
+            REG_DAY_OF_MONTH => to_bcd(datetime.day),
+            REG_MONTH => to_bcd(datetime.month),
+            register @ REG_RAM_START..=REG_RAM_END => self.ram[(register - REG_RAM_START) as usize],
+            REG_INTERRUPT_STATUS => 0,
+            REG_INTERRUPT_CONTROL => self.interrupt_control,
+            // No counter rolled over during the read.
+            REG_STATUS => 0,
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port.wrapping_sub(self.base_port) {
+            register @ REG_TEN_THOUSANDTHS..=REG_MONTH => self.write_counter(register, data),
+            register @ REG_RAM_START..=REG_RAM_END => self.ram[(register - REG_RAM_START) as usize] = data,
+            REG_INTERRUPT_CONTROL => self.interrupt_control = data,
+            REG_COUNTER_RESET if data == 0xFF => {
+                // Reset all counters to their lowest value: January 1st, midnight.
+                let datetime = DateTime { month: 1, day: 1, hour: 0, minute: 0, second: 0, ..self.datetime() };
+                self.time_us = datetime.to_unix_seconds() * US_PER_SECOND;
+            }
+            REG_RAM_RESET if data == 0xFF => self.ram = [0; 8],
+            REG_GO => {
+                // Restart the second from zero.
+                self.time_us -= self.time_us.rem_euclid(US_PER_SECOND);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (self.base_port..self.base_port + RTC_PORT_COUNT).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_conversions() {
+        let datetime = DateTime::parse("1987-04-02 13:45:07").unwrap();
+        assert_eq!(DateTime::from_unix_seconds(datetime.to_unix_seconds()), datetime);
+        assert_eq!(DateTime::from_unix_seconds(0).day_of_week(), 5);
+        assert_eq!(DateTime::parse("2000-02-29").unwrap().to_unix_seconds(), 951_782_400);
+        assert!(DateTime::parse("1999-02-29").is_none());
+    }
+
+    #[test]
+    fn fixed_time_runs_with_emulated_time() {
+        let start = DateTime::parse("1985-12-31 23:59:59").unwrap();
+        let mut card = RtcCard::new(RTC_CARD_DEFAULT_PORT, RtcTimeSource::Fixed(start));
+        let nul_delta = DeviceRunTimeUnit::Microseconds(0.0);
+
+        assert_eq!(card.read_u8(RTC_CARD_DEFAULT_PORT + REG_SECONDS, nul_delta), 0x59);
+        assert_eq!(card.read_u8(RTC_CARD_DEFAULT_PORT + REG_YEAR_RAM, nul_delta), 0x05);
+
+        card.run(1_500_000.0);
+        assert_eq!(card.read_u8(RTC_CARD_DEFAULT_PORT + REG_MONTH, nul_delta), 0x01);
+        assert_eq!(card.read_u8(RTC_CARD_DEFAULT_PORT + REG_HUNDREDTHS, nul_delta), 0x50);
+
+        card.write_u8(RTC_CARD_DEFAULT_PORT + REG_MINUTES, 0x30, None, nul_delta);
+        assert_eq!(card.datetime().minute, 30);
+    }
+}
@@ -0,0 +1,491 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::sb.rs
+
+    Implements the digital audio portion of a Sound Blaster 2.0 (DSP version
+    2.01).
+
+    The DSP is fixed at the factory default resources of base port 220h,
+    IRQ 7 and DMA channel 1. Only 8-bit PCM playback is supported; ADPCM and
+    recording commands are acknowledged but produce silence.
+
+    The FM portion of the card is not implemented here - enable the AdLib
+    card for FM music.
+*/
+
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+use crate::devices::dma;
+
+pub const SB_BASE_PORT: u16 = 0x220;
+pub const SB_RESET_PORT: u16 = SB_BASE_PORT + 0x06;
+pub const SB_READ_DATA_PORT: u16 = SB_BASE_PORT + 0x0A;
+pub const SB_WRITE_PORT: u16 = SB_BASE_PORT + 0x0C;
+pub const SB_READ_STATUS_PORT: u16 = SB_BASE_PORT + 0x0E;
+
+pub const SB_IRQ: u8 = 7;
+pub const SB_DMA: usize = 1;
+
+/// Adjust the Sound Blaster's output relative to the PC speaker.
+pub const SB_VOLUME: f32 = 0.25;
+
+const DSP_VERSION_MAJOR: u8 = 0x02;
+const DSP_VERSION_MINOR: u8 = 0x01;
+const DSP_RESET_ACK: u8 = 0xAA;
+
+const DSP_STATUS_READY: u8 = 0b1000_0000;
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum PlaybackMode {
+    Off,
+    Dma,
+    Silence
+}
+
+pub struct SoundBlaster {
+    reset_pending: bool,
+    command: Option<u8>,
+    params: Vec<u8>,
+    output_queue: VecDeque<u8>,
+    last_read: u8,
+    test_register: u8,
+
+    speaker_on: bool,
+    time_constant: u8,
+    block_size: u16,
+
+    mode: PlaybackMode,
+    auto_init: bool,
+    high_speed: bool,
+    paused: bool,
+    exit_auto_init: bool,
+    samples_remaining: u32,
+    sample_accum: f64,
+
+    dac_value: u8,
+
+    send_interrupt: bool,
+    end_interrupt: bool,
+    pending_interrupt: bool,
+}
+
+impl IoDevice for SoundBlaster {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port {
+            SB_READ_DATA_PORT => {
+                if let Some(byte) = self.output_queue.pop_front() {
+                    self.last_read = byte;
+                }
+                self.last_read
+            }
+            SB_WRITE_PORT => {
+                // Bit 7 clear indicates the DSP is ready to accept a command. We are always ready.
+                !DSP_STATUS_READY
+            }
+            SB_READ_STATUS_PORT => {
+                // Reading the read-buffer status port acknowledges the 8-bit DMA interrupt.
+                if self.pending_interrupt {
+                    self.end_interrupt = true;
+                }
+                if self.output_queue.is_empty() {
+                    !DSP_STATUS_READY
+                }
+                else {
+                    0xFF
+                }
+            }
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port {
+            SB_RESET_PORT => {
+                if data & 0x01 != 0 {
+                    self.reset_pending = true;
+                }
+                else if self.reset_pending {
+                    self.reset();
+                    self.output_queue.push_back(DSP_RESET_ACK);
+                }
+            }
+            SB_WRITE_PORT => {
+                self.handle_write(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        vec![
+            SB_RESET_PORT,
+            SB_READ_DATA_PORT,
+            SB_WRITE_PORT,
+            SB_READ_STATUS_PORT
+        ]
+    }
+}
+
+impl Default for SoundBlaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoundBlaster {
+    pub fn new() -> Self {
+        Self {
+            reset_pending: false,
+            command: None,
+            params: Vec::new(),
+            output_queue: VecDeque::new(),
+            last_read: 0xFF,
+            test_register: 0,
+
+            speaker_on: false,
+            time_constant: 0,
+            block_size: 0,
+
+            mode: PlaybackMode::Off,
+            auto_init: false,
+            high_speed: false,
+            paused: false,
+            exit_auto_init: false,
+            samples_remaining: 0,
+            sample_accum: 0.0,
+
+            dac_value: 0x80,
+
+            send_interrupt: false,
+            end_interrupt: false,
+            pending_interrupt: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        // Leave any raised interrupt to be lowered by run(), since we don't have the PIC here.
+        let pending_interrupt = self.pending_interrupt;
+        *self = SoundBlaster::new();
+        self.pending_interrupt = pending_interrupt;
+        self.end_interrupt = pending_interrupt;
+    }
+
+    /// Return the number of parameter bytes that follow the specified DSP command.
+    fn command_param_count(command: u8) -> usize {
+        match command {
+            0x10 | 0x38 | 0x40 | 0xE0 | 0xE4 => 1,
+            0x14 | 0x16 | 0x17 | 0x24 | 0x48 | 0x74..=0x77 | 0x80 => 2,
+            _ => 0
+        }
+    }
+
+    fn handle_write(&mut self, data: u8) {
+
+        // In high-speed mode the DSP ignores all commands until reset or end of block.
+        if self.high_speed && self.mode != PlaybackMode::Off {
+            return
+        }
+
+        match self.command {
+            None => {
+                self.command = Some(data);
+                self.params.clear();
+            }
+            Some(_) => {
+                self.params.push(data);
+            }
+        }
+
+        let command = self.command.unwrap();
+        if self.params.len() >= SoundBlaster::command_param_count(command) {
+            self.command = None;
+            self.execute_command(command);
+        }
+    }
+
+    fn param_u16(&self) -> u16 {
+        self.params[0] as u16 | (self.params[1] as u16) << 8
+    }
+
+    fn start_dma(&mut self, samples: u32, auto_init: bool, high_speed: bool) {
+        self.mode = PlaybackMode::Dma;
+        self.samples_remaining = samples;
+        self.auto_init = auto_init;
+        self.high_speed = high_speed;
+        self.exit_auto_init = false;
+        self.paused = false;
+        self.sample_accum = 0.0;
+        log::trace!(
+            "SB: Starting DMA playback of {} samples at {}Hz, auto-init: {} high-speed: {}",
+            samples,
+            self.sample_rate(),
+            auto_init,
+            high_speed
+        );
+    }
+
+    fn execute_command(&mut self, command: u8) {
+        match command {
+            0x10 => {
+                // Direct DAC output
+                self.dac_value = self.params[0];
+            }
+            0x14 => {
+                // 8-bit single-cycle DMA output
+                let len = self.param_u16() as u32 + 1;
+                self.start_dma(len, false, false);
+            }
+            0x16 | 0x17 | 0x74..=0x77 => {
+                // ADPCM DMA output. We don't decode ADPCM, but run the transfer so the
+                // completion interrupt still fires.
+                log::warn!("SB: ADPCM playback command {:02X} not supported", command);
+                let len = self.param_u16() as u32 + 1;
+                self.start_dma(len, false, false);
+            }
+            0x1C => {
+                // 8-bit auto-init DMA output
+                self.start_dma(self.block_size as u32 + 1, true, false);
+            }
+            0x1F | 0x7D | 0x7F => {
+                log::warn!("SB: ADPCM auto-init command {:02X} not supported", command);
+                self.start_dma(self.block_size as u32 + 1, true, false);
+            }
+            0x20 => {
+                // Direct ADC input. Return silence.
+                self.output_queue.push_back(0x80);
+            }
+            0x24 => {
+                log::warn!("SB: DMA ADC input not supported");
+            }
+            0x40 => {
+                self.time_constant = self.params[0];
+            }
+            0x48 => {
+                self.block_size = self.param_u16();
+            }
+            0x80 => {
+                // Output a block of silence, then interrupt.
+                self.mode = PlaybackMode::Silence;
+                self.samples_remaining = self.param_u16() as u32 + 1;
+                self.sample_accum = 0.0;
+            }
+            0x90 => {
+                // High-speed 8-bit auto-init DMA output
+                self.start_dma(self.block_size as u32 + 1, true, true);
+            }
+            0x91 => {
+                // High-speed 8-bit single-cycle DMA output
+                self.start_dma(self.block_size as u32 + 1, false, true);
+            }
+            0xD0 => {
+                self.paused = true;
+            }
+            0xD1 => {
+                self.speaker_on = true;
+            }
+            0xD3 => {
+                self.speaker_on = false;
+            }
+            0xD4 => {
+                self.paused = false;
+            }
+            0xD8 => {
+                self.output_queue.push_back(if self.speaker_on { 0xFF } else { 0x00 });
+            }
+            0xDA => {
+                // Exit auto-init mode after the current block completes.
+                self.exit_auto_init = true;
+            }
+            0xE0 => {
+                // DSP identification. Returns the inverted parameter byte.
+                self.output_queue.push_back(!self.params[0]);
+            }
+            0xE1 => {
+                self.output_queue.push_back(DSP_VERSION_MAJOR);
+                self.output_queue.push_back(DSP_VERSION_MINOR);
+            }
+            0xE4 => {
+                self.test_register = self.params[0];
+            }
+            0xE8 => {
+                self.output_queue.push_back(self.test_register);
+            }
+            0xF2 => {
+                // Force an 8-bit interrupt.
+                self.send_interrupt = true;
+            }
+            _ => {
+                log::warn!("SB: Unhandled DSP command: {:02X}", command);
+            }
+        }
+    }
+
+    /// Return the current playback rate in Hz, as set by the time constant.
+    pub fn sample_rate(&self) -> f64 {
+        1_000_000.0 / (256 - self.time_constant as u32) as f64
+    }
+
+    /// Return the period of one sample in microseconds.
+    fn sample_period(&self) -> f64 {
+        (256 - self.time_constant as u32) as f64
+    }
+
+    fn end_block(&mut self) {
+        self.send_interrupt = true;
+
+        if self.mode == PlaybackMode::Dma && self.auto_init && !self.exit_auto_init {
+            self.samples_remaining = self.block_size as u32 + 1;
+        }
+        else {
+            self.mode = PlaybackMode::Off;
+            self.auto_init = false;
+            self.exit_auto_init = false;
+            self.high_speed = false;
+        }
+    }
+
+    /// Run the Sound Blaster. DMA transfers are performed at the programmed sample rate.
+    /// Return the time in microseconds until the DSP may next raise an interrupt without 
+    /// further IO, or None if it will not.
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        if self.send_interrupt {
+            return Some(0.0)
+        }
+        if self.mode == PlaybackMode::Off || self.paused {
+            return None
+        }
+        // The block ends after its remaining samples have played.
+        Some((self.samples_remaining as f64 * self.sample_period() - self.sample_accum).max(0.0))
+    }
+
+    pub fn run(&mut self, dma: &mut dma::DMAController, bus: &mut BusInterface, us: f64) {
+
+        if self.send_interrupt {
+            bus.pic_mut().as_mut().unwrap().request_interrupt(SB_IRQ);
+            self.pending_interrupt = true;
+            self.send_interrupt = false;
+        }
+
+        if self.end_interrupt {
+            bus.pic_mut().as_mut().unwrap().clear_interrupt(SB_IRQ);
+            self.pending_interrupt = false;
+            self.end_interrupt = false;
+        }
+
+        if self.mode == PlaybackMode::Off || self.paused {
+            return
+        }
+
+        self.sample_accum += us;
+        let period = self.sample_period();
+
+        while self.sample_accum >= period && self.mode != PlaybackMode::Off {
+            self.sample_accum -= period;
+
+            if self.mode == PlaybackMode::Dma {
+                if !dma.check_dma_ready(SB_DMA) {
+                    // Channel is masked. The DSP will wait for DMA service.
+                    break;
+                }
+                self.dac_value = dma.do_dma_read_u8(bus, SB_DMA);
+            }
+
+            self.samples_remaining = self.samples_remaining.saturating_sub(1);
+            if self.samples_remaining == 0 {
+                self.end_block();
+            }
+        }
+    }
+
+    /// Return the current output of the DAC as a sample. The DAC holds its value between
+    /// writes, so this may be sampled at any rate.
+    pub fn generate_sample(&self) -> f32 {
+        if self.speaker_on {
+            (self.dac_value as f32 - 128.0) / 128.0
+        }
+        else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::Microseconds(0.0);
+
+    fn read_dsp(sb: &mut SoundBlaster) -> Option<u8> {
+        if sb.read_u8(SB_READ_STATUS_PORT, NO_DELTA) & DSP_STATUS_READY == 0 {
+            return None
+        }
+        Some(sb.read_u8(SB_READ_DATA_PORT, NO_DELTA))
+    }
+
+    #[test]
+    fn test_dsp_reset_and_version() {
+        let mut sb = SoundBlaster::new();
+        assert_eq!(read_dsp(&mut sb), None);
+
+        // Pulse the reset line, then the DSP acknowledges with AAh
+        sb.write_u8(SB_RESET_PORT, 0x01, None, NO_DELTA);
+        sb.write_u8(SB_RESET_PORT, 0x00, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(DSP_RESET_ACK));
+        assert_eq!(read_dsp(&mut sb), None);
+
+        // The DSP is always ready for commands
+        assert_eq!(sb.read_u8(SB_WRITE_PORT, NO_DELTA) & DSP_STATUS_READY, 0);
+
+        sb.write_u8(SB_WRITE_PORT, 0xE1, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(DSP_VERSION_MAJOR));
+        assert_eq!(read_dsp(&mut sb), Some(DSP_VERSION_MINOR));
+        assert_eq!(read_dsp(&mut sb), None);
+    }
+
+    #[test]
+    fn test_dsp_identification_and_speaker() {
+        let mut sb = SoundBlaster::new();
+
+        sb.write_u8(SB_WRITE_PORT, 0xE0, None, NO_DELTA);
+        sb.write_u8(SB_WRITE_PORT, 0x5A, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(0xA5));
+
+        sb.write_u8(SB_WRITE_PORT, 0xD1, None, NO_DELTA);
+        sb.write_u8(SB_WRITE_PORT, 0xD8, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(0xFF));
+
+        // A reset turns the speaker back off
+        sb.write_u8(SB_RESET_PORT, 0x01, None, NO_DELTA);
+        sb.write_u8(SB_RESET_PORT, 0x00, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(DSP_RESET_ACK));
+        sb.write_u8(SB_WRITE_PORT, 0xD8, None, NO_DELTA);
+        assert_eq!(read_dsp(&mut sb), Some(0x00));
+    }
+}
@@ -27,22 +27,27 @@
     devices::serial.rs
 
     Implements the IBM Asynchronous Communications Adapter based on the 
-    INS8250 Serial Controller chip.
+    INS8250 Serial Controller chip, or its successor the NS16450, which adds
+    a scratch register and supports baud rates up to 115200.
     
-    Two adapters are emulated, a primary and secondary controller.
+    Two adapters are emulated, a primary and secondary controller. Either 
+    may be bridged to a host serial port or a TCP socket; see 
+    serial_bridge.rs.
 
     Primary Documentation:
     IBM Publication 6361501
     "IBM Asynchronous Communications Adapter"
 */
 
-use std::{
-    io::Read, 
-    collections::VecDeque
-};
+use std::collections::VecDeque;
+
+use serde_derive::Deserialize;
 
 use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
 use crate::devices::pic;
+use crate::devices::serial_bridge::{
+    BridgeParity, BridgeStopBits, LineParams, ModemLines, SerialBridge, TcpTarget
+};
 
 /*  1.8Mhz Oscillator. 
     Divided by 16, then again by programmable Divisor to select baud rate.
@@ -70,6 +75,7 @@ pub const SERIAL1_LINE_CONTROL: u16 = 0x3FB;
 pub const SERIAL1_MODEM_CONTROL: u16 = 0x3FC;
 pub const SERIAL1_LINE_STATUS: u16 = 0x3FD;
 pub const SERIAL1_MODEM_STATUS: u16 = 0x3FE;
+pub const SERIAL1_SCRATCH: u16 = 0x3FF;
 
 pub const SERIAL2_RX_TX_BUFFER: u16 = 0x2F8;
 //pub const SERIAL2_DIVISOR_LATCH_LSB: u16 = 0x2F8;
@@ -80,24 +86,33 @@ pub const SERIAL2_LINE_CONTROL: u16 = 0x2FB;
 pub const SERIAL2_MODEM_CONTROL: u16 = 0x2FC;
 pub const SERIAL2_LINE_STATUS: u16 = 0x2FD;
 pub const SERIAL2_MODEM_STATUS: u16 = 0x2FE;
+pub const SERIAL2_SCRATCH: u16 = 0x2FF;
+
+/// The UART chip installed on the adapters.
+#[derive (Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+pub enum UartType {
+    #[default]
+    Ins8250,
+    Ns16450,
+}
 
 // Line Control Register constants
 const WORD_LENGTH_SELECT_MASK: u8 = 0b0000_0011;
 const STOP_BIT_SELECT_BIT: u8 = 0b0000_0100;
 const PARITY_ENABLE_BIT: u8 = 0b0000_1000;
+const EVEN_PARITY_SELECT_BIT: u8 = 0b0001_0000;
 const DIVISOR_LATCH_ACCESS_BIT: u8 = 0b1000_0000;
 
 // Line Status Register constants
 const STATUS_DATA_READY: u8 = 0b0000_0001;
-//const STATUS_OVERRUN_ERROR: u8 = 0b0000_0010;
+const STATUS_OVERRUN_ERROR: u8 = 0b0000_0010;
 //const STATUS_PARITY_ERROR: u8 = 0b0000_0100;
 //const STATUS_FRAMING_ERROR: u8 = 0b0000_1000;
 //const STATUS_BREAK_INTERRUPT: u8 = 0b0001_0000;
+const STATUS_ERROR_MASK: u8 = 0b0001_1110;
 const STATUS_TRANSMIT_EMPTY: u8 = 0b0010_0000;
 //const STATUS_TX_SHIFT_EMPTY: u8 = 0b0100_0000;
 
-const INTERRUPT_ID_MASK: u8 = 0b0000_0011;
-
 const INTERRUPT_DATA_AVAIL: u8 = 0b0000_0001;
 const INTERRUPT_TX_EMPTY: u8 = 0b0000_0010;
 const INTERRUPT_RX_LINE_STATUS: u8 = 0b0000_0100;
@@ -118,8 +133,8 @@ const MODEM_CONTROL_LOOP: u8 = 0b0001_0000;
 
 const MODEM_STATUS_DCTS: u8 = 0b0000_0001;
 const MODEM_STATUS_DDSR: u8 = 0b0000_0010;
-//const MODEM_STATUS_TERI: u8 = 0b0000_0100;
-//const MODEM_STATUS_DRLSD: u8 = 0b0000_1000;
+const MODEM_STATUS_TERI: u8 = 0b0000_0100;
+const MODEM_STATUS_DRLSD: u8 = 0b0000_1000;
 const MODEM_STATUS_CTS: u8 = 0b0001_0000;
 const MODEM_STATUS_DSR: u8 = 0b0010_0000;
 const MODEM_STATUS_RI: u8 = 0b0100_0000;
@@ -137,12 +152,14 @@ impl IoDevice for SerialPortController {
             SERIAL2_INTERRUPT_ID => self.port[1].interrupt_id_read(),
             SERIAL1_LINE_CONTROL => self.port[0].line_control_read(),
             SERIAL2_LINE_CONTROL => self.port[1].line_control_read(),
-            SERIAL1_MODEM_CONTROL => 0,
-            SERIAL2_MODEM_CONTROL => 0,
+            SERIAL1_MODEM_CONTROL => self.port[0].modem_control_reg,
+            SERIAL2_MODEM_CONTROL => self.port[1].modem_control_reg,
             SERIAL1_LINE_STATUS => self.port[0].line_status_read(),
             SERIAL2_LINE_STATUS => self.port[1].line_status_read(),
             SERIAL1_MODEM_STATUS => self.port[0].modem_status_read(),         
             SERIAL2_MODEM_STATUS => self.port[1].modem_status_read(),         
+            SERIAL1_SCRATCH => self.port[0].scratch_read(),
+            SERIAL2_SCRATCH => self.port[1].scratch_read(),
             _ => 0
         }
     }
@@ -163,6 +180,8 @@ impl IoDevice for SerialPortController {
             SERIAL2_LINE_STATUS => {},
             SERIAL1_MODEM_STATUS => {},
             SERIAL2_MODEM_STATUS => {},
+            SERIAL1_SCRATCH => self.port[0].scratch_reg = byte,
+            SERIAL2_SCRATCH => self.port[1].scratch_reg = byte,
             _ => {}
 
         }
@@ -177,6 +196,7 @@ impl IoDevice for SerialPortController {
             SERIAL1_MODEM_CONTROL,
             SERIAL1_LINE_STATUS,
             SERIAL1_MODEM_STATUS,
+            SERIAL1_SCRATCH,
 
             SERIAL2_RX_TX_BUFFER,
             SERIAL2_INTERRUPT_ENABLE,
@@ -185,6 +205,7 @@ impl IoDevice for SerialPortController {
             SERIAL2_MODEM_CONTROL,
             SERIAL2_LINE_STATUS,
             SERIAL2_MODEM_STATUS,
+            SERIAL2_SCRATCH,
         ]
     }
 }
@@ -199,6 +220,7 @@ pub enum StopBits {
 pub struct SerialPort {
     name: String,
     irq: u8,
+    uart: UartType,
     line_control_reg: u8,
     word_length: u8,
     stop_bits: StopBits,
@@ -222,17 +244,18 @@ pub struct SerialPort {
     tx_queue: VecDeque<u8>,
     tx_timer: f64,
     us_per_byte: f64,
+    scratch_reg: u8,
 
     // Serial port bridge
-    bridge_port: Option<Box<dyn serialport::SerialPort>>,
-    bridge_buf: Vec<u8>
+    bridge: Option<SerialBridge>,
 }
 
 impl SerialPort {
-    pub fn new(name: String, irq: u8) -> Self {
+    pub fn new(name: String, irq: u8, uart: UartType) -> Self {
         Self {
             name,
             irq,
+            uart,
             line_control_reg: 0,
             word_length: 8,
             stop_bits: StopBits::One,
@@ -248,7 +271,7 @@ impl SerialPort {
             loopback: false,
             modem_status_reg: 0,
             rx_byte: 0,
-            rx_was_read: false,
+            rx_was_read: true,
             tx_holding_reg: 0,
             tx_holding_empty: true,
             rx_queue: VecDeque::new(),
@@ -256,14 +279,14 @@ impl SerialPort {
             tx_queue: VecDeque::new(),
             tx_timer: 0.0,
             us_per_byte: 833.333, // 9600 baud
+            scratch_reg: 0,
 
-            bridge_port: None,
-            bridge_buf: vec![0; 1000]
+            bridge: None,
         }
     }
     /// Convert the integer divisor value into baud rate
-    fn divisor_to_baud(divisor: u16) -> u16 {
-        return ((SERIAL_CLOCK * 1_000_000.0) / divisor as f64 / 16.0) as u16;
+    fn divisor_to_baud(divisor: u16) -> u32 {
+        return ((SERIAL_CLOCK * 1_000_000.0) / divisor.max(1) as f64 / 16.0) as u32;
     }
 
     /// Sets the value of us_per_byte, the microsecond delay between sending a byte out of the 
@@ -271,12 +294,46 @@ impl SerialPort {
     /// This function should be called whenever the divisor has changed.
     fn set_timing(&mut self) {
 
-        if self.divisor < 12 {
-            // Minimum divisor of 12 (9600 baud)
-            self.divisor = 12;
+        match self.uart {
+            UartType::Ins8250 if self.divisor < 12 => {
+                // Minimum divisor of 12 (9600 baud)
+                self.divisor = 12;
+            }
+            _ => {
+                self.divisor = self.divisor.max(1);
+            }
         }
-        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u16;
+        let bytes_per_second = SerialPort::divisor_to_baud(self.divisor) / self.word_length as u32;
         self.us_per_byte = 1.0 / bytes_per_second as f64 * 1_000_000.0;
+        self.update_bridge_line_params();
+    }
+
+    /// Apply the current line settings to a bridged port.
+    fn update_bridge_line_params(&mut self) {
+        let params = LineParams {
+            baud: SerialPort::divisor_to_baud(self.divisor),
+            word_length: self.word_length,
+            stop_bits: match self.stop_bits {
+                StopBits::One => BridgeStopBits::One,
+                _ => BridgeStopBits::Two,
+            },
+            parity: match (self.parity_enable, self.line_control_reg & EVEN_PARITY_SELECT_BIT != 0) {
+                (false, _) => BridgeParity::None,
+                (true, false) => BridgeParity::Odd,
+                (true, true) => BridgeParity::Even,
+            },
+        };
+        if let Some(bridge) = &mut self.bridge {
+            bridge.set_line_params(&params);
+        }
+    }
+
+    /// Read the scratch register. The INS8250 has no scratch register, so reads float high.
+    fn scratch_read(&self) -> u8 {
+        match self.uart {
+            UartType::Ins8250 => 0xFF,
+            UartType::Ns16450 => self.scratch_reg,
+        }
     }
 
     fn line_control_read(&self) -> u8 {
@@ -303,6 +360,7 @@ impl SerialPort {
 
         self.parity_enable = byte & PARITY_ENABLE_BIT != 0;
         self.divisor_latch_access = byte & DIVISOR_LATCH_ACCESS_BIT != 0;
+        self.update_bridge_line_params();
 
         log::trace!("{}: Write to Line Control Register: {:02X} Word Length: {} Parity: {} Stop Bits: {:?}", 
             self.name, 
@@ -338,8 +396,8 @@ impl SerialPort {
     }
 
     /// Send a byte to the serial port tx buffer register.
-    /// For COM1, COM1 is always attached to Mouse which ignores input.
-    /// COM2 may be bridged to a host serial port.
+    /// COM1 is usually attached to a mouse, which ignores input.
+    /// Either port may be bridged to a host serial port or TCP socket.
     fn tx_buffer_write(&mut self, byte: u8) {
         // If DSLAB, set Divisor Latch LSB
         if self.divisor_latch_access { 
@@ -419,9 +477,12 @@ impl SerialPort {
 
     }
 
-    // Handle reading the Line Status Register
-    fn line_status_read(&self ) -> u8 {
-        self.line_status_reg
+    // Handle reading the Line Status Register. Reading clears the error bits.
+    fn line_status_read(&mut self) -> u8 {
+        let byte = self.line_status_reg;
+        self.line_status_reg &= !STATUS_ERROR_MASK;
+        self.lower_interrupt_type(INTERRUPT_RX_LINE_STATUS);
+        byte
     }
 
     /// Handle a read of the Interrupt ID Register.
@@ -433,7 +494,7 @@ impl SerialPort {
         let mut byte = 0;
 
         // Set bit 0 to 1 if interrupt is NOT pending
-        if self.interrupts_active & INTERRUPT_ID_MASK == 0 {
+        if self.interrupts_active == 0 {
             byte |= 1;
         }
 
@@ -470,6 +531,13 @@ impl SerialPort {
         if self.loopback {
             log::trace!("{}: Loopback mode enabled", self.name);
         }
+
+        // In loopback mode the control outputs are disconnected from the line.
+        let dtr = !self.loopback && self.modem_control_reg & MODEM_CONTROL_DTR != 0;
+        let rts = !self.loopback && self.modem_control_reg & MODEM_CONTROL_RTS != 0;
+        if let Some(bridge) = &mut self.bridge {
+            bridge.set_control_lines(dtr, rts);
+        }
     }
 
     /// Handle reading from the Modem Status register
@@ -496,14 +564,46 @@ impl SerialPort {
         else {
             let byte = self.modem_status_reg;
 
-            // Clear DCTS and DDSR flags
-            self.modem_status_reg &= !MODEM_STATUS_DCTS;
-            self.modem_status_reg &= !MODEM_STATUS_DDSR;
+            // Clear the delta flags and any Modem Status interrupt
+            self.modem_status_reg &= !(MODEM_STATUS_DCTS | MODEM_STATUS_DDSR | MODEM_STATUS_TERI | MODEM_STATUS_DRLSD);
+            self.lower_interrupt_type(INTERRUPT_MODEM_STATUS);
 
             byte
         }
     }
 
+    /// Update the Modem Status Register from the state of the lines of a bridged port, setting
+    /// the delta bits for any lines that changed.
+    fn set_modem_lines(&mut self, lines: ModemLines) {
+        let old = self.modem_status_reg;
+        let mut new = old & 0x0F;
+
+        for (state, line, delta) in [
+            (lines.cts, MODEM_STATUS_CTS, MODEM_STATUS_DCTS),
+            (lines.dsr, MODEM_STATUS_DSR, MODEM_STATUS_DDSR),
+            (lines.dcd, MODEM_STATUS_RLSD, MODEM_STATUS_DRLSD),
+        ] {
+            if state {
+                new |= line;
+            }
+            if state != (old & line != 0) {
+                new |= delta;
+            }
+        }
+        if lines.ri {
+            new |= MODEM_STATUS_RI;
+        }
+        else if old & MODEM_STATUS_RI != 0 {
+            // TERI is only set on the trailing edge of RI
+            new |= MODEM_STATUS_TERI;
+        }
+
+        self.modem_status_reg = new;
+        if new & 0x0F != old & 0x0F {
+            self.raise_interrupt_type(INTERRUPT_MODEM_STATUS);
+        }
+    }
+
     fn set_modem_status_connected(&mut self) {
 
         if self.modem_status_reg & MODEM_STATUS_CTS == 0 {
@@ -547,16 +647,10 @@ impl SerialPort {
 
     fn bridge_port(&mut self, port_name: String) -> anyhow::Result<bool> {
 
-        let port_result = serialport::new(port_name.clone(), 9600)
-            .timeout(std::time::Duration::from_millis(5))
-            .stop_bits(serialport::StopBits::One)
-            .parity(serialport::Parity::None)
-            .open();
-
-        match port_result {
-            Ok(bridge_port) => {
+        match SerialBridge::open_host(&port_name) {
+            Ok(bridge) => {
                 log::trace!("Successfully opened host port {}", port_name);
-                self.bridge_port = Some(bridge_port);
+                self.attach_bridge(bridge);
                 self.set_modem_status_connected();
                 Ok(true)
             }
@@ -566,6 +660,29 @@ impl SerialPort {
             }
         }
     }
+
+    fn bridge_tcp(&mut self, target: &TcpTarget, telnet: bool) -> anyhow::Result<bool> {
+
+        match SerialBridge::open_tcp(target, telnet) {
+            Ok(bridge) => {
+                log::trace!("{}: Bridged to {}", self.name, bridge.description());
+                self.attach_bridge(bridge);
+                Ok(true)
+            }
+            Err(e) => {
+                log::trace!("Error opening TCP bridge: {}", e);
+                anyhow::bail!("Error opening TCP bridge: {}", e)
+            }
+        }
+    }
+
+    /// Attach a bridge and bring it up to date with the UART's line settings and control lines.
+    fn attach_bridge(&mut self, bridge: SerialBridge) {
+        self.bridge = Some(bridge);
+        self.tx_queue.clear();
+        self.update_bridge_line_params();
+        self.modem_control_write(self.modem_control_reg);
+    }
 }
 
 
@@ -578,12 +695,20 @@ impl SerialPortController {
     pub fn new() -> Self {
         Self {
             port: [
-                SerialPort::new("COM1".to_string(), SERIAL1_IRQ),
-                SerialPort::new("COM2".to_string(), SERIAL2_IRQ)
+                SerialPort::new("COM1".to_string(), SERIAL1_IRQ, UartType::default()),
+                SerialPort::new("COM2".to_string(), SERIAL2_IRQ, UartType::default())
             ]
         }
     }
 
+    /// Set the UART chip installed on both adapters.
+    pub fn set_uart_type(&mut self, uart: UartType) {
+        for port in self.port.iter_mut() {
+            port.uart = uart;
+            port.set_timing();
+        }
+    }
+
     /// Get status of specified serial port's RTS line
     pub fn get_rts(&self, port: usize) -> bool {
         self.port[port].modem_control_reg & MODEM_CONTROL_RTS != 0
@@ -605,7 +730,44 @@ impl SerialPortController {
         self.port[port].bridge_port(port_name)
     }
 
+    /// Bridge the specified serial port to a TCP socket
+    pub fn bridge_tcp(&mut self, port: usize, target: &TcpTarget, telnet: bool) -> anyhow::Result<bool> {
+        self.port[port].bridge_tcp(target, telnet)
+    }
+
+    /// Remove any bridge from the specified serial port
+    pub fn unbridge_port(&mut self, port: usize) {
+        if self.port[port].bridge.take().is_some() {
+            self.port[port].set_modem_lines(ModemLines::default());
+        }
+    }
+
+    /// Return a description of the specified serial port's bridge, if it is bridged
+    pub fn bridge_description(&self, port: usize) -> Option<String> {
+        self.port[port].bridge.as_ref().map(|bridge| bridge.description())
+    }
+
     /// Run the serial ports for the specified number of microseconds
+    /// Return the time in microseconds until a port may next raise an interrupt without 
+    /// further IO, or None if none will.
+    pub fn next_interrupt_us(&self) -> Option<f64> {
+        self.port.iter()
+            .filter_map(|port| {
+                if port.raise_interrupt {
+                    return Some(0.0)
+                }
+                // Bytes are received and transmitted when the timers pass the byte period.
+                let rx_us = (!port.rx_queue.is_empty()).then_some(port.us_per_byte - port.rx_timer);
+                let tx_us = (!port.tx_holding_empty).then_some(port.us_per_byte - port.tx_timer);
+                match (rx_us, tx_us) {
+                    (Some(rx), Some(tx)) => Some(rx.min(tx)),
+                    (rx, tx) => rx.or(tx)
+                }
+            })
+            .map(|us| us.max(0.0))
+            .reduce(f64::min)
+    }
+
     pub fn run(&mut self, pic: &mut pic::Pic, us: f64) {
 
         for port in self.port.iter_mut() {
@@ -632,8 +794,9 @@ impl SerialPortController {
                     // We have a byte to receive
 
                     if !port.rx_was_read {
-                        // Previous byte was never read :(
-                        // TODO: Handle overflow interrupt
+                        // Previous byte was never read and is overwritten
+                        port.line_status_reg |= STATUS_OVERRUN_ERROR;
+                        port.raise_interrupt_type(INTERRUPT_RX_LINE_STATUS);
                     }
 
                     port.rx_byte = b;
@@ -660,8 +823,12 @@ impl SerialPortController {
                 // Is there a byte waiting to be sent in the tx holding register?
                 if !port.tx_holding_empty {
                     
-                    // If we have bridged this serial port, send the byte to the tx queue
-                    if let Some(_) = &port.bridge_port {
+                    // In loopback mode, the byte is received by the same port. Otherwise, if we
+                    // have bridged this serial port, send the byte to the tx queue
+                    if port.loopback {
+                        port.rx_queue.push_back(port.tx_holding_reg);
+                    }
+                    else if port.bridge.is_some() {
                         //log::trace!("{}: Sending byte: {:02X}", port.name, port.tx_holding_reg);
                         port.tx_queue.push_back(port.tx_holding_reg);
                     }
@@ -686,49 +853,20 @@ impl SerialPortController {
 
         for port in &mut self.port {
             
-            match &mut port.bridge_port {
-                Some(bridge_port) => {
-                    
-                    // Write any pending bytes
-                    if port.tx_queue.len() > 0 {
-
-                        port.tx_queue.make_contiguous();
-                        let (tx1, _) = port.tx_queue.as_slices();
-                        
-                        match bridge_port.write(tx1) {
-                            Ok(_) => {
-                                //log::trace!("Wrote bytes: {:?}", tx1);
-                            }
-                            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
-                            Err(e) => log::error!("Error writing byte: {:?}", e),                            
-                        }
-
-                        port.tx_queue.clear();
-                    }
-
-
-                    // Read any pending bytes
-                    match bridge_port.read(port.bridge_buf.as_mut_slice()) {
-                        Ok(ct) => {
-
-                            if ct > 0 {
-                                log::trace!("Read {} bytes from serial port", ct);
-                            }
-                            for i in 0..ct {
-                                // TODO: Must be a more efficient way to copy the vec to vecdeque?
-                                let byte = port.bridge_buf[i];
-                                port.rx_queue.push_back(byte);
-                                //log::trace!("Wrote byte : {:02X} to buf", byte);
-                            }
-                        },
-                        Err(_) => {
-                            //log::error!("Error reading serial device: {}", e);
-                        }
-                    }
-                },
-                None => {}
+            if let Some(bridge) = &mut port.bridge {
+                // Write any pending bytes, then read any pending bytes
+                bridge.send(&mut port.tx_queue);
+                bridge.receive(&mut port.rx_queue);
+
+                let lines = bridge.modem_lines();
+                let closed = bridge.is_closed();
+                port.set_modem_lines(lines);
+
+                if closed {
+                    log::info!("{}: Bridge closed", port.name);
+                    port.bridge = None;
+                }
             }
         }
     }
-
-}
\ No newline at end of file
+}
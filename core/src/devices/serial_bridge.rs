@@ -0,0 +1,476 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::serial_bridge.rs
+
+    Connects an emulated serial port to the outside world, either through a
+    host serial port or over a TCP socket.
+
+    Host serial ports are passed through: the baud rate, word length, parity
+    and stop bits programmed into the UART are applied to the host port, DTR 
+    and RTS are driven from the Modem Control Register, and CTS, DSR, DCD and
+    RI are reflected in the Modem Status Register.
+
+    A TCP bridge either connects to a remote address or listens for a single
+    incoming connection, acting as a null-modem cable to another emulator or
+    to a telnet server. While a connection is up, CTS, DSR and DCD are 
+    asserted. With the telnet option, IAC sequences from the remote end are 
+    filtered out of the data stream and 0xFF bytes sent are escaped.
+*/
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+const BRIDGE_BUF_LEN: usize = 1024;
+
+// Telnet protocol bytes
+const TELNET_IAC: u8 = 0xFF;
+const TELNET_SB: u8 = 0xFA;
+const TELNET_SE: u8 = 0xF0;
+const TELNET_WILL: u8 = 0xFB;
+const TELNET_DONT: u8 = 0xFE;
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum BridgeStopBits {
+    One,
+    Two
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum BridgeParity {
+    None,
+    Odd,
+    Even
+}
+
+/// Serial line settings programmed into the UART.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct LineParams {
+    pub baud: u32,
+    pub word_length: u8,
+    pub stop_bits: BridgeStopBits,
+    pub parity: BridgeParity,
+}
+
+/// The state of the modem status lines driven by the remote end.
+#[derive (Copy, Clone, Debug, Default, PartialEq)]
+pub struct ModemLines {
+    pub cts: bool,
+    pub dsr: bool,
+    pub dcd: bool,
+    pub ri: bool,
+}
+
+/// The address a TCP bridge connects to or listens on.
+#[derive (Clone, Debug)]
+pub enum TcpTarget {
+    Connect(String),
+    Listen(u16),
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum TelnetState {
+    Data,
+    Iac,
+    Option,
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+/// Removes telnet commands from a received byte stream.
+#[derive (Debug)]
+pub struct TelnetFilter {
+    state: TelnetState,
+}
+
+impl Default for TelnetFilter {
+    fn default() -> Self {
+        Self { state: TelnetState::Data }
+    }
+}
+
+impl TelnetFilter {
+    /// Filter a received byte. Returns the byte if it is data.
+    pub fn filter(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (TelnetState::Data, TELNET_IAC) => {
+                self.state = TelnetState::Iac;
+                None
+            }
+            (TelnetState::Data, _) => Some(byte),
+            (TelnetState::Iac, TELNET_IAC) => {
+                // An escaped 0xFF data byte
+                self.state = TelnetState::Data;
+                Some(byte)
+            }
+            (TelnetState::Iac, TELNET_WILL..=TELNET_DONT) => {
+                self.state = TelnetState::Option;
+                None
+            }
+            (TelnetState::Iac, TELNET_SB) => {
+                self.state = TelnetState::Subnegotiation;
+                None
+            }
+            (TelnetState::Iac, _) | (TelnetState::Option, _) => {
+                self.state = TelnetState::Data;
+                None
+            }
+            (TelnetState::Subnegotiation, TELNET_IAC) => {
+                self.state = TelnetState::SubnegotiationIac;
+                None
+            }
+            (TelnetState::Subnegotiation, _) => None,
+            (TelnetState::SubnegotiationIac, TELNET_SE) => {
+                self.state = TelnetState::Data;
+                None
+            }
+            (TelnetState::SubnegotiationIac, _) => {
+                self.state = TelnetState::Subnegotiation;
+                None
+            }
+        }
+    }
+}
+
+pub struct TcpBridge {
+    target: TcpTarget,
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+    telnet: Option<TelnetFilter>,
+    closed: bool,
+}
+
+impl TcpBridge {
+    fn open(target: &TcpTarget, telnet: bool) -> anyhow::Result<Self> {
+        let mut bridge = Self {
+            target: target.clone(),
+            listener: None,
+            stream: None,
+            telnet: telnet.then(TelnetFilter::default),
+            closed: false,
+        };
+
+        match target {
+            TcpTarget::Connect(addr) => {
+                let stream = TcpStream::connect(addr.as_str())?;
+                bridge.attach_stream(stream)?;
+            }
+            TcpTarget::Listen(port) => {
+                let listener = TcpListener::bind(("0.0.0.0", *port))?;
+                listener.set_nonblocking(true)?;
+                bridge.listener = Some(listener);
+            }
+        }
+        Ok(bridge)
+    }
+
+    fn attach_stream(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        if let Ok(peer) = stream.peer_addr() {
+            log::info!("Serial bridge connected to {}", peer);
+        }
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        if self.stream.take().is_some() {
+            log::info!("Serial bridge connection closed");
+        }
+        // A bridge that connected out is finished; a listening bridge waits for another client.
+        if self.listener.is_none() {
+            self.closed = true;
+        }
+    }
+
+    fn accept(&mut self) {
+        if self.stream.is_some() {
+            return
+        }
+        if let Some(listener) = &self.listener {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = self.attach_stream(stream) {
+                        log::error!("Error accepting serial bridge connection: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => log::error!("Error accepting serial bridge connection: {}", e),
+            }
+        }
+    }
+
+    fn send(&mut self, queue: &mut VecDeque<u8>) {
+        let Some(stream) = &mut self.stream else {
+            // Nobody is listening; bytes sent while disconnected are lost.
+            queue.clear();
+            return
+        };
+
+        let bytes: Vec<u8> = match self.telnet {
+            Some(_) => queue.iter().flat_map(|&b| if b == TELNET_IAC { vec![b, b] } else { vec![b] }).collect(),
+            None => queue.iter().copied().collect(),
+        };
+        queue.clear();
+
+        match stream.write_all(&bytes) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                log::warn!("Serial bridge send buffer full, dropping data");
+            }
+            Err(e) => {
+                log::error!("Error writing to serial bridge: {}", e);
+                self.disconnect();
+            }
+        }
+    }
+
+    fn receive(&mut self, queue: &mut VecDeque<u8>) {
+        let Some(stream) = &mut self.stream else {
+            return
+        };
+
+        let mut buf = [0; BRIDGE_BUF_LEN];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    self.disconnect();
+                    return
+                }
+                Ok(ct) => {
+                    match &mut self.telnet {
+                        Some(filter) => queue.extend(buf[..ct].iter().filter_map(|&b| filter.filter(b))),
+                        None => queue.extend(&buf[..ct]),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log::error!("Error reading from serial bridge: {}", e);
+                    self.disconnect();
+                    return
+                }
+            }
+        }
+    }
+}
+
+/// A connection from an emulated serial port to a host serial port or a TCP socket.
+pub enum SerialBridge {
+    Host(Box<dyn serialport::SerialPort>),
+    Tcp(TcpBridge),
+}
+
+impl SerialBridge {
+    /// Open the named host serial port.
+    pub fn open_host(port_name: &str) -> anyhow::Result<Self> {
+        let port = serialport::new(port_name, 9600)
+            .timeout(std::time::Duration::from_millis(5))
+            .stop_bits(serialport::StopBits::One)
+            .parity(serialport::Parity::None)
+            .open()?;
+        Ok(SerialBridge::Host(port))
+    }
+
+    /// Connect to, or listen on, a TCP address.
+    pub fn open_tcp(target: &TcpTarget, telnet: bool) -> anyhow::Result<Self> {
+        Ok(SerialBridge::Tcp(TcpBridge::open(target, telnet)?))
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            SerialBridge::Host(port) => port.name().unwrap_or_else(|| "host port".to_string()),
+            SerialBridge::Tcp(tcp) => match &tcp.target {
+                TcpTarget::Connect(addr) => format!("tcp:{}", addr),
+                TcpTarget::Listen(port) => format!("tcp listen:{}", port),
+            }
+        }
+    }
+
+    /// Return true if the bridge has been closed by the remote end and should be removed.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            SerialBridge::Host(_) => false,
+            SerialBridge::Tcp(tcp) => tcp.closed,
+        }
+    }
+
+    /// Apply the UART's line settings to the bridge.
+    pub fn set_line_params(&mut self, params: &LineParams) {
+        if let SerialBridge::Host(port) = self {
+            let data_bits = match params.word_length {
+                5 => serialport::DataBits::Five,
+                6 => serialport::DataBits::Six,
+                7 => serialport::DataBits::Seven,
+                _ => serialport::DataBits::Eight,
+            };
+            let stop_bits = match params.stop_bits {
+                BridgeStopBits::One => serialport::StopBits::One,
+                BridgeStopBits::Two => serialport::StopBits::Two,
+            };
+            let parity = match params.parity {
+                BridgeParity::None => serialport::Parity::None,
+                BridgeParity::Odd => serialport::Parity::Odd,
+                BridgeParity::Even => serialport::Parity::Even,
+            };
+
+            let result = port.set_baud_rate(params.baud)
+                .and_then(|_| port.set_data_bits(data_bits))
+                .and_then(|_| port.set_stop_bits(stop_bits))
+                .and_then(|_| port.set_parity(parity));
+            if let Err(e) = result {
+                log::error!("Error setting host serial port parameters: {}", e);
+            }
+        }
+    }
+
+    /// Drive the DTR and RTS lines.
+    pub fn set_control_lines(&mut self, dtr: bool, rts: bool) {
+        if let SerialBridge::Host(port) = self {
+            let result = port.write_data_terminal_ready(dtr)
+                .and_then(|_| port.write_request_to_send(rts));
+            if let Err(e) = result {
+                log::error!("Error setting host serial port control lines: {}", e);
+            }
+        }
+    }
+
+    /// Return the state of the modem status lines.
+    pub fn modem_lines(&mut self) -> ModemLines {
+        match self {
+            SerialBridge::Host(port) => ModemLines {
+                cts: port.read_clear_to_send().unwrap_or(false),
+                dsr: port.read_data_set_ready().unwrap_or(false),
+                dcd: port.read_carrier_detect().unwrap_or(false),
+                ri: port.read_ring_indicator().unwrap_or(false),
+            },
+            SerialBridge::Tcp(tcp) => {
+                let connected = tcp.stream.is_some();
+                ModemLines {
+                    cts: connected,
+                    dsr: connected,
+                    dcd: connected,
+                    ri: false,
+                }
+            }
+        }
+    }
+
+    /// Send the bytes in the queue, removing them from it.
+    pub fn send(&mut self, queue: &mut VecDeque<u8>) {
+        if queue.is_empty() {
+            return
+        }
+        match self {
+            SerialBridge::Host(port) => {
+                let (tx1, tx2) = queue.as_slices();
+                let result = port.write_all(tx1).and_then(|_| port.write_all(tx2));
+                match result {
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+                    Err(e) => log::error!("Error writing byte: {:?}", e),
+                }
+                queue.clear();
+            }
+            SerialBridge::Tcp(tcp) => tcp.send(queue),
+        }
+    }
+
+    /// Receive any pending bytes into the queue.
+    pub fn receive(&mut self, queue: &mut VecDeque<u8>) {
+        match self {
+            SerialBridge::Host(port) => {
+                let mut buf = [0; BRIDGE_BUF_LEN];
+                if let Ok(ct) = port.read(&mut buf) {
+                    if ct > 0 {
+                        log::trace!("Read {} bytes from serial port", ct);
+                    }
+                    queue.extend(&buf[..ct]);
+                }
+            }
+            SerialBridge::Tcp(tcp) => {
+                tcp.accept();
+                tcp.receive(queue);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_telnet_filter() {
+        let mut filter = TelnetFilter::default();
+        let stream = [
+            b'A',
+            TELNET_IAC, TELNET_WILL, 0x01,                      // WILL ECHO
+            b'B',
+            TELNET_IAC, TELNET_IAC,                             // Escaped 0xFF
+            TELNET_IAC, TELNET_SB, 0x18, 0x01, TELNET_IAC, TELNET_SE,  // Terminal type subnegotiation
+            TELNET_IAC, 0xF1,                                   // NOP
+            b'C',
+        ];
+        let data: Vec<u8> = stream.iter().filter_map(|&b| filter.filter(b)).collect();
+        assert_eq!(data, vec![b'A', b'B', 0xFF, b'C']);
+    }
+
+    #[test]
+    fn test_tcp_bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut bridge = SerialBridge::open_tcp(&TcpTarget::Connect(addr), false).unwrap();
+        let (mut remote, _) = listener.accept().unwrap();
+        assert!(bridge.modem_lines().dcd);
+
+        let mut tx: VecDeque<u8> = b"ATZ\r".iter().copied().collect();
+        bridge.send(&mut tx);
+        assert!(tx.is_empty());
+        let mut buf = [0; 4];
+        remote.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ATZ\r");
+
+        remote.write_all(b"OK").unwrap();
+        drop(remote);
+        let mut rx = VecDeque::new();
+        for _ in 0..100 {
+            bridge.receive(&mut rx);
+            if bridge.is_closed() {
+                break
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(rx, VecDeque::from(b"OK".to_vec()));
+        assert!(bridge.is_closed());
+        assert!(!bridge.modem_lines().dcd);
+    }
+}
@@ -0,0 +1,314 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    devices::sn76489.rs
+
+    Implements the Texas Instruments SN76489 programmable sound generator, as
+    built into the IBM PCjr (an SN76496 at port C0h) and the Tandy 1000.
+
+    The chip has three square wave tone channels and a noise channel, each 
+    with a 4-bit attenuator in 2dB steps. The tone generators count down at 
+    1/16th of the 3.579545MHz input clock, toggling their output each time 
+    the counter reaches 0, giving a frequency of clock / (32 * N) for a 
+    10-bit period N. The noise channel shifts a 15-bit LFSR at one of three 
+    fixed rates or at the rate of tone channel 3.
+
+    All registers are written through a single write-only port. A byte with
+    bit 7 set latches a register and writes its low 4 bits; a following byte
+    with bit 7 clear writes the upper 6 bits of a tone period, or the whole
+    value of other registers.
+
+    Output is generated at the host's sample rate by averaging the chip's
+    output over the internal clock ticks within each sample.
+*/
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+/// The PCjr decodes port C0h-C7h for the sound chip.
+pub const SN76489_PCJR_PORT: u16 = 0xC0;
+pub const SN76489_PCJR_PORT_COUNT: u16 = 8;
+
+/// The PCjr and Tandy 1000 clock the sound chip from the NTSC colorburst frequency.
+pub const SN76489_CLOCK: f64 = 3_579_545.0;
+
+/// Adjust the SN76489's output relative to the PC speaker.
+pub const SN76489_VOLUME: f32 = 0.25;
+
+const CLOCK_DIVISOR: f64 = 16.0;
+
+const REG_LATCH: u8 = 0b1000_0000;
+const REG_NOISE: usize = 6;
+
+const NOISE_WHITE: u8 = 0b0000_0100;
+const NOISE_RATE_MASK: u8 = 0b0000_0011;
+
+const LFSR_RESET: u16 = 0x4000;
+const ATTENUATION_OFF: u8 = 0x0F;
+
+pub struct Sn76489 {
+    base_port: u16,
+    latched: usize,
+    tone_period: [u16; 3],
+    attenuation: [u8; 4],
+    noise_ctrl: u8,
+
+    counters: [u16; 4],
+    outputs: [bool; 4],
+    lfsr: u16,
+
+    sample_rate: f64,
+    ticks_per_sample: f64,
+    tick_accum: f64,
+    volume_table: [f32; 16],
+}
+
+impl IoDevice for Sn76489 {
+    fn read_u8(&mut self, _port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        // The SN76489 has no readable registers.
+        0xFF
+    }
+
+    fn write_u8(&mut self, _port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        self.write(data);
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (self.base_port..self.base_port + SN76489_PCJR_PORT_COUNT).collect()
+    }
+}
+
+impl Sn76489 {
+    pub fn new(base_port: u16, sample_rate: u32) -> Self {
+        // Each attenuation step is 2dB. The highest setting silences the channel.
+        let mut volume_table = [0.0; 16];
+        for (i, volume) in volume_table.iter_mut().enumerate().take(15) {
+            *volume = f32::powf(10.0, -0.1 * i as f32);
+        }
+
+        let mut sn = Self {
+            base_port,
+            latched: 0,
+            tone_period: [0; 3],
+            attenuation: [ATTENUATION_OFF; 4],
+            noise_ctrl: 0,
+            counters: [0; 4],
+            outputs: [false; 4],
+            lfsr: LFSR_RESET,
+            sample_rate: 0.0,
+            ticks_per_sample: 0.0,
+            tick_accum: 0.0,
+            volume_table,
+        };
+        sn.set_sample_rate(sample_rate);
+        sn
+    }
+
+    pub fn reset(&mut self) {
+        *self = Sn76489::new(self.base_port, self.sample_rate as u32);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f64;
+        self.ticks_per_sample = SN76489_CLOCK / CLOCK_DIVISOR / self.sample_rate;
+    }
+
+    /// Return the 10-bit period of the specified tone channel.
+    pub fn tone_period(&self, channel: usize) -> u16 {
+        self.tone_period[channel]
+    }
+
+    /// Return the attenuation of the specified channel, where channel 3 is noise.
+    pub fn attenuation(&self, channel: usize) -> u8 {
+        self.attenuation[channel]
+    }
+
+    /// Handle a write to the sound chip's port.
+    pub fn write(&mut self, data: u8) {
+        if data & REG_LATCH != 0 {
+            self.latched = ((data >> 4) & 0x07) as usize;
+            let value = data & 0x0F;
+            match self.latched {
+                REG_NOISE => self.write_noise(value),
+                reg if reg & 1 == 0 => {
+                    let ch = reg / 2;
+                    self.tone_period[ch] = (self.tone_period[ch] & 0x3F0) | value as u16;
+                }
+                reg => self.attenuation[reg / 2] = value,
+            }
+        }
+        else {
+            match self.latched {
+                REG_NOISE => self.write_noise(data & 0x0F),
+                reg if reg & 1 == 0 => {
+                    let ch = reg / 2;
+                    self.tone_period[ch] = (self.tone_period[ch] & 0x00F) | ((data as u16 & 0x3F) << 4);
+                }
+                reg => self.attenuation[reg / 2] = data & 0x0F,
+            }
+        }
+    }
+
+    fn write_noise(&mut self, value: u8) {
+        self.noise_ctrl = value & 0x07;
+        // Writing the noise register resets the shift register.
+        self.lfsr = LFSR_RESET;
+    }
+
+    /// Return the reload value of the noise channel's counter.
+    fn noise_period(&self) -> u16 {
+        match self.noise_ctrl & NOISE_RATE_MASK {
+            0 => 0x10,
+            1 => 0x20,
+            2 => 0x40,
+            _ => self.tone_period[2],
+        }
+    }
+
+    /// Run the chip for one tick of its internal clock.
+    fn tick(&mut self) {
+        for ch in 0..3 {
+            self.counters[ch] = self.counters[ch].saturating_sub(1);
+            if self.counters[ch] == 0 {
+                // A period of 0 behaves as a period of 0x400.
+                self.counters[ch] = match self.tone_period[ch] {
+                    0 => 0x400,
+                    period => period
+                };
+                self.outputs[ch] = !self.outputs[ch];
+            }
+        }
+
+        self.counters[3] = self.counters[3].saturating_sub(1);
+        if self.counters[3] == 0 {
+            self.counters[3] = self.noise_period().max(1);
+            self.outputs[3] = !self.outputs[3];
+
+            // The shift register is clocked on the rising edge of the noise counter output.
+            if self.outputs[3] {
+                let feedback = match self.noise_ctrl & NOISE_WHITE != 0 {
+                    true => (self.lfsr ^ (self.lfsr >> 1)) & 1,
+                    false => self.lfsr & 1
+                };
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            }
+        }
+    }
+
+    /// Return the current output level of the chip from -1.0 to 1.0.
+    fn output(&self) -> f32 {
+        let mut sample = 0.0;
+        for ch in 0..3 {
+            // A period of 1 produces a constant output level, which software uses to play samples.
+            let level = match self.tone_period[ch] {
+                1 => true,
+                _ => self.outputs[ch]
+            };
+            let volume = self.volume_table[self.attenuation[ch] as usize];
+            sample += if level { volume } else { -volume };
+        }
+        let noise_volume = self.volume_table[self.attenuation[3] as usize];
+        sample += if self.lfsr & 1 != 0 { noise_volume } else { -noise_volume };
+        sample / 4.0
+    }
+
+    /// Generate the next output sample at the configured sample rate.
+    pub fn generate_sample(&mut self) -> f32 {
+        self.tick_accum += self.ticks_per_sample;
+        let ticks = self.tick_accum as u32;
+        self.tick_accum -= ticks as f64;
+
+        if ticks == 0 {
+            return self.output()
+        }
+
+        let mut sum = 0.0;
+        for _ in 0..ticks {
+            self.tick();
+            sum += self.output();
+        }
+        sum / ticks as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_writes() {
+        let mut sn = Sn76489::new(SN76489_PCJR_PORT, 44100);
+
+        // Latch tone 1 with low bits 0xE, then write the high bits 0x0F
+        sn.write(0x8E);
+        sn.write(0x0F);
+        assert_eq!(sn.tone_period(0), 0xFE);
+
+        // A second data byte replaces only the high bits
+        sn.write(0x01);
+        assert_eq!(sn.tone_period(0), 0x1E);
+
+        // Attenuation of tone 3, then noise
+        sn.write(0xD5);
+        assert_eq!(sn.attenuation(2), 0x05);
+        sn.write(0xF0);
+        assert_eq!(sn.attenuation(3), 0x00);
+
+        // A data byte following an attenuation latch rewrites the attenuation
+        sn.write(0x0A);
+        assert_eq!(sn.attenuation(3), 0x0A);
+    }
+
+    #[test]
+    fn test_tone_frequency() {
+        let mut sn = Sn76489::new(SN76489_PCJR_PORT, 44100);
+
+        // Period 0x100 produces a frequency of clock / (32 * 256), or about 437Hz.
+        sn.write(0x80);
+        sn.write(0x10);
+
+        let clock_ticks = (SN76489_CLOCK / CLOCK_DIVISOR) as usize;
+        let mut toggles = 0;
+        let mut last = sn.outputs[0];
+        for _ in 0..clock_ticks {
+            sn.tick();
+            if sn.outputs[0] != last {
+                toggles += 1;
+                last = sn.outputs[0];
+            }
+        }
+        let hz = toggles / 2;
+        assert!((436..=438).contains(&hz), "frequency was {}Hz", hz);
+    }
+
+    #[test]
+    fn test_silent() {
+        let mut sn = Sn76489::new(SN76489_PCJR_PORT, 44100);
+        for _ in 0..100 {
+            assert_eq!(sn.generate_sample(), 0.0);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::timer_card.rs
+
+    Implements a simple high resolution timer card, for benchmark software 
+    that can use an external time base instead of the PIT.
+
+    The card contains a free-running 32-bit counter clocked at 1MHz. Reading
+    the base port latches the entire count so that the four count bytes can 
+    be read from base+0 to base+3 without tearing. Writing any value with 
+    bit 0 set to base+4 resets the count to 0. Reading base+4 returns an ID
+    byte that software can use to detect the card.
+
+*/
+
+use crate::bus::{BusInterface, IoDevice, DeviceRunTimeUnit};
+
+pub const TIMER_CARD_DEFAULT_PORT: u16 = 0x2C0;
+pub const TIMER_CARD_FREQUENCY: f64 = 1_000_000.0;
+pub const TIMER_CARD_ID: u8 = 0x54;
+
+const TIMER_CARD_PORT_COUNT: u16 = 5;
+const CONTROL_RESET: u8 = 0b0000_0001;
+
+pub struct TimerCard {
+    base_port: u16,
+    count: u32,
+    latch: u32,
+    accum: f64,
+}
+
+impl TimerCard {
+    pub fn new(base_port: u16) -> Self {
+        Self {
+            base_port,
+            count: 0,
+            latch: 0,
+            accum: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.latch = 0;
+        self.accum = 0.0;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Advance the counter by the specified number of microseconds.
+    pub fn run(&mut self, us: f64) {
+        self.accum += us * TIMER_CARD_FREQUENCY / 1_000_000.0;
+        let ticks = self.accum.floor();
+        self.accum -= ticks;
+        self.count = self.count.wrapping_add(ticks as u64 as u32);
+    }
+}
+
+impl IoDevice for TimerCard {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port.wrapping_sub(self.base_port) {
+            0 => {
+                self.latch = self.count;
+                self.latch as u8
+            }
+            offset @ 1..=3 => (self.latch >> (offset * 8)) as u8,
+            4 => TIMER_CARD_ID,
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        if port.wrapping_sub(self.base_port) == 4 && data & CONTROL_RESET != 0 {
+            self.reset();
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (self.base_port..self.base_port + TIMER_CARD_PORT_COUNT).collect()
+    }
+}
@@ -0,0 +1,147 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    vga::draw.rs
+
+    Implement direct (clock-accurate) rendering of the VGA's 256-color modes.
+
+    Each scanline is drawn into the back buffer when the display enable period 
+    of that line ends, resolving pixels through the DAC as it is at that moment. 
+    This allows palette animation and raster effects that reprogram the DAC 
+    mid-frame to display as they would on real hardware.
+
+    Both chained (mode 13h) and unchained (mode X) memory layouts are handled 
+    the same way: in either case the pixel at column x is found in plane x & 3,
+    at offset x / 4 from the start of the current row. The CRTC Start Address 
+    is latched at the start of each frame, so page flipping by reprogramming 
+    the Start Address takes effect on the following frame.
+
+    Pixels are stored as RGBA. Since every 256-color pixel is two dots wide, 
+    each is written twice horizontally.
+
+*/
+
+use crate::devices::vga::*;
+
+/// Max width and height of the VGA direct framebuffer. Scanlines and pixels outside
+/// these bounds are clipped.
+pub const VGA_MAX_FIELD_W: u32 = 800;
+pub const VGA_MAX_FIELD_H: u32 = 600;
+
+// Cursor and text blink rates, as masks of the frame counter
+const VGA_CURSOR_BLINK_MASK: u64 = 0x07;
+const VGA_TEXT_BLINK_MASK: u64 = 0x0F;
+
+impl VGACard {
+
+    /// Return whether the Attribute Controller is in 8-bit color (256-color) mode.
+    pub(crate) fn is_256_color(&self) -> bool {
+        matches!(self.attribute_mode_control.mode(), AttributeMode::Graphics)
+            && matches!(self.attribute_mode_control.pixel_clock_select(), PixelClock::EveryOtherCycle)
+    }
+
+    /// Advance the row scan counter and memory address at the end of a scanline.
+    pub(crate) fn end_scanline(&mut self) {
+
+        // The Scan Doubling bit repeats each row scan twice
+        let row_scans = match self.crtc_maximum_scanline.two_to_four() {
+            true => (self.crtc_maximum_scanline.maximum_scanline() as u32 + 1) * 2,
+            false => self.crtc_maximum_scanline.maximum_scanline() as u32 + 1
+        };
+
+        self.row_scan += 1;
+        if self.row_scan >= row_scans {
+            self.row_scan = 0;
+            self.row_start += self.crtc_offset as usize * 2;
+        }
+
+        // The Line Compare register resets the memory address counter to 0, splitting the screen
+        if self.scanline == self.crtc_line_compare as u32 {
+            self.row_start = 0;
+            self.row_scan = 0;
+        }
+
+        self.in_vretrace = self.scanline >= self.crtc_vertical_retrace_start as u32 
+            && self.scanline < self.crtc_vertical_retrace_end_norm as u32;
+    }
+
+    /// Swap the display buffers, latch the Start Address and advance the blink phases at the 
+    /// end of a frame.
+    pub(crate) fn end_frame(&mut self) {
+
+        self.row_scan = self.crtc_preset_row_scan.preset_row_scan() as u32;
+        self.row_start = self.crtc_start_address as usize;
+        self.frame_count += 1;
+
+        // Blink phases are driven by vertical sync
+        if !self.blink_frozen {
+            if self.frame_count & VGA_CURSOR_BLINK_MASK == 0 {
+                self.cursor_status = !self.cursor_status;
+            }
+            if self.frame_count & VGA_TEXT_BLINK_MASK == 0 {
+                self.blink_state = !self.blink_state;
+            }
+        }
+
+        let (w, h) = self.get_display_size();
+        self.extents.field_w = VGA_MAX_FIELD_W;
+        self.extents.field_h = VGA_MAX_FIELD_H;
+        self.extents.aperture_w = w.min(VGA_MAX_FIELD_W);
+        self.extents.aperture_h = h.min(VGA_MAX_FIELD_H);
+        self.extents.visible_w = self.extents.aperture_w;
+        self.extents.visible_h = self.extents.aperture_h;
+        self.extents.row_stride = VGA_MAX_FIELD_W as usize;
+
+        std::mem::swap(&mut self.front_buf, &mut self.back_buf);
+    }
+
+    /// Draw the current scanline into the back buffer, if in a 256-color mode.
+    pub(crate) fn draw_scanline(&mut self) {
+
+        if !self.is_256_color() || self.scanline >= VGA_MAX_FIELD_H {
+            return
+        }
+
+        let (w, _) = self.get_display_size();
+        let w = w.min(VGA_MAX_FIELD_W) as usize;
+        // In 256-color modes the pel panning register shifts by half pixels
+        let pan = (self.attribute_pel_panning as usize & 0x07) >> 1;
+        let row = self.scanline as usize * VGA_MAX_FIELD_W as usize * 4;
+        let plane_len = self.planes[0].buf.len();
+
+        // Take the buffer so we can read the display planes while we draw
+        let mut buf = std::mem::take(&mut self.buf[self.back_buf]);
+
+        for x in 0..w {
+            let pixel = x / 2 + pan;
+            let addr = (self.row_start + pixel / 4) % plane_len;
+            let index = self.planes[pixel & 0x03].buf[addr] & self.color_pel_mask;
+
+            let dst = row + x * 4;
+            buf[dst..dst + 4].copy_from_slice(&self.color_registers_rgba[index as usize]);
+        }
+
+        self.buf[self.back_buf] = buf;
+    }
+}
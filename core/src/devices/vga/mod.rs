@@ -57,6 +57,7 @@ mod crtc_regs;
 mod graphics_regs;
 mod sequencer_regs;
 mod color_regs;
+mod draw;
 
 use attribute_regs::*;
 use crtc_regs::*;
@@ -65,6 +66,8 @@ use sequencer_regs::*;
 #[allow(unused_imports)]
 use color_regs::*;
 
+pub use draw::{VGA_MAX_FIELD_W, VGA_MAX_FIELD_H};
+
 pub const VGA_CLOCK_1: f64 = 25.175;
 pub const VGA_CLOCK_2: f64 = 28.322;
 pub const US_PER_CLOCK_1: f64 = 1.0 / VGA_CLOCK_1;
@@ -305,6 +308,12 @@ pub struct VGACard {
     cursor_frames: u32,
     in_hblank: bool,
     in_vblank: bool,
+    in_vretrace: bool,
+    row_scan: u32,
+    row_start: usize,
+    frame_count: u64,
+    blink_state: bool,
+    blink_frozen: bool,
     
     cursor_status: bool,
     cursor_slowblink: bool,
@@ -403,6 +412,11 @@ pub struct VGACard {
     pipeline_buf: [u8; 4],
     write_buf: [u8; 4],
 
+    // Direct rendering buffers for 256-color modes
+    buf: [Vec<u8>; 2],
+    front_buf: usize,
+    back_buf: usize,
+
     trace_logger: TraceLogger,
 }
 
@@ -655,6 +669,12 @@ impl VGACard {
             scanline_cycles: 0,
             in_hblank: false,
             in_vblank: false,
+            in_vretrace: false,
+            row_scan: 0,
+            row_start: 0,
+            frame_count: 0,
+            blink_state: false,
+            blink_frozen: false,
 
             cursor_status: false,
             cursor_slowblink: false,
@@ -739,7 +759,7 @@ impl VGACard {
             color_pel_read_address: 0,
             color_pel_read_address_color: 0,
             color_dac_state: 0,
-            color_pel_mask: 0,
+            color_pel_mask: 0xFF,
             color_registers: [[0; 3]; 256],
             color_registers_rgba: [[0; 4]; 256],
 
@@ -758,6 +778,13 @@ impl VGACard {
             pipeline_buf: [0; 4],
             write_buf: [0; 4],
 
+            buf: [
+                vec![0; (VGA_MAX_FIELD_W * VGA_MAX_FIELD_H * 4) as usize],
+                vec![0; (VGA_MAX_FIELD_W * VGA_MAX_FIELD_H * 4) as usize]
+            ],
+            front_buf: 0,
+            back_buf: 1,
+
             trace_logger
         }
     }
@@ -778,6 +805,9 @@ impl VGACard {
         self.scanline_cycles = 0;
         self.in_hblank = false;
         self.in_vblank = false;
+        self.in_vretrace = false;
+        self.row_scan = 0;
+        self.row_start = 0;
 
         self.cursor_status = false;
         self.cursor_slowblink = false;
@@ -890,7 +920,7 @@ impl VGACard {
         if self.in_hblank || self.in_vblank {
             byte |= 0x01;
         }
-        if self.in_vblank {
+        if self.in_vretrace {
             byte |= 0x08;
         }
 
@@ -960,6 +990,10 @@ impl VGACard {
                     }
                 }
             }
+            AttributeMode::Graphics if self.is_256_color() => {
+                // Both chained (mode 13h) and unchained (mode X) 256-color modes
+                self.display_mode = DisplayMode::Mode13VGALowRes256;
+            }
             AttributeMode::Graphics => {
                 //self.display_mode = match 
                 self.display_mode = match (
@@ -1225,6 +1259,12 @@ impl VGACard {
             self.in_hblank = false;
         }
 
+        // Draw the scanline when its display enable period ends
+        if self.scanline_cycles == self.u_timings.hblank_start 
+            && self.scanline <= self.crtc_vertical_display_end as u32 {
+            self.draw_scanline();
+        }
+
         if self.scanline_cycles >= self.u_timings.scanline_end {
             self.scanline_cycles = 0;
             
@@ -1232,9 +1272,11 @@ impl VGACard {
                 //log::trace!("last scanline hit: {}", self.scanline);
                 self.scanline = 0;
                 self.frame_cycles = 0;
+                self.end_frame();
             }
             else {
                 self.scanline += 1;
+                self.end_scanline();
             }
         }
 
@@ -1293,8 +1335,13 @@ impl VideoCard for VGACard {
         VideoType::VGA
     }
 
+    /// 256-color modes are rendered directly so that mid-frame DAC updates are displayed.
+    /// All other modes are rendered indirectly.
     fn get_render_mode(&self) -> RenderMode {
-        RenderMode::Indirect
+        match self.is_256_color() {
+            true => RenderMode::Direct,
+            false => RenderMode::Indirect
+        }
     }
 
     fn get_display_mode(&self) -> DisplayMode {
@@ -1320,32 +1367,39 @@ impl VideoCard for VGACard {
         (width, height)
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Only valid for direct rendering.
     fn get_display_extents(&self) -> &DisplayExtents {
         &self.extents
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Only valid for direct rendering.
     fn get_display_aperture(&self) -> (u32, u32) {
-        (0, 0)
+        (self.extents.aperture_w, self.extents.aperture_h)
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Return the position of the CRT beam in dots and scanlines.
     fn get_beam_pos(&self) -> Option<(u32, u32)> {
-        None
+        Some((self.scanline_cycles, self.scanline))
     }
 
-    fn debug_tick(&mut self, _ticks: u32) {
-        self.tick();
+    fn debug_tick(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
     }
 
     fn get_overscan_color(&self) -> u8 {
         0
     }    
     
+    /// This adapter has no composite output.
+    fn get_color_burst(&self) -> bool {
+        false
+    }
+
     /// Get the current scanline being rendered.
     fn get_scanline(&self) -> u32 {
-        0
+        self.scanline
     }
 
     /// Return whether to double scanlines produced by this adapter.
@@ -1354,14 +1408,14 @@ impl VideoCard for VGACard {
         false
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Only valid for direct rendering.
     fn get_display_buf(&self) -> &[u8] {
-        &[0]
+        &self.buf[self.front_buf]
     }
 
-    /// Unimplemented for indirect rendering.
+    /// Only valid for direct rendering.
     fn get_back_buf(&self) -> &[u8] {
-        &[0]
+        &self.buf[self.back_buf]
     }    
     
     /// Return the current refresh rate.
@@ -1435,7 +1489,14 @@ impl VideoCard for VGACard {
         }
     }    
 
-    fn get_current_font(&self) -> FontInfo {
+    fn get_blink_info(&self) -> BlinkInfo {
+        BlinkInfo {
+            enabled: matches!(self.attribute_mode_control.enable_blink_or_intensity(), AttributeBlinkOrIntensity::Blink),
+            visible: self.blink_state
+        }
+    }
+
+    fn get_current_font(&self) -> FontInfo<'_> {
 
         let w = EGA_FONTS[self.current_font].w;
         let h = EGA_FONTS[self.current_font].h;
@@ -1876,7 +1937,37 @@ impl VideoCard for VGACard {
     }
 
     fn get_frame_count(&self) -> u64 {
-        0
+        self.frame_count
+    }
+
+    fn set_blink_frozen(&mut self, frozen: bool) {
+        self.blink_frozen = frozen;
+    }
+
+    fn set_crtc_logging(&mut self, _enabled: bool) {
+        // Not implemented
+    }
+
+    fn take_crtc_log(&mut self) -> Vec<(u32, u8, u8)> {
+        Vec::new()
+    }
+
+    fn get_text_screen(&self) -> Option<TextScreen> {
+        if self.mode_graphics {
+            return None
+        }
+
+        let columns = if self.is_40_columns() { 40 } else { 80 };
+        let rows = self.get_display_size().1 / self.get_character_height().max(1) as u32;
+        let size = (columns * rows) as usize;
+
+        // Characters are stored in plane 0 and attributes in plane 1.
+        let base = self.get_start_address() as usize;
+        let plane_len = self.planes[0].buf.len();
+        let chars = (0..size).map(|i| self.planes[0].buf[(base + i) % plane_len]).collect();
+        let attrs = (0..size).map(|i| self.planes[1].buf[(base + i) % plane_len]).collect();
+
+        Some(TextScreen { columns, rows, chars, attrs })
     }
 
     fn write_trace_log(&mut self, msg: String) {
@@ -2264,4 +2355,91 @@ mod tests {
         */
 
     }
-}
\ No newline at end of file
+
+    /// Set up a tiny unchained 256-color display: 40 pixels (80 dots) by 10 scanlines, 
+    /// with 96 dots by 20 scanlines in total. Each pixel's color index is its plane + 1.
+    fn mode_x_card() -> VGACard {
+        let mut vga = VGACard::new(TraceLogger::None);
+
+        vga.attribute_mode_control = AModeControl::new()
+            .with_mode(AttributeMode::Graphics)
+            .with_pixel_clock_select(PixelClock::EveryOtherCycle);
+        vga.crtc_horizontal_total = 7;
+        vga.crtc_horizontal_display_end = 9;
+        vga.crtc_vertical_total = 17;
+        vga.crtc_vertical_display_end = 9;
+        vga.crtc_maximum_scanline = CMaximumScanline::new().with_maximum_scanline(1);
+        vga.crtc_offset = 5;
+        vga.crtc_line_compare = 0x3FF;
+        vga.recalculate_timings();
+        vga.recalculate_mode();
+
+        for (i, plane) in vga.planes.iter_mut().enumerate() {
+            plane.buf.fill(i as u8 + 1);
+        }
+        vga
+    }
+
+    #[test]
+    fn dac_change_mid_frame_is_drawn() {
+        let mut vga = mode_x_card();
+        assert_eq!(vga.get_display_mode(), DisplayMode::Mode13VGALowRes256);
+        assert!(matches!(vga.get_render_mode(), RenderMode::Direct));
+
+        // Change color 1 halfway down the display
+        vga.color_registers_rgba[1] = [0xFF, 0x00, 0x00, 0xFF];
+        vga.debug_tick(5 * 96);
+        vga.color_registers_rgba[1] = [0x00, 0x00, 0xFF, 0xFF];
+        vga.debug_tick(15 * 96);
+
+        assert_eq!(vga.get_frame_count(), 1);
+        assert_eq!(vga.get_display_aperture(), (80, 10));
+
+        let buf = vga.get_display_buf();
+        let stride = vga.get_display_extents().row_stride * 4;
+        // Pixel 0 is from plane 0 and is two dots wide
+        assert_eq!(buf[0..4], [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(buf[4..8], [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(buf[4 * stride..4 * stride + 4], [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(buf[5 * stride..5 * stride + 4], [0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(buf[9 * stride + 64..9 * stride + 68], [0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn start_address_is_latched_per_frame() {
+        let mut vga = mode_x_card();
+        vga.color_registers_rgba[1] = [0x11, 0x11, 0x11, 0xFF];
+        vga.color_registers_rgba[5] = [0x55, 0x55, 0x55, 0xFF];
+
+        // Fill a second page, starting 100 bytes into each plane, with color 5
+        vga.planes[0].buf[100..200].fill(5);
+
+        // Flipping pages mid-frame should not affect the frame being drawn
+        vga.debug_tick(5 * 96);
+        vga.crtc_start_address = 100;
+        vga.debug_tick(15 * 96);
+        assert_eq!(vga.get_display_buf()[0..4], [0x11, 0x11, 0x11, 0xFF]);
+
+        vga.debug_tick(20 * 96);
+        assert_eq!(vga.get_frame_count(), 2);
+        assert_eq!(vga.get_display_buf()[0..4], [0x55, 0x55, 0x55, 0xFF]);
+    }
+
+    #[test]
+    fn blink_phase_follows_vsync() {
+        let mut vga = mode_x_card();
+        let frame_ticks = 20 * 96;
+        let visible = vga.get_blink_info().visible;
+
+        // Text blinks every 16 frames
+        vga.debug_tick(15 * frame_ticks);
+        assert_eq!(vga.get_blink_info().visible, visible);
+        vga.debug_tick(frame_ticks);
+        assert_eq!(vga.get_frame_count(), 16);
+        assert_ne!(vga.get_blink_info().visible, visible);
+
+        vga.set_blink_frozen(true);
+        vga.debug_tick(16 * frame_ticks);
+        assert_ne!(vga.get_blink_info().visible, visible);
+    }
+}
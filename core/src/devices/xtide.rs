@@ -0,0 +1,752 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices::xtide.rs
+
+    Implements an XT-IDE (revision 1) compatible hard disk controller.
+
+    The XT-IDE maps the ATA task file registers to ports 0x300-0x307, and the
+    device control / alternate status register to 0x30E. Since the 8-bit ISA
+    bus cannot carry a 16-bit ATA data word, the high byte of each word is
+    latched at port 0x308: reading the data register latches the high byte,
+    and writing the data register sends the previously latched high byte 
+    along with it.
+
+    The controller is intended for use with the XTIDE Universal BIOS, which
+    polls for command completion, so interrupts are not generated. Commands
+    complete immediately.
+*/
+
+use std::{
+    error::Error,
+    fmt::Display
+};
+
+use crate::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use crate::vhd::VirtualHardDisk;
+use crate::disk_activity::DriveActivity;
+
+pub const XTIDE_BASE_PORT: u16 = 0x300;
+pub const XTIDE_PORT_COUNT: u16 = 0x10;
+pub const DEFAULT_XTIDE_ROM_ADDRESS: u32 = 0xC8000;
+pub const SECTOR_SIZE: usize = 512;
+
+// Geometry limits for CHS addressing.
+pub const XTIDE_MAX_CYLINDERS: u32 = 65535;
+pub const XTIDE_MAX_HEADS: u32 = 16;
+pub const XTIDE_MAX_SECTORS: u32 = 63;
+
+// The maximum number of sectors transferred per block by READ/WRITE MULTIPLE.
+const MAX_MULTIPLE: u8 = 16;
+
+// Register offsets from the base port.
+const REG_DATA: u16 = 0x0;
+const REG_ERROR_FEATURES: u16 = 0x1;
+const REG_SECTOR_COUNT: u16 = 0x2;
+const REG_SECTOR_NUMBER: u16 = 0x3;
+const REG_CYLINDER_LOW: u16 = 0x4;
+const REG_CYLINDER_HIGH: u16 = 0x5;
+const REG_DRIVE_HEAD: u16 = 0x6;
+const REG_STATUS_COMMAND: u16 = 0x7;
+const REG_DATA_HIGH: u16 = 0x8;
+const REG_ALT_STATUS_CONTROL: u16 = 0xE;
+
+const STATUS_ERR: u8 = 0b0000_0001;
+const STATUS_DRQ: u8 = 0b0000_1000;
+const STATUS_DSC: u8 = 0b0001_0000;
+const STATUS_DRDY: u8 = 0b0100_0000;
+
+const ERROR_ABRT: u8 = 0b0000_0100;
+const ERROR_IDNF: u8 = 0b0001_0000;
+const ERROR_UNC: u8 = 0b0100_0000;
+
+const CONTROL_SRST: u8 = 0b0000_0100;
+
+const DRIVE_HEAD_LBA: u8 = 0b0100_0000;
+const DRIVE_HEAD_DRV: u8 = 0b0001_0000;
+
+const CMD_RECALIBRATE: u8 = 0x10;
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_NORETRY: u8 = 0x21;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_NORETRY: u8 = 0x31;
+const CMD_VERIFY_SECTORS: u8 = 0x40;
+const CMD_VERIFY_SECTORS_NORETRY: u8 = 0x41;
+const CMD_SEEK: u8 = 0x70;
+const CMD_EXECUTE_DIAGNOSTIC: u8 = 0x90;
+const CMD_INITIALIZE_PARAMETERS: u8 = 0x91;
+const CMD_READ_MULTIPLE: u8 = 0xC4;
+const CMD_WRITE_MULTIPLE: u8 = 0xC5;
+const CMD_SET_MULTIPLE_MODE: u8 = 0xC6;
+const CMD_IDENTIFY: u8 = 0xEC;
+const CMD_SET_FEATURES: u8 = 0xEF;
+
+const FEATURE_ENABLE_8BIT: u8 = 0x01;
+const FEATURE_DISABLE_8BIT: u8 = 0x81;
+
+#[derive (Debug)]
+pub enum XtIdeError {
+    InvalidDevice,
+    UnsupportedVHD,
+}
+impl Error for XtIdeError {}
+impl Display for XtIdeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            XtIdeError::InvalidDevice => write!(f, "The specified Device ID was out of range [0..1]"),
+            XtIdeError::UnsupportedVHD => write!(f, "The VHD geometry exceeds the limits of the XT-IDE controller."),
+        }
+    }
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum Transfer {
+    None,
+    Identify,
+    Read,
+    Write,
+}
+
+struct IdeDrive {
+    vhd: Option<VirtualHardDisk>,
+    // Logical geometry used for CHS addressing. Defaults to the image geometry, and may be 
+    // changed by INITIALIZE DEVICE PARAMETERS.
+    logical_heads: u32,
+    logical_sectors: u32,
+    multiple_count: u8,
+    // Cylinder of the last sector accessed, to count the steps the head takes.
+    cylinder: u16,
+    activity: DriveActivity,
+}
+
+impl IdeDrive {
+    fn new() -> Self {
+        Self {
+            vhd: None,
+            logical_heads: 0,
+            logical_sectors: 0,
+            multiple_count: 0,
+            cylinder: 0,
+            activity: DriveActivity::default(),
+        }
+    }
+
+    fn total_sectors(&self) -> u32 {
+        match &self.vhd {
+            Some(vhd) => vhd.max_cylinders * vhd.max_heads * vhd.max_sectors,
+            None => 0
+        }
+    }
+
+    /// Count an access to the specified cylinder.
+    fn record_access(&mut self, cylinder: u16) {
+        self.activity.record_seek(self.cylinder, cylinder);
+        self.activity.record_access();
+        self.cylinder = cylinder;
+    }
+
+    /// Read a sector by LBA, translating to the geometry of the disk image.
+    fn read_lba(&mut self, lba: u32, buf: &mut [u8]) -> bool {
+        match &mut self.vhd {
+            Some(vhd) => {
+                let (c, h, s) = lba_to_chs(lba, vhd.max_heads, vhd.max_sectors);
+                let result = vhd.read_sector(buf, c, h, s).is_ok();
+                self.record_access(c);
+                result
+            }
+            None => false
+        }
+    }
+
+    fn write_lba(&mut self, lba: u32, buf: &[u8]) -> bool {
+        match &mut self.vhd {
+            Some(vhd) => {
+                let (c, h, s) = lba_to_chs(lba, vhd.max_heads, vhd.max_sectors);
+                let result = vhd.write_sector(buf, c, h, s).is_ok();
+                self.record_access(c);
+                result
+            }
+            None => false
+        }
+    }
+}
+
+/// Convert an LBA to a zero-based CHS address for the given geometry.
+fn lba_to_chs(lba: u32, heads: u32, sectors: u32) -> (u16, u8, u8) {
+    let c = lba / (heads * sectors);
+    let h = (lba / sectors) % heads;
+    let s = lba % sectors;
+    (c as u16, h as u8, s as u8)
+}
+
+/// Store an ATA string into IDENTIFY data. ATA strings are stored with the first character
+/// of each word in the high byte.
+fn put_ata_string(buf: &mut [u8], word: usize, len_words: usize, s: &str) {
+    let mut bytes: Vec<u8> = s.bytes().collect();
+    bytes.resize(len_words * 2, b' ');
+    for i in 0..len_words {
+        buf[(word + i) * 2] = bytes[i * 2 + 1];
+        buf[(word + i) * 2 + 1] = bytes[i * 2];
+    }
+}
+
+fn put_word(buf: &mut [u8], word: usize, value: u16) {
+    buf[word * 2..word * 2 + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub struct XtIdeController {
+    drives: [IdeDrive; 2],
+
+    features: u8,
+    sector_count: u8,
+    sector_number: u8,
+    cylinder: u16,
+    drive_head: u8,
+    error: u8,
+    status: u8,
+    device_control: u8,
+
+    data_high_latch: u8,
+    eight_bit: bool,
+
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    transfer: Transfer,
+    transfer_lba: u32,
+    transfer_remaining: u32,
+    transfer_block: u32,
+}
+
+impl IoDevice for XtIdeController {
+    fn read_u8(&mut self, port: u16, _delta: DeviceRunTimeUnit) -> u8 {
+        match port - XTIDE_BASE_PORT {
+            REG_DATA => self.handle_data_read(),
+            REG_ERROR_FEATURES => self.error,
+            REG_SECTOR_COUNT => self.sector_count,
+            REG_SECTOR_NUMBER => self.sector_number,
+            REG_CYLINDER_LOW => self.cylinder as u8,
+            REG_CYLINDER_HIGH => (self.cylinder >> 8) as u8,
+            REG_DRIVE_HEAD => self.drive_head,
+            REG_STATUS_COMMAND | REG_ALT_STATUS_CONTROL => self.read_status(),
+            REG_DATA_HIGH => self.data_high_latch,
+            _ => 0xFF
+        }
+    }
+
+    fn write_u8(&mut self, port: u16, data: u8, _bus: Option<&mut BusInterface>, _delta: DeviceRunTimeUnit) {
+        match port - XTIDE_BASE_PORT {
+            REG_DATA => self.handle_data_write(data),
+            REG_ERROR_FEATURES => self.features = data,
+            REG_SECTOR_COUNT => self.sector_count = data,
+            REG_SECTOR_NUMBER => self.sector_number = data,
+            REG_CYLINDER_LOW => self.cylinder = (self.cylinder & 0xFF00) | data as u16,
+            REG_CYLINDER_HIGH => self.cylinder = (self.cylinder & 0x00FF) | (data as u16) << 8,
+            REG_DRIVE_HEAD => self.drive_head = data,
+            REG_STATUS_COMMAND => self.handle_command(data),
+            REG_DATA_HIGH => self.data_high_latch = data,
+            REG_ALT_STATUS_CONTROL => self.handle_control_write(data),
+            _ => {}
+        }
+    }
+
+    fn port_list(&self) -> Vec<u16> {
+        (XTIDE_BASE_PORT..XTIDE_BASE_PORT + XTIDE_PORT_COUNT).collect()
+    }
+}
+
+impl Default for XtIdeController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XtIdeController {
+    pub fn new() -> Self {
+        Self {
+            drives: [IdeDrive::new(), IdeDrive::new()],
+            features: 0,
+            sector_count: 1,
+            sector_number: 1,
+            cylinder: 0,
+            drive_head: 0,
+            error: 0x01,
+            status: STATUS_DRDY | STATUS_DSC,
+            device_control: 0,
+            data_high_latch: 0,
+            eight_bit: false,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+            transfer: Transfer::None,
+            transfer_lba: 0,
+            transfer_remaining: 0,
+            transfer_block: 1,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        log::trace!("Resetting XT-IDE controller...");
+        self.sector_count = 1;
+        self.sector_number = 1;
+        self.cylinder = 0;
+        self.drive_head = 0;
+        // Diagnostic code 0x01: no error detected.
+        self.error = 0x01;
+        self.status = STATUS_DRDY | STATUS_DSC;
+        self.eight_bit = false;
+        self.end_transfer();
+    }
+
+    pub fn set_vhd(&mut self, device_id: usize, vhd: VirtualHardDisk) -> Result<(), XtIdeError> {
+        if device_id > 1 {
+            return Err(XtIdeError::InvalidDevice)
+        }
+
+        let supported = vhd.max_cylinders > 0 && vhd.max_cylinders <= XTIDE_MAX_CYLINDERS
+            && vhd.max_heads > 0 && vhd.max_heads <= XTIDE_MAX_HEADS
+            && vhd.max_sectors > 0 && vhd.max_sectors <= XTIDE_MAX_SECTORS;
+        if !supported {
+            return Err(XtIdeError::UnsupportedVHD)
+        }
+
+        let drive = &mut self.drives[device_id];
+        drive.logical_heads = vhd.max_heads;
+        drive.logical_sectors = vhd.max_sectors;
+        drive.vhd = Some(vhd);
+        Ok(())
+    }
+
+    /// Flush any pending writes on all mounted disk images.
+    /// Return the activity of the specified drive since this was last called, or None if
+    /// no disk image is mounted in the drive.
+    pub fn take_activity(&mut self, device_id: usize) -> Option<DriveActivity> {
+        let drive = self.drives.get_mut(device_id)?;
+        drive.activity.motor_on = drive.vhd.is_some();
+        drive.vhd.as_ref().map(|_| drive.activity.take())
+    }
+
+    pub fn flush(&mut self) {
+        for (i, drive) in self.drives.iter_mut().enumerate() {
+            if let Some(vhd) = &mut drive.vhd {
+                if let Err(e) = vhd.flush() {
+                    log::error!("Failed to flush disk image for drive {}: {}", i, e);
+                }
+            }
+        }
+    }
+
+    fn selected(&self) -> usize {
+        if self.drive_head & DRIVE_HEAD_DRV != 0 { 1 } else { 0 }
+    }
+
+    fn read_status(&self) -> u8 {
+        // An absent drive does not drive the bus.
+        if self.drives[self.selected()].vhd.is_none() {
+            return 0
+        }
+        self.status
+    }
+
+    fn handle_control_write(&mut self, data: u8) {
+        // A software reset occurs on the falling edge of SRST.
+        if self.device_control & CONTROL_SRST != 0 && data & CONTROL_SRST == 0 {
+            self.reset();
+        }
+        self.device_control = data;
+    }
+
+    fn abort(&mut self, error: u8) {
+        self.error = error;
+        self.status = STATUS_DRDY | STATUS_DSC | STATUS_ERR;
+        self.end_transfer();
+    }
+
+    fn complete(&mut self) {
+        self.error = 0;
+        self.status = STATUS_DRDY | STATUS_DSC;
+    }
+
+    fn end_transfer(&mut self) {
+        self.transfer = Transfer::None;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.status &= !STATUS_DRQ;
+    }
+
+    /// Return the LBA addressed by the task file registers.
+    fn task_file_lba(&self) -> Option<u32> {
+        if self.drive_head & DRIVE_HEAD_LBA != 0 {
+            return Some(
+                ((self.drive_head & 0x0F) as u32) << 24
+                    | (self.cylinder as u32) << 8
+                    | self.sector_number as u32
+            )
+        }
+        let drive = &self.drives[self.selected()];
+        let head = (self.drive_head & 0x0F) as u32;
+        if self.sector_number == 0 
+            || self.sector_number as u32 > drive.logical_sectors 
+            || head >= drive.logical_heads 
+        {
+            return None
+        }
+        Some((self.cylinder as u32 * drive.logical_heads + head) * drive.logical_sectors + self.sector_number as u32 - 1)
+    }
+
+    /// Update the task file registers to address the specified LBA.
+    fn set_task_file_lba(&mut self, lba: u32) {
+        if self.drive_head & DRIVE_HEAD_LBA != 0 {
+            self.sector_number = lba as u8;
+            self.cylinder = (lba >> 8) as u16;
+            self.drive_head = (self.drive_head & 0xF0) | ((lba >> 24) as u8 & 0x0F);
+        }
+        else {
+            let drive = &self.drives[self.selected()];
+            if drive.logical_heads > 0 && drive.logical_sectors > 0 {
+                let (c, h, s) = lba_to_chs(lba, drive.logical_heads, drive.logical_sectors);
+                self.cylinder = c;
+                self.drive_head = (self.drive_head & 0xF0) | (h & 0x0F);
+                self.sector_number = s + 1;
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: u8) {
+        let drive_n = self.selected();
+        if self.drives[drive_n].vhd.is_none() {
+            log::trace!("XT-IDE: Command {:02X} to absent drive {}", command, drive_n);
+            return
+        }
+        log::trace!("XT-IDE: Command {:02X} to drive {}", command, drive_n);
+        self.end_transfer();
+
+        match command {
+            CMD_IDENTIFY => {
+                self.buffer = self.identify_data(drive_n);
+                self.buffer_pos = 0;
+                self.transfer = Transfer::Identify;
+                self.complete();
+                self.status |= STATUS_DRQ;
+            }
+            CMD_READ_SECTORS | CMD_READ_SECTORS_NORETRY | CMD_READ_MULTIPLE => {
+                let block = if command == CMD_READ_MULTIPLE { self.drives[drive_n].multiple_count } else { 1 };
+                self.start_transfer(Transfer::Read, block);
+            }
+            CMD_WRITE_SECTORS | CMD_WRITE_SECTORS_NORETRY | CMD_WRITE_MULTIPLE => {
+                let block = if command == CMD_WRITE_MULTIPLE { self.drives[drive_n].multiple_count } else { 1 };
+                self.start_transfer(Transfer::Write, block);
+            }
+            CMD_VERIFY_SECTORS | CMD_VERIFY_SECTORS_NORETRY => {
+                let count = if self.sector_count == 0 { 256 } else { self.sector_count as u32 };
+                match self.task_file_lba() {
+                    Some(lba) if lba + count <= self.drives[drive_n].total_sectors() => {
+                        self.set_task_file_lba(lba + count - 1);
+                        self.complete();
+                    }
+                    _ => self.abort(ERROR_IDNF)
+                }
+            }
+            CMD_SEEK => {
+                match self.task_file_lba() {
+                    Some(lba) if lba < self.drives[drive_n].total_sectors() => self.complete(),
+                    _ => self.abort(ERROR_IDNF)
+                }
+            }
+            c if c & 0xF0 == CMD_RECALIBRATE => {
+                self.cylinder = 0;
+                self.complete();
+            }
+            CMD_EXECUTE_DIAGNOSTIC => {
+                self.complete();
+                self.error = 0x01;
+            }
+            CMD_INITIALIZE_PARAMETERS => {
+                let heads = (self.drive_head & 0x0F) as u32 + 1;
+                let sectors = self.sector_count as u32;
+                if sectors == 0 {
+                    self.abort(ERROR_ABRT);
+                }
+                else {
+                    let drive = &mut self.drives[drive_n];
+                    drive.logical_heads = heads;
+                    drive.logical_sectors = sectors;
+                    self.complete();
+                }
+            }
+            CMD_SET_MULTIPLE_MODE => {
+                let count = self.sector_count;
+                if count <= MAX_MULTIPLE && (count == 0 || count.is_power_of_two()) {
+                    self.drives[drive_n].multiple_count = count;
+                    self.complete();
+                }
+                else {
+                    self.abort(ERROR_ABRT);
+                }
+            }
+            CMD_SET_FEATURES => {
+                match self.features {
+                    FEATURE_ENABLE_8BIT => {
+                        self.eight_bit = true;
+                        self.complete();
+                    }
+                    FEATURE_DISABLE_8BIT => {
+                        self.eight_bit = false;
+                        self.complete();
+                    }
+                    // Accept and ignore transfer mode and cache settings.
+                    0x02 | 0x03 | 0x82 => self.complete(),
+                    _ => self.abort(ERROR_ABRT)
+                }
+            }
+            _ => {
+                log::debug!("XT-IDE: Unsupported command: {:02X}", command);
+                self.abort(ERROR_ABRT);
+            }
+        }
+    }
+
+    fn start_transfer(&mut self, transfer: Transfer, block: u8) {
+        if block == 0 {
+            // READ/WRITE MULTIPLE without SET MULTIPLE MODE.
+            self.abort(ERROR_ABRT);
+            return
+        }
+        let count = if self.sector_count == 0 { 256 } else { self.sector_count as u32 };
+        let lba = match self.task_file_lba() {
+            Some(lba) if lba + count <= self.drives[self.selected()].total_sectors() => lba,
+            _ => {
+                self.abort(ERROR_IDNF);
+                return
+            }
+        };
+
+        self.transfer = transfer;
+        self.transfer_lba = lba;
+        self.transfer_remaining = count;
+        self.transfer_block = block as u32;
+        self.complete();
+
+        match transfer {
+            Transfer::Read => self.load_read_block(),
+            _ => self.status |= STATUS_DRQ
+        }
+    }
+
+    fn block_sectors(&self) -> u32 {
+        self.transfer_block.min(self.transfer_remaining)
+    }
+
+    /// Read the next block of sectors of a read transfer into the buffer.
+    fn load_read_block(&mut self) {
+        let sectors = self.block_sectors();
+        self.buffer = vec![0; sectors as usize * SECTOR_SIZE];
+        self.buffer_pos = 0;
+
+        let drive_n = self.selected();
+        for i in 0..sectors {
+            let lba = self.transfer_lba + i;
+            let start = i as usize * SECTOR_SIZE;
+            if !self.drives[drive_n].read_lba(lba, &mut self.buffer[start..start + SECTOR_SIZE]) {
+                log::error!("XT-IDE: Error reading LBA {} from drive {}", lba, drive_n);
+                self.set_task_file_lba(lba);
+                self.abort(ERROR_UNC);
+                return
+            }
+        }
+        self.status |= STATUS_DRQ;
+    }
+
+    /// Called when the host has transferred a full block.
+    fn block_done(&mut self) {
+        let sectors = self.block_sectors();
+        let drive_n = self.selected();
+
+        if self.transfer == Transfer::Write {
+            for i in 0..sectors {
+                let lba = self.transfer_lba + i;
+                let start = i as usize * SECTOR_SIZE;
+                let sector = self.buffer[start..start + SECTOR_SIZE].to_vec();
+                if !self.drives[drive_n].write_lba(lba, &sector) {
+                    log::error!("XT-IDE: Error writing LBA {} to drive {}", lba, drive_n);
+                    self.set_task_file_lba(lba);
+                    self.abort(ERROR_UNC);
+                    return
+                }
+            }
+        }
+
+        self.transfer_lba += sectors;
+        self.transfer_remaining -= sectors;
+        self.set_task_file_lba(self.transfer_lba - 1);
+        self.sector_count = self.transfer_remaining as u8;
+
+        if self.transfer_remaining == 0 {
+            self.end_transfer();
+            return
+        }
+
+        match self.transfer {
+            Transfer::Read => self.load_read_block(),
+            _ => {
+                self.buffer.clear();
+                self.buffer_pos = 0;
+            }
+        }
+    }
+
+    fn handle_data_read(&mut self) -> u8 {
+        if !matches!(self.transfer, Transfer::Identify | Transfer::Read) || self.buffer_pos >= self.buffer.len() {
+            return 0xFF
+        }
+
+        let byte = self.buffer[self.buffer_pos];
+        if self.eight_bit {
+            self.buffer_pos += 1;
+        }
+        else {
+            self.data_high_latch = self.buffer.get(self.buffer_pos + 1).copied().unwrap_or(0xFF);
+            self.buffer_pos += 2;
+        }
+
+        if self.buffer_pos >= self.buffer.len() {
+            match self.transfer {
+                Transfer::Identify => self.end_transfer(),
+                _ => self.block_done()
+            }
+        }
+        byte
+    }
+
+    fn handle_data_write(&mut self, data: u8) {
+        if self.transfer != Transfer::Write {
+            return
+        }
+
+        self.buffer.push(data);
+        if !self.eight_bit {
+            self.buffer.push(self.data_high_latch);
+        }
+
+        if self.buffer.len() >= self.block_sectors() as usize * SECTOR_SIZE {
+            self.block_done();
+        }
+    }
+
+    fn identify_data(&self, drive_n: usize) -> Vec<u8> {
+        let mut buf = vec![0; SECTOR_SIZE];
+        let drive = &self.drives[drive_n];
+        let (cylinders, heads, sectors) = match &drive.vhd {
+            Some(vhd) => (vhd.max_cylinders, vhd.max_heads, vhd.max_sectors),
+            None => (0, 0, 0)
+        };
+        let total = drive.total_sectors();
+
+        put_word(&mut buf, 0, 0x0040); // Fixed drive
+        put_word(&mut buf, 1, cylinders as u16);
+        put_word(&mut buf, 3, heads as u16);
+        put_word(&mut buf, 6, sectors as u16);
+        put_ata_string(&mut buf, 10, 10, &format!("MARTYPC{:02}", drive_n));
+        put_ata_string(&mut buf, 23, 4, "1.0");
+        put_ata_string(&mut buf, 27, 20, "MartyPC XT-IDE Disk");
+        put_word(&mut buf, 47, 0x8000 | MAX_MULTIPLE as u16);
+        put_word(&mut buf, 49, 0x0200); // LBA supported
+        put_word(&mut buf, 53, 0x0001); // Words 54-58 are valid
+
+        let current_cylinders = if drive.logical_heads > 0 && drive.logical_sectors > 0 {
+            (total / (drive.logical_heads * drive.logical_sectors)).min(XTIDE_MAX_CYLINDERS)
+        }
+        else {
+            0
+        };
+        put_word(&mut buf, 54, current_cylinders as u16);
+        put_word(&mut buf, 55, drive.logical_heads as u16);
+        put_word(&mut buf, 56, drive.logical_sectors as u16);
+        let current_capacity = current_cylinders * drive.logical_heads * drive.logical_sectors;
+        put_word(&mut buf, 57, current_capacity as u16);
+        put_word(&mut buf, 58, (current_capacity >> 16) as u16);
+        if drive.multiple_count > 0 {
+            put_word(&mut buf, 59, 0x0100 | drive.multiple_count as u16);
+        }
+        put_word(&mut buf, 60, total as u16);
+        put_word(&mut buf, 61, (total >> 16) as u16);
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_DELTA: DeviceRunTimeUnit = DeviceRunTimeUnit::Microseconds(0.0);
+
+    #[test]
+    fn test_register_decode() {
+        let mut xtide = XtIdeController::new();
+        assert_eq!(xtide.port_list(), (0x300..0x310).collect::<Vec<u16>>());
+
+        xtide.write_u8(0x302, 0x11, None, NO_DELTA);
+        xtide.write_u8(0x303, 0x22, None, NO_DELTA);
+        xtide.write_u8(0x304, 0x33, None, NO_DELTA);
+        xtide.write_u8(0x305, 0x44, None, NO_DELTA);
+        xtide.write_u8(0x306, 0xA5, None, NO_DELTA);
+        xtide.write_u8(0x308, 0x66, None, NO_DELTA);
+
+        assert_eq!(xtide.sector_count, 0x11);
+        assert_eq!(xtide.sector_number, 0x22);
+        assert_eq!(xtide.cylinder, 0x4433);
+        assert_eq!(xtide.drive_head, 0xA5);
+        assert_eq!(xtide.read_u8(0x302, NO_DELTA), 0x11);
+        assert_eq!(xtide.read_u8(0x303, NO_DELTA), 0x22);
+        assert_eq!(xtide.read_u8(0x304, NO_DELTA), 0x33);
+        assert_eq!(xtide.read_u8(0x305, NO_DELTA), 0x44);
+        assert_eq!(xtide.read_u8(0x306, NO_DELTA), 0xA5);
+        assert_eq!(xtide.read_u8(0x308, NO_DELTA), 0x66);
+
+        // Unassigned ports float high; an absent drive doesn't drive the status register
+        assert_eq!(xtide.read_u8(0x309, NO_DELTA), 0xFF);
+        assert_eq!(xtide.read_u8(0x307, NO_DELTA), 0x00);
+        assert_eq!(xtide.read_u8(0x30E, NO_DELTA), 0x00);
+    }
+
+    #[test]
+    fn test_software_reset() {
+        let mut xtide = XtIdeController::new();
+        xtide.write_u8(0x302, 0x11, None, NO_DELTA);
+        xtide.write_u8(0x304, 0x33, None, NO_DELTA);
+        xtide.write_u8(0x306, 0xB0, None, NO_DELTA);
+
+        // Reset occurs when SRST is cleared, not when it is set
+        xtide.write_u8(0x30E, CONTROL_SRST, None, NO_DELTA);
+        assert_eq!(xtide.read_u8(0x302, NO_DELTA), 0x11);
+        xtide.write_u8(0x30E, 0x00, None, NO_DELTA);
+
+        assert_eq!(xtide.read_u8(0x302, NO_DELTA), 0x01);
+        assert_eq!(xtide.read_u8(0x303, NO_DELTA), 0x01);
+        assert_eq!(xtide.read_u8(0x304, NO_DELTA), 0x00);
+        assert_eq!(xtide.read_u8(0x306, NO_DELTA), 0x00);
+        // Diagnostic code: no error
+        assert_eq!(xtide.read_u8(0x301, NO_DELTA), 0x01);
+    }
+}
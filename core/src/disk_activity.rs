@@ -0,0 +1,277 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    disk_activity.rs
+
+    Tracks the activity of floppy and hard disk drives for the frontend's 
+    activity indicators, and synthesizes drive sounds from it.
+
+    Disk controllers count the sectors accessed and the cylinders stepped by 
+    each drive. The machine collects these counts once per frame. Sounds are
+    synthesized rather than sampled: a floppy motor rumble, a click for each
+    head step, and a quieter hum and tick for hard disks.
+*/
+
+use std::f32::consts::TAU;
+
+/// Volume of drive sounds in the machine's audio mix.
+pub const DRIVE_SOUND_VOLUME: f32 = 0.5;
+
+// Interval between floppy head steps, in seconds. PC BIOSes program a 6ms step rate.
+const FLOPPY_STEP_TIME: f32 = 0.006;
+// Interval between hard disk seek ticks, in seconds.
+const HDD_SEEK_TIME: f32 = 0.015;
+
+// Limit the steps queued, so a long burst of activity doesn't keep clicking after it ends.
+const MAX_PENDING_STEPS: u32 = 160;
+
+const FLOPPY_MOTOR_LEVEL: f32 = 0.08;
+const FLOPPY_STEP_LEVEL: f32 = 0.5;
+const FLOPPY_STEP_FREQ: f32 = 1200.0;
+const FLOPPY_STEP_DECAY: f32 = 0.0025;
+const HDD_SPIN_LEVEL: f32 = 0.015;
+const HDD_SPIN_FREQ: f32 = 120.0;
+const HDD_SEEK_LEVEL: f32 = 0.2;
+const HDD_SEEK_FREQ: f32 = 2500.0;
+const HDD_SEEK_DECAY: f32 = 0.001;
+
+// Frequency of the floppy motor rumble's amplitude modulation: 300 RPM.
+const FLOPPY_ROTATION_FREQ: f32 = 5.0;
+
+/// The activity of a disk drive since it was last taken.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DriveActivity {
+    /// The drive's spindle motor is running
+    pub motor_on: bool,
+    /// Number of sector operations
+    pub accesses: u32,
+    /// Number of cylinders the head has stepped
+    pub seek_steps: u32,
+}
+
+impl DriveActivity {
+    pub fn record_access(&mut self) {
+        self.accesses = self.accesses.saturating_add(1);
+    }
+
+    pub fn record_seek(&mut self, from: u16, to: u16) {
+        self.seek_steps = self.seek_steps.saturating_add(from.abs_diff(to) as u32);
+    }
+
+    /// Return the activity recorded so far and clear the counts. The motor state is kept.
+    pub fn take(&mut self) -> DriveActivity {
+        let activity = *self;
+        self.accesses = 0;
+        self.seek_steps = 0;
+        activity
+    }
+
+    pub fn busy(&self) -> bool {
+        self.accesses > 0 || self.seek_steps > 0
+    }
+
+    /// Add the activity of a later period to this one.
+    pub fn merge(&mut self, later: &DriveActivity) {
+        self.motor_on = later.motor_on;
+        self.accesses = self.accesses.saturating_add(later.accesses);
+        self.seek_steps = self.seek_steps.saturating_add(later.seek_steps);
+    }
+}
+
+/// The activity of every disk drive in the machine. A drive that isn't present has no entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskActivity {
+    pub floppy: Vec<DriveActivity>,
+    pub hard_disk: Vec<DriveActivity>,
+}
+
+impl DiskActivity {
+    /// Add the activity of a later period to this one.
+    pub fn merge(&mut self, later: &DiskActivity) {
+        for (list, later_list) in [(&mut self.floppy, &later.floppy), (&mut self.hard_disk, &later.hard_disk)] {
+            list.resize(later_list.len(), DriveActivity::default());
+            for (drive, later_drive) in list.iter_mut().zip(later_list) {
+                drive.merge(later_drive);
+            }
+        }
+    }
+}
+
+/// A decaying sine burst, for head step clicks.
+#[derive(Default)]
+struct Click {
+    phase: f32,
+    envelope: f32,
+}
+
+impl Click {
+    fn trigger(&mut self) {
+        self.phase = 0.0;
+        self.envelope = 1.0;
+    }
+
+    fn sample(&mut self, freq: f32, decay: f32, sample_rate: f32) -> f32 {
+        if self.envelope < 0.001 {
+            return 0.0;
+        }
+        let out = (self.phase * TAU).sin() * self.envelope;
+        self.phase = (self.phase + freq / sample_rate).fract();
+        self.envelope *= (-1.0 / (decay * sample_rate)).exp();
+        out
+    }
+}
+
+/// Queues steps and plays them at a fixed rate.
+#[derive(Default)]
+struct StepQueue {
+    pending: u32,
+    timer: f32,
+    click: Click,
+}
+
+impl StepQueue {
+    fn add(&mut self, steps: u32) {
+        self.pending = (self.pending + steps).min(MAX_PENDING_STEPS);
+    }
+
+    fn sample(&mut self, interval: f32, freq: f32, decay: f32, sample_rate: f32) -> f32 {
+        self.timer -= 1.0 / sample_rate;
+        if self.timer <= 0.0 && self.pending > 0 {
+            self.pending -= 1;
+            self.timer = interval;
+            self.click.trigger();
+        }
+        self.click.sample(freq, decay, sample_rate)
+    }
+}
+
+/// Synthesizes drive sounds from disk activity.
+pub struct DriveSound {
+    sample_rate: f32,
+    floppy_motors: usize,
+    hdd_spinning: bool,
+    floppy_steps: StepQueue,
+    hdd_seeks: StepQueue,
+    rotation_phase: f32,
+    spin_phase: f32,
+    noise: u32,
+    rumble: f32,
+}
+
+impl DriveSound {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f32,
+            floppy_motors: 0,
+            hdd_spinning: false,
+            floppy_steps: StepQueue::default(),
+            hdd_seeks: StepQueue::default(),
+            rotation_phase: 0.0,
+            spin_phase: 0.0,
+            noise: 0x2545_F491,
+            rumble: 0.0,
+        }
+    }
+
+    /// Queue the sounds for a frame's worth of disk activity.
+    pub fn update(&mut self, activity: &DiskActivity) {
+        self.floppy_motors = activity.floppy.iter().filter(|d| d.motor_on).count();
+        self.hdd_spinning = activity.hard_disk.iter().any(|d| d.motor_on);
+        self.floppy_steps.add(activity.floppy.iter().map(|d| d.seek_steps).sum());
+        // Hard disks seek too quickly to hear each cylinder; tick once per seek.
+        self.hdd_seeks.add(activity.hard_disk.iter().filter(|d| d.seek_steps > 0).count() as u32);
+    }
+
+    pub fn generate_sample(&mut self) -> f32 {
+        let rate = self.sample_rate;
+        let mut output = 0.0;
+
+        if self.floppy_motors > 0 {
+            // Low-pass filtered noise, modulated by the rotation of the disk.
+            self.noise ^= self.noise << 13;
+            self.noise ^= self.noise >> 17;
+            self.noise ^= self.noise << 5;
+            let white = (self.noise as f32 / u32::MAX as f32) * 2.0 - 1.0;
+            self.rumble += (white - self.rumble) * 0.02;
+            self.rotation_phase = (self.rotation_phase + FLOPPY_ROTATION_FREQ / rate).fract();
+            let modulation = 0.75 + 0.25 * (self.rotation_phase * TAU).sin();
+            output += self.rumble * modulation * FLOPPY_MOTOR_LEVEL * self.floppy_motors as f32;
+        }
+
+        if self.hdd_spinning {
+            self.spin_phase = (self.spin_phase + HDD_SPIN_FREQ / rate).fract();
+            output += (self.spin_phase * TAU).sin() * HDD_SPIN_LEVEL;
+        }
+
+        output += self.floppy_steps.sample(FLOPPY_STEP_TIME, FLOPPY_STEP_FREQ, FLOPPY_STEP_DECAY, rate) * FLOPPY_STEP_LEVEL;
+        output += self.hdd_seeks.sample(HDD_SEEK_TIME, HDD_SEEK_FREQ, HDD_SEEK_DECAY, rate) * HDD_SEEK_LEVEL;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_and_merge_activity() {
+        let mut drive = DriveActivity { motor_on: true, ..Default::default() };
+        drive.record_seek(10, 2);
+        drive.record_access();
+        let taken = drive.take();
+        assert_eq!(taken, DriveActivity { motor_on: true, accesses: 1, seek_steps: 8 });
+        assert!(!drive.busy());
+        assert!(drive.motor_on);
+
+        let mut total = DiskActivity::default();
+        total.merge(&DiskActivity { floppy: vec![taken, DriveActivity::default()], hard_disk: Vec::new() });
+        total.merge(&DiskActivity { floppy: vec![taken, DriveActivity::default()], hard_disk: Vec::new() });
+        assert_eq!(total.floppy[0].seek_steps, 16);
+        assert!(!total.floppy[1].busy());
+    }
+
+    #[test]
+    fn steps_are_paced() {
+        let mut sound = DriveSound::new(1000);
+        let mut activity = DiskActivity { floppy: vec![DriveActivity::default()], hard_disk: Vec::new() };
+        activity.floppy[0].seek_steps = 3;
+        sound.update(&activity);
+
+        // Silent until a step triggers, and each step is 6 samples apart at 1kHz.
+        sound.generate_sample();
+        assert_eq!(sound.floppy_steps.pending, 2);
+        for _ in 0..6 {
+            sound.generate_sample();
+        }
+        assert_eq!(sound.floppy_steps.pending, 1);
+
+        // With no steps or motors, the synthesizer falls silent.
+        for _ in 0..100 {
+            sound.generate_sample();
+        }
+        assert_eq!(sound.generate_sample(), 0.0);
+    }
+}
@@ -0,0 +1,503 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    disk_image.rs
+
+    Creates new blank floppy and hard disk images.
+
+    Floppy images are raw sector images of one of the standard PC and AT 
+    formats. A formatted floppy receives a DOS 2.0+ boot sector, two empty 
+    FATs and an empty root directory, as FORMAT would write it.
+
+    Hard disk images use the geometry of one of the first AT drive types. 
+    A formatted hard disk receives a master boot record with a single active 
+    primary FAT partition spanning the disk, leaving the last cylinder free 
+    for diagnostics as FDISK does. The image data is written into a VHD by
+    vhd::create_vhd_from().
+
+    Neither boot sector contains an operating system: booting a formatted 
+    disk prints a message until one is installed with SYS.
+*/
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use crate::devices::hdc::HardDiskFormat;
+
+pub const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+const RESERVED_SECTORS: usize = 1;
+const FAT_COUNT: usize = 2;
+
+// A FAT with this many clusters or more must be FAT16.
+const FAT12_MAX_CLUSTERS: usize = 4085;
+
+// The byte DOS FORMAT fills the data area of a floppy with.
+const FORMAT_FILL_BYTE: u8 = 0xF6;
+
+const HARD_DISK_MEDIA_DESCRIPTOR: u8 = 0xF8;
+const HARD_DISK_ROOT_ENTRIES: usize = 512;
+
+const PARTITION_TABLE_OFFSET: usize = 0x1BE;
+const PARTITION_ACTIVE: u8 = 0x80;
+const PARTITION_TYPE_FAT12: u8 = 0x01;
+const PARTITION_TYPE_FAT16: u8 = 0x04;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+// Volume boot record code, entered at offset 0x3E. Prints the message at 
+// 0x58 and reboots via int 19h on a keypress.
+const VBR_CODE_OFFSET: usize = 0x3E;
+const VBR_CODE: [u8; 26] = [
+    0x31, 0xC0,             // xor ax, ax
+    0x8E, 0xD8,             // mov ds, ax
+    0x31, 0xDB,             // xor bx, bx
+    0xBE, 0x58, 0x7C,       // mov si, 7C58h
+    0xAC,                   // lodsb
+    0x08, 0xC0,             // or al, al
+    0x74, 0x06,             // jz done
+    0xB4, 0x0E,             // mov ah, 0Eh
+    0xCD, 0x10,             // int 10h
+    0xEB, 0xF5,             // jmp lodsb
+    0x31, 0xC0,             // done: xor ax, ax
+    0xCD, 0x16,             // int 16h
+    0xCD, 0x19,             // int 19h
+];
+const VBR_MESSAGE_OFFSET: usize = 0x58;
+const VBR_MESSAGE: &[u8] = b"Non-system disk. Press a key to reboot.\0";
+
+// Master boot record code. Relocates itself to 0000:0600, finds the active
+// partition and chains to its boot sector at 0000:7C00.
+const MBR_CODE: [u8; 99] = [
+    0xFA, 0xFC,             // cli, cld
+    0x31, 0xC0,             // xor ax, ax
+    0x8E, 0xD0,             // mov ss, ax
+    0xBC, 0x00, 0x7C,       // mov sp, 7C00h
+    0x8E, 0xD8,             // mov ds, ax
+    0x8E, 0xC0,             // mov es, ax
+    0xFB,                   // sti
+    0xBE, 0x00, 0x7C,       // mov si, 7C00h
+    0xBF, 0x00, 0x06,       // mov di, 0600h
+    0xB9, 0x00, 0x01,       // mov cx, 256
+    0xF3, 0xA5,             // rep movsw
+    0xEA, 0x1E, 0x06, 0x00, 0x00, // jmp 0000:061E
+    0xBE, 0xBE, 0x07,       // mov si, 07BEh
+    0xB9, 0x04, 0x00,       // mov cx, 4
+    0x80, 0x3C, 0x80,       // next: cmp byte [si], 80h
+    0x74, 0x0E,             // je found
+    0x83, 0xC6, 0x10,       // add si, 16
+    0xE2, 0xF6,             // loop next
+    0xBE, 0x63, 0x06,       // mov si, no_active
+    0xE8, 0x21, 0x00,       // fail: call print
+    0xF4,                   // halt: hlt
+    0xEB, 0xFD,             // jmp halt
+    0x8B, 0x14,             // found: mov dx, [si]
+    0x8B, 0x4C, 0x02,       // mov cx, [si+2]
+    0xBB, 0x00, 0x7C,       // mov bx, 7C00h
+    0xB8, 0x01, 0x02,       // mov ax, 0201h
+    0xCD, 0x13,             // int 13h
+    0x72, 0x0A,             // jc error
+    0x81, 0x3E, 0xFE, 0x7D, 0x55, 0xAA, // cmp word [7DFEh], AA55h
+    0x75, 0x02,             // jne error
+    0xFF, 0xE3,             // jmp bx
+    0xBE, 0x78, 0x06,       // error: mov si, load_error
+    0xEB, 0xDC,             // jmp fail
+    0xAC,                   // print: lodsb
+    0x08, 0xC0,             // or al, al
+    0x74, 0x08,             // jz print_done
+    0xB4, 0x0E,             // mov ah, 0Eh
+    0x31, 0xDB,             // xor bx, bx
+    0xCD, 0x10,             // int 10h
+    0xEB, 0xF3,             // jmp print
+    0xC3,                   // print_done: ret
+];
+const MBR_NO_ACTIVE_OFFSET: usize = 0x63;
+const MBR_NO_ACTIVE: &[u8] = b"No active partition.\0";
+const MBR_LOAD_ERROR_OFFSET: usize = 0x78;
+const MBR_LOAD_ERROR: &[u8] = b"Error loading operating system.\0";
+
+#[derive(Debug)]
+pub enum DiskImageError {
+    FileExists,
+    FileWriteError,
+}
+impl Error for DiskImageError {}
+impl Display for DiskImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiskImageError::FileExists => write!(f, "The requested image file already exists."),
+            DiskImageError::FileWriteError => write!(f, "A file write error occurred."),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloppyFormat {
+    Floppy160K,
+    Floppy180K,
+    Floppy320K,
+    Floppy360K,
+    Floppy720K,
+    Floppy1200K,
+    Floppy1440K,
+}
+
+impl FloppyFormat {
+    pub const ALL: [FloppyFormat; 7] = [
+        FloppyFormat::Floppy160K,
+        FloppyFormat::Floppy180K,
+        FloppyFormat::Floppy320K,
+        FloppyFormat::Floppy360K,
+        FloppyFormat::Floppy720K,
+        FloppyFormat::Floppy1200K,
+        FloppyFormat::Floppy1440K,
+    ];
+
+    pub fn desc(&self) -> &'static str {
+        match self {
+            FloppyFormat::Floppy160K => "160K 5.25\" SSDD",
+            FloppyFormat::Floppy180K => "180K 5.25\" SSDD",
+            FloppyFormat::Floppy320K => "320K 5.25\" DSDD",
+            FloppyFormat::Floppy360K => "360K 5.25\" DSDD",
+            FloppyFormat::Floppy720K => "720K 3.5\" DD",
+            FloppyFormat::Floppy1200K => "1.2M 5.25\" HD",
+            FloppyFormat::Floppy1440K => "1.44M 3.5\" HD",
+        }
+    }
+
+    /// Return the geometry of the format as (cylinders, heads, sectors per track).
+    pub fn geometry(&self) -> (usize, usize, usize) {
+        match self {
+            FloppyFormat::Floppy160K => (40, 1, 8),
+            FloppyFormat::Floppy180K => (40, 1, 9),
+            FloppyFormat::Floppy320K => (40, 2, 8),
+            FloppyFormat::Floppy360K => (40, 2, 9),
+            FloppyFormat::Floppy720K => (80, 2, 9),
+            FloppyFormat::Floppy1200K => (80, 2, 15),
+            FloppyFormat::Floppy1440K => (80, 2, 18),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        let (c, h, s) = self.geometry();
+        c * h * s * SECTOR_SIZE
+    }
+
+    /// Return the (media descriptor, sectors per cluster, root directory entries) 
+    /// that DOS FORMAT uses for this format.
+    fn fat_params(&self) -> (u8, usize, usize) {
+        match self {
+            FloppyFormat::Floppy160K => (0xFE, 1, 64),
+            FloppyFormat::Floppy180K => (0xFC, 1, 64),
+            FloppyFormat::Floppy320K => (0xFF, 2, 112),
+            FloppyFormat::Floppy360K => (0xFD, 2, 112),
+            FloppyFormat::Floppy720K => (0xF9, 2, 112),
+            FloppyFormat::Floppy1200K => (0xF9, 1, 224),
+            FloppyFormat::Floppy1440K => (0xF0, 1, 224),
+        }
+    }
+}
+
+/// Return the hard disk geometries that may be created, matching AT drive types 1-3.
+pub fn hard_disk_formats() -> Vec<HardDiskFormat> {
+    vec![
+        HardDiskFormat { max_cylinders: 306, max_heads: 4, max_sectors: 17, desc: "10MB, Type 1".to_string() },
+        HardDiskFormat { max_cylinders: 615, max_heads: 4, max_sectors: 17, desc: "20MB, Type 2".to_string() },
+        HardDiskFormat { max_cylinders: 615, max_heads: 6, max_sectors: 17, desc: "30MB, Type 3".to_string() },
+    ]
+}
+
+/// Describes the layout of a FAT volume.
+struct FatVolume {
+    total_sectors: usize,
+    sectors_per_cluster: usize,
+    root_entries: usize,
+    media: u8,
+    sectors_per_track: usize,
+    heads: usize,
+    hidden_sectors: usize,
+    drive_number: u8,
+    fat_sectors: usize,
+    fat16: bool,
+}
+
+impl FatVolume {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        total_sectors: usize,
+        sectors_per_cluster: usize,
+        root_entries: usize,
+        media: u8,
+        sectors_per_track: usize,
+        heads: usize,
+        hidden_sectors: usize,
+        drive_number: u8
+    ) -> Self {
+        let root_sectors = root_entries * DIR_ENTRY_SIZE / SECTOR_SIZE;
+
+        // The size of the FAT depends on the number of clusters, which depends 
+        // on the size of the FAT. Grow the FAT until it covers the data area.
+        let mut fat_sectors = 1;
+        let mut fat16;
+        loop {
+            let data_sectors = total_sectors - RESERVED_SECTORS - FAT_COUNT * fat_sectors - root_sectors;
+            let clusters = data_sectors / sectors_per_cluster;
+            fat16 = clusters >= FAT12_MAX_CLUSTERS;
+            let fat_bytes = if fat16 { (clusters + 2) * 2 } else { ((clusters + 2) * 3).div_ceil(2) };
+            let needed = fat_bytes.div_ceil(SECTOR_SIZE);
+            if needed <= fat_sectors {
+                break;
+            }
+            fat_sectors = needed;
+        }
+
+        Self {
+            total_sectors,
+            sectors_per_cluster,
+            root_entries,
+            media,
+            sectors_per_track,
+            heads,
+            hidden_sectors,
+            drive_number,
+            fat_sectors,
+            fat16,
+        }
+    }
+
+    fn root_sectors(&self) -> usize {
+        self.root_entries * DIR_ENTRY_SIZE / SECTOR_SIZE
+    }
+
+    fn data_offset(&self) -> usize {
+        (RESERVED_SECTORS + FAT_COUNT * self.fat_sectors + self.root_sectors()) * SECTOR_SIZE
+    }
+
+    /// Write the volume's boot sector, FATs and root directory to the start of buf.
+    fn format(&self, buf: &mut [u8]) {
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+
+        let bs = &mut buf[0..SECTOR_SIZE];
+        bs[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        bs[3..11].copy_from_slice(b"MARTYPC ");
+
+        // BIOS Parameter Block
+        bs[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        bs[13] = self.sectors_per_cluster as u8;
+        bs[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+        bs[16] = FAT_COUNT as u8;
+        bs[17..19].copy_from_slice(&(self.root_entries as u16).to_le_bytes());
+        bs[19..21].copy_from_slice(&(self.total_sectors as u16).to_le_bytes());
+        bs[21] = self.media;
+        bs[22..24].copy_from_slice(&(self.fat_sectors as u16).to_le_bytes());
+        bs[24..26].copy_from_slice(&(self.sectors_per_track as u16).to_le_bytes());
+        bs[26..28].copy_from_slice(&(self.heads as u16).to_le_bytes());
+        bs[28..32].copy_from_slice(&(self.hidden_sectors as u32).to_le_bytes());
+
+        // Extended BPB
+        bs[36] = self.drive_number;
+        bs[38] = 0x29;
+        bs[39..43].copy_from_slice(&serial.to_le_bytes());
+        bs[43..54].copy_from_slice(b"NO NAME    ");
+        bs[54..62].copy_from_slice(if self.fat16 { b"FAT16   " } else { b"FAT12   " });
+
+        bs[VBR_CODE_OFFSET..VBR_CODE_OFFSET + VBR_CODE.len()].copy_from_slice(&VBR_CODE);
+        bs[VBR_MESSAGE_OFFSET..VBR_MESSAGE_OFFSET + VBR_MESSAGE.len()].copy_from_slice(VBR_MESSAGE);
+        bs[SECTOR_SIZE - 2..].copy_from_slice(&BOOT_SIGNATURE);
+
+        // The first two FAT entries hold the media descriptor; every cluster is free.
+        for i in 0..FAT_COUNT {
+            let fat_offset = (RESERVED_SECTORS + i * self.fat_sectors) * SECTOR_SIZE;
+            let fat = &mut buf[fat_offset..fat_offset + self.fat_sectors * SECTOR_SIZE];
+            fat.fill(0);
+            fat[0] = self.media;
+            fat[1] = 0xFF;
+            fat[2] = 0xFF;
+            if self.fat16 {
+                fat[3] = 0xFF;
+            }
+        }
+
+        let root_offset = (RESERVED_SECTORS + FAT_COUNT * self.fat_sectors) * SECTOR_SIZE;
+        buf[root_offset..self.data_offset()].fill(0);
+    }
+}
+
+/// Create the contents of a new floppy image of the specified format.
+/// An unformatted image is filled with zeros.
+pub fn create_floppy_image(format: FloppyFormat, formatted: bool) -> Vec<u8> {
+    let mut buf = vec![0; format.size()];
+
+    if formatted {
+        let (_, heads, sectors) = format.geometry();
+        let (media, sectors_per_cluster, root_entries) = format.fat_params();
+        let volume = FatVolume::new(
+            buf.len() / SECTOR_SIZE,
+            sectors_per_cluster,
+            root_entries,
+            media,
+            sectors,
+            heads,
+            0,
+            0x00
+        );
+
+        buf[volume.data_offset()..].fill(FORMAT_FILL_BYTE);
+        volume.format(&mut buf);
+    }
+    buf
+}
+
+/// Write a new floppy image of the specified format to the specified path.
+/// An existing file will not be overwritten.
+pub fn write_floppy_image(path: &Path, format: FloppyFormat, formatted: bool) -> Result<(), DiskImageError> {
+    if fs::metadata(path).is_ok() {
+        log::warn!("Requested floppy image already exists: {:?}", path);
+        return Err(DiskImageError::FileExists);
+    }
+
+    let buf = create_floppy_image(format, formatted);
+    let mut file = File::create(path).map_err(|_| DiskImageError::FileWriteError)?;
+    file.write_all(&buf).map_err(|_| DiskImageError::FileWriteError)?;
+    Ok(())
+}
+
+/// Encode a cylinder, head and sector into the 3-byte form used in a partition table entry.
+fn encode_chs(cylinder: usize, head: usize, sector: usize) -> [u8; 3] {
+    [
+        head as u8,
+        (sector as u8 & 0x3F) | ((cylinder >> 2) as u8 & 0xC0),
+        cylinder as u8,
+    ]
+}
+
+/// Create the contents of a new hard disk image of the specified geometry.
+/// A formatted image is partitioned with a single active FAT partition. 
+/// An unformatted image is filled with zeros.
+pub fn create_hard_disk_image(cylinders: u16, heads: u8, sectors: u8, formatted: bool) -> Vec<u8> {
+    let (c, h, s) = (cylinders as usize, heads as usize, sectors as usize);
+    let mut buf = vec![0; c * h * s * SECTOR_SIZE];
+
+    if !formatted || c < 3 {
+        return buf;
+    }
+
+    // The partition starts at the second head of cylinder 0 and ends at the 
+    // second to last cylinder.
+    let start_lba = s;
+    let end_cylinder = c - 2;
+    let partition_sectors = (end_cylinder + 1) * h * s - start_lba;
+
+    let volume = FatVolume::new(
+        partition_sectors,
+        if partition_sectors < 32768 { 8 } else { 4 },
+        HARD_DISK_ROOT_ENTRIES,
+        HARD_DISK_MEDIA_DESCRIPTOR,
+        s,
+        h,
+        start_lba,
+        0x80
+    );
+
+    let mbr = &mut buf[0..SECTOR_SIZE];
+    mbr[0..MBR_CODE.len()].copy_from_slice(&MBR_CODE);
+    mbr[MBR_NO_ACTIVE_OFFSET..MBR_NO_ACTIVE_OFFSET + MBR_NO_ACTIVE.len()].copy_from_slice(MBR_NO_ACTIVE);
+    mbr[MBR_LOAD_ERROR_OFFSET..MBR_LOAD_ERROR_OFFSET + MBR_LOAD_ERROR.len()].copy_from_slice(MBR_LOAD_ERROR);
+
+    let entry = &mut mbr[PARTITION_TABLE_OFFSET..PARTITION_TABLE_OFFSET + 16];
+    entry[0] = PARTITION_ACTIVE;
+    entry[1..4].copy_from_slice(&encode_chs(0, 1, 1));
+    entry[4] = if volume.fat16 { PARTITION_TYPE_FAT16 } else { PARTITION_TYPE_FAT12 };
+    entry[5..8].copy_from_slice(&encode_chs(end_cylinder, h - 1, s));
+    entry[8..12].copy_from_slice(&(start_lba as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&(partition_sectors as u32).to_le_bytes());
+    mbr[SECTOR_SIZE - 2..].copy_from_slice(&BOOT_SIGNATURE);
+
+    volume.format(&mut buf[start_lba * SECTOR_SIZE..]);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatted_floppy_layout() {
+        let buf = create_floppy_image(FloppyFormat::Floppy360K, true);
+        assert_eq!(buf.len(), 368640);
+        assert_eq!(&buf[510..512], &BOOT_SIGNATURE);
+        assert_eq!(u16::from_le_bytes([buf[19], buf[20]]), 720);
+        assert_eq!(buf[21], 0xFD);
+        // 360K floppies have 2 sectors per FAT
+        assert_eq!(u16::from_le_bytes([buf[22], buf[23]]), 2);
+        assert_eq!(&buf[512..515], &[0xFD, 0xFF, 0xFF]);
+        assert_eq!(&buf[3 * 512..3 * 512 + 3], &[0xFD, 0xFF, 0xFF]);
+        // The data area follows the boot sector, 2 FATs and 7 sector root directory.
+        assert_eq!(buf[12 * 512], FORMAT_FILL_BYTE);
+        assert_eq!(buf[12 * 512 - 1], 0);
+
+        for format in FloppyFormat::ALL {
+            let (media, _, _) = format.fat_params();
+            let buf = create_floppy_image(format, true);
+            assert_eq!(buf[21], media);
+            assert_eq!(&buf[54..62], b"FAT12   ");
+        }
+
+        assert!(create_floppy_image(FloppyFormat::Floppy1440K, false).iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn formatted_hard_disk_layout() {
+        let buf = create_hard_disk_image(615, 4, 17, true);
+        assert_eq!(&buf[510..512], &BOOT_SIGNATURE);
+
+        let entry = &buf[PARTITION_TABLE_OFFSET..PARTITION_TABLE_OFFSET + 16];
+        assert_eq!(entry[0], PARTITION_ACTIVE);
+        assert_eq!(&entry[1..4], &[1, 1, 0]);
+        assert_eq!(entry[4], PARTITION_TYPE_FAT16);
+        // Cylinder 613: high bits in sector byte
+        assert_eq!(&entry[5..8], &[3, 0x80 | 17, 0x65]);
+        assert_eq!(u32::from_le_bytes(entry[8..12].try_into().unwrap()), 17);
+        assert_eq!(u32::from_le_bytes(entry[12..16].try_into().unwrap()), 614 * 4 * 17 - 17);
+
+        let vbr = &buf[17 * 512..18 * 512];
+        assert_eq!(&vbr[510..512], &BOOT_SIGNATURE);
+        assert_eq!(vbr[21], HARD_DISK_MEDIA_DESCRIPTOR);
+        assert_eq!(u32::from_le_bytes(vbr[28..32].try_into().unwrap()), 17);
+
+        let buf = create_hard_disk_image(306, 4, 17, true);
+        assert_eq!(buf[PARTITION_TABLE_OFFSET + 4], PARTITION_TYPE_FAT12);
+    }
+}
@@ -0,0 +1,274 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    event_timeline.rs
+
+    Implements a unified timeline of peripheral activity.
+
+    After devices are run, the timeline samples the PIC, DMA controller, 
+    floppy controller and video card, and records an event for every IRQ 
+    request, DMA request, FDC command and video mode change since the last 
+    sample. Each event is stamped with the CPU cycle count and the CS:IP of 
+    the next instruction, so device activity can be correlated with the code 
+    that caused or handled it.
+
+    Devices only keep running counters of their activity; the timeline turns 
+    counter increases into events. Recording is off by default.
+*/
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::bus::BusInterface;
+use crate::devices::fdc::Command;
+use crate::videocard::DisplayMode;
+
+pub const DEFAULT_TIMELINE_CAPACITY: usize = 20_000;
+
+/// The timeline tracks events are grouped into.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimelineTrack {
+    Irq,
+    Dma,
+    Fdc,
+    Video,
+}
+
+impl TimelineTrack {
+    pub const ALL: [TimelineTrack; 4] = [TimelineTrack::Irq, TimelineTrack::Dma, TimelineTrack::Fdc, TimelineTrack::Video];
+}
+
+impl fmt::Display for TimelineTrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimelineTrack::Irq => write!(f, "IRQ"),
+            TimelineTrack::Dma => write!(f, "DMA"),
+            TimelineTrack::Fdc => write!(f, "FDC"),
+            TimelineTrack::Video => write!(f, "Video"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimelineEventKind {
+    /// An IRQ line was asserted.
+    Irq(u8),
+    /// A device requested service on a DMA channel.
+    DmaRequest(u8),
+    /// The floppy controller received a command.
+    FdcCommand(Command),
+    /// The video card changed display mode.
+    VideoMode(DisplayMode),
+}
+
+impl TimelineEventKind {
+    pub fn track(&self) -> TimelineTrack {
+        match self {
+            TimelineEventKind::Irq(_) => TimelineTrack::Irq,
+            TimelineEventKind::DmaRequest(_) => TimelineTrack::Dma,
+            TimelineEventKind::FdcCommand(_) => TimelineTrack::Fdc,
+            TimelineEventKind::VideoMode(_) => TimelineTrack::Video,
+        }
+    }
+}
+
+impl fmt::Display for TimelineEventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimelineEventKind::Irq(line) => write!(f, "IRQ {}", line),
+            TimelineEventKind::DmaRequest(channel) => write!(f, "DREQ {}", channel),
+            TimelineEventKind::FdcCommand(command) => write!(f, "{:?}", command),
+            TimelineEventKind::VideoMode(mode) => write!(f, "{:?}", mode),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimelineEvent {
+    /// CPU cycle count when the event was sampled.
+    pub cycle: u64,
+    pub cs: u16,
+    pub ip: u16,
+    pub kind: TimelineEventKind,
+}
+
+/// Device activity counters at the time of the last sample.
+#[derive(Clone, Default, PartialEq)]
+struct DeviceSnapshot {
+    irq_requests: [u64; 8],
+    dma_requests: [u64; 4],
+    fdc_commands: u64,
+    video_mode: Option<DisplayMode>,
+}
+
+impl DeviceSnapshot {
+    fn take(bus: &BusInterface) -> Self {
+        Self {
+            irq_requests: bus.pic().as_ref().map(|pic| pic.get_request_counts()).unwrap_or_default(),
+            dma_requests: bus.dma().as_ref().map(|dma| dma.get_request_counts()).unwrap_or_default(),
+            fdc_commands: bus.fdc().as_ref().map(|fdc| fdc.get_command_count()).unwrap_or_default(),
+            video_mode: bus.video().map(|video| video.get_display_mode()),
+        }
+    }
+}
+
+pub struct EventTimeline {
+    enabled: bool,
+    capacity: usize,
+    events: VecDeque<TimelineEvent>,
+    last: DeviceSnapshot,
+}
+
+impl Default for EventTimeline {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMELINE_CAPACITY)
+    }
+}
+
+impl EventTimeline {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            enabled: false,
+            capacity: capacity.max(1),
+            events: VecDeque::new(),
+            last: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop recording. Recording starts from the current device state, so activity 
+    /// while the timeline was disabled is not reported.
+    pub fn set_enabled(&mut self, bus: &BusInterface, enabled: bool) {
+        if enabled && !self.enabled {
+            self.last = DeviceSnapshot::take(bus);
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Return the recorded events, oldest first.
+    pub fn events(&self) -> &VecDeque<TimelineEvent> {
+        &self.events
+    }
+
+    /// Sample the devices on the bus and record any activity since the last sample.
+    pub fn sample(&mut self, bus: &BusInterface, cycle: u64, cs: u16, ip: u16) {
+        if !self.enabled {
+            return
+        }
+
+        let snapshot = DeviceSnapshot::take(bus);
+        if snapshot == self.last {
+            return
+        }
+        let last = std::mem::replace(&mut self.last, snapshot.clone());
+
+        for (line, (new, old)) in snapshot.irq_requests.iter().zip(last.irq_requests.iter()).enumerate() {
+            if new > old {
+                self.push(cycle, cs, ip, TimelineEventKind::Irq(line as u8));
+            }
+        }
+        for (channel, (new, old)) in snapshot.dma_requests.iter().zip(last.dma_requests.iter()).enumerate() {
+            if new > old {
+                self.push(cycle, cs, ip, TimelineEventKind::DmaRequest(channel as u8));
+            }
+        }
+        if snapshot.fdc_commands > last.fdc_commands {
+            if let Some(fdc) = bus.fdc() {
+                self.push(cycle, cs, ip, TimelineEventKind::FdcCommand(fdc.get_received_command()));
+            }
+        }
+        if snapshot.video_mode != last.video_mode {
+            if let Some(mode) = snapshot.video_mode {
+                self.push(cycle, cs, ip, TimelineEventKind::VideoMode(mode));
+            }
+        }
+    }
+
+    fn push(&mut self, cycle: u64, cs: u16, ip: u16, kind: TimelineEventKind) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(TimelineEvent { cycle, cs, ip, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{dma::DMAController, pic::Pic};
+
+    fn test_bus() -> BusInterface {
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        *bus.dma_mut() = Some(DMAController::new());
+        bus
+    }
+
+    #[test]
+    fn records_requests_since_last_sample() {
+        let mut bus = test_bus();
+        let mut timeline = EventTimeline::new(16);
+
+        // Activity before recording starts is not reported
+        bus.pic_mut().as_mut().unwrap().request_interrupt(0);
+        timeline.sample(&bus, 0, 0, 0);
+        timeline.set_enabled(&bus, true);
+        timeline.sample(&bus, 10, 0xF000, 0x1234);
+        assert!(timeline.events().is_empty());
+
+        bus.pic_mut().as_mut().unwrap().request_interrupt(6);
+        // Holding DREQ high is a single request
+        bus.dma_mut().as_mut().unwrap().request_service(2);
+        bus.dma_mut().as_mut().unwrap().request_service(2);
+        timeline.sample(&bus, 20, 0xF000, 0x1236);
+
+        let kinds: Vec<_> = timeline.events().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![TimelineEventKind::Irq(6), TimelineEventKind::DmaRequest(2)]);
+        assert_eq!(timeline.events()[0].cycle, 20);
+        assert_eq!((timeline.events()[0].cs, timeline.events()[0].ip), (0xF000, 0x1236));
+    }
+
+    #[test]
+    fn oldest_events_are_dropped() {
+        let mut bus = test_bus();
+        let mut timeline = EventTimeline::new(2);
+        timeline.set_enabled(&bus, true);
+
+        for line in 0..3 {
+            bus.pic_mut().as_mut().unwrap().request_interrupt(line);
+            timeline.sample(&bus, line as u64, 0, 0);
+        }
+
+        let kinds: Vec<_> = timeline.events().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![TimelineEventKind::Irq(1), TimelineEventKind::Irq(2)]);
+    }
+}
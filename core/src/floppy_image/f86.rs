@@ -0,0 +1,350 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::f86.rs
+
+    Parser for 86Box (.86F) floppy images.
+
+    86F images store the raw bitcells of each track rather than decoded 
+    sectors, so we decode the MFM bitstream ourselves to recover sector IDs, 
+    data, deleted data marks and CRC errors. If the image includes surface
+    data, bitcells marked as weak or unformatted become the weak mask of 
+    the sector they fall in.
+
+    Only version 2 images with MFM encoded tracks are supported. FM, M2FM and
+    GCR tracks are skipped.
+
+    Layout:
+        "86BF", minor version, major version, disk flags (u16)
+        track offset table of u32 entries, indexed by (track * sides + side)
+        per track: flags (u16), [extra bitcells (i32)], index position (u32),
+        bitcell data as LE u16 words, MSB first, then surface data of the 
+        same length if present.
+*/
+
+use super::{crc16, FloppyImage, FloppyImageError, FloppyImageParser, FloppyImageType, Sector, SectorId, Track};
+
+pub const F86_SIGNATURE: &[u8] = b"86BF";
+pub const F86_MAJOR_VERSION: u8 = 2;
+pub const F86_HEADER_LEN: usize = 8;
+
+const F86_DISK_HAS_SURFACE: u16 = 0b0000_0000_0000_0001;
+const F86_DISK_TWO_SIDES: u16 = 0b0000_0000_0000_1000;
+const F86_DISK_EXTRA_BITCELLS: u16 = 0b0000_0000_1000_0000;
+
+const F86_TRACK_ENCODING_MASK: u16 = 0b0000_0000_0001_1000;
+const F86_TRACK_ENCODING_MFM: u16 = 0b0000_0000_0000_1000;
+
+const F86_TRACK_TABLE_ENTRIES: usize = 256;
+
+/// Three A1 sync bytes with a missing clock bit, as MFM bitcells.
+const MFM_SYNC: u64 = 0x4489_4489_4489;
+const MFM_SYNC_MASK: u64 = 0xFFFF_FFFF_FFFF;
+
+const IDAM: u8 = 0xFE;
+const DAM: u8 = 0xFB;
+const DDAM: u8 = 0xF8;
+
+pub struct F86Parser;
+
+/// A view over a track's bitcells, and surface data if present.
+struct Bitstream<'a> {
+    cells: &'a [u8],
+    surface: Option<&'a [u8]>
+}
+
+fn get_bit(data: &[u8], index: usize) -> bool {
+    let word = index / 16;
+    let shift = 15 - (index % 16);
+    let w = u16::from_le_bytes([data[word * 2], data[word * 2 + 1]]);
+    (w >> shift) & 0x01 != 0
+}
+
+impl<'a> Bitstream<'a> {
+    fn len(&self) -> usize {
+        (self.cells.len() / 2) * 16
+    }
+
+    fn cell(&self, index: usize) -> bool {
+        get_bit(self.cells, index)
+    }
+
+    fn weak(&self, index: usize) -> bool {
+        match self.surface {
+            Some(surface) => get_bit(surface, index),
+            None => false
+        }
+    }
+
+    /// Decode an MFM byte from the 16 bitcells starting at 'index'. Data bits are the second
+    /// cell of each clock/data pair.
+    fn read_byte(&self, index: usize) -> Option<u8> {
+        if index + 16 > self.len() {
+            return None
+        }
+        let mut byte = 0;
+        for i in 0..8 {
+            byte = (byte << 1) | self.cell(index + i * 2 + 1) as u8;
+        }
+        Some(byte)
+    }
+
+    /// Return a mask of the data bits of the byte at 'index' that fall on weak bitcells.
+    fn read_weak(&self, index: usize) -> u8 {
+        let mut mask = 0;
+        for i in 0..8 {
+            mask = (mask << 1) | self.weak(index + i * 2 + 1) as u8;
+        }
+        mask
+    }
+
+    fn read_bytes(&self, index: usize, len: usize) -> Option<Vec<u8>> {
+        (0..len).map(|i| self.read_byte(index + i * 16)).collect()
+    }
+}
+
+/// Decode the sectors of an MFM track.
+fn decode_mfm_track(stream: &Bitstream, cylinder: u8, head: u8) -> Track {
+
+    let mut track = Track {
+        cylinder,
+        head,
+        sectors: Vec::new()
+    };
+
+    let mut pending_id: Option<(SectorId, bool)> = None;
+    let mut shift_reg: u64 = 0;
+    let mut index = 0;
+
+    while index < stream.len() {
+        shift_reg = ((shift_reg << 1) | stream.cell(index) as u64) & MFM_SYNC_MASK;
+        index += 1;
+
+        if shift_reg != MFM_SYNC {
+            continue
+        }
+
+        let mark = match stream.read_byte(index) {
+            Some(mark) => mark,
+            None => break
+        };
+        let field_start = index + 16;
+
+        match mark {
+            IDAM => {
+                if let Some(field) = stream.read_bytes(field_start, 6) {
+                    let crc = crc16(&[0xA1, 0xA1, 0xA1, IDAM, field[0], field[1], field[2], field[3]], 0xFFFF);
+                    let crc_ok = crc == u16::from_be_bytes([field[4], field[5]]);
+                    let id = SectorId { c: field[0], h: field[1], r: field[2], n: field[3] };
+                    pending_id = Some((id, crc_ok));
+                    index = field_start + 6 * 16;
+                }
+            }
+            DAM | DDAM => {
+                // Data fields without a preceding ID field can't be addressed, so ignore them.
+                if let Some((id, id_crc_ok)) = pending_id.take() {
+                    let size = id.size();
+                    if let Some(field) = stream.read_bytes(field_start, size + 2) {
+                        let mut crc = crc16(&[0xA1, 0xA1, 0xA1, mark], 0xFFFF);
+                        crc = crc16(&field[..size], crc);
+                        let crc_ok = crc == u16::from_be_bytes([field[size], field[size + 1]]);
+
+                        let weak_mask: Vec<u8> = (0..size).map(|i| stream.read_weak(field_start + i * 16)).collect();
+                        let weak_mask = if weak_mask.iter().any(|b| *b != 0) { Some(weak_mask) } else { None };
+
+                        track.sectors.push(Sector {
+                            id,
+                            data: field[..size].to_vec(),
+                            deleted: mark == DDAM,
+                            data_error: !crc_ok || !id_crc_ok,
+                            weak_mask
+                        });
+                        index = field_start + (size + 2) * 16;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    track
+}
+
+impl FloppyImageParser for F86Parser {
+
+    fn detect(data: &[u8]) -> bool {
+        data.starts_with(F86_SIGNATURE)
+    }
+
+    fn parse(data: &[u8]) -> Result<FloppyImage, FloppyImageError> {
+
+        if !Self::detect(data) || data.len() < F86_HEADER_LEN {
+            return Err(FloppyImageError::BadHeader)
+        }
+
+        let (minor, major) = (data[4], data[5]);
+        if major != F86_MAJOR_VERSION {
+            return Err(FloppyImageError::UnsupportedVersion(major, minor))
+        }
+
+        let disk_flags = u16::from_le_bytes([data[6], data[7]]);
+        let has_surface = disk_flags & F86_DISK_HAS_SURFACE != 0;
+        let sides = if disk_flags & F86_DISK_TWO_SIDES != 0 { 2 } else { 1 };
+        let extra_bitcells = disk_flags & F86_DISK_EXTRA_BITCELLS != 0;
+
+        let table_len = F86_TRACK_TABLE_ENTRIES * sides * 4;
+        let table = data
+            .get(F86_HEADER_LEN..F86_HEADER_LEN + table_len)
+            .ok_or(FloppyImageError::UnexpectedEof)?;
+
+        let offsets: Vec<usize> = table
+            .chunks_exact(4)
+            .map(|d| u32::from_le_bytes([d[0], d[1], d[2], d[3]]) as usize)
+            .collect();
+
+        // Each track's data extends to the start of the next track, or the end of the file.
+        let mut sorted_offsets: Vec<usize> = offsets.iter().copied().filter(|o| *o != 0).collect();
+        sorted_offsets.sort_unstable();
+
+        let mut tracks = Vec::new();
+        for (i, offset) in offsets.iter().enumerate() {
+            if *offset == 0 {
+                continue
+            }
+            let cylinder = (i / sides).min(255) as u8;
+            let head = (i % sides) as u8;
+
+            let end = sorted_offsets
+                .iter()
+                .copied()
+                .find(|o| *o > *offset)
+                .unwrap_or(data.len());
+
+            let track_data = data.get(*offset..end).ok_or(FloppyImageError::BadTrackData(cylinder, head))?;
+            let header_len = if extra_bitcells { 10 } else { 6 };
+            if track_data.len() < header_len {
+                return Err(FloppyImageError::BadTrackData(cylinder, head))
+            }
+
+            let track_flags = u16::from_le_bytes([track_data[0], track_data[1]]);
+            if track_flags & F86_TRACK_ENCODING_MASK != F86_TRACK_ENCODING_MFM {
+                log::warn!("86F: skipping track c:{} h:{} with unsupported encoding", cylinder, head);
+                continue
+            }
+
+            let body = &track_data[header_len..];
+            let stream = if has_surface {
+                let (cells, surface) = body.split_at(body.len() / 2);
+                Bitstream { cells, surface: Some(surface) }
+            }
+            else {
+                Bitstream { cells: body, surface: None }
+            };
+
+            tracks.push(decode_mfm_track(&stream, cylinder, head));
+        }
+
+        Ok(FloppyImage::new(FloppyImageType::F86, tracks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode bytes as MFM bitcells, inserting clock bits between consecutive zero data bits.
+    fn mfm_encode(cells: &mut Vec<bool>, bytes: &[u8]) {
+        for byte in bytes {
+            for i in (0..8).rev() {
+                let bit = (byte >> i) & 0x01 != 0;
+                let prev = cells.last().copied().unwrap_or(false);
+                cells.push(!bit && !prev);
+                cells.push(bit);
+            }
+        }
+    }
+
+    fn mfm_sync(cells: &mut Vec<bool>) {
+        for _ in 0..3 {
+            for i in (0..16).rev() {
+                cells.push((0x4489 >> i) & 0x01 != 0);
+            }
+        }
+    }
+
+    fn pack_cells(cells: &[bool]) -> Vec<u8> {
+        let mut packed = Vec::new();
+        for chunk in cells.chunks(16) {
+            let mut word = 0u16;
+            for (i, cell) in chunk.iter().enumerate() {
+                word |= (*cell as u16) << (15 - i);
+            }
+            packed.extend_from_slice(&word.to_le_bytes());
+        }
+        packed
+    }
+
+    #[test]
+    fn test_86f_decode() {
+        let mut cells = Vec::new();
+        mfm_encode(&mut cells, &[0x4E; 16]);
+        mfm_encode(&mut cells, &[0x00; 12]);
+        mfm_sync(&mut cells);
+        let id = [0x00, 0x00, 0x07, 0x02];
+        let mut id_field = vec![IDAM];
+        id_field.extend_from_slice(&id);
+        let crc = crc16(&[&[0xA1, 0xA1, 0xA1][..], &id_field[..]].concat(), 0xFFFF);
+        id_field.extend_from_slice(&crc.to_be_bytes());
+        mfm_encode(&mut cells, &id_field);
+
+        mfm_encode(&mut cells, &[0x4E; 22]);
+        mfm_encode(&mut cells, &[0x00; 12]);
+        mfm_sync(&mut cells);
+        let mut data_field = vec![DDAM];
+        data_field.extend((0..512).map(|i| i as u8));
+        let crc = crc16(&[&[0xA1, 0xA1, 0xA1][..], &data_field[..]].concat(), 0xFFFF);
+        data_field.extend_from_slice(&crc.to_be_bytes());
+        mfm_encode(&mut cells, &data_field);
+        mfm_encode(&mut cells, &[0x4E; 16]);
+
+        let mut image = b"86BF\x0C\x02".to_vec();
+        image.extend_from_slice(&0u16.to_le_bytes());
+        let track_offset = (F86_HEADER_LEN + F86_TRACK_TABLE_ENTRIES * 4) as u32;
+        image.extend_from_slice(&track_offset.to_le_bytes());
+        image.extend(vec![0; (F86_TRACK_TABLE_ENTRIES - 1) * 4]);
+        image.extend_from_slice(&F86_TRACK_ENCODING_MFM.to_le_bytes());
+        image.extend_from_slice(&0u32.to_le_bytes());
+        image.extend(pack_cells(&cells));
+
+        let floppy = F86Parser::parse(&image).unwrap();
+        assert_eq!(floppy.cylinders, 1);
+        let s = floppy.find_sector(0, 0, 0, 0, 7).unwrap();
+        assert!(s.deleted);
+        assert!(!s.data_error);
+        assert!(s.weak_mask.is_none());
+        assert_eq!(s.data[255], 0xFF);
+    }
+}
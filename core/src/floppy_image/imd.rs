@@ -0,0 +1,227 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::imd.rs
+
+    Parser for ImageDisk (.IMD) floppy images.
+
+    An IMD file begins with an ASCII header and comment terminated by 0x1A,
+    followed by a record for each track:
+
+        mode, cylinder, head, sector count, sector size code
+        sector numbering map
+        optional cylinder map (head bit 7)
+        optional head map (head bit 6)
+        a data record for each sector
+
+    Each data record begins with a type byte which indicates whether the 
+    data is present, compressed to a single fill byte, written with a 
+    deleted data mark, or was read with a data error.
+*/
+
+use super::{FloppyImage, FloppyImageError, FloppyImageParser, FloppyImageType, Sector, SectorId, Track};
+
+pub const IMD_SIGNATURE: &[u8] = b"IMD ";
+pub const IMD_COMMENT_END: u8 = 0x1A;
+
+const IMD_HEAD_CYLINDER_MAP: u8 = 0b1000_0000;
+const IMD_HEAD_HEAD_MAP: u8 = 0b0100_0000;
+const IMD_HEAD_MASK: u8 = 0b0000_0001;
+const IMD_SIZE_TABLE: u8 = 0xFF;
+
+pub struct ImdParser;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn u8(&mut self) -> Result<u8, FloppyImageError> {
+        let byte = *self.data.get(self.pos).ok_or(FloppyImageError::UnexpectedEof)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FloppyImageError> {
+        let slice = self.data.get(self.pos..self.pos + len).ok_or(FloppyImageError::UnexpectedEof)?;
+        self.pos += len;
+        Ok(slice)
+    }
+}
+
+impl FloppyImageParser for ImdParser {
+
+    fn detect(data: &[u8]) -> bool {
+        data.starts_with(IMD_SIGNATURE)
+    }
+
+    fn parse(data: &[u8]) -> Result<FloppyImage, FloppyImageError> {
+
+        if !Self::detect(data) {
+            return Err(FloppyImageError::BadHeader)
+        }
+
+        // Skip header and comment
+        let comment_end = data.iter().position(|b| *b == IMD_COMMENT_END).ok_or(FloppyImageError::BadHeader)?;
+        let header = String::from_utf8_lossy(&data[..comment_end]);
+        log::debug!("ImageDisk header: {}", header.lines().next().unwrap_or(""));
+
+        let mut reader = Reader { data, pos: comment_end + 1 };
+        let mut tracks = Vec::new();
+
+        while !reader.at_end() {
+            let _mode = reader.u8()?;
+            let cylinder = reader.u8()?;
+            let head_byte = reader.u8()?;
+            let sector_ct = reader.u8()? as usize;
+            let size_code = reader.u8()?;
+            let head = head_byte & IMD_HEAD_MASK;
+
+            let sector_map = reader.bytes(sector_ct)?;
+            let cylinder_map = if head_byte & IMD_HEAD_CYLINDER_MAP != 0 { Some(reader.bytes(sector_ct)?) } else { None };
+            let head_map = if head_byte & IMD_HEAD_HEAD_MAP != 0 { Some(reader.bytes(sector_ct)?) } else { None };
+
+            // A size code of 0xFF indicates a table of 16-bit sector sizes follows.
+            let sizes: Vec<usize> = if size_code == IMD_SIZE_TABLE {
+                reader
+                    .bytes(sector_ct * 2)?
+                    .chunks_exact(2)
+                    .map(|w| u16::from_le_bytes([w[0], w[1]]) as usize)
+                    .collect()
+            }
+            else if size_code <= 6 {
+                vec![128 << size_code; sector_ct]
+            }
+            else {
+                return Err(FloppyImageError::BadTrackData(cylinder, head))
+            };
+
+            let mut track = Track {
+                cylinder,
+                head,
+                sectors: Vec::with_capacity(sector_ct)
+            };
+
+            for i in 0..sector_ct {
+                let size = sizes[i];
+                let n = (0..8u8).find(|n| (128usize << n) >= size).unwrap_or(7);
+                let id = SectorId {
+                    c: cylinder_map.map_or(cylinder, |m| m[i]),
+                    h: head_map.map_or(head, |m| m[i]),
+                    r: sector_map[i],
+                    n
+                };
+
+                let record_type = reader.u8()?;
+                let (data, deleted, data_error) = match record_type {
+                    // Sector data could not be read when the disk was imaged.
+                    0x00 => (vec![0; size], false, true),
+                    0x01 | 0x03 | 0x05 | 0x07 => {
+                        (reader.bytes(size)?.to_vec(), record_type & 0x02 != 0, record_type >= 0x05)
+                    }
+                    0x02 | 0x04 | 0x06 | 0x08 => {
+                        let fill = reader.u8()?;
+                        (vec![fill; size], record_type == 0x04 || record_type == 0x08, record_type >= 0x06)
+                    }
+                    _ => return Err(FloppyImageError::BadTrackData(cylinder, head))
+                };
+
+                track.sectors.push(Sector {
+                    id,
+                    data,
+                    deleted,
+                    data_error,
+                    weak_mask: None
+                });
+            }
+
+            tracks.push(track);
+        }
+
+        Ok(FloppyImage::new(FloppyImageType::Imd, tracks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn imd_track(cylinder: u8, head_byte: u8, sector_map: &[u8], records: &[u8]) -> Vec<u8> {
+        let mut track = vec![0x05, cylinder, head_byte, sector_map.len() as u8, 0x02];
+        track.extend_from_slice(sector_map);
+        track.extend_from_slice(records);
+        track
+    }
+
+    #[test]
+    fn test_imd_parse() {
+        let mut image = b"IMD 1.18: 01/01/2023 00:00:00\r\ntest\x1A".to_vec();
+
+        // Track 0: two compressed sectors, the second with a deleted data mark.
+        image.extend(imd_track(0, 0, &[1, 2], &[0x02, 0xE5, 0x04, 0xF6]));
+
+        // Track 1: one sector with a data error and a cylinder map.
+        let mut records = vec![0x05];
+        records.extend(vec![0xAA; 512]);
+        let mut track = imd_track(1, IMD_HEAD_CYLINDER_MAP, &[0x41], &[]);
+        track.push(0x7F);
+        track.extend(records);
+        image.extend(track);
+
+        let floppy = ImdParser::parse(&image).unwrap();
+        assert_eq!(floppy.cylinders, 2);
+        assert_eq!(floppy.heads, 1);
+
+        let s = floppy.find_sector(0, 0, 0, 0, 2).unwrap();
+        assert!(s.deleted && !s.data_error);
+        assert_eq!(s.data, vec![0xF6; 512]);
+
+        let s = floppy.find_sector(1, 0, 0x7F, 0, 0x41).unwrap();
+        assert!(s.data_error && !s.deleted);
+        assert_eq!(s.data[511], 0xAA);
+
+        assert!(floppy.to_raw().is_none());
+    }
+
+    #[test]
+    fn test_imd_to_raw() {
+        let mut image = b"IMD 1.18\x1A".to_vec();
+        image.extend(imd_track(0, 0, &[2, 1], &[0x02, 0x22, 0x02, 0x11]));
+        image.extend(imd_track(1, 0, &[1, 2], &[0x02, 0x33, 0x02, 0x44]));
+
+        let raw = ImdParser::parse(&image).unwrap().to_raw().unwrap();
+        assert_eq!(raw.len(), 4 * 512);
+        assert_eq!(raw[0], 0x11);
+        assert_eq!(raw[512], 0x22);
+        assert_eq!(raw[1024], 0x33);
+        assert_eq!(raw[1536], 0x44);
+    }
+}
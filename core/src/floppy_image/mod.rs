@@ -0,0 +1,236 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    floppy_image::mod.rs
+
+    Support for structured floppy disk image formats.
+
+    Raw sector images can only describe disks with a standard layout. 
+    Structured formats such as ImageDisk (.IMD) and 86Box (.86F) record each
+    sector's ID field and status, so they can preserve non-standard sector 
+    numbering, odd sector sizes, deleted data marks, CRC errors and weak bits
+    that copy protection schemes rely on.
+
+    Each format implements the FloppyImageParser trait, which converts an
+    image into a common FloppyImage representation of tracks and sectors.
+*/
+
+pub mod f86;
+pub mod imd;
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+pub use self::f86::F86Parser;
+pub use self::imd::ImdParser;
+
+/// Sector size of a standard PC sector, as a size code (128 << 2 == 512)
+pub const STANDARD_SIZE_CODE: u8 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FloppyImageType {
+    Imd,
+    F86
+}
+
+impl FloppyImageType {
+    pub fn desc(&self) -> &'static str {
+        match self {
+            FloppyImageType::Imd => "ImageDisk",
+            FloppyImageType::F86 => "86F"
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FloppyImageError {
+    BadHeader,
+    UnsupportedVersion(u8, u8),
+    UnexpectedEof,
+    BadTrackData(u8, u8)
+}
+impl Error for FloppyImageError {}
+impl Display for FloppyImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FloppyImageError::BadHeader => write!(f, "Image header is invalid."),
+            FloppyImageError::UnsupportedVersion(major, minor) => write!(f, "Unsupported image version: {}.{}", major, minor),
+            FloppyImageError::UnexpectedEof => write!(f, "Unexpected end of image data."),
+            FloppyImageError::BadTrackData(c, h) => write!(f, "Invalid data for track c:{} h:{}", c, h),
+        }
+    }
+}
+
+/// The ID field of a sector as written on disk. These values need not match the physical 
+/// location of the sector.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SectorId {
+    pub c: u8,
+    pub h: u8,
+    pub r: u8,
+    pub n: u8
+}
+
+impl SectorId {
+    /// Return the size of the sector in bytes as specified by the size code 'n'.
+    pub fn size(&self) -> usize {
+        128 << self.n.min(7)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Sector {
+    pub id: SectorId,
+    pub data: Vec<u8>,
+    /// The sector was written with a Deleted Data Address Mark.
+    pub deleted: bool,
+    /// The sector data failed its CRC check when imaged.
+    pub data_error: bool,
+    /// Bits set in the weak mask do not read back consistently, and should be randomized 
+    /// on every read.
+    pub weak_mask: Option<Vec<u8>>
+}
+
+impl Sector {
+    pub fn is_standard(&self, cylinder: u8, head: u8) -> bool {
+        self.id.c == cylinder 
+            && self.id.h == head 
+            && self.id.n == STANDARD_SIZE_CODE
+            && self.data.len() == self.id.size()
+            && !self.deleted 
+            && !self.data_error 
+            && self.weak_mask.is_none()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Track {
+    pub cylinder: u8,
+    pub head: u8,
+    /// Sectors in the order they appear on the track
+    pub sectors: Vec<Sector>
+}
+
+#[derive(Clone, Debug)]
+pub struct FloppyImage {
+    pub image_type: FloppyImageType,
+    pub cylinders: u8,
+    pub heads: u8,
+    pub tracks: Vec<Track>
+}
+
+impl FloppyImage {
+    pub fn new(image_type: FloppyImageType, mut tracks: Vec<Track>) -> Self {
+
+        tracks.sort_by_key(|t| (t.cylinder, t.head));
+        let cylinders = tracks.iter().map(|t| t.cylinder as usize + 1).max().unwrap_or(0).min(255) as u8;
+        let heads = tracks.iter().map(|t| t.head + 1).max().unwrap_or(0);
+
+        Self {
+            image_type,
+            cylinders,
+            heads,
+            tracks
+        }
+    }
+
+    /// Return the track at the specified physical cylinder and head, if present.
+    pub fn track(&self, cylinder: u8, head: u8) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.cylinder == cylinder && t.head == head)
+    }
+
+    /// Return the highest number of sectors on any track.
+    pub fn max_sectors(&self) -> u8 {
+        self.tracks.iter().map(|t| t.sectors.len()).max().unwrap_or(0).min(255) as u8
+    }
+
+    /// Search the track at the specified physical cylinder and head for a sector with a matching
+    /// ID. Like the FDC, we compare the C, H and R fields of the ID.
+    pub fn find_sector(&self, cylinder: u8, head: u8, id_c: u8, id_h: u8, id_r: u8) -> Option<&Sector> {
+        self.track(cylinder, head)?
+            .sectors
+            .iter()
+            .find(|s| s.id.c == id_c && s.id.h == id_h && s.id.r == id_r)
+    }
+
+    /// If the image has a standard PC layout - every track present with sectors numbered from
+    /// 1 to N of 512 bytes and no sector metadata - return it as a raw sector image.
+    pub fn to_raw(&self) -> Option<Vec<u8>> {
+
+        let spt = self.tracks.first()?.sectors.len();
+        if spt == 0 || self.tracks.len() != self.cylinders as usize * self.heads as usize {
+            return None
+        }
+
+        let mut raw = Vec::with_capacity(self.tracks.len() * spt * 512);
+        for track in &self.tracks {
+            if track.sectors.len() != spt {
+                return None
+            }
+            for r in 1..=spt {
+                let sector = track.sectors.iter().find(|s| s.id.r as usize == r)?;
+                if !sector.is_standard(track.cylinder, track.head) {
+                    return None
+                }
+                raw.extend_from_slice(&sector.data);
+            }
+        }
+        Some(raw)
+    }
+}
+
+pub trait FloppyImageParser {
+    /// Return true if 'data' appears to be an image of this format.
+    fn detect(data: &[u8]) -> bool;
+    /// Parse 'data' into a FloppyImage.
+    fn parse(data: &[u8]) -> Result<FloppyImage, FloppyImageError>;
+}
+
+/// Parse 'data' if it is in one of the supported structured image formats. Returns None if the
+/// format is not recognized, in which case the data should be treated as a raw sector image.
+pub fn parse_structured_image(data: &[u8]) -> Option<Result<FloppyImage, FloppyImageError>> {
+    if ImdParser::detect(data) {
+        Some(ImdParser::parse(data))
+    }
+    else if F86Parser::detect(data) {
+        Some(F86Parser::parse(data))
+    }
+    else {
+        None
+    }
+}
+
+/// Calculate the CRC-CCITT used by the FDC for ID and data fields.
+pub fn crc16(data: &[u8], init: u16) -> u16 {
+    let mut crc = init;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -26,8 +26,14 @@
 
     floppy_manager.rs
 
-    Enumerate images in the 'floppy' directory to allow floppy selection 
-    from within the GUI.
+    Enumerate images in the floppy directory tree to allow floppy selection 
+    from within the GUI, and keep a list of the images recently mounted in
+    each drive.
+
+    Images in subdirectories are named by their path relative to the 
+    floppy directory. A name that isn't found in the directory is treated 
+    as a path, so that images from elsewhere (dropped onto the window or 
+    mounted from the recent list) can be loaded and saved too.
 
 */
 
@@ -40,6 +46,14 @@ use std::{
     fmt::Display
 };
 
+use serde_derive::{Deserialize, Serialize};
+
+pub const FLOPPY_EXTENSIONS: [&str; 4] = ["img", "ima", "imd", "86f"];
+pub const RECENT_IMAGE_LIMIT: usize = 8;
+
+// Limit the depth of the directory scan in case of symlink loops.
+const MAX_SCAN_DEPTH: usize = 8;
+
 #[derive(Debug)]
 pub enum FloppyError {
     DirNotFound,
@@ -62,66 +76,101 @@ impl Display for FloppyError {
 #[allow(dead_code)]
 pub struct FloppyImage {
     path: PathBuf,
-    size: u64
+    size: u64,
+    write_protected: bool,
+}
+
+/// A description of a floppy image in the floppy directory, for display.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloppyImageInfo {
+    pub name: OsString,
+    pub path: PathBuf,
+    pub size: u64,
+    pub write_protected: bool,
 }
 
 pub struct FloppyManager {
-    image_vec: Vec<FloppyImage>,
     image_map: HashMap<OsString, FloppyImage>
 }
 
 impl FloppyManager {
     pub fn new() -> Self {
         Self {
-            image_vec: Vec::new(),
             image_map: HashMap::new()
         }
     }
 
+    /// Return whether the specified file has the extension of a supported floppy image.
+    pub fn is_floppy_image(path: &Path) -> bool {
+        path.extension()
+            .map(|ext| FLOPPY_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_ref()))
+            .unwrap_or(false)
+    }
+
     pub fn scan_dir(&mut self, path: &Path) -> Result<bool, FloppyError> {
 
         // Read in directory entries within the provided path
-        let dir = match fs::read_dir(path) {
-            Ok(dir) => dir,
-            Err(_) => return Err(FloppyError::DirNotFound)
-        };
-
-        let extensions = ["img", "ima"];
+        if !path.is_dir() {
+            return Err(FloppyError::DirNotFound)
+        }
 
-        // Clear and rebuild image lists.
-        self.image_vec.clear();
+        // Clear and rebuild image list.
         self.image_map.clear();
+        self.scan_tree(path, path, 0);
+        Ok(true)
+    }
+
+    fn scan_tree(&mut self, root: &Path, dir: &Path, depth: usize) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            log::warn!("Couldn't read floppy directory: {}", dir.display());
+            return
+        };
 
         // Scan through all entries in the directory and find all files with matching extension
-        for entry in dir {
-            if let Ok(entry) = entry {
-                if entry.path().is_file() {
-                    if let Some(extension) = entry.path().extension() {
-                        if extensions.contains(&extension.to_string_lossy().to_lowercase().as_ref()) {
-
-                            println!("Found floppy image: {:?} size: {}", entry.path(), entry.metadata().unwrap().len());
-                            
-                            self.image_vec.push( 
-                                FloppyImage {
-                                    path: entry.path(),
-                                    size: entry.metadata().unwrap().len()
-                                }
-                            );
-                        
-                            self.image_map.insert(entry.file_name(), 
-                                FloppyImage { 
-                                    path: entry.path(),
-                                    size: entry.metadata().unwrap().len()
-                                 }
-                            );
-                        }
-                    }
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if depth < MAX_SCAN_DEPTH {
+                    self.scan_tree(root, &path, depth + 1);
                 }
+                continue;
             }
+            if !path.is_file() || !FloppyManager::is_floppy_image(&path) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            let name: Vec<String> = path.strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+
+            log::debug!("Found floppy image: {:?} size: {}", path, metadata.len());
+            self.image_map.insert(
+                OsString::from(name.join("/")),
+                FloppyImage {
+                    path,
+                    size: metadata.len(),
+                    write_protected: metadata.permissions().readonly(),
+                }
+            );
         }
-        Ok(true)
     }
 
+    /// Return a description of each image in the floppy directory, sorted by name.
+    pub fn get_floppy_images(&self) -> Vec<FloppyImageInfo> {
+        let mut images: Vec<FloppyImageInfo> = self.image_map.iter().map(|(name, image)| FloppyImageInfo {
+            name: name.clone(),
+            path: image.path.clone(),
+            size: image.size,
+            write_protected: image.write_protected,
+        }).collect();
+        images.sort_by_key(|image| image.name.to_ascii_uppercase());
+        images
+    }
 
     pub fn get_floppy_names(&self) -> Vec<OsString> {
         let mut vec: Vec<OsString> = Vec::new();
@@ -132,26 +181,37 @@ impl FloppyManager {
         vec
     }
 
+    /// Return the path of the floppy image with the specified name. A name not found in the
+    /// floppy directory is treated as the path of an image file.
+    pub fn get_floppy_path(&self, name: &OsString) -> Option<PathBuf> {
+        match self.image_map.get(name) {
+            Some(floppy) => Some(floppy.path.clone()),
+            None => {
+                let path = PathBuf::from(name);
+                path.is_file().then_some(path)
+            }
+        }
+    }
+
     pub fn load_floppy_data(&self, name: &OsString ) -> Result<Vec<u8>, FloppyError> {
 
-        let mut floppy_vec = Vec::new();
-        if let Some(floppy) = self.image_map.get(name) {
-            floppy_vec = match std::fs::read(&floppy.path) {
-                Ok(vec) => vec,
-                Err(e) => {
-                    eprintln!("Couldn't open floppy image: {}", e);
-                    return Err(FloppyError::FileReadError);
-                }
-            };
+        let Some(path) = self.get_floppy_path(name) else {
+            return Err(FloppyError::ImageNotFound);
+        };
+        match std::fs::read(&path) {
+            Ok(vec) => Ok(vec),
+            Err(e) => {
+                eprintln!("Couldn't open floppy image: {}", e);
+                Err(FloppyError::FileReadError)
+            }
         }
-        Ok(floppy_vec)
     }
 
     pub fn save_floppy_data(&self, data: &[u8], name: &OsString ) -> Result<(), FloppyError> {
 
-        if let Some(floppy) = self.image_map.get(name) {
+        if let Some(path) = self.get_floppy_path(name) {
 
-            match std::fs::write(&floppy.path, data) {
+            match std::fs::write(&path, data) {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     eprintln!("Couldn't save floppy image: {}", e);
@@ -165,3 +225,66 @@ impl FloppyManager {
     }    
 
 }
+
+/// The images most recently mounted in each floppy drive, most recent first. The list is kept
+/// in a JSON file so that it persists between sessions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecentImages {
+    drives: Vec<Vec<PathBuf>>,
+}
+
+impl RecentImages {
+    /// Load the list from the specified file. A missing or invalid file gives an empty list.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), FloppyError> {
+        let text = serde_json::to_string_pretty(self).map_err(|_| FloppyError::FileWriteError)?;
+        fs::write(path, text).map_err(|_| FloppyError::FileWriteError)
+    }
+
+    /// Record an image as mounted in the specified drive.
+    pub fn add(&mut self, drive: usize, image: PathBuf) {
+        if self.drives.len() <= drive {
+            self.drives.resize(drive + 1, Vec::new());
+        }
+        let list = &mut self.drives[drive];
+        list.retain(|p| *p != image);
+        list.insert(0, image);
+        list.truncate(RECENT_IMAGE_LIMIT);
+    }
+
+    pub fn get(&self, drive: usize) -> &[PathBuf] {
+        self.drives.get(drive).map(|list| list.as_slice()).unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_images_are_most_recent_first() {
+        let mut recent = RecentImages::default();
+        for i in 0..RECENT_IMAGE_LIMIT + 2 {
+            recent.add(1, PathBuf::from(format!("disk{}.img", i)));
+        }
+        recent.add(1, PathBuf::from("disk5.img"));
+
+        assert!(recent.get(0).is_empty());
+        assert_eq!(recent.get(1).len(), RECENT_IMAGE_LIMIT);
+        assert_eq!(recent.get(1)[0], PathBuf::from("disk5.img"));
+        assert_eq!(recent.get(1)[1], PathBuf::from("disk9.img"));
+        assert_eq!(recent.get(1).iter().filter(|p| **p == PathBuf::from("disk5.img")).count(), 1);
+    }
+
+    #[test]
+    fn floppy_extensions() {
+        assert!(FloppyManager::is_floppy_image(Path::new("games/KQ1.IMG")));
+        assert!(!FloppyManager::is_floppy_image(Path::new("hdd/drive.vhd")));
+    }
+}
@@ -0,0 +1,547 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    gdb_server.rs
+
+    Implements a server for the GDB remote serial protocol, so that guest code
+    can be debugged from GDB or any frontend that speaks the protocol, such as
+    IDA or Ghidra.
+
+    The server listens on a local TCP port and accepts a single client at a
+    time. The machine is paused when a client connects. Supported packets:
+
+    ?                     report why the machine stopped
+    g / G                 read or write all registers
+    p / P                 read or write a single register
+    m / M                 read or write memory
+    c / s                 continue or single-step an instruction
+    Z0-Z1 / z0-z1         set or remove an execution breakpoint
+    Z2-Z4 / z2-z4         set or remove a write, read or access watchpoint
+    D / k                 detach, clearing breakpoints and resuming the machine
+
+    Registers are reported in the order of GDB's i386 register file, with the
+    16-bit registers zero-extended and FS and GS reading as zero. IP is the 
+    offset within CS, while memory and breakpoint addresses are flat 20-bit
+    addresses. Connect with 'set architecture i8086' before 'target remote'.
+*/
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::breakpoints::{BreakPointType, ConditionContext, WatchType};
+use crate::cpu_808x::Register16;
+use crate::machine::{ExecutionControl, ExecutionOperation, ExecutionState, Machine};
+
+/// The largest packet we accept or send, advertised to the client in qSupported.
+const MAX_PACKET_LEN: usize = 4096;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum GdbRegister {
+    Reg(Register16),
+    Flags,
+    Unused,
+}
+
+/// Registers in the order of GDB's i386 register file.
+const GDB_REGISTERS: [GdbRegister; 16] = [
+    GdbRegister::Reg(Register16::AX),
+    GdbRegister::Reg(Register16::CX),
+    GdbRegister::Reg(Register16::DX),
+    GdbRegister::Reg(Register16::BX),
+    GdbRegister::Reg(Register16::SP),
+    GdbRegister::Reg(Register16::BP),
+    GdbRegister::Reg(Register16::SI),
+    GdbRegister::Reg(Register16::DI),
+    GdbRegister::Reg(Register16::IP),
+    GdbRegister::Flags,
+    GdbRegister::Reg(Register16::CS),
+    GdbRegister::Reg(Register16::SS),
+    GdbRegister::Reg(Register16::DS),
+    GdbRegister::Reg(Register16::ES),
+    GdbRegister::Unused,
+    GdbRegister::Unused,
+];
+
+/// A unit of input from the client.
+#[derive(Debug, PartialEq)]
+enum Packet {
+    Data(String),
+    BadChecksum,
+    Interrupt,
+}
+
+/// A resume request whose stop reply has not yet been sent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Resume {
+    Step,
+    Continue,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_packet(data: &str) -> Vec<u8> {
+    format!("${}#{:02x}", data, checksum(data.as_bytes())).into_bytes()
+}
+
+/// Take the next packet from the input buffer. Acknowledgements are discarded.
+fn next_packet(buf: &mut Vec<u8>) -> Option<Packet> {
+    loop {
+        match buf.first() {
+            Some(b'+') | Some(b'-') => {
+                buf.remove(0);
+            }
+            Some(0x03) => {
+                buf.remove(0);
+                return Some(Packet::Interrupt)
+            }
+            Some(b'$') => break,
+            Some(_) => {
+                // Discard anything outside of a packet
+                let skip = buf.iter().position(|b| matches!(*b, b'$' | 0x03)).unwrap_or(buf.len());
+                buf.drain(..skip);
+            }
+            None => return None
+        }
+    }
+
+    let end = match buf.iter().position(|b| *b == b'#') {
+        Some(end) if buf.len() >= end + 3 => end,
+        Some(_) => return None,
+        None if buf.len() > MAX_PACKET_LEN => {
+            buf.clear();
+            return Some(Packet::BadChecksum)
+        }
+        None => return None
+    };
+
+    let packet: Vec<u8> = buf.drain(..end + 3).collect();
+    let data = &packet[1..end];
+    let sum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+    if sum != Some(checksum(data)) {
+        return Some(Packet::BadChecksum)
+    }
+    Some(Packet::Data(String::from_utf8_lossy(data).to_string()))
+}
+
+/// Encode a register value as 32 bits of little-endian hex.
+fn encode_register(value: u16) -> String {
+    format!("{:08x}", (value as u32).swap_bytes())
+}
+
+/// Decode a register value from 32 bits of little-endian hex, truncating it to 16 bits.
+fn decode_register(hex: &str) -> Option<u16> {
+    if hex.len() != 8 {
+        return None
+    }
+    u32::from_str_radix(hex, 16).ok().map(|v| v.swap_bytes() as u16)
+}
+
+fn decode_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair).ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+/// Parse the 'addr,length' arguments of memory and breakpoint packets.
+fn parse_addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u32::from_str_radix(addr, 16).ok()?, u32::from_str_radix(len, 16).ok()?))
+}
+
+struct Client {
+    stream: TcpStream,
+    in_buf: Vec<u8>,
+    out_buf: Vec<u8>,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            in_buf: Vec::new(),
+            out_buf: Vec::new(),
+        })
+    }
+
+    /// Read any available data from the client. Returns false if the client disconnected.
+    fn read(&mut self) -> bool {
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(n) => self.in_buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false
+            }
+        }
+    }
+
+    /// Write as much pending output as the socket will accept. Returns false if the client
+    /// disconnected.
+    fn flush(&mut self) -> bool {
+        while !self.out_buf.is_empty() {
+            match self.stream.write(&self.out_buf) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.out_buf.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false
+            }
+        }
+        true
+    }
+}
+
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<Client>,
+    breakpoints: Vec<BreakPointType>,
+    resume: Option<Resume>,
+    last_signal: u8,
+}
+
+impl GdbServer {
+    /// Start listening for a GDB client on the specified local port.
+    pub fn new(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        log::info!("GDB server listening on 127.0.0.1:{}", port);
+
+        Ok(Self {
+            listener,
+            client: None,
+            breakpoints: Vec::new(),
+            resume: None,
+            last_signal: SIGTRAP,
+        })
+    }
+
+    /// Accept new clients, report stops and process any packets received. Should be called 
+    /// once per frame.
+    pub fn poll(&mut self, machine: &mut Machine, exec_control: &mut ExecutionControl) {
+        match self.listener.accept() {
+            Ok((stream, addr)) => {
+                if self.client.is_some() {
+                    log::warn!("GDB client already connected; rejecting connection from {}", addr);
+                }
+                else {
+                    match Client::new(stream) {
+                        Ok(client) => {
+                            log::info!("GDB client connected from {}", addr);
+                            self.client = Some(client);
+                            self.resume = None;
+                            self.last_signal = SIGTRAP;
+                            exec_control.set_op(ExecutionOperation::Pause);
+                        }
+                        Err(e) => log::error!("Error accepting GDB client: {}", e)
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => log::error!("Error accepting GDB client: {}", e)
+        }
+
+        let connected = match &mut self.client {
+            Some(client) => client.read(),
+            None => return
+        };
+
+        self.check_stop(exec_control);
+
+        if connected {
+            while let Some(packet) = self.client.as_mut().and_then(|c| next_packet(&mut c.in_buf)) {
+                match packet {
+                    Packet::Data(data) => {
+                        self.respond_raw(b"+");
+                        self.execute(&data, machine, exec_control);
+                    }
+                    Packet::BadChecksum => self.respond_raw(b"-"),
+                    Packet::Interrupt => {
+                        exec_control.set_op(ExecutionOperation::Pause);
+                        self.check_stop(exec_control);
+                    }
+                }
+            }
+        }
+
+        let flushed = match &mut self.client {
+            Some(client) => client.flush(),
+            None => true
+        };
+        if !connected || !flushed {
+            log::info!("GDB client disconnected");
+            self.detach(machine, exec_control);
+            self.client = None;
+        }
+    }
+
+    /// Send a stop reply if a pending resume request has finished.
+    fn check_stop(&mut self, exec_control: &mut ExecutionControl) {
+        let resume = match self.resume {
+            Some(resume) => resume,
+            None => return
+        };
+        // The requested operation has not been picked up by the machine yet.
+        if !matches!(exec_control.peek_op(), ExecutionOperation::None) {
+            return
+        }
+        let signal = match (exec_control.get_state(), resume) {
+            (ExecutionState::Running, _) => return,
+            (ExecutionState::Paused, Resume::Continue) => SIGINT,
+            _ => SIGTRAP,
+        };
+        self.resume = None;
+        self.last_signal = signal;
+        self.respond(&format!("S{:02x}", signal));
+    }
+
+    /// Remove our breakpoints and let the machine run freely.
+    fn detach(&mut self, machine: &mut Machine, exec_control: &mut ExecutionControl) {
+        if !self.breakpoints.is_empty() {
+            self.breakpoints.clear();
+            machine.set_breakpoints(Vec::new());
+        }
+        self.resume = None;
+        exec_control.set_op(ExecutionOperation::Run);
+    }
+
+    fn respond_raw(&mut self, bytes: &[u8]) {
+        if let Some(client) = &mut self.client {
+            client.out_buf.extend_from_slice(bytes);
+        }
+    }
+
+    fn respond(&mut self, data: &str) {
+        self.respond_raw(&encode_packet(data));
+    }
+
+    fn read_register(machine: &Machine, reg: GdbRegister) -> u16 {
+        match reg {
+            GdbRegister::Reg(reg) => machine.cpu().get_register16(reg),
+            GdbRegister::Flags => machine.cpu().get_state().flags,
+            GdbRegister::Unused => 0,
+        }
+    }
+
+    fn write_register(machine: &mut Machine, reg: GdbRegister, value: u16) {
+        match reg {
+            GdbRegister::Reg(reg) => machine.set_cpu_register16(reg, value),
+            GdbRegister::Flags => machine.set_cpu_flags(value),
+            GdbRegister::Unused => {}
+        }
+    }
+
+    fn same_breakpoint(a: &BreakPointType, b: &BreakPointType) -> bool {
+        match (a, b) {
+            (BreakPointType::ExecuteFlat(a), BreakPointType::ExecuteFlat(b)) => a == b,
+            (BreakPointType::Watch(a, a_len, a_type), BreakPointType::Watch(b, b_len, b_type)) => {
+                a == b && a_len == b_len && a_type == b_type
+            }
+            _ => false
+        }
+    }
+
+    fn set_breakpoint(&mut self, machine: &mut Machine, bp: BreakPointType, insert: bool) {
+        self.breakpoints.retain(|b| !Self::same_breakpoint(b, &bp));
+        if insert {
+            self.breakpoints.push(bp);
+        }
+        machine.set_breakpoints(self.breakpoints.clone());
+    }
+
+    fn execute(&mut self, data: &str, machine: &mut Machine, exec_control: &mut ExecutionControl) {
+        let (cmd, args) = data.split_at(data.chars().next().map_or(0, |c| c.len_utf8()));
+        match cmd {
+            "?" => {
+                let msg = format!("S{:02x}", self.last_signal);
+                self.respond(&msg);
+            }
+            "g" => {
+                let regs: String = GDB_REGISTERS.iter()
+                    .map(|reg| encode_register(Self::read_register(machine, *reg)))
+                    .collect();
+                self.respond(&regs);
+            }
+            "G" => {
+                if args.len() < GDB_REGISTERS.len() * 8 {
+                    self.respond("E01");
+                    return
+                }
+                for (i, reg) in GDB_REGISTERS.iter().enumerate() {
+                    if let Some(value) = args.get(i * 8..i * 8 + 8).and_then(decode_register) {
+                        Self::write_register(machine, *reg, value);
+                    }
+                }
+                self.respond("OK");
+            }
+            "p" => {
+                match usize::from_str_radix(args, 16).ok().and_then(|i| GDB_REGISTERS.get(i)) {
+                    Some(reg) => {
+                        let value = encode_register(Self::read_register(machine, *reg));
+                        self.respond(&value);
+                    }
+                    None => self.respond("E01")
+                }
+            }
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, v)| {
+                    let reg = usize::from_str_radix(n, 16).ok().and_then(|i| GDB_REGISTERS.get(i))?;
+                    Some((*reg, decode_register(v)?))
+                });
+                match parsed {
+                    Some((reg, value)) => {
+                        Self::write_register(machine, reg, value);
+                        self.respond("OK");
+                    }
+                    None => self.respond("E01")
+                }
+            }
+            "m" => {
+                match parse_addr_len(args) {
+                    Some((addr, len)) => {
+                        let len = len.min(MAX_PACKET_LEN as u32 / 2);
+                        let bytes: String = (0..len)
+                            .map(|i| format!("{:02x}", machine.cpu().peek_u8(addr.wrapping_add(i) & 0xFFFFF)))
+                            .collect();
+                        self.respond(&bytes);
+                    }
+                    None => self.respond("E01")
+                }
+            }
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, hex)| {
+                    Some((parse_addr_len(range)?, decode_hex_bytes(hex)?))
+                });
+                match parsed {
+                    Some(((addr, len), bytes)) if bytes.len() == len as usize => {
+                        for (i, byte) in bytes.iter().enumerate() {
+                            let address = (addr.wrapping_add(i as u32) & 0xFFFFF) as usize;
+                            if machine.bus_mut().write_u8(address, *byte, 0).is_err() {
+                                self.respond("E03");
+                                return
+                            }
+                        }
+                        self.respond("OK");
+                    }
+                    _ => self.respond("E01")
+                }
+            }
+            "c" | "s" => {
+                let (op, resume) = match cmd {
+                    "c" => (ExecutionOperation::Run, Resume::Continue),
+                    _ => (ExecutionOperation::Step, Resume::Step),
+                };
+                exec_control.set_op(op);
+                self.resume = Some(resume);
+            }
+            "Z" | "z" => {
+                let insert = cmd == "Z";
+                let bp = args.split_once(',').and_then(|(kind, rest)| {
+                    let (addr, len) = parse_addr_len(rest)?;
+                    let addr = addr & 0xFFFFF;
+                    match kind {
+                        "0" | "1" => Some(BreakPointType::ExecuteFlat(addr)),
+                        "2" => Some(BreakPointType::Watch(addr, len.max(1), WatchType::Write)),
+                        "3" => Some(BreakPointType::Watch(addr, len.max(1), WatchType::Read)),
+                        "4" => Some(BreakPointType::Watch(addr, len.max(1), WatchType::ReadWrite)),
+                        _ => None
+                    }
+                });
+                match bp {
+                    Some(bp) => {
+                        self.set_breakpoint(machine, bp, insert);
+                        self.respond("OK");
+                    }
+                    // An empty response tells the client the breakpoint type is unsupported.
+                    None => self.respond("")
+                }
+            }
+            "D" => {
+                self.respond("OK");
+                self.detach(machine, exec_control);
+            }
+            "k" => self.detach(machine, exec_control),
+            "H" | "T" => self.respond("OK"),
+            "q" => {
+                let reply = match args.split(':').next().unwrap_or("") {
+                    "Supported" => format!("PacketSize={:x}", MAX_PACKET_LEN),
+                    "Attached" => "1".to_string(),
+                    "C" => "QC1".to_string(),
+                    "fThreadInfo" => "m1".to_string(),
+                    "sThreadInfo" => "l".to_string(),
+                    _ => String::new()
+                };
+                self.respond(&reply);
+            }
+            _ => self.respond("")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(encode_packet("OK"), b"$OK#9a".to_vec());
+
+        let mut buf = b"+$g#67\x03$m0,4#00$qSupported".to_vec();
+        assert_eq!(next_packet(&mut buf), Some(Packet::Data("g".to_string())));
+        assert_eq!(next_packet(&mut buf), Some(Packet::Interrupt));
+        assert_eq!(next_packet(&mut buf), Some(Packet::BadChecksum));
+        assert_eq!(next_packet(&mut buf), None);
+        assert_eq!(buf, b"$qSupported".to_vec());
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode_register(0x1234), "34120000");
+        assert_eq!(decode_register("34120000"), Some(0x1234));
+        assert_eq!(decode_register("3412"), None);
+        assert_eq!(decode_hex_bytes("00ff10"), Some(vec![0x00, 0xFF, 0x10]));
+        assert_eq!(decode_hex_bytes("0"), None);
+        assert_eq!(parse_addr_len("f0000,10"), Some((0xF0000, 0x10)));
+        assert_eq!(parse_addr_len("f0000"), None);
+    }
+}
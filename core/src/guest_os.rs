@@ -0,0 +1,300 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    guest_os.rs
+
+    Implements detection of the guest operating environment.
+
+    Scanning all of conventional memory every frame would be wasteful, so the
+    detector watches for boot milestones instead: a change of the INT 21h
+    (DOS) or INT 2Fh (multiplex) vectors in the IVT. A DOS kernel installs 
+    both while booting, and Windows hooks both again when it starts. Some
+    frames after a milestone, once the guest has had time to print its
+    banner, conventional memory is scanned for known signature strings.
+
+    The detected environment is shown in the frontend's status bar and may be
+    queried via Machine::guest_os() to apply per-OS overrides.
+*/
+
+use std::fmt;
+
+/// The number of frames to wait after a milestone before scanning memory.
+pub const DEFAULT_SCAN_DELAY_FRAMES: u32 = 30;
+
+const IVT_INT21_OFFSET: usize = 0x21 * 4;
+const IVT_INT2F_OFFSET: usize = 0x2F * 4;
+const CONVENTIONAL_MEMORY_SIZE: usize = 0xA0000;
+/// How far past a vendor signature to look for a version string.
+const VERSION_SEARCH_WINDOW: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DosVendor {
+    Ibm,
+    Microsoft,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GuestOs {
+    #[default]
+    Unknown,
+    Dos { vendor: DosVendor, version: Option<(u8, u8)> },
+    Windows { major: u8, minor: Option<u8> },
+}
+
+impl fmt::Display for GuestOs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuestOs::Unknown => write!(f, "Unknown"),
+            GuestOs::Dos { vendor, version } => {
+                let name = match vendor {
+                    DosVendor::Ibm => "PC-DOS",
+                    DosVendor::Microsoft => "MS-DOS",
+                };
+                match version {
+                    Some((major, minor)) => write!(f, "{} {}.{:02}", name, major, minor),
+                    None => write!(f, "{}", name),
+                }
+            }
+            GuestOs::Windows { major, minor } => match minor {
+                Some(minor) => write!(f, "Windows {}.{}", major, minor),
+                None => write!(f, "Windows {}.x", major),
+            },
+        }
+    }
+}
+
+pub struct GuestOsDetector {
+    scan_delay: u32,
+    vectors: Option<(u32, u32)>,
+    dos_int21: Option<u32>,
+    pending: Option<u32>,
+    os: GuestOs,
+    dos: GuestOs,
+}
+
+impl Default for GuestOsDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCAN_DELAY_FRAMES)
+    }
+}
+
+impl GuestOsDetector {
+    pub fn new(scan_delay: u32) -> Self {
+        Self {
+            scan_delay,
+            vectors: None,
+            dos_int21: None,
+            pending: None,
+            os: GuestOs::Unknown,
+            dos: GuestOs::Unknown,
+        }
+    }
+
+    /// Return the currently detected guest environment.
+    pub fn guest_os(&self) -> GuestOs {
+        self.os
+    }
+
+    /// Check the IVT for boot milestones and run any scan that has come due. `mem` should
+    /// begin at physical address 0. Returns the new guest environment if it changed.
+    pub fn frame_update(&mut self, mem: &[u8]) -> Option<GuestOs> {
+        let vectors = (read_vector(mem, IVT_INT21_OFFSET), read_vector(mem, IVT_INT2F_OFFSET));
+
+        if self.vectors != Some(vectors) {
+            self.vectors = Some(vectors);
+            self.pending = Some(self.scan_delay);
+
+            // Windows restores the DOS vectors when it exits, at which point we are back at
+            // the DOS prompt. Don't rescan, as the Windows signatures are likely still in memory.
+            if matches!(self.os, GuestOs::Windows { .. }) && self.dos_int21 == Some(vectors.0) {
+                self.pending = None;
+                return self.set_os(self.dos);
+            }
+        }
+
+        match self.pending {
+            Some(0) => {
+                self.pending = None;
+                let detected = self.scan(mem, vectors.0);
+                self.set_os(detected)
+            }
+            Some(n) => {
+                self.pending = Some(n - 1);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.vectors = None;
+        self.dos_int21 = None;
+        self.pending = None;
+        self.os = GuestOs::Unknown;
+        self.dos = GuestOs::Unknown;
+    }
+
+    fn set_os(&mut self, os: GuestOs) -> Option<GuestOs> {
+        if os != self.os {
+            self.os = os;
+            Some(os)
+        }
+        else {
+            None
+        }
+    }
+
+    fn scan(&mut self, mem: &[u8], int21: u32) -> GuestOs {
+        let mem = &mem[..mem.len().min(CONVENTIONAL_MEMORY_SIZE)];
+
+        if let Some(windows) = scan_windows(mem) {
+            return windows
+        }
+
+        if let Some(dos) = scan_dos(mem) {
+            // Remember the DOS vector so we can tell when Windows exits.
+            self.dos = dos;
+            self.dos_int21 = Some(int21);
+            return dos
+        }
+
+        // A scan can miss the banner if it has already been overwritten; keep whatever
+        // we found before.
+        self.os
+    }
+}
+
+fn read_vector(mem: &[u8], offset: usize) -> u32 {
+    match mem.get(offset..offset + 4) {
+        Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        None => 0,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parse a version string of the form "Version x.yy" following `start`.
+fn find_version(mem: &[u8], start: usize) -> Option<(u8, u8)> {
+    let window = &mem[start..mem.len().min(start + VERSION_SEARCH_WINDOW)];
+    let pos = find(window, b"Version ")? + 8;
+    let text = &window[pos..];
+
+    let major = *text.first()?;
+    if !major.is_ascii_digit() || text.get(1) != Some(&b'.') {
+        return None
+    }
+    let minor_digits: Vec<u8> = text[2..].iter().take(2).take_while(|c| c.is_ascii_digit()).copied().collect();
+    let minor = std::str::from_utf8(&minor_digits).ok()?.parse::<u8>().ok()?;
+    Some((major - b'0', minor))
+}
+
+fn scan_dos(mem: &[u8]) -> Option<GuestOs> {
+    const SIGNATURES: [(&[u8], DosVendor); 3] = [
+        (b"IBM Personal Computer DOS", DosVendor::Ibm),
+        (b"PC DOS", DosVendor::Ibm),
+        (b"MS-DOS", DosVendor::Microsoft),
+    ];
+
+    SIGNATURES.iter().find_map(|(sig, vendor)| {
+        find(mem, sig).map(|pos| GuestOs::Dos {
+            vendor: *vendor,
+            version: find_version(mem, pos),
+        })
+    })
+}
+
+fn scan_windows(mem: &[u8]) -> Option<GuestOs> {
+    let version = find(mem, b"Microsoft Windows").and_then(|pos| find_version(mem, pos));
+
+    // The shell distinguishes the generations: Windows 1.x and 2.x run the MS-DOS Executive,
+    // Windows 3.x runs Program Manager.
+    let major = if find(mem, b"Program Manager").is_some() {
+        3
+    }
+    else if find(mem, b"MS-DOS Executive").is_some() {
+        match version {
+            Some((major @ 1..=2, _)) => major,
+            _ => 2,
+        }
+    }
+    else {
+        return None
+    };
+
+    let minor = match version {
+        Some((v_major, v_minor)) if v_major == major => Some(v_minor),
+        _ => None,
+    };
+    Some(GuestOs::Windows { major, minor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_str(mem: &mut [u8], addr: usize, s: &str) {
+        mem[addr..addr + s.len()].copy_from_slice(s.as_bytes());
+    }
+
+    fn run_frames(detector: &mut GuestOsDetector, mem: &[u8], frames: u32) -> Option<GuestOs> {
+        let mut changed = None;
+        for _ in 0..frames {
+            changed = detector.frame_update(mem).or(changed);
+        }
+        changed
+    }
+
+    #[test]
+    fn test_dos_then_windows() {
+        let mut mem = vec![0u8; 0x10000];
+        let mut detector = GuestOsDetector::new(2);
+        assert_eq!(run_frames(&mut detector, &mem, 4), None);
+
+        // Boot DOS: the kernel installs INT 21h and COMMAND.COM prints its banner.
+        mem[IVT_INT21_OFFSET..IVT_INT21_OFFSET + 4].copy_from_slice(&[0x40, 0x01, 0x70, 0x02]);
+        write_str(&mut mem, 0x5000, "The IBM Personal Computer DOS\r\nVersion 3.30 (C)Copyright IBM");
+        assert_eq!(detector.frame_update(&mem), None);
+        let dos = GuestOs::Dos { vendor: DosVendor::Ibm, version: Some((3, 30)) };
+        assert_eq!(run_frames(&mut detector, &mem, 3), Some(dos));
+        assert_eq!(dos.to_string(), "PC-DOS 3.30");
+
+        // Start Windows: INT 21h is hooked again.
+        mem[IVT_INT21_OFFSET..IVT_INT21_OFFSET + 4].copy_from_slice(&[0x00, 0x00, 0x00, 0x30]);
+        write_str(&mut mem, 0x8000, "Microsoft Windows Version 3.0");
+        write_str(&mut mem, 0x9000, "Program Manager");
+        let windows = run_frames(&mut detector, &mem, 3).unwrap();
+        assert_eq!(windows.to_string(), "Windows 3.0");
+
+        // Exit Windows: the DOS vector is restored.
+        mem[IVT_INT21_OFFSET..IVT_INT21_OFFSET + 4].copy_from_slice(&[0x40, 0x01, 0x70, 0x02]);
+        assert_eq!(detector.frame_update(&mem), Some(dos));
+
+        detector.reset();
+        assert_eq!(detector.guest_os(), GuestOs::Unknown);
+    }
+}
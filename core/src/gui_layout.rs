@@ -0,0 +1,105 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    gui_layout.rs
+
+    Persists the layout of the GUI between sessions: which windows were open,
+    and where they were placed. The layout is kept in a JSON file alongside
+    the list of recent floppy images.
+
+*/
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// The placement of a single GUI window.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowLayout {
+    pub open: bool,
+    pub pos: Option<[f32; 2]>,
+}
+
+/// The placement of all GUI windows, keyed by window name.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GuiLayout {
+    windows: BTreeMap<String, WindowLayout>,
+}
+
+impl GuiLayout {
+    /// Load the layout from the specified file. A missing or invalid file gives an empty layout,
+    /// leaving every window in its default state.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)
+    }
+
+    pub fn get(&self, window: &str) -> Option<&WindowLayout> {
+        self.windows.get(window)
+    }
+
+    pub fn set(&mut self, window: &str, layout: WindowLayout) {
+        self.windows.insert(window.to_string(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_round_trip() {
+        let mut layout = GuiLayout::default();
+        layout.set("MemoryViewer", WindowLayout { open: true, pos: Some([10.0, 20.0]) });
+        layout.set("CpuControl", WindowLayout { open: false, pos: None });
+
+        let path = std::env::temp_dir().join("marty_gui_layout_test.json");
+        layout.save(&path).unwrap();
+        let loaded = GuiLayout::load(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded, layout);
+        assert!(loaded.get("MemoryViewer").unwrap().open);
+        assert!(loaded.get("About").is_none());
+    }
+
+    #[test]
+    fn missing_layout_is_empty() {
+        let layout = GuiLayout::load(Path::new("this_layout_does_not_exist.json"));
+        assert_eq!(layout, GuiLayout::default());
+    }
+}
@@ -0,0 +1,181 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    heatmap.rs
+
+    Implements counting of memory accesses per 4K page of the address space, 
+    for visualizing memory activity over time. Accesses are counted by the 
+    bus master that made them: the CPU, DMA transfers, or the CPU accessing
+    a video card's memory. The wait states the CPU incurred on each page are
+    counted as well, to show where it contended with other devices.
+
+    The bus counts accesses for the current frame only; the history of 
+    frames is kept by whoever displays it.
+*/
+
+pub const HEATMAP_PAGE_SHIFT: usize = 12;
+pub const HEATMAP_PAGE_SIZE: usize = 1 << HEATMAP_PAGE_SHIFT;
+pub const HEATMAP_PAGES: usize = 0x100000 >> HEATMAP_PAGE_SHIFT;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessSource {
+    Cpu,
+    Dma,
+    Video,
+}
+
+impl AccessSource {
+    pub const ALL: [AccessSource; 3] = [AccessSource::Cpu, AccessSource::Dma, AccessSource::Video];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessSource::Cpu => "CPU",
+            AccessSource::Dma => "DMA",
+            AccessSource::Video => "Video",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PageActivity {
+    reads: [u32; 3],
+    writes: [u32; 3],
+    pub wait_states: u32,
+}
+
+impl PageActivity {
+    pub fn reads(&self, source: AccessSource) -> u32 {
+        self.reads[source as usize]
+    }
+
+    pub fn writes(&self, source: AccessSource) -> u32 {
+        self.writes[source as usize]
+    }
+
+    pub fn accesses(&self, source: AccessSource) -> u32 {
+        self.reads(source) + self.writes(source)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == PageActivity::default()
+    }
+}
+
+pub struct HeatmapRecorder {
+    enabled: bool,
+    active: bool,
+    pages: Vec<PageActivity>,
+}
+
+impl Default for HeatmapRecorder {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            active: false,
+            pages: vec![PageActivity::default(); HEATMAP_PAGES],
+        }
+    }
+}
+
+impl HeatmapRecorder {
+    /// Enable or disable counting. Disabling counting discards the counts for the current frame.
+    pub fn set_enabled(&mut self, state: bool) {
+        if !state && self.active {
+            self.take();
+        }
+        self.enabled = state;
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Count an access to the specified address, and the wait states it incurred.
+    #[inline]
+    pub fn record(&mut self, address: usize, source: AccessSource, write: bool, wait_states: u32) {
+        if !self.enabled {
+            return
+        }
+        if let Some(page) = self.pages.get_mut(address >> HEATMAP_PAGE_SHIFT) {
+            match write {
+                true => page.writes[source as usize] += 1,
+                false => page.reads[source as usize] += 1,
+            }
+            page.wait_states += wait_states;
+            self.active = true;
+        }
+    }
+
+    /// Return the counts for each page since the last call and start a new frame. Returns None
+    /// if there was no activity, such as when the machine is paused.
+    pub fn take(&mut self) -> Option<Vec<PageActivity>> {
+        if !self.active {
+            return None
+        }
+        self.active = false;
+        Some(std::mem::replace(&mut self.pages, vec![PageActivity::default(); HEATMAP_PAGES]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut recorder = HeatmapRecorder::default();
+        recorder.record(0x1000, AccessSource::Cpu, false, 0);
+        assert_eq!(recorder.take(), None);
+
+        recorder.set_enabled(true);
+        recorder.record(0x1000, AccessSource::Cpu, false, 0);
+        recorder.record(0x1FFF, AccessSource::Cpu, true, 0);
+        recorder.record(0xB8000, AccessSource::Video, true, 6);
+        recorder.record(0x400, AccessSource::Dma, true, 0);
+        recorder.record(0x100000, AccessSource::Dma, true, 0);
+
+        let pages = recorder.take().unwrap();
+        assert_eq!(pages.len(), HEATMAP_PAGES);
+        assert_eq!(pages[0].writes(AccessSource::Dma), 1);
+        assert_eq!(pages[1].reads(AccessSource::Cpu), 1);
+        assert_eq!(pages[1].accesses(AccessSource::Cpu), 2);
+        assert_eq!(pages[0xB8].accesses(AccessSource::Video), 1);
+        assert_eq!(pages[0xB8].wait_states, 6);
+        assert!(pages[2].is_empty());
+        assert_eq!(recorder.take(), None);
+    }
+
+    #[test]
+    fn test_disable_discards() {
+        let mut recorder = HeatmapRecorder::default();
+        recorder.set_enabled(true);
+        recorder.record(0x1000, AccessSource::Cpu, false, 0);
+        recorder.set_enabled(false);
+        recorder.set_enabled(true);
+        assert_eq!(recorder.take(), None);
+    }
+}
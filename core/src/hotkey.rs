@@ -0,0 +1,384 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    hotkey.rs
+
+    Rebindable emulator shortcuts. Each hotkey action is bound to a key 
+    combination such as "Ctrl+F10". Bindings start from a set of defaults 
+    and are overridden by the [hotkeys] table of the hotkey file, which the
+    GUI's hotkey editor writes back when a binding is changed.
+
+*/
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs,
+    path::Path,
+    str::FromStr
+};
+
+use serde_derive::{Deserialize, Serialize};
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+/// The number of save state slots that can be bound to hotkeys.
+pub const STATE_SLOTS: u8 = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HotkeyAction {
+    MouseCapture,
+    Fullscreen,
+    Screenshot,
+    Pause,
+    Reboot,
+    CtrlAltDel,
+    FastForward,
+    SpeedUp,
+    SpeedDown,
+    NormalSpeed,
+    SaveState(u8),
+    LoadState(u8),
+}
+
+impl HotkeyAction {
+    /// Return every hotkey action, in the order they are listed in the hotkey editor.
+    pub fn all() -> Vec<HotkeyAction> {
+        let mut actions = vec![
+            HotkeyAction::MouseCapture,
+            HotkeyAction::Fullscreen,
+            HotkeyAction::Screenshot,
+            HotkeyAction::Pause,
+            HotkeyAction::Reboot,
+            HotkeyAction::CtrlAltDel,
+            HotkeyAction::FastForward,
+            HotkeyAction::SpeedUp,
+            HotkeyAction::SpeedDown,
+            HotkeyAction::NormalSpeed,
+        ];
+        actions.extend((1..=STATE_SLOTS).map(HotkeyAction::SaveState));
+        actions.extend((1..=STATE_SLOTS).map(HotkeyAction::LoadState));
+        actions
+    }
+
+    pub fn desc(&self) -> String {
+        match self {
+            HotkeyAction::MouseCapture => "Toggle mouse capture".to_string(),
+            HotkeyAction::Fullscreen => "Toggle fullscreen".to_string(),
+            HotkeyAction::Screenshot => "Take screenshot".to_string(),
+            HotkeyAction::Pause => "Pause / resume".to_string(),
+            HotkeyAction::Reboot => "Reboot machine".to_string(),
+            HotkeyAction::CtrlAltDel => "Send CTRL-ALT-DEL".to_string(),
+            HotkeyAction::FastForward => "Fast-forward (hold)".to_string(),
+            HotkeyAction::SpeedUp => "Speed up".to_string(),
+            HotkeyAction::SpeedDown => "Slow down".to_string(),
+            HotkeyAction::NormalSpeed => "Normal speed".to_string(),
+            HotkeyAction::SaveState(slot) => format!("Save state to slot {}", slot),
+            HotkeyAction::LoadState(slot) => format!("Load state from slot {}", slot),
+        }
+    }
+}
+
+impl fmt::Display for HotkeyAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HotkeyAction::SaveState(slot) => write!(f, "SaveState{}", slot),
+            HotkeyAction::LoadState(slot) => write!(f, "LoadState{}", slot),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl FromStr for HotkeyAction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        HotkeyAction::all()
+            .into_iter()
+            .find(|action| action.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown hotkey action: {}", s))
+    }
+}
+
+/// Names of the keys that can be bound, as written in the hotkey file.
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = &[
+    ("F1", VirtualKeyCode::F1), ("F2", VirtualKeyCode::F2), ("F3", VirtualKeyCode::F3),
+    ("F4", VirtualKeyCode::F4), ("F5", VirtualKeyCode::F5), ("F6", VirtualKeyCode::F6),
+    ("F7", VirtualKeyCode::F7), ("F8", VirtualKeyCode::F8), ("F9", VirtualKeyCode::F9),
+    ("F10", VirtualKeyCode::F10), ("F11", VirtualKeyCode::F11), ("F12", VirtualKeyCode::F12),
+    ("A", VirtualKeyCode::A), ("B", VirtualKeyCode::B), ("C", VirtualKeyCode::C),
+    ("D", VirtualKeyCode::D), ("E", VirtualKeyCode::E), ("F", VirtualKeyCode::F),
+    ("G", VirtualKeyCode::G), ("H", VirtualKeyCode::H), ("I", VirtualKeyCode::I),
+    ("J", VirtualKeyCode::J), ("K", VirtualKeyCode::K), ("L", VirtualKeyCode::L),
+    ("M", VirtualKeyCode::M), ("N", VirtualKeyCode::N), ("O", VirtualKeyCode::O),
+    ("P", VirtualKeyCode::P), ("Q", VirtualKeyCode::Q), ("R", VirtualKeyCode::R),
+    ("S", VirtualKeyCode::S), ("T", VirtualKeyCode::T), ("U", VirtualKeyCode::U),
+    ("V", VirtualKeyCode::V), ("W", VirtualKeyCode::W), ("X", VirtualKeyCode::X),
+    ("Y", VirtualKeyCode::Y), ("Z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0), ("1", VirtualKeyCode::Key1), ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3), ("4", VirtualKeyCode::Key4), ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6), ("7", VirtualKeyCode::Key7), ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("PageUp", VirtualKeyCode::PageUp), ("PageDown", VirtualKeyCode::PageDown),
+    ("Home", VirtualKeyCode::Home), ("End", VirtualKeyCode::End),
+    ("Insert", VirtualKeyCode::Insert), ("Delete", VirtualKeyCode::Delete),
+    ("Up", VirtualKeyCode::Up), ("Down", VirtualKeyCode::Down),
+    ("Left", VirtualKeyCode::Left), ("Right", VirtualKeyCode::Right),
+    ("Pause", VirtualKeyCode::Pause), ("ScrollLock", VirtualKeyCode::Scroll),
+    ("PrintScreen", VirtualKeyCode::Snapshot), ("Escape", VirtualKeyCode::Escape),
+    ("Tab", VirtualKeyCode::Tab), ("Space", VirtualKeyCode::Space),
+    ("Backspace", VirtualKeyCode::Back), ("Enter", VirtualKeyCode::Return),
+    ("Grave", VirtualKeyCode::Grave), ("Minus", VirtualKeyCode::Minus),
+    ("Equals", VirtualKeyCode::Equals),
+];
+
+/// A key pressed together with a set of modifier keys.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub key: VirtualKeyCode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyCombo {
+    pub fn new(key: VirtualKeyCode, modifiers: ModifiersState) -> Self {
+        Self {
+            key,
+            ctrl: modifiers.ctrl(),
+            shift: modifiers.shift(),
+            alt: modifiers.alt(),
+        }
+    }
+
+    /// Return whether a key can be bound. Modifier keys on their own cannot.
+    pub fn is_bindable(key: VirtualKeyCode) -> bool {
+        KEY_NAMES.iter().any(|(_, k)| *k == key)
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+        if self.alt {
+            write!(f, "Alt+")?;
+        }
+        match KEY_NAMES.iter().find(|(_, k)| *k == self.key) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{:?}", self.key),
+        }
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut combo = KeyCombo { key: VirtualKeyCode::Escape, ctrl: false, shift: false, alt: false };
+        let mut key = None;
+        for part in s.split('+').map(|part| part.trim()) {
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => combo.ctrl = true,
+                "shift" => combo.shift = true,
+                "alt" => combo.alt = true,
+                _ => {
+                    if key.is_some() {
+                        return Err(format!("More than one key in hotkey: {}", s))
+                    }
+                    key = KEY_NAMES
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(part))
+                        .map(|(_, k)| *k);
+                    if key.is_none() {
+                        return Err(format!("Unknown key in hotkey: {}", part))
+                    }
+                }
+            }
+        }
+        combo.key = key.ok_or_else(|| format!("No key in hotkey: {}", s))?;
+        Ok(combo)
+    }
+}
+
+/// The format of the hotkey file.
+#[derive(Default, Serialize, Deserialize)]
+struct HotkeyFile {
+    #[serde(default)]
+    hotkeys: BTreeMap<String, String>,
+}
+
+pub struct HotkeyManager {
+    bindings: BTreeMap<HotkeyAction, KeyCombo>,
+}
+
+impl Default for HotkeyManager {
+    fn default() -> Self {
+        let mut bindings = BTreeMap::new();
+        let defaults = [
+            (HotkeyAction::MouseCapture, "Ctrl+F10"),
+            (HotkeyAction::Fullscreen, "Alt+Enter"),
+            (HotkeyAction::Screenshot, "Ctrl+F5"),
+            (HotkeyAction::Reboot, "Ctrl+F12"),
+            (HotkeyAction::FastForward, "Ctrl+End"),
+            (HotkeyAction::SpeedUp, "Ctrl+PageUp"),
+            (HotkeyAction::SpeedDown, "Ctrl+PageDown"),
+            (HotkeyAction::NormalSpeed, "Ctrl+Home"),
+        ];
+        for (action, keys) in defaults {
+            bindings.insert(action, keys.parse().unwrap());
+        }
+        for slot in 1..=STATE_SLOTS {
+            let key = KEY_NAMES[slot as usize - 1].1;
+            bindings.insert(HotkeyAction::SaveState(slot), KeyCombo { key, ctrl: true, shift: true, alt: false });
+            bindings.insert(HotkeyAction::LoadState(slot), KeyCombo { key, ctrl: true, shift: false, alt: false });
+        }
+        Self { bindings }
+    }
+}
+
+impl HotkeyManager {
+    /// Load bindings from the specified hotkey file over the defaults. A missing file leaves the
+    /// defaults; invalid entries are skipped. An action bound to "" is unbound.
+    pub fn load(path: &Path) -> Self {
+        let mut manager = Self::default();
+        let file: HotkeyFile = match fs::read_to_string(path) {
+            Ok(text) => match toml::from_str(&text) {
+                Ok(file) => file,
+                Err(e) => {
+                    log::error!("Error parsing hotkey file {}: {}", path.display(), e);
+                    return manager
+                }
+            },
+            Err(_) => return manager,
+        };
+
+        for (action, keys) in &file.hotkeys {
+            let action = match action.parse::<HotkeyAction>() {
+                Ok(action) => action,
+                Err(e) => {
+                    log::warn!("{}", e);
+                    continue
+                }
+            };
+            if keys.trim().is_empty() {
+                manager.unbind(action);
+                continue
+            }
+            match keys.parse::<KeyCombo>() {
+                Ok(combo) => manager.bind(action, combo),
+                Err(e) => log::warn!("Hotkey for {}: {}", action, e),
+            }
+        }
+        manager
+    }
+
+    /// Save every binding to the specified hotkey file. Unbound actions are written as "" so that
+    /// they stay unbound instead of reverting to their default.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = HotkeyFile {
+            hotkeys: self.list()
+                .into_iter()
+                .map(|(action, combo)| {
+                    (action.to_string(), combo.map(|combo| combo.to_string()).unwrap_or_default())
+                })
+                .collect(),
+        };
+        let text = toml::to_string(&file).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    /// Return every action with its binding, in the order they are listed in the hotkey editor.
+    pub fn list(&self) -> Vec<(HotkeyAction, Option<KeyCombo>)> {
+        HotkeyAction::all()
+            .into_iter()
+            .map(|action| (action, self.binding(action)))
+            .collect()
+    }
+
+    pub fn binding(&self, action: HotkeyAction) -> Option<KeyCombo> {
+        self.bindings.get(&action).copied()
+    }
+
+    /// Bind an action to a key combination. Any other action bound to the same combination is
+    /// unbound, so a key press always maps to a single action.
+    pub fn bind(&mut self, action: HotkeyAction, combo: KeyCombo) {
+        self.bindings.retain(|_, bound| *bound != combo);
+        self.bindings.insert(action, combo);
+    }
+
+    pub fn unbind(&mut self, action: HotkeyAction) {
+        self.bindings.remove(&action);
+    }
+
+    /// Return the action bound to a key pressed with the specified modifiers, if any.
+    pub fn action(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<HotkeyAction> {
+        let combo = KeyCombo::new(key, modifiers);
+        self.bindings
+            .iter()
+            .find(|(_, bound)| **bound == combo)
+            .map(|(action, _)| *action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_combo_round_trip() {
+        let combo: KeyCombo = "ctrl+shift+f3".parse().unwrap();
+        assert_eq!(combo, KeyCombo { key: VirtualKeyCode::F3, ctrl: true, shift: true, alt: false });
+        assert_eq!(combo.to_string(), "Ctrl+Shift+F3");
+        assert_eq!("SaveState2".parse::<HotkeyAction>(), Ok(HotkeyAction::SaveState(2)));
+        assert!("Ctrl+Shift".parse::<KeyCombo>().is_err());
+        assert!("Ctrl+Nope".parse::<KeyCombo>().is_err());
+        assert!("SaveState9".parse::<HotkeyAction>().is_err());
+    }
+
+    #[test]
+    fn bindings_load_and_save() {
+        let path = std::env::temp_dir().join("marty_hotkey_test.toml");
+        fs::write(&path, "[hotkeys]\nScreenshot = \"Ctrl+F10\"\nFastForward = \"\"\nBogus = \"F1\"\n").unwrap();
+
+        let mut hotkeys = HotkeyManager::load(&path);
+        // Screenshot took Ctrl+F10 from mouse capture
+        let ctrl = ModifiersState::CTRL;
+        assert_eq!(hotkeys.action(VirtualKeyCode::F10, ctrl), Some(HotkeyAction::Screenshot));
+        assert_eq!(hotkeys.binding(HotkeyAction::MouseCapture), None);
+        assert_eq!(hotkeys.binding(HotkeyAction::FastForward), None);
+        assert_eq!(hotkeys.action(VirtualKeyCode::F1, ctrl), Some(HotkeyAction::LoadState(1)));
+        assert_eq!(hotkeys.action(VirtualKeyCode::F1, ModifiersState::empty()), None);
+
+        hotkeys.bind(HotkeyAction::MouseCapture, "Alt+M".parse().unwrap());
+        hotkeys.save(&path).unwrap();
+        let reloaded = HotkeyManager::load(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(reloaded.bindings, hotkeys.bindings);
+    }
+}
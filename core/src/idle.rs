@@ -0,0 +1,230 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    idle.rs
+
+    Implements detection of an idle guest.
+
+    Each emulated frame is classified as idle or busy. A frame is idle if the
+    CPU spent most of it halted, or if the guest repeatedly polled the
+    keyboard or called the DOS idle interrupt without any keyboard input 
+    arriving. The guest is considered idle after a number of consecutive idle 
+    frames, and busy again after a number of consecutive busy frames. 
+    
+    Frontends can use the idle state to dim the display, reduce host polling
+    and so on.
+*/
+
+pub const DEFAULT_IDLE_ENTER_FRAMES: u32 = 60;
+pub const DEFAULT_IDLE_EXIT_FRAMES: u32 = 2;
+
+/// The fraction of a frame's cycles the CPU must spend halted for the frame to be idle.
+const IDLE_HALT_RATIO: f64 = 0.5;
+/// The number of keyboard polls or DOS idle calls in a frame for the frame to be idle.
+const IDLE_CALL_THRESHOLD: u32 = 2;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct FrameSample {
+    cycles: u64,
+    halt_cycles: u64,
+    idle_calls: u32,
+    input: bool,
+}
+
+impl FrameSample {
+    fn is_idle(&self) -> bool {
+        if self.input {
+            return false
+        }
+        let halt_ratio = self.halt_cycles as f64 / self.cycles as f64;
+        halt_ratio >= IDLE_HALT_RATIO || self.idle_calls >= IDLE_CALL_THRESHOLD
+    }
+}
+
+pub struct IdleDetector {
+    enter_frames: u32,
+    exit_frames: u32,
+    sample: FrameSample,
+    idle_run: u32,
+    busy_run: u32,
+    idle: bool,
+    idle_frames: u64,
+}
+
+impl Default for IdleDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_ENTER_FRAMES, DEFAULT_IDLE_EXIT_FRAMES)
+    }
+}
+
+impl IdleDetector {
+    pub fn new(enter_frames: u32, exit_frames: u32) -> Self {
+        Self {
+            enter_frames: enter_frames.max(1),
+            exit_frames: exit_frames.max(1),
+            sample: Default::default(),
+            idle_run: 0,
+            busy_run: 0,
+            idle: false,
+            idle_frames: 0,
+        }
+    }
+
+    /// Set the number of consecutive idle frames before the guest is considered idle, and the
+    /// number of consecutive busy frames before it is considered busy again.
+    pub fn set_hysteresis(&mut self, enter_frames: u32, exit_frames: u32) {
+        self.enter_frames = enter_frames.max(1);
+        self.exit_frames = exit_frames.max(1);
+    }
+
+    pub fn hysteresis(&self) -> (u32, u32) {
+        (self.enter_frames, self.exit_frames)
+    }
+
+    /// Account for cycles executed by the CPU.
+    #[inline]
+    pub fn add_cycles(&mut self, cycles: u32, halted: bool) {
+        self.sample.cycles += cycles as u64;
+        if halted {
+            self.sample.halt_cycles += cycles as u64;
+        }
+    }
+
+    /// Account for idle-indicating calls made by the guest, such as keyboard polls.
+    pub fn add_idle_calls(&mut self, calls: u32) {
+        self.sample.idle_calls += calls;
+    }
+
+    /// Note that input was delivered to the guest during the current frame.
+    pub fn note_input(&mut self) {
+        self.sample.input = true;
+    }
+
+    /// Classify the frame that just ended and update the idle state. Frames in which no cycles
+    /// were executed (ie, the machine is paused) are ignored. Returns the new idle state if 
+    /// it changed.
+    pub fn frame_update(&mut self) -> Option<bool> {
+        let sample = std::mem::take(&mut self.sample);
+        if sample.cycles == 0 {
+            return None
+        }
+
+        if sample.is_idle() {
+            self.idle_run = self.idle_run.saturating_add(1);
+            self.busy_run = 0;
+        }
+        else {
+            self.busy_run = self.busy_run.saturating_add(1);
+            self.idle_run = 0;
+        }
+
+        let was_idle = self.idle;
+        if !self.idle && self.idle_run >= self.enter_frames {
+            self.idle = true;
+        }
+        else if self.idle && self.busy_run >= self.exit_frames {
+            self.idle = false;
+        }
+
+        if self.idle {
+            self.idle_frames += 1;
+        }
+        else {
+            self.idle_frames = 0;
+        }
+
+        if self.idle != was_idle {
+            Some(self.idle)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Return whether the guest is currently considered idle.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Return the number of frames the guest has been considered idle, or 0 if it is busy.
+    pub fn idle_frames(&self) -> u64 {
+        self.idle_frames
+    }
+
+    pub fn reset(&mut self) {
+        self.sample = Default::default();
+        self.idle_run = 0;
+        self.busy_run = 0;
+        self.idle = false;
+        self.idle_frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frame(detector: &mut IdleDetector, halted: bool, idle_calls: u32) -> Option<bool> {
+        detector.add_cycles(1000, halted);
+        detector.add_idle_calls(idle_calls);
+        detector.frame_update()
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut detector = IdleDetector::new(3, 2);
+
+        assert_eq!(run_frame(&mut detector, true, 0), None);
+        assert_eq!(run_frame(&mut detector, true, 0), None);
+        assert_eq!(run_frame(&mut detector, false, 5), Some(true));
+        assert!(detector.is_idle());
+
+        // A single busy frame does not end the idle state.
+        assert_eq!(run_frame(&mut detector, false, 0), None);
+        assert_eq!(run_frame(&mut detector, true, 0), None);
+        assert_eq!(detector.idle_frames(), 3);
+
+        assert_eq!(run_frame(&mut detector, false, 0), None);
+        assert_eq!(run_frame(&mut detector, false, 0), Some(false));
+        assert_eq!(detector.idle_frames(), 0);
+    }
+
+    #[test]
+    fn test_input_and_paused_frames() {
+        let mut detector = IdleDetector::new(2, 1);
+
+        // Polling the keyboard while keys are being delivered isn't idle.
+        detector.note_input();
+        assert_eq!(run_frame(&mut detector, false, 5), None);
+        assert_eq!(run_frame(&mut detector, false, 5), None);
+        assert!(!detector.is_idle());
+
+        // Frames without any cycles don't count.
+        assert_eq!(detector.frame_update(), None);
+        assert_eq!(run_frame(&mut detector, false, 5), Some(true));
+    }
+}
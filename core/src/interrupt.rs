@@ -26,7 +26,8 @@
 
     interrupt.rs
 
-    Interrupt logging routines.
+    Interrupt logging routines, and interrupt vector table inspection for
+    the interrupt monitor.
 */
 
 #![allow(dead_code)]
@@ -36,6 +37,8 @@ use log;
 use crate::cpu_808x::CpuRegisterState;
 use crate::bus::BusInterface;
 
+pub const IVT_ENTRIES: usize = 256;
+
 /// Function to log interrupt return values - called on return from interrupt (IRET)
 pub fn log_post_interrupt(int: u8, ah: u8, regs: &CpuRegisterState, bus: &mut BusInterface ) {
 
@@ -79,4 +82,133 @@ pub fn log_post_interrupt21(ah: u8, regs: &CpuRegisterState, bus: &mut BusInterf
             log::trace!("int21h: {:02X}", ah);
         }
     }
-}
\ No newline at end of file
+}
+/// Return the conventional name of the specified interrupt vector on an IBM PC 
+/// compatible, or None if the vector has no well-known use.
+pub fn vector_name(vector: u8) -> Option<&'static str> {
+    let name = match vector {
+        0x00 => "Divide Error",
+        0x01 => "Single Step",
+        0x02 => "NMI",
+        0x03 => "Breakpoint",
+        0x04 => "Overflow",
+        0x05 => "Print Screen",
+        0x08 => "IRQ0 Timer",
+        0x09 => "IRQ1 Keyboard",
+        0x0A => "IRQ2",
+        0x0B => "IRQ3 COM2",
+        0x0C => "IRQ4 COM1",
+        0x0D => "IRQ5 Hard Disk",
+        0x0E => "IRQ6 Floppy Disk",
+        0x0F => "IRQ7 Printer",
+        0x10 => "Video Services",
+        0x11 => "Equipment List",
+        0x12 => "Memory Size",
+        0x13 => "Disk Services",
+        0x14 => "Serial Services",
+        0x15 => "Cassette Services",
+        0x16 => "Keyboard Services",
+        0x17 => "Printer Services",
+        0x18 => "ROM BASIC",
+        0x19 => "Bootstrap Loader",
+        0x1A => "Time of Day",
+        0x1B => "Ctrl-Break",
+        0x1C => "User Timer Tick",
+        0x1D => "Video Parameters",
+        0x1E => "Diskette Parameters",
+        0x1F => "Graphics Characters",
+        0x20 => "DOS Terminate",
+        0x21 => "DOS Services",
+        0x22 => "DOS Terminate Address",
+        0x23 => "DOS Ctrl-C Handler",
+        0x24 => "DOS Critical Error",
+        0x25 => "DOS Absolute Read",
+        0x26 => "DOS Absolute Write",
+        0x27 => "DOS Terminate and Stay Resident",
+        0x28 => "DOS Idle",
+        0x29 => "DOS Fast Console Output",
+        0x2F => "DOS Multiplex",
+        0x33 => "Mouse Services",
+        0x41 => "Hard Disk 0 Parameters",
+        0x43 => "EGA Graphics Characters",
+        0x46 => "Hard Disk 1 Parameters",
+        0x67 => "EMS Services",
+        _ => return None
+    };
+    Some(name)
+}
+
+/// Describe the region of the address space an interrupt handler resides in. 
+/// Handlers in RAM usually indicate a vector hooked by DOS, a driver or a TSR.
+pub fn handler_region(segment: u16, offset: u16) -> &'static str {
+    let address = ((segment as usize) << 4).wrapping_add(offset as usize) & 0xFFFFF;
+    match address {
+        0x00000..=0x003FF => "IVT",
+        0x00400..=0x9FFFF => "RAM",
+        0xA0000..=0xBFFFF => "Video RAM",
+        0xC0000..=0xEFFFF => "Option ROM",
+        _ => "System ROM",
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct IvtEntry {
+    pub vector: u8,
+    pub segment: u16,
+    pub offset: u16,
+}
+
+/// Read the entire interrupt vector table from the bus.
+pub fn read_ivt(bus: &mut BusInterface) -> Vec<IvtEntry> {
+    (0..IVT_ENTRIES)
+        .map(|v| {
+            let (offset, _) = bus.read_u16(v * 4, 0).unwrap_or((0, 0));
+            let (segment, _) = bus.read_u16(v * 4 + 2, 0).unwrap_or((0, 0));
+            IvtEntry {
+                vector: v as u8,
+                segment,
+                offset,
+            }
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct IrqState {
+    pub irq: u8,
+    pub vector: u8,
+    pub masked: bool,
+    pub requested: bool,
+    pub in_service: bool,
+    pub request_count: u64,
+    pub serviced_count: u64,
+}
+
+/// A snapshot of the interrupt vector table and the state of each IRQ line of 
+/// the primary PIC, for display in the interrupt monitor.
+#[derive(Debug, Default)]
+pub struct InterruptMonitorState {
+    pub ivt: Vec<IvtEntry>,
+    pub irqs: Vec<IrqState>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_names() {
+        assert_eq!(vector_name(0x08), Some("IRQ0 Timer"));
+        assert_eq!(vector_name(0x21), Some("DOS Services"));
+        assert_eq!(vector_name(0x80), None);
+    }
+
+    #[test]
+    fn test_handler_region() {
+        assert_eq!(handler_region(0xF000, 0xFEA5), "System ROM");
+        assert_eq!(handler_region(0xC800, 0x0100), "Option ROM");
+        assert_eq!(handler_region(0x0070, 0x0123), "RAM");
+        // Segment:offset pairs past 1MB wrap around on the 8088
+        assert_eq!(handler_region(0xFFFF, 0x0100), "IVT");
+    }
+}
@@ -32,27 +32,61 @@
 
 pub mod devices;
 
+pub mod artifacts;
+pub mod assembler;
+pub mod automation;
+pub mod blep;
 pub mod breakpoints;
 pub mod bus;
 pub mod bytebuf;
 pub mod bytequeue;
+pub mod cartridge;
+pub mod codepage;
 pub mod config;
+pub mod config_validator;
+pub mod coverage;
+pub mod device_manager;
+pub mod disk_activity;
+pub mod disk_image;
+pub mod event_timeline;
 pub mod cpu_common;
 pub mod cpu_808x;
+pub mod floppy_image;
 pub mod floppy_manager;
+pub mod gdb_server;
+pub mod guest_os;
+pub mod heatmap;
+pub mod hotkey;
+pub mod gui_layout;
 pub mod file_util;
+pub mod idle;
 pub mod interrupt;
 pub mod machine;
 pub mod machine_manager;
 pub mod memerror;
+pub mod mem_search;
+pub mod monitor;
+pub mod movie;
+pub mod network;
+pub mod palette;
+pub mod paste;
+pub mod profiler;
+#[cfg(not(feature = "cpu_validator"))]
+pub mod rewind;
 pub mod rom_manager;
+pub mod savestate;
+pub mod scripting;
 pub mod sound;
+pub mod speed;
 pub mod syntax_token;
+pub mod trace_session;
 pub mod tracelogger;
 pub mod updatable;
 pub mod util;
+pub mod validation_preset;
 
 pub mod vhd;
+pub mod virtual_floppy;
 pub mod vhd_manager;
 pub mod videocard; // VideoCard trait
 pub mod input;
@@ -65,3 +99,9 @@ pub mod arduino8088_client;
 #[cfg(feature = "arduino_validator")]
 #[macro_use]
 pub mod arduino8088_validator;
+#[cfg(feature = "cpu_validator")]
+pub mod reference_validator;
+#[cfg(feature = "cpu_validator")]
+pub mod trace_validator;
+#[cfg(feature = "cpu_validator")]
+pub mod validator_case;
@@ -39,39 +39,91 @@ use std::{
     cell::Cell, 
     collections::VecDeque,
     fs::File,
-    io::{BufWriter, Write}
+    io::{BufWriter, Write},
+    path::Path
 };
 
 use crate::{
-    config::{ConfigFileParams, MachineType, VideoType, TraceMode},
+    config::{ConfigFileParams, CgaFontSelect, MachineType, VideoType, TraceFormat, TraceMode, HardDiskControllerType},
     breakpoints::BreakPointType,
-    bus::{BusInterface, ClockFactor, DeviceEvent, MEM_CP_BIT},
+    bus::{BuiltinDevices, BusInterface, ClockFactor, DeviceEvent, MEM_CP_BIT},
     devices::{
+        cga::CgaFont,
         pit::{self, PitDisplayState},
         pic::{PicStringState},
         ppi::{PpiStringState},
         dma::{DMAControllerStringState},
         fdc::{FloppyController},
         hdc::{HardDiskController},
+        xtide::{XtIdeController, DEFAULT_XTIDE_ROM_ADDRESS},
         mouse::Mouse,
+        adlib::ADLIB_VOLUME,
+        sb::SB_VOLUME,
+        timer_card::TIMER_CARD_DEFAULT_PORT,
+        rtc_card::{DateTime, RtcTimeSource, RTC_CARD_DEFAULT_PORT},
+        game_port::GamePort,
+        ne2000::{Ne2000, NE2000_DEFAULT_PORT, NE2000_DEFAULT_IRQ, NE2000_DEFAULT_MAC},
+        sn76489::{SN76489_PCJR_PORT, SN76489_VOLUME},
+        serial_bridge::TcpTarget,
     },
-    cpu_808x::{Cpu, CpuError, CpuAddress, StepResult, ServiceEvent },
-    cpu_common::{CpuType, CpuOption},
+    cpu_808x::{Cpu, CpuError, CpuAddress, Register16, StepResult, ServiceEvent, cycle_trace::CYCLE_TRACE_CSV_HEADER },
+    cartridge::Cartridge,
+    cpu_common::CpuOption,
+    codepage::Codepage,
+    guest_os::{GuestOs, GuestOsDetector},
+    network::slirp::SlirpBackend,
+    paste::{PasteQueue, DEFAULT_PASTE_DELAY_MS},
+    profiler::{ProfileReport, Profiler},
+    speed::{CpuClock, SpeedControl},
+    idle::{IdleDetector, DEFAULT_IDLE_ENTER_FRAMES, DEFAULT_IDLE_EXIT_FRAMES},
+    interrupt::{InterruptMonitorState, read_ivt},
+    blep::BlepSynth,
+    device_manager::IoConflict,
+    disk_activity::{DiskActivity, DriveSound, DRIVE_SOUND_VOLUME},
+    event_timeline::EventTimeline,
     machine_manager::{MachineDescriptor},
-    rom_manager::{RomManager, RawRomDescriptor},
-    sound::{BUFFER_MS, VOLUME_ADJUST, SoundPlayer},
+    memerror::MemError,
+    movie::{Movie, MovieEvent, MovieRecorder, state_checksum},
+    rom_manager::{
+        RomManager, 
+        RawRomDescriptor, 
+        scan_option_roms, 
+        BIOS_BOOTSTRAP_ADDRESS, 
+        OPTION_ROM_INIT_OFFSET, 
+        OPTION_ROM_SCAN_START, 
+        OPTION_ROM_SCAN_END
+    },
+    savestate::{SaveState, SaveStateError, StateFile, StateMigration, StateReader, StateWriter},
+    sound::{BUFFER_MS, VOLUME_ADJUST, DEFAULT_SAMPLE_RATE, SoundPlayer},
+    trace_session::TraceSessionManager,
     tracelogger::TraceLogger,
+    vhd::VirtualHardDisk,
     videocard::{VideoCard, VideoCardState},
 };
 
+#[cfg(not(feature = "cpu_validator"))]
+use crate::rewind::{MachineSnapshot, RewindBuffer, DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_BUFFER_LEN};
+#[cfg(feature = "cpu_validator")]
+use crate::{
+    config::ValidatorType,
+    trace_validator::{ReplayValidator, TraceRecorder},
+};
+
 use ringbuf::{RingBuffer, Producer, Consumer};
 
 pub const STEP_OVER_TIMEOUT: u32 = 320000;
 
 pub const NUM_HDDS: u32 = 2;
 
+/// Emulated time per call to run() with an unlimited CPU clock, until set by the frontend.
+pub const DEFAULT_UNLIMITED_FRAME_US: f64 = 1_000_000.0 / 60.0;
+
 pub const MAX_MEMORY_ADDRESS: usize = 0xFFFFF;
 
+/// The most cycles skipped at once while the CPU is halted, so that devices are still run in
+/// short steps.
+pub const HALT_SKIP_MAX_CYCLES: u32 = 256;
+
 #[derive(Copy, Clone, Debug)]
 pub enum MachineState {
     On,
@@ -183,11 +235,28 @@ impl ExecutionControl {
 pub struct PitData {
     buffer_consumer: Consumer<u8>,
     samples_produced: u64,
+    base_ticks_per_sample: f64,
     ticks_per_sample: f64,
     log_file: Option<Box<BufWriter<File>>>,
     logging_triggered: bool,
     fractional_part: f64,
-    next_sample_size: usize
+    next_sample_size: usize,
+    synth: BlepSynth,
+}
+
+/// Progress of the option ROM init calls made on behalf of BIOSes that don't scan for them.
+#[derive (Copy, Clone, PartialEq)]
+enum OptionRomInitState {
+    Pending,
+    Running,
+    Done
+}
+
+/// An input movie being recorded or played back.
+enum MovieState {
+    None,
+    Recording(MovieRecorder),
+    Playing(Movie),
 }
 
 #[allow(dead_code)]
@@ -197,7 +266,7 @@ pub struct Machine
     machine_desc: MachineDescriptor,
     state: MachineState,
     video_type: VideoType,
-    sound_player: SoundPlayer,
+    sound_player: Option<SoundPlayer>,
     rom_manager: RomManager,
     load_bios: bool,
     cpu: Cpu, 
@@ -205,12 +274,88 @@ pub struct Machine
     pit_data: PitData,
     debug_snd_file: Option<File>,
     kb_buf: VecDeque<u8>,
+    paste: Option<PasteQueue>,
+    paste_delay_ms: u32,
     error: bool,
     error_str: Option<String>,
     cpu_factor: ClockFactor,
     next_cpu_factor: ClockFactor,
+    unlimited_clock: bool,
+    unlimited_frame_us: f64,
+    tick_remainder: u64,
     cpu_cycles: u64,
     system_ticks: u64,
+    #[cfg(not(feature = "cpu_validator"))]
+    rewind: Option<RewindBuffer>,
+    idle: IdleDetector,
+    profiler: Profiler,
+    timeline: EventTimeline,
+    guest_os: GuestOsDetector,
+    codepage: Codepage,
+    speed: SpeedControl,
+    dram_refresh: bool,
+    dram_refresh_cadence: Option<u32>,
+    option_rom_init: bool,
+    option_rom_init_state: OptionRomInitState,
+    deterministic: bool,
+    halt_skip: bool,
+    movie: MovieState,
+    movie_frame: u64,
+    movie_result: Option<bool>,
+    patch_history: Vec<(usize, Vec<u8>)>,
+    disk_activity: DiskActivity,
+    drive_sound: Option<DriveSound>,
+}
+
+// Version 2 widened the clock factor to two 32-bit values to store clock ratios.
+fn migrate_machine_v1(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 2 {
+        return Err("missing clock factor".to_string())
+    }
+    let mut w = StateWriter::new();
+    w.write_u8(data[0]);
+    w.write_u32(data[1] as u32);
+    w.write_u32(0);
+    let mut v2 = w.into_vec();
+    v2.extend_from_slice(&data[2..]);
+    Ok(v2)
+}
+
+impl SaveState for Machine {
+    const STATE_ID: &'static str = "machine";
+    const STATE_VERSION: u16 = 2;
+
+    fn save_state(&self, w: &mut StateWriter) {
+        let (kind, a, b) = match self.cpu_factor {
+            ClockFactor::Divisor(n) => (0, n as u32, 0),
+            ClockFactor::Multiplier(n) => (1, n as u32, 0),
+            ClockFactor::Ratio(t, c) => (2, t, c),
+        };
+        w.write_u8(kind);
+        w.write_u32(a);
+        w.write_u32(b);
+        w.write_u64(self.cpu_cycles);
+        w.write_u64(self.system_ticks);
+        w.write_bytes(&self.kb_buf.iter().copied().collect::<Vec<u8>>());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cpu_factor = match (r.read_u8()?, r.read_u32()?, r.read_u32()?) {
+            (0, n, _) if n > 0 && n <= 0xFF => ClockFactor::Divisor(n as u8),
+            (1, n, _) if n > 0 && n <= 0xFF => ClockFactor::Multiplier(n as u8),
+            (2, t, c) if t > 0 && c > 0 => ClockFactor::Ratio(t, c),
+            _ => return Err(r.invalid("bad clock factor"))
+        };
+        self.next_cpu_factor = self.cpu_factor;
+        self.cpu_cycles = r.read_u64()?;
+        self.system_ticks = r.read_u64()?;
+        self.kb_buf = r.read_bytes()?.iter().copied().collect();
+        Ok(())
+    }
+
+    fn state_migrations() -> &'static [StateMigration] {
+        &[StateMigration { from_version: 1, migrate: migrate_machine_v1 }]
+    }
 }
 
 impl Machine {
@@ -220,8 +365,8 @@ impl Machine {
         machine_desc: MachineDescriptor,
         trace_mode: TraceMode,
         video_type: VideoType,
-        sound_player: SoundPlayer,
-        rom_manager: RomManager,
+        sound_player: Option<SoundPlayer>,
+        mut rom_manager: RomManager,
         ) -> Machine 
     {
 
@@ -241,6 +386,9 @@ impl Machine {
                     log::error!("Couldn't create specified CPU tracelog file: {}", filename);
                     eprintln!("Couldn't create specified CPU tracelog file: {}", filename);
                 }
+                else if trace_mode == TraceMode::Cycle && config.emulator.trace_format == TraceFormat::Csv {
+                    trace_logger.println(CYCLE_TRACE_CSV_HEADER);
+                }
             }
         }
 
@@ -267,8 +415,38 @@ impl Machine {
             }
         }            
 
+        // The replay validator reads a recorded trace, so is created here rather than by the CPU.
+        #[cfg(feature = "cpu_validator")]
+        let (validator_trace, replay_validator) = match config.validator.vtype {
+            Some(ValidatorType::Replay) => {
+                let validator = match &config.validator.replay_trace {
+                    Some(path) => ReplayValidator::from_file(path, validator_trace)
+                        .map_err(|e| log::error!("Failed to load validator trace: {}", e))
+                        .ok(),
+                    None => {
+                        log::error!("The Replay validator requires a replay_trace to be specified.");
+                        None
+                    }
+                };
+                (TraceLogger::None, validator)
+            }
+            _ => (validator_trace, None)
+        };
+
+        // A machine profile may change the CPU clocks of its motherboard.
+        let machine_desc = match &config.active_profile {
+            Some(profile) => profile.apply_to_descriptor(machine_desc),
+            None => machine_desc
+        };
+        let machine_desc = machine_desc.with_conventional_ram(config.machine.conventional_memory);
+        log::debug!("Conventional memory: {}K", machine_desc.conventional_ram / 1024);
+
+        // The config may override the CPU normally installed in this machine, ie with a V20.
+        let cpu_type = config.machine.cpu_type.unwrap_or(machine_desc.cpu_type);
+        log::debug!("Creating CPU of type {:?}", cpu_type);
+
         let mut cpu = Cpu::new(
-            CpuType::Intel8088,
+            cpu_type,
             trace_mode,
             trace_logger,
             #[cfg(feature = "cpu_validator")]
@@ -276,25 +454,66 @@ impl Machine {
             #[cfg(feature = "cpu_validator")]
             validator_trace
         );
+        cpu.set_trace_format(config.emulator.trace_format);
+
+        #[cfg(feature = "cpu_validator")]
+        {
+            if let Some(validator) = replay_validator {
+                if !cpu.set_validator(Box::new(validator)) {
+                    log::error!("Failed to init replay validator.");
+                }
+            }
+
+            // Record instructions that pass validation, to be replayed without the validator.
+            if let Some(path) = &config.validator.record_trace {
+                match File::create(path) {
+                    Ok(file) => {
+                        if let Some(validator) = cpu.take_validator() {
+                            log::debug!("Recording validator trace to {}", path.display());
+                            cpu.set_validator(Box::new(TraceRecorder::new(validator, file)));
+                        }
+                    }
+                    Err(e) => log::error!("Failed to create validator trace {}: {}", path.display(), e)
+                }
+            }
+
+            cpu.set_validator_mask_flags(config.validator.mask_flags);
+        }
 
         cpu.set_option(CpuOption::TraceLoggingEnabled(config.emulator.trace_on));
         cpu.set_option(CpuOption::OffRailsDetection(config.cpu.off_rails_detection)); 
+        if let Some(len) = config.cpu.instruction_history_len {
+            cpu.set_option(CpuOption::InstructionHistoryLen(len));
+        }
+        if let Some(adjust) = config.machine.dram_refresh_adjust {
+            cpu.set_option(CpuOption::DramRefreshAdjust(adjust));
+        }
 
         // Set up Ringbuffer for PIT channel #2 sampling for PC speaker
         let speaker_buf_size = ((pit::PIT_MHZ * 1_000_000.0) * (BUFFER_MS as f64 / 1000.0)) as usize;
         let speaker_buf: RingBuffer<u8> = RingBuffer::new(speaker_buf_size);
         let (speaker_buf_producer, speaker_buf_consumer) = speaker_buf.split();
-        let sample_rate = sound_player.sample_rate();
+        // Without a sound player, audio is generated at a nominal rate and discarded.
+        let sample_rate = sound_player.as_ref().map(|sp| sp.sample_rate()).unwrap_or(DEFAULT_SAMPLE_RATE);
         let pit_ticks_per_sample = (pit::PIT_MHZ * 1_000_000.0) / sample_rate as f64;
 
+        // Start at the configured emulation speed without ramping.
+        let mut speed = SpeedControl::default();
+        if let Some(initial_speed) = config.emulator.speed {
+            speed.set_immediate(initial_speed);
+        }
+        let speed_ticks_per_sample = pit_ticks_per_sample * speed.current();
+
         let pit_data = PitData {
             buffer_consumer: speaker_buf_consumer,
-            ticks_per_sample: pit_ticks_per_sample,
+            base_ticks_per_sample: pit_ticks_per_sample,
+            ticks_per_sample: speed_ticks_per_sample,
             samples_produced: 0,
             log_file: pit_output_file_option,
             logging_triggered: false,
-            fractional_part: pit_ticks_per_sample.fract(),
-            next_sample_size: pit_ticks_per_sample.trunc() as usize
+            fractional_part: speed_ticks_per_sample.fract(),
+            next_sample_size: speed_ticks_per_sample.trunc() as usize,
+            synth: BlepSynth::new(),
         };
 
         // open a file to write the sound to
@@ -309,12 +528,257 @@ impl Machine {
         }
 
         // Install devices
+        let builtin = BuiltinDevices {
+            fdc: config.machine.fdc_enabled.unwrap_or(true),
+            hdc: config.machine.hdc == HardDiskControllerType::Xebec,
+            serial: config.machine.serial_enabled.unwrap_or(true),
+            video: config.machine.video_enabled.unwrap_or(true),
+        };
         cpu.bus_mut().install_devices(
             video_type, 
             &machine_desc, 
             video_trace, 
-            config.emulator.video_frame_debug
+            config.emulator.video_frame_debug,
+            builtin
         );
+        if let Some(secondary_video) = config.machine.secondary_video {
+            cpu.bus_mut().install_secondary_video(secondary_video);
+        }
+
+        // Set any DIP switches specified by the machine profile
+        if let (Some(profile), Some(ppi)) = (&config.active_profile, cpu.bus_mut().ppi_mut()) {
+            ppi.set_dip_switches(profile.dip_sw1, profile.dip_sw2);
+        }
+
+        // Set the UART type and open any configured serial port bridges
+        if let Some(spc) = cpu.bus_mut().serial_mut() {
+            if let Some(uart) = config.machine.serial_uart {
+                spc.set_uart_type(uart);
+            }
+            for bridge in config.machine.serial_bridge.iter().flatten() {
+                let port = match bridge.port {
+                    1 | 2 => (bridge.port - 1) as usize,
+                    _ => {
+                        log::error!("Invalid serial bridge port: COM{}", bridge.port);
+                        continue
+                    }
+                };
+                let result = match (&bridge.host, &bridge.tcp_connect, bridge.tcp_listen) {
+                    (Some(host), _, _) => spc.bridge_port(port, host.clone()),
+                    (None, Some(addr), _) => spc.bridge_tcp(port, &TcpTarget::Connect(addr.clone()), bridge.telnet),
+                    (None, None, Some(listen)) => spc.bridge_tcp(port, &TcpTarget::Listen(listen), bridge.telnet),
+                    (None, None, None) => Err(anyhow::anyhow!("no host port or TCP address specified")),
+                };
+                if let Err(e) = result {
+                    log::error!("Failed to bridge COM{}: {}", bridge.port, e);
+                }
+            }
+        }
+
+        // Set the interval for writing hard disk image changes back to disk
+        if let Some(interval) = config.machine.hdd_flush_interval {
+            if let Some(hdc) = cpu.bus_mut().hdc_mut() {
+                hdc.set_flush_interval(interval);
+            }
+        }
+
+        // Set the type of each floppy drive
+        if let Some(fdc) = cpu.bus_mut().fdc_mut() {
+            fdc.set_drive_type(0, config.machine.floppy0_type);
+            fdc.set_drive_type(1, config.machine.floppy1_type);
+            fdc.set_write_protect(0, config.machine.floppy0_write_protect.unwrap_or(false));
+            fdc.set_write_protect(1, config.machine.floppy1_write_protect.unwrap_or(false));
+        }
+
+        // Configure video card memory and wait states
+        if let Some(video_memory) = config.machine.video_memory {
+            cpu.bus_mut().set_video_memory_size(video_memory as usize * 1024);
+        }
+        cpu.bus_mut().set_video_wait_states(config.machine.video_wait_states.unwrap_or(true));
+
+        // Load the CGA font from a character ROM dump. Without one, only the built-in thick font
+        // is available.
+        let cga_font = config.machine.cga_font.unwrap_or_default();
+        match &config.machine.cga_font_rom {
+            Some(rom_path) => match std::fs::read(rom_path) {
+                Ok(rom) => match CgaFont::from_rom(&rom, cga_font) {
+                    Ok(font) => {
+                        log::debug!("Loaded {:?} CGA font from {}", cga_font, rom_path.display());
+                        cpu.bus_mut().set_cga_font(font);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to load CGA font ROM {}: {}", rom_path.display(), e);
+                    }
+                },
+                Err(e) => {
+                    log::error!("Failed to read CGA font ROM {}: {}", rom_path.display(), e);
+                }
+            },
+            None if cga_font == CgaFontSelect::Thin => {
+                log::warn!("The thin CGA font requires a character ROM set with 'cga_font_rom'. Using the built-in font.");
+            }
+            None => {}
+        }
+
+        // Add any configured slow memory regions
+        if let Some(regions) = &config.machine.wait_state_regions {
+            for region in regions {
+                log::debug!(
+                    "Adding wait state region at {:05X}, size: {:05X}, wait states: {}", 
+                    region.address, 
+                    region.size, 
+                    region.wait_states
+                );
+                cpu.bus_mut().add_wait_region(region.address as usize, region.size as usize, region.wait_states);
+            }
+        }
+
+        // Add any configured slow IO port ranges
+        if let Some(regions) = &config.machine.io_wait_state_regions {
+            for region in regions {
+                log::debug!(
+                    "Adding IO wait states at port {:04X}, size: {:04X}, wait states: {}", 
+                    region.port, 
+                    region.size, 
+                    region.wait_states
+                );
+                cpu.bus_mut().add_io_wait_ports(region.port, region.size, region.wait_states);
+            }
+        }
+
+        // Install optional AdLib card
+        if config.machine.adlib {
+            cpu.bus_mut().install_adlib(sample_rate);
+        }
+
+        // Install optional Sound Blaster card
+        if config.machine.sound_blaster {
+            cpu.bus_mut().install_sound_blaster();
+        }
+
+        // Install optional high resolution timer card
+        if config.machine.timer_card {
+            cpu.bus_mut().install_timer_card(config.machine.timer_card_port.unwrap_or(TIMER_CARD_DEFAULT_PORT));
+        }
+
+        // Install optional clock card
+        if config.machine.rtc_card {
+            let host_time = RtcTimeSource::Host(config.machine.rtc_card_utc_offset.unwrap_or(0));
+            let source = match &config.machine.rtc_card_time {
+                Some(text) => match DateTime::parse(text) {
+                    Some(datetime) => RtcTimeSource::Fixed(datetime),
+                    None => {
+                        log::error!("Invalid clock card time '{}': expected YYYY-MM-DD HH:MM:SS. Using host time.", text);
+                        host_time
+                    }
+                },
+                None => host_time,
+            };
+            cpu.bus_mut().install_rtc_card(config.machine.rtc_card_port.unwrap_or(RTC_CARD_DEFAULT_PORT), source);
+        }
+
+        // Configure the address space and install the optional A20 gate
+        if let Some(mask) = config.machine.address_mask {
+            if mask >= 0xFFFFF && mask < 0x1000000 && (mask + 1).is_power_of_two() {
+                cpu.bus_mut().set_address_mask(mask as usize);
+            }
+            else {
+                log::error!("Invalid address mask {:X}: must cover 20 to 24 address lines.", mask);
+            }
+        }
+        if config.machine.a20_gate {
+            cpu.bus_mut().install_a20_gate();
+        }
+
+        // Install optional game port
+        if config.machine.game_port {
+            cpu.bus_mut().install_game_port();
+        }
+
+        // Install optional NE2000 network card, connected to the user-mode NAT backend
+        if config.machine.ne2000 {
+            cpu.bus_mut().install_ne2000(
+                config.machine.ne2000_port.unwrap_or(NE2000_DEFAULT_PORT),
+                config.machine.ne2000_irq.unwrap_or(NE2000_DEFAULT_IRQ),
+                NE2000_DEFAULT_MAC,
+                Box::new(SlirpBackend::new())
+            );
+        }
+
+        // The PCjr has a built-in SN76489 sound generator and two cartridge slots.
+        if machine_desc.machine_type == MachineType::IBM_PCJR_4860 {
+            cpu.bus_mut().install_sn76489(SN76489_PCJR_PORT, sample_rate);
+
+            for cart_path in config.machine.cartridges.iter().flatten() {
+                match std::fs::read(cart_path) {
+                    Ok(bytes) => match Cartridge::from_bytes(&bytes) {
+                        Ok(cart) => {
+                            log::debug!("Loading cartridge {} at {:05X}", cart_path.display(), cart.address);
+                            rom_manager.add_cartridge(cart.rom, cart.address);
+                        }
+                        Err(e) => {
+                            log::error!("Failed to load cartridge {}: {}", cart_path.display(), e);
+                        }
+                    },
+                    Err(e) => {
+                        log::error!("Failed to read cartridge {}: {}", cart_path.display(), e);
+                    }
+                }
+            }
+        }
+        else if config.machine.cartridges.is_some() {
+            log::warn!("Cartridges are only supported by the PCjr and will not be loaded.");
+        }
+
+        // Install optional XT-IDE controller and its BIOS
+        if let HardDiskControllerType::XtIde = config.machine.hdc {
+            cpu.bus_mut().install_xtide();
+
+            match &config.machine.xtide_rom {
+                Some(rom_path) => match std::fs::read(rom_path) {
+                    Ok(rom) => {
+                        let address = config.machine.xtide_rom_address.unwrap_or(DEFAULT_XTIDE_ROM_ADDRESS);
+                        log::debug!("Loading XT-IDE ROM {} at {:05X}", rom_path.display(), address);
+                        rom_manager.add_option_rom(rom, address);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read XT-IDE ROM {}: {}", rom_path.display(), e);
+                    }
+                },
+                None => {
+                    log::warn!("XT-IDE controller installed without a ROM. Drives will not be visible to DOS.");
+                }
+            }
+        }
+
+        // All devices are now installed, so report any that claimed the same IO ports.
+        for conflict in cpu.bus().io_conflicts() {
+            log::error!("{}", conflict);
+        }
+
+        // Load option ROM images mapped by the config
+        if let Some(option_roms) = &config.machine.option_roms {
+            for option_rom in option_roms {
+                match std::fs::read(&option_rom.path) {
+                    Ok(rom) => {
+                        let address = (option_rom.segment as u32) << 4;
+                        log::debug!("Loading option ROM {} at {:05X}", option_rom.path.display(), address);
+                        rom_manager.add_option_rom(rom, address);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read option ROM {}: {}", option_rom.path.display(), e);
+                    }
+                }
+            }
+        }
+
+        // BIOSes that predate option ROMs won't run them, so the machine calls their init 
+        // routines itself before the BIOS bootstraps.
+        let option_rom_init = !config.emulator.no_bios 
+            && config.machine.option_rom_init.unwrap_or(!rom_manager.bios_scans_option_roms());
+        if option_rom_init {
+            log::debug!("Option ROMs will be initialized by the emulator at bootstrap.");
+        }
 
         // Load BIOS ROM images unless config option suppressed rom loading
         if !config.emulator.no_bios {
@@ -330,7 +794,7 @@ impl Machine {
             cpu.set_reset_vector(CpuAddress::Segmented(rom_entry_point.0, rom_entry_point.1));
         }
 
-        // Set CPU clock divisor/multiplier
+        // Set CPU clock divisor/multiplier. A configured clock overrides the turbo setting.
         let cpu_factor;
         if config.machine.turbo { 
             cpu_factor = machine_desc.cpu_turbo_factor;
@@ -338,9 +802,54 @@ impl Machine {
         else {
             cpu_factor = machine_desc.cpu_factor;
         }
+        let cpu_clock = config.machine.cpu_clock;
+        let cpu_factor = cpu_clock
+            .and_then(|clock| clock.factor(machine_desc.system_crystal))
+            .unwrap_or(cpu_factor);
 
         cpu.reset();
 
+        // Create the rewind buffer, if enabled
+        #[cfg(not(feature = "cpu_validator"))]
+        let rewind = match config.emulator.rewind {
+            true => Some(RewindBuffer::new(
+                config.emulator.rewind_interval.unwrap_or(DEFAULT_REWIND_INTERVAL),
+                config.emulator.rewind_buffer_len.map_or(DEFAULT_REWIND_BUFFER_LEN, |len| len as usize)
+            )),
+            false => None
+        };
+        #[cfg(feature = "cpu_validator")]
+        if config.emulator.rewind {
+            log::warn!("Rewind is not available when the CPU validator is enabled.");
+        }
+
+        let movie = match (&config.emulator.play_movie, &config.emulator.record_movie) {
+            (Some(path), _) => match Movie::from_file(path) {
+                Ok(movie) => {
+                    if movie.machine_type != machine_type {
+                        log::warn!("Movie {} was recorded on a {:?}; playback will likely desync.", path.display(), movie.machine_type);
+                    }
+                    log::info!("Playing movie: {} ({} events)", path.display(), movie.events.len());
+                    MovieState::Playing(movie)
+                }
+                Err(e) => {
+                    log::error!("Failed to load movie {}: {}", path.display(), e);
+                    MovieState::None
+                }
+            },
+            (None, Some(path)) => match MovieRecorder::new(path, machine_type) {
+                Ok(recorder) => {
+                    log::info!("Recording movie: {}", path.display());
+                    MovieState::Recording(recorder)
+                }
+                Err(e) => {
+                    log::error!("Failed to create movie {}: {}", path.display(), e);
+                    MovieState::None
+                }
+            },
+            (None, None) => MovieState::None
+        };
+
         Machine {
             machine_type,
             machine_desc,
@@ -354,12 +863,44 @@ impl Machine {
             pit_data,
             debug_snd_file: None,
             kb_buf: VecDeque::new(),
+            paste: None,
+            paste_delay_ms: config.input.paste_delay_ms.unwrap_or(DEFAULT_PASTE_DELAY_MS),
             error: false,
             error_str: None,
             cpu_factor,
             next_cpu_factor: cpu_factor,
+            unlimited_clock: cpu_clock == Some(CpuClock::Unlimited),
+            unlimited_frame_us: DEFAULT_UNLIMITED_FRAME_US,
+            tick_remainder: 0,
             cpu_cycles: 0,
-            system_ticks: 0
+            system_ticks: 0,
+            #[cfg(not(feature = "cpu_validator"))]
+            rewind,
+            idle: IdleDetector::new(
+                config.emulator.idle_enter_frames.unwrap_or(DEFAULT_IDLE_ENTER_FRAMES),
+                config.emulator.idle_exit_frames.unwrap_or(DEFAULT_IDLE_EXIT_FRAMES)
+            ),
+            profiler: Profiler::new(),
+            timeline: Default::default(),
+            guest_os: Default::default(),
+            codepage: config.emulator.codepage.unwrap_or_default(),
+            speed,
+            // The PCjr's video gate array refreshes memory, so there is no DMA refresh to simulate.
+            dram_refresh: config.machine.dram_refresh.unwrap_or(machine_desc.machine_type != MachineType::IBM_PCJR_4860),
+            dram_refresh_cadence: config.machine.dram_refresh_cadence.filter(|c| *c > 0),
+            option_rom_init,
+            option_rom_init_state: match option_rom_init {
+                true => OptionRomInitState::Pending,
+                false => OptionRomInitState::Done
+            },
+            deterministic: config.deterministic(),
+            halt_skip: config.cpu.halt_skip,
+            movie,
+            movie_frame: 0,
+            movie_result: None,
+            patch_history: Vec::new(),
+            disk_activity: DiskActivity::default(),
+            drive_sound: config.emulator.drive_sounds.then(|| DriveSound::new(sample_rate)),
         }
     }
 
@@ -428,10 +969,70 @@ impl Machine {
         self.cpu.bus_mut().video_mut()
     }
 
+    pub fn secondary_videocard(&mut self) -> Option<Box<&mut dyn VideoCard>> {
+        self.cpu.bus_mut().secondary_video_mut()
+    }
+
     pub fn cpu(&self) -> &Cpu {
         &self.cpu
     }
 
+    /// Set a CPU register between instructions. Writing CS or IP redirects execution, so the
+    /// prefetch queue is flushed.
+    pub fn set_cpu_register16(&mut self, reg: Register16, value: u16) {
+        match reg {
+            Register16::CS => self.cpu.inject_jump(value, self.cpu.get_register16(Register16::IP)),
+            Register16::IP => self.cpu.inject_jump(self.cpu.get_register16(Register16::CS), value),
+            _ => self.cpu.set_register16(reg, value)
+        }
+    }
+
+    pub fn set_cpu_flags(&mut self, flags: u16) {
+        self.cpu.set_flags(flags);
+    }
+
+    /// Enable or disable the profiler. Disabling the profiler keeps the data recorded so far.
+    pub fn set_profiler_enabled(&mut self, state: bool) {
+        self.profiler.set_enabled(state);
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    pub fn profile_report(&self, limit: usize) -> ProfileReport {
+        self.profiler.report(limit)
+    }
+
+    pub fn clear_profiler(&mut self) {
+        self.profiler.clear();
+    }
+
+    pub fn reset_validator_stats(&mut self) {
+        self.cpu.reset_validator_stats();
+    }
+
+    pub fn trace_sessions(&self) -> &TraceSessionManager {
+        self.cpu.trace_sessions()
+    }
+
+    pub fn trace_sessions_mut(&mut self) -> &mut TraceSessionManager {
+        self.cpu.trace_sessions_mut()
+    }
+
+    pub fn timeline(&self) -> &EventTimeline {
+        &self.timeline
+    }
+
+    /// Start or stop recording peripheral activity to the event timeline.
+    pub fn set_timeline_enabled(&mut self, enabled: bool) {
+        self.timeline.set_enabled(self.cpu.bus(), enabled);
+    }
+
+    pub fn clear_timeline(&mut self) {
+        self.timeline.clear();
+    }
+
     /// Set a CPU option. Avoids needing to borrow CPU.
     pub fn set_cpu_option(&mut self, opt: CpuOption) {
         self.cpu.set_option(opt);
@@ -462,6 +1063,41 @@ impl Machine {
             ClockFactor::Multiplier(n) => {
                 self.machine_desc.system_crystal * (n as f64)
             }
+            ClockFactor::Ratio(t, c) => {
+                self.machine_desc.system_crystal * (c as f64) / (t as f64)
+            }
+        }
+    }
+
+    /// Set the CPU clock. The new clock takes effect at the start of the next call to run().
+    /// With CpuClock::Unlimited, the CPU runs the full cycle target passed to run() in the
+    /// time set by set_unlimited_frame_time(), so frontends may run as many cycles as the 
+    /// host can manage.
+    pub fn set_cpu_clock(&mut self, clock: CpuClock) {
+        self.unlimited_clock = clock == CpuClock::Unlimited;
+        if let Some(factor) = clock.factor(self.machine_desc.system_crystal) {
+            self.next_cpu_factor = factor;
+        }
+        log::debug!("Set CPU clock to: {} New cpu factor is {:?}", clock, self.next_cpu_factor);
+    }
+
+    /// Return the selected CPU clock, or None if the CPU factor is not one of the presets.
+    pub fn cpu_clock(&self) -> Option<CpuClock> {
+        match self.unlimited_clock {
+            true => Some(CpuClock::Unlimited),
+            false => CpuClock::from_factor(self.next_cpu_factor, self.machine_desc.system_crystal)
+        }
+    }
+
+    /// Set the emulated time, in microseconds, that each call to run() represents with an
+    /// unlimited CPU clock. This is normally one frame at the current emulation speed.
+    pub fn set_unlimited_frame_time(&mut self, frame_us: f64) {
+        // Host frame timing must not affect a deterministic machine.
+        if self.deterministic {
+            return
+        }
+        if frame_us.is_finite() && frame_us > 0.0 {
+            self.unlimited_frame_us = frame_us;
         }
     }
 
@@ -471,7 +1107,10 @@ impl Machine {
     /// We must be careful not to update this between step() and run_devices() or devices' 
     /// advance_ticks may overflow device update ticks.
     pub fn set_turbo_mode(&mut self, state: bool) {
-        
+        if !self.accept_input(MovieEvent::Turbo(state)) {
+            return
+        }
+        self.unlimited_clock = false;
         if state {
             self.next_cpu_factor = self.machine_desc.cpu_turbo_factor;
         }
@@ -481,6 +1120,17 @@ impl Machine {
         log::debug!("Set turbo mode to: {} New cpu factor is {:?}", state, self.next_cpu_factor);
     }
 
+    /// Enable or disable skipping ahead while the CPU is halted. When disabled, every halted
+    /// cycle is stepped individually, which is slower but keeps interrupt latency cycle-exact.
+    pub fn set_halt_skip(&mut self, state: bool) {
+        self.halt_skip = state;
+    }
+
+    /// Return whether the CPU is running at the machine's turbo clock.
+    pub fn turbo_mode(&self) -> bool {
+        !self.unlimited_clock && self.next_cpu_factor == self.machine_desc.cpu_turbo_factor
+    }
+
     pub fn fdc(&mut self) -> &mut Option<FloppyController> {
         self.cpu.bus_mut().fdc_mut()
     }
@@ -489,6 +1139,40 @@ impl Machine {
         self.cpu.bus_mut().hdc_mut()
     }
 
+    pub fn xtide(&mut self) -> &mut Option<XtIdeController> {
+        self.cpu.bus_mut().xtide_mut()
+    }
+
+    /// Return the IO ports claimed by more than one device when the machine was built.
+    pub fn io_conflicts(&self) -> &[IoConflict] {
+        self.cpu.bus().io_conflicts()
+    }
+
+    /// Mount a hard disk image on the installed hard disk controller. The XT-IDE controller is 
+    /// used if installed, otherwise the Xebec controller.
+    pub fn mount_vhd(&mut self, device_id: usize, vhd: VirtualHardDisk) -> Result<(), String> {
+        if let Some(xtide) = self.cpu.bus_mut().xtide_mut() {
+            return xtide.set_vhd(device_id, vhd).map_err(|e| e.to_string())
+        }
+        match self.cpu.bus_mut().hdc_mut() {
+            Some(hdc) => hdc.set_vhd(device_id, vhd).map_err(|e| e.to_string()),
+            None => Err("No Hard Disk Controller present!".to_string())
+        }
+    }
+
+    /// Write any pending floppy and hard disk image changes to disk. Should be called before exiting.
+    pub fn flush_disks(&mut self) {
+        if let Some(fdc) = self.cpu.bus_mut().fdc_mut() {
+            fdc.flush();
+        }
+        if let Some(hdc) = self.cpu.bus_mut().hdc_mut() {
+            hdc.flush();
+        }
+        if let Some(xtide) = self.cpu.bus_mut().xtide_mut() {
+            xtide.flush();
+        }
+    }
+
     pub fn cpu_cycles(&self) -> u64 {
         self.cpu_cycles
     }
@@ -525,6 +1209,55 @@ impl Machine {
         self.cpu.bus_mut().pic_mut().as_mut().unwrap().get_string_state()
     }
 
+    /// Patch memory with the specified bytes from the debugger. The bytes that were replaced 
+    /// are kept so that the patch can be undone. If any byte can't be written, for example 
+    /// because it is ROM, memory is left unchanged.
+    pub fn patch_memory(&mut self, address: usize, bytes: &[u8]) -> Result<(), MemError> {
+        let bus = self.cpu.bus_mut();
+        if address + bytes.len() > bus.size() {
+            return Err(MemError::SeekOutOfBoundsError)
+        }
+        let original = bus.get_slice_at(address, bytes.len()).to_vec();
+
+        for (i, byte) in bytes.iter().enumerate() {
+            if let Err(e) = bus.edit_u8(address + i, *byte) {
+                for (j, old) in original[..i].iter().enumerate() {
+                    let _ = bus.edit_u8(address + j, *old);
+                }
+                return Err(e)
+            }
+        }
+
+        self.patch_history.push((address, original));
+        Ok(())
+    }
+
+    /// Undo the most recent memory patch, returning the address it was made at.
+    pub fn undo_patch(&mut self) -> Option<usize> {
+        let (address, original) = self.patch_history.pop()?;
+        let bus = self.cpu.bus_mut();
+        for (i, byte) in original.iter().enumerate() {
+            let _ = bus.edit_u8(address + i, *byte);
+        }
+        Some(address)
+    }
+
+    /// Return the number of memory patches that can be undone.
+    pub fn patch_count(&self) -> usize {
+        self.patch_history.len()
+    }
+
+    /// Return the current interrupt vector table and the state of each IRQ of the
+    /// primary PIC.
+    pub fn interrupt_state(&mut self) -> InterruptMonitorState {
+        let bus = self.cpu.bus_mut();
+        let irqs = bus.pic().as_ref().map(|pic| pic.get_irq_states()).unwrap_or_default();
+        InterruptMonitorState {
+            ivt: read_ivt(bus),
+            irqs,
+        }
+    }
+
     pub fn ppi_state(&mut self) -> Option<PpiStringState> {
 
         if let Some(ppi) = self.cpu.bus_mut().ppi_mut() {
@@ -544,6 +1277,11 @@ impl Machine {
         // TODO: Handle secondary DMA if present.
         self.cpu.bus_mut().dma_mut().as_mut().unwrap().get_string_state()
     }
+
+    /// Return the number of bytes transferred on each DMA channel.
+    pub fn dma_transfer_counts(&self) -> [u64; 4] {
+        self.cpu.bus().dma().as_ref().map(|dma| dma.get_transfer_counts()).unwrap_or_default()
+    }
     
     pub fn videocard_state(&mut self) -> Option<VideoCardState> {
         if let Some(video_card) = self.cpu.bus_mut().video_mut() {
@@ -562,17 +1300,52 @@ impl Machine {
 
     /// Enter a keypress scancode into the keyboard buffer.
     pub fn key_press(&mut self, code: u8) {
+        if !self.accept_input(MovieEvent::KeyDown(code)) {
+            return
+        }
+        self.idle.note_input();
         self.kb_buf.push_back(code);
     }
 
     /// Enter a key release scancode into the keyboard buffer.
     pub fn key_release(&mut self, code: u8 ) {
+        if !self.accept_input(MovieEvent::KeyUp(code)) {
+            return
+        }
         // HO Bit set converts a scancode into its 'release' code
         self.kb_buf.push_back(code | 0x80);
     }
 
+    /// Type text into the keyboard, replacing any text still being typed. Returns the number
+    /// of characters that could not be typed and were skipped.
+    pub fn paste_text(&mut self, text: &str) -> usize {
+        if !self.accept_input(MovieEvent::Paste(text.to_string())) {
+            return 0
+        }
+        let (paste, skipped) = PasteQueue::new(text, self.codepage, self.paste_delay_ms);
+        self.idle.note_input();
+        self.paste = Some(paste);
+        skipped
+    }
+
+    /// Stop typing pasted text.
+    pub fn cancel_paste(&mut self) {
+        if !self.accept_input(MovieEvent::CancelPaste) {
+            return
+        }
+        self.paste = None;
+    }
+
+    /// Return the number of scancodes left to type from pasted text.
+    pub fn paste_remaining(&self) -> usize {
+        self.paste.as_ref().map_or(0, |paste| paste.len())
+    }
+
     /// Simulate the user pressing control-alt-delete.
     pub fn ctrl_alt_del(&mut self) {
+        if !self.accept_input(MovieEvent::CtrlAltDel) {
+            return
+        }
         self.kb_buf.push_back(0x1D); // Left-control
         self.kb_buf.push_back(0x38); // Left-alt
         self.kb_buf.push_back(0x53); // Delete
@@ -589,26 +1362,460 @@ impl Machine {
         self.cpu.bus_mut().mouse_mut()
     }
 
-    pub fn bridge_serial_port(&mut self, port_num: usize, port_name: String) {
-
-        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
-            if let Err(e) = spc.bridge_port(port_num, port_name) {
-                log::error!("Failed to bridge serial port: {}", e );
-            }
+    /// Send a mouse update to the mouse, if one is installed. Returns false if there is no mouse.
+    pub fn mouse_update(&mut self, l_button_pressed: bool, r_button_pressed: bool, delta_x: f64, delta_y: f64) -> bool {
+        if self.mouse_mut().is_none() {
+            return false
         }
-        else {
-            log::error!("No serial port controller present!");
+        let event = MovieEvent::Mouse { l: l_button_pressed, r: r_button_pressed, dx: delta_x, dy: delta_y };
+        if self.accept_input(event) {
+            if let Some(mouse) = self.mouse_mut() {
+                mouse.update(l_button_pressed, r_button_pressed, delta_x, delta_y);
+            }
         }
+        true
     }
 
-    pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
+    /// Return whether the machine runs deterministically. The frontend must then run a fixed
+    /// number of cycles per frame.
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Offer a host input event to the movie, if any. Returns false if a movie is being played
+    /// back, in which case the host's input must be ignored. Events the machine doesn't apply
+    /// itself, such as loading a floppy image, should be offered by the frontend before it 
+    /// applies them.
+    pub fn accept_input(&mut self, event: MovieEvent) -> bool {
+        match &mut self.movie {
+            MovieState::None => true,
+            MovieState::Recording(recorder) => {
+                if let Err(e) = recorder.record(self.movie_frame, &event) {
+                    log::error!("Failed to record movie event: {}", e);
+                    self.movie = MovieState::None;
+                }
+                true
+            }
+            MovieState::Playing(_) => false
+        }
+    }
+
+    /// Return whether a movie is being played back.
+    pub fn movie_playing(&self) -> bool {
+        matches!(self.movie, MovieState::Playing(_))
+    }
+
+    /// Return the result of movie playback once it has finished: true if the machine reached 
+    /// the recorded state, false if playback desynced.
+    pub fn movie_result(&self) -> Option<bool> {
+        self.movie_result
+    }
+
+    /// Stop recording and write the end of the movie. Does nothing if no movie is recording.
+    pub fn finish_movie(&mut self) {
+        if let MovieState::Recording(recorder) = std::mem::replace(&mut self.movie, MovieState::None) {
+            let checksum = self.state_checksum();
+            match recorder.finish(self.movie_frame, checksum) {
+                Ok(()) => log::info!("Movie recording finished at frame {}.", self.movie_frame),
+                Err(e) => log::error!("Failed to finish movie: {}", e)
+            }
+        }
+    }
+
+    /// Stop any movie being recorded or played back, because the machine's state was changed 
+    /// in a way a movie can't reproduce.
+    fn cancel_movie(&mut self, reason: &str) {
+        if !matches!(self.movie, MovieState::None) {
+            log::warn!("Movie stopped: {}", reason);
+            self.finish_movie();
+            self.movie = MovieState::None;
+        }
+    }
+
+    fn state_checksum(&self) -> u64 {
+        let bus = self.cpu.bus();
+        state_checksum(bus.get_slice_at(0, bus.size()), self.cpu_cycles)
+    }
+
+    /// Advance the movie by one frame. During playback, this applies the events recorded for
+    /// the frame and checks the machine's state at the end of the movie.
+    fn movie_frame_update(&mut self) {
+        let frame = self.movie_frame;
+        self.movie_frame += 1;
+
+        // Take the movie so that its events are applied like host input.
+        let MovieState::Playing(mut movie) = std::mem::replace(&mut self.movie, MovieState::None) else {
+            return
+        };
+        for event in movie.take_events(frame) {
+            self.apply_movie_event(event);
+        }
+
+        if !movie.is_finished(frame) {
+            self.movie = MovieState::Playing(movie);
+            return
+        }
+        let synced = match movie.end {
+            Some((_, checksum)) => {
+                let state = self.state_checksum();
+                if state != checksum {
+                    log::error!("Movie desynced: state checksum at frame {} is {:016X}, expected {:016X}", frame, state, checksum);
+                }
+                state == checksum
+            }
+            // Without an end, there's nothing to check against.
+            None => true
+        };
+        log::info!("Movie playback finished at frame {}.", frame);
+        self.movie_result = Some(synced);
+    }
+
+    fn apply_movie_event(&mut self, event: MovieEvent) {
+        match event {
+            MovieEvent::KeyDown(code) => self.key_press(code),
+            MovieEvent::KeyUp(code) => self.key_release(code),
+            MovieEvent::Mouse { l, r, dx, dy } => {
+                self.mouse_update(l, r, dx, dy);
+            }
+            MovieEvent::Paste(text) => {
+                self.paste_text(&text);
+            }
+            MovieEvent::CancelPaste => self.cancel_paste(),
+            MovieEvent::CtrlAltDel => self.ctrl_alt_del(),
+            MovieEvent::Reset => self.reset(),
+            MovieEvent::Turbo(state) => self.set_turbo_mode(state),
+            MovieEvent::LoadFloppy(drive_select, path) => {
+                let result = std::fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|vec| match self.fdc() {
+                        Some(fdc) => {
+                            fdc.load_image_from(drive_select, vec)?;
+                            fdc.set_image_path(drive_select, Some(path.clone()));
+                            Ok(())
+                        }
+                        None => Err("no floppy controller".to_string())
+                    });
+                if let Err(e) = result {
+                    log::error!("Movie failed to load floppy image {}: {}", path.display(), e);
+                }
+            }
+            MovieEvent::EjectFloppy(drive_select) => {
+                if let Some(fdc) = self.fdc() {
+                    fdc.unload_image(drive_select);
+                }
+            }
+        }
+    }
+
+    pub fn game_port_mut(&mut self) -> &mut Option<GamePort> {
+        self.cpu.bus_mut().game_port_mut()
+    }
+
+    pub fn ne2000_mut(&mut self) -> &mut Option<Ne2000> {
+        self.cpu.bus_mut().ne2000_mut()
+    }
+
+    pub fn bridge_serial_port(&mut self, port_num: usize, port_name: String) {
+
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if let Err(e) = spc.bridge_port(port_num, port_name) {
+                log::error!("Failed to bridge serial port: {}", e );
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    pub fn bridge_serial_tcp(&mut self, port_num: usize, target: TcpTarget, telnet: bool) {
+
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            if let Err(e) = spc.bridge_tcp(port_num, &target, telnet) {
+                log::error!("Failed to bridge serial port: {}", e );
+            }
+        }
+        else {
+            log::error!("No serial port controller present!");
+        }
+    }
+
+    pub fn unbridge_serial_port(&mut self, port_num: usize) {
+        if let Some(spc) = self.cpu.bus_mut().serial_mut() {
+            spc.unbridge_port(port_num);
+        }
+    }
+
+    /// Return a description of the bridge attached to the specified serial port, if any.
+    pub fn serial_bridge_description(&mut self, port_num: usize) -> Option<String> {
+        self.cpu.bus_mut().serial_mut().as_ref().and_then(|spc| spc.bridge_description(port_num))
+    }
+
+    /// Take a snapshot of the state of the machine. Pages of state that are unchanged since 
+    /// 'prev' are shared with it.
+    #[cfg(not(feature = "cpu_validator"))]
+    pub fn snapshot(&self, prev: Option<&MachineSnapshot>) -> MachineSnapshot {
+        let mut state = StateFile::new(&self.state_machine_id());
+        state.save(self);
+        state.save(&self.cpu);
+        self.cpu.bus().save_devices(&mut state);
+        MachineSnapshot::new(&state, self.cpu_cycles, prev)
+    }
+
+    /// Restore the machine to the state in the specified snapshot. Unlike loading a state file,
+    /// the machine is not reset first, so devices that are not saved in snapshots (the disk 
+    /// controllers, serial ports and mouse) keep their current state. If restoring fails, the
+    /// machine is reset.
+    #[cfg(not(feature = "cpu_validator"))]
+    pub fn restore_snapshot(&mut self, snapshot: &MachineSnapshot) -> Result<(), SaveStateError> {
+        self.cancel_movie("machine state was restored");
+        self.error = false;
+        self.error_str = None;
+
+        if let Err(e) = self.load_state_sections(&snapshot.to_state_file()) {
+            log::error!("Failed to restore snapshot: {}", e);
+            self.reset();
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Return the number of frames the machine can currently be rewound, or None if rewind
+    /// is disabled.
+    #[cfg(not(feature = "cpu_validator"))]
+    pub fn rewind_depth(&self) -> Option<u64> {
+        self.rewind.as_ref().map(|rewind| rewind.depth())
+    }
+
+    #[cfg(feature = "cpu_validator")]
+    pub fn rewind_depth(&self) -> Option<u64> {
+        None
+    }
+
+    /// Step the machine backwards by at least the specified number of frames, or as far as 
+    /// the rewind buffer allows. Returns false if there was no snapshot to rewind to.
+    #[cfg(not(feature = "cpu_validator"))]
+    pub fn rewind(&mut self, frames: u64) -> bool {
+        match self.rewind.as_mut().and_then(|rewind| rewind.rewind(frames)) {
+            Some(snapshot) => {
+                log::debug!("Rewinding machine to cycle {}", snapshot.cpu_cycles);
+                self.restore_snapshot(&snapshot).is_ok()
+            }
+            None => false
+        }
+    }
+
+    #[cfg(feature = "cpu_validator")]
+    pub fn rewind(&mut self, _frames: u64) -> bool {
+        false
+    }
+
+    /// Return the string identifying this machine's configuration in state files. A state 
+    /// can only be loaded into a machine with the same identifier.
+    fn state_machine_id(&self) -> String {
+        format!("{:?}/{:?}", self.machine_type, self.video_type)
+    }
+
+    /// Save the state of the machine into a state file image.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateFile::new(&self.state_machine_id());
+        state.save(self);
+        state.save(&self.cpu);
+        self.cpu.bus().save_devices(&mut state);
+        state.to_bytes()
+    }
+
+    /// Load the state of the machine from a state file image. The machine is reset first, so 
+    /// any device not present in the state file is left in its reset state. If loading fails,
+    /// the machine is reset again.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), SaveStateError> {
+        let state = StateFile::from_bytes(bytes)?;
+        let machine_id = self.state_machine_id();
+        if state.machine != machine_id {
+            return Err(SaveStateError::MachineMismatch(state.machine, machine_id));
+        }
+        log::debug!("Loading state saved by MartyPC {}", state.emulator_version);
+        self.cancel_movie("machine state was loaded");
+
+        self.reset();
+        if let Err(e) = self.load_state_sections(&state) {
+            log::error!("Failed to load state: {}", e);
+            self.reset();
+            return Err(e)
+        }
+        Ok(())
+    }
+
+    /// Load the state of the machine, CPU and bus devices from their sections in the state file.
+    fn load_state_sections(&mut self, state: &StateFile) -> Result<(), SaveStateError> {
+        state.load(self)?;
+        state.load(&mut self.cpu)?;
+        self.cpu.bus_mut().load_devices(state)?;
+        self.cpu.bus_mut().set_cpu_factor(self.cpu_factor);
+        Ok(())
+    }
+
+    /// Save the state of the machine to the specified file.
+    pub fn save_state_file(&self, path: &Path) -> Result<(), SaveStateError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| SaveStateError::FileError(e.to_string()))?;
+        }
+        std::fs::write(path, self.save_state()).map_err(|e| SaveStateError::FileError(e.to_string()))
+    }
+
+    /// Load the state of the machine from the specified file.
+    pub fn load_state_file(&mut self, path: &Path) -> Result<(), SaveStateError> {
+        let bytes = std::fs::read(path).map_err(|e| SaveStateError::FileError(e.to_string()))?;
+        self.load_state(&bytes)
+    }
+
+    /// Return whether the guest currently appears to be idle, ie, halted or waiting for 
+    /// keyboard input.
+    pub fn is_idle(&self) -> bool {
+        self.idle.is_idle()
+    }
+
+    /// Return the number of frames the guest has been idle, or 0 if it is busy.
+    pub fn idle_frames(&self) -> u64 {
+        self.idle.idle_frames()
+    }
+
+    /// Set the number of consecutive idle frames before the guest is considered idle, and the
+    /// number of consecutive busy frames before it is considered busy again.
+    pub fn set_idle_hysteresis(&mut self, enter_frames: u32, exit_frames: u32) {
+        self.idle.set_hysteresis(enter_frames, exit_frames);
+    }
+
+    /// Return the activity of the machine's disk drives since this was last called.
+    pub fn take_disk_activity(&mut self) -> DiskActivity {
+        std::mem::take(&mut self.disk_activity)
+    }
+
+    /// Enable or disable synthesized drive sounds.
+    pub fn set_drive_sounds(&mut self, state: bool) {
+        if state == self.drive_sound.is_some() {
+            return
+        }
+        self.drive_sound = if state {
+            let sample_rate = self.sound_player.as_ref().map(|sp| sp.sample_rate()).unwrap_or(DEFAULT_SAMPLE_RATE);
+            Some(DriveSound::new(sample_rate))
+        }
+        else {
+            None
+        };
+    }
+
+    /// Collect the activity of the machine's disk drives for this frame.
+    fn update_disk_activity(&mut self) {
+        let mut activity = DiskActivity::default();
+        let bus = self.cpu.bus_mut();
+        if let Some(fdc) = bus.fdc_mut() {
+            activity.floppy = (0..2).map(|drive| fdc.take_activity(drive)).collect();
+        }
+        if let Some(hdc) = bus.hdc_mut() {
+            activity.hard_disk.extend((0..2).filter_map(|drive| hdc.take_activity(drive)));
+        }
+        if let Some(xtide) = bus.xtide_mut() {
+            activity.hard_disk.extend((0..2).filter_map(|drive| xtide.take_activity(drive)));
+        }
+
+        if let Some(drive_sound) = &mut self.drive_sound {
+            drive_sound.update(&activity);
+        }
+        self.disk_activity.merge(&activity);
+    }
+
+    /// Return the detected guest operating environment.
+    pub fn guest_os(&self) -> GuestOs {
+        self.guest_os.guest_os()
+    }
+
+    /// Request an emulation speed, as a factor of the machine's real speed. The effective
+    /// speed ramps toward the requested speed over the following frames.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed.set_target(speed);
+    }
+
+    /// Request the next speed step above or below the current requested speed.
+    pub fn step_speed(&mut self, up: bool) {
+        self.speed.step(up);
+    }
+
+    /// Return the requested emulation speed.
+    pub fn speed(&self) -> f64 {
+        self.speed.target()
+    }
+
+    /// Return the effective emulation speed for the current frame. Frontends should run
+    /// this factor times the number of cycles in a frame at 1x.
+    pub fn effective_speed(&self) -> f64 {
+        self.speed.current()
+    }
+
+    /// Return the codepage used to translate text to and from the guest.
+    pub fn codepage(&self) -> Codepage {
+        self.codepage
+    }
+
+    /// Return the contents of the active text mode page as UTF-8, one line per row, or None
+    /// if the video card is not in a text mode.
+    pub fn screen_text(&self) -> Option<String> {
+        let screen = self.cpu.bus().video()?.get_text_screen()?;
+        let mut text = String::new();
+        for row in screen.chars.chunks(screen.columns.max(1) as usize) {
+            text.push_str(self.codepage.decode(row).trim_end());
+            text.push('\n');
+        }
+        Some(text)
+    }
+
+    pub fn set_breakpoints(&mut self, bp_list: Vec<BreakPointType>) {
         self.cpu.set_breakpoints(bp_list)
     }
 
+    /// Scan for option ROMs and call the init routine of each ROM with a valid checksum. The 
+    /// calls are injected in reverse order, so that each ROM returns into the init routine of the
+    /// next, and the last returns to the bootstrap loader. ROMs such as disk BIOSes may hook 
+    /// INT 19h during init, so once the calls return, the bootstrap is redirected to the 
+    /// current INT 19h vector.
+    fn init_option_roms(&mut self) {
+        match self.option_rom_init_state {
+            OptionRomInitState::Pending => {
+                let region = self.cpu.bus().get_slice_at(OPTION_ROM_SCAN_START, OPTION_ROM_SCAN_END - OPTION_ROM_SCAN_START);
+                let roms = scan_option_roms(region, OPTION_ROM_SCAN_START);
+
+                self.option_rom_init_state = OptionRomInitState::Done;
+                for rom in roms.iter().rev() {
+                    if rom.checksum_ok {
+                        log::debug!("Calling init routine of {}K option ROM at {:04X}", rom.size / 1024, rom.segment);
+                        self.cpu.inject_far_call(rom.segment, OPTION_ROM_INIT_OFFSET);
+                        self.option_rom_init_state = OptionRomInitState::Running;
+                    }
+                    else {
+                        log::warn!("Option ROM at {:04X} has a bad checksum and will not be initialized.", rom.segment);
+                    }
+                }
+            }
+            OptionRomInitState::Running => {
+                self.option_rom_init_state = OptionRomInitState::Done;
+
+                let vector = self.cpu.bus().get_slice_at(0x19 * 4, 4);
+                let offset = u16::from_le_bytes([vector[0], vector[1]]);
+                let segment = u16::from_le_bytes([vector[2], vector[3]]);
+                if ((segment as u32) << 4) + offset as u32 != BIOS_BOOTSTRAP_ADDRESS {
+                    log::debug!("INT 19h was hooked by an option ROM, bootstrapping from {:04X}:{:04X}", segment, offset);
+                    self.cpu.inject_jump(segment, offset);
+                }
+            }
+            OptionRomInitState::Done => {}
+        }
+    }
+
     pub fn reset(&mut self) {
 
         // TODO: Reload any program specified here?
 
+        self.accept_input(MovieEvent::Reset);
+
         // Clear any error state.
         self.error = false;
         self.error_str = None;
@@ -628,6 +1835,12 @@ impl Machine {
 
         // Reset all installed devices.
         self.cpu.bus_mut().reset_devices();
+
+        if self.option_rom_init {
+            self.option_rom_init_state = OptionRomInitState::Pending;
+        }
+        self.idle.reset();
+        self.guest_os.reset();
     }
 
     #[inline]
@@ -635,21 +1848,22 @@ impl Machine {
     /// divisor and system crystal speed.
     fn cpu_cycles_to_us(&self, cycles: u32) -> f64 {
 
-        let mhz = match self.cpu_factor {
-            ClockFactor::Divisor(n) => self.machine_desc.system_crystal / (n as f64),
-            ClockFactor::Multiplier(n) => self.machine_desc.system_crystal * (n as f64)
-        };
-
-        1.0 / mhz * cycles as f64
+        1.0 / self.get_cpu_mhz() * cycles as f64
     }
     
     #[inline]
     /// Convert a count of CPU cycles to system clock ticks based on the current CPU
-    /// clock divisor.
-    fn cpu_cycles_to_system_ticks(&self, cycles: u32) -> u32 {
+    /// clock divisor. Fractional ticks from clock ratios are carried over to the next call
+    /// so that devices stay in the correct ratio to the CPU.
+    fn cpu_cycles_to_system_ticks(&mut self, cycles: u32) -> u32 {
         match self.cpu_factor {
             ClockFactor::Divisor(n) => cycles * (n as u32),
-            ClockFactor::Multiplier(n) => cycles / (n as u32)
+            ClockFactor::Multiplier(n) => cycles / (n as u32),
+            ClockFactor::Ratio(t, c) => {
+                let total = cycles as u64 * t as u64 + self.tick_remainder;
+                self.tick_remainder = total % c as u64;
+                (total / c as u64) as u32
+            }
         }
     }
 
@@ -659,8 +1873,18 @@ impl Machine {
         let mut skip_breakpoint = false;
         let mut instr_count = 0;
 
-        // Update cpu factor.
+        // Update cpu factor. An unlimited clock runs the cycle target in one frame's time.
+        if self.unlimited_clock {
+            self.next_cpu_factor = CpuClock::unlimited_factor(
+                cycle_target, 
+                self.unlimited_frame_us, 
+                self.machine_desc.system_crystal
+            );
+        }
         let new_factor = self.next_cpu_factor;
+        if new_factor != self.cpu_factor {
+            self.tick_remainder = 0;
+        }
         self.cpu_factor = new_factor;
         self.bus_mut().set_cpu_factor(new_factor);
 
@@ -765,6 +1989,11 @@ impl Machine {
             return 0;
         }
 
+        // Each frame run while running is a frame of any movie being recorded or played back.
+        if let ExecutionState::Running = exec_control.state {
+            self.movie_frame_update();
+        }
+
         let mut cycles_elapsed = 0;
 
         while cycles_elapsed < cycle_target_adj {
@@ -790,6 +2019,11 @@ impl Machine {
                     self.rom_manager.install_patch(self.cpu.bus_mut(), flat_address);
                 }
             }
+
+            // Run option ROM init routines when the BIOS reaches its bootstrap loader
+            if self.option_rom_init_state != OptionRomInitState::Done && flat_address == BIOS_BOOTSTRAP_ADDRESS {
+                self.init_option_roms();
+            }
             
             let mut step_over_target = None;
 
@@ -814,7 +2048,10 @@ impl Machine {
                             return 1
                         }                        
                     }
-                    
+
+                    if self.profiler.enabled() {
+                        self.profiler.record(flat_address, cpu_cycles, self.cpu.call_stack_targets());
+                    }
                 },
                 Err(err) => {
                     if let CpuError::CpuHaltedError(_) = err {
@@ -836,6 +2073,7 @@ impl Machine {
             instr_count += 1;
             cycles_elapsed += cpu_cycles;
             self.cpu_cycles += cpu_cycles as u64;            
+            self.idle.add_cycles(cpu_cycles, self.cpu.is_halted());
 
             if cpu_cycles == 0 {
                 log::warn!("Instruction returned 0 cycles");
@@ -844,6 +2082,11 @@ impl Machine {
 
             self.run_devices(cpu_cycles, &mut kb_event_processed);
 
+            // A halted CPU does nothing until an interrupt arrives, so run devices ahead until one does.
+            if self.halt_skip && self.cpu.is_halted() && matches!(exec_control.state, ExecutionState::Running) {
+                cycles_elapsed += self.skip_halt(cycle_target_adj.saturating_sub(cycles_elapsed), &mut kb_event_processed);
+            }
+
             // If we returned a step over target address, execution is paused, and step over was requested, 
             // then consume as many instructions as needed to get to to the 'next' instruction. This will
             // skip over any CALL or interrupt encountered.
@@ -952,6 +2195,20 @@ impl Machine {
             }
         }
 
+        // Type pasted text while the user is not typing. Pasted scancodes are paced by the 
+        // paste delay rather than limited to one per frame.
+        if let Some(paste) = &mut self.paste {
+            if kb_byte_opt.is_some() {
+                paste.defer();
+            }
+            else if self.kb_buf.is_empty() {
+                kb_byte_opt = paste.next_due(us);
+            }
+            if paste.is_empty() {
+                self.paste = None;
+            }
+        }
+
         // Run devices.
         // We send the IO bus the elapsed time in us, and a mutable reference to the PIT channel #2 ring buffer
         // so that we can collect output from the timer.
@@ -962,16 +2219,21 @@ impl Machine {
             &mut self.speaker_buf_producer
         );
 
+        // The PCjr keyboard interrupts through NMI, held until the BIOS clears the keyboard latch.
+        if let Some(nmi) = self.cpu.bus().keyboard_nmi() {
+            self.cpu.set_nmi(nmi);
+        }
+
+        // Record device activity to the event timeline
+        if self.timeline.is_enabled() {
+            if let CpuAddress::Segmented(cs, ip) = self.cpu.get_csip() {
+                self.timeline.sample(self.cpu.bus(), self.cpu_cycles, cs, ip);
+            }
+        }
+
         // Currently only one device run event type
         if let Some(DeviceEvent::DramRefreshUpdate(dma_counter, dma_counter_val)) = device_event {
-            self.cpu.set_option(
-                CpuOption::SimulateDramRefresh(
-                    true, 
-                    self.timer_ticks_to_cpu_cycles(dma_counter), 
-                    self.timer_ticks_to_cpu_cycles(dma_counter_val)
-                    //self.timer_ticks_to_cpu_cycles(0)
-                )
-            )
+            self.update_dram_refresh(dma_counter, dma_counter_val);
         }
 
         // Sample the PIT channel #2 for sound
@@ -983,6 +2245,87 @@ impl Machine {
         sys_ticks
     }
 
+    /// Return the time in microseconds until a device other than the PIT, or keyboard input 
+    /// queued by the frontend, may next interrupt the CPU.
+    fn next_interrupt_us(&self, kb_event_processed: bool) -> Option<f64> {
+        if !self.kb_buf.is_empty() && !kb_event_processed {
+            return Some(0.0)
+        }
+        let paste_us = self.paste.as_ref().map(|paste| paste.due_in_us());
+        match (self.cpu.bus().next_device_interrupt_us(), paste_us) {
+            (Some(device_us), Some(paste_us)) => Some(device_us.min(paste_us)),
+            (device_us, paste_us) => device_us.or(paste_us)
+        }
+    }
+
+    /// Skip up to 'max_cycles' cycles while the CPU is halted, running devices in slices that end no
+    /// later than the earliest point timer channel 0 can reach terminal count or another device
+    /// can raise an interrupt. Stops as soon as an interrupt is pending that would resume the CPU,
+    /// or when a device is busy and must be run every step. Returns the number of cycles skipped.
+    fn skip_halt(&mut self, max_cycles: u32, kb_event_processed: &mut bool) -> u32 {
+
+        let mut skipped = 0;
+
+        while skipped < max_cycles && !self.cpu.halt_wake_pending() {
+
+            // Round up, so that the slice ends after the device's interrupt is raised.
+            let device_cycles = match self.next_interrupt_us(*kb_event_processed) {
+                Some(us) => (us * self.get_cpu_mhz()).ceil().min(HALT_SKIP_MAX_CYCLES as f64) as u32,
+                None => HALT_SKIP_MAX_CYCLES
+            };
+            if device_cycles == 0 {
+                break
+            }
+
+            let timer_cycles = match self.cpu.bus().pit() {
+                Some(pit) => {
+                    // Mode 3 decrements the counting element by two, so half of it is the soonest it can expire.
+                    let (_, count) = pit.get_channel_count(0);
+                    let ticks = if count == 0 { 0x8000 } else { (count as u64 / 2).max(1) };
+                    let sys_ticks = ticks * self.machine_desc.timer_divisor as u64;
+                    let cycles = match self.cpu_factor {
+                        ClockFactor::Divisor(n) => sys_ticks / n as u64,
+                        ClockFactor::Multiplier(n) => sys_ticks * n as u64,
+                        ClockFactor::Ratio(t, c) => sys_ticks * c as u64 / t as u64,
+                    };
+                    cycles.min(HALT_SKIP_MAX_CYCLES as u64) as u32
+                }
+                None => HALT_SKIP_MAX_CYCLES
+            };
+
+            let slice = timer_cycles.min(device_cycles).min(max_cycles - skipped).max(1);
+            self.cpu.skip_halt_cycles(slice);
+            self.run_devices(slice, kb_event_processed);
+            skipped += slice;
+        }
+
+        self.cpu_cycles += skipped as u64;
+        self.idle.add_cycles(skipped, true);
+        skipped
+    }
+
+    /// Configure DRAM refresh simulation after software reprograms timer channel 1. 
+    /// 'dma_counter' is the channel's reload value and 'dma_counter_val' the number of timer 
+    /// ticks elapsed in the current period, which sets the phase of refresh.
+    fn update_dram_refresh(&mut self, dma_counter: u16, dma_counter_val: u16) {
+
+        if !self.dram_refresh {
+            self.cpu.set_option(CpuOption::SimulateDramRefresh(false, 0, 0));
+            return
+        }
+
+        let mut cycle_target = self.timer_ticks_to_cpu_cycles(dma_counter);
+        let mut cycles = self.timer_ticks_to_cpu_cycles(dma_counter_val);
+
+        if let Some(cadence) = self.dram_refresh_cadence {
+            // Keep the phase within the overridden period
+            cycles = cycles.min(cadence - 1);
+            cycle_target = cadence;
+        }
+
+        self.cpu.set_option(CpuOption::SimulateDramRefresh(true, cycle_target, cycles));
+    }
+
     fn timer_ticks_to_cpu_cycles(&self, timer_ticks: u16) -> u32 {
 
         let timer_multiplier = 
@@ -996,7 +2339,7 @@ impl Machine {
                     ClockFactor::Divisor(n) => {
                         self.machine_desc.timer_divisor / (n as u32)
                     }
-                    ClockFactor::Multiplier(_n) => {
+                    ClockFactor::Multiplier(_) | ClockFactor::Ratio(..) => {
                         todo!("unimplemented conversion for CPU multiplier");
                         //1
                     }
@@ -1015,10 +2358,60 @@ impl Machine {
         if let Some(spc) =  self.cpu.bus_mut().serial_mut() {
             spc.update();
         }  
+
+        // Exchange frames between the network card and its backend, if present
+        if let Some(ne2000) = self.cpu.bus_mut().ne2000_mut() {
+            ne2000.update();
+        }
+
+        self.update_disk_activity();
+
+        // Update guest idle state
+        self.idle.add_idle_calls(self.cpu.take_idle_call_count());
+        if let Some(idle) = self.idle.frame_update() {
+            log::debug!("Guest is now {}", if idle { "idle" } else { "busy" });
+        }
+
+        // Ramp emulation speed. Audio is resampled so that the sound buffer is consumed at the
+        // same rate regardless of speed.
+        if let Some(speed) = self.speed.frame_update() {
+            self.pit_data.ticks_per_sample = self.pit_data.base_ticks_per_sample * speed;
+        }
+
+        // Check for boot milestones and detect the guest OS
+        let mem = self.cpu.bus().get_slice_at(0, 0xA0000);
+        if let Some(os) = self.guest_os.frame_update(mem) {
+            log::debug!("Detected guest OS: {}", os);
+        }
+
+        // Take a rewind snapshot, if due
+        #[cfg(not(feature = "cpu_validator"))]
+        if self.rewind.as_mut().map_or(false, |rewind| rewind.frame_update(self.cpu_cycles)) {
+            let snapshot = self.snapshot(self.rewind.as_ref().and_then(|rewind| rewind.latest()));
+            log::trace!("Took rewind snapshot at cycle {} ({} new bytes)", snapshot.cpu_cycles, snapshot.unique_bytes());
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(snapshot);
+            }
+        }
     }
 
     pub fn play_sound_buffer(&self) {
-        self.sound_player.play();
+        if let Some(sound_player) = &self.sound_player {
+            sound_player.play();
+        }
+    }
+
+    /// Stop audio output, such as while the machine is paused. Resume with play_sound_buffer().
+    pub fn pause_sound_buffer(&self) {
+        if let Some(sound_player) = &self.sound_player {
+            sound_player.pause();
+        }
+    }
+
+    /// Return how full the audio output buffer is, from 0.0 to 1.0, or None if there is no
+    /// audio device.
+    pub fn sound_buffer_fill(&self) -> Option<f32> {
+        self.sound_player.as_ref().map(|sp| sp.buffer_fill())
     }
 
     pub fn pit_buf_to_sound_buf(&mut self) {
@@ -1028,55 +2421,55 @@ impl Machine {
             return
         }
 
-        let mut sum = 0;
-        let mut sample;
-        let mut samples_read = false;
+        // Each PIT tick sets the speaker level at its position within the audio sample, so
+        // that level changes are synthesized band-limited instead of averaged.
+        let logging = self.pit_data.logging_triggered;
+        for i in 0..nsamples {
+            let sample = match self.pit_data.buffer_consumer.pop() {
+                Some(s) => s,
+                None => {
+                    log::trace!("No byte in pit buffer");
+                    0
+                }
+            };
 
-        // If logging enabled, read samples and log to file.
-        if let Some(file) = self.pit_data.log_file.as_mut() {
-            if self.pit_data.logging_triggered {
-                for _ in 0..nsamples {
+            // If logging enabled, log samples to file.
+            if let (true, Some(file)) = (logging, self.pit_data.log_file.as_mut()) {
+                let sample_f32: f32 = if sample == 0 { 0.0 } else { 1.0 };
+                file.write(&sample_f32.to_le_bytes()).expect("Error writing to debug sound file");
+            }
 
-                    sample = match self.pit_data.buffer_consumer.pop() {
-                        Some(s) => s,
-                        None => {
-                            log::trace!("No byte in pit buffer");
-                            0
-                        }
-                    };
-                    sum += sample;
+            let level = if sample == 0 { 0.0 } else { 1.0 };
+            self.pit_data.synth.set_level(i as f32 / nsamples as f32, level);
+        }
+        let speaker = self.pit_data.synth.read_sample();
 
-                    let sample_f32: f32 = if sample == 0 { 0.0 } else { 1.0 };
-                    file.write(&sample_f32.to_le_bytes()).expect("Error writing to debug sound file");
+        self.pit_data.samples_produced += 1;
+        //log::trace!("producer: {}", self.pit_samples_produced);
+        let mut output = speaker * VOLUME_ADJUST;
 
-                }
-                samples_read = true;
-            }
+        // Mix in AdLib output, if present
+        if let Some(adlib) = self.cpu.bus_mut().adlib_mut() {
+            output += adlib.generate_sample() * ADLIB_VOLUME;
         }
 
-        // Otherwise, just read samples
-        if !samples_read {
-            for _ in 0..nsamples {
-            
-                sample = match self.pit_data.buffer_consumer.pop() {
-                    Some(s) => s,
-                    None => {
-                        log::trace!("No byte in pit buffer");
-                        0
-                    }
-                };
-                sum += sample;
-            }
+        // Mix in Sound Blaster DAC output, if present
+        if let Some(sb) = self.cpu.bus_mut().sb_mut() {
+            output += sb.generate_sample() * SB_VOLUME;
         }
 
-        // Averaging samples is effectively a poor lowpass filter.
-        // TODO: replace with actual lowpass filter from biquad?
-        let average: f32 = sum as f32 / nsamples as f32;
+        // Mix in PCjr sound generator output, if present
+        if let Some(sn76489) = self.cpu.bus_mut().sn76489_mut() {
+            output += sn76489.generate_sample() * SN76489_VOLUME;
+        }
 
-        //log::trace!("Sample: sum: {}, ticks: {}, avg: {}", sum, pit_ticks, average);
-        self.pit_data.samples_produced += 1;
-        //log::trace!("producer: {}", self.pit_samples_produced);
-        self.sound_player.queue_sample(average as f32 * VOLUME_ADJUST);
+        // Mix in drive sounds, if enabled
+        if let Some(drive_sound) = &mut self.drive_sound {
+            output += drive_sound.generate_sample() * DRIVE_SOUND_VOLUME;
+        }
+        if let Some(sound_player) = &mut self.sound_player {
+            sound_player.queue_sample(output);
+        }
 
         // Calculate size of next audio sample in pit samples by carrying over fractional part
         let next_sample_f: f64 = self.pit_data.ticks_per_sample + self.pit_data.fractional_part;
@@ -1085,4 +2478,216 @@ impl Machine {
         self.pit_data.fractional_part = next_sample_f.fract();
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_config_from_str;
+    use crate::cpu_808x::Flag;
+    use crate::devices::fdc::FDC_DIGITAL_OUTPUT_REGISTER;
+    use crate::devices::serial::SERIAL1_LINE_CONTROL;
+    use crate::machine_manager::MACHINE_DESCS;
+    #[cfg(not(feature = "cpu_validator"))]
+    use crate::rewind::SNAPSHOT_PAGE_SIZE;
+
+    const PROGRAM_SEG: u16 = 0x0100;
+    const IRQ_HANDLER: u16 = 0x0500;
+
+    // Program timer channel 0 for a rate generator of 0x1000 ticks and the PIC to deliver IRQ0
+    // at vector 8, then halt with interrupts enabled.
+    const HALT_PROGRAM: [u8; 30] = [
+        0xB0, 0x34, 0xE6, 0x43,     // MOV AL,34h; OUT 43h,AL
+        0xB0, 0x00, 0xE6, 0x40,     // MOV AL,00h; OUT 40h,AL
+        0xB0, 0x10, 0xE6, 0x40,     // MOV AL,10h; OUT 40h,AL
+        0xB0, 0x13, 0xE6, 0x20,     // MOV AL,13h; OUT 20h,AL (ICW1)
+        0xB0, 0x08, 0xE6, 0x21,     // MOV AL,08h; OUT 21h,AL (ICW2)
+        0xB0, 0x01, 0xE6, 0x21,     // MOV AL,01h; OUT 21h,AL (ICW4)
+        0xB0, 0xFE, 0xE6, 0x21,     // MOV AL,FEh; OUT 21h,AL (OCW1)
+        0xFB, 0xF4,                 // STI; HLT
+    ];
+
+    // Start timer channel 0 counting down from 10000h without interrupting, program the PIC to 
+    // deliver only IRQ3 at vector 0Bh and COM2 to interrupt when data is received, then halt 
+    // with interrupts enabled.
+    const SERIAL_HALT_PROGRAM: [u8; 40] = [
+        0xB0, 0x34, 0xE6, 0x43,     // MOV AL,34h; OUT 43h,AL
+        0xB0, 0x00, 0xE6, 0x40,     // MOV AL,00h; OUT 40h,AL
+        0xE6, 0x40,                 // OUT 40h,AL
+        0xB0, 0x13, 0xE6, 0x20,     // MOV AL,13h; OUT 20h,AL (ICW1)
+        0xB0, 0x08, 0xE6, 0x21,     // MOV AL,08h; OUT 21h,AL (ICW2)
+        0xB0, 0x01, 0xE6, 0x21,     // MOV AL,01h; OUT 21h,AL (ICW4)
+        0xB0, 0xF7, 0xE6, 0x21,     // MOV AL,F7h; OUT 21h,AL (OCW1)
+        0xBA, 0xF9, 0x02,           // MOV DX,2F9h
+        0xB0, 0x01, 0xEE,           // MOV AL,01h; OUT DX,AL (IER)
+        0xBA, 0xFC, 0x02,           // MOV DX,2FCh
+        0xB0, 0x08, 0xEE,           // MOV AL,08h; OUT DX,AL (MCR OUT2)
+        0xFB, 0xF4,                 // STI; HLT
+    ];
+
+    // Latch and read timer channel 0 into BX, then halt for good with interrupts disabled.
+    const IRQ_PROGRAM: [u8; 13] = [
+        0xB0, 0x00, 0xE6, 0x43,     // MOV AL,00h; OUT 43h,AL
+        0xE4, 0x40, 0x88, 0xC3,     // IN AL,40h; MOV BL,AL
+        0xE4, 0x40, 0x88, 0xC7,     // IN AL,40h; MOV BH,AL
+        0xF4,                       // HLT
+    ];
+
+    // Increment AX and the word at CS:0200h forever.
+    const COUNT_PROGRAM: [u8; 8] = [
+        0x40,                               // INC AX
+        0x2E, 0xFF, 0x06, 0x00, 0x02,       // INC WORD CS:[0200h]
+        0xEB, 0xF8,                         // JMP 0000h
+    ];
+    const COUNTER_ADDRESS: usize = ((PROGRAM_SEG as usize) << 4) + 0x200;
+
+    fn test_config() -> ConfigFileParams {
+        let mut config = get_config_from_str(include_str!("../../install/martypc.toml")).unwrap();
+        config.emulator.no_bios = true;
+        config
+    }
+
+    fn test_machine(config: &ConfigFileParams) -> Machine {
+        let model = config.machine.model;
+        Machine::new(
+            config,
+            model,
+            MACHINE_DESCS[&model],
+            TraceMode::None,
+            config.machine.video,
+            None,
+            RomManager::new(model, Vec::new(), None),
+        )
+    }
+
+    fn running() -> ExecutionControl {
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+        exec_control
+    }
+
+    /// Return the registers and counter word modified by COUNT_PROGRAM.
+    #[cfg(not(feature = "cpu_validator"))]
+    fn count_state(machine: &Machine) -> (u16, u16, Vec<u8>) {
+        (
+            machine.cpu.get_register16(Register16::AX),
+            machine.cpu.get_register16(Register16::IP),
+            machine.bus().get_slice_at(COUNTER_ADDRESS, 2).to_vec()
+        )
+    }
+
+    /// Run a program that halts on a machine without a BIOS, and return the timer count the
+    /// handler for 'vector' latched after the CPU woke from halt. 'setup' is called before the
+    /// program runs.
+    fn halt_wake_count(halt_skip: bool, program: &[u8], vector: usize, setup: impl FnOnce(&mut Machine)) -> u16 {
+        let mut config = test_config();
+        config.cpu.halt_skip = halt_skip;
+        let mut machine = test_machine(&config);
+
+        let bus = machine.bus_mut();
+        bus.write_u8(vector * 4, IRQ_HANDLER as u8, 0).unwrap();
+        bus.write_u8(vector * 4 + 1, (IRQ_HANDLER >> 8) as u8, 0).unwrap();
+        bus.copy_from(&IRQ_PROGRAM, IRQ_HANDLER as usize, 0, false).unwrap();
+        machine.load_program(program, PROGRAM_SEG, 0).unwrap();
+        setup(&mut machine);
+
+        machine.run(40_000, &mut running());
+
+        assert!(machine.cpu.is_halted());
+        assert!(!machine.cpu.get_flag(Flag::Interrupt));
+        machine.cpu.get_register16(Register16::BX)
+    }
+
+    #[test]
+    fn test_halt_skip_wake_timing() {
+        let count = halt_wake_count(false, &HALT_PROGRAM, 8, |_| {});
+        // The handler ran shortly after the timer reloaded.
+        assert!(count < 0x1000 && count > 0x0F00, "count: {:04X}", count);
+        assert_eq!(halt_wake_count(true, &HALT_PROGRAM, 8, |_| {}), count);
+    }
+
+    #[test]
+    fn test_halt_skip_serial_wake_timing() {
+        // COM2 receives the byte one byte period after the machine starts.
+        let receive = |machine: &mut Machine| machine.bus_mut().serial_mut().as_mut().unwrap().queue_byte(1, 0x55);
+        let count = halt_wake_count(false, &SERIAL_HALT_PROGRAM, 0x0B, receive);
+        // 9600 baud takes about 1000 timer ticks per byte.
+        assert!(count < 0xFD00 && count > 0xFB00, "count: {:04X}", count);
+        assert_eq!(halt_wake_count(true, &SERIAL_HALT_PROGRAM, 0x0B, receive), count);
+    }
+
+    #[test]
+    #[cfg(not(feature = "cpu_validator"))]
+    fn test_snapshot_restore() {
+        let mut machine = test_machine(&test_config());
+        machine.load_program(&COUNT_PROGRAM, PROGRAM_SEG, 0).unwrap();
+        let mut exec_control = running();
+
+        machine.run(5_000, &mut exec_control);
+        let snapshot = machine.snapshot(None);
+        let saved = count_state(&machine);
+
+        machine.run(5_000, &mut exec_control);
+        let (ax, _, counter) = count_state(&machine);
+        assert_ne!(ax, saved.0);
+        assert_ne!(counter, saved.2);
+
+        // Nearly all of memory is unchanged, so a second snapshot shares it with the first.
+        let next = machine.snapshot(Some(&snapshot));
+        assert!(next.unique_bytes() <= 4 * SNAPSHOT_PAGE_SIZE, "unique bytes: {}", next.unique_bytes());
+        assert!(snapshot.unique_bytes() < 0x10000);
+
+        machine.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(count_state(&machine), saved);
+    }
+
+    #[test]
+    #[cfg(not(feature = "cpu_validator"))]
+    fn test_rewind_evicts_oldest() {
+        let mut config = test_config();
+        config.emulator.rewind = true;
+        config.emulator.rewind_interval = Some(1);
+        config.emulator.rewind_buffer_len = Some(4);
+        let mut machine = test_machine(&config);
+        machine.load_program(&COUNT_PROGRAM, PROGRAM_SEG, 0).unwrap();
+        let mut exec_control = running();
+
+        let mut states = Vec::new();
+        for _ in 0..6 {
+            machine.run(2_000, &mut exec_control);
+            machine.frame_update();
+            states.push(count_state(&machine));
+        }
+
+        // Snapshots of frames 3 to 6 remain.
+        assert_eq!(machine.rewind_depth(), Some(3));
+
+        assert!(machine.rewind(1));
+        assert_eq!(count_state(&machine), states[4]);
+
+        assert!(machine.rewind(100));
+        assert_eq!(count_state(&machine), states[2]);
+        assert_eq!(machine.rewind_depth(), Some(0));
+        assert!(!machine.rewind(1));
+    }
+
+    #[test]
+    #[cfg(not(feature = "cpu_validator"))]
+    fn test_snapshot_keeps_unsaved_devices() {
+        let mut machine = test_machine(&test_config());
+        machine.load_program(&COUNT_PROGRAM, PROGRAM_SEG, 0).unwrap();
+        machine.fdc().as_mut().unwrap().load_image_from(0, vec![0; 368640]).unwrap();
+        machine.run(1_000, &mut running());
+        let snapshot = machine.snapshot(None);
+
+        // Turn on the floppy motor and change the serial line settings after the snapshot.
+        let bus = machine.bus_mut();
+        bus.io_write_u8(FDC_DIGITAL_OUTPUT_REGISTER, 0x1C, 0);
+        bus.io_write_u8(SERIAL1_LINE_CONTROL, 0x1B, 0);
+
+        // The floppy controller and serial ports don't implement SaveState, so restoring the
+        // snapshot leaves them as they are.
+        machine.restore_snapshot(&snapshot).unwrap();
+        assert!(machine.fdc().as_mut().unwrap().take_activity(0).motor_on);
+        assert_eq!(machine.bus_mut().io_read_u8(SERIAL1_LINE_CONTROL, 0), 0x1B);
+    }
+}
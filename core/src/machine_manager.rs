@@ -27,22 +27,37 @@
     machine_manager.rs
 
     This module manages machine configuration defintions.
+
+    A machine descriptor describes the motherboard of a machine type. A 
+    machine profile builds on a descriptor to describe a particular machine,
+    such as a turbo XT clone built on a 5160 compatible motherboard: its CPU 
+    clocks, preferred BIOS ROMs, DIP switch settings and bus wait states. 
+    Profiles are defined in TOML; the built-in profiles below can be 
+    extended or replaced by [[machine_profile]] sections in the config file.
   
 */
 
 use std::collections::HashMap;
 use lazy_static::lazy_static;
+use serde_derive::Deserialize;
 
 use crate::devices::pit::PitType;
-use crate::config::MachineType;
+use crate::config::{IoWaitStateRegion, MachineType, WaitStateRegion};
 use crate::cpu_common::CpuType;
 use crate::bus::ClockFactor;
+use crate::speed::CpuClock;
 
 // Clock derivision from reenigne
 // See https://www.vogons.org/viewtopic.php?t=55049
 pub const IBM_PC_SYSTEM_CLOCK: f64 = 157.5/11.0;
 pub const PIT_DIVISOR: u32 = 12;
 
+// Limits of configurable conventional memory. Sizes are rounded down to a whole number of 
+// 32K units, the granularity of the 5150's memory switches.
+pub const CONVENTIONAL_RAM_MIN: u32 = 0x10000;
+pub const CONVENTIONAL_RAM_MAX: u32 = 0xA0000;
+pub const CONVENTIONAL_RAM_UNIT: u32 = 0x8000;
+
 #[derive (Copy, Clone, Debug)]
 pub enum KbControllerType {
     Ppi,
@@ -116,7 +131,7 @@ lazy_static! {
                         pit_type: PitType::Model8253,
                         pic_type: PicType::Single,
                         dma_type: DmaType::Single,
-                        conventional_ram: 0x100000,
+                        conventional_ram: CONVENTIONAL_RAM_MAX,
                         conventional_ram_speed: 200.0,
                         num_floppies: 2,
                         serial_ports: true,
@@ -141,15 +156,219 @@ lazy_static! {
                         pit_type: PitType::Model8253,
                         pic_type: PicType::Single,
                         dma_type: DmaType::Single,
-                        conventional_ram: 0x100000,
+                        conventional_ram: CONVENTIONAL_RAM_MAX,
                         conventional_ram_speed: 200.0,
                         num_floppies: 2,
                         serial_ports: true,
                         serial_mouse: true
                     }
-                ),        
+                ),
+                (
+                    // The PCjr's 128K of RAM is shared with its video gate array, which also
+                    // refreshes it. The diskette adapter is not emulated, so the machine boots 
+                    // to cassette BASIC or a cartridge.
+                    MachineType::IBM_PCJR_4860,
+                    MachineDescriptor {
+                        machine_type: MachineType::IBM_PCJR_4860,
+                        system_crystal: IBM_PC_SYSTEM_CLOCK,
+                        timer_crystal: None,
+                        bus_crystal: IBM_PC_SYSTEM_CLOCK,
+                        cpu_type: CpuType::Intel8088,
+                        cpu_factor: ClockFactor::Divisor(3),
+                        cpu_turbo_factor: ClockFactor::Divisor(2),
+                        bus_type: BusType::Isa8,
+                        bus_factor: ClockFactor::Divisor(1),
+                        timer_divisor: PIT_DIVISOR,
+                        have_ppi: true,
+                        kb_controller: KbControllerType::Ppi,
+                        pit_type: PitType::Model8253,
+                        pic_type: PicType::Single,
+                        dma_type: DmaType::Single,
+                        conventional_ram: 0x20000,
+                        conventional_ram_speed: 200.0,
+                        num_floppies: 0,
+                        serial_ports: true,
+                        serial_mouse: true
+                    }
+                ),
             ]
         );
         map
     };
-}
\ No newline at end of file
+}
+
+impl MachineDescriptor {
+    /// Set the amount of conventional memory installed from the size configured in kilobytes.
+    /// Memory expansion cards are not modelled separately; the configured size is the total 
+    /// of motherboard and expansion memory. The PCjr's memory is shared with its video gate 
+    /// array and cannot be changed.
+    pub fn with_conventional_ram(mut self, size_kb: Option<u32>) -> MachineDescriptor {
+        let Some(kb) = size_kb else {
+            return self
+        };
+        if self.machine_type == MachineType::IBM_PCJR_4860 {
+            log::warn!("Conventional memory size cannot be configured on the PCjr.");
+            return self
+        }
+        let requested = kb.saturating_mul(1024);
+        let clamped = requested.clamp(CONVENTIONAL_RAM_MIN, CONVENTIONAL_RAM_MAX);
+        let size = clamped - clamped % CONVENTIONAL_RAM_UNIT;
+        if size != requested {
+            log::warn!("Conventional memory size of {}K adjusted to {}K.", kb, size / 1024);
+        }
+        self.conventional_ram = size;
+        self
+    }
+}
+
+/// The built-in machine profiles.
+pub const BUILTIN_PROFILES: &str = r#"
+[[machine_profile]]
+name = "ibm5150"
+description = "IBM PC 5150"
+model = "IBM_PC_5150"
+bios = [
+    "f453eb2df6daf21ec644d33663d85434", # 5150 BIOS v3 10/27/82
+    "6a1ed4e3f500d785a01ff4d3e000d79c", # 5150 BIOS v2 10/19/81
+    "6338a9808445de12109a2389b71ee2eb", # 5150 BIOS v1 04/24/81
+]
+
+[[machine_profile]]
+name = "ibm5160"
+description = "IBM PC/XT 5160"
+model = "IBM_XT_5160"
+bios = [
+    "9696472098999c02217bf922786c1f4a", # 5160 BIOS v05/09/86
+    "fd9ff9cbe0a8f154746ccb0a33f6d3e7", # 5160 BIOS v01/10/86
+    "1a2ac1ae0fe0f7783197e78da8b3126c", # 5160 BIOS v11/08/82
+]
+
+[[machine_profile]]
+name = "turbo_xt"
+description = "Generic Turbo XT, 8MHz"
+model = "IBM_XT_5160"
+cpu_clock = "Mhz4_77"
+turbo_clock = "Mhz8"
+turbo = true
+bios = [
+    "f36c2dd29344eff6f55135f8b3014b81", # GLaBIOS 0.2.5 (8XC)
+    "c9090b75c0332fc3509642ea193de7a2", # GLaBIOS 0.2.4
+]
+# Adapter ROMs are too slow to be read at 8MHz without a wait state.
+wait_state_regions = [ { address = 0xC0000, size = 0x40000, wait_states = 1 } ]
+"#;
+
+/// A machine profile, selected with the 'profile' key in the [machine] section of the config 
+/// file.
+#[derive (Clone, Debug, Deserialize)]
+pub struct MachineProfile {
+    pub name: String,
+    pub description: Option<String>,
+    /// The motherboard the machine is built on. This determines the onboard devices and which
+    /// ROMs are recognized.
+    pub model: MachineType,
+    /// CPU clock with the turbo button off
+    pub cpu_clock: Option<CpuClock>,
+    /// CPU clock with the turbo button on
+    pub turbo_clock: Option<CpuClock>,
+    /// Whether the turbo button is on at power on
+    #[serde(default)]
+    pub turbo: bool,
+    /// MD5 digests of BIOS ROMs in order of preference. The ROM set containing the most 
+    /// preferred BIOS found is loaded.
+    pub bios: Option<Vec<String>>,
+    /// DIP switch block values as read from the PPI, overriding the values derived from the
+    /// configured floppy drives and video card.
+    pub dip_sw1: Option<u8>,
+    pub dip_sw2: Option<u8>,
+    pub wait_state_regions: Option<Vec<WaitStateRegion>>,
+    pub io_wait_state_regions: Option<Vec<IoWaitStateRegion>>,
+}
+
+#[derive (Deserialize)]
+struct ProfileFile {
+    machine_profile: Vec<MachineProfile>,
+}
+
+impl MachineProfile {
+    /// Apply the profile's CPU clocks to the descriptor of its motherboard.
+    pub fn apply_to_descriptor(&self, mut desc: MachineDescriptor) -> MachineDescriptor {
+        if let Some(factor) = self.cpu_clock.and_then(|clock| clock.factor(desc.system_crystal)) {
+            desc.cpu_factor = factor;
+        }
+        if let Some(factor) = self.turbo_clock.and_then(|clock| clock.factor(desc.system_crystal)) {
+            desc.cpu_turbo_factor = factor;
+        }
+        desc
+    }
+}
+
+/// Parse machine profiles from TOML text containing [[machine_profile]] sections.
+pub fn parse_profiles(toml_text: &str) -> Result<Vec<MachineProfile>, toml::de::Error> {
+    toml::from_str::<ProfileFile>(toml_text).map(|file| file.machine_profile)
+}
+
+/// Return the built-in machine profiles.
+pub fn builtin_profiles() -> Vec<MachineProfile> {
+    parse_profiles(BUILTIN_PROFILES).expect("Invalid built-in machine profiles")
+}
+
+/// Find a profile by name. Profiles defined in the config file take precedence over built-in
+/// profiles of the same name.
+pub fn find_profile(name: &str, user_profiles: &[MachineProfile]) -> Option<MachineProfile> {
+    user_profiles
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+        .cloned()
+        .or_else(|| builtin_profiles().into_iter().find(|p| p.name.eq_ignore_ascii_case(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles() {
+        let profiles = builtin_profiles();
+        assert_eq!(profiles.len(), 3);
+
+        let turbo_xt = find_profile("turbo_xt", &[]).unwrap();
+        assert_eq!(turbo_xt.model, MachineType::IBM_XT_5160);
+        let desc = turbo_xt.apply_to_descriptor(MACHINE_DESCS[&turbo_xt.model]);
+        assert_eq!(desc.cpu_factor, ClockFactor::Divisor(3));
+        assert_eq!(Some(desc.cpu_turbo_factor), CpuClock::Mhz8.factor(desc.system_crystal));
+        assert_eq!(turbo_xt.wait_state_regions.unwrap()[0].address, 0xC0000);
+    }
+
+    #[test]
+    fn test_conventional_ram() {
+        let desc = MACHINE_DESCS[&MachineType::IBM_PC_5150];
+        assert_eq!(desc.with_conventional_ram(None).conventional_ram, 0xA0000);
+        assert_eq!(desc.with_conventional_ram(Some(256)).conventional_ram, 0x40000);
+        assert_eq!(desc.with_conventional_ram(Some(100)).conventional_ram, 0x18000);
+        assert_eq!(desc.with_conventional_ram(Some(16)).conventional_ram, CONVENTIONAL_RAM_MIN);
+        assert_eq!(desc.with_conventional_ram(Some(1024)).conventional_ram, CONVENTIONAL_RAM_MAX);
+
+        let pcjr = MACHINE_DESCS[&MachineType::IBM_PCJR_4860];
+        assert_eq!(pcjr.with_conventional_ram(Some(64)).conventional_ram, 0x20000);
+    }
+
+    #[test]
+    fn test_user_profile_override() {
+        let user = parse_profiles(
+            r#"
+            [[machine_profile]]
+            name = "turbo_xt"
+            model = "IBM_XT_5160"
+            turbo_clock = "Mhz7_16"
+            dip_sw1 = 0x6D
+            "#
+        ).unwrap();
+        let profile = find_profile("TURBO_XT", &user).unwrap();
+        assert_eq!(profile.turbo_clock, Some(CpuClock::Mhz7_16));
+        assert_eq!(profile.dip_sw1, Some(0x6D));
+        assert!(!profile.turbo);
+        assert!(find_profile("ibm5150", &user).is_some());
+        assert!(find_profile("nonexistent", &user).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    mem_search.rs
+
+    Implements searching memory for byte patterns and strings.
+
+    A search pattern is either a list of hex bytes separated by whitespace, 
+    where '??' matches any byte (e.g. "CD 21 ?? 4C"), or a string enclosed in 
+    double quotes (e.g. "\"MartyPC\""). Searches start at a given address 
+    and wrap around the end of memory in either direction.
+*/
+
+use std::error::Error;
+use std::fmt::Display;
+
+/// A search pattern. None matches any byte.
+pub type SearchPattern = Vec<Option<u8>>;
+
+#[derive(Debug, PartialEq)]
+pub enum PatternError {
+    Empty,
+    BadByte(String),
+    UnterminatedString,
+}
+impl Error for PatternError {}
+impl Display for PatternError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatternError::Empty => write!(f, "Search pattern is empty."),
+            PatternError::BadByte(s) => write!(f, "Invalid byte in search pattern: '{}'", s),
+            PatternError::UnterminatedString => write!(f, "Search string is missing a closing quote."),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+/// Parse a search pattern from a string of hex bytes or a quoted string.
+pub fn parse_pattern(s: &str) -> Result<SearchPattern, PatternError> {
+    let s = s.trim();
+
+    let pattern: SearchPattern = if let Some(quoted) = s.strip_prefix('"') {
+        let string = quoted.strip_suffix('"').ok_or(PatternError::UnterminatedString)?;
+        string.bytes().map(Some).collect()
+    }
+    else {
+        s.split_whitespace()
+            .map(|byte| match byte {
+                "??" => Ok(None),
+                _ if byte.len() <= 2 => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| PatternError::BadByte(byte.to_string())),
+                _ => Err(PatternError::BadByte(byte.to_string())),
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    if pattern.is_empty() {
+        return Err(PatternError::Empty)
+    }
+    Ok(pattern)
+}
+
+fn matches_at(mem: &[u8], pattern: &[Option<u8>], address: usize) -> bool {
+    mem[address..address + pattern.len()]
+        .iter()
+        .zip(pattern)
+        .all(|(byte, p)| p.is_none() || *p == Some(*byte))
+}
+
+/// Find the pattern in memory. A forward search returns the first match at or after 
+/// 'start', a backward search the last match before 'start'. Either search wraps around 
+/// if nothing is found before reaching the end of memory.
+pub fn find_pattern(mem: &[u8], pattern: &[Option<u8>], start: usize, direction: SearchDirection) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > mem.len() {
+        return None
+    }
+    let last = mem.len() - pattern.len();
+    let start = start.min(last + 1);
+
+    match direction {
+        SearchDirection::Forward => (start..=last)
+            .chain(0..start)
+            .find(|&address| matches_at(mem, pattern, address)),
+        SearchDirection::Backward => (0..start)
+            .rev()
+            .chain((start..=last).rev())
+            .find(|&address| matches_at(mem, pattern, address)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pattern() {
+        assert_eq!(parse_pattern("CD 21 ?? 4c"), Ok(vec![Some(0xCD), Some(0x21), None, Some(0x4C)]));
+        assert_eq!(parse_pattern("\"AB\""), Ok(vec![Some(b'A'), Some(b'B')]));
+        assert_eq!(parse_pattern("  "), Err(PatternError::Empty));
+        assert_eq!(parse_pattern("CD2"), Err(PatternError::BadByte("CD2".to_string())));
+        assert_eq!(parse_pattern("\"AB"), Err(PatternError::UnterminatedString));
+    }
+
+    #[test]
+    fn test_find_pattern() {
+        let mem = [0x00, 0xCD, 0x21, 0x00, 0xCD, 0x10, 0xCD, 0x21];
+        let int21 = [Some(0xCD), Some(0x21)];
+        let any_int = [Some(0xCD), None];
+
+        assert_eq!(find_pattern(&mem, &int21, 0, SearchDirection::Forward), Some(1));
+        assert_eq!(find_pattern(&mem, &int21, 2, SearchDirection::Forward), Some(6));
+        // Forward search wraps around
+        assert_eq!(find_pattern(&mem, &int21, 7, SearchDirection::Forward), Some(1));
+        assert_eq!(find_pattern(&mem, &any_int, 2, SearchDirection::Forward), Some(4));
+
+        assert_eq!(find_pattern(&mem, &int21, 6, SearchDirection::Backward), Some(1));
+        // Backward search wraps around
+        assert_eq!(find_pattern(&mem, &int21, 1, SearchDirection::Backward), Some(6));
+
+        assert_eq!(find_pattern(&mem, &[Some(0xFF)], 0, SearchDirection::Forward), None);
+    }
+}
@@ -40,6 +40,9 @@ pub enum MemError {
     SeekOutOfBoundsError,
     FileReadError,
     MmioError,
+    MmioRegionError,
+    MmioRegionLimitError,
+    WriteProtectedError,
 }
 impl Error for MemError {}
 impl Display for MemError{
@@ -48,7 +51,10 @@ impl Display for MemError{
             MemError::ReadOutOfBoundsError => write!(f, "An attempt was made to read out of buffer bounds."),
             MemError::SeekOutOfBoundsError => write!(f, "An attempt was made to move the buffer cursor out of bounds."),
             MemError::FileReadError => write!(f, "Error reading file into MemBuf."),
-            MemError::MmioError => write!(f, "Error accessing map for memory mapped device.")
+            MemError::MmioError => write!(f, "Error accessing map for memory mapped device."),
+            MemError::MmioRegionError => write!(f, "A memory mapped region was misaligned or out of bounds."),
+            MemError::MmioRegionLimitError => write!(f, "Too many memory mapped regions were registered."),
+            MemError::WriteProtectedError => write!(f, "An attempt was made to write to ROM.")
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,142 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    monitor.rs
+
+    Defines the monitor attached to the emulated video card. The monitor
+    determines how the card's output is presented: whether composite
+    artifact color is decoded, whether the picture is drawn in a single
+    phosphor color, and which display adjustments make sense to offer.
+*/
+
+use std::{fmt::Display, str::FromStr};
+
+use serde_derive::Deserialize;
+
+use crate::config::VideoType;
+use crate::palette::MonochromePhosphor;
+
+/// The monitor attached to the video card. For CGA, this selects between
+/// RGBI and composite rendering.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum MonitorType {
+    /// IBM 5153 RGBI color display.
+    #[default]
+    Rgb,
+    /// NTSC color monitor or television connected to the CGA composite output.
+    CompositeColor,
+    /// Monochrome monitor connected to the CGA composite output.
+    CompositeMono,
+    /// IBM 5151 monochrome display.
+    MdaMono,
+}
+
+impl MonitorType {
+    pub const ALL: [MonitorType; 4] = [
+        MonitorType::Rgb,
+        MonitorType::CompositeColor,
+        MonitorType::CompositeMono,
+        MonitorType::MdaMono,
+    ];
+
+    /// Return the monitor normally paired with the specified video card.
+    pub fn default_for(video: VideoType) -> Self {
+        match video {
+            VideoType::MDA => MonitorType::MdaMono,
+            _ => MonitorType::Rgb,
+        }
+    }
+
+    /// Return whether this monitor can be connected to the specified video card.
+    pub fn supports(&self, video: VideoType) -> bool {
+        match self {
+            MonitorType::Rgb => video != VideoType::MDA,
+            MonitorType::CompositeColor | MonitorType::CompositeMono => video == VideoType::CGA,
+            MonitorType::MdaMono => video == VideoType::MDA,
+        }
+    }
+
+    /// Composite monitors are rendered through the composite decoder and
+    /// offer the composite adjustment controls.
+    pub fn is_composite(&self) -> bool {
+        matches!(self, MonitorType::CompositeColor | MonitorType::CompositeMono)
+    }
+
+    pub fn is_monochrome(&self) -> bool {
+        matches!(self, MonitorType::CompositeMono | MonitorType::MdaMono)
+    }
+
+    /// Return the phosphor to draw with, or None if this monitor displays color.
+    pub fn phosphor(&self, phosphor: MonochromePhosphor) -> Option<MonochromePhosphor> {
+        if self.is_monochrome() {
+            Some(phosphor)
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl Display for MonitorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorType::Rgb => write!(f, "RGB (IBM 5153)"),
+            MonitorType::CompositeColor => write!(f, "Composite Color"),
+            MonitorType::CompositeMono => write!(f, "Composite Monochrome"),
+            MonitorType::MdaMono => write!(f, "Monochrome (IBM 5151)"),
+        }
+    }
+}
+
+impl FromStr for MonitorType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "rgb" | "5153" => Ok(MonitorType::Rgb),
+            "compositecolor" => Ok(MonitorType::CompositeColor),
+            "compositemono" => Ok(MonitorType::CompositeMono),
+            "mdamono" | "5151" => Ok(MonitorType::MdaMono),
+            _ => Err("Bad value for monitor".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monitor_phosphor() {
+        assert_eq!(MonitorType::Rgb.phosphor(MonochromePhosphor::Amber), None);
+        assert_eq!(MonitorType::CompositeMono.phosphor(MonochromePhosphor::Amber), Some(MonochromePhosphor::Amber));
+        assert!(MonitorType::CompositeColor.supports(VideoType::CGA));
+        assert!(!MonitorType::CompositeColor.supports(VideoType::EGA));
+        assert!(!MonitorType::Rgb.supports(VideoType::MDA));
+    }
+}
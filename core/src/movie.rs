@@ -0,0 +1,391 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    movie.rs
+
+    Records host input to a movie file and plays it back.
+
+    In deterministic mode the frontend runs the machine for a fixed number of
+    cycles per frame, so the state of the machine depends only on its
+    configuration and on the input it receives. A movie records each input
+    event along with the frame it was applied at, and playback applies the
+    same events at the same frames to reproduce a session exactly.
+
+    Movies are text files with one entry per line:
+
+        martypc-movie 1
+        machine IBM_XT_5160
+        120 key_down 0x1C
+        121 key_up 0x1C
+        300 paste dir\n
+        900 end 0x3A5F0C1D22B4E817
+
+    The end line records the frame recording stopped at and a checksum of
+    memory and the cycle count, which playback compares against to confirm
+    the session was reproduced.
+*/
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::{self, Display},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::config::MachineType;
+
+pub const MOVIE_MAGIC: &str = "martypc-movie";
+pub const MOVIE_VERSION: u32 = 1;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+#[derive (Debug)]
+pub enum MovieError {
+    Io(std::io::Error),
+    BadHeader,
+    UnsupportedVersion(u32),
+    Parse(usize, String),
+}
+impl Error for MovieError {}
+impl Display for MovieError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(fmt, "IO error: {}", e),
+            MovieError::BadHeader => write!(fmt, "Not a MartyPC movie file."),
+            MovieError::UnsupportedVersion(v) => write!(fmt, "Unsupported movie version: {}", v),
+            MovieError::Parse(line, msg) => write!(fmt, "Line {}: {}", line, msg),
+        }
+    }
+}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+/// An input event applied to the machine at the start of a frame.
+#[derive (Clone, Debug, PartialEq)]
+pub enum MovieEvent {
+    KeyDown(u8),
+    KeyUp(u8),
+    Mouse { l: bool, r: bool, dx: f64, dy: f64 },
+    Paste(String),
+    CancelPaste,
+    CtrlAltDel,
+    Reset,
+    Turbo(bool),
+    LoadFloppy(usize, PathBuf),
+    EjectFloppy(usize),
+}
+
+impl Display for MovieEvent {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieEvent::KeyDown(code) => write!(fmt, "key_down {:#04X}", code),
+            MovieEvent::KeyUp(code) => write!(fmt, "key_up {:#04X}", code),
+            MovieEvent::Mouse { l, r, dx, dy } => write!(fmt, "mouse {} {} {} {}", *l as u8, *r as u8, dx, dy),
+            MovieEvent::Paste(text) => write!(fmt, "paste {}", escape(text)),
+            MovieEvent::CancelPaste => write!(fmt, "cancel_paste"),
+            MovieEvent::CtrlAltDel => write!(fmt, "ctrl_alt_del"),
+            MovieEvent::Reset => write!(fmt, "reset"),
+            MovieEvent::Turbo(state) => write!(fmt, "turbo {}", *state as u8),
+            MovieEvent::LoadFloppy(drive, path) => write!(fmt, "load_floppy {} {}", drive, path.display()),
+            MovieEvent::EjectFloppy(drive) => write!(fmt, "eject_floppy {}", drive),
+        }
+    }
+}
+
+impl FromStr for MovieEvent {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, args) = s.split_once(' ').unwrap_or((s, ""));
+        let mut fields = args.split_whitespace();
+        let mut next = || fields.next().ok_or_else(|| format!("Missing argument to {}", name));
+
+        let event = match name {
+            "key_down" => MovieEvent::KeyDown(parse_u8(next()?)?),
+            "key_up" => MovieEvent::KeyUp(parse_u8(next()?)?),
+            "mouse" => MovieEvent::Mouse {
+                l: parse_bool(next()?)?,
+                r: parse_bool(next()?)?,
+                dx: next()?.parse().map_err(|_| "Bad mouse delta".to_string())?,
+                dy: next()?.parse().map_err(|_| "Bad mouse delta".to_string())?,
+            },
+            "paste" => MovieEvent::Paste(unescape(args)?),
+            "cancel_paste" => MovieEvent::CancelPaste,
+            "ctrl_alt_del" => MovieEvent::CtrlAltDel,
+            "reset" => MovieEvent::Reset,
+            "turbo" => MovieEvent::Turbo(parse_bool(next()?)?),
+            "load_floppy" => {
+                let (drive, path) = args.split_once(' ').ok_or_else(|| "Missing floppy image path".to_string())?;
+                MovieEvent::LoadFloppy(parse_drive(drive)?, PathBuf::from(path))
+            }
+            "eject_floppy" => MovieEvent::EjectFloppy(parse_drive(next()?)?),
+            _ => return Err(format!("Unknown event: {}", name))
+        };
+        Ok(event)
+    }
+}
+
+fn parse_u8(s: &str) -> Result<u8, String> {
+    let s = s.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(s, 16).map_err(|_| format!("Bad scancode: {}", s))
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("Bad flag: {}", s))
+    }
+}
+
+fn parse_drive(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("Bad drive number: {}", s))
+}
+
+fn parse_checksum(s: &str) -> Result<u64, String> {
+    let s = s.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(s, 16).map_err(|_| format!("Bad checksum: {}", s))
+}
+
+/// Escape pasted text so that it fits on one line.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            _ => return Err("Bad escape sequence in pasted text".to_string())
+        }
+    }
+    Ok(out)
+}
+
+/// Return a checksum of the machine's memory and elapsed cycles, used to confirm that
+/// playback reproduced a recorded session.
+pub fn state_checksum(mem: &[u8], cpu_cycles: u64) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in mem.iter().chain(cpu_cycles.to_le_bytes().iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// A parsed movie file.
+#[derive (Debug, PartialEq)]
+pub struct Movie {
+    pub machine_type: MachineType,
+    pub events: VecDeque<(u64, MovieEvent)>,
+    /// The frame recording stopped at and the state checksum at that frame, if the recording
+    /// was finished.
+    pub end: Option<(u64, u64)>,
+}
+
+impl Movie {
+    pub fn from_file(path: &Path) -> Result<Movie, MovieError> {
+        Movie::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.trim_end_matches('\r')))
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+
+        match lines.next().and_then(|(_, line)| line.split_once(' ')) {
+            Some((MOVIE_MAGIC, version)) => {
+                let version: u32 = version.trim().parse().map_err(|_| MovieError::BadHeader)?;
+                if version != MOVIE_VERSION {
+                    return Err(MovieError::UnsupportedVersion(version))
+                }
+            }
+            _ => return Err(MovieError::BadHeader)
+        }
+
+        let machine_type = match lines.next() {
+            Some((n, line)) => match line.split_once(' ') {
+                Some(("machine", model)) => MachineType::from_str(model.trim()).map_err(|e| MovieError::Parse(n, e))?,
+                _ => return Err(MovieError::Parse(n, "Expected machine type".to_string()))
+            },
+            None => return Err(MovieError::BadHeader)
+        };
+
+        let mut events = VecDeque::new();
+        let mut end = None;
+        let mut last_frame = 0;
+        for (n, line) in lines {
+            if end.is_some() {
+                return Err(MovieError::Parse(n, "Entry after end of movie".to_string()))
+            }
+            let (frame, entry) = line.split_once(' ').ok_or_else(|| MovieError::Parse(n, "Missing event".to_string()))?;
+            let frame: u64 = frame.parse().map_err(|_| MovieError::Parse(n, format!("Bad frame number: {}", frame)))?;
+            if frame < last_frame {
+                return Err(MovieError::Parse(n, "Frame numbers must not decrease".to_string()))
+            }
+            last_frame = frame;
+
+            match entry.split_once(' ') {
+                Some(("end", checksum)) => {
+                    end = Some((frame, parse_checksum(checksum.trim()).map_err(|e| MovieError::Parse(n, e))?));
+                }
+                _ => {
+                    events.push_back((frame, MovieEvent::from_str(entry).map_err(|e| MovieError::Parse(n, e))?));
+                }
+            }
+        }
+
+        Ok(Movie { machine_type, events, end })
+    }
+
+    /// Remove and return the events to be applied at the specified frame.
+    pub fn take_events(&mut self, frame: u64) -> Vec<MovieEvent> {
+        let mut events = Vec::new();
+        while let Some((event_frame, _)) = self.events.front() {
+            if *event_frame > frame {
+                break;
+            }
+            if let Some((_, event)) = self.events.pop_front() {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Return true if there is nothing left to play back after the specified frame.
+    pub fn is_finished(&self, frame: u64) -> bool {
+        match self.end {
+            Some((end_frame, _)) => frame >= end_frame,
+            None => self.events.is_empty()
+        }
+    }
+}
+
+/// Writes input events to a movie file as they are recorded.
+pub struct MovieRecorder {
+    writer: BufWriter<File>,
+}
+
+impl MovieRecorder {
+    pub fn new(path: &Path, machine_type: MachineType) -> Result<MovieRecorder, MovieError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(writer, "machine {:?}", machine_type)?;
+        Ok(MovieRecorder { writer })
+    }
+
+    pub fn record(&mut self, frame: u64, event: &MovieEvent) -> Result<(), MovieError> {
+        writeln!(self.writer, "{} {}", frame, event)?;
+        Ok(())
+    }
+
+    /// Write the end of the movie and close the file.
+    pub fn finish(mut self, frame: u64, checksum: u64) -> Result<(), MovieError> {
+        writeln!(self.writer, "{} end {:#018X}", frame, checksum)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_round_trip() {
+        let events = [
+            MovieEvent::KeyDown(0x1C),
+            MovieEvent::KeyUp(0x9C),
+            MovieEvent::Mouse { l: true, r: false, dx: -1.5, dy: 0.1 },
+            MovieEvent::Paste("dir a:\\\r\n\tx".to_string()),
+            MovieEvent::CancelPaste,
+            MovieEvent::CtrlAltDel,
+            MovieEvent::Reset,
+            MovieEvent::Turbo(true),
+            MovieEvent::LoadFloppy(1, PathBuf::from("media/floppies/dos 3.30.img")),
+            MovieEvent::EjectFloppy(0),
+        ];
+        for event in events {
+            assert_eq!(MovieEvent::from_str(&event.to_string()), Ok(event));
+        }
+        assert!(MovieEvent::from_str("key_down").is_err());
+        assert!(MovieEvent::from_str("jump 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_movie() {
+        let text = "martypc-movie 1\nmachine IBM_XT_5160\n\n# comment\n0 turbo 1\n10 key_down 0x1C\n10 key_up 0x9C\n20 end 0x00000000000000FF\n";
+        let mut movie = Movie::parse(text).unwrap();
+        assert_eq!(movie.machine_type, MachineType::IBM_XT_5160);
+        assert_eq!(movie.end, Some((20, 0xFF)));
+        assert_eq!(movie.take_events(5), vec![MovieEvent::Turbo(true)]);
+        assert_eq!(movie.take_events(9), vec![]);
+        assert_eq!(movie.take_events(10), vec![MovieEvent::KeyDown(0x1C), MovieEvent::KeyUp(0x9C)]);
+        assert!(!movie.is_finished(19));
+        assert!(movie.is_finished(20));
+
+        assert!(matches!(Movie::parse("martypc-movie 2\nmachine IBM_XT_5160\n"), Err(MovieError::UnsupportedVersion(2))));
+        assert!(matches!(Movie::parse("machine IBM_XT_5160\n"), Err(MovieError::BadHeader)));
+        assert!(matches!(
+            Movie::parse("martypc-movie 1\nmachine IBM_XT_5160\n10 reset\n5 reset\n"),
+            Err(MovieError::Parse(4, _))
+        ));
+    }
+
+    #[test]
+    fn test_state_checksum() {
+        let mem = [0u8; 16];
+        assert_eq!(state_checksum(&mem, 100), state_checksum(&mem, 100));
+        assert_ne!(state_checksum(&mem, 100), state_checksum(&mem, 101));
+        assert_ne!(state_checksum(&mem, 100), state_checksum(&[1u8; 16], 100));
+    }
+}
@@ -0,0 +1,47 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    network::mod.rs
+
+    Host network backends for emulated network cards.
+
+    A network card hands each Ethernet frame the guest transmits to its
+    backend, and polls the backend for frames to deliver to the guest.
+*/
+
+pub mod packet;
+pub mod slirp;
+
+/// A host network backend exchanging raw Ethernet frames with an emulated network card.
+pub trait NetworkBackend: Send {
+    /// Send a frame transmitted by the guest.
+    fn send(&mut self, frame: &[u8]);
+    /// Return the next frame to be received by the guest, if any.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+    /// Service host connections. Called once per emulated frame.
+    fn poll(&mut self);
+}
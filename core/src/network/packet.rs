@@ -0,0 +1,394 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    network::packet.rs
+
+    Parsing and construction of the Ethernet, ARP, IPv4, UDP and TCP packets
+    handled by the user-mode network backend.
+
+    Only what the backend needs is supported. IP options are skipped and
+    fragmented IP packets are not reassembled.
+*/
+
+pub type MacAddress = [u8; 6];
+pub type Ipv4Address = [u8; 4];
+
+pub const BROADCAST_MAC: MacAddress = [0xFF; 6];
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+pub const ETHERNET_HEADER_LEN: usize = 14;
+/// Minimum length of an Ethernet frame, excluding the frame check sequence.
+pub const ETHERNET_MIN_LEN: usize = 60;
+
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_HEADER_LEN: usize = 20;
+const TCP_OPTION_MSS: u8 = 2;
+const IP_DEFAULT_TTL: u8 = 64;
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Add data to a running ones' complement sum.
+fn sum_words(data: &[u8], mut sum: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+fn fold_sum(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Calculate the Internet checksum of the data.
+pub fn checksum(data: &[u8]) -> u16 {
+    fold_sum(sum_words(data, 0))
+}
+
+/// Calculate the checksum of a TCP or UDP segment, including the IPv4 pseudo-header.
+fn transport_checksum(src: Ipv4Address, dst: Ipv4Address, protocol: u8, segment: &[u8]) -> u16 {
+    let mut sum = sum_words(&src, 0);
+    sum = sum_words(&dst, sum);
+    sum += protocol as u32;
+    sum += segment.len() as u32;
+    fold_sum(sum_words(segment, sum))
+}
+
+pub struct EthernetFrame<'a> {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < ETHERNET_HEADER_LEN {
+            return None
+        }
+        Some(Self {
+            dst: data[0..6].try_into().ok()?,
+            src: data[6..12].try_into().ok()?,
+            ethertype: read_u16(data, 12),
+            payload: &data[ETHERNET_HEADER_LEN..],
+        })
+    }
+}
+
+/// Build an Ethernet frame, padded to the minimum frame length.
+pub fn build_ethernet(dst: MacAddress, src: MacAddress, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity((ETHERNET_HEADER_LEN + payload.len()).max(ETHERNET_MIN_LEN));
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if frame.len() < ETHERNET_MIN_LEN {
+        frame.resize(ETHERNET_MIN_LEN, 0);
+    }
+    frame
+}
+
+/// An ARP request or reply for IPv4 over Ethernet.
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+
+impl ArpPacket {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        // Hardware type Ethernet, protocol IPv4, address lengths 6 and 4
+        if data.len() < 28 || read_u16(data, 0) != 1 || read_u16(data, 2) != ETHERTYPE_IPV4 || data[4] != 6 || data[5] != 4 {
+            return None
+        }
+        Some(Self {
+            operation: read_u16(data, 6),
+            sender_mac: data[8..14].try_into().ok()?,
+            sender_ip: data[14..18].try_into().ok()?,
+            target_mac: data[18..24].try_into().ok()?,
+            target_ip: data[24..28].try_into().ok()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(28);
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data.extend_from_slice(&[6, 4]);
+        data.extend_from_slice(&self.operation.to_be_bytes());
+        data.extend_from_slice(&self.sender_mac);
+        data.extend_from_slice(&self.sender_ip);
+        data.extend_from_slice(&self.target_mac);
+        data.extend_from_slice(&self.target_ip);
+        data
+    }
+}
+
+pub struct Ipv4Packet<'a> {
+    pub src: Ipv4Address,
+    pub dst: Ipv4Address,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parse an IPv4 packet. Returns None for malformed packets and fragments.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < IPV4_HEADER_LEN || data[0] >> 4 != 4 {
+            return None
+        }
+        let header_len = ((data[0] & 0x0F) as usize) * 4;
+        let total_len = read_u16(data, 2) as usize;
+        if header_len < IPV4_HEADER_LEN || total_len < header_len || total_len > data.len() {
+            return None
+        }
+        // More fragments flag, or a fragment offset
+        if read_u16(data, 6) & 0x3FFF != 0 {
+            return None
+        }
+        Some(Self {
+            src: data[12..16].try_into().ok()?,
+            dst: data[16..20].try_into().ok()?,
+            protocol: data[9],
+            payload: &data[header_len..total_len],
+        })
+    }
+}
+
+pub fn build_ipv4(src: Ipv4Address, dst: Ipv4Address, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = (IPV4_HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&0x4000u16.to_be_bytes()); // Don't fragment
+    packet.extend_from_slice(&[IP_DEFAULT_TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&src);
+    packet.extend_from_slice(&dst);
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+pub struct UdpDatagram<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < UDP_HEADER_LEN {
+            return None
+        }
+        let len = read_u16(data, 4) as usize;
+        if len < UDP_HEADER_LEN || len > data.len() {
+            return None
+        }
+        Some(Self {
+            src_port: read_u16(data, 0),
+            dst_port: read_u16(data, 2),
+            payload: &data[UDP_HEADER_LEN..len],
+        })
+    }
+}
+
+pub fn build_udp(src: Ipv4Address, dst: Ipv4Address, src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let len = (UDP_HEADER_LEN + payload.len()) as u16;
+    let mut segment = Vec::with_capacity(len as usize);
+    segment.extend_from_slice(&src_port.to_be_bytes());
+    segment.extend_from_slice(&dst_port.to_be_bytes());
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+    let sum = match transport_checksum(src, dst, IP_PROTO_UDP, &segment) {
+        // A computed checksum of 0 is sent as all ones, as 0 means no checksum
+        0 => 0xFFFF,
+        sum => sum
+    };
+    segment[6..8].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+pub struct TcpSegment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < TCP_HEADER_LEN {
+            return None
+        }
+        let header_len = ((data[12] >> 4) as usize) * 4;
+        if header_len < TCP_HEADER_LEN || header_len > data.len() {
+            return None
+        }
+
+        // Look for a maximum segment size option
+        let mut mss = None;
+        let mut options = &data[TCP_HEADER_LEN..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        break
+                    }
+                    if kind == TCP_OPTION_MSS && len == 4 {
+                        mss = Some(read_u16(options, 2));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        Some(Self {
+            src_port: read_u16(data, 0),
+            dst_port: read_u16(data, 2),
+            seq: read_u32(data, 4),
+            ack: read_u32(data, 8),
+            flags: data[13],
+            window: read_u16(data, 14),
+            mss,
+            payload: &data[header_len..],
+        })
+    }
+}
+
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+}
+
+pub fn build_tcp(src: Ipv4Address, dst: Ipv4Address, header: &TcpHeader, payload: &[u8]) -> Vec<u8> {
+    let options_len = if header.mss.is_some() { 4 } else { 0 };
+    let header_len = TCP_HEADER_LEN + options_len;
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&header.src_port.to_be_bytes());
+    segment.extend_from_slice(&header.dst_port.to_be_bytes());
+    segment.extend_from_slice(&header.seq.to_be_bytes());
+    segment.extend_from_slice(&header.ack.to_be_bytes());
+    segment.extend_from_slice(&[((header_len / 4) as u8) << 4, header.flags]);
+    segment.extend_from_slice(&header.window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]); // Checksum and urgent pointer
+    if let Some(mss) = header.mss {
+        segment.extend_from_slice(&[TCP_OPTION_MSS, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    let sum = transport_checksum(src, dst, IP_PROTO_TCP, &segment);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        // Example IPv4 header with its checksum field zeroed
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+            0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7
+        ];
+        assert_eq!(checksum(&header), 0xB861);
+    }
+
+    #[test]
+    fn test_tcp_roundtrip() {
+        let src = [10, 0, 2, 2];
+        let dst = [10, 0, 2, 15];
+        let header = TcpHeader {
+            src_port: 80,
+            dst_port: 1024,
+            seq: 1000,
+            ack: 2000,
+            flags: TCP_SYN | TCP_ACK,
+            window: 8192,
+            mss: Some(1460),
+        };
+        let segment = build_tcp(src, dst, &header, b"hello");
+        let packet = build_ipv4(src, dst, IP_PROTO_TCP, 1, &segment);
+        assert_eq!(checksum(&packet[..20]), 0);
+
+        let ip = Ipv4Packet::parse(&packet).unwrap();
+        assert_eq!(ip.protocol, IP_PROTO_TCP);
+        assert_eq!(transport_checksum(ip.src, ip.dst, IP_PROTO_TCP, ip.payload), 0);
+
+        let tcp = TcpSegment::parse(ip.payload).unwrap();
+        assert_eq!((tcp.src_port, tcp.dst_port, tcp.seq, tcp.ack), (80, 1024, 1000, 2000));
+        assert_eq!(tcp.flags, TCP_SYN | TCP_ACK);
+        assert_eq!(tcp.mss, Some(1460));
+        assert_eq!(tcp.payload, b"hello");
+    }
+}
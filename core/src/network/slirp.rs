@@ -0,0 +1,837 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    network::slirp.rs
+
+    Implements a user-mode NAT network backend in the style of SLiRP.
+
+    The guest sees a private 10.0.2.0/24 network. A virtual gateway at
+    10.0.2.2 answers ARP, DHCP and pings, and 10.0.2.3 acts as a DNS server
+    that resolves names through the host. Connections to 10.0.2.2 are made
+    to the host itself.
+
+    The guest's TCP connections and UDP datagrams are carried over ordinary
+    host sockets, so no privileges or host configuration are needed. Only
+    outgoing connections are supported, and pings to hosts other than the
+    virtual gateway and DNS server are not answered.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::network::{packet::*, NetworkBackend};
+
+pub const GUEST_IP: Ipv4Address = [10, 0, 2, 15];
+pub const GATEWAY_IP: Ipv4Address = [10, 0, 2, 2];
+pub const DNS_IP: Ipv4Address = [10, 0, 2, 3];
+pub const NETMASK: Ipv4Address = [255, 255, 255, 0];
+pub const GATEWAY_MAC: MacAddress = [0x52, 0x55, 0x0A, 0x00, 0x02, 0x02];
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+const DHCP_LEASE_SECS: u32 = 86400;
+const DNS_PORT: u16 = 53;
+
+const TCP_MSS: usize = 1460;
+const TCP_WINDOW: u16 = 8192;
+/// Maximum data buffered for the guest to write to a host socket.
+const TCP_MAX_PENDING: usize = 65536;
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const TCP_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_QUEUED_FRAMES: usize = 256;
+
+fn is_local(addr: Ipv4Address) -> bool {
+    addr[0..3] == GUEST_IP[0..3]
+}
+
+/// Translate an address as seen by the guest to the host address to use.
+fn guest_to_host(addr: Ipv4Address) -> Ipv4Addr {
+    match addr {
+        GATEWAY_IP => Ipv4Addr::LOCALHOST,
+        _ => Ipv4Addr::from(addr),
+    }
+}
+
+/// Translate a host address to the address the guest sees.
+fn host_to_guest(addr: Ipv4Addr) -> Ipv4Address {
+    match addr.is_loopback() {
+        true => GATEWAY_IP,
+        false => addr.octets(),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TcpState {
+    /// Waiting for the host connection to be established.
+    Connecting,
+    /// SYN-ACK sent to the guest, waiting for its ACK.
+    SynReceived,
+    Established,
+}
+
+#[derive(Copy, Clone, Hash, PartialEq, Eq)]
+struct TcpKey {
+    guest_port: u16,
+    remote_ip: Ipv4Address,
+    remote_port: u16,
+}
+
+struct TcpConnection {
+    state: TcpState,
+    connect_rx: Option<Receiver<std::io::Result<TcpStream>>>,
+    stream: Option<TcpStream>,
+    /// Next sequence number expected from the guest.
+    guest_next: u32,
+    /// Oldest sequence number not yet acknowledged by the guest.
+    una: u32,
+    /// Data read from the host, starting at `una`.
+    unacked: VecDeque<u8>,
+    /// Number of bytes of `unacked` sent to the guest.
+    sent: usize,
+    guest_window: usize,
+    mss: usize,
+    last_send: Instant,
+    /// Data from the guest not yet written to the host.
+    pending: Vec<u8>,
+    host_eof: bool,
+    fin_sent: bool,
+    guest_fin: bool,
+}
+
+impl TcpConnection {
+    /// The sequence number of the next segment sent.
+    fn next_seq(&self) -> u32 {
+        self.una.wrapping_add(self.sent as u32)
+    }
+}
+
+struct UdpBinding {
+    socket: UdpSocket,
+    last_used: Instant,
+}
+
+struct DnsLookup {
+    guest_port: u16,
+    query: Vec<u8>,
+    result_rx: Receiver<Option<Vec<Ipv4Addr>>>,
+}
+
+pub struct SlirpBackend {
+    guest_mac: MacAddress,
+    to_guest: VecDeque<Vec<u8>>,
+    ip_id: u16,
+    isn: u32,
+    tcp: HashMap<TcpKey, TcpConnection>,
+    udp: HashMap<u16, UdpBinding>,
+    dns: Vec<DnsLookup>,
+}
+
+impl SlirpBackend {
+    pub fn new() -> Self {
+        let isn = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        Self {
+            guest_mac: BROADCAST_MAC,
+            to_guest: VecDeque::new(),
+            ip_id: 0,
+            isn,
+            tcp: HashMap::new(),
+            udp: HashMap::new(),
+            dns: Vec::new(),
+        }
+    }
+
+    fn queue_frame(&mut self, frame: Vec<u8>) {
+        if self.to_guest.len() < MAX_QUEUED_FRAMES {
+            self.to_guest.push_back(frame);
+        }
+    }
+
+    fn send_ipv4(&mut self, src: Ipv4Address, dst: Ipv4Address, protocol: u8, payload: &[u8]) {
+        self.ip_id = self.ip_id.wrapping_add(1);
+        let packet = build_ipv4(src, dst, protocol, self.ip_id, payload);
+        let dst_mac = match dst {
+            [255, 255, 255, 255] => BROADCAST_MAC,
+            _ => self.guest_mac,
+        };
+        self.queue_frame(build_ethernet(dst_mac, GATEWAY_MAC, ETHERTYPE_IPV4, &packet));
+    }
+
+    fn send_udp(&mut self, src: Ipv4Address, src_port: u16, dst: Ipv4Address, dst_port: u16, payload: &[u8]) {
+        let segment = build_udp(src, dst, src_port, dst_port, payload);
+        self.send_ipv4(src, dst, IP_PROTO_UDP, &segment);
+    }
+
+    fn send_tcp(&mut self, key: TcpKey, seq: u32, ack: u32, flags: u8, mss: Option<u16>, payload: &[u8]) {
+        let header = TcpHeader {
+            src_port: key.remote_port,
+            dst_port: key.guest_port,
+            seq,
+            ack,
+            flags,
+            window: TCP_WINDOW,
+            mss,
+        };
+        let segment = build_tcp(key.remote_ip, GUEST_IP, &header, payload);
+        self.send_ipv4(key.remote_ip, GUEST_IP, IP_PROTO_TCP, &segment);
+    }
+
+    fn handle_arp(&mut self, payload: &[u8]) {
+        let Some(arp) = ArpPacket::parse(payload) else {
+            return
+        };
+        if arp.operation != ARP_REQUEST || !is_local(arp.target_ip) || arp.target_ip == GUEST_IP {
+            return
+        }
+        let reply = ArpPacket {
+            operation: ARP_REPLY,
+            sender_mac: GATEWAY_MAC,
+            sender_ip: arp.target_ip,
+            target_mac: arp.sender_mac,
+            target_ip: arp.sender_ip,
+        };
+        self.queue_frame(build_ethernet(arp.sender_mac, GATEWAY_MAC, ETHERTYPE_ARP, &reply.to_bytes()));
+    }
+
+    fn handle_ipv4(&mut self, payload: &[u8]) {
+        let Some(ip) = Ipv4Packet::parse(payload) else {
+            return
+        };
+        match ip.protocol {
+            IP_PROTO_ICMP => self.handle_icmp(&ip),
+            IP_PROTO_UDP => self.handle_udp(&ip),
+            IP_PROTO_TCP => self.handle_tcp(&ip),
+            _ => {}
+        }
+    }
+
+    fn handle_icmp(&mut self, ip: &Ipv4Packet) {
+        // Answer echo requests to the virtual gateway and DNS server.
+        if ip.payload.len() < 8 || ip.payload[0] != 8 || !matches!(ip.dst, GATEWAY_IP | DNS_IP) {
+            return
+        }
+        let mut reply = ip.payload.to_vec();
+        reply[0] = 0;
+        reply[2..4].fill(0);
+        let sum = checksum(&reply);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        self.send_ipv4(ip.dst, ip.src, IP_PROTO_ICMP, &reply);
+    }
+
+    fn handle_udp(&mut self, ip: &Ipv4Packet) {
+        let Some(udp) = UdpDatagram::parse(ip.payload) else {
+            return
+        };
+        if udp.dst_port == DHCP_SERVER_PORT {
+            self.handle_dhcp(udp.payload);
+        }
+        else if ip.dst == DNS_IP && udp.dst_port == DNS_PORT {
+            self.handle_dns(udp.src_port, udp.payload);
+        }
+        else if ip.dst == GATEWAY_IP || !is_local(ip.dst) {
+            let binding = match self.udp.entry(udp.src_port) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    let Ok(socket) = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) else {
+                        return
+                    };
+                    _ = socket.set_nonblocking(true);
+                    _ = socket.set_broadcast(true);
+                    entry.insert(UdpBinding { socket, last_used: Instant::now() })
+                }
+            };
+            binding.last_used = Instant::now();
+            let dst = SocketAddrV4::new(guest_to_host(ip.dst), udp.dst_port);
+            _ = binding.socket.send_to(udp.payload, dst);
+        }
+    }
+
+    fn handle_dhcp(&mut self, payload: &[u8]) {
+        if payload.len() < 240 || payload[0] != 1 || payload[236..240] != DHCP_MAGIC {
+            return
+        }
+
+        // Find the message type option
+        let mut message_type = None;
+        let mut options = &payload[240..];
+        while let Some(&code) = options.first() {
+            match code {
+                0 => options = &options[1..],
+                255 => break,
+                _ => {
+                    let Some(&len) = options.get(1) else {
+                        break
+                    };
+                    let len = len as usize;
+                    if options.len() < len + 2 {
+                        break
+                    }
+                    if code == 53 && len == 1 {
+                        message_type = Some(options[2]);
+                    }
+                    options = &options[len + 2..];
+                }
+            }
+        }
+
+        let reply_type = match message_type {
+            Some(1) => 2, // DISCOVER -> OFFER
+            Some(3) => 5, // REQUEST -> ACK
+            _ => return,
+        };
+
+        let mut reply = vec![0u8; 240];
+        reply[0] = 2; // BOOTREPLY
+        reply[1] = 1; // Ethernet
+        reply[2] = 6;
+        reply[4..8].copy_from_slice(&payload[4..8]); // Transaction id
+        reply[10..12].copy_from_slice(&payload[10..12]); // Flags
+        reply[16..20].copy_from_slice(&GUEST_IP);
+        reply[20..24].copy_from_slice(&GATEWAY_IP);
+        reply[28..44].copy_from_slice(&payload[28..44]); // Client hardware address
+        reply[236..240].copy_from_slice(&DHCP_MAGIC);
+        reply.extend_from_slice(&[53, 1, reply_type]);
+        reply.extend_from_slice(&[54, 4]);
+        reply.extend_from_slice(&GATEWAY_IP);
+        reply.extend_from_slice(&[51, 4]);
+        reply.extend_from_slice(&DHCP_LEASE_SECS.to_be_bytes());
+        reply.extend_from_slice(&[1, 4]);
+        reply.extend_from_slice(&NETMASK);
+        reply.extend_from_slice(&[3, 4]);
+        reply.extend_from_slice(&GATEWAY_IP);
+        reply.extend_from_slice(&[6, 4]);
+        reply.extend_from_slice(&DNS_IP);
+        reply.push(255);
+
+        self.send_udp(GATEWAY_IP, DHCP_SERVER_PORT, [255, 255, 255, 255], DHCP_CLIENT_PORT, &reply);
+    }
+
+    fn handle_dns(&mut self, guest_port: u16, query: &[u8]) {
+        // Only standard queries with a single question are resolved.
+        if query.len() < 12 || query[2] & 0xF8 != 0 || query[4..6] != [0, 1] {
+            return
+        }
+        let Some((name, qtype, _)) = parse_dns_question(query) else {
+            return
+        };
+        if qtype != 1 {
+            // Not an A record query. Answer with no records.
+            let response = build_dns_response(query, &[]);
+            self.send_udp(DNS_IP, DNS_PORT, GUEST_IP, guest_port, &response);
+            return
+        }
+
+        let (result_tx, result_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = (name.as_str(), 0).to_socket_addrs().ok().map(|addrs| {
+                addrs
+                    .filter_map(|addr| match addr.ip() {
+                        IpAddr::V4(v4) => Some(v4),
+                        IpAddr::V6(_) => None,
+                    })
+                    .collect()
+            });
+            _ = result_tx.send(result);
+        });
+        self.dns.push(DnsLookup {
+            guest_port,
+            query: query.to_vec(),
+            result_rx,
+        });
+    }
+
+    fn handle_tcp(&mut self, ip: &Ipv4Packet) {
+        let Some(seg) = TcpSegment::parse(ip.payload) else {
+            return
+        };
+        let key = TcpKey {
+            guest_port: seg.src_port,
+            remote_ip: ip.dst,
+            remote_port: seg.dst_port,
+        };
+
+        if !self.tcp.contains_key(&key) {
+            if seg.flags & TCP_RST != 0 {
+                return
+            }
+            if seg.flags & (TCP_SYN | TCP_ACK) != TCP_SYN || (is_local(ip.dst) && ip.dst != GATEWAY_IP) {
+                // Reset segments for unknown connections, and connections to other local addresses.
+                let seq = if seg.flags & TCP_ACK != 0 { seg.ack } else { 0 };
+                let ack = seg.seq.wrapping_add(seg.payload.len() as u32).wrapping_add((seg.flags & TCP_SYN != 0) as u32);
+                self.send_tcp(key, seq, ack, TCP_RST | TCP_ACK, None, &[]);
+                return
+            }
+
+            let (connect_tx, connect_rx) = mpsc::channel();
+            let addr = SocketAddr::V4(SocketAddrV4::new(guest_to_host(ip.dst), seg.dst_port));
+            thread::spawn(move || {
+                _ = connect_tx.send(TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT));
+            });
+
+            self.isn = self.isn.wrapping_add(0x10000);
+            self.tcp.insert(
+                key,
+                TcpConnection {
+                    state: TcpState::Connecting,
+                    connect_rx: Some(connect_rx),
+                    stream: None,
+                    guest_next: seg.seq.wrapping_add(1),
+                    una: self.isn,
+                    unacked: VecDeque::new(),
+                    sent: 0,
+                    guest_window: seg.window as usize,
+                    mss: seg.mss.map_or(536, |mss| mss as usize).min(TCP_MSS),
+                    last_send: Instant::now(),
+                    pending: Vec::new(),
+                    host_eof: false,
+                    fin_sent: false,
+                    guest_fin: false,
+                },
+            );
+            return
+        }
+
+        let conn = self.tcp.get_mut(&key).unwrap();
+        if seg.flags & TCP_RST != 0 {
+            self.tcp.remove(&key);
+            return
+        }
+        if seg.flags & TCP_SYN != 0 {
+            // Retransmitted SYN; the SYN-ACK will be retransmitted by poll().
+            return
+        }
+        if conn.state == TcpState::Connecting {
+            return
+        }
+
+        if seg.flags & TCP_ACK != 0 {
+            let acked = seg.ack.wrapping_sub(conn.una) as usize;
+            if conn.state == TcpState::SynReceived {
+                // The guest acknowledges our SYN.
+                if acked == 1 {
+                    conn.una = conn.una.wrapping_add(1);
+                    conn.state = TcpState::Established;
+                }
+            }
+            else if acked <= conn.sent {
+                // Data bytes acknowledged, plus one for our FIN if it was sent.
+                let data_acked = acked.min(conn.unacked.len());
+                conn.unacked.drain(..data_acked);
+                conn.sent -= acked;
+                conn.una = seg.ack;
+            }
+            conn.guest_window = seg.window as usize;
+        }
+        if conn.state != TcpState::Established {
+            return
+        }
+
+        let mut send_ack = false;
+        if seg.seq == conn.guest_next {
+            if !seg.payload.is_empty() && conn.pending.len() + seg.payload.len() <= TCP_MAX_PENDING && !conn.guest_fin {
+                conn.pending.extend_from_slice(seg.payload);
+                conn.guest_next = conn.guest_next.wrapping_add(seg.payload.len() as u32);
+                send_ack = true;
+            }
+            if seg.flags & TCP_FIN != 0 && conn.guest_next == seg.seq.wrapping_add(seg.payload.len() as u32) {
+                conn.guest_fin = true;
+                conn.guest_next = conn.guest_next.wrapping_add(1);
+                send_ack = true;
+            }
+        }
+        else if !seg.payload.is_empty() || seg.flags & TCP_FIN != 0 {
+            // Out of order segment; acknowledge what we have so the guest retransmits.
+            send_ack = true;
+        }
+
+        let (seq, ack) = (conn.next_seq(), conn.guest_next);
+        let finished = conn.guest_fin && conn.fin_sent && conn.sent == 0 && conn.unacked.is_empty();
+        if send_ack {
+            self.send_tcp(key, seq, ack, TCP_ACK, None, &[]);
+        }
+        if finished {
+            self.tcp.remove(&key);
+        }
+    }
+
+    fn poll_tcp(&mut self) {
+        let keys: Vec<TcpKey> = self.tcp.keys().copied().collect();
+        for key in keys {
+            let mut segments = Vec::new();
+            let mut close = false;
+            let conn = self.tcp.get_mut(&key).unwrap();
+
+            if let Some(connect_rx) = &conn.connect_rx {
+                match connect_rx.try_recv() {
+                    Ok(Ok(stream)) => {
+                        _ = stream.set_nonblocking(true);
+                        _ = stream.set_nodelay(true);
+                        conn.stream = Some(stream);
+                        conn.connect_rx = None;
+                        conn.state = TcpState::SynReceived;
+                        conn.last_send = Instant::now();
+                        segments.push((conn.una, TCP_SYN | TCP_ACK, Some(TCP_MSS as u16), Vec::new()));
+                    }
+                    Ok(Err(_)) | Err(TryRecvError::Disconnected) => {
+                        segments.push((0, TCP_RST | TCP_ACK, None, Vec::new()));
+                        close = true;
+                    }
+                    Err(TryRecvError::Empty) => {}
+                }
+            }
+            else if conn.state == TcpState::SynReceived {
+                if conn.last_send.elapsed() >= TCP_RETRANSMIT_TIMEOUT {
+                    conn.last_send = Instant::now();
+                    segments.push((conn.una, TCP_SYN | TCP_ACK, Some(TCP_MSS as u16), Vec::new()));
+                }
+            }
+            else if let Some(stream) = &mut conn.stream {
+                // Write guest data to the host.
+                while !conn.pending.is_empty() {
+                    match stream.write(&conn.pending) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            conn.pending.drain(..n);
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(_) => {
+                            close = true;
+                            break
+                        }
+                    }
+                }
+                if conn.guest_fin && conn.pending.is_empty() {
+                    _ = stream.shutdown(Shutdown::Write);
+                }
+
+                // Read host data, keeping no more buffered than the guest will accept.
+                let mut buf = [0u8; 4096];
+                while !conn.host_eof && conn.unacked.len() < conn.guest_window.max(TCP_MSS) {
+                    match stream.read(&mut buf) {
+                        Ok(0) => conn.host_eof = true,
+                        Ok(n) => conn.unacked.extend(&buf[..n]),
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(_) => {
+                            close = true;
+                            break
+                        }
+                    }
+                }
+
+                // Go back and resend everything unacknowledged after a timeout.
+                if conn.sent > 0 && conn.last_send.elapsed() >= TCP_RETRANSMIT_TIMEOUT {
+                    conn.sent = 0;
+                    conn.fin_sent = false;
+                }
+
+                while conn.sent < conn.unacked.len() && conn.sent < conn.guest_window {
+                    let len = (conn.unacked.len() - conn.sent).min(conn.mss).min(conn.guest_window - conn.sent);
+                    let data: Vec<u8> = conn.unacked.range(conn.sent..conn.sent + len).copied().collect();
+                    segments.push((conn.next_seq(), TCP_ACK | TCP_PSH, None, data));
+                    conn.sent += len;
+                    conn.last_send = Instant::now();
+                }
+                if conn.host_eof && !conn.fin_sent && conn.sent == conn.unacked.len() {
+                    segments.push((conn.next_seq(), TCP_FIN | TCP_ACK, None, Vec::new()));
+                    conn.sent += 1;
+                    conn.fin_sent = true;
+                    conn.last_send = Instant::now();
+                }
+
+                if close {
+                    segments.push((conn.next_seq(), TCP_RST | TCP_ACK, None, Vec::new()));
+                }
+            }
+
+            let ack = conn.guest_next;
+            for (seq, flags, mss, data) in segments {
+                self.send_tcp(key, seq, ack, flags, mss, &data);
+            }
+            if close {
+                self.tcp.remove(&key);
+            }
+        }
+    }
+
+    fn poll_udp(&mut self) {
+        let mut buf = [0u8; 2048];
+        let mut datagrams = Vec::new();
+        self.udp.retain(|&guest_port, binding| {
+            while let Ok((len, addr)) = binding.socket.recv_from(&mut buf) {
+                if let SocketAddr::V4(addr) = addr {
+                    binding.last_used = Instant::now();
+                    datagrams.push((host_to_guest(*addr.ip()), addr.port(), guest_port, buf[..len].to_vec()));
+                }
+            }
+            binding.last_used.elapsed() < UDP_IDLE_TIMEOUT
+        });
+        for (src, src_port, guest_port, data) in datagrams {
+            self.send_udp(src, src_port, GUEST_IP, guest_port, &data);
+        }
+    }
+
+    fn poll_dns(&mut self) {
+        let mut responses = Vec::new();
+        self.dns.retain(|lookup| match lookup.result_rx.try_recv() {
+            Ok(result) => {
+                responses.push((lookup.guest_port, build_dns_response(&lookup.query, &result.unwrap_or_default())));
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => false,
+        });
+        for (guest_port, response) in responses {
+            self.send_udp(DNS_IP, DNS_PORT, GUEST_IP, guest_port, &response);
+        }
+    }
+}
+
+impl NetworkBackend for SlirpBackend {
+    fn send(&mut self, frame: &[u8]) {
+        let Some(eth) = EthernetFrame::parse(frame) else {
+            return
+        };
+        if eth.dst != GATEWAY_MAC && eth.dst != BROADCAST_MAC {
+            return
+        }
+        self.guest_mac = eth.src;
+        match eth.ethertype {
+            ETHERTYPE_ARP => self.handle_arp(eth.payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(eth.payload),
+            _ => {}
+        }
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.to_guest.pop_front()
+    }
+
+    fn poll(&mut self) {
+        self.poll_dns();
+        self.poll_udp();
+        self.poll_tcp();
+    }
+}
+
+/// Parse the first question of a DNS query, returning the name, type and the offset of the
+/// end of the question.
+fn parse_dns_question(query: &[u8]) -> Option<(String, u16, usize)> {
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break
+        }
+        if len > 63 {
+            // Compression isn't used in questions
+            return None
+        }
+        labels.push(String::from_utf8_lossy(query.get(pos..pos + len)?).into_owned());
+        pos += len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+    query.get(pos + 3)?;
+    Some((labels.join("."), qtype, pos + 4))
+}
+
+/// Build a response to a DNS query with the specified A records. An empty list of addresses
+/// is answered with a name error.
+fn build_dns_response(query: &[u8], addrs: &[Ipv4Addr]) -> Vec<u8> {
+    let question_end = parse_dns_question(query).map_or(12, |(_, _, end)| end);
+    let mut response = query[..question_end].to_vec();
+    let rcode = if addrs.is_empty() { 3 } else { 0 };
+    response[2] = 0x80 | (query[2] & 0x01); // Response, preserving recursion desired
+    response[3] = 0x80 | rcode; // Recursion available
+    response[6..8].copy_from_slice(&(addrs.len() as u16).to_be_bytes());
+    response[8..12].fill(0);
+    for addr in addrs {
+        // Pointer to the name in the question, type A, class IN, TTL and address
+        response.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+        response.extend_from_slice(&300u32.to_be_bytes());
+        response.extend_from_slice(&[0, 4]);
+        response.extend_from_slice(&addr.octets());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MAC: MacAddress = [0x00, 0x00, 0x1B, 0x12, 0x34, 0x56];
+
+    #[test]
+    fn test_arp() {
+        let mut slirp = SlirpBackend::new();
+        let request = ArpPacket {
+            operation: ARP_REQUEST,
+            sender_mac: TEST_MAC,
+            sender_ip: GUEST_IP,
+            target_mac: [0; 6],
+            target_ip: GATEWAY_IP,
+        };
+        slirp.send(&build_ethernet(BROADCAST_MAC, TEST_MAC, ETHERTYPE_ARP, &request.to_bytes()));
+
+        let frame = slirp.receive().unwrap();
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(eth.dst, TEST_MAC);
+        let reply = ArpPacket::parse(eth.payload).unwrap();
+        assert_eq!(reply.operation, ARP_REPLY);
+        assert_eq!(reply.sender_mac, GATEWAY_MAC);
+        assert_eq!(reply.sender_ip, GATEWAY_IP);
+        assert!(slirp.receive().is_none());
+    }
+
+    #[test]
+    fn test_dhcp() {
+        let mut slirp = SlirpBackend::new();
+        let mut discover = vec![0u8; 240];
+        discover[0] = 1;
+        discover[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        discover[28..34].copy_from_slice(&TEST_MAC);
+        discover[236..240].copy_from_slice(&DHCP_MAGIC);
+        discover.extend_from_slice(&[53, 1, 1, 255]);
+        let udp = build_udp([0; 4], [255; 4], DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &discover);
+        let ip = build_ipv4([0; 4], [255; 4], IP_PROTO_UDP, 1, &udp);
+        slirp.send(&build_ethernet(BROADCAST_MAC, TEST_MAC, ETHERTYPE_IPV4, &ip));
+
+        let frame = slirp.receive().unwrap();
+        let eth = EthernetFrame::parse(&frame).unwrap();
+        let ip = Ipv4Packet::parse(eth.payload).unwrap();
+        assert_eq!(ip.src, GATEWAY_IP);
+        let udp = UdpDatagram::parse(ip.payload).unwrap();
+        assert_eq!(udp.dst_port, DHCP_CLIENT_PORT);
+        let offer = udp.payload;
+        assert_eq!(offer[0], 2);
+        assert_eq!(offer[4..8], [1, 2, 3, 4]);
+        assert_eq!(offer[16..20], GUEST_IP);
+        assert_eq!(offer[240..243], [53, 1, 2]);
+    }
+
+    #[test]
+    fn test_dns_response() {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        let (name, qtype, end) = parse_dns_question(&query).unwrap();
+        assert_eq!(name, "example.com");
+        assert_eq!(qtype, 1);
+        assert_eq!(end, query.len());
+
+        let response = build_dns_response(&query, &[Ipv4Addr::new(93, 184, 216, 34)]);
+        assert_eq!(response[0..2], [0x12, 0x34]);
+        assert_eq!(response[3] & 0x0F, 0);
+        assert_eq!(response[6..8], [0, 1]);
+        assert_eq!(response[response.len() - 4..], [93, 184, 216, 34]);
+
+        let response = build_dns_response(&query, &[]);
+        assert_eq!(response[3] & 0x0F, 3);
+    }
+
+    fn send_guest_tcp(slirp: &mut SlirpBackend, dst_port: u16, seq: u32, ack: u32, flags: u8, payload: &[u8]) {
+        let header = TcpHeader {
+            src_port: 1024,
+            dst_port,
+            seq,
+            ack,
+            flags,
+            window: 4096,
+            mss: None,
+        };
+        let segment = build_tcp(GUEST_IP, GATEWAY_IP, &header, payload);
+        let ip = build_ipv4(GUEST_IP, GATEWAY_IP, IP_PROTO_TCP, 1, &segment);
+        slirp.send(&build_ethernet(GATEWAY_MAC, TEST_MAC, ETHERTYPE_IPV4, &ip));
+    }
+
+    /// Poll the backend until it sends a TCP segment to the guest, returning its
+    /// sequence number, acknowledgement number, flags and payload.
+    fn wait_guest_tcp(slirp: &mut SlirpBackend) -> (u32, u32, u8, Vec<u8>) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            slirp.poll();
+            if let Some(frame) = slirp.receive() {
+                let eth = EthernetFrame::parse(&frame).unwrap();
+                let ip = Ipv4Packet::parse(eth.payload).unwrap();
+                let tcp = TcpSegment::parse(ip.payload).unwrap();
+                return (tcp.seq, tcp.ack, tcp.flags, tcp.payload.to_vec())
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Timed out waiting for TCP segment");
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut slirp = SlirpBackend::new();
+
+        // Handshake
+        send_guest_tcp(&mut slirp, port, 100, 0, TCP_SYN, &[]);
+        let (mut server, _) = listener.accept().unwrap();
+        let (seq, ack, flags, _) = wait_guest_tcp(&mut slirp);
+        assert_eq!(flags, TCP_SYN | TCP_ACK);
+        assert_eq!(ack, 101);
+        let mut host_seq = seq.wrapping_add(1);
+        send_guest_tcp(&mut slirp, port, 101, host_seq, TCP_ACK, &[]);
+
+        // Guest to host
+        send_guest_tcp(&mut slirp, port, 101, host_seq, TCP_ACK | TCP_PSH, b"hello");
+        let (_, ack, _, _) = wait_guest_tcp(&mut slirp);
+        assert_eq!(ack, 106);
+        slirp.poll();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Host to guest
+        server.write_all(b"world").unwrap();
+        let (seq, _, _, data) = wait_guest_tcp(&mut slirp);
+        assert_eq!(seq, host_seq);
+        assert_eq!(data, b"world");
+        host_seq = host_seq.wrapping_add(5);
+        send_guest_tcp(&mut slirp, port, 106, host_seq, TCP_ACK, &[]);
+
+        // Host closes
+        drop(server);
+        let (seq, _, flags, _) = wait_guest_tcp(&mut slirp);
+        assert_eq!(seq, host_seq);
+        assert_ne!(flags & TCP_FIN, 0);
+        send_guest_tcp(&mut slirp, port, 106, host_seq.wrapping_add(1), TCP_ACK | TCP_FIN, &[]);
+        assert!(slirp.tcp.is_empty());
+    }
+}
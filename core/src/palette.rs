@@ -0,0 +1,237 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    palette.rs
+
+    Selects the palette used to convert CGA color indices to RGB for display.
+    The standard palette uses the values given by most references; the VileR
+    palette is measured from a real IBM 5153 monitor. A custom palette of 16
+    colors can be loaded from a TOML file:
+
+        colors = [
+            "#000000", "#0000AA", "#00AA00", "#00AAAA",
+            "#AA0000", "#AA00AA", "#AA5500", "#AAAAAA",
+            "#555555", "#5555FF", "#55FF55", "#55FFFF",
+            "#FF5555", "#FF55FF", "#FFFF55", "#FFFFFF",
+        ]
+*/
+
+use std::{error::Error, fmt::Display, path::Path, str::FromStr};
+
+use serde_derive::Deserialize;
+
+pub const PALETTE_SIZE: usize = 16;
+
+/// 16 colors as RGB triplets, in CGA color index order.
+pub type PaletteColors = [[u8; 3]; PALETTE_SIZE];
+
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum DisplayPalette {
+    #[default]
+    Standard,
+    VileR,
+    Custom,
+}
+
+impl DisplayPalette {
+    pub const ALL: [DisplayPalette; 3] = [DisplayPalette::Standard, DisplayPalette::VileR, DisplayPalette::Custom];
+}
+
+impl Display for DisplayPalette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayPalette::Standard => write!(f, "Standard"),
+            DisplayPalette::VileR => write!(f, "VileR (IBM 5153)"),
+            DisplayPalette::Custom => write!(f, "Custom"),
+        }
+    }
+}
+
+impl FromStr for DisplayPalette {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(DisplayPalette::Standard),
+            "viler" | "5153" => Ok(DisplayPalette::VileR),
+            "custom" => Ok(DisplayPalette::Custom),
+            _ => Err("Bad value for palette".to_string()),
+        }
+    }
+}
+
+/// Phosphor colors for simulating a monochrome composite monitor.
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum MonochromePhosphor {
+    #[default]
+    Green,
+    Amber,
+    White,
+}
+
+impl MonochromePhosphor {
+    pub const ALL: [MonochromePhosphor; 3] = [MonochromePhosphor::Green, MonochromePhosphor::Amber, MonochromePhosphor::White];
+
+    /// The color of the phosphor at full brightness.
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            MonochromePhosphor::Green => [0x33, 0xFF, 0x33],
+            MonochromePhosphor::Amber => [0xFF, 0xB0, 0x00],
+            MonochromePhosphor::White => [0xEE, 0xEE, 0xF4],
+        }
+    }
+}
+
+impl Display for MonochromePhosphor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonochromePhosphor::Green => write!(f, "Green"),
+            MonochromePhosphor::Amber => write!(f, "Amber"),
+            MonochromePhosphor::White => write!(f, "White"),
+        }
+    }
+}
+
+impl FromStr for MonochromePhosphor {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "green" => Ok(MonochromePhosphor::Green),
+            "amber" => Ok(MonochromePhosphor::Amber),
+            "white" => Ok(MonochromePhosphor::White),
+            _ => Err("Bad value for monochrome".to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    Parse(String),
+    BadColor(String),
+    WrongLength(usize),
+}
+
+impl Error for PaletteError {}
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::Io(e) => write!(f, "Couldn't read palette file: {}", e),
+            PaletteError::Parse(e) => write!(f, "Couldn't parse palette file: {}", e),
+            PaletteError::BadColor(s) => write!(f, "Invalid color in palette file: {}", s),
+            PaletteError::WrongLength(n) => write!(f, "Palette file has {} colors, expected {}", n, PALETTE_SIZE),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PaletteFile {
+    colors: Vec<String>,
+}
+
+/// Parse a color in the form "#RRGGBB" or "RRGGBB".
+fn parse_color(s: &str) -> Result<[u8; 3], PaletteError> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(PaletteError::BadColor(s.to_string()));
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| PaletteError::BadColor(s.to_string()))?;
+    Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+/// Parse a palette from the contents of a TOML palette file.
+pub fn parse_palette(toml_str: &str) -> Result<PaletteColors, PaletteError> {
+    let file: PaletteFile = toml::from_str(toml_str).map_err(|e| PaletteError::Parse(e.to_string()))?;
+    palette_from_file(file)
+}
+
+/// Parse a palette from the contents of a JSON palette file.
+pub fn parse_palette_json(json_str: &str) -> Result<PaletteColors, PaletteError> {
+    let file: PaletteFile = serde_json::from_str(json_str).map_err(|e| PaletteError::Parse(e.to_string()))?;
+    palette_from_file(file)
+}
+
+fn palette_from_file(file: PaletteFile) -> Result<PaletteColors, PaletteError> {
+    if file.colors.len() != PALETTE_SIZE {
+        return Err(PaletteError::WrongLength(file.colors.len()));
+    }
+    let mut colors = [[0; 3]; PALETTE_SIZE];
+    for (color, s) in colors.iter_mut().zip(file.colors.iter()) {
+        *color = parse_color(s)?;
+    }
+    Ok(colors)
+}
+
+/// Load a palette file. Files with a .json extension are parsed as JSON, anything else as TOML.
+pub fn load_palette_file(path: &Path) -> Result<PaletteColors, PaletteError> {
+    let palette_str = std::fs::read_to_string(path).map_err(PaletteError::Io)?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("json") => parse_palette_json(&palette_str),
+        _ => parse_palette(&palette_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_palette() {
+        let mut toml_str = String::from("colors = [");
+        for i in 0..PALETTE_SIZE {
+            toml_str.push_str(&format!("\"#{:02X}{:02X}{:02X}\", ", i * 16, i, 0xFF - i));
+        }
+        toml_str.push(']');
+
+        let colors = parse_palette(&toml_str).unwrap();
+        assert_eq!(colors[0], [0x00, 0x00, 0xFF]);
+        assert_eq!(colors[15], [0xF0, 0x0F, 0xF0]);
+
+        assert!(matches!(parse_palette("colors = [\"#000000\"]"), Err(PaletteError::WrongLength(1))));
+        assert!(matches!(
+            parse_palette(&toml_str.replace("#F00FF0", "#F00FZ0")),
+            Err(PaletteError::BadColor(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_palette_json() {
+        let colors: Vec<String> = (0..PALETTE_SIZE).map(|i| format!("\"{:02x}{:02x}{:02x}\"", i, i, i)).collect();
+        let json_str = format!("{{ \"colors\": [{}] }}", colors.join(", "));
+
+        let colors = parse_palette_json(&json_str).unwrap();
+        assert_eq!(colors[1], [0x01, 0x01, 0x01]);
+        assert_eq!(colors[15], [0x0F, 0x0F, 0x0F]);
+
+        assert!(matches!(parse_palette_json("colors = []"), Err(PaletteError::Parse(_))));
+    }
+}
@@ -0,0 +1,218 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    paste.rs
+
+    Converts text into a stream of XT keyboard scancodes so that text from the
+    host can be typed into the guest, such as a BASIC listing or a DOS command.
+
+    Characters are mapped to keys on a US keyboard, holding shift as needed.
+    Characters outside of ASCII are entered by holding Alt and typing their
+    code in the guest codepage on the numeric keypad, which the BIOS 
+    translates into the character.
+
+    Scancodes are released to the keyboard one at a time with a delay between
+    them, since the PPI has no buffer and the guest must read each scancode
+    before the next arrives.
+*/
+
+use std::collections::VecDeque;
+
+use crate::codepage::Codepage;
+
+pub const DEFAULT_PASTE_DELAY_MS: u32 = 10;
+
+const SCANCODE_RELEASE: u8 = 0x80;
+const SCANCODE_LSHIFT: u8 = 0x2A;
+const SCANCODE_ALT: u8 = 0x38;
+const SCANCODE_ENTER: u8 = 0x1C;
+
+// Numeric keypad scancodes for the digits 0-9
+const KEYPAD_DIGITS: [u8; 10] = [0x52, 0x4F, 0x50, 0x51, 0x4B, 0x4C, 0x4D, 0x47, 0x48, 0x49];
+
+/// Return the scancode of the key that types the specified character on a US keyboard, and 
+/// whether shift must be held.
+pub fn char_to_scancode(c: char) -> Option<(u8, bool)> {
+
+    const ROW_NUMBERS: &[u8] = b"1234567890-=";
+    const ROW_NUMBERS_SHIFT: &[u8] = b"!@#$%^&*()_+";
+    const ROW_TOP: &[u8] = b"qwertyuiop[]";
+    const ROW_TOP_SHIFT: &[u8] = b"QWERTYUIOP{}";
+    const ROW_HOME: &[u8] = b"asdfghjkl;'`";
+    const ROW_HOME_SHIFT: &[u8] = b"ASDFGHJKL:\"~";
+    const ROW_BOTTOM: &[u8] = b"\\zxcvbnm,./";
+    const ROW_BOTTOM_SHIFT: &[u8] = b"|ZXCVBNM<>?";
+
+    let rows: [(&[u8], &[u8], u8); 4] = [
+        (ROW_NUMBERS, ROW_NUMBERS_SHIFT, 0x02),
+        (ROW_TOP, ROW_TOP_SHIFT, 0x10),
+        (ROW_HOME, ROW_HOME_SHIFT, 0x1E),
+        (ROW_BOTTOM, ROW_BOTTOM_SHIFT, 0x2B),
+    ];
+
+    match c {
+        ' ' => return Some((0x39, false)),
+        '\t' => return Some((0x0F, false)),
+        '\n' => return Some((SCANCODE_ENTER, false)),
+        '\x08' => return Some((0x0E, false)),
+        _ if !c.is_ascii() => return None,
+        _ => {}
+    }
+
+    let byte = c as u8;
+    for (keys, shift_keys, base) in rows {
+        if let Some(pos) = keys.iter().position(|&k| k == byte) {
+            return Some((base + pos as u8, false))
+        }
+        if let Some(pos) = shift_keys.iter().position(|&k| k == byte) {
+            return Some((base + pos as u8, true))
+        }
+    }
+    None
+}
+
+/// A queue of scancodes typing out pasted text.
+pub struct PasteQueue {
+    codes: VecDeque<u8>,
+    delay_us: f64,
+    wait_us: f64,
+}
+
+impl PasteQueue {
+    /// Convert text into scancodes to be released `delay_ms` milliseconds apart. Returns the
+    /// queue and the number of characters that could not be typed and were skipped.
+    pub fn new(text: &str, codepage: Codepage, delay_ms: u32) -> (Self, usize) {
+        let mut codes = VecDeque::new();
+        let mut skipped = 0;
+
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            // Type CRLF and lone CRs as a single Enter
+            let c = match c {
+                '\r' if chars.peek() == Some(&'\n') => continue,
+                '\r' => '\n',
+                _ => c
+            };
+
+            if let Some((code, shift)) = char_to_scancode(c) {
+                if shift {
+                    codes.push_back(SCANCODE_LSHIFT);
+                }
+                codes.push_back(code);
+                codes.push_back(code | SCANCODE_RELEASE);
+                if shift {
+                    codes.push_back(SCANCODE_LSHIFT | SCANCODE_RELEASE);
+                }
+            }
+            else if let Some(byte) = codepage.from_char(c).filter(|b| *b > 0) {
+                codes.push_back(SCANCODE_ALT);
+                for digit in byte.to_string().bytes() {
+                    let key = KEYPAD_DIGITS[(digit - b'0') as usize];
+                    codes.push_back(key);
+                    codes.push_back(key | SCANCODE_RELEASE);
+                }
+                codes.push_back(SCANCODE_ALT | SCANCODE_RELEASE);
+            }
+            else {
+                skipped += 1;
+            }
+        }
+
+        let queue = Self {
+            codes,
+            delay_us: delay_ms as f64 * 1000.0,
+            wait_us: 0.0,
+        };
+        (queue, skipped)
+    }
+
+    /// Return the number of scancodes left to send.
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// Restart the delay before the next scancode, as when another scancode has just been sent
+    /// to the keyboard.
+    pub fn defer(&mut self) {
+        self.wait_us = self.delay_us;
+    }
+
+    /// Return the time in microseconds until the next scancode is due.
+    pub fn due_in_us(&self) -> f64 {
+        self.wait_us.max(0.0)
+    }
+
+    /// Advance the queue by `us` microseconds of emulated time. Returns the next scancode if it
+    /// is due.
+    pub fn next_due(&mut self, us: f64) -> Option<u8> {
+        self.wait_us -= us;
+        if self.wait_us > 0.0 {
+            return None
+        }
+        let code = self.codes.pop_front()?;
+        self.wait_us = self.delay_us;
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_to_scancode() {
+        assert_eq!(char_to_scancode('1'), Some((0x02, false)));
+        assert_eq!(char_to_scancode('+'), Some((0x0D, true)));
+        assert_eq!(char_to_scancode('q'), Some((0x10, false)));
+        assert_eq!(char_to_scancode('A'), Some((0x1E, true)));
+        assert_eq!(char_to_scancode('\\'), Some((0x2B, false)));
+        assert_eq!(char_to_scancode('?'), Some((0x35, true)));
+        assert_eq!(char_to_scancode('\n'), Some((0x1C, false)));
+        assert_eq!(char_to_scancode('é'), None);
+    }
+
+    #[test]
+    fn test_paste_queue() {
+        let (mut queue, skipped) = PasteQueue::new("A\r\n", Codepage::default(), 1);
+        assert_eq!(skipped, 0);
+        assert_eq!(queue.len(), 6);
+
+        assert_eq!(queue.next_due(0.0), Some(0x2A));
+        assert_eq!(queue.next_due(500.0), None);
+        assert_eq!(queue.next_due(500.0), Some(0x1E));
+        assert_eq!(queue.next_due(1000.0), Some(0x9E));
+        assert_eq!(queue.next_due(1000.0), Some(0xAA));
+        assert_eq!(queue.next_due(1000.0), Some(0x1C));
+        assert_eq!(queue.next_due(1000.0), Some(0x9C));
+        assert!(queue.is_empty());
+        assert_eq!(queue.next_due(1000.0), None);
+    }
+}
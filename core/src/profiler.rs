@@ -0,0 +1,229 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    profiler.rs
+
+    Implements a counting profiler for guest code. While enabled, the cycles
+    and instruction count of every instruction executed are attributed to its
+    flat address, and to the call stack it was executed under.
+
+    Functions are identified by the entry address of a CALL or interrupt on
+    the CPU's call stack; code executed with an empty call stack is reported
+    as top-level. Call stacks can be exported in the folded format read by
+    flamegraph.pl and compatible tools.
+*/
+
+use std::{
+    collections::HashMap,
+    io::Write,
+};
+
+/// The frame name used for code executed with an empty call stack.
+const TOP_LEVEL_FRAME: &str = "top";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ProfileEntry {
+    pub address: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl ProfileEntry {
+    fn add(&mut self, instructions: u64, cycles: u64) {
+        self.instructions += instructions;
+        self.cycles += cycles;
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    pub cycles: u64,
+    /// The hottest instruction addresses, by cycles.
+    pub addresses: Vec<ProfileEntry>,
+    /// The hottest functions, by cycles spent in the function itself rather than its callees.
+    pub functions: Vec<ProfileEntry>,
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    enabled: bool,
+    addresses: HashMap<u32, ProfileEntry>,
+    /// Call stacks seen, as lists of function entry addresses from outermost to innermost,
+    /// with the instructions and cycles executed under each.
+    stacks: Vec<(Vec<u32>, ProfileEntry)>,
+    stack_ids: HashMap<Vec<u32>, usize>,
+    /// The call stack of the last instruction recorded, cached so the stack only needs to be 
+    /// looked up when it changes.
+    current_stack: Option<(Vec<u32>, usize)>,
+    instructions: u64,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Enable or disable recording. Disabling recording keeps the data recorded so far.
+    pub fn set_enabled(&mut self, state: bool) {
+        self.enabled = state;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+        self.stacks.clear();
+        self.stack_ids.clear();
+        self.current_stack = None;
+        self.instructions = 0;
+        self.cycles = 0;
+    }
+
+    /// Record an instruction executed at the specified flat address. 'call_stack' gives the 
+    /// entry addresses of the functions it was executed under, from outermost to innermost.
+    pub fn record(&mut self, address: u32, cycles: u32, call_stack: impl Iterator<Item = u32> + Clone) {
+        let cycles = cycles as u64;
+        self.instructions += 1;
+        self.cycles += cycles;
+        self.addresses
+            .entry(address)
+            .or_insert(ProfileEntry { address, ..Default::default() })
+            .add(1, cycles);
+
+        let stack_id = match &self.current_stack {
+            Some((stack, id)) if stack.iter().copied().eq(call_stack.clone()) => *id,
+            _ => {
+                let stack: Vec<u32> = call_stack.collect();
+                let id = match self.stack_ids.get(&stack) {
+                    Some(id) => *id,
+                    None => {
+                        let id = self.stacks.len();
+                        self.stacks.push((stack.clone(), ProfileEntry::default()));
+                        self.stack_ids.insert(stack.clone(), id);
+                        id
+                    }
+                };
+                self.current_stack = Some((stack, id));
+                id
+            }
+        };
+        self.stacks[stack_id].1.add(1, cycles);
+    }
+
+    /// Return a report of the hottest addresses and functions, keeping the specified number 
+    /// of each.
+    pub fn report(&self, limit: usize) -> ProfileReport {
+        let mut addresses: Vec<ProfileEntry> = self.addresses.values().copied().collect();
+
+        let mut function_map: HashMap<u32, ProfileEntry> = HashMap::new();
+        for (stack, entry) in &self.stacks {
+            if let Some(&function) = stack.last() {
+                function_map
+                    .entry(function)
+                    .or_insert(ProfileEntry { address: function, ..Default::default() })
+                    .add(entry.instructions, entry.cycles);
+            }
+        }
+        let mut functions: Vec<ProfileEntry> = function_map.into_values().collect();
+
+        for list in [&mut addresses, &mut functions] {
+            list.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.address.cmp(&b.address)));
+            list.truncate(limit);
+        }
+
+        ProfileReport {
+            instructions: self.instructions,
+            cycles: self.cycles,
+            addresses,
+            functions,
+        }
+    }
+
+    /// Write the recorded call stacks in folded format, one line per stack of the frames 
+    /// separated by semicolons followed by the cycles spent in it.
+    pub fn write_folded(&self, w: &mut impl Write) -> std::io::Result<()> {
+        for (stack, entry) in &self.stacks {
+            if entry.cycles == 0 {
+                continue;
+            }
+            let frames: Vec<String> = std::iter::once(TOP_LEVEL_FRAME.to_string())
+                .chain(stack.iter().map(|address| format!("{:05X}", address)))
+                .collect();
+            writeln!(w, "{} {}", frames.join(";"), entry.cycles)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x100, 4, [].into_iter());
+        profiler.record(0x200, 10, [0x200].into_iter());
+        profiler.record(0x202, 20, [0x200].into_iter());
+        profiler.record(0x300, 8, [0x200, 0x300].into_iter());
+        profiler.record(0x100, 4, [].into_iter());
+
+        let report = profiler.report(2);
+        assert_eq!(report.instructions, 5);
+        assert_eq!(report.cycles, 46);
+        assert_eq!(report.addresses, vec![
+            ProfileEntry { address: 0x202, instructions: 1, cycles: 20 },
+            ProfileEntry { address: 0x200, instructions: 1, cycles: 10 },
+        ]);
+        assert_eq!(report.functions, vec![
+            ProfileEntry { address: 0x200, instructions: 2, cycles: 30 },
+            ProfileEntry { address: 0x300, instructions: 1, cycles: 8 },
+        ]);
+
+        profiler.clear();
+        assert_eq!(profiler.report(2), ProfileReport::default());
+    }
+
+    #[test]
+    fn test_folded() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x100, 4, [].into_iter());
+        profiler.record(0xF0000, 10, [0xF0000].into_iter());
+        profiler.record(0x300, 8, [0xF0000, 0x300].into_iter());
+        profiler.record(0xF0002, 5, [0xF0000].into_iter());
+
+        let mut out = Vec::new();
+        profiler.write_folded(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "top 4\ntop;F0000 15\ntop;F0000;00300 8\n"
+        );
+    }
+}
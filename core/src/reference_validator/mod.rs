@@ -0,0 +1,462 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    reference_validator::mod.rs
+
+    Implements a CPU validator that checks the emulated CPU against a simple
+    software reference interpreter instead of real hardware.
+
+    The reference CPU is not cycle accurate, so only architectural state is
+    validated: registers, flags, and the data written to memory and IO.
+    Memory and IO reads made by the reference CPU are satisfied from the reads
+    the emulator performed, so no copy of system memory is required.
+*/
+
+mod refcpu;
+
+use crate::tracelogger::TraceLogger;
+use crate::cpu_808x::{
+    CPU_FLAG_CARRY,
+    CPU_FLAG_PARITY,
+    CPU_FLAG_AUX_CARRY,
+    CPU_FLAG_ZERO,
+    CPU_FLAG_SIGN,
+    CPU_FLAG_TRAP,
+    CPU_FLAG_INT_ENABLE,
+    CPU_FLAG_DIRECTION,
+    CPU_FLAG_OVERFLOW
+};
+use crate::cpu_validator::*;
+
+use refcpu::{RefBus, RefCpu, RefCpuError};
+
+macro_rules! trace {
+    ($self:ident, $($t:tt)*) => {{
+        $self.trace_logger.print(&format!($($t)*));
+        $self.trace_logger.print("\n".to_string());
+    }};
+}
+
+macro_rules! trace_error {
+    ($self:ident, $($t:tt)*) => {{
+        log::error!("{}", &format!($($t)*));
+        $self.trace_logger.print(&format!($($t)*));
+        $self.trace_logger.print("\n".to_string());
+    }};
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum BusOpType {
+    MemRead,
+    MemWrite,
+    IoRead,
+    IoWrite,
+}
+
+#[derive (Copy, Clone)]
+struct BusOp {
+    op_type: BusOpType,
+    addr: u32,
+    data: u8,
+    consumed: bool,
+}
+
+/// A RefBus that satisfies reads from the bus operations logged by the emulator,
+/// and records writes for comparison.
+struct ReplayBus<'a> {
+    emu_ops: &'a mut Vec<BusOp>,
+    ref_writes: Vec<BusOp>,
+}
+
+impl<'a> ReplayBus<'a> {
+    fn replay_read(&mut self, op_type: BusOpType, addr: u32) -> Option<u8> {
+        let op = self.emu_ops
+            .iter_mut()
+            .find(|op| op.op_type == op_type && op.addr == addr && !op.consumed)?;
+
+        op.consumed = true;
+        Some(op.data)
+    }
+
+    fn record_write(&mut self, op_type: BusOpType, addr: u32, data: u8) {
+        self.ref_writes.push(BusOp {
+            op_type,
+            addr,
+            data,
+            consumed: false
+        });
+    }
+}
+
+impl<'a> RefBus for ReplayBus<'a> {
+    fn read_u8(&mut self, addr: u32) -> Result<u8, RefCpuError> {
+        self.replay_read(BusOpType::MemRead, addr).ok_or(RefCpuError::ReadNotFound(addr))
+    }
+    fn write_u8(&mut self, addr: u32, data: u8) {
+        self.record_write(BusOpType::MemWrite, addr, data);
+    }
+    fn io_read_u8(&mut self, port: u16) -> Result<u8, RefCpuError> {
+        self.replay_read(BusOpType::IoRead, port as u32).ok_or(RefCpuError::IoReadNotFound(port))
+    }
+    fn io_write_u8(&mut self, port: u16, data: u8) {
+        self.record_write(BusOpType::IoWrite, port as u32, data);
+    }
+}
+
+pub struct ReferenceValidator {
+    regs_before: VRegisters,
+    ref_regs: VRegisters,
+    emu_ops: Vec<BusOp>,
+
+    opcode: u8,
+    modrm: u8,
+    discard: bool,
+    end_addr: usize,
+    mask_flags: bool,
+
+    trace_logger: TraceLogger
+}
+
+pub fn make_pointer(base: u16, offset: u16) -> u32 {
+    (((base as u32) << 4) + offset as u32) & 0xFFFFF
+}
+
+impl ReferenceValidator {
+
+    pub fn new(trace_logger: TraceLogger) -> Self {
+        Self {
+            regs_before: Default::default(),
+            ref_regs: Default::default(),
+            emu_ops: Vec::new(),
+            opcode: 0,
+            modrm: 0,
+            discard: false,
+            end_addr: 0,
+            mask_flags: true,
+            trace_logger
+        }
+    }
+
+    fn print_regs(&mut self, regs: &VRegisters) {
+        trace!(
+            self,
+            "AX: {:04x} BX: {:04x} CX: {:04x} DX: {:04x}\n\
+            SP: {:04x} BP: {:04x} SI: {:04x} DI: {:04x}\n\
+            CS: {:04x} DS: {:04x} ES: {:04x} SS: {:04x}\n\
+            IP: {:04x}\n\
+            FLAGS: {:04x}",
+            regs.ax, regs.bx, regs.cx, regs.dx,
+            regs.sp, regs.bp, regs.si, regs.di,
+            regs.cs, regs.ds, regs.es, regs.ss,
+            regs.ip,
+            regs.flags
+        );
+    }
+
+    fn flag_mask(&self) -> u16 {
+        if self.mask_flags {
+            refcpu::FLAGS_DEFINED & !refcpu::undefined_flags(self.opcode, self.modrm)
+        }
+        else {
+            refcpu::FLAGS_DEFINED
+        }
+    }
+
+    /// Compare the reference CPU's registers against the emulator's. Returns true if they match.
+    /// Compare emulator registers against the reference CPU. Flag differences are reported
+    /// separately from other registers.
+    fn compare_registers(&mut self, emu: &VRegisters) -> Result<(), ValidatorError> {
+        let r = self.ref_regs;
+
+        let regs_validate =
+            r.ax == emu.ax && r.bx == emu.bx && r.cx == emu.cx && r.dx == emu.dx
+            && r.cs == emu.cs && r.ss == emu.ss && r.ds == emu.ds && r.es == emu.es
+            && r.sp == emu.sp && r.bp == emu.bp && r.si == emu.si && r.di == emu.di
+            && r.ip == emu.ip;
+
+        let mask = self.flag_mask();
+        let ref_flags_masked = r.flags & mask;
+        let emu_flags_masked = emu.flags & mask;
+
+        if ref_flags_masked != emu_flags_masked {
+            trace_error!(self, "CPU flags mismatch! EMU: 0b{:016b} != REF: 0b{:016b}", emu_flags_masked, ref_flags_masked);
+
+            let flag_diff = emu_flags_masked ^ ref_flags_masked;
+            let flag_names = [
+                (CPU_FLAG_CARRY, "CARRY"),
+                (CPU_FLAG_PARITY, "PARITY"),
+                (CPU_FLAG_AUX_CARRY, "AUX CARRY"),
+                (CPU_FLAG_ZERO, "ZERO"),
+                (CPU_FLAG_SIGN, "SIGN"),
+                (CPU_FLAG_TRAP, "TRAP"),
+                (CPU_FLAG_INT_ENABLE, "INT"),
+                (CPU_FLAG_DIRECTION, "DIRECTION"),
+                (CPU_FLAG_OVERFLOW, "OVERFLOW"),
+            ];
+            for (flag, name) in flag_names {
+                if flag_diff & flag != 0 {
+                    trace_error!(self, "{} flag differs.", name);
+                }
+            }
+            if regs_validate {
+                return Err(ValidatorError::FlagsMismatch);
+            }
+        }
+
+        if !regs_validate {
+            return Err(ValidatorError::RegisterMismatch);
+        }
+        Ok(())
+    }
+
+    /// Compare the writes made by the reference CPU against those made by the emulator.
+    fn compare_writes(&mut self, ref_writes: &[BusOp]) -> bool {
+        let emu_writes: Vec<BusOp> = self.emu_ops
+            .iter()
+            .filter(|op| matches!(op.op_type, BusOpType::MemWrite | BusOpType::IoWrite))
+            .copied()
+            .collect();
+
+        if emu_writes.len() != ref_writes.len() {
+            trace_error!(self, "Write count mismatch! EMU: {} REF: {}", emu_writes.len(), ref_writes.len());
+            return false;
+        }
+
+        for (emu_op, ref_op) in emu_writes.iter().zip(ref_writes.iter()) {
+            if emu_op.op_type != ref_op.op_type
+                || emu_op.addr != ref_op.addr
+                || emu_op.data != ref_op.data {
+
+                trace_error!(
+                    self,
+                    "Write mismatch! EMU: {:?} [{:05X}] <- {:02X} REF: {:?} [{:05X}] <- {:02X}",
+                    emu_op.op_type, emu_op.addr, emu_op.data,
+                    ref_op.op_type, ref_op.addr, ref_op.data
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl CpuValidator for ReferenceValidator {
+
+    fn init(&mut self, _mode: ValidatorMode, mask_flags: bool, _cycle_trace: bool, _visit_once: bool) -> bool {
+        // The reference CPU has no notion of cycles, so validation mode and cycle tracing are ignored.
+        self.mask_flags = mask_flags;
+        true
+    }
+
+    fn reset_instruction(&mut self) {
+        self.emu_ops.clear();
+    }
+
+    fn begin_instruction(&mut self, regs: &VRegisters, _end_instr: usize, end_program: usize) {
+        self.discard = false;
+        self.regs_before = *regs;
+        self.end_addr = end_program;
+    }
+
+    fn set_regs(&mut self) {
+        // The reference CPU is loaded with the emulator's registers before every instruction.
+        self.ref_regs = self.regs_before;
+    }
+
+    fn validate_instruction(
+        &mut self,
+        name: String,
+        instr: &[u8],
+        _peek_fetch: u16,
+        has_modrm: bool,
+        _cycles: i32,
+        regs: &VRegisters,
+        _emu_states: &[CycleState]
+    ) -> Result<ValidatorResult, ValidatorError> {
+
+        if instr.is_empty() {
+            trace_error!(self, "Instruction length was 0");
+            return Err(ValidatorError::ParameterError);
+        }
+
+        // Scan through prefix bytes to find opcode
+        let mut i = 0;
+        let mut rep = false;
+        while i < instr.len() - 1 {
+            match instr[i] {
+                0x26 | 0x2E | 0x36 | 0x3E | 0xF0 | 0xF1 => i += 1,
+                0xF2 | 0xF3 => {
+                    rep = true;
+                    i += 1;
+                }
+                _ => break
+            }
+        }
+        self.opcode = instr[i];
+        self.modrm = if has_modrm && i + 1 < instr.len() { instr[i + 1] } else { 0 };
+
+        trace!(
+            self,
+            "VALIDATE: {} {:02X?} @ [{:04X}:{:04X}] Memops: {}",
+            name,
+            instr,
+            self.regs_before.cs,
+            self.regs_before.ip,
+            self.emu_ops.len()
+        );
+
+        if self.discard {
+            self.emu_ops.clear();
+            return Ok(ValidatorResult::Ok);
+        }
+
+        // If the emulator left IP on a repeated string instruction, it was interrupted
+        // part way through. Stop the reference CPU at the same point.
+        let stop_cx = if rep && regs.cs == self.regs_before.cs && regs.ip == self.regs_before.ip {
+            Some(regs.cx)
+        }
+        else {
+            None
+        };
+
+        let mut cpu = RefCpu::from_vregs(&self.regs_before);
+        let mut bus = ReplayBus {
+            emu_ops: &mut self.emu_ops,
+            ref_writes: Vec::new()
+        };
+
+        let result = cpu.execute(instr, &mut bus, stop_cx);
+        let ref_writes = std::mem::take(&mut bus.ref_writes);
+
+        match result {
+            Ok(_) => {}
+            Err(RefCpuError::Unsupported(opcode)) => {
+                trace!(self, "Opcode {:02X} form not supported by reference CPU, skipping.", opcode);
+                self.ref_regs = *regs;
+                self.emu_ops.clear();
+                return Ok(ValidatorResult::Ok);
+            }
+            Err(RefCpuError::DivideError) => {
+                // Divide exceptions are not validated.
+                self.ref_regs = *regs;
+                self.emu_ops.clear();
+                return Ok(ValidatorResult::Ok);
+            }
+            Err(e) => {
+                trace_error!(self, "Reference CPU error: {}", e);
+                trace_error!(self, "EMU BEFORE:");
+                let regs_before = self.regs_before;
+                self.print_regs(&regs_before);
+                self.trace_logger.flush();
+                return Err(ValidatorError::MemOpMismatch);
+            }
+        }
+
+        self.ref_regs = cpu.to_vregs();
+
+        if !self.compare_writes(&ref_writes) {
+            trace_error!(self, "Memory validation failure. EMU:");
+            self.print_regs(regs);
+            trace_error!(self, "REF:");
+            let ref_regs = self.ref_regs;
+            self.print_regs(&ref_regs);
+            self.trace_logger.flush();
+            return Err(ValidatorError::MemOpMismatch);
+        }
+
+        if let Err(e) = self.compare_registers(regs) {
+            trace_error!(self, "Register validation failure. EMU BEFORE:");
+            let regs_before = self.regs_before;
+            self.print_regs(&regs_before);
+            trace_error!(self, "EMU AFTER:");
+            self.print_regs(regs);
+            trace_error!(self, "REF AFTER:");
+            let ref_regs = self.ref_regs;
+            self.print_regs(&ref_regs);
+            self.trace_logger.flush();
+            return Err(e);
+        }
+
+        self.emu_ops.clear();
+
+        if make_pointer(self.ref_regs.cs, self.ref_regs.ip) as usize == self.end_addr {
+            trace!(self, " >>> Validator finalizing!");
+            Ok(ValidatorResult::OkEnd)
+        }
+        else {
+            Ok(ValidatorResult::Ok)
+        }
+    }
+
+    fn validate_regs(&mut self, regs: &VRegisters) -> Result<(), ValidatorError> {
+        if let Err(e) = self.compare_registers(regs) {
+            trace_error!(self, "Register validation failure. EMU:");
+            self.print_regs(regs);
+            trace_error!(self, "REF:");
+            let ref_regs = self.ref_regs;
+            self.print_regs(&ref_regs);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn emu_read_byte(&mut self, addr: u32, data: u8, bus_type: BusType, read_type: ReadType) {
+        if self.discard {
+            return;
+        }
+
+        // Instruction bytes are passed to validate_instruction() directly, so code fetches
+        // are not logged.
+        let op_type = match (bus_type, read_type) {
+            (BusType::Mem, ReadType::Code) => return,
+            (BusType::Mem, ReadType::Data) => BusOpType::MemRead,
+            (BusType::Io, _) => BusOpType::IoRead
+        };
+
+        self.emu_ops.push(BusOp { op_type, addr, data, consumed: false });
+    }
+
+    fn emu_write_byte(&mut self, addr: u32, data: u8, bus_type: BusType) {
+        if self.discard {
+            return;
+        }
+
+        let op_type = match bus_type {
+            BusType::Mem => BusOpType::MemWrite,
+            BusType::Io => BusOpType::IoWrite
+        };
+
+        self.emu_ops.push(BusOp { op_type, addr, data, consumed: false });
+    }
+
+    fn discard_op(&mut self) {
+        self.discard = true;
+    }
+
+    fn flush(&mut self) {
+        self.trace_logger.flush();
+    }
+}
@@ -0,0 +1,1484 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    reference_validator::refcpu.rs
+
+    A simple, instruction-level 8088 interpreter used as a reference for
+    validating the main CPU core.
+
+    This interpreter deliberately shares no code with cpu_808x. It has no
+    concept of cycles, the prefetch queue or the bus; it executes a single
+    instruction at a time against a RefBus. It aims to be obviously correct
+    rather than fast.
+*/
+
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::cpu_validator::VRegisters;
+
+pub const FLAG_CARRY: u16     = 0x0001;
+pub const FLAG_PARITY: u16    = 0x0004;
+pub const FLAG_AUX_CARRY: u16 = 0x0010;
+pub const FLAG_ZERO: u16      = 0x0040;
+pub const FLAG_SIGN: u16      = 0x0080;
+pub const FLAG_TRAP: u16      = 0x0100;
+pub const FLAG_INTERRUPT: u16 = 0x0200;
+pub const FLAG_DIRECTION: u16 = 0x0400;
+pub const FLAG_OVERFLOW: u16  = 0x0800;
+
+// Flags that are architecturally meaningful on the 8088.
+pub const FLAGS_DEFINED: u16 = 0x0FD5;
+// Reserved flags that always read as 1 on the 8088.
+pub const FLAGS_RESERVED_ON: u16 = 0xF002;
+
+const REG_AX: usize = 0;
+const REG_CX: usize = 1;
+const REG_DX: usize = 2;
+const REG_BX: usize = 3;
+const REG_SP: usize = 4;
+const REG_BP: usize = 5;
+const REG_SI: usize = 6;
+const REG_DI: usize = 7;
+
+const SEG_ES: usize = 0;
+const SEG_CS: usize = 1;
+const SEG_SS: usize = 2;
+const SEG_DS: usize = 3;
+
+#[derive (Debug, PartialEq)]
+pub enum RefCpuError {
+    /// The reference CPU read memory the emulator never read.
+    ReadNotFound(u32),
+    /// The reference CPU read an IO port the emulator never read.
+    IoReadNotFound(u16),
+    /// The instruction is not implemented by the reference CPU; it cannot be validated.
+    Unsupported(u8),
+    /// The instruction caused a divide error exception.
+    DivideError,
+    /// Instruction decoding ran past the end of the instruction bytes provided.
+    CodeOverrun,
+}
+
+impl Error for RefCpuError {}
+impl Display for RefCpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefCpuError::ReadNotFound(addr) => {
+                write!(f, "Reference CPU read address [{:05X}] not read by emulator.", addr)
+            }
+            RefCpuError::IoReadNotFound(port) => {
+                write!(f, "Reference CPU read IO port {:04X} not read by emulator.", port)
+            }
+            RefCpuError::Unsupported(opcode) => {
+                write!(f, "Opcode {:02X} not supported by reference CPU.", opcode)
+            }
+            RefCpuError::DivideError => {
+                write!(f, "Divide error.")
+            }
+            RefCpuError::CodeOverrun => {
+                write!(f, "Decoded past end of instruction.")
+            }
+        }
+    }
+}
+
+/// The interface through which the reference CPU accesses memory and IO.
+pub trait RefBus {
+    fn read_u8(&mut self, addr: u32) -> Result<u8, RefCpuError>;
+    fn write_u8(&mut self, addr: u32, data: u8);
+    fn io_read_u8(&mut self, port: u16) -> Result<u8, RefCpuError>;
+    fn io_write_u8(&mut self, port: u16, data: u8);
+}
+
+#[derive (Copy, Clone, PartialEq)]
+enum Width {
+    Byte,
+    Word
+}
+
+impl Width {
+    fn mask(&self) -> u32 {
+        match self {
+            Width::Byte => 0xFF,
+            Width::Word => 0xFFFF
+        }
+    }
+    fn sign(&self) -> u32 {
+        match self {
+            Width::Byte => 0x80,
+            Width::Word => 0x8000
+        }
+    }
+}
+
+#[derive (Copy, Clone)]
+struct ModRm {
+    mode: u8,
+    reg: u8,
+    rm: u8,
+    // Segment and offset of the memory operand, if mode != 3
+    seg: u16,
+    offset: u16,
+}
+
+#[derive (Copy, Clone, PartialEq)]
+enum RepPrefix {
+    None,
+    Repne,
+    Repe
+}
+
+pub struct RefCpu {
+    regs: [u16; 8],
+    sregs: [u16; 4],
+    ip: u16,
+    flags: u16,
+
+    code: Vec<u8>,
+    code_pos: usize,
+    seg_override: Option<usize>,
+}
+
+impl RefCpu {
+    pub fn from_vregs(v: &VRegisters) -> Self {
+        Self {
+            regs: [v.ax, v.cx, v.dx, v.bx, v.sp, v.bp, v.si, v.di],
+            sregs: [v.es, v.cs, v.ss, v.ds],
+            ip: v.ip,
+            flags: v.flags,
+            code: Vec::new(),
+            code_pos: 0,
+            seg_override: None,
+        }
+    }
+
+    pub fn to_vregs(&self) -> VRegisters {
+        VRegisters {
+            ax: self.regs[REG_AX],
+            bx: self.regs[REG_BX],
+            cx: self.regs[REG_CX],
+            dx: self.regs[REG_DX],
+            cs: self.sregs[SEG_CS],
+            ss: self.sregs[SEG_SS],
+            ds: self.sregs[SEG_DS],
+            es: self.sregs[SEG_ES],
+            sp: self.regs[REG_SP],
+            bp: self.regs[REG_BP],
+            si: self.regs[REG_SI],
+            di: self.regs[REG_DI],
+            ip: self.ip,
+            flags: self.flags
+        }
+    }
+
+    fn linear(seg: u16, offset: u16) -> u32 {
+        (((seg as u32) << 4) + offset as u32) & 0xFFFFF
+    }
+
+    fn get_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u16, state: bool) {
+        if state {
+            self.flags |= flag;
+        }
+        else {
+            self.flags &= !flag;
+        }
+    }
+
+    // Instruction stream ----------------------------------------------------
+
+    fn fetch_u8(&mut self) -> Result<u8, RefCpuError> {
+        if self.code_pos >= self.code.len() {
+            return Err(RefCpuError::CodeOverrun)
+        }
+        let byte = self.code[self.code_pos];
+        self.code_pos += 1;
+        Ok(byte)
+    }
+
+    fn fetch_u16(&mut self) -> Result<u16, RefCpuError> {
+        let lo = self.fetch_u8()? as u16;
+        let hi = self.fetch_u8()? as u16;
+        Ok(lo | (hi << 8))
+    }
+
+    fn fetch(&mut self, w: Width) -> Result<u16, RefCpuError> {
+        match w {
+            Width::Byte => Ok(self.fetch_u8()? as u16),
+            Width::Word => self.fetch_u16()
+        }
+    }
+
+    /// Return IP of the next instruction.
+    fn next_ip(&self) -> u16 {
+        self.ip.wrapping_add(self.code_pos as u16)
+    }
+
+    // Memory ------------------------------------------------------------------
+
+    fn read_u8(&mut self, bus: &mut dyn RefBus, seg: u16, offset: u16) -> Result<u8, RefCpuError> {
+        bus.read_u8(RefCpu::linear(seg, offset))
+    }
+
+    fn read_u16(&mut self, bus: &mut dyn RefBus, seg: u16, offset: u16) -> Result<u16, RefCpuError> {
+        // Word accesses wrap within the segment.
+        let lo = bus.read_u8(RefCpu::linear(seg, offset))? as u16;
+        let hi = bus.read_u8(RefCpu::linear(seg, offset.wrapping_add(1)))? as u16;
+        Ok(lo | (hi << 8))
+    }
+
+    fn write_u8(&mut self, bus: &mut dyn RefBus, seg: u16, offset: u16, data: u8) {
+        bus.write_u8(RefCpu::linear(seg, offset), data);
+    }
+
+    fn write_u16(&mut self, bus: &mut dyn RefBus, seg: u16, offset: u16, data: u16) {
+        bus.write_u8(RefCpu::linear(seg, offset), data as u8);
+        bus.write_u8(RefCpu::linear(seg, offset.wrapping_add(1)), (data >> 8) as u8);
+    }
+
+    fn read_mem(&mut self, bus: &mut dyn RefBus, w: Width, seg: u16, offset: u16) -> Result<u16, RefCpuError> {
+        match w {
+            Width::Byte => Ok(self.read_u8(bus, seg, offset)? as u16),
+            Width::Word => self.read_u16(bus, seg, offset)
+        }
+    }
+
+    fn write_mem(&mut self, bus: &mut dyn RefBus, w: Width, seg: u16, offset: u16, data: u16) {
+        match w {
+            Width::Byte => self.write_u8(bus, seg, offset, data as u8),
+            Width::Word => self.write_u16(bus, seg, offset, data)
+        }
+    }
+
+    fn push(&mut self, bus: &mut dyn RefBus, data: u16) {
+        self.regs[REG_SP] = self.regs[REG_SP].wrapping_sub(2);
+        let ss = self.sregs[SEG_SS];
+        let sp = self.regs[REG_SP];
+        self.write_u16(bus, ss, sp, data);
+    }
+
+    fn pop(&mut self, bus: &mut dyn RefBus) -> Result<u16, RefCpuError> {
+        let ss = self.sregs[SEG_SS];
+        let sp = self.regs[REG_SP];
+        let data = self.read_u16(bus, ss, sp)?;
+        self.regs[REG_SP] = self.regs[REG_SP].wrapping_add(2);
+        Ok(data)
+    }
+
+    // Registers ---------------------------------------------------------------
+
+    fn get_reg(&self, w: Width, reg: u8) -> u16 {
+        match w {
+            Width::Word => self.regs[reg as usize],
+            Width::Byte => {
+                let r = self.regs[(reg & 0x03) as usize];
+                if reg < 4 { r & 0xFF } else { r >> 8 }
+            }
+        }
+    }
+
+    fn set_reg(&mut self, w: Width, reg: u8, data: u16) {
+        match w {
+            Width::Word => self.regs[reg as usize] = data,
+            Width::Byte => {
+                let r = &mut self.regs[(reg & 0x03) as usize];
+                if reg < 4 {
+                    *r = (*r & 0xFF00) | (data & 0xFF);
+                }
+                else {
+                    *r = (*r & 0x00FF) | ((data & 0xFF) << 8);
+                }
+            }
+        }
+    }
+
+    fn data_segment(&self, default: usize) -> u16 {
+        self.sregs[self.seg_override.unwrap_or(default)]
+    }
+
+    // ModRM -------------------------------------------------------------------
+
+    fn decode_modrm(&mut self) -> Result<ModRm, RefCpuError> {
+        let byte = self.fetch_u8()?;
+        let mode = byte >> 6;
+        let reg = (byte >> 3) & 0x07;
+        let rm = byte & 0x07;
+
+        if mode == 3 {
+            return Ok(ModRm { mode, reg, rm, seg: 0, offset: 0 })
+        }
+
+        let bx = self.regs[REG_BX];
+        let bp = self.regs[REG_BP];
+        let si = self.regs[REG_SI];
+        let di = self.regs[REG_DI];
+
+        let (base, default_seg) = match rm {
+            0 => (bx.wrapping_add(si), SEG_DS),
+            1 => (bx.wrapping_add(di), SEG_DS),
+            2 => (bp.wrapping_add(si), SEG_SS),
+            3 => (bp.wrapping_add(di), SEG_SS),
+            4 => (si, SEG_DS),
+            5 => (di, SEG_DS),
+            6 => {
+                if mode == 0 { (0, SEG_DS) } else { (bp, SEG_SS) }
+            }
+            _ => (bx, SEG_DS)
+        };
+
+        let disp = match mode {
+            0 if rm == 6 => self.fetch_u16()?,
+            1 => self.fetch_u8()? as i8 as i16 as u16,
+            2 => self.fetch_u16()?,
+            _ => 0
+        };
+
+        Ok(ModRm {
+            mode,
+            reg,
+            rm,
+            seg: self.data_segment(default_seg),
+            offset: base.wrapping_add(disp)
+        })
+    }
+
+    fn read_rm(&mut self, bus: &mut dyn RefBus, w: Width, m: &ModRm) -> Result<u16, RefCpuError> {
+        if m.mode == 3 {
+            Ok(self.get_reg(w, m.rm))
+        }
+        else {
+            self.read_mem(bus, w, m.seg, m.offset)
+        }
+    }
+
+    fn write_rm(&mut self, bus: &mut dyn RefBus, w: Width, m: &ModRm, data: u16) {
+        if m.mode == 3 {
+            self.set_reg(w, m.rm, data);
+        }
+        else {
+            self.write_mem(bus, w, m.seg, m.offset, data);
+        }
+    }
+
+    // Flags -------------------------------------------------------------------
+
+    fn set_szp(&mut self, w: Width, result: u32) {
+        let result = result & w.mask();
+        self.set_flag(FLAG_ZERO, result == 0);
+        self.set_flag(FLAG_SIGN, result & w.sign() != 0);
+        self.set_flag(FLAG_PARITY, (result as u8).count_ones() % 2 == 0);
+    }
+
+    fn add(&mut self, w: Width, a: u32, b: u32, carry_in: bool) -> u16 {
+        let c = carry_in as u32;
+        let result = a + b + c;
+        self.set_flag(FLAG_CARRY, result > w.mask());
+        self.set_flag(FLAG_AUX_CARRY, (a ^ b ^ result) & 0x10 != 0);
+        self.set_flag(FLAG_OVERFLOW, (result ^ a) & (result ^ b) & w.sign() != 0);
+        self.set_szp(w, result);
+        (result & w.mask()) as u16
+    }
+
+    fn sub(&mut self, w: Width, a: u32, b: u32, borrow_in: bool) -> u16 {
+        let c = borrow_in as u32;
+        let result = a.wrapping_sub(b).wrapping_sub(c);
+        self.set_flag(FLAG_CARRY, b + c > a);
+        self.set_flag(FLAG_AUX_CARRY, (a ^ b ^ result) & 0x10 != 0);
+        self.set_flag(FLAG_OVERFLOW, (a ^ b) & (a ^ result) & w.sign() != 0);
+        self.set_szp(w, result);
+        (result & w.mask()) as u16
+    }
+
+    fn logic(&mut self, w: Width, result: u32) -> u16 {
+        self.set_flag(FLAG_CARRY, false);
+        self.set_flag(FLAG_OVERFLOW, false);
+        self.set_flag(FLAG_AUX_CARRY, false);
+        self.set_szp(w, result);
+        (result & w.mask()) as u16
+    }
+
+    /// Perform one of the eight basic ALU operations. Returns None for CMP.
+    fn alu(&mut self, op: u8, w: Width, a: u16, b: u16) -> Option<u16> {
+        let (a, b) = (a as u32, b as u32);
+        let cf = self.get_flag(FLAG_CARRY);
+        match op & 0x07 {
+            0 => Some(self.add(w, a, b, false)),
+            1 => Some(self.logic(w, a | b)),
+            2 => Some(self.add(w, a, b, cf)),
+            3 => Some(self.sub(w, a, b, cf)),
+            4 => Some(self.logic(w, a & b)),
+            5 => Some(self.sub(w, a, b, false)),
+            6 => Some(self.logic(w, a ^ b)),
+            _ => {
+                self.sub(w, a, b, false);
+                None
+            }
+        }
+    }
+
+    fn inc_dec(&mut self, w: Width, a: u16, dec: bool) -> u16 {
+        // INC and DEC do not affect the carry flag.
+        let cf = self.get_flag(FLAG_CARRY);
+        let result = if dec {
+            self.sub(w, a as u32, 1, false)
+        }
+        else {
+            self.add(w, a as u32, 1, false)
+        };
+        self.set_flag(FLAG_CARRY, cf);
+        result
+    }
+
+    fn shift(&mut self, op: u8, w: Width, value: u16, count: u8) -> u16 {
+        let mut v = value as u32;
+        let mask = w.mask();
+        let sign = w.sign();
+
+        if count == 0 {
+            return value
+        }
+
+        for _ in 0..count {
+            match op & 0x07 {
+                0 => {
+                    // ROL
+                    let msb = v & sign != 0;
+                    v = ((v << 1) | msb as u32) & mask;
+                    self.set_flag(FLAG_CARRY, msb);
+                    self.set_flag(FLAG_OVERFLOW, ((v & sign != 0) as u8 ^ msb as u8) != 0);
+                }
+                1 => {
+                    // ROR
+                    let lsb = v & 1 != 0;
+                    v = (v >> 1) | if lsb { sign } else { 0 };
+                    self.set_flag(FLAG_CARRY, lsb);
+                    self.set_flag(FLAG_OVERFLOW, ((v ^ (v << 1)) & sign) != 0);
+                }
+                2 => {
+                    // RCL
+                    let msb = v & sign != 0;
+                    v = ((v << 1) | self.get_flag(FLAG_CARRY) as u32) & mask;
+                    self.set_flag(FLAG_CARRY, msb);
+                    self.set_flag(FLAG_OVERFLOW, ((v & sign != 0) as u8 ^ msb as u8) != 0);
+                }
+                3 => {
+                    // RCR
+                    let lsb = v & 1 != 0;
+                    let old_msb = v & sign != 0;
+                    v = (v >> 1) | if self.get_flag(FLAG_CARRY) { sign } else { 0 };
+                    self.set_flag(FLAG_CARRY, lsb);
+                    self.set_flag(FLAG_OVERFLOW, ((v & sign != 0) as u8 ^ old_msb as u8) != 0);
+                }
+                4 => {
+                    // SHL. Aux carry is undefined, but follows bit 4 of the result.
+                    let msb = v & sign != 0;
+                    v = (v << 1) & mask;
+                    self.set_flag(FLAG_CARRY, msb);
+                    self.set_flag(FLAG_OVERFLOW, ((v & sign != 0) as u8 ^ msb as u8) != 0);
+                    self.set_flag(FLAG_AUX_CARRY, v & 0x10 != 0);
+                    self.set_szp(w, v);
+                }
+                5 => {
+                    // SHR. Aux carry is undefined, but is cleared.
+                    let msb = v & sign != 0;
+                    self.set_flag(FLAG_CARRY, v & 1 != 0);
+                    v >>= 1;
+                    self.set_flag(FLAG_OVERFLOW, msb);
+                    self.set_flag(FLAG_AUX_CARRY, false);
+                    self.set_szp(w, v);
+                }
+                6 => {
+                    // SETMO (undocumented). Sets all bits of the operand.
+                    v = mask;
+                    self.set_flag(FLAG_CARRY, false);
+                    self.set_flag(FLAG_OVERFLOW, false);
+                    self.set_szp(w, v);
+                }
+                _ => {
+                    // SAR. Aux carry is undefined, but is cleared.
+                    self.set_flag(FLAG_CARRY, v & 1 != 0);
+                    v = (v >> 1) | (v & sign);
+                    self.set_flag(FLAG_OVERFLOW, false);
+                    self.set_flag(FLAG_AUX_CARRY, false);
+                    self.set_szp(w, v);
+                }
+            }
+        }
+        v as u16
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        let cf = self.get_flag(FLAG_CARRY);
+        let zf = self.get_flag(FLAG_ZERO);
+        let sf = self.get_flag(FLAG_SIGN);
+        let of = self.get_flag(FLAG_OVERFLOW);
+        let pf = self.get_flag(FLAG_PARITY);
+        let result = match cc >> 1 {
+            0 => of,
+            1 => cf,
+            2 => zf,
+            3 => cf || zf,
+            4 => sf,
+            5 => pf,
+            6 => sf != of,
+            _ => zf || (sf != of)
+        };
+        if cc & 1 != 0 { !result } else { result }
+    }
+
+    fn interrupt(&mut self, bus: &mut dyn RefBus, vector: u8, return_ip: u16) -> Result<(), RefCpuError> {
+        let new_ip = self.read_u16(bus, 0, vector as u16 * 4)?;
+        let new_cs = self.read_u16(bus, 0, vector as u16 * 4 + 2)?;
+        let flags = self.flags;
+        self.push(bus, flags);
+        self.set_flag(FLAG_INTERRUPT, false);
+        self.set_flag(FLAG_TRAP, false);
+        let cs = self.sregs[SEG_CS];
+        self.push(bus, cs);
+        self.push(bus, return_ip);
+        self.sregs[SEG_CS] = new_cs;
+        self.ip = new_ip;
+        Ok(())
+    }
+
+    // String operations -------------------------------------------------------
+
+    fn string_step(&self, w: Width) -> u16 {
+        let size = if w == Width::Byte { 1u16 } else { 2u16 };
+        if self.get_flag(FLAG_DIRECTION) { size.wrapping_neg() } else { size }
+    }
+
+    fn string_op(&mut self, bus: &mut dyn RefBus, opcode: u8) -> Result<(), RefCpuError> {
+        let w = if opcode & 1 == 0 { Width::Byte } else { Width::Word };
+        let step = self.string_step(w);
+        let src_seg = self.data_segment(SEG_DS);
+        let es = self.sregs[SEG_ES];
+        let si = self.regs[REG_SI];
+        let di = self.regs[REG_DI];
+
+        match opcode {
+            0xA4 | 0xA5 => {
+                // MOVS
+                let data = self.read_mem(bus, w, src_seg, si)?;
+                self.write_mem(bus, w, es, di, data);
+                self.regs[REG_SI] = si.wrapping_add(step);
+                self.regs[REG_DI] = di.wrapping_add(step);
+            }
+            0xA6 | 0xA7 => {
+                // CMPS
+                let a = self.read_mem(bus, w, src_seg, si)?;
+                let b = self.read_mem(bus, w, es, di)?;
+                self.sub(w, a as u32, b as u32, false);
+                self.regs[REG_SI] = si.wrapping_add(step);
+                self.regs[REG_DI] = di.wrapping_add(step);
+            }
+            0xAA | 0xAB => {
+                // STOS
+                let data = self.get_reg(w, REG_AX as u8);
+                self.write_mem(bus, w, es, di, data);
+                self.regs[REG_DI] = di.wrapping_add(step);
+            }
+            0xAC | 0xAD => {
+                // LODS
+                let data = self.read_mem(bus, w, src_seg, si)?;
+                self.set_reg(w, REG_AX as u8, data);
+                self.regs[REG_SI] = si.wrapping_add(step);
+            }
+            _ => {
+                // SCAS
+                let b = self.read_mem(bus, w, es, di)?;
+                let a = self.get_reg(w, REG_AX as u8);
+                self.sub(w, a as u32, b as u32, false);
+                self.regs[REG_DI] = di.wrapping_add(step);
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute a string instruction, with optional REP prefix. If 'stop_cx' is provided, a
+    /// repeated instruction stops when CX reaches that value without completing, as if it
+    /// were interrupted.
+    fn string_instr(&mut self, bus: &mut dyn RefBus, opcode: u8, rep: RepPrefix, stop_cx: Option<u16>) -> Result<bool, RefCpuError> {
+
+        if rep == RepPrefix::None {
+            self.string_op(bus, opcode)?;
+            return Ok(true)
+        }
+
+        let compares = matches!(opcode, 0xA6 | 0xA7 | 0xAE | 0xAF);
+        loop {
+            if self.regs[REG_CX] == 0 {
+                return Ok(true)
+            }
+            if Some(self.regs[REG_CX]) == stop_cx {
+                return Ok(false)
+            }
+            self.string_op(bus, opcode)?;
+            self.regs[REG_CX] = self.regs[REG_CX].wrapping_sub(1);
+
+            if compares {
+                let zf = self.get_flag(FLAG_ZERO);
+                if (rep == RepPrefix::Repe && !zf) || (rep == RepPrefix::Repne && zf) {
+                    return Ok(true)
+                }
+            }
+        }
+    }
+
+    // Multiply and divide -----------------------------------------------------
+
+    fn muldiv(&mut self, op: u8, w: Width, src: u16) -> Result<(), RefCpuError> {
+        match (op, w) {
+            (4, Width::Byte) => {
+                let result = (self.regs[REG_AX] & 0xFF) * src;
+                self.regs[REG_AX] = result;
+                self.logic(w, (result >> 8) as u32);
+                let overflow = result & 0xFF00 != 0;
+                self.set_flag(FLAG_CARRY, overflow);
+                self.set_flag(FLAG_OVERFLOW, overflow);
+            }
+            (4, Width::Word) => {
+                let result = self.regs[REG_AX] as u32 * src as u32;
+                self.regs[REG_AX] = result as u16;
+                self.regs[REG_DX] = (result >> 16) as u16;
+                self.logic(w, result >> 16);
+                let overflow = result & 0xFFFF0000 != 0;
+                self.set_flag(FLAG_CARRY, overflow);
+                self.set_flag(FLAG_OVERFLOW, overflow);
+            }
+            (5, Width::Byte) => {
+                let result = (self.regs[REG_AX] as u8 as i8 as i16) * (src as u8 as i8 as i16);
+                self.regs[REG_AX] = result as u16;
+                self.add(w, (result as u16 >> 8) as u32, 0, result & 0x80 != 0);
+                let overflow = result != (result as i8 as i16);
+                self.set_flag(FLAG_CARRY, overflow);
+                self.set_flag(FLAG_OVERFLOW, overflow);
+            }
+            (5, Width::Word) => {
+                let result = (self.regs[REG_AX] as i16 as i32) * (src as i16 as i32);
+                self.regs[REG_AX] = result as u16;
+                self.regs[REG_DX] = (result >> 16) as u16;
+                self.add(w, (result as u32) >> 16, 0, result & 0x8000 != 0);
+                let overflow = result != (result as i16 as i32);
+                self.set_flag(FLAG_CARRY, overflow);
+                self.set_flag(FLAG_OVERFLOW, overflow);
+            }
+            (6, Width::Byte) => {
+                let dividend = self.regs[REG_AX];
+                if !self.cord_flags(w, (dividend >> 8) as u32, (dividend & 0xFF) as u32, src as u32) {
+                    return Err(RefCpuError::DivideError)
+                }
+                let quotient = dividend / src;
+                if quotient > 0xFF {
+                    return Err(RefCpuError::DivideError)
+                }
+                let remainder = dividend % src;
+                self.regs[REG_AX] = (remainder << 8) | quotient;
+            }
+            (6, Width::Word) => {
+                let dividend = ((self.regs[REG_DX] as u32) << 16) | self.regs[REG_AX] as u32;
+                if !self.cord_flags(w, dividend >> 16, dividend & 0xFFFF, src as u32) {
+                    return Err(RefCpuError::DivideError)
+                }
+                let quotient = dividend / src as u32;
+                if quotient > 0xFFFF {
+                    return Err(RefCpuError::DivideError)
+                }
+                self.regs[REG_AX] = quotient as u16;
+                self.regs[REG_DX] = (dividend % src as u32) as u16;
+            }
+            (7, Width::Byte) => {
+                let dividend = self.regs[REG_AX] as i16 as i32;
+                let divisor = src as u8 as i8 as i32;
+                let magnitude = dividend.unsigned_abs();
+                if !self.cord_flags(w, magnitude >> 8, magnitude & 0xFF, divisor.unsigned_abs()) {
+                    return Err(RefCpuError::DivideError)
+                }
+                let quotient = dividend / divisor;
+                // The 8088 cannot produce a quotient of -128.
+                if quotient > 127 || quotient < -127 {
+                    return Err(RefCpuError::DivideError)
+                }
+                let remainder = dividend % divisor;
+                self.regs[REG_AX] = ((remainder as u8 as u16) << 8) | (quotient as u8 as u16);
+                self.set_flag(FLAG_CARRY, false);
+                self.set_flag(FLAG_OVERFLOW, false);
+            }
+            _ => {
+                let dividend = (((self.regs[REG_DX] as u32) << 16) | self.regs[REG_AX] as u32) as i32 as i64;
+                let divisor = src as i16 as i64;
+                let magnitude = dividend.unsigned_abs() as u32;
+                if !self.cord_flags(w, magnitude >> 16, magnitude & 0xFFFF, divisor.unsigned_abs() as u32) {
+                    return Err(RefCpuError::DivideError)
+                }
+                let quotient = dividend / divisor;
+                // The 8088 cannot produce a quotient of -32768.
+                if quotient > 32767 || quotient < -32767 {
+                    return Err(RefCpuError::DivideError)
+                }
+                self.regs[REG_AX] = quotient as u16;
+                self.regs[REG_DX] = (dividend % divisor) as u16;
+                self.set_flag(FLAG_CARRY, false);
+                self.set_flag(FLAG_OVERFLOW, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the flags left by the 8088's shift-and-subtract division microcode to the
+    /// specified dividend and divisor magnitudes. Each step that doesn't shift a bit out of the
+    /// partial remainder subtracts the divisor with flags, so the flags are those of the last
+    /// such subtraction. Returns false, without changing flags, if the quotient will overflow.
+    fn cord_flags(&mut self, w: Width, high: u32, low: u32, divisor: u32) -> bool {
+        let mask = w.mask();
+        let (mut remainder, mut quotient) = (high & mask, low & mask);
+        if remainder >= divisor {
+            return false
+        }
+
+        let bits = if w == Width::Byte { 8 } else { 16 };
+        let mut carry = true;
+        for _ in 0..bits {
+            let quotient_out = quotient & w.sign() != 0;
+            quotient = ((quotient << 1) | carry as u32) & mask;
+            let remainder_out = remainder & w.sign() != 0;
+            remainder = ((remainder << 1) | quotient_out as u32) & mask;
+            let difference = remainder.wrapping_sub(divisor) & mask;
+
+            if remainder_out {
+                remainder = difference;
+                carry = false;
+            }
+            else {
+                carry = divisor > remainder;
+                self.sub(w, remainder, divisor, false);
+                if !carry {
+                    remainder = difference;
+                }
+            }
+        }
+        true
+    }
+
+    // BCD ---------------------------------------------------------------------
+
+    fn daa_das(&mut self, subtract: bool) {
+        let old_al = self.regs[REG_AX] & 0xFF;
+        let old_cf = self.get_flag(FLAG_CARRY);
+        let mut al = old_al;
+
+        if (al & 0x0F) > 9 || self.get_flag(FLAG_AUX_CARRY) {
+            al = if subtract { al.wrapping_sub(6) } else { al + 6 } & 0xFF;
+            self.set_flag(FLAG_AUX_CARRY, true);
+        }
+        else {
+            self.set_flag(FLAG_AUX_CARRY, false);
+        }
+
+        if old_al > 0x99 || old_cf {
+            al = if subtract { al.wrapping_sub(0x60) } else { al + 0x60 } & 0xFF;
+            self.set_flag(FLAG_CARRY, true);
+        }
+        else {
+            self.set_flag(FLAG_CARRY, false);
+        }
+
+        self.set_reg(Width::Byte, 0, al);
+        self.set_szp(Width::Byte, al as u32);
+    }
+
+    fn aaa_aas(&mut self, subtract: bool) {
+        let al = self.regs[REG_AX] & 0xFF;
+        if (al & 0x0F) > 9 || self.get_flag(FLAG_AUX_CARRY) {
+            let ax = if subtract {
+                let ah = (self.regs[REG_AX] >> 8).wrapping_sub(1) & 0xFF;
+                (ah << 8) | (al.wrapping_sub(6) & 0xFF)
+            }
+            else {
+                self.regs[REG_AX].wrapping_add(0x106)
+            };
+            self.regs[REG_AX] = ax;
+            self.set_flag(FLAG_AUX_CARRY, true);
+            self.set_flag(FLAG_CARRY, true);
+        }
+        else {
+            self.set_flag(FLAG_AUX_CARRY, false);
+            self.set_flag(FLAG_CARRY, false);
+        }
+        self.regs[REG_AX] &= 0xFF0F;
+    }
+
+    // Execution ---------------------------------------------------------------
+
+    /// Execute the single instruction in 'code', which must begin at the current CS:IP.
+    /// If 'stop_cx' is specified, a REP-prefixed string instruction will stop early with
+    /// IP pointing at the instruction when CX reaches that value.
+    pub fn execute(&mut self, code: &[u8], bus: &mut dyn RefBus, stop_cx: Option<u16>) -> Result<(), RefCpuError> {
+
+        self.code = code.to_vec();
+        self.code_pos = 0;
+        self.seg_override = None;
+
+        let mut rep = RepPrefix::None;
+
+        // Read prefixes
+        let mut opcode;
+        loop {
+            opcode = self.fetch_u8()?;
+            match opcode {
+                0x26 => self.seg_override = Some(SEG_ES),
+                0x2E => self.seg_override = Some(SEG_CS),
+                0x36 => self.seg_override = Some(SEG_SS),
+                0x3E => self.seg_override = Some(SEG_DS),
+                0xF0 | 0xF1 => {}
+                0xF2 => rep = RepPrefix::Repne,
+                0xF3 => rep = RepPrefix::Repe,
+                _ => break
+            }
+        }
+
+        let w = if opcode & 1 == 0 { Width::Byte } else { Width::Word };
+        // Set to false by control flow instructions that set IP themselves.
+        let mut advance_ip = true;
+
+        match opcode {
+            0x00..=0x3F if opcode & 0x07 < 6 => {
+                let op = (opcode >> 3) & 0x07;
+                match opcode & 0x07 {
+                    0x00 | 0x01 => {
+                        // op r/m, reg
+                        let m = self.decode_modrm()?;
+                        let a = self.read_rm(bus, w, &m)?;
+                        let b = self.get_reg(w, m.reg);
+                        if let Some(result) = self.alu(op, w, a, b) {
+                            self.write_rm(bus, w, &m, result);
+                        }
+                    }
+                    0x02 | 0x03 => {
+                        // op reg, r/m
+                        let m = self.decode_modrm()?;
+                        let a = self.get_reg(w, m.reg);
+                        let b = self.read_rm(bus, w, &m)?;
+                        if let Some(result) = self.alu(op, w, a, b) {
+                            self.set_reg(w, m.reg, result);
+                        }
+                    }
+                    _ => {
+                        // op AL/AX, imm
+                        let a = self.get_reg(w, 0);
+                        let b = self.fetch(w)?;
+                        if let Some(result) = self.alu(op, w, a, b) {
+                            self.set_reg(w, 0, result);
+                        }
+                    }
+                }
+            }
+            0x06 | 0x0E | 0x16 | 0x1E => {
+                let sreg = self.sregs[(opcode >> 3) as usize];
+                self.push(bus, sreg);
+            }
+            0x07 | 0x0F | 0x17 | 0x1F => {
+                // POP sreg. 0x0F is POP CS on the 8088.
+                let data = self.pop(bus)?;
+                self.sregs[(opcode >> 3) as usize] = data;
+            }
+            0x27 => self.daa_das(false),
+            0x2F => self.daa_das(true),
+            0x37 => self.aaa_aas(false),
+            0x3F => self.aaa_aas(true),
+            0x40..=0x47 => {
+                let r = self.regs[(opcode & 0x07) as usize];
+                self.regs[(opcode & 0x07) as usize] = self.inc_dec(Width::Word, r, false);
+            }
+            0x48..=0x4F => {
+                let r = self.regs[(opcode & 0x07) as usize];
+                self.regs[(opcode & 0x07) as usize] = self.inc_dec(Width::Word, r, true);
+            }
+            0x50..=0x57 => {
+                // PUSH reg. PUSH SP pushes the decremented value on the 8088.
+                let reg = (opcode & 0x07) as usize;
+                self.regs[REG_SP] = self.regs[REG_SP].wrapping_sub(2);
+                let data = self.regs[reg];
+                let (ss, sp) = (self.sregs[SEG_SS], self.regs[REG_SP]);
+                self.write_u16(bus, ss, sp, data);
+            }
+            0x58..=0x5F => {
+                let data = self.pop(bus)?;
+                self.regs[(opcode & 0x07) as usize] = data;
+            }
+            0x60..=0x7F => {
+                // 0x60-0x6F are aliases of 0x70-0x7F on the 8088.
+                let rel = self.fetch_u8()? as i8 as i16 as u16;
+                if self.condition(opcode & 0x0F) {
+                    self.ip = self.next_ip().wrapping_add(rel);
+                    advance_ip = false;
+                }
+            }
+            0x80..=0x83 => {
+                let m = self.decode_modrm()?;
+                let a = self.read_rm(bus, w, &m)?;
+                let b = match opcode {
+                    0x83 => self.fetch_u8()? as i8 as i16 as u16,
+                    0x81 => self.fetch_u16()?,
+                    _ => self.fetch_u8()? as u16
+                };
+                if let Some(result) = self.alu(m.reg, w, a, b) {
+                    self.write_rm(bus, w, &m, result);
+                }
+            }
+            0x84 | 0x85 => {
+                let m = self.decode_modrm()?;
+                let a = self.read_rm(bus, w, &m)?;
+                let b = self.get_reg(w, m.reg);
+                self.logic(w, (a & b) as u32);
+            }
+            0x86 | 0x87 => {
+                let m = self.decode_modrm()?;
+                let a = self.read_rm(bus, w, &m)?;
+                let b = self.get_reg(w, m.reg);
+                self.set_reg(w, m.reg, a);
+                self.write_rm(bus, w, &m, b);
+            }
+            0x88 | 0x89 => {
+                let m = self.decode_modrm()?;
+                let data = self.get_reg(w, m.reg);
+                self.write_rm(bus, w, &m, data);
+            }
+            0x8A | 0x8B => {
+                let m = self.decode_modrm()?;
+                let data = self.read_rm(bus, w, &m)?;
+                self.set_reg(w, m.reg, data);
+            }
+            0x8C => {
+                let m = self.decode_modrm()?;
+                let data = self.sregs[(m.reg & 0x03) as usize];
+                self.write_rm(bus, Width::Word, &m, data);
+            }
+            0x8D => {
+                let m = self.decode_modrm()?;
+                if m.mode == 3 {
+                    return Err(RefCpuError::Unsupported(opcode))
+                }
+                self.set_reg(Width::Word, m.reg, m.offset);
+            }
+            0x8E => {
+                let m = self.decode_modrm()?;
+                let data = self.read_rm(bus, Width::Word, &m)?;
+                self.sregs[(m.reg & 0x03) as usize] = data;
+            }
+            0x8F => {
+                let m = self.decode_modrm()?;
+                let data = self.pop(bus)?;
+                self.write_rm(bus, Width::Word, &m, data);
+            }
+            0x90..=0x97 => {
+                let reg = (opcode & 0x07) as usize;
+                self.regs.swap(REG_AX, reg);
+            }
+            0x98 => {
+                self.regs[REG_AX] = self.regs[REG_AX] as u8 as i8 as i16 as u16;
+            }
+            0x99 => {
+                self.regs[REG_DX] = if self.regs[REG_AX] & 0x8000 != 0 { 0xFFFF } else { 0 };
+            }
+            0x9A => {
+                let new_ip = self.fetch_u16()?;
+                let new_cs = self.fetch_u16()?;
+                let cs = self.sregs[SEG_CS];
+                let ret = self.next_ip();
+                self.push(bus, cs);
+                self.push(bus, ret);
+                self.sregs[SEG_CS] = new_cs;
+                self.ip = new_ip;
+                advance_ip = false;
+            }
+            0x9B => {
+                // WAIT
+            }
+            0x9C => {
+                let flags = self.flags;
+                self.push(bus, flags);
+            }
+            0x9D => {
+                let data = self.pop(bus)?;
+                self.flags = (data & FLAGS_DEFINED) | FLAGS_RESERVED_ON;
+            }
+            0x9E => {
+                let ah = self.regs[REG_AX] >> 8;
+                let mask = FLAG_SIGN | FLAG_ZERO | FLAG_AUX_CARRY | FLAG_PARITY | FLAG_CARRY;
+                self.flags = (self.flags & !mask) | (ah & mask);
+            }
+            0x9F => {
+                let flags = self.flags & 0xFF;
+                self.set_reg(Width::Byte, 4, flags);
+            }
+            0xA0..=0xA3 => {
+                let offset = self.fetch_u16()?;
+                let seg = self.data_segment(SEG_DS);
+                if opcode < 0xA2 {
+                    let data = self.read_mem(bus, w, seg, offset)?;
+                    self.set_reg(w, 0, data);
+                }
+                else {
+                    let data = self.get_reg(w, 0);
+                    self.write_mem(bus, w, seg, offset, data);
+                }
+            }
+            0xA4..=0xA7 | 0xAA..=0xAF => {
+                if !self.string_instr(bus, opcode, rep, stop_cx)? {
+                    // Repeated string instruction was interrupted; IP stays on the instruction.
+                    advance_ip = false;
+                }
+            }
+            0xA8 | 0xA9 => {
+                let a = self.get_reg(w, 0);
+                let b = self.fetch(w)?;
+                self.logic(w, (a & b) as u32);
+            }
+            0xB0..=0xB7 => {
+                let data = self.fetch_u8()? as u16;
+                self.set_reg(Width::Byte, opcode & 0x07, data);
+            }
+            0xB8..=0xBF => {
+                let data = self.fetch_u16()?;
+                self.set_reg(Width::Word, opcode & 0x07, data);
+            }
+            0xC0 | 0xC2 => {
+                let imm = self.fetch_u16()?;
+                self.ip = self.pop(bus)?;
+                self.regs[REG_SP] = self.regs[REG_SP].wrapping_add(imm);
+                advance_ip = false;
+            }
+            0xC1 | 0xC3 => {
+                self.ip = self.pop(bus)?;
+                advance_ip = false;
+            }
+            0xC4 | 0xC5 => {
+                let m = self.decode_modrm()?;
+                if m.mode == 3 {
+                    return Err(RefCpuError::Unsupported(opcode))
+                }
+                let offset = self.read_u16(bus, m.seg, m.offset)?;
+                let seg = self.read_u16(bus, m.seg, m.offset.wrapping_add(2))?;
+                self.set_reg(Width::Word, m.reg, offset);
+                self.sregs[if opcode == 0xC4 { SEG_ES } else { SEG_DS }] = seg;
+            }
+            0xC6 | 0xC7 => {
+                let m = self.decode_modrm()?;
+                let data = self.fetch(w)?;
+                self.write_rm(bus, w, &m, data);
+            }
+            0xC8 | 0xCA => {
+                let imm = self.fetch_u16()?;
+                self.ip = self.pop(bus)?;
+                self.sregs[SEG_CS] = self.pop(bus)?;
+                self.regs[REG_SP] = self.regs[REG_SP].wrapping_add(imm);
+                advance_ip = false;
+            }
+            0xC9 | 0xCB => {
+                self.ip = self.pop(bus)?;
+                self.sregs[SEG_CS] = self.pop(bus)?;
+                advance_ip = false;
+            }
+            0xCC => {
+                let ret = self.next_ip();
+                self.interrupt(bus, 3, ret)?;
+                advance_ip = false;
+            }
+            0xCD => {
+                let vector = self.fetch_u8()?;
+                let ret = self.next_ip();
+                self.interrupt(bus, vector, ret)?;
+                advance_ip = false;
+            }
+            0xCE => {
+                if self.get_flag(FLAG_OVERFLOW) {
+                    let ret = self.next_ip();
+                    self.interrupt(bus, 4, ret)?;
+                    advance_ip = false;
+                }
+            }
+            0xCF => {
+                self.ip = self.pop(bus)?;
+                self.sregs[SEG_CS] = self.pop(bus)?;
+                let flags = self.pop(bus)?;
+                self.flags = (flags & FLAGS_DEFINED) | FLAGS_RESERVED_ON;
+                advance_ip = false;
+            }
+            0xD0..=0xD3 => {
+                let m = self.decode_modrm()?;
+                let value = self.read_rm(bus, w, &m)?;
+                let count = if opcode < 0xD2 { 1 } else { self.regs[REG_CX] as u8 };
+                let result = self.shift(m.reg, w, value, count);
+                self.write_rm(bus, w, &m, result);
+            }
+            0xD4 => {
+                // AAM
+                let base = self.fetch_u8()? as u16;
+                if base == 0 {
+                    return Err(RefCpuError::DivideError)
+                }
+                let al = self.regs[REG_AX] & 0xFF;
+                self.regs[REG_AX] = ((al / base) << 8) | (al % base);
+                self.logic(Width::Byte, (al % base) as u32);
+            }
+            0xD5 => {
+                // AAD
+                let base = self.fetch_u8()? as u32;
+                let al = (self.regs[REG_AX] & 0xFF) as u32;
+                let ah = (self.regs[REG_AX] >> 8) as u32;
+                let product = (ah * base) & 0xFF;
+                let result = self.add(Width::Byte, al, product, false);
+                self.regs[REG_AX] = result;
+            }
+            0xD6 => {
+                // SALC (undocumented)
+                let data = if self.get_flag(FLAG_CARRY) { 0xFF } else { 0x00 };
+                self.set_reg(Width::Byte, 0, data);
+            }
+            0xD7 => {
+                // XLAT
+                let seg = self.data_segment(SEG_DS);
+                let offset = self.regs[REG_BX].wrapping_add(self.regs[REG_AX] & 0xFF);
+                let data = self.read_u8(bus, seg, offset)? as u16;
+                self.set_reg(Width::Byte, 0, data);
+            }
+            0xD8..=0xDF => {
+                // ESC. With no coprocessor, the 8088 only decodes the operand.
+                self.decode_modrm()?;
+            }
+            0xE0..=0xE2 => {
+                // LOOPNE, LOOPE, LOOP
+                let rel = self.fetch_u8()? as i8 as i16 as u16;
+                self.regs[REG_CX] = self.regs[REG_CX].wrapping_sub(1);
+                let zf = self.get_flag(FLAG_ZERO);
+                let take = self.regs[REG_CX] != 0 && match opcode {
+                    0xE0 => !zf,
+                    0xE1 => zf,
+                    _ => true
+                };
+                if take {
+                    self.ip = self.next_ip().wrapping_add(rel);
+                    advance_ip = false;
+                }
+            }
+            0xE3 => {
+                let rel = self.fetch_u8()? as i8 as i16 as u16;
+                if self.regs[REG_CX] == 0 {
+                    self.ip = self.next_ip().wrapping_add(rel);
+                    advance_ip = false;
+                }
+            }
+            0xE4 | 0xE5 | 0xEC | 0xED => {
+                let port = if opcode < 0xE8 { self.fetch_u8()? as u16 } else { self.regs[REG_DX] };
+                let lo = bus.io_read_u8(port)? as u16;
+                if w == Width::Word {
+                    let hi = bus.io_read_u8(port.wrapping_add(1))? as u16;
+                    self.regs[REG_AX] = lo | (hi << 8);
+                }
+                else {
+                    self.set_reg(Width::Byte, 0, lo);
+                }
+            }
+            0xE6 | 0xE7 | 0xEE | 0xEF => {
+                let port = if opcode < 0xE8 { self.fetch_u8()? as u16 } else { self.regs[REG_DX] };
+                let ax = self.regs[REG_AX];
+                bus.io_write_u8(port, ax as u8);
+                if w == Width::Word {
+                    bus.io_write_u8(port.wrapping_add(1), (ax >> 8) as u8);
+                }
+            }
+            0xE8 => {
+                let rel = self.fetch_u16()?;
+                let ret = self.next_ip();
+                self.push(bus, ret);
+                self.ip = ret.wrapping_add(rel);
+                advance_ip = false;
+            }
+            0xE9 => {
+                let rel = self.fetch_u16()?;
+                self.ip = self.next_ip().wrapping_add(rel);
+                advance_ip = false;
+            }
+            0xEA => {
+                let new_ip = self.fetch_u16()?;
+                let new_cs = self.fetch_u16()?;
+                self.ip = new_ip;
+                self.sregs[SEG_CS] = new_cs;
+                advance_ip = false;
+            }
+            0xEB => {
+                let rel = self.fetch_u8()? as i8 as i16 as u16;
+                self.ip = self.next_ip().wrapping_add(rel);
+                advance_ip = false;
+            }
+            0xF4 => {
+                // HLT
+            }
+            0xF5 => {
+                let cf = self.get_flag(FLAG_CARRY);
+                self.set_flag(FLAG_CARRY, !cf);
+            }
+            0xF6 | 0xF7 => {
+                let m = self.decode_modrm()?;
+                match m.reg {
+                    0 | 1 => {
+                        let a = self.read_rm(bus, w, &m)?;
+                        let b = self.fetch(w)?;
+                        self.logic(w, (a & b) as u32);
+                    }
+                    2 => {
+                        let a = self.read_rm(bus, w, &m)?;
+                        self.write_rm(bus, w, &m, !a & w.mask() as u16);
+                    }
+                    3 => {
+                        let a = self.read_rm(bus, w, &m)?;
+                        let result = self.sub(w, 0, a as u32, false);
+                        self.set_flag(FLAG_CARRY, a != 0);
+                        self.write_rm(bus, w, &m, result);
+                    }
+                    _ => {
+                        if rep != RepPrefix::None {
+                            // A REP prefix modifies the sign of IMUL/IDIV results on the 8088.
+                            return Err(RefCpuError::Unsupported(opcode))
+                        }
+                        let src = self.read_rm(bus, w, &m)?;
+                        self.muldiv(m.reg, w, src)?;
+                    }
+                }
+            }
+            0xF8 => self.set_flag(FLAG_CARRY, false),
+            0xF9 => self.set_flag(FLAG_CARRY, true),
+            0xFA => self.set_flag(FLAG_INTERRUPT, false),
+            0xFB => self.set_flag(FLAG_INTERRUPT, true),
+            0xFC => self.set_flag(FLAG_DIRECTION, false),
+            0xFD => self.set_flag(FLAG_DIRECTION, true),
+            0xFE | 0xFF => {
+                let m = self.decode_modrm()?;
+                match m.reg {
+                    0 | 1 => {
+                        let a = self.read_rm(bus, w, &m)?;
+                        let result = self.inc_dec(w, a, m.reg == 1);
+                        self.write_rm(bus, w, &m, result);
+                    }
+                    _ if w == Width::Byte => {
+                        // FE with reg >= 2 performs a mix of byte and word operations.
+                        return Err(RefCpuError::Unsupported(opcode))
+                    }
+                    2 => {
+                        let target = self.read_rm(bus, w, &m)?;
+                        let ret = self.next_ip();
+                        self.push(bus, ret);
+                        self.ip = target;
+                        advance_ip = false;
+                    }
+                    3 | 5 => {
+                        if m.mode == 3 {
+                            return Err(RefCpuError::Unsupported(opcode))
+                        }
+                        let new_ip = self.read_u16(bus, m.seg, m.offset)?;
+                        let new_cs = self.read_u16(bus, m.seg, m.offset.wrapping_add(2))?;
+                        if m.reg == 3 {
+                            let cs = self.sregs[SEG_CS];
+                            let ret = self.next_ip();
+                            self.push(bus, cs);
+                            self.push(bus, ret);
+                        }
+                        self.sregs[SEG_CS] = new_cs;
+                        self.ip = new_ip;
+                        advance_ip = false;
+                    }
+                    4 => {
+                        self.ip = self.read_rm(bus, w, &m)?;
+                        advance_ip = false;
+                    }
+                    _ => {
+                        // PUSH r/m. 0xFF /7 is an alias on the 8088.
+                        if m.mode == 3 && m.rm == REG_SP as u8 {
+                            // PUSH SP via modrm pushes the decremented value
+                            self.regs[REG_SP] = self.regs[REG_SP].wrapping_sub(2);
+                            let (ss, sp) = (self.sregs[SEG_SS], self.regs[REG_SP]);
+                            self.write_u16(bus, ss, sp, sp);
+                        }
+                        else {
+                            let data = self.read_rm(bus, w, &m)?;
+                            self.push(bus, data);
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(RefCpuError::Unsupported(opcode))
+            }
+        }
+
+        if advance_ip {
+            self.ip = self.next_ip();
+        }
+        self.flags = (self.flags & FLAGS_DEFINED) | FLAGS_RESERVED_ON;
+        Ok(())
+    }
+}
+
+/// Return a mask of the flags left undefined by the specified instruction.
+pub fn undefined_flags(opcode: u8, modrm: u8) -> u16 {
+    let reg = (modrm >> 3) & 0x07;
+    let logic = FLAG_AUX_CARRY;
+    match opcode {
+        0x08..=0x0D | 0x20..=0x25 | 0x30..=0x35 | 0x84 | 0x85 | 0xA8 | 0xA9 => logic,
+        0x80..=0x83 if reg == 1 || reg == 4 || reg == 6 => logic,
+        0x27 | 0x2F => FLAG_OVERFLOW,
+        0x37 | 0x3F => FLAG_OVERFLOW | FLAG_SIGN | FLAG_ZERO | FLAG_PARITY,
+        0xD0 | 0xD1 => FLAG_AUX_CARRY,
+        0xD2 | 0xD3 => FLAG_AUX_CARRY | FLAG_OVERFLOW,
+        0xD4 | 0xD5 => FLAG_OVERFLOW | FLAG_AUX_CARRY | FLAG_CARRY,
+        0xF6 | 0xF7 => match reg {
+            0 | 1 => logic,
+            4 | 5 => FLAG_SIGN | FLAG_ZERO | FLAG_AUX_CARRY | FLAG_PARITY,
+            6 | 7 => FLAG_CARRY | FLAG_OVERFLOW | FLAG_SIGN | FLAG_ZERO | FLAG_AUX_CARRY | FLAG_PARITY,
+            _ => 0
+        },
+        _ => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestBus {
+        mem: Vec<u8>
+    }
+
+    impl TestBus {
+        fn new() -> Self {
+            Self { mem: vec![0; 0x100000] }
+        }
+    }
+
+    impl RefBus for TestBus {
+        fn read_u8(&mut self, addr: u32) -> Result<u8, RefCpuError> {
+            Ok(self.mem[addr as usize])
+        }
+        fn write_u8(&mut self, addr: u32, data: u8) {
+            self.mem[addr as usize] = data;
+        }
+        fn io_read_u8(&mut self, _port: u16) -> Result<u8, RefCpuError> {
+            Ok(0xFF)
+        }
+        fn io_write_u8(&mut self, _port: u16, _data: u8) {}
+    }
+
+    #[test]
+    fn test_add_al_imm() {
+        let mut bus = TestBus::new();
+        let mut cpu = RefCpu::from_vregs(&VRegisters { ax: 0x0001, ..Default::default() });
+
+        // ADD AL, 7Fh
+        cpu.execute(&[0x04, 0x7F], &mut bus, None).unwrap();
+        let regs = cpu.to_vregs();
+        assert_eq!(regs.ax, 0x0080);
+        assert_eq!(regs.ip, 2);
+        assert_eq!(
+            regs.flags & FLAGS_DEFINED, 
+            FLAG_OVERFLOW | FLAG_SIGN | FLAG_AUX_CARRY
+        );
+    }
+
+    #[test]
+    fn test_flag_masking() {
+        let mut bus = TestBus::new();
+        let mut cpu = RefCpu::from_vregs(&VRegisters { flags: 0xFFFF, ..Default::default() });
+
+        // CLC: undefined bits 1, 3 and 5 are cleared and the reserved high bits stay set.
+        cpu.execute(&[0xF8], &mut bus, None).unwrap();
+        assert_eq!(cpu.to_vregs().flags, (FLAGS_DEFINED & !FLAG_CARRY) | FLAGS_RESERVED_ON);
+
+        // Logical ops leave AF undefined; ADD does not.
+        assert_eq!(undefined_flags(0x80, 0b0010_0000), FLAG_AUX_CARRY);
+        assert_eq!(undefined_flags(0x80, 0b0000_0000), 0);
+        assert_eq!(undefined_flags(0xD4, 0x0A), FLAG_OVERFLOW | FLAG_AUX_CARRY | FLAG_CARRY);
+    }
+
+    #[test]
+    fn test_mov_to_memory() {
+        let mut bus = TestBus::new();
+        let mut cpu = RefCpu::from_vregs(&VRegisters { 
+            ax: 0x1234, 
+            bx: 0x0010, 
+            si: 0x0002, 
+            ds: 0x0100, 
+            ..Default::default() 
+        });
+
+        // MOV [BX+SI], AX
+        cpu.execute(&[0x89, 0x00], &mut bus, None).unwrap();
+        assert_eq!(bus.mem[0x1012], 0x34);
+        assert_eq!(bus.mem[0x1013], 0x12);
+
+        // MOV CX, [BX+SI]
+        cpu.execute(&[0x8B, 0x08], &mut bus, None).unwrap();
+        assert_eq!(cpu.to_vregs().cx, 0x1234);
+        assert_eq!(cpu.to_vregs().ip, 4);
+    }
+
+    #[test]
+    fn test_div_flags_from_last_subtraction() {
+        let mut cpu = RefCpu::from_vregs(&VRegisters::default());
+        cpu.regs[REG_AX] = 6;
+        cpu.muldiv(6, Width::Byte, 2).unwrap();
+        assert_eq!(cpu.regs[REG_AX], 0x0003);
+        // The last step subtracts the divisor from a partial remainder of 2.
+        assert!(cpu.get_flag(FLAG_ZERO));
+        assert!(cpu.get_flag(FLAG_PARITY));
+        assert!(!cpu.get_flag(FLAG_CARRY));
+
+        // Flags are unchanged by a divide error.
+        cpu.flags = FLAG_CARRY | FLAG_SIGN;
+        cpu.regs[REG_AX] = 0x0200;
+        assert_eq!(cpu.muldiv(6, Width::Byte, 2), Err(RefCpuError::DivideError));
+        assert_eq!(cpu.flags, FLAG_CARRY | FLAG_SIGN);
+    }
+
+    #[test]
+    fn test_multibit_shift_flags() {
+        let mut cpu = RefCpu::from_vregs(&VRegisters::default());
+        // SHL 0x13 by 3: the last shift is 0x4C -> 0x98
+        assert_eq!(cpu.shift(4, Width::Byte, 0x13, 3), 0x98);
+        assert!(!cpu.get_flag(FLAG_CARRY));
+        assert!(cpu.get_flag(FLAG_OVERFLOW));
+        assert!(cpu.get_flag(FLAG_AUX_CARRY));
+
+        // SHR 0x8000 by 2: the last shift is 0x4000 -> 0x2000
+        assert_eq!(cpu.shift(5, Width::Word, 0x8000, 2), 0x2000);
+        assert!(!cpu.get_flag(FLAG_OVERFLOW));
+        assert!(!cpu.get_flag(FLAG_AUX_CARRY));
+    }
+}
@@ -0,0 +1,327 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    rewind.rs
+
+    Implements machine snapshots and a ring buffer of periodic snapshots that
+    allows the emulated machine to be stepped backwards in time.
+
+    A snapshot holds the serialized state of each component that implements
+    SaveState. Section data is split into pages, and pages that are unchanged
+    since the previous snapshot are shared with it rather than copied. Since
+    most of memory does not change between snapshots, a snapshot typically 
+    costs a small fraction of the size of the machine's memory.
+
+    Restoring a snapshot loads its sections over the running machine without
+    resetting it first. Components that do not implement SaveState, such as
+    the disk controllers, serial ports and mouse, are left in their current
+    state, as are host resources such as hard disk images.
+*/
+
+use std::{
+    collections::VecDeque,
+    sync::Arc
+};
+
+use crate::savestate::StateFile;
+
+pub const DEFAULT_REWIND_INTERVAL: u32 = 30;
+pub const DEFAULT_REWIND_BUFFER_LEN: usize = 60;
+pub const SNAPSHOT_PAGE_SIZE: usize = 4096;
+
+struct SnapshotSection {
+    id: String,
+    version: u16,
+    pages: Vec<Arc<[u8]>>
+}
+
+/// The saved state of the emulated machine at a point in time.
+pub struct MachineSnapshot {
+    machine: String,
+    sections: Vec<SnapshotSection>,
+    pub(crate) cpu_cycles: u64,
+}
+
+impl MachineSnapshot {
+    /// Create a snapshot from the sections of a state file. Pages identical to the same page
+    /// of the same section in 'prev' are shared with it.
+    pub fn new(state: &StateFile, cpu_cycles: u64, prev: Option<&MachineSnapshot>) -> Self {
+        let sections = state.sections().map(|(id, version, data)| {
+            let prev_pages = prev
+                .and_then(|prev| prev.sections.iter().find(|s| s.id == id))
+                .map_or(&[][..], |s| s.pages.as_slice());
+
+            let pages = data.chunks(SNAPSHOT_PAGE_SIZE)
+                .enumerate()
+                .map(|(i, chunk)| match prev_pages.get(i) {
+                    Some(page) if **page == *chunk => page.clone(),
+                    _ => Arc::from(chunk)
+                })
+                .collect();
+
+            SnapshotSection {
+                id: id.to_string(),
+                version,
+                pages
+            }
+        }).collect();
+
+        Self {
+            machine: state.machine.clone(),
+            sections,
+            cpu_cycles
+        }
+    }
+
+    /// Reassemble the snapshot into a state file.
+    pub fn to_state_file(&self) -> StateFile {
+        let mut state = StateFile::new(&self.machine);
+        for section in &self.sections {
+            state.push_section(&section.id, section.version, section.pages.concat());
+        }
+        state
+    }
+
+    /// Return the number of bytes held by this snapshot that are not shared with any other.
+    pub fn unique_bytes(&self) -> usize {
+        self.sections.iter()
+            .flat_map(|s| s.pages.iter())
+            .filter(|page| Arc::strong_count(page) == 1)
+            .map(|page| page.len())
+            .sum()
+    }
+}
+struct RewindEntry {
+    frame: u64,
+    snapshot: MachineSnapshot
+}
+
+/// A ring buffer of machine snapshots taken every 'interval' frames. Once the buffer is full, 
+/// the oldest snapshot is discarded for each new one.
+pub struct RewindBuffer {
+    entries: VecDeque<RewindEntry>,
+    capacity: usize,
+    interval: u32,
+    frame: u64,
+    last_cycles: u64,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frame: 0,
+            last_cycles: 0
+        }
+    }
+
+    /// Advance the frame counter. Frames in which the machine did not run (ie, while paused)
+    /// are not counted. Returns true if a snapshot should be taken this frame.
+    pub fn frame_update(&mut self, cpu_cycles: u64) -> bool {
+        if cpu_cycles == self.last_cycles {
+            return false
+        }
+        self.last_cycles = cpu_cycles;
+        self.frame += 1;
+        self.frame % self.interval as u64 == 0
+    }
+
+    pub fn push(&mut self, snapshot: MachineSnapshot) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(RewindEntry {
+            frame: self.frame,
+            snapshot
+        });
+    }
+
+    /// Remove and return the most recent snapshot that is at least 'frames' frames old, 
+    /// discarding any newer snapshots. If no snapshot is old enough, the oldest snapshot 
+    /// is returned.
+    pub fn rewind(&mut self, frames: u64) -> Option<MachineSnapshot> {
+        let target_frame = self.frame.saturating_sub(frames);
+
+        while let Some(entry) = self.entries.pop_back() {
+            if entry.frame <= target_frame || self.entries.is_empty() {
+                self.frame = entry.frame;
+                self.last_cycles = entry.snapshot.cpu_cycles;
+                return Some(entry.snapshot)
+            }
+        }
+        None
+    }
+
+    /// Return the number of frames that can be rewound.
+    pub fn depth(&self) -> u64 {
+        match self.entries.front() {
+            Some(entry) => self.frame - entry.frame,
+            None => 0
+        }
+    }
+
+    /// Return the most recent snapshot, if any. New snapshots should share pages with it.
+    pub fn latest(&self) -> Option<&MachineSnapshot> {
+        self.entries.back().map(|entry| &entry.snapshot)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TraceMode;
+    use crate::cpu_808x::{Cpu, CpuAddress, Register16};
+    use crate::cpu_common::CpuType;
+    use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+    use crate::tracelogger::TraceLogger;
+
+    struct TestMemory {
+        data: Vec<u8>
+    }
+
+    impl SaveState for TestMemory {
+        const STATE_ID: &'static str = "test";
+        const STATE_VERSION: u16 = 1;
+
+        fn save_state(&self, w: &mut StateWriter) {
+            w.write_bytes(&self.data);
+        }
+
+        fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+            r.read_into(&mut self.data)
+        }
+    }
+
+    fn snapshot(mem: &TestMemory, cycles: u64, prev: Option<&MachineSnapshot>) -> MachineSnapshot {
+        let mut state = StateFile::new("test");
+        state.save(mem);
+        MachineSnapshot::new(&state, cycles, prev)
+    }
+
+    #[test]
+    fn test_snapshot_shares_pages() {
+        let mut mem = TestMemory { data: vec![0; SNAPSHOT_PAGE_SIZE * 8] };
+        let first = snapshot(&mem, 0, None);
+
+        mem.data[SNAPSHOT_PAGE_SIZE * 3 + 10] = 0xAA;
+        let second = snapshot(&mem, 1, Some(&first));
+        assert_eq!(second.unique_bytes(), SNAPSHOT_PAGE_SIZE);
+
+        let mut restored = TestMemory { data: vec![0; SNAPSHOT_PAGE_SIZE * 8] };
+        assert!(first.to_state_file().load(&mut restored).unwrap());
+        assert!(restored.data.iter().all(|&b| b == 0));
+        assert!(second.to_state_file().load(&mut restored).unwrap());
+        assert_eq!(restored.data, mem.data);
+    }
+
+    #[test]
+    fn test_buffer_evicts_oldest() {
+        let mem = TestMemory { data: vec![0; 16] };
+        let mut buffer = RewindBuffer::new(2, 3);
+
+        for cycles in 1..=10 {
+            if buffer.frame_update(cycles) {
+                let snapshot = snapshot(&mem, cycles, buffer.latest());
+                buffer.push(snapshot);
+            }
+        }
+        // Frames without cycles run are not counted.
+        assert!(!buffer.frame_update(10));
+
+        // Snapshots were taken at frames 2, 4, 6, 8 and 10, and the first two were evicted.
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.depth(), 4);
+
+        assert_eq!(buffer.rewind(2).map(|s| s.cpu_cycles), Some(8));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.rewind(100).map(|s| s.cpu_cycles), Some(6));
+        assert!(buffer.is_empty());
+        assert!(buffer.rewind(1).is_none());
+    }
+
+    #[test]
+    fn test_restore_cpu_and_memory() {
+        const CODE_SEG: u16 = 0x1000;
+        const COUNTER_ADDRESS: usize = ((CODE_SEG as usize) << 4) + 0x200;
+        // INC AX; INC WORD [0200h]; JMP 0000h
+        let code = [0x40, 0xFF, 0x06, 0x00, 0x02, 0xEB, 0xF9];
+
+        let mut cpu = Cpu::new(
+            CpuType::Intel8088,
+            TraceMode::None,
+            TraceLogger::None,
+            #[cfg(feature = "cpu_validator")]
+            crate::config::ValidatorType::None,
+            #[cfg(feature = "cpu_validator")]
+            TraceLogger::None
+        );
+        cpu.bus_mut().copy_from(&code, (CODE_SEG as usize) << 4, 0, false).unwrap();
+        cpu.set_reset_vector(CpuAddress::Segmented(CODE_SEG, 0));
+        cpu.reset();
+        cpu.set_register16(Register16::DS, CODE_SEG);
+
+        let run = |cpu: &mut Cpu| {
+            for _ in 0..30 {
+                cpu.step(false).unwrap();
+            }
+            cpu.biu_bus_wait_finish();
+        };
+        let cpu_state = |cpu: &Cpu| {
+            (
+                cpu.get_register16(Register16::AX),
+                cpu.get_register16(Register16::IP),
+                cpu.bus().get_slice_at(COUNTER_ADDRESS, 2).to_vec()
+            )
+        };
+
+        run(&mut cpu);
+        let mut state = StateFile::new("test");
+        state.save(&cpu);
+        cpu.bus().save_devices(&mut state);
+        let snapshot = MachineSnapshot::new(&state, 0, None);
+        let saved = cpu_state(&cpu);
+
+        run(&mut cpu);
+        assert_ne!(cpu_state(&cpu).0, saved.0);
+        assert_ne!(cpu_state(&cpu).2, saved.2);
+
+        let state = snapshot.to_state_file();
+        assert!(state.load(&mut cpu).unwrap());
+        cpu.bus_mut().load_devices(&state).unwrap();
+        assert_eq!(cpu_state(&cpu), saved);
+    }
+}
@@ -62,6 +62,60 @@ use crate::bus::{BusInterface, MEM_CP_BIT};
 
 pub const BIOS_READ_CYCLE_COST: u32 = 4;
 
+// The region scanned for option ROMs at boot, and the alignment of the ROMs within it.
+pub const OPTION_ROM_SCAN_START: usize = 0xC0000;
+pub const OPTION_ROM_SCAN_END: usize = 0xF0000;
+pub const OPTION_ROM_ALIGNMENT: usize = 0x800;
+
+// The offset of an option ROM's init routine from the start of its segment.
+pub const OPTION_ROM_INIT_OFFSET: u16 = 3;
+
+// The entry point of the IBM BIOS bootstrap loader (INT 19h), at F000:E6F2.
+pub const BIOS_BOOTSTRAP_ADDRESS: u32 = 0xFE6F2;
+
+/// An option ROM found by scan_option_roms().
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct OptionRomInfo {
+    pub segment: u16,
+    pub size: usize,
+    pub checksum_ok: bool,
+}
+
+/// Return true if the bytes of the option ROM image sum to 0 over the length given in its header.
+fn option_rom_checksum_ok(rom: &[u8]) -> bool {
+    let len = (rom[2] as usize * 512).min(rom.len());
+    rom[..len].iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Scan a region of memory starting at address `base` for option ROMs, the way the BIOS does 
+/// at boot. Each 2K boundary is checked for the 55AA signature. A ROM with a good checksum is 
+/// skipped over in full; a ROM with a bad checksum is reported but would not be initialized.
+pub fn scan_option_roms(mem: &[u8], base: usize) -> Vec<OptionRomInfo> {
+
+    let mut roms = Vec::new();
+    let mut offset = (OPTION_ROM_ALIGNMENT - base % OPTION_ROM_ALIGNMENT) % OPTION_ROM_ALIGNMENT;
+
+    while offset + 3 <= mem.len() {
+        let rom = &mem[offset..];
+        if rom[0] == 0x55 && rom[1] == 0xAA && rom[2] != 0 {
+            let size = (rom[2] as usize * 512).min(rom.len());
+            let checksum_ok = option_rom_checksum_ok(rom);
+            roms.push(OptionRomInfo { 
+                segment: ((base + offset) >> 4) as u16, 
+                size, 
+                checksum_ok 
+            });
+            if checksum_ok {
+                // Resume the scan at the next boundary past the end of this ROM.
+                offset += (size + OPTION_ROM_ALIGNMENT - 1) / OPTION_ROM_ALIGNMENT * OPTION_ROM_ALIGNMENT;
+                continue;
+            }
+        }
+        offset += OPTION_ROM_ALIGNMENT;
+    }
+    roms
+}
+
 #[derive (Copy, Clone, Debug)]
 pub struct RawRomDescriptor {
     pub addr: u32,
@@ -92,12 +146,14 @@ impl Display for RomError{
     }
 }
 
+#[derive (Clone)]
 pub enum RomInterleave {
     None,
     Odd,
     Even
 }
 
+#[derive (Clone)]
 pub enum RomOrder {
     Normal,
     Reversed
@@ -111,7 +167,7 @@ pub enum RomFeature {
     VGA
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum RomType {
     BIOS,
     BASIC,
@@ -133,6 +189,8 @@ pub struct RomSet {
     machine_type: MachineType,
     priority: u32,
     reset_vector: (u16, u16),
+    // Whether the BIOS scans for and initializes option ROMs itself
+    scans_option_roms: bool,
     roms: Vec<&'static str>,
     is_complete: Cell<bool>,
 }
@@ -141,6 +199,7 @@ pub struct RomSetOverride {
     roms: Vec<PathBuf>,
 }
 
+#[derive (Clone)]
 pub struct RomDescriptor {
     rom_type: RomType,
     present: bool,
@@ -159,6 +218,7 @@ pub struct RomDescriptor {
     checkpoints: HashMap<u32, &'static str>,
 }
 
+#[derive (Clone)]
 pub struct RomManager {
 
     machine_type: MachineType,
@@ -173,7 +233,9 @@ pub struct RomManager {
     features_available: Vec<RomFeature>,
     features_requested: Vec<RomFeature>,
     rom_override: Option<Vec<RomOverride>>,
-    raw_roms: Vec<(Vec<u8>, RawRomDescriptor)>
+    raw_roms: Vec<(Vec<u8>, RawRomDescriptor)>,
+    option_roms: Vec<(Vec<u8>, u32)>,
+    preferred_bios: Vec<String>,
 }
 
 impl RomManager {
@@ -193,6 +255,7 @@ impl RomManager {
                     priority: 0,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: false,
                     roms: vec![
                         "6338a9808445de12109a2389b71ee2eb",  // 5150 BIOS v1 04/24/81
                         "2ad31da203a49b504fad3a34af0c719f",  // Basic v1.0
@@ -205,6 +268,7 @@ impl RomManager {
                     priority: 1,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: false,
                     roms: vec![
                         "6a1ed4e3f500d785a01ff4d3e000d79c", // 5150 BIOS v2 10/19/81
                         "2ad31da203a49b504fad3a34af0c719f",  // Basic v1.0
@@ -217,6 +281,7 @@ impl RomManager {
                     priority: 2,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "f453eb2df6daf21ec644d33663d85434", // 5150 BIOS v3 10/27/82
                         "2ad31da203a49b504fad3a34af0c719f",  // Basic v1.0
@@ -229,6 +294,7 @@ impl RomManager {
                     priority: 2,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "d2fbadfecb1bd5509ddeaf40acf143ec", // GLABIOS_0.2.5_8PC.ROM
                         "2ad31da203a49b504fad3a34af0c719f",  // Basic v1.0
@@ -241,6 +307,7 @@ impl RomManager {
                     priority: 10,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "3a0eacac07f1020b95ce06043982dfd1" // Supersoft Diagnostic ROM
                     ]
@@ -250,6 +317,7 @@ impl RomManager {
                     priority: 3,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "1a2ac1ae0fe0f7783197e78da8b3126c", // 5160 BIOS u18 v11/08/82
                         "e816a89768a1bf4b8d52b454d5c9d1e1", // 5160 BIOS u19 v11/08/82
@@ -265,6 +333,7 @@ impl RomManager {
                     priority: 4,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "fd9ff9cbe0a8f154746ccb0a33f6d3e7", // 5160 BIOS u18 v01/10/86
                         "f051b4bbc3b60c3a14df94a0e4ee720f", // 5160 BIOS u19 v01/10/86
@@ -280,6 +349,7 @@ impl RomManager {
                     priority: 5,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "9696472098999c02217bf922786c1f4a", // 5160 BIOS u18 v05/09/86
                         "df9f29de490d7f269a6405df1fed69b7", // 5160 BIOS u19 v05/09/86
@@ -295,6 +365,7 @@ impl RomManager {
                     priority: 2,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "c9090b75c0332fc3509642ea193de7a2", // GLABIOS_0.2.4
                         "66631d1a095d8d0d54cc917fbdece684", // IBM / Xebec 20 MB Fixed Disk Drive Adapter
@@ -308,6 +379,7 @@ impl RomManager {
                     priority: 2,
                     is_complete: Cell::new(false),
                     reset_vector: (0xFFFF, 0),
+                    scans_option_roms: true,
                     roms: vec![
                         "f36c2dd29344eff6f55135f8b3014b81", // GLABIOS_0.2.5_8XC.ROM
                         "66631d1a095d8d0d54cc917fbdece684", // IBM / Xebec 20 MB Fixed Disk Drive Adapter
@@ -1231,10 +1303,18 @@ impl RomManager {
             features_available: Vec::new(),
             features_requested,
             rom_override,
-            raw_roms: Vec::new()
+            raw_roms: Vec::new(),
+            option_roms: Vec::new(),
+            preferred_bios: Vec::new(),
         }
     }
 
+    /// Set the BIOS ROMs to prefer, by MD5 digest, in order of preference. Must be called
+    /// before try_load_from_dir().
+    pub fn set_preferred_bios(&mut self, bios: Vec<String>) {
+        self.preferred_bios = bios;
+    }
+
     pub fn try_load_override(&mut self) -> Result<bool, RomError> {
 
 
@@ -1330,6 +1410,22 @@ impl RomManager {
             return Err(RomError::RomNotFoundForMachine);
         }
 
+        // Move sets containing a preferred BIOS to the front, keeping priority order otherwise
+        if !self.preferred_bios.is_empty() {
+            let preference = |set: &RomSet| {
+                self.preferred_bios
+                    .iter()
+                    .position(|bios| set.roms.contains(&bios.as_str()))
+                    .unwrap_or(usize::MAX)
+            };
+            let mut sets = std::mem::take(&mut self.rom_sets_complete);
+            sets.sort_by_key(|set| preference(set));
+            if preference(&sets[0]) == usize::MAX {
+                log::warn!("None of the preferred BIOS ROMs were found. Using highest priority ROM set.");
+            }
+            self.rom_sets_complete = sets;
+        }
+
         // Select the active rom set from the highest priority complete set
         let mut rom_set_active = self.rom_sets_complete[0].clone();
 
@@ -1476,6 +1572,8 @@ impl RomManager {
     /// Only copy Feature ROMs if they match the list of requested features.
     pub fn copy_into_memory(&self, bus: &mut BusInterface) -> bool {
 
+        self.copy_option_roms(bus);
+
         if self.raw_roms.len() > 0 {
             // Some raw roms were loaded, copy them into memory.
 
@@ -1599,6 +1697,48 @@ impl RomManager {
         Ok(())
     }
 
+    /// Add an expansion card option ROM, such as a disk controller BIOS, to be mapped at the 
+    /// specified address. Option ROMs are not identified by hash like system ROMs, so any image
+    /// may be used. 
+    pub fn add_option_rom(&mut self, rom: Vec<u8>, address: u32) {
+
+        if rom.len() < 3 || rom[0] != 0x55 || rom[1] != 0xAA {
+            log::warn!("Option ROM at {:05X} does not have a valid signature and may not be run by the BIOS.", address);
+        }
+        else {
+            // The third byte is the ROM length in 512 byte blocks, and all bytes within it 
+            // should sum to 0.
+            if !option_rom_checksum_ok(&rom) {
+                log::warn!("Option ROM at {:05X} has a bad checksum and may not be run by the BIOS.", address);
+            }
+        }
+        self.option_roms.push((rom, address));
+    }
+
+    /// Add a PCjr cartridge ROM to be mapped at the specified address. Cartridges use a CRC
+    /// rather than the option ROM checksum, so only the signature is checked.
+    pub fn add_cartridge(&mut self, rom: Vec<u8>, address: u32) {
+
+        if rom.len() < 3 || rom[0] != 0x55 || rom[1] != 0xAA {
+            log::warn!("Cartridge at {:05X} does not have a valid signature and may not be run by the BIOS.", address);
+        }
+        self.option_roms.push((rom, address));
+    }
+
+    fn copy_option_roms(&self, bus: &mut BusInterface) {
+
+        for (rom, address) in &self.option_roms {
+            match bus.copy_from(rom, *address as usize, BIOS_READ_CYCLE_COST, true) {
+                Ok(_) => {
+                    log::debug!("Mounted option rom at location {:06X}", address);
+                }
+                Err(e) => {
+                    log::error!("Failed to mount option rom at location {:06X}: {}", address, e);
+                }
+            }
+        }
+    }
+
     pub fn add_raw_rom(&mut self, rom: &[u8], rom_desc: RawRomDescriptor) {
 
         self.raw_roms.push((rom.to_vec(), rom_desc));
@@ -1743,6 +1883,15 @@ impl RomManager {
         }
     }
 
+    /// Return whether the BIOS of the active ROM set scans for option ROMs at boot. The earliest
+    /// 5150 BIOSes predate option ROMs and boot without running them.
+    pub fn bios_scans_option_roms(&self) -> bool {
+        match &self.rom_set_active {
+            Some(rom_set) if self.rom_override.is_none() && self.raw_roms.is_empty() => rom_set.scans_option_roms,
+            _ => true
+        }
+    }
+
     pub fn get_checkpoint(&self, addr: u32) -> Option<&&str> {
         self.checkpoints_active.get(&addr)
     }
@@ -1751,4 +1900,54 @@ impl RomManager {
         &self.features_available
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_rom(blocks: u8, fill: &[(usize, u8)]) -> Vec<u8> {
+        let mut rom = vec![0u8; blocks as usize * 512];
+        for (offset, byte) in fill {
+            rom[*offset] = *byte;
+        }
+        rom[0] = 0x55;
+        rom[1] = 0xAA;
+        rom[2] = blocks;
+        rom[3] = 0xCB; // RETF
+        let sum = rom.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        rom[5] = 0u8.wrapping_sub(sum);
+        rom
+    }
+
+    #[test]
+    fn test_scan_option_roms() {
+        let mut region = vec![0xFFu8; OPTION_ROM_SCAN_END - OPTION_ROM_SCAN_START];
+
+        // A 12K ROM at C800 hides a signature inside it at CA00.
+        let rom = make_rom(24, &[(0x2000, 0x55), (0x2001, 0xAA), (0x2002, 0x04)]);
+        region[0x8000..0x8000 + rom.len()].copy_from_slice(&rom);
+
+        // A ROM with a bad checksum at D000.
+        let mut bad_rom = make_rom(4, &[]);
+        bad_rom[6] = 1;
+        region[0x10000..0x10000 + bad_rom.len()].copy_from_slice(&bad_rom);
+
+        let roms = scan_option_roms(&region, OPTION_ROM_SCAN_START);
+        assert_eq!(roms, vec![
+            OptionRomInfo { segment: 0xC800, size: 12288, checksum_ok: true },
+            OptionRomInfo { segment: 0xD000, size: 2048, checksum_ok: false },
+        ]);
+    }
+
+    #[test]
+    fn test_scan_option_roms_unaligned_base() {
+        let mut region = vec![0u8; 0x1000];
+        let rom = make_rom(1, &[]);
+        region[0x100..0x100 + rom.len()].copy_from_slice(&rom);
+        region[0x500..0x500 + rom.len()].copy_from_slice(&rom);
+
+        // Only the ROM on a 2K boundary is found.
+        let roms = scan_option_roms(&region, 0xC7F00);
+        assert_eq!(roms, vec![OptionRomInfo { segment: 0xC800, size: 512, checksum_ok: true }]);
+    }
+}
@@ -0,0 +1,545 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    --------------------------------------------------------------------------
+
+    savestate.rs
+
+    Implements the save state file format.
+
+    A state file consists of a header followed by a list of sections, one per
+    emulated component. Each section is tagged with the component's id and
+    the version of the component's state format. 
+
+    When the state format of a component changes, its version must be 
+    incremented and a migration from the previous version added to its 
+    list of migrations. This allows state files written by older versions 
+    of the emulator to be upgraded section by section when loaded. If a 
+    section cannot be migrated, loading fails with an error naming the 
+    component.
+
+    Components store programmer-visible state where possible rather than
+    mirroring their internal representation, to keep state formats stable
+    across refactors.
+*/
+
+use std::{
+    error::Error,
+    fmt::Display
+};
+
+pub const STATE_FILE_MAGIC: &[u8; 8] = b"MARTYSTA";
+pub const STATE_FILE_VERSION: u16 = 1;
+pub const STATE_FILE_EXTENSION: &str = "mst";
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedFileVersion(u16),
+    MachineMismatch(String, String),
+    Truncated(String),
+    InvalidValue(String, String),
+    NewerVersion(String, u16, u16),
+    NoMigration(String, u16),
+    MigrationFailed(String, u16, String),
+    FileError(String),
+}
+impl Error for SaveStateError {}
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "Not a MartyPC state file."),
+            SaveStateError::UnsupportedFileVersion(v) => {
+                write!(f, "Unsupported state file version: {}. This state file was saved by a newer version of MartyPC.", v)
+            }
+            SaveStateError::MachineMismatch(saved, current) => {
+                write!(f, "State file was saved from a {} machine, but the current machine is a {}.", saved, current)
+            }
+            SaveStateError::Truncated(id) => write!(f, "State for component '{}' is truncated.", id),
+            SaveStateError::InvalidValue(id, msg) => write!(f, "Invalid state for component '{}': {}", id, msg),
+            SaveStateError::NewerVersion(id, found, supported) => {
+                write!(
+                    f, 
+                    "State for component '{}' is version {}, but this version of MartyPC only supports up to version {}.", 
+                    id, found, supported
+                )
+            }
+            SaveStateError::NoMigration(id, from) => {
+                write!(f, "State for component '{}' is version {}, which can no longer be loaded.", id, from)
+            }
+            SaveStateError::MigrationFailed(id, from, msg) => {
+                write!(f, "Failed to upgrade state for component '{}' from version {}: {}", id, from, msg)
+            }
+            SaveStateError::FileError(msg) => write!(f, "Error accessing state file: {}", msg),
+        }
+    }
+}
+
+/// A function that upgrades a section's data from one version to the next.
+pub type MigrationFn = fn(&[u8]) -> Result<Vec<u8>, String>;
+
+/// A migration from version 'from_version' of a component's state to version 'from_version + 1'.
+pub struct StateMigration {
+    pub from_version: u16,
+    pub migrate: MigrationFn
+}
+
+/// Implemented by components that can save and restore their state.
+pub trait SaveState {
+    /// Unique identifier for the component's section in a state. This must never change.
+    const STATE_ID: &'static str;
+    /// The current version of the component's state format.
+    const STATE_VERSION: u16;
+
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError>;
+
+    /// Migrations from previous versions of the component's state format. There should be one 
+    /// migration for each version prior to STATE_VERSION that can still be loaded.
+    fn state_migrations() -> &'static [StateMigration] {
+        &[]
+    }
+}
+
+/// Serializes a component's state into a section's data.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new()
+        }
+    }
+
+    pub fn write_u8(&mut self, b: u8) {
+        self.data.push(b);
+    }
+
+    pub fn write_bool(&mut self, b: bool) {
+        self.data.push(b as u8);
+    }
+
+    pub fn write_u16(&mut self, w: u16) {
+        self.data.extend_from_slice(&w.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, dw: u32) {
+        self.data.extend_from_slice(&dw.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, qw: u64) {
+        self.data.extend_from_slice(&qw.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, f: f64) {
+        self.data.extend_from_slice(&f.to_le_bytes());
+    }
+
+    /// Write a length-prefixed byte slice.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Deserializes a component's state from a section's data.
+pub struct StateReader<'a> {
+    id: &'a str,
+    data: &'a [u8],
+    cursor: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(id: &'a str, data: &'a [u8]) -> Self {
+        Self {
+            id,
+            data,
+            cursor: 0
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.cursor + len > self.data.len() {
+            return Err(SaveStateError::Truncated(self.id.to_string()))
+        }
+        let slice = &self.data[self.cursor..self.cursor + len];
+        self.cursor += len;
+        Ok(slice)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, SaveStateError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Read a length-prefixed byte slice.
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, SaveStateError> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.invalid("invalid string"))
+    }
+
+    /// Read a length-prefixed byte slice into a buffer, which must be of the same length.
+    pub fn read_into(&mut self, dest: &mut [u8]) -> Result<(), SaveStateError> {
+        let bytes = self.read_bytes()?;
+        if bytes.len() != dest.len() {
+            return Err(self.invalid(&format!("expected {} bytes, found {}", dest.len(), bytes.len())))
+        }
+        dest.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Return an InvalidValue error for the component being read.
+    pub fn invalid(&self, msg: &str) -> SaveStateError {
+        SaveStateError::InvalidValue(self.id.to_string(), msg.to_string())
+    }
+}
+
+struct StateSection {
+    id: String,
+    version: u16,
+    data: Vec<u8>
+}
+
+/// The saved state of a machine.
+pub struct StateFile {
+    pub emulator_version: String,
+    pub machine: String,
+    sections: Vec<StateSection>
+}
+
+impl StateFile {
+    pub fn new(machine: &str) -> Self {
+        Self {
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            machine: machine.to_string(),
+            sections: Vec::new()
+        }
+    }
+
+    /// Add a section containing the state of the specified component.
+    pub fn save<T: SaveState>(&mut self, component: &T) {
+        self.save_named(T::STATE_ID, component);
+    }
+
+    /// Add a section containing the state of the specified component under a specific id. Used
+    /// for components that may have more than one instance, such as a secondary PIC.
+    pub fn save_named<T: SaveState>(&mut self, id: &str, component: &T) {
+        let mut w = StateWriter::new();
+        component.save_state(&mut w);
+        self.push_section(id, T::STATE_VERSION, w.into_vec());
+    }
+
+    pub fn has_section(&self, id: &str) -> bool {
+        self.sections.iter().any(|s| s.id == id)
+    }
+
+    /// Restore the state of the specified component from its section, upgrading the section 
+    /// first if it was written by an older version. Returns false if there is no section for
+    /// the component.
+    pub fn load<T: SaveState>(&self, component: &mut T) -> Result<bool, SaveStateError> {
+        self.load_named(T::STATE_ID, component)
+    }
+
+    /// Restore the state of the specified component from the section with the specified id.
+    pub fn load_named<T: SaveState>(&self, id: &str, component: &mut T) -> Result<bool, SaveStateError> {
+        let section = match self.sections.iter().find(|s| s.id == id) {
+            Some(section) => section,
+            None => return Ok(false)
+        };
+
+        let data = StateFile::migrate::<T>(section)?;
+        let mut r = StateReader::new(id, &data);
+        component.load_state(&mut r)?;
+        Ok(true)
+    }
+
+    fn migrate<T: SaveState>(section: &StateSection) -> Result<Vec<u8>, SaveStateError> {
+        if section.version > T::STATE_VERSION {
+            return Err(SaveStateError::NewerVersion(section.id.clone(), section.version, T::STATE_VERSION))
+        }
+
+        let mut data = section.data.clone();
+        let mut version = section.version;
+        while version < T::STATE_VERSION {
+            let migration = T::state_migrations()
+                .iter()
+                .find(|m| m.from_version == version)
+                .ok_or_else(|| SaveStateError::NoMigration(section.id.clone(), version))?;
+
+            data = (migration.migrate)(&data)
+                .map_err(|e| SaveStateError::MigrationFailed(section.id.clone(), version, e))?;
+            log::debug!("Upgraded state for '{}' from version {} to {}", section.id, version, version + 1);
+            version += 1;
+        }
+        Ok(data)
+    }
+
+    /// Return the raw sections as (id, version, data) tuples.
+    pub(crate) fn sections(&self) -> impl Iterator<Item = (&str, u16, &[u8])> {
+        self.sections.iter().map(|s| (s.id.as_str(), s.version, s.data.as_slice()))
+    }
+
+    /// Add a section containing data previously returned by sections().
+    pub(crate) fn push_section(&mut self, id: &str, version: u16, data: Vec<u8>) {
+        self.sections.retain(|s| s.id != id);
+        self.sections.push(StateSection {
+            id: id.to_string(),
+            version,
+            data
+        });
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.data.extend_from_slice(STATE_FILE_MAGIC);
+        w.write_u16(STATE_FILE_VERSION);
+        w.write_str(&self.emulator_version);
+        w.write_str(&self.machine);
+        w.write_u32(self.sections.len() as u32);
+        for section in &self.sections {
+            w.write_str(&section.id);
+            w.write_u16(section.version);
+            w.write_bytes(&section.data);
+        }
+        w.into_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        if bytes.len() < STATE_FILE_MAGIC.len() || &bytes[0..STATE_FILE_MAGIC.len()] != STATE_FILE_MAGIC {
+            return Err(SaveStateError::BadMagic)
+        }
+
+        let mut r = StateReader::new("header", &bytes[STATE_FILE_MAGIC.len()..]);
+        let file_version = r.read_u16()?;
+        if file_version > STATE_FILE_VERSION {
+            return Err(SaveStateError::UnsupportedFileVersion(file_version))
+        }
+
+        let emulator_version = r.read_string()?;
+        let machine = r.read_string()?;
+        let section_ct = r.read_u32()?;
+        let mut sections = Vec::new();
+        for _ in 0..section_ct {
+            let id = r.read_string()?;
+            let version = r.read_u16()?;
+            let data = r.read_bytes()?.to_vec();
+            sections.push(StateSection {
+                id,
+                version,
+                data
+            });
+        }
+
+        Ok(Self {
+            emulator_version,
+            machine,
+            sections
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDevice {
+        a: u16,
+        b: bool
+    }
+
+    impl SaveState for TestDevice {
+        const STATE_ID: &'static str = "test";
+        const STATE_VERSION: u16 = 1;
+
+        fn save_state(&self, w: &mut StateWriter) {
+            w.write_u16(self.a);
+            w.write_bool(self.b);
+        }
+
+        fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+            self.a = r.read_u16()?;
+            self.b = r.read_bool()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = StateFile::new("test machine");
+        state.save(&TestDevice { a: 0x1234, b: true });
+        state.save_named("test2", &TestDevice { a: 0x5678, b: false });
+
+        let mut dev = TestDevice { a: 0, b: false };
+        assert!(state.load(&mut dev).unwrap());
+        assert_eq!(dev.a, 0x1234);
+        assert!(dev.b);
+
+        assert!(state.load_named("test2", &mut dev).unwrap());
+        assert_eq!(dev.a, 0x5678);
+        assert!(!state.load_named("test3", &mut dev).unwrap());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut state = StateFile::new("test machine");
+        state.push_section("test", 1, vec![0x34, 0x12]);
+
+        let mut dev = TestDevice { a: 0, b: false };
+        assert!(matches!(state.load(&mut dev), Err(SaveStateError::Truncated(_))));
+    }
+
+    struct TestDeviceV1 {
+        a: u8
+    }
+
+    impl SaveState for TestDeviceV1 {
+        const STATE_ID: &'static str = "versioned";
+        const STATE_VERSION: u16 = 1;
+
+        fn save_state(&self, w: &mut StateWriter) {
+            w.write_u8(self.a);
+        }
+
+        fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+            self.a = r.read_u8()?;
+            Ok(())
+        }
+    }
+
+    // Version 2 widened 'a' to 16 bits and added 'b'.
+    struct TestDeviceV2 {
+        a: u16,
+        b: bool
+    }
+
+    fn migrate_test_v1(data: &[u8]) -> Result<Vec<u8>, String> {
+        let a = *data.first().ok_or("missing field a")?;
+        let mut w = StateWriter::new();
+        w.write_u16(a as u16);
+        w.write_bool(false);
+        Ok(w.into_vec())
+    }
+
+    impl SaveState for TestDeviceV2 {
+        const STATE_ID: &'static str = "versioned";
+        const STATE_VERSION: u16 = 2;
+
+        fn save_state(&self, w: &mut StateWriter) {
+            w.write_u16(self.a);
+            w.write_bool(self.b);
+        }
+
+        fn load_state(&mut self, r: &mut StateReader) -> Result<(), SaveStateError> {
+            self.a = r.read_u16()?;
+            self.b = r.read_bool()?;
+            Ok(())
+        }
+
+        fn state_migrations() -> &'static [StateMigration] {
+            &[StateMigration { from_version: 1, migrate: migrate_test_v1 }]
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let mut file = StateFile::new("test machine");
+        file.save(&TestDeviceV2 { a: 0x1234, b: true });
+
+        let file = StateFile::from_bytes(&file.to_bytes()).unwrap();
+        assert_eq!(file.machine, "test machine");
+
+        let mut dev = TestDeviceV2 { a: 0, b: false };
+        assert!(file.load(&mut dev).unwrap());
+        assert_eq!(dev.a, 0x1234);
+        assert!(dev.b);
+    }
+
+    #[test]
+    fn test_migration() {
+        let mut file = StateFile::new("test machine");
+        file.save(&TestDeviceV1 { a: 0x42 });
+
+        let mut dev = TestDeviceV2 { a: 0, b: true };
+        assert!(file.load(&mut dev).unwrap());
+        assert_eq!(dev.a, 0x42);
+        assert!(!dev.b);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut file = StateFile::new("test machine");
+        file.save(&TestDeviceV2 { a: 1, b: false });
+
+        let mut dev = TestDeviceV1 { a: 0 };
+        match file.load(&mut dev) {
+            Err(SaveStateError::NewerVersion(id, 2, 1)) => assert_eq!(id, "versioned"),
+            _ => panic!("expected NewerVersion error")
+        }
+    }
+
+    #[test]
+    fn test_bad_file() {
+        assert!(matches!(StateFile::from_bytes(b"NOTSTATE"), Err(SaveStateError::BadMagic)));
+
+        let mut bytes = StateFile::new("test machine").to_bytes();
+        bytes.truncate(bytes.len() - 2);
+        assert!(matches!(StateFile::from_bytes(&bytes), Err(SaveStateError::Truncated(_))));
+    }
+}
@@ -0,0 +1,527 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    scripting.rs
+
+    Implements a scripting interface based on the Rhai scripting language,
+    for automating the emulator and writing regression tests for software
+    compatibility.
+
+    Scripts run on their own thread, so that they can wait for the machine
+    to run without blocking the emulator. Functions that access the machine
+    send a request to the ScriptHost and block until the host services it 
+    from the emulator's main loop, which it does once per frame.
+
+    Functions available to scripts:
+
+    peek(addr), peekw(addr)   read a byte or word of memory
+    poke(addr, byte)          write a byte to memory
+    reg(name)                 read a register, such as "ax", "cs", "ip" or "flags"
+    addr(expr)                evaluate an address expression such as "cs:ip"
+    cycles()                  return the number of CPU cycles executed
+    key_down(code)            press a keyboard scancode
+    key_up(code)              release a keyboard scancode
+    tap(code)                 press and release a keyboard scancode
+    breakpoint(addr)          set an execution breakpoint at a flat address
+    clear_breakpoints()       clear all breakpoints set by the script
+    wait_break(frames)        wait up to the given number of frames for a 
+                              breakpoint to be hit. Returns true if one was.
+    resume()                  resume execution after a breakpoint
+    pause()                   pause execution
+    wait(frames)              wait for the given number of frames
+    wait_text(text, frames)   wait up to the given number of frames for text
+                              to appear on screen. Returns true if it did.
+    screen_text()             return the text on screen
+    screenshot()              save a screenshot
+    reset()                   reboot the machine
+    assert(cond, msg)         stop the script with an error if cond is false
+    exit(code)                exit the emulator with the given status code
+
+    print() and debug() output is sent to the script console.
+*/
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender}
+    },
+    thread,
+    time::{Duration, Instant}
+};
+
+use rhai::{Engine, EvalAltResult};
+
+use crate::breakpoints::{self, BreakPointType, ConditionContext};
+use crate::machine::{ExecutionControl, ExecutionOperation, ExecutionState, Machine, MachineState};
+
+/// The maximum time per frame the host will spend servicing script requests.
+const SCRIPT_TIME_SLICE: Duration = Duration::from_millis(2);
+
+/// The number of frames a key is held for by tap().
+const TAP_FRAMES: i64 = 2;
+
+/// Actions requested by a script that must be performed by the frontend.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScriptAction {
+    Screenshot,
+    Exit(i32),
+}
+
+#[derive(Clone, Debug)]
+enum ScriptCall {
+    Peek(u32),
+    PeekWord(u32),
+    Poke(u32, u8),
+    Register(String),
+    Address(String),
+    Cycles,
+    KeyDown(u8),
+    KeyUp(u8),
+    SetBreakpoint(u32),
+    ClearBreakpoints,
+    WaitBreak(u32),
+    Resume,
+    Pause,
+    Wait(u32),
+    WaitText(String, u32),
+    ScreenText,
+    Screenshot,
+    Reset,
+    Exit(i32),
+}
+
+#[derive(Clone, Debug)]
+enum ScriptReply {
+    Unit,
+    Int(i64),
+    Bool(bool),
+    Text(String),
+    Error(String),
+}
+
+enum ScriptMessage {
+    Call(ScriptCall, Sender<ScriptReply>),
+    Print(String),
+    Done(Result<(), String>),
+}
+
+/// A request that cannot complete until the machine has run for a while.
+enum Wait {
+    Frames(u32),
+    Break(u32),
+    Text(String, u32),
+}
+
+enum Outcome {
+    Reply(ScriptReply),
+    Wait(Wait),
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn call(tx: &Sender<ScriptMessage>, call: ScriptCall) -> ScriptResult<ScriptReply> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    tx.send(ScriptMessage::Call(call, reply_tx)).map_err(|_| "script stopped")?;
+    match reply_rx.recv() {
+        Ok(ScriptReply::Error(e)) => Err(e.into()),
+        Ok(reply) => Ok(reply),
+        Err(_) => Err("script stopped".into())
+    }
+}
+
+fn call_unit(tx: &Sender<ScriptMessage>, c: ScriptCall) -> ScriptResult<()> {
+    call(tx, c).map(|_| ())
+}
+
+fn call_int(tx: &Sender<ScriptMessage>, c: ScriptCall) -> ScriptResult<i64> {
+    match call(tx, c)? {
+        ScriptReply::Int(i) => Ok(i),
+        reply => Err(format!("unexpected reply: {:?}", reply).into())
+    }
+}
+
+fn call_bool(tx: &Sender<ScriptMessage>, c: ScriptCall) -> ScriptResult<bool> {
+    match call(tx, c)? {
+        ScriptReply::Bool(b) => Ok(b),
+        reply => Err(format!("unexpected reply: {:?}", reply).into())
+    }
+}
+
+fn call_text(tx: &Sender<ScriptMessage>, c: ScriptCall) -> ScriptResult<String> {
+    match call(tx, c)? {
+        ScriptReply::Text(s) => Ok(s),
+        reply => Err(format!("unexpected reply: {:?}", reply).into())
+    }
+}
+
+fn build_engine(tx: &Sender<ScriptMessage>, cancel: Arc<AtomicBool>) -> Engine {
+    let mut engine = Engine::new();
+
+    let print_tx = tx.clone();
+    engine.on_print(move |s| {
+        _ = print_tx.send(ScriptMessage::Print(s.to_string()));
+    });
+    let debug_tx = tx.clone();
+    engine.on_debug(move |s, _src, pos| {
+        _ = debug_tx.send(ScriptMessage::Print(format!("{:?}: {}", pos, s)));
+    });
+    // Allow a script stuck in a loop to be stopped.
+    engine.on_progress(move |_ops| {
+        if cancel.load(Ordering::Relaxed) {
+            Some("script stopped".into())
+        }
+        else {
+            None
+        }
+    });
+
+    let t = tx.clone();
+    engine.register_fn("peek", move |addr: i64| call_int(&t, ScriptCall::Peek(addr as u32)));
+    let t = tx.clone();
+    engine.register_fn("peekw", move |addr: i64| call_int(&t, ScriptCall::PeekWord(addr as u32)));
+    let t = tx.clone();
+    engine.register_fn("poke", move |addr: i64, data: i64| call_unit(&t, ScriptCall::Poke(addr as u32, data as u8)));
+    let t = tx.clone();
+    engine.register_fn("reg", move |name: &str| call_int(&t, ScriptCall::Register(name.to_lowercase())));
+    let t = tx.clone();
+    engine.register_fn("addr", move |expr: &str| call_int(&t, ScriptCall::Address(expr.to_string())));
+    let t = tx.clone();
+    engine.register_fn("cycles", move || call_int(&t, ScriptCall::Cycles));
+    let t = tx.clone();
+    engine.register_fn("key_down", move |code: i64| call_unit(&t, ScriptCall::KeyDown(code as u8)));
+    let t = tx.clone();
+    engine.register_fn("key_up", move |code: i64| call_unit(&t, ScriptCall::KeyUp(code as u8)));
+    let t = tx.clone();
+    engine.register_fn("tap", move |code: i64| {
+        call_unit(&t, ScriptCall::KeyDown(code as u8))?;
+        call_unit(&t, ScriptCall::Wait(TAP_FRAMES as u32))?;
+        call_unit(&t, ScriptCall::KeyUp(code as u8))
+    });
+    let t = tx.clone();
+    engine.register_fn("breakpoint", move |addr: i64| call_unit(&t, ScriptCall::SetBreakpoint(addr as u32)));
+    let t = tx.clone();
+    engine.register_fn("clear_breakpoints", move || call_unit(&t, ScriptCall::ClearBreakpoints));
+    let t = tx.clone();
+    engine.register_fn("wait_break", move |frames: i64| call_bool(&t, ScriptCall::WaitBreak(frames.max(1) as u32)));
+    let t = tx.clone();
+    engine.register_fn("resume", move || call_unit(&t, ScriptCall::Resume));
+    let t = tx.clone();
+    engine.register_fn("pause", move || call_unit(&t, ScriptCall::Pause));
+    let t = tx.clone();
+    engine.register_fn("wait", move |frames: i64| call_unit(&t, ScriptCall::Wait(frames.max(1) as u32)));
+    let t = tx.clone();
+    engine.register_fn("wait_text", move |text: &str, frames: i64| {
+        call_bool(&t, ScriptCall::WaitText(text.to_string(), frames.max(1) as u32))
+    });
+    let t = tx.clone();
+    engine.register_fn("screen_text", move || call_text(&t, ScriptCall::ScreenText));
+    let t = tx.clone();
+    engine.register_fn("screenshot", move || call_unit(&t, ScriptCall::Screenshot));
+    let t = tx.clone();
+    engine.register_fn("reset", move || call_unit(&t, ScriptCall::Reset));
+    let t = tx.clone();
+    engine.register_fn("exit", move |code: i64| call_unit(&t, ScriptCall::Exit(code as i32)));
+    engine.register_fn("assert", |cond: bool, msg: &str| -> ScriptResult<()> {
+        if cond {
+            Ok(())
+        }
+        else {
+            Err(format!("assertion failed: {}", msg).into())
+        }
+    });
+
+    engine
+}
+
+pub struct ScriptHost {
+    rx: Receiver<ScriptMessage>,
+    cancel: Arc<AtomicBool>,
+    wait: Option<(Wait, Sender<ScriptReply>)>,
+    breakpoints: Vec<BreakPointType>,
+    output: Vec<String>,
+    actions: Vec<ScriptAction>,
+    result: Option<Result<(), String>>,
+}
+
+impl ScriptHost {
+    /// Start running the specified script source on a new thread.
+    pub fn start(source: String) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = cancel.clone();
+
+        thread::spawn(move || {
+            let engine = build_engine(&tx, thread_cancel);
+            let result = engine.run(&source).map_err(|e| e.to_string());
+            _ = tx.send(ScriptMessage::Done(result));
+        });
+
+        Self {
+            rx,
+            cancel,
+            wait: None,
+            breakpoints: Vec::new(),
+            output: Vec::new(),
+            actions: Vec::new(),
+            result: None,
+        }
+    }
+
+    /// Stop the script. The script thread exits at its next call into the host.
+    pub fn stop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        // Dropping a pending reply sender wakes the script with an error.
+        self.wait = None;
+    }
+
+    /// Return the result of the script, or None if it is still running.
+    pub fn result(&self) -> Option<&Result<(), String>> {
+        self.result.as_ref()
+    }
+
+    /// Take the output the script has printed since the last call.
+    pub fn take_output(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Take the actions the script has requested of the frontend since the last call.
+    pub fn take_actions(&mut self) -> Vec<ScriptAction> {
+        std::mem::take(&mut self.actions)
+    }
+
+    /// Service requests from the script. Should be called once per frame.
+    pub fn poll(&mut self, machine: &mut Machine, exec_control: &mut ExecutionControl) {
+
+        if self.result.is_some() {
+            return
+        }
+
+        // Requests are not processed while a wait is in progress.
+        if let Some((wait, reply)) = self.wait.take() {
+            match Self::check_wait(wait, machine, exec_control) {
+                Outcome::Reply(value) => {
+                    _ = reply.send(value);
+                }
+                Outcome::Wait(wait) => {
+                    self.wait = Some((wait, reply));
+                    return
+                }
+            }
+        }
+
+        let deadline = Instant::now() + SCRIPT_TIME_SLICE;
+        loop {
+            let msg = match self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.result = Some(Err("script thread exited".to_string()));
+                    break
+                }
+            };
+
+            match msg {
+                ScriptMessage::Print(s) => self.output.push(s),
+                ScriptMessage::Done(result) => {
+                    match &result {
+                        Ok(_) => log::info!("Script finished."),
+                        Err(e) => {
+                            log::error!("Script error: {}", e);
+                            self.output.push(format!("Error: {}", e));
+                        }
+                    }
+                    self.result = Some(result);
+                    break
+                }
+                ScriptMessage::Call(c, reply) => {
+                    match self.execute(c, machine, exec_control) {
+                        Outcome::Reply(value) => {
+                            _ = reply.send(value);
+                        }
+                        Outcome::Wait(wait) => {
+                            self.wait = Some((wait, reply));
+                            break
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_wait(wait: Wait, machine: &mut Machine, exec_control: &ExecutionControl) -> Outcome {
+        match wait {
+            Wait::Frames(n) if n <= 1 => Outcome::Reply(ScriptReply::Unit),
+            Wait::Frames(n) => Outcome::Wait(Wait::Frames(n - 1)),
+            Wait::Break(_) if matches!(exec_control.get_state(), ExecutionState::BreakpointHit) => {
+                Outcome::Reply(ScriptReply::Bool(true))
+            }
+            Wait::Break(n) if n <= 1 => Outcome::Reply(ScriptReply::Bool(false)),
+            Wait::Break(n) => Outcome::Wait(Wait::Break(n - 1)),
+            Wait::Text(text, n) => {
+                if machine.screen_text().is_some_and(|screen| screen.contains(&text)) {
+                    Outcome::Reply(ScriptReply::Bool(true))
+                }
+                else if n <= 1 {
+                    Outcome::Reply(ScriptReply::Bool(false))
+                }
+                else {
+                    Outcome::Wait(Wait::Text(text, n - 1))
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, c: ScriptCall, machine: &mut Machine, exec_control: &mut ExecutionControl) -> Outcome {
+        let reply = match c {
+            ScriptCall::Peek(addr) => ScriptReply::Int(machine.cpu().peek_u8(addr) as i64),
+            ScriptCall::PeekWord(addr) => {
+                let lo = machine.cpu().peek_u8(addr) as i64;
+                let hi = machine.cpu().peek_u8(addr.wrapping_add(1)) as i64;
+                ScriptReply::Int(hi << 8 | lo)
+            }
+            ScriptCall::Poke(addr, data) => {
+                match machine.bus_mut().write_u8(addr as usize, data, 0) {
+                    Ok(_) => ScriptReply::Unit,
+                    Err(e) => ScriptReply::Error(format!("poke failed at {:05X}: {}", addr, e))
+                }
+            }
+            ScriptCall::Register(name) => {
+                let cpu = machine.cpu();
+                if name == "flags" {
+                    ScriptReply::Int(cpu.get_state().flags as i64)
+                }
+                else if let Some(reg) = breakpoints::parse_register16(&name) {
+                    ScriptReply::Int(cpu.register16(reg) as i64)
+                }
+                else if let Some(reg) = breakpoints::parse_register8(&name) {
+                    ScriptReply::Int(cpu.register8(reg) as i64)
+                }
+                else {
+                    ScriptReply::Error(format!("unknown register: {}", name))
+                }
+            }
+            ScriptCall::Address(expr) => {
+                match machine.cpu().eval_address(&expr) {
+                    Some(addr) => ScriptReply::Int(u32::from(addr) as i64),
+                    None => ScriptReply::Error(format!("invalid address expression: {}", expr))
+                }
+            }
+            ScriptCall::Cycles => ScriptReply::Int(machine.cpu_cycles() as i64),
+            ScriptCall::KeyDown(code) => {
+                machine.key_press(code);
+                ScriptReply::Unit
+            }
+            ScriptCall::KeyUp(code) => {
+                machine.key_release(code);
+                ScriptReply::Unit
+            }
+            ScriptCall::SetBreakpoint(addr) => {
+                self.breakpoints.push(BreakPointType::ExecuteFlat(addr));
+                machine.set_breakpoints(self.breakpoints.clone());
+                ScriptReply::Unit
+            }
+            ScriptCall::ClearBreakpoints => {
+                self.breakpoints.clear();
+                machine.set_breakpoints(Vec::new());
+                ScriptReply::Unit
+            }
+            ScriptCall::WaitBreak(frames) => return Outcome::Wait(Wait::Break(frames)),
+            ScriptCall::Resume => {
+                exec_control.set_op(ExecutionOperation::Run);
+                ScriptReply::Unit
+            }
+            ScriptCall::Pause => {
+                exec_control.set_op(ExecutionOperation::Pause);
+                ScriptReply::Unit
+            }
+            ScriptCall::Wait(frames) => return Outcome::Wait(Wait::Frames(frames)),
+            ScriptCall::WaitText(text, frames) => return Outcome::Wait(Wait::Text(text, frames)),
+            ScriptCall::ScreenText => {
+                match machine.screen_text() {
+                    Some(text) => ScriptReply::Text(text),
+                    None => ScriptReply::Error("no text screen available".to_string())
+                }
+            }
+            ScriptCall::Screenshot => {
+                self.actions.push(ScriptAction::Screenshot);
+                ScriptReply::Unit
+            }
+            ScriptCall::Reset => {
+                machine.change_state(MachineState::Rebooting);
+                ScriptReply::Unit
+            }
+            ScriptCall::Exit(code) => {
+                self.actions.push(ScriptAction::Exit(code));
+                ScriptReply::Unit
+            }
+        };
+        Outcome::Reply(reply)
+    }
+}
+
+impl Drop for ScriptHost {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_calls() {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let engine = build_engine(&tx, Arc::new(AtomicBool::new(false)));
+            engine.run("let b = peek(0x400); assert(b == 0x12, \"peek\"); print(`value ${b}`);")
+                .map_err(|e| e.to_string())
+        });
+
+        // Service the script's requests without a machine.
+        loop {
+            match rx.recv().unwrap() {
+                ScriptMessage::Call(ScriptCall::Peek(0x400), reply) => reply.send(ScriptReply::Int(0x12)).unwrap(),
+                ScriptMessage::Call(c, _) => panic!("unexpected call: {:?}", c),
+                ScriptMessage::Print(s) => {
+                    assert_eq!(s, "value 18");
+                    break
+                }
+                ScriptMessage::Done(r) => panic!("script ended early: {:?}", r),
+            }
+        }
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_script_assert_fails() {
+        let (tx, _rx) = mpsc::channel();
+        let engine = build_engine(&tx, Arc::new(AtomicBool::new(false)));
+        let err = engine.run("assert(1 == 2, \"math\");").unwrap_err();
+        assert!(err.to_string().contains("assertion failed: math"));
+    }
+}
@@ -44,6 +44,9 @@ use ringbuf::{
 
 pub const VOLUME_ADJUST: f32 = 0.10;
 
+/// Sample rate used when there is no audio device, such as when running headless.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
 #[cfg(target_arch = "wasm32")]
 pub const BUFFER_MS: f32 = 100.0;
 
@@ -164,6 +167,12 @@ impl SoundPlayer {
         self.output_stream.play().unwrap();
     }
 
+    pub fn pause(&self) {
+        if let Err(e) = self.output_stream.pause() {
+            log::warn!("Couldn't pause audio stream: {}", e);
+        }
+    }
+
     pub fn queue_sample(&mut self, data: f32) {
         match self.buffer_producer.push(data) {
             Ok(_) => {},
@@ -179,6 +188,11 @@ impl SoundPlayer {
         self.sample_rate
     }
 
+    /// Return how full the output buffer is, from 0.0 to 1.0.
+    pub fn buffer_fill(&self) -> f32 {
+        self.buffer_producer.len() as f32 / self.buffer_producer.capacity() as f32
+    }
+
 }
 
 fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
@@ -0,0 +1,247 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    speed.rs
+
+    Implements emulation speed control.
+
+    The emulation speed is a factor of the machine's real speed, from 0.1x to
+    16x. Frontends run speed * (cycles per frame at 1x) cycles each frame.
+    Changing the speed abruptly causes an audible pop as the audio resampler 
+    suddenly changes rate, and makes the PIT-driven timing of the guest jump
+    from one frame to the next, so the effective speed ramps geometrically 
+    toward the requested speed over a number of frames.
+
+    The CPU clock can also be changed at runtime. As devices are clocked in
+    system ticks, CPU cycles are converted to system ticks by the clock 
+    factor and devices stay in the correct ratio at any clock. In unlimited
+    mode the CPU runs as many cycles as the host can manage each frame, and 
+    the clock factor is recalculated each frame so that devices still see a 
+    frame's worth of system ticks.
+*/
+
+use serde_derive::Deserialize;
+
+use crate::bus::ClockFactor;
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 16.0;
+
+/// The speeds stepped through by speed up/down hotkeys.
+pub const SPEED_STEPS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 12.0, 16.0];
+
+/// The maximum ratio the effective speed may change by in one frame.
+pub const DEFAULT_RAMP_RATE: f64 = 1.1;
+
+#[derive(Debug)]
+pub struct SpeedControl {
+    target: f64,
+    current: f64,
+    ramp_rate: f64,
+}
+
+impl Default for SpeedControl {
+    fn default() -> Self {
+        Self::new(DEFAULT_RAMP_RATE)
+    }
+}
+
+impl SpeedControl {
+    pub fn new(ramp_rate: f64) -> Self {
+        Self {
+            target: 1.0,
+            current: 1.0,
+            ramp_rate: ramp_rate.max(1.0),
+        }
+    }
+
+    /// Set the requested speed. The speed is clamped to MIN_SPEED..MAX_SPEED.
+    pub fn set_target(&mut self, speed: f64) {
+        if speed.is_finite() {
+            self.target = speed.clamp(MIN_SPEED, MAX_SPEED);
+        }
+    }
+
+    /// Set the speed immediately, without ramping.
+    pub fn set_immediate(&mut self, speed: f64) {
+        self.set_target(speed);
+        self.current = self.target;
+    }
+
+    /// Return the requested speed.
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Return the effective speed for the current frame.
+    pub fn current(&self) -> f64 {
+        self.current
+    }
+
+    pub fn is_ramping(&self) -> bool {
+        self.current != self.target
+    }
+
+    /// Request the next speed step above or below the requested speed.
+    pub fn step(&mut self, up: bool) {
+        let next = if up {
+            SPEED_STEPS.iter().find(|s| **s > self.target + f64::EPSILON)
+        }
+        else {
+            SPEED_STEPS.iter().rev().find(|s| **s < self.target - f64::EPSILON)
+        };
+        if let Some(speed) = next {
+            self.target = *speed;
+        }
+    }
+
+    /// Move the effective speed toward the requested speed. Called once per frame. Returns the
+    /// new effective speed if it changed.
+    pub fn frame_update(&mut self) -> Option<f64> {
+        if !self.is_ramping() {
+            return None
+        }
+
+        self.current = if self.target > self.current {
+            (self.current * self.ramp_rate).min(self.target)
+        }
+        else {
+            (self.current / self.ramp_rate).max(self.target)
+        };
+        Some(self.current)
+    }
+}
+
+/// CPU clock presets that can be selected at runtime.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum CpuClock {
+    /// 4.77MHz, the crystal divided by 3 (IBM PC and XT)
+    #[default]
+    Mhz4_77,
+    /// 7.16MHz, the crystal divided by 2 (common turbo XT clones)
+    Mhz7_16,
+    /// 8MHz, as on turbo XT clones with a separate CPU crystal
+    Mhz8,
+    /// As fast as the host can run, with devices kept at real time
+    Unlimited,
+}
+
+impl CpuClock {
+    pub const ALL: [CpuClock; 4] = [CpuClock::Mhz4_77, CpuClock::Mhz7_16, CpuClock::Mhz8, CpuClock::Unlimited];
+
+    /// Return the clock factor for this clock given the system crystal frequency in MHz, or None
+    /// for Unlimited, whose factor is set each frame.
+    pub fn factor(&self, system_crystal: f64) -> Option<ClockFactor> {
+        match self {
+            CpuClock::Mhz4_77 => Some(ClockFactor::Divisor(3)),
+            CpuClock::Mhz7_16 => Some(ClockFactor::Divisor(2)),
+            CpuClock::Mhz8 => Some(ClockFactor::Ratio((system_crystal * 100_000.0).round() as u32, 800_000)),
+            CpuClock::Unlimited => None,
+        }
+    }
+
+    /// Return the preset with the specified clock factor, if there is one.
+    pub fn from_factor(factor: ClockFactor, system_crystal: f64) -> Option<CpuClock> {
+        CpuClock::ALL
+            .iter()
+            .find(|clock| clock.factor(system_crystal) == Some(factor))
+            .copied()
+    }
+
+    /// Return the clock factor that runs `cycles` CPU cycles in `frame_us` microseconds, for 
+    /// Unlimited.
+    pub fn unlimited_factor(cycles: u32, frame_us: f64, system_crystal: f64) -> ClockFactor {
+        let frame_ticks = (frame_us * system_crystal).round() as u32;
+        ClockFactor::Ratio(frame_ticks.max(1), cycles.max(1))
+    }
+}
+
+impl std::fmt::Display for CpuClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuClock::Mhz4_77 => write!(f, "4.77MHz"),
+            CpuClock::Mhz7_16 => write!(f, "7.16MHz"),
+            CpuClock::Mhz8 => write!(f, "8MHz"),
+            CpuClock::Unlimited => write!(f, "Unlimited"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp() {
+        let mut speed = SpeedControl::new(2.0);
+
+        speed.set_target(5.0);
+        assert_eq!(speed.frame_update(), Some(2.0));
+        assert_eq!(speed.frame_update(), Some(4.0));
+        assert_eq!(speed.frame_update(), Some(5.0));
+        assert_eq!(speed.frame_update(), None);
+
+        speed.set_target(1.0);
+        assert_eq!(speed.frame_update(), Some(2.5));
+        assert!(speed.is_ramping());
+
+        speed.set_immediate(100.0);
+        assert_eq!(speed.current(), MAX_SPEED);
+        assert!(!speed.is_ramping());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut speed = SpeedControl::default();
+
+        speed.step(true);
+        assert_eq!(speed.target(), 2.0);
+        speed.set_target(0.3);
+        speed.step(false);
+        assert_eq!(speed.target(), 0.25);
+        speed.set_target(MIN_SPEED);
+        speed.step(false);
+        assert_eq!(speed.target(), MIN_SPEED);
+    }
+
+    #[test]
+    fn test_cpu_clock() {
+        const CRYSTAL: f64 = 14.318180;
+
+        assert_eq!(CpuClock::from_factor(ClockFactor::Divisor(2), CRYSTAL), Some(CpuClock::Mhz7_16));
+        assert_eq!(CpuClock::from_factor(ClockFactor::Divisor(4), CRYSTAL), None);
+
+        // 8MHz runs 8 million cycles in 1 million microseconds' worth of system ticks
+        match CpuClock::Mhz8.factor(CRYSTAL) {
+            Some(ClockFactor::Ratio(ticks, cycles)) => {
+                assert!((CRYSTAL * cycles as f64 / ticks as f64 - 8.0).abs() < 0.0001);
+            }
+            f => panic!("unexpected factor: {:?}", f),
+        }
+
+        assert_eq!(CpuClock::unlimited_factor(100_000, 1000.0, CRYSTAL), ClockFactor::Ratio(14318, 100_000));
+    }
+}
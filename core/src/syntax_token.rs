@@ -31,13 +31,15 @@
     TokenListView can use these tokens to format output with syntax coloring.
 */
 
+use std::fmt;
+
 pub const TOKEN_MAX_AGE: u8 = 255;
 
 pub trait SyntaxTokenize {
     fn tokenize(&self) -> Vec<SyntaxToken>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum SyntaxToken {
 
     NullToken,
@@ -74,3 +76,56 @@ pub enum SyntaxToken {
 impl Default for SyntaxToken {
     fn default() -> Self { SyntaxToken::NullToken }
 }
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxToken::NullToken => Ok(()),
+            SyntaxToken::StateString(s, _, _) => write!(f, "{}", s),
+            SyntaxToken::ErrorString(s) => write!(f, "{}", s),
+            SyntaxToken::MemoryAddressSeg16(_, _, s) => write!(f, "{}", s),
+            SyntaxToken::MemoryAddressFlat(_, s) => write!(f, "{}", s),
+            SyntaxToken::MemoryByteHexValue(_, _, s, _, _) => write!(f, "{}", s),
+            SyntaxToken::MemoryByteAsciiValue(_, _, s, _) => write!(f, "{}", s),
+            SyntaxToken::ErrorText(s) => write!(f, "{}", s),
+            SyntaxToken::InstructionBytes(s) => write!(f, "{}", s),
+            SyntaxToken::Prefix(s) => write!(f, "{}", s),
+            SyntaxToken::Mnemonic(s) => write!(f, "{}", s),
+            SyntaxToken::Text(s) => write!(f, "{}", s),
+            SyntaxToken::Segment(s) => write!(f, "{}", s),
+            SyntaxToken::Colon => write!(f, ":"),
+            SyntaxToken::Comma => write!(f, ","),
+            SyntaxToken::PlusSign => write!(f, "+"),
+            SyntaxToken::OpenBracket => write!(f, "["),
+            SyntaxToken::CloseBracket => write!(f, "]"),
+            SyntaxToken::HexValue(s) => write!(f, "{}", s),
+            SyntaxToken::Register(s) => write!(f, "{}", s),
+            SyntaxToken::Displacement(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// Format lines of tokens as plain text, ie for writing to a file.
+pub fn tokens_to_string(lines: &[Vec<SyntaxToken>]) -> String {
+    let mut text = String::new();
+
+    for line in lines {
+        let mut last_token: Option<&SyntaxToken> = None;
+        for token in line {
+            // Separate tokens with a space, except around punctuation.
+            let needs_space = match (last_token, token) {
+                (None, _) => false,
+                (Some(SyntaxToken::Colon | SyntaxToken::OpenBracket | SyntaxToken::PlusSign), _) => false,
+                (_, SyntaxToken::Colon | SyntaxToken::Comma | SyntaxToken::CloseBracket | SyntaxToken::PlusSign) => false,
+                _ => true
+            };
+            if needs_space {
+                text.push(' ');
+            }
+            text.push_str(&token.to_string());
+            last_token = Some(token);
+        }
+        text.push('\n');
+    }
+    text
+}
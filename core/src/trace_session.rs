@@ -0,0 +1,388 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    trace_session.rs
+
+    Manages trace sessions. Each session traces one kind of event - CPU
+    instructions, CPU cycles or IO port accesses - through its own filter to
+    its own TraceLogger, and can be paused, resumed and stopped independently
+    of any other session.
+
+    Sessions are host resources like the loggers they own. Cloning a session
+    manager clones the session list, but file loggers in the clone discard 
+    their output.
+*/
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::tracelogger::TraceLogger;
+
+pub type TraceSessionId = u32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceKind {
+    Instruction,
+    Cycle,
+    Io,
+}
+
+impl TraceKind {
+    pub const ALL: [TraceKind; 3] = [TraceKind::Instruction, TraceKind::Cycle, TraceKind::Io];
+
+    fn index(&self) -> usize {
+        match self {
+            TraceKind::Instruction => 0,
+            TraceKind::Cycle => 1,
+            TraceKind::Io => 2,
+        }
+    }
+}
+
+impl fmt::Display for TraceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceKind::Instruction => write!(f, "Instruction"),
+            TraceKind::Cycle => write!(f, "Cycle"),
+            TraceKind::Io => write!(f, "IO"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceSessionState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+impl fmt::Display for TraceSessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceSessionState::Running => write!(f, "Running"),
+            TraceSessionState::Paused => write!(f, "Paused"),
+            TraceSessionState::Stopped => write!(f, "Stopped"),
+        }
+    }
+}
+
+/// Restricts the events a trace session logs. An empty filter logs everything.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Trace only instructions and cycles executed in this code segment.
+    pub segment: Option<u16>,
+    /// Trace only IO accesses to ports in this inclusive range.
+    pub ports: Option<(u16, u16)>,
+}
+
+impl TraceFilter {
+    /// Parse a filter from a hex code segment and a hex port or port range such as "3F0-3F7".
+    /// Empty strings don't filter.
+    pub fn parse(segment: &str, ports: &str) -> Result<Self, String> {
+        let parse_hex = |s: &str| u16::from_str_radix(s.trim(), 16).map_err(|_| format!("Invalid hex value: {}", s.trim()));
+
+        let segment = match segment.trim() {
+            "" => None,
+            s => Some(parse_hex(s)?),
+        };
+        let ports = match ports.trim() {
+            "" => None,
+            s => {
+                let (start, end) = match s.split_once('-') {
+                    Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+                    None => (parse_hex(s)?, parse_hex(s)?),
+                };
+                if end < start {
+                    return Err(format!("Invalid port range: {}", s));
+                }
+                Some((start, end))
+            }
+        };
+        Ok(Self { segment, ports })
+    }
+
+    #[inline]
+    pub fn matches_segment(&self, cs: u16) -> bool {
+        self.segment.is_none_or(|segment| segment == cs)
+    }
+
+    #[inline]
+    pub fn matches_port(&self, port: u16) -> bool {
+        self.ports.is_none_or(|(start, end)| port >= start && port <= end)
+    }
+}
+
+impl fmt::Display for TraceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.segment, self.ports) {
+            (None, None) => write!(f, "All"),
+            (Some(segment), None) => write!(f, "CS={:04X}", segment),
+            (None, Some((start, end))) => write!(f, "Ports {:04X}-{:04X}", start, end),
+            (Some(segment), Some((start, end))) => write!(f, "CS={:04X}, Ports {:04X}-{:04X}", segment, start, end),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TraceSession {
+    id: TraceSessionId,
+    name: String,
+    kind: TraceKind,
+    filter: TraceFilter,
+    state: TraceSessionState,
+    path: Option<PathBuf>,
+    lines: u64,
+    logger: TraceLogger,
+}
+
+impl TraceSession {
+    pub fn id(&self) -> TraceSessionId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> TraceKind {
+        self.kind
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    pub fn state(&self) -> TraceSessionState {
+        self.state
+    }
+
+    /// Return the path of the file the session logs to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Return the number of events logged by the session.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    #[inline]
+    fn log(&mut self, msg: &str) {
+        self.logger.println(msg);
+        self.lines += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TraceSessionManager {
+    sessions: Vec<TraceSession>,
+    next_id: TraceSessionId,
+    active: [bool; 3],
+}
+
+impl TraceSessionManager {
+    /// Start a new session logging to the specified logger. The path of the log file, if any,
+    /// is shown to the user.
+    pub fn start(
+        &mut self,
+        name: &str,
+        kind: TraceKind,
+        filter: TraceFilter,
+        logger: TraceLogger,
+        path: Option<PathBuf>,
+    ) -> TraceSessionId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        log::debug!("Starting {} trace session {}: {} ({})", kind, id, name, filter);
+        self.sessions.push(TraceSession {
+            id,
+            name: name.to_string(),
+            kind,
+            filter,
+            state: TraceSessionState::Running,
+            path,
+            lines: 0,
+            logger,
+        });
+        self.update_active();
+        id
+    }
+
+    /// Pause or resume a running or paused session. Returns false if the session does not 
+    /// exist or is stopped.
+    pub fn set_paused(&mut self, id: TraceSessionId, paused: bool) -> bool {
+        let result = match self.sessions.iter_mut().find(|s| s.id == id) {
+            Some(session) if session.state != TraceSessionState::Stopped => {
+                session.state = if paused { TraceSessionState::Paused } else { TraceSessionState::Running };
+                if paused {
+                    session.logger.flush();
+                }
+                true
+            }
+            _ => false,
+        };
+        self.update_active();
+        result
+    }
+
+    /// Stop a session, closing its log. A stopped session stays in the session list until 
+    /// removed.
+    pub fn stop(&mut self, id: TraceSessionId) -> bool {
+        let result = match self.sessions.iter_mut().find(|s| s.id == id) {
+            Some(session) => {
+                session.logger.flush();
+                session.logger = TraceLogger::None;
+                session.state = TraceSessionState::Stopped;
+                log::debug!("Stopped trace session {}: {} lines logged", id, session.lines);
+                true
+            }
+            None => false,
+        };
+        self.update_active();
+        result
+    }
+
+    /// Stop a session and remove it from the session list.
+    pub fn remove(&mut self, id: TraceSessionId) -> bool {
+        let found = self.stop(id);
+        self.sessions.retain(|s| s.id != id);
+        found
+    }
+
+    pub fn sessions(&self) -> &[TraceSession] {
+        &self.sessions
+    }
+
+    pub fn get(&self, id: TraceSessionId) -> Option<&TraceSession> {
+        self.sessions.iter().find(|s| s.id == id)
+    }
+
+    /// Return whether any running session traces the specified kind of event.
+    #[inline(always)]
+    pub fn is_active(&self, kind: TraceKind) -> bool {
+        self.active[kind.index()]
+    }
+
+    /// Return whether any session is running.
+    #[inline(always)]
+    pub fn any_active(&self) -> bool {
+        self.active.iter().any(|a| *a)
+    }
+
+    /// Log a CPU instruction or cycle event to every running session of that kind whose filter
+    /// accepts the current code segment.
+    pub fn trace_cpu(&mut self, kind: TraceKind, cs: u16, msg: &str) {
+        for session in self.sessions.iter_mut() {
+            if session.kind == kind 
+                && session.state == TraceSessionState::Running 
+                && session.filter.matches_segment(cs) {
+                session.log(msg);
+            }
+        }
+    }
+
+    /// Log an IO port access to every running IO session whose filter accepts the port and
+    /// code segment.
+    pub fn trace_io(&mut self, cs: u16, port: u16, msg: &str) {
+        for session in self.sessions.iter_mut() {
+            if session.kind == TraceKind::Io 
+                && session.state == TraceSessionState::Running 
+                && session.filter.matches_port(port)
+                && session.filter.matches_segment(cs) {
+                session.log(msg);
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for session in self.sessions.iter_mut() {
+            session.logger.flush();
+        }
+    }
+
+    fn update_active(&mut self) {
+        for kind in TraceKind::ALL {
+            self.active[kind.index()] = self.sessions.iter().any(|s| {
+                s.kind == kind && s.state == TraceSessionState::Running
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_parse() {
+        let filter = TraceFilter::parse("f000", "3F0-3F7").unwrap();
+        assert_eq!(filter.segment, Some(0xF000));
+        assert_eq!(filter.ports, Some((0x3F0, 0x3F7)));
+        assert!(filter.matches_port(0x3F5));
+        assert!(!filter.matches_port(0x3F8));
+
+        let filter = TraceFilter::parse("", " 61 ").unwrap();
+        assert_eq!(filter, TraceFilter { segment: None, ports: Some((0x61, 0x61)) });
+        assert!(filter.matches_segment(0x1234));
+
+        assert!(TraceFilter::parse("xyz", "").is_err());
+        assert!(TraceFilter::parse("", "3F7-3F0").is_err());
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let mut sessions = TraceSessionManager::default();
+        let instr = sessions.start("bios", TraceKind::Instruction, TraceFilter::parse("F000", "").unwrap(), TraceLogger::None, None);
+        let io = sessions.start("fdc", TraceKind::Io, TraceFilter::parse("", "3F0-3F7").unwrap(), TraceLogger::None, None);
+
+        assert!(sessions.is_active(TraceKind::Instruction));
+        assert!(sessions.is_active(TraceKind::Io));
+        assert!(!sessions.is_active(TraceKind::Cycle));
+
+        sessions.trace_cpu(TraceKind::Instruction, 0xF000, "in bios");
+        sessions.trace_cpu(TraceKind::Instruction, 0x0070, "in dos");
+        sessions.trace_io(0x0070, 0x3F5, "fdc data");
+        sessions.trace_io(0x0070, 0x61, "ppi");
+        assert_eq!(sessions.get(instr).unwrap().lines(), 1);
+        assert_eq!(sessions.get(io).unwrap().lines(), 1);
+
+        // Paused sessions don't log.
+        assert!(sessions.set_paused(instr, true));
+        assert!(!sessions.is_active(TraceKind::Instruction));
+        sessions.trace_cpu(TraceKind::Instruction, 0xF000, "in bios");
+        assert_eq!(sessions.get(instr).unwrap().lines(), 1);
+
+        // Stopped sessions can't be resumed, but remain until removed.
+        assert!(sessions.stop(io));
+        assert!(!sessions.set_paused(io, false));
+        assert!(!sessions.is_active(TraceKind::Io));
+        assert_eq!(sessions.get(io).unwrap().state(), TraceSessionState::Stopped);
+        assert!(sessions.remove(io));
+        assert!(sessions.get(io).is_none());
+        assert_eq!(sessions.sessions().len(), 1);
+    }
+}
@@ -0,0 +1,646 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    trace_validator.rs
+
+    Records validated instructions to a trace file, and implements a CPU
+    validator that replays such a trace instead of running on hardware.
+
+    A TraceRecorder wraps another validator, normally the Arduino8088
+    validator. Each instruction that passes validation is written to the
+    trace as a line of JSON: the instruction bytes, the registers before and
+    after, the memory and IO writes, and the bus state of every cycle.
+
+    The ReplayValidator reads a recorded trace and checks the emulator
+    against it instruction by instruction, including cycle states, so cycle
+    validation can be run without the hardware. The emulator must execute the
+    same program from the same state as when the trace was recorded; if the
+    instructions diverge, the CPU is reported as desynced.
+*/
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cpu_808x::QueueOp;
+use crate::cpu_validator::*;
+use crate::tracelogger::TraceLogger;
+
+macro_rules! trace {
+    ($self:ident, $($t:tt)*) => {{
+        $self.trace_logger.print(&format!($($t)*));
+        $self.trace_logger.print("\n".to_string());
+    }};
+}
+
+macro_rules! trace_error {
+    ($self:ident, $($t:tt)*) => {{
+        log::error!("{}", &format!($($t)*));
+        $self.trace_logger.print(&format!($($t)*));
+        $self.trace_logger.print("\n".to_string());
+    }};
+}
+
+/// The bus state of a single recorded cycle. Only the fields compared by CycleState's
+/// PartialEq implementation are stored.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceCycle {
+    pub addr: u32,
+    pub t: u8,
+    pub bus: u8,
+    pub access: u8,
+    pub ale: bool,
+    pub mrdc: bool,
+    pub amwc: bool,
+    pub mwtc: bool,
+    pub iorc: bool,
+    pub q_op: u8,
+}
+
+impl From<&CycleState> for TraceCycle {
+    fn from(state: &CycleState) -> Self {
+        Self {
+            addr: state.addr,
+            t: match state.t_state {
+                BusCycle::T1 => 1,
+                BusCycle::T2 => 2,
+                BusCycle::T3 => 3,
+                BusCycle::T4 => 4,
+                BusCycle::Tw => 0,
+            },
+            bus: state.b_state as u8,
+            access: state.a_type as u8,
+            ale: state.ale,
+            mrdc: state.mrdc,
+            amwc: state.amwc,
+            mwtc: state.mwtc,
+            iorc: state.iorc,
+            q_op: match state.q_op {
+                QueueOp::Idle => 0,
+                QueueOp::First => 1,
+                QueueOp::Flush => 2,
+                QueueOp::Subsequent => 3,
+            },
+        }
+    }
+}
+
+impl TraceCycle {
+    pub fn to_state(&self) -> CycleState {
+        CycleState {
+            n: 0,
+            addr: self.addr,
+            t_state: match self.t {
+                1 => BusCycle::T1,
+                2 => BusCycle::T2,
+                3 => BusCycle::T3,
+                4 => BusCycle::T4,
+                _ => BusCycle::Tw,
+            },
+            a_type: match self.access {
+                0 => AccessType::AlternateData,
+                1 => AccessType::Stack,
+                2 => AccessType::CodeOrNone,
+                _ => AccessType::Data,
+            },
+            b_state: match self.bus {
+                0 => BusState::INTA,
+                1 => BusState::IOR,
+                2 => BusState::IOW,
+                3 => BusState::HALT,
+                4 => BusState::CODE,
+                5 => BusState::MEMR,
+                6 => BusState::MEMW,
+                _ => BusState::PASV,
+            },
+            ale: self.ale,
+            mrdc: self.mrdc,
+            amwc: self.amwc,
+            mwtc: self.mwtc,
+            iorc: self.iorc,
+            aiowc: false,
+            iowc: false,
+            inta: false,
+            q_op: match self.q_op {
+                1 => QueueOp::First,
+                2 => QueueOp::Flush,
+                3 => QueueOp::Subsequent,
+                _ => QueueOp::Idle,
+            },
+            q_byte: 0,
+            q_len: 0,
+            q: [0; 4],
+            data_bus: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceWrite {
+    pub io: bool,
+    pub addr: u32,
+    pub data: u8,
+}
+
+/// A validated instruction, as stored on one line of a trace file.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TraceInstruction {
+    pub name: String,
+    pub instr: Vec<u8>,
+    pub regs_before: VRegisters,
+    pub regs_after: VRegisters,
+    pub writes: Vec<TraceWrite>,
+    pub cycles: Vec<TraceCycle>,
+}
+
+/// Read a trace file. Blank lines are ignored.
+pub fn read_trace(path: &Path) -> Result<VecDeque<TraceInstruction>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut trace = VecDeque::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let instruction = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+        trace.push_back(instruction);
+    }
+    Ok(trace)
+}
+
+fn make_write(addr: u32, data: u8, bus_type: BusType) -> TraceWrite {
+    TraceWrite {
+        io: bus_type == BusType::Io,
+        addr,
+        data
+    }
+}
+
+fn make_pointer(base: u16, offset: u16) -> u32 {
+    (((base as u32) << 4) + offset as u32) & 0xFFFFF
+}
+
+/// Wraps a validator and records each instruction that passes validation to a trace file.
+pub struct TraceRecorder {
+    inner: Box<dyn CpuValidator>,
+    writer: BufWriter<File>,
+    regs_before: VRegisters,
+    writes: Vec<TraceWrite>,
+    discard: bool,
+}
+
+impl TraceRecorder {
+    pub fn new(inner: Box<dyn CpuValidator>, file: File) -> Self {
+        Self {
+            inner,
+            writer: BufWriter::new(file),
+            regs_before: Default::default(),
+            writes: Vec::new(),
+            discard: false,
+        }
+    }
+
+    fn record(&mut self, instruction: &TraceInstruction) {
+        let result = serde_json::to_string(instruction)
+            .map_err(|e| e.to_string())
+            .and_then(|line| writeln!(self.writer, "{}", line).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            log::error!("Failed to write validator trace: {}", e);
+        }
+    }
+}
+
+impl CpuValidator for TraceRecorder {
+
+    fn init(&mut self, mode: ValidatorMode, mask_flags: bool, cycle_trace: bool, visit_once: bool) -> bool {
+        self.inner.init(mode, mask_flags, cycle_trace, visit_once)
+    }
+
+    fn reset_instruction(&mut self) {
+        self.writes.clear();
+        self.inner.reset_instruction();
+    }
+
+    fn begin_instruction(&mut self, regs: &VRegisters, end_instr: usize, end_program: usize) {
+        self.discard = false;
+        self.regs_before = *regs;
+        self.inner.begin_instruction(regs, end_instr, end_program);
+    }
+
+    fn set_regs(&mut self) {
+        self.inner.set_regs();
+    }
+
+    fn validate_instruction(
+        &mut self,
+        name: String,
+        instr: &[u8],
+        peek_fetch: u16,
+        has_modrm: bool,
+        cycles: i32,
+        regs: &VRegisters,
+        emu_states: &[CycleState]
+    ) -> Result<ValidatorResult, ValidatorError> {
+
+        let result = self.inner.validate_instruction(
+            name.clone(),
+            instr,
+            peek_fetch,
+            has_modrm,
+            cycles,
+            regs,
+            emu_states
+        );
+
+        if result.is_ok() && !self.discard {
+            let instruction = TraceInstruction {
+                name,
+                instr: instr.to_vec(),
+                regs_before: self.regs_before,
+                regs_after: *regs,
+                writes: std::mem::take(&mut self.writes),
+                cycles: emu_states.iter().map(TraceCycle::from).collect(),
+            };
+            self.record(&instruction);
+        }
+        self.writes.clear();
+        result
+    }
+
+    fn validate_regs(&mut self, regs: &VRegisters) -> Result<(), ValidatorError> {
+        self.inner.validate_regs(regs)
+    }
+
+    fn emu_read_byte(&mut self, addr: u32, data: u8, bus_type: BusType, read_type: ReadType) {
+        self.inner.emu_read_byte(addr, data, bus_type, read_type);
+    }
+
+    fn emu_write_byte(&mut self, addr: u32, data: u8, bus_type: BusType) {
+        self.writes.push(make_write(addr, data, bus_type));
+        self.inner.emu_write_byte(addr, data, bus_type);
+    }
+
+    fn discard_op(&mut self) {
+        self.discard = true;
+        self.inner.discard_op();
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            log::error!("Failed to flush validator trace: {}", e);
+        }
+        self.inner.flush();
+    }
+}
+
+/// A validator that checks the emulator against a recorded trace.
+pub struct ReplayValidator {
+    trace: VecDeque<TraceInstruction>,
+    regs_before: VRegisters,
+    regs_after: VRegisters,
+    writes: Vec<TraceWrite>,
+    mode: ValidatorMode,
+    discard: bool,
+    end_addr: usize,
+
+    trace_logger: TraceLogger
+}
+
+impl ReplayValidator {
+
+    pub fn new(trace: VecDeque<TraceInstruction>, trace_logger: TraceLogger) -> Self {
+        Self {
+            trace,
+            regs_before: Default::default(),
+            regs_after: Default::default(),
+            writes: Vec::new(),
+            mode: ValidatorMode::Cycle,
+            discard: false,
+            end_addr: 0,
+            trace_logger
+        }
+    }
+
+    pub fn from_file(path: &Path, trace_logger: TraceLogger) -> Result<Self, String> {
+        let trace = read_trace(path)?;
+        log::debug!("Loaded {} instructions from validator trace {}", trace.len(), path.display());
+        Ok(Self::new(trace, trace_logger))
+    }
+
+    /// The number of recorded instructions not yet replayed.
+    pub fn remaining(&self) -> usize {
+        self.trace.len()
+    }
+
+    fn print_regs(&mut self, regs: &VRegisters) {
+        trace!(
+            self,
+            "AX: {:04x} BX: {:04x} CX: {:04x} DX: {:04x}\n\
+            SP: {:04x} BP: {:04x} SI: {:04x} DI: {:04x}\n\
+            CS: {:04x} DS: {:04x} ES: {:04x} SS: {:04x}\n\
+            IP: {:04x}\n\
+            FLAGS: {:04x}",
+            regs.ax, regs.bx, regs.cx, regs.dx,
+            regs.sp, regs.bp, regs.si, regs.di,
+            regs.cs, regs.ds, regs.es, regs.ss,
+            regs.ip,
+            regs.flags
+        );
+    }
+
+    /// Compare emulator registers against the recorded registers. Flag differences are reported
+    /// separately from other registers.
+    fn compare_registers(&mut self, emu: &VRegisters) -> Result<(), ValidatorError> {
+        let r = self.regs_after;
+        if r == *emu {
+            return Ok(())
+        }
+
+        let mut flagless = *emu;
+        flagless.flags = r.flags;
+        if flagless == r {
+            trace_error!(self, "CPU flags mismatch! EMU: 0b{:016b} != TRACE: 0b{:016b}", emu.flags, r.flags);
+            return Err(ValidatorError::FlagsMismatch);
+        }
+        Err(ValidatorError::RegisterMismatch)
+    }
+
+    fn compare_cycles(&mut self, recorded: &[TraceCycle], emu_states: &[CycleState]) -> bool {
+        if recorded.len() != emu_states.len() {
+            trace_error!(self, "Cycle count mismatch! EMU: {} TRACE: {}", emu_states.len(), recorded.len());
+            return false;
+        }
+
+        for (i, (trace_cycle, emu_state)) in recorded.iter().zip(emu_states.iter()).enumerate() {
+            if trace_cycle.to_state() != *emu_state {
+                trace_error!(
+                    self,
+                    "Cycle state mismatch at cycle {}! EMU: {:?} TRACE: {:?}",
+                    i,
+                    TraceCycle::from(emu_state),
+                    trace_cycle
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl CpuValidator for ReplayValidator {
+
+    fn init(&mut self, mode: ValidatorMode, _mask_flags: bool, _cycle_trace: bool, _visit_once: bool) -> bool {
+        // Flags were masked when the trace was recorded, so the recorded flags are compared as-is.
+        self.mode = mode;
+        true
+    }
+
+    fn reset_instruction(&mut self) {
+        self.writes.clear();
+    }
+
+    fn begin_instruction(&mut self, regs: &VRegisters, _end_instr: usize, end_program: usize) {
+        self.discard = false;
+        self.regs_before = *regs;
+        self.end_addr = end_program;
+    }
+
+    fn set_regs(&mut self) {
+        // Registers are checked against the trace at the start of each instruction instead.
+    }
+
+    fn validate_instruction(
+        &mut self,
+        name: String,
+        instr: &[u8],
+        _peek_fetch: u16,
+        _has_modrm: bool,
+        _cycles: i32,
+        regs: &VRegisters,
+        emu_states: &[CycleState]
+    ) -> Result<ValidatorResult, ValidatorError> {
+
+        trace!(
+            self,
+            "VALIDATE: {} {:02X?} @ [{:04X}:{:04X}] Writes: {}",
+            name,
+            instr,
+            self.regs_before.cs,
+            self.regs_before.ip,
+            self.writes.len()
+        );
+
+        if self.discard {
+            self.writes.clear();
+            return Ok(ValidatorResult::Ok);
+        }
+
+        let recorded = match self.trace.pop_front() {
+            Some(recorded) => recorded,
+            None => {
+                trace!(self, " >>> End of trace, validator finalizing!");
+                return Ok(ValidatorResult::OkEnd);
+            }
+        };
+        self.regs_after = recorded.regs_after;
+
+        if recorded.instr != instr || recorded.regs_before != self.regs_before {
+            trace_error!(self, "Emulator diverged from trace! EMU: {} {:02X?} TRACE: {} {:02X?}", name, instr, recorded.name, recorded.instr);
+            trace_error!(self, "EMU BEFORE:");
+            let regs_before = self.regs_before;
+            self.print_regs(&regs_before);
+            trace_error!(self, "TRACE BEFORE:");
+            self.print_regs(&recorded.regs_before);
+            self.trace_logger.flush();
+            return Err(ValidatorError::CpuDesynced);
+        }
+
+        if self.writes != recorded.writes {
+            trace_error!(self, "Write mismatch! EMU: {:?} TRACE: {:?}", self.writes, recorded.writes);
+            self.trace_logger.flush();
+            return Err(ValidatorError::MemOpMismatch);
+        }
+
+        if self.mode == ValidatorMode::Cycle && !self.compare_cycles(&recorded.cycles, emu_states) {
+            self.trace_logger.flush();
+            return Err(ValidatorError::CycleMismatch);
+        }
+
+        if let Err(e) = self.compare_registers(regs) {
+            trace_error!(self, "Register validation failure. EMU AFTER:");
+            self.print_regs(regs);
+            trace_error!(self, "TRACE AFTER:");
+            self.print_regs(&recorded.regs_after);
+            self.trace_logger.flush();
+            return Err(e);
+        }
+
+        self.writes.clear();
+
+        if make_pointer(regs.cs, regs.ip) as usize == self.end_addr {
+            trace!(self, " >>> Validator finalizing!");
+            Ok(ValidatorResult::OkEnd)
+        }
+        else {
+            Ok(ValidatorResult::Ok)
+        }
+    }
+
+    fn validate_regs(&mut self, regs: &VRegisters) -> Result<(), ValidatorError> {
+        if let Err(e) = self.compare_registers(regs) {
+            trace_error!(self, "Register validation failure. EMU:");
+            self.print_regs(regs);
+            trace_error!(self, "TRACE:");
+            let regs_after = self.regs_after;
+            self.print_regs(&regs_after);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn emu_read_byte(&mut self, _addr: u32, _data: u8, _bus_type: BusType, _read_type: ReadType) {
+        // Reads are inputs to the instruction; any difference shows up in its results.
+    }
+
+    fn emu_write_byte(&mut self, addr: u32, data: u8, bus_type: BusType) {
+        if self.discard {
+            return;
+        }
+        self.writes.push(make_write(addr, data, bus_type));
+    }
+
+    fn discard_op(&mut self) {
+        self.discard = true;
+    }
+
+    fn flush(&mut self) {
+        self.trace_logger.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle(t_state: BusCycle, b_state: BusState, q_op: QueueOp) -> CycleState {
+        let mut state = TraceCycle {
+            addr: 0xFFFF0,
+            t: 0,
+            bus: 0,
+            access: AccessType::CodeOrNone as u8,
+            ale: t_state == BusCycle::T1,
+            mrdc: false,
+            amwc: false,
+            mwtc: false,
+            iorc: false,
+            q_op: 0,
+        }.to_state();
+
+        state.t_state = t_state;
+        state.b_state = b_state;
+        state.q_op = q_op;
+        state
+    }
+
+    fn nop_instruction() -> (TraceInstruction, Vec<CycleState>) {
+        let states = vec![
+            cycle(BusCycle::T1, BusState::CODE, QueueOp::First),
+            cycle(BusCycle::T2, BusState::CODE, QueueOp::Idle),
+            cycle(BusCycle::Tw, BusState::PASV, QueueOp::Subsequent),
+        ];
+        let instruction = TraceInstruction {
+            name: "nop".to_string(),
+            instr: vec![0x90],
+            regs_before: VRegisters { cs: 0x100, ip: 0x10, ..Default::default() },
+            regs_after: VRegisters { cs: 0x100, ip: 0x11, ..Default::default() },
+            writes: vec![TraceWrite { io: false, addr: 0x1000, data: 0x55 }],
+            cycles: states.iter().map(TraceCycle::from).collect(),
+        };
+        (instruction, states)
+    }
+
+    fn replay(validator: &mut ReplayValidator, instruction: &TraceInstruction, states: &[CycleState])
+        -> Result<ValidatorResult, ValidatorError>
+    {
+        validator.reset_instruction();
+        validator.begin_instruction(&instruction.regs_before, 0, 0xFFFFF);
+        for write in &instruction.writes {
+            validator.emu_write_byte(write.addr, write.data, BusType::Mem);
+        }
+        validator.validate_instruction(
+            instruction.name.clone(),
+            &instruction.instr,
+            0,
+            false,
+            0,
+            &instruction.regs_after,
+            states
+        )
+    }
+
+    #[test]
+    fn test_trace_cycle_roundtrip() {
+        let (_, states) = nop_instruction();
+        for state in &states {
+            let recorded = TraceCycle::from(state);
+            assert!(recorded.to_state() == *state);
+
+            let text = serde_json::to_string(&recorded).unwrap();
+            assert_eq!(serde_json::from_str::<TraceCycle>(&text).unwrap(), recorded);
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let (instruction, states) = nop_instruction();
+        let mut validator = ReplayValidator::new(VecDeque::from(vec![instruction.clone(); 3]), TraceLogger::None);
+        validator.init(ValidatorMode::Cycle, true, true, true);
+
+        assert_eq!(replay(&mut validator, &instruction, &states).unwrap(), ValidatorResult::Ok);
+
+        // A different cycle state is a cycle mismatch
+        let mut bad_states = states.clone();
+        bad_states[2].q_op = QueueOp::Flush;
+        assert!(matches!(replay(&mut validator, &instruction, &bad_states), Err(ValidatorError::CycleMismatch)));
+
+        // A different instruction means the emulator has diverged from the trace
+        let mut other = instruction.clone();
+        other.instr = vec![0xF8];
+        assert!(matches!(replay(&mut validator, &other, &states), Err(ValidatorError::CpuDesynced)));
+
+        // Running past the end of the trace ends validation
+        assert_eq!(validator.remaining(), 0);
+        assert_eq!(replay(&mut validator, &instruction, &states).unwrap(), ValidatorResult::OkEnd);
+    }
+}
@@ -0,0 +1,240 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    validation_preset.rs
+
+    Runs a preset list of timing-sensitive video effects and checks that they
+    render correctly.
+
+    Each effect is booted from a floppy image and run for a fixed number of
+    frames. During the final frame, the CRTC register writes made by the 
+    effect are recorded along with the scanline on which they occurred. 
+    Checksums of the write log and of the completed frame are then compared 
+    against known-good values recorded from a reference run.
+
+    Effects without recorded checksums are reported as unverified along with
+    their observed checksums, so a preset can be recorded once emulation of 
+    an effect has been confirmed correct.
+*/
+
+use std::{
+    fmt,
+    fs,
+    path::Path
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::machine::{ExecutionControl, ExecutionState, Machine, MachineState};
+
+/// Number of cycles to run between checks of the frame counter.
+const RUN_SLICE_CYCLES: u32 = 1000;
+/// Number of extra frames' worth of cycles to allow before giving up on an effect.
+const TIMEOUT_FRAME_MARGIN: u64 = 60;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EffectCheck {
+    pub name: String,
+    pub floppy: String,
+    pub frame: u64,
+    pub frame_checksum: Option<String>,
+    pub crtc_checksum: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ValidationPreset {
+    #[serde(rename = "effect")]
+    pub effects: Vec<EffectCheck>,
+}
+
+impl ValidationPreset {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EffectStatus {
+    Pass,
+    Fail,
+    Unverified,
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct EffectResult {
+    pub name: String,
+    pub status: EffectStatus,
+    pub frame_checksum: String,
+    pub crtc_checksum: String,
+    pub crtc_writes: usize,
+    pub message: Option<String>,
+}
+
+impl EffectResult {
+    fn error(effect: &EffectCheck, message: String) -> Self {
+        Self {
+            name: effect.name.clone(),
+            status: EffectStatus::Error,
+            frame_checksum: String::new(),
+            crtc_checksum: String::new(),
+            crtc_writes: 0,
+            message: Some(message),
+        }
+    }
+}
+
+impl fmt::Display for EffectResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status_str = match self.status {
+            EffectStatus::Pass => "PASS",
+            EffectStatus::Fail => "FAIL",
+            EffectStatus::Unverified => "UNVERIFIED",
+            EffectStatus::Error => "ERROR",
+        };
+        write!(f, "{:<10} {}", status_str, self.name)?;
+        if let Some(message) = &self.message {
+            return write!(f, ": {}", message)
+        }
+        write!(
+            f,
+            " (frame: {} crtc: {}, {} writes)",
+            self.frame_checksum,
+            self.crtc_checksum,
+            self.crtc_writes
+        )
+    }
+}
+
+/// Run the machine until the video card's frame counter reaches 'target'. Returns false if the
+/// frame was not reached within 'cycle_limit' cycles.
+fn run_until_frame(machine: &mut Machine, exec_control: &mut ExecutionControl, target: u64, cycle_limit: u64) -> bool {
+    let mut cycles = 0;
+    loop {
+        let frame = match machine.videocard() {
+            Some(card) => card.get_frame_count(),
+            None => return false
+        };
+        if frame >= target {
+            return true
+        }
+        if cycles >= cycle_limit {
+            return false
+        }
+        machine.run(RUN_SLICE_CYCLES, exec_control);
+        cycles += RUN_SLICE_CYCLES as u64;
+    }
+}
+
+/// Reboot the machine with the specified floppy image and run the effect, comparing the result
+/// against the effect's recorded checksums.
+pub fn run_effect(machine: &mut Machine, floppy: Vec<u8>, effect: &EffectCheck) -> EffectResult {
+
+    machine.change_state(MachineState::Rebooting);
+
+    match machine.fdc() {
+        Some(fdc) => {
+            if let Err(e) = fdc.load_image_from(0, floppy) {
+                return EffectResult::error(effect, format!("failed to load floppy: {}", e))
+            }
+        }
+        None => return EffectResult::error(effect, "machine has no floppy controller".to_string())
+    }
+
+    let start_frame = match machine.videocard() {
+        Some(card) => card.get_frame_count(),
+        None => return EffectResult::error(effect, "machine has no video card".to_string())
+    };
+
+    let mut exec_control = ExecutionControl::new();
+    exec_control.set_state(ExecutionState::Running);
+
+    let cycles_per_frame = (machine.get_cpu_mhz() * 1000000.0 / 60.0) as u64;
+    let cycle_limit = (effect.frame + TIMEOUT_FRAME_MARGIN) * cycles_per_frame;
+
+    // Run up to the start of the checked frame, then log CRTC writes through it.
+    let check_frame = start_frame + effect.frame.max(1);
+    if !run_until_frame(machine, &mut exec_control, check_frame - 1, cycle_limit) {
+        return EffectResult::error(effect, "timed out before reaching frame".to_string())
+    }
+    if let Some(card) = machine.videocard() {
+        card.set_crtc_logging(true);
+    }
+    if !run_until_frame(machine, &mut exec_control, check_frame, cycles_per_frame * 2) {
+        return EffectResult::error(effect, "timed out during checked frame".to_string())
+    }
+
+    let (frame_digest, crtc_log) = match machine.videocard() {
+        Some(card) => {
+            let log = card.take_crtc_log();
+            card.set_crtc_logging(false);
+            (md5::compute(card.get_display_buf()), log)
+        }
+        None => return EffectResult::error(effect, "machine has no video card".to_string())
+    };
+
+    let mut log_bytes = Vec::with_capacity(crtc_log.len() * 6);
+    for (scanline, reg, value) in &crtc_log {
+        log_bytes.extend_from_slice(&scanline.to_le_bytes());
+        log_bytes.push(*reg);
+        log_bytes.push(*value);
+    }
+
+    let frame_checksum = format!("{:x}", frame_digest);
+    let crtc_checksum = format!("{:x}", md5::compute(&log_bytes));
+
+    let checks = [
+        (&effect.frame_checksum, &frame_checksum),
+        (&effect.crtc_checksum, &crtc_checksum),
+    ];
+    let status = if checks.iter().all(|(expected, _)| expected.is_none()) {
+        EffectStatus::Unverified
+    }
+    else if checks.iter().all(|(expected, actual)| match expected {
+        Some(expected) => expected.eq_ignore_ascii_case(actual),
+        None => true
+    }) {
+        EffectStatus::Pass
+    }
+    else {
+        EffectStatus::Fail
+    };
+
+    EffectResult {
+        name: effect.name.clone(),
+        status,
+        frame_checksum,
+        crtc_checksum,
+        crtc_writes: crtc_log.len(),
+        message: None,
+    }
+}
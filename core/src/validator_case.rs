@@ -0,0 +1,294 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    validator_case.rs
+
+    Captures, minimizes and replays failing CPU validator cases.
+
+    When the validator reports a mismatch, the failing instruction is 
+    captured along with the register state and the contents of memory. 
+    The case is then reduced by repeatedly mutating it and replaying it 
+    through the validator, keeping each mutation that still produces the 
+    same kind of mismatch: prefixes are removed, registers are cleared and 
+    memory is zeroed in progressively smaller chunks. The result is saved
+    as a small TOML file that can be replayed against hardware later.
+*/
+
+use std::{
+    fs,
+    path::Path
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::cpu_808x::Cpu;
+use crate::cpu_validator::{MismatchKind, VRegisters, MISMATCH_KINDS};
+
+/// Size of the address space captured with each case.
+const CASE_MEM_SIZE: usize = 0x100000;
+/// Memory is neutralized in chunks down to this size.
+const MIN_CHUNK_SIZE: usize = 16;
+/// Flags with only the reserved bits set, used when neutralizing the flags register.
+const NEUTRAL_FLAGS: u16 = 0xF002;
+/// Upper bound on steps when replaying a case, as REP string instructions execute once per step.
+const MAX_CASE_STEPS: usize = 0x20000;
+
+const PREFIX_BYTES: [u8; 7] = [0x26, 0x2E, 0x36, 0x3E, 0xF0, 0xF2, 0xF3];
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CaseMemory {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+/// A minimized failing case as saved to disk. Memory not listed is zero.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ValidatorCase {
+    pub mismatch: String,
+    pub instr: Vec<u8>,
+    pub regs: VRegisters,
+    pub memory: Vec<CaseMemory>,
+}
+
+impl ValidatorCase {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// A case with the full contents of memory, as used while minimizing.
+#[derive(Clone)]
+pub struct FullCase {
+    pub instr: Vec<u8>,
+    pub regs: VRegisters,
+    pub mem: Vec<u8>,
+}
+
+impl FullCase {
+    /// Capture the instruction of the specified length at CS:IP, along with the current 
+    /// registers and memory. Call before the instruction is executed.
+    pub fn capture(cpu: &Cpu, instr_len: usize) -> Self {
+        let regs = cpu.get_vregisters();
+        let addr = Cpu::calc_linear_address(regs.cs, regs.ip) as usize;
+        let mem = cpu.bus().get_slice_at(0, CASE_MEM_SIZE).to_vec();
+
+        Self {
+            instr: (0..instr_len).map(|i| mem[(addr + i) % CASE_MEM_SIZE]).collect(),
+            regs,
+            mem,
+        }
+    }
+
+    /// Load this case into the CPU ready to execute.
+    pub fn apply(&self, cpu: &mut Cpu) {
+        cpu.bus_mut().patch_from(&self.mem, 0).expect("Case memory out of range.");
+
+        let addr = Cpu::calc_linear_address(self.regs.cs, self.regs.ip) as usize;
+        for (i, byte) in self.instr.iter().enumerate() {
+            cpu.bus_mut().write_u8((addr + i) % CASE_MEM_SIZE, *byte, 0).expect("Mem err");
+        }
+
+        cpu.set_vregisters(&self.regs);
+        cpu.set_end_address(addr + self.instr.len());
+    }
+
+    /// Convert to a ValidatorCase, storing only the non-zero runs of memory. 
+    pub fn to_case(&self, kind: MismatchKind) -> ValidatorCase {
+        let mut memory: Vec<CaseMemory> = Vec::new();
+        let mut addr = 0;
+        while addr < self.mem.len() {
+            if self.mem[addr] == 0 {
+                addr += 1;
+                continue;
+            }
+            let start = addr;
+            while addr < self.mem.len() && self.mem[addr] != 0 {
+                addr += 1;
+            }
+            memory.push(CaseMemory { addr: start as u32, data: self.mem[start..addr].to_vec() });
+        }
+
+        ValidatorCase {
+            mismatch: kind.name().to_string(),
+            instr: self.instr.clone(),
+            regs: self.regs,
+            memory,
+        }
+    }
+
+    pub fn from_case(case: &ValidatorCase) -> Self {
+        let mut mem = vec![0; CASE_MEM_SIZE];
+        for region in &case.memory {
+            for (i, byte) in region.data.iter().enumerate() {
+                mem[(region.addr as usize + i) % CASE_MEM_SIZE] = *byte;
+            }
+        }
+
+        Self {
+            instr: case.instr.clone(),
+            regs: case.regs,
+            mem,
+        }
+    }
+}
+
+/// Execute a case through the validator. Returns the kind of mismatch produced, if any.
+pub fn run_case(cpu: &mut Cpu, case: &FullCase) -> Option<MismatchKind> {
+
+    let count_mismatches = |cpu: &Cpu| cpu.validator_stats().map(|s| s.mismatches).unwrap_or_default();
+
+    case.apply(cpu);
+    let before = count_mismatches(cpu);
+
+    // REP string instructions are executed one iteration per step.
+    for _ in 0..MAX_CASE_STEPS {
+        match cpu.step(false) {
+            Ok(_) if cpu.in_rep() => continue,
+            _ => break,
+        }
+    }
+
+    let after = count_mismatches(cpu);
+    MISMATCH_KINDS.into_iter().find(|kind| after[*kind as usize] > before[*kind as usize])
+}
+
+/// Reduce a failing case while it still produces the same kind of mismatch.
+pub fn minimize_case(cpu: &mut Cpu, case: &FullCase, kind: MismatchKind) -> FullCase {
+
+    let mut best = case.clone();
+    let fails = |cpu: &mut Cpu, candidate: &FullCase| run_case(cpu, candidate) == Some(kind);
+
+    // Remove prefixes one at a time.
+    let mut i = 0;
+    while i < best.instr.len() && PREFIX_BYTES.contains(&best.instr[i]) {
+        let mut candidate = best.clone();
+        candidate.instr.remove(i);
+        if fails(cpu, &candidate) {
+            log::debug!("Removed prefix {:02X}", best.instr[i]);
+            best = candidate;
+        }
+        else {
+            i += 1;
+        }
+    }
+
+    // Clear registers. CS:IP locates the instruction and is left alone.
+    let neutralizers: [fn(&mut VRegisters); 12] = [
+        |r| r.ax = 0,
+        |r| r.bx = 0,
+        |r| r.cx = 0,
+        |r| r.dx = 0,
+        |r| r.sp = 0,
+        |r| r.bp = 0,
+        |r| r.si = 0,
+        |r| r.di = 0,
+        |r| r.ds = 0,
+        |r| r.es = 0,
+        |r| r.ss = 0,
+        |r| r.flags = NEUTRAL_FLAGS,
+    ];
+    for neutralize in neutralizers {
+        let mut candidate = best.clone();
+        neutralize(&mut candidate.regs);
+        if candidate.regs != best.regs && fails(cpu, &candidate) {
+            best = candidate;
+        }
+    }
+
+    // Zero memory, splitting into smaller chunks wherever a chunk turns out to be relevant.
+    let mut chunks = vec![(0, CASE_MEM_SIZE)];
+    while let Some((start, len)) = chunks.pop() {
+        if best.mem[start..start + len].iter().all(|b| *b == 0) {
+            continue;
+        }
+        let mut candidate = best.clone();
+        candidate.mem[start..start + len].fill(0);
+        if fails(cpu, &candidate) {
+            best = candidate;
+        }
+        else if len > MIN_CHUNK_SIZE {
+            let half = len / 2;
+            chunks.push((start + half, len - half));
+            chunks.push((start, half));
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TraceMode, ValidatorType};
+    use crate::cpu_common::{CpuType, CpuOption};
+    use crate::tracelogger::TraceLogger;
+
+    fn nop_case() -> FullCase {
+        let mut mem = vec![0; CASE_MEM_SIZE];
+        mem[0x2000..0x2004].copy_from_slice(&[1, 2, 3, 4]);
+        FullCase {
+            instr: vec![0x90],
+            regs: VRegisters { cs: 0x100, ip: 0x10, ax: 0x1234, flags: NEUTRAL_FLAGS, ..Default::default() },
+            mem,
+        }
+    }
+
+    #[test]
+    fn test_case_roundtrip() {
+        let full = nop_case();
+        let case = full.to_case(MismatchKind::Flags);
+        assert_eq!(case.memory, vec![CaseMemory { addr: 0x2000, data: vec![1, 2, 3, 4] }]);
+
+        let text = toml::to_string(&case).unwrap();
+        let loaded: ValidatorCase = toml::from_str(&text).unwrap();
+        assert_eq!(loaded, case);
+
+        let restored = FullCase::from_case(&loaded);
+        assert_eq!(restored.mem, full.mem);
+        assert_eq!(restored.regs, full.regs);
+    }
+
+    #[test]
+    fn test_run_passing_case() {
+        let mut cpu = Cpu::new(
+            CpuType::Intel8088,
+            TraceMode::None,
+            TraceLogger::None,
+            ValidatorType::Reference,
+            TraceLogger::None
+        );
+        cpu.set_option(CpuOption::EnableWaitStates(false));
+
+        assert_eq!(run_case(&mut cpu, &nop_case()), None);
+        assert_eq!(cpu.validator_stats().unwrap().instructions, 1);
+    }
+}
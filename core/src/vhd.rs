@@ -27,9 +27,16 @@
     vhd.rs
     
     Implements VHD support including reading and writing to VHD images.
+    Raw sector images (without a VHD footer) are also supported, in which 
+    case the drive geometry is inferred from the image size.
+
+    Sector writes are held in a write-back cache until flush() is called, 
+    which the hard disk controller does periodically. Any remaining writes
+    are flushed when the disk is dropped.
 
 */
 
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
@@ -52,6 +59,12 @@ pub const VHD_FEATURE_RESERVED: u32 = 0x02;
 pub const VHD_CHECKSUM_OFFSET: usize = 64;
 pub const VHD_DISK_TYPE: u32 = 0x02;
 
+// Fixed disks of the XT era used 17 sectors per track with MFM encoding.
+pub const RAW_SECTORS_PER_TRACK: u32 = 17;
+pub const RAW_MAX_CYLINDERS: u32 = 1024;
+// Head counts to try when inferring the geometry of a raw image, in order of preference.
+const RAW_HEAD_CANDIDATES: [u32; 8] = [4, 2, 6, 8, 5, 3, 7, 16];
+
 #[derive (Debug)]
 pub enum VirtualHardDiskError {
     FileExists,
@@ -59,7 +72,9 @@ pub enum VirtualHardDiskError {
     InvalidFooter,
     InvalidVersion,
     InvalidType,
+    InvalidChecksum,
     InvalidSeek,
+    UnknownGeometry,
 }
 impl Error for VirtualHardDiskError {}
 impl Display for VirtualHardDiskError{
@@ -70,20 +85,33 @@ impl Display for VirtualHardDiskError{
             VirtualHardDiskError::InvalidFooter => write!(f, "The VHD footer was invalid or contained an invalid value."),
             VirtualHardDiskError::InvalidVersion => write!(f, "The VHD file is an unsupported version."),
             VirtualHardDiskError::InvalidType => write!(f, "The VHD file is not a supported type."),
-            VirtualHardDiskError::InvalidSeek => write!(f, "An IO operation was requested out of bounds.")
+            VirtualHardDiskError::InvalidChecksum => write!(f, "The VHD footer checksum was incorrect."),
+            VirtualHardDiskError::InvalidSeek => write!(f, "An IO operation was requested out of bounds."),
+            VirtualHardDiskError::UnknownGeometry => write!(f, "Could not determine the drive geometry from the image size."),
         }
     }
 }
 
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum DiskImageType {
+    Vhd,
+    Raw
+}
+
 #[allow(dead_code)]
 pub struct VirtualHardDisk {
 
     vhd_file: File,
     footer: VHDFileFooter,
+    image_type: DiskImageType,
 
     size: u64,
+    data_len: u64,
     checksum: u32,
 
+    // Sectors written but not yet flushed to the image file, by byte offset
+    write_cache: BTreeMap<u64, Vec<u8>>,
+
     pub max_cylinders: u32,
     pub max_heads: u32,
     pub max_sectors: u32,
@@ -252,7 +280,7 @@ impl VHDFileFooter {
         footer.checksum = bytebuf.read_u32_be()?;
 
         if footer.checksum != VHDFileFooter::calculate_footer_checksum(buf) {
-            log::warn!("VHD Checksum incorrect");
+            bail!(VirtualHardDiskError::InvalidChecksum);
         }
         
         // Parse the UUID
@@ -300,14 +328,44 @@ impl VirtualHardDisk {
         // Read in the entire footer
         vhd_file.read_exact(&mut trailer_buf)?;
 
-        let footer = VHDFileFooter::parse_vhd_footer(&mut trailer_buf)?;
+        // If there's no VHD cookie, treat the file as a raw sector image.
+        let (footer, image_type, data_len) = if &trailer_buf[0..8] == "conectix".as_bytes() {
+            let footer = VHDFileFooter::parse_vhd_footer(&mut trailer_buf)?;
+            (footer, DiskImageType::Vhd, metadata.len() - VHD_FOOTER_LEN as u64)
+        }
+        else {
+            let (c, h, s) = VirtualHardDisk::raw_geometry(metadata.len())?;
+            log::info!("Raw disk image detected. Geometry: c:{} h:{} s:{}", c, h, s);
+            let footer = VHDFileFooter::new(c as u16, h as u8, s as u8, Uuid::nil());
+            (footer, DiskImageType::Raw, metadata.len())
+        };
+
+        let geometry_size = 
+            footer.geometry.c as u64 
+            * footer.geometry.h as u64 
+            * footer.geometry.s as u64 
+            * VHD_SECTOR_SIZE as u64;
+
+        if geometry_size == 0 {
+            bail!(VirtualHardDiskError::UnknownGeometry);
+        }
+        if geometry_size > data_len {
+            log::warn!(
+                "Disk image geometry ({} bytes) exceeds image data size ({} bytes). Sectors past the end of the image will be unreadable.",
+                geometry_size,
+                data_len
+            );
+        }
 
         Ok(
             VirtualHardDisk {
                 vhd_file,
+                image_type,
 
                 size: metadata.len(),
+                data_len,
                 checksum: 0,
+                write_cache: BTreeMap::new(),
 
                 max_cylinders: footer.geometry.c as u32,
                 max_heads: footer.geometry.h as u32,
@@ -322,6 +380,55 @@ impl VirtualHardDisk {
         )
     }
 
+    /// Infer the geometry of a raw sector image from its size. All fixed disks of the era used 17
+    /// sectors per track; we pick the first head count from a list of common values that divides 
+    /// the image into a whole number of cylinders.
+    pub fn raw_geometry(len: u64) -> Result<(u32, u32, u32), anyhow::Error> {
+
+        if len % VHD_SECTOR_SIZE as u64 != 0 {
+            bail!(VirtualHardDiskError::InvalidLength);
+        }
+
+        let total_sectors = len / VHD_SECTOR_SIZE as u64;
+        for heads in RAW_HEAD_CANDIDATES {
+            let track_sectors = (heads * RAW_SECTORS_PER_TRACK) as u64;
+            if total_sectors % track_sectors == 0 {
+                let cylinders = total_sectors / track_sectors;
+                if cylinders > 0 && cylinders <= RAW_MAX_CYLINDERS as u64 {
+                    return Ok((cylinders as u32, heads, RAW_SECTORS_PER_TRACK));
+                }
+            }
+        }
+        bail!(VirtualHardDiskError::UnknownGeometry);
+    }
+
+    pub fn image_type(&self) -> DiskImageType {
+        self.image_type
+    }
+
+    /// Return true if there are sector writes that have not yet been flushed to the image file.
+    pub fn is_dirty(&self) -> bool {
+        !self.write_cache.is_empty()
+    }
+
+    /// Write any cached sector writes back to the image file.
+    pub fn flush(&mut self) -> Result<(), anyhow::Error> {
+
+        if self.write_cache.is_empty() {
+            return Ok(())
+        }
+
+        log::debug!("Flushing {} sectors to disk image.", self.write_cache.len());
+        for (offset, buf) in &self.write_cache {
+            self.vhd_file.seek(SeekFrom::Start(*offset))?;
+            self.vhd_file.write_all(buf).context("Error writing sector to disk image")?;
+        }
+        self.vhd_file.sync_data().context("Error syncing disk image")?;
+        self.write_cache.clear();
+
+        Ok(())
+    }
+
     /// Return a byte offset given a CHS (Cylinder, Head, Sector) address
     /// 
     /// Hard drive sectors are allowed to start at 0
@@ -335,15 +442,20 @@ impl VirtualHardDisk {
 
     pub fn read_sector(&mut self, buf: &mut [u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
 
-        let read_offset = self.get_chs_offset(cylinder, head, sector);
+        let read_offset = self.get_chs_offset(cylinder, head, sector) as u64;
 
-        let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if read_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+        if read_offset + VHD_SECTOR_SIZE as u64 > self.data_len {
             // Read requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
 
-        self.vhd_file.seek(SeekFrom::Start(read_offset as u64))?;
+        // Return sector from write cache if it hasn't been flushed yet.
+        if let Some(cached) = self.write_cache.get(&read_offset) {
+            buf.copy_from_slice(cached);
+            return Ok(())
+        }
+
+        self.vhd_file.seek(SeekFrom::Start(read_offset))?;
 
         self.vhd_file.read_exact(buf).context("Error reading sector from VHD")?;
 
@@ -352,27 +464,43 @@ impl VirtualHardDisk {
 
     pub fn write_sector(&mut self, buf: &[u8], cylinder: u16, head: u8, sector: u8) -> Result<(), anyhow::Error> {
 
-        let write_offset = self.get_chs_offset(cylinder, head, sector);
+        let write_offset = self.get_chs_offset(cylinder, head, sector) as u64;
 
-        let metadata = self.vhd_file.metadata().context("Couldn't get VHD file metadata")?;
-        if write_offset as u64 > metadata.len() - VHD_FOOTER_LEN as u64 - VHD_SECTOR_SIZE as u64 {
+        if write_offset + VHD_SECTOR_SIZE as u64 > self.data_len {
             // Write requested past last sector in file
             bail!(VirtualHardDiskError::InvalidSeek);
         }
 
-        self.vhd_file.seek(SeekFrom::Start(write_offset as u64))?;
-
-        let write_len = self.vhd_file.write(buf)?;
-        if write_len != VHD_SECTOR_SIZE {
+        let mut sector_buf = buf.to_vec();
+        if sector_buf.len() != VHD_SECTOR_SIZE {
             log::error!("Incomplete VHD Sector Write!");
+            sector_buf.resize(VHD_SECTOR_SIZE, 0);
         }
 
+        // Hold the write in the cache until the next flush.
+        self.write_cache.insert(write_offset, sector_buf);
+
         Ok(())
     }    
 
 }
 
+impl Drop for VirtualHardDisk {
+    fn drop(&mut self) {
+        // Don't lose any pending writes when the disk is unmounted or the emulator exits.
+        if let Err(e) = self.flush() {
+            log::error!("Failed to flush disk image: {}", e);
+        }
+    }
+}
+
 pub fn create_vhd(filename: OsString, c: u16, h: u8, s: u8 ) -> Result<File, anyhow::Error> {
+    create_vhd_from(filename, c, h, s, &[])
+}
+
+/// Create a VHD of the specified geometry with its first sectors initialized from 'data'.
+/// The remainder of the disk is filled with zeros.
+pub fn create_vhd_from(filename: OsString, c: u16, h: u8, s: u8, data: &[u8]) -> Result<File, anyhow::Error> {
 
     assert_eq!(VHD_FOOTER_LEN, VHD_SECTOR_SIZE);
 
@@ -390,19 +518,115 @@ pub fn create_vhd(filename: OsString, c: u16, h: u8, s: u8 ) -> Result<File, any
 
     let mut write_buf = vec![0; VHD_SECTOR_SIZE];
     
-    // Write all 0's by sector buf size
+    // Write the provided data, then all 0's by sector buf size
     let n_sectors = c as u32 * h as u32 * s as u32;
+    let mut data_chunks = data.chunks(VHD_SECTOR_SIZE);
 
     for _ in 0..n_sectors {
+        write_buf.fill(0);
+        if let Some(chunk) = data_chunks.next() {
+            write_buf[0..chunk.len()].copy_from_slice(chunk);
+        }
         vhd_file.write(&write_buf).context("Error writing VHD file to disk.")?;
     }
 
     let footer = VHDFileFooter::new(c, h, s, uuid);
 
     // Since the length of a VHD footer == a sector size, re-use sector buf
+    write_buf.fill(0);
     VHDFileFooter::make_vhd_footer_bytes(&mut write_buf, footer);
 
     vhd_file.write(&write_buf).context("Error writing VHD footer to disk.")?;
 
     Ok(vhd_file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+
+    fn temp_vhd_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("martypc_{}_{}.vhd", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn open_rw(path: &PathBuf) -> File {
+        OpenOptions::new().read(true).write(true).open(path).unwrap()
+    }
+
+    fn footer_bytes(c: u16, h: u8, s: u8) -> Vec<u8> {
+        let mut buf = vec![0u8; VHD_FOOTER_LEN];
+        VHDFileFooter::make_vhd_footer_bytes(&mut buf, VHDFileFooter::new(c, h, s, Uuid::new_v4()));
+        buf
+    }
+
+    #[test]
+    fn test_parse_footer() {
+        let buf = footer_bytes(612, 4, 17);
+        let footer = VHDFileFooter::parse_vhd_footer(&buf).unwrap();
+
+        assert_eq!(footer.geometry.c, 612);
+        assert_eq!(footer.geometry.h, 4);
+        assert_eq!(footer.geometry.s, 17);
+        assert_eq!(footer.current_size, 612 * 4 * 17 * VHD_SECTOR_SIZE as u64);
+        assert_eq!(footer.checksum, VHDFileFooter::calculate_footer_checksum(&buf));
+    }
+
+    #[test]
+    fn test_parse_footer_bad_cookie() {
+        let mut buf = footer_bytes(612, 4, 17);
+        buf[0..8].copy_from_slice("cnectix!".as_bytes());
+
+        let err = VHDFileFooter::parse_vhd_footer(&buf).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(VirtualHardDiskError::InvalidFooter)));
+    }
+
+    #[test]
+    fn test_parse_footer_bad_checksum() {
+        let mut buf = footer_bytes(612, 4, 17);
+        // Corrupt the geometry without updating the checksum.
+        buf[0x38] ^= 0x01;
+
+        let err = VHDFileFooter::parse_vhd_footer(&buf).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(VirtualHardDiskError::InvalidChecksum)));
+    }
+
+    #[test]
+    fn test_write_sector_persists() {
+        let path = temp_vhd_path("vhd_write");
+        create_vhd_from(path.clone().into_os_string(), 2, 2, 17, &[0xAA; VHD_SECTOR_SIZE]).unwrap();
+
+        let pattern: Vec<u8> = (0..VHD_SECTOR_SIZE).map(|i| i as u8).collect();
+        let mut buf = vec![0u8; VHD_SECTOR_SIZE];
+        {
+            let mut vhd = VirtualHardDisk::from_file(open_rw(&path)).unwrap();
+            assert_eq!(vhd.image_type(), DiskImageType::Vhd);
+            assert_eq!((vhd.max_cylinders, vhd.max_heads, vhd.max_sectors), (2, 2, 17));
+
+            vhd.read_sector(&mut buf, 0, 0, 0).unwrap();
+            assert_eq!(buf, vec![0xAA; VHD_SECTOR_SIZE]);
+
+            vhd.write_sector(&pattern, 1, 1, 16).unwrap();
+            assert!(vhd.is_dirty());
+            vhd.read_sector(&mut buf, 1, 1, 16).unwrap();
+            assert_eq!(buf, pattern);
+
+            // Writes past the end of the disk are rejected.
+            assert!(vhd.write_sector(&pattern, 2, 0, 0).is_err());
+            // Dropping the disk flushes the pending write.
+        }
+
+        let mut vhd = VirtualHardDisk::from_file(open_rw(&path)).unwrap();
+        assert!(!vhd.is_dirty());
+        vhd.read_sector(&mut buf, 1, 1, 16).unwrap();
+        assert_eq!(buf, pattern);
+        vhd.read_sector(&mut buf, 0, 0, 0).unwrap();
+        assert_eq!(buf, vec![0xAA; VHD_SECTOR_SIZE]);
+
+        drop(vhd);
+        fs::remove_file(&path).unwrap();
+    }
+}
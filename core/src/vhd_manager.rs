@@ -92,7 +92,7 @@ impl VHDManager {
             Err(_) => return Err(VHDManagerError::DirNotFound)
         };
 
-        let extensions = ["vhd"];
+        let extensions = ["vhd", "img"];
 
         // Scan through all entries in the directory
         for entry in dir {
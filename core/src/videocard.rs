@@ -95,6 +95,7 @@ use std::path::Path;
 use crate::config::VideoType;
 
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum VideoCardStateEntry {
     Value8(u8),
     Value16(u16),
@@ -106,7 +107,7 @@ pub type VideoCardState = HashMap<String, Vec<(String, VideoCardStateEntry)>>;
 
 /// All valid graphics modes for CGA, EGA and VGA Cards
 #[allow (dead_code)] 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum DisplayMode {
     Disabled,
     Mode0TextBw40,
@@ -141,10 +142,27 @@ pub struct CursorInfo {
     pub visible: bool
 }
 
-pub struct FontInfo {
+/// The state of the text mode blink attribute.
+#[derive(Copy, Clone, Debug)]
+pub struct BlinkInfo {
+    /// Attribute bit 7 blinks the character. When false, it selects a bright background instead.
+    pub enabled: bool,
+    /// Blinking characters are in the visible phase of their blink cycle.
+    pub visible: bool
+}
+
+/// The contents of the active text mode page.
+pub struct TextScreen {
+    pub columns: u32,
+    pub rows: u32,
+    pub chars: Vec<u8>,
+    pub attrs: Vec<u8>
+}
+
+pub struct FontInfo<'a> {
     pub w: u32,
     pub h: u32,
-    pub font_data: &'static [u8]
+    pub font_data: &'a [u8]
 }
 
 pub enum CGAPalette {
@@ -241,6 +259,10 @@ pub trait VideoCard {
     /// Return a bool determining whether we double scanlines for this device (for CGA mostly)
     fn get_scanline_double(&self) -> bool;
 
+    /// Return whether the adapter generated an NTSC color burst on its composite output for 
+    /// the displayed frame. Composite monitors only decode color when a color burst is present.
+    fn get_color_burst(&self) -> bool;
+
     /// Get the current refresh rate from the adapter. Different adapters might
     /// support different refresh rates, even per mode.
     fn get_refresh_rate(&self) -> u32;
@@ -257,8 +279,11 @@ pub trait VideoCard {
     /// Returns a CursorInfo struct describing the current state of the text mode cursor.
     fn get_cursor_info(&self) -> CursorInfo;
 
+    /// Returns a BlinkInfo struct describing the current state of the text mode blink attribute.
+    fn get_blink_info(&self) -> BlinkInfo;
+
     /// Return a FontInfo struct describing the currently selected font
-    fn get_current_font(&self) -> FontInfo;
+    fn get_current_font(&self) -> FontInfo<'_>;
 
     /// Returns the currently programmed character height
     /// (CRTC Maximum Scanline + 1)
@@ -290,12 +315,28 @@ pub trait VideoCard {
     /// Read pixel color value as RGBA
     fn get_pixel(&self, x: u32, y: u32) -> &[u8];
 
-    /// Return the specified bitplane as a slice
+    /// Return the specified bitplane as a slice. Adapters without bitplanes return their
+    /// entire video memory as plane 0.
     fn get_plane_slice(&self, plane: usize) -> &[u8];
 
     /// Return the number of frames the video device has rendered
     fn get_frame_count(&self) -> u64;
 
+    /// Hold the blink phase of the cursor and blinking text, for stable screenshots.
+    /// Adapters that do not emulate blinking in hardware may ignore this.
+    fn set_blink_frozen(&mut self, frozen: bool);
+
+    /// Start or stop recording CRTC register writes, for validating timing-sensitive effects.
+    /// Adapters that do not support logging may ignore this.
+    fn set_crtc_logging(&mut self, enabled: bool);
+
+    /// Return and clear the CRTC register writes recorded so far, as (scanline, register, value).
+    fn take_crtc_log(&mut self) -> Vec<(u32, u8, u8)>;
+
+    /// Return the characters and attributes of the active text page, or None if the card is in
+    /// a graphics mode.
+    fn get_text_screen(&self) -> Option<TextScreen>;
+
     /// Dump graphics memory to disk
     fn dump_mem(&self, path: &Path);
 
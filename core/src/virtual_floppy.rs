@@ -0,0 +1,726 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    virtual_floppy.rs
+
+    Builds a FAT12 floppy image from a directory on the host, so that files
+    can be transferred into the guest without disk image tools.
+
+    Host file names are converted to 8.3 names; names that don't fit are 
+    shortened DOS-style (LONGNA~1.TXT). Subdirectories are included up to a
+    limited depth. File times are converted as UTC.
+
+    If write-back is enabled, the guest filesystem is read back from the 
+    image whenever the floppy controller flushes the disk, and new or 
+    changed files are written to the host directory. Files that were 
+    imported from the host, or written back, and are then deleted in the 
+    guest are moved to a trash directory inside the host directory, so that
+    formatting or wiping the disk in the guest can't destroy host files.
+    No other host files are touched.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH
+};
+
+use crate::config::FloppyDriveType;
+use crate::devices::rtc_card::DateTime;
+use crate::disk_image::{self, FloppyFormat, SECTOR_SIZE};
+
+const DIR_ENTRY_SIZE: usize = 32;
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_LABEL: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ENTRY_DELETED: u8 = 0xE5;
+const ENTRY_END: u8 = 0x00;
+
+const FAT12_MAX_CLUSTERS: usize = 4085;
+const FAT12_END_OF_CHAIN: u16 = 0xFFF;
+
+// Limit the depth of the directory scan in case of symlink loops.
+const MAX_DIR_DEPTH: usize = 8;
+
+/// Directory within the host directory that receives files deleted in the guest. It isn't
+/// a valid 8.3 name, so the guest can't create it, and it's left out of the image.
+pub const TRASH_DIR: &str = ".martypc_trash";
+
+#[derive(Debug)]
+pub enum VirtualFloppyError {
+    DirNotFound,
+    DirReadError,
+    TooManyFiles,
+    DiskFull,
+    BadFilesystem,
+    FileWriteError(PathBuf),
+}
+impl Error for VirtualFloppyError {}
+impl Display for VirtualFloppyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VirtualFloppyError::DirNotFound => write!(f, "Couldn't find the requested directory."),
+            VirtualFloppyError::DirReadError => write!(f, "A directory read error occurred."),
+            VirtualFloppyError::TooManyFiles => write!(f, "The directory has too many entries for the root directory of the disk."),
+            VirtualFloppyError::DiskFull => write!(f, "The directory contents don't fit on the disk."),
+            VirtualFloppyError::BadFilesystem => write!(f, "The disk doesn't contain a FAT12 filesystem."),
+            VirtualFloppyError::FileWriteError(path) => write!(f, "Couldn't write file: {}", path.display()),
+        }
+    }
+}
+
+/// Return the largest floppy format the specified drive type accepts.
+pub fn format_for_drive(drive_type: Option<FloppyDriveType>) -> FloppyFormat {
+    match drive_type {
+        Some(FloppyDriveType::Floppy360K) => FloppyFormat::Floppy360K,
+        Some(FloppyDriveType::Floppy720K) => FloppyFormat::Floppy720K,
+        Some(FloppyDriveType::Floppy12M) => FloppyFormat::Floppy1200K,
+        Some(FloppyDriveType::Floppy144M) | None => FloppyFormat::Floppy1440K,
+    }
+}
+
+/// The parameters of a FAT12 volume, read from its BIOS Parameter Block.
+#[derive(Copy, Clone, Debug)]
+struct Bpb {
+    sectors_per_cluster: usize,
+    reserved_sectors: usize,
+    fat_count: usize,
+    root_entries: usize,
+    total_sectors: usize,
+    fat_sectors: usize,
+}
+
+impl Bpb {
+    fn parse(image: &[u8]) -> Option<Bpb> {
+        if image.len() < SECTOR_SIZE {
+            return None;
+        }
+        let word = |offset: usize| u16::from_le_bytes([image[offset], image[offset + 1]]) as usize;
+
+        let bpb = Bpb {
+            sectors_per_cluster: image[13] as usize,
+            reserved_sectors: word(14),
+            fat_count: image[16] as usize,
+            root_entries: word(17),
+            total_sectors: word(19),
+            fat_sectors: word(22),
+        };
+
+        let valid = word(11) == SECTOR_SIZE
+            && bpb.sectors_per_cluster.is_power_of_two()
+            && bpb.reserved_sectors > 0
+            && bpb.fat_count > 0
+            && bpb.fat_sectors > 0
+            && bpb.root_entries > 0
+            && bpb.total_sectors * SECTOR_SIZE <= image.len()
+            && bpb.data_offset() < bpb.total_sectors * SECTOR_SIZE
+            && bpb.cluster_count() < FAT12_MAX_CLUSTERS
+            // Each FAT must be large enough to hold every cluster's entry.
+            && (bpb.cluster_count() + 2) * 3 / 2 < bpb.fat_sectors * SECTOR_SIZE;
+
+        valid.then_some(bpb)
+    }
+
+    fn fat_offset(&self) -> usize {
+        self.reserved_sectors * SECTOR_SIZE
+    }
+
+    fn root_offset(&self) -> usize {
+        (self.reserved_sectors + self.fat_count * self.fat_sectors) * SECTOR_SIZE
+    }
+
+    fn data_offset(&self) -> usize {
+        self.root_offset() + (self.root_entries * DIR_ENTRY_SIZE).div_ceil(SECTOR_SIZE) * SECTOR_SIZE
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster * SECTOR_SIZE
+    }
+
+    fn cluster_count(&self) -> usize {
+        (self.total_sectors * SECTOR_SIZE).saturating_sub(self.data_offset()) / self.cluster_size()
+    }
+
+    fn cluster_offset(&self, cluster: u16) -> usize {
+        self.data_offset() + (cluster as usize - 2) * self.cluster_size()
+    }
+
+    fn is_data_cluster(&self, cluster: u16) -> bool {
+        cluster >= 2 && (cluster as usize) < self.cluster_count() + 2
+    }
+
+    fn fat_entry(&self, image: &[u8], cluster: u16) -> u16 {
+        let offset = self.fat_offset() + cluster as usize * 3 / 2;
+        let value = u16::from_le_bytes([image[offset], image[offset + 1]]);
+        if cluster & 1 == 1 { value >> 4 } else { value & 0xFFF }
+    }
+
+    fn set_fat_entry(&self, image: &mut [u8], cluster: u16, value: u16) {
+        for fat in 0..self.fat_count {
+            let offset = self.fat_offset() + fat * self.fat_sectors * SECTOR_SIZE + cluster as usize * 3 / 2;
+            let old = u16::from_le_bytes([image[offset], image[offset + 1]]);
+            let new = if cluster & 1 == 1 {
+                (old & 0x000F) | (value << 4)
+            }
+            else {
+                (old & 0xF000) | (value & 0xFFF)
+            };
+            image[offset..offset + 2].copy_from_slice(&new.to_le_bytes());
+        }
+    }
+
+    /// Return the clusters of the chain starting at the specified cluster. A chain 
+    /// that loops or leaves the data area is cut short.
+    fn chain(&self, image: &[u8], first: u16) -> Vec<u16> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while self.is_data_cluster(cluster) && clusters.len() < self.cluster_count() {
+            clusters.push(cluster);
+            cluster = self.fat_entry(image, cluster);
+        }
+        clusters
+    }
+
+    fn read_chain(&self, image: &[u8], first: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        for cluster in self.chain(image, first) {
+            let offset = self.cluster_offset(cluster);
+            data.extend_from_slice(&image[offset..offset + self.cluster_size()]);
+        }
+        data
+    }
+}
+
+/// A directory entry read from the guest filesystem.
+struct GuestEntry {
+    name: String,
+    attr: u8,
+    cluster: u16,
+    size: usize,
+}
+
+fn parse_dir_entries(dir: &[u8]) -> Vec<GuestEntry> {
+    let mut entries = Vec::new();
+    for raw in dir.chunks_exact(DIR_ENTRY_SIZE) {
+        match raw[0] {
+            ENTRY_END => break,
+            ENTRY_DELETED | b'.' => continue,
+            _ => {}
+        }
+        let attr = raw[11];
+        if attr & ATTR_LONG_NAME == ATTR_LONG_NAME || attr & ATTR_VOLUME_LABEL != 0 {
+            continue;
+        }
+
+        let base = String::from_utf8_lossy(&raw[0..8]).trim_end().to_string();
+        let ext = String::from_utf8_lossy(&raw[8..11]).trim_end().to_string();
+        let name = if ext.is_empty() { base } else { format!("{}.{}", base, ext) };
+        // Don't let a guest name escape the host directory.
+        if name.is_empty() || name.contains(['/', '\\', ':']) {
+            continue;
+        }
+
+        entries.push(GuestEntry {
+            name,
+            attr,
+            cluster: u16::from_le_bytes([raw[26], raw[27]]),
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]) as usize,
+        });
+    }
+    entries
+}
+
+/// A file or directory scanned from the host, to be placed on the disk.
+struct HostNode {
+    short_name: [u8; 11],
+    guest_path: String,
+    host_path: PathBuf,
+    attr: u8,
+    time: (u16, u16),
+    data: Vec<u8>,
+    children: Vec<HostNode>,
+}
+
+/// Convert a host file name into an 8.3 name that is unique among 'used'. 
+fn make_short_name(name: &str, used: &HashSet<[u8; 11]>) -> Option<[u8; 11]> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c);
+    let clean = |s: &str| s.chars().filter(|c| valid(*c)).map(|c| c.to_ascii_uppercase()).collect::<String>();
+
+    let (base, ext) = match name.rfind('.') {
+        Some(pos) if pos > 0 => (&name[..pos], &name[pos + 1..]),
+        _ => (name, "")
+    };
+    let (clean_base, clean_ext) = (clean(base), clean(ext));
+    let fits = clean_base.len() == base.len() 
+        && clean_ext.len() == ext.len() 
+        && (1..=8).contains(&base.len()) 
+        && ext.len() <= 3;
+
+    let to_raw = |base: &str, ext: &str| {
+        let mut raw = [b' '; 11];
+        raw[..base.len()].copy_from_slice(base.as_bytes());
+        raw[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        raw
+    };
+
+    let ext: String = clean_ext.chars().take(3).collect();
+    if fits {
+        let raw = to_raw(&clean_base, &ext);
+        if !used.contains(&raw) {
+            return Some(raw);
+        }
+    }
+
+    let stem = if clean_base.is_empty() { "FILE".to_string() } else { clean_base };
+    for n in 1..=9999 {
+        let tail = format!("~{}", n);
+        let head: String = stem.chars().take(8 - tail.len()).collect();
+        let raw = to_raw(&format!("{}{}", head, tail), &ext);
+        if !used.contains(&raw) {
+            return Some(raw);
+        }
+    }
+    None
+}
+
+/// Convert a host file time into a DOS (date, time) pair.
+fn dos_time(path: &Path) -> (u16, u16) {
+    let seconds = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let dt = DateTime::from_unix_seconds(seconds);
+    if dt.year < 1980 {
+        // DOS can't represent dates before 1980; use 1980-01-01.
+        return ((1 << 5) | 1, 0);
+    }
+    let date = (((dt.year - 1980).min(127) as u16) << 9) | ((dt.month as u16) << 5) | dt.day as u16;
+    let time = ((dt.hour as u16) << 11) | ((dt.minute as u16) << 5) | (dt.second as u16 / 2);
+    (date, time)
+}
+
+fn scan_host_dir(dir: &Path, guest_prefix: &str, depth: usize) -> Result<Vec<HostNode>, VirtualFloppyError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|_| VirtualFloppyError::DirReadError)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    paths.sort();
+
+    let mut used = HashSet::new();
+    let mut nodes = Vec::new();
+    for path in paths {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        if depth == 0 && name == TRASH_DIR {
+            continue;
+        }
+        let short_name = make_short_name(&name, &used).ok_or(VirtualFloppyError::TooManyFiles)?;
+
+        let guest_name = String::from_utf8_lossy(&short_name[..8]).trim_end().to_string();
+        let ext = String::from_utf8_lossy(&short_name[8..]).trim_end().to_string();
+        let guest_name = if ext.is_empty() { guest_name } else { format!("{}.{}", guest_name, ext) };
+        let guest_path = format!("{}{}", guest_prefix, guest_name);
+
+        let node = if path.is_dir() {
+            if depth >= MAX_DIR_DEPTH {
+                continue;
+            }
+            HostNode {
+                short_name,
+                children: scan_host_dir(&path, &format!("{}/", guest_path), depth + 1)?,
+                guest_path,
+                time: dos_time(&path),
+                host_path: path,
+                attr: ATTR_DIRECTORY,
+                data: Vec::new(),
+            }
+        }
+        else if path.is_file() {
+            let read_only = fs::metadata(&path).map(|m| m.permissions().readonly()).unwrap_or(false);
+            HostNode {
+                short_name,
+                guest_path,
+                time: dos_time(&path),
+                data: fs::read(&path).map_err(|_| VirtualFloppyError::DirReadError)?,
+                host_path: path,
+                attr: ATTR_ARCHIVE | if read_only { ATTR_READ_ONLY } else { 0 },
+                children: Vec::new(),
+            }
+        }
+        else {
+            continue;
+        };
+        used.insert(short_name);
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// Places scanned host files into a freshly formatted image.
+struct ImageBuilder<'a> {
+    image: &'a mut [u8],
+    bpb: Bpb,
+    next_cluster: u16,
+    host_paths: HashMap<String, PathBuf>,
+}
+
+impl ImageBuilder<'_> {
+    /// Allocate a chain of clusters large enough for 'len' bytes, and write 'data' into it.
+    /// Returns the first cluster, or 0 for an empty file.
+    fn write_chain(&mut self, data: &[u8], len: usize) -> Result<u16, VirtualFloppyError> {
+        let count = len.div_ceil(self.bpb.cluster_size());
+        if count == 0 {
+            return Ok(0);
+        }
+        let first = self.next_cluster;
+        if first as usize + count > self.bpb.cluster_count() + 2 {
+            return Err(VirtualFloppyError::DiskFull);
+        }
+        for i in 0..count {
+            let cluster = first + i as u16;
+            let next = if i + 1 == count { FAT12_END_OF_CHAIN } else { cluster + 1 };
+            self.bpb.set_fat_entry(self.image, cluster, next);
+        }
+        let offset = self.bpb.cluster_offset(first);
+        self.image[offset..offset + count * self.bpb.cluster_size()].fill(0);
+        self.image[offset..offset + data.len()].copy_from_slice(data);
+        self.next_cluster += count as u16;
+        Ok(first)
+    }
+
+    /// Place the specified nodes on the disk and return their directory entries.
+    fn place(&mut self, nodes: &[HostNode], parent_cluster: u16) -> Result<Vec<u8>, VirtualFloppyError> {
+        let mut entries = Vec::new();
+        for node in nodes {
+            let (cluster, size) = if node.attr & ATTR_DIRECTORY != 0 {
+                // Reserve the directory's clusters first, so its entries can refer to it.
+                let len = (node.children.len() + 2) * DIR_ENTRY_SIZE;
+                let cluster = self.write_chain(&[], len)?;
+                let mut dir = dir_entry(b".          ", ATTR_DIRECTORY, node.time, cluster, 0);
+                dir.extend(dir_entry(b"..         ", ATTR_DIRECTORY, node.time, parent_cluster, 0));
+                dir.extend(self.place(&node.children, cluster)?);
+                let offset = self.bpb.cluster_offset(cluster);
+                self.image[offset..offset + dir.len()].copy_from_slice(&dir);
+                (cluster, 0)
+            }
+            else {
+                (self.write_chain(&node.data, node.data.len())?, node.data.len())
+            };
+            entries.extend(dir_entry(&node.short_name, node.attr, node.time, cluster, size));
+            self.host_paths.insert(node.guest_path.clone(), node.host_path.clone());
+        }
+        Ok(entries)
+    }
+}
+
+fn dir_entry(name: &[u8; 11], attr: u8, (date, time): (u16, u16), cluster: u16, size: usize) -> Vec<u8> {
+    let mut entry = vec![0; DIR_ENTRY_SIZE];
+    entry[0..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&(size as u32).to_le_bytes());
+    entry
+}
+
+/// A floppy disk built from a host directory.
+#[derive(Debug)]
+pub struct VirtualFloppy {
+    dir: PathBuf,
+    write_back: bool,
+    /// Host paths of the guest files and directories, by guest path ("DIR/FILE.TXT")
+    host_paths: HashMap<String, PathBuf>,
+}
+
+impl VirtualFloppy {
+    /// Build an image of the specified format from the contents of 'dir'. Returns the 
+    /// VirtualFloppy, which writes changes back to 'dir' if 'write_back' is set, and the image.
+    pub fn build(dir: &Path, format: FloppyFormat, write_back: bool) -> Result<(VirtualFloppy, Vec<u8>), VirtualFloppyError> {
+        if !dir.is_dir() {
+            return Err(VirtualFloppyError::DirNotFound);
+        }
+        let nodes = scan_host_dir(dir, "", 0)?;
+
+        let mut image = disk_image::create_floppy_image(format, true);
+        let bpb = Bpb::parse(&image).ok_or(VirtualFloppyError::BadFilesystem)?;
+        if nodes.len() > bpb.root_entries {
+            return Err(VirtualFloppyError::TooManyFiles);
+        }
+
+        let mut builder = ImageBuilder {
+            image: &mut image,
+            bpb,
+            next_cluster: 2,
+            host_paths: HashMap::new(),
+        };
+        let root = builder.place(&nodes, 0)?;
+        let host_paths = builder.host_paths;
+        let root_offset = bpb.root_offset();
+        image[root_offset..root_offset + root.len()].copy_from_slice(&root);
+
+        log::debug!("Built virtual floppy from {} with {} entries", dir.display(), host_paths.len());
+        Ok((
+            VirtualFloppy {
+                dir: dir.to_path_buf(),
+                write_back,
+                host_paths,
+            },
+            image
+        ))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn write_back(&self) -> bool {
+        self.write_back
+    }
+
+    /// Write files that were created or changed in the guest back to the host directory, and 
+    /// move files known to this VirtualFloppy that were deleted in the guest to the trash 
+    /// directory. Returns the number of host files changed. Does nothing if write-back is disabled.
+    pub fn sync(&mut self, image: &[u8]) -> Result<usize, VirtualFloppyError> {
+        if !self.write_back {
+            return Ok(0);
+        }
+        let bpb = Bpb::parse(image).ok_or(VirtualFloppyError::BadFilesystem)?;
+        let root = &image[bpb.root_offset()..bpb.data_offset()];
+
+        let mut seen = HashMap::new();
+        let mut changed = 0;
+        self.sync_dir(image, &bpb, root, "", &self.dir.clone(), 0, &mut seen, &mut changed)?;
+
+        // Move files that no longer exist in the guest to the trash, deepest first so directories
+        // are empty. Directories are only removed once empty, so host files the guest never saw
+        // are kept.
+        let mut removed: Vec<(&String, &PathBuf)> = self.host_paths.iter()
+            .filter(|(guest_path, _)| !seen.contains_key(*guest_path))
+            .collect();
+        removed.sort_by_key(|(guest_path, _)| std::cmp::Reverse(guest_path.matches('/').count()));
+        for (_, host_path) in removed {
+            let result = if host_path.is_dir() { 
+                fs::remove_dir(host_path).map(|_| host_path.clone())
+            } 
+            else { 
+                self.move_to_trash(host_path) 
+            };
+            match result {
+                Ok(path) => {
+                    log::debug!("Virtual floppy: removed {}, moved to {}", host_path.display(), path.display());
+                    changed += 1;
+                }
+                Err(e) => log::warn!("Virtual floppy: couldn't remove {}: {}", host_path.display(), e),
+            }
+        }
+
+        self.host_paths = seen;
+        Ok(changed)
+    }
+
+    /// Move a host file into the trash directory, keeping its path relative to the host 
+    /// directory. A file already in the trash under the same name isn't overwritten; a numeric
+    /// suffix is added instead. Returns the new path of the file.
+    fn move_to_trash(&self, host_path: &Path) -> std::io::Result<PathBuf> {
+        let relative = host_path.strip_prefix(&self.dir).unwrap_or(host_path);
+        let mut trash_path = self.dir.join(TRASH_DIR).join(relative);
+        if let Some(parent) = trash_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file_name = trash_path.file_name().unwrap_or_default().to_os_string();
+        let mut i = 1;
+        while trash_path.exists() {
+            let mut name = file_name.clone();
+            name.push(format!(".{}", i));
+            trash_path.set_file_name(name);
+            i += 1;
+        }
+        fs::rename(host_path, &trash_path)?;
+        Ok(trash_path)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sync_dir(
+        &self,
+        image: &[u8],
+        bpb: &Bpb,
+        dir: &[u8],
+        guest_prefix: &str,
+        host_dir: &Path,
+        depth: usize,
+        seen: &mut HashMap<String, PathBuf>,
+        changed: &mut usize
+    ) -> Result<(), VirtualFloppyError> {
+        for entry in parse_dir_entries(dir) {
+            let guest_path = format!("{}{}", guest_prefix, entry.name);
+            let host_path = self.host_paths.get(&guest_path)
+                .cloned()
+                .unwrap_or_else(|| host_dir.join(&entry.name));
+
+            if entry.attr & ATTR_DIRECTORY != 0 {
+                if depth >= MAX_DIR_DEPTH || !bpb.is_data_cluster(entry.cluster) {
+                    continue;
+                }
+                if !host_path.is_dir() {
+                    fs::create_dir_all(&host_path).map_err(|_| VirtualFloppyError::FileWriteError(host_path.clone()))?;
+                    *changed += 1;
+                }
+                let sub_dir = bpb.read_chain(image, entry.cluster);
+                let prefix = format!("{}/", guest_path);
+                self.sync_dir(image, bpb, &sub_dir, &prefix, &host_path, depth + 1, seen, changed)?;
+            }
+            else {
+                let mut data = bpb.read_chain(image, entry.cluster);
+                // A file whose chain is shorter than its size is still being written; 
+                // leave it to the next flush.
+                if data.len() < entry.size {
+                    seen.insert(guest_path, host_path);
+                    continue;
+                }
+                data.truncate(entry.size);
+                if fs::read(&host_path).ok().as_deref() != Some(&data[..]) {
+                    fs::write(&host_path, &data).map_err(|_| VirtualFloppyError::FileWriteError(host_path.clone()))?;
+                    log::debug!("Virtual floppy: wrote {}", host_path.display());
+                    *changed += 1;
+                }
+            }
+            seen.insert(guest_path, host_path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("martypc_vfloppy_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn build_image_from_dir() {
+        let dir = temp_dir("build");
+        fs::write(dir.join("readme.txt"), b"hello").unwrap();
+        fs::write(dir.join("a long file name.text"), vec![0x55; 3000]).unwrap();
+        fs::write(dir.join("sub").join("inner.bin"), b"inner").unwrap();
+
+        let (vf, image) = VirtualFloppy::build(&dir, FloppyFormat::Floppy360K, false).unwrap();
+        assert_eq!(image.len(), FloppyFormat::Floppy360K.size());
+        assert_eq!(vf.host_paths.len(), 4);
+
+        let bpb = Bpb::parse(&image).unwrap();
+        let root = parse_dir_entries(&image[bpb.root_offset()..bpb.data_offset()]);
+        let names: Vec<&str> = root.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["ALONGF~1.TEX", "README.TXT", "SUB"]);
+
+        let long = &root[0];
+        assert_eq!(long.size, 3000);
+        assert_eq!(bpb.chain(&image, long.cluster).len(), 3);
+        assert_eq!(&bpb.read_chain(&image, root[1].cluster)[..5], b"hello");
+
+        let sub = parse_dir_entries(&bpb.read_chain(&image, root[2].cluster));
+        assert_eq!(sub[0].name, "INNER.BIN");
+        assert_eq!(&bpb.read_chain(&image, sub[0].cluster)[..5], b"inner");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_back_changes() {
+        let dir = temp_dir("sync");
+        fs::write(dir.join("keep.txt"), b"keep").unwrap();
+        fs::write(dir.join("delete.txt"), b"delete").unwrap();
+        fs::write(dir.join("unrelated.dat"), b"x").unwrap();
+
+        let (mut vf, mut image) = VirtualFloppy::build(&dir, FloppyFormat::Floppy720K, true).unwrap();
+        // Files created on the host after mounting aren't touched.
+        fs::write(dir.join("later.txt"), b"later").unwrap();
+        let bpb = Bpb::parse(&image).unwrap();
+
+        // Delete DELETE.TXT, the first root entry, and rewrite KEEP.TXT.
+        let root = bpb.root_offset();
+        image[root] = ENTRY_DELETED;
+        let keep = parse_dir_entries(&image[root..bpb.data_offset()]).remove(0);
+        let offset = bpb.cluster_offset(keep.cluster);
+        image[offset..offset + 4].copy_from_slice(b"KEEP");
+
+        assert_eq!(vf.sync(&image).unwrap(), 2);
+        assert_eq!(fs::read(dir.join("keep.txt")).unwrap(), b"KEEP");
+        assert!(!dir.join("delete.txt").exists());
+        assert_eq!(fs::read(dir.join(TRASH_DIR).join("delete.txt")).unwrap(), b"delete");
+        assert!(dir.join("unrelated.dat").exists());
+        assert!(dir.join("later.txt").exists());
+        assert_eq!(vf.sync(&image).unwrap(), 0);
+
+        // The trash isn't included in the next image.
+        let (vf, _) = VirtualFloppy::build(&dir, FloppyFormat::Floppy720K, true).unwrap();
+        assert!(!vf.host_paths.contains_key("DELETE.TXT"));
+        assert!(!vf.host_paths.keys().any(|guest_path| guest_path.contains("TRASH")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn guest_format_keeps_host_files() {
+        let dir = temp_dir("format");
+        fs::write(dir.join("data.txt"), b"data").unwrap();
+        fs::write(dir.join("sub").join("inner.bin"), b"inner").unwrap();
+        fs::create_dir_all(dir.join(TRASH_DIR)).unwrap();
+        fs::write(dir.join(TRASH_DIR).join("data.txt"), b"old").unwrap();
+
+        let (mut vf, _) = VirtualFloppy::build(&dir, FloppyFormat::Floppy360K, true).unwrap();
+        // Files created on the host after mounting stay where they are.
+        fs::write(dir.join("sub").join("later.txt"), b"later").unwrap();
+
+        // The guest formats the disk, leaving an empty filesystem.
+        let image = disk_image::create_floppy_image(FloppyFormat::Floppy360K, true);
+        assert_eq!(vf.sync(&image).unwrap(), 2);
+
+        let trash = dir.join(TRASH_DIR);
+        assert!(!dir.join("data.txt").exists());
+        assert_eq!(fs::read(trash.join("data.txt")).unwrap(), b"old");
+        assert_eq!(fs::read(trash.join("data.txt.1")).unwrap(), b"data");
+        assert_eq!(fs::read(trash.join("sub").join("inner.bin")).unwrap(), b"inner");
+        // SUB still holds a host file, so it's kept.
+        assert_eq!(fs::read(dir.join("sub").join("later.txt")).unwrap(), b"later");
+        assert!(vf.host_paths.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
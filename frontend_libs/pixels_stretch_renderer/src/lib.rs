@@ -44,34 +44,9 @@ pub struct SurfaceSize {
 }
 
 use ultraviolet::Mat4;
-use wgpu::{
-    TextureDescriptor,
-    util::DeviceExt
-};
+use wgpu::util::DeviceExt;
 
 
-fn create_texture_view(pixels: &pixels::Pixels, width: u32, height: u32) -> wgpu::TextureView {
-    let device = pixels.device();
-    let texture_descriptor = TextureDescriptor {
-        label: None,
-        size: pixels::wgpu::Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: pixels.render_texture_format(),
-        view_formats: &[pixels.render_texture_format()],
-        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
-    };
-
-    device
-        .create_texture(&texture_descriptor)
-        .create_view(&wgpu::TextureViewDescriptor::default())
-}
-
 fn create_bind_group(
     device: &wgpu::Device,
     bind_group_layout: &wgpu::BindGroupLayout,
@@ -113,7 +88,7 @@ pub struct StretchingRenderer {
     texture_height: u32,
     screen_width: u32,
     screen_height: u32,
-
+    target_rect: (u32, u32, u32, u32),
 }
 
 impl StretchingRenderer {
@@ -129,7 +104,6 @@ impl StretchingRenderer {
         let shader = wgpu::include_wgsl!("./shaders/scale.wgsl");
         let module = device.create_shader_module(shader);
 
-        //let texture_view = create_texture_view(pixels, screen_width, screen_height);
         let texture_view = pixels.texture().create_view(&wgpu::TextureViewDescriptor::default());
 
         // Create a texture sampler with nearest neighbor
@@ -270,7 +244,8 @@ impl StretchingRenderer {
             texture_width,
             texture_height,
             screen_width,
-            screen_height
+            screen_height,
+            target_rect: (0, 0, screen_width, screen_height),
         }
     }
 
@@ -300,14 +275,10 @@ impl StretchingRenderer {
         rpass.set_bind_group(0, &self.bind_group, &[]);
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
 
-        /*
-        rpass.set_scissor_rect(
-            0,
-            0,
-            1,
-            1
-        );
-        */
+        // The full-screen triangle extends past the target rectangle; clip it so the 
+        // margins stay black.
+        let (x, y, w, h) = self.target_rect;
+        rpass.set_scissor_rect(x, y, w.max(1), h.max(1));
         
         rpass.draw(0..3, 0..1);
     }
@@ -320,7 +291,8 @@ impl StretchingRenderer {
         screen_width: u32,
         screen_height: u32,
     ) {
-        self.texture_view = create_texture_view(pixels, screen_width, screen_height);
+        // The pixel buffer's texture is recreated when the buffer is resized.
+        self.texture_view = pixels.texture().create_view(&wgpu::TextureViewDescriptor::default());
         self.bind_group = create_bind_group(
             pixels.device(),
             &self.bind_group_layout,
@@ -337,6 +309,31 @@ impl StretchingRenderer {
         pixels
             .queue()
             .write_buffer(&self.uniform_buffer, 0, transform_bytes);
+
+        self.texture_width = texture_width;
+        self.texture_height = texture_height;
+        self.screen_width = screen_width;
+        self.screen_height = screen_height;
+        self.target_rect = (0, 0, screen_width, screen_height);
+    }
+
+    /// Draw the pixel buffer into a rectangle of the screen, given by its top left 
+    /// corner and size in pixels. The rest of the screen is cleared to black.
+    pub fn set_target_rect(&mut self, pixels: &pixels::Pixels, x: u32, y: u32, w: u32, h: u32) {
+        let x = x.min(self.screen_width);
+        let y = y.min(self.screen_height);
+        let w = w.min(self.screen_width - x);
+        let h = h.min(self.screen_height - y);
+
+        let matrix = ScalingMatrix::from_rect(
+            (x as f32, y as f32, w as f32, h as f32),
+            (self.screen_width as f32, self.screen_height as f32),
+        );
+        pixels
+            .queue()
+            .write_buffer(&self.uniform_buffer, 0, matrix.as_bytes());
+
+        self.target_rect = (x, y, w, h);
     }
 }
 
@@ -386,6 +383,29 @@ impl ScalingMatrix {
         }
     }
 
+    // Map the unit square onto a rectangle of the screen, given in pixels.
+    fn from_rect(rect: (f32, f32, f32, f32), screen_size: (f32, f32)) -> Self {
+        let (x, y, w, h) = rect;
+        let (screen_width, screen_height) = screen_size;
+
+        let sw = w / screen_width;
+        let sh = h / screen_height;
+        let tx = (2.0 * x + w) / screen_width - 1.0;
+        let ty = 1.0 - (2.0 * y + h) / screen_height;
+
+        #[rustfmt::skip]
+        let transform: [f32; 16] = [
+            sw,  0.0, 0.0, 0.0,
+            0.0, sh,  0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            tx,  ty,  0.0, 1.0,
+        ];
+
+        Self {
+            transform: Mat4::from(transform),
+        }
+    }
+
     fn as_bytes(&self) -> &[u8] {
         self.transform.as_byte_slice()
     }
@@ -38,10 +38,76 @@
     See https://github.com/dbalsom/cga_artifact_color for more details on the
     implementation.
 
+    Both the original ("old style") CGA and the 1983 revision ("new style") 
+    are supported. The new style card mixes a luminance signal derived from 
+    its RGBI outputs into the composite signal, which changes the brightness
+    and hue of artifact colors.
+
 */
 
+use std::fmt::Display;
+
 //use cgmath::{Matrix3, Vector3};
 use glam::{Mat3, Mat3A, Vec3, Vec3A};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+// Iterate over the rows of an image, in parallel where threads are available.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! rows_mut {
+    ($buf:expr, $row_len:expr) => { $buf.par_chunks_exact_mut($row_len) };
+}
+#[cfg(target_arch = "wasm32")]
+macro_rules! rows_mut {
+    ($buf:expr, $row_len:expr) => { $buf.chunks_exact_mut($row_len) };
+}
+
+// Run a closure over each row with per-thread state created by $init, so that scratch buffers
+// are allocated once per thread rather than once per row.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! for_each_init {
+    ($iter:expr, $init:expr, $op:expr) => { $iter.for_each_init($init, $op) };
+}
+#[cfg(target_arch = "wasm32")]
+macro_rules! for_each_init {
+    ($iter:expr, $init:expr, $op:expr) => {{
+        let mut state = ($init)();
+        let mut op = $op;
+        $iter.for_each(|item| op(&mut state, item))
+    }};
+}
+
+/// The composite output circuit to simulate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CompositeStandard {
+    /// NTSC from an original IBM CGA. Luma is derived from the chroma signal alone.
+    #[default]
+    NtscOldCga,
+    /// NTSC from a revised IBM CGA, which adds a luma component for each RGBI color.
+    NtscNewCga,
+}
+
+impl CompositeStandard {
+    pub const ALL: [CompositeStandard; 2] = [CompositeStandard::NtscOldCga, CompositeStandard::NtscNewCga];
+
+    /// Return the hue adjustment, in radians, for this revision relative to the old style CGA.
+    /// The new style card's chroma delay line differs, shifting every artifact color slightly.
+    pub fn hue_offset(&self) -> f32 {
+        match self {
+            CompositeStandard::NtscOldCga => 0.0,
+            CompositeStandard::NtscNewCga => NEW_CGA_HUE_OFFSET,
+        }
+    }
+}
+
+impl Display for CompositeStandard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompositeStandard::NtscOldCga => write!(f, "NTSC (Old CGA)"),
+            CompositeStandard::NtscNewCga => write!(f, "NTSC (New CGA)"),
+        }
+    }
+}
 
 // Composite stufff
 pub const EDGE_RESPONSE: f32 = 0.80;
@@ -49,6 +115,11 @@ pub const INTENSITY_GAIN: f32 = 0.25;
 pub const INTENSITY_GAIN_INT: u8 = 64;
 pub const LUMA_ATTENUATE: f32 = 0.75;
 
+// Luma added by the new style CGA for each of the 8 base colors, derived from weighting the 
+// R, G and B outputs. The chroma signal is halved to make room for it.
+pub const NEW_CGA_LUMA_INT: [u8; 8] = [0, 7, 38, 45, 19, 26, 57, 64];
+const NEW_CGA_HUE_OFFSET: f32 = -0.2;
+
 // Luma contribution of each color for each 1/2 Hdot of a color cycle
 pub const COLOR_GEN_HALF_INT: [[u8; 8]; 8] = [
     [  0,   0,   0,   0,   0,   0,   0,   0 ], // Black
@@ -107,7 +178,7 @@ pub fn get_cycle_hdot(x: i32) -> usize {
 /// The input image should be a slice of CGA color indices (0-15).
 /// The output image should be a slice of u8 values to receive the grayscale composite signal.
 /// 
-/// Uses integer math.
+/// Uses integer math. Rows are converted in parallel.
 pub fn process_cga_composite_int(
     cga_buf: &[u8], 
     img_w: u32, 
@@ -115,92 +186,144 @@ pub fn process_cga_composite_int(
     x_offset: u32,
     _y_offset: u32,
     stride: u32, 
+    standard: CompositeStandard,
     img_out: &mut [u8]
 ) {
 
     //bench_t = Instant::now();
 
-    let mut dst_o = 0;
-
-    for y in 0..img_h {
-        for x in x_offset..(img_w - x_offset) {
-            //get_sample_slice_cga(&cga_buf, img_w, img_h, x, y, &mut sample_slice);
-            //let luma = get_cga_luma_avg_from_slice(&sample_slice, x as i32 - (WINDOW_SIZE / 2));
+    rows_mut!(img_out, (img_w * 2) as usize)
+        .take(img_h as usize)
+        .enumerate()
+        .for_each(|(y, out_row)| {
+            let y = y as u32;
+            for x in x_offset..(img_w - x_offset) {
+                //get_sample_slice_cga(&cga_buf, img_w, img_h, x, y, &mut sample_slice);
+                //let luma = get_cga_luma_avg_from_slice(&sample_slice, x as i32 - (WINDOW_SIZE / 2));
 
-            let mut last_hhdot_value = 0;
+                let mut last_hhdot_value = 0;
 
-            let src_o = (y * stride + x) as usize;
+                let src_o = (y * stride + x) as usize;
             
-            // Convert 0-15 color range to 0-7
-            let color = cga_buf[src_o];
-            let next_color = if x < (img_w - 1) {
-                cga_buf[src_o + 1 as usize] % 8
-            }
-            else {
-                0
-            };
-            let base_color = color % 8;
-            let is_bright = color > 7;
+                // Convert 0-15 color range to 0-7
+                let color = cga_buf[src_o];
+                let next_color = if x < (img_w - 1) {
+                    cga_buf[src_o + 1 as usize] % 8
+                }
+                else {
+                    0
+                };
+                let base_color = color % 8;
+                let is_bright = color > 7;
 
-            let hdot = get_cycle_hdot(x as i32);
+                let hdot = get_cycle_hdot(x as i32);
 
-            for h in 0..2usize {
+                for h in 0..2usize {
 
-                let mut attenuate = false;
+                    let mut attenuate = false;
                 
-                let mut hhdot_value = COLOR_GEN_HALF_INT[base_color as usize][(hdot * 2 + h) as usize];
-                let next_hhdot_value = match h {
-                    0 => {
-                        COLOR_GEN_HALF_INT[base_color as usize][((hdot * 2 + h) + 1) % 8 as usize ]
+                    let mut hhdot_value = COLOR_GEN_HALF_INT[base_color as usize][(hdot * 2 + h) as usize];
+                    let next_hhdot_value = match h {
+                        0 => {
+                            COLOR_GEN_HALF_INT[base_color as usize][((hdot * 2 + h) + 1) % 8 as usize ]
+                        }
+                        _ => {
+                            COLOR_GEN_HALF_INT[next_color as usize][((hdot * 2 + h) + 1) % 8 as usize ]   
+                        }
+                    };
+                    let hhdot_is_edge = COLOR_GEN_EDGES_HALF[base_color as usize][(hdot * 2 + h) as usize];
+
+                    if hhdot_value == 255 && last_hhdot_value == 0 {
+                        // Signal is rising.
+                        if hhdot_is_edge == true {
+                            // Signal is rising with rising edge of color clock. Attenuate edge slew.
+                            attenuate = true;
+                        }
                     }
-                    _ => {
-                        COLOR_GEN_HALF_INT[next_color as usize][((hdot * 2 + h) + 1) % 8 as usize ]   
+                    else if hhdot_value == 255 && next_hhdot_value == 0 {
+                        // Signal is falling on next hhdot.
+                        if hhdot_is_edge == true {
+                            // Signal is falling with falling edge of color clock. Attenuate edge slew.
+                            attenuate = true;
+                        }
                     }
-                };
-                let hhdot_is_edge = COLOR_GEN_EDGES_HALF[base_color as usize][(hdot * 2 + h) as usize];
-
-                if hhdot_value == 255 && last_hhdot_value == 0 {
-                    // Signal is rising.
-                    if hhdot_is_edge == true {
-                        // Signal is rising with rising edge of color clock. Attenuate edge slew.
-                        attenuate = true;
-                    }
-                }
-                else if hhdot_value == 255 && next_hhdot_value == 0 {
-                    // Signal is falling on next hhdot.
-                    if hhdot_is_edge == true {
-                        // Signal is falling with falling edge of color clock. Attenuate edge slew.
-                        attenuate = true;
-                    }
-                }
 
-                last_hhdot_value = hhdot_value;
+                    last_hhdot_value = hhdot_value;
 
-                /*
-                if attenuate {
-                    hhdot_value = ((hhdot_value as u32 * 768) >> 10) as u8;
-                }
-                */
+                    /*
+                    if attenuate {
+                        hhdot_value = ((hhdot_value as u32 * 768) >> 10) as u8;
+                    }
+                    */
 
-                // Integer version of * 0.75
-                hhdot_value = ((hhdot_value as u32 * 768) >> 10) as u8;
+                    hhdot_value = match standard {
+                        // Integer version of * 0.75
+                        CompositeStandard::NtscOldCga => ((hhdot_value as u32 * 768) >> 10) as u8,
+                        CompositeStandard::NtscNewCga => (hhdot_value >> 1) + NEW_CGA_LUMA_INT[base_color as usize],
+                    };
 
-                if is_bright {
-                    hhdot_value += INTENSITY_GAIN_INT;
-                }
+                    if is_bright {
+                        hhdot_value += INTENSITY_GAIN_INT;
+                    }
                 
-                let dst_o = ((y * img_w * 2) + ((x- x_offset) * 2)) as usize;
-                img_out[dst_o + h] =  hhdot_value as u8;
+                    let dst_o = ((x - x_offset) * 2) as usize;
+                    out_row[dst_o + h] =  hhdot_value as u8;
                 
+                }
             }
-            //dst_o += 2;
-        }
-    }
+        });
 
     //let us = (Instant::now() - bench_t).as_micros();
     //log::debug!("Composite conversion took: {} milliseconds", us as f32 / 1000.0 );
 }
 
+/// Decode one row of the composite signal into RGB, passing each output pixel to `put`.
+/// The row holds two composite samples per output pixel. `terms` is scratch space that is 
+/// reused between rows.
+#[inline]
+fn decode_row(
+    signal: &[u8],
+    sync_table: &[(f32, f32, f32)],
+    adjust_mat: Mat3A,
+    color_burst: bool,
+    out_w: usize,
+    terms: &mut Vec<Vec3A>,
+    mut put: impl FnMut(usize, [u8; 3])
+) {
+    let last = signal.len() as i32 - 1;
+
+    // Multiply each sample by the color carrier once, rather than once per pixel it contributes 
+    // to. Samples past either end of the row are clamped to the edge.
+    terms.clear();
+    terms.extend((-CCYCLE_HALF..(out_w as i32 * 2 + CCYCLE_HALF))
+        .map(|n| {
+            let s = signal[n.clamp(0, last) as usize] as f32 / 255.0;
+            let sync = sync_table[(n + CCYCLE_HALF) as usize];
+            Vec3A::new(s, s * sync.1, s * sync.2)
+        }));
+
+    for x in 0..out_w {
+        let window = &terms[x * 2..x * 2 + CCYCLE as usize];
+        let mut yiq = window.iter().fold(Vec3A::ZERO, |acc, t| acc + *t) / CCYCLE as f32;
+
+        // Without a color burst to lock on to, the monitor's color killer disables chroma decoding
+        if !color_burst {
+            yiq.y = 0.0;
+            yiq.z = 0.0;
+        }
+
+        let adjust_yiq = adjust(yiq, adjust_mat);
+        let rgb = YIQ2RGB * adjust_yiq;
+
+        put(x, [
+            to_u8_clamped(rgb.x * 255.0), 
+            to_u8_clamped(rgb.y * 255.0), 
+            to_u8_clamped(rgb.z * 255.0)
+        ]);
+    }
+}
+
+/// Decode a composite image into RGBA, doubling each row. Rows are decoded in parallel.
 pub fn artifact_colors_fast(
     img_in: &[u8],
     img_in_w: u32,
@@ -212,52 +335,29 @@ pub fn artifact_colors_fast(
     hue: f32,
     sat: f32,
     luma: f32,
+    color_burst: bool,
 ) {
 
     let adjust_mat = make_adjust_mat(hue, sat, luma);
-
-    for y in 0..img_in_h {
-        
-        let mut dst_o0 = ((y * 2) * (img_out_w * 4)) as usize;
-        let mut dst_o1 = dst_o0 + (img_out_w * 4) as usize;
-
-        for x in 0..img_out_w {
-            //let mut yiq: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);  // cgmath
-            let mut yiq = Vec3A::new(0.0, 0.0, 0.0);
-
-            for n in -CCYCLE_HALF..CCYCLE_HALF {
-                let signal = sample_gy_xy(img_in, img_in_w, img_in_h, (x * 2) as i32 + n, y as i32);
-
-                let sti = ((x * 2) as i32 + n as i32 + CCYCLE_HALF) as usize;
-                let signal_i = signal * sync_table[sti].1;
-                let signal_q = signal * sync_table[sti].2;
-
-                //log::trace!("Sync: Calc: {},{} Table: {},{}", sync.y, sync.z, sync_table[sti].1, sync_table[sti].2);
-                yiq.x += signal;
-                yiq.y += signal_i;
-                yiq.z += signal_q;
-            }
-            yiq = yiq / CCYCLE as f32;
-
-            let adjust_yiq = adjust(yiq, adjust_mat);
-            let rgb = YIQ2RGB * adjust_yiq;
-
-            img_out[dst_o0 + 0] = to_u8_clamped(rgb.x * 255.0);
-            img_out[dst_o0 + 1] = to_u8_clamped(rgb.y * 255.0);
-            img_out[dst_o0 + 2] = to_u8_clamped(rgb.z * 255.0);
-            img_out[dst_o0 + 3] = 0xFF;
-
-            img_out[dst_o1 + 0] = to_u8_clamped(rgb.x * 255.0);
-            img_out[dst_o1 + 1] = to_u8_clamped(rgb.y * 255.0);
-            img_out[dst_o1 + 2] = to_u8_clamped(rgb.z * 255.0);
-            img_out[dst_o1 + 3] = 0xFF;
-
-            dst_o0 += 4;
-            dst_o1 += 4;
+    let in_w = img_in_w as usize;
+    let out_w = img_out_w as usize;
+
+    for_each_init!(
+        rows_mut!(img_out, out_w * 4 * 2).take(img_in_h as usize).enumerate(),
+        Vec::new,
+        |terms: &mut Vec<Vec3A>, (y, dst): (usize, &mut [u8])| {
+            let (row0, row1) = dst.split_at_mut(out_w * 4);
+
+            decode_row(&img_in[y * in_w..(y + 1) * in_w], sync_table, adjust_mat, color_burst, out_w, terms, |x, rgb| {
+                row0[x * 4..x * 4 + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 0xFF]);
+            });
+            row1.copy_from_slice(row0);
         }
-    }
+    );
 }
 
+/// Decode a composite image into RGBA, doubling each row, writing whole pixels at a time. 
+/// Rows are decoded in parallel.
 pub fn artifact_colors_fast_u32(
     img_in: &[u8],
     img_in_w: u32,
@@ -269,47 +369,27 @@ pub fn artifact_colors_fast_u32(
     hue: f32,
     sat: f32,
     luma: f32,
+    color_burst: bool,
 ) {
 
     let img_out_u32: &mut [u32] = bytemuck::cast_slice_mut(img_out);
 
     let adjust_mat = make_adjust_mat(hue, sat, luma);
-
-    for y in 0..img_in_h {
-        
-        let mut dst_o0 = ((y * 2) * img_out_w) as usize;
-        let mut dst_o1 = dst_o0 + img_out_w as usize;
-
-        for x in 0..img_out_w {
-            //let mut yiq: Vector3<f32> = Vector3::new(0.0, 0.0, 0.0);  // cgmath
-            let mut yiq = Vec3A::new(0.0, 0.0,0.0);
-
-            for n in -CCYCLE_HALF..CCYCLE_HALF {
-                let signal = sample_gy_xy(img_in, img_in_w, img_in_h, (x * 2) as i32 + n, y as i32);
-
-                let sti = ((x * 2) as i32 + n as i32 + CCYCLE_HALF) as usize;
-                let signal_i = signal * sync_table[sti].1;
-                let signal_q = signal * sync_table[sti].2;
-
-                //log::trace!("Sync: Calc: {},{} Table: {},{}", sync.y, sync.z, sync_table[sti].1, sync_table[sti].2);
-                yiq.x += signal;
-                yiq.y += signal_i;
-                yiq.z += signal_q;
-            }
-            yiq = yiq / CCYCLE as f32;
-
-            let adjust_yiq = adjust(yiq, adjust_mat);
-            let rgb = YIQ2RGB * adjust_yiq;
-
-            let pixel = to_u32_clamped(rgb.x * 255.0) << 24 | to_u32_clamped(rgb.y * 255.0) << 16 | to_u32_clamped(rgb.x * 255.0) << 8 | 0xFF;
-
-            img_out_u32[dst_o0] = pixel;
-            img_out_u32[dst_o1] = pixel;
-
-            dst_o0 += 1;
-            dst_o1 += 1;
+    let in_w = img_in_w as usize;
+    let out_w = img_out_w as usize;
+
+    for_each_init!(
+        rows_mut!(img_out_u32, out_w * 2).take(img_in_h as usize).enumerate(),
+        Vec::new,
+        |terms: &mut Vec<Vec3A>, (y, dst): (usize, &mut [u32])| {
+            let (row0, row1) = dst.split_at_mut(out_w);
+
+            decode_row(&img_in[y * in_w..(y + 1) * in_w], sync_table, adjust_mat, color_burst, out_w, terms, |x, rgb| {
+                row0[x] = u32::from_ne_bytes([rgb[0], rgb[1], rgb[2], 0xFF]);
+            });
+            row1.copy_from_slice(row0);
         }
-    }
+    );
 }
 
 #[inline]
@@ -392,4 +472,145 @@ pub fn regen_sync_table(table: &mut [(f32, f32, f32)], table_len: usize) {
         let phase: f32 = ((x - CCYCLE_HALF) as f32) * TAU / 8.0;
         table[x as usize] = (phase, phase.cos(), phase.sin());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode one scanline of a 640x200 mode bit pattern of alternating white and black pixels.
+    fn decode_hires_pattern(color_burst: bool) -> Vec<u8> {
+        const W: u32 = 64;
+        let cga_buf: Vec<u8> = (0..W).map(|x| if x & 0x02 == 0 { 0x0F } else { 0x00 }).collect();
+
+        let mut composite_buf = vec![0; (W * 2) as usize];
+        process_cga_composite_int(&cga_buf, W, 1, 0, 0, W, CompositeStandard::NtscOldCga, &mut composite_buf);
+
+        let mut sync_table = vec![(0.0, 0.0, 0.0); (W * 2) as usize + CCYCLE as usize];
+        regen_sync_table(&mut sync_table, (W * 2) as usize);
+
+        let mut rgba = vec![0; (W * 2 * 4) as usize];
+        artifact_colors_fast(&composite_buf, W * 2, 1, &sync_table, &mut rgba, W, 2, 1.0, 1.0, 1.0, color_burst);
+        rgba
+    }
+
+    #[test]
+    fn test_hires_artifact_color() {
+        // With a color burst, a pixel pattern at the color carrier frequency produces color
+        let rgba = decode_hires_pattern(true);
+        let px = &rgba[32 * 4..32 * 4 + 3];
+        assert!(px[0] != px[1] || px[1] != px[2]);
+
+        // Without one, the monitor displays shades of gray
+        let rgba = decode_hires_pattern(false);
+        for px in rgba.chunks_exact(4) {
+            assert_eq!(px[0], px[1]);
+            assert_eq!(px[1], px[2]);
+        }
+    }
+
+    /// The original per-pixel decoder, which samples the signal for each pixel's window 
+    /// separately. Used as the reference for the row decoder.
+    fn artifact_colors_scalar(
+        img_in: &[u8],
+        img_in_w: u32,
+        img_in_h: u32,
+        sync_table: &[(f32, f32, f32)],
+        img_out: &mut [u8],
+        adjust_mat: Mat3A,
+        color_burst: bool,
+    ) {
+        let img_out_w = img_in_w / 2;
+        for y in 0..img_in_h {
+            let mut dst_o0 = ((y * 2) * (img_out_w * 4)) as usize;
+            let mut dst_o1 = dst_o0 + (img_out_w * 4) as usize;
+
+            for x in 0..img_out_w {
+                let mut yiq = Vec3A::new(0.0, 0.0, 0.0);
+
+                for n in -CCYCLE_HALF..CCYCLE_HALF {
+                    let signal = sample_gy_xy(img_in, img_in_w, img_in_h, (x * 2) as i32 + n, y as i32);
+                    let sti = ((x * 2) as i32 + n + CCYCLE_HALF) as usize;
+                    yiq.x += signal;
+                    yiq.y += signal * sync_table[sti].1;
+                    yiq.z += signal * sync_table[sti].2;
+                }
+                yiq /= CCYCLE as f32;
+
+                if !color_burst {
+                    yiq.y = 0.0;
+                    yiq.z = 0.0;
+                }
+
+                let rgb = YIQ2RGB * adjust(yiq, adjust_mat);
+                let px = [to_u8_clamped(rgb.x * 255.0), to_u8_clamped(rgb.y * 255.0), to_u8_clamped(rgb.z * 255.0), 0xFF];
+                img_out[dst_o0..dst_o0 + 4].copy_from_slice(&px);
+                img_out[dst_o1..dst_o1 + 4].copy_from_slice(&px);
+                dst_o0 += 4;
+                dst_o1 += 4;
+            }
+        }
+    }
+
+    #[test]
+    fn test_row_decoder_matches_scalar() {
+        const W: u32 = 80;
+        const H: u32 = 3;
+
+        // 640x200 mode, a pattern at the color carrier frequency
+        let hires: Vec<u8> = (0..W * H).map(|i| if i & 0x02 == 0 { 0x0F } else { 0x00 }).collect();
+        // 320x200 mode with each palette, each pixel two dots wide
+        let palettes: [[u8; 4]; 4] = [[0, 2, 4, 6], [0, 10, 12, 14], [0, 3, 5, 7], [0, 11, 13, 15]];
+        let lores = palettes.map(|pal| (0..W * H).map(|i| pal[((i / 2 + i / W) % 4) as usize]).collect::<Vec<u8>>());
+        // Every color, at varying run lengths
+        let mut state = 0x1234u32;
+        let random: Vec<u8> = (0..W * H).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) & 0x0F) as u8
+        }).collect();
+
+        let mut sync_table = vec![(0.0, 0.0, 0.0); (W * 2) as usize + CCYCLE as usize];
+        regen_sync_table(&mut sync_table, (W * 2) as usize);
+
+        let images = std::iter::once(&hires).chain(lores.iter()).chain(std::iter::once(&random));
+        for cga_buf in images {
+            for standard in CompositeStandard::ALL {
+                let mut composite_buf = vec![0; (W * 2 * H) as usize];
+                process_cga_composite_int(cga_buf, W, H, 0, 0, W, standard, &mut composite_buf);
+
+                for (hue, sat, luma, color_burst) in [(0.0, 1.0, 1.0, true), (0.5, 1.5, 0.8, true), (0.0, 1.0, 1.0, false)] {
+                    let mut expected = vec![0; (W * H * 2 * 4) as usize];
+                    let adjust_mat = make_adjust_mat(hue, sat, luma);
+                    artifact_colors_scalar(&composite_buf, W * 2, H, &sync_table, &mut expected, adjust_mat, color_burst);
+
+                    let mut rgba = vec![0; expected.len()];
+                    artifact_colors_fast(&composite_buf, W * 2, H, &sync_table, &mut rgba, W, H * 2, hue, sat, luma, color_burst);
+                    assert_eq!(rgba, expected, "{:?} hue {} sat {} luma {} burst {}", standard, hue, sat, luma, color_burst);
+
+                    let mut rgba_u32 = vec![0; expected.len()];
+                    artifact_colors_fast_u32(&composite_buf, W * 2, H, &sync_table, &mut rgba_u32, W, H * 2, hue, sat, luma, color_burst);
+                    assert_eq!(rgba_u32, expected, "{:?} hue {} sat {} luma {} burst {}", standard, hue, sat, luma, color_burst);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_new_cga_luma() {
+        // Return the average composite signal level of a solid line of the given color
+        let average = |color: u8, standard: CompositeStandard| -> u32 {
+            let cga_buf = vec![color; 16];
+            let mut composite_buf = vec![0; 32];
+            process_cga_composite_int(&cga_buf, 16, 1, 0, 0, 16, standard, &mut composite_buf);
+            composite_buf.iter().map(|&v| v as u32).sum::<u32>() / 32
+        };
+
+        // The old style CGA produces the same luma for every color with a 50% duty cycle
+        assert_eq!(average(1, CompositeStandard::NtscOldCga), average(2, CompositeStandard::NtscOldCga));
+        // The new style CGA displays green brighter than blue
+        assert!(average(2, CompositeStandard::NtscNewCga) > average(1, CompositeStandard::NtscNewCga));
+        // Bright white is at full level on both
+        assert_eq!(average(15, CompositeStandard::NtscOldCga), 255);
+        assert_eq!(average(15, CompositeStandard::NtscNewCga), 255);
+    }
+}
@@ -0,0 +1,263 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::crt.rs
+
+    CPU-side CRT post-processing effects, applied to the final display buffer
+    after rendering and aspect correction.
+
+    Scanlines darken the edges of each emulated scanline; the number of 
+    emulated scanlines is passed in so the effect lines up with the source
+    image regardless of scaling. Barrel distortion bows the image outwards
+    like the curved glass of a CRT, using a lookup table that is rebuilt when
+    the buffer size changes. Phosphor persistence blends each frame with a 
+    decayed copy of the previous one, so that moving objects leave a short 
+    trail as on a slow phosphor.
+
+*/
+
+/// Parameters for the CRT post-processing effects.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CrtParams {
+    pub scanlines: bool,
+    /// How much the edges of scanlines are darkened, from 0.0 to 1.0.
+    pub scanline_strength: f32,
+    pub barrel: bool,
+    /// Amount of barrel distortion, from 0.0 to 1.0.
+    pub barrel_strength: f32,
+    pub persistence: bool,
+    /// How much of the previous frame remains visible, from 0.0 to 1.0.
+    pub persistence_decay: f32,
+}
+
+impl Default for CrtParams {
+    fn default() -> Self {
+        Self {
+            scanlines: false,
+            scanline_strength: 0.5,
+            barrel: false,
+            barrel_strength: 0.15,
+            persistence: false,
+            persistence_decay: 0.4,
+        }
+    }
+}
+
+impl CrtParams {
+    pub fn any_enabled(&self) -> bool {
+        self.scanlines || self.barrel || self.persistence
+    }
+}
+
+#[derive(Default)]
+pub struct CrtProcessor {
+    params: CrtParams,
+
+    // Persistence
+    last_frame: Vec<u8>,
+
+    // Barrel distortion lookup table. Each entry is the source pixel index for a destination 
+    // pixel, or None if the destination pixel is outside the distorted image.
+    barrel_map: Vec<Option<u32>>,
+    barrel_map_size: (u32, u32),
+    barrel_map_strength: f32,
+    scratch: Vec<u8>,
+}
+
+impl CrtProcessor {
+    pub fn new(params: CrtParams) -> Self {
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+
+    pub fn params(&self) -> &CrtParams {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: CrtParams) {
+        if !params.persistence {
+            self.last_frame.clear();
+        }
+        self.params = params;
+    }
+
+    /// Apply the enabled effects to a RGBA frame of the specified size. 'lines' is the number
+    /// of emulated scanlines the frame was rendered from.
+    pub fn process(&mut self, frame: &mut [u8], w: u32, h: u32, lines: u32) {
+        let len = (w * h * 4) as usize;
+        if !self.params.any_enabled() || frame.len() < len || w == 0 || h == 0 {
+            return;
+        }
+        let frame = &mut frame[..len];
+
+        if self.params.persistence {
+            self.apply_persistence(frame);
+        }
+        if self.params.scanlines {
+            Self::apply_scanlines(frame, w, h, lines, self.params.scanline_strength);
+        }
+        if self.params.barrel {
+            self.apply_barrel(frame, w, h);
+        }
+    }
+
+    fn apply_persistence(&mut self, frame: &mut [u8]) {
+        if self.last_frame.len() != frame.len() {
+            // The frame size changed, so there's nothing to blend with.
+            self.last_frame = frame.to_vec();
+            return;
+        }
+
+        let decay = (self.params.persistence_decay.clamp(0.0, 1.0) * 256.0) as u32;
+        for (pixel, last) in frame.chunks_exact_mut(4).zip(self.last_frame.chunks_exact_mut(4)) {
+            for c in 0..3 {
+                let faded = ((last[c] as u32 * decay) >> 8) as u8;
+                pixel[c] = pixel[c].max(faded);
+                last[c] = pixel[c];
+            }
+        }
+    }
+
+    /// Return the brightness of each row of a frame h rows high showing the specified number
+    /// of scanlines, as a fraction of 256. Rows at the center of a scanline are full brightness.
+    fn scanline_weights(h: u32, lines: u32, strength: f32) -> Vec<u32> {
+        let strength = strength.clamp(0.0, 1.0);
+        let lines = lines.clamp(1, h);
+        (0..h)
+            .map(|y| {
+                // Position of the center of this row within its scanline, from 0.0 to 1.0
+                let pos = ((y as f32 + 0.5) * lines as f32 / h as f32).fract();
+                let weight = 1.0 - strength * (1.0 - (pos * std::f32::consts::PI).sin());
+                (weight * 256.0) as u32
+            })
+            .collect()
+    }
+
+    fn apply_scanlines(frame: &mut [u8], w: u32, h: u32, lines: u32, strength: f32) {
+        let weights = Self::scanline_weights(h, lines, strength);
+        for (row, weight) in frame.chunks_exact_mut((w * 4) as usize).zip(weights) {
+            if weight >= 256 {
+                continue;
+            }
+            for pixel in row.chunks_exact_mut(4) {
+                for c in &mut pixel[0..3] {
+                    *c = ((*c as u32 * weight) >> 8) as u8;
+                }
+            }
+        }
+    }
+
+    fn build_barrel_map(&mut self, w: u32, h: u32) {
+        let k = self.params.barrel_strength.clamp(0.0, 1.0);
+        let half_w = w as f32 / 2.0;
+        let half_h = h as f32 / 2.0;
+
+        self.barrel_map.clear();
+        self.barrel_map.reserve((w * h) as usize);
+        for y in 0..h {
+            for x in 0..w {
+                // Normalize to -1.0..1.0 from the center of the frame
+                let nx = (x as f32 + 0.5 - half_w) / half_w;
+                let ny = (y as f32 + 0.5 - half_h) / half_h;
+                let r2 = nx * nx + ny * ny;
+                let scale = 1.0 + k * r2;
+
+                let sx = nx * scale * half_w + half_w;
+                let sy = ny * scale * half_h + half_h;
+                if sx < 0.0 || sy < 0.0 || sx >= w as f32 || sy >= h as f32 {
+                    self.barrel_map.push(None);
+                }
+                else {
+                    self.barrel_map.push(Some(sy as u32 * w + sx as u32));
+                }
+            }
+        }
+        self.barrel_map_size = (w, h);
+        self.barrel_map_strength = k;
+    }
+
+    fn apply_barrel(&mut self, frame: &mut [u8], w: u32, h: u32) {
+        if self.barrel_map_size != (w, h) || self.barrel_map_strength != self.params.barrel_strength.clamp(0.0, 1.0) {
+            self.build_barrel_map(w, h);
+        }
+
+        self.scratch.clear();
+        self.scratch.extend_from_slice(frame);
+        for (pixel, src) in frame.chunks_exact_mut(4).zip(self.barrel_map.iter()) {
+            match src {
+                Some(i) => {
+                    let i = *i as usize * 4;
+                    pixel[0..3].copy_from_slice(&self.scratch[i..i + 3]);
+                }
+                None => pixel[0..3].fill(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scanlines() {
+        // 4 rows showing 2 scanlines: the edge rows of each scanline should be darker.
+        let weights = CrtProcessor::scanline_weights(4, 2, 1.0);
+        assert_eq!(weights.len(), 4);
+        assert!(weights[0] < 256 && weights[1] < 256);
+        assert_eq!(weights[0], weights[1]);
+        assert_eq!(weights[0], weights[2]);
+
+        // 3 rows per scanline; the center row is full brightness.
+        let weights = CrtProcessor::scanline_weights(6, 2, 0.5);
+        assert_eq!(weights[1], 256);
+        assert!(weights[0] < 256);
+
+        let weights = CrtProcessor::scanline_weights(4, 2, 0.0);
+        assert!(weights.iter().all(|w| *w == 256));
+    }
+
+    #[test]
+    fn test_persistence_and_barrel() {
+        let mut crt = CrtProcessor::new(CrtParams { persistence: true, persistence_decay: 0.5, ..Default::default() });
+
+        let mut frame = vec![0xFF; 4 * 4 * 4];
+        crt.process(&mut frame, 4, 4, 4);
+        let mut frame = vec![0; 4 * 4 * 4];
+        crt.process(&mut frame, 4, 4, 4);
+        assert_eq!(&frame[0..4], &[0x7F, 0x7F, 0x7F, 0]);
+
+        // The center of the image is unaffected by barrel distortion, but the corners are 
+        // pushed out of the frame.
+        let mut crt = CrtProcessor::new(CrtParams { barrel: true, barrel_strength: 0.5, ..Default::default() });
+        let mut frame: Vec<u8> = (0..8 * 8).flat_map(|i| [i as u8, 0, 0, 0xFF]).collect();
+        crt.process(&mut frame, 8, 8, 8);
+        assert_eq!(frame[(4 * 8 + 4) * 4], 4 * 8 + 4);
+        assert_eq!(&frame[0..4], &[0, 0, 0, 0xFF]);
+    }
+}
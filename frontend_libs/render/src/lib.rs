@@ -46,14 +46,30 @@ use bytemuck::*;
 
 pub mod resize;
 pub mod composite;
+pub mod crt;
+pub mod recorder;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod render_thread;
+pub mod scaling;
+pub mod tile_ripper;
+pub mod vram_viewer;
 
 // Re-export submodules
 pub use self::resize::*;
 pub use self::composite::*;
+pub use self::crt::*;
+pub use self::recorder::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::render_thread::*;
+pub use self::scaling::*;
+pub use self::tile_ripper::*;
+pub use self::vram_viewer::*;
 
 use marty_core::{
     config::VideoType,
-    videocard::{VideoCard, CGAColor, CGAPalette, CursorInfo, DisplayExtents, DisplayMode, FontInfo},
+    monitor::MonitorType,
+    palette::{DisplayPalette, MonochromePhosphor, PaletteColors},
+    videocard::{VideoCard, BlinkInfo, CGAColor, CGAPalette, CursorInfo, DisplayExtents, DisplayMode, FontInfo},
     devices::cga,
     bus::BusInterface,
     file_util
@@ -104,7 +120,10 @@ pub struct VideoData {
     pub aspect_w: u32,
     pub aspect_h: u32,
     pub aspect_correction_enabled: bool,
-    pub composite_params: CompositeParams
+    pub composite_params: CompositeParams,
+    pub palette: DisplayPalette,
+    pub monitor: MonitorType,
+    pub monochrome: Option<MonochromePhosphor>,
 }
 
 
@@ -118,7 +137,9 @@ pub struct AspectRatio {
 pub struct CompositeParams {
     pub hue: f32,
     pub sat: f32,
-    pub luma: f32
+    pub luma: f32,
+    pub standard: CompositeStandard,
+    pub color_burst: bool   // Set from the video card each frame. Chroma is not decoded without a color burst.
 }
 
 impl Default for CompositeParams {
@@ -126,7 +147,9 @@ impl Default for CompositeParams {
         Self {
             hue: 1.0,
             sat: 1.15,
-            luma: 1.15
+            luma: 1.15,
+            standard: Default::default(),
+            color_burst: true
         }
     }
 }
@@ -191,46 +214,6 @@ const CGA_RGBA_COLORS: &[[[u8; 4]; 16]; 2] = &[
     ],
 ];
 
-// Little-endian
-const CGA_RGBA_COLORS_U32: &[[u32; 16]; 2] = &[
-    [
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][0]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][1]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][2]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][3]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][4]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][5]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][6]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][7]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][8]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][9]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][10]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][11]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][12]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][13]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][14]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[0][15]),
-    ],
-    // VileR's palette
-    [
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][0]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][1]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][2]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][3]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][4]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][5]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][6]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][7]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][8]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][9]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][10]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][11]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][12]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][13]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][14]),
-        u32::from_le_bytes(CGA_RGBA_COLORS[1][15]),
-    ],
-];
 
 // Return a RGBA slice given a CGA color Enum
 pub fn color_enum_to_rgba(color: &CGAColor) -> &'static [u8; 4] {
@@ -431,6 +414,31 @@ pub fn get_cga_gfx_color(bits: u8, palette: &CGAPalette, intensity: bool) -> &'s
     }
 }
 
+/// A pair of frames captured from the same CGA display buffer, one decoded as RGBI and one
+/// processed by the composite monitor simulation. Both are RGBA and of the same dimensions.
+#[derive (Clone)]
+pub struct CompositeCapture {
+    pub w: u32,
+    pub h: u32,
+    pub rgbi: Vec<u8>,
+    pub composite: Vec<u8>
+}
+
+impl CompositeCapture {
+    /// Return a single RGBA image with the RGBI frame on the left and the composite frame on 
+    /// the right.
+    pub fn side_by_side(&self) -> Vec<u8> {
+        let row_len = (self.w * 4) as usize;
+        let mut buf = Vec::with_capacity(self.rgbi.len() * 2);
+
+        for (rgbi_row, composite_row) in self.rgbi.chunks_exact(row_len).zip(self.composite.chunks_exact(row_len)) {
+            buf.extend_from_slice(rgbi_row);
+            buf.extend_from_slice(composite_row);
+        }
+        buf
+    }
+}
+
 pub struct VideoRenderer {
     mode: DisplayMode,
     cols: u32,
@@ -439,7 +447,16 @@ pub struct VideoRenderer {
     composite_buf: Option<Vec<u8>>,
     composite_params: CompositeParams,
     sync_table_w: u32,
-    sync_table: Vec<(f32, f32, f32)>
+    sync_table: Vec<(f32, f32, f32)>,
+
+    capture_requested: bool,
+    capture: Option<CompositeCapture>,
+
+    palette: [[u8; 4]; 16],
+    palette_u32: [u32; 16],
+
+    // Maps luminance to phosphor color when simulating a monochrome monitor
+    monochrome_lut: Option<Box<[[u8; 4]; 256]>>,
 }
 
 impl VideoRenderer {
@@ -466,10 +483,60 @@ impl VideoRenderer {
             composite_buf: composite_vec_opt,
             composite_params: Default::default(),
             sync_table_w: 0,
-            sync_table: Vec::new()
+            sync_table: Vec::new(),
+
+            capture_requested: false,
+            capture: None,
+
+            palette: CGA_RGBA_COLORS[0],
+            palette_u32: CGA_RGBA_COLORS[0].map(u32::from_le_bytes),
+
+            monochrome_lut: None,
+        }
+    }
+
+    /// Simulate a monochrome composite monitor with the specified phosphor, or None to 
+    /// display in color.
+    pub fn set_monochrome(&mut self, phosphor: Option<MonochromePhosphor>) {
+        self.monochrome_lut = phosphor.map(|phosphor| {
+            let [r, g, b] = phosphor.color();
+            let mut lut = Box::new([[0, 0, 0, 0xFF]; 256]);
+            for (y, entry) in lut.iter_mut().enumerate() {
+                let scale = |c: u8| ((c as u32 * y as u32) / 255) as u8;
+                *entry = [scale(r), scale(g), scale(b), 0xFF];
+            }
+            lut
+        });
+    }
+
+    /// Convert a rendered frame to monochrome by mapping the luminance of each pixel to the
+    /// phosphor color ramp, if a monochrome monitor is being simulated.
+    fn apply_monochrome(&self, frame: &mut [u8]) {
+        if let Some(lut) = &self.monochrome_lut {
+            for pixel in frame.chunks_exact_mut(4) {
+                // Rec. 601 luma
+                let y = (pixel[0] as u32 * 77 + pixel[1] as u32 * 150 + pixel[2] as u32 * 29) >> 8;
+                pixel.copy_from_slice(&lut[y as usize]);
+            }
         }
     }
 
+    /// Select the palette used to convert CGA color indices to RGB in direct mode. The custom
+    /// palette uses the specified colors; if none were loaded, the standard palette is used.
+    pub fn set_palette(&mut self, palette: DisplayPalette, custom: Option<&PaletteColors>) {
+        self.palette = match (palette, custom) {
+            (DisplayPalette::Standard, _) => CGA_RGBA_COLORS[0],
+            (DisplayPalette::VileR, _) => CGA_RGBA_COLORS[1],
+            (DisplayPalette::Custom, Some(colors)) => colors.map(|[r, g, b]| [r, g, b, 0xFF]),
+            (DisplayPalette::Custom, None) => {
+                log::warn!("No custom palette loaded, using standard palette.");
+                CGA_RGBA_COLORS[0]
+            }
+        };
+        // Little-endian
+        self.palette_u32 = self.palette.map(u32::from_le_bytes);
+    }
+
     /// Given the specified resolution and desired aspect ratio, return an aspect corrected resolution
     /// by adjusting the vertical resolution (Horizontal resolution will never be changed)
     pub fn get_aspect_corrected_res(res: (u32, u32), aspect: AspectRatio) -> (u32, u32) {
@@ -481,6 +548,22 @@ impl VideoRenderer {
         (res.0, adjusted_h)
     }
 
+    /// Calculate where a display of the given resolution is drawn within a surface, 
+    /// applying aspect correction first if an aspect ratio is given. The returned 
+    /// rectangle's position gives the letterbox margins.
+    pub fn get_display_rect(
+        res: (u32, u32), 
+        aspect: Option<AspectRatio>, 
+        surface: (u32, u32), 
+        mode: ScalingMode
+    ) -> DisplayRect {
+        let res = match aspect {
+            Some(aspect) => VideoRenderer::get_aspect_corrected_res(res, aspect),
+            None => res
+        };
+        scaling::get_display_rect(res, surface, mode)
+    }
+
     pub fn draw(&self, frame: &mut [u8], video_card: Box<&dyn VideoCard>, bus: &BusInterface, composite: bool) {
 
         //let video_card = video.borrow();        
@@ -497,6 +580,7 @@ impl VideoRenderer {
             DisplayMode::Mode0TextBw40 | DisplayMode::Mode1TextCo40 | DisplayMode::Mode2TextBw80 | DisplayMode::Mode3TextCo80 => {
                 let video_type = video_card.get_video_type();
                 let cursor = video_card.get_cursor_info();
+                let blink = video_card.get_blink_info();
                 let char_height = video_card.get_character_height();
     
                 // Start address is multiplied by two due to 2 bytes per character (char + attr)
@@ -517,6 +601,7 @@ impl VideoRenderer {
                 self.draw_text_mode(
                     video_type, 
                     cursor, 
+                    blink,
                     frame, 
                     frame_w, 
                     frame_h, 
@@ -579,6 +664,8 @@ impl VideoRenderer {
                 // blank screen here?
             }
         }
+
+        self.apply_monochrome(frame);
     }
 
     pub fn screenshot(
@@ -590,7 +677,7 @@ impl VideoRenderer {
     {
 
         // Find first unique filename in screenshot dir
-        let filename = file_util::find_unique_filename(path, "screenshot", ".png");
+        let filename = file_util::find_unique_filename(path, "screenshot", "png");
 
         match image::save_buffer(
             filename.clone(),
@@ -606,10 +693,63 @@ impl VideoRenderer {
         }
     }
 
+    /// Request that the next frame drawn with composite processing also be captured as RGBI, 
+    /// producing a CompositeCapture.
+    pub fn request_composite_capture(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// Return the last CompositeCapture produced, if any.
+    pub fn take_composite_capture(&mut self) -> Option<CompositeCapture> {
+        self.capture.take()
+    }
+
+    /// Save a CompositeCapture to the specified directory. The side by side image is saved along
+    /// with each individual frame.
+    pub fn save_composite_capture(capture: &CompositeCapture, path: &Path) {
+
+        let filename = file_util::find_unique_filename(path, "capture", "png");
+        let stem = filename.file_stem().unwrap_or_default().to_string_lossy().to_string();
+
+        let images = [
+            (filename.clone(), capture.side_by_side(), capture.w * 2),
+            (path.join(format!("{}_rgbi.png", stem)), capture.rgbi.clone(), capture.w),
+            (path.join(format!("{}_composite.png", stem)), capture.composite.clone(), capture.w),
+        ];
+
+        for (image_filename, buf, w) in images {
+            match image::save_buffer(
+                image_filename.clone(),
+                &buf,
+                w,
+                capture.h, 
+                image::ColorType::Rgba8) 
+            {
+                Ok(_) => log::info!("Saved composite capture: {}", image_filename.display()),
+                Err(e) => {
+                    log::error!("Error writing composite capture: {}: {}", image_filename.display(), e)
+                }
+            }
+        }
+    }
+
+    /// Complete a pending composite capture of the frame just drawn, given the RGBI version of
+    /// the same frame.
+    fn finish_composite_capture(&mut self, frame: &[u8], rgbi: Vec<u8>, w: u32, h: u32) {
+        self.capture = Some(CompositeCapture {
+            w,
+            h,
+            rgbi,
+            composite: frame.to_vec()
+        });
+        self.capture_requested = false;
+    }
+
     pub fn draw_text_mode(
         &self, 
         video_type: VideoType,
         cursor: CursorInfo, 
+        blink: BlinkInfo,
         frame: &mut [u8], 
         frame_w: u32, 
         frame_h: u32, 
@@ -647,15 +787,15 @@ impl VideoRenderer {
                 break;
             }
 
-            let (fg_color, bg_color) = get_colors_from_attr_byte(char[1]);
+            let (fg_color, bg_color) = get_text_colors_from_attr_byte(char[1], blink);
 
             match (video_type, lowres) {
                 (VideoType::CGA, true) => {
-                    draw_glyph4x(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font)
+                    draw_glyph4x(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font, &self.palette)
                 }
                 (VideoType::CGA, false) => {
-                    //draw_glyph2x(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font)
-                    draw_glyph1x1(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font)
+                    //draw_glyph2x(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font, &self.palette)
+                    draw_glyph1x1(char[0], fg_color, bg_color, frame, frame_w, frame_h, char_height, x * 8, y * char_height, font, &self.palette)
                 }
                 (VideoType::EGA, true) => {
                     draw_glyph2x1(
@@ -668,7 +808,8 @@ impl VideoRenderer {
                         char_height, 
                         x * 8 * 2, 
                         y * char_height, 
-                        font)
+                        font,
+                        &self.palette)
                 }
                 (VideoType::EGA, false) => {
                     draw_glyph1x1(
@@ -681,7 +822,8 @@ impl VideoRenderer {
                         char_height, 
                         x * 8, 
                         y * char_height, 
-                        font)                    
+                        font,
+                        &self.palette)                    
                 }
                 (VideoType::VGA, false) => {
                     draw_glyph1x1(
@@ -694,7 +836,8 @@ impl VideoRenderer {
                         char_height, 
                         x * 8, 
                         y * char_height, 
-                        font)                    
+                        font,
+                        &self.palette)                    
                 }
                 _=> {}
             }
@@ -702,13 +845,13 @@ impl VideoRenderer {
         }
 
         match (video_type, lowres) {
-            (VideoType::CGA, true) => draw_cursor4x(cursor, frame, frame_w, frame_h, mem, font ),
+            (VideoType::CGA, true) => draw_cursor4x(cursor, frame, frame_w, frame_h, mem, font, &self.palette),
             (VideoType::CGA, false) => {
-                //draw_cursor2x(cursor, frame, frame_w, frame_h, mem, font ),
-                draw_cursor(cursor, frame, frame_w, frame_h, mem, font )
+                //draw_cursor2x(cursor, frame, frame_w, frame_h, mem, font, &self.palette),
+                draw_cursor(cursor, frame, frame_w, frame_h, mem, font, &self.palette)
             }
             (VideoType::EGA, true) | (VideoType::EGA, false) => {
-                draw_cursor(cursor, frame, frame_w, frame_h, mem, font )
+                draw_cursor(cursor, frame, frame_w, frame_h, mem, font, &self.palette)
             }
             _=> {}
         }
@@ -810,8 +953,39 @@ impl VideoRenderer {
 
         if composite_enabled {
             self.draw_cga_direct_composite(frame, w, h, dbuf, extents, composite_params);
+            if self.capture_requested {
+                let mut rgbi = vec![0; frame.len()];
+                self.draw_cga_direct_rgbi(&mut rgbi, w, h, dbuf, extents);
+                self.finish_composite_capture(frame, rgbi, w, h);
+            }
+            self.apply_monochrome(frame);
             return
         }
+        else if self.capture_requested {
+            log::warn!("Composite capture requested, but composite display is not enabled.");
+            self.capture_requested = false;
+        }
+
+        let (max_x, max_y) = self.draw_cga_direct_rgbi(frame, w, h, dbuf, extents);
+        self.apply_monochrome(frame);
+
+        // Draw crosshairs for debugging crt beam pos
+        if let Some(beam) = beam_pos {
+            self.draw_horizontal_xor_line(frame, w, max_x, max_y, beam.1);
+            self.draw_vertical_xor_line(frame, w, max_x, max_y, beam.0);
+        }
+    }
+
+    /// Draw the CGA display buffer as RGBI, without composite processing. Returns the extents 
+    /// of the area drawn.
+    fn draw_cga_direct_rgbi(
+        &self,
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        extents: &DisplayExtents
+    ) -> (u32, u32) {
 
         // Attempt to center the image by reducing right overscan 
         //let overscan_total = extents.aperture_w.saturating_sub(extents.visible_w);
@@ -847,23 +1021,79 @@ impl VideoRenderer {
 
                 let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
 
-                frame[fo0]       = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][0];
-                frame[fo0 + 1]   = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][1];
-                frame[fo0 + 2]   = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][2];
+                frame[fo0]       = self.palette[(dbuf[dbo] & 0x0F) as usize][0];
+                frame[fo0 + 1]   = self.palette[(dbuf[dbo] & 0x0F) as usize][1];
+                frame[fo0 + 2]   = self.palette[(dbuf[dbo] & 0x0F) as usize][2];
                 frame[fo0 + 3]   = 0xFFu8;
 
-                frame[fo1]       = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][0];
-                frame[fo1 + 1]   = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][1];
-                frame[fo1 + 2]   = CGA_RGBA_COLORS[0][(dbuf[dbo] & 0x0F) as usize][2];
+                frame[fo1]       = self.palette[(dbuf[dbo] & 0x0F) as usize][0];
+                frame[fo1 + 1]   = self.palette[(dbuf[dbo] & 0x0F) as usize][1];
+                frame[fo1 + 2]   = self.palette[(dbuf[dbo] & 0x0F) as usize][2];
                 frame[fo1 + 3]   = 0xFFu8;                
             }
         }
 
-        // Draw crosshairs for debugging crt beam pos
-        if let Some(beam) = beam_pos {
-            self.draw_horizontal_xor_line(frame, w, max_x, max_y, beam.1);
-            self.draw_vertical_xor_line(frame, w, max_x, max_y, beam.0);
+        (max_x, max_y)
+    }
+
+    /// Draw the EGA card in Direct Mode. 
+    /// The EGA's display buffer holds 6-bit rgbRGB colors, one byte per pixel. Scanlines are
+    /// not doubled.
+    pub fn draw_ega_direct(
+        &mut self,
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        extents: &DisplayExtents
+    ) {
+        let max_y = std::cmp::min(h, extents.aperture_h);
+        let max_x = std::cmp::min(w, extents.aperture_w);
+
+        for y in 0..max_y {
+
+            let dbuf_row_offset = y as usize * extents.row_stride;
+            let frame_row_offset = (y * (w * 4)) as usize;
+
+            for x in 0..max_x {
+                let fo = frame_row_offset + (x * 4) as usize;
+                let color = get_ega_gfx_color64(dbuf[dbuf_row_offset + x as usize] & 0x3F);
+
+                frame[fo..fo + 4].copy_from_slice(color);
+            }
+        }
+
+        self.apply_monochrome(frame);
+    }
+
+    /// Draw the VGA card in Direct Mode. 
+    /// The VGA's display buffer holds RGBA colors already resolved through the DAC. Scanlines
+    /// are not doubled.
+    pub fn draw_vga_direct(
+        &mut self,
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        extents: &DisplayExtents
+    ) {
+        let max_y = std::cmp::min(h, extents.aperture_h);
+        let max_x = std::cmp::min(w, extents.aperture_w) as usize;
+
+        for y in 0..max_y {
+
+            let dbuf_row_offset = y as usize * extents.row_stride * 4;
+            let frame_row_offset = (y * (w * 4)) as usize;
+
+            frame[frame_row_offset..frame_row_offset + max_x * 4]
+                .copy_from_slice(&dbuf[dbuf_row_offset..dbuf_row_offset + max_x * 4]);
+
+            for x in 0..max_x {
+                frame[frame_row_offset + x * 4 + 3] = 0xFF;
+            }
         }
+
+        self.apply_monochrome(frame);
     }
 
     /// Draw the CGA card in Direct Mode. 
@@ -883,8 +1113,39 @@ impl VideoRenderer {
 
         if composite_enabled {
             self.draw_cga_direct_composite_u32(frame, w, h, dbuf, extents, composite_params);
+            if self.capture_requested {
+                let mut rgbi = vec![0; frame.len()];
+                self.draw_cga_direct_rgbi_u32(&mut rgbi, w, h, dbuf, extents);
+                self.finish_composite_capture(frame, rgbi, w, h);
+            }
+            self.apply_monochrome(frame);
             return
         }
+        else if self.capture_requested {
+            log::warn!("Composite capture requested, but composite display is not enabled.");
+            self.capture_requested = false;
+        }
+
+        let (max_x, max_y) = self.draw_cga_direct_rgbi_u32(frame, w, h, dbuf, extents);
+        self.apply_monochrome(frame);
+
+        // Draw crosshairs for debugging crt beam pos
+        if let Some(beam) = beam_pos {
+            self.draw_horizontal_xor_line(frame, w, max_x, max_y, beam.1);
+            self.draw_vertical_xor_line(frame, w, max_x, max_y, beam.0);
+        }
+    }
+
+    /// Draw the CGA display buffer as RGBI, without composite processing, using 32-bit pixel
+    /// writes. Returns the extents of the area drawn.
+    fn draw_cga_direct_rgbi_u32(
+        &self,
+        frame: &mut [u8],
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        extents: &DisplayExtents
+    ) -> (u32, u32) {
 
         // Attempt to center the image by reducing right overscan 
         //let overscan_total = extents.aperture_w.saturating_sub(extents.visible_w);
@@ -922,16 +1183,12 @@ impl VideoRenderer {
 
                 let dbo = dbuf_row_offset + (x + horiz_adjust) as usize;
 
-                frame_u32[fo0] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
-                frame_u32[fo1] = CGA_RGBA_COLORS_U32[0][(dbuf[dbo] & 0x0F) as usize];
+                frame_u32[fo0] = self.palette_u32[(dbuf[dbo] & 0x0F) as usize];
+                frame_u32[fo1] = self.palette_u32[(dbuf[dbo] & 0x0F) as usize];
             }
         }
 
-        // Draw crosshairs for debugging crt beam pos
-        if let Some(beam) = beam_pos {
-            self.draw_horizontal_xor_line(frame, w, max_x, max_y, beam.1);
-            self.draw_vertical_xor_line(frame, w, max_x, max_y, beam.0);
-        }
+        (max_x, max_y)
     }    
 
     pub fn draw_cga_direct_composite(
@@ -958,6 +1215,7 @@ impl VideoRenderer {
                 extents.aperture_x,
                 extents.aperture_y,
                 extents.row_stride as u32, 
+                composite_params.standard,
                 composite_buf);
 
             // Regen sync table if width changed
@@ -976,9 +1234,10 @@ impl VideoRenderer {
                 frame, 
                 max_w, 
                 max_h, 
-                composite_params.hue, 
+                composite_params.hue + composite_params.standard.hue_offset(), 
                 composite_params.sat,
-                composite_params.luma
+                composite_params.luma,
+                composite_params.color_burst
             );
         }
     }
@@ -1006,6 +1265,7 @@ impl VideoRenderer {
                 extents.overscan_l,
                 extents.overscan_t,
                 extents.row_stride as u32, 
+                composite_params.standard,
                 composite_buf);
 
             // Regen sync table if width changed
@@ -1024,9 +1284,10 @@ impl VideoRenderer {
                 frame, 
                 max_w, 
                 max_h, 
-                composite_params.hue, 
+                composite_params.hue + composite_params.standard.hue_offset(), 
                 composite_params.sat,
-                composite_params.luma
+                composite_params.luma,
+                composite_params.color_burst
             );
         }
     }
@@ -1309,6 +1570,24 @@ pub fn draw_gfx_mode2x_composite(frame: &mut [u8], frame_w: u32, _frame_h: u32,
     }
 }
 
+/// Return the foreground and background colors of a text mode character. With blinking enabled,
+/// attribute bit 7 blinks the character and only the low-intensity background colors are 
+/// available. Otherwise, bit 7 selects a bright background color.
+pub fn get_text_colors_from_attr_byte(byte: u8, blink: BlinkInfo) -> (CGAColor, CGAColor) {
+
+    if !blink.enabled {
+        return get_colors_from_attr_byte(byte);
+    }
+
+    let (fg_color, bg_color) = get_colors_from_attr_byte(byte & 0x7F);
+    if byte & 0x80 != 0 && !blink.visible {
+        (bg_color, bg_color)
+    }
+    else {
+        (fg_color, bg_color)
+    }
+}
+
 pub fn get_colors_from_attr_byte(byte: u8) -> (CGAColor, CGAColor) {
 
     let fg_nibble = byte & 0x0F;
@@ -1354,7 +1633,8 @@ pub fn draw_glyph4x(
     char_height: u32,
     pos_x: u32, 
     pos_y: u32,
-    font: &FontInfo )
+    font: &FontInfo,
+    palette: &[[u8; 4]; 16])
 {
 
     // Do not draw glyph off screen
@@ -1383,10 +1663,10 @@ pub fn draw_glyph4x(
             let test_bit: u8 = 0x80u8 >> draw_glyph_x;
 
             let color = if test_bit & glyph_byte > 0 {
-                color_enum_to_rgba(&fg_color)
+                &palette[fg_color as usize]
             }
             else {
-                color_enum_to_rgba(&bg_color)
+                &palette[bg_color as usize]
             };
 
             let dst_offset = dst_row_offset + ((pos_x * 2) + (draw_glyph_x*2)) * 4;
@@ -1426,7 +1706,8 @@ pub fn draw_glyph2x(
     char_height: u32,
     pos_x: u32, 
     pos_y: u32,
-    font: &FontInfo ) 
+    font: &FontInfo,
+    palette: &[[u8; 4]; 16]) 
 {
 
     // Do not draw glyph off screen
@@ -1459,10 +1740,10 @@ pub fn draw_glyph2x(
             let test_bit: u8 = 0x80u8 >> draw_glyph_x;
 
             let color = if test_bit & glyph_byte > 0 {
-                color_enum_to_rgba(&fg_color)
+                &palette[fg_color as usize]
             }
             else {
-                color_enum_to_rgba(&bg_color)
+                &palette[bg_color as usize]
             };
 
             let dst_offset = dst_row_offset + (pos_x + draw_glyph_x) * 4;
@@ -1480,7 +1761,7 @@ pub fn draw_glyph2x(
     }     
 }
 
-pub fn draw_cursor4x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8], font: &FontInfo ) {
+pub fn draw_cursor4x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8], font: &FontInfo, palette: &[[u8; 4]; 16]) {
         
     // First off, is cursor even visible?
     if !cursor.visible {
@@ -1514,7 +1795,7 @@ pub fn draw_cursor4x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h
     }
     let cursor_attr: u8 = mem[attr_addr];
     let (fg_color, _bg_color) = get_colors_from_attr_byte(cursor_attr);
-    let color = color_enum_to_rgba(&fg_color);
+    let color = &palette[fg_color as usize];
 
     for draw_glyph_y in line_start..line_end {
 
@@ -1549,7 +1830,7 @@ pub fn draw_cursor4x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h
 }
 
 /// Draw the cursor as a character cell into the specified framebuffer with 2x height
-pub fn draw_cursor2x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8] , font: &FontInfo ) {
+pub fn draw_cursor2x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8] , font: &FontInfo, palette: &[[u8; 4]; 16]) {
     
     // First off, is cursor even visible?
     if !cursor.visible {
@@ -1586,7 +1867,7 @@ pub fn draw_cursor2x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h
     }
     let cursor_attr: u8 = mem[attr_addr];
     let (fg_color, _bg_color) = get_colors_from_attr_byte(cursor_attr);
-    let color = color_enum_to_rgba(&fg_color);
+    let color = &palette[fg_color as usize];
 
     for draw_glyph_y in line_start..=line_end {
 
@@ -1612,7 +1893,7 @@ pub fn draw_cursor2x(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h
 }
 
 /// Draw the cursor as a character cell into the specified framebuffer at native height
-pub fn draw_cursor(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8] , font: &FontInfo ) {
+pub fn draw_cursor(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h: u32, mem: &[u8] , font: &FontInfo, palette: &[[u8; 4]; 16]) {
     
     // First off, is cursor even visible?
     if !cursor.visible {
@@ -1649,7 +1930,7 @@ pub fn draw_cursor(cursor: CursorInfo, frame: &mut [u8], frame_w: u32, frame_h:
     }
     let cursor_attr: u8 = mem[attr_addr];
     let (fg_color, _bg_color) = get_colors_from_attr_byte(cursor_attr);
-    let color = color_enum_to_rgba(&fg_color);
+    let color = &palette[fg_color as usize];
 
     for draw_glyph_y in line_start..=line_end {
 
@@ -1676,7 +1957,8 @@ pub fn draw_glyph2x1(
     char_height: u32,
     pos_x: u32, 
     pos_y: u32,
-    font: &FontInfo )
+    font: &FontInfo,
+    palette: &[[u8; 4]; 16])
 {
 
     // Do not draw a glyph off screen
@@ -1707,10 +1989,10 @@ pub fn draw_glyph2x1(
             let test_bit: u8 = 0x80u8 >> draw_glyph_x;
 
             let color = if test_bit & glyph_byte > 0 {
-                color_enum_to_rgba(&fg_color)
+                &palette[fg_color as usize]
             }
             else {
-                color_enum_to_rgba(&bg_color)
+                &palette[bg_color as usize]
             };
 
             let dst_offset = dst_row_offset + (pos_x + draw_glyph_x * 2) * 4;
@@ -1738,7 +2020,8 @@ pub fn draw_glyph1x1(
     char_height: u32,
     pos_x: u32, 
     pos_y: u32,
-    font: &FontInfo )
+    font: &FontInfo,
+    palette: &[[u8; 4]; 16])
 {
 
     // Do not draw glyph off screen
@@ -1769,10 +2052,10 @@ pub fn draw_glyph1x1(
             let test_bit: u8 = 0x80u8 >> draw_glyph_x;
 
             let color = if test_bit & glyph_byte > 0 {
-                color_enum_to_rgba(&fg_color)
+                &palette[fg_color as usize]
             }
             else {
-                color_enum_to_rgba(&bg_color)
+                &palette[bg_color as usize]
             };
 
             let dst_offset = dst_row_offset + (pos_x + draw_glyph_x) * 4;
@@ -0,0 +1,451 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::recorder.rs
+
+    Records rendered frames to an animated GIF or an uncompressed AVI file.
+
+    Frames are fitted to the resolution the recording was started at; if the
+    display resolution changes during a recording, frames are cropped or
+    padded with black. GIFs are recorded at half the display rate, as GIF
+    frame delays are specified in hundredths of a second.
+
+    AVI files are limited to 1GB, the limit for AVI 1.0 files. The recording
+    is ended when the limit is reached.
+
+*/
+
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, RgbaImage,
+};
+
+use marty_core::file_util;
+
+/// Only every GIF_FRAME_DIVISOR'th frame is recorded to a GIF.
+const GIF_FRAME_DIVISOR: u64 = 2;
+/// Speed of color quantization for GIF frames with more than 256 colors (1-30, higher is faster).
+const GIF_QUANTIZE_SPEED: i32 = 10;
+const AVI_MAX_BYTES: u64 = 1 << 30;
+
+const AVI_HEADER_SIZE: u64 = 224;
+const AVI_MOVI_OFFSET: u64 = 220;
+const AVI_TOTAL_FRAMES_OFFSET: u64 = 48;
+const AVI_LENGTH_OFFSET: u64 = 140;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    Gif,
+    Avi,
+}
+
+impl RecordingFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Avi => "avi",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecorderError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    FileTooLarge,
+}
+
+impl Error for RecorderError {}
+impl Display for RecorderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecorderError::Io(e) => write!(f, "{}", e),
+            RecorderError::Image(e) => write!(f, "{}", e),
+            RecorderError::FileTooLarge => write!(f, "The recording reached the maximum file size."),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecorderError {
+    fn from(e: std::io::Error) -> Self {
+        RecorderError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for RecorderError {
+    fn from(e: image::ImageError) -> Self {
+        RecorderError::Image(e)
+    }
+}
+
+/// Writes uncompressed 24-bit AVI files. The headers are written with placeholder sizes,
+/// which are patched when the file is finished.
+struct AviWriter {
+    file: BufWriter<File>,
+    w: u32,
+    h: u32,
+    index: Vec<(u32, u32)>,
+    pos: u64,
+    row_buf: Vec<u8>,
+}
+
+impl AviWriter {
+    fn stride(w: u32) -> u32 {
+        (w * 3).div_ceil(4) * 4
+    }
+
+    fn create(path: &Path, w: u32, h: u32, fps: u32) -> Result<Self, RecorderError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let frame_size = Self::stride(w) * h;
+
+        let mut hdr: Vec<u8> = Vec::with_capacity(AVI_HEADER_SIZE as usize);
+        let fourcc = |hdr: &mut Vec<u8>, cc: &[u8; 4]| hdr.extend_from_slice(cc);
+        let dword = |hdr: &mut Vec<u8>, v: u32| hdr.extend_from_slice(&v.to_le_bytes());
+        let word = |hdr: &mut Vec<u8>, v: u16| hdr.extend_from_slice(&v.to_le_bytes());
+
+        fourcc(&mut hdr, b"RIFF");
+        dword(&mut hdr, 0);
+        fourcc(&mut hdr, b"AVI ");
+
+        fourcc(&mut hdr, b"LIST");
+        dword(&mut hdr, 192);
+        fourcc(&mut hdr, b"hdrl");
+
+        // Main AVI header
+        fourcc(&mut hdr, b"avih");
+        dword(&mut hdr, 56);
+        dword(&mut hdr, 1_000_000 / fps);
+        dword(&mut hdr, frame_size * fps);
+        dword(&mut hdr, 0);
+        dword(&mut hdr, AVIF_HASINDEX);
+        dword(&mut hdr, 0); // Total frames
+        dword(&mut hdr, 0);
+        dword(&mut hdr, 1);
+        dword(&mut hdr, frame_size);
+        dword(&mut hdr, w);
+        dword(&mut hdr, h);
+        for _ in 0..4 {
+            dword(&mut hdr, 0);
+        }
+
+        fourcc(&mut hdr, b"LIST");
+        dword(&mut hdr, 116);
+        fourcc(&mut hdr, b"strl");
+
+        // Video stream header
+        fourcc(&mut hdr, b"strh");
+        dword(&mut hdr, 56);
+        fourcc(&mut hdr, b"vids");
+        fourcc(&mut hdr, b"DIB ");
+        dword(&mut hdr, 0);
+        word(&mut hdr, 0);
+        word(&mut hdr, 0);
+        dword(&mut hdr, 0);
+        dword(&mut hdr, 1);
+        dword(&mut hdr, fps);
+        dword(&mut hdr, 0);
+        dword(&mut hdr, 0); // Length in frames
+        dword(&mut hdr, frame_size);
+        dword(&mut hdr, u32::MAX);
+        dword(&mut hdr, 0);
+        word(&mut hdr, 0);
+        word(&mut hdr, 0);
+        word(&mut hdr, w as u16);
+        word(&mut hdr, h as u16);
+
+        // BITMAPINFOHEADER. A positive height indicates bottom-up rows.
+        fourcc(&mut hdr, b"strf");
+        dword(&mut hdr, 40);
+        dword(&mut hdr, 40);
+        dword(&mut hdr, w);
+        dword(&mut hdr, h);
+        word(&mut hdr, 1);
+        word(&mut hdr, 24);
+        dword(&mut hdr, 0);
+        dword(&mut hdr, frame_size);
+        for _ in 0..4 {
+            dword(&mut hdr, 0);
+        }
+
+        fourcc(&mut hdr, b"LIST");
+        dword(&mut hdr, 0);
+        fourcc(&mut hdr, b"movi");
+
+        debug_assert_eq!(hdr.len() as u64, AVI_HEADER_SIZE);
+        file.write_all(&hdr)?;
+
+        Ok(Self {
+            file,
+            w,
+            h,
+            index: Vec::new(),
+            pos: AVI_HEADER_SIZE,
+            row_buf: vec![0; Self::stride(w) as usize],
+        })
+    }
+
+    fn write_frame(&mut self, rgba: &[u8]) -> Result<(), RecorderError> {
+        let stride = Self::stride(self.w);
+        let frame_size = stride * self.h;
+
+        if self.pos + 8 + frame_size as u64 + 16 * (self.index.len() as u64 + 1) > AVI_MAX_BYTES {
+            return Err(RecorderError::FileTooLarge);
+        }
+
+        self.index.push(((self.pos - AVI_MOVI_OFFSET) as u32, frame_size));
+
+        self.file.write_all(b"00db")?;
+        self.file.write_all(&frame_size.to_le_bytes())?;
+        for y in (0..self.h as usize).rev() {
+            let row = &rgba[y * self.w as usize * 4..(y + 1) * self.w as usize * 4];
+            for (dst, src) in self.row_buf.chunks_exact_mut(3).zip(row.chunks_exact(4)) {
+                dst[0] = src[2];
+                dst[1] = src[1];
+                dst[2] = src[0];
+            }
+            self.file.write_all(&self.row_buf)?;
+        }
+        self.pos += 8 + frame_size as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), RecorderError> {
+        let movi_size = (self.pos - AVI_MOVI_OFFSET) as u32;
+
+        self.file.write_all(b"idx1")?;
+        self.file.write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for (offset, size) in &self.index {
+            self.file.write_all(b"00db")?;
+            self.file.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.file.write_all(&offset.to_le_bytes())?;
+            self.file.write_all(&size.to_le_bytes())?;
+        }
+        let file_size = self.pos + 8 + self.index.len() as u64 * 16;
+        let frames = self.index.len() as u32;
+
+        let patches = [
+            (4, (file_size - 8) as u32),
+            (AVI_TOTAL_FRAMES_OFFSET, frames),
+            (AVI_LENGTH_OFFSET, frames),
+            (AVI_MOVI_OFFSET - 4, movi_size),
+        ];
+        for (offset, value) in patches {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+enum RecorderBackend {
+    Gif(GifEncoder<BufWriter<File>>),
+    Avi(AviWriter),
+}
+
+/// Records frames to a uniquely named file in the specified directory.
+pub struct ScreenRecorder {
+    format: RecordingFormat,
+    path: PathBuf,
+    w: u32,
+    h: u32,
+    frames: u64,
+    written: u64,
+    fit_buf: Vec<u8>,
+    pending_gif: Option<RgbaImage>,
+    backend: RecorderBackend,
+}
+
+impl ScreenRecorder {
+    pub fn start(dir: &Path, format: RecordingFormat, w: u32, h: u32, fps: u32) -> Result<Self, RecorderError> {
+        let path = file_util::find_unique_filename(dir, "recording", format.extension());
+
+        let backend = match format {
+            RecordingFormat::Gif => {
+                let file = BufWriter::new(File::create(&path)?);
+                let mut encoder = GifEncoder::new_with_speed(file, GIF_QUANTIZE_SPEED);
+                encoder.set_repeat(Repeat::Infinite)?;
+                RecorderBackend::Gif(encoder)
+            }
+            RecordingFormat::Avi => RecorderBackend::Avi(AviWriter::create(&path, w, h, fps)?),
+        };
+
+        log::debug!("Started {:?} recording at {}x{}: {}", format, w, h, path.display());
+
+        Ok(Self {
+            format,
+            path,
+            w,
+            h,
+            frames: 0,
+            written: 0,
+            fit_buf: vec![0; (w * h * 4) as usize],
+            pending_gif: None,
+            backend,
+        })
+    }
+
+    pub fn format(&self) -> RecordingFormat {
+        self.format
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the number of frames written to the file.
+    pub fn frames_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Copy a frame into the recording's resolution, cropping or padding as needed.
+    fn fit_frame(&mut self, frame: &[u8], w: u32, h: u32) {
+        if (w, h) == (self.w, self.h) {
+            let len = self.fit_buf.len();
+            self.fit_buf.copy_from_slice(&frame[..len]);
+            return;
+        }
+        self.fit_buf.fill(0);
+        let copy_w = w.min(self.w) as usize * 4;
+        for y in 0..h.min(self.h) as usize {
+            let src = y * w as usize * 4;
+            let dst = y * self.w as usize * 4;
+            self.fit_buf[dst..dst + copy_w].copy_from_slice(&frame[src..src + copy_w]);
+        }
+        for pixel in self.fit_buf.chunks_exact_mut(4) {
+            pixel[3] = 0xFF;
+        }
+    }
+
+    /// Return the GIF frame delay in hundredths of a second for the nth recorded GIF frame,
+    /// spreading the rounding error over successive frames.
+    fn gif_delay_cs(n: u64) -> u32 {
+        let cs = |n: u64| (n * GIF_FRAME_DIVISOR * 100 + 30) / 60;
+        (cs(n + 1) - cs(n)) as u32
+    }
+
+    /// Add a rendered RGBA frame of the specified dimensions to the recording. Call once per
+    /// displayed frame.
+    pub fn add_frame(&mut self, frame: &[u8], w: u32, h: u32) -> Result<(), RecorderError> {
+        let frame_n = self.frames;
+        self.frames += 1;
+
+        match self.format {
+            RecordingFormat::Gif => {
+                if !frame_n.is_multiple_of(GIF_FRAME_DIVISOR) {
+                    return Ok(());
+                }
+                self.fit_frame(frame, w, h);
+                let image = RgbaImage::from_raw(self.w, self.h, self.fit_buf.clone())
+                    .expect("Frame buffer size mismatch.");
+
+                // A frame's delay is the time until the next frame, so each frame is written
+                // when the next one arrives.
+                if let Some(prev) = self.pending_gif.replace(image) {
+                    self.write_gif_frame(prev)?;
+                }
+            }
+            RecordingFormat::Avi => {
+                self.fit_frame(frame, w, h);
+                if let RecorderBackend::Avi(avi) = &mut self.backend {
+                    avi.write_frame(&self.fit_buf)?;
+                }
+                self.written += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_gif_frame(&mut self, image: RgbaImage) -> Result<(), RecorderError> {
+        let delay = Delay::from_numer_denom_ms(Self::gif_delay_cs(self.written) * 10, 1);
+        if let RecorderBackend::Gif(encoder) = &mut self.backend {
+            encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Finish the recording and close the file. Returns the path of the file written.
+    pub fn finish(mut self) -> Result<PathBuf, RecorderError> {
+        if let Some(last) = self.pending_gif.take() {
+            self.write_gif_frame(last)?;
+        }
+        match self.backend {
+            RecorderBackend::Gif(encoder) => drop(encoder),
+            RecorderBackend::Avi(avi) => avi.finish()?,
+        }
+        log::debug!("Finished recording of {} frames: {}", self.written, self.path.display());
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gif_delays() {
+        // Recording every other frame at 60Hz, delays should average 1/30th of a second.
+        let total: u32 = (0..30).map(ScreenRecorder::gif_delay_cs).sum();
+        assert_eq!(total, 100);
+        assert!((0..30).all(|n| (3..=4).contains(&ScreenRecorder::gif_delay_cs(n))));
+    }
+
+    #[test]
+    fn test_avi_recording() {
+        let dir = std::env::temp_dir().join(format!("marty_recorder_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut recorder = ScreenRecorder::start(&dir, RecordingFormat::Avi, 6, 2, 60).unwrap();
+        let frame = vec![0x80; 6 * 2 * 4];
+        recorder.add_frame(&frame, 6, 2).unwrap();
+        // A larger frame is cropped to the recording size.
+        recorder.add_frame(&[0x40; 8 * 3 * 4], 8, 3).unwrap();
+        let path = recorder.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        // 6 pixels * 3 bytes is padded to a 20 byte stride.
+        let frame_size = 20 * 2;
+        let expected_len = AVI_HEADER_SIZE as usize + 2 * (8 + frame_size) + 8 + 2 * 16;
+        assert_eq!(data.len(), expected_len);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize, expected_len - 8);
+        assert_eq!(u32::from_le_bytes(data[48..52].try_into().unwrap()), 2);
+        assert_eq!(&data[AVI_HEADER_SIZE as usize..AVI_HEADER_SIZE as usize + 4], b"00db");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
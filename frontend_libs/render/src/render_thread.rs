@@ -0,0 +1,385 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::render_thread.rs
+
+    Renders the display buffers of cards in Direct Mode on a dedicated worker
+    thread.
+
+    The emulation thread submits a copy of the card's display buffer each
+    frame and picks up the most recently completed frame, so a slow frame
+    (such as one with composite conversion) never stalls emulation. Jobs and
+    frames are passed between threads through triple buffers: the writer
+    always has a free buffer to fill and the reader always has a complete
+    one to read, so neither side waits on the other. A frame that is replaced
+    before it is read is dropped.
+
+    Cards in Indirect Mode read VRAM over the bus while drawing, so they are
+    still drawn on the emulation thread.
+
+*/
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use marty_core::{
+    config::VideoType,
+    videocard::DisplayExtents
+};
+
+use crate::{CompositeCapture, CompositeParams, VideoRenderer};
+
+/// How long the worker waits for a job before checking for shutdown.
+const WORKER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+struct TripleBufferShared<T> {
+    // The most recently published value, and whether the reader has yet to see it.
+    middle: Mutex<(T, bool)>,
+    published: Condvar,
+}
+
+/// The writing half of a triple buffer.
+pub struct TripleBufferWriter<T> {
+    back: T,
+    shared: Arc<TripleBufferShared<T>>,
+}
+
+/// The reading half of a triple buffer.
+pub struct TripleBufferReader<T> {
+    front: T,
+    shared: Arc<TripleBufferShared<T>>,
+}
+
+/// Create a triple buffer from three initial values. The reader starts with `front`.
+pub fn triple_buffer<T>(back: T, middle: T, front: T) -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    let shared = Arc::new(TripleBufferShared {
+        middle: Mutex::new((middle, false)),
+        published: Condvar::new(),
+    });
+
+    (
+        TripleBufferWriter { back, shared: shared.clone() },
+        TripleBufferReader { front, shared },
+    )
+}
+
+impl<T> TripleBufferWriter<T> {
+    /// Return the buffer to be written before the next call to publish().
+    pub fn back_mut(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    /// Make the back buffer available to the reader, replacing any value it has not read.
+    pub fn publish(&mut self) {
+        let mut middle = self.shared.middle.lock().unwrap();
+        std::mem::swap(&mut self.back, &mut middle.0);
+        middle.1 = true;
+        self.shared.published.notify_one();
+    }
+}
+
+impl<T> TripleBufferReader<T> {
+    /// Swap in the latest published value, if there is one. Returns true if the front buffer
+    /// was updated.
+    pub fn update(&mut self) -> bool {
+        let mut middle = self.shared.middle.lock().unwrap();
+        if middle.1 {
+            std::mem::swap(&mut self.front, &mut middle.0);
+            middle.1 = false;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Like update(), but wait up to `timeout` for a value to be published.
+    pub fn wait_update(&mut self, timeout: Duration) -> bool {
+        let middle = self.shared.middle.lock().unwrap();
+        let (mut middle, _) = self.shared.published.wait_timeout_while(middle, timeout, |m| !m.1).unwrap();
+        if middle.1 {
+            std::mem::swap(&mut self.front, &mut middle.0);
+            middle.1 = false;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    pub fn front(&self) -> &T {
+        &self.front
+    }
+
+    pub fn front_mut(&mut self) -> &mut T {
+        &mut self.front
+    }
+}
+
+/// A snapshot of a video card's display buffer to be rendered.
+#[derive(Clone)]
+pub struct RenderJob {
+    pub video_type: VideoType,
+    pub w: u32,
+    pub h: u32,
+    pub dbuf: Vec<u8>,
+    pub extents: DisplayExtents,
+    pub composite: bool,
+    pub composite_params: CompositeParams,
+    pub beam_pos: Option<(u32, u32)>,
+}
+
+impl RenderJob {
+    fn new(video_type: VideoType) -> Self {
+        Self {
+            video_type,
+            w: 0,
+            h: 0,
+            dbuf: Vec::new(),
+            extents: Default::default(),
+            composite: false,
+            composite_params: Default::default(),
+            beam_pos: None,
+        }
+    }
+}
+
+/// A rendered RGBA frame.
+#[derive(Default)]
+pub struct RenderedFrame {
+    pub w: u32,
+    pub h: u32,
+    pub buf: Vec<u8>,
+    /// A composite capture completed while rendering this frame, if one was requested.
+    pub capture: Option<CompositeCapture>,
+    /// Time the renderer took to draw this frame.
+    pub render_time: Duration,
+}
+
+enum WorkerMode {
+    Threaded {
+        jobs: TripleBufferWriter<RenderJob>,
+        shutdown: Arc<AtomicBool>,
+        handle: Option<JoinHandle<()>>,
+    },
+    Inline {
+        job: RenderJob,
+        frames: TripleBufferWriter<RenderedFrame>,
+    },
+}
+
+/// Renders frames from a card in Direct Mode, either on a worker thread or on the calling
+/// thread as each frame is submitted.
+pub struct RenderThread {
+    renderer: Arc<Mutex<VideoRenderer>>,
+    mode: WorkerMode,
+    frames: TripleBufferReader<RenderedFrame>,
+}
+
+impl RenderThread {
+    /// Create a render thread for the specified renderer. If `threaded` is false, frames are
+    /// rendered inline by submit().
+    pub fn new(renderer: VideoRenderer, video_type: VideoType, threaded: bool) -> Self {
+        let renderer = Arc::new(Mutex::new(renderer));
+        let (frame_writer, frames) = triple_buffer(Default::default(), Default::default(), Default::default());
+
+        let mode = match threaded {
+            true => {
+                let (jobs, job_reader) = triple_buffer(
+                    RenderJob::new(video_type),
+                    RenderJob::new(video_type),
+                    RenderJob::new(video_type)
+                );
+                let shutdown = Arc::new(AtomicBool::new(false));
+
+                let worker_renderer = renderer.clone();
+                let worker_shutdown = shutdown.clone();
+                let handle = thread::Builder::new()
+                    .name("render".to_string())
+                    .spawn(move || run_worker(worker_renderer, job_reader, frame_writer, worker_shutdown))
+                    .expect("Failed to spawn render thread");
+
+                WorkerMode::Threaded { jobs, shutdown, handle: Some(handle) }
+            }
+            false => WorkerMode::Inline { job: RenderJob::new(video_type), frames: frame_writer }
+        };
+
+        Self {
+            renderer,
+            mode,
+            frames,
+        }
+    }
+
+    pub fn is_threaded(&self) -> bool {
+        matches!(self.mode, WorkerMode::Threaded { .. })
+    }
+
+    /// Lock the renderer to change its settings, or to draw a card in Indirect Mode. This
+    /// waits for any frame the worker is rendering.
+    pub fn renderer(&self) -> MutexGuard<'_, VideoRenderer> {
+        self.renderer.lock().unwrap()
+    }
+
+    /// Submit a card's display buffer to be rendered into a frame of the specified size.
+    pub fn submit(
+        &mut self,
+        video_type: VideoType,
+        w: u32,
+        h: u32,
+        dbuf: &[u8],
+        extents: &DisplayExtents,
+        composite: bool,
+        composite_params: &CompositeParams,
+        beam_pos: Option<(u32, u32)>
+    ) {
+        let job = match &mut self.mode {
+            WorkerMode::Threaded { jobs, .. } => jobs.back_mut(),
+            WorkerMode::Inline { job, .. } => job,
+        };
+
+        job.video_type = video_type;
+        job.w = w;
+        job.h = h;
+        job.dbuf.clear();
+        job.dbuf.extend_from_slice(dbuf);
+        job.extents = *extents;
+        job.composite = composite;
+        job.composite_params = *composite_params;
+        job.beam_pos = beam_pos;
+
+        match &mut self.mode {
+            WorkerMode::Threaded { jobs, .. } => jobs.publish(),
+            WorkerMode::Inline { job, frames } => {
+                render_job(&mut self.renderer.lock().unwrap(), job, frames.back_mut());
+                frames.publish();
+            }
+        }
+    }
+
+    /// Return the most recently rendered frame if one has completed since the last call.
+    pub fn take_frame(&mut self) -> Option<&mut RenderedFrame> {
+        match self.frames.update() {
+            true => Some(self.frames.front_mut()),
+            false => None
+        }
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        if let WorkerMode::Threaded { shutdown, handle, .. } = &mut self.mode {
+            shutdown.store(true, Ordering::Relaxed);
+            if let Some(handle) = handle.take() {
+                _ = handle.join();
+            }
+        }
+    }
+}
+
+fn run_worker(
+    renderer: Arc<Mutex<VideoRenderer>>,
+    mut jobs: TripleBufferReader<RenderJob>,
+    mut frames: TripleBufferWriter<RenderedFrame>,
+    shutdown: Arc<AtomicBool>
+) {
+    while !shutdown.load(Ordering::Relaxed) {
+        if jobs.wait_update(WORKER_POLL_INTERVAL) {
+            render_job(&mut renderer.lock().unwrap(), jobs.front(), frames.back_mut());
+            frames.publish();
+        }
+    }
+}
+
+fn render_job(renderer: &mut VideoRenderer, job: &RenderJob, frame: &mut RenderedFrame) {
+    let render_start = Instant::now();
+
+    // Buffers are recycled between frames; only clear one when the frame size changes.
+    if (frame.w, frame.h) != (job.w, job.h) {
+        frame.w = job.w;
+        frame.h = job.h;
+        frame.buf.clear();
+        frame.buf.resize((job.w * job.h * 4) as usize, 0);
+    }
+
+    match job.video_type {
+        VideoType::EGA => {
+            renderer.draw_ega_direct(&mut frame.buf, job.w, job.h, &job.dbuf, &job.extents);
+        }
+        VideoType::VGA => {
+            renderer.draw_vga_direct(&mut frame.buf, job.w, job.h, &job.dbuf, &job.extents);
+        }
+        _ => {
+            renderer.draw_cga_direct(
+                &mut frame.buf,
+                job.w,
+                job.h,
+                &job.dbuf,
+                &job.extents,
+                job.composite,
+                &job.composite_params,
+                job.beam_pos
+            );
+        }
+    }
+
+    frame.capture = renderer.take_composite_capture();
+    frame.render_time = render_start.elapsed();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triple_buffer() {
+        let (mut writer, mut reader) = triple_buffer(0, 0, 0);
+        assert!(!reader.update());
+
+        // Only the latest published value is read
+        *writer.back_mut() = 1;
+        writer.publish();
+        *writer.back_mut() = 2;
+        writer.publish();
+        assert!(reader.update());
+        assert_eq!(*reader.front(), 2);
+        assert!(!reader.update());
+
+        let handle = thread::spawn(move || {
+            *writer.back_mut() = 3;
+            writer.publish();
+        });
+        assert!(reader.wait_update(Duration::from_secs(5)));
+        assert_eq!(*reader.front(), 3);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::scaling.rs
+
+    Calculates where the display buffer is drawn within the window for each
+    display scaling mode. Integer scaling keeps every emulated pixel the same
+    size; fit scales as large as possible while keeping the buffer's aspect
+    ratio; stretch fills the window. Any space left over is split evenly
+    between the margins on either side.
+
+*/
+
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ScalingMode {
+    /// Scale by the largest whole number that fits the window.
+    Integer,
+    /// Scale as large as possible while preserving the aspect ratio.
+    #[default]
+    Fit,
+    /// Fill the window, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl ScalingMode {
+    pub const ALL: [ScalingMode; 3] = [ScalingMode::Integer, ScalingMode::Fit, ScalingMode::Stretch];
+}
+
+impl Display for ScalingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScalingMode::Integer => write!(f, "Integer"),
+            ScalingMode::Fit => write!(f, "Fit (Correct Aspect)"),
+            ScalingMode::Stretch => write!(f, "Stretch"),
+        }
+    }
+}
+
+impl FromStr for ScalingMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        match s.to_lowercase().as_str() {
+            "integer" => Ok(ScalingMode::Integer),
+            "fit" => Ok(ScalingMode::Fit),
+            "stretch" => Ok(ScalingMode::Stretch),
+            _ => Err("Bad value for scaling mode".to_string()),
+        }
+    }
+}
+
+/// The area of the window the display buffer is drawn to. x and y are the 
+/// left and top margins.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DisplayRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Calculate the area of a surface of the given size that a buffer of the given size
+/// should be drawn to.
+pub fn get_display_rect(buffer: (u32, u32), surface: (u32, u32), mode: ScalingMode) -> DisplayRect {
+    let (buf_w, buf_h) = (buffer.0.max(1), buffer.1.max(1));
+    let (surf_w, surf_h) = surface;
+
+    let (w, h) = match mode {
+        ScalingMode::Stretch => (surf_w, surf_h),
+        ScalingMode::Fit | ScalingMode::Integer => {
+            let scale = (surf_w as f64 / buf_w as f64).min(surf_h as f64 / buf_h as f64);
+            // Integer scaling falls back to fitting if the window is smaller than the buffer.
+            let scale = if mode == ScalingMode::Integer && scale >= 1.0 { scale.floor() } else { scale };
+            (
+                ((buf_w as f64 * scale) as u32).min(surf_w),
+                ((buf_h as f64 * scale) as u32).min(surf_h),
+            )
+        }
+    };
+
+    DisplayRect {
+        x: (surf_w - w) / 2,
+        y: (surf_h - h) / 2,
+        w,
+        h,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_rect() {
+        let rect = get_display_rect((640, 480), (1600, 1000), ScalingMode::Integer);
+        assert_eq!(rect, DisplayRect { x: 160, y: 20, w: 1280, h: 960 });
+
+        let rect = get_display_rect((640, 480), (1600, 1000), ScalingMode::Fit);
+        assert_eq!(rect, DisplayRect { x: 133, y: 0, w: 1333, h: 1000 });
+
+        let rect = get_display_rect((640, 480), (1600, 1000), ScalingMode::Stretch);
+        assert_eq!(rect, DisplayRect { x: 0, y: 0, w: 1600, h: 1000 });
+
+        // A window smaller than the buffer can't be integer scaled.
+        let rect = get_display_rect((640, 480), (320, 480), ScalingMode::Integer);
+        assert_eq!(rect, DisplayRect { x: 0, y: 120, w: 320, h: 240 });
+    }
+}
@@ -0,0 +1,402 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::tile_ripper.rs
+
+    Decodes arbitrary regions of guest memory as a sheet of fixed-size tiles,
+    for locating and extracting sprites and tile graphics. Tiles may be packed
+    pixel data of 1, 2, 4 or 8 bits per pixel, or 4-plane EGA-style planar 
+    data.
+
+*/
+
+use std::path::{Path, PathBuf};
+
+use marty_core::{
+    videocard::VideoCard,
+    file_util
+};
+
+use crate::{get_cga_gfx_color, get_ega_gfx_color16};
+
+pub const TILE_MAX_DIM: u32 = 64;
+pub const TILE_MAX_COUNT: u32 = 1024;
+
+/// Border color drawn between tiles in a tile sheet.
+const SHEET_GRID_COLOR: [u8; 4] = [0x40, 0x40, 0x40, 0xFF];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileFormat {
+    Packed1,
+    Packed2,
+    Packed4,
+    Packed8,
+    Planar4,
+}
+
+impl TileFormat {
+    pub const ALL: [TileFormat; 5] = [
+        TileFormat::Packed1,
+        TileFormat::Packed2,
+        TileFormat::Packed4,
+        TileFormat::Packed8,
+        TileFormat::Planar4,
+    ];
+
+    pub fn bits_per_pixel(&self) -> u32 {
+        match self {
+            TileFormat::Packed1 => 1,
+            TileFormat::Packed2 => 2,
+            TileFormat::Packed4 | TileFormat::Planar4 => 4,
+            TileFormat::Packed8 => 8,
+        }
+    }
+
+    pub fn desc(&self) -> &'static str {
+        match self {
+            TileFormat::Packed1 => "1bpp",
+            TileFormat::Packed2 => "2bpp (CGA)",
+            TileFormat::Packed4 => "4bpp packed",
+            TileFormat::Packed8 => "8bpp",
+            TileFormat::Planar4 => "4bpp planar (EGA)",
+        }
+    }
+}
+
+/// The data a tile sheet is decoded from. Linear sources are a flat run of bytes, such as
+/// conventional memory. Planar sources are the four bitplanes of an EGA or VGA card.
+pub enum TileSource<'a> {
+    Linear(&'a [u8]),
+    Planes([&'a [u8]; 4]),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileLayout {
+    pub format: TileFormat,
+    pub width: u32,
+    pub height: u32,
+    pub columns: u32,
+    pub count: u32,
+}
+
+impl Default for TileLayout {
+    fn default() -> Self {
+        Self {
+            format: TileFormat::Packed2,
+            width: 16,
+            height: 16,
+            columns: 16,
+            count: 128,
+        }
+    }
+}
+
+impl TileLayout {
+
+    /// Number of bytes in one row of a tile. For planar tiles, this is the size of one row
+    /// in a single plane.
+    pub fn row_bytes(&self) -> usize {
+        match self.format {
+            TileFormat::Planar4 => self.width.div_ceil(8) as usize,
+            _ => (self.width * self.format.bits_per_pixel()).div_ceil(8) as usize
+        }
+    }
+
+    /// Number of bytes one tile occupies in a linear source. Planar tiles in a linear source
+    /// are stored with the rows of each plane interleaved.
+    pub fn tile_bytes(&self) -> usize {
+        match self.format {
+            TileFormat::Planar4 => self.row_bytes() * 4 * self.height as usize,
+            _ => self.row_bytes() * self.height as usize
+        }
+    }
+
+    /// Number of bytes one tile occupies in each plane of a planar source.
+    pub fn tile_plane_bytes(&self) -> usize {
+        self.row_bytes() * self.height as usize
+    }
+
+    fn clamped(&self) -> TileLayout {
+        TileLayout {
+            format: self.format,
+            width: self.width.clamp(1, TILE_MAX_DIM),
+            height: self.height.clamp(1, TILE_MAX_DIM),
+            columns: self.columns.max(1),
+            count: self.count.clamp(1, TILE_MAX_COUNT),
+        }
+    }
+}
+
+/// A decoded sheet of tiles in RGBA format. Tiles are separated by a one pixel grid.
+#[derive(Clone, Default, PartialEq)]
+pub struct TileSheet {
+    pub layout: Option<TileLayout>,
+    pub w: u32,
+    pub h: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl TileSheet {
+
+    /// Return the tile index at the specified pixel coordinate in the sheet, if any.
+    pub fn tile_at(&self, x: u32, y: u32) -> Option<u32> {
+        let layout = self.layout?;
+        let col = x / (layout.width + 1);
+        let row = y / (layout.height + 1);
+        let idx = row * layout.columns + col;
+
+        if col < layout.columns && idx < layout.count {
+            Some(idx)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Extract a single tile from the sheet as an RGBA buffer without the grid.
+    pub fn tile_rgba(&self, idx: u32) -> Option<Vec<u8>> {
+        let layout = self.layout?;
+        if idx >= layout.count {
+            return None
+        }
+
+        let x0 = (idx % layout.columns) * (layout.width + 1) + 1;
+        let y0 = (idx / layout.columns) * (layout.height + 1) + 1;
+        let mut buf = Vec::with_capacity((layout.width * layout.height * 4) as usize);
+
+        for y in y0..(y0 + layout.height) {
+            let start = ((y * self.w + x0) * 4) as usize;
+            buf.extend_from_slice(&self.rgba[start..start + (layout.width * 4) as usize]);
+        }
+        Some(buf)
+    }
+
+    /// Save the entire sheet, or a single selected tile, as a PNG file in the specified 
+    /// directory. Returns the path of the file written.
+    pub fn save_png(&self, path: &Path, tile: Option<u32>) -> Result<PathBuf, image::ImageError> {
+
+        let (buf, w, h) = match (tile, self.layout) {
+            (Some(idx), Some(layout)) => {
+                let buf = self.tile_rgba(idx).unwrap_or_default();
+                (buf, layout.width, layout.height)
+            }
+            _ => (self.rgba.clone(), self.w, self.h)
+        };
+
+        let filename = file_util::find_unique_filename(path, "tiles", "png");
+        image::save_buffer(filename.clone(), &buf, w, h, image::ColorType::Rgba8)?;
+        Ok(filename)
+    }
+}
+
+/// Build a palette suitable for previewing tiles of the given format, based on the current 
+/// state of the active video card.
+pub fn ripper_palette(card: Option<&dyn VideoCard>, format: TileFormat) -> Vec<[u8; 4]> {
+
+    match format {
+        TileFormat::Packed1 => {
+            vec![[0x00, 0x00, 0x00, 0xFF], [0xFF, 0xFF, 0xFF, 0xFF]]
+        }
+        TileFormat::Packed2 => {
+            match card {
+                Some(card) => {
+                    let (palette, intensity) = card.get_cga_palette();
+                    (0..4u8).map(|i| {
+                        let mut color = *get_cga_gfx_color(i, &palette, intensity);
+                        // Monochrome palettes use a transparent background
+                        color[3] = 0xFF;
+                        color
+                    }).collect()
+                }
+                _ => (0..4u8).map(|i| [i * 0x55, i * 0x55, i * 0x55, 0xFF]).collect()
+            }
+        }
+        TileFormat::Packed4 | TileFormat::Planar4 => {
+            // Map IRGB to the EGA's default RGBrgb palette
+            (0..16u8).map(|i| *get_ega_gfx_color16((i & 0x07) | ((i & 0x08) << 1))).collect()
+        }
+        TileFormat::Packed8 => {
+            // No attribute palette is available for 256 color data, so show a grey ramp
+            (0..=255u8).map(|i| [i, i, i, 0xFF]).collect()
+        }
+    }
+}
+
+#[inline]
+fn packed_pixel(data: &[u8], row_start: usize, x: u32, bpp: u32) -> u8 {
+    let bit_offset = x * bpp;
+    let byte = match data.get(row_start + (bit_offset / 8) as usize) {
+        Some(b) => *b,
+        None => return 0
+    };
+    let shift = 8 - bpp - (bit_offset % 8);
+    (byte >> shift) & ((1u16 << bpp) - 1) as u8
+}
+
+#[inline]
+fn planar_pixel(planes: [&[u8]; 4], row_starts: [usize; 4], x: u32) -> u8 {
+    let byte_offset = (x / 8) as usize;
+    let shift = 7 - (x % 8);
+    let mut pixel = 0;
+    for (i, plane) in planes.iter().enumerate() {
+        let byte = plane.get(row_starts[i] + byte_offset).copied().unwrap_or(0);
+        pixel |= ((byte >> shift) & 0x01) << i;
+    }
+    pixel
+}
+
+/// Decode `layout.count` tiles starting at `offset` into the source, arranged in a sheet 
+/// `layout.columns` tiles wide. Data past the end of the source decodes as color 0.
+pub fn rip_tiles(source: &TileSource, offset: usize, layout: &TileLayout, palette: &[[u8; 4]]) -> TileSheet {
+
+    let layout = layout.clamped();
+    let columns = layout.columns.min(layout.count);
+    let rows = layout.count.div_ceil(columns);
+
+    let w = columns * (layout.width + 1) + 1;
+    let h = rows * (layout.height + 1) + 1;
+    let mut rgba = SHEET_GRID_COLOR.repeat((w * h) as usize);
+
+    let bpp = layout.format.bits_per_pixel();
+    let row_bytes = layout.row_bytes();
+    let black = [0x00, 0x00, 0x00, 0xFF];
+
+    for tile in 0..layout.count {
+        let x0 = (tile % columns) * (layout.width + 1) + 1;
+        let y0 = (tile / columns) * (layout.height + 1) + 1;
+
+        for ty in 0..layout.height {
+            let ty_usize = ty as usize;
+
+            for tx in 0..layout.width {
+                let pixel = match (source, layout.format) {
+                    (TileSource::Linear(data), TileFormat::Planar4) => {
+                        let tile_start = offset + tile as usize * layout.tile_bytes();
+                        let row_start = tile_start + ty_usize * row_bytes * 4;
+                        let starts = [0, 1, 2, 3].map(|p| row_start + p * row_bytes);
+                        planar_pixel([data; 4], starts, tx)
+                    }
+                    (TileSource::Planes(planes), TileFormat::Planar4) => {
+                        let row_start = offset + tile as usize * layout.tile_plane_bytes() + ty_usize * row_bytes;
+                        planar_pixel(*planes, [row_start; 4], tx)
+                    }
+                    (TileSource::Linear(data), _) => {
+                        let row_start = offset + tile as usize * layout.tile_bytes() + ty_usize * row_bytes;
+                        packed_pixel(data, row_start, tx, bpp)
+                    }
+                    (TileSource::Planes(planes), _) => {
+                        // Packed formats read from the first plane only
+                        let row_start = offset + tile as usize * layout.tile_bytes() + ty_usize * row_bytes;
+                        packed_pixel(planes[0], row_start, tx, bpp)
+                    }
+                };
+
+                let color = palette.get(pixel as usize).unwrap_or(&black);
+                let o = (((y0 + ty) * w + x0 + tx) * 4) as usize;
+                rgba[o..o + 4].copy_from_slice(color);
+            }
+        }
+    }
+
+    TileSheet {
+        layout: Some(TileLayout { columns, ..layout }),
+        w,
+        h,
+        rgba,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAL4: [[u8; 4]; 4] = [
+        [0x00, 0x00, 0x00, 0xFF],
+        [0x55, 0xFF, 0xFF, 0xFF],
+        [0xFF, 0x55, 0xFF, 0xFF],
+        [0xFF, 0xFF, 0xFF, 0xFF],
+    ];
+
+    fn pixel(sheet: &TileSheet, x: u32, y: u32) -> [u8; 4] {
+        let o = ((y * sheet.w + x) * 4) as usize;
+        sheet.rgba[o..o + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_packed2_decode() {
+        // Two 4x1 tiles of 2bpp data: 0,1,2,3 and 3,2,1,0
+        let data = [0b00_01_10_11, 0b11_10_01_00];
+        let layout = TileLayout {
+            format: TileFormat::Packed2,
+            width: 4,
+            height: 1,
+            columns: 2,
+            count: 2,
+        };
+        let sheet = rip_tiles(&TileSource::Linear(&data), 0, &layout, &PAL4);
+
+        assert_eq!((sheet.w, sheet.h), (2 * 5 + 1, 3));
+        for x in 0..4 {
+            assert_eq!(pixel(&sheet, 1 + x, 1), PAL4[x as usize]);
+            assert_eq!(pixel(&sheet, 6 + x, 1), PAL4[3 - x as usize]);
+        }
+        // Grid between tiles
+        assert_eq!(pixel(&sheet, 5, 1), SHEET_GRID_COLOR);
+
+        assert_eq!(sheet.tile_at(1, 1), Some(0));
+        assert_eq!(sheet.tile_at(7, 1), Some(1));
+        assert_eq!(sheet.tile_rgba(1).unwrap()[0..4], PAL4[3]);
+    }
+
+    #[test]
+    fn test_planar_sources_match() {
+        // One 8x1 planar tile, stored as separate planes and as interleaved linear rows.
+        let planes = [[0b1000_0001u8], [0b0100_0001], [0b0010_0001], [0b0001_0001]];
+        let linear: Vec<u8> = planes.iter().map(|p| p[0]).collect();
+        let layout = TileLayout {
+            format: TileFormat::Planar4,
+            width: 8,
+            height: 1,
+            columns: 1,
+            count: 1,
+        };
+        let palette: Vec<[u8; 4]> = (0..16u8).map(|i| [i, i, i, 0xFF]).collect();
+
+        let from_planes = rip_tiles(
+            &TileSource::Planes([&planes[0], &planes[1], &planes[2], &planes[3]]),
+            0,
+            &layout,
+            &palette,
+        );
+        let from_linear = rip_tiles(&TileSource::Linear(&linear), 0, &layout, &palette);
+
+        assert!(from_planes == from_linear);
+        let expected = [1u8, 2, 4, 8, 0, 0, 0, 15];
+        for (x, c) in expected.iter().enumerate() {
+            assert_eq!(pixel(&from_planes, 1 + x as u32, 1)[0], *c);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    render::vram_viewer.rs
+
+    Draws the contents of a video card's memory as images for inspection,
+    regardless of the mode the card is in: every text page, the two
+    interleaved scanline banks of the CGA, each bitplane of the EGA and VGA,
+    and the font glyph sets.
+
+    Each image is divided into a grid of cells that map back to the memory
+    they were drawn from, so that the viewer can describe the memory under
+    the mouse.
+
+*/
+
+use marty_core::videocard::FontInfo;
+
+use crate::CGA_RGBA_COLORS;
+
+/// Text pages are drawn at most this many rows tall.
+pub const VRAM_TEXT_ROWS: u32 = 25;
+/// The maximum number of text pages drawn.
+pub const VRAM_MAX_PAGES: usize = 8;
+/// Bitplanes are drawn at most this many rows tall.
+pub const VRAM_MAX_PLANE_ROWS: u32 = 1024;
+
+/// Offsets of the font blocks in plane 2 of the EGA, selected by the Character Map Select
+/// register.
+pub const EGA_FONT_BLOCKS: [usize; 4] = [0x0000, 0x4000, 0x8000, 0xC000];
+/// The VGA adds four more blocks between those of the EGA.
+pub const VGA_FONT_BLOCKS: [usize; 8] = [0x0000, 0x4000, 0x8000, 0xC000, 0x2000, 0x6000, 0xA000, 0xE000];
+
+/// Size of each CGA scanline bank.
+const CGA_BANK_SIZE: usize = 0x2000;
+const CGA_BANK_ROW_BYTES: usize = 80;
+
+/// Size of a glyph in a font loaded into plane 2 of an EGA or VGA.
+const RAM_GLYPH_SPAN: usize = 32;
+/// Glyphs per row in a drawn font.
+const FONT_COLUMNS: u32 = 16;
+/// Alternating background colors of glyph cells, so that adjacent glyphs can be told apart.
+const FONT_BG_COLORS: [[u8; 4]; 2] = [[0x20, 0x20, 0x20, 0xFF], [0x30, 0x30, 0x38, 0xFF]];
+const FONT_FG_COLOR: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+const PLANE_COLORS: [[u8; 4]; 4] = [
+    [0x40, 0x40, 0xFF, 0xFF],
+    [0x40, 0xFF, 0x40, 0xFF],
+    [0xFF, 0x40, 0x40, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
+const PLANE_BG_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+
+/// What the viewer draws from video memory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VramView {
+    TextPages,
+    CgaBanks,
+    Planes,
+    Fonts,
+}
+
+impl VramView {
+    pub const ALL: [VramView; 4] = [VramView::TextPages, VramView::CgaBanks, VramView::Planes, VramView::Fonts];
+
+    pub fn desc(&self) -> &'static str {
+        match self {
+            VramView::TextPages => "Text Pages",
+            VramView::CgaBanks => "CGA Banks",
+            VramView::Planes => "Bitplanes",
+            VramView::Fonts => "Fonts",
+        }
+    }
+}
+
+/// The memory a cell of a drawn image was drawn from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VramCell {
+    /// A text mode character cell. The offset is that of the character.
+    Text { offset: usize, glyph: u8, attr: u8 },
+    /// A byte of graphics memory, in a plane if the card has them.
+    Byte { plane: Option<usize>, offset: usize, value: u8 },
+    /// A font glyph, and its offset if the font is in video memory.
+    Glyph { offset: Option<usize>, glyph: u8 },
+}
+
+impl VramCell {
+    pub fn describe(&self) -> String {
+        match self {
+            VramCell::Text { offset, glyph, attr } => {
+                format!("{:05X}: char {:02X} {} attr {:02X}", offset, glyph, printable(*glyph), attr)
+            }
+            VramCell::Byte { plane: Some(plane), offset, value } => {
+                format!("Plane {} {:05X}: {:02X} ({:08b})", plane, offset, value, value)
+            }
+            VramCell::Byte { plane: None, offset, value } => {
+                format!("{:05X}: {:02X} ({:08b})", offset, value, value)
+            }
+            VramCell::Glyph { offset: Some(offset), glyph } => {
+                format!("Glyph {:02X} {} at {:05X}", glyph, printable(*glyph), offset)
+            }
+            VramCell::Glyph { offset: None, glyph } => {
+                format!("Glyph {:02X} {}", glyph, printable(*glyph))
+            }
+        }
+    }
+}
+
+fn printable(byte: u8) -> String {
+    match byte.is_ascii_graphic() {
+        true => format!("'{}'", byte as char),
+        false => String::new()
+    }
+}
+
+/// An image drawn from video memory, divided into a grid of cells.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VramImage {
+    pub label: String,
+    pub w: u32,
+    pub h: u32,
+    pub rgba: Vec<u8>,
+    pub cell_w: u32,
+    pub cell_h: u32,
+    pub cells: Vec<VramCell>,
+}
+
+impl VramImage {
+    fn new(label: String, w: u32, h: u32, cell_w: u32, cell_h: u32) -> Self {
+        Self {
+            label,
+            w,
+            h,
+            rgba: vec![0; (w * h * 4) as usize],
+            cell_w,
+            cell_h,
+            cells: Vec::new(),
+        }
+    }
+
+    fn put(&mut self, x: u32, y: u32, color: &[u8; 4]) {
+        if x < self.w && y < self.h {
+            let o = ((y * self.w + x) * 4) as usize;
+            self.rgba[o..o + 4].copy_from_slice(color);
+        }
+    }
+
+    /// Return the cell at the specified pixel coordinate, if any.
+    pub fn cell_at(&self, x: u32, y: u32) -> Option<&VramCell> {
+        if x >= self.w || y >= self.h || self.cell_w == 0 || self.cell_h == 0 {
+            return None
+        }
+        let columns = self.w / self.cell_w;
+        let idx = (y / self.cell_h) * columns + (x / self.cell_w);
+        self.cells.get(idx as usize)
+    }
+}
+
+/// Text mode memory. Cards with a linear buffer store each character followed by its
+/// attribute. The EGA and VGA store characters in plane 0 and attributes in plane 1.
+pub enum TextSource<'a> {
+    Interleaved(&'a [u8]),
+    Planes(&'a [u8], &'a [u8]),
+}
+
+impl TextSource<'_> {
+    fn cells(&self) -> usize {
+        match self {
+            TextSource::Interleaved(mem) => mem.len() / 2,
+            TextSource::Planes(chars, attrs) => chars.len().min(attrs.len()),
+        }
+    }
+
+    /// Return the offset, character and attribute of the specified cell.
+    fn cell(&self, i: usize) -> (usize, u8, u8) {
+        match self {
+            TextSource::Interleaved(mem) => (i * 2, mem[i * 2], mem[i * 2 + 1]),
+            TextSource::Planes(chars, attrs) => (i, chars[i], attrs[i]),
+        }
+    }
+}
+
+/// Return a row of a glyph from a font in row-major order, as used by the video cards'
+/// ROM fonts.
+fn rom_glyph_row(font: &FontInfo, glyph: u8, row: u32) -> u8 {
+    match row < font.h {
+        true => font.font_data.get((row * 256 + glyph as u32) as usize).copied().unwrap_or(0),
+        false => 0
+    }
+}
+
+fn draw_glyph(image: &mut VramImage, (x0, y0): (u32, u32), (w, h): (u32, u32), fg: &[u8; 4], bg: &[u8; 4], row_fn: impl Fn(u32) -> u8) {
+    for y in 0..h {
+        let bits = row_fn(y);
+        for x in 0..w.min(8) {
+            let color = match bits & (0x80 >> x) != 0 {
+                true => fg,
+                false => bg
+            };
+            image.put(x0 + x, y0 + y, color);
+        }
+    }
+}
+
+/// Draw each page of text mode memory with the specified font. Pages are 4K apart in 80
+/// column modes and 2K apart in 40 column modes, as the BIOS places them.
+pub fn draw_text_pages(source: &TextSource, columns: u32, font: &FontInfo) -> Vec<VramImage> {
+    let columns = columns.max(1);
+    let page_cells = (columns as usize * VRAM_TEXT_ROWS as usize * 2).next_power_of_two() / 2;
+    let pages = source.cells().div_ceil(page_cells).min(VRAM_MAX_PAGES);
+    let char_w = font.w.clamp(1, 8);
+    let char_h = font.h.max(1);
+    let palette = &CGA_RGBA_COLORS[0];
+
+    (0..pages).map(|page| {
+        let mut image = VramImage::new(
+            format!("Page {}", page),
+            columns * char_w,
+            VRAM_TEXT_ROWS * char_h,
+            char_w,
+            char_h
+        );
+
+        for i in 0..(columns * VRAM_TEXT_ROWS) as usize {
+            let cell = page * page_cells + i;
+            if cell >= source.cells() {
+                break;
+            }
+            let (offset, glyph, attr) = source.cell(cell);
+            let fg = &palette[(attr & 0x0F) as usize];
+            let bg = &palette[((attr >> 4) & 0x07) as usize];
+            let x0 = (i as u32 % columns) * char_w;
+            let y0 = (i as u32 / columns) * char_h;
+            draw_glyph(&mut image, (x0, y0), (char_w, char_h), fg, bg, |row| rom_glyph_row(font, glyph, row));
+            image.cells.push(VramCell::Text { offset, glyph, attr });
+        }
+        image
+    }).collect()
+}
+
+/// Draw the two scanline banks of CGA memory. The CGA stores even scanlines in the first
+/// 8K and odd scanlines in the second. `palette` holds 4 colors for 2bpp modes, or 2 for
+/// the 640 pixel mode.
+pub fn draw_cga_banks(mem: &[u8], palette: &[[u8; 4]]) -> Vec<VramImage> {
+    let bpp = if palette.len() > 2 { 2 } else { 1 };
+    let pixels_per_byte = 8 / bpp;
+    let rows = (CGA_BANK_SIZE / CGA_BANK_ROW_BYTES) as u32;
+    let black = [0x00, 0x00, 0x00, 0xFF];
+
+    ["Even Scanlines", "Odd Scanlines"].iter().enumerate().map(|(bank, label)| {
+        let mut image = VramImage::new(
+            label.to_string(),
+            CGA_BANK_ROW_BYTES as u32 * pixels_per_byte,
+            rows,
+            pixels_per_byte,
+            1
+        );
+
+        for i in 0..(rows as usize * CGA_BANK_ROW_BYTES) {
+            let offset = bank * CGA_BANK_SIZE + i;
+            let value = mem.get(offset).copied().unwrap_or(0);
+            let x0 = (i % CGA_BANK_ROW_BYTES) as u32 * pixels_per_byte;
+            let y = (i / CGA_BANK_ROW_BYTES) as u32;
+
+            for p in 0..pixels_per_byte {
+                let shift = 8 - bpp * (p + 1);
+                let color = (value >> shift) & ((1 << bpp) - 1);
+                image.put(x0 + p, y, palette.get(color as usize).unwrap_or(&black));
+            }
+            image.cells.push(VramCell::Byte { plane: None, offset, value });
+        }
+        image
+    }).collect()
+}
+
+/// Draw each of the four bitplanes of an EGA or VGA as a 1bpp image, `row_bytes` bytes wide.
+pub fn draw_planes(planes: [&[u8]; 4], row_bytes: u32) -> Vec<VramImage> {
+    let row_bytes = row_bytes.max(1);
+
+    planes.iter().enumerate().map(|(plane, data)| {
+        let rows = (data.len() as u32).div_ceil(row_bytes).clamp(1, VRAM_MAX_PLANE_ROWS);
+        let mut image = VramImage::new(format!("Plane {}", plane), row_bytes * 8, rows, 8, 1);
+
+        for offset in 0..(rows * row_bytes) as usize {
+            let value = data.get(offset).copied().unwrap_or(0);
+            let x0 = (offset as u32 % row_bytes) * 8;
+            let y = offset as u32 / row_bytes;
+            for bit in 0..8 {
+                let color = match value & (0x80 >> bit) != 0 {
+                    true => &PLANE_COLORS[plane],
+                    false => &PLANE_BG_COLOR
+                };
+                image.put(x0 + bit, y, color);
+            }
+            image.cells.push(VramCell::Byte { plane: Some(plane), offset, value });
+        }
+        image
+    }).collect()
+}
+
+fn font_image(label: String, w: u32, h: u32, row_fn: impl Fn(u8, u32) -> u8, offset_fn: impl Fn(u8) -> Option<usize>) -> VramImage {
+    let mut image = VramImage::new(label, FONT_COLUMNS * w, (256 / FONT_COLUMNS) * h, w, h);
+    for glyph in 0..=255u8 {
+        let col = glyph as u32 % FONT_COLUMNS;
+        let row = glyph as u32 / FONT_COLUMNS;
+        let bg = &FONT_BG_COLORS[((col + row) % 2) as usize];
+        draw_glyph(&mut image, (col * w, row * h), (w, h), &FONT_FG_COLOR, bg, |y| row_fn(glyph, y));
+        image.cells.push(VramCell::Glyph { offset: offset_fn(glyph), glyph });
+    }
+    image
+}
+
+/// Draw the glyphs of a video card's ROM font.
+pub fn draw_rom_font(label: &str, font: &FontInfo) -> VramImage {
+    font_image(
+        label.to_string(),
+        font.w.clamp(1, 8),
+        font.h.max(1),
+        |glyph, row| rom_glyph_row(font, glyph, row),
+        |_| None
+    )
+}
+
+/// Draw fonts loaded into plane 2 of an EGA or VGA. Each glyph occupies 32 bytes, of which
+/// the first `char_height` are drawn. `blocks` lists the offsets of the font blocks in
+/// the plane.
+pub fn draw_ram_fonts(plane: &[u8], char_height: u32, blocks: &[usize]) -> Vec<VramImage> {
+    let char_height = char_height.clamp(1, RAM_GLYPH_SPAN as u32);
+
+    blocks.iter().enumerate().map(|(i, &base)| {
+        font_image(
+            format!("Font Block {} ({:04X})", i, base),
+            8,
+            char_height,
+            |glyph, row| plane.get(base + glyph as usize * RAM_GLYPH_SPAN + row as usize).copied().unwrap_or(0),
+            |glyph| Some(base + glyph as usize * RAM_GLYPH_SPAN)
+        )
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_font() -> FontInfo<'static> {
+        // Glyph 0x41 has its top row filled. Every other glyph is blank.
+        let data: &'static mut [u8] = Box::leak(vec![0u8; 256 * 8].into_boxed_slice());
+        data[0x41] = 0xFF;
+        FontInfo { w: 8, h: 8, font_data: data }
+    }
+
+    #[test]
+    fn test_text_pages() {
+        let mut mem = vec![0u8; 0x4000];
+        // Page 1, row 0, column 1: white 'A' on blue
+        mem[0x1000 + 2] = 0x41;
+        mem[0x1000 + 3] = 0x1F;
+
+        let pages = draw_text_pages(&TextSource::Interleaved(&mem), 80, &test_font());
+        assert_eq!(pages.len(), 4);
+        assert_eq!((pages[1].w, pages[1].h), (640, 200));
+
+        let page = &pages[1];
+        assert_eq!(page.cell_at(9, 3), Some(&VramCell::Text { offset: 0x1002, glyph: 0x41, attr: 0x1F }));
+        // Foreground in the top row of the glyph, background below it
+        let o = (8 * 4) as usize;
+        assert_eq!(&page.rgba[o..o + 4], &CGA_RGBA_COLORS[0][0x0F]);
+        let o = ((page.w + 8) * 4) as usize;
+        assert_eq!(&page.rgba[o..o + 4], &CGA_RGBA_COLORS[0][0x01]);
+
+        // 40 column pages are 2K apart
+        assert_eq!(draw_text_pages(&TextSource::Interleaved(&mem), 40, &test_font()).len(), 8);
+
+        let chars = vec![0x41u8; 0x2000];
+        let attrs = vec![0x07u8; 0x2000];
+        let pages = draw_text_pages(&TextSource::Planes(&chars, &attrs), 80, &test_font());
+        assert_eq!(pages.len(), 4);
+        assert_eq!(pages[2].cell_at(0, 0), Some(&VramCell::Text { offset: 0x1000, glyph: 0x41, attr: 0x07 }));
+    }
+
+    #[test]
+    fn test_cga_banks() {
+        let mut mem = vec![0u8; 0x4000];
+        mem[0x2000 + 81] = 0b1110_0100;
+        let palette = [[0, 0, 0, 0xFF], [1, 1, 1, 0xFF], [2, 2, 2, 0xFF], [3, 3, 3, 0xFF]];
+
+        let banks = draw_cga_banks(&mem, &palette);
+        assert_eq!(banks.len(), 2);
+        assert_eq!((banks[1].w, banks[1].h), (320, 102));
+        assert_eq!(banks[1].cell_at(5, 1), Some(&VramCell::Byte { plane: None, offset: 0x2051, value: 0b1110_0100 }));
+        let o = ((banks[1].w + 4) * 4) as usize;
+        assert_eq!(&banks[1].rgba[o..o + 16], &[3, 3, 3, 0xFF, 2, 2, 2, 0xFF, 1, 1, 1, 0xFF, 0, 0, 0, 0xFF]);
+
+        let banks = draw_cga_banks(&mem, &palette[..2]);
+        assert_eq!(banks[0].w, 640);
+    }
+
+    #[test]
+    fn test_planes_and_fonts() {
+        let plane = vec![0x80u8; 160];
+        let images = draw_planes([&plane, &plane, &[], &plane], 80);
+        assert_eq!(images.len(), 4);
+        assert_eq!((images[0].w, images[0].h), (640, 2));
+        assert_eq!(images[3].cell_at(639, 1), Some(&VramCell::Byte { plane: Some(3), offset: 159, value: 0x80 }));
+        assert_eq!(&images[1].rgba[0..4], &PLANE_COLORS[1]);
+        assert_eq!(&images[1].rgba[4..8], &PLANE_BG_COLOR);
+
+        let font = draw_rom_font("ROM", &test_font());
+        assert_eq!((font.w, font.h), (128, 128));
+        assert_eq!(font.cell_at(8, 32), Some(&VramCell::Glyph { offset: None, glyph: 0x41 }));
+        let o = ((32 * font.w + 8) * 4) as usize;
+        assert_eq!(&font.rgba[o..o + 4], &FONT_FG_COLOR);
+
+        let fonts = draw_ram_fonts(&vec![0u8; 0x10000], 14, &[0x0000, 0x4000]);
+        assert_eq!(fonts.len(), 2);
+        assert_eq!(fonts[1].cell_at(0, 14), Some(&VramCell::Glyph { offset: Some(0x4000 + 16 * 32), glyph: 0x10 }));
+    }
+}
@@ -0,0 +1,255 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    bin_test.rs
+
+    Run standalone CPU test binaries, such as third-party 8088 test suites, 
+    without a BIOS. A flat binary is loaded at a configurable address and run 
+    until the CPU halts, execution reaches a trigger address, or a cycle limit 
+    expires. The final register state and any requested memory ranges are 
+    collected into a BinTestResult which can be written out as JSON for 
+    comparison against results from real hardware.
+*/
+
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use marty_core::cpu_808x::CpuRegisterState;
+
+/// Default cycle limit for a test binary. About 21 seconds of emulated time at 4.77Mhz.
+pub const DEFAULT_MAX_CYCLES: u64 = 100_000_000;
+
+/// Parse a hexadecimal number, with or without a '0x' prefix.
+fn parse_hex(s: &str) -> Option<u32> {
+    let s = s.trim();
+    let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    u32::from_str_radix(s, 16).ok()
+}
+
+/// Parse a flat address in hexadecimal, or a segmented address in the form SEG:OFS.
+pub fn parse_address(s: &str) -> Option<u32> {
+    match s.split_once(':') {
+        Some((seg, ofs)) => {
+            let (seg, ofs) = parse_seg_ofs_parts(seg, ofs)?;
+            Some((((seg as u32) << 4) + ofs as u32) & 0xFFFFF)
+        }
+        None => parse_hex(s).filter(|a| *a <= 0xFFFFF),
+    }
+}
+
+/// Parse a segmented load address in the form SEG:OFS.
+pub fn parse_seg_ofs(s: &str) -> Option<(u16, u16)> {
+    let (seg, ofs) = s.split_once(':')?;
+    parse_seg_ofs_parts(seg, ofs)
+}
+
+fn parse_seg_ofs_parts(seg: &str, ofs: &str) -> Option<(u16, u16)> {
+    let seg = u16::try_from(parse_hex(seg)?).ok()?;
+    let ofs = u16::try_from(parse_hex(ofs)?).ok()?;
+    Some((seg, ofs))
+}
+
+/// A range of memory to dump once a test binary has finished.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRange {
+    pub address: u32,
+    pub len: u32,
+}
+
+impl FromStr for MemoryRange {
+    type Err = String;
+
+    /// Parse a range in the form ADDR:LEN, where ADDR is a flat address and LEN a byte count, 
+    /// both in hexadecimal. 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Invalid memory range: {} (expected ADDR:LEN)", s);
+
+        let (address, len) = s.split_once(':').ok_or_else(err)?;
+        let address = parse_hex(address).ok_or_else(err)?;
+        let len = parse_hex(len).ok_or_else(err)?;
+
+        if address > 0xFFFFF || len == 0 || address + len > 0x100000 {
+            return Err(err());
+        }
+        Ok(MemoryRange { address, len })
+    }
+}
+
+/// Parameters for running a test binary.
+#[derive(Clone, Debug)]
+pub struct BinTestSpec {
+    pub load_seg: u16,
+    pub load_ofs: u16,
+    /// Flat address that ends the test when execution reaches it.
+    pub trigger: Option<u32>,
+    pub max_cycles: u64,
+    pub dump: Vec<MemoryRange>,
+}
+
+impl Default for BinTestSpec {
+    fn default() -> Self {
+        Self {
+            load_seg: 0x1000,
+            load_ofs: 0x0000,
+            trigger: None,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            dump: Vec::new(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The CPU executed a HLT instruction.
+    Halt,
+    /// Execution reached the trigger address.
+    Trigger,
+    /// Execution ran past the end of the loaded binary.
+    ProgramEnd,
+    /// The cycle limit expired.
+    Timeout,
+    /// The CPU encountered an error.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Registers {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub sp: u16,
+    pub bp: u16,
+    pub si: u16,
+    pub di: u16,
+    pub cs: u16,
+    pub ds: u16,
+    pub ss: u16,
+    pub es: u16,
+    pub ip: u16,
+    pub flags: u16,
+}
+
+impl From<&CpuRegisterState> for Registers {
+    fn from(state: &CpuRegisterState) -> Self {
+        Self {
+            ax: state.ax,
+            bx: state.bx,
+            cx: state.cx,
+            dx: state.dx,
+            sp: state.sp,
+            bp: state.bp,
+            si: state.si,
+            di: state.di,
+            cs: state.cs,
+            ds: state.ds,
+            ss: state.ss,
+            es: state.es,
+            ip: state.ip,
+            flags: state.flags,
+        }
+    }
+}
+
+/// The contents of a dumped memory range. Data is stored as a string of hex bytes to keep
+/// result files compact and easy to diff.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MemoryDump {
+    pub address: u32,
+    pub data: String,
+}
+
+impl MemoryDump {
+    pub fn new(address: u32, bytes: &[u8]) -> Self {
+        Self {
+            address,
+            data: bytes.iter().map(|b| format!("{:02X}", b)).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BinTestResult {
+    pub stop_reason: StopReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub registers: Registers,
+    pub memory: Vec<MemoryDump>,
+}
+
+impl BinTestResult {
+    /// Return true if the test binary ran to completion, as opposed to timing out or 
+    /// crashing the CPU. Whether the results are correct is up to the comparison.
+    pub fn completed(&self) -> bool {
+        matches!(self.stop_reason, StopReason::Halt | StopReason::Trigger | StopReason::ProgramEnd)
+    }
+
+    pub fn to_json(&self) -> String {
+        // Serializing plain structs can't fail.
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_seg_ofs("1000:0100"), Some((0x1000, 0x0100)));
+        assert_eq!(parse_seg_ofs("10000:0"), None);
+        assert_eq!(parse_address("0xFFFF0"), Some(0xFFFF0));
+        assert_eq!(parse_address("F000:FFF0"), Some(0xFFFF0));
+        assert_eq!(parse_address("100000"), None);
+
+        assert_eq!("400:100".parse::<MemoryRange>(), Ok(MemoryRange { address: 0x400, len: 0x100 }));
+        assert!("FFFFF:2".parse::<MemoryRange>().is_err());
+        assert!("400".parse::<MemoryRange>().is_err());
+    }
+
+    #[test]
+    fn test_result_json() {
+        let result = BinTestResult {
+            stop_reason: StopReason::Halt,
+            error: None,
+            instructions: 10,
+            cycles: 100,
+            registers: Registers::from(&CpuRegisterState::default()),
+            memory: vec![MemoryDump::new(0x400, &[0x01, 0xAB])],
+        };
+        assert!(result.completed());
+
+        let json: serde_json::Value = serde_json::from_str(&result.to_json()).unwrap();
+        assert_eq!(json["stop_reason"], "halt");
+        assert_eq!(json["memory"][0]["data"], "01AB");
+        assert_eq!(json["registers"]["ax"], 0);
+        assert!(json.get("error").is_none());
+    }
+}
@@ -0,0 +1,466 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    lib.rs
+
+    Run the emulator core without a window, GUI or sound device. 
+
+    HeadlessMachine provides a small programmatic API to run a machine for a
+    number of frames, feed it keyboard input and capture the display, so that
+    compatibility tests can be run as batch jobs on servers.
+*/
+
+pub mod bin_test;
+pub mod tools;
+
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use marty_core::{
+    breakpoints::BreakPointType,
+    bus::BusInterface,
+    config::{ConfigFileParams, HardDiskControllerType, VideoType},
+    devices::mda,
+    device_manager::IoConflict,
+    floppy_manager::{FloppyManager, FloppyError},
+    machine::{Machine, ExecutionControl, ExecutionState},
+    machine_manager::MACHINE_DESCS,
+    monitor::MonitorType,
+    palette::{self, PaletteError},
+    rom_manager::{RomManager, RomError, RomFeature},
+    videocard::{RenderMode, VideoCard},
+};
+
+use marty_render::{VideoRenderer, CompositeParams};
+
+use crate::bin_test::{BinTestResult, BinTestSpec, MemoryDump, Registers, StopReason};
+
+/// Frames are emulated at the nominal refresh rate of the video card.
+pub const HEADLESS_FPS: f64 = 60.0;
+
+/// Maximum vertical resolution rendered for the CGA in Direct mode, before scanline doubling.
+const MAX_DIRECT_HEIGHT: u32 = 240;
+
+/// Cycles to run between checks for the end of a test binary. Kept small so that the CPU 
+/// doesn't sit halted for long before we notice.
+const BIN_TEST_SLICE_CYCLES: u32 = 100;
+
+#[derive (Debug)]
+pub enum HeadlessError {
+    InvalidMachine,
+    RomError(RomError),
+    FloppyError(FloppyError),
+    NoFloppyController,
+    FloppyLoadError(&'static str),
+    NoVideoCard,
+    ImageError(image::ImageError),
+    PaletteError(PaletteError),
+    ProgramLoadError,
+    IoConflicts(Vec<IoConflict>),
+}
+impl Error for HeadlessError {}
+impl Display for HeadlessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeadlessError::InvalidMachine => write!(f, "No machine description found for the configured machine type."),
+            HeadlessError::RomError(e) => write!(f, "Error loading ROMs: {}", e),
+            HeadlessError::FloppyError(e) => write!(f, "Error reading floppy image: {}", e),
+            HeadlessError::NoFloppyController => write!(f, "The machine has no floppy controller."),
+            HeadlessError::FloppyLoadError(e) => write!(f, "Error loading floppy image: {}", e),
+            HeadlessError::NoVideoCard => write!(f, "The machine has no video card."),
+            HeadlessError::ImageError(e) => write!(f, "Error writing image: {}", e),
+            HeadlessError::PaletteError(e) => write!(f, "Error loading palette file: {}", e),
+            HeadlessError::ProgramLoadError => write!(f, "Program binary does not fit in memory at the load address."),
+            HeadlessError::IoConflicts(conflicts) => {
+                let messages: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+        }
+    }
+}
+
+/// An RGBA image of the emulated display.
+pub struct Framebuffer {
+    pub w: u32,
+    pub h: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn save_png(&self, path: &Path) -> Result<(), HeadlessError> {
+        image::save_buffer(path, &self.rgba, self.w, self.h, image::ColorType::Rgba8)
+            .map_err(HeadlessError::ImageError)
+    }
+
+    /// Combine this image with another placed to its right. The shorter of the two images is
+    /// padded with black.
+    pub fn side_by_side(&self, right: &Framebuffer) -> Framebuffer {
+        let w = self.w + right.w;
+        let h = self.h.max(right.h);
+        let mut rgba = vec![0; (w * h * 4) as usize];
+
+        for (image, x) in [(self, 0), (right, self.w)] {
+            let span = (image.w * 4) as usize;
+            for (y, row) in image.rgba.chunks_exact(span).enumerate() {
+                let offset = ((y as u32 * w + x) * 4) as usize;
+                rgba[offset..offset + span].copy_from_slice(row);
+            }
+        }
+        VideoRenderer::set_alpha(&mut rgba, w, h, 255);
+
+        Framebuffer { w, h, rgba }
+    }
+
+    /// Return a 64-bit FNV-1a hash of the image dimensions and pixels, as a hex string. 
+    /// Used to check that a scenario produces an identical display.
+    pub fn checksum(&self) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let dims = self.w.to_le_bytes().into_iter().chain(self.h.to_le_bytes());
+        for byte in dims.chain(self.rgba.iter().copied()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        format!("{:016x}", hash)
+    }
+}
+
+pub struct HeadlessMachine {
+    machine: Machine,
+    exec_control: ExecutionControl,
+    renderer: VideoRenderer,
+    secondary_renderer: Option<VideoRenderer>,
+    composite: bool,
+    cycles_per_frame: u32,
+    frames: u64,
+}
+
+impl HeadlessMachine {
+    /// Create a machine from the specified configuration. ROMs are loaded from the 'roms' 
+    /// directory under the configured base directory.
+    pub fn new(config: &ConfigFileParams) -> Result<Self, HeadlessError> {
+
+        let machine_desc = *MACHINE_DESCS.get(&config.machine.model).ok_or(HeadlessError::InvalidMachine)?;
+
+        // Determine required ROM features from configuration options
+        let mut features = Vec::new();
+        match config.machine.video {
+            VideoType::EGA => features.push(RomFeature::EGA),
+            VideoType::VGA => features.push(RomFeature::VGA),
+            _ => {}
+        }
+        if let HardDiskControllerType::Xebec = config.machine.hdc {
+            features.push(RomFeature::XebecHDC);
+        }
+
+        let mut rom_manager = 
+            RomManager::new(
+                config.machine.model, 
+                features,
+                config.machine.rom_override.clone(),
+            );
+
+        // Prefer the BIOS of the selected machine profile
+        if let Some(bios) = config.active_profile.as_ref().and_then(|p| p.bios.clone()) {
+            rom_manager.set_preferred_bios(bios);
+        }
+
+        let mut rom_path = PathBuf::new();
+        rom_path.push(config.emulator.basedir.clone());
+        rom_path.push("roms");
+        // ROMs aren't needed to run standalone test binaries.
+        if !config.emulator.no_bios {
+            rom_manager.try_load_from_dir(&rom_path).map_err(HeadlessError::RomError)?;
+        }
+
+        let machine = Machine::new(
+            config,
+            config.machine.model,
+            machine_desc,
+            config.emulator.trace_mode,
+            config.machine.video,
+            None,
+            rom_manager,
+        );
+        if !machine.io_conflicts().is_empty() {
+            return Err(HeadlessError::IoConflicts(machine.io_conflicts().to_vec()))
+        }
+
+        let cycles_per_frame = (machine.get_cpu_mhz() * 1000000.0 / HEADLESS_FPS) as u32;
+
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+
+        let mut renderer = VideoRenderer::new(config.machine.video);
+        let custom_palette = match &config.emulator.palette_file {
+            Some(path) => Some(palette::load_palette_file(path).map_err(HeadlessError::PaletteError)?),
+            None => None,
+        };
+        renderer.set_palette(config.emulator.palette, custom_palette.as_ref());
+        let monitor = config.monitor();
+        renderer.set_monochrome(monitor.phosphor(config.emulator.monochrome.unwrap_or_default()));
+
+        // A secondary card is always shown on its default monitor.
+        let secondary_renderer = config.machine.secondary_video.map(|video_type| {
+            let mut renderer = VideoRenderer::new(video_type);
+            renderer.set_palette(config.emulator.palette, custom_palette.as_ref());
+            renderer.set_monochrome(MonitorType::default_for(video_type).phosphor(config.emulator.monochrome.unwrap_or_default()));
+            renderer
+        });
+
+        Ok(Self {
+            machine,
+            exec_control,
+            renderer,
+            secondary_renderer,
+            composite: monitor.is_composite(),
+            cycles_per_frame,
+            frames: 0,
+        })
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Return the number of frames run so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Run the machine for the specified number of frames. Returns the number of instructions 
+    /// executed. Execution stops early if the machine is halted or hits a breakpoint.
+    pub fn run_frames(&mut self, frames: u32) -> u64 {
+        let mut instr_count = 0;
+        for _ in 0..frames {
+            if !matches!(self.exec_control.get_state(), ExecutionState::Running) {
+                break;
+            }
+            instr_count += self.machine.run(self.cycles_per_frame, &mut self.exec_control);
+            self.machine.frame_update();
+            self.frames += 1;
+        }
+        instr_count
+    }
+
+    /// Load a flat binary and run it as a standalone test until it halts, reaches the trigger 
+    /// address or runs out of cycles, then collect the register state and requested memory 
+    /// ranges.
+    pub fn run_bin_test(&mut self, program: &[u8], spec: &BinTestSpec) -> Result<BinTestResult, HeadlessError> {
+        self.machine
+            .load_program(program, spec.load_seg, spec.load_ofs)
+            .map_err(|_| HeadlessError::ProgramLoadError)?;
+
+        if let Some(trigger) = spec.trigger {
+            self.machine.set_breakpoints(vec![BreakPointType::ExecuteFlat(trigger)]);
+        }
+        self.exec_control.set_state(ExecutionState::Running);
+
+        let start_cycles = self.machine.cpu_cycles();
+        let mut instructions = 0;
+
+        let stop_reason = loop {
+            instructions += self.machine.run(BIN_TEST_SLICE_CYCLES, &mut self.exec_control);
+
+            // A HLT with interrupts disabled is reported by the CPU as an error, but it is the
+            // usual way for a test binary to finish, so check for it first.
+            if self.machine.cpu().is_halted() {
+                break StopReason::Halt;
+            }
+            if self.machine.get_error_str().is_some() {
+                break StopReason::Error;
+            }
+            match self.exec_control.get_state() {
+                ExecutionState::BreakpointHit => break StopReason::Trigger,
+                ExecutionState::Halted => break StopReason::ProgramEnd,
+                _ => {}
+            }
+            if self.machine.cpu_cycles() - start_cycles >= spec.max_cycles {
+                break StopReason::Timeout;
+            }
+        };
+
+        let bus = self.machine.bus();
+        let memory = spec.dump
+            .iter()
+            .map(|range| MemoryDump::new(range.address, bus.get_slice_at(range.address as usize, range.len as usize)))
+            .collect();
+
+        Ok(BinTestResult {
+            stop_reason,
+            error: match stop_reason {
+                StopReason::Error => self.machine.get_error_str().clone(),
+                _ => None,
+            },
+            instructions,
+            cycles: self.machine.cpu_cycles() - start_cycles,
+            registers: Registers::from(&self.machine.cpu().get_state()),
+            memory,
+        })
+    }
+
+    /// Return true if the machine has stopped running, such as from a halt or breakpoint.
+    pub fn is_stopped(&self) -> bool {
+        !matches!(self.exec_control.get_state(), ExecutionState::Running)
+    }
+
+    pub fn key_press(&mut self, scancode: u8) {
+        self.machine.key_press(scancode);
+    }
+
+    pub fn key_release(&mut self, scancode: u8) {
+        self.machine.key_release(scancode);
+    }
+
+    /// Press and release a key, running a frame in between so the keyboard controller sees
+    /// each event.
+    pub fn tap(&mut self, scancode: u8) {
+        self.machine.key_press(scancode);
+        self.run_frames(1);
+        self.machine.key_release(scancode);
+        self.run_frames(1);
+    }
+
+    /// Load a floppy image file into the specified drive.
+    pub fn load_floppy(&mut self, drive_select: usize, path: &Path) -> Result<(), HeadlessError> {
+        let vec = std::fs::read(path).map_err(|_| HeadlessError::FloppyError(FloppyError::FileReadError))?;
+        
+        let fdc = self.machine.fdc().as_mut().ok_or(HeadlessError::NoFloppyController)?;
+        fdc.load_image_from(drive_select, vec).map_err(HeadlessError::FloppyLoadError)?;
+        fdc.set_image_path(drive_select, Some(path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Run the machine for the specified number of frames, tapping each key at the frame given 
+    /// with it. Keys must be sorted by frame.
+    pub fn run_with_keys(&mut self, frames: u32, keys: &[(u32, u8)]) {
+        for (frame, code) in keys {
+            let frame = (*frame).min(frames) as u64;
+            if frame > self.frames {
+                self.run_frames((frame - self.frames) as u32);
+            }
+            self.tap(*code);
+        }
+        if (frames as u64) > self.frames {
+            self.run_frames(frames - self.frames as u32);
+        }
+    }
+
+    /// Load a floppy image by name from a FloppyManager into the specified drive.
+    pub fn load_floppy_from(&mut self, floppy_manager: &FloppyManager, drive_select: usize, name: &OsString) -> Result<(), HeadlessError> {
+        let vec = floppy_manager.load_floppy_data(name).map_err(HeadlessError::FloppyError)?;
+
+        let fdc = self.machine.fdc().as_mut().ok_or(HeadlessError::NoFloppyController)?;
+        fdc.load_image_from(drive_select, vec).map_err(HeadlessError::FloppyLoadError)?;
+        fdc.set_image_path(drive_select, floppy_manager.get_floppy_path(name));
+        Ok(())
+    }
+
+    /// Return the contents of the screen as text, if the video card is in a text mode.
+    pub fn screen_text(&self) -> Option<String> {
+        self.machine.screen_text()
+    }
+
+    /// Render the current display into an RGBA framebuffer.
+    pub fn framebuffer(&mut self) -> Result<Framebuffer, HeadlessError> {
+        let bus = self.machine.bus();
+        let card = bus.video().ok_or(HeadlessError::NoVideoCard)?;
+        Ok(render_card(&mut self.renderer, *card, bus, self.composite))
+    }
+
+    /// Render the display of the secondary video card, if one is installed.
+    pub fn secondary_framebuffer(&mut self) -> Option<Framebuffer> {
+        let bus = self.machine.bus();
+        let card = bus.secondary_video()?;
+        let renderer = self.secondary_renderer.as_mut()?;
+        Some(render_card(renderer, *card, bus, false))
+    }
+
+    /// Render the current display and save it as a PNG file. If a secondary video card is 
+    /// installed, its display is placed to the right of the primary display.
+    pub fn save_png(&mut self, path: &Path) -> Result<(), HeadlessError> {
+        let framebuffer = self.framebuffer()?;
+        match self.secondary_framebuffer() {
+            Some(secondary) => framebuffer.side_by_side(&secondary).save_png(path),
+            None => framebuffer.save_png(path)
+        }
+    }
+}
+
+/// Render the display of a video card into an RGBA framebuffer.
+fn render_card(renderer: &mut VideoRenderer, card: &dyn VideoCard, bus: &BusInterface, composite: bool) -> Framebuffer {
+
+    let (w, mut h) = match card.get_render_mode() {
+        RenderMode::Direct => {
+            let (w, h) = card.get_display_aperture();
+            match card.get_video_type() {
+                VideoType::MDA => (w, h.min(mda::MDA_YRES)),
+                VideoType::EGA | VideoType::VGA => (w, h),
+                _ => (w, h.min(MAX_DIRECT_HEIGHT))
+            }
+        }
+        RenderMode::Indirect => card.get_display_size()
+    };
+    if card.get_scanline_double() {
+        h *= 2;
+    }
+
+    let mut rgba = vec![0; (w * h * 4) as usize];
+
+    match card.get_render_mode() {
+        RenderMode::Direct if card.get_video_type() == VideoType::EGA => {
+            renderer.draw_ega_direct(&mut rgba, w, h, card.get_display_buf(), card.get_display_extents());
+        }
+        RenderMode::Direct if card.get_video_type() == VideoType::VGA => {
+            renderer.draw_vga_direct(&mut rgba, w, h, card.get_display_buf(), card.get_display_extents());
+        }
+        RenderMode::Direct => {
+            renderer.draw_cga_direct(
+                &mut rgba,
+                w,
+                h,
+                card.get_display_buf(),
+                card.get_display_extents(),
+                composite,
+                &CompositeParams::default(),
+                None
+            );
+        }
+        RenderMode::Indirect => {
+            renderer.draw(&mut rgba, Box::new(card), bus, composite);
+        }
+    }
+    VideoRenderer::set_alpha(&mut rgba, w, h, 255);
+
+    Framebuffer { w, h, rgba }
+}
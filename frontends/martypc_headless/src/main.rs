@@ -0,0 +1,344 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    main.rs
+
+    Command line frontend for running the emulator headless, for batch 
+    compatibility testing.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use marty_core::config;
+use martypc_headless::{
+    bin_test::{self, BinTestSpec, MemoryRange},
+    tools::{self, bisect::{self, Scenario}},
+    HeadlessMachine,
+};
+
+const DEFAULT_CONFIG_FILE: &str = "./martypc.toml";
+const DEFAULT_FRAMES: u32 = 600;
+
+const USAGE: &str = "\
+Usage: martypc_headless [OPTIONS]
+
+Options:
+    --configfile <PATH>    Configuration file to use (default: ./martypc.toml)
+    --frames <N>           Number of frames to run (default: 600)
+    --floppy0 <PATH>       Floppy image to load into drive A:
+    --floppy1 <PATH>       Floppy image to load into drive B:
+    --key <FRAME:CODE>     Tap the keyboard scancode CODE (hex) at frame FRAME. 
+                           May be given multiple times.
+    --screenshot <PATH>    Save the display to a PNG file after the last frame
+    --text                 Print the screen text after the last frame
+    --checksum             Print a checksum of the display after the last frame
+    --help                 Print this message
+
+Test binary options:
+    --run-bin <PATH>       Load a flat binary and run it until HLT, the trigger 
+                           address or the cycle limit, then output the register 
+                           and memory state as JSON. Set no_bios in the config to
+                           run without a BIOS.
+    --load <SEG:OFS>       Load address (default: run_bin_seg:run_bin_ofs from 
+                           the config, or 1000:0000)
+    --trigger <ADDR>       Stop when execution reaches ADDR (flat hex or SEG:OFS)
+    --max-cycles <N>       Cycle limit (default: 100000000)
+    --dump <ADDR:LEN>      Include a memory range in the results (hex). May be 
+                           given multiple times.
+    --result <PATH>        Write JSON results to PATH instead of stdout
+
+Bisect options:
+    --bisect <SCENARIO>    Run the scenario file across a set of candidates and
+                           report the first one that fails its check. Without
+                           --artifacts, every combination of the scenario's
+                           flags is tried.
+    --artifacts <DIR>      Directory of martypc_headless builds to bisect, 
+                           ordered oldest to newest by file name
+    --linear               Run artifacts in order instead of bisecting";
+
+struct HeadlessArgs {
+    configfile: PathBuf,
+    frames: u32,
+    floppies: [Option<PathBuf>; 2],
+    keys: Vec<(u32, u8)>,
+    screenshot: Option<PathBuf>,
+    print_text: bool,
+    print_checksum: bool,
+    run_bin: Option<PathBuf>,
+    load: Option<(u16, u16)>,
+    trigger: Option<u32>,
+    max_cycles: u64,
+    dump: Vec<MemoryRange>,
+    result: Option<PathBuf>,
+    bisect: Option<PathBuf>,
+    artifacts: Option<PathBuf>,
+    linear: bool,
+}
+
+fn parse_args() -> Result<HeadlessArgs, String> {
+    let mut args = HeadlessArgs {
+        configfile: PathBuf::from(DEFAULT_CONFIG_FILE),
+        frames: DEFAULT_FRAMES,
+        floppies: [None, None],
+        keys: Vec::new(),
+        screenshot: None,
+        print_text: false,
+        print_checksum: false,
+        run_bin: None,
+        load: None,
+        trigger: None,
+        max_cycles: bin_test::DEFAULT_MAX_CYCLES,
+        dump: Vec::new(),
+        result: None,
+        bisect: None,
+        artifacts: None,
+        linear: false,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--configfile" => args.configfile = PathBuf::from(value()?),
+            "--frames" => {
+                let v = value()?;
+                args.frames = v.parse().map_err(|_| format!("Invalid frame count: {}", v))?;
+            }
+            "--floppy0" => args.floppies[0] = Some(PathBuf::from(value()?)),
+            "--floppy1" => args.floppies[1] = Some(PathBuf::from(value()?)),
+            "--key" => {
+                let v = value()?;
+                args.keys.push(tools::parse_key(&v).ok_or(format!("Invalid key: {} (expected FRAME:CODE)", v))?);
+            }
+            "--screenshot" => args.screenshot = Some(PathBuf::from(value()?)),
+            "--text" => args.print_text = true,
+            "--checksum" => args.print_checksum = true,
+            "--run-bin" => args.run_bin = Some(PathBuf::from(value()?)),
+            "--load" => {
+                let v = value()?;
+                args.load = Some(bin_test::parse_seg_ofs(&v).ok_or(format!("Invalid load address: {} (expected SEG:OFS)", v))?);
+            }
+            "--trigger" => {
+                let v = value()?;
+                args.trigger = Some(bin_test::parse_address(&v).ok_or(format!("Invalid trigger address: {}", v))?);
+            }
+            "--max-cycles" => {
+                let v = value()?;
+                args.max_cycles = v.parse().map_err(|_| format!("Invalid cycle count: {}", v))?;
+            }
+            "--dump" => args.dump.push(value()?.parse()?),
+            "--result" => args.result = Some(PathBuf::from(value()?)),
+            "--bisect" => args.bisect = Some(PathBuf::from(value()?)),
+            "--artifacts" => args.artifacts = Some(PathBuf::from(value()?)),
+            "--linear" => args.linear = true,
+            "--help" | "-h" => return Err(String::new()),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+
+    args.keys.sort_by_key(|(frame, _)| *frame);
+    Ok(args)
+}
+
+fn main() -> ExitCode {
+
+    env_logger::init();
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("{}", msg);
+            }
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Some(path) = &args.bisect {
+        return run_bisect(path, &args);
+    }
+
+    let config = match std::fs::read_to_string(&args.configfile)
+        .map_err(|e| e.to_string())
+        .and_then(|text| config::get_config_from_str(&text).map_err(|e| e.to_string())) 
+    {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error reading configuration file {}: {}", args.configfile.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut machine = match HeadlessMachine::new(&config) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = &args.run_bin {
+        return run_bin_test(&mut machine, &config, path, &args);
+    }
+
+    for (drive_select, floppy) in args.floppies.iter().enumerate() {
+        if let Some(path) = floppy {
+            if let Err(e) = machine.load_floppy(drive_select, path) {
+                eprintln!("{}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    machine.run_with_keys(args.frames, &args.keys);
+
+    if machine.is_stopped() {
+        eprintln!("Machine stopped at frame {}.", machine.frames());
+    }
+
+    if args.print_text {
+        match machine.screen_text() {
+            Some(text) => print!("{}", text),
+            None => eprintln!("Display is not in a text mode."),
+        }
+    }
+
+    if args.print_checksum {
+        match machine.framebuffer() {
+            Ok(framebuffer) => println!("{}", framebuffer.checksum()),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.screenshot {
+        if let Err(e) = machine.save_png(path) {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+        println!("Saved screenshot: {}", path.display());
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Run a standalone test binary and output the results. Returns failure if the binary didn't 
+/// run to completion.
+fn run_bin_test(machine: &mut HeadlessMachine, config: &config::ConfigFileParams, path: &Path, args: &HeadlessArgs) -> ExitCode {
+    let program = match std::fs::read(path) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut spec = BinTestSpec {
+        trigger: args.trigger,
+        max_cycles: args.max_cycles,
+        dump: args.dump.clone(),
+        ..Default::default()
+    };
+    if let (Some(seg), Some(ofs)) = (config.emulator.run_bin_seg, config.emulator.run_bin_ofs) {
+        (spec.load_seg, spec.load_ofs) = (seg, ofs);
+    }
+    if let Some((seg, ofs)) = args.load {
+        (spec.load_seg, spec.load_ofs) = (seg, ofs);
+    }
+
+    let result = match machine.run_bin_test(&program, &spec) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let json = result.to_json();
+    match &args.result {
+        Some(result_path) => {
+            if let Err(e) = std::fs::write(result_path, json) {
+                eprintln!("{}: {}", result_path.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => println!("{}", json),
+    }
+
+    if !result.completed() {
+        eprintln!("Test binary did not complete: {:?}", result.stop_reason);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Run a scenario across build artifacts or flag combinations and report the first failing one.
+/// Returns failure if no candidate failed.
+fn run_bisect(path: &Path, args: &HeadlessArgs) -> ExitCode {
+    let result = Scenario::load(path).and_then(|scenario| {
+        let candidates = match &args.artifacts {
+            Some(dir) => bisect::artifact_candidates(dir)?,
+            None => bisect::flag_candidates(&scenario.flags),
+        };
+        Ok((scenario, candidates))
+    });
+    let (scenario, candidates) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let run = |i: usize| {
+        let outcome = candidates[i].run(&scenario);
+        println!("[{}/{}] {}: {}", i + 1, candidates.len(), candidates[i], outcome);
+        outcome
+    };
+    let report = if args.artifacts.is_some() && !args.linear {
+        bisect::bisect(candidates.len(), run)
+    }
+    else {
+        bisect::scan(candidates.len(), run)
+    };
+
+    match report.first_failing {
+        Some(i) => {
+            println!("First failing configuration: {} ({} runs)", candidates[i], report.runs);
+            ExitCode::SUCCESS
+        }
+        None => {
+            println!("No failing configuration found ({} runs)", report.runs);
+            ExitCode::FAILURE
+        }
+    }
+}
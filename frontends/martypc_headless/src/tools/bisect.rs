@@ -0,0 +1,436 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    tools::bisect.rs
+
+    Find the first configuration in which a reproducible headless scenario 
+    fails, to track down accuracy regressions.
+
+    A scenario is described by a TOML file giving the configuration file to
+    use, the number of frames to run, optional floppy images and key presses,
+    and a check to apply at the end of the run:
+
+        config = "martypc.toml"
+        frames = 900
+        floppy0 = "floppy/8088mph.img"
+        keys = ["300:1C"]
+        flags = ["machine.dram_refresh", "cpu.off_rails_detection"]
+
+        [check]
+        checksum = "3b6f0c2ad2e4f1a7"
+        text = "Press any key"
+
+    Candidates are either build artifacts - martypc_headless executables built
+    from a range of git revisions, run as separate processes - or every 
+    combination of the boolean configuration flags listed in the scenario, 
+    run in this process. Artifacts are ordered by file name and assumed to 
+    pass before some point and fail after it, so they can be bisected. Flag 
+    combinations have no such order and are scanned one by one.
+*/
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+
+use marty_core::config;
+
+use crate::{Framebuffer, HeadlessMachine};
+
+const DEFAULT_SCENARIO_FRAMES: u32 = 600;
+
+/// Flags are run in every combination, so keep the number of runs reasonable.
+pub const MAX_FLAGS: usize = 8;
+
+const fn default_frames() -> u32 { DEFAULT_SCENARIO_FRAMES }
+
+/// The condition a scenario must satisfy at the end of its run to pass.
+#[derive(Debug, Default, Deserialize)]
+pub struct Check {
+    /// Expected display checksum, as printed by martypc_headless --checksum.
+    pub checksum: Option<String>,
+    /// Text that must appear on screen.
+    pub text: Option<String>,
+}
+
+impl Check {
+    pub fn evaluate(&self, screen_text: Option<&str>, framebuffer: Option<&Framebuffer>) -> Outcome {
+        if let Some(expected) = &self.text {
+            match screen_text {
+                Some(text) if text.contains(expected.as_str()) => {}
+                Some(_) => return Outcome::Fail(format!("screen does not contain \"{}\"", expected)),
+                None => return Outcome::Fail("display is not in a text mode".to_string()),
+            }
+        }
+        if let Some(expected) = &self.checksum {
+            match framebuffer {
+                Some(fb) => {
+                    let checksum = fb.checksum();
+                    if !checksum.eq_ignore_ascii_case(expected) {
+                        return Outcome::Fail(format!("display checksum is {}", checksum));
+                    }
+                }
+                None => return Outcome::Error("no display to check".to_string()),
+            }
+        }
+        Outcome::Pass
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    pub config: PathBuf,
+    #[serde(default = "default_frames")]
+    pub frames: u32,
+    pub floppy0: Option<PathBuf>,
+    pub floppy1: Option<PathBuf>,
+    #[serde(default, rename = "keys")]
+    key_strings: Vec<String>,
+    #[serde(skip)]
+    pub keys: Vec<(u32, u8)>,
+    /// Boolean configuration values, as "section.key", to try in every combination.
+    #[serde(default)]
+    pub flags: Vec<String>,
+    pub check: Check,
+}
+
+impl Scenario {
+    /// Load a scenario file. Relative paths in the scenario are relative to the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut scenario: Scenario = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        scenario.config = base.join(&scenario.config);
+        for floppy in [&mut scenario.floppy0, &mut scenario.floppy1].into_iter().flatten() {
+            *floppy = base.join(&floppy);
+        }
+
+        for key in &scenario.key_strings {
+            let event = super::parse_key(key).ok_or(format!("Invalid key: {} (expected FRAME:CODE)", key))?;
+            scenario.keys.push(event);
+        }
+        scenario.keys.sort_by_key(|(frame, _)| *frame);
+
+        if scenario.check.checksum.is_none() && scenario.check.text.is_none() {
+            return Err(format!("{}: [check] must specify a checksum or text", path.display()));
+        }
+        if scenario.flags.len() > MAX_FLAGS {
+            return Err(format!("{}: no more than {} flags can be given", path.display(), MAX_FLAGS));
+        }
+        Ok(scenario)
+    }
+
+    fn floppies(&self) -> impl Iterator<Item = (usize, &PathBuf)> {
+        [&self.floppy0, &self.floppy1]
+            .into_iter()
+            .enumerate()
+            .filter_map(|(drive, floppy)| floppy.as_ref().map(|path| (drive, path)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    /// The candidate couldn't be run. It is skipped when bisecting.
+    Error(String),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Pass => write!(f, "PASS"),
+            Outcome::Fail(reason) => write!(f, "FAIL: {}", reason),
+            Outcome::Error(reason) => write!(f, "ERROR: {}", reason),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Candidate {
+    /// A martypc_headless executable, such as one built from a particular revision.
+    Artifact(PathBuf),
+    /// Configuration flag values, as "section.key" paths.
+    Flags(Vec<(String, bool)>),
+}
+
+impl Display for Candidate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Candidate::Artifact(path) => write!(f, "{}", path.display()),
+            Candidate::Flags(flags) => {
+                let flags: Vec<String> = flags.iter().map(|(flag, value)| format!("{}={}", flag, value)).collect();
+                write!(f, "{}", flags.join(", "))
+            }
+        }
+    }
+}
+
+impl Candidate {
+    pub fn run(&self, scenario: &Scenario) -> Outcome {
+        match self {
+            Candidate::Artifact(path) => run_artifact(scenario, path),
+            Candidate::Flags(flags) => run_flags(scenario, flags),
+        }
+    }
+}
+
+/// List the build artifacts in a directory, ordered by file name. Name them so that they 
+/// sort from oldest to newest, for example by prefixing each with its position in 
+/// `git rev-list --reverse`.
+pub fn artifact_candidates(dir: &Path) -> Result<Vec<Candidate>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths.into_iter().map(Candidate::Artifact).collect())
+}
+
+/// Every combination of the given flags, starting with all flags off.
+pub fn flag_candidates(flags: &[String]) -> Vec<Candidate> {
+    (0..1usize << flags.len())
+        .map(|bits| {
+            Candidate::Flags(
+                flags.iter().enumerate().map(|(i, flag)| (flag.clone(), bits & (1 << i) != 0)).collect()
+            )
+        })
+        .collect()
+}
+
+/// Set boolean values in the text of a configuration file.
+pub fn apply_flags(toml_text: &str, flags: &[(String, bool)]) -> Result<String, String> {
+    let mut root: toml::Value = toml_text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    for (flag, value) in flags {
+        let (section, key) = flag
+            .split_once('.')
+            .ok_or(format!("Flag must be in the form section.key: {}", flag))?;
+        let table = root
+            .get_mut(section)
+            .and_then(|section| section.as_table_mut())
+            .ok_or(format!("No [{}] section in configuration", section))?;
+        table.insert(key.to_string(), toml::Value::Boolean(*value));
+    }
+    toml::to_string(&root).map_err(|e| e.to_string())
+}
+
+fn run_flags(scenario: &Scenario, flags: &[(String, bool)]) -> Outcome {
+    let config = std::fs::read_to_string(&scenario.config)
+        .map_err(|e| format!("{}: {}", scenario.config.display(), e))
+        .and_then(|text| apply_flags(&text, flags))
+        .and_then(|text| config::get_config_from_str(&text).map_err(|e| e.to_string()));
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => return Outcome::Error(e),
+    };
+
+    let mut machine = match HeadlessMachine::new(&config) {
+        Ok(machine) => machine,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+    for (drive_select, path) in scenario.floppies() {
+        if let Err(e) = machine.load_floppy(drive_select, path) {
+            return Outcome::Error(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    machine.run_with_keys(scenario.frames, &scenario.keys);
+
+    let text = machine.screen_text();
+    let framebuffer = match scenario.check.checksum {
+        Some(_) => machine.framebuffer().ok(),
+        None => None,
+    };
+    scenario.check.evaluate(text.as_deref(), framebuffer.as_ref())
+}
+
+fn run_artifact(scenario: &Scenario, path: &Path) -> Outcome {
+    let screenshot = std::env::temp_dir().join(format!("martypc_bisect_{}.png", std::process::id()));
+
+    // Only use options that older builds of martypc_headless understand.
+    let mut command = Command::new(path);
+    command
+        .arg("--configfile").arg(&scenario.config)
+        .arg("--frames").arg(scenario.frames.to_string());
+    for (drive_select, floppy) in scenario.floppies() {
+        command.arg(format!("--floppy{}", drive_select)).arg(floppy);
+    }
+    for (frame, code) in &scenario.keys {
+        command.arg("--key").arg(format!("{}:{:02X}", frame, code));
+    }
+    if scenario.check.text.is_some() {
+        command.arg("--text");
+    }
+    if scenario.check.checksum.is_some() {
+        command.arg("--screenshot").arg(&screenshot);
+    }
+
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or_default();
+        return Outcome::Error(format!("{}: {}", output.status, reason));
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let framebuffer = match scenario.check.checksum {
+        Some(_) => {
+            let image = image::open(&screenshot);
+            _ = std::fs::remove_file(&screenshot);
+            match image {
+                Ok(image) => {
+                    let image = image.to_rgba8();
+                    Some(Framebuffer { w: image.width(), h: image.height(), rgba: image.into_raw() })
+                }
+                Err(e) => return Outcome::Error(format!("Couldn't read screenshot: {}", e)),
+            }
+        }
+        None => None,
+    };
+    scenario.check.evaluate(Some(&text), framebuffer.as_ref())
+}
+
+#[derive(Debug, Default)]
+pub struct BisectReport {
+    /// Index of the first failing candidate, if any failed.
+    pub first_failing: Option<usize>,
+    pub runs: usize,
+}
+
+/// Run candidates in order until one fails.
+pub fn scan(count: usize, mut run: impl FnMut(usize) -> Outcome) -> BisectReport {
+    let mut report = BisectReport::default();
+    for i in 0..count {
+        report.runs += 1;
+        if let Outcome::Fail(_) = run(i) {
+            report.first_failing = Some(i);
+            break;
+        }
+    }
+    report
+}
+
+/// Binary search ordered candidates for the first failing one. The last candidate must fail; if
+/// the first candidate fails too, the regression is older than the range and the first candidate
+/// is reported. Candidates that can't be run are skipped, so the reported candidate may follow
+/// skipped ones that would also have failed.
+pub fn bisect(count: usize, mut run: impl FnMut(usize) -> Outcome) -> BisectReport {
+    let mut report = BisectReport::default();
+    let mut candidates: Vec<usize> = (0..count).collect();
+    let mut test = |i: usize, report: &mut BisectReport| {
+        report.runs += 1;
+        run(i)
+    };
+
+    // Find the newest candidate that runs. If it passes there is nothing to find.
+    loop {
+        let Some(&last) = candidates.last() else { return report };
+        match test(last, &mut report) {
+            Outcome::Pass => return report,
+            Outcome::Fail(_) => break,
+            Outcome::Error(_) => _ = candidates.pop(),
+        }
+    }
+
+    // Find the oldest candidate that runs. 
+    loop {
+        if candidates.len() == 1 {
+            report.first_failing = Some(candidates[0]);
+            return report
+        }
+        match test(candidates[0], &mut report) {
+            Outcome::Pass => break,
+            Outcome::Fail(_) => {
+                report.first_failing = Some(candidates[0]);
+                return report
+            }
+            Outcome::Error(_) => _ = candidates.remove(0),
+        }
+    }
+
+    // The candidate at lo passes and the candidate at hi fails.
+    let (mut lo, mut hi) = (0, candidates.len() - 1);
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        match test(candidates[mid], &mut report) {
+            Outcome::Pass => lo = mid,
+            Outcome::Fail(_) => hi = mid,
+            Outcome::Error(_) => {
+                candidates.remove(mid);
+                hi -= 1;
+            }
+        }
+    }
+    report.first_failing = Some(candidates[hi]);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bisect() {
+        use Outcome::*;
+        let fail = || Fail(String::new());
+        let error = || Error(String::new());
+
+        let results = [Pass, Pass, Pass, fail(), fail(), fail()];
+        let report = bisect(results.len(), |i| results[i].clone());
+        assert_eq!(report.first_failing, Some(3));
+        assert!(report.runs < results.len());
+        assert_eq!(scan(results.len(), |i| results[i].clone()).first_failing, Some(3));
+
+        // Candidates that can't be run are skipped.
+        let results = [error(), Pass, error(), error(), fail(), error()];
+        assert_eq!(bisect(results.len(), |i| results[i].clone()).first_failing, Some(4));
+
+        let results = [Pass, Pass];
+        assert_eq!(bisect(results.len(), |i| results[i].clone()).first_failing, None);
+        let results = [fail(), fail()];
+        assert_eq!(bisect(results.len(), |i| results[i].clone()).first_failing, Some(0));
+    }
+
+    #[test]
+    fn test_flags() {
+        let candidates = flag_candidates(&["machine.turbo".to_string(), "cpu.off_rails_detection".to_string()]);
+        assert_eq!(candidates.len(), 4);
+        assert_eq!(candidates[1].to_string(), "machine.turbo=true, cpu.off_rails_detection=false");
+
+        let text = apply_flags("[machine]\nturbo = false\n[cpu]\n", &[("machine.turbo".to_string(), true)]).unwrap();
+        let value: toml::Value = text.parse().unwrap();
+        assert_eq!(value["machine"]["turbo"].as_bool(), Some(true));
+        assert!(apply_flags("[machine]\n", &[("video.turbo".to_string(), true)]).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    bug_report.rs
+
+    Bundles the emulator display, the effective configuration and the
+    contents of open debugger windows into a timestamped folder that can be
+    attached to a bug report.
+
+    The version of egui we use cannot capture images of individual windows,
+    so debugger windows are saved as text.
+*/
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use marty_core::{
+    machine::Machine,
+    syntax_token::tokens_to_string
+};
+
+use crate::egui::GuiWindow;
+
+pub struct BugReport {
+    dir: PathBuf
+}
+
+impl BugReport {
+    /// Create a new, empty report folder in 'reports_dir'.
+    pub fn new(reports_dir: &Path) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut dir = PathBuf::from(reports_dir);
+        dir.push(format!("report_{}", timestamp));
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Add a text file to the report.
+    pub fn add_text(&self, name: &str, text: &str) -> io::Result<()> {
+        fs::write(self.dir.join(name), text)
+    }
+
+    /// Add an RGBA image to the report as a PNG file.
+    pub fn add_image(&self, name: &str, buf: &[u8], w: u32, h: u32) -> Result<(), image::ImageError> {
+        let len = (w * h * 4) as usize;
+        image::save_buffer(self.dir.join(name), &buf[..len.min(buf.len())], w, h, image::ColorType::Rgba8)
+    }
+}
+
+/// Return a text dump of the contents of the specified debugger window, or None if the 
+/// window has no meaningful text representation.
+pub fn window_text(machine: &mut Machine, window: GuiWindow) -> Option<String> {
+    match window {
+        GuiWindow::CpuStateViewer => Some(format!("{:#?}", machine.cpu().get_string_state())),
+        GuiWindow::HistoryViewer => Some(machine.cpu().dump_instruction_history_string()),
+        GuiWindow::CallStack => Some(machine.cpu().dump_call_stack()),
+        GuiWindow::IvrViewer => Some(tokens_to_string(&machine.bus_mut().dump_ivr_tokens())),
+        GuiWindow::PicViewer => Some(format!("{:#?}", machine.pic_state())),
+        GuiWindow::InterruptViewer => Some(format!("{:#?}", machine.interrupt_state().irqs)),
+        GuiWindow::PpiViewer => machine.ppi_state().map(|state| format!("{:#?}", state)),
+        GuiWindow::DmaViewer => Some(format!("{:#?}", machine.dma_state())),
+        GuiWindow::PitViewer => {
+            let mut text = String::new();
+            for (i, channel) in machine.pit_state().iter().enumerate() {
+                text.push_str(&format!("Channel {}:\n", i));
+                for (key, token) in channel {
+                    text.push_str(&format!("    {}: {}\n", key, token));
+                }
+            }
+            Some(text)
+        }
+        GuiWindow::VideoCardViewer => machine.videocard_state().map(|state| format!("{:#?}", state)),
+        _ => None
+    }
+}
@@ -32,7 +32,8 @@
 */
 
 use crate::egui::*;
-use marty_render::CompositeParams;
+use crate::egui::help::help_button;
+use marty_render::{CompositeParams, CompositeStandard};
 
 pub struct CompositeAdjustControl {
     params: CompositeParams
@@ -47,13 +48,26 @@ impl CompositeAdjustControl {
         }
     }
 
-    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent> ) {
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+            help_button(ui, HelpTopic::Composite, events);
+        });
       
         egui::Grid::new("composite_adjust")
             .striped(false)
             .min_col_width(100.0)
             .show(ui, |ui| {
-                
+
+                    ui.label(egui::RichText::new("Standard:").text_style(egui::TextStyle::Monospace));
+                    egui::ComboBox::from_id_source("composite_standard")
+                        .selected_text(self.params.standard.to_string())
+                        .show_ui(ui, |ui| {
+                            for standard in CompositeStandard::ALL {
+                                ui.selectable_value(&mut self.params.standard, standard, standard.to_string());
+                            }
+                        });
+                ui.end_row();
                     ui.label(egui::RichText::new("Hue:").text_style(egui::TextStyle::Monospace));
                     ui.add(egui::Slider::new(&mut self.params.hue, 0.0..=2.0));
                 ui.end_row();
@@ -0,0 +1,111 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    -------------------------------------------------------------------------
+
+    egui::composite_capture.rs
+
+    Displays a captured pair of RGBI and composite frames side by side for
+    comparison.
+
+*/
+
+use crate::egui::*;
+use marty_render::CompositeCapture;
+
+const CAPTURE_DISPLAY_SCALE: f32 = 0.5;
+
+pub struct CompositeCaptureViewer {
+    capture: Option<CompositeCapture>,
+    rgbi_texture: Option<egui::TextureHandle>,
+    composite_texture: Option<egui::TextureHandle>,
+    textures_dirty: bool,
+}
+
+impl CompositeCaptureViewer {
+
+    pub fn new() -> Self {
+        Self {
+            capture: None,
+            rgbi_texture: None,
+            composite_texture: None,
+            textures_dirty: false,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, ctx: &Context, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            if ui.button("Capture").clicked() {
+                events.push_back(GuiEvent::CaptureComposite);
+            }
+            ui.add_enabled_ui(self.capture.is_some(), |ui| {
+                if ui.button("Save").clicked() {
+                    events.push_back(GuiEvent::SaveCompositeCapture);
+                }
+            });
+        });
+        ui.separator();
+
+        let capture = match &self.capture {
+            Some(capture) => capture,
+            None => {
+                ui.label("Enable the composite monitor and press Capture to grab the next frame.");
+                return
+            }
+        };
+
+        if self.textures_dirty {
+            let size = [capture.w as usize, capture.h as usize];
+            let rgbi_image = ColorImage::from_rgba_unmultiplied(size, &capture.rgbi);
+            let composite_image = ColorImage::from_rgba_unmultiplied(size, &capture.composite);
+            self.rgbi_texture = Some(ctx.load_texture("composite_capture_rgbi", rgbi_image, Default::default()));
+            self.composite_texture = Some(ctx.load_texture("composite_capture_composite", composite_image, Default::default()));
+            self.textures_dirty = false;
+        }
+
+        if let (Some(rgbi_texture), Some(composite_texture)) = (&self.rgbi_texture, &self.composite_texture) {
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("RGBI");
+                    ui.image(rgbi_texture, rgbi_texture.size_vec2() * CAPTURE_DISPLAY_SCALE);
+                });
+                ui.vertical(|ui| {
+                    ui.label("Composite");
+                    ui.image(composite_texture, composite_texture.size_vec2() * CAPTURE_DISPLAY_SCALE);
+                });
+            });
+        }
+    }
+
+    pub fn set_capture(&mut self, capture: CompositeCapture) {
+        self.capture = Some(capture);
+        self.textures_dirty = true;
+    }
+
+    pub fn capture(&self) -> Option<&CompositeCapture> {
+        self.capture.as_ref()
+    }
+}
@@ -38,3 +38,6 @@ pub const DMA_VIEWER_WIDTH: f32 = 350.0;
 
 // Color definitions
 pub const COLOR32_CYAN: Color32 = Color32::from_rgb(0, 255, 255);
+
+// Rewind menu steps, in frames
+pub const REWIND_FRAME_STEPS: [u64; 4] = [30, 60, 300, 600];
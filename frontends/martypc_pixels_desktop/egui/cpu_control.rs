@@ -36,14 +36,25 @@ use std::{
     rc::Rc,
 };
 use crate::egui::*;
+use crate::egui::help::help_button;
 
-use marty_core::machine::{ExecutionControl, ExecutionState, ExecutionOperation};
+use marty_core::{
+    breakpoints::WatchType,
+    bus::BusAccess,
+    machine::{ExecutionControl, ExecutionState, ExecutionOperation}
+};
 pub struct CpuControl {
 
     exec_control: Rc<RefCell<ExecutionControl>>,
     breakpoint: String,
+    breakpoint_condition: String,
+    condition_error: Option<String>,
     mem_breakpoint: String,
     int_breakpoint: String,
+    watch: String,
+    watch_len: String,
+    watch_type: WatchType,
+    watch_hit: Option<BusAccess>,
 }
 
 impl CpuControl {
@@ -52,8 +63,14 @@ impl CpuControl {
         Self {
             exec_control,
             breakpoint: String::new(),
+            breakpoint_condition: String::new(),
+            condition_error: None,
             mem_breakpoint: String::new(),
             int_breakpoint: String::new(),
+            watch: String::new(),
+            watch_len: String::new(),
+            watch_type: Default::default(),
+            watch_hit: None,
         }
     }
 
@@ -154,6 +171,9 @@ impl CpuControl {
         ui.horizontal(|ui|{
             ui.label("Run state: ");
             ui.label(&state_str);
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                help_button(ui, HelpTopic::Debugger, events);
+            });
         });
         ui.separator();
         ui.horizontal(|ui|{
@@ -162,6 +182,15 @@ impl CpuControl {
                 events.push_back(GuiEvent::EditBreakpoint);
             };
         });
+        ui.horizontal(|ui|{
+            ui.label("Condition: ");
+            if ui.text_edit_singleline(&mut self.breakpoint_condition).changed() {
+                events.push_back(GuiEvent::EditBreakpoint);
+            };
+        });
+        if let Some(err) = &self.condition_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, err);
+        }
         ui.separator();
         ui.horizontal(|ui|{
             ui.label("Mem Breakpoint: ");
@@ -175,12 +204,58 @@ impl CpuControl {
             if ui.text_edit_singleline(&mut self.int_breakpoint).changed() {
                 events.push_back(GuiEvent::EditBreakpoint);
             }
-        });                
+        });
+        ui.separator();
+        ui.horizontal(|ui|{
+            ui.label("Watch: ");
+            if ui.text_edit_singleline(&mut self.watch).changed() {
+                events.push_back(GuiEvent::EditBreakpoint);
+            }
+        });
+        ui.horizontal(|ui|{
+            ui.label("Length: ");
+            if ui.add(egui::TextEdit::singleline(&mut self.watch_len).desired_width(50.0)).changed() {
+                events.push_back(GuiEvent::EditBreakpoint);
+            }
+            let old_type = self.watch_type;
+            egui::ComboBox::from_id_source("watch_type")
+                .selected_text(self.watch_type.to_string())
+                .show_ui(ui, |ui| {
+                    for watch_type in [WatchType::Read, WatchType::Write, WatchType::ReadWrite] {
+                        ui.selectable_value(&mut self.watch_type, watch_type, watch_type.to_string());
+                    }
+                });
+            if self.watch_type != old_type {
+                events.push_back(GuiEvent::EditBreakpoint);
+            }
+        });
+        if let Some(hit) = &self.watch_hit {
+            ui.label(format!("Last hit: {}", hit));
+        }
     }
 
     pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
         (&self.breakpoint, &self.mem_breakpoint, &self.int_breakpoint)
     }
 
+    pub fn get_breakpoint_condition(&self) -> &str {
+        &self.breakpoint_condition
+    }
+
+    /// Set the error message from parsing the breakpoint condition, or None if it is valid.
+    pub fn set_condition_error(&mut self, error: Option<String>) {
+        self.condition_error = error;
+    }
+
+    /// Set the memory access that last triggered a watchpoint, including accesses made by DMA.
+    pub fn set_watch_hit(&mut self, hit: Option<BusAccess>) {
+        self.watch_hit = hit;
+    }
+
+    /// Return the watchpoint address expression, length (in hex) and access type.
+    pub fn get_watchpoint(&self) -> (&str, &str, WatchType) {
+        (&self.watch, &self.watch_len, self.watch_type)
+    }
+
 
 }
\ No newline at end of file
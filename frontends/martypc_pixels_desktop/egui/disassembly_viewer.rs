@@ -31,6 +31,11 @@
     the next X instructions from the specified address. This address can
     be an expression, such as 'cs:ip'
 
+    The view can be locked to CS:IP so that it follows execution, and 
+    arrows are drawn from direct jumps and calls to their targets. Clicking
+    an address selects it for patching, either by assembling an instruction
+    or by entering bytes in hex. Patches can be undone.
+
 */
 use std::collections::VecDeque;
 
@@ -38,12 +43,33 @@ use crate::egui::*;
 use crate::egui::token_listview::*;
 use marty_core::syntax_token::*;
 
+pub const DISASSEMBLY_ROWS: usize = 24;
+
+/// Describes a row of the disassembly listing.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DisassemblyRow {
+    pub address: u32,
+    pub cs_ip: Option<(u16, u16)>,
+    /// The flat address of the branch target, if this row is a direct branch.
+    pub target: Option<u32>,
+}
+
 pub struct DisassemblyControl {
 
     pub address: String,
     pub row: usize,
     pub lastrow: usize,
     tlv: TokenListView,
+
+    follow: bool,
+    rows: Vec<DisassemblyRow>,
+    current: Option<(u16, u16)>,
+
+    patch_address: String,
+    patch_text: String,
+    patch_hex: bool,
+    patch_count: usize,
+    status: String,
 }
 
 impl DisassemblyControl {
@@ -53,31 +79,121 @@ impl DisassemblyControl {
             address: "cs:ip".to_string(),
             row: 0,
             lastrow: 0,
-            tlv: TokenListView::new()
+            tlv: TokenListView::new(),
+
+            follow: false,
+            rows: Vec::new(),
+            current: None,
+
+            patch_address: String::new(),
+            patch_text: String::new(),
+            patch_hex: false,
+            patch_count: 0,
+            status: String::new(),
         }
     }
 
     pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
 
         ui.horizontal(|ui| {
-            ui.label("Address: ");
-            if ui.text_edit_singleline(&mut self.address).changed() {
-                events.push_back(GuiEvent::MemoryUpdate);
+            if ui.checkbox(&mut self.follow, "Follow CS:IP").changed() && !self.follow {
+                // Stop at the current instruction so the listing can be browsed from there.
+                if let Some((cs, ip)) = self.current {
+                    self.address = format!("{:04X}:{:04X}", cs, ip);
+                }
             }
+            ui.label("Address: ");
+            ui.add_enabled_ui(!self.follow, |ui| {
+                if ui.text_edit_singleline(&mut self.address).changed() {
+                    events.push_back(GuiEvent::MemoryUpdate);
+                }
+            });
         });
         ui.separator();
 
-        self.tlv.set_capacity(24);
-        self.tlv.set_visible(24);
+        self.tlv.set_capacity(DISASSEMBLY_ROWS);
+        self.tlv.set_visible(DISASSEMBLY_ROWS);
 
         let mut new_row = self.row;
         ui.horizontal(|ui| {
             self.tlv.draw(ui, events, &mut new_row);
         });
+
+        if let Some((address, _)) = self.tlv.take_clicked() {
+            if let Some(row) = self.rows.iter().find(|row| row.address as usize == address) {
+                self.patch_address = match row.cs_ip {
+                    Some((cs, ip)) => format!("{:04X}:{:04X}", cs, ip),
+                    None => format!("{:05X}", row.address),
+                };
+            }
+        }
+
+        ui.separator();
+        egui::Grid::new("disassembly_patch")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Patch at: ");
+                ui.text_edit_singleline(&mut self.patch_address)
+                    .on_hover_text("Click an address in the listing to select it");
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.patch_hex, false, "Assemble");
+                    ui.radio_value(&mut self.patch_hex, true, "Hex");
+                });
+                let hint = match self.patch_hex {
+                    true => "Bytes in hex, e.g. 90 90",
+                    false => "Instruction, e.g. jmp 0120",
+                };
+                ui.add(egui::TextEdit::singleline(&mut self.patch_text).hint_text(hint));
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                events.push_back(GuiEvent::PatchCode(self.patch_address.clone(), self.patch_text.clone(), self.patch_hex));
+            }
+            if ui.add_enabled(self.patch_count > 0, egui::Button::new("Undo")).clicked() {
+                events.push_back(GuiEvent::UndoPatch);
+            }
+            ui.label(&self.status);
+        });
     }
 
-    pub fn set_content(&mut self, mem: Vec<Vec<SyntaxToken>>) {
+    /// Set the contents of the listing. The row descriptions are used to draw branch arrows 
+    /// and to highlight the instruction at CS:IP.
+    pub fn set_content(&mut self, mem: Vec<Vec<SyntaxToken>>, rows: Vec<DisassemblyRow>, current: (u16, u16)) {
+
+        let current_flat = ((current.0 as u32) << 4).wrapping_add(current.1 as u32) & 0xFFFFF;
+        self.tlv.set_highlight_row(rows.iter().position(|row| row.address == current_flat));
+
+        let arrows = rows.iter()
+            .enumerate()
+            .filter_map(|(i, row)| {
+                row.target.map(|target| RowArrow {
+                    from: i,
+                    to: rows.iter().position(|r| r.address == target),
+                    down: target > row.address,
+                })
+            })
+            .collect();
+        self.tlv.set_arrows(arrows);
         self.tlv.set_contents(mem);
+
+        self.rows = rows;
+        self.current = Some(current);
+    }
+
+    pub fn follow(&self) -> bool {
+        self.follow
+    }
+
+    pub fn set_patch_count(&mut self, count: usize) {
+        self.patch_count = count;
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
     }
 
     #[allow(dead_code)]
@@ -86,6 +202,9 @@ impl DisassemblyControl {
     }
 
     pub fn get_address(&mut self) -> String {
-        self.address.clone()
+        match self.follow {
+            true => "cs:ip".to_string(),
+            false => self.address.clone(),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,179 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::disk_creator.rs
+
+    Implements a window for creating new blank floppy and hard disk images,
+    optionally formatted so that they are usable without running FORMAT or 
+    FDISK first. Floppy images are created in the floppy directory and VHDs
+    in the hdd directory.
+
+*/
+
+use crate::egui::*;
+
+use marty_core::{
+    devices::hdc::HardDiskFormat,
+    disk_image::{self, FloppyFormat},
+};
+
+const FLOPPY_FILENAME_EXTENSIONS: [&str; 2] = [".img", ".ima"];
+const VHD_FILENAME_EXTENSION: &str = ".vhd";
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum DiskKind {
+    Floppy,
+    HardDisk,
+}
+
+pub struct DiskCreatorControl {
+    kind: DiskKind,
+    floppy_format: FloppyFormat,
+    vhd_formats: Vec<HardDiskFormat>,
+    vhd_format_idx: usize,
+    formatted: bool,
+    floppy_filename: String,
+    vhd_filename: String,
+}
+
+impl DiskCreatorControl {
+
+    pub fn new() -> Self {
+        Self {
+            kind: DiskKind::Floppy,
+            floppy_format: FloppyFormat::Floppy360K,
+            vhd_formats: disk_image::hard_disk_formats(),
+            vhd_format_idx: 1,
+            formatted: true,
+            floppy_filename: String::new(),
+            vhd_filename: String::new(),
+        }
+    }
+
+    /// Return whether a filename is usable: non-empty, not a path, and with the right extension.
+    fn filename_valid(filename: &str, extensions: &[&str]) -> bool {
+        let lower = filename.to_lowercase();
+        !filename.contains(['/', '\\'])
+            && extensions.iter().any(|ext| lower.len() > ext.len() && lower.ends_with(ext))
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.kind, DiskKind::Floppy, "💾 Floppy Image");
+            ui.radio_value(&mut self.kind, DiskKind::HardDisk, "🖴 Hard Disk (VHD)");
+        });
+        ui.separator();
+
+        egui::Grid::new("disk_creator_grid")
+            .num_columns(2)
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+                ui.label("Format:");
+                match self.kind {
+                    DiskKind::Floppy => {
+                        egui::ComboBox::from_id_source("disk_creator_floppy_format")
+                            .selected_text(self.floppy_format.desc())
+                            .show_ui(ui, |ui| {
+                                for format in FloppyFormat::ALL {
+                                    ui.selectable_value(&mut self.floppy_format, format, format.desc());
+                                }
+                            });
+                    }
+                    DiskKind::HardDisk => {
+                        egui::ComboBox::from_id_source("disk_creator_vhd_format")
+                            .selected_text(self.vhd_formats[self.vhd_format_idx].desc.as_str())
+                            .show_ui(ui, |ui| {
+                                for (i, fmt) in self.vhd_formats.iter().enumerate() {
+                                    ui.selectable_value(&mut self.vhd_format_idx, i, fmt.desc.as_str());
+                                }
+                            });
+                    }
+                }
+                ui.end_row();
+
+                ui.label("Geometry:");
+                let geometry = match self.kind {
+                    DiskKind::Floppy => {
+                        let (c, h, s) = self.floppy_format.geometry();
+                        format!("{} cylinders, {} heads, {} sectors", c, h, s)
+                    }
+                    DiskKind::HardDisk => {
+                        let fmt = &self.vhd_formats[self.vhd_format_idx];
+                        format!("{} cylinders, {} heads, {} sectors", fmt.max_cylinders, fmt.max_heads, fmt.max_sectors)
+                    }
+                };
+                ui.label(egui::RichText::new(geometry).monospace());
+                ui.end_row();
+
+                ui.label("Filename:");
+                let filename = match self.kind {
+                    DiskKind::Floppy => &mut self.floppy_filename,
+                    DiskKind::HardDisk => &mut self.vhd_filename,
+                };
+                ui.text_edit_singleline(filename);
+                ui.end_row();
+            });
+
+        let format_hint = match self.kind {
+            DiskKind::Floppy => "Write an empty FAT12 filesystem to the image.",
+            DiskKind::HardDisk => "Create a single active DOS partition with an empty FAT filesystem.",
+        };
+        ui.checkbox(&mut self.formatted, "Formatted").on_hover_text(format_hint);
+        ui.separator();
+
+        let (enabled, hint) = match self.kind {
+            DiskKind::Floppy => (
+                Self::filename_valid(&self.floppy_filename, &FLOPPY_FILENAME_EXTENSIONS),
+                "The image will be created in the floppy directory. Filename must end in .img or .ima."
+            ),
+            DiskKind::HardDisk => (
+                Self::filename_valid(&self.vhd_filename, &[VHD_FILENAME_EXTENSION]),
+                "The image will be created in the hdd directory. Filename must end in .vhd."
+            ),
+        };
+        ui.label(hint);
+
+        if ui.add_enabled(enabled, egui::Button::new("Create")).clicked() {
+            match self.kind {
+                DiskKind::Floppy => {
+                    events.push_back(GuiEvent::CreateFloppy(
+                        OsString::from(&self.floppy_filename),
+                        self.floppy_format,
+                        self.formatted
+                    ));
+                }
+                DiskKind::HardDisk => {
+                    events.push_back(GuiEvent::CreateVHD(
+                        OsString::from(&self.vhd_filename),
+                        self.vhd_formats[self.vhd_format_idx].clone(),
+                        self.formatted
+                    ));
+                }
+            }
+        }
+    }
+}
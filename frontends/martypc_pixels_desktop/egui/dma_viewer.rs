@@ -26,19 +26,42 @@
 
     egui::dma_viewer.rs
 
-    Implements a viewer control for the DMA Controller.
+    Implements a viewer control for the DMA Controller. The registers and
+    mode of all four channels are shown side by side, along with the number
+    of bytes each channel has transferred and its current throughput.
 
 */
 #[allow (dead_code)]
 
-use marty_core::devices::dma::DMAControllerStringState;
+use marty_core::devices::dma::{DMAChannelStringState, DMAControllerStringState};
 use crate::egui::*;
 use crate::egui::constants::*;
+use crate::egui::rate_meter::RateMeter;
+
+type ChannelField = fn(&DMAChannelStringState) -> &String;
+
+const CHANNEL_ROWS: [(&str, ChannelField); 14] = [
+    ("Page:", |c| &c.page),
+    ("CAR:", |c| &c.current_address_reg),
+    ("CWC:", |c| &c.current_word_count_reg),
+    ("BAR:", |c| &c.base_address_reg),
+    ("BWC:", |c| &c.base_word_count_reg),
+    ("Address:", |c| &c.physical_address),
+    ("Service Mode:", |c| &c.service_mode),
+    ("Address Mode:", |c| &c.address_mode),
+    ("Xfer Type:", |c| &c.transfer_type),
+    ("Auto Init:", |c| &c.auto_init),
+    ("Masked:", |c| &c.masked),
+    ("DREQ:", |c| &c.request),
+    ("Terminal Ct:", |c| &c.terminal_count),
+    ("TC Reached:", |c| &c.terminal_count_reached),
+];
 
 pub struct DmaViewerControl {
 
     dma_state: DMAControllerStringState,
-    dma_channel_select: u32, 
+    transfer_counts: [u64; 4],
+    throughput: RateMeter<4>,
 }
 
 impl DmaViewerControl {
@@ -46,98 +69,73 @@ impl DmaViewerControl {
     pub fn new() -> Self {
         Self {
             dma_state: Default::default(),
-            dma_channel_select: 0,
+            transfer_counts: [0; 4],
+            throughput: RateMeter::new(),
         }
     }
     
     pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent> ) {
 
+        ui.set_min_width(DMA_VIEWER_WIDTH);
+
         egui::Grid::new("dma_view")
             .num_columns(2)
             .striped(true)
             .min_col_width(50.0)
             .show(ui, |ui| {
 
-                ui.set_min_width(DMA_VIEWER_WIDTH);
-
-                ui.label(egui::RichText::new("Enabled:".to_string()).text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.dma_state.enabled).font(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Enabled:").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new(&self.dma_state.enabled).text_style(egui::TextStyle::Monospace));
                 ui.end_row();     
 
-                ui.label(egui::RichText::new("DREQ:".to_string()).text_style(egui::TextStyle::Monospace));
-                ui.add(egui::TextEdit::singleline(&mut self.dma_state.dreq).font(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new("Flip-flop:").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new(&self.dma_state.flipflop).text_style(egui::TextStyle::Monospace));
                 ui.end_row();  
 
-                ui.separator();
-                ui.end_row();    
-
-                ui.horizontal(|ui| {
-                    egui::ComboBox::from_label("Channel #")
-                        .selected_text(format!("Channel #{}", self.dma_channel_select))
-                        .show_ui(ui, |ui| {
-                            for (i, _chan) in self.dma_state.dma_channel_state.iter_mut().enumerate() {
-                                ui.selectable_value(&mut self.dma_channel_select, i as u32, format!("Channel #{}",i));
-                            }
-                        });
-                });                        
-                ui.end_row();   
-
-                if (self.dma_channel_select as usize) < self.dma_state.dma_channel_state.len() {
-                    let chan = &mut self.dma_state.dma_channel_state[self.dma_channel_select as usize];
-
-                    ui.label(egui::RichText::new(format!("#{} CAR:         ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.current_address_reg).font(egui::TextStyle::Monospace));
-                    ui.end_row();
-
-                    ui.label(egui::RichText::new(format!("#{} Page:        ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.page).font(egui::TextStyle::Monospace));
-                    ui.end_row();                      
-
-                    ui.label(egui::RichText::new(format!("#{} CWC:         ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.current_word_count_reg).font(egui::TextStyle::Monospace));
-                    ui.end_row();
-
-                    ui.label(egui::RichText::new(format!("#{} BAR:         ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.base_address_reg).font(egui::TextStyle::Monospace));
-                    ui.end_row();
+                ui.label(egui::RichText::new("DREQ:").text_style(egui::TextStyle::Monospace));
+                ui.label(egui::RichText::new(&self.dma_state.dreq).text_style(egui::TextStyle::Monospace));
+                ui.end_row();  
+            });
 
-                    ui.label(egui::RichText::new(format!("#{} BWC:         ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.base_word_count_reg).font(egui::TextStyle::Monospace));
-                    ui.end_row();    
+        ui.separator();
 
-                    ui.label(egui::RichText::new(format!("#{} Service Mode:", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.service_mode).font(egui::TextStyle::Monospace));
-                    ui.end_row();
+        egui::Grid::new("dma_channel_view")
+            .striped(true)
+            .min_col_width(60.0)
+            .show(ui, |ui| {
 
-                    ui.label(egui::RichText::new(format!("#{} Address Mode:", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.address_mode).font(egui::TextStyle::Monospace));
-                    ui.end_row();
+                ui.label("");
+                for i in 0..self.dma_state.dma_channel_state.len() {
+                    ui.label(egui::RichText::new(format!("Channel #{}", i)).strong());
+                }
+                ui.end_row();
 
-                    ui.label(egui::RichText::new(format!("#{} Xfer Type:   ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.transfer_type).font(egui::TextStyle::Monospace));
+                for (label, field) in CHANNEL_ROWS {
+                    ui.label(egui::RichText::new(label).text_style(egui::TextStyle::Monospace));
+                    for chan in &self.dma_state.dma_channel_state {
+                        ui.label(egui::RichText::new(field(chan)).text_style(egui::TextStyle::Monospace));
+                    }
                     ui.end_row();
+                }
 
-                    ui.label(egui::RichText::new(format!("#{} Auto Init:   ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.auto_init).font(egui::TextStyle::Monospace));
-                    ui.end_row();   
-
-                    ui.label(egui::RichText::new(format!("#{} Terminal Ct: ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.terminal_count).font(egui::TextStyle::Monospace));
-                    ui.end_row();  
-
-                    ui.label(egui::RichText::new(format!("#{} TC Reached:  ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.terminal_count_reached).font(egui::TextStyle::Monospace));
-                    ui.end_row();
+                ui.label(egui::RichText::new("Transferred:").text_style(egui::TextStyle::Monospace));
+                for count in self.transfer_counts {
+                    ui.label(egui::RichText::new(format!("{}", count)).text_style(egui::TextStyle::Monospace));
+                }
+                ui.end_row();
 
-                    ui.label(egui::RichText::new(format!("#{} Masked:      ", self.dma_channel_select)).text_style(egui::TextStyle::Monospace));
-                    ui.add(egui::TextEdit::singleline(&mut chan.masked).font(egui::TextStyle::Monospace));
-                    ui.end_row();
+                ui.label(egui::RichText::new("Bytes/s:").text_style(egui::TextStyle::Monospace));
+                for i in 0..self.transfer_counts.len() {
+                    ui.label(egui::RichText::new(format!("{}", self.throughput.rate(i))).text_style(egui::TextStyle::Monospace));
                 }
+                ui.end_row();
             });
     }
 
-    pub fn update_state(&mut self, state: DMAControllerStringState) {
+    pub fn update_state(&mut self, state: DMAControllerStringState, transfer_counts: [u64; 4]) {
         self.dma_state = state;
+        self.transfer_counts = transfer_counts;
+        self.throughput.update(&transfer_counts);
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,187 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+
+    egui::event_timeline.rs
+
+    Implements the peripheral event timeline. IRQ requests, DMA requests, 
+    FDC commands and video mode changes are drawn on separate tracks along a
+    shared CPU cycle axis. The view can be zoomed with the mouse wheel or
+    slider and panned by dragging; hovering an event shows the CS:IP it was
+    recorded at.
+
+*/
+
+use egui::Color32;
+
+use crate::egui::*;
+use marty_core::event_timeline::{TimelineEvent, TimelineTrack};
+
+const LABEL_W: f32 = 48.0;
+const TRACK_H: f32 = 24.0;
+const AXIS_H: f32 = 16.0;
+const GRAPH_MIN_W: f32 = 320.0;
+/// Distance in pixels within which the pointer hovers an event.
+const HOVER_DIST: f32 = 3.0;
+
+const MIN_CYCLES_PER_PX: f64 = 1.0;
+const MAX_CYCLES_PER_PX: f64 = 1_000_000.0;
+const DEFAULT_CYCLES_PER_PX: f64 = 500.0;
+
+fn track_color(track: TimelineTrack) -> Color32 {
+    match track {
+        TimelineTrack::Irq => Color32::from_rgb(0xE0, 0x60, 0x40),
+        TimelineTrack::Dma => Color32::from_rgb(0x40, 0xC0, 0x40),
+        TimelineTrack::Fdc => Color32::from_rgb(0x60, 0x90, 0xFF),
+        TimelineTrack::Video => Color32::from_rgb(0xE0, 0xC0, 0x40),
+    }
+}
+
+pub struct EventTimelineViewer {
+    events: Vec<TimelineEvent>,
+    recording: bool,
+    current_cycle: u64,
+    /// Cycle at the right edge of the graph.
+    end_cycle: u64,
+    cycles_per_px: f64,
+    follow: bool,
+}
+
+impl EventTimelineViewer {
+
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            recording: false,
+            current_cycle: 0,
+            end_cycle: 0,
+            cycles_per_px: DEFAULT_CYCLES_PER_PX,
+            follow: true,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent>) {
+
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.recording, "Record").changed() {
+                events.push_back(GuiEvent::SetTimelineRecording(self.recording));
+            }
+            if ui.button("Clear").clicked() {
+                events.push_back(GuiEvent::ClearTimeline);
+            }
+            ui.checkbox(&mut self.follow, "Follow");
+            ui.add(
+                egui::Slider::new(&mut self.cycles_per_px, MIN_CYCLES_PER_PX..=MAX_CYCLES_PER_PX)
+                    .logarithmic(true)
+                    .text("cycles/px")
+            );
+        });
+        ui.label(format!("{} events", self.events.len()));
+
+        let graph_w = ui.available_width().max(GRAPH_MIN_W);
+        let graph_h = TRACK_H * TimelineTrack::ALL.len() as f32 + AXIS_H;
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(graph_w, graph_h), egui::Sense::click_and_drag());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_black_alpha(192));
+
+        // Pan by dragging and zoom with the mouse wheel
+        if response.dragged() {
+            let delta = (response.drag_delta().x as f64 * self.cycles_per_px) as i64;
+            self.end_cycle = (self.end_cycle as i64 - delta).clamp(0, self.current_cycle as i64) as u64;
+            self.follow = false;
+        }
+        if response.hovered() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            if scroll != 0.0 {
+                self.cycles_per_px = (self.cycles_per_px * (1.0 - scroll as f64 * 0.002))
+                    .clamp(MIN_CYCLES_PER_PX, MAX_CYCLES_PER_PX);
+            }
+        }
+        if self.follow {
+            self.end_cycle = self.current_cycle;
+        }
+
+        let graph_left = rect.left() + LABEL_W;
+        let span = ((rect.right() - graph_left) as f64 * self.cycles_per_px) as u64;
+        let start_cycle = self.end_cycle.saturating_sub(span);
+        let cycle_to_x = |cycle: u64| graph_left + ((cycle.saturating_sub(start_cycle)) as f64 / self.cycles_per_px) as f32;
+
+        let font = egui::FontId::monospace(10.0);
+        for (i, track) in TimelineTrack::ALL.iter().enumerate() {
+            let y = rect.top() + i as f32 * TRACK_H;
+            painter.text(
+                egui::pos2(rect.left() + 4.0, y + TRACK_H / 2.0),
+                egui::Align2::LEFT_CENTER,
+                track.to_string(),
+                font.clone(),
+                track_color(*track)
+            );
+            painter.line_segment(
+                [egui::pos2(rect.left(), y + TRACK_H), egui::pos2(rect.right(), y + TRACK_H)],
+                egui::Stroke::new(1.0, Color32::DARK_GRAY)
+            );
+        }
+
+        // Events are recorded in cycle order, so find the visible range by binary search.
+        let first = self.events.partition_point(|e| e.cycle < start_cycle);
+        let last = self.events.partition_point(|e| e.cycle <= self.end_cycle);
+
+        let hover_pos = response.hover_pos();
+        let mut hovered: Option<&TimelineEvent> = None;
+
+        for event in &self.events[first..last] {
+            let track = event.kind.track();
+            let row = TimelineTrack::ALL.iter().position(|t| *t == track).unwrap_or(0);
+            let x = cycle_to_x(event.cycle);
+            let top = rect.top() + row as f32 * TRACK_H + 2.0;
+            let bottom = top + TRACK_H - 4.0;
+
+            painter.line_segment([egui::pos2(x, top), egui::pos2(x, bottom)], egui::Stroke::new(1.0, track_color(track)));
+
+            if let Some(pos) = hover_pos {
+                if (pos.x - x).abs() <= HOVER_DIST && pos.y >= top && pos.y <= bottom {
+                    hovered = Some(event);
+                }
+            }
+        }
+
+        // Cycle axis
+        let axis_y = rect.bottom() - AXIS_H / 2.0;
+        painter.text(egui::pos2(graph_left, axis_y), egui::Align2::LEFT_CENTER, format!("{}", start_cycle), font.clone(), Color32::GRAY);
+        painter.text(egui::pos2(rect.right() - 4.0, axis_y), egui::Align2::RIGHT_CENTER, format!("{}", self.end_cycle), font, Color32::GRAY);
+
+        if let Some(event) = hovered {
+            response.on_hover_text_at_pointer(
+                format!("{}\nCycle: {}\nCS:IP: {:04X}:{:04X}", event.kind, event.cycle, event.cs, event.ip)
+            );
+        }
+    }
+
+    pub fn update(&mut self, events: &VecDeque<TimelineEvent>, recording: bool, current_cycle: u64) {
+        self.events.clear();
+        self.events.extend(events.iter().copied());
+        self.recording = recording;
+        self.current_cycle = current_cycle;
+    }
+}
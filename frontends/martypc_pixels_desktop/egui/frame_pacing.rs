@@ -0,0 +1,197 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::frame_pacing.rs
+
+    Implements the frame pacing diagnostics overlay. Each time a frame is 
+    presented, the main loop records the host time since the last present, 
+    the emulated time that passed, how many emulated frames completed and how
+    full the audio buffer is. The overlay graphs recent samples so that judder
+    on VRR and high refresh rate displays can be seen, and counts dropped and
+    duplicated frames.
+*/
+
+use std::{collections::VecDeque, time::Duration};
+
+use egui::Color32;
+
+use crate::egui::*;
+
+/// Number of presents to keep in the graph. The graph is one pixel per sample.
+const HISTORY_LEN: usize = 240;
+const GRAPH_H: f32 = 80.0;
+/// Present interval represented by the full height of the graph.
+const GRAPH_MAX_MS: f32 = 50.0;
+
+const COLOR_NORMAL: Color32 = Color32::from_rgb(0x40, 0xC0, 0x40);
+const COLOR_DROPPED: Color32 = Color32::from_rgb(0xE0, 0x40, 0x40);
+const COLOR_DUPLICATED: Color32 = Color32::from_rgb(0xE0, 0xC0, 0x40);
+const COLOR_EMULATED: Color32 = Color32::from_rgb(0x60, 0x90, 0xFF);
+const COLOR_AUDIO: Color32 = Color32::from_rgb(0xC0, 0x60, 0xE0);
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FramePacingSample {
+    /// Host time elapsed since the previous present.
+    pub present_interval: Duration,
+    /// Emulated time elapsed since the previous present.
+    pub emulated_interval: Duration,
+    /// Emulated frames (vsyncs) completed since the previous present. More than one means 
+    /// frames were dropped; zero means the previous frame was presented again.
+    pub new_frames: u64,
+    /// Audio buffer occupancy from 0.0 to 1.0, if there is an audio device.
+    pub audio_fill: Option<f32>,
+}
+
+pub struct FramePacingOverlay {
+    samples: VecDeque<FramePacingSample>,
+    dropped: u64,
+    duplicated: u64,
+}
+
+impl FramePacingOverlay {
+
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(HISTORY_LEN),
+            dropped: 0,
+            duplicated: 0,
+        }
+    }
+
+    pub fn push_sample(&mut self, sample: FramePacingSample) {
+        match sample.new_frames {
+            0 => self.duplicated += 1,
+            1 => {},
+            n => self.dropped += n - 1,
+        }
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn sample_color(sample: &FramePacingSample) -> Color32 {
+        match sample.new_frames {
+            0 => COLOR_DUPLICATED,
+            1 => COLOR_NORMAL,
+            _ => COLOR_DROPPED,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+
+        let intervals: Vec<f64> = self.samples.iter().map(|s| s.present_interval.as_secs_f64() * 1000.0).collect();
+        let count = intervals.len().max(1) as f64;
+        let mean = intervals.iter().sum::<f64>() / count;
+        let jitter = (intervals.iter().map(|i| (i - mean) * (i - mean)).sum::<f64>() / count).sqrt();
+        let min = intervals.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = intervals.iter().copied().fold(0.0, f64::max);
+
+        // Difference between host and emulated time over the samples shown. Positive values mean
+        // the emulator is falling behind the host.
+        let drift: f64 = self.samples
+            .iter()
+            .map(|s| s.present_interval.as_secs_f64() - s.emulated_interval.as_secs_f64())
+            .sum::<f64>() * 1000.0;
+
+        egui::Grid::new("frame_pacing_stats")
+            .striped(true)
+            .min_col_width(80.0)
+            .show(ui, |ui| {
+                ui.label("Present interval: ");
+                match intervals.is_empty() {
+                    true => ui.label("-"),
+                    false => ui.label(format!("{:.2}ms (min {:.2}, max {:.2})", mean, min, max)),
+                };
+                ui.end_row();
+                ui.label("Jitter: ");
+                ui.label(format!("{:.2}ms", jitter));
+                ui.end_row();
+                ui.label("Emulation drift: ");
+                ui.label(format!("{:+.2}ms", drift));
+                ui.end_row();
+                ui.label("Dropped frames: ");
+                ui.label(egui::RichText::new(format!("{}", self.dropped)).color(COLOR_DROPPED));
+                ui.end_row();
+                ui.label("Duplicated frames: ");
+                ui.label(egui::RichText::new(format!("{}", self.duplicated)).color(COLOR_DUPLICATED));
+                ui.end_row();
+                ui.label("Audio buffer: ");
+                match self.samples.back().and_then(|s| s.audio_fill) {
+                    Some(fill) => ui.label(format!("{:.0}%", fill * 100.0)),
+                    None => ui.label("No audio device"),
+                };
+                ui.end_row();
+            });
+
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(HISTORY_LEN as f32, GRAPH_H), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_black_alpha(192));
+
+        let ms_to_y = |ms: f64| rect.bottom() - (ms as f32 / GRAPH_MAX_MS).min(1.0) * GRAPH_H;
+
+        // Target frame time
+        let target_y = ms_to_y(1000.0 / crate::FPS_TARGET);
+        painter.line_segment(
+            [egui::pos2(rect.left(), target_y), egui::pos2(rect.right(), target_y)],
+            egui::Stroke::new(1.0, Color32::GRAY)
+        );
+
+        let mut last_emu_pos = None;
+        let mut last_audio_pos = None;
+        for (i, sample) in self.samples.iter().enumerate() {
+            let x = rect.left() + i as f32 + 0.5;
+
+            painter.line_segment(
+                [egui::pos2(x, rect.bottom()), egui::pos2(x, ms_to_y(sample.present_interval.as_secs_f64() * 1000.0))],
+                egui::Stroke::new(1.0, Self::sample_color(sample))
+            );
+
+            let emu_pos = egui::pos2(x, ms_to_y(sample.emulated_interval.as_secs_f64() * 1000.0));
+            if let Some(last) = last_emu_pos {
+                painter.line_segment([last, emu_pos], egui::Stroke::new(1.0, COLOR_EMULATED));
+            }
+            last_emu_pos = Some(emu_pos);
+
+            // Audio fill is scaled to the full height of the graph.
+            let audio_pos = sample.audio_fill.map(|fill| egui::pos2(x, rect.bottom() - fill.clamp(0.0, 1.0) * GRAPH_H));
+            if let (Some(last), Some(pos)) = (last_audio_pos, audio_pos) {
+                painter.line_segment([last, pos], egui::Stroke::new(1.0, COLOR_AUDIO));
+            }
+            last_audio_pos = audio_pos;
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(egui::RichText::new("■ present").color(COLOR_NORMAL));
+            ui.label(egui::RichText::new("— emulated").color(COLOR_EMULATED));
+            ui.label(egui::RichText::new("— audio").color(COLOR_AUDIO));
+        });
+
+        if ui.button("Reset").clicked() {
+            *self = Self::new();
+        }
+    }
+}
@@ -0,0 +1,330 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::help.rs
+
+    Implements a help browser for the emulator's built-in documentation.
+
+    Help topics are markdown files in assets/help, embedded into the binary.
+    Only the subset of markdown used by those files is rendered: headings,
+    bullet lists, code blocks, inline code, bold text and links. A link 
+    target without a scheme is the id of another help topic.
+
+*/
+
+use crate::egui::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HelpTopic {
+    Index,
+    MachineSetup,
+    Debugger,
+    Hotkeys,
+    Composite,
+    Scripting,
+}
+
+pub const HELP_TOPICS: [HelpTopic; 6] = [
+    HelpTopic::Index,
+    HelpTopic::MachineSetup,
+    HelpTopic::Debugger,
+    HelpTopic::Hotkeys,
+    HelpTopic::Composite,
+    HelpTopic::Scripting,
+];
+
+impl HelpTopic {
+    pub fn id(&self) -> &'static str {
+        match self {
+            HelpTopic::Index => "index",
+            HelpTopic::MachineSetup => "machine_setup",
+            HelpTopic::Debugger => "debugger",
+            HelpTopic::Hotkeys => "hotkeys",
+            HelpTopic::Composite => "composite",
+            HelpTopic::Scripting => "scripting",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            HelpTopic::Index => "Contents",
+            HelpTopic::MachineSetup => "Machine Setup",
+            HelpTopic::Debugger => "Debugger",
+            HelpTopic::Hotkeys => "Hotkeys",
+            HelpTopic::Composite => "Composite Monitor",
+            HelpTopic::Scripting => "Scripting",
+        }
+    }
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            HelpTopic::Index => include_str!("../../../assets/help/index.md"),
+            HelpTopic::MachineSetup => include_str!("../../../assets/help/machine_setup.md"),
+            HelpTopic::Debugger => include_str!("../../../assets/help/debugger.md"),
+            HelpTopic::Hotkeys => include_str!("../../../assets/help/hotkeys.md"),
+            HelpTopic::Composite => include_str!("../../../assets/help/composite.md"),
+            HelpTopic::Scripting => include_str!("../../../assets/help/scripting.md"),
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<HelpTopic> {
+        HELP_TOPICS.iter().find(|t| t.id() == id).copied()
+    }
+}
+
+/// Draw a small '?' button that opens the help browser to the specified topic. 
+/// Used to provide context-sensitive help from other windows.
+pub fn help_button(ui: &mut egui::Ui, topic: HelpTopic, events: &mut VecDeque<GuiEvent>) {
+    if ui.small_button("?").on_hover_text(format!("Help: {}", topic.title())).clicked() {
+        events.push_back(GuiEvent::ShowHelp(topic));
+    }
+}
+
+enum Span<'a> {
+    Text(&'a str),
+    Bold(&'a str),
+    Code(&'a str),
+    Link(&'a str, &'a str),
+}
+
+/// Split a line of markdown into styled spans. Unterminated markup is treated as plain text.
+fn parse_inline(line: &str) -> Vec<Span<'_>> {
+    let mut spans = Vec::new();
+    let mut rest = line;
+
+    while !rest.is_empty() {
+        let next = rest.find(|c: char| c == '`' || c == '*' || c == '[').unwrap_or(rest.len());
+        if next > 0 {
+            spans.push(Span::Text(&rest[..next]));
+            rest = &rest[next..];
+            continue;
+        }
+
+        if let Some(inner) = rest.strip_prefix('`') {
+            if let Some(end) = inner.find('`') {
+                spans.push(Span::Code(&inner[..end]));
+                rest = &inner[end + 1..];
+                continue;
+            }
+        }
+        else if let Some(inner) = rest.strip_prefix("**") {
+            if let Some(end) = inner.find("**") {
+                spans.push(Span::Bold(&inner[..end]));
+                rest = &inner[end + 2..];
+                continue;
+            }
+        }
+        else if let Some(inner) = rest.strip_prefix('[') {
+            if let Some(label_end) = inner.find("](") {
+                if let Some(target_end) = inner[label_end + 2..].find(')') {
+                    let target_start = label_end + 2;
+                    spans.push(Span::Link(&inner[..label_end], &inner[target_start..target_start + target_end]));
+                    rest = &inner[target_start + target_end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        // Not valid markup, so emit the markup character as text.
+        spans.push(Span::Text(&rest[..1]));
+        rest = &rest[1..];
+    }
+    spans
+}
+
+pub struct HelpBrowser {
+    topic: HelpTopic,
+    history: Vec<HelpTopic>,
+    search: String,
+}
+
+impl HelpBrowser {
+
+    pub fn new() -> Self {
+        Self {
+            topic: HelpTopic::Index,
+            history: Vec::new(),
+            search: String::new(),
+        }
+    }
+
+    /// Navigate to the specified topic, remembering the current topic so we can go back to it.
+    pub fn set_topic(&mut self, topic: HelpTopic) {
+        if topic != self.topic {
+            self.history.push(self.topic);
+            self.topic = topic;
+        }
+        self.search.clear();
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent>) {
+
+        let mut new_topic = None;
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.history.is_empty(), egui::Button::new("⬅")).clicked() {
+                if let Some(topic) = self.history.pop() {
+                    self.topic = topic;
+                    self.search.clear();
+                }
+            }
+            if ui.button("🏠").clicked() {
+                new_topic = Some(HelpTopic::Index);
+            }
+            ui.label("Search: ");
+            ui.text_edit_singleline(&mut self.search);
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                if self.search.trim().is_empty() {
+                    new_topic = new_topic.or(HelpBrowser::draw_markdown(ui, self.topic.text()));
+                }
+                else {
+                    new_topic = new_topic.or(HelpBrowser::draw_search_results(ui, self.search.trim()));
+                }
+            });
+
+        if let Some(topic) = new_topic {
+            self.set_topic(topic);
+        }
+    }
+
+    /// List each line of each topic that contains the search string. Returns the topic of a 
+    /// result that was clicked, if any.
+    fn draw_search_results(ui: &mut egui::Ui, search: &str) -> Option<HelpTopic> {
+
+        let search = search.to_lowercase();
+        let mut clicked = None;
+        let mut found = false;
+
+        for topic in HELP_TOPICS {
+            let matches: Vec<&str> = topic.text()
+                .lines()
+                .filter(|line| line.to_lowercase().contains(&search))
+                .collect();
+
+            if matches.is_empty() {
+                continue;
+            }
+            found = true;
+
+            if ui.link(egui::RichText::new(topic.title()).strong()).clicked() {
+                clicked = Some(topic);
+            }
+            for line in matches {
+                let line = line.trim_start_matches(|c| c == '#' || c == '-' || c == ' ');
+                ui.label(format!("    {}", line.replace("**", "")));
+            }
+            ui.add_space(6.0);
+        }
+
+        if !found {
+            ui.label("No results.");
+        }
+        clicked
+    }
+
+    /// Render a help topic. Returns the topic of a link that was clicked, if any.
+    fn draw_markdown(ui: &mut egui::Ui, text: &str) -> Option<HelpTopic> {
+
+        let mut clicked = None;
+        let mut in_code_block = false;
+
+        for line in text.lines() {
+
+            if line.starts_with("```") {
+                in_code_block = !in_code_block;
+                continue;
+            }
+            if in_code_block {
+                ui.label(egui::RichText::new(line).monospace());
+                continue;
+            }
+
+            if let Some(heading) = line.strip_prefix("# ") {
+                ui.label(egui::RichText::new(heading).strong().font(egui::FontId::proportional(24.0)));
+                ui.separator();
+            }
+            else if let Some(heading) = line.strip_prefix("## ") {
+                ui.add_space(6.0);
+                ui.label(egui::RichText::new(heading).strong().font(egui::FontId::proportional(19.0)));
+            }
+            else if let Some(heading) = line.strip_prefix("### ") {
+                ui.label(egui::RichText::new(heading).strong());
+            }
+            else if let Some(item) = line.strip_prefix("- ").or(line.strip_prefix("* ")) {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("  •  ");
+                    clicked = clicked.or(HelpBrowser::draw_inline(ui, item));
+                });
+            }
+            else if line.trim().is_empty() {
+                ui.add_space(4.0);
+            }
+            else {
+                ui.horizontal_wrapped(|ui| {
+                    clicked = clicked.or(HelpBrowser::draw_inline(ui, line));
+                });
+            }
+        }
+        clicked
+    }
+
+    fn draw_inline(ui: &mut egui::Ui, line: &str) -> Option<HelpTopic> {
+
+        let mut clicked = None;
+        ui.spacing_mut().item_spacing.x = 0.0;
+
+        for span in parse_inline(line) {
+            match span {
+                Span::Text(text) => {
+                    ui.label(text);
+                }
+                Span::Bold(text) => {
+                    ui.label(egui::RichText::new(text).strong());
+                }
+                Span::Code(text) => {
+                    ui.label(egui::RichText::new(text).code());
+                }
+                Span::Link(label, target) => {
+                    if target.contains("://") {
+                        ui.hyperlink_to(label, target);
+                    }
+                    else if ui.link(label).clicked() {
+                        clicked = HelpTopic::from_id(target);
+                        if clicked.is_none() {
+                            log::warn!("Help link to unknown topic: {}", target);
+                        }
+                    }
+                }
+            }
+        }
+        clicked
+    }
+}
@@ -0,0 +1,104 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::hotkey_editor.rs
+
+    Implements a window for rebinding emulator hotkeys. Clicking Rebind on
+    an action captures the next key combination pressed, which main binds
+    to that action and saves to the hotkey file.
+
+*/
+
+use crate::egui::*;
+use marty_core::hotkey::{HotkeyAction, KeyCombo};
+
+pub struct HotkeyEditor {
+    bindings: Vec<(HotkeyAction, Option<KeyCombo>)>,
+    capturing: Option<HotkeyAction>,
+}
+
+impl HotkeyEditor {
+
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+            capturing: None,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        egui::Grid::new("hotkey_editor_grid")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                for (action, combo) in &self.bindings {
+                    ui.label(action.desc());
+                    if self.capturing == Some(*action) {
+                        ui.label(egui::RichText::new("Press a key...").italics());
+                    }
+                    else {
+                        match combo {
+                            Some(combo) => ui.label(egui::RichText::new(combo.to_string()).text_style(egui::TextStyle::Monospace)),
+                            None => ui.label(egui::RichText::new("Unbound").weak()),
+                        };
+                    }
+                    if ui.button("Rebind").clicked() {
+                        self.capturing = Some(*action);
+                    }
+                    if ui.add_enabled(combo.is_some(), egui::Button::new("Clear")).clicked() {
+                        events.push_back(GuiEvent::UnbindHotkey(*action));
+                    }
+                    ui.end_row();
+                }
+            });
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.button("Reset to defaults").clicked() {
+                self.capturing = None;
+                events.push_back(GuiEvent::ResetHotkeys);
+            }
+            if self.capturing.is_some() {
+                ui.label("Press a key combination, or Escape to cancel.");
+            }
+        });
+    }
+
+    /// Set the bindings to display, in the order they should be listed.
+    pub fn set_bindings(&mut self, bindings: Vec<(HotkeyAction, Option<KeyCombo>)>) {
+        self.bindings = bindings;
+    }
+
+    /// Return the action waiting for a key combination to be pressed, if any.
+    pub fn capturing(&self) -> Option<HotkeyAction> {
+        self.capturing
+    }
+
+    pub fn cancel_capture(&mut self) {
+        self.capturing = None;
+    }
+}
@@ -28,7 +28,8 @@
 
     Implements the instruction history viewer control.
     The control is a virtual window that will display the disassembly of 
-    the last X executed instructions. 
+    the last X executed instructions. The window can be scrolled through
+    the entire history, and the history can be exported to a text file.
 
 */
 use std::collections::VecDeque;
@@ -37,11 +38,15 @@ use crate::egui::*;
 use crate::egui::token_listview::*;
 use marty_core::syntax_token::*;
 
+const HISTORY_VISIBLE_ROWS: usize = 32;
+
 pub struct InstructionHistoryControl {
 
     pub address: String,
     pub row: usize,
     pub lastrow: usize,
+    show_regs: bool,
+    history_len: usize,
     tlv: TokenListView,
 }
 
@@ -52,22 +57,43 @@ impl InstructionHistoryControl {
             address: "cs:ip".to_string(),
             row: 0,
             lastrow: 0,
+            show_regs: false,
+            history_len: 0,
             tlv: TokenListView::new()
         }
     }
 
     pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
 
-        self.tlv.set_capacity(32);
-        self.tlv.set_visible(32);
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show_regs, "Registers");
+            if ui.button("Export...").clicked() {
+                events.push_back(GuiEvent::DumpInstructionHistory);
+            }
+            ui.label(format!("{} instructions", self.history_len));
+        });
+        ui.separator();
+
+        self.tlv.set_capacity(self.history_len.max(HISTORY_VISIBLE_ROWS));
+        self.tlv.set_visible(HISTORY_VISIBLE_ROWS);
 
         let mut new_row = self.row;
         ui.horizontal(|ui| {
             self.tlv.draw(ui, events, &mut new_row);
         });
+        // The token listview rounds its row for the memory viewer; we want the exact row.
+        self.row = self.tlv.row;
+    }
+
+    /// Return the first visible row, the number of visible rows and whether registers 
+    /// should be shown, for requesting the visible portion of the history.
+    pub fn get_view(&self) -> (usize, usize, bool) {
+        (self.row, HISTORY_VISIBLE_ROWS, self.show_regs)
     }
 
-    pub fn set_content(&mut self, mem: Vec<Vec<SyntaxToken>>) {
+    /// Set the visible portion of the history and the total number of entries in it.
+    pub fn set_content(&mut self, mem: Vec<Vec<SyntaxToken>>, history_len: usize) {
+        self.history_len = history_len;
         self.tlv.set_contents(mem);
     }
 
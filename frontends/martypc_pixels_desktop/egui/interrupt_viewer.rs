@@ -0,0 +1,142 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::interrupt_viewer.rs
+
+    Implements the interrupt monitor. The state of each IRQ of the primary 
+    PIC is shown along with live request counters and request rates, 
+    followed by the full interrupt vector table with the conventional name 
+    of each vector and the region of memory its handler resides in.
+
+    An IRQ that stays in service while its requests keep arriving usually 
+    points to a handler that never sent an EOI.
+
+*/
+
+use crate::egui::*;
+use crate::egui::rate_meter::RateMeter;
+use marty_core::interrupt::{InterruptMonitorState, handler_region, vector_name};
+
+const IN_SERVICE_COLOR: Color32 = Color32::from_rgb(0xE0, 0xC0, 0x40);
+const MASKED_COLOR: Color32 = Color32::from_rgb(0xE0, 0x60, 0x40);
+
+pub struct InterruptViewerControl {
+    state: InterruptMonitorState,
+    named_only: bool,
+    rates: RateMeter<8>,
+}
+
+impl InterruptViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            state: Default::default(),
+            named_only: false,
+            rates: RateMeter::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent>) {
+
+        ui.heading("IRQs");
+        egui::Grid::new("interrupt_viewer_irqs")
+            .striped(true)
+            .min_col_width(40.0)
+            .show(ui, |ui| {
+                for header in ["IRQ", "Vector", "Handler", "Masked", "Pending", "In Service", "Requests", "Serviced", "Req/s"] {
+                    ui.label(egui::RichText::new(header).strong());
+                }
+                ui.end_row();
+
+                for irq in &self.state.irqs {
+                    let handler = self.state.ivt.get(irq.vector as usize)
+                        .map(|entry| format!("{:04X}:{:04X}", entry.segment, entry.offset))
+                        .unwrap_or_default();
+
+                    ui.label(egui::RichText::new(format!("{}", irq.irq)).monospace());
+                    ui.label(egui::RichText::new(format!("{:02X}h", irq.vector)).monospace());
+                    ui.label(egui::RichText::new(handler).monospace());
+                    if irq.masked {
+                        ui.label(egui::RichText::new("yes").monospace().color(MASKED_COLOR));
+                    }
+                    else {
+                        ui.label(egui::RichText::new("no").monospace());
+                    }
+                    ui.label(egui::RichText::new(if irq.requested { "yes" } else { "no" }).monospace());
+                    if irq.in_service {
+                        ui.label(egui::RichText::new("yes").monospace().color(IN_SERVICE_COLOR));
+                    }
+                    else {
+                        ui.label(egui::RichText::new("no").monospace());
+                    }
+                    ui.label(egui::RichText::new(format!("{}", irq.request_count)).monospace());
+                    ui.label(egui::RichText::new(format!("{}", irq.serviced_count)).monospace());
+                    ui.label(egui::RichText::new(format!("{}", self.rates.rate(irq.irq as usize))).monospace());
+                    ui.end_row();
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.heading("Interrupt Vector Table");
+            ui.checkbox(&mut self.named_only, "Named vectors only");
+        });
+
+        let named_only = self.named_only;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .max_height(320.0)
+            .show(ui, |ui| {
+                egui::Grid::new("interrupt_viewer_ivt")
+                    .striped(true)
+                    .min_col_width(40.0)
+                    .show(ui, |ui| {
+                        for header in ["Vector", "Address", "Region", "Name"] {
+                            ui.label(egui::RichText::new(header).strong());
+                        }
+                        ui.end_row();
+
+                        for entry in &self.state.ivt {
+                            let name = vector_name(entry.vector);
+                            if named_only && name.is_none() {
+                                continue;
+                            }
+                            ui.label(egui::RichText::new(format!("{:02X}h", entry.vector)).monospace());
+                            ui.label(egui::RichText::new(format!("{:04X}:{:04X}", entry.segment, entry.offset)).monospace());
+                            ui.label(egui::RichText::new(handler_region(entry.segment, entry.offset)).monospace());
+                            ui.label(name.unwrap_or(""));
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+
+    pub fn update_state(&mut self, state: InterruptMonitorState) {
+        let counts: Vec<u64> = state.irqs.iter().map(|irq| irq.request_count).collect();
+        self.rates.update(&counts);
+        self.state = state;
+    }
+}
@@ -0,0 +1,193 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::media_manager.rs
+
+    Implements a window for browsing the floppy images in the floppy 
+    directory tree and mounting them, with the images recently mounted in 
+    each drive. Image files dropped onto the emulator window are mounted in 
+    the drive selected here.
+
+    A host directory can also be mounted as a virtual floppy, optionally
+    writing changes made in the guest back to the directory.
+
+*/
+
+use std::path::PathBuf;
+
+use crate::egui::*;
+
+use marty_core::floppy_manager::FloppyImageInfo;
+
+const DRIVE_NAMES: [&str; 2] = ["A:", "B:"];
+
+pub struct MediaManagerControl {
+    drive: usize,
+    filter: String,
+    selected: Option<OsString>,
+    images: Vec<FloppyImageInfo>,
+    recent: [Vec<PathBuf>; 2],
+    host_dir: String,
+    write_back: bool,
+}
+
+impl MediaManagerControl {
+
+    pub fn new() -> Self {
+        Self {
+            drive: 0,
+            filter: String::new(),
+            selected: None,
+            images: Vec::new(),
+            recent: [Vec::new(), Vec::new()],
+            host_dir: String::new(),
+            write_back: false,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            ui.label("Drive:");
+            for (drive, name) in DRIVE_NAMES.iter().enumerate() {
+                ui.radio_value(&mut self.drive, drive, *name);
+            }
+            ui.separator();
+            ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("Filter").desired_width(160.0));
+            if ui.button("⟲ Rescan").clicked() {
+                events.push_back(GuiEvent::RescanMediaFolders);
+            }
+        });
+        ui.separator();
+
+        let filter = self.filter.to_lowercase();
+        let mut mount = None;
+
+        egui::ScrollArea::vertical()
+            .id_source("media_manager_images")
+            .max_height(300.0)
+            .auto_shrink([false, true])
+            .show(ui, |ui| {
+                egui::Grid::new("media_manager_image_grid")
+                    .striped(true)
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        ui.label("Image");
+                        ui.label("Size");
+                        ui.label("");
+                        ui.end_row();
+
+                        for image in &self.images {
+                            let name = image.name.to_string_lossy();
+                            if !filter.is_empty() && !name.to_lowercase().contains(&filter) {
+                                continue;
+                            }
+                            let selected = self.selected.as_ref() == Some(&image.name);
+                            let response = ui.selectable_label(selected, name.as_ref())
+                                .on_hover_text(image.path.display().to_string());
+                            if response.clicked() {
+                                self.selected = Some(image.name.clone());
+                            }
+                            if response.double_clicked() {
+                                mount = Some(image.name.clone());
+                            }
+                            ui.label(egui::RichText::new(format!("{}K", image.size / 1024)).monospace());
+                            if image.write_protected {
+                                ui.label("🔒").on_hover_text("The image file is read-only");
+                            }
+                            else {
+                                ui.label("");
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if self.images.is_empty() {
+            ui.label("No floppy images found.");
+        }
+
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(self.selected.is_some(), |ui| {
+                if ui.button(format!("💾 Mount in Drive {}", DRIVE_NAMES[self.drive])).clicked() {
+                    mount = self.selected.clone();
+                }
+            });
+        });
+
+        ui.separator();
+        ui.label(format!("Recent images in Drive {}", DRIVE_NAMES[self.drive]));
+        if self.recent[self.drive].is_empty() {
+            ui.label("None");
+        }
+        for path in &self.recent[self.drive] {
+            let label = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            if ui.button(label).on_hover_text(path.display().to_string()).clicked() {
+                mount = Some(path.clone().into_os_string());
+            }
+        }
+
+        ui.separator();
+        ui.label(format!("Mount host directory in Drive {}", DRIVE_NAMES[self.drive]));
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.host_dir).hint_text("Directory path").desired_width(240.0));
+            if ui.add_enabled(!self.host_dir.trim().is_empty(), egui::Button::new("📁 Mount")).clicked() {
+                events.push_back(GuiEvent::MountDirectory(self.drive, PathBuf::from(self.host_dir.trim()), self.write_back));
+            }
+        });
+        ui.checkbox(&mut self.write_back, "Write changes back to directory")
+            .on_hover_text("Files created or changed in the guest are updated in the host directory. Files deleted in the guest are moved to the .martypc_trash directory.");
+
+        ui.separator();
+        ui.label(
+            egui::RichText::new("Drop an image file or directory onto the emulator window to mount it in the selected drive.").weak()
+        );
+
+        if let Some(name) = mount {
+            events.push_back(GuiEvent::LoadFloppy(self.drive, name));
+        }
+    }
+
+    /// Return the drive that image files dropped onto the window are mounted in.
+    pub fn drop_drive(&self) -> usize {
+        self.drive
+    }
+
+    /// Return whether directories dropped onto the window should be mounted with write-back.
+    pub fn drop_write_back(&self) -> bool {
+        self.write_back
+    }
+
+    pub fn set_images(&mut self, images: Vec<FloppyImageInfo>) {
+        self.images = images;
+    }
+
+    pub fn set_recent(&mut self, drive: usize, recent: &[PathBuf]) {
+        if let Some(list) = self.recent.get_mut(drive) {
+            *list = recent.to_vec();
+        }
+    }
+}
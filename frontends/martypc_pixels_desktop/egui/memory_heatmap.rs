@@ -0,0 +1,234 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::memory_heatmap.rs
+
+    Implements a live heatmap of memory accesses per 4K page. Each column is
+    a frame, with the newest on the right, and each row a page of the address
+    space. CPU, DMA and video memory accesses are drawn in red, green and
+    blue respectively, so overlapping activity mixes colors. The wait states
+    view instead shows where the CPU was held up by wait states.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::egui::*;
+
+use marty_core::heatmap::{AccessSource, PageActivity, HEATMAP_PAGES, HEATMAP_PAGE_SIZE};
+
+const HEATMAP_HISTORY: usize = 256;
+const HEATMAP_SCALE_MIN: f32 = 1.0;
+const HEATMAP_SCALE_MAX: f32 = 4.0;
+
+#[derive(Copy, Clone, PartialEq)]
+enum HeatmapView {
+    Accesses,
+    WaitStates,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum AccessFilter {
+    All,
+    Reads,
+    Writes,
+}
+
+pub struct MemoryHeatmapViewer {
+    frames: VecDeque<Vec<PageActivity>>,
+    view: HeatmapView,
+    filter: AccessFilter,
+    show_source: [bool; 3],
+    scale: f32,
+    texture: Option<egui::TextureHandle>,
+    texture_dirty: bool,
+}
+
+/// Scale a count to a color intensity. A log scale keeps pages with occasional accesses 
+/// visible next to the busiest ones.
+fn intensity(count: u32, max: u32) -> u8 {
+    if count == 0 || max == 0 {
+        return 0
+    }
+    let level = (1.0 + count as f32).ln() / (1.0 + max as f32).ln();
+    // Any activity at all is drawn bright enough to see.
+    (64.0 + level * 191.0) as u8
+}
+
+impl MemoryHeatmapViewer {
+
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            view: HeatmapView::Accesses,
+            filter: AccessFilter::All,
+            show_source: [true; 3],
+            scale: 2.0,
+            texture: None,
+            texture_dirty: true,
+        }
+    }
+
+    fn count(&self, page: &PageActivity, source: AccessSource) -> u32 {
+        if !self.show_source[source as usize] {
+            return 0
+        }
+        match self.filter {
+            AccessFilter::All => page.accesses(source),
+            AccessFilter::Reads => page.reads(source),
+            AccessFilter::Writes => page.writes(source),
+        }
+    }
+
+    fn build_image(&self) -> ColorImage {
+        let mut image = ColorImage::new([HEATMAP_HISTORY, HEATMAP_PAGES], Color32::BLACK);
+        // Newest frame in the rightmost column.
+        let x_offset = HEATMAP_HISTORY - self.frames.len();
+
+        match self.view {
+            HeatmapView::Accesses => {
+                let mut max = [0u32; 3];
+                for page in self.frames.iter().flatten() {
+                    for source in AccessSource::ALL {
+                        max[source as usize] = max[source as usize].max(self.count(page, source));
+                    }
+                }
+                for (x, frame) in self.frames.iter().enumerate() {
+                    for (y, page) in frame.iter().enumerate() {
+                        let [r, g, b] = AccessSource::ALL.map(|source| {
+                            intensity(self.count(page, source), max[source as usize])
+                        });
+                        image.pixels[y * HEATMAP_HISTORY + x + x_offset] = Color32::from_rgb(r, g, b);
+                    }
+                }
+            }
+            HeatmapView::WaitStates => {
+                let max = self.frames.iter().flatten().map(|page| page.wait_states).max().unwrap_or(0);
+                for (x, frame) in self.frames.iter().enumerate() {
+                    for (y, page) in frame.iter().enumerate() {
+                        let level = intensity(page.wait_states, max);
+                        image.pixels[y * HEATMAP_HISTORY + x + x_offset] = Color32::from_rgb(level, level, level / 4);
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    /// Describe the activity of a page in the specified frame, counting back from the newest.
+    fn describe(page_index: usize, frames_ago: usize, page: &PageActivity) -> String {
+        let start = page_index * HEATMAP_PAGE_SIZE;
+        let mut text = format!("{:05X}-{:05X}, {} frames ago", start, start + HEATMAP_PAGE_SIZE - 1, frames_ago);
+        for source in AccessSource::ALL {
+            text.push_str(&format!(
+                "\n{}: {} reads, {} writes",
+                source.name(),
+                page.reads(source),
+                page.writes(source)
+            ));
+        }
+        text.push_str(&format!("\nWait states: {}", page.wait_states));
+        text
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, ctx: &Context) {
+
+        ui.horizontal(|ui| {
+            let mut changed = false;
+            changed |= ui.radio_value(&mut self.view, HeatmapView::Accesses, "Accesses").changed();
+            changed |= ui.radio_value(&mut self.view, HeatmapView::WaitStates, "Wait states").changed();
+            ui.separator();
+            ui.label("Scale:");
+            ui.add(egui::Slider::new(&mut self.scale, HEATMAP_SCALE_MIN..=HEATMAP_SCALE_MAX));
+            if ui.button("Clear").clicked() {
+                self.frames.clear();
+                changed = true;
+            }
+            self.texture_dirty |= changed;
+        });
+
+        if self.view == HeatmapView::Accesses {
+            ui.horizontal(|ui| {
+                let mut changed = false;
+                for (source, color) in AccessSource::ALL.iter().zip([Color32::RED, Color32::GREEN, Color32::LIGHT_BLUE]) {
+                    changed |= ui.checkbox(
+                        &mut self.show_source[*source as usize], 
+                        egui::RichText::new(source.name()).color(color)
+                    ).changed();
+                }
+                ui.separator();
+                changed |= ui.radio_value(&mut self.filter, AccessFilter::All, "All").changed();
+                changed |= ui.radio_value(&mut self.filter, AccessFilter::Reads, "Reads").changed();
+                changed |= ui.radio_value(&mut self.filter, AccessFilter::Writes, "Writes").changed();
+                self.texture_dirty |= changed;
+            });
+        }
+        ui.separator();
+
+        if self.texture_dirty || self.texture.is_none() {
+            let image = self.build_image();
+            self.texture = Some(ctx.load_texture("memory_heatmap", image, Default::default()));
+            self.texture_dirty = false;
+        }
+
+        let texture = match &self.texture {
+            Some(texture) => texture,
+            None => return
+        };
+
+        let scale = self.scale;
+        egui::ScrollArea::both()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                let response = ui.add(
+                    egui::Image::new(texture, texture.size_vec2() * scale)
+                        .sense(egui::Sense::hover())
+                );
+
+                if let Some(pos) = response.hover_pos() {
+                    let local = (pos - response.rect.min) / scale;
+                    let x = local.x as usize;
+                    let page_index = local.y as usize;
+                    let x_offset = HEATMAP_HISTORY - self.frames.len();
+                    if x >= x_offset && page_index < HEATMAP_PAGES {
+                        let frame_index = x - x_offset;
+                        let frames_ago = self.frames.len() - 1 - frame_index;
+                        let page = &self.frames[frame_index][page_index];
+                        response.on_hover_text(Self::describe(page_index, frames_ago, page));
+                    }
+                }
+            });
+    }
+
+    /// Add a frame of page activity, discarding the oldest frame once the history is full.
+    pub fn push_frame(&mut self, frame: Vec<PageActivity>) {
+        if self.frames.len() == HEATMAP_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+        self.texture_dirty = true;
+    }
+}
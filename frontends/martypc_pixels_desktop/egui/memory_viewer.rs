@@ -32,13 +32,61 @@
     active display as it is scrolled by sending GuiEvent::MemoryUpdate
     events.
 
+    Clicking a byte places the edit cursor on it. Hex digits typed over the 
+    hex column, or characters typed over the ASCII column, are written to
+    memory with GuiEvent::MemoryEdit. The arrow keys move the cursor, Tab
+    switches columns and Escape ends editing.
+
+    Memory can be searched for a byte pattern or quoted string, and ROM,
+    video memory and EMS page frame regions are shaded.
+
+    The coverage overlay shades bytes that were executed, written, or 
+    modified after being executed.
+
 */
 
 use std::collections::VecDeque;
+use std::ops::Range;
 
 use crate::egui::*;
 use crate::egui::token_listview::*;
-use marty_core::syntax_token::*;
+use marty_core::{
+    bus::{MemoryRegion, MemoryRegionKind},
+    coverage::{CoverageSummary, COVERAGE_EXECUTED, COVERAGE_SMC, COVERAGE_WRITTEN},
+    mem_search::SearchDirection,
+    syntax_token::*,
+};
+
+const MEMORY_VIEWER_ROWS: usize = 16;
+const ADDRESS_SPACE_END: usize = 0xFFFFF;
+
+fn region_color(kind: MemoryRegionKind) -> Color32 {
+    match kind {
+        MemoryRegionKind::Rom => Color32::from_rgba_unmultiplied(0x90, 0x30, 0x30, 0x60),
+        MemoryRegionKind::VideoMemory => Color32::from_rgba_unmultiplied(0x30, 0x50, 0xB0, 0x60),
+        MemoryRegionKind::EmsPageFrame => Color32::from_rgba_unmultiplied(0x30, 0x90, 0x30, 0x60),
+    }
+}
+
+const COVERAGE_KINDS: [(u8, &str, Color32); 3] = [
+    (COVERAGE_SMC, "Self-modified", Color32::from_rgba_premultiplied(0x60, 0x10, 0x10, 0x60)),
+    (COVERAGE_EXECUTED, "Executed", Color32::from_rgba_premultiplied(0x10, 0x50, 0x10, 0x60)),
+    (COVERAGE_WRITTEN, "Written", Color32::from_rgba_premultiplied(0x50, 0x48, 0x10, 0x60)),
+];
+
+/// Return the overlay color for a byte's coverage flags. Self-modification takes precedence over
+/// execution, which takes precedence over writes.
+fn coverage_color(flags: u8) -> Option<Color32> {
+    COVERAGE_KINDS.iter().find(|(mask, _, _)| flags & mask != 0).map(|(_, _, color)| *color)
+}
+
+fn region_name(kind: MemoryRegionKind) -> &'static str {
+    match kind {
+        MemoryRegionKind::Rom => "ROM",
+        MemoryRegionKind::VideoMemory => "Video Memory",
+        MemoryRegionKind::EmsPageFrame => "EMS Page Frame",
+    }
+}
 
 pub struct MemoryViewerControl {
 
@@ -49,6 +97,22 @@ pub struct MemoryViewerControl {
     //update_scroll_pos: bool,
 
     tlv: TokenListView,
+
+    edit_id: egui::Id,
+    cursor: Option<usize>,
+    edit_ascii: bool,
+    high_nibble: Option<u8>,
+    grab_focus: bool,
+
+    search: String,
+    status: String,
+    annotate: bool,
+    region_highlights: Vec<(Range<u32>, Color32)>,
+
+    record_coverage: bool,
+    show_coverage: bool,
+    coverage_highlights: Vec<(Range<u32>, Color32)>,
+    coverage_summary: CoverageSummary,
 }
 
 impl MemoryViewerControl {
@@ -60,7 +124,23 @@ impl MemoryViewerControl {
             lastrow: 0,
             mem: Vec::new(),
             //update_scroll_pos: false,
-            tlv: TokenListView::new()
+            tlv: TokenListView::new(),
+
+            edit_id: egui::Id::new("memory_viewer_edit"),
+            cursor: None,
+            edit_ascii: false,
+            high_nibble: None,
+            grab_focus: false,
+
+            search: String::new(),
+            status: String::new(),
+            annotate: true,
+            region_highlights: Vec::new(),
+
+            record_coverage: false,
+            show_coverage: false,
+            coverage_highlights: Vec::new(),
+            coverage_summary: Default::default(),
         }
     }
 
@@ -72,10 +152,57 @@ impl MemoryViewerControl {
                 events.push_back(GuiEvent::MemoryUpdate);
             }
         });
+        ui.horizontal(|ui| {
+            ui.label("Search: ");
+            let response = ui.text_edit_singleline(&mut self.search)
+                .on_hover_text("Hex bytes, ?? matches any byte (CD 21 ?? 4C), or a quoted string (\"MartyPC\")");
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                events.push_back(GuiEvent::MemorySearch(self.search.clone(), SearchDirection::Forward));
+            }
+            if ui.button("Previous").clicked() {
+                events.push_back(GuiEvent::MemorySearch(self.search.clone(), SearchDirection::Backward));
+            }
+            if ui.button("Next").clicked() {
+                events.push_back(GuiEvent::MemorySearch(self.search.clone(), SearchDirection::Forward));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.annotate, "Annotate regions");
+            if self.annotate {
+                for kind in [MemoryRegionKind::Rom, MemoryRegionKind::VideoMemory, MemoryRegionKind::EmsPageFrame] {
+                    ui.label(egui::RichText::new(region_name(kind)).background_color(region_color(kind)));
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.record_coverage, "Record coverage").changed() {
+                events.push_back(GuiEvent::SetCoverage(self.record_coverage));
+            }
+            ui.checkbox(&mut self.show_coverage, "Show coverage");
+            if ui.button("Clear").clicked() {
+                events.push_back(GuiEvent::ClearCoverage);
+            }
+            if ui.button("Export").clicked() {
+                events.push_back(GuiEvent::ExportCoverage);
+            }
+        });
+        if self.show_coverage {
+            ui.horizontal(|ui| {
+                for (_, name, color) in COVERAGE_KINDS {
+                    ui.label(egui::RichText::new(name).background_color(color));
+                }
+                ui.label(format!(
+                    "{} executed, {} written, {} self-modified",
+                    self.coverage_summary.executed,
+                    self.coverage_summary.written,
+                    self.coverage_summary.smc
+                ));
+            });
+        }
         ui.separator();
 
         self.tlv.set_capacity(0xFFFFF);
-        self.tlv.set_visible(16);
+        self.tlv.set_visible(MEMORY_VIEWER_ROWS);
 
         let mut new_row = self.row;
         ui.horizontal(|ui| {
@@ -89,6 +216,173 @@ impl MemoryViewerControl {
             self.row = new_row;
         }
 
+        // Register the editor's interest in keyboard focus so that it keeps focus between frames.
+        // Having focus also keeps keystrokes from being sent to the emulated machine.
+        let editor = ui.interact(ui.min_rect(), self.edit_id, egui::Sense::focusable_noninteractive());
+
+        if let Some((address, ascii)) = self.tlv.take_clicked() {
+            self.cursor = Some(address);
+            self.edit_ascii = ascii;
+            self.high_nibble = None;
+            self.grab_focus = true;
+        }
+        if self.grab_focus {
+            editor.request_focus();
+            self.grab_focus = false;
+        }
+
+        if self.cursor.is_some() {
+            if editor.has_focus() {
+                // Keep Tab from moving focus to the next widget, as it switches columns.
+                ui.memory_mut(|m| m.lock_focus(self.edit_id, true));
+                self.handle_edit_input(ui, events);
+            }
+            else {
+                // Focus was taken by another widget, so stop editing.
+                self.cursor = None;
+                self.high_nibble = None;
+            }
+        }
+
+        ui.separator();
+        match self.cursor {
+            Some(cursor) => {
+                ui.label(format!(
+                    "Editing {:05X} ({}). Arrow keys move, Tab switches columns, Esc stops editing.", 
+                    cursor, 
+                    if self.edit_ascii { "ASCII" } else { "hex" }
+                ));
+            }
+            None => {
+                ui.label("Click a byte to edit it.");
+            }
+        }
+        if !self.status.is_empty() {
+            ui.label(&self.status);
+        }
+    }
+
+    fn handle_edit_input(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent>) {
+
+        let input_events = ui.input(|i| i.events.clone());
+        for event in input_events {
+            match event {
+                egui::Event::Text(text) => {
+                    for c in text.chars() {
+                        let Some(address) = self.cursor else {
+                            break
+                        };
+                        if self.edit_ascii {
+                            if c.is_ascii() && !c.is_ascii_control() {
+                                events.push_back(GuiEvent::MemoryEdit(address, c as u8));
+                                self.move_cursor(1, events);
+                            }
+                        }
+                        else if let Some(digit) = c.to_digit(16) {
+                            match self.high_nibble.take() {
+                                Some(high) => {
+                                    events.push_back(GuiEvent::MemoryEdit(address, (high << 4) | digit as u8));
+                                    self.move_cursor(1, events);
+                                }
+                                None => self.high_nibble = Some(digit as u8),
+                            }
+                        }
+                    }
+                }
+                egui::Event::Key { key, pressed: true, .. } => {
+                    match key {
+                        egui::Key::ArrowLeft => self.move_cursor(-1, events),
+                        egui::Key::ArrowRight => self.move_cursor(1, events),
+                        egui::Key::ArrowUp => self.move_cursor(-16, events),
+                        egui::Key::ArrowDown => self.move_cursor(16, events),
+                        egui::Key::Tab => {
+                            self.edit_ascii = !self.edit_ascii;
+                            self.high_nibble = None;
+                        }
+                        egui::Key::Escape => {
+                            self.cursor = None;
+                            self.high_nibble = None;
+                            ui.memory_mut(|m| m.surrender_focus(self.edit_id));
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Move the edit cursor, scrolling the view if the cursor leaves it.
+    fn move_cursor(&mut self, delta: isize, events: &mut VecDeque<GuiEvent>) {
+        if let Some(cursor) = self.cursor {
+            let new_cursor = (cursor as isize + delta).clamp(0, ADDRESS_SPACE_END as isize) as usize;
+            self.set_cursor(new_cursor);
+            events.push_back(GuiEvent::MemoryUpdate);
+        }
+    }
+
+    /// Place the edit cursor at the specified address, scrolling the view if the cursor is
+    /// not visible.
+    pub fn set_cursor(&mut self, address: usize) {
+        self.cursor = Some(address);
+        self.high_nibble = None;
+        self.grab_focus = true;
+        let view_size = MEMORY_VIEWER_ROWS * 16;
+        if address < self.row || address >= self.row + view_size {
+            self.set_row(address);
+            self.address = format!("{:05X}", self.row);
+        }
+    }
+
+    pub fn cursor(&self) -> Option<usize> {
+        self.cursor
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    pub fn set_regions(&mut self, regions: &[MemoryRegion]) {
+        self.region_highlights = match self.annotate {
+            true => regions.iter()
+                .map(|r| (r.address as u32..(r.address + r.size) as u32, region_color(r.kind)))
+                .collect(),
+            false => Vec::new(),
+        };
+        self.update_highlights();
+    }
+
+    pub fn show_coverage(&self) -> bool {
+        self.show_coverage
+    }
+
+    /// Set the coverage flags of the displayed memory, starting at the specified address.
+    pub fn set_coverage(&mut self, address: usize, flags: &[u8], summary: CoverageSummary) {
+        self.coverage_highlights.clear();
+        if self.show_coverage {
+            // Merge runs of bytes with the same color into a single highlight.
+            for (i, color) in flags.iter().map(|f| coverage_color(*f)).enumerate() {
+                let byte_address = (address + i) as u32;
+                match (self.coverage_highlights.last_mut(), color) {
+                    (Some((range, last_color)), Some(color)) if range.end == byte_address && *last_color == color => {
+                        range.end += 1;
+                    }
+                    (_, Some(color)) => self.coverage_highlights.push((byte_address..byte_address + 1, color)),
+                    _ => {}
+                }
+            }
+        }
+        self.coverage_summary = summary;
+        self.update_highlights();
+    }
+
+    /// Coverage takes precedence over the region shading.
+    fn update_highlights(&mut self) {
+        let highlights = self.coverage_highlights.iter()
+            .chain(self.region_highlights.iter())
+            .cloned()
+            .collect();
+        self.tlv.set_highlights(highlights);
     }
 
     #[allow (dead_code)]
@@ -118,4 +412,4 @@ impl MemoryViewerControl {
         self.tlv.set_hover_text(text);
     }
 
-}
\ No newline at end of file
+}
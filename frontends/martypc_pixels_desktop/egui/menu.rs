@@ -30,9 +30,20 @@
 
 */
 
-use crate::egui::{GuiState, GuiWindow, GuiEvent, GuiOption};
-
-use marty_core::machine::MachineState;
+use crate::egui::{GuiState, GuiWindow, GuiEvent, GuiOption, HelpTopic};
+use crate::egui::constants::REWIND_FRAME_STEPS;
+
+use marty_core::{
+    artifacts::ArtifactKind,
+    config::FullscreenMode,
+    hotkey,
+    machine::MachineState,
+    monitor::MonitorType,
+    palette::{DisplayPalette, MonochromePhosphor},
+    devices::serial_bridge::TcpTarget,
+    speed::{CpuClock, MIN_SPEED, MAX_SPEED}
+};
+use marty_render::{RecordingFormat, ScalingMode};
 
 impl GuiState {
 
@@ -45,6 +56,54 @@ impl GuiState {
                     *self.window_flag(GuiWindow::PerfViewer) = true;
                     ui.close_menu();
                 }
+                if ui.checkbox(&mut self.get_option_mut(GuiOption::FramePacingOverlay), "Frame Pacing Overlay").clicked() {
+
+                    let new_opt = self.get_option(GuiOption::FramePacingOverlay).unwrap();
+
+                    self.event_queue.push_back(
+                        GuiEvent::OptionChanged(
+                            GuiOption::FramePacingOverlay, 
+                            new_opt 
+                        )
+                    );
+                    ui.close_menu();
+                }
+                if ui.button("📜 Script Console...").clicked() {
+                    *self.window_flag(GuiWindow::ScriptConsole) = true;
+                    ui.close_menu();
+                }
+                if ui.button("🖥 New Machine Window").clicked() {
+                    self.event_queue.push_back(GuiEvent::NewInstance);
+                    ui.close_menu();
+                }
+                if ui.button("⌨ Hotkeys...").clicked() {
+                    *self.window_flag(GuiWindow::HotkeyEditor) = true;
+                    ui.close_menu();
+                }
+                ui.menu_button("📂 Output", |ui| {
+                    if ui.button("Open Output Folder").clicked() {
+                        self.event_queue.push_back(GuiEvent::OpenArtifactDir);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    for (label, kind) in [
+                        ("Latest Screenshot", ArtifactKind::Screenshot),
+                        ("Latest Composite Capture", ArtifactKind::Capture),
+                        ("Latest Memory Dump", ArtifactKind::Dump),
+                        ("Latest Trace", ArtifactKind::Trace),
+                        ("Latest Validator Trace", ArtifactKind::Validator),
+                        ("Latest Bug Report", ArtifactKind::BugReport),
+                    ] {
+                        if ui.button(label).clicked() {
+                            self.event_queue.push_back(GuiEvent::OpenLatestArtifact(kind));
+                            ui.close_menu();
+                        }
+                    }
+                });
+                if ui.button("📖 Help...").clicked() {
+                    self.show_help(HelpTopic::Index);
+                    ui.close_menu();
+                }
                 if ui.button("❓ About...").clicked() {
                     *self.window_flag(GuiWindow::About) = true;
                     ui.close_menu();
@@ -84,6 +143,56 @@ impl GuiState {
                     ui.close_menu();
                 }
 
+                ui.menu_button("CPU Clock", |ui| {
+                    for clock in CpuClock::ALL {
+                        if ui.radio(self.cpu_clock == Some(clock), clock.to_string()).clicked() {
+                            self.event_queue.push_back(GuiEvent::SetCpuClock(clock));
+                            ui.close_menu();
+                        }
+                    }
+                });
+
+                if ui.checkbox(&mut self.get_option_mut(GuiOption::WarpSpeed), "Warp Speed").clicked() {
+
+                    let new_opt = self.get_option(GuiOption::WarpSpeed).unwrap();
+
+                    self.event_queue.push_back(
+                        GuiEvent::OptionChanged(
+                            GuiOption::WarpSpeed, 
+                            new_opt 
+                        )
+                    );
+                    ui.close_menu();
+                }
+
+                if ui.checkbox(&mut self.get_option_mut(GuiOption::PauseOnFocusLoss), "Pause When Inactive").clicked() {
+
+                    let new_opt = self.get_option(GuiOption::PauseOnFocusLoss).unwrap();
+
+                    self.event_queue.push_back(
+                        GuiEvent::OptionChanged(
+                            GuiOption::PauseOnFocusLoss, 
+                            new_opt 
+                        )
+                    );
+                    ui.close_menu();
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Speed:");
+                    if ui.add(
+                        egui::Slider::new(&mut self.speed, MIN_SPEED..=MAX_SPEED)
+                            .logarithmic(true)
+                            .max_decimals(2)
+                            .suffix("x")
+                    ).changed() {
+                        self.event_queue.push_back(GuiEvent::SetSpeed(self.speed));
+                    }
+                    if ui.button("1x").clicked() {
+                        self.event_queue.push_back(GuiEvent::SetSpeed(1.0));
+                    }
+                });
+
                 ui.add_enabled_ui(is_on && !is_paused, |ui| {
                     if ui.button("⏸ Pause").clicked() {
                         self.event_queue.push_back(GuiEvent::MachineStateChange(MachineState::Paused));
@@ -112,6 +221,59 @@ impl GuiState {
                     }  
                 });
 
+                ui.add_enabled_ui(is_on, |ui| {             
+                    if ui.button("📋 Paste Text...").clicked() {
+                        *self.window_flag(GuiWindow::PasteText) = true;
+                        ui.close_menu();
+                    }  
+                });
+
+                if let Some(depth) = self.rewind_depth {
+                    ui.add_enabled_ui(is_on && depth > 0, |ui| {
+                        ui.menu_button("⏪ Rewind", |ui| {
+                            for frames in REWIND_FRAME_STEPS {
+                                ui.add_enabled_ui(depth >= frames, |ui| {
+                                    if ui.button(format!("{} frames", frames)).clicked() {
+                                        self.event_queue.push_back(GuiEvent::Rewind(frames));
+                                        ui.close_menu();
+                                    }
+                                });
+                            }
+                            if ui.button(format!("As far as possible ({} frames)", depth)).clicked() {
+                                self.event_queue.push_back(GuiEvent::Rewind(depth));
+                                ui.close_menu();
+                            }
+                        });
+                    });
+                }
+
+                ui.add_enabled_ui(is_on, |ui| {
+                    if ui.button("💾 Quick save state").clicked() {
+                        self.event_queue.push_back(GuiEvent::SaveState(0));
+                        ui.close_menu();
+                    }
+                    if ui.button("📂 Quick load state").clicked() {
+                        self.event_queue.push_back(GuiEvent::LoadState(0));
+                        ui.close_menu();
+                    }
+                    ui.menu_button("💾 Save state to slot", |ui| {
+                        for slot in 1..=hotkey::STATE_SLOTS {
+                            if ui.button(format!("Slot {}", slot)).clicked() {
+                                self.event_queue.push_back(GuiEvent::SaveState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("📂 Load state from slot", |ui| {
+                        for slot in 1..=hotkey::STATE_SLOTS {
+                            if ui.button(format!("Slot {}", slot)).clicked() {
+                                self.event_queue.push_back(GuiEvent::LoadState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                });
+
                 ui.add_enabled_ui(is_on, |ui| {
                     if ui.button("🔌 Power off").clicked() {
                         self.event_queue.push_back(GuiEvent::MachineStateChange(MachineState::Off));
@@ -132,6 +294,26 @@ impl GuiState {
                 ui.set_min_size(egui::vec2(240.0, 0.0));
                 //ui.style_mut().spacing.item_spacing = egui::Vec2{ x: 6.0, y:6.0 };
 
+                for (drive, name, media) in [
+                    ("A:", &self.floppy0_name, &self.floppy0_media), 
+                    ("B:", &self.floppy1_name, &self.floppy1_media)
+                ] {
+                    match (name, media) {
+                        (Some(name), Some(media)) => {
+                            ui.label(format!("Drive {} {} ({})", drive, name.to_string_lossy(), media));
+                        }
+                        _ => {
+                            ui.label(format!("Drive {} (empty)", drive));
+                        }
+                    }
+                }
+                ui.separator();
+
+                if ui.button("💾 Media Manager...").clicked() {
+                    *self.window_flag(GuiWindow::MediaManager) = true;
+                    ui.close_menu();
+                }
+
                 ui.menu_button("💾 Load Floppy in Drive A:...", |ui| {
                     for name in &self.floppy_names {
 
@@ -188,15 +370,27 @@ impl GuiState {
                     }
                 });                
                 
+                for (option, label) in [
+                    (GuiOption::WriteProtectDriveA, "Write protect Drive A:"),
+                    (GuiOption::WriteProtectDriveB, "Write protect Drive B:"),
+                    (GuiOption::DriveSounds, "Drive sounds")
+                ] {
+                    if ui.checkbox(&mut self.get_option_mut(option), label).clicked() {
+                        let new_opt = self.get_option(option).unwrap();
+                        self.event_queue.push_back(GuiEvent::OptionChanged(option, new_opt));
+                        ui.close_menu();
+                    }
+                }
+
                 if ui.button("⏏ Eject Floppy in Drive A:").clicked() {
                     self.event_queue.push_back(GuiEvent::EjectFloppy(0));
-                    self.floppy0_name = None;
+                    self.set_floppy_status(0, None, None);
                     ui.close_menu();
                 };       
                 
                 if ui.button("⏏ Eject Floppy in Drive B:").clicked() {
                     self.event_queue.push_back(GuiEvent::EjectFloppy(1));
-                    self.floppy1_name = None;
+                    self.set_floppy_status(1, None, None);
                     ui.close_menu();
                 };                              
 
@@ -231,8 +425,8 @@ impl GuiState {
                     });                      
                 });
 
-                if ui.button("🖹 Create new VHD...").clicked() {
-                    *self.window_flag(GuiWindow::VHDCreator) = true;
+                if ui.button("🖹 Create new Disk Image...").clicked() {
+                    *self.window_flag(GuiWindow::DiskCreator) = true;
                     ui.close_menu();
                 };
 
@@ -242,6 +436,30 @@ impl GuiState {
                     self.event_queue.push_back(GuiEvent::TakeScreenshot);
                     ui.close_menu();
                 }; 
+
+                if self.recording.is_some() {
+                    if ui.button("⏹ Stop Recording").clicked() {
+                        self.event_queue.push_back(GuiEvent::StopRecording);
+                        ui.close_menu();
+                    };
+                }
+                else {
+                    ui.menu_button("⏺ Start Recording", |ui| {
+                        if ui.button("Animated GIF").clicked() {
+                            self.event_queue.push_back(GuiEvent::StartRecording(RecordingFormat::Gif));
+                            ui.close_menu();
+                        };
+                        if ui.button("Uncompressed AVI").clicked() {
+                            self.event_queue.push_back(GuiEvent::StartRecording(RecordingFormat::Avi));
+                            ui.close_menu();
+                        };
+                    });
+                }
+
+                if ui.button("🖹 Save Screen Text").clicked() {
+                    self.event_queue.push_back(GuiEvent::SaveScreenText);
+                    ui.close_menu();
+                };
                 
             });
 
@@ -298,6 +516,18 @@ impl GuiState {
                         );
                         ui.close_menu();
                     }
+                    if ui.checkbox(&mut self.get_option_mut(GuiOption::CpuHaltSkip), "Skip Halted Cycles").clicked() {
+
+                        let new_opt = self.get_option(GuiOption::CpuHaltSkip).unwrap();
+    
+                        self.event_queue.push_back(
+                            GuiEvent::OptionChanged(
+                                GuiOption::CpuHaltSkip, 
+                                new_opt 
+                            )
+                        );
+                        ui.close_menu();
+                    }
                     if ui.checkbox(&mut self.get_option_mut(GuiOption::CpuTraceLoggingEnabled), "Trace Logging Enabled").clicked() {
 
                         let new_opt = self.get_option(GuiOption::CpuTraceLoggingEnabled).unwrap();
@@ -331,6 +561,10 @@ impl GuiState {
                     *self.window_flag(GuiWindow::MemoryViewer) = true;
                     ui.close_menu();
                 }
+                if ui.button("Memory Heatmap...").clicked() {
+                    *self.window_flag(GuiWindow::MemoryHeatmap) = true;
+                    ui.close_menu();
+                }
                 if ui.button("Instruction History...").clicked() {
                     *self.window_flag(GuiWindow::HistoryViewer) = true;
                     ui.close_menu();
@@ -350,7 +584,32 @@ impl GuiState {
                 if ui.button("IVR...").clicked() {
                     *self.window_flag(GuiWindow::IvrViewer) = true;
                     ui.close_menu();
+                }
+                if ui.button("Interrupts...").clicked() {
+                    *self.window_flag(GuiWindow::InterruptViewer) = true;
+                    ui.close_menu();
+                }
+                if ui.button("Tile Ripper...").clicked() {
+                    *self.window_flag(GuiWindow::TileRipper) = true;
+                    ui.close_menu();
                 }       
+                if ui.button("Trace Sessions...").clicked() {
+                    *self.window_flag(GuiWindow::TraceSessions) = true;
+                    ui.close_menu();
+                }
+                if ui.button("Event Timeline...").clicked() {
+                    *self.window_flag(GuiWindow::EventTimeline) = true;
+                    ui.close_menu();
+                }
+                if ui.button("Profiler...").clicked() {
+                    *self.window_flag(GuiWindow::Profiler) = true;
+                    ui.close_menu();
+                }
+                #[cfg(feature = "cpu_validator")]
+                if ui.button("Validator Statistics...").clicked() {
+                    *self.window_flag(GuiWindow::ValidatorStats) = true;
+                    ui.close_menu();
+                }
                 #[cfg(feature = "devtools")]
                 if ui.button("Device control...").clicked() {
                     *self.window_flag(GuiWindow::DeviceControl) = true;
@@ -376,6 +635,10 @@ impl GuiState {
                     *self.window_flag(GuiWindow::VideoCardViewer) = true;
                     ui.close_menu();
                 }
+                if ui.button("Video Memory...").clicked() {
+                    *self.window_flag(GuiWindow::VideoMemViewer) = true;
+                    ui.close_menu();
+                }
                 if ui.checkbox(&mut self.get_option_mut(GuiOption::ShowBackBuffer), "Debug back buffer").clicked() {
 
                     let new_opt = self.get_option(GuiOption::ShowBackBuffer).unwrap();
@@ -388,11 +651,28 @@ impl GuiState {
                     );
                     ui.close_menu();
                 }
+                if ui.checkbox(&mut self.get_option_mut(GuiOption::FreezeBlink), "Freeze blink phase").clicked() {
+
+                    let new_opt = self.get_option(GuiOption::FreezeBlink).unwrap();
+
+                    self.event_queue.push_back(
+                        GuiEvent::OptionChanged(
+                            GuiOption::FreezeBlink, 
+                            new_opt 
+                        )
+                    );
+                    ui.close_menu();
+                }
                 
                 if ui.button("Flush Trace Logs").clicked() {
                     self.event_queue.push_back(GuiEvent::FlushLogs);
                     ui.close_menu();
                 }
+
+                if ui.button("Create Bug Report").clicked() {
+                    self.event_queue.push_back(GuiEvent::CreateBugReport);
+                    ui.close_menu();
+                }
             });
             ui.menu_button("Options", |ui| {
 
@@ -409,14 +689,103 @@ impl GuiState {
                         );
                         ui.close_menu();
                     }
-                    if ui.checkbox(&mut self.composite, "Composite Monitor").clicked() {
-                        ui.close_menu();
-                    }
+                    ui.menu_button("Scaling", |ui| {
+                        for mode in ScalingMode::ALL {
+                            if ui.radio_value(&mut self.scaling_mode, mode, mode.to_string()).clicked() {
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("Fullscreen", |ui| {
+                        for (fullscreen, label) in [
+                            (None, "Windowed"),
+                            (Some(FullscreenMode::Borderless), "Borderless"),
+                            (Some(FullscreenMode::Exclusive), "Exclusive")
+                        ] {
+                            if ui.radio_value(&mut self.fullscreen, fullscreen, label).clicked() {
+                                self.event_queue.push_back(GuiEvent::SetFullscreen(fullscreen));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+
+                    ui.menu_button("Monitor", |ui| {
+                        for monitor in MonitorType::ALL {
+                            ui.add_enabled_ui(monitor.supports(self.video_type), |ui| {
+                                if ui.radio_value(&mut self.monitor, monitor, monitor.to_string()).clicked() {
+                                    self.event_queue.push_back(GuiEvent::SetMonitor(monitor));
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+                    });
+
+                    ui.menu_button("Palette", |ui| {
+                        for palette in DisplayPalette::ALL {
+                            let enabled = palette != DisplayPalette::Custom || self.custom_palette_loaded;
+                            ui.add_enabled_ui(enabled, |ui| {
+                                if ui.radio_value(&mut self.palette, palette, palette.to_string()).clicked() {
+                                    self.event_queue.push_back(GuiEvent::SetPalette(palette));
+                                    ui.close_menu();
+                                }
+                            });
+                        }
+                    });
+
+                    ui.add_enabled_ui(self.monitor.is_monochrome(), |ui| {
+                        ui.menu_button("Phosphor", |ui| {
+                            for phosphor in MonochromePhosphor::ALL {
+                                if ui.radio_value(&mut self.phosphor, phosphor, phosphor.to_string()).clicked() {
+                                    self.event_queue.push_back(GuiEvent::SetPhosphor(phosphor));
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    });
 
-                    if ui.button("Composite Adjustments...").clicked() {
-                        *self.window_flag(GuiWindow::CompositeAdjust) = true;
-                        ui.close_menu();
-                    }
+                    ui.menu_button("CRT Effects", |ui| {
+                        let old_params = self.crt_params;
+                        let params = &mut self.crt_params;
+
+                        ui.checkbox(&mut params.scanlines, "Scanlines");
+                        ui.add_enabled(
+                            params.scanlines, 
+                            egui::Slider::new(&mut params.scanline_strength, 0.0..=1.0).text("Strength")
+                        );
+                        ui.checkbox(&mut params.barrel, "Barrel Distortion");
+                        ui.add_enabled(
+                            params.barrel, 
+                            egui::Slider::new(&mut params.barrel_strength, 0.0..=0.5).text("Amount")
+                        );
+                        ui.checkbox(&mut params.persistence, "Phosphor Persistence");
+                        ui.add_enabled(
+                            params.persistence, 
+                            egui::Slider::new(&mut params.persistence_decay, 0.0..=0.9).text("Decay")
+                        );
+
+                        if *params != old_params {
+                            self.event_queue.push_back(GuiEvent::SetCrtParams(*params));
+                        }
+                    });
+
+                    ui.add_enabled_ui(self.monitor.is_composite(), |ui| {
+                        if ui.button("Composite Adjustments...").clicked() {
+                            *self.window_flag(GuiWindow::CompositeAdjust) = true;
+                            ui.close_menu();
+                        }
+
+                        if ui.button("Composite Capture...").clicked() {
+                            *self.window_flag(GuiWindow::CompositeCapture) = true;
+                            ui.close_menu();
+                        }
+                    });
+
+                    ui.add_enabled_ui(self.secondary_video_type.is_some(), |ui| {
+                        if ui.button("Secondary Display...").clicked() {
+                            *self.window_flag(GuiWindow::SecondaryDisplay) = true;
+                            ui.close_menu();
+                        }
+                    });
 
                 });                
 
@@ -429,6 +798,44 @@ impl GuiState {
                             ui.close_menu();
                         }
                     }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Connect to:");
+                        ui.text_edit_singleline(&mut self.serial_tcp_addr);
+                        if ui.button("Connect").clicked() {
+                            self.event_queue.push_back(GuiEvent::BridgeSerialTcp(
+                                TcpTarget::Connect(self.serial_tcp_addr.clone()),
+                                self.serial_telnet
+                            ));
+                            ui.close_menu();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Listen on TCP port:");
+                        ui.text_edit_singleline(&mut self.serial_tcp_port);
+                        let port = self.serial_tcp_port.trim().parse::<u16>().ok();
+                        if ui.add_enabled(port.is_some(), egui::Button::new("Listen")).clicked() {
+                            if let Some(port) = port {
+                                self.event_queue.push_back(GuiEvent::BridgeSerialTcp(
+                                    TcpTarget::Listen(port),
+                                    self.serial_telnet
+                                ));
+                            }
+                            ui.close_menu();
+                        }
+                    });
+                    ui.checkbox(&mut self.serial_telnet, "Filter telnet commands");
+                    ui.separator();
+                    ui.add_enabled_ui(self.serial_bridge.is_some(), |ui| {
+                        let label = match &self.serial_bridge {
+                            Some(bridge) => format!("Detach ({})", bridge),
+                            None => "Detach".to_string()
+                        };
+                        if ui.button(label).clicked() {
+                            self.event_queue.push_back(GuiEvent::UnbridgeSerialPort);
+                            ui.close_menu();
+                        }
+                    });
                 });                                
             });
         });
@@ -33,6 +33,7 @@ use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
     ffi::OsString,
+    path::PathBuf,
     rc::Rc,
     time::{Duration, Instant},
 };
@@ -55,13 +56,13 @@ use winit::{window::Window, event_loop::EventLoopWindowTarget};
 use marty_render::VideoData;
 
 use serialport::SerialPortInfo;
-use regex::Regex;
 
 // Bring in submodules
 mod about;
 mod color;
 mod color_swatch;
 mod composite_adjust;
+mod composite_capture;
 mod constants;
 mod cpu_control;
 mod cpu_state_viewer;
@@ -69,18 +70,36 @@ mod cycle_trace_viewer;
 mod delay_adjust;
 mod device_control;
 mod disassembly_viewer;
+mod disk_creator;
 mod dma_viewer;
+mod event_timeline;
+mod frame_pacing;
+mod help;
+mod hotkey_editor;
 mod image;
 mod instruction_history_viewer;
+mod interrupt_viewer;
 mod ivr_viewer;
+mod media_manager;
+mod memory_heatmap;
 mod memory_viewer;
 mod menu;
+mod paste_text;
 mod performance_viewer;
+mod script_console;
+mod secondary_display;
+mod status_bar;
 mod pic_viewer;
 mod pit_viewer;
+mod profiler_viewer;
+mod rate_meter;
 mod theme;
+mod tile_ripper;
 mod token_listview;
+mod trace_sessions;
+mod validator_stats;
 mod videocard_viewer;
+mod vram_viewer;
 
 use crate::{
 
@@ -89,47 +108,84 @@ use crate::{
     // Use custom windows
     egui::about::AboutDialog,
     egui::composite_adjust::CompositeAdjustControl,
+    egui::composite_capture::CompositeCaptureViewer,
     egui::cpu_control::CpuControl,
     egui::cpu_state_viewer::CpuViewerControl,
     egui::cycle_trace_viewer::CycleTraceViewerControl,
+    egui::media_manager::MediaManagerControl,
+    egui::memory_heatmap::MemoryHeatmapViewer,
     egui::memory_viewer::MemoryViewerControl,
     egui::delay_adjust::DelayAdjustControl,
+    egui::status_bar::StatusBar,
+    egui::disk_creator::DiskCreatorControl,
     egui::device_control::DeviceControl,
     egui::disassembly_viewer::DisassemblyControl,
     egui::dma_viewer::DmaViewerControl,
+    egui::event_timeline::EventTimelineViewer,
+    egui::frame_pacing::FramePacingOverlay,
+    egui::help::HelpBrowser,
+    egui::hotkey_editor::HotkeyEditor,
     egui::performance_viewer::PerformanceViewerControl,
     egui::pic_viewer::PicViewerControl,
     egui::pit_viewer::PitViewerControl,
+    egui::profiler_viewer::ProfilerViewer,
+    egui::paste_text::PasteTextControl,
+    egui::script_console::ScriptConsole,
+    egui::secondary_display::SecondaryDisplayViewer,
     egui::instruction_history_viewer::InstructionHistoryControl,
+    egui::interrupt_viewer::InterruptViewerControl,
     egui::ivr_viewer::IvrViewerControl,
     egui::theme::GuiTheme,
+    egui::tile_ripper::TileRipperControl,
+    egui::trace_sessions::TraceSessionControl,
+    egui::validator_stats::ValidatorStatsViewer,
+    egui::vram_viewer::VramViewerControl,
 };
 
 use marty_core::{
+    artifacts::ArtifactKind,
     machine::{MachineState, ExecutionControl},
+    mem_search::SearchDirection,
+    disk_image::FloppyFormat,
     devices::{
         hdc::HardDiskFormat,
         pit::PitDisplayState, 
         pic::PicStringState,
         ppi::PpiStringState, 
+        serial_bridge::TcpTarget,
     },    
+    config::{FullscreenMode, GuiThemeMode, VideoType},
+    gui_layout::{GuiLayout, WindowLayout},
+    hotkey::HotkeyAction,
+    monitor::MonitorType,
+    palette::{DisplayPalette, MonochromePhosphor},
+    speed::CpuClock,
+    trace_session::{TraceFilter, TraceKind, TraceSessionId},
     videocard::{VideoCardState, VideoCardStateEntry}
 };
 
-use marty_render::CompositeParams;
+use marty_render::{CompositeParams, CrtParams, RecordingFormat, ScalingMode};
 
-const VHD_REGEX: &str = r"[\w_]*.vhd$";
+pub(crate) use crate::egui::disassembly_viewer::{DisassemblyRow, DISASSEMBLY_ROWS};
+pub(crate) use crate::egui::frame_pacing::FramePacingSample;
+pub(crate) use crate::egui::help::HelpTopic;
+pub(crate) use crate::egui::profiler_viewer::PROFILER_REPORT_LEN;
+pub(crate) use crate::egui::tile_ripper::TileRipSource;
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) enum GuiWindow {
     About,
     CpuControl,
     PerfViewer,
     MemoryViewer,
+    MemoryHeatmap,
     CompositeAdjust,
+    CompositeCapture,
+    SecondaryDisplay,
     CpuStateViewer,
     HistoryViewer,
     IvrViewer,
+    InterruptViewer,
     DelayAdjust,
     DeviceControl,
     DisassemblyViewer,
@@ -140,34 +196,64 @@ pub(crate) enum GuiWindow {
     VideoCardViewer,
     VideoMemViewer,
     CallStack,
-    VHDCreator,
+    DiskCreator,
     CycleTraceViewer,
+    HelpBrowser,
+    TileRipper,
+    ScriptConsole,
+    PasteText,
+    TraceSessions,
+    EventTimeline,
+    ValidatorStats,
+    Profiler,
+    MediaManager,
+    HotkeyEditor,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum GuiOption {
     CompositeDisplay,
     CorrectAspect,
     CpuEnableWaitStates,
     CpuInstructionHistory,
     CpuTraceLoggingEnabled,
+    CpuHaltSkip,
     TurboButton,
+    WarpSpeed,
     ShowBackBuffer,
+    FreezeBlink,
+    WriteProtectDriveA,
+    WriteProtectDriveB,
+    FramePacingOverlay,
+    DriveSounds,
+    PauseOnFocusLoss,
 }
 
 #[allow(dead_code)]
 pub enum GuiEvent {
     LoadVHD(usize, OsString),
-    CreateVHD(OsString, HardDiskFormat),
+    CreateVHD(OsString, HardDiskFormat, bool),
+    CreateFloppy(OsString, FloppyFormat, bool),
     LoadFloppy(usize, OsString),
+    MountDirectory(usize, PathBuf, bool),
     SaveFloppy(usize, OsString),
     EjectFloppy(usize),
     BridgeSerialPort(String),
+    BridgeSerialTcp(TcpTarget, bool),
+    UnbridgeSerialPort,
     DumpVRAM,
     DumpCS,
     DumpAllMem,
+    DumpInstructionHistory,
     EditBreakpoint,
     MemoryUpdate,
+    MemoryEdit(usize, u8),
+    MemorySearch(String, SearchDirection),
+    SetCoverage(bool),
+    ClearCoverage,
+    ExportCoverage,
+    PatchCode(String, String, bool),
+    UndoPatch,
     TokenHover(usize),
     OptionChanged(GuiOption, bool),
     CompositeAdjust(CompositeParams),
@@ -175,12 +261,50 @@ pub enum GuiEvent {
     DelayAdjust,
     TickDevice(DeviceSelection, u32),
     MachineStateChange(MachineState),
+    SetPalette(DisplayPalette),
+    SetMonitor(MonitorType),
+    SetPhosphor(MonochromePhosphor),
+    SetCrtParams(CrtParams),
+    SetFullscreen(Option<FullscreenMode>),
+    NewInstance,
     TakeScreenshot,
+    StartRecording(RecordingFormat),
+    StopRecording,
+    SaveScreenText,
+    CreateBugReport,
     Exit,
     SetNMI(bool),
     TriggerParity,
     RescanMediaFolders,
-    CtrlAltDel
+    CtrlAltDel,
+    PasteText(String),
+    CancelPaste,
+    Rewind(u64),
+    SetSpeed(f64),
+    SetCpuClock(CpuClock),
+    SaveState(u8),
+    LoadState(u8),
+    UnbindHotkey(HotkeyAction),
+    ResetHotkeys,
+    CaptureComposite,
+    SaveCompositeCapture,
+    SaveTiles(Option<u32>),
+    RunScript(String),
+    StopScript,
+    ShowHelp(HelpTopic),
+    OpenArtifactDir,
+    OpenLatestArtifact(ArtifactKind),
+    ExportValidatorStats,
+    ResetValidatorStats,
+    SetProfiler(bool),
+    ClearProfiler,
+    ExportProfile,
+    StartTraceSession(String, TraceKind, TraceFilter),
+    PauseTraceSession(TraceSessionId, bool),
+    StopTraceSession(TraceSessionId),
+    RemoveTraceSession(TraceSessionId),
+    SetTimelineRecording(bool),
+    ClearTimeline,
 }
 
 pub enum DeviceSelection {
@@ -215,9 +339,14 @@ pub struct PerformanceStats {
     pub current_cps: u64,
     pub current_tps: u64,
     pub current_ips: u64,
+    pub frame_time: Duration,
     pub emulation_time: Duration,
     pub render_time: Duration,
+    pub worker_render_time: Duration,
     pub gui_time: Duration,
+    pub guest_idle_frames: u64,
+    pub speed_multiplier: f64,
+    pub fast_forward: bool,
 }
 
 /// Example application state. A real application will need a lot more state than this.
@@ -227,13 +356,21 @@ pub(crate) struct GuiState {
 
     /// Only show the associated window when true.
     window_open_flags: HashMap::<GuiWindow, bool>,
+    /// Window placements restored from the last session, and where windows are now.
+    window_layout: GuiLayout,
+    window_pos: HashMap::<GuiWindow, egui::Pos2>,
     error_dialog_open: bool,
     
     option_flags: HashMap::<GuiOption, bool>,
 
     machine_state: MachineState,
+    rewind_depth: Option<u64>,
+    guest_os: String,
+    speed: f64,
+    pub status_bar: StatusBar,
+    cpu_clock: Option<CpuClock>,
+    recording: Option<RecordingFormat>,
 
-    video_mem: ColorImage,
     video_data: VideoData,
     perf_stats: PerformanceStats,
 
@@ -241,6 +378,8 @@ pub(crate) struct GuiState {
     floppy_names: Vec<OsString>,
     floppy0_name: Option<OsString>,
     floppy1_name: Option<OsString>,
+    floppy0_media: Option<String>,
+    floppy1_media: Option<String>,
     
     // VHD Images
     vhd_names: Vec<OsString>,
@@ -249,14 +388,13 @@ pub(crate) struct GuiState {
     new_vhd_name1: Option<OsString>,
     vhd_name1: OsString,
 
-    vhd_formats: Vec<HardDiskFormat>,
-    selected_format_idx: usize,
-    new_vhd_filename: String,
-    vhd_regex: Regex,
-
     // Serial ports
     serial_ports: Vec<SerialPortInfo>,
     serial_port_name: String,
+    serial_bridge: Option<String>,
+    serial_tcp_addr: String,
+    serial_tcp_port: String,
+    serial_telnet: bool,
 
     exec_control: Rc<RefCell<ExecutionControl>>,
 
@@ -267,9 +405,12 @@ pub(crate) struct GuiState {
     pub cpu_viewer: CpuViewerControl,
     pub cycle_trace_viewer: CycleTraceViewerControl,
     pub memory_viewer: MemoryViewerControl,
+    pub memory_heatmap: MemoryHeatmapViewer,
 
     pub perf_viewer: PerformanceViewerControl,
+    pub frame_pacing: FramePacingOverlay,
     pub delay_adjust: DelayAdjustControl,
+    pub disk_creator: DiskCreatorControl,
     
     pub pit_viewer: PitViewerControl,
     pub pic_viewer: PicViewerControl,
@@ -281,12 +422,34 @@ pub(crate) struct GuiState {
     pub dma_viewer: DmaViewerControl,
     pub trace_viewer: InstructionHistoryControl,
     pub composite_adjust: CompositeAdjustControl,
+    pub composite_capture: CompositeCaptureViewer,
+    pub secondary_display: SecondaryDisplayViewer,
     pub ivr_viewer: IvrViewerControl,
+    pub interrupt_viewer: InterruptViewerControl,
     pub device_control: DeviceControl,
+    pub help_browser: HelpBrowser,
+    pub tile_ripper: TileRipperControl,
+    pub script_console: ScriptConsole,
+    pub paste_text: PasteTextControl,
+    pub trace_sessions: TraceSessionControl,
+    pub media_manager: MediaManagerControl,
+    pub event_timeline: EventTimelineViewer,
+    pub validator_stats: ValidatorStatsViewer,
+    pub profiler_viewer: ProfilerViewer,
+    pub vram_viewer: VramViewerControl,
+    pub hotkey_editor: HotkeyEditor,
 
     call_stack_string: String,
 
-    composite: bool
+    scaling_mode: ScalingMode,
+    fullscreen: Option<FullscreenMode>,
+    video_type: VideoType,
+    secondary_video_type: Option<VideoType>,
+    monitor: MonitorType,
+    palette: DisplayPalette,
+    custom_palette_loaded: bool,
+    phosphor: MonochromePhosphor,
+    crt_params: CrtParams,
 }
 
 impl Framework {
@@ -298,6 +461,7 @@ impl Framework {
         scale_factor: f32, 
         pixels: &pixels::Pixels,
         exec_control: Rc<RefCell<ExecutionControl>>,
+        theme: GuiThemeMode,
         theme_color: Option<u32>
     
     ) -> Self {
@@ -323,11 +487,17 @@ impl Framework {
         let textures = TexturesDelta::default();
         let gui = GuiState::new(exec_control);
 
-        let visuals = egui::Visuals::dark();
+        let visuals = match theme {
+            GuiThemeMode::Dark => egui::Visuals::dark(),
+            GuiThemeMode::Light => egui::Visuals::light(),
+        };
 
-        if let Some(color) = theme_color {
-            let theme = GuiTheme::new(&visuals, crate::egui::color::hex_to_c32(color));
-            egui_ctx.set_visuals(theme.visuals().clone());
+        match theme_color {
+            Some(color) => {
+                let theme = GuiTheme::new(&visuals, crate::egui::color::hex_to_c32(color));
+                egui_ctx.set_visuals(theme.visuals().clone());
+            }
+            None => egui_ctx.set_visuals(visuals),
         }
 
         //egui_ctx.set_debug_on_hover(true);
@@ -352,6 +522,11 @@ impl Framework {
         }
     }
 
+    /// Return whether the pointer is over an egui window or panel.
+    pub(crate) fn pointer_over_gui(&self) -> bool {
+        self.egui_ctx.is_pointer_over_area()
+    }
+
     /// Handle input events from the window manager.
     pub(crate) fn handle_event(&mut self, event: &winit::event::WindowEvent) {
         #[cfg(not(target_arch = "wasm32"))]
@@ -389,6 +564,7 @@ impl Framework {
             self.paint_jobs = self.egui_ctx.tessellate(output.shapes);
 
             self.gui.perf_stats.gui_time = Instant::now() - gui_start;
+            self.gui.perf_viewer.update_gui_time(self.gui.perf_stats.gui_time);
         }
     }
 
@@ -454,10 +630,14 @@ impl GuiState {
             (GuiWindow::CpuControl, false),
             (GuiWindow::PerfViewer, false),
             (GuiWindow::MemoryViewer, false),
+            (GuiWindow::MemoryHeatmap, false),
             (GuiWindow::CompositeAdjust, false),
+            (GuiWindow::CompositeCapture, false),
+            (GuiWindow::SecondaryDisplay, false),
             (GuiWindow::CpuStateViewer, false),
             (GuiWindow::HistoryViewer, false),
             (GuiWindow::IvrViewer, false),
+            (GuiWindow::InterruptViewer, false),
             (GuiWindow::DelayAdjust, false),
             (GuiWindow::DeviceControl, false),
             (GuiWindow::DisassemblyViewer, false),
@@ -468,8 +648,18 @@ impl GuiState {
             (GuiWindow::VideoCardViewer, false),
             (GuiWindow::VideoMemViewer, false),
             (GuiWindow::CallStack, false),
-            (GuiWindow::VHDCreator, false),
+            (GuiWindow::DiskCreator, false),
             (GuiWindow::CycleTraceViewer, false),
+            (GuiWindow::HelpBrowser, false),
+            (GuiWindow::TileRipper, false),
+            (GuiWindow::ScriptConsole, false),
+            (GuiWindow::PasteText, false),
+            (GuiWindow::TraceSessions, false),
+            (GuiWindow::EventTimeline, false),
+            (GuiWindow::ValidatorStats, false),
+            (GuiWindow::Profiler, false),
+            (GuiWindow::MediaManager, false),
+            (GuiWindow::HotkeyEditor, false),
         ].into();
 
         let option_flags: HashMap<GuiOption, bool> = [
@@ -478,19 +668,34 @@ impl GuiState {
             (GuiOption::CpuEnableWaitStates, true),
             (GuiOption::CpuInstructionHistory, false),
             (GuiOption::CpuTraceLoggingEnabled, false),
+            (GuiOption::CpuHaltSkip, true),
             (GuiOption::TurboButton, false),
-            (GuiOption::ShowBackBuffer, true)
+            (GuiOption::WarpSpeed, false),
+            (GuiOption::ShowBackBuffer, true),
+            (GuiOption::FreezeBlink, false),
+            (GuiOption::WriteProtectDriveA, false),
+            (GuiOption::WriteProtectDriveB, false),
+            (GuiOption::FramePacingOverlay, false),
+            (GuiOption::DriveSounds, false),
+            (GuiOption::PauseOnFocusLoss, false),
         ].into();
 
         Self { 
             event_queue: VecDeque::new(),
             window_open_flags,
+            window_layout: GuiLayout::default(),
+            window_pos: HashMap::new(),
             error_dialog_open: false,
 
             option_flags,
 
             machine_state: MachineState::Off,
-            video_mem: ColorImage::new([320,200], egui::Color32::BLACK),
+            rewind_depth: None,
+            guest_os: String::new(),
+            speed: 1.0,
+            status_bar: StatusBar::new(),
+            cpu_clock: None,
+            recording: None,
 
             video_data: Default::default(),
             perf_stats: Default::default(),
@@ -498,6 +703,8 @@ impl GuiState {
             floppy_names: Vec::new(),
             floppy0_name: Option::None,
             floppy1_name: Option::None,
+            floppy0_media: Option::None,
+            floppy1_media: Option::None,
 
             vhd_names: Vec::new(),
             new_vhd_name0: Option::None,
@@ -505,13 +712,12 @@ impl GuiState {
             new_vhd_name1: Option::None,
             vhd_name1: OsString::new(),
 
-            vhd_formats: Vec::new(),
-            selected_format_idx: 0,
-            new_vhd_filename: String::new(),
-            vhd_regex: Regex::new(VHD_REGEX).unwrap(),
-
             serial_ports: Vec::new(),
             serial_port_name: String::new(),
+            serial_bridge: None,
+            serial_tcp_addr: "localhost:2323".to_string(),
+            serial_tcp_port: "2323".to_string(),
+            serial_telnet: false,
 
             exec_control: exec_control.clone(),
 
@@ -522,9 +728,12 @@ impl GuiState {
             cpu_viewer: CpuViewerControl::new(),
             cycle_trace_viewer: CycleTraceViewerControl::new(),
             memory_viewer: MemoryViewerControl::new(),
+            memory_heatmap: MemoryHeatmapViewer::new(),
 
             perf_viewer: PerformanceViewerControl::new(),
+            frame_pacing: FramePacingOverlay::new(),
             delay_adjust: DelayAdjustControl::new(),
+            disk_creator: DiskCreatorControl::new(),
             pit_viewer: PitViewerControl::new(),
             pic_viewer: PicViewerControl::new(),
             ppi_state: Default::default(),
@@ -534,12 +743,34 @@ impl GuiState {
             dma_viewer: DmaViewerControl::new(),
             trace_viewer: InstructionHistoryControl::new(),
             composite_adjust: CompositeAdjustControl::new(),
+            composite_capture: CompositeCaptureViewer::new(),
+            secondary_display: SecondaryDisplayViewer::new(),
+            tile_ripper: TileRipperControl::new(),
+            script_console: ScriptConsole::new(),
+            paste_text: PasteTextControl::new(),
+            trace_sessions: TraceSessionControl::new(),
+            media_manager: MediaManagerControl::new(),
+            event_timeline: EventTimelineViewer::new(),
+            validator_stats: ValidatorStatsViewer::new(),
+            profiler_viewer: ProfilerViewer::new(),
+            vram_viewer: VramViewerControl::new(),
+            hotkey_editor: HotkeyEditor::new(),
             ivr_viewer: IvrViewerControl::new(),
+            interrupt_viewer: InterruptViewerControl::new(),
             device_control: DeviceControl::new(),
+            help_browser: HelpBrowser::new(),
             call_stack_string: String::new(),
 
             // Options menu items
-            composite: false
+            scaling_mode: Default::default(),
+            fullscreen: None,
+            video_type: VideoType::CGA,
+            secondary_video_type: None,
+            monitor: Default::default(),
+            palette: Default::default(),
+            custom_palette_loaded: false,
+            phosphor: Default::default(),
+            crt_params: Default::default(),
         }
     }
 
@@ -547,7 +778,6 @@ impl GuiState {
         self.event_queue.pop_front()
     }
 
-    #[allow (dead_code)]
     pub fn send_event(&mut self, event: GuiEvent) {
         self.event_queue.push_back(event);
     }
@@ -566,11 +796,60 @@ impl GuiState {
         }
     }
 
+    /// Return a list of all currently open windows.
+    pub fn open_windows(&self) -> Vec<GuiWindow> {
+        self.window_open_flags
+            .iter()
+            .filter_map(|(window, open)| if *open { Some(*window) } else { None })
+            .collect()
+    }
+
     pub fn set_window_open(&mut self, window: GuiWindow, state: bool) {
 
         *self.window_open_flags.get_mut(&window).unwrap() = state;
     }    
 
+    /// Restore the open windows and their placement from a previous session.
+    pub fn set_layout(&mut self, layout: GuiLayout) {
+        for (window, open) in self.window_open_flags.iter_mut() {
+            if let Some(window_layout) = layout.get(&format!("{:?}", window)) {
+                *open = window_layout.open;
+            }
+        }
+        self.window_layout = layout;
+    }
+
+    /// Return the open windows and their placement, to be restored in the next session. Windows
+    /// not shown this session keep the placement they were restored with.
+    pub fn layout(&self) -> GuiLayout {
+        let mut layout = self.window_layout.clone();
+        for (window, open) in &self.window_open_flags {
+            let name = format!("{:?}", window);
+            let pos = match self.window_pos.get(window) {
+                Some(pos) => Some([pos.x, pos.y]),
+                None => layout.get(&name).and_then(|window_layout| window_layout.pos),
+            };
+            layout.set(&name, WindowLayout { open: *open, pos });
+        }
+        layout
+    }
+
+    /// Create the specified window, placed where it was left in the last session.
+    fn layout_window<'open>(&self, window: GuiWindow, title: &str) -> egui::Window<'open> {
+        let egui_window = egui::Window::new(title);
+        match self.window_layout.get(&format!("{:?}", window)).and_then(|window_layout| window_layout.pos) {
+            Some([x, y]) => egui_window.default_pos(egui::pos2(x, y)),
+            None => egui_window,
+        }
+    }
+
+    /// Record where a window was drawn this frame.
+    fn track_window<R>(&mut self, window: GuiWindow, response: Option<egui::InnerResponse<R>>) {
+        if let Some(response) = response {
+            self.window_pos.insert(window, response.response.rect.min);
+        }
+    }
+
     pub fn set_option(&mut self, option: GuiOption, state: bool) {
         if let Some(opt) = self.option_flags.get_mut(&option) {
             *opt = state
@@ -599,10 +878,81 @@ impl GuiState {
         self.machine_state = state;
     }
 
+    /// Set the number of frames the machine can be rewound, or None if rewind is disabled.
+    pub fn set_rewind_depth(&mut self, depth: Option<u64>) {
+        self.rewind_depth = depth;
+    }
+
+    /// Set the requested emulation speed shown in the Machine menu.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Set the CPU clock shown in the Machine menu, or None if the machine's clock is not
+    /// one of the presets.
+    pub fn set_cpu_clock(&mut self, clock: Option<CpuClock>) {
+        self.cpu_clock = clock;
+    }
+
+    /// Set the display palette selected, and whether a custom palette was loaded.
+    pub fn set_palette(&mut self, palette: DisplayPalette, custom_palette_loaded: bool) {
+        self.palette = palette;
+        self.custom_palette_loaded = custom_palette_loaded;
+    }
+
+    /// Set the monitor attached to the video card. Only monitors that can be
+    /// attached to the card are offered in the Display menu.
+    pub fn set_monitor(&mut self, monitor: MonitorType, video_type: VideoType) {
+        self.monitor = monitor;
+        self.video_type = video_type;
+    }
+
+    /// Set the type of the secondary video card, if one is installed.
+    pub fn set_secondary_video(&mut self, video_type: Option<VideoType>) {
+        self.secondary_video_type = video_type;
+    }
+
+    /// Set the phosphor color used by monochrome monitors.
+    pub fn set_phosphor(&mut self, phosphor: MonochromePhosphor) {
+        self.phosphor = phosphor;
+    }
+
+    /// Set the CRT effect parameters shown in the Display menu.
+    pub fn set_crt_params(&mut self, params: CrtParams) {
+        self.crt_params = params;
+    }
+
+    /// Set the format of the screen recording in progress, or None if not recording.
+    pub fn set_recording(&mut self, format: Option<RecordingFormat>) {
+        self.recording = format;
+    }
+
+    /// Set the description of the detected guest OS shown in the status bar.
+    pub fn set_guest_os(&mut self, os: String) {
+        self.guest_os = os;
+    }
+
     pub fn set_floppy_names(&mut self, names: Vec<OsString>) {
         self.floppy_names = names;
     }
 
+    /// Set the name and format description of the floppy image mounted in the specified drive,
+    /// or None if the drive is empty.
+    pub fn set_floppy_status(&mut self, drive: usize, name: Option<OsString>, media: Option<&str>) {
+        let media = media.map(|m| m.to_string());
+        match drive {
+            0 => {
+                self.floppy0_name = name;
+                self.floppy0_media = media;
+            }
+            1 => {
+                self.floppy1_name = name;
+                self.floppy1_media = media;
+            }
+            _ => {}
+        }
+    }
+
     pub fn set_vhd_names(&mut self, names: Vec<OsString>) {
         self.vhd_names = names;
     }
@@ -633,8 +983,23 @@ impl GuiState {
         *self.window_open_flags.get_mut(&window).unwrap() = true;
     }
 
+    /// Open the help browser to the specified topic.
+    pub fn show_help(&mut self, topic: HelpTopic) {
+        self.help_browser.set_topic(topic);
+        self.show_window(GuiWindow::HelpBrowser);
+    }
+
+    pub fn get_scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    /// Set the fullscreen mode shown in the Display menu, or None if windowed.
+    pub fn set_fullscreen(&mut self, fullscreen: Option<FullscreenMode>) {
+        self.fullscreen = fullscreen;
+    }
+
     pub fn get_composite_enabled(&self) -> bool {
-        self.composite
+        self.monitor.is_composite()
     }
 
     pub fn get_breakpoints(&mut self) -> (&str, &str, &str) {
@@ -653,8 +1018,9 @@ impl GuiState {
         self.ppi_state = state;
     }
 
-    pub fn update_vhd_formats(&mut self, formats: Vec<HardDiskFormat>) {
-        self.vhd_formats = formats
+    /// Set a description of the bridge attached to COM2, if any.
+    pub fn set_serial_bridge(&mut self, bridge: Option<String>) {
+        self.serial_bridge = bridge;
     }
 
     pub fn update_serial_ports(&mut self, ports: Vec<SerialPortInfo>) {
@@ -665,12 +1031,6 @@ impl GuiState {
         self.videocard_state = state;
     }
 
-    #[allow (dead_code)]
-    pub fn update_videomem_state(&mut self, mem: Vec<u8>, w: u32, h: u32) {
-
-        self.video_mem = ColorImage::from_rgba_unmultiplied([w as usize, h as usize],&mem);
-    }
-
     /// Create the UI using egui.
     fn ui(&mut self, ctx: &Context) {
 
@@ -678,27 +1038,30 @@ impl GuiState {
         egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
             self.draw_menu(ui);
         });
+
+        // Draw bottom status bar
+        egui::TopBottomPanel::bottom("statusbar_container").show(ctx, |ui| {
+            self.status_bar.draw(ui, &self.guest_os, self.speed);
+        });
         
-        egui::Window::new("About")
+        let response = self.layout_window(GuiWindow::About, "About")
             .open(self.window_open_flags.get_mut(&GuiWindow::About).unwrap())
             .show(ctx, |ui| {
 
                 self.about_dialog.draw(ui, ctx, &mut self.event_queue);
 
             });
+        self.track_window(GuiWindow::About, response);
 
-        //let video_texture: &egui::TextureHandle = self.texture.get_or_insert_with(|| {
-        //        ctx.load_texture(
-        //            "video_mem",
-        //            self.video_mem,
-        //        )
-        //    });
-
-        egui::Window::new("Video Mem")
+        let response = self.layout_window(GuiWindow::VideoMemViewer, "Video Memory")
             .open(self.window_open_flags.get_mut(&GuiWindow::VideoMemViewer).unwrap())
-            .show(ctx, |_ui| {
-
-            });            
+            .resizable(true)
+            .default_width(680.0)
+            .default_height(500.0)
+            .show(ctx, |ui| {
+                self.vram_viewer.draw(ui, ctx, self.video_type);
+            });
+        self.track_window(GuiWindow::VideoMemViewer, response);
 
         egui::Window::new("Error")
             .open(&mut self.error_dialog_open)
@@ -709,44 +1072,80 @@ impl GuiState {
                 });
             });
 
-        egui::Window::new("Performance")
+        let response = self.layout_window(GuiWindow::PerfViewer, "Performance")
             .open(self.window_open_flags.get_mut(&GuiWindow::PerfViewer).unwrap())
             .show(ctx, |ui| {
 
                 self.perf_viewer.draw(ui, &mut self.event_queue);
             });
+        self.track_window(GuiWindow::PerfViewer, response);
+
+        if self.get_option(GuiOption::FramePacingOverlay).unwrap_or(false) {
+            egui::Window::new("Frame Pacing")
+                .anchor(egui::Align2::RIGHT_TOP, [-8.0, 32.0])
+                .resizable(false)
+                .show(ctx, |ui| {
+                    self.frame_pacing.draw(ui);
+                });
+        }
+
+        // Show an overlay over the display while the machine is paused
+        if let MachineState::Paused = self.machine_state {
+            egui::Area::new("paused_overlay")
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(egui::RichText::new("⏸ Paused").size(32.0).strong());
+                    });
+                });
+        }
 
-        egui::Window::new("CPU Control")
+        let response = self.layout_window(GuiWindow::CpuControl, "CPU Control")
             .open(self.window_open_flags.get_mut(&GuiWindow::CpuControl).unwrap())
             .show(ctx, |ui| {
                 self.cpu_control.draw(ui, &mut self.option_flags, &mut self.event_queue);
             });
+        self.track_window(GuiWindow::CpuControl, response);
 
-        egui::Window::new("Memory View")
+        let response = self.layout_window(GuiWindow::MemoryViewer, "Memory View")
             .open(self.window_open_flags.get_mut(&GuiWindow::MemoryViewer).unwrap())
             .resizable(true)
             .default_width(540.0)
             .show(ctx, |ui| {
                 self.memory_viewer.draw(ui, &mut self.event_queue);
             });
+        self.track_window(GuiWindow::MemoryViewer, response);
+
+        let response = self.layout_window(GuiWindow::MemoryHeatmap, "Memory Heatmap")
+            .open(self.window_open_flags.get_mut(&GuiWindow::MemoryHeatmap).unwrap())
+            .resizable(true)
+            .default_width(560.0)
+            .default_height(600.0)
+            .show(ctx, |ui| {
+                self.memory_heatmap.draw(ui, ctx);
+            });
+        self.track_window(GuiWindow::MemoryHeatmap, response);
 
-        egui::Window::new("Instruction History")
+        let response = self.layout_window(GuiWindow::HistoryViewer, "Instruction History")
             .open(self.window_open_flags.get_mut(&GuiWindow::HistoryViewer).unwrap())
             .resizable(true)
             .default_width(540.0)
             .show(ctx, |ui| {
                 self.trace_viewer.draw(ui, &mut self.event_queue);
-            });       
+            });
+        self.track_window(GuiWindow::HistoryViewer, response);
 
-        egui::Window::new("Cycle Trace")
+        let response = self.layout_window(GuiWindow::CycleTraceViewer, "Cycle Trace")
             .open(self.window_open_flags.get_mut(&GuiWindow::CycleTraceViewer).unwrap())
             .resizable(true)
             .default_width(540.0)
             .show(ctx, |ui| {
                 self.cycle_trace_viewer.draw(ui, &mut self.event_queue);
-            });               
+            });
+        self.track_window(GuiWindow::CycleTraceViewer, response);
 
-        egui::Window::new("Call Stack")
+        let response = self.layout_window(GuiWindow::CallStack, "Call Stack")
             .open(self.window_open_flags.get_mut(&GuiWindow::CallStack).unwrap())
             .resizable(true)
             .default_width(540.0)
@@ -758,50 +1157,65 @@ impl GuiState {
                             .font(egui::TextStyle::Monospace));
                     ui.end_row()
                 });
-            });              
+            });
+        self.track_window(GuiWindow::CallStack, response);
 
-        egui::Window::new("Disassembly View")
+        let response = self.layout_window(GuiWindow::DisassemblyViewer, "Disassembly View")
             .open(self.window_open_flags.get_mut(&GuiWindow::DisassemblyViewer).unwrap())
             .resizable(true)
             .default_width(540.0)
             .show(ctx, |ui| {
                 self.disassembly_viewer.draw(ui, &mut self.event_queue);
-            });             
+            });
+        self.track_window(GuiWindow::DisassemblyViewer, response);
 
-        egui::Window::new("IVR Viewer")
+        let response = self.layout_window(GuiWindow::IvrViewer, "IVR Viewer")
             .open(self.window_open_flags.get_mut(&GuiWindow::IvrViewer).unwrap())
             .resizable(true)
             .default_width(400.0)
             .show(ctx, |ui| {
                 self.ivr_viewer.draw(ui, &mut self.event_queue);
             }
-        );  
+        );
+        self.track_window(GuiWindow::IvrViewer, response);
+
+        let response = self.layout_window(GuiWindow::InterruptViewer, "Interrupts")
+            .open(self.window_open_flags.get_mut(&GuiWindow::InterruptViewer).unwrap())
+            .resizable(true)
+            .default_width(600.0)
+            .show(ctx, |ui| {
+                self.interrupt_viewer.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::InterruptViewer, response);
 
-        egui::Window::new("CPU State")
+        let response = self.layout_window(GuiWindow::CpuStateViewer, "CPU State")
             .open(self.window_open_flags.get_mut(&GuiWindow::CpuStateViewer).unwrap())
             .resizable(false)
             .default_width(220.0)
             .show(ctx, |ui| {
                 self.cpu_viewer.draw(ui, &mut self.event_queue);
-            });      
+            });
+        self.track_window(GuiWindow::CpuStateViewer, response);
 
-        egui::Window::new("Delay Adjust")
+        let response = self.layout_window(GuiWindow::DelayAdjust, "Delay Adjust")
             .open(self.window_open_flags.get_mut(&GuiWindow::DelayAdjust).unwrap())
             .resizable(true)
             .default_width(800.0)
             .show(ctx, |ui| {
                 self.delay_adjust.draw(ui, &mut self.event_queue);
-            });            
+            });
+        self.track_window(GuiWindow::DelayAdjust, response);
 
-        egui::Window::new("Device Control")
+        let response = self.layout_window(GuiWindow::DeviceControl, "Device Control")
             .open(self.window_open_flags.get_mut(&GuiWindow::DeviceControl).unwrap())
             .resizable(true)
             .default_width(400.0)
             .show(ctx, |ui| {
                 self.device_control.draw(ui, &mut self.event_queue);
-            });                       
+            });
+        self.track_window(GuiWindow::DeviceControl, response);
             
-        egui::Window::new("PIT View")
+        let response = self.layout_window(GuiWindow::PitViewer, "PIT View")
             .open(self.window_open_flags.get_mut(&GuiWindow::PitViewer).unwrap())
             .resizable(false)
             .min_width(600.0)
@@ -810,18 +1224,20 @@ impl GuiState {
 
                 self.pit_viewer.draw(ui, &mut self.event_queue);
 
-            });               
+            });
+        self.track_window(GuiWindow::PitViewer, response);
 
-        egui::Window::new("PIC View")
+        let response = self.layout_window(GuiWindow::PicViewer, "PIC View")
             .open(self.window_open_flags.get_mut(&GuiWindow::PicViewer).unwrap())
             .resizable(true)
             .default_width(600.0)
             .show(ctx, |ui| {
 
                 self.pic_viewer.draw(ui, &mut self.event_queue);
-            });           
+            });
+        self.track_window(GuiWindow::PicViewer, response);
             
-        egui::Window::new("PPI View")
+        let response = self.layout_window(GuiWindow::PpiViewer, "PPI View")
             .open(self.window_open_flags.get_mut(&GuiWindow::PpiViewer).unwrap())
             .resizable(true)
             .default_width(600.0)
@@ -865,59 +1281,154 @@ impl GuiState {
                     ui.end_row();
                 });
             });
+        self.track_window(GuiWindow::PpiViewer, response);
 
-        egui::Window::new("DMA View")
+        let response = self.layout_window(GuiWindow::DmaViewer, "DMA View")
             .open(self.window_open_flags.get_mut(&GuiWindow::DmaViewer).unwrap())
             .resizable(false)
-            .default_width(200.0)
+            .default_width(520.0)
             .show(ctx, |ui| {
                 self.dma_viewer.draw(ui, &mut self.event_queue);
-            });                       
+            });
+        self.track_window(GuiWindow::DmaViewer, response);
 
-        egui::Window::new("Video Card View")
+        let response = self.layout_window(GuiWindow::VideoCardViewer, "Video Card View")
             .open(self.window_open_flags.get_mut(&GuiWindow::VideoCardViewer).unwrap())
             .resizable(false)
             .default_width(300.0)
             .show(ctx, |ui| {
                 GuiState::draw_video_card_panel(ui, &self.videocard_state);
-            });         
+            });
+        self.track_window(GuiWindow::VideoCardViewer, response);
 
-        egui::Window::new("Create VHD")
-            .open(self.window_open_flags.get_mut(&GuiWindow::VHDCreator).unwrap())
+        let response = self.layout_window(GuiWindow::DiskCreator, "Create Disk Image")
+            .open(self.window_open_flags.get_mut(&GuiWindow::DiskCreator).unwrap())
             .resizable(false)
             .default_width(400.0)
             .show(ctx, |ui| {
+                self.disk_creator.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::DiskCreator, response);
 
-                if !self.vhd_formats.is_empty() {
-                    egui::ComboBox::from_label("Format")
-                    .selected_text(format!("{}", self.vhd_formats[self.selected_format_idx].desc))
-                    .show_ui(ui, |ui| {
-                        for (i, fmt) in self.vhd_formats.iter_mut().enumerate() {
-                            ui.selectable_value(&mut self.selected_format_idx, i, fmt.desc.to_string());
-                        }
-                    });
+        let response = self.layout_window(GuiWindow::CompositeAdjust, "Composite Adjustment")
+            .open(self.window_open_flags.get_mut(&GuiWindow::CompositeAdjust).unwrap())
+            .resizable(false)
+            .default_width(300.0)
+            .show(ctx, |ui| {
+                self.composite_adjust.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::CompositeAdjust, response);
 
-                    ui.horizontal(|ui| {
-                        ui.label("Filename: ");
-                        ui.text_edit_singleline(&mut self.new_vhd_filename);
-                    });               
+        let response = self.layout_window(GuiWindow::CompositeCapture, "Composite Capture")
+            .open(self.window_open_flags.get_mut(&GuiWindow::CompositeCapture).unwrap())
+            .resizable(true)
+            .default_width(700.0)
+            .show(ctx, |ui| {
+                self.composite_capture.draw(ui, ctx, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::CompositeCapture, response);
 
-                    let enabled = self.vhd_regex.is_match(&self.new_vhd_filename.to_lowercase());
+        let response = self.layout_window(GuiWindow::SecondaryDisplay, "Secondary Display")
+            .open(self.window_open_flags.get_mut(&GuiWindow::SecondaryDisplay).unwrap())
+            .resizable(true)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                self.secondary_display.draw(ui, ctx);
+            });
+        self.track_window(GuiWindow::SecondaryDisplay, response);
 
-                    if ui.add_enabled(enabled, egui::Button::new("Create"))
-                        .clicked() {
-                        self.event_queue.push_back(GuiEvent::CreateVHD(OsString::from(&self.new_vhd_filename), self.vhd_formats[self.selected_format_idx].clone()))
-                    };                        
-                }
+        let response = self.layout_window(GuiWindow::TileRipper, "Tile Ripper")
+            .open(self.window_open_flags.get_mut(&GuiWindow::TileRipper).unwrap())
+            .resizable(true)
+            .default_width(600.0)
+            .default_height(500.0)
+            .show(ctx, |ui| {
+                self.tile_ripper.draw(ui, ctx, &mut self.event_queue);
             });
+        self.track_window(GuiWindow::TileRipper, response);
 
-        egui::Window::new("Composite Adjustment")
-            .open(self.window_open_flags.get_mut(&GuiWindow::CompositeAdjust).unwrap())
+        let response = self.layout_window(GuiWindow::ScriptConsole, "Script Console")
+            .open(self.window_open_flags.get_mut(&GuiWindow::ScriptConsole).unwrap())
+            .resizable(true)
+            .default_width(500.0)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                self.script_console.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::ScriptConsole, response);
+
+        let response = self.layout_window(GuiWindow::PasteText, "Paste Text")
+            .open(self.window_open_flags.get_mut(&GuiWindow::PasteText).unwrap())
+            .resizable(true)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                self.paste_text.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::PasteText, response);
+
+        let response = self.layout_window(GuiWindow::MediaManager, "Media Manager")
+            .open(self.window_open_flags.get_mut(&GuiWindow::MediaManager).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                self.media_manager.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::MediaManager, response);
+
+        let response = self.layout_window(GuiWindow::TraceSessions, "Trace Sessions")
+            .open(self.window_open_flags.get_mut(&GuiWindow::TraceSessions).unwrap())
+            .resizable(true)
+            .default_width(450.0)
+            .show(ctx, |ui| {
+                self.trace_sessions.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::TraceSessions, response);
+
+        let response = self.layout_window(GuiWindow::EventTimeline, "Event Timeline")
+            .open(self.window_open_flags.get_mut(&GuiWindow::EventTimeline).unwrap())
+            .resizable(true)
+            .default_width(640.0)
+            .show(ctx, |ui| {
+                self.event_timeline.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::EventTimeline, response);
+
+        let response = self.layout_window(GuiWindow::ValidatorStats, "Validator Statistics")
+            .open(self.window_open_flags.get_mut(&GuiWindow::ValidatorStats).unwrap())
             .resizable(false)
-            .default_width(300.0)
+            .default_width(350.0)
             .show(ctx, |ui| {
-                self.composite_adjust.draw(ui, &mut self.event_queue);
-            });     
+                self.validator_stats.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::ValidatorStats, response);
+
+        let response = self.layout_window(GuiWindow::Profiler, "Profiler")
+            .open(self.window_open_flags.get_mut(&GuiWindow::Profiler).unwrap())
+            .resizable(true)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                self.profiler_viewer.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::Profiler, response);
+
+        let response = self.layout_window(GuiWindow::HotkeyEditor, "Hotkeys")
+            .open(self.window_open_flags.get_mut(&GuiWindow::HotkeyEditor).unwrap())
+            .resizable(false)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                self.hotkey_editor.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::HotkeyEditor, response);
+
+        let response = self.layout_window(GuiWindow::HelpBrowser, "Help")
+            .open(self.window_open_flags.get_mut(&GuiWindow::HelpBrowser).unwrap())
+            .resizable(true)
+            .default_width(500.0)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                self.help_browser.draw(ui, &mut self.event_queue);
+            });
+        self.track_window(GuiWindow::HelpBrowser, response);
 
     }
 }
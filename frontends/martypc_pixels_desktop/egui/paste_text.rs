@@ -0,0 +1,96 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::paste_text.rs
+
+    Implements a window for typing text into the emulator. Text pasted from 
+    the host clipboard into the window is typed on the emulated keyboard when
+    the Type button is clicked.
+
+*/
+
+use crate::egui::*;
+
+pub struct PasteTextControl {
+    text: String,
+    remaining: usize,
+    status: String,
+}
+
+impl PasteTextControl {
+
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            remaining: 0,
+            status: String::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.label("Paste text below, then click Type to type it into the emulator.");
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .show(ui, |ui| {
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.text)
+                        .code_editor()
+                        .desired_rows(12)
+                        .desired_width(f32::INFINITY)
+                );
+            });
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.text.is_empty(), egui::Button::new("Type")).clicked() {
+                events.push_back(GuiEvent::PasteText(self.text.clone()));
+            }
+            if ui.add_enabled(self.remaining > 0, egui::Button::new("Stop")).clicked() {
+                events.push_back(GuiEvent::CancelPaste);
+            }
+            if ui.button("Clear").clicked() {
+                self.text.clear();
+            }
+            if self.remaining > 0 {
+                ui.label(format!("Typing... {} keys remaining", self.remaining));
+            }
+            else {
+                ui.label(&self.status);
+            }
+        });
+    }
+
+    /// Set the number of scancodes left to type.
+    pub fn set_remaining(&mut self, remaining: usize) {
+        self.remaining = remaining;
+    }
+
+    /// Set the status shown once typing has finished.
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_string();
+    }
+}
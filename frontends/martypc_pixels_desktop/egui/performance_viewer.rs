@@ -24,23 +24,39 @@
 
     ---------------------------------------------------------------------------
 
-    egui::memory.rs
+    egui::performance_viewer.rs
 
-    Implements a memory viewer control.
-    The control is a virtual window that can be scrolled over the entire 
-    address space. The virtual machine is polled for the contents of the 
-    active display as it is scrolled by sending GuiEvent::MemoryUpdate
-    events.
+    Implements the performance viewer control.
+    Alongside the current statistics, the viewer keeps a rolling history of 
+    frame, emulation, render and GUI times while it is open. The history is
+    plotted, and used to calculate 1% and 0.1% low FPS - the frame rate 
+    implied by the slowest 1% and 0.1% of frames - which show stutter that
+    an average FPS figure hides.
 
 */
 
-use std::collections::VecDeque;
+use std::{collections::VecDeque, time::Duration};
+
+use egui::plot::{Legend, Line, Plot, PlotPoints};
 
 use crate::egui::*;
 
+/// Number of frames of history to keep, 20 seconds at 60fps. This is enough for 0.1% lows.
+const HISTORY_LEN: usize = 1200;
+const PLOT_H: f32 = 140.0;
+
+#[derive(Copy, Clone, Default)]
+struct PerformanceSample {
+    frame_ms: f64,
+    emulation_ms: f64,
+    render_ms: f64,
+    gui_ms: f64,
+}
+
 pub struct PerformanceViewerControl {
     stats: PerformanceStats,
     video_data: VideoData,
+    history: VecDeque<PerformanceSample>,
 }
 
 
@@ -49,10 +65,38 @@ impl PerformanceViewerControl {
     pub fn new() -> Self {
         Self {
             stats: Default::default(),
-            video_data: Default::default()
+            video_data: Default::default(),
+            history: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    /// Return the FPS corresponding to the frame time at the specified percentile of slowest 
+    /// frames, eg, 1.0 for 1% low FPS. Returns None until there is enough history to contain
+    /// at least one such frame.
+    fn low_fps(&self, percent: f64) -> Option<f64> {
+        let mut frame_times: Vec<f64> = self.history.iter().map(|s| s.frame_ms).collect();
+        let slowest = (frame_times.len() as f64 * percent / 100.0).floor() as usize;
+        if slowest == 0 {
+            return None
+        }
+
+        frame_times.sort_by(|a, b| b.total_cmp(a));
+        let ms = frame_times[..slowest].iter().sum::<f64>() / slowest as f64;
+        match ms > 0.0 {
+            true => Some(1000.0 / ms),
+            false => None
         }
     }
 
+    fn plot_line(&self, name: &str, value: impl Fn(&PerformanceSample) -> f64) -> Line {
+        let points: PlotPoints = self.history
+            .iter()
+            .enumerate()
+            .map(|(i, s)| [i as f64, value(s)])
+            .collect();
+        Line::new(points).name(name)
+    }
+
     pub fn draw(&mut self, ui: &mut egui::Ui, _events: &mut VecDeque<GuiEvent> ) {
       
         egui::Grid::new("perf")
@@ -98,6 +142,12 @@ impl PerformanceViewerControl {
             ui.end_row();  
             ui.label("CPS: ");
             ui.label(egui::RichText::new(format!("{}", self.stats.current_cps)));
+            ui.end_row();
+            ui.label("Effective speed: ");
+            match self.stats.fast_forward {
+                true => ui.label(egui::RichText::new(format!("{:.2}x (fast-forward)", self.stats.speed_multiplier))),
+                false => ui.label(egui::RichText::new(format!("{:.2}x", self.stats.speed_multiplier)))
+            };
             ui.end_row();        
             ui.label("TPS: ");
             ui.label(egui::RichText::new(format!("{}", self.stats.current_tps)));
@@ -108,10 +158,54 @@ impl PerformanceViewerControl {
             ui.label("Render time: ");
             ui.label(egui::RichText::new(format!("{}", ((self.stats.render_time.as_micros() as f64) / 1000.0))));
             ui.end_row();
+            ui.label("Render thread time: ");
+            ui.label(egui::RichText::new(format!("{}", ((self.stats.worker_render_time.as_micros() as f64) / 1000.0))));
+            ui.end_row();
             ui.label("Gui Render time: ");
             ui.label(egui::RichText::new(format!("{}", ((self.stats.gui_time.as_micros() as f64) / 1000.0))));
-            ui.end_row();                        
+            ui.end_row();
+            ui.label("Guest idle: ");
+            match self.stats.guest_idle_frames {
+                0 => ui.label(egui::RichText::new("No")),
+                frames => ui.label(egui::RichText::new(format!("Yes ({} frames)", frames)))
+            };
+            ui.end_row();
+            ui.label("1% low FPS: ");
+            match self.low_fps(1.0) {
+                Some(fps) => ui.label(egui::RichText::new(format!("{:.1}", fps))),
+                None => ui.label(egui::RichText::new("-"))
+            };
+            ui.end_row();
+            ui.label("0.1% low FPS: ");
+            match self.low_fps(0.1) {
+                Some(fps) => ui.label(egui::RichText::new(format!("{:.1}", fps))),
+                None => ui.label(egui::RichText::new("-"))
+            };
+            ui.end_row();
         });          
+
+        ui.separator();
+
+        // Frame times in milliseconds over the history, newest on the right
+        Plot::new("perf_plot")
+            .height(PLOT_H)
+            .legend(Legend::default())
+            .include_x(HISTORY_LEN as f64)
+            .include_y(0.0)
+            .include_y(1000.0 / crate::FPS_TARGET)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(self.plot_line("Frame", |s| s.frame_ms));
+                plot_ui.line(self.plot_line("Emulation", |s| s.emulation_ms));
+                plot_ui.line(self.plot_line("Render", |s| s.render_ms));
+                plot_ui.line(self.plot_line("Gui", |s| s.gui_ms));
+            });
+
+        if ui.button("Reset history").clicked() {
+            self.history.clear();
+        }
     }
 
     pub fn update_video_data(&mut self, video_data: VideoData ) {
@@ -122,5 +216,21 @@ impl PerformanceViewerControl {
         let save_gui_time = self.stats.gui_time;
         self.stats = stats.clone();
         self.stats.gui_time = save_gui_time;
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        self.history.push_back(PerformanceSample {
+            frame_ms: ms(self.stats.frame_time),
+            emulation_ms: ms(self.stats.emulation_time),
+            render_ms: ms(self.stats.render_time),
+            gui_ms: ms(self.stats.gui_time),
+        });
+    }
+
+    /// Set the time taken to build the GUI in the last frame.
+    pub fn update_gui_time(&mut self, gui_time: Duration) {
+        self.stats.gui_time = gui_time;
     }
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::profiler_viewer.rs
+
+    Implements a viewer for the guest code profiler, listing the functions
+    and instruction addresses where the most cycles were spent.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::egui::*;
+
+use marty_core::profiler::{ProfileEntry, ProfileReport};
+
+pub const PROFILER_REPORT_LEN: usize = 20;
+
+pub struct ProfilerViewer {
+    recording: bool,
+    report: ProfileReport,
+}
+
+impl ProfilerViewer {
+
+    pub fn new() -> Self {
+        Self {
+            recording: false,
+            report: Default::default(),
+        }
+    }
+
+    fn draw_entries(ui: &mut egui::Ui, id: &str, entries: &[ProfileEntry], total_cycles: u64) {
+        if entries.is_empty() {
+            ui.label("None");
+            return;
+        }
+        egui::Grid::new(id)
+            .striped(true)
+            .num_columns(4)
+            .show(ui, |ui| {
+                ui.label("Address");
+                ui.label("Instructions");
+                ui.label("Cycles");
+                ui.label("%");
+                ui.end_row();
+
+                for entry in entries {
+                    let percent = entry.cycles as f64 * 100.0 / total_cycles.max(1) as f64;
+                    ui.label(egui::RichText::new(format!("{:05X}", entry.address)).monospace());
+                    ui.label(egui::RichText::new(format!("{}", entry.instructions)).monospace());
+                    ui.label(egui::RichText::new(format!("{}", entry.cycles)).monospace());
+                    ui.label(egui::RichText::new(format!("{:.2}", percent)).monospace());
+                    ui.end_row();
+                }
+            });
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            if ui.checkbox(&mut self.recording, "Record").changed() {
+                events.push_back(GuiEvent::SetProfiler(self.recording));
+            }
+            if ui.button("Reset").clicked() {
+                events.push_back(GuiEvent::ClearProfiler);
+            }
+            if ui.button("Export Flamegraph").clicked() {
+                events.push_back(GuiEvent::ExportProfile);
+            }
+        });
+        ui.label(format!(
+            "{} instructions, {} cycles recorded.",
+            self.report.instructions,
+            self.report.cycles
+        ));
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                ui.label("Hottest functions (cycles spent outside of callees):");
+                Self::draw_entries(ui, "profiler_functions", &self.report.functions, self.report.cycles);
+                ui.separator();
+                ui.label("Hottest instructions:");
+                Self::draw_entries(ui, "profiler_addresses", &self.report.addresses, self.report.cycles);
+            });
+    }
+
+    pub fn update(&mut self, report: ProfileReport) {
+        self.report = report;
+    }
+}
@@ -0,0 +1,82 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::rate_meter.rs
+
+    Measures the rate at which a set of ever-increasing device counters 
+    advance, for display in debugger windows.
+
+*/
+
+use std::time::{Duration, Instant};
+
+/// Interval over which rates are measured.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct RateMeter<const N: usize> {
+    start: Instant,
+    counts: [u64; N],
+    rates: [u64; N],
+}
+
+impl<const N: usize> RateMeter<N> {
+
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            counts: [0; N],
+            rates: [0; N],
+        }
+    }
+
+    /// Update the meter with the current counter values. Rates are recalculated once 
+    /// per measurement interval.
+    pub fn update(&mut self, counts: &[u64]) {
+
+        // Counters are cleared when their device is reset, so restart the measurement 
+        // if any of them went backwards.
+        let reset = counts.iter().zip(self.counts.iter()).any(|(now, then)| now < then);
+        let elapsed = self.start.elapsed();
+
+        if reset || elapsed >= RATE_INTERVAL {
+            for (i, count) in counts.iter().enumerate().take(N) {
+                self.rates[i] = if reset {
+                    0
+                }
+                else {
+                    ((count - self.counts[i]) as f64 / elapsed.as_secs_f64()) as u64
+                };
+                self.counts[i] = *count;
+            }
+            self.start = Instant::now();
+        }
+    }
+
+    /// Return the per-second rate of the specified counter.
+    pub fn rate(&self, i: usize) -> u64 {
+        self.rates.get(i).copied().unwrap_or(0)
+    }
+}
@@ -0,0 +1,116 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::script_console.rs
+
+    Implements a console for running automation scripts. The script source 
+    is edited in the console and run with the Run button; output from the 
+    script is shown below it.
+
+*/
+
+use crate::egui::*;
+use crate::egui::help::help_button;
+
+const MAX_OUTPUT_LINES: usize = 1000;
+
+pub struct ScriptConsole {
+    source: String,
+    output: Vec<String>,
+    running: bool,
+    status: String,
+}
+
+impl ScriptConsole {
+
+    pub fn new() -> Self {
+        Self {
+            source: String::new(),
+            output: Vec::new(),
+            running: false,
+            status: "Not running".to_string(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.running, egui::Button::new("Run")).clicked() {
+                self.output.clear();
+                events.push_back(GuiEvent::RunScript(self.source.clone()));
+            }
+            if ui.add_enabled(self.running, egui::Button::new("Stop")).clicked() {
+                events.push_back(GuiEvent::StopScript);
+            }
+            if ui.button("Clear").clicked() {
+                self.output.clear();
+            }
+            ui.label(&self.status);
+            help_button(ui, HelpTopic::Scripting, events);
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .id_source("script_source")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.source)
+                        .code_editor()
+                        .desired_rows(10)
+                        .desired_width(f32::INFINITY)
+                );
+            });
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .id_source("script_output")
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for line in &self.output {
+                    ui.monospace(line);
+                }
+            });
+    }
+
+    pub fn set_source(&mut self, source: String) {
+        self.source = source;
+    }
+
+    pub fn set_running(&mut self, running: bool, status: &str) {
+        self.running = running;
+        self.status = status.to_string();
+    }
+
+    pub fn add_output(&mut self, lines: Vec<String>) {
+        self.output.extend(lines);
+        if self.output.len() > MAX_OUTPUT_LINES {
+            let excess = self.output.len() - MAX_OUTPUT_LINES;
+            self.output.drain(..excess);
+        }
+    }
+}
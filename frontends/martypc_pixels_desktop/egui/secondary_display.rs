@@ -0,0 +1,87 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::secondary_display.rs
+
+    Displays the output of a secondary video card, such as an MDA installed
+    alongside a CGA.
+
+*/
+
+use crate::egui::*;
+
+pub struct SecondaryDisplayViewer {
+    frame: Option<(u32, u32, Vec<u8>)>,
+    texture: Option<egui::TextureHandle>,
+    texture_dirty: bool,
+}
+
+impl SecondaryDisplayViewer {
+
+    pub fn new() -> Self {
+        Self {
+            frame: None,
+            texture: None,
+            texture_dirty: false,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, ctx: &Context) {
+
+        let (w, h, rgba) = match &self.frame {
+            Some(frame) => frame,
+            None => {
+                ui.label("No secondary video card is installed.");
+                return
+            }
+        };
+
+        if self.texture_dirty {
+            let image = ColorImage::from_rgba_unmultiplied([*w as usize, *h as usize], rgba);
+            match &mut self.texture {
+                Some(texture) => texture.set(image, Default::default()),
+                None => self.texture = Some(ctx.load_texture("secondary_display", image, Default::default())),
+            }
+            self.texture_dirty = false;
+        }
+
+        if let Some(texture) = &self.texture {
+            // Scale the image to the width of the window, keeping a 4:3 aspect ratio.
+            let width = ui.available_width();
+            ui.image(texture, egui::vec2(width, width * 3.0 / 4.0));
+        }
+    }
+
+    /// Update the displayed frame. The buffer is reused between frames.
+    pub fn frame_mut(&mut self, w: u32, h: u32) -> &mut [u8] {
+        let frame = self.frame.get_or_insert_with(|| (0, 0, Vec::new()));
+        if (frame.0, frame.1) != (w, h) {
+            *frame = (w, h, vec![0; (w * h * 4) as usize]);
+        }
+        self.texture_dirty = true;
+        &mut frame.2
+    }
+}
@@ -0,0 +1,135 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::status_bar.rs
+
+    Implements the status bar at the bottom of the main window, showing the
+    detected guest OS, the emulation speed, and an activity LED for each 
+    disk drive. A floppy LED glows dimly while its motor runs and lights 
+    fully while the drive is read, written or stepped; a hard disk LED 
+    lights while the disk is accessed.
+
+*/
+
+use std::time::{Duration, Instant};
+
+use crate::egui::*;
+
+use marty_core::disk_activity::{DiskActivity, DriveActivity};
+
+// Keep an LED lit for a while after activity, so that short accesses are visible.
+const LED_HOLD_TIME: Duration = Duration::from_millis(150);
+const LED_RADIUS: f32 = 4.5;
+
+const FLOPPY_LED_ON: egui::Color32 = egui::Color32::from_rgb(0x30, 0xF0, 0x40);
+const FLOPPY_LED_MOTOR: egui::Color32 = egui::Color32::from_rgb(0x20, 0x70, 0x28);
+const HDD_LED_ON: egui::Color32 = egui::Color32::from_rgb(0xFF, 0x50, 0x30);
+const LED_OFF: egui::Color32 = egui::Color32::from_rgb(0x38, 0x38, 0x38);
+
+const FLOPPY_DRIVE_NAMES: [&str; 2] = ["A:", "B:"];
+const HDD_DRIVE_NAMES: [&str; 2] = ["C:", "D:"];
+
+#[derive(Default)]
+struct DriveLed {
+    motor_on: bool,
+    last_busy: Option<Instant>,
+}
+
+impl DriveLed {
+    fn update(&mut self, activity: &DriveActivity, now: Instant) {
+        self.motor_on = activity.motor_on;
+        if activity.busy() {
+            self.last_busy = Some(now);
+        }
+    }
+
+    fn lit(&self, now: Instant) -> bool {
+        self.last_busy.map_or(false, |t| now.duration_since(t) < LED_HOLD_TIME)
+    }
+}
+
+#[derive(Default)]
+pub struct StatusBar {
+    floppy: Vec<DriveLed>,
+    hard_disk: Vec<DriveLed>,
+}
+
+impl StatusBar {
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Update the drive LEDs with a frame's worth of disk activity.
+    pub fn update_activity(&mut self, activity: &DiskActivity) {
+        let now = Instant::now();
+        for (leds, list) in [(&mut self.floppy, &activity.floppy), (&mut self.hard_disk, &activity.hard_disk)] {
+            leds.resize_with(list.len(), DriveLed::default);
+            for (led, drive) in leds.iter_mut().zip(list) {
+                led.update(drive, now);
+            }
+        }
+    }
+
+    fn draw_led(ui: &mut egui::Ui, name: &str, color: egui::Color32, hover: &str) {
+        ui.label(name);
+        let size = egui::vec2(LED_RADIUS * 2.0, LED_RADIUS * 2.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+        ui.painter().circle_filled(rect.center(), LED_RADIUS, color);
+        response.on_hover_text(hover);
+    }
+
+    pub fn draw(&self, ui: &mut egui::Ui, guest_os: &str, speed: f64) {
+        let now = Instant::now();
+        ui.horizontal(|ui| {
+            ui.label(format!("Guest OS: {}", guest_os));
+            ui.separator();
+            ui.label(format!("Speed: {:.2}x", speed));
+
+            if self.floppy.is_empty() && self.hard_disk.is_empty() {
+                return
+            }
+            ui.separator();
+
+            for (led, name) in self.floppy.iter().zip(FLOPPY_DRIVE_NAMES) {
+                let (color, hover) = if led.lit(now) {
+                    (FLOPPY_LED_ON, "Drive active")
+                }
+                else if led.motor_on {
+                    (FLOPPY_LED_MOTOR, "Motor on")
+                }
+                else {
+                    (LED_OFF, "Idle")
+                };
+                Self::draw_led(ui, name, color, hover);
+            }
+            for (led, name) in self.hard_disk.iter().zip(HDD_DRIVE_NAMES) {
+                let (color, hover) = if led.lit(now) { (HDD_LED_ON, "Drive active") } else { (LED_OFF, "Idle") };
+                Self::draw_led(ui, name, color, hover);
+            }
+        });
+    }
+}
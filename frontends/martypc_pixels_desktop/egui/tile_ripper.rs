@@ -0,0 +1,209 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::tile_ripper.rs
+
+    Implements a tile and sprite ripper. A region of conventional memory or 
+    video memory is decoded as a sheet of tiles of a chosen size and format, 
+    using the palette of the active video card, and the sheet or a selected 
+    tile can be exported as PNG.
+
+*/
+
+use crate::egui::*;
+use marty_render::{TileFormat, TileLayout, TileSheet, TILE_MAX_DIM, TILE_MAX_COUNT};
+
+const TILE_SHEET_SCALE: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileRipSource {
+    Memory,
+    VideoMemory,
+}
+
+pub struct TileRipperControl {
+    source: TileRipSource,
+    address: String,
+    skip: usize,
+    layout: TileLayout,
+    sheet: TileSheet,
+    texture: Option<egui::TextureHandle>,
+    texture_dirty: bool,
+    selected: Option<u32>,
+}
+
+impl TileRipperControl {
+
+    pub fn new() -> Self {
+        Self {
+            source: TileRipSource::Memory,
+            address: format!("{:05X}", 0xB8000),
+            skip: 0,
+            layout: Default::default(),
+            sheet: Default::default(),
+            texture: None,
+            texture_dirty: false,
+            selected: None,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, ctx: &Context, events: &mut VecDeque<GuiEvent> ) {
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.source, TileRipSource::Memory, "Memory");
+            ui.radio_value(&mut self.source, TileRipSource::VideoMemory, "Video Memory");
+        });
+
+        egui::Grid::new("tile_ripper_params")
+            .num_columns(2)
+            .show(ui, |ui| {
+
+                if let TileRipSource::Memory = self.source {
+                    ui.label("Address:");
+                    ui.text_edit_singleline(&mut self.address);
+                    ui.end_row();
+                }
+
+                ui.label("Offset:");
+                ui.horizontal(|ui| {
+                    let page = self.layout.tile_bytes() * self.layout.count as usize;
+                    if ui.button("<<").clicked() {
+                        self.skip = self.skip.saturating_sub(page);
+                    }
+                    if ui.button("<").clicked() {
+                        self.skip = self.skip.saturating_sub(1);
+                    }
+                    ui.add(egui::DragValue::new(&mut self.skip).hexadecimal(5, false, true));
+                    if ui.button(">").clicked() {
+                        self.skip += 1;
+                    }
+                    if ui.button(">>").clicked() {
+                        self.skip += page;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Format:");
+                egui::ComboBox::from_id_source("tile_format")
+                    .selected_text(self.layout.format.desc())
+                    .show_ui(ui, |ui| {
+                        for format in TileFormat::ALL {
+                            ui.selectable_value(&mut self.layout.format, format, format.desc());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Tile size:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.layout.width).clamp_range(1..=TILE_MAX_DIM));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut self.layout.height).clamp_range(1..=TILE_MAX_DIM));
+                });
+                ui.end_row();
+
+                ui.label("Tiles:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut self.layout.count).clamp_range(1..=TILE_MAX_COUNT));
+                    ui.label("Per row:");
+                    ui.add(egui::DragValue::new(&mut self.layout.columns).clamp_range(1..=64));
+                });
+                ui.end_row();
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Export Sheet").clicked() {
+                events.push_back(GuiEvent::SaveTiles(None));
+            }
+            ui.add_enabled_ui(self.selected.is_some(), |ui| {
+                if ui.button("Export Selected Tile").clicked() {
+                    events.push_back(GuiEvent::SaveTiles(self.selected));
+                }
+            });
+            if let Some(tile) = self.selected {
+                let tile_offset = self.skip + tile as usize * self.layout.tile_bytes();
+                ui.label(format!("Tile {} at +{:05X}", tile, tile_offset));
+            }
+        });
+        ui.separator();
+
+        if self.texture_dirty {
+            let size = [self.sheet.w as usize, self.sheet.h as usize];
+            let image = ColorImage::from_rgba_unmultiplied(size, &self.sheet.rgba);
+            self.texture = Some(ctx.load_texture("tile_ripper_sheet", image, Default::default()));
+            self.texture_dirty = false;
+        }
+
+        if let Some(texture) = &self.texture {
+            egui::ScrollArea::both()
+                .auto_shrink([false; 2])
+                .show(ui, |ui| {
+                    let response = ui.add(
+                        egui::Image::new(texture, texture.size_vec2() * TILE_SHEET_SCALE)
+                            .sense(egui::Sense::click())
+                    );
+
+                    if response.clicked() {
+                        if let Some(pos) = response.interact_pointer_pos() {
+                            let local = (pos - response.rect.min) / TILE_SHEET_SCALE;
+                            self.selected = self.sheet.tile_at(local.x as u32, local.y as u32);
+                        }
+                    }
+                });
+        }
+    }
+
+    pub fn source(&self) -> TileRipSource {
+        self.source
+    }
+
+    pub fn get_address(&self) -> String {
+        self.address.clone()
+    }
+
+    pub fn skip(&self) -> usize {
+        self.skip
+    }
+
+    pub fn layout(&self) -> TileLayout {
+        self.layout
+    }
+
+    pub fn set_sheet(&mut self, sheet: TileSheet) {
+        if sheet != self.sheet {
+            if let (Some(selected), Some(layout)) = (self.selected, sheet.layout) {
+                if selected >= layout.count {
+                    self.selected = None;
+                }
+            }
+            self.sheet = sheet;
+            self.texture_dirty = true;
+        }
+    }
+
+    pub fn sheet(&self) -> &TileSheet {
+        &self.sheet
+    }
+}
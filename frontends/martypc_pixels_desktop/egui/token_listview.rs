@@ -36,6 +36,7 @@
 
 */
 use std::mem::discriminant;
+use std::ops::Range;
 
 use egui::*;
 use crate::egui::*;
@@ -44,6 +45,19 @@ use crate::egui::constants::*;
 use marty_core::syntax_token::*;
 
 
+const ARROW_LANE_W: f32 = 6.0;
+const ARROW_MAX_LANES: usize = 6;
+const ARROW_COLOR: Color32 = Color32::from_rgb(228, 214, 116);
+
+/// An arrow drawn in the left gutter from one row to another, such as from a jump to its
+/// target. If the target is not visible, the arrow points off the top or bottom of the view.
+#[derive(Copy, Clone, Debug)]
+pub struct RowArrow {
+    pub from: usize,
+    pub to: Option<usize>,
+    pub down: bool,
+}
+
 pub struct TokenListView {
 
     pub row: usize,
@@ -57,6 +71,10 @@ pub struct TokenListView {
     pub t_margin: f32,
 
     hover_text: String,
+    highlights: Vec<(Range<u32>, Color32)>,
+    clicked: Option<(usize, bool)>,
+    arrows: Vec<RowArrow>,
+    highlight_row: Option<usize>,
 }
 
 impl TokenListView {
@@ -73,7 +91,11 @@ impl TokenListView {
             l_margin: 5.0,
             t_margin: 3.0,
 
-            hover_text: String::new()
+            hover_text: String::new(),
+            highlights: Vec::new(),
+            clicked: None,
+            arrows: Vec::new(),
+            highlight_row: None,
         }
     }
 
@@ -159,6 +181,31 @@ impl TokenListView {
         self.hover_text = text;
     }
 
+    /// Set background colors for memory byte tokens within the specified address ranges.
+    pub fn set_highlights(&mut self, highlights: Vec<(Range<u32>, Color32)>) {
+        self.highlights = highlights;
+    }
+
+    /// Return the address of the memory byte token clicked since the last call, and whether 
+    /// it was the ASCII representation of the byte that was clicked.
+    pub fn take_clicked(&mut self) -> Option<(usize, bool)> {
+        self.clicked.take()
+    }
+
+    /// Set the arrows to draw between rows of the current contents.
+    pub fn set_arrows(&mut self, arrows: Vec<RowArrow>) {
+        self.arrows = arrows;
+    }
+
+    /// Set a row of the current contents to draw highlighted.
+    pub fn set_highlight_row(&mut self, row: Option<usize>) {
+        self.highlight_row = row;
+    }
+
+    fn highlight(&self, addr: u32) -> Option<Color32> {
+        self.highlights.iter().find(|(range, _)| range.contains(&addr)).map(|(_, color)| *color)
+    }
+
     pub fn measure_token(&self, ui: &mut Ui, token: &SyntaxToken, fontid: FontId ) -> Rect {
 
         let old_clip_rect = ui.clip_rect();
@@ -246,8 +293,26 @@ impl TokenListView {
                 let plus = "+".to_string();
                 let null = "[missing token!]".to_string();
 
+                let lanes = usize::min(self.arrows.len(), ARROW_MAX_LANES);
+                let gutter = match lanes {
+                    0 => 0.0,
+                    _ => lanes as f32 * ARROW_LANE_W + ARROW_LANE_W,
+                };
+
+                if let Some(row) = self.highlight_row.filter(|row| *row < show_rows) {
+                    let y = start_y + (row as f32 * row_height) + self.t_margin;
+                    ui.painter().rect_filled(
+                        Rect::from_min_max(
+                            egui::pos2(ui.min_rect().left(), y - 1.0),
+                            egui::pos2(ui.clip_rect().right(), y + row_height - 1.0)
+                        ),
+                        egui::Rounding::none(),
+                        Color32::from_rgb(40, 40, 90)
+                    );
+                }
+
                 for (i, row) in self.contents[0..show_rows].iter().enumerate() {
-                    let x = ui.min_rect().left() + self.l_margin;
+                    let x = ui.min_rect().left() + self.l_margin + gutter;
                     let y = start_y + ((i as f32) * row_height) + self.t_margin;
 
                     let mut token_x = x;
@@ -259,7 +324,7 @@ impl TokenListView {
 
                         let drawn;
                         match token {
-                            SyntaxToken::MemoryAddressFlat(_addr, s) => {
+                            SyntaxToken::MemoryAddressFlat(addr, s) => {
                                 text_rect = ui.painter().text(
                                     egui::pos2(token_x, y),
                                     egui::Align2::LEFT_TOP,
@@ -267,28 +332,41 @@ impl TokenListView {
                                     font_id.clone(),
                                     Color32::LIGHT_GRAY,
                                 );
+                                if ui.interact(text_rect, ui.id().with(("address", *addr)), egui::Sense::click()).clicked() {
+                                    self.clicked = Some((*addr as usize, false));
+                                }
                                 token_x = text_rect.max.x + 10.0;
                                 used_rect = used_rect.union(text_rect);
                                 drawn = true;
                             }
                             SyntaxToken::MemoryByteHexValue(addr, _, s, cursor, age) => {
 
-                                if ui.put(
-                                    Rect {
-                                        min: egui::pos2(token_x, y), 
-                                        max: egui::pos2(token_x + label_rect.max.x + 1.0, y + label_rect.max.y)
-                                    },
+                                let token_rect = Rect {
+                                    min: egui::pos2(token_x, y), 
+                                    max: egui::pos2(token_x + label_rect.max.x + 1.0, y + label_rect.max.y)
+                                };
+                                if let Some(color) = self.highlight(*addr) {
+                                    ui.painter().rect_filled(token_rect.expand(2.0), egui::Rounding::none(), color);
+                                }
+
+                                let response = ui.put(
+                                    token_rect,
                                     egui::Label::new(
                                         egui::RichText::new(s)
                                             .text_style(egui::TextStyle::Monospace)
                                             .color(fade_c32(Color32::GRAY, Color32::from_rgb(0, 255, 255), 255-*age))
                                         )
+                                        .sense(egui::Sense::click())
                                 )
-                                .on_hover_text(format!("{}", self.hover_text))
-                                .hovered() {
+                                .on_hover_text(format!("{}", self.hover_text));
+
+                                if response.hovered() {
                                     column_select = j;
                                     events.push_back(GuiEvent::TokenHover(*addr as usize));
                                 }
+                                if response.clicked() {
+                                    self.clicked = Some((*addr as usize, false));
+                                }
 
                                 if *cursor {
                                     ui.painter().rect(
@@ -316,7 +394,9 @@ impl TokenListView {
                                 used_rect = used_rect.union(text_rect);
                                 */
                             }
-                            SyntaxToken::MemoryByteAsciiValue(_addr, _, s, age) => {
+                            SyntaxToken::MemoryByteAsciiValue(addr, _, s, age) => {
+                                // Reserve a shape under the text for the highlight, as the size of the text isn't known yet.
+                                let highlight_shape = ui.painter().add(egui::Shape::Noop);
                                 text_rect = ui.painter().text(
                                     egui::pos2(token_x, y),
                                     egui::Align2::LEFT_TOP,
@@ -324,6 +404,12 @@ impl TokenListView {
                                     font_id.clone(),
                                     fade_c32(Color32::LIGHT_GRAY, Color32::from_rgb(0, 255, 255), 255-*age),
                                 );
+                                if let Some(color) = self.highlight(*addr) {
+                                    ui.painter().set(highlight_shape, egui::Shape::rect_filled(text_rect, egui::Rounding::none(), color));
+                                }
+                                if ui.interact(text_rect, ui.id().with(("ascii", *addr)), egui::Sense::click()).clicked() {
+                                    self.clicked = Some((*addr as usize, true));
+                                }
 
                                 // If previous hex byte was hovered, show a rectangle around this ascii byte
                                 // TODO: Rather than rely on hex bytes directly preceding the ascii bytes, 
@@ -417,6 +503,44 @@ impl TokenListView {
                     }
                 }
 
+                if lanes > 0 {
+                    let text_left = ui.min_rect().left() + self.l_margin + gutter - 2.0;
+                    let row_center = |row: usize| start_y + (row as f32 * row_height) + self.t_margin + (row_height - ui.spacing().item_spacing.y) / 2.0;
+                    let top = start_y + self.t_margin;
+                    let bottom = start_y + (show_rows as f32 * row_height);
+                    let stroke = egui::Stroke::new(1.0, ARROW_COLOR);
+
+                    // Give the shortest arrows the innermost lanes, so that arrows nest.
+                    let mut arrows: Vec<&RowArrow> = self.arrows.iter().filter(|a| a.from < show_rows).collect();
+                    arrows.sort_by_key(|a| match a.to {
+                        Some(to) => to.abs_diff(a.from),
+                        None => usize::MAX,
+                    });
+
+                    for (i, arrow) in arrows.iter().enumerate() {
+                        let lane_x = text_left - ((i % ARROW_MAX_LANES) as f32 + 1.0) * ARROW_LANE_W;
+                        let from_y = row_center(arrow.from);
+                        let to_y = match arrow.to {
+                            Some(to) if to < show_rows => row_center(to),
+                            _ if arrow.down => bottom,
+                            _ => top,
+                        };
+
+                        ui.painter().line_segment([egui::pos2(text_left, from_y), egui::pos2(lane_x, from_y)], stroke);
+                        ui.painter().line_segment([egui::pos2(lane_x, from_y), egui::pos2(lane_x, to_y)], stroke);
+                        match arrow.to {
+                            Some(to) if to < show_rows => {
+                                ui.painter().line_segment([egui::pos2(lane_x, to_y), egui::pos2(text_left, to_y)], stroke);
+                                ui.painter().arrow(egui::pos2(text_left - 4.0, to_y), egui::vec2(4.0, 0.0), stroke);
+                            }
+                            _ => {
+                                let dir = if arrow.down { 4.0 } else { -4.0 };
+                                ui.painter().arrow(egui::pos2(lane_x, to_y - dir), egui::vec2(0.0, dir), stroke);
+                            }
+                        }
+                    }
+                }
+
                 //egui::TextEdit::multiline(&mut format!("hi!"))
                 //    .font(egui::TextStyle::Monospace);
 
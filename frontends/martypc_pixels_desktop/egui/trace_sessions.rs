@@ -0,0 +1,197 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::trace_sessions.rs
+
+    Implements a window for managing trace sessions. New sessions are 
+    started with a name, the kind of trace and an optional filter; each
+    running session can be paused, resumed, stopped and removed.
+
+*/
+
+use crate::egui::*;
+use crate::egui::help::help_button;
+
+use marty_core::trace_session::{TraceFilter, TraceKind, TraceSession, TraceSessionId, TraceSessionState};
+
+/// A snapshot of a trace session for display.
+struct TraceSessionRow {
+    id: TraceSessionId,
+    name: String,
+    kind: TraceKind,
+    filter: String,
+    state: TraceSessionState,
+    lines: u64,
+    path: String,
+}
+
+pub struct TraceSessionControl {
+    name: String,
+    kind: TraceKind,
+    segment: String,
+    ports: String,
+    error: Option<String>,
+    rows: Vec<TraceSessionRow>,
+}
+
+impl TraceSessionControl {
+
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            kind: TraceKind::Instruction,
+            segment: String::new(),
+            ports: String::new(),
+            error: None,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        egui::Grid::new("trace_session_new")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Name: ");
+                ui.text_edit_singleline(&mut self.name);
+                ui.end_row();
+
+                ui.label("Trace: ");
+                egui::ComboBox::from_id_source("trace_session_kind")
+                    .selected_text(self.kind.to_string())
+                    .show_ui(ui, |ui| {
+                        for kind in TraceKind::ALL {
+                            ui.selectable_value(&mut self.kind, kind, kind.to_string());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Segment: ");
+                ui.add(egui::TextEdit::singleline(&mut self.segment).hint_text("Any"));
+                ui.end_row();
+
+                if self.kind == TraceKind::Io {
+                    ui.label("Ports: ");
+                    ui.add(egui::TextEdit::singleline(&mut self.ports).hint_text("Any, eg. 3F0-3F7"));
+                    ui.end_row();
+                }
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Start").clicked() {
+                let ports = if self.kind == TraceKind::Io { self.ports.as_str() } else { "" };
+                match TraceFilter::parse(&self.segment, ports) {
+                    Ok(filter) => {
+                        self.error = None;
+                        events.push_back(GuiEvent::StartTraceSession(self.session_name(), self.kind, filter));
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            help_button(ui, HelpTopic::Debugger, events);
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        ui.separator();
+
+        if self.rows.is_empty() {
+            ui.label("No trace sessions.");
+            return;
+        }
+
+        egui::Grid::new("trace_session_list")
+            .striped(true)
+            .num_columns(6)
+            .show(ui, |ui| {
+                ui.label("Name");
+                ui.label("Trace");
+                ui.label("Filter");
+                ui.label("State");
+                ui.label("Lines");
+                ui.label("");
+                ui.end_row();
+
+                for row in &self.rows {
+                    ui.label(&row.name).on_hover_text(&row.path);
+                    ui.label(row.kind.to_string());
+                    ui.label(&row.filter);
+                    ui.label(row.state.to_string());
+                    ui.label(egui::RichText::new(format!("{}", row.lines)).monospace());
+                    ui.horizontal(|ui| {
+                        match row.state {
+                            TraceSessionState::Running => {
+                                if ui.button("⏸").on_hover_text("Pause").clicked() {
+                                    events.push_back(GuiEvent::PauseTraceSession(row.id, true));
+                                }
+                            }
+                            TraceSessionState::Paused => {
+                                if ui.button("▶").on_hover_text("Resume").clicked() {
+                                    events.push_back(GuiEvent::PauseTraceSession(row.id, false));
+                                }
+                            }
+                            TraceSessionState::Stopped => {}
+                        }
+                        if row.state != TraceSessionState::Stopped {
+                            if ui.button("⏹").on_hover_text("Stop").clicked() {
+                                events.push_back(GuiEvent::StopTraceSession(row.id));
+                            }
+                        }
+                        else if ui.button("🗙").on_hover_text("Remove").clicked() {
+                            events.push_back(GuiEvent::RemoveTraceSession(row.id));
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Return the session name entered, made safe for use as a filename, or a name based on 
+    /// the kind of trace if none was entered.
+    fn session_name(&self) -> String {
+        let name: String = self.name.trim()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+
+        match name.is_empty() {
+            true => format!("{}_trace", self.kind.to_string().to_lowercase()),
+            false => name
+        }
+    }
+
+    pub fn update(&mut self, sessions: &[TraceSession]) {
+        self.rows = sessions.iter().map(|session| TraceSessionRow {
+            id: session.id(),
+            name: session.name().to_string(),
+            kind: session.kind(),
+            filter: session.filter().to_string(),
+            state: session.state(),
+            lines: session.lines(),
+            path: session.path().map(|p| p.display().to_string()).unwrap_or_default(),
+        }).collect();
+    }
+}
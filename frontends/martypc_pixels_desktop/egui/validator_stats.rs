@@ -0,0 +1,131 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    ---------------------------------------------------------------------------
+
+    egui::validator_stats.rs
+
+    Implements a viewer for CPU validator statistics, summarizing the
+    instructions validated, mismatches by kind and the opcodes responsible 
+    for the most mismatches.
+
+*/
+
+use std::collections::VecDeque;
+
+use crate::egui::*;
+
+use marty_core::cpu_validator::{ValidatorStats, MISMATCH_KINDS};
+
+const TOP_OPCODE_COUNT: usize = 10;
+
+pub struct ValidatorStatsViewer {
+    stats: Option<ValidatorStats>,
+}
+
+impl ValidatorStatsViewer {
+
+    pub fn new() -> Self {
+        Self {
+            stats: None
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, events: &mut VecDeque<GuiEvent> ) {
+
+        let stats = match &self.stats {
+            Some(stats) => stats,
+            None => {
+                ui.label("No CPU validator is configured.");
+                return;
+            }
+        };
+
+        egui::Grid::new("validator_stats")
+            .striped(true)
+            .min_col_width(100.0)
+            .show(ui, |ui| {
+
+                ui.label("Instructions validated: ");
+                ui.label(egui::RichText::new(format!("{}", stats.instructions)).monospace());
+                ui.end_row();
+
+                for kind in MISMATCH_KINDS {
+                    ui.label(format!("{} mismatches: ", kind.name()));
+                    ui.label(egui::RichText::new(format!("{}", stats.mismatch_count(kind))).monospace());
+                    ui.end_row();
+                }
+
+                ui.label("Time in validator: ");
+                ui.label(egui::RichText::new(format!("{:.3}s", stats.elapsed.as_secs_f64())).monospace());
+                ui.end_row();
+
+                ui.label("Instructions/sec: ");
+                ui.label(egui::RichText::new(format!("{:.0}", stats.instructions_per_sec())).monospace());
+                ui.end_row();
+            });
+
+        ui.separator();
+        ui.label("Top offending opcodes:");
+
+        let top = stats.top_opcodes(TOP_OPCODE_COUNT);
+        if top.is_empty() {
+            ui.label("None");
+        }
+        else {
+            egui::Grid::new("validator_top_opcodes")
+                .striped(true)
+                .num_columns(4)
+                .show(ui, |ui| {
+                    ui.label("Opcode");
+                    ui.label("Mnemonic");
+                    ui.label("Validated");
+                    ui.label("Mismatches");
+                    ui.end_row();
+
+                    for (opcode, op_stats) in top {
+                        ui.label(egui::RichText::new(format!("{:02X}", opcode)).monospace());
+                        ui.label(egui::RichText::new(&op_stats.mnemonic).monospace());
+                        ui.label(egui::RichText::new(format!("{}", op_stats.validated)).monospace());
+                        ui.label(egui::RichText::new(format!("{}", op_stats.mismatches)).monospace());
+                        ui.end_row();
+                    }
+                });
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Export CSV").clicked() {
+                events.push_back(GuiEvent::ExportValidatorStats);
+            }
+            if ui.button("Reset").clicked() {
+                events.push_back(GuiEvent::ResetValidatorStats);
+            }
+        });
+    }
+
+    pub fn update(&mut self, stats: Option<&ValidatorStats>) {
+        self.stats = stats.cloned();
+    }
+}
@@ -0,0 +1,157 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    -------------------------------------------------------------------------
+
+    egui::vram_viewer.rs
+
+    Implements a viewer for video memory. Text pages, CGA scanline banks,
+    EGA and VGA bitplanes and font glyphs are drawn as images, and hovering
+    over an image shows the memory it was drawn from.
+
+*/
+
+use crate::egui::*;
+use marty_render::{VramImage, VramView};
+
+const VRAM_SCALE_MIN: f32 = 0.5;
+const VRAM_SCALE_MAX: f32 = 4.0;
+
+pub struct VramViewerControl {
+    view: VramView,
+    plane_row_bytes: u32,
+    cga_hires: bool,
+    scale: f32,
+    images: Vec<VramImage>,
+    textures: Vec<egui::TextureHandle>,
+    textures_dirty: bool,
+}
+
+impl VramViewerControl {
+
+    pub fn new() -> Self {
+        Self {
+            view: VramView::TextPages,
+            plane_row_bytes: 80,
+            cga_hires: false,
+            scale: 1.0,
+            images: Vec::new(),
+            textures: Vec::new(),
+            textures_dirty: false,
+        }
+    }
+
+    /// Return whether the specified view applies to a video card of the specified type.
+    pub fn view_available(view: VramView, video_type: VideoType) -> bool {
+        match view {
+            VramView::TextPages | VramView::Fonts => true,
+            VramView::CgaBanks => video_type == VideoType::CGA,
+            VramView::Planes => matches!(video_type, VideoType::EGA | VideoType::VGA),
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui, ctx: &Context, video_type: VideoType) {
+
+        if !Self::view_available(self.view, video_type) {
+            self.view = VramView::TextPages;
+        }
+
+        ui.horizontal(|ui| {
+            for view in VramView::ALL {
+                if Self::view_available(view, video_type) {
+                    ui.radio_value(&mut self.view, view, view.desc());
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            match self.view {
+                VramView::CgaBanks => {
+                    ui.checkbox(&mut self.cga_hires, "640 pixel mode");
+                }
+                VramView::Planes => {
+                    ui.label("Bytes per row:");
+                    ui.add(egui::DragValue::new(&mut self.plane_row_bytes).clamp_range(1..=256));
+                }
+                _ => {}
+            }
+            ui.label("Scale:");
+            ui.add(egui::Slider::new(&mut self.scale, VRAM_SCALE_MIN..=VRAM_SCALE_MAX));
+        });
+        ui.separator();
+
+        if self.textures_dirty {
+            self.textures = self.images.iter().enumerate().map(|(i, image)| {
+                let color_image = ColorImage::from_rgba_unmultiplied([image.w as usize, image.h as usize], &image.rgba);
+                ctx.load_texture(format!("vram_viewer_{}", i), color_image, Default::default())
+            }).collect();
+            self.textures_dirty = false;
+        }
+
+        if self.images.is_empty() {
+            ui.label("No video memory to display.");
+            return
+        }
+
+        let scale = self.scale;
+        egui::ScrollArea::both()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                for (image, texture) in self.images.iter().zip(self.textures.iter()) {
+                    ui.label(&image.label);
+                    let response = ui.add(
+                        egui::Image::new(texture, texture.size_vec2() * scale)
+                            .sense(egui::Sense::hover())
+                    );
+
+                    if let Some(pos) = response.hover_pos() {
+                        let local = (pos - response.rect.min) / scale;
+                        if let Some(cell) = image.cell_at(local.x as u32, local.y as u32) {
+                            response.on_hover_text(cell.describe());
+                        }
+                    }
+                    ui.add_space(4.0);
+                }
+            });
+    }
+
+    pub fn view(&self) -> VramView {
+        self.view
+    }
+
+    pub fn plane_row_bytes(&self) -> u32 {
+        self.plane_row_bytes
+    }
+
+    pub fn cga_hires(&self) -> bool {
+        self.cga_hires
+    }
+
+    pub fn set_images(&mut self, images: Vec<VramImage>) {
+        if images != self.images {
+            self.images = images;
+            self.textures_dirty = true;
+        }
+    }
+}
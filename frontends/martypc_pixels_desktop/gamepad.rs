@@ -0,0 +1,130 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    gamepad.rs
+
+    Maps host gamepads to the joysticks of the emulated game port. The first
+    two gamepads connected become joysticks A and B. The left stick (or the
+    d-pad) drives the joystick axes, and the first two face buttons are the
+    joystick buttons.
+*/
+
+use gilrs::{Axis, Button, EventType, Gamepad, GamepadId, Gilrs};
+
+use marty_core::devices::game_port::{GamePort, JOYSTICK_COUNT};
+
+pub struct GamepadInput {
+    gilrs: Gilrs,
+    sticks: [Option<GamepadId>; JOYSTICK_COUNT],
+}
+
+impl GamepadInput {
+    /// Start reading host gamepads. Returns None if gamepads are not supported on this host.
+    pub fn new() -> Option<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                log::error!("Couldn't initialize gamepad support: {}", e);
+                return None
+            }
+        };
+
+        let mut input = Self {
+            gilrs,
+            sticks: [None; JOYSTICK_COUNT],
+        };
+        let connected: Vec<GamepadId> = input.gilrs.gamepads().map(|(id, _)| id).collect();
+        for id in connected {
+            input.assign(id);
+        }
+        Some(input)
+    }
+
+    fn assign(&mut self, id: GamepadId) {
+        if self.sticks.contains(&Some(id)) {
+            return
+        }
+        if let Some((i, slot)) = self.sticks.iter_mut().enumerate().find(|(_, slot)| slot.is_none()) {
+            *slot = Some(id);
+            log::info!("Gamepad {} connected as joystick {}", self.gilrs.gamepad(id).name(), stick_name(i));
+        }
+    }
+
+    fn unassign(&mut self, id: GamepadId) {
+        for (i, slot) in self.sticks.iter_mut().enumerate() {
+            if *slot == Some(id) {
+                *slot = None;
+                log::info!("Joystick {} disconnected", stick_name(i));
+            }
+        }
+    }
+
+    /// Process gamepad events and update the game port's joysticks from the assigned gamepads.
+    pub fn update(&mut self, game_port: &mut GamePort) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => self.assign(event.id),
+                EventType::Disconnected => self.unassign(event.id),
+                _ => {}
+            }
+        }
+
+        for (i, slot) in self.sticks.iter().enumerate() {
+            game_port.set_connected(i, slot.is_some());
+            if let Some(id) = slot {
+                let gamepad = self.gilrs.gamepad(*id);
+                let (x, y) = stick_position(&gamepad);
+                game_port.set_position(i, x, y);
+                game_port.set_button(i, 0, gamepad.is_pressed(Button::South));
+                game_port.set_button(i, 1, gamepad.is_pressed(Button::East));
+            }
+        }
+    }
+}
+
+fn stick_name(stick: usize) -> char {
+    (b'A' + stick as u8) as char
+}
+
+/// Return the position of a gamepad's left stick, overridden by the d-pad if it is pressed.
+/// Game port joysticks are positive to the right and down.
+fn stick_position(gamepad: &Gamepad) -> (f64, f64) {
+    let mut x = gamepad.value(Axis::LeftStickX) as f64;
+    let mut y = -gamepad.value(Axis::LeftStickY) as f64;
+
+    if gamepad.is_pressed(Button::DPadLeft) {
+        x = -1.0;
+    }
+    else if gamepad.is_pressed(Button::DPadRight) {
+        x = 1.0;
+    }
+    if gamepad.is_pressed(Button::DPadUp) {
+        y = -1.0;
+    }
+    else if gamepad.is_pressed(Button::DPadDown) {
+        y = 1.0;
+    }
+    (x, y)
+}
@@ -0,0 +1,244 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    instance.rs
+
+    Runs additional machines alongside the main one, each in its own window.
+    An instance has its own Machine, execution state and renderer, and only
+    receives the window events of its own window, so instances are fully
+    independent of each other and of the main machine. Instances have no
+    GUI or sound; keyboard input and floppy images dropped on the window go
+    to the instance's machine.
+*/
+
+use std::path::PathBuf;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::{
+    dpi::LogicalSize,
+    event::{ElementState, KeyboardInput, WindowEvent},
+    event_loop::EventLoopWindowTarget,
+    window::{Window, WindowBuilder, WindowId}
+};
+
+use marty_core::{
+    config::{ConfigFileParams, VideoType},
+    input,
+    machine::{ExecutionControl, ExecutionState, Machine},
+    rom_manager::RomManager,
+    videocard::RenderMode,
+};
+use marty_render::VideoRenderer;
+
+use crate::FPS_TARGET;
+
+const INSTANCE_WINDOW_WIDTH: u32 = 640;
+const INSTANCE_WINDOW_HEIGHT: u32 = 480;
+
+pub struct MachineInstance {
+    number: usize,
+    window: Window,
+    pixels: Pixels,
+    machine: Machine,
+    exec_control: ExecutionControl,
+    renderer: VideoRenderer,
+    buffer_size: (u32, u32),
+}
+
+impl MachineInstance {
+    /// Create a machine from the configuration and open a window for it. The instance number is
+    /// shown in the window title to tell instances apart.
+    pub fn new<T>(
+        number: usize,
+        window_target: &EventLoopWindowTarget<T>,
+        config: &ConfigFileParams,
+        rom_manager: RomManager
+    ) -> Result<Self, String> {
+
+        let window = WindowBuilder::new()
+            .with_title(format!("MartyPC {} - Machine {}", env!("CARGO_PKG_VERSION"), number))
+            .with_inner_size(LogicalSize::new(INSTANCE_WINDOW_WIDTH as f64, INSTANCE_WINDOW_HEIGHT as f64))
+            .build(window_target)
+            .map_err(|e| e.to_string())?;
+
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        let pixels = Pixels::new(INSTANCE_WINDOW_WIDTH, INSTANCE_WINDOW_HEIGHT, surface_texture)
+            .map_err(|e| e.to_string())?;
+
+        let machine = crate::new_machine(config, rom_manager, None)?;
+
+        let mut exec_control = ExecutionControl::new();
+        exec_control.set_state(ExecutionState::Running);
+
+        log::info!("Started machine instance {}.", number);
+        Ok(Self {
+            number,
+            window,
+            pixels,
+            machine,
+            exec_control,
+            renderer: VideoRenderer::new(config.machine.video),
+            buffer_size: (INSTANCE_WINDOW_WIDTH, INSTANCE_WINDOW_HEIGHT),
+        })
+    }
+
+    pub fn window_id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    /// Handle an event for the instance's window. Returns false if the window was closed, after
+    /// which the instance should be dropped.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CloseRequested => {
+                self.close();
+                return false
+            }
+            WindowEvent::Resized(size) => {
+                if self.pixels.resize_surface(size.width, size.height).is_err() {
+                    // Errors get thrown when the window minimizes.
+                    log::debug!("Unable to resize pixels surface of machine instance {}", self.number);
+                }
+            }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                if self.pixels.resize_surface(new_inner_size.width, new_inner_size.height).is_err() {
+                    log::debug!("Unable to resize pixels surface of machine instance {}", self.number);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    virtual_keycode: Some(keycode),
+                    state,
+                    ..
+                },
+                ..
+            } => {
+                if let Some(keycode) = input::match_virtual_keycode(*keycode) {
+                    match state {
+                        ElementState::Pressed => self.machine.key_press(keycode),
+                        ElementState::Released => self.machine.key_release(keycode),
+                    }
+                }
+            }
+            WindowEvent::DroppedFile(path) => {
+                self.load_floppy(path.clone());
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Write back any modified disk images before the instance is dropped.
+    pub fn close(&mut self) {
+        log::info!("Closing machine instance {}.", self.number);
+        self.machine.flush_disks();
+    }
+
+    /// Load a floppy image into drive A:.
+    fn load_floppy(&mut self, path: PathBuf) {
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to read floppy image {}: {}", path.display(), e);
+                return
+            }
+        };
+        if let Some(fdc) = self.machine.fdc() {
+            match fdc.load_image_from(0, data) {
+                Ok(()) => {
+                    log::info!("Machine instance {} loaded floppy image {}", self.number, path.display());
+                    fdc.set_image_path(0, Some(path));
+                }
+                Err(e) => log::warn!("Floppy image failed to load: {}", e),
+            }
+        }
+    }
+
+    /// Run the machine for one frame and draw its display.
+    pub fn run_frame(&mut self) {
+        let cycles = (self.machine.get_cpu_mhz() * 1000000.0 / FPS_TARGET) as u32;
+        self.machine.run(cycles, &mut self.exec_control);
+        self.draw();
+    }
+
+    fn draw(&mut self) {
+        let bus = self.machine.bus();
+        let Some(card) = bus.video() else {
+            return
+        };
+
+        let (w, mut h) = match card.get_render_mode() {
+            RenderMode::Direct => card.get_display_aperture(),
+            RenderMode::Indirect => card.get_display_size()
+        };
+        if card.get_scanline_double() {
+            h *= 2;
+        }
+        if w == 0 || h == 0 {
+            return
+        }
+
+        if self.buffer_size != (w, h) {
+            if let Err(e) = self.pixels.resize_buffer(w, h) {
+                log::error!("Failed to resize pixel buffer of machine instance {}: {}", self.number, e);
+                return
+            }
+            self.buffer_size = (w, h);
+        }
+
+        let frame = self.pixels.frame_mut();
+        match card.get_render_mode() {
+            RenderMode::Direct => match card.get_video_type() {
+                VideoType::EGA => {
+                    self.renderer.draw_ega_direct(frame, w, h, card.get_display_buf(), card.get_display_extents());
+                }
+                VideoType::VGA => {
+                    self.renderer.draw_vga_direct(frame, w, h, card.get_display_buf(), card.get_display_extents());
+                }
+                _ => {
+                    self.renderer.draw_cga_direct(
+                        frame,
+                        w,
+                        h,
+                        card.get_display_buf(),
+                        card.get_display_extents(),
+                        false,
+                        &Default::default(),
+                        None
+                    );
+                }
+            },
+            RenderMode::Indirect => {
+                self.renderer.draw(frame, card, bus, false);
+            }
+        }
+        VideoRenderer::set_alpha(frame, w, h, 255);
+
+        if let Err(e) = self.pixels.render() {
+            log::error!("Failed to render machine instance {}: {}", self.number, e);
+        }
+    }
+}
@@ -39,18 +39,28 @@ use std::{
     cell::RefCell,
     rc::Rc,
     ffi::OsString,
-    path::PathBuf
+    path::{Path, PathBuf}
 };
 
 mod egui;
+mod bug_report;
+mod gamepad;
+mod instance;
+mod mouse_capture;
+mod run_state;
 
 #[cfg(feature = "arduino_validator")]
 mod main_fuzzer;
 
 use crate::egui::{Framework, DeviceSelection};
+use crate::gamepad::GamepadInput;
+use crate::instance::MachineInstance;
+use crate::mouse_capture::MouseCapture;
+use crate::run_state::RunStateManager;
 
 use log::error;
 use pixels::{Pixels, SurfaceTexture};
+use pixels_stretch_renderer::StretchingRenderer;
 
 use winit::{
     dpi::LogicalSize,
@@ -60,13 +70,14 @@ use winit::{
         DeviceEvent, 
         ElementState, 
         StartCause, 
+        ModifiersState,
         VirtualKeyCode,
     },
     event_loop::{
         ControlFlow,
         EventLoop
     },
-    window::WindowBuilder
+    window::{Fullscreen, Window, WindowBuilder}
 };
 
 use winit_input_helper::WinitInputHelper;
@@ -75,30 +86,48 @@ use winit_input_helper::WinitInputHelper;
 use crate::main_fuzzer::main_fuzzer;
 
 use marty_core::{
-    breakpoints::BreakPointType,
+    artifacts::{ArtifactManager, ArtifactKind, DEFAULT_ARTIFACT_DIR, DEFAULT_ARTIFACT_RETENTION_MB},
+    automation::{AutomationServer, DEFAULT_AUTOMATION_RATE_LIMIT},
+    gdb_server::GdbServer,
+    scripting::{ScriptHost, ScriptAction},
+    breakpoints::{BreakPointType, BreakPointCondition},
     config::{self, *},
+    file_util,
     machine::{self, Machine, MachineState, ExecutionControl, ExecutionState},
-    cpu_808x::{Cpu, CpuAddress},
+    assembler,
+    cpu_808x::{BranchTarget, Cpu, CpuAddress},
     cpu_common::CpuOption,
     rom_manager::{RomManager, RomError, RomFeature},
-    floppy_manager::{FloppyManager, FloppyError},
+    savestate,
+    floppy_manager::{FloppyManager, FloppyError, RecentImages},
+    gui_layout::GuiLayout,
+    hotkey::{HotkeyAction, HotkeyManager, KeyCombo},
+    guest_os::GuestOs,
+    palette,
     machine_manager::MACHINE_DESCS,
+    mem_search::{SearchDirection, parse_pattern},
+    monitor::MonitorType,
+    movie::MovieEvent,
+    devices::mda,
+    disk_image,
     vhd_manager::{VHDManager, VHDManagerError},
     vhd::{self, VirtualHardDisk},
+    virtual_floppy::{self, VirtualFloppy},
     videocard::{RenderMode},
     bytequeue::ByteQueue,
     sound::SoundPlayer,
+    speed::CpuClock,
     syntax_token::SyntaxToken,
-    input::{
-        self,
-        MouseButton
-    },
-    util
+    trace_session::TraceKind,
+    tracelogger::TraceLogger,
+    input,
+    util,
+    validation_preset::{self, EffectStatus, ValidationPreset}
 };
 
 
-use crate::egui::{GuiEvent, GuiOption , GuiWindow, PerformanceStats};
-use marty_render::{VideoData, VideoRenderer, CompositeParams, ResampleContext};
+use crate::egui::{DisassemblyRow, DISASSEMBLY_ROWS, FramePacingSample, GuiEvent, GuiOption , GuiWindow, PerformanceStats, PROFILER_REPORT_LEN};
+use marty_render::{VideoData, VideoRenderer, CompositeParams, CrtParams, CrtProcessor, RenderThread, ResampleContext, ScalingMode, ScreenRecorder, TextSource, TileFormat, TileSource, VramView};
 
 const EGUI_MENU_BAR: u32 = 25;
 const WINDOW_WIDTH: u32 = 1280;
@@ -113,6 +142,8 @@ const RENDER_ASPECT: f32 = 0.75;
 
 pub const FPS_TARGET: f64 = 60.0;
 const MICROS_PER_FRAME: f64 = 1.0 / FPS_TARGET * 1000000.0;
+/// While fast-forwarding, only every Nth frame is rendered unless configured otherwise.
+const DEFAULT_FAST_FORWARD_FRAMESKIP: u32 = 4;
 
 // Remove static frequency references
 //const CYCLES_PER_FRAME: u32 = (cpu_808x::CPU_MHZ * 1000000.0 / FPS_TARGET) as u32;
@@ -146,6 +177,7 @@ struct Counter {
     current_pit_tps: u64,
     emulation_time: Duration,
     render_time: Duration,
+    worker_render_time: Duration,
     accumulated_us: u128,
     cpu_mhz: f64,
     cycles_per_frame: u32,
@@ -181,6 +213,7 @@ impl Counter {
             current_pit_tps: 0,
             emulation_time: Duration::ZERO,
             render_time: Duration::ZERO,
+            worker_render_time: Duration::ZERO,
             accumulated_us: 0,
             cpu_mhz: 0.0,
             cycles_per_frame: 0,
@@ -188,62 +221,12 @@ impl Counter {
         }
     }
 }
-struct MouseData {
-    reverse_buttons: bool,
-    l_button_id: u32,
-    r_button_id: u32,
-    is_captured: bool,
-    have_update: bool,
-    l_button_was_pressed: bool,
-    l_button_was_released: bool,
-    l_button_is_pressed: bool,
-    r_button_was_pressed: bool,
-    r_button_was_released: bool,
-    r_button_is_pressed: bool,
-    frame_delta_x: f64,
-    frame_delta_y: f64
-}
-impl MouseData {
-    fn new(reverse_buttons: bool) -> Self {
-        Self {
-            reverse_buttons,
-            l_button_id: input::get_mouse_buttons(reverse_buttons).0,
-            r_button_id: input::get_mouse_buttons(reverse_buttons).1,
-            is_captured: false,
-            have_update: false,
-            l_button_was_pressed: false,
-            l_button_was_released: false,
-            l_button_is_pressed: false,
-            r_button_was_pressed: false,
-            r_button_was_released: false,
-            r_button_is_pressed: false,
-            frame_delta_x: 0.0,
-            frame_delta_y: 0.0
-        }
-    }
-    pub fn reset(&mut self) {
-        if !self.l_button_is_pressed {
-            self.l_button_was_pressed = false;
-        }
-        if !self.r_button_is_pressed {
-            self.r_button_was_pressed = false;
-        }
-
-        self.l_button_was_released = false;
-        self.r_button_was_released = false;
-
-        self.frame_delta_x = 0.0;
-        self.frame_delta_y = 0.0;
-        self.have_update = false;
-    }
-}
-
 struct KeyboardData {
-    ctrl_pressed: bool
+    modifiers: ModifiersState
 }
 impl KeyboardData {
     fn new() -> Self {
-        Self { ctrl_pressed: false }
+        Self { modifiers: ModifiersState::empty() }
     }
 }
 
@@ -275,13 +258,27 @@ fn main() {
                     std::process::exit(1);
                 }                
                 None => {
-                    eprintln!("Failed to parse configuration file. There may be a typo or otherwise invalid toml:\n{}", e);
+                    eprintln!("Failed to parse configuration file:\n{}", e);
                     std::process::exit(1);
                 }
             }
         }
     };
 
+    // Report any non-fatal configuration problems.
+    for issue in &config.issues {
+        eprintln!("{}", issue);
+    }
+
+    // In validation mode, exit now with the result.
+    if config.emulator.validate_only {
+        if config.issues.is_empty() {
+            println!("Configuration file is valid.");
+            std::process::exit(0);
+        }
+        std::process::exit(1);
+    }
+
     // Determine required ROM features from configuration options
     match config.machine.video {
         VideoType::EGA => {
@@ -320,6 +317,11 @@ fn main() {
             config.machine.rom_override.clone(),
         );
 
+    // Prefer the BIOS of the selected machine profile
+    if let Some(bios) = config.active_profile.as_ref().and_then(|p| p.bios.clone()) {
+        rom_manager.set_preferred_bios(bios);
+    }
+
     let mut rom_path = PathBuf::new();
     rom_path.push(config.emulator.basedir.clone());
     rom_path.push("roms");
@@ -358,7 +360,7 @@ fn main() {
     // Scan the floppy directory
     let mut floppy_path = PathBuf::new();
     floppy_path.push(config.emulator.basedir.clone());
-    floppy_path.push("floppy");
+    floppy_path.push(config.emulator.floppy_dir.as_deref().unwrap_or("floppy"));
 
     if let Err(e) = floppy_manager.scan_dir(&floppy_path) {
         match e {
@@ -372,6 +374,10 @@ fn main() {
         std::process::exit(1);
     }
 
+    // Load the list of recently mounted floppy images
+    let recent_floppies_path = config.emulator.basedir.join("recent_floppies.json");
+    let mut recent_floppies = RecentImages::load(&recent_floppies_path);
+
     // Instantiate the VHD manager
     let mut vhd_manager = VHDManager::new();
 
@@ -404,11 +410,20 @@ fn main() {
         log::debug!("Found serial port: {:?}", port);
     }
 
+    // Create the output folder for this run and clean up old runs
+    let artifacts = create_artifact_manager(&config);
+    redirect_artifact_paths(&mut config, &artifacts);
+
 
     // If fuzzer mode was specified, run the emulator in fuzzer mode now
     #[cfg(feature = "cpu_validator")]
     if config.emulator.fuzzer {
-        return main_fuzzer(&config, rom_manager, floppy_manager);
+        return main_fuzzer(&config, rom_manager, floppy_manager, &artifacts);
+    }
+
+    // If a validation preset was specified, run it now
+    if let Some(preset_path) = config.emulator.validation_preset.clone() {
+        return main_validation_preset(&config, &preset_path, rom_manager, floppy_manager);
     }
 
     // If headless mode was specified, run the emulator in headless mode now
@@ -416,8 +431,12 @@ fn main() {
         return main_headless(&config, rom_manager, floppy_manager);
     }
 
-    // Create the video renderer
-    let mut video = VideoRenderer::new(config.machine.video);
+    // Create the video renderer. Cards in Direct Mode are rendered on a worker thread unless disabled.
+    let mut video = RenderThread::new(
+        VideoRenderer::new(config.machine.video),
+        config.machine.video,
+        config.emulator.render_thread
+    );
 
     // Init graphics & GUI 
     let event_loop = EventLoop::new();
@@ -460,6 +479,13 @@ fn main() {
 
     // Create render buf
     let mut render_src = vec![0; (DEFAULT_RENDER_WIDTH * DEFAULT_RENDER_HEIGHT * 4) as usize];
+    let mut recorder: Option<ScreenRecorder> = None;
+
+    // Frame pacing state as of the last present
+    let mut last_present = Instant::now();
+    let mut last_present_interval = Duration::ZERO;
+    let mut last_present_cycles: u64 = 0;
+    let mut last_present_frames: u64 = 0;
     let mut video_data = VideoData {
         render_w: DEFAULT_RENDER_WIDTH,
         render_h: DEFAULT_RENDER_HEIGHT,
@@ -467,7 +493,12 @@ fn main() {
         aspect_h: 480,
         aspect_correction_enabled: false,
         composite_params: Default::default(),
+        palette: config.emulator.palette,
+        monitor: config.monitor(),
+        monochrome: None,
     };
+    let mut phosphor = config.emulator.monochrome.unwrap_or_default();
+    video_data.monochrome = video_data.monitor.phosphor(phosphor);
 
     // Create resampling context
     let mut resample_context = ResampleContext::new();
@@ -486,12 +517,21 @@ fn main() {
                 scale_factor, 
                 &pixels, 
                 exec_control.clone(),
+                config.gui.theme,
                 config.gui.theme_color
             );
 
         (pixels, framework)
     };
 
+    // The display renderer scales the pixel buffer into the window according to the 
+    // selected scaling mode. It is updated whenever the buffer, window or mode changes.
+    let mut display_renderer = {
+        let window_size = window.inner_size();
+        StretchingRenderer::new(&pixels, video_data.aspect_w, video_data.aspect_h, window_size.width, window_size.height)
+    };
+    let mut display_scaling: Option<((u32, u32), (u32, u32), ScalingMode)> = None;
+
     let adapter_info = pixels.adapter().get_info();
     let backend_str = format!("{:?}", adapter_info.backend);
     let adapter_name_str =  format!("{}", adapter_info.name);
@@ -506,7 +546,16 @@ fn main() {
     let mut kb_data = KeyboardData::new();
 
     // Mouse event struct
-    let mut mouse_data = MouseData::new(config.input.reverse_mouse_buttons);
+    let mut mouse_capture = MouseCapture::new(
+        config.input.reverse_mouse_buttons,
+        config.input.mouse_sensitivity.unwrap_or(1.0)
+    );
+
+    // Host gamepads are only read if there is a game port to connect them to.
+    let mut gamepads = match config.machine.game_port {
+        true => GamepadInput::new(),
+        false => None
+    };
 
     // Init sound 
     // The cpal sound library uses generics to initialize depending on the SampleFormat type.
@@ -518,48 +567,107 @@ fn main() {
         cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(),
     };
 
-    // Look up the machine description given the machine type in the configuration file
-    let machine_desc_opt = MACHINE_DESCS.get(&config.machine.model);
-    if let Some(machine_desc) = machine_desc_opt {
-        log::debug!("Given machine type {:?} got machine description: {:?}", config.machine.model, machine_desc);
-    }
-    else {
-        log::error!("Couldn't get machine description for {:?}", config.machine.model);
-
-        eprintln!(
-            "Couldn't get machine description for machine type {:?}. \
-             Check that you have a valid machine type specified in configuration file.",
-            config.machine.model
-        );
-        std::process::exit(1);        
-    }
-
     // Instantiate the main Machine data struct
     // Machine coordinates all the parts of the emulated computer
-    let mut machine = Machine::new(
-        &config,
-        config.machine.model,
-        *machine_desc_opt.unwrap(),
-        config.emulator.trace_mode,
-        config.machine.video, 
-        sp, 
-        rom_manager
-    );
+    let mut machine = match new_machine(&config, rom_manager.clone(), Some(sp)) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Set options from config. We do this now so that we can set the same state for both GUI and machine
     framework.gui.set_option(GuiOption::CorrectAspect, config.emulator.correct_aspect);
 
+    // Load the custom palette, if specified
+    let custom_palette = match &config.emulator.palette_file {
+        Some(path) => match palette::load_palette_file(path) {
+            Ok(colors) => Some(colors),
+            Err(e) => {
+                log::error!("Error loading palette file {}: {}", path.display(), e);
+                None
+            }
+        },
+        None => None
+    };
+    video.renderer().set_palette(video_data.palette, custom_palette.as_ref());
+    framework.gui.set_palette(video_data.palette, custom_palette.is_some());
+    video.renderer().set_monochrome(video_data.monochrome);
+    framework.gui.set_monitor(video_data.monitor, config.machine.video);
+    framework.gui.set_phosphor(phosphor);
+
+    // A secondary video card is shown in its own window, on its default monitor.
+    let mut secondary_video = config.machine.secondary_video.map(|video_type| {
+        let mut renderer = VideoRenderer::new(video_type);
+        renderer.set_palette(video_data.palette, custom_palette.as_ref());
+        renderer.set_monochrome(MonitorType::default_for(video_type).phosphor(phosphor));
+        renderer
+    });
+    framework.gui.set_secondary_video(config.machine.secondary_video);
+
+    let mut crt = CrtProcessor::new(CrtParams {
+        scanlines: config.emulator.crt_scanlines,
+        barrel: config.emulator.crt_barrel,
+        persistence: config.emulator.crt_persistence,
+        ..Default::default()
+    });
+    framework.gui.set_crt_params(*crt.params());
+
     framework.gui.set_option(GuiOption::CpuEnableWaitStates, config.cpu.wait_states_enabled);
     machine.set_cpu_option(CpuOption::EnableWaitStates(config.cpu.wait_states_enabled));
 
     framework.gui.set_option(GuiOption::CpuInstructionHistory, config.cpu.instruction_history);
     machine.set_cpu_option(CpuOption::InstructionHistory(config.cpu.instruction_history));
 
+    framework.gui.set_option(GuiOption::CpuHaltSkip, config.cpu.halt_skip);
+
     framework.gui.set_option(GuiOption::CpuTraceLoggingEnabled, config.emulator.trace_on);
     machine.set_cpu_option(CpuOption::TraceLoggingEnabled(config.emulator.trace_on));
 
     framework.gui.set_option(GuiOption::TurboButton, config.machine.turbo);
 
+    for drive in 0..2 {
+        framework.gui.media_manager.set_recent(drive, recent_floppies.get(drive));
+    }
+
+    // Warpspeed may be enabled only until the guest OS has booted.
+    let mut warp = config.emulator.warpspeed || config.emulator.warp_boot;
+    let mut warp_boot_pending = config.emulator.warp_boot && !config.emulator.warpspeed;
+    framework.gui.set_option(GuiOption::WarpSpeed, warp);
+
+    // Fast-forward runs unthrottled while its hotkey is held, rendering only every Nth frame.
+    let mut fast_forward = false;
+    let fast_forward_frameskip = config.emulator.fast_forward_frameskip.unwrap_or(DEFAULT_FAST_FORWARD_FRAMESKIP).max(1) as u64;
+    framework.gui.set_option(GuiOption::WriteProtectDriveA, config.machine.floppy0_write_protect.unwrap_or(false));
+    framework.gui.set_option(GuiOption::WriteProtectDriveB, config.machine.floppy1_write_protect.unwrap_or(false));
+    framework.gui.set_option(GuiOption::DriveSounds, config.emulator.drive_sounds);
+    framework.gui.set_option(GuiOption::PauseOnFocusLoss, config.emulator.pause_on_focus_loss);
+    let mut run_state = RunStateManager::new(config.emulator.pause_on_focus_loss);
+
+    let mut automation = start_automation_server(&config);
+    let mut gdb_server = start_gdb_server(&config);
+
+    // Run the script given on the command line, if any.
+    let mut script_host = None;
+    if let Some(script_path) = &config.emulator.script {
+        match std::fs::read_to_string(script_path) {
+            Ok(source) => {
+                log::info!("Running script {}", script_path.display());
+                framework.gui.script_console.set_source(source.clone());
+                framework.gui.script_console.set_running(true, "Running");
+                script_host = Some(ScriptHost::start(source));
+            }
+            Err(e) => {
+                eprintln!("Error reading script {}: {}", script_path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    // A script run from the command line is treated as a test: if it fails, we exit with an
+    // error status.
+    let mut script_from_cli = script_host.is_some();
+
     // Debug mode on? 
     if config.emulator.debug_mode {
         // Open default debug windows
@@ -576,7 +684,7 @@ fn main() {
     }
 
     // Load program binary if one was specified in config options
-    if let Some(prog_bin) = config.emulator.run_bin {
+    if let Some(prog_bin) = &config.emulator.run_bin {
 
         if let Some(prog_seg) = config.emulator.run_bin_seg {
             if let Some(prog_ofs) = config.emulator.run_bin_ofs {
@@ -638,10 +746,13 @@ fn main() {
                 let window_resize_w = if double_res { aper_correct_x * 2 } else { aper_correct_x };
                 let window_resize_h = if double_res { aper_correct_y * 2 } else { aper_correct_y };
 
-                log::debug!("Resizing window to {}x{}", window_resize_w, window_resize_h);
                 //resize_h = if card.get_scanline_double() { resize_h * 2 } else { resize_h };
 
-                window.set_inner_size(winit::dpi::LogicalSize::new(window_resize_w, window_resize_h));
+                // A fullscreen window keeps the size of the screen; the display is scaled into it.
+                if window.fullscreen().is_none() {
+                    log::debug!("Resizing window to {}x{}", window_resize_w, window_resize_h);
+                    window.set_inner_size(winit::dpi::LogicalSize::new(window_resize_w, window_resize_h));
+                }
 
                 log::debug!("Reiszing render buffer to {}x{}", aper_x, aper_y);
 
@@ -684,6 +795,36 @@ fn main() {
         }
     }
         
+    // Try to load default floppy images for drives A: and B:
+    for (drive_select, floppy_name) in [(0, &config.machine.floppy0), (1, &config.machine.floppy1)] {
+        if let Some(floppy_name) = floppy_name {
+            let floppy_os_name: OsString = floppy_name.into();
+            match floppy_manager.load_floppy_data(&floppy_os_name) {
+                Ok(vec) => {
+                    if let Some(fdc) = machine.fdc() {
+                        match fdc.load_image_from(drive_select, vec) {
+                            Ok(()) => {
+                                log::info!("Floppy image {:?} successfully loaded into drive: {}", floppy_os_name, drive_select);
+                                fdc.set_image_path(drive_select, floppy_manager.get_floppy_path(&floppy_os_name));
+                                framework.gui.set_floppy_status(
+                                    drive_select, 
+                                    Some(floppy_os_name.clone()), 
+                                    fdc.get_media_desc(drive_select)
+                                );
+                            }
+                            Err(err) => {
+                                log::error!("Floppy image {:?} failed to load: {}", floppy_os_name, err);
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::error!("Failed to load floppy image {:?}: {}", floppy_os_name, err);
+                }
+            }
+        }
+    }
+
     // Try to load default vhd for drive0: 
     if let Some(vhd_name) = config.machine.drive0 {
         let vhd_os_name: OsString = vhd_name.into();
@@ -691,18 +832,13 @@ fn main() {
             Ok(vhd_file) => {
                 match VirtualHardDisk::from_file(vhd_file) {
                     Ok(vhd) => {
-                        if let Some(hdc) = machine.hdc() {
-                            match hdc.set_vhd(0_usize, vhd) {
-                                Ok(_) => {
-                                    log::info!("VHD image {:?} successfully loaded into virtual drive: {}", vhd_os_name, 0);
-                                }
-                                Err(err) => {
-                                    log::error!("Error mounting VHD: {}", err);
-                                }
+                        match machine.mount_vhd(0_usize, vhd) {
+                            Ok(_) => {
+                                log::info!("VHD image {:?} successfully loaded into virtual drive: {}", vhd_os_name, 0);
+                            }
+                            Err(err) => {
+                                log::error!("Error mounting VHD: {}", err);
                             }
-                        }
-                        else {
-                            log::error!("Couldn't load VHD: No Hard Disk Controller present!");
                         }
                     },
                     Err(err) => {
@@ -724,18 +860,13 @@ fn main() {
             Ok(vhd_file) => {
                 match VirtualHardDisk::from_file(vhd_file) {
                     Ok(vhd) => {
-                        if let Some(hdc) = machine.hdc() {
-                            match hdc.set_vhd(1_usize, vhd) {
-                                Ok(_) => {
-                                    log::info!("VHD image {:?} successfully loaded into virtual drive: {}", vhd_os_name, 1);
-                                }
-                                Err(err) => {
-                                    log::error!("Error mounting VHD: {}", err);
-                                }
+                        match machine.mount_vhd(1_usize, vhd) {
+                            Ok(_) => {
+                                log::info!("VHD image {:?} successfully loaded into virtual drive: {}", vhd_os_name, 1);
+                            }
+                            Err(err) => {
+                                log::error!("Error mounting VHD: {}", err);
                             }
-                        }
-                        else {
-                            log::error!("Couldn't load VHD: No Hard Disk Controller present!");
                         }
                     },
                     Err(err) => {
@@ -749,19 +880,63 @@ fn main() {
         }    
     }       
 
+    // Restore the GUI window layout from the last session
+    let gui_layout_path = config.emulator.basedir.join("gui_layout.json");
+    let persist_layout = config.gui.persist_layout;
+    if persist_layout {
+        framework.gui.set_layout(GuiLayout::load(&gui_layout_path));
+    }
+
+    // The fullscreen mode entered by the fullscreen hotkey. Choosing a mode from the menu 
+    // makes it the one the hotkey uses.
+    let mut fullscreen_mode = config.emulator.fullscreen_mode;
+    if config.emulator.fullscreen {
+        framework.gui.set_fullscreen(set_fullscreen(&window, Some(fullscreen_mode)));
+    }
+
+    // Load hotkey bindings over the defaults
+    let hotkey_path = config.emulator.basedir.join("hotkeys.toml");
+    let mut hotkeys = HotkeyManager::load(&hotkey_path);
+    framework.gui.hotkey_editor.set_bindings(hotkeys.list());
+
+    // Additional machines, each running in its own window. Instances are numbered from 2; the
+    // main machine is machine 1.
+    let mut instances: Vec<MachineInstance> = Vec::new();
+    let mut next_instance = 2;
+
     // Start buffer playback
     machine.play_sound_buffer();
     
     // Run the winit event loop
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, window_target, control_flow| {
 
         //*control_flow = ControlFlow::Poll;
+
+        // Events for the windows of other machine instances go only to that instance
+        if let Event::WindowEvent { window_id, event: window_event } = &event {
+            if *window_id != window.id() {
+                if let Some(i) = instances.iter().position(|instance| instance.window_id() == *window_id) {
+                    if !instances[i].handle_event(window_event) {
+                        instances.remove(i);
+                    }
+                }
+                return;
+            }
+        }
     
         // Handle input events
         if input.update(&event) {
             // Close events
             
             if input.quit() {
+                machine.flush_disks();
+                machine.finish_movie();
+                for instance in &mut instances {
+                    instance.close();
+                }
+                if persist_layout {
+                    save_gui_layout(&framework, &gui_layout_path);
+                }
                 *control_flow = ControlFlow::Exit;
                 return;
             }
@@ -795,52 +970,13 @@ fn main() {
                     DeviceEvent::MouseMotion {
                         delta: (x, y)
                     } => {
-                        // We can get a lot more mouse updates than we want to send to the virtual mouse,
-                        // so add up all deltas between each mouse polling period
-                        mouse_data.have_update = true;
-                        mouse_data.frame_delta_x += x;
-                        mouse_data.frame_delta_y += y;
+                        mouse_capture.motion(x, y);
                     },
                     DeviceEvent::Button { 
                         button,
                         state 
                     } => {
-                        // Button ID is a raw u32. It appears that the id's for relative buttons are not consistent
-                        // accross platforms. 1 == left button on windows, 3 == left button on macos. So we resolve
-                        // button ids to button enums based on platform. There is a config option to override button 
-                        // order.
-
-                        // Resolve the winit button id to a button enum based on platform and reverse flag.
-                        let mbutton = input::button_from_id(button, mouse_data.reverse_buttons);
-
-                        // A mouse click could be faster than one frame (pressed & released in 16.6ms), therefore mouse 
-                        // clicks are 'sticky', if a button was pressed during the last update period it will be sent as
-                        // pressed during virtual mouse update.
-
-                        match (mbutton, state) {
-                            (MouseButton::Left, ElementState::Pressed) => {
-                                mouse_data.l_button_was_pressed = true;
-                                mouse_data.l_button_is_pressed = true;
-                                mouse_data.have_update = true;
-                            },
-                            (MouseButton::Left, ElementState::Released) => {
-                                mouse_data.l_button_is_pressed = false;
-                                mouse_data.l_button_was_released = true;
-                                mouse_data.have_update = true;
-                            },
-                            (MouseButton::Right, ElementState::Pressed) => {
-                                mouse_data.r_button_was_pressed = true;
-                                mouse_data.r_button_is_pressed = true;
-                                mouse_data.have_update = true;
-                            },
-                            (MouseButton::Right, ElementState::Released) => {
-                                mouse_data.r_button_is_pressed = false;
-                                mouse_data.r_button_was_released = true;
-                                mouse_data.have_update = true;
-                            }                              
-                            _=> {}
-                        }
-                        //log::debug!("Mouse button: {:?} state: {:?}", button, state);
+                        mouse_capture.button(button, state);
                     }
                     _ => {
 
@@ -850,8 +986,59 @@ fn main() {
             Event::WindowEvent{ event, .. } => {
 
                 match event {
+                    WindowEvent::DroppedFile(path) => {
+                        // Mount dropped floppy images and directories in the drive selected in the media manager.
+                        if path.is_dir() {
+                            let drive = framework.gui.media_manager.drop_drive();
+                            let write_back = framework.gui.media_manager.drop_write_back();
+                            log::debug!("Directory dropped: {}", path.display());
+                            framework.gui.send_event(GuiEvent::MountDirectory(drive, path, write_back));
+                        }
+                        else if FloppyManager::is_floppy_image(&path) {
+                            let drive = framework.gui.media_manager.drop_drive();
+                            log::debug!("Floppy image dropped: {}", path.display());
+                            framework.gui.send_event(GuiEvent::LoadFloppy(drive, path.into_os_string()));
+                        }
+                        else {
+                            framework.gui.show_error(&format!("Not a floppy image: {}", path.display()));
+                        }
+                    }
+                    WindowEvent::Focused(focused) => {
+                        // Key releases are not seen while unfocused, so end any fast-forward now
+                        if !focused {
+                            fast_forward = false;
+                            // The cursor grab is lost when the window loses focus
+                            mouse_capture.release(&window, &mut machine);
+                            update_window_title(&window, &mouse_capture, &hotkeys);
+                        }
+                        if let Some(state) = run_state.focus_changed(focused, machine.get_state()) {
+                            machine.change_state(state);
+                        }
+                    }
+                    WindowEvent::MouseInput { 
+                        state: ElementState::Pressed,
+                        button: winit::event::MouseButton::Left,
+                        .. 
+                    } if config.input.mouse_capture_on_click 
+                        && !mouse_capture.is_captured() 
+                        && machine.mouse_mut().is_some()
+                        && !framework.pointer_over_gui() => {
+                        // Clicking on the emulator display captures the mouse.
+                        mouse_capture.capture(&window);
+                        update_window_title(&window, &mouse_capture, &hotkeys);
+                    }
+                    WindowEvent::ScaleFactorChanged { scale_factor, ref new_inner_size } => {
+                        // Moving between monitors with different DPI changes the surface size 
+                        // without a resize event.
+                        framework.scale_factor(scale_factor);
+                        if pixels.resize_surface(new_inner_size.width, new_inner_size.height).is_err() {
+                            log::error!("Unable to resize pixels surface!");
+                        }
+                        framework.resize(new_inner_size.width, new_inner_size.height);
+                        framework.handle_event(&event);
+                    }
                     WindowEvent::ModifiersChanged(modifier_state) => {
-                        kb_data.ctrl_pressed = modifier_state.ctrl();
+                        kb_data.modifiers = modifier_state;
                     }
                     WindowEvent::KeyboardInput {
                         input: winit::event::KeyboardInput {
@@ -862,47 +1049,97 @@ fn main() {
                         ..
                     } => {
 
-                        // Match global hotkeys regardless of egui focus
-                        match (state, keycode) {
-                            (winit::event::ElementState::Pressed, VirtualKeyCode::F10 ) => {
-                                if kb_data.ctrl_pressed {
-                                    // Ctrl-F10 pressed. Toggle mouse capture.
-                                    log::info!("Control F10 pressed. Capturing mouse cursor.");
-                                    if !mouse_data.is_captured {
-                                        let mut grab_success = false;
-                                        match window.set_cursor_grab(winit::window::CursorGrabMode::Confined) {
-                                            Ok(_) => {
-                                                mouse_data.is_captured = true;
-                                                grab_success = true;
-                                            }
-                                            Err(_) => {
-                                                // Try alternate grab mode (Windows/Mac require opposite modes)
-                                                match window.set_cursor_grab(winit::window::CursorGrabMode::Locked) {
-                                                    Ok(_) => {
-                                                        mouse_data.is_captured = true;
-                                                        grab_success = true;
-                                                    } 
-                                                    Err(e) => log::error!("Couldn't set cursor grab mode: {:?}", e)
+                        // A key pressed while the hotkey editor is waiting for one is bound instead of
+                        // being sent to the emulated machine.
+                        if let Some(action) = framework.gui.hotkey_editor.capturing() {
+                            if state == ElementState::Pressed {
+                                if keycode == VirtualKeyCode::Escape && kb_data.modifiers.is_empty() {
+                                    framework.gui.hotkey_editor.cancel_capture();
+                                }
+                                else if KeyCombo::is_bindable(keycode) {
+                                    let combo = KeyCombo::new(keycode, kb_data.modifiers);
+                                    log::info!("Binding hotkey {} to {}", action, combo);
+                                    hotkeys.bind(action, combo);
+                                    save_hotkeys(&mut framework, &hotkeys, &hotkey_path);
+                                    framework.gui.hotkey_editor.cancel_capture();
+                                }
+                            }
+                            return
+                        }
+
+                        // Match global hotkeys regardless of egui focus. Hotkey presses are not sent to
+                        // the emulated machine.
+                        match state {
+                            ElementState::Pressed => {
+                                if let Some(action) = hotkeys.action(keycode, kb_data.modifiers) {
+                                    match action {
+                                        HotkeyAction::MouseCapture => {
+                                            mouse_capture.toggle(&window, &mut machine);
+                                            update_window_title(&window, &mouse_capture, &hotkeys);
+                                        }
+                                        HotkeyAction::Fullscreen => {
+                                            let fullscreen = match window.fullscreen() {
+                                                Some(_) => None,
+                                                None => Some(fullscreen_mode)
+                                            };
+                                            framework.gui.send_event(GuiEvent::SetFullscreen(fullscreen));
+                                        }
+                                        HotkeyAction::Screenshot => {
+                                            framework.gui.send_event(GuiEvent::TakeScreenshot);
+                                        }
+                                        HotkeyAction::Pause => {
+                                            match machine.get_state() {
+                                                MachineState::On => {
+                                                    framework.gui.send_event(GuiEvent::MachineStateChange(MachineState::Paused));
                                                 }
+                                                MachineState::Paused => {
+                                                    framework.gui.send_event(GuiEvent::MachineStateChange(MachineState::Resuming));
+                                                }
+                                                _ => {}
                                             }
                                         }
-                                        // Hide mouse cursor if grab successful
-                                        if grab_success {
-                                            window.set_cursor_visible(false);
+                                        HotkeyAction::Reboot => {
+                                            framework.gui.send_event(GuiEvent::MachineStateChange(MachineState::Rebooting));
                                         }
-                                    }
-                                    else {
-                                        // Cursor is grabbed, ungrab
-                                        match window.set_cursor_grab(winit::window::CursorGrabMode::None) {
-                                            Ok(_) => mouse_data.is_captured = false,
-                                            Err(e) => log::error!("Couldn't set cursor grab mode: {:?}", e)
+                                        HotkeyAction::CtrlAltDel => {
+                                            framework.gui.send_event(GuiEvent::CtrlAltDel);
+                                        }
+                                        HotkeyAction::FastForward => {
+                                            // Fast-forward until the hotkey is released.
+                                            if !fast_forward {
+                                                log::info!("Fast-forward started.");
+                                                fast_forward = true;
+                                            }
+                                        }
+                                        HotkeyAction::SpeedUp => {
+                                            machine.step_speed(true);
+                                            log::info!("Emulation speed: {}x", machine.speed());
+                                        }
+                                        HotkeyAction::SpeedDown => {
+                                            machine.step_speed(false);
+                                            log::info!("Emulation speed: {}x", machine.speed());
+                                        }
+                                        HotkeyAction::NormalSpeed => {
+                                            machine.set_speed(1.0);
+                                        }
+                                        HotkeyAction::SaveState(slot) => {
+                                            framework.gui.send_event(GuiEvent::SaveState(slot));
+                                        }
+                                        HotkeyAction::LoadState(slot) => {
+                                            framework.gui.send_event(GuiEvent::LoadState(slot));
                                         }
-                                        window.set_cursor_visible(true);
                                     }
-                                    
+                                    return
+                                }
+                            }
+                            ElementState::Released => {
+                                // Only the key needs to be released; the modifiers may already be up.
+                                let ff_key = hotkeys.binding(HotkeyAction::FastForward).map(|combo| combo.key);
+                                if fast_forward && ff_key == Some(keycode) {
+                                    log::info!("Fast-forward stopped.");
+                                    fast_forward = false;
                                 }
                             }
-                            _=>{}
                         }
 
                         if !framework.has_focus() {
@@ -1013,64 +1250,103 @@ fn main() {
                     //    }
                     //}
 
-                    if let Some(mouse) = machine.mouse_mut() {
-                        // Send any pending mouse update to machine if mouse is captured
-                        if mouse_data.is_captured && mouse_data.have_update {
-                            mouse.update(
-                                mouse_data.l_button_was_pressed,
-                                mouse_data.r_button_was_pressed,
-                                mouse_data.frame_delta_x,
-                                mouse_data.frame_delta_y
-                            );
-
-                            // Handle release event
-                            let l_release_state = 
-                                if mouse_data.l_button_was_released {
-                                    false
-                                }
-                                else {
-                                    mouse_data.l_button_was_pressed
-                                };
-                            
-                            let r_release_state = 
-                                if mouse_data.r_button_was_released {
-                                    false
-                                }
-                                else {
-                                    mouse_data.r_button_was_pressed
-                                };
-
-                            if mouse_data.l_button_was_released || mouse_data.r_button_was_released {
-                                // Send release event
-                                mouse.update(
-                                    l_release_state,
-                                    r_release_state,
-                                    0.0,
-                                    0.0
-                                );                            
-                            }
+                    // Send any pending mouse update to machine if mouse is captured
+                    mouse_capture.send_update(&mut machine);
 
-                            // Reset mouse for next frame
-                            mouse_data.reset();
-                        }
+                    // Update joysticks from host gamepads
+                    if let (Some(gamepads), Some(game_port)) = (&mut gamepads, machine.game_port_mut().as_mut()) {
+                        gamepads.update(game_port);
                     }
 
                     // Emulate a frame worth of instructions
                     // ---------------------------------------------------------------------------
 
-                    // Recalculate cycle target based on current CPU speed if it has changed (or uninitialized)
+                    // Recalculate cycle target based on current CPU speed if it has changed (or uninitialized).
+                    // An unlimited CPU clock changes every frame to match the cycle target, so is ignored,
+                    // unless the machine is deterministic and so runs its clock at a fixed rate.
+                    let unlimited_clock = machine.cpu_clock() == Some(CpuClock::Unlimited);
+                    let deterministic = machine.deterministic();
                     let mhz = machine.get_cpu_mhz();
-                    if mhz != stat_counter.cpu_mhz {
+                    if mhz != stat_counter.cpu_mhz && (!unlimited_clock || deterministic) {
                         stat_counter.cycles_per_frame = (machine.get_cpu_mhz() * 1000000.0 / FPS_TARGET) as u32;
                         stat_counter.cycle_target = stat_counter.cycles_per_frame;
                         log::info!("CPU clock has changed to {}Mhz; new cycle target: {}", mhz, stat_counter.cycle_target);
                         stat_counter.cpu_mhz = mhz;
                     }
+
+                    // The cycle target may not exceed a frame's worth of cycles at the current 
+                    // emulation speed. Below that, it is reduced if the host can't keep up.
+                    // Warpspeed, fast-forward and an unlimited CPU clock run as many cycles as the host 
+                    // can manage. A deterministic machine always runs exactly one frame's worth of cycles.
+                    let unthrottled = warp || fast_forward || unlimited_clock;
+                    let speed_cycles = (stat_counter.cycles_per_frame as f64 * machine.effective_speed()) as u32;
+                    if deterministic {
+                        stat_counter.cycle_target = stat_counter.cycles_per_frame;
+                    }
+                    else if stat_counter.cycle_target > speed_cycles && !unthrottled {
+                        stat_counter.cycle_target = speed_cycles.max(1);
+                    }
                     
+                    let mut run_frame = true;
+                    if let Some(server) = &mut automation {
+                        server.poll(&mut machine);
+                        run_frame = server.may_run();
+                    }
+
+                    if let Some(server) = &mut gdb_server {
+                        server.poll(&mut machine, &mut exec_control.borrow_mut());
+                    }
+
+                    if let Some(host) = &mut script_host {
+                        host.poll(&mut machine, &mut exec_control.borrow_mut());
+                        framework.gui.script_console.add_output(host.take_output());
+
+                        for action in host.take_actions() {
+                            match action {
+                                ScriptAction::Screenshot => framework.gui.send_event(GuiEvent::TakeScreenshot),
+                                ScriptAction::Exit(code) => {
+                                    machine.flush_disks();
+                                    machine.finish_movie();
+                                    std::process::exit(code);
+                                }
+                            }
+                        }
+
+                        match host.result() {
+                            Some(Ok(_)) => {
+                                framework.gui.script_console.set_running(false, "Finished");
+                                script_host = None;
+                            }
+                            Some(Err(_)) => {
+                                if script_from_cli {
+                                    machine.flush_disks();
+                                    machine.finish_movie();
+                                    std::process::exit(1);
+                                }
+                                framework.gui.script_console.set_running(false, "Failed");
+                                script_host = None;
+                            }
+                            None => {}
+                        }
+                    }
+
+                    machine.set_unlimited_frame_time(MICROS_PER_FRAME * machine.effective_speed());
+
                     let emulation_start = Instant::now();
-                    stat_counter.instr_count += machine.run(stat_counter.cycle_target, &mut exec_control.borrow_mut());
+                    if run_frame {
+                        stat_counter.instr_count += machine.run(stat_counter.cycle_target, &mut exec_control.borrow_mut());
+                        if let Some(server) = &mut automation {
+                            server.frame_done();
+                        }
+                    }
                     stat_counter.emulation_time = Instant::now() - emulation_start;
 
+                    // Other machine instances run at their own speed, whatever the state of the 
+                    // main machine.
+                    for instance in &mut instances {
+                        instance.run_frame();
+                    }
+
                     // Add instructions to IPS counter
                     stat_counter.cycle_count += stat_counter.cycle_target as u64;
 
@@ -1098,7 +1374,10 @@ fn main() {
                     }                    
 
                     // If emulation time took too long, reduce CYCLE_TARGET
-                    if emulation_time > emulation_time_allowed_us {
+                    if deterministic {
+                        // The cycle target is fixed.
+                    }
+                    else if emulation_time > emulation_time_allowed_us {
                         // Emulation running slower than 60fps
                         let factor: f64 = (stat_counter.emulation_time.as_micros() as f64) / emulation_time_allowed_us as f64;
                         // Decrease speed by half of scaling factor
@@ -1126,11 +1405,11 @@ fn main() {
                         let new_target = (stat_counter.cycle_target as f64 / factor) as u32;
                         stat_counter.cycle_target += (new_target - old_target) / 2;
 
-                        if stat_counter.cycle_target > stat_counter.cycles_per_frame {
+                        if stat_counter.cycle_target > speed_cycles {
                             // Warpspeed runs entire emulator as fast as possible 
                             // TODO: Limit cycle target based on render/gui time to maintain 60fps GUI updates
-                            if !config.emulator.warpspeed {
-                                stat_counter.cycle_target = stat_counter.cycles_per_frame;
+                            if !unthrottled {
+                                stat_counter.cycle_target = speed_cycles;
                             }
                         }
                         else {
@@ -1167,9 +1446,14 @@ fn main() {
                             RenderMode::Direct => {
                                 (new_w, new_h) = video_card.get_display_aperture();
 
-                                // Set a sane maximum
-                                if new_h > 240 { 
-                                    new_h = 240;
+                                // Set a sane maximum. The EGA's aperture is limited to its display area.
+                                let max_h = match video_card.get_video_type() {
+                                    VideoType::MDA => mda::MDA_YRES,
+                                    VideoType::EGA | VideoType::VGA => new_h,
+                                    _ => 240
+                                };
+                                if new_h > max_h { 
+                                    new_h = max_h;
                                 }
                             }
                             RenderMode::Indirect => {
@@ -1231,13 +1515,23 @@ fn main() {
 
                     let render_start = Instant::now();
 
+                    // While fast-forwarding, skip rendering all but every Nth frame
+                    let skip_render = fast_forward && (stat_counter.frame_count % fast_forward_frameskip != 0);
+
                     // Draw video if there is a video card present
                     let bus = machine.bus_mut();
+                    let mut emulated_lines = video_data.render_h;
+                    let mut new_frame = false;
+
+                    if let (false, Some(video_card)) = (skip_render, bus.video()) {
 
-                    if let Some(video_card) = bus.video() {
+                        if video_card.get_scanline_double() {
+                            emulated_lines /= 2;
+                        }
 
                         if composite_enabled {
                             video_data.composite_params = framework.gui.composite_adjust.get_params().clone();
+                            video_data.composite_params.color_burst = video_card.get_color_burst();
                         }
 
                         let beam_pos;
@@ -1263,65 +1557,26 @@ fn main() {
                         }
 
                         // Get the render mode from the device and render appropriately
-                        match (video_card.get_video_type(), video_card.get_render_mode()) {
-
-                            (VideoType::CGA, RenderMode::Direct) => {
-                                // Draw device's front buffer in direct mode (CGA only for now)
-
-                                match aspect_correct {
-                                    true => {
-                                        video.draw_cga_direct(
-                                            &mut render_src,
-                                            video_data.render_w, 
-                                            video_data.render_h,                                             
-                                            video_buffer,
-                                            video_card.get_display_extents(),
-                                            composite_enabled,
-                                            &video_data.composite_params,
-                                            beam_pos
-                                        );
-
-                                        /*
-                                        marty_render::resize_linear(
-                                            &render_src, 
-                                            video_data.render_w, 
-                                            video_data.render_h, 
-                                            pixels.frame_mut(), 
-                                            video_data.aspect_w, 
-                                            video_data.aspect_h,
-                                            &resample_context
-                                        );
-                                        */
-                                        marty_render::resize_linear_fast(
-                                            &mut render_src, 
-                                            video_data.render_w, 
-                                            video_data.render_h, 
-                                            pixels.frame_mut(), 
-                                            video_data.aspect_w, 
-                                            video_data.aspect_h,
-                                            &mut resample_context
-                                        );
-
-                                    }
-                                    false => {
-                                        video.draw_cga_direct(
-                                            pixels.frame_mut(),
-                                            video_data.render_w, 
-                                            video_data.render_h,                                                                                         
-                                            video_buffer,
-                                            video_card.get_display_extents(),
-                                            composite_enabled,
-                                            &video_data.composite_params,
-                                            beam_pos                                         
-                                        );
-                                    }
-                                }
+                        match video_card.get_render_mode() {
+                            RenderMode::Direct => {
+                                // Cards in Direct Mode generate their own framebuffers. Hand the front
+                                // buffer to the render thread; the frame is displayed once it completes.
+                                video.submit(
+                                    video_card.get_video_type(),
+                                    video_data.render_w,
+                                    video_data.render_h,
+                                    video_buffer,
+                                    video_card.get_display_extents(),
+                                    composite_enabled,
+                                    &video_data.composite_params,
+                                    beam_pos
+                                );
                             }
-                            (_, RenderMode::Indirect) => {
+                            RenderMode::Indirect => {
                                 // Draw VRAM in indirect mode
                                 match aspect_correct {
                                     true => {
-                                        video.draw(&mut render_src, video_card, bus, composite_enabled);
+                                        video.renderer().draw(&mut render_src, video_card, bus, composite_enabled);
                                         marty_render::resize_linear(
                                             &render_src, 
                                             video_data.render_w, 
@@ -1333,15 +1588,115 @@ fn main() {
                                         );                            
                                     }
                                     false => {
-                                        video.draw(pixels.frame_mut(), video_card, bus, composite_enabled);
+                                        video.renderer().draw(pixels.frame_mut(), video_card, bus, composite_enabled);
                                     }
-                                }                                
+                                }
+                                new_frame = true;
+                            }
+                        }
+                    }
+
+                    // Display the latest frame from the render thread, if one has completed
+                    if let Some(frame) = video.take_frame() {
+                        // Frames rendered before a change in resolution are dropped
+                        if (frame.w, frame.h) == (video_data.render_w, video_data.render_h) {
+                            match aspect_correct {
+                                true => {
+                                    render_src.copy_from_slice(&frame.buf);
+                                    marty_render::resize_linear_fast(
+                                        &mut render_src, 
+                                        video_data.render_w, 
+                                        video_data.render_h, 
+                                        pixels.frame_mut(), 
+                                        video_data.aspect_w, 
+                                        video_data.aspect_h,
+                                        &mut resample_context
+                                    );
+                                }
+                                false => {
+                                    pixels.frame_mut()[..frame.buf.len()].copy_from_slice(&frame.buf);
+                                }
                             }
-                            _ => panic!("Invalid combination of VideoType and RenderMode")
+                            new_frame = true;
+                        }
+
+                        // Hand any completed composite capture to the GUI
+                        if let Some(capture) = frame.capture.take() {
+                            framework.gui.composite_capture.set_capture(capture);
                         }
+                        stat_counter.worker_render_time = frame.render_time;
+                    }
+
+                    // Apply CRT effects to the final display buffer. The display buffer is left
+                    // alone until a new frame arrives, so effects are only applied once per frame.
+                    if new_frame {
+                        let (display_w, display_h) = match aspect_correct {
+                            true => (video_data.aspect_w, video_data.aspect_h),
+                            false => (video_data.render_w, video_data.render_h),
+                        };
+                        crt.process(pixels.frame_mut(), display_w, display_h, emulated_lines);
                     }
+
                     stat_counter.render_time = Instant::now() - render_start;
 
+                    // Draw the secondary video card, if its window is open
+                    if let Some(renderer) = &mut secondary_video {
+                        let bus = machine.bus();
+                        if let (true, Some(card)) = (!skip_render && framework.gui.is_window_open(GuiWindow::SecondaryDisplay), bus.secondary_video()) {
+                            let (w, mut h) = match card.get_render_mode() {
+                                RenderMode::Direct => card.get_display_aperture(),
+                                RenderMode::Indirect => card.get_display_size()
+                            };
+                            if card.get_scanline_double() {
+                                h *= 2;
+                            }
+
+                            let frame = framework.gui.secondary_display.frame_mut(w, h);
+                            match card.get_render_mode() {
+                                RenderMode::Direct if card.get_video_type() == VideoType::EGA => {
+                                    renderer.draw_ega_direct(frame, w, h, card.get_display_buf(), card.get_display_extents());
+                                }
+                                RenderMode::Direct if card.get_video_type() == VideoType::VGA => {
+                                    renderer.draw_vga_direct(frame, w, h, card.get_display_buf(), card.get_display_extents());
+                                }
+                                RenderMode::Direct => {
+                                    renderer.draw_cga_direct(
+                                        frame,
+                                        w,
+                                        h,
+                                        card.get_display_buf(),
+                                        card.get_display_extents(),
+                                        false,
+                                        &video_data.composite_params,
+                                        None
+                                    );
+                                }
+                                RenderMode::Indirect => {
+                                    renderer.draw(frame, card, bus, false);
+                                }
+                            }
+                            VideoRenderer::set_alpha(frame, w, h, 255);
+                        }
+                    }
+
+                    // Add the rendered frame to any screen recording in progress
+                    if let Some(rec) = recorder.as_mut() {
+                        let frame: &[u8] = match aspect_correct {
+                            true => &render_src,
+                            false => pixels.frame(),
+                        };
+                        if let Err(e) = rec.add_frame(frame, video_data.render_w, video_data.render_h) {
+                            log::error!("Error writing screen recording: {}", e);
+                            if let Some(rec) = recorder.take() {
+                                match rec.finish() {
+                                    Ok(path) => log::info!("Saved screen recording: {}", path.display()),
+                                    Err(e) => log::error!("Error finishing screen recording: {}", e)
+                                }
+                            }
+                            framework.gui.set_recording(None);
+                        }
+                    }
+
                     // Update egui data
 
                     // Is the machine in an error state? If so, display an error dialog.
@@ -1360,7 +1715,14 @@ fn main() {
                             match gui_event {
                                 GuiEvent::Exit => {
                                     // User chose exit option from menu. Shut down.
-                                    // TODO: Add a timeout from last VHD write for safety?
+                                    machine.flush_disks();
+                                    machine.finish_movie();
+                                    for instance in &mut instances {
+                                        instance.close();
+                                    }
+                                    if persist_layout {
+                                        save_gui_layout(&framework, &gui_layout_path);
+                                    }
                                     println!("Thank you for using MartyPC!");
                                     *control_flow = ControlFlow::Exit;
                                 }
@@ -1386,23 +1748,57 @@ fn main() {
                                         (GuiOption::CpuTraceLoggingEnabled, state) => {
                                             machine.set_cpu_option(CpuOption::TraceLoggingEnabled(state));
                                         }
+                                        (GuiOption::CpuHaltSkip, state) => {
+                                            machine.set_halt_skip(state);
+                                        }
                                         (GuiOption::TurboButton, state) => {
                                             machine.set_turbo_mode(state);
                                         }
-                                        _ => {}
-                                    }
-                                }
-    
-                                GuiEvent::CreateVHD(filename, fmt) => {
-                                    log::info!("Got CreateVHD event: {:?}, {:?}", filename, fmt);
-    
-                                    let vhd_path = hdd_path.join(filename);
-    
-                                    match vhd::create_vhd(
+                                        (GuiOption::WarpSpeed, state) => {
+                                            warp = state;
+                                            warp_boot_pending = false;
+                                        }
+                                        (GuiOption::FreezeBlink, state) => {
+                                            if let Some(video_card) = machine.videocard() {
+                                                video_card.set_blink_frozen(state);
+                                            }
+                                        }
+                                        (GuiOption::WriteProtectDriveA, state) => {
+                                            if let Some(fdc) = machine.fdc() {
+                                                fdc.set_write_protect(0, state);
+                                            }
+                                        }
+                                        (GuiOption::WriteProtectDriveB, state) => {
+                                            if let Some(fdc) = machine.fdc() {
+                                                fdc.set_write_protect(1, state);
+                                            }
+                                        }
+                                        (GuiOption::DriveSounds, state) => {
+                                            machine.set_drive_sounds(state);
+                                        }
+                                        (GuiOption::PauseOnFocusLoss, state) => {
+                                            run_state.set_pause_on_focus_loss(state);
+                                        }
+                                        _ => {}
+                                    }
+                                }
+    
+                                GuiEvent::CreateVHD(filename, fmt, formatted) => {
+                                    log::info!("Got CreateVHD event: {:?}, {:?}, formatted: {}", filename, fmt, formatted);
+    
+                                    let vhd_path = hdd_path.join(filename);
+                                    let data = disk_image::create_hard_disk_image(
+                                        fmt.max_cylinders,
+                                        fmt.max_heads,
+                                        fmt.max_sectors,
+                                        formatted);
+    
+                                    match vhd::create_vhd_from(
                                         vhd_path.into_os_string(), 
                                         fmt.max_cylinders, 
                                         fmt.max_heads, 
-                                        fmt.max_sectors) {
+                                        fmt.max_sectors,
+                                        &data) {
     
                                         Ok(_) => {
                                             // We don't actually do anything with the newly created file
@@ -1414,6 +1810,23 @@ fn main() {
                                         }
                                         Err(err) => {
                                             log::error!("Error creating VHD: {}", err);
+                                            framework.gui.show_error(&format!("Error creating VHD: {}", err));
+                                        }
+                                    }
+                                }
+                                GuiEvent::CreateFloppy(filename, format, formatted) => {
+                                    log::info!("Got CreateFloppy event: {:?}, {:?}, formatted: {}", filename, format, formatted);
+
+                                    let image_path = floppy_path.join(filename);
+                                    match disk_image::write_floppy_image(&image_path, format, formatted) {
+                                        Ok(_) => {
+                                            if let Err(e) = floppy_manager.scan_dir(&floppy_path) {
+                                                log::error!("Error scanning floppy directory: {}", e);
+                                            }
+                                        }
+                                        Err(err) => {
+                                            log::error!("Error creating floppy image: {}", err);
+                                            framework.gui.show_error(&format!("Error creating floppy image: {}", err));
                                         }
                                     }
                                 }
@@ -1427,6 +1840,13 @@ fn main() {
                                 }
                                 GuiEvent::LoadFloppy(drive_select, filename) => {
                                     log::debug!("Load floppy image: {:?} into drive: {}", filename, drive_select);
+
+                                    let floppy_image_path = floppy_manager.get_floppy_path(&filename)
+                                        .unwrap_or_else(|| PathBuf::from(&filename));
+                                    if !machine.accept_input(MovieEvent::LoadFloppy(drive_select, floppy_image_path.clone())) {
+                                        log::warn!("Can't change floppy images while a movie is playing.");
+                                        continue;
+                                    }
     
                                     match floppy_manager.load_floppy_data(&filename) {
                                         Ok(vec) => {
@@ -1435,9 +1855,24 @@ fn main() {
                                                 match fdc.load_image_from(drive_select, vec) {
                                                     Ok(()) => {
                                                         log::info!("Floppy image successfully loaded into virtual drive.");
+                                                        fdc.set_image_path(drive_select, floppy_manager.get_floppy_path(&filename));
+
+                                                        recent_floppies.add(drive_select, floppy_image_path.clone());
+                                                        if let Err(e) = recent_floppies.save(&recent_floppies_path) {
+                                                            log::warn!("Couldn't save list of recent floppy images: {}", e);
+                                                        }
+                                                        framework.gui.media_manager.set_recent(drive_select, recent_floppies.get(drive_select));
+
+                                                        framework.gui.set_floppy_status(
+                                                            drive_select, 
+                                                            Some(filename.clone()), 
+                                                            fdc.get_media_desc(drive_select)
+                                                        );
                                                     }
                                                     Err(err) => {
                                                         log::warn!("Floppy image failed to load: {}", err);
+                                                        framework.gui.set_floppy_status(drive_select, None, None);
+                                                        framework.gui.show_error(&format!("Floppy image failed to load: {}", err));
                                                     }
                                                 }
                                             }
@@ -1449,6 +1884,41 @@ fn main() {
                                         }
                                     }                                
                                 }
+                                GuiEvent::MountDirectory(drive_select, dir, write_back) => {
+                                    log::debug!("Mount directory: {} into drive: {} write back: {}", dir.display(), drive_select, write_back);
+
+                                    // Directory mounts can't be replayed, so aren't recorded in movies.
+                                    if machine.movie_playing() {
+                                        log::warn!("Can't change floppy images while a movie is playing.");
+                                        continue;
+                                    }
+
+                                    if let Some(fdc) = machine.fdc() {
+                                        let format = virtual_floppy::format_for_drive(fdc.drive_type(drive_select));
+                                        let result = VirtualFloppy::build(&dir, format, write_back)
+                                            .map_err(|e| e.to_string())
+                                            .and_then(|(virtual_floppy, image)| {
+                                                fdc.load_image_from(drive_select, image)?;
+                                                fdc.set_virtual_floppy(drive_select, Some(virtual_floppy));
+                                                Ok(())
+                                            });
+                                        match result {
+                                            Ok(()) => {
+                                                log::info!("Directory {} mounted as {} floppy.", dir.display(), format.desc());
+                                                let name = dir.file_name().map(|n| n.to_os_string()).unwrap_or_else(|| dir.clone().into_os_string());
+                                                framework.gui.set_floppy_status(
+                                                    drive_select, 
+                                                    Some(name), 
+                                                    fdc.get_media_desc(drive_select)
+                                                );
+                                            }
+                                            Err(err) => {
+                                                log::warn!("Directory failed to mount: {}", err);
+                                                framework.gui.show_error(&format!("Couldn't mount directory {}: {}", dir.display(), err));
+                                            }
+                                        }
+                                    }
+                                }
                                 GuiEvent::SaveFloppy(drive_select, filename) => {
                                     log::debug!("Save floppy image: {:?} into drive: {}", filename, drive_select);
 
@@ -1469,6 +1939,10 @@ fn main() {
                                 }
                                 GuiEvent::EjectFloppy(drive_select) => {
                                     log::info!("Ejecting floppy in drive: {}", drive_select);
+                                    if !machine.accept_input(MovieEvent::EjectFloppy(drive_select)) {
+                                        log::warn!("Can't change floppy images while a movie is playing.");
+                                        continue;
+                                    }
                                     if let Some(fdc) = machine.fdc() {
                                         fdc.unload_image(drive_select);
                                     }
@@ -1478,39 +1952,53 @@ fn main() {
                                     log::info!("Bridging serial port: {}", port_name);
                                     machine.bridge_serial_port(1, port_name);
                                 }
+                                GuiEvent::BridgeSerialTcp(target, telnet) => {
+                                    log::info!("Bridging serial port to TCP: {:?}", target);
+                                    machine.bridge_serial_tcp(1, target, telnet);
+                                }
+                                GuiEvent::UnbridgeSerialPort => {
+                                    machine.unbridge_serial_port(1);
+                                }
                                GuiEvent::DumpVRAM => {
                                     if let Some(video_card) = machine.videocard() {
-                                        let mut dump_path = PathBuf::new();
-                                        dump_path.push(config.emulator.basedir.clone());
-                                        dump_path.push("dumps");
-                                        video_card.dump_mem(&dump_path);
+                                        video_card.dump_mem(&artifacts.dir(ArtifactKind::Dump));
                                     }
                                 }
                                 GuiEvent::DumpCS => {
-                                    let mut dump_path = PathBuf::new();
-                                    dump_path.push(config.emulator.basedir.clone());
-                                    dump_path.push("dumps");
+                                    let dump_path = artifacts.dir(ArtifactKind::Dump);
                                                                     
                                     machine.cpu().dump_cs(&dump_path);
                                 }
                                 GuiEvent::DumpAllMem => {
-                                    let mut dump_path = PathBuf::new();
-                                    dump_path.push(config.emulator.basedir.clone());
-                                    dump_path.push("dumps");
+                                    let dump_path = artifacts.dir(ArtifactKind::Dump);
                                                                                                     
                                     machine.bus().dump_mem(&dump_path);
                                 }
                                 GuiEvent::EditBreakpoint => {
+                                    // Parse the exec breakpoint condition, if one was entered
+                                    let bp_cond_str = framework.gui.cpu_control.get_breakpoint_condition().trim().to_string();
+                                    let condition = match bp_cond_str.is_empty() {
+                                        true => Ok(None),
+                                        false => BreakPointCondition::parse(&bp_cond_str).map(Some),
+                                    };
+
                                     // Get breakpoints from GUI
                                     let (bp_str, bp_mem_str, bp_int_str) = framework.gui.get_breakpoints();
     
                                     let mut breakpoints = Vec::new();
     
-                                    // Push exec breakpoint to list if valid expression
+                                    // Push exec breakpoint to list if valid expression, with its condition
                                     if let Some(addr) = machine.cpu().eval_address(&bp_str) {
                                         let flat_addr = u32::from(addr);
                                         if flat_addr > 0 && flat_addr < 0x100000 {
-                                            breakpoints.push(BreakPointType::ExecuteFlat(flat_addr));
+                                            match &condition {
+                                                Ok(Some(condition)) => {
+                                                    breakpoints.push(BreakPointType::ExecuteFlatIf(flat_addr, condition.clone()));
+                                                }
+                                                // Don't break unconditionally on an invalid condition
+                                                Ok(None) => breakpoints.push(BreakPointType::ExecuteFlat(flat_addr)),
+                                                Err(_) => {}
+                                            }
                                         }
                                     };
                                 
@@ -1529,6 +2017,20 @@ fn main() {
                                         }
                                     }
 
+                                    // Push watchpoint to list if valid expression. Length defaults to 1.
+                                    let (watch_str, watch_len_str, watch_type) = framework.gui.cpu_control.get_watchpoint();
+                                    let watch_len = match watch_len_str.trim() {
+                                        "" => Some(1),
+                                        len_str => u32::from_str_radix(len_str, 16).ok().filter(|len| *len > 0),
+                                    };
+                                    if let (Some(addr), Some(len)) = (machine.cpu().eval_address(watch_str), watch_len) {
+                                        let flat_addr = u32::from(addr);
+                                        if flat_addr < 0x100000 {
+                                            breakpoints.push(BreakPointType::Watch(flat_addr, len, watch_type));
+                                        }
+                                    }
+                                    framework.gui.cpu_control.set_condition_error(condition.err());
+
                                     machine.set_breakpoints(breakpoints);
                                 }
                                 GuiEvent::MemoryUpdate => {
@@ -1546,6 +2048,93 @@ fn main() {
                                     };
                                     framework.gui.memory_viewer.set_row(mem_dump_addr as usize);                                    
                                 }
+                                GuiEvent::MemoryEdit(addr, data) => {
+                                    match machine.bus_mut().edit_u8(addr, data) {
+                                        Ok(_) => framework.gui.memory_viewer.set_status(String::new()),
+                                        Err(e) => framework.gui.memory_viewer.set_status(format!("Can't edit {:05X}: {}", addr, e)),
+                                    }
+                                }
+                                GuiEvent::MemorySearch(pattern_str, direction) => {
+                                    match parse_pattern(&pattern_str) {
+                                        Ok(pattern) => {
+                                            // Continue from the edit cursor, or the top of the view if there is none.
+                                            let start = match (framework.gui.memory_viewer.cursor(), direction) {
+                                                (Some(cursor), SearchDirection::Forward) => cursor + 1,
+                                                (Some(cursor), SearchDirection::Backward) => cursor,
+                                                (None, _) => framework.gui.memory_viewer.row,
+                                            };
+                                            match machine.bus().search(&pattern, start, direction) {
+                                                Some(found) => {
+                                                    framework.gui.memory_viewer.set_cursor(found);
+                                                    framework.gui.memory_viewer.set_status(format!("Found at {:05X}", found));
+                                                }
+                                                None => framework.gui.memory_viewer.set_status("Pattern not found.".to_string()),
+                                            }
+                                        }
+                                        Err(e) => framework.gui.memory_viewer.set_status(format!("{}", e)),
+                                    }
+                                }
+                                GuiEvent::SetCoverage(state) => {
+                                    machine.bus_mut().set_coverage_enabled(state);
+                                }
+                                GuiEvent::ClearCoverage => {
+                                    machine.bus_mut().clear_coverage();
+                                }
+                                GuiEvent::ExportCoverage => {
+                                    // Save a readable report of the covered ranges along with the raw map, which 
+                                    // has a byte of flags for each byte of the address space.
+                                    let dump_path = artifacts.dir(ArtifactKind::Dump);
+                                    let report_filename = file_util::find_unique_filename(&dump_path, "coverage", "txt");
+                                    let map_filename = report_filename.with_extension("bin");
+                                    let coverage = machine.bus().coverage();
+                                    let mut report = Vec::new();
+                                    let result = coverage.write_report(&mut report)
+                                        .and_then(|_| std::fs::write(&report_filename, report))
+                                        .and_then(|_| std::fs::write(&map_filename, coverage.as_bytes()));
+                                    let status = match result {
+                                        Ok(_) => format!("Saved coverage: {}", report_filename.display()),
+                                        Err(e) => format!("Error writing coverage: {}: {}", report_filename.display(), e)
+                                    };
+                                    log::info!("{}", status);
+                                    framework.gui.memory_viewer.set_status(status);
+                                }
+                                GuiEvent::PatchCode(address_str, text, hex) => {
+                                    // Relative branches are assembled against the offset of the patch address. A flat
+                                    // address is taken to be in the segment of its 64K block.
+                                    let location = match machine.cpu().eval_address(&address_str) {
+                                        Some(CpuAddress::Segmented(segment, offset)) => Some((Cpu::calc_linear_address(segment, offset), offset)),
+                                        Some(address) => {
+                                            let flat: u32 = address.into();
+                                            Some((flat, flat as u16))
+                                        }
+                                        None => None
+                                    };
+                                    let status = match location {
+                                        Some((flat, ip)) => {
+                                            let bytes = match hex {
+                                                true => parse_pattern(&text)
+                                                    .map_err(|e| e.to_string())
+                                                    .and_then(|pattern| pattern.into_iter().collect::<Option<Vec<u8>>>()
+                                                        .ok_or_else(|| "Wildcards can't be patched.".to_string())),
+                                                false => assembler::assemble(&text, ip).map_err(|e| e.to_string()),
+                                            };
+                                            match bytes {
+                                                Ok(bytes) => match machine.patch_memory(flat as usize, &bytes) {
+                                                    Ok(_) => format!("Patched {} bytes at {:05X}", bytes.len(), flat),
+                                                    Err(e) => format!("Can't patch {:05X}: {}", flat, e),
+                                                },
+                                                Err(e) => e,
+                                            }
+                                        }
+                                        None => format!("Invalid patch address: {}", address_str)
+                                    };
+                                    framework.gui.disassembly_viewer.set_status(status);
+                                }
+                                GuiEvent::UndoPatch => {
+                                    if let Some(address) = machine.undo_patch() {
+                                        framework.gui.disassembly_viewer.set_status(format!("Undid patch at {:05X}", address));
+                                    }
+                                }
                                 GuiEvent::TokenHover(addr) => {
                                     // Hovered over a token in a TokenListView.
                                     let debug = machine.bus_mut().get_memory_debug(addr);
@@ -1582,14 +2171,32 @@ fn main() {
                                         }
                                         _ => {}
                                     }
+                                    run_state.user_state_change();
                                     machine.change_state(state);
                                 }
+                                GuiEvent::NewInstance => {
+                                    match MachineInstance::new(next_instance, window_target, &config, rom_manager.clone()) {
+                                        Ok(instance) => {
+                                            instances.push(instance);
+                                            next_instance += 1;
+                                        }
+                                        Err(e) => {
+                                            log::error!("Couldn't start machine instance: {}", e);
+                                            framework.gui.show_error(&format!("Couldn't start machine: {}", e));
+                                        }
+                                    }
+                                }
+                                GuiEvent::SetFullscreen(fullscreen) => {
+                                    let fullscreen = set_fullscreen(&window, fullscreen);
+                                    if let Some(mode) = fullscreen {
+                                        fullscreen_mode = mode;
+                                    }
+                                    framework.gui.set_fullscreen(fullscreen);
+                                }
                                 GuiEvent::TakeScreenshot => {
-                                    let mut screenshot_path = PathBuf::new();
-                                    screenshot_path.push(config.emulator.basedir.clone());
-                                    screenshot_path.push("screenshots");
+                                    let screenshot_path = artifacts.dir(ArtifactKind::Screenshot);
 
-                                    video.screenshot(
+                                    video.renderer().screenshot(
                                         &mut render_src,
                                         video_data.render_w, 
                                         video_data.render_h, 
@@ -1597,9 +2204,274 @@ fn main() {
                                     );
 
                                 }
+                                GuiEvent::StartRecording(format) => {
+                                    let capture_path = artifacts.dir(ArtifactKind::Capture);
+                                    match ScreenRecorder::start(&capture_path, format, video_data.render_w, video_data.render_h, 60) {
+                                        Ok(rec) => {
+                                            log::info!("Started screen recording: {}", rec.path().display());
+                                            recorder = Some(rec);
+                                            framework.gui.set_recording(Some(format));
+                                        }
+                                        Err(e) => log::error!("Error starting screen recording: {}", e)
+                                    }
+                                }
+                                GuiEvent::StopRecording => {
+                                    if let Some(rec) = recorder.take() {
+                                        match rec.finish() {
+                                            Ok(path) => log::info!("Saved screen recording: {}", path.display()),
+                                            Err(e) => log::error!("Error finishing screen recording: {}", e)
+                                        }
+                                    }
+                                    framework.gui.set_recording(None);
+                                }
+                                GuiEvent::DumpInstructionHistory => {
+                                    let dump_path = artifacts.dir(ArtifactKind::Dump);
+                                    let filename = file_util::find_unique_filename(&dump_path, "history", "txt");
+                                    match std::fs::write(&filename, machine.cpu().dump_instruction_history_string()) {
+                                        Ok(_) => log::info!("Saved instruction history: {}", filename.display()),
+                                        Err(e) => log::error!("Error writing instruction history: {}: {}", filename.display(), e)
+                                    }
+                                }
+                                GuiEvent::ExportValidatorStats => {
+                                    if let Some(stats) = machine.cpu().validator_stats() {
+                                        let validator_path = artifacts.dir(ArtifactKind::Validator);
+                                        let filename = file_util::find_unique_filename(&validator_path, "validator_stats", "csv");
+                                        match std::fs::write(&filename, stats.to_csv()) {
+                                            Ok(_) => log::info!("Saved validator statistics: {}", filename.display()),
+                                            Err(e) => log::error!("Error writing validator statistics: {}: {}", filename.display(), e)
+                                        }
+                                    }
+                                }
+                                GuiEvent::SetPalette(palette) => {
+                                    video_data.palette = palette;
+                                    video.renderer().set_palette(palette, custom_palette.as_ref());
+                                    if let Some(renderer) = &mut secondary_video {
+                                        renderer.set_palette(palette, custom_palette.as_ref());
+                                    }
+                                }
+                                GuiEvent::SetMonitor(monitor) => {
+                                    video_data.monitor = monitor;
+                                    video_data.monochrome = monitor.phosphor(phosphor);
+                                    video.renderer().set_monochrome(video_data.monochrome);
+                                }
+                                GuiEvent::SetPhosphor(new_phosphor) => {
+                                    phosphor = new_phosphor;
+                                    video_data.monochrome = video_data.monitor.phosphor(phosphor);
+                                    video.renderer().set_monochrome(video_data.monochrome);
+                                    if let (Some(renderer), Some(video_type)) = (&mut secondary_video, config.machine.secondary_video) {
+                                        renderer.set_monochrome(MonitorType::default_for(video_type).phosphor(phosphor));
+                                    }
+                                }
+                                GuiEvent::SetCrtParams(params) => {
+                                    crt.set_params(params);
+                                }
+                                GuiEvent::StartTraceSession(name, kind, filter) => {
+                                    let trace_path = artifacts.dir(ArtifactKind::Trace);
+                                    let filename = file_util::find_unique_filename(&trace_path, &name, "log");
+                                    let logger = TraceLogger::from_filename(&filename);
+                                    if logger.is_some() {
+                                        log::info!("Started {} trace session {}: {}", kind, name, filename.display());
+                                        machine.trace_sessions_mut().start(&name, kind, filter, logger, Some(filename));
+                                    }
+                                    else {
+                                        log::error!("Couldn't create trace file: {}", filename.display());
+                                    }
+                                }
+                                GuiEvent::PauseTraceSession(id, paused) => {
+                                    machine.trace_sessions_mut().set_paused(id, paused);
+                                }
+                                GuiEvent::StopTraceSession(id) => {
+                                    machine.trace_sessions_mut().stop(id);
+                                }
+                                GuiEvent::RemoveTraceSession(id) => {
+                                    machine.trace_sessions_mut().remove(id);
+                                }
+                                GuiEvent::SetTimelineRecording(state) => {
+                                    machine.set_timeline_enabled(state);
+                                }
+                                GuiEvent::ClearTimeline => {
+                                    machine.clear_timeline();
+                                }
+                                GuiEvent::SetProfiler(state) => {
+                                    machine.set_profiler_enabled(state);
+                                }
+                                GuiEvent::ClearProfiler => {
+                                    machine.clear_profiler();
+                                }
+                                GuiEvent::ExportProfile => {
+                                    // Call stacks are saved in the folded format read by flamegraph.pl and 
+                                    // compatible tools.
+                                    let dump_path = artifacts.dir(ArtifactKind::Dump);
+                                    let filename = file_util::find_unique_filename(&dump_path, "profile", "folded");
+                                    let mut folded = Vec::new();
+                                    let result = machine.profiler().write_folded(&mut folded)
+                                        .and_then(|_| std::fs::write(&filename, folded));
+                                    match result {
+                                        Ok(_) => log::info!("Saved profile: {}", filename.display()),
+                                        Err(e) => log::error!("Error writing profile: {}: {}", filename.display(), e)
+                                    }
+                                }
+                                GuiEvent::ResetValidatorStats => {
+                                    machine.reset_validator_stats();
+                                }
+                                GuiEvent::SetSpeed(speed) => {
+                                    machine.set_speed(speed);
+                                }
+                                GuiEvent::SetCpuClock(clock) => {
+                                    machine.set_cpu_clock(clock);
+                                }
+                                GuiEvent::SaveScreenText => {
+                                    match machine.screen_text() {
+                                        Some(text) => {
+                                            let capture_path = artifacts.dir(ArtifactKind::Capture);
+                                            let filename = file_util::find_unique_filename(&capture_path, "screen", "txt");
+                                            match std::fs::write(&filename, text) {
+                                                Ok(_) => log::info!("Saved screen text: {}", filename.display()),
+                                                Err(e) => log::error!("Error writing screen text: {}: {}", filename.display(), e)
+                                            }
+                                        }
+                                        None => {
+                                            framework.gui.show_error(&"Screen text can only be saved in a text mode.".to_string());
+                                        }
+                                    }
+                                }
+                                GuiEvent::OpenArtifactDir => {
+                                    open_host_path(artifacts.run_dir());
+                                }
+                                GuiEvent::OpenLatestArtifact(kind) => {
+                                    match artifacts.latest(kind) {
+                                        Some(path) => open_host_path(&path),
+                                        None => {
+                                            framework.gui.show_error(&format!("No {} have been saved during this run.", kind.subdir()));
+                                        }
+                                    }
+                                }
+                                GuiEvent::ShowHelp(topic) => {
+                                    framework.gui.show_help(topic);
+                                }
+                                GuiEvent::CreateBugReport => {
+                                    match bug_report::BugReport::new(&artifacts.dir(ArtifactKind::BugReport)) {
+                                        Ok(report) => {
+                                            if let Err(e) = report.add_image(
+                                                "display.png", 
+                                                &render_src, 
+                                                video_data.render_w, 
+                                                video_data.render_h) 
+                                            {
+                                                log::error!("Error saving display image to bug report: {}", e);
+                                            }
+                                            if let Err(e) = report.add_text("config.txt", &format!("{:#?}", config)) {
+                                                log::error!("Error saving config to bug report: {}", e);
+                                            }
+
+                                            let open_windows = framework.gui.open_windows();
+                                            let mut window_list = String::new();
+                                            for window in open_windows {
+                                                window_list.push_str(&format!("{:?}\n", window));
+                                                if let Some(text) = bug_report::window_text(&mut machine, window) {
+                                                    if let Err(e) = report.add_text(&format!("{:?}.txt", window), &text) {
+                                                        log::error!("Error saving {:?} to bug report: {}", window, e);
+                                                    }
+                                                }
+                                            }
+                                            if let Err(e) = report.add_text("windows.txt", &window_list) {
+                                                log::error!("Error saving window list to bug report: {}", e);
+                                            }
+
+                                            log::info!("Saved bug report to {}", report.dir().display());
+                                        }
+                                        Err(e) => {
+                                            log::error!("Error creating bug report folder: {}", e);
+                                        }
+                                    }
+                                }
+                                GuiEvent::CaptureComposite => {
+                                    if framework.gui.get_composite_enabled() {
+                                        video.renderer().request_composite_capture();
+                                    }
+                                    else {
+                                        log::warn!("Composite capture requires the composite monitor to be enabled.");
+                                    }
+                                }
+                                GuiEvent::SaveCompositeCapture => {
+                                    if let Some(capture) = framework.gui.composite_capture.capture() {
+                                        let capture_path = artifacts.dir(ArtifactKind::Capture);
+
+                                        VideoRenderer::save_composite_capture(capture, &capture_path);
+                                    }
+                                }
+                                GuiEvent::RunScript(source) => {
+                                    framework.gui.script_console.set_running(true, "Running");
+                                    script_host = Some(ScriptHost::start(source));
+                                    script_from_cli = false;
+                                }
+                                GuiEvent::StopScript => {
+                                    if let Some(mut host) = script_host.take() {
+                                        host.stop();
+                                    }
+                                    framework.gui.script_console.set_running(false, "Stopped");
+                                }
+                                GuiEvent::SaveTiles(tile) => {
+                                    let capture_path = artifacts.dir(ArtifactKind::Capture);
+
+                                    match framework.gui.tile_ripper.sheet().save_png(&capture_path, tile) {
+                                        Ok(filename) => log::info!("Saved tiles: {}", filename.display()),
+                                        Err(e) => log::error!("Error saving tiles: {}", e)
+                                    }
+                                }
                                 GuiEvent::CtrlAltDel => {
                                     machine.ctrl_alt_del();
                                 }
+                                GuiEvent::PasteText(text) => {
+                                    let skipped = machine.paste_text(&text);
+                                    let status = match skipped {
+                                        0 => "Done".to_string(),
+                                        n => format!("Done; skipped {} characters that can't be typed", n)
+                                    };
+                                    framework.gui.paste_text.set_status(&status);
+                                }
+                                GuiEvent::CancelPaste => {
+                                    machine.cancel_paste();
+                                    framework.gui.paste_text.set_status("Stopped");
+                                }
+                                GuiEvent::SaveState(slot) => {
+                                    let state_path = state_slot_path(&config.emulator.basedir, slot);
+
+                                    match machine.save_state_file(&state_path) {
+                                        Ok(()) => log::info!("Saved state to {}", state_path.display()),
+                                        Err(e) => log::error!("Failed to save state: {}", e)
+                                    }
+                                }
+                                GuiEvent::LoadState(slot) => {
+                                    let state_path = state_slot_path(&config.emulator.basedir, slot);
+
+                                    match machine.load_state_file(&state_path) {
+                                        Ok(()) => {
+                                            log::info!("Loaded state from {}", state_path.display());
+                                            if let Some(video_card) = machine.videocard() {
+                                                stat_counter.emulated_frames = video_card.get_frame_count();
+                                            }
+                                        }
+                                        Err(e) => log::error!("Failed to load state: {}", e)
+                                    }
+                                }
+                                GuiEvent::UnbindHotkey(action) => {
+                                    hotkeys.unbind(action);
+                                    save_hotkeys(&mut framework, &hotkeys, &hotkey_path);
+                                }
+                                GuiEvent::ResetHotkeys => {
+                                    hotkeys = HotkeyManager::default();
+                                    save_hotkeys(&mut framework, &hotkeys, &hotkey_path);
+                                }
+                                GuiEvent::Rewind(frames) => {
+                                    if machine.rewind(frames) {
+                                        // The video card's frame counter was rewound with the 
+                                        // rest of the machine.
+                                        if let Some(video_card) = machine.videocard() {
+                                            stat_counter.emulated_frames = video_card.get_frame_count();
+                                        }
+                                    }
+                                }
                                 _ => {}
                             }
                         }
@@ -1610,19 +2482,35 @@ fn main() {
 
                     // -- Update machine state
                     framework.gui.set_machine_state(machine.get_state());
+                    framework.gui.set_rewind_depth(machine.rewind_depth());
+                    framework.gui.set_guest_os(machine.guest_os().to_string());
+                    framework.gui.set_speed(machine.speed());
+                    framework.gui.status_bar.update_activity(&machine.take_disk_activity());
+                    framework.gui.set_cpu_clock(machine.cpu_clock());
+                    framework.gui.paste_text.set_remaining(machine.paste_remaining());
+                    framework.gui.set_serial_bridge(machine.serial_bridge_description(1));
+                    framework.gui.set_option(GuiOption::TurboButton, machine.turbo_mode());
+
+                    // -- Silence audio while the machine is paused
+                    match run_state.update_audio(machine.get_state()) {
+                        Some(true) => machine.pause_sound_buffer(),
+                        Some(false) => machine.play_sound_buffer(),
+                        None => {}
+                    }
+
+                    // -- End warpspeed once the guest OS has booted
+                    if warp_boot_pending && machine.guest_os() != GuestOs::Unknown {
+                        log::info!("Guest OS detected: {}. Ending warpspeed boot.", machine.guest_os());
+                        warp = false;
+                        warp_boot_pending = false;
+                        framework.gui.set_option(GuiOption::WarpSpeed, false);
+                    }
 
                     // -- Update list of floppies
                     let name_vec = floppy_manager.get_floppy_names();
                     framework.gui.set_floppy_names(name_vec);
-
-                    // -- Update VHD Creator window
-                    if framework.gui.is_window_open(egui::GuiWindow::VHDCreator) {
-                        if let Some(hdc) = machine.hdc() {
-                            framework.gui.update_vhd_formats(hdc.get_supported_formats());
-                        }
-                        else {
-                            log::error!("Couldn't query available formats: No Hard Disk Controller present!");
-                        }
+                    if framework.gui.is_window_open(egui::GuiWindow::MediaManager) {
+                        framework.gui.media_manager.set_images(floppy_manager.get_floppy_images());
                     }
 
                     // -- Update list of VHD images
@@ -1644,18 +2532,13 @@ fn main() {
                                     match VirtualHardDisk::from_file(vhd_file) {
                                         Ok(vhd) => {
 
-                                            if let Some(hdc) = machine.hdc() {
-                                                match hdc.set_vhd(i as usize, vhd) {
-                                                    Ok(_) => {
-                                                        log::info!("VHD image {:?} successfully loaded into virtual drive: {}", new_vhd_name, i);
-                                                    }
-                                                    Err(err) => {
-                                                        log::error!("Error mounting VHD: {}", err);
-                                                    }
+                                            match machine.mount_vhd(i as usize, vhd) {
+                                                Ok(_) => {
+                                                    log::info!("VHD image {:?} successfully loaded into virtual drive: {}", new_vhd_name, i);
+                                                }
+                                                Err(err) => {
+                                                    log::error!("Error mounting VHD: {}", err);
                                                 }
-                                            }
-                                            else {
-                                                log::error!("No Hard Disk Controller present!");
                                             }
                                         },
                                         Err(err) => {
@@ -1684,9 +2567,14 @@ fn main() {
                                 current_cps: stat_counter.current_cps,
                                 current_tps: stat_counter.current_sys_tps,
                                 current_ips: stat_counter.current_ips,
+                                frame_time: last_present_interval,
                                 emulation_time: stat_counter.emulation_time,
                                 render_time: stat_counter.render_time,
-                                gui_time: Default::default()
+                                worker_render_time: stat_counter.worker_render_time,
+                                gui_time: Default::default(),
+                                guest_idle_frames: machine.idle_frames(),
+                                speed_multiplier: stat_counter.current_cps as f64 / (machine.get_cpu_mhz() * 1000000.0),
+                                fast_forward,
                             }
                         )
                     }
@@ -1704,18 +2592,106 @@ fn main() {
                             None => (0,0)
                         };
 
-                        let mem_dump_vec = machine.bus().dump_flat_tokens(mem_dump_addr as usize, addr as usize, 256);
+                        // Show the edit cursor if there is one, otherwise the cursor marks the entered address.
+                        let cursor = framework.gui.memory_viewer.cursor().unwrap_or(addr as usize);
+                        let mem_dump_vec = machine.bus().dump_flat_tokens(mem_dump_addr as usize, cursor, 256);
                     
                         //framework.gui.memory_viewer.set_row(mem_dump_addr as usize);
                         framework.gui.memory_viewer.set_memory(mem_dump_vec);
+                        framework.gui.memory_viewer.set_regions(&machine.bus().memory_regions());
+                        if framework.gui.memory_viewer.show_coverage() {
+                            let coverage = machine.bus().coverage();
+                            framework.gui.memory_viewer.set_coverage(
+                                mem_dump_addr as usize,
+                                coverage.flags(mem_dump_addr as usize, 256),
+                                coverage.summary()
+                            );
+                        }
                     }   
 
+                    // -- Update tile ripper window if open
+                    if framework.gui.is_window_open(egui::GuiWindow::TileRipper) {
+                        let layout = framework.gui.tile_ripper.layout();
+                        let skip = framework.gui.tile_ripper.skip();
+
+                        let palette = match machine.videocard() {
+                            Some(card) => marty_render::ripper_palette(Some(&**card), layout.format),
+                            None => marty_render::ripper_palette(None, layout.format)
+                        };
+
+                        let sheet = match framework.gui.tile_ripper.source() {
+                            egui::TileRipSource::Memory => {
+                                let addr_str = framework.gui.tile_ripper.get_address();
+                                let addr: u32 = match machine.cpu().eval_address(&addr_str) {
+                                    Some(i) => i.into(),
+                                    None => 0
+                                };
+
+                                let bus = machine.bus();
+                                let start = (addr as usize + skip).min(bus.size());
+                                let data = bus.get_slice_at(start, bus.size() - start);
+                                Some(marty_render::rip_tiles(&TileSource::Linear(data), 0, &layout, &palette))
+                            }
+                            egui::TileRipSource::VideoMemory => {
+                                machine.videocard().map(|card| {
+                                    let planes = [0, 1, 2, 3].map(|p| card.get_plane_slice(p));
+                                    let source = match layout.format {
+                                        TileFormat::Planar4 => TileSource::Planes(planes),
+                                        _ => TileSource::Linear(planes[0])
+                                    };
+                                    marty_render::rip_tiles(&source, skip, &layout, &palette)
+                                })
+                            }
+                        };
+
+                        if let Some(sheet) = sheet {
+                            framework.gui.tile_ripper.set_sheet(sheet);
+                        }
+                    }
+
                     // -- Update IVR viewer window if open
                     if framework.gui.is_window_open(egui::GuiWindow::IvrViewer) {
                         let vec = machine.bus_mut().dump_ivr_tokens();
                         framework.gui.ivr_viewer.set_content(vec);
                     }                     
 
+                    // -- Update interrupt monitor window if open
+                    if framework.gui.is_window_open(egui::GuiWindow::InterruptViewer) {
+                        framework.gui.interrupt_viewer.update_state(machine.interrupt_state());
+                    }
+
+                    // -- Update validator statistics window
+                    if framework.gui.is_window_open(egui::GuiWindow::ValidatorStats) {
+                        framework.gui.validator_stats.update(machine.cpu().validator_stats());
+                    }
+
+                    // -- Update memory heatmap window. Accesses are only counted while it is open.
+                    let heatmap_open = framework.gui.is_window_open(egui::GuiWindow::MemoryHeatmap);
+                    machine.bus_mut().set_heatmap_enabled(heatmap_open);
+                    if let Some(frame) = machine.bus_mut().take_page_activity() {
+                        framework.gui.memory_heatmap.push_frame(frame);
+                    }
+
+                    // -- Update profiler window
+                    if framework.gui.is_window_open(egui::GuiWindow::Profiler) {
+                        framework.gui.profiler_viewer.update(machine.profile_report(PROFILER_REPORT_LEN));
+                    }
+
+                    // -- Update CPU control window
+                    if framework.gui.is_window_open(egui::GuiWindow::CpuControl) {
+                        framework.gui.cpu_control.set_watch_hit(machine.cpu().last_watch_hit());
+                    }
+
+                    // -- Update trace session window
+                    if framework.gui.is_window_open(egui::GuiWindow::TraceSessions) {
+                        framework.gui.trace_sessions.update(machine.trace_sessions().sessions());
+                    }
+
+                    if framework.gui.is_window_open(egui::GuiWindow::EventTimeline) {
+                        let timeline = machine.timeline();
+                        framework.gui.event_timeline.update(timeline.events(), timeline.is_enabled(), machine.cpu_cycles());
+                    }
+
                     // -- Update register viewer window
                     if framework.gui.is_window_open(egui::GuiWindow::CpuStateViewer) {
                         let cpu_state = machine.cpu().get_string_state();
@@ -1749,7 +2725,7 @@ fn main() {
                     // -- Update DMA viewer window
                     if framework.gui.is_window_open(egui::GuiWindow::DmaViewer) {
                         let dma_state = machine.dma_state();
-                        framework.gui.dma_viewer.update_state(dma_state);
+                        framework.gui.dma_viewer.update_state(dma_state, machine.dma_transfer_counts());
                     }
                     
                     // -- Update VideoCard Viewer (Replace CRTC Viewer)
@@ -1760,10 +2736,57 @@ fn main() {
                         }
                     }
 
+                    // -- Update Video Memory viewer
+                    if framework.gui.is_window_open(egui::GuiWindow::VideoMemViewer) {
+                        let view = framework.gui.vram_viewer.view();
+                        let row_bytes = framework.gui.vram_viewer.plane_row_bytes();
+                        let hires = framework.gui.vram_viewer.cga_hires();
+
+                        let images = match machine.videocard() {
+                            Some(card) => {
+                                let video_type = card.get_video_type();
+                                let planes = [0, 1, 2, 3].map(|p| card.get_plane_slice(p));
+                                let font = card.get_current_font();
+
+                                match view {
+                                    VramView::TextPages => {
+                                        let source = match video_type {
+                                            VideoType::EGA | VideoType::VGA => TextSource::Planes(planes[0], planes[1]),
+                                            _ => TextSource::Interleaved(planes[0])
+                                        };
+                                        let columns = if card.is_40_columns() { 40 } else { 80 };
+                                        marty_render::draw_text_pages(&source, columns, &font)
+                                    }
+                                    VramView::CgaBanks => {
+                                        let format = if hires { TileFormat::Packed1 } else { TileFormat::Packed2 };
+                                        let palette = marty_render::ripper_palette(Some(&**card), format);
+                                        marty_render::draw_cga_banks(planes[0], &palette)
+                                    }
+                                    VramView::Planes => marty_render::draw_planes(planes, row_bytes),
+                                    VramView::Fonts => {
+                                        let mut images = vec![marty_render::draw_rom_font("ROM Font", &font)];
+                                        let blocks: &[usize] = match video_type {
+                                            VideoType::EGA => &marty_render::EGA_FONT_BLOCKS,
+                                            VideoType::VGA => &marty_render::VGA_FONT_BLOCKS,
+                                            _ => &[]
+                                        };
+                                        let char_height = card.get_character_height() as u32;
+                                        images.extend(marty_render::draw_ram_fonts(planes[2], char_height, blocks));
+                                        images
+                                    }
+                                }
+                            }
+                            None => Vec::new()
+                        };
+                        framework.gui.vram_viewer.set_images(images);
+                    }
+
                     // -- Update Instruction Trace window
                     if framework.gui.is_window_open(egui::GuiWindow::HistoryViewer) {
-                        let trace = machine.cpu().dump_instruction_history_tokens();
-                        framework.gui.trace_viewer.set_content(trace);
+                        let (start, count, regs) = framework.gui.trace_viewer.get_view();
+                        let trace = machine.cpu().dump_instruction_history_tokens(start, count, regs);
+                        let history_len = machine.cpu().instruction_history_len();
+                        framework.gui.trace_viewer.set_content(trace, history_len);
                     }
 
                     // -- Update Call Stack window
@@ -1775,7 +2798,7 @@ fn main() {
                     // -- Update cycle trace viewer window
                     if framework.gui.is_window_open(egui::GuiWindow::CycleTraceViewer) {
 
-                        if machine.get_cpu_option(CpuOption::TraceLoggingEnabled(true)) {
+                        if machine.trace_sessions().is_active(TraceKind::Cycle) {
                             let trace_vec = machine.cpu().get_cycle_trace();
                             framework.gui.cycle_trace_viewer.update(trace_vec);
                         }
@@ -1795,23 +2818,34 @@ fn main() {
                             None => 0
                         };
 
+                        let cpu_type = machine.cpu().cpu_type();
+                        let current_csip = match machine.cpu().get_csip() {
+                            CpuAddress::Segmented(cs, ip) => (cs, ip),
+                            _ => (0, 0)
+                        };
+                        framework.gui.disassembly_viewer.set_patch_count(machine.patch_count());
                         let bus = machine.bus_mut();
                         
                         let mut listview_vec = Vec::new();
+                        let mut row_vec = Vec::new();
 
                         //let mut disassembly_string = String::new();
                         let mut disassembly_addr_flat = start_addr_flat as usize;
                         let mut disassembly_addr_seg = start_addr;
 
-                        for _ in 0..24 {
+                        for _ in 0..DISASSEMBLY_ROWS {
 
                             if disassembly_addr_flat < machine::MAX_MEMORY_ADDRESS {
 
                                 bus.seek(disassembly_addr_flat);
 
                                 let mut decode_vec = Vec::new();
+                                let mut row = DisassemblyRow {
+                                    address: disassembly_addr_flat as u32,
+                                    ..Default::default()
+                                };
 
-                                match Cpu::decode(bus) {
+                                match Cpu::decode_for(bus, cpu_type) {
                                     Ok(i) => {
                                     
                                         let instr_slice = bus.get_slice_at(disassembly_addr_flat, i.size as usize);
@@ -1821,6 +2855,19 @@ fn main() {
 
                                         let mut instr_vec = Cpu::tokenize_instruction(&i);
 
+                                        // Without a segment, a near target can still be found from its distance to
+                                        // this instruction.
+                                        row.target = match (i.branch_target(0), disassembly_addr_seg) {
+                                            (Some(BranchTarget::Far(segment, offset)), _) => Some(Cpu::calc_linear_address(segment, offset)),
+                                            (Some(BranchTarget::Near(target)), Some(CpuAddress::Segmented(segment, offset))) => {
+                                                Some(Cpu::calc_linear_address(segment, offset.wrapping_add(target)))
+                                            }
+                                            (Some(BranchTarget::Near(target)), _) => {
+                                                Some((disassembly_addr_flat as u32).wrapping_add(target as i16 as u32) & 0xFFFFF)
+                                            }
+                                            (None, _) => None
+                                        };
+
                                         //let decode_str = format!("{:05X} {:012} {}\n", disassembly_addr, instr_bytes_str, i);
                                         
                                         disassembly_addr_flat += i.size as usize;
@@ -1829,6 +2876,7 @@ fn main() {
                                         // from advancing flat address, so if a wrap is detected, adjust the flat address.
                                         if let Some(CpuAddress::Segmented(segment, offset)) = disassembly_addr_seg {
 
+                                            row.cs_ip = Some((segment, offset));
                                             decode_vec.push(SyntaxToken::MemoryAddressSeg16(segment, offset, format!("{:04X}:{:04X}", segment, offset)));
 
                                             let new_offset = offset.wrapping_add(i.size as u16);
@@ -1850,21 +2898,36 @@ fn main() {
 
                                 //disassembly_string.push_str(&decode_str);
                                 listview_vec.push(decode_vec);
+                                row_vec.push(row);
                             }
                         }
 
                         //framework.gui.update_dissassembly_view(disassembly_string);
-                        framework.gui.disassembly_viewer.set_content(listview_vec);
+                        framework.gui.disassembly_viewer.set_content(listview_vec, row_vec, current_csip);
                     }
 
                     // Prepare egui
                     framework.prepare(&window);
 
+                    // Update the display area if the buffer, window or scaling mode changed
+                    let buffer_extent = pixels.context().texture_extent;
+                    let buffer_size = (buffer_extent.width, buffer_extent.height);
+                    let window_size = window.inner_size();
+                    let surface_size = (window_size.width, window_size.height);
+                    let scaling_mode = framework.gui.get_scaling_mode();
+                    if display_scaling != Some((buffer_size, surface_size, scaling_mode)) {
+                        let rect = VideoRenderer::get_display_rect(buffer_size, None, surface_size, scaling_mode);
+                        log::debug!("Display area for {:?} scaling: {:?}", scaling_mode, rect);
+                        display_renderer.resize(&pixels, buffer_size.0, buffer_size.1, surface_size.0, surface_size.1);
+                        display_renderer.set_target_rect(&pixels, rect.x, rect.y, rect.w, rect.h);
+                        display_scaling = Some((buffer_size, surface_size, scaling_mode));
+                    }
+
                     // Render everything together
                     let render_result = pixels.render_with(|encoder, render_target, context| {
 
                         // Render the world texture
-                        context.scaling_renderer.render(encoder, render_target);
+                        display_renderer.render(encoder, render_target);
 
                         // Render egui
                         #[cfg(not(feature = "pi_validator"))]
@@ -1880,6 +2943,28 @@ fn main() {
                     {
                         *control_flow = ControlFlow::Exit;
                     }   
+
+                    // Record frame pacing. Samples are only taken while running, as every present
+                    // would otherwise count as a duplicated frame while paused.
+                    let present_time = Instant::now();
+                    let cpu_cycles = machine.cpu_cycles();
+                    if framework.gui.get_option(GuiOption::FramePacingOverlay).unwrap_or(false) 
+                        && matches!(exec_control.borrow().get_state(), ExecutionState::Running) 
+                    {
+                        let emulated_secs = cpu_cycles.saturating_sub(last_present_cycles) as f64 
+                            / (machine.get_cpu_mhz() * 1_000_000.0);
+
+                        framework.gui.frame_pacing.push_sample(FramePacingSample {
+                            present_interval: present_time - last_present,
+                            emulated_interval: Duration::from_secs_f64(emulated_secs),
+                            new_frames: stat_counter.emulated_frames.saturating_sub(last_present_frames),
+                            audio_fill: machine.sound_buffer_fill(),
+                        });
+                    }
+                    last_present_interval = present_time - last_present;
+                    last_present = present_time;
+                    last_present_cycles = cpu_cycles;
+                    last_present_frames = stat_counter.emulated_frames;
                 }
             }
             
@@ -1892,50 +2977,291 @@ fn main() {
     });
 }
 
-pub fn main_headless(
-    config: &ConfigFileParams,
-    rom_manager: RomManager,
-    _floppy_manager: FloppyManager
-) {
+/// Create the output folder for this run and delete old runs beyond the retention limit, or 
+/// exit if the output folder can't be created.
+fn create_artifact_manager(config: &ConfigFileParams) -> ArtifactManager {
+    let mut artifact_path = PathBuf::new();
+    artifact_path.push(config.emulator.basedir.clone());
+    artifact_path.push(config.emulator.artifact_dir.as_deref().unwrap_or(DEFAULT_ARTIFACT_DIR));
+
+    // A retention limit of 0 keeps all runs.
+    let retention_mb = match config.emulator.artifact_retention_mb.unwrap_or(DEFAULT_ARTIFACT_RETENTION_MB) {
+        0 => None,
+        mb => Some(mb)
+    };
 
-    // Init sound 
-    // The cpal sound library uses generics to initialize depending on the SampleFormat type.
-    // On Windows at least a sample type of f32 is typical, but just in case...
-    let sample_fmt = SoundPlayer::get_sample_format();
-    let sp = match sample_fmt {
-        cpal::SampleFormat::F32 => SoundPlayer::new::<f32>(),
-        cpal::SampleFormat::I16 => SoundPlayer::new::<i16>(),
-        cpal::SampleFormat::U16 => SoundPlayer::new::<u16>(),
+    match ArtifactManager::new(&artifact_path, retention_mb) {
+        Ok(artifacts) => {
+            let deleted = artifacts.enforce_retention();
+            if deleted > 0 {
+                log::info!("Deleted {} old run folder(s) from {}", deleted, artifact_path.display());
+            }
+            log::debug!("Saving output to {}", artifacts.run_dir().display());
+            artifacts
+        }
+        Err(e) => {
+            eprintln!("Couldn't create output directory {}: {}", artifact_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Place all configured trace files in the output folder for this run.
+fn redirect_artifact_paths(config: &mut ConfigFileParams, artifacts: &ArtifactManager) {
+    let redirect = |file: &mut Option<String>, kind: ArtifactKind| {
+        if let Some(name) = file {
+            *name = artifacts.file_path(kind, name).to_string_lossy().to_string();
+        }
     };
 
-    // Look up the machine description given the machine type in the configuration file
-    let machine_desc_opt = MACHINE_DESCS.get(&config.machine.model);
-    if let Some(machine_desc) = machine_desc_opt {
-        log::debug!("Given machine type {:?} got machine description: {:?}", config.machine.model, machine_desc);
+    redirect(&mut config.emulator.trace_file, ArtifactKind::Trace);
+    redirect(&mut config.emulator.video_trace_file, ArtifactKind::Trace);
+    redirect(&mut config.emulator.pit_output_file, ArtifactKind::Trace);
+    redirect(&mut config.validator.trace_file, ArtifactKind::Validator);
+
+    if let Some(path) = &mut config.validator.record_trace {
+        *path = artifacts.file_path(ArtifactKind::Validator, &path.to_string_lossy());
+    }
+}
+
+/// Open a file or folder with the host's default application.
+fn open_host_path(path: &Path) {
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("explorer").arg(path).spawn();
+    #[cfg(target_os = "macos")]
+    let result = std::process::Command::new("open").arg(path).spawn();
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let result = std::process::Command::new("xdg-open").arg(path).spawn();
+
+    if let Err(e) = result {
+        log::error!("Couldn't open {}: {}", path.display(), e);
+    }
+}
+
+/// Save the GUI window layout to be restored in the next session.
+fn save_gui_layout(framework: &Framework, path: &Path) {
+    if let Err(e) = framework.gui.layout().save(path) {
+        log::warn!("Couldn't save GUI layout: {}", e);
+    }
+}
+
+/// Switch the window to the specified fullscreen mode, or back to windowed with None. Returns the
+/// mode that was entered. Exclusive fullscreen falls back to borderless if no video mode of the
+/// current monitor can be used.
+fn set_fullscreen(window: &Window, fullscreen: Option<FullscreenMode>) -> Option<FullscreenMode> {
+    match fullscreen {
+        None => {
+            log::info!("Leaving fullscreen.");
+            window.set_fullscreen(None);
+            None
+        }
+        Some(FullscreenMode::Borderless) => {
+            log::info!("Entering borderless fullscreen.");
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+            Some(FullscreenMode::Borderless)
+        }
+        Some(FullscreenMode::Exclusive) => {
+            // Use the monitor's native resolution, with the deepest color and fastest refresh.
+            let video_mode = window.current_monitor().and_then(|monitor| {
+                let native = monitor.size();
+                monitor
+                    .video_modes()
+                    .filter(|mode| mode.size() == native)
+                    .max_by_key(|mode| (mode.bit_depth(), mode.refresh_rate_millihertz()))
+            });
+            match video_mode {
+                Some(video_mode) => {
+                    log::info!(
+                        "Entering exclusive fullscreen: {}x{} @ {:.2}Hz", 
+                        video_mode.size().width, 
+                        video_mode.size().height, 
+                        video_mode.refresh_rate_millihertz() as f64 / 1000.0
+                    );
+                    window.set_fullscreen(Some(Fullscreen::Exclusive(video_mode)));
+                    Some(FullscreenMode::Exclusive)
+                }
+                None => {
+                    log::warn!("No exclusive fullscreen video mode available. Using borderless fullscreen.");
+                    set_fullscreen(window, Some(FullscreenMode::Borderless))
+                }
+            }
+        }
+    }
+}
+
+/// Show in the window title whether the mouse is captured, and how to release it.
+fn update_window_title(window: &Window, mouse_capture: &MouseCapture, hotkeys: &HotkeyManager) {
+    let title = format!("MartyPC {}", env!("CARGO_PKG_VERSION"));
+    if mouse_capture.is_captured() {
+        match hotkeys.binding(HotkeyAction::MouseCapture) {
+            Some(combo) => window.set_title(&format!("{} - Mouse captured, press {} to release", title, combo)),
+            None => window.set_title(&format!("{} - Mouse captured", title))
+        }
     }
     else {
-        log::error!("Couldn't get machine description for {:?}", config.machine.model);
+        window.set_title(&title);
+    }
+}
+
+/// Save hotkey bindings after they are changed, and show the new bindings in the hotkey editor.
+fn save_hotkeys(framework: &mut Framework, hotkeys: &HotkeyManager, path: &Path) {
+    if let Err(e) = hotkeys.save(path) {
+        log::warn!("Couldn't save hotkeys: {}", e);
+    }
+    framework.gui.hotkey_editor.set_bindings(hotkeys.list());
+}
+
+/// Return the path of a save state slot. Slot 0 is the quick save slot.
+fn state_slot_path(basedir: &Path, slot: u8) -> PathBuf {
+    let name = match slot {
+        0 => "quicksave".to_string(),
+        n => format!("slot{}", n)
+    };
+    let mut state_path = basedir.join("states");
+    state_path.push(format!("{}.{}", name, savestate::STATE_FILE_EXTENSION));
+    state_path
+}
+
+/// Start the automation server if an automation port was configured.
+fn start_automation_server(config: &ConfigFileParams) -> Option<AutomationServer> {
+    let port = config.emulator.automation_port?;
+    let rate_limit = config.emulator.automation_rate_limit.unwrap_or(DEFAULT_AUTOMATION_RATE_LIMIT);
+
+    match AutomationServer::new(port, rate_limit) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::error!("Failed to start automation server on port {}: {}", port, e);
+            None
+        }
+    }
+}
+
+/// Start the GDB remote debugging server if a GDB port was configured.
+fn start_gdb_server(config: &ConfigFileParams) -> Option<GdbServer> {
+    let port = config.emulator.gdb_port?;
 
-        eprintln!(
+    match GdbServer::new(port) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::error!("Failed to start GDB server on port {}: {}", port, e);
+            None
+        }
+    }
+}
+
+/// Create a machine for running without a GUI, or exit if the configured machine type is invalid.
+/// Create a machine as specified by the configuration. Fails if there is no description for the
+/// machine model, or if the configured devices have conflicting IO ports.
+pub(crate) fn new_machine(
+    config: &ConfigFileParams, 
+    rom_manager: RomManager, 
+    sound_player: Option<SoundPlayer>
+) -> Result<Machine, String> {
+
+    // Look up the machine description given the machine type in the configuration file
+    let Some(machine_desc) = MACHINE_DESCS.get(&config.machine.model) else {
+        log::error!("Couldn't get machine description for {:?}", config.machine.model);
+        return Err(format!(
             "Couldn't get machine description for machine type {:?}. \
              Check that you have a valid machine type specified in configuration file.",
             config.machine.model
-        );
-        std::process::exit(1);        
-    }
+        ))
+    };
+    log::debug!("Given machine type {:?} got machine description: {:?}", config.machine.model, machine_desc);
 
-    // Instantiate the main Machine data struct
-    // Machine coordinates all the parts of the emulated computer
-    let mut machine = Machine::new(
+    let machine = Machine::new(
         config,
         config.machine.model,
-        *machine_desc_opt.unwrap(),
+        *machine_desc,
         config.emulator.trace_mode,
         config.machine.video, 
-        sp, 
+        sound_player, 
         rom_manager, 
     );
 
+    // Devices claiming the same IO ports can't work, so treat this as a configuration error.
+    if !machine.io_conflicts().is_empty() {
+        let conflicts: Vec<String> = machine.io_conflicts().iter().map(|c| format!("error: {}", c)).collect();
+        return Err(conflicts.join("\n"))
+    }
+    Ok(machine)
+}
+
+fn new_headless_machine(config: &ConfigFileParams, rom_manager: RomManager) -> Machine {
+    match new_machine(config, rom_manager, None) {
+        Ok(machine) => machine,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run each effect in a validation preset and report the results, then exit.
+pub fn main_validation_preset(
+    config: &ConfigFileParams,
+    preset_path: &Path,
+    rom_manager: RomManager,
+    floppy_manager: FloppyManager
+) {
+    let mut preset = match ValidationPreset::load(preset_path) {
+        Ok(preset) => preset,
+        Err(e) => {
+            eprintln!("Failed to load validation preset: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut machine = new_headless_machine(config, rom_manager);
+    let mut failed = false;
+
+    for effect in preset.effects.iter_mut() {
+        let result = match floppy_manager.load_floppy_data(&OsString::from(&effect.floppy)) {
+            Ok(floppy) if !floppy.is_empty() => validation_preset::run_effect(&mut machine, floppy, effect),
+            Ok(_) => {
+                println!("ERROR      {}: floppy image {} not found", effect.name, effect.floppy);
+                failed = true;
+                continue;
+            }
+            Err(e) => {
+                println!("ERROR      {}: failed to read floppy image {}: {}", effect.name, effect.floppy, e);
+                failed = true;
+                continue;
+            }
+        };
+        println!("{}", result);
+
+        match result.status {
+            EffectStatus::Fail | EffectStatus::Error => failed = true,
+            _ => {}
+        }
+
+        if config.emulator.validation_record && result.status != EffectStatus::Error {
+            effect.frame_checksum = Some(result.frame_checksum);
+            effect.crtc_checksum = Some(result.crtc_checksum);
+        }
+    }
+
+    if config.emulator.validation_record {
+        if let Err(e) = preset.save(preset_path) {
+            eprintln!("Failed to save validation preset: {}", e);
+            std::process::exit(1);
+        }
+        println!("Recorded checksums to {}", preset_path.display());
+        std::process::exit(0);
+    }
+
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
+pub fn main_headless(
+    config: &ConfigFileParams,
+    rom_manager: RomManager,
+    _floppy_manager: FloppyManager
+) {
+
+    let mut machine = new_headless_machine(config, rom_manager);
+
     // Load program binary if one was specified in config options
     if let Some(prog_bin) = &config.emulator.run_bin {
 
@@ -1968,9 +3294,38 @@ pub fn main_headless(
     let mut exec_control = ExecutionControl::new();
     exec_control.set_state(ExecutionState::Running);
 
+    let mut automation = start_automation_server(config);
+    let cycles_per_frame = (machine.get_cpu_mhz() * 1000000.0 / FPS_TARGET) as u32;
+
     loop {
-        // This should really return a Result
-        machine.run(1000, &mut exec_control);
+        match &mut automation {
+            Some(server) => {
+                // Run a frame at a time so the automation client is serviced regularly.
+                server.poll(&mut machine);
+                if server.may_run() {
+                    machine.run(cycles_per_frame, &mut exec_control);
+                    server.frame_done();
+                }
+                else {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            None if machine.deterministic() => {
+                // Run whole frames as the GUI does, so that movies play back identically.
+                machine.run(cycles_per_frame, &mut exec_control);
+                machine.frame_update();
+
+                if let Some(synced) = machine.movie_result() {
+                    machine.flush_disks();
+                    println!("Movie playback {}.", if synced { "matched the recording" } else { "desynced" });
+                    std::process::exit(if synced { 0 } else { 1 });
+                }
+            }
+            None => {
+                // This should really return a Result
+                machine.run(1000, &mut exec_control);
+            }
+        }
     }
     
     //std::process::exit(0);
@@ -32,11 +32,13 @@ use std::{
     fs::File,
     io::{BufWriter, Write},
     cell::RefCell,
+    path::Path,
     rc::Rc,
 };
 
 use marty_core::{
     
+    artifacts::{ArtifactManager, ArtifactKind},
     bytequeue::ByteQueue,
     cpu_808x::{
         *,
@@ -49,12 +51,58 @@ use marty_core::{
     floppy_manager::{FloppyManager},
     tracelogger::{TraceLogger},
     devices::pic::Pic,
+    file_util,
+    validator_case::{self, FullCase, ValidatorCase},
 };
 
+/// Replay a saved validator case. Returns true if the case validated successfully.
+fn replay_case(cpu: &mut Cpu, path: &Path) -> bool {
+    let case = match ValidatorCase::load(path) {
+        Ok(case) => case,
+        Err(e) => {
+            eprintln!("Error loading validator case: {}", e);
+            return false;
+        }
+    };
+
+    println!("Replaying validator case {} (expected mismatch: {})", path.display(), case.mismatch);
+    match validator_case::run_case(cpu, &FullCase::from_case(&case)) {
+        Some(kind) => {
+            println!("Case failed validation: {} mismatch.", kind.name());
+            false
+        }
+        None => {
+            println!("Case validated successfully.");
+            true
+        }
+    }
+}
+
+/// Reproduce a failing case, minimize it and save it to the validator output folder.
+fn save_minimized_case(cpu: &mut Cpu, case: &FullCase, artifacts: &ArtifactManager) {
+    let kind = match validator_case::run_case(cpu, case) {
+        Some(kind) => kind,
+        None => {
+            log::warn!("Failing case did not reproduce; not saving a minimized case.");
+            return;
+        }
+    };
+
+    log::info!("Minimizing failing case ({} mismatch)...", kind.name());
+    let minimized = validator_case::minimize_case(cpu, case, kind).to_case(kind);
+
+    let filename = file_util::find_unique_filename(&artifacts.dir(ArtifactKind::Validator), "case", "toml");
+    match minimized.save(&filename) {
+        Ok(()) => println!("Saved minimized validator case: {}", filename.display()),
+        Err(e) => log::error!("Error saving validator case: {}", e),
+    }
+}
+
 pub fn main_fuzzer <'a>(
     config: &ConfigFileParams,
     _rom_manager: RomManager,
-    _floppy_manager: FloppyManager
+    _floppy_manager: FloppyManager,
+    artifacts: &ArtifactManager,
 ) {
 
     let mut trace_file_option: Box<dyn Write + 'a> = Box::new(std::io::stdout());
@@ -90,10 +138,18 @@ pub fn main_fuzzer <'a>(
         #[cfg(feature = "cpu_validator")]
         validator_trace
     );
+    #[cfg(feature = "cpu_validator")]
+    cpu.set_validator_mask_flags(config.validator.mask_flags);
 
     cpu.randomize_seed(1234);
     cpu.randomize_mem();
 
+    if let Some(case_path) = &config.validator.replay_case {
+        cpu.set_option(CpuOption::EnableWaitStates(false));
+        let passed = replay_case(&mut cpu, case_path);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
     let mut test_num = 0;
 
     'testloop: loop {
@@ -333,6 +389,12 @@ pub fn main_fuzzer <'a>(
         // Set terminating address for CPU validator.
         cpu.set_end_address((i.address + i.size) as usize);
 
+        // Keep a copy of the initial state to minimize if this test fails.
+        let case = match config.validator.minimize_failures {
+            true => Some(FullCase::capture(&cpu, i.size as usize)),
+            false => None
+        };
+
         // We loop here to handle REP string instructions, which are broken up into 1 effective instruction
         // execution per iteration. The 8088 makes no such distinction.
         loop {
@@ -348,6 +410,9 @@ pub fn main_fuzzer <'a>(
                 Err(err) => {
                     log::error!("CPU Error: {}\n", err);
                     cpu.trace_flush();
+                    if let Some(case) = &case {
+                        save_minimized_case(&mut cpu, case, artifacts);
+                    }
                     break 'testloop;
                 } 
             }
@@ -0,0 +1,236 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    mouse_capture.rs
+
+    Captures the host mouse for the emulated serial mouse. While captured,
+    the cursor is grabbed and hidden, and raw relative motion from the 
+    mouse device is accumulated between updates, so movement isn't limited
+    by the edges of the window. Capture is toggled with the mouse capture
+    hotkey, and is released when the window loses focus.
+*/
+
+use winit::{
+    event::ElementState,
+    window::{CursorGrabMode, Window}
+};
+
+use marty_core::{
+    input::{self, MouseButton},
+    machine::Machine
+};
+
+pub struct MouseCapture {
+    reverse_buttons: bool,
+    sensitivity: f64,
+    is_captured: bool,
+    have_update: bool,
+    l_button_was_pressed: bool,
+    l_button_was_released: bool,
+    l_button_is_pressed: bool,
+    r_button_was_pressed: bool,
+    r_button_was_released: bool,
+    r_button_is_pressed: bool,
+    frame_delta_x: f64,
+    frame_delta_y: f64
+}
+
+impl MouseCapture {
+    pub fn new(reverse_buttons: bool, sensitivity: f64) -> Self {
+        Self {
+            reverse_buttons,
+            sensitivity,
+            is_captured: false,
+            have_update: false,
+            l_button_was_pressed: false,
+            l_button_was_released: false,
+            l_button_is_pressed: false,
+            r_button_was_pressed: false,
+            r_button_was_released: false,
+            r_button_is_pressed: false,
+            frame_delta_x: 0.0,
+            frame_delta_y: 0.0
+        }
+    }
+
+    pub fn is_captured(&self) -> bool {
+        self.is_captured
+    }
+
+    /// Grab and hide the host cursor. Returns false if the cursor couldn't be grabbed.
+    pub fn capture(&mut self, window: &Window) -> bool {
+        if self.is_captured {
+            return true
+        }
+        // Prefer locking the cursor in place. Not every platform supports both grab modes (Windows
+        // and X11 only confine the cursor, macOS only locks it), so fall back to the other.
+        let grab = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+
+        match grab {
+            Ok(_) => {
+                // Don't send motion or clicks from before the capture.
+                self.clear();
+                self.is_captured = true;
+                window.set_cursor_visible(false);
+                log::info!("Mouse captured.");
+                true
+            }
+            Err(e) => {
+                log::error!("Couldn't set cursor grab mode: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Release the host cursor. Any buttons held down are released on the emulated mouse.
+    pub fn release(&mut self, window: &Window, machine: &mut Machine) {
+        if !self.is_captured {
+            return
+        }
+        if let Err(e) = window.set_cursor_grab(CursorGrabMode::None) {
+            log::error!("Couldn't set cursor grab mode: {:?}", e);
+        }
+        window.set_cursor_visible(true);
+
+        if self.l_button_is_pressed || self.r_button_is_pressed {
+            machine.mouse_update(false, false, 0.0, 0.0);
+        }
+        if let Some(mouse) = machine.mouse_mut() {
+            mouse.clear_motion();
+        }
+        self.clear();
+        self.is_captured = false;
+        log::info!("Mouse released.");
+    }
+
+    pub fn toggle(&mut self, window: &Window, machine: &mut Machine) {
+        if self.is_captured {
+            self.release(window, machine);
+        }
+        else {
+            self.capture(window);
+        }
+    }
+
+    /// Add raw mouse device motion. Motion is ignored unless the mouse is captured.
+    pub fn motion(&mut self, x: f64, y: f64) {
+        if self.is_captured {
+            // We can get a lot more mouse updates than we want to send to the virtual mouse,
+            // so add up all deltas between each mouse polling period
+            self.have_update = true;
+            self.frame_delta_x += x;
+            self.frame_delta_y += y;
+        }
+    }
+
+    /// Record a raw mouse device button event. Buttons are ignored unless the mouse is captured.
+    pub fn button(&mut self, button: u32, state: ElementState) {
+        if !self.is_captured {
+            return
+        }
+
+        // Button ID is a raw u32. It appears that the id's for relative buttons are not consistent
+        // accross platforms. 1 == left button on windows, 3 == left button on macos. So we resolve
+        // button ids to button enums based on platform. There is a config option to override button 
+        // order.
+        let mbutton = input::button_from_id(button, self.reverse_buttons);
+
+        // A mouse click could be faster than one frame (pressed & released in 16.6ms), therefore mouse 
+        // clicks are 'sticky', if a button was pressed during the last update period it will be sent as
+        // pressed during virtual mouse update.
+        match (mbutton, state) {
+            (MouseButton::Left, ElementState::Pressed) => {
+                self.l_button_was_pressed = true;
+                self.l_button_is_pressed = true;
+                self.have_update = true;
+            },
+            (MouseButton::Left, ElementState::Released) => {
+                self.l_button_is_pressed = false;
+                self.l_button_was_released = true;
+                self.have_update = true;
+            },
+            (MouseButton::Right, ElementState::Pressed) => {
+                self.r_button_was_pressed = true;
+                self.r_button_is_pressed = true;
+                self.have_update = true;
+            },
+            (MouseButton::Right, ElementState::Released) => {
+                self.r_button_is_pressed = false;
+                self.r_button_was_released = true;
+                self.have_update = true;
+            }
+            _=> {}
+        }
+    }
+
+    /// Send motion and buttons accumulated since the last update to the emulated mouse.
+    pub fn send_update(&mut self, machine: &mut Machine) {
+        if !self.is_captured || !self.have_update {
+            return
+        }
+
+        machine.mouse_update(
+            self.l_button_was_pressed,
+            self.r_button_was_pressed,
+            self.frame_delta_x * self.sensitivity,
+            self.frame_delta_y * self.sensitivity
+        );
+
+        // A button pressed and released within one update was sent as pressed above, so send
+        // the release separately.
+        if self.l_button_was_released || self.r_button_was_released {
+            let l_state = !self.l_button_was_released && self.l_button_was_pressed;
+            let r_state = !self.r_button_was_released && self.r_button_was_pressed;
+            machine.mouse_update(l_state, r_state, 0.0, 0.0);
+        }
+
+        // Reset mouse for next frame
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        if !self.l_button_is_pressed {
+            self.l_button_was_pressed = false;
+        }
+        if !self.r_button_is_pressed {
+            self.r_button_was_pressed = false;
+        }
+
+        self.l_button_was_released = false;
+        self.r_button_was_released = false;
+
+        self.frame_delta_x = 0.0;
+        self.frame_delta_y = 0.0;
+        self.have_update = false;
+    }
+
+    fn clear(&mut self) {
+        self.l_button_is_pressed = false;
+        self.r_button_is_pressed = false;
+        self.reset();
+    }
+}
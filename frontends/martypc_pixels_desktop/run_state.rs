@@ -0,0 +1,94 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER   
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+    --------------------------------------------------------------------------
+
+    run_state.rs
+
+    Manages the run state of the machine in response to the host window.
+    When enabled, the machine is paused while the window does not have focus
+    and resumed when focus returns, unless the user paused it themselves.
+    Audio output is stopped whenever the machine is paused.
+*/
+
+use marty_core::machine::MachineState;
+
+pub struct RunStateManager {
+    pause_on_focus_loss: bool,
+    focus_paused: bool,
+    audio_paused: bool,
+}
+
+impl RunStateManager {
+    pub fn new(pause_on_focus_loss: bool) -> Self {
+        Self {
+            pause_on_focus_loss,
+            focus_paused: false,
+            audio_paused: false,
+        }
+    }
+
+    pub fn set_pause_on_focus_loss(&mut self, state: bool) {
+        self.pause_on_focus_loss = state;
+    }
+
+    /// Handle a change in window focus. Returns the state change to request of the machine, if any.
+    /// Only a machine that was paused on focus loss is resumed when focus returns.
+    pub fn focus_changed(&mut self, focused: bool, state: MachineState) -> Option<MachineState> {
+        match (focused, state) {
+            (false, MachineState::On) if self.pause_on_focus_loss => {
+                log::debug!("Window lost focus. Pausing machine.");
+                self.focus_paused = true;
+                Some(MachineState::Paused)
+            }
+            (true, MachineState::Paused) if self.focus_paused => {
+                log::debug!("Window regained focus. Resuming machine.");
+                self.focus_paused = false;
+                Some(MachineState::Resuming)
+            }
+            (true, _) => {
+                self.focus_paused = false;
+                None
+            }
+            _ => None
+        }
+    }
+
+    /// The user changed the machine state. Any pause made on focus loss is now theirs to undo.
+    pub fn user_state_change(&mut self) {
+        self.focus_paused = false;
+    }
+
+    /// Returns Some(true) when audio output should be stopped and Some(false) when it should 
+    /// be started again, following the machine state.
+    pub fn update_audio(&mut self, state: MachineState) -> Option<bool> {
+        let paused = matches!(state, MachineState::Paused);
+        if paused != self.audio_paused {
+            self.audio_paused = paused;
+            Some(paused)
+        }
+        else {
+            None
+        }
+    }
+}
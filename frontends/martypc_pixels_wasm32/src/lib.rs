@@ -218,6 +218,9 @@ pub async fn run(cfg: &str) {
         aspect_h: DEFAULT_ASPECT_HEIGHT,
         aspect_correction_enabled: false,
         composite_params: Default::default(),
+        palette: Default::default(),
+        monitor: Default::default(),
+        monochrome: None,
     };
 
     // Create the video renderer
@@ -346,6 +349,11 @@ pub async fn run(cfg: &str) {
         };
 
         video = VideoRenderer::new(config.machine.video);
+        // Palette files can't be loaded from the browser.
+        video.set_palette(config.emulator.palette, None);
+        video_data.monitor = config.monitor();
+        video_data.monochrome = video_data.monitor.phosphor(config.emulator.monochrome.unwrap_or_default());
+        video.set_monochrome(video_data.monochrome);
 
         let rom_override = match config.machine.rom_override {
             Some(ref rom_override) => rom_override,
@@ -419,7 +427,7 @@ pub async fn run(cfg: &str) {
             *machine_desc_opt.unwrap(),
             config.emulator.trace_mode,
             config.machine.video, 
-            sp, 
+            Some(sp), 
             rom_manager, 
         );
 
@@ -661,7 +669,7 @@ pub async fn run(cfg: &str) {
                     }
 
                     // -- Draw video memory --
-                    let composite_enabled = false;
+                    let composite_enabled = video_data.monitor.is_composite();
                     let aspect_correct = false;
 
                     let render_start = Instant::now();
@@ -676,6 +684,7 @@ pub async fn run(cfg: &str) {
 
                         video_buffer = video_card.get_display_buf();
                         beam_pos = None;
+                        video_data.composite_params.color_burst = video_card.get_color_burst();
 
                         // Get the render mode from the device and render appropriately
                         match (video_card.get_video_type(), video_card.get_render_mode()) {
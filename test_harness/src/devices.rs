@@ -0,0 +1,119 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    devices.rs
+
+    Implements HarnessDevice for the devices that can be tested in isolation.
+*/
+
+use marty_core::bus::{DeviceRunTimeUnit, IoDevice};
+use marty_core::devices::cga::CGACard;
+use marty_core::devices::dma::DMAController;
+use marty_core::devices::fdc::FloppyController;
+use marty_core::devices::game_port::GamePort;
+use marty_core::devices::ne2000::Ne2000;
+use marty_core::devices::pic::Pic;
+use marty_core::devices::pit::Pit;
+use marty_core::devices::ppi::Ppi;
+use marty_core::devices::timer_card::TimerCard;
+use marty_core::videocard::VideoCard;
+
+use crate::{MockBus, TICKS_PER_US};
+
+/// A device that can be driven by the test harness.
+pub trait HarnessDevice: IoDevice {
+    /// Connect the device to the mock bus before the test begins.
+    fn attach(&mut self, _bus: &mut MockBus) {}
+
+    /// Run the device for the specified number of system ticks.
+    fn advance(&mut self, bus: &mut MockBus, ticks: u32);
+}
+
+impl HarnessDevice for Pit {
+    fn attach(&mut self, bus: &mut MockBus) {
+        // Tie gates for channels 0 & 1 high, as on the motherboard.
+        self.set_channel_gate(0, true, &mut bus.bus);
+        self.set_channel_gate(1, true, &mut bus.bus);
+    }
+
+    fn advance(&mut self, bus: &mut MockBus, ticks: u32) {
+        self.run(&mut bus.bus, &mut bus.speaker_producer, DeviceRunTimeUnit::SystemTicks(ticks));
+        bus.drain_speaker();
+    }
+}
+
+impl HarnessDevice for Pic {
+    fn advance(&mut self, _bus: &mut MockBus, ticks: u32) {
+        self.run(ticks);
+    }
+}
+
+impl HarnessDevice for DMAController {
+    fn advance(&mut self, bus: &mut MockBus, _ticks: u32) {
+        self.run(&mut bus.bus);
+    }
+}
+
+impl HarnessDevice for FloppyController {
+    /// The FDC performs transfers through the mock bus's DMA controller, so DMA port writes in
+    /// a script program the DMA controller the FDC will use.
+    fn advance(&mut self, bus: &mut MockBus, ticks: u32) {
+        let mut dma = bus.bus.dma_mut().take().unwrap();
+        self.run(&mut dma, &mut bus.bus, ticks as f64 / TICKS_PER_US);
+        dma.run(&mut bus.bus);
+        *bus.bus.dma_mut() = Some(dma);
+    }
+}
+
+impl HarnessDevice for Ppi {
+    fn advance(&mut self, bus: &mut MockBus, ticks: u32) {
+        self.run(bus.pic(), ticks as f64 / TICKS_PER_US);
+    }
+}
+
+impl HarnessDevice for CGACard {
+    fn advance(&mut self, _bus: &mut MockBus, ticks: u32) {
+        self.run(DeviceRunTimeUnit::SystemTicks(ticks));
+    }
+}
+
+impl HarnessDevice for TimerCard {
+    fn advance(&mut self, _bus: &mut MockBus, ticks: u32) {
+        self.run(ticks as f64 / TICKS_PER_US);
+    }
+}
+
+impl HarnessDevice for GamePort {
+    fn advance(&mut self, _bus: &mut MockBus, ticks: u32) {
+        self.run(ticks as f64 / TICKS_PER_US);
+    }
+}
+
+impl HarnessDevice for Ne2000 {
+    fn advance(&mut self, bus: &mut MockBus, ticks: u32) {
+        self.run(bus.pic(), ticks as f64 / TICKS_PER_US);
+    }
+}
@@ -0,0 +1,263 @@
+/*
+    MartyPC
+    https://github.com/dbalsom/martypc
+
+    Copyright 2022-2023 Daniel Balsom
+
+    Permission is hereby granted, free of charge, to any person obtaining a
+    copy of this software and associated documentation files (the “Software”),
+    to deal in the Software without restriction, including without limitation
+    the rights to use, copy, modify, merge, publish, distribute, sublicense,
+    and/or sell copies of the Software, and to permit persons to whom the
+    Software is furnished to do so, subject to the following conditions:
+
+    The above copyright notice and this permission notice shall be included in
+    all copies or substantial portions of the Software.
+
+    THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+    IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+    FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+    AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+    LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+    FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+    DEALINGS IN THE SOFTWARE.
+
+    --------------------------------------------------------------------------
+
+    lib.rs
+
+    Device-level test harness.
+
+    Instantiates a single device against a mock bus and drives it with 
+    scripted port writes, port reads and cycle advances, so that individual
+    devices can be regression tested without running a whole machine.
+
+    The mock bus is a bare BusInterface with only the devices every other 
+    device depends on installed - the primary PIC, so raised IRQs can be 
+    observed, and the primary DMA controller. Port accesses that don't 
+    belong to the device under test are routed to these.
+*/
+
+pub mod devices;
+
+use marty_core::bus::{BusInterface, DeviceRunTimeUnit, IoDevice};
+use marty_core::devices::dma::DMAController;
+use marty_core::devices::pic::Pic;
+use ringbuf::{Consumer, Producer, RingBuffer};
+
+pub use crate::devices::HarnessDevice;
+
+/// System ticks per microsecond, from the 14.31818Mhz system crystal.
+pub const TICKS_PER_US: f64 = 14.31818;
+
+const SPEAKER_BUF_SIZE: usize = 4096;
+
+/// A single step of a device test script.
+#[derive(Copy, Clone, Debug)]
+pub enum Step {
+    /// Write a byte to an IO port
+    Out(u16, u8),
+    /// Read a byte from an IO port and check it against the expected value
+    In(u16, u8),
+    /// Read a byte from an IO port and check it against the expected value under a mask
+    InMasked(u16, u8, u8),
+    /// Read a byte from an IO port and discard it
+    Read(u16),
+    /// Advance the device by the specified number of system ticks
+    Advance(u32),
+    /// Check the state of the specified IR line at the PIC
+    AssertIrq(u8, bool),
+    /// Check the state of the PIC's INTR output
+    AssertIntr(bool),
+}
+
+/// A minimal bus for a device under test.
+pub struct MockBus {
+    pub bus: BusInterface,
+    pub speaker_producer: Producer<u8>,
+    speaker_consumer: Consumer<u8>,
+}
+
+impl Default for MockBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBus {
+    pub fn new() -> Self {
+        let mut bus = BusInterface::default();
+        *bus.pic_mut() = Some(Pic::new());
+        *bus.dma_mut() = Some(DMAController::new());
+
+        let (speaker_producer, speaker_consumer) = RingBuffer::<u8>::new(SPEAKER_BUF_SIZE).split();
+        Self {
+            bus,
+            speaker_producer,
+            speaker_consumer,
+        }
+    }
+
+    pub fn pic(&mut self) -> &mut Pic {
+        self.bus.pic_mut().as_mut().unwrap()
+    }
+
+    pub fn dma(&mut self) -> &mut DMAController {
+        self.bus.dma_mut().as_mut().unwrap()
+    }
+
+    /// Return the state of the specified IR line at the PIC.
+    pub fn irq_line(&mut self, irq: u8) -> bool {
+        self.pic().get_ir() & (0x01 << irq) != 0
+    }
+
+    /// Return whether the specified IRQ has been latched in the PIC's Interrupt Request Register.
+    pub fn irq_requested(&mut self, irq: u8) -> bool {
+        self.pic().get_irr() & (0x01 << irq) != 0
+    }
+
+    pub fn read_mem(&mut self, address: usize) -> u8 {
+        self.bus.read_u8(address, 0).unwrap().0
+    }
+
+    pub fn write_mem(&mut self, address: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.bus.write_u8(address + i, *byte, 0).unwrap();
+        }
+    }
+
+    /// Discard any samples the device produced for the PC speaker.
+    pub fn drain_speaker(&mut self) {
+        while self.speaker_consumer.pop().is_some() {}
+    }
+
+    fn write_port(&mut self, port: u16, data: u8) -> bool {
+        if self.pic().port_list().contains(&port) {
+            self.pic().write_u8(port, data, None, DeviceRunTimeUnit::SystemTicks(0));
+        }
+        else if self.dma().port_list().contains(&port) {
+            self.dma().write_u8(port, data, None, DeviceRunTimeUnit::SystemTicks(0));
+        }
+        else {
+            return false
+        }
+        true
+    }
+
+    fn read_port(&mut self, port: u16) -> Option<u8> {
+        if self.pic().port_list().contains(&port) {
+            Some(self.pic().read_u8(port, DeviceRunTimeUnit::SystemTicks(0)))
+        }
+        else if self.dma().port_list().contains(&port) {
+            Some(self.dma().read_u8(port, DeviceRunTimeUnit::SystemTicks(0)))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// A device under test, attached to a mock bus.
+pub struct Harness<D: HarnessDevice> {
+    pub device: D,
+    pub bus: MockBus,
+    ticks: u64,
+    step: usize,
+}
+
+impl<D: HarnessDevice> Harness<D> {
+    pub fn new(mut device: D) -> Self {
+        let mut bus = MockBus::new();
+        device.attach(&mut bus);
+        Self {
+            device,
+            bus,
+            ticks: 0,
+            step: 0,
+        }
+    }
+
+    /// Return the number of system ticks the device has been advanced.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Write a byte to an IO port. Ports belonging to the device under test take priority over 
+    /// the mock bus's devices.
+    pub fn out(&mut self, port: u16, data: u8) {
+        if self.device.port_list().contains(&port) {
+            self.device.write_u8(port, data, Some(&mut self.bus.bus), DeviceRunTimeUnit::SystemTicks(0));
+        }
+        else if !self.bus.write_port(port, data) {
+            panic!("step {}: write to unmapped port {:04X}", self.step, port);
+        }
+    }
+
+    /// Read a byte from an IO port.
+    pub fn inp(&mut self, port: u16) -> u8 {
+        if self.device.port_list().contains(&port) {
+            self.device.read_u8(port, DeviceRunTimeUnit::SystemTicks(0))
+        }
+        else {
+            match self.bus.read_port(port) {
+                Some(byte) => byte,
+                None => panic!("step {}: read from unmapped port {:04X}", self.step, port)
+            }
+        }
+    }
+
+    /// Advance the device and the mock bus's PIC by the specified number of system ticks.
+    pub fn advance(&mut self, ticks: u32) {
+        if ticks == 0 {
+            return
+        }
+        self.device.advance(&mut self.bus, ticks);
+        self.bus.pic().run(ticks);
+        self.ticks += ticks as u64;
+    }
+
+    /// Advance the device one tick at a time until 'condition' is met, or 'max_ticks' have
+    /// elapsed. Returns the number of ticks advanced if the condition was met.
+    pub fn advance_until<F>(&mut self, max_ticks: u32, mut condition: F) -> Option<u32>
+    where
+        F: FnMut(&mut Self) -> bool,
+    {
+        for t in 0..max_ticks {
+            if condition(self) {
+                return Some(t)
+            }
+            self.advance(1);
+        }
+        None
+    }
+
+    /// Run a script of steps, panicking with the index of the failing step if any check fails.
+    pub fn run_script(&mut self, script: &[Step]) {
+        for (i, step) in script.iter().enumerate() {
+            self.step = i;
+            log::trace!("step {}: {:?}", i, step);
+            match *step {
+                Step::Out(port, data) => self.out(port, data),
+                Step::In(port, expected) => {
+                    let byte = self.inp(port);
+                    assert_eq!(byte, expected, "step {}: read from port {:04X}", i, port);
+                }
+                Step::InMasked(port, mask, expected) => {
+                    let byte = self.inp(port) & mask;
+                    assert_eq!(byte, expected & mask, "step {}: masked read from port {:04X}", i, port);
+                }
+                Step::Read(port) => {
+                    self.inp(port);
+                }
+                Step::Advance(ticks) => self.advance(ticks),
+                Step::AssertIrq(irq, state) => {
+                    assert_eq!(self.bus.irq_line(irq), state, "step {}: IRQ{} line", i, irq);
+                }
+                Step::AssertIntr(state) => {
+                    assert_eq!(self.bus.pic().query_interrupt_line(), state, "step {}: INTR", i);
+                }
+            }
+        }
+        self.step = 0;
+    }
+}
@@ -0,0 +1,27 @@
+use marty_core::devices::cga::CGACard;
+use marty_core::tracelogger::TraceLogger;
+use marty_core::videocard::VideoCard;
+use marty_test_harness::{Harness, Step};
+
+const CGA_STATUS: u16 = 0x3DA;
+const CGA_FRAME_TICKS: u32 = 912 * 262;
+
+#[test]
+fn test_cga_frame_and_vsync() {
+    let mut h = Harness::new(CGACard::new(TraceLogger::None, false));
+
+    h.run_script(&[
+        // 80 column text mode, video enabled
+        Step::Out(0x3D8, 0x09),
+        Step::Advance(CGA_FRAME_TICKS),
+    ]);
+
+    let start_frame = h.device.get_frame_count();
+
+    // Vertical retrace must begin within one frame.
+    let vsync = h.advance_until(CGA_FRAME_TICKS, |h| h.inp(CGA_STATUS) & 0x08 != 0);
+    assert!(vsync.is_some());
+
+    h.advance(CGA_FRAME_TICKS);
+    assert!(h.device.get_frame_count() > start_frame);
+}
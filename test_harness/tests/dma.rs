@@ -0,0 +1,59 @@
+use marty_core::bus::{BusAccess, BusInitiator, MEM_BPA_BIT};
+use marty_core::devices::dma::DMAController;
+use marty_test_harness::{Harness, Step};
+
+#[test]
+fn test_dma_address_count_readback() {
+    let mut h = Harness::new(DMAController::new());
+
+    h.run_script(&[
+        // Clear flip-flop, then program channel 2 address and count
+        Step::Out(0x0C, 0x00),
+        Step::Out(0x04, 0x34),
+        Step::Out(0x04, 0x12),
+        Step::Out(0x05, 0xFF),
+        Step::Out(0x05, 0x01),
+        // Read them back
+        Step::Out(0x0C, 0x00),
+        Step::In(0x04, 0x34),
+        Step::In(0x04, 0x12),
+        Step::In(0x05, 0xFF),
+        Step::In(0x05, 0x01),
+    ]);
+
+    assert_eq!(h.device.get_dma_transfer_address(2), 0x1234);
+    assert_eq!(h.device.get_dma_transfer_size(2), 0x200);
+}
+
+#[test]
+fn test_dma_watchpoint_access() {
+    let mut h = Harness::new(DMAController::new());
+
+    h.run_script(&[
+        // Program channel 2: single mode, write to memory, address 0x1000, 4 bytes
+        Step::Out(0x0C, 0x00),
+        Step::Out(0x0B, 0x46),
+        Step::Out(0x04, 0x00),
+        Step::Out(0x04, 0x10),
+        Step::Out(0x81, 0x00),
+        Step::Out(0x05, 0x03),
+        Step::Out(0x05, 0x00),
+    ]);
+
+    h.bus.bus.set_flags(0x1002, MEM_BPA_BIT);
+
+    // CPU accesses are checked by the CPU itself and are not recorded.
+    h.bus.write_mem(0x1002, &[0x55]);
+    assert!(h.bus.bus.take_device_accesses().is_empty());
+
+    for byte in [0x11, 0x22, 0x33, 0x44] {
+        h.device.do_dma_write_u8(&mut h.bus.bus, 2, byte);
+    }
+
+    assert_eq!(h.bus.read_mem(0x1002), 0x33);
+    assert_eq!(
+        h.bus.bus.take_device_accesses(),
+        vec![BusAccess { address: 0x1002, write: true, initiator: BusInitiator::Dma(2) }]
+    );
+    assert!(h.bus.bus.take_device_accesses().is_empty());
+}
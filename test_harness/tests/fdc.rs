@@ -0,0 +1,145 @@
+use marty_core::devices::fdc::FloppyController;
+use marty_test_harness::{Harness, Step};
+
+const FDC_MSR: u16 = 0x3F4;
+const FDC_DATA: u16 = 0x3F5;
+const FDC_DOR: u16 = 0x3F2;
+
+/// Run the FDC until it raises IRQ6.
+fn wait_irq6(h: &mut Harness<FloppyController>) {
+    let result = h.advance_until(10_000, |h| h.bus.irq_line(6));
+    assert!(result.is_some(), "FDC did not raise IRQ6");
+}
+
+#[test]
+fn test_fdc_reset_interrupt() {
+    let mut h = Harness::new(FloppyController::new());
+
+    h.run_script(&[
+        Step::Out(FDC_DOR, 0x00),
+        Step::Out(FDC_DOR, 0x0C),
+    ]);
+    wait_irq6(&mut h);
+
+    // Sense Interrupt Status after reset
+    h.run_script(&[
+        Step::InMasked(FDC_MSR, 0xC0, 0x80),
+        Step::Out(FDC_DATA, 0x08),
+        Step::InMasked(FDC_MSR, 0xC0, 0xC0),
+        Step::InMasked(FDC_DATA, 0xC0, 0xC0),
+        Step::Read(FDC_DATA),
+    ]);
+}
+
+#[test]
+fn test_fdc_read_sector_dma() {
+    let mut h = Harness::new(FloppyController::new());
+
+    let mut image = vec![0u8; 368_640];
+    image[0] = 0xEB;
+    image[511] = 0xAA;
+    h.device.load_image_from(0, image).unwrap();
+
+    h.run_script(&[
+        Step::Out(FDC_DOR, 0x1C),
+        // Program DMA channel 2: single mode, write to memory, address 0x1000, 512 bytes
+        Step::Out(0x0C, 0x00),
+        Step::Out(0x0B, 0x46),
+        Step::Out(0x04, 0x00),
+        Step::Out(0x04, 0x10),
+        Step::Out(0x81, 0x00),
+        Step::Out(0x05, 0xFF),
+        Step::Out(0x05, 0x01),
+        Step::Out(0x0A, 0x02),
+        // Read Sector: drive 0, c:0 h:0 s:1, 512 bytes, EOT 9
+        Step::Out(FDC_DATA, 0x66),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x01),
+        Step::Out(FDC_DATA, 0x02),
+        Step::Out(FDC_DATA, 0x09),
+        Step::Out(FDC_DATA, 0x2A),
+        Step::Out(FDC_DATA, 0xFF),
+    ]);
+    wait_irq6(&mut h);
+
+    // ST0 should indicate normal termination
+    h.run_script(&[
+        Step::InMasked(FDC_DATA, 0xC0, 0x00),
+    ]);
+
+    assert_eq!(h.bus.read_mem(0x1000), 0xEB);
+    assert_eq!(h.bus.read_mem(0x11FF), 0xAA);
+}
+
+#[test]
+fn test_fdc_format_track() {
+    let mut h = Harness::new(FloppyController::new());
+    h.device.load_image_from(0, vec![0u8; 368_640]).unwrap();
+
+    // Format buffers for cylinder 0, head 1, sectors 1-9
+    let mut buffers = Vec::new();
+    for s in 1..=9 {
+        buffers.extend_from_slice(&[0, 1, s, 2]);
+    }
+    h.bus.write_mem(0x2000, &buffers);
+
+    h.run_script(&[
+        Step::Out(FDC_DOR, 0x1C),
+        // Program DMA channel 2: single mode, read from memory, address 0x2000, 36 bytes
+        Step::Out(0x0C, 0x00),
+        Step::Out(0x0B, 0x4A),
+        Step::Out(0x04, 0x00),
+        Step::Out(0x04, 0x20),
+        Step::Out(0x81, 0x00),
+        Step::Out(0x05, 35),
+        Step::Out(0x05, 0x00),
+        Step::Out(0x0A, 0x02),
+        // Format Track: drive 0, head 1, 512 byte sectors, 9 sectors, fill 0xF6
+        Step::Out(FDC_DATA, 0x4D),
+        Step::Out(FDC_DATA, 0x04),
+        Step::Out(FDC_DATA, 0x02),
+        Step::Out(FDC_DATA, 0x09),
+        Step::Out(FDC_DATA, 0x50),
+        Step::Out(FDC_DATA, 0xF6),
+    ]);
+    wait_irq6(&mut h);
+    h.run_script(&[
+        Step::InMasked(FDC_DATA, 0xC0, 0x00),
+    ]);
+
+    let image = h.device.get_image_data(0).unwrap();
+    // Track 0 head 0 is untouched, track 0 head 1 is filled.
+    assert_eq!(image[9 * 512 - 1], 0x00);
+    assert!(image[9 * 512..18 * 512].iter().all(|b| *b == 0xF6));
+    assert_eq!(image[18 * 512], 0x00);
+}
+
+#[test]
+fn test_fdc_write_protect() {
+    let mut h = Harness::new(FloppyController::new());
+    h.device.load_image_from(0, vec![0u8; 368_640]).unwrap();
+    h.device.set_write_protect(0, true);
+
+    h.run_script(&[
+        Step::Out(FDC_DOR, 0x1C),
+        // Write Sector: drive 0, c:0 h:0 s:1
+        Step::Out(FDC_DATA, 0x45),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x00),
+        Step::Out(FDC_DATA, 0x01),
+        Step::Out(FDC_DATA, 0x02),
+        Step::Out(FDC_DATA, 0x09),
+        Step::Out(FDC_DATA, 0x2A),
+        Step::Out(FDC_DATA, 0xFF),
+    ]);
+    wait_irq6(&mut h);
+
+    // Abnormal termination with the Not Writable bit set in ST1
+    h.run_script(&[
+        Step::InMasked(FDC_DATA, 0xC0, 0x40),
+        Step::InMasked(FDC_DATA, 0x02, 0x02),
+    ]);
+}
@@ -0,0 +1,50 @@
+use marty_core::devices::game_port::{GamePort, GAME_PORT_ADDRESS};
+use marty_test_harness::{Harness, Step, TICKS_PER_US};
+
+const PORT: u16 = GAME_PORT_ADDRESS;
+
+/// Convert microseconds to system ticks
+fn us(n: u32) -> u32 {
+    (n as f64 * TICKS_PER_US) as u32
+}
+
+#[test]
+fn test_game_port_one_shots() {
+    let mut port = GamePort::new();
+    // Joystick A centered horizontally and pushed fully up. Joystick B is not connected.
+    port.set_connected(0, true);
+    port.set_position(0, 0.0, -1.0);
+    let mut h = Harness::new(port);
+
+    h.run_script(&[
+        // One-shots are idle until fired
+        Step::In(PORT, 0xF0),
+        Step::Out(PORT, 0x00),
+        Step::In(PORT, 0xFF),
+        // Y axis at 0 ohms times out after 24.2us
+        Step::Advance(us(30)),
+        Step::In(PORT, 0xFD),
+        // X axis at 50K ohms times out after 574.2us
+        Step::Advance(us(540)),
+        Step::In(PORT, 0xFD),
+        Step::Advance(us(10)),
+        Step::In(PORT, 0xFC),
+        // Joystick B's one-shots never time out
+        Step::Advance(us(100_000)),
+        Step::In(PORT, 0xFC),
+    ]);
+}
+
+#[test]
+fn test_game_port_buttons() {
+    let mut port = GamePort::new();
+    port.set_connected(1, true);
+    port.set_button(1, 1, true);
+    let mut h = Harness::new(port);
+
+    h.run_script(&[Step::In(PORT, 0x70)]);
+
+    h.device.set_button(1, 1, false);
+    h.device.set_button(1, 0, true);
+    h.run_script(&[Step::In(PORT, 0xB0)]);
+}
@@ -0,0 +1,92 @@
+use marty_core::devices::ne2000::{Ne2000, NE2000_DEFAULT_MAC};
+use marty_test_harness::{Harness, Step};
+
+const BASE: u16 = 0x300;
+const IRQ: u8 = 3;
+const CR: u16 = BASE;
+const ISR: u16 = BASE + 0x07;
+const DATA: u16 = BASE + 0x10;
+const RESET: u16 = BASE + 0x1F;
+
+/// Set up a remote DMA transfer of `count` bytes at `addr`.
+fn remote_dma(addr: u16, count: u16, command: u8) -> Vec<Step> {
+    vec![
+        Step::Out(BASE + 0x08, addr as u8),
+        Step::Out(BASE + 0x09, (addr >> 8) as u8),
+        Step::Out(BASE + 0x0A, count as u8),
+        Step::Out(BASE + 0x0B, (count >> 8) as u8),
+        Step::Out(CR, command),
+    ]
+}
+
+#[test]
+fn test_ne2000_prom() {
+    let mut h = Harness::new(Ne2000::new(BASE, IRQ, NE2000_DEFAULT_MAC, None));
+
+    let mut script = vec![
+        Step::Read(RESET),
+        Step::InMasked(ISR, 0x80, 0x80),
+    ];
+    // Read the doubled station address and signature bytes
+    script.extend(remote_dma(0, 32, 0x0A));
+    for byte in NE2000_DEFAULT_MAC {
+        script.extend([Step::In(DATA, byte), Step::In(DATA, byte)]);
+    }
+    script.extend((12..28).map(|_| Step::In(DATA, 0)));
+    script.extend([Step::In(DATA, 0x57), Step::In(DATA, 0x57), Step::In(DATA, 0x57), Step::In(DATA, 0x57)]);
+    // Remote DMA complete
+    script.push(Step::InMasked(ISR, 0x40, 0x40));
+    h.run_script(&script);
+}
+
+#[test]
+fn test_ne2000_receive() {
+    let mut h = Harness::new(Ne2000::new(BASE, IRQ, NE2000_DEFAULT_MAC, None));
+
+    let mut script = vec![
+        // Stop, set up the receive ring at pages 46h-80h and accept broadcasts
+        Step::Out(CR, 0x21),
+        Step::Out(BASE + 0x01, 0x46),
+        Step::Out(BASE + 0x02, 0x80),
+        Step::Out(BASE + 0x03, 0x46),
+        Step::Out(BASE + 0x0C, 0x04),
+        Step::Out(ISR, 0xFF),
+        Step::Out(BASE + 0x0F, 0x01),
+        // Page 1: current page
+        Step::Out(CR, 0x61),
+        Step::Out(BASE + 0x07, 0x47),
+        // Start
+        Step::Out(CR, 0x22),
+        Step::AssertIrq(IRQ, false),
+    ];
+    h.run_script(&script);
+
+    let mut frame = vec![0xFF; 6];
+    frame.extend_from_slice(&[0x52, 0x55, 0x0A, 0x00, 0x02, 0x02, 0x08, 0x06]);
+    frame.extend_from_slice(&[0xAA; 50]);
+    h.device.receive_frame(&frame);
+
+    // The frame is stored at the current page with a header and raises the interrupt
+    script = vec![
+        Step::Advance(1),
+        Step::AssertIrq(IRQ, true),
+        Step::In(ISR, 0x01),
+        Step::Out(CR, 0x62),
+        Step::In(BASE + 0x07, 0x48),
+        Step::Out(CR, 0x22),
+    ];
+    script.extend(remote_dma(0x4700, 6, 0x0A));
+    script.extend([
+        Step::In(DATA, 0x21),
+        Step::In(DATA, 0x48),
+        Step::In(DATA, 68),
+        Step::In(DATA, 0),
+        Step::In(DATA, 0xFF),
+        Step::In(DATA, 0xFF),
+        // Acknowledge the interrupt
+        Step::Out(ISR, 0x41),
+        Step::Advance(1),
+        Step::AssertIrq(IRQ, false),
+    ]);
+    h.run_script(&script);
+}
@@ -0,0 +1,77 @@
+use marty_core::bus::MemoryMappedDevice;
+use marty_core::config::{MachineType, VideoType};
+use marty_core::devices::cga::CGACard;
+use marty_core::devices::ppi::Ppi;
+use marty_core::tracelogger::TraceLogger;
+use marty_test_harness::{Harness, Step, TICKS_PER_US};
+
+const PORT_C: u16 = 0x62;
+const NMI_PORT: u16 = 0xA0;
+const KB_LATCHED: u8 = 0x01;
+const KB_DATA: u8 = 0x40;
+const PAGE_REGISTER: u16 = 0x3DF;
+
+/// Convert microseconds to system ticks
+fn us(n: u32) -> u32 {
+    (n as f64 * TICKS_PER_US) as u32
+}
+
+#[test]
+fn test_pcjr_keyboard_serial() {
+    let mut h = Harness::new(Ppi::new(MachineType::IBM_PCJR_4860, VideoType::CGA, 0));
+    assert_eq!(h.device.keyboard_nmi(), Some(false));
+
+    h.device.send_keyboard(0x1E);
+    // Move to the middle of the first half cell of the start bit.
+    h.advance(us(220 + 110));
+    assert_eq!(h.device.keyboard_nmi(), Some(true));
+    assert_ne!(h.inp(PORT_C) & KB_LATCHED, 0);
+
+    // Each bit is a 440us cell, sent as its value for the first half and its inverse for the
+    // second: a start bit, 8 data bits LSB first, and odd parity.
+    let mut bits = Vec::new();
+    for _ in 0..10 {
+        let first = h.inp(PORT_C) & KB_DATA != 0;
+        h.advance(us(220));
+        let second = h.inp(PORT_C) & KB_DATA != 0;
+        h.advance(us(220));
+        assert_ne!(first, second);
+        bits.push(first);
+    }
+    assert!(bits[0]);
+    let byte = bits[1..9].iter().rev().fold(0u8, |byte, bit| byte << 1 | *bit as u8);
+    assert_eq!(byte, 0x1E);
+    assert_eq!(bits[1..].iter().filter(|bit| **bit).count() % 2, 1);
+
+    // Reading port A0 clears the latch, and with it the NMI request.
+    h.run_script(&[
+        Step::Read(NMI_PORT),
+        Step::InMasked(PORT_C, KB_LATCHED, 0),
+    ]);
+    assert_eq!(h.device.keyboard_nmi(), Some(false));
+}
+
+#[test]
+fn test_pcjr_video_pages() {
+    let mut cga = CGACard::new(TraceLogger::None, false);
+    cga.set_pcjr_mode();
+    let mut h = Harness::new(cga);
+
+    // CPU page 7 maps the B8000 window to the top 16K of RAM, mirrored across the window.
+    h.out(PAGE_REGISTER, 7 << 3);
+    h.device.mmio_write_u8(0xB8000, 0x5A, 0);
+    assert_eq!(h.device.pcjr_ram_address(0xB8000), Some(0x1C000));
+    assert_eq!(h.device.pcjr_ram_address(0xBC001), Some(0x1C001));
+    assert_eq!(h.device.mmio_read_u8(0x1C000, 0).0, 0x5A);
+    assert_eq!(h.device.mmio_read_u8(0xBC000, 0).0, 0x5A);
+
+    // In the 32K modes the window covers two pages, starting at an even page.
+    h.out(PAGE_REGISTER, 0xC0 | 7 << 3);
+    assert_eq!(h.device.pcjr_ram_address(0xB8000), Some(0x18000));
+    assert_eq!(h.device.pcjr_ram_address(0xBC000), Some(0x1C000));
+
+    // Writes to low memory are decoded too, so the card sees all of RAM.
+    h.device.mmio_write_u8(0x00400, 0xA5, 0);
+    assert_eq!(h.device.pcjr_ram_address(0x00400), Some(0x00400));
+    assert_eq!(h.device.mmio_read_u8(0x00400, 0).0, 0xA5);
+}
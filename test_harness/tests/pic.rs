@@ -0,0 +1,42 @@
+use marty_core::devices::pic::Pic;
+use marty_test_harness::{Harness, Step};
+
+/// ICW1-ICW4 as programmed by the IBM PC BIOS, then an IMR with only IRQ0 unmasked.
+const PIC_INIT: [Step; 4] = [
+    Step::Out(0x20, 0x13),
+    Step::Out(0x21, 0x08),
+    Step::Out(0x21, 0x09),
+    Step::Out(0x21, 0xFE),
+];
+
+#[test]
+fn test_pic_imr_readback() {
+    let mut h = Harness::new(Pic::new());
+
+    h.run_script(&PIC_INIT);
+    h.run_script(&[
+        Step::In(0x21, 0xFE),
+        Step::Out(0x21, 0xBC),
+        Step::In(0x21, 0xBC),
+    ]);
+}
+
+#[test]
+fn test_pic_masked_request() {
+    let mut h = Harness::new(Pic::new());
+    h.run_script(&PIC_INIT);
+
+    // IRQ1 is masked, so INTR stays low until it is unmasked.
+    h.device.request_interrupt(1);
+    assert!(!h.device.query_interrupt_line());
+
+    h.out(0x21, 0xFC);
+    h.advance(20);
+    assert_eq!(h.device.get_irr() & 0x02, 0x02);
+
+    h.device.request_interrupt(0);
+    assert!(h.device.query_interrupt_line());
+
+    // IRQ0 has priority over IRQ1.
+    assert_eq!(h.device.get_interrupt_vector(), Some(0x08));
+}
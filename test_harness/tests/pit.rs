@@ -0,0 +1,50 @@
+use marty_core::devices::pit::{Pit, PitType};
+use marty_test_harness::{Harness, Step};
+
+const PIT_DIVISOR: u32 = 12;
+
+fn pit_harness() -> Harness<Pit> {
+    Harness::new(Pit::new(PitType::Model8253, 14.31818, PIT_DIVISOR))
+}
+
+#[test]
+fn test_pit_mode0_raises_irq0() {
+    let mut h = pit_harness();
+
+    h.run_script(&[
+        // Channel 0, LSB/MSB, mode 0
+        Step::Out(0x43, 0x30),
+        Step::Out(0x40, 100),
+        Step::Out(0x40, 0),
+        Step::AssertIrq(0, false),
+        Step::Advance(50 * PIT_DIVISOR),
+        Step::AssertIrq(0, false),
+        Step::Advance(60 * PIT_DIVISOR),
+        Step::AssertIrq(0, true),
+    ]);
+
+    assert!(h.bus.irq_requested(0));
+}
+
+#[test]
+fn test_pit_latch_count() {
+    let mut h = pit_harness();
+
+    h.run_script(&[
+        // Channel 0, LSB/MSB, mode 2
+        Step::Out(0x43, 0x34),
+        Step::Out(0x40, 0x00),
+        Step::Out(0x40, 0x10),
+        // Output is high while counting in mode 2
+        Step::AssertIrq(0, true),
+        Step::Advance(100 * PIT_DIVISOR),
+        // Latch channel 0
+        Step::Out(0x43, 0x00),
+        Step::Advance(20 * PIT_DIVISOR),
+    ]);
+
+    let lsb = h.inp(0x40) as u16;
+    let msb = h.inp(0x40) as u16;
+    let count = msb << 8 | lsb;
+    assert!(count < 0x1000 && count > 0x1000 - 110, "unexpected count: {:04X}", count);
+}
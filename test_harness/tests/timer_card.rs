@@ -0,0 +1,43 @@
+use marty_core::devices::timer_card::{TimerCard, TIMER_CARD_DEFAULT_PORT, TIMER_CARD_ID};
+use marty_test_harness::{Harness, Step, TICKS_PER_US};
+
+const BASE: u16 = TIMER_CARD_DEFAULT_PORT;
+
+/// Convert microseconds to system ticks
+fn us(n: u32) -> u32 {
+    (n as f64 * TICKS_PER_US) as u32
+}
+
+fn read_count(h: &mut Harness<TimerCard>) -> u32 {
+    (0..4).fold(0, |count, i| count | (h.inp(BASE + i) as u32) << (i * 8))
+}
+
+#[test]
+fn test_timer_card_counts_microseconds() {
+    let mut h = Harness::new(TimerCard::new(BASE));
+
+    h.run_script(&[
+        Step::In(BASE + 4, TIMER_CARD_ID),
+        Step::Advance(us(10_000)),
+    ]);
+
+    let count = read_count(&mut h);
+    assert!((9_999..=10_000).contains(&count), "unexpected count: {}", count);
+}
+
+#[test]
+fn test_timer_card_latch_and_reset() {
+    let mut h = Harness::new(TimerCard::new(BASE));
+
+    h.run_script(&[
+        Step::Advance(us(0x1234) + 1),
+        // Reading the base port latches the count
+        Step::In(BASE, 0x34),
+        Step::Advance(us(0x100)),
+        Step::In(BASE + 1, 0x12),
+        // Reset the counter
+        Step::Out(BASE + 4, 0x01),
+        Step::In(BASE, 0x00),
+        Step::In(BASE + 1, 0x00),
+    ]);
+}